@@ -0,0 +1,290 @@
+"""Python bindings for the KeyMagic input method engine
+
+    >>> import keymagic
+    >>> keyboard = keymagic.Keyboard.load("MyanSan.km2")
+    >>> engine = keymagic.Engine(keyboard)
+    >>> engine.type_string("ka")
+    'က'
+
+The bindings wrap the keymagic-core C API with ctypes. Engines are safe to
+share between threads; the library serializes calls on each engine.
+"""
+
+import ctypes
+import enum
+import os
+import threading
+from dataclasses import dataclass
+from typing import Optional, Union
+
+from . import _ffi
+from ._ffi import lib
+
+__all__ = [
+    "Action",
+    "Engine",
+    "KeyMagicError",
+    "Keyboard",
+    "KeyboardInfo",
+    "Output",
+    "library_version",
+]
+
+
+class KeyMagicError(Exception):
+    """A call into the engine failed"""
+
+    def __init__(self, message: str, code: Optional[int] = None):
+        super().__init__(message)
+        self.code = code
+
+
+_ERRORS = {
+    _ffi.ERROR_INVALID_HANDLE: "invalid handle",
+    _ffi.ERROR_INVALID_PARAMETER: "invalid parameter",
+    _ffi.ERROR_ENGINE_FAILURE: "engine failure",
+    _ffi.ERROR_UTF8_CONVERSION: "text is not valid UTF-8",
+    _ffi.ERROR_NO_KEYBOARD: "no keyboard loaded",
+}
+
+
+def _check(code: int, what: str) -> None:
+    if code != _ffi.SUCCESS:
+        raise KeyMagicError(f"{what}: {_ERRORS.get(code, f'error {code}')}", code)
+
+
+def library_version() -> str:
+    """Version of the loaded keymagic-core library"""
+    return lib.keymagic_get_version().decode("utf-8")
+
+
+class Action(enum.IntEnum):
+    """What the host should do with its text, see `Output`"""
+
+    NONE = 0
+    INSERT = 1
+    DELETE = 2
+    DELETE_AND_INSERT = 3
+
+
+@dataclass(frozen=True)
+class Output:
+    """Result of processing one key"""
+
+    action: Action
+    #: Text to insert after deleting
+    text: str
+    #: Characters (Unicode scalar values) to delete before the caret
+    delete_count: int
+    #: Composing text after the key
+    composing: str
+    #: Whether the keyboard handled the key; if not the host should pass it on
+    is_processed: bool
+    #: `delete_count` in UTF-16 code units
+    delete_utf16_count: int
+    #: Caret position within `composing`, in UTF-16 code units
+    composing_caret_utf16: int
+    #: Message a rule asked the host to show (`@notify`); never set with
+    #: `dry_run`, and at most one per keyboard every 500 ms
+    notification: Optional[str] = None
+    #: Whether the engine cut `delete_count` short because the keyboard
+    #: asked to delete more than it typed
+    delete_clamped: bool = False
+
+
+@dataclass(frozen=True)
+class KeyboardInfo:
+    """Format version, table sizes and layout options of a keyboard"""
+
+    format_version: tuple
+    string_count: int
+    info_count: int
+    rule_count: int
+    track_caps: bool
+    auto_backspace: bool
+    eat: bool
+    pos_based: bool
+    right_alt: bool
+    case_insensitive_ascii: bool
+
+
+class Keyboard:
+    """A compiled (.km2) keyboard layout"""
+
+    def __init__(self, handle: int):
+        if not handle:
+            raise KeyMagicError("invalid keyboard handle")
+        self._handle = handle
+        self.name = _ffi.take_string(lib.keymagic_km2_get_name(handle))
+        self.description = _ffi.take_string(lib.keymagic_km2_get_description(handle))
+        self.hotkey = _ffi.take_string(lib.keymagic_km2_get_hotkey(handle))
+
+        info = _ffi.Km2Info()
+        _check(lib.keymagic_km2_get_info(handle, ctypes.byref(info)), "Failed to read keyboard")
+        self.info = KeyboardInfo(
+            format_version=(info.major_version, info.minor_version),
+            string_count=info.string_count,
+            info_count=info.info_count,
+            rule_count=info.rule_count,
+            track_caps=bool(info.track_caps),
+            auto_backspace=bool(info.auto_bksp),
+            eat=bool(info.eat),
+            pos_based=bool(info.pos_based),
+            right_alt=bool(info.right_alt),
+            case_insensitive_ascii=lib.keymagic_km2_get_layout_option(handle, b"case_insensitive_ascii") == 1,
+        )
+
+    @classmethod
+    def load(cls, path: Union[str, "os.PathLike[str]"]) -> "Keyboard":
+        """Loads a keyboard from a .km2 file"""
+        handle = lib.keymagic_km2_load(os.fsencode(path))
+        if not handle:
+            raise KeyMagicError(f"Failed to load keyboard {os.fspath(path)}")
+        return cls(handle)
+
+    @classmethod
+    def from_bytes(cls, data: bytes) -> "Keyboard":
+        """Loads a keyboard from the contents of a .km2 file"""
+        handle = lib.keymagic_km2_load_from_memory(data, len(data))
+        if not handle:
+            raise KeyMagicError("Failed to load keyboard from memory")
+        return cls(handle)
+
+    def __del__(self):
+        handle, self._handle = getattr(self, "_handle", None), None
+        if handle:
+            lib.keymagic_km2_free(handle)
+
+    def __repr__(self):
+        return f"Keyboard(name={self.name!r})"
+
+
+# Windows virtual key codes used by `Engine.type_string`
+_VK_SPACE = 0x20
+_VK_0 = 0x30
+_VK_A = 0x41
+
+
+def _key_for_char(ch: str):
+    """US layout key (VK code, shift) typing `ch`, or (0, False) if none"""
+    if "a" <= ch <= "z":
+        return _VK_A + ord(ch) - ord("a"), False
+    if "A" <= ch <= "Z":
+        return _VK_A + ord(ch) - ord("A"), True
+    if "0" <= ch <= "9":
+        return _VK_0 + ord(ch) - ord("0"), False
+    if ch == " ":
+        return _VK_SPACE, False
+    return 0, False
+
+
+# (has a VK code, dry run) -> C function
+_PROCESS_KEY = {
+    (False, False): lib.keymagic_engine_process_key,
+    (True, False): lib.keymagic_engine_process_key_win,
+    (False, True): lib.keymagic_engine_process_key_test,
+    (True, True): lib.keymagic_engine_process_key_test_win,
+}
+
+
+class Engine:
+    """An engine instance with its own composing state"""
+
+    def __init__(self, keyboard: Keyboard):
+        # Guards the handle against `close()` while another thread uses it
+        self._lock = threading.Lock()
+        self._handle = lib.keymagic_engine_new()
+        if not self._handle:
+            raise KeyMagicError("Failed to create engine")
+        self.keyboard = keyboard
+        _check(lib.keymagic_engine_load_km2(self._handle, keyboard._handle), "Failed to load keyboard")
+
+    def _require_handle(self) -> int:
+        if not self._handle:
+            raise KeyMagicError("Engine is closed")
+        return self._handle
+
+    def key(
+        self,
+        vk: int,
+        char: Optional[str] = None,
+        shift: bool = False,
+        ctrl: bool = False,
+        alt: bool = False,
+        caps_lock: bool = False,
+        *,
+        dry_run: bool = False,
+    ) -> Output:
+        """Processes a key press
+
+        `vk` is a Windows virtual key code (0x41 for A), or 0 to match on
+        `char` alone. `char` is the ASCII character the key produces. With
+        `dry_run` the output is computed without changing the engine state.
+        """
+        if char is not None and (len(char) != 1 or ord(char) > 0x7F):
+            raise ValueError("char must be a single ASCII character")
+        character = (char or "\0").encode("ascii")
+        output = _ffi.ProcessKeyOutput()
+
+        with self._lock:
+            handle = self._require_handle()
+            process = _PROCESS_KEY[bool(vk), dry_run]
+            code = process(handle, vk, character, shift, ctrl, alt, caps_lock, ctypes.byref(output))
+            text = _ffi.take_string(output.text)
+            composing = _ffi.take_string(output.composing_text)
+            notification = _ffi.take_string(output.notification)
+        _check(code, "Failed to process key")
+
+        return Output(
+            action=Action(output.action_type),
+            text=text or "",
+            delete_count=output.delete_count,
+            composing=composing or "",
+            is_processed=bool(output.is_processed),
+            delete_utf16_count=output.delete_utf16_count,
+            composing_caret_utf16=output.composing_caret_utf16,
+            notification=notification,
+            delete_clamped=bool(output.delete_clamped),
+        )
+
+    def type_string(self, text: str) -> str:
+        """Types ASCII `text` key by key on a US layout and returns the
+        composing text afterwards"""
+        for ch in text:
+            vk, shift = _key_for_char(ch)
+            self.key(vk, ch, shift=shift)
+        return self.composing
+
+    @property
+    def composing(self) -> str:
+        """The composing text, as the host would show it"""
+        with self._lock:
+            return _ffi.take_string(lib.keymagic_engine_get_composition(self._require_handle())) or ""
+
+    @composing.setter
+    def composing(self, text: str) -> None:
+        with self._lock:
+            code = lib.keymagic_engine_set_composition(self._require_handle(), text.encode("utf-8"))
+        _check(code, "Failed to set composing text")
+
+    def reset(self) -> None:
+        """Clears the composing text and active states"""
+        with self._lock:
+            _check(lib.keymagic_engine_reset(self._require_handle()), "Failed to reset engine")
+
+    def close(self) -> None:
+        """Frees the engine; it cannot be used afterwards"""
+        with self._lock:
+            handle, self._handle = self._handle, None
+            if handle:
+                lib.keymagic_engine_free(handle)
+
+    def __enter__(self) -> "Engine":
+        return self
+
+    def __exit__(self, *exc) -> None:
+        self.close()
+
+    def __del__(self):
+        if getattr(self, "_lock", None) is not None:
+            self.close()
@@ -0,0 +1,135 @@
+"""ctypes declarations for the keymagic-core C API
+
+Every `char*` the library allocates is copied into a Python string by
+`take_string` and handed straight back to `keymagic_free_string`; no
+pointer owned by the library outlives the call that returned it.
+"""
+
+import ctypes
+import os
+import platform
+from pathlib import Path
+from typing import Optional
+
+
+class ProcessKeyOutput(ctypes.Structure):
+    _fields_ = [
+        ("action_type", ctypes.c_int),
+        ("text", ctypes.c_void_p),
+        ("delete_count", ctypes.c_int),
+        ("composing_text", ctypes.c_void_p),
+        ("is_processed", ctypes.c_int),
+        ("delete_utf16_count", ctypes.c_int),
+        ("composing_caret_utf16", ctypes.c_int),
+        ("notification", ctypes.c_void_p),
+        ("delete_clamped", ctypes.c_int),
+    ]
+
+
+class Km2Info(ctypes.Structure):
+    _fields_ = [
+        ("major_version", ctypes.c_int),
+        ("minor_version", ctypes.c_int),
+        ("string_count", ctypes.c_int),
+        ("info_count", ctypes.c_int),
+        ("rule_count", ctypes.c_int),
+        ("track_caps", ctypes.c_int),
+        ("auto_bksp", ctypes.c_int),
+        ("eat", ctypes.c_int),
+        ("pos_based", ctypes.c_int),
+        ("right_alt", ctypes.c_int),
+    ]
+
+
+# KeyMagicResult
+SUCCESS = 0
+ERROR_INVALID_HANDLE = -1
+ERROR_INVALID_PARAMETER = -2
+ERROR_ENGINE_FAILURE = -3
+ERROR_UTF8_CONVERSION = -4
+ERROR_NO_KEYBOARD = -5
+
+
+def _library_name() -> str:
+    system = platform.system()
+    if system == "Windows":
+        return "keymagic_core.dll"
+    if system == "Darwin":
+        return "libkeymagic_core.dylib"
+    return "libkeymagic_core.so"
+
+
+def _find_library() -> str:
+    """Looks for the library in $KEYMAGIC_CORE_LIB, next to this package
+    (wheels) and in the cargo target directory of a source checkout"""
+    override = os.environ.get("KEYMAGIC_CORE_LIB")
+    if override:
+        return override
+
+    name = _library_name()
+    package_dir = Path(__file__).resolve().parent
+    repo_root = package_dir.parent.parent.parent
+    candidates = [
+        package_dir / name,
+        repo_root / "target" / "release" / name,
+        repo_root / "target" / "debug" / name,
+    ]
+    for candidate in candidates:
+        if candidate.exists():
+            return str(candidate)
+
+    searched = ", ".join(str(c) for c in candidates)
+    raise OSError(
+        f"Could not find {name} (searched {searched}); "
+        "build it with `cargo build --release -p keymagic-core` or set KEYMAGIC_CORE_LIB"
+    )
+
+
+def _declare(lib: ctypes.CDLL) -> None:
+    engine = ctypes.c_void_p
+    km2 = ctypes.c_void_p
+    key_args = [engine, ctypes.c_int, ctypes.c_char, ctypes.c_int, ctypes.c_int,
+                ctypes.c_int, ctypes.c_int, ctypes.POINTER(ProcessKeyOutput)]
+
+    signatures = {
+        "keymagic_get_version": (ctypes.c_char_p, []),
+        "keymagic_free_string": (None, [ctypes.c_void_p]),
+        "keymagic_engine_new": (engine, []),
+        "keymagic_engine_free": (None, [engine]),
+        "keymagic_engine_load_km2": (ctypes.c_int, [engine, km2]),
+        "keymagic_engine_process_key": (ctypes.c_int, key_args),
+        "keymagic_engine_process_key_win": (ctypes.c_int, key_args),
+        "keymagic_engine_process_key_test": (ctypes.c_int, key_args),
+        "keymagic_engine_process_key_test_win": (ctypes.c_int, key_args),
+        "keymagic_engine_reset": (ctypes.c_int, [engine]),
+        "keymagic_engine_get_composition": (ctypes.c_void_p, [engine]),
+        "keymagic_engine_set_composition": (ctypes.c_int, [engine, ctypes.c_char_p]),
+        "keymagic_km2_load": (km2, [ctypes.c_char_p]),
+        "keymagic_km2_load_from_memory": (km2, [ctypes.c_char_p, ctypes.c_size_t]),
+        "keymagic_km2_free": (None, [km2]),
+        "keymagic_km2_get_name": (ctypes.c_void_p, [km2]),
+        "keymagic_km2_get_description": (ctypes.c_void_p, [km2]),
+        "keymagic_km2_get_hotkey": (ctypes.c_void_p, [km2]),
+        "keymagic_km2_get_info": (ctypes.c_int, [km2, ctypes.POINTER(Km2Info)]),
+        "keymagic_km2_get_layout_option": (ctypes.c_int, [km2, ctypes.c_char_p]),
+    }
+    for name, (restype, argtypes) in signatures.items():
+        function = getattr(lib, name)
+        function.restype = restype
+        function.argtypes = argtypes
+
+
+def take_string(pointer: Optional[int]) -> Optional[str]:
+    """Copies a string allocated by the library and frees the original"""
+    if not pointer:
+        return None
+    try:
+        return ctypes.string_at(pointer).decode("utf-8")
+    finally:
+        lib.keymagic_free_string(pointer)
+
+
+# ctypes releases the GIL for the duration of every foreign call, so a call
+# waiting on the engine's lock never blocks other Python threads
+lib = ctypes.CDLL(_find_library())
+_declare(lib)
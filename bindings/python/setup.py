@@ -0,0 +1,70 @@
+"""Builds keymagic-core with cargo and bundles the library into the wheel."""
+
+import os
+import platform
+import shutil
+import subprocess
+from pathlib import Path
+
+from setuptools import setup
+from setuptools.command.build_py import build_py
+
+try:
+    from wheel.bdist_wheel import bdist_wheel
+except ImportError:  # pragma: no cover
+    bdist_wheel = None
+
+HERE = Path(__file__).resolve().parent
+REPO_ROOT = HERE.parent.parent
+
+
+def library_name():
+    system = platform.system()
+    if system == "Windows":
+        return "keymagic_core.dll"
+    if system == "Darwin":
+        return "libkeymagic_core.dylib"
+    return "libkeymagic_core.so"
+
+
+class BuildWithCargo(build_py):
+    """Runs `cargo build --release -p keymagic-core` and copies the library
+    next to the Python sources"""
+
+    def run(self):
+        target = os.environ.get("CARGO_BUILD_TARGET")
+        command = ["cargo", "build", "--release", "-p", "keymagic-core"]
+        if target:
+            command += ["--target", target]
+        subprocess.check_call(command, cwd=REPO_ROOT)
+
+        target_dir = Path(os.environ.get("CARGO_TARGET_DIR", REPO_ROOT / "target"))
+        if target:
+            target_dir = target_dir / target
+        library = target_dir / "release" / library_name()
+
+        super().run()
+        destination = Path(self.build_lib) / "keymagic"
+        destination.mkdir(parents=True, exist_ok=True)
+        shutil.copy2(library, destination / library_name())
+
+
+cmdclass = {"build_py": BuildWithCargo}
+
+if bdist_wheel is not None:
+
+    class PlatformWheel(bdist_wheel):
+        """The bundled library is native code, but the wrapper uses ctypes
+        only, so the wheel is tagged for the platform and any Python 3"""
+
+        def finalize_options(self):
+            super().finalize_options()
+            self.root_is_pure = False
+
+        def get_tag(self):
+            _, _, plat = super().get_tag()
+            return "py3", "none", plat
+
+    cmdclass["bdist_wheel"] = PlatformWheel
+
+setup(cmdclass=cmdclass)
@@ -0,0 +1,34 @@
+import subprocess
+from pathlib import Path
+
+import pytest
+
+REPO_ROOT = Path(__file__).resolve().parents[3]
+TEST_KMS = REPO_ROOT / "keymagic-core" / "tests" / "test_keyboard.kms"
+
+
+@pytest.fixture(scope="session")
+def test_km2(tmp_path_factory) -> Path:
+    """The core FFI test keyboard, compiled with kms2km2"""
+    km2 = tmp_path_factory.mktemp("keyboards") / "test_keyboard.km2"
+    subprocess.run(
+        ["cargo", "run", "--quiet", "-p", "kms2km2", "--bin", "kms2km2", "--", str(TEST_KMS), str(km2)],
+        cwd=REPO_ROOT,
+        check=True,
+    )
+    return km2
+
+
+@pytest.fixture
+def keyboard(test_km2):
+    import keymagic
+
+    return keymagic.Keyboard.load(test_km2)
+
+
+@pytest.fixture
+def engine(keyboard):
+    import keymagic
+
+    with keymagic.Engine(keyboard) as engine:
+        yield engine
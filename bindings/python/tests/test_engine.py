@@ -0,0 +1,163 @@
+"""Mirrors a subset of the keymagic-core engine and FFI tests"""
+
+import threading
+
+import pytest
+
+import keymagic
+from keymagic import Action, KeyMagicError
+
+VK_BACK = 0x08
+VK_A = 0x41
+VK_H = 0x48
+VK_K = 0x4B
+
+
+def test_library_version():
+    assert keymagic.library_version()
+
+
+def test_keyboard_metadata(keyboard):
+    assert keyboard.name == "Test Keyboard"
+    assert keyboard.description == "Simple test keyboard for FFI testing"
+    assert keyboard.hotkey is None
+    assert keyboard.info.format_version == (1, 5)
+    assert keyboard.info.rule_count == 5
+    assert not keyboard.info.track_caps
+    assert keyboard.info.auto_backspace
+    assert not keyboard.info.case_insensitive_ascii
+
+
+def test_load_missing_file():
+    with pytest.raises(KeyMagicError):
+        keymagic.Keyboard.load("nonexistent.km2")
+
+
+def test_load_from_bytes(test_km2):
+    keyboard = keymagic.Keyboard.from_bytes(test_km2.read_bytes())
+    assert keyboard.name == "Test Keyboard"
+
+    with pytest.raises(KeyMagicError):
+        keymagic.Keyboard.from_bytes(b"not a keyboard")
+
+
+def test_simple_rule(engine):
+    output = engine.key(VK_A, "a")
+    assert output.action == Action.INSERT
+    assert output.text == "အ"
+    assert output.composing == "အ"
+    assert output.is_processed
+    assert engine.composing == "အ"
+
+
+def test_multi_char_rule_replaces_composition(engine):
+    engine.key(VK_K, "k")
+    output = engine.key(VK_A, "a")
+    assert output.action == Action.DELETE_AND_INSERT
+    assert output.delete_count == 1
+    assert output.text == "က"
+    assert engine.composing == "က"
+
+
+def test_virtual_key_rule(engine):
+    output = engine.key(VK_A, "A", shift=True)
+    assert output.composing == "အ"
+
+
+def test_backspace_rule(engine):
+    assert engine.type_string("ka") == "က"
+    output = engine.key(VK_BACK)
+    assert output.is_processed
+    assert engine.composing == ""
+
+
+def test_type_string(engine):
+    assert engine.type_string("kha") == "ခ"
+
+
+def test_unmatched_key_is_appended(engine):
+    output = engine.key(VK_H, "h")
+    assert output.composing == "h"
+
+
+def test_dry_run_does_not_change_state(engine):
+    output = engine.key(VK_A, "a", dry_run=True)
+    assert output.composing == "အ"
+    assert engine.composing == ""
+
+
+def test_reset(engine):
+    engine.type_string("k")
+    engine.reset()
+    assert engine.composing == ""
+
+
+def test_set_composing(engine):
+    engine.composing = "က"
+    assert engine.composing == "က"
+
+
+def test_char_must_be_ascii(engine):
+    with pytest.raises(ValueError):
+        engine.key(VK_A, "အ")
+
+
+def test_closed_engine(keyboard):
+    engine = keymagic.Engine(keyboard)
+    engine.close()
+    engine.close()
+    with pytest.raises(KeyMagicError):
+        engine.key(VK_A, "a")
+
+
+def test_engine_outlives_keyboard(test_km2):
+    engine = keymagic.Engine(keymagic.Keyboard.load(test_km2))
+    assert engine.type_string("a") == "အ"
+    engine.close()
+
+
+def test_returned_strings_are_copied_then_freed(monkeypatch, engine):
+    lib = keymagic._ffi.lib
+    real_free = lib.keymagic_free_string
+    freed = []
+
+    def free(pointer):
+        freed.append(pointer)
+        real_free(pointer)
+
+    monkeypatch.setattr(lib, "keymagic_free_string", free)
+
+    # Insert: text and composing text
+    output = engine.key(VK_A, "a")
+    assert len(freed) == 2
+    # The copies stay valid after the library's strings are gone
+    assert output.text == "အ" and output.composing == "အ"
+
+    # Unmatched key: composing text only
+    engine.key(VK_BACK)
+    assert len(freed) == 3
+
+    assert engine.composing == ""
+    assert len(freed) == 4
+
+
+def test_engine_shared_between_threads(keyboard):
+    engine = keymagic.Engine(keyboard)
+    errors = []
+
+    def type_keys():
+        try:
+            for _ in range(200):
+                engine.key(VK_A, "a", dry_run=True)
+        except Exception as e:  # pragma: no cover
+            errors.append(e)
+
+    threads = [threading.Thread(target=type_keys) for _ in range(4)]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+
+    assert not errors
+    assert engine.composing == ""
+    engine.close()
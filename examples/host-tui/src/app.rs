@@ -0,0 +1,163 @@
+//! The text field the example host edits: committed text and a composition
+//!
+//! The rules for when to commit are the ones the Windows TSF input method
+//! follows in composition mode:
+//!
+//! - An unprocessed key commits the composition, then the host handles the
+//!   key itself (Enter adds a line, Backspace deletes committed text).
+//! - A processed Space whose composing text ends with a space commits, as
+//!   do Enter and Tab.
+//! - Otherwise the host shows the engine's composing text as is; it never
+//!   edits the composition itself.
+//! - Committing takes the text from `keymagic_engine_flush`, which also
+//!   resets the engine for the next word.
+
+use crate::engine::{Engine, ResetLevel};
+use crate::keymap::HostKey;
+use keymagic_core::VirtualKey;
+
+pub struct App {
+    engine: Engine,
+    committed: String,
+    composing: String,
+    status: String,
+    quit: bool,
+}
+
+impl App {
+    pub fn new(engine: Engine) -> Self {
+        Self {
+            engine,
+            committed: String::new(),
+            composing: String::new(),
+            status: String::new(),
+            quit: false,
+        }
+    }
+
+    pub fn committed(&self) -> &str {
+        &self.committed
+    }
+
+    pub fn composing(&self) -> &str {
+        &self.composing
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    /// Whether the user asked to quit
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Handles a key press; `None` is a key the engine has no code for
+    pub fn handle_key(&mut self, key: Option<HostKey>) {
+        let Some(key) = key else {
+            self.commit();
+            return;
+        };
+
+        // Host bindings never reach the engine
+        if key.is_ctrl('c') || key.is_ctrl('q') {
+            self.quit = true;
+            return;
+        }
+        if key.is(VirtualKey::F9) {
+            self.focus_lost();
+            return;
+        }
+        if key.is_ctrl('l') {
+            return self.reset(ResetLevel::Context);
+        }
+        if key.is_ctrl('r') {
+            return self.reset(ResetLevel::Full);
+        }
+        if key.is(VirtualKey::Escape) {
+            return self.reset(ResetLevel::Composing);
+        }
+
+        match self.engine.process(&key) {
+            Ok(output) => {
+                if let Some(message) = output.notification {
+                    self.status = message;
+                }
+                self.composing = output.composing_text;
+                if !output.is_processed {
+                    self.commit();
+                    self.apply(&key);
+                } else if key.is(VirtualKey::Return)
+                    || key.is(VirtualKey::Tab)
+                    || (key.is(VirtualKey::Space) && self.composing.ends_with(' '))
+                {
+                    self.commit();
+                }
+            }
+            Err(e) => {
+                // The engine did not take the key: keep what was composed
+                self.status = e.to_string();
+                self.commit();
+                self.apply(&key);
+            }
+        }
+    }
+
+    /// The terminal lost focus: commit, or the composition would be lost
+    pub fn focus_lost(&mut self) {
+        self.commit();
+        self.status = "Focus lost: composition committed".to_string();
+    }
+
+    /// The terminal got focus back; the text around the caret may have
+    /// changed meanwhile, so states from before no longer apply
+    pub fn focus_gained(&mut self) {
+        self.reset(ResetLevel::Context);
+    }
+
+    /// The panes, one line per item
+    pub fn render_lines(&self, width: usize) -> Vec<String> {
+        let rule = "─".repeat(width);
+        let mut lines = vec!["Committed".to_string()];
+        lines.extend(self.committed.split('\n').map(str::to_string));
+        lines.push(rule.clone());
+        lines.push("Composing".to_string());
+        lines.push(self.composing.clone());
+        lines.push(rule);
+        lines.push(self.status.clone());
+        lines.push("F9 focus loss · Esc/Ctrl+L/Ctrl+R composing/context/full reset · Ctrl+Q quit".to_string());
+        lines
+    }
+
+    /// Moves the composition to the committed text
+    fn commit(&mut self) {
+        let text = self.engine.flush();
+        self.committed.push_str(&text);
+        self.composing.clear();
+    }
+
+    /// Clears engine state at `level` and drops the composition
+    fn reset(&mut self, level: ResetLevel) {
+        self.status = match self.engine.reset(level) {
+            Ok(()) => format!("{:?} reset", level),
+            Err(e) => e.to_string(),
+        };
+        self.composing.clear();
+    }
+
+    /// What the key does in a field without an input method
+    fn apply(&mut self, key: &HostKey) {
+        if key.ctrl || key.alt {
+            return;
+        }
+        if key.is(VirtualKey::Return) {
+            self.committed.push('\n');
+        } else if key.is(VirtualKey::Tab) {
+            self.committed.push('\t');
+        } else if key.is(VirtualKey::Back) {
+            self.committed.pop();
+        } else if let Some(ch) = key.character {
+            self.committed.push(ch);
+        }
+    }
+}
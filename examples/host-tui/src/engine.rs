@@ -0,0 +1,165 @@
+//! Safe wrapper around an engine handle of the C interface
+//!
+//! Everything here goes through the `extern "C"` functions a host written in
+//! C or C++ would call; see `keymagic-windows/shared/include/keymagic_ffi.h`
+//! for the same calls in C. The rules the wrapper follows are the ones hosts
+//! get wrong most often:
+//!
+//! - Every string the engine returns is copied and then freed with
+//!   `keymagic_free_string`, including the ones the host does not use.
+//! - `ProcessKeyOutput` is zeroed before each call: the engine leaves it
+//!   untouched when it rejects the key code.
+//! - One handle is used by one thread. The raw handle keeps `Engine` from
+//!   being `Send`, so keys reach the engine in the order they were typed.
+
+use keymagic_core::ffi::{
+    keymagic_engine_flush, keymagic_engine_free, keymagic_engine_last_error,
+    keymagic_engine_load_keyboard, keymagic_engine_new, keymagic_engine_process_key_win,
+    keymagic_engine_reset_level, keymagic_free_string, EngineHandle, KeyMagicResult, ProcessKeyOutput,
+};
+use std::ffi::{c_char, CStr, CString};
+use std::fmt;
+use std::path::Path;
+use std::ptr;
+
+use crate::keymap::HostKey;
+
+/// How much of the engine state a reset clears; the values of
+/// `KeyMagicResetLevel` in the C header
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetLevel {
+    /// The composing text and undo history; states stay on for the next key
+    Composing = 0,
+    /// Also the states, for a new text context
+    Context = 1,
+    /// Everything the engine tracks between keys
+    Full = 2,
+}
+
+/// A call into the engine that failed
+#[derive(Debug, PartialEq)]
+pub enum EngineError {
+    /// The keyboard did not load; the reason comes from the engine
+    Load(String),
+    /// A call returned an error code
+    Call(&'static str, KeyMagicResult),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Load(reason) => write!(f, "Failed to load keyboard: {}", reason),
+            EngineError::Call(function, result) => write!(f, "{} failed: {:?}", function, result),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+/// What the engine made of one key
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyOutput {
+    /// The composing text after the key, as the host shows it
+    pub composing_text: String,
+    /// Whether the engine used the key; an unprocessed key belongs to the
+    /// host, which commits the composition and then handles the key itself
+    pub is_processed: bool,
+    /// A message the keyboard asked the host to show
+    pub notification: Option<String>,
+}
+
+/// An engine handle with a keyboard loaded
+pub struct Engine {
+    handle: *mut EngineHandle,
+}
+
+impl Engine {
+    /// Creates an engine and loads the keyboard at `path`
+    pub fn load(path: &Path) -> Result<Self, EngineError> {
+        let path_text = path.to_str().ok_or_else(|| EngineError::Load("path is not UTF-8".to_string()))?;
+        let path_text = CString::new(path_text).map_err(|_| EngineError::Load("path contains a NUL".to_string()))?;
+
+        // Owning the handle right away frees it on every error path below
+        let engine = Engine { handle: keymagic_engine_new() };
+        if engine.handle.is_null() {
+            return Err(EngineError::Call("keymagic_engine_new", KeyMagicResult::ErrorEngineFailure));
+        }
+        match keymagic_engine_load_keyboard(engine.handle, path_text.as_ptr()) {
+            KeyMagicResult::Success => Ok(engine),
+            result => {
+                let reason = take_string(keymagic_engine_last_error()).unwrap_or_else(|| format!("{:?}", result));
+                Err(EngineError::Load(reason))
+            }
+        }
+    }
+
+    /// Sends one key to the engine
+    pub fn process(&mut self, key: &HostKey) -> Result<KeyOutput, EngineError> {
+        let mut output = zeroed_output();
+        let character = key.character.filter(char::is_ascii).map_or(0, |ch| ch as u8 as c_char);
+        let result = keymagic_engine_process_key_win(
+            self.handle,
+            key.vk as i32,
+            character,
+            key.shift as i32,
+            key.ctrl as i32,
+            key.alt as i32,
+            key.caps_lock as i32,
+            &mut output,
+        );
+
+        // The strings are the host's to free whatever the result
+        let _ = take_string(output.text);
+        let composing_text = take_string(output.composing_text).unwrap_or_default();
+        let notification = take_string(output.notification);
+        match result {
+            KeyMagicResult::Success => Ok(KeyOutput { composing_text, is_processed: output.is_processed != 0, notification }),
+            result => Err(EngineError::Call("keymagic_engine_process_key_win", result)),
+        }
+    }
+
+    /// Ends the composition and returns its text for the host to commit
+    pub fn flush(&mut self) -> String {
+        take_string(keymagic_engine_flush(self.handle)).unwrap_or_default()
+    }
+
+    /// Clears part of the engine state; the host drops its composition too
+    pub fn reset(&mut self, level: ResetLevel) -> Result<(), EngineError> {
+        match keymagic_engine_reset_level(self.handle, level as i32) {
+            KeyMagicResult::Success => Ok(()),
+            result => Err(EngineError::Call("keymagic_engine_reset_level", result)),
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        keymagic_engine_free(self.handle);
+    }
+}
+
+/// A `ProcessKeyOutput` with no strings
+fn zeroed_output() -> ProcessKeyOutput {
+    ProcessKeyOutput {
+        action_type: 0,
+        text: ptr::null_mut(),
+        delete_count: 0,
+        composing_text: ptr::null_mut(),
+        is_processed: 0,
+        delete_utf16_count: 0,
+        composing_caret_utf16: 0,
+        notification: ptr::null_mut(),
+        delete_clamped: 0,
+    }
+}
+
+/// Copies a string the engine returned and frees it; null gives `None`
+fn take_string(s: *mut c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    let text = unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned();
+    keymagic_free_string(s);
+    Some(text)
+}
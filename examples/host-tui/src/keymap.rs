@@ -0,0 +1,93 @@
+//! Terminal keys to the Windows virtual key codes the engine takes
+//!
+//! Terminals report the character a key typed, not the key. The engine
+//! matches rules on keys, so a typed character is mapped back to the key a
+//! US layout types it with, the way the engine's own tooling does
+//! (`keymagic_core::analysis::typed_key`). Caps Lock is not reported by
+//! terminals and is always off here.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use keymagic_core::analysis::typed_key;
+use keymagic_core::key_event::{KeyEventDto, KeyEventError};
+use keymagic_core::VirtualKey;
+
+/// A key as the host hands it to `keymagic_engine_process_key_win`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostKey {
+    /// Windows virtual key code
+    pub vk: u16,
+    /// The character the key types; the C interface only carries ASCII
+    pub character: Option<char>,
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub caps_lock: bool,
+}
+
+impl HostKey {
+    /// A key without a character
+    pub fn key(key: VirtualKey, shift: bool, ctrl: bool, alt: bool) -> Self {
+        Self { vk: key.to_win_vk(), character: None, shift, ctrl, alt, caps_lock: false }
+    }
+
+    /// Whether this is `key` with no modifiers other than Shift
+    pub fn is(&self, key: VirtualKey) -> bool {
+        self.vk == key.to_win_vk() && !self.ctrl && !self.alt
+    }
+
+    /// Whether this is Ctrl with the letter `letter`
+    pub fn is_ctrl(&self, letter: char) -> bool {
+        self.ctrl && !self.alt && self.vk == letter.to_ascii_uppercase() as u16
+    }
+
+    /// The key a compact key text event names, e.g. one of `"k{BKSP}<Ctrl+l>"`
+    pub fn from_event(event: &KeyEventDto) -> Result<Self, KeyEventError> {
+        let input = event.clone().with_us_character()?.to_key_input()?;
+        let key = VirtualKey::from_raw(input.key_code).ok_or(KeyEventError::UnknownKeyCode(input.key_code))?;
+        let modifiers = input.modifiers;
+        Ok(Self {
+            vk: key.to_win_vk(),
+            character: input.character,
+            shift: modifiers.shift,
+            ctrl: modifiers.ctrl,
+            alt: modifiers.alt,
+            caps_lock: modifiers.caps_lock,
+        })
+    }
+}
+
+/// The key for a terminal key press, or `None` for keys the engine has no
+/// code for (arrows, Home, End); those belong to the host alone
+pub fn host_key(event: &KeyEvent) -> Option<HostKey> {
+    let shift = event.modifiers.contains(KeyModifiers::SHIFT);
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    let alt = event.modifiers.contains(KeyModifiers::ALT);
+    let key = match event.code {
+        KeyCode::Char(ch) => {
+            let input = typed_key(ch);
+            // Characters off the US layout have no key to send
+            let key = VirtualKey::from_raw(input.key_code)?;
+            // Ctrl and Alt chords type no character
+            let character = if ctrl || alt { None } else { Some(ch) };
+            return Some(HostKey {
+                vk: key.to_win_vk(),
+                character,
+                shift: shift || input.modifiers.shift,
+                ctrl,
+                alt,
+                caps_lock: false,
+            });
+        }
+        KeyCode::Backspace => VirtualKey::Back,
+        KeyCode::Enter => VirtualKey::Return,
+        KeyCode::Tab | KeyCode::BackTab => VirtualKey::Tab,
+        KeyCode::Esc => VirtualKey::Escape,
+        KeyCode::Delete => VirtualKey::Delete,
+        KeyCode::PageUp => VirtualKey::Prior,
+        KeyCode::PageDown => VirtualKey::Next,
+        KeyCode::F(n @ 1..=12) => VirtualKey::from_win_vk(0x70 + n as u16 - 1)?,
+        _ => return None,
+    };
+    let shift = shift || event.code == KeyCode::BackTab;
+    Some(HostKey::key(key, shift, ctrl, alt))
+}
@@ -0,0 +1,180 @@
+//! # keymagic-host-tui
+//!
+//! An example host for the KeyMagic engine, in a terminal. It drives the
+//! engine through the C interface (`keymagic_core::ffi`, declared for C in
+//! `keymagic-windows/shared/include/keymagic_ffi.h`) rather than the Rust API,
+//! so that it shows what a host in any language has to do. It also serves to
+//! try a keyboard on any platform with a terminal.
+//!
+//! ## Running
+//!
+//! ```text
+//! cargo run -p keymagic-host-tui -- path/to/keyboard.km2
+//! ```
+//!
+//! The top pane holds the committed text, the one below it the composition.
+//! On quitting, the committed text is printed.
+//!
+//! | Key       | Action                                                   |
+//! |-----------|----------------------------------------------------------|
+//! | F9        | Act as if the terminal lost focus: commit the composition |
+//! | Esc       | Composing reset: drop the composition, keep states       |
+//! | Ctrl+L    | Context reset: also turn the states off                  |
+//! | Ctrl+R    | Full reset                                               |
+//! | Ctrl+Q    | Quit (also Ctrl+C)                                       |
+//!
+//! Terminals that report focus changes commit on real focus loss as well.
+//!
+//! ## Headless
+//!
+//! `--script` types keys in the compact key text of `keymagic_core::key_event`
+//! instead of reading the terminal, then prints the panes:
+//!
+//! ```text
+//! $ keymagic-host-tui keyboard.km2 --script "ka{F9}k"
+//! committed: က
+//! composing: က
+//! ```
+//!
+//! ## What a host must get right
+//!
+//! - Free every string the engine returns with `keymagic_free_string`, even
+//!   the ones it does not use ([`engine`]).
+//! - Zero `ProcessKeyOutput` before each call, since a rejected key code
+//!   leaves it as it was ([`engine`]).
+//! - Call an engine handle from one thread only, in key order ([`engine`]).
+//! - Hand unprocessed keys back to the text field after committing the
+//!   composition ([`app`]).
+//! - Commit on focus loss, and reset with the level that fits: Composing
+//!   within a text, Context when the text around the caret changes ([`app`]).
+//! - Send Windows virtual key codes with the character the key typed; the
+//!   engine matches rules on keys ([`keymap`]).
+
+mod app;
+mod engine;
+mod keymap;
+
+use app::App;
+use clap::Parser;
+use crossterm::event::{self, DisableFocusChange, EnableFocusChange, Event, KeyEventKind};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue, style};
+use engine::Engine;
+use keymagic_core::key_event::parse_key_sequence;
+use keymap::HostKey;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Setup failed: the keyboard could not be loaded or the terminal set up
+const EXIT_SETUP: u8 = 1;
+/// The script does not parse
+const EXIT_SCRIPT: u8 = 3;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Example KeyMagic host: type through a keyboard in the terminal", long_about = None)]
+#[command(after_help = "Keys: F9 simulate focus loss, Esc composing reset, Ctrl+L context reset, \
+Ctrl+R full reset, Ctrl+Q quit.\n\n\
+Exit codes: 0 success, 1 setup failed, 2 bad arguments, 3 the script does not parse")]
+struct Args {
+    /// Keyboard file (.km2)
+    keyboard: PathBuf,
+
+    /// Type these keys (compact key text, e.g. "ka{BKSP}<Ctrl+l>") and print the panes instead of opening the UI
+    #[arg(long)]
+    script: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let engine = match Engine::load(&args.keyboard) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::from(EXIT_SETUP);
+        }
+    };
+    let mut app = App::new(engine);
+
+    match args.script {
+        Some(script) => run_script(&mut app, &script),
+        None => match run_terminal(&mut app) {
+            Ok(()) => {
+                println!("{}", app.committed());
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ExitCode::from(EXIT_SETUP)
+            }
+        },
+    }
+}
+
+/// Types the script and prints the panes
+fn run_script(app: &mut App, script: &str) -> ExitCode {
+    let keys = parse_key_sequence(script).and_then(|events| events.iter().map(HostKey::from_event).collect::<Result<Vec<_>, _>>());
+    let keys = match keys {
+        Ok(keys) => keys,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::from(EXIT_SCRIPT);
+        }
+    };
+
+    for key in keys {
+        app.handle_key(Some(key));
+    }
+    println!("committed: {}", app.committed().escape_debug());
+    println!("composing: {}", app.composing().escape_debug());
+    if !app.status().is_empty() {
+        println!("status: {}", app.status());
+    }
+    ExitCode::SUCCESS
+}
+
+/// Puts the terminal back however the UI ends
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!(io::stdout(), EnterAlternateScreen, EnableFocusChange)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), DisableFocusChange, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Runs the UI until the user quits
+fn run_terminal(app: &mut App) -> io::Result<()> {
+    let _guard = TerminalGuard::enter()?;
+    loop {
+        draw(app)?;
+        match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => app.handle_key(keymap::host_key(&key)),
+            Event::FocusLost => app.focus_lost(),
+            Event::FocusGained => app.focus_gained(),
+            _ => {}
+        }
+        if app.should_quit() {
+            return Ok(());
+        }
+    }
+}
+
+fn draw(app: &App) -> io::Result<()> {
+    let (width, _) = terminal::size()?;
+    let mut stdout = io::stdout();
+    queue!(stdout, terminal::Clear(terminal::ClearType::All))?;
+    for (row, line) in app.render_lines(width as usize).iter().enumerate() {
+        queue!(stdout, cursor::MoveTo(0, row as u16), style::Print(line))?;
+    }
+    stdout.flush()
+}
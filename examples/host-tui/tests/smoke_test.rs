@@ -0,0 +1,79 @@
+//! Drives the example host headlessly with scripted keys
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Compiles the fixture keyboard to a km2 file for this test
+fn fixture_keyboard(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("keymagic-host-tui-{}-{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("demo.km2");
+    let kms = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/demo.kms");
+    kms2km2::convert_kms_to_km2(&kms, &path).unwrap();
+    path
+}
+
+fn run_script(test: &str, script: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_keymagic-host-tui"))
+        .arg(fixture_keyboard(test))
+        .args(["--script", script])
+        .output()
+        .unwrap()
+}
+
+/// The committed and composing panes after the script
+fn panes(test: &str, script: &str) -> (String, String) {
+    let output = run_script(test, script);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let pane = |name: &str| {
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .unwrap_or_else(|| panic!("no {} line in {}", name, stdout))
+            .to_string()
+    };
+    (pane("committed: "), pane("composing: "))
+}
+
+#[test]
+fn test_composes_until_committed() {
+    assert_eq!(panes("compose", "ka"), (String::new(), "ကာ".to_string()));
+    // Enter commits, then adds the line the engine left to the host
+    assert_eq!(panes("enter", "ka{VK_RETURN}k"), ("ကာ\\n".to_string(), "က".to_string()));
+    // A space ending the composition commits it
+    assert_eq!(panes("space", "k k"), ("က ".to_string(), "က".to_string()));
+}
+
+#[test]
+fn test_focus_loss_flushes() {
+    assert_eq!(panes("focus", "ka{F9}k"), ("ကာ".to_string(), "က".to_string()));
+}
+
+#[test]
+fn test_reset_levels() {
+    // Esc drops the composition but the state stays on for the next key
+    assert_eq!(panes("composing", "k{F2}{ESCAPE}1"), (String::new(), "၁".to_string()));
+    // A context reset turns the state off
+    assert_eq!(panes("context", "k{F2}<Ctrl+l>1"), (String::new(), "1".to_string()));
+    assert_eq!(panes("full", "k{F2}<Ctrl+r>1"), (String::new(), "1".to_string()));
+}
+
+#[test]
+fn test_unprocessed_backspace_edits_committed_text() {
+    assert_eq!(panes("backspace", "ka{F9}{BKSP}"), ("က".to_string(), String::new()));
+}
+
+#[test]
+fn test_errors() {
+    let output = run_script("script", "k{NOT_A_KEY}");
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_keymagic-host-tui"))
+        .arg(std::env::temp_dir().join("keymagic-host-tui-missing.km2"))
+        .args(["--script", "k"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to load keyboard"));
+}
@@ -0,0 +1,134 @@
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use keymagic_core::analysis::typed_key;
+use keymagic_core::env_config::EngineConfig;
+use keymagic_core::KeyMagicEngine;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Setup failed: bad config, or the keyboard could not be loaded
+const EXIT_SETUP: u8 = 1;
+/// Some input could not be typed; the other lines were still written
+const EXIT_TYPING: u8 = 3;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Types text through a KeyMagic keyboard and prints the committed output", long_about = None)]
+#[command(after_help = "The keyboard and options can also come from KEYMAGIC_KEYBOARD, KEYMAGIC_OPTIONS_JSON \
+and a JSON config file named by KEYMAGIC_CONFIG; arguments win over them.\n\n\
+Exit codes: 0 success, 1 setup failed, 2 bad arguments, 3 some input could not be typed")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(ClapArgs, Debug)]
+struct EngineArgs {
+    /// Keyboard file (.km2)
+    #[arg(long, short)]
+    keyboard: Option<PathBuf>,
+
+    /// Layout option override, e.g. auto_bksp=true (repeatable)
+    #[arg(long = "option", short = 'o', value_parser = parse_option)]
+    options: Vec<(String, bool)>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Type one text and print the result
+    Type {
+        #[command(flatten)]
+        engine: EngineArgs,
+
+        /// Text to type, one key per character on a US layout
+        #[arg(long, short)]
+        text: String,
+    },
+    /// Type each input line from an empty composition and print one output line per input line
+    Batch {
+        #[command(flatten)]
+        engine: EngineArgs,
+
+        /// Read the lines from standard input
+        #[arg(long, required = true)]
+        stdin: bool,
+    },
+}
+
+fn parse_option(text: &str) -> Result<(String, bool), String> {
+    let (name, value) = text.split_once('=').ok_or_else(|| format!("expected NAME=true|false, got '{}'", text))?;
+    let value = value.parse::<bool>().map_err(|_| format!("expected true or false for '{}', got '{}'", name, value))?;
+    Ok((name.to_string(), value))
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let (engine_args, input) = match args.command {
+        Command::Type { engine, text } => (engine, Input::Text(text)),
+        Command::Batch { engine, .. } => (engine, Input::Stdin),
+    };
+
+    let mut engine = match create_engine(engine_args) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::from(EXIT_SETUP);
+        }
+    };
+
+    let typed = match input {
+        Input::Text(text) => type_lines(&mut engine, [Ok(text)]),
+        Input::Stdin => type_lines(&mut engine, io::stdin().lock().lines()),
+    };
+    match typed {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(EXIT_TYPING),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(EXIT_TYPING)
+        }
+    }
+}
+
+enum Input {
+    Text(String),
+    Stdin,
+}
+
+fn create_engine(args: EngineArgs) -> Result<KeyMagicEngine, String> {
+    let config = EngineConfig::from_env().map_err(|e| e.to_string())?;
+    let config = config.merge(EngineConfig {
+        keyboard: args.keyboard,
+        options: args.options.into_iter().collect(),
+    });
+    if config.keyboard.is_none() {
+        return Err("No keyboard given; use --keyboard or set KEYMAGIC_KEYBOARD".to_string());
+    }
+    config.load_engine().map_err(|e| e.to_string())
+}
+
+/// Types each line and writes what it commits. A line that fails writes an
+/// empty line so output stays aligned with input; returns false if any did.
+fn type_lines(engine: &mut KeyMagicEngine, lines: impl IntoIterator<Item = io::Result<String>>) -> io::Result<bool> {
+    let mut stdout = io::stdout().lock();
+    let mut all_typed = true;
+    for (number, line) in lines.into_iter().enumerate() {
+        let line = line?;
+        let output = type_text(engine, line.trim_end_matches('\r')).unwrap_or_else(|e| {
+            eprintln!("Line {}: {}", number + 1, e);
+            all_typed = false;
+            String::new()
+        });
+        writeln!(stdout, "{}", output)?;
+    }
+    stdout.flush()?;
+    Ok(all_typed)
+}
+
+/// Text committed by typing `text` from an empty composition
+fn type_text(engine: &mut KeyMagicEngine, text: &str) -> keymagic_core::Result<String> {
+    engine.reset();
+    for ch in text.chars() {
+        engine.process_key(typed_key(ch))?;
+    }
+    Ok(engine.composing_text().to_string())
+}
@@ -0,0 +1,123 @@
+//! Runs the keymagic-cli binary against a fixture keyboard
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// Compiles the fixture keyboard to a km2 file for this test
+fn fixture_keyboard(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("keymagic-cli-{}-{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("basic.km2");
+    let kms = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/basic.kms");
+    kms2km2::convert_kms_to_km2(&kms, &path).unwrap();
+    path
+}
+
+fn cli() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_keymagic-cli"));
+    for var in ["KEYMAGIC_CONFIG", "KEYMAGIC_KEYBOARD", "KEYMAGIC_OPTIONS_JSON"] {
+        command.env_remove(var);
+    }
+    command
+}
+
+fn run_batch(mut command: Command, input: &str) -> Output {
+    let mut child = command
+        .args(["batch", "--stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn test_type_text() {
+    let keyboard = fixture_keyboard("type");
+    let output = cli().args(["type", "--keyboard"]).arg(&keyboard).args(["--text", "ka kyi"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "ကာ ကျိ\n");
+}
+
+#[test]
+fn test_batch_from_stdin() {
+    let keyboard = fixture_keyboard("batch");
+    let mut command = cli();
+    command.env("KEYMAGIC_KEYBOARD", &keyboard);
+
+    // Each line starts from an empty composition; blank lines stay blank
+    let output = run_batch(command, "ka\r\n\nkyi\n");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "ကာ\n\nကျိ\n");
+}
+
+#[test]
+fn test_options_from_arguments_and_environment() {
+    let keyboard = fixture_keyboard("options");
+    let output = cli().args(["type", "-k"]).arg(&keyboard).args(["-t", "KA"]).output().unwrap();
+    assert_eq!(stdout(&output), "KA\n");
+
+    let output = cli()
+        .args(["type", "-k"])
+        .arg(&keyboard)
+        .args(["-t", "KA", "-o", "case_insensitive_ascii=true"])
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "ကာ\n");
+
+    // Arguments win over the environment
+    let with_env = || {
+        let mut command = cli();
+        command.env("KEYMAGIC_KEYBOARD", &keyboard).env("KEYMAGIC_OPTIONS_JSON", r#"{"case_insensitive_ascii": true}"#);
+        command
+    };
+    let output = with_env().args(["type", "-t", "KA"]).output().unwrap();
+    assert_eq!(stdout(&output), "ကာ\n");
+    let output = with_env().args(["type", "-t", "KA", "-o", "case_insensitive_ascii=false"]).output().unwrap();
+    assert_eq!(stdout(&output), "KA\n");
+}
+
+#[test]
+fn test_config_file() {
+    let keyboard = fixture_keyboard("config");
+    let config = keyboard.with_file_name("keymagic.json");
+    std::fs::write(&config, r#"{"keyboard": "basic.km2", "options": {"case_insensitive_ascii": true}}"#).unwrap();
+
+    let output = cli().env("KEYMAGIC_CONFIG", &config).args(["type", "-t", "Kyi"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "ကျိ\n");
+}
+
+#[test]
+fn test_exit_codes() {
+    // No keyboard
+    let output = cli().args(["type", "-t", "ka"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("KEYMAGIC_KEYBOARD"));
+
+    // Not a keyboard file
+    let keyboard = fixture_keyboard("exit-codes");
+    let broken = keyboard.with_file_name("broken.km2");
+    std::fs::write(&broken, b"not a keyboard").unwrap();
+    let output = cli().args(["type", "-k"]).arg(&broken).args(["-t", "ka"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    // Bad option value and unknown option
+    let output = cli().args(["type", "-k"]).arg(&keyboard).args(["-t", "ka", "-o", "eat=maybe"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let output = cli().args(["type", "-k"]).arg(&keyboard).args(["-t", "ka", "-o", "smart_quotes=true"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let output = cli().env("KEYMAGIC_OPTIONS_JSON", "[true]").args(["type", "-k"]).arg(&keyboard).args(["-t", "ka"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    // batch needs --stdin
+    let output = cli().args(["batch", "-k"]).arg(&keyboard).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,88 @@
+//! Load time and string memory of `Km2Loader::load`
+//!
+//! Run with `cargo bench -p keymagic-core --bench km2_load`. "eager" is the
+//! load followed by decoding every string, which is what loading cost before
+//! strings were decoded on first use; "lazy" is the load alone plus the
+//! strings a few keystrokes need.
+
+use std::hint::black_box;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use keymagic_core::km2::Km2Loader;
+use keymagic_core::{ModifierState, KeyInput, KeyMagicEngine, Km2File, VirtualKey};
+
+const ROUNDS: usize = 50;
+
+/// A keyboard with `count` long output variables, each used by one rule
+fn large_keyboard(count: usize) -> Vec<u8> {
+    let mut kms = String::new();
+    for i in 0..count {
+        let text: String = (0..200).map(|j| char::from_u32(0x1000 + ((i + j) % 0x40) as u32).unwrap()).collect();
+        kms.push_str(&format!("$v{} = \"{}{}\"\n", i, i, text));
+    }
+    for i in 0..count {
+        kms.push_str(&format!("\"k{}\" => $v{}\n", i, i));
+    }
+    kms.push_str("\"k\" => \"က\"\n");
+
+    let mut buffer = Vec::new();
+    keymagic_core::km2::Km2Writer::new(&mut buffer)
+        .write_km2_file(&kms2km2::compile_kms(&kms).unwrap())
+        .unwrap();
+    buffer
+}
+
+fn median(mut samples: Vec<Duration>) -> Duration {
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+fn time(mut f: impl FnMut()) -> Duration {
+    median(
+        (0..ROUNDS)
+            .map(|_| {
+                let start = Instant::now();
+                f();
+                start.elapsed()
+            })
+            .collect(),
+    )
+}
+
+fn decoded_bytes(km2: &Km2File) -> usize {
+    km2.strings.decoded().map(|entry| entry.value.capacity()).sum()
+}
+
+fn report(name: &str, data: &[u8]) {
+    let eager = time(|| {
+        let km2 = Km2Loader::load(black_box(data)).unwrap();
+        black_box(km2.strings.iter().count());
+    });
+    let lazy = time(|| {
+        black_box(Km2Loader::load(black_box(data)).unwrap());
+    });
+
+    let km2 = Km2Loader::load(data).unwrap();
+    let mut engine = KeyMagicEngine::new(km2.clone()).unwrap();
+    for _ in 0..5 {
+        engine.process_key(KeyInput::new(VirtualKey::KeyK as u16, ModifierState::default(), Some('k'))).unwrap();
+    }
+    let typed = decoded_bytes(&km2);
+    km2.strings.iter().count();
+    let all = decoded_bytes(&km2);
+
+    println!("{} ({} bytes, {} strings)", name, data.len(), km2.strings.len());
+    println!("  load, eager: {:?}", eager);
+    println!("  load, lazy:  {:?}", lazy);
+    println!("  decoded strings after 5 keys: {} of {} bytes", typed, all);
+}
+
+fn main() {
+    let bundled = Path::new(env!("CARGO_MANIFEST_DIR")).join("../keyboards/bundled/ZawCode.km2");
+    match std::fs::read(&bundled) {
+        Ok(data) => report("ZawCode.km2", &data),
+        Err(e) => println!("Skipping {}: {}", bundled.display(), e),
+    }
+    report("generated, 2000 variables", &large_keyboard(2000));
+}
@@ -1,4 +1,5 @@
-use keymagic_core::{km2::Km2Loader, Metadata};
+use keymagic_core::km2::Km2Loader;
+use keymagic_core::types::km2::Metadata;
 use std::env;
 use std::fs;
 
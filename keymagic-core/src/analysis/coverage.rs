@@ -0,0 +1,159 @@
+//! Dynamic rule coverage from a corpus of typed key sequences
+
+use crate::engine::{KeyInput, ModifierState};
+use crate::error::Result;
+use crate::km2::RuleFormatter;
+use crate::{KeyMagicEngine, VirtualKey};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Hit count of a single rule
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RuleCoverage {
+    /// Rule index
+    pub rule: usize,
+    /// The rule in KMS-like syntax
+    pub text: String,
+    /// Number of times the rule was applied, recursive applications included
+    pub hits: usize,
+}
+
+/// Coverage of a corpus over all rules of a keyboard
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CoverageReport {
+    /// Number of corpus lines typed
+    pub lines: usize,
+    /// Number of keys typed
+    pub keys: usize,
+    /// Per-rule hit counts, in rule order
+    pub rules: Vec<RuleCoverage>,
+    /// Indices of rules that were never applied
+    pub unhit: Vec<usize>,
+    /// Percentage of rules applied at least once
+    pub covered_percent: f64,
+}
+
+/// Shifted characters of the digit keys 0-9 on a US layout
+const SHIFTED_DIGITS: [char; 10] = [')', '!', '@', '#', '$', '%', '^', '&', '*', '('];
+
+/// Punctuation keys on a US layout: Windows VK code, unshifted, shifted
+const US_PUNCTUATION: &[(u16, char, char)] = &[
+    (0xC0, '`', '~'),
+    (0xBD, '-', '_'),
+    (0xBB, '=', '+'),
+    (0xDB, '[', '{'),
+    (0xDD, ']', '}'),
+    (0xDC, '\\', '|'),
+    (0xBA, ';', ':'),
+    (0xDE, '\'', '"'),
+    (0xBC, ',', '<'),
+    (0xBE, '.', '>'),
+    (0xBF, '/', '?'),
+];
+
+/// Builds the key press that produces `ch` on a US layout. Characters that
+/// have no key are sent as character-only input.
+pub fn typed_key(ch: char) -> KeyInput {
+    let key = match ch {
+        'a'..='z' => Some((ch.to_ascii_uppercase() as u16, false)),
+        'A'..='Z' | '0'..='9' => Some((ch as u16, ch.is_ascii_uppercase())),
+        ' ' => Some((0x20, false)),
+        _ => SHIFTED_DIGITS
+            .iter()
+            .position(|c| *c == ch)
+            .map(|digit| (0x30 + digit as u16, true))
+            .or_else(|| {
+                US_PUNCTUATION.iter().find_map(|(vk, unshifted, shifted)| {
+                    if *unshifted == ch {
+                        Some((*vk, false))
+                    } else if *shifted == ch {
+                        Some((*vk, true))
+                    } else {
+                        None
+                    }
+                })
+            }),
+    };
+
+    match key.and_then(|(vk, shift)| VirtualKey::from_win_vk(vk).map(|key| (key, shift))) {
+        Some((key, shift)) => KeyInput::new(key as u16, ModifierState::new(shift, false, false, false), Some(ch)),
+        None => KeyInput::from_char(ch),
+    }
+}
+
+/// The character a key types on a US layout; Caps Lock only changes letters
+pub fn us_character(key: VirtualKey, shift: bool, caps_lock: bool) -> Option<char> {
+    let vk = key.to_win_vk();
+    match vk {
+        0x41..=0x5A => {
+            let upper = (vk as u8) as char;
+            Some(if shift != caps_lock { upper } else { upper.to_ascii_lowercase() })
+        }
+        0x30..=0x39 if shift => Some(SHIFTED_DIGITS[(vk - 0x30) as usize]),
+        0x30..=0x39 => Some((vk as u8) as char),
+        0x20 => Some(' '),
+        _ => US_PUNCTUATION
+            .iter()
+            .find(|(code, _, _)| *code == vk)
+            .map(|(_, unshifted, shifted)| if shift { *shifted } else { *unshifted }),
+    }
+}
+
+/// Types every corpus line into the engine, starting each line from an empty
+/// composition, and counts which rules were applied
+pub fn run_coverage<'a, I>(engine: &mut KeyMagicEngine, corpus: I) -> Result<CoverageReport>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let rule_count = engine.keyboard().rules.len();
+    let mut hits = vec![0usize; rule_count];
+    let mut lines = 0;
+    let mut keys = 0;
+
+    for line in corpus {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            continue;
+        }
+        lines += 1;
+
+        engine.reset();
+        for ch in line.chars() {
+            engine.process_key(typed_key(ch))?;
+            keys += 1;
+            for &rule in engine.last_matched_rules() {
+                hits[rule] += 1;
+            }
+        }
+    }
+    engine.reset();
+
+    let keyboard = engine.keyboard();
+    let formatter = RuleFormatter::new(&keyboard.strings);
+    let rules: Vec<RuleCoverage> = hits
+        .iter()
+        .enumerate()
+        .map(|(rule, &hits)| RuleCoverage {
+            rule,
+            text: formatter.format_rule(&keyboard.rules[rule]),
+            hits,
+        })
+        .collect();
+    let unhit: Vec<usize> = rules.iter().filter(|r| r.hits == 0).map(|r| r.rule).collect();
+    let covered_percent = if rule_count == 0 {
+        100.0
+    } else {
+        (rule_count - unhit.len()) as f64 * 100.0 / rule_count as f64
+    };
+
+    Ok(CoverageReport {
+        lines,
+        keys,
+        rules,
+        unhit,
+        covered_percent,
+    })
+}
@@ -0,0 +1,66 @@
+//! Static detection of rules deleting more than the engine lets a key delete
+//!
+//! A rule replaces the text it matched before the key. When that text was
+//! typed with the keyboard the engine deletes it freely, but text the host
+//! synced back (after the cursor moved) may only be deleted up to the
+//! engine's delete slack; larger deletes are clamped and the host warns that
+//! the keyboard misbehaves. Such rules are reported here.
+
+use crate::engine::matching::PatternElement;
+use crate::km2::RuleFormatter;
+use crate::types::km2::BinaryFormatElement;
+use crate::KeyMagicEngine;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// A rule whose delete is clamped when the text it matched came from the host
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LongDelete {
+    /// Index of the rule
+    pub rule: usize,
+    /// The rule in KMS-like syntax
+    pub text: String,
+    /// Characters before the key the rule deletes at most
+    pub deletes: usize,
+}
+
+/// Finds the rules that delete more characters before the key than the
+/// engine's delete slack
+///
+/// The count is an upper bound: the matched text before the key, less the
+/// literal text the output starts with when the pattern starts with it too.
+pub fn find_long_deletes(engine: &KeyMagicEngine) -> Vec<LongDelete> {
+    let keyboard = engine.keyboard();
+    let formatter = RuleFormatter::new(&keyboard.strings);
+    let slack = engine.delete_slack();
+
+    let mut long_deletes = Vec::new();
+    for (index, pattern) in engine.prioritized_rules() {
+        let Some(length) = pattern.calculate_match_length(engine.strings()) else {
+            continue;
+        };
+        // Without a virtual key the typed character ends the match; it is
+        // not in the text before the key
+        let before_key = if pattern.has_vk() { length } else { length.saturating_sub(1) };
+
+        let kept = match (pattern.elements.first(), keyboard.rules[index].rhs.first()) {
+            (Some(PatternElement::String(matched)), Some(BinaryFormatElement::String(output))) => {
+                matched.chars().zip(output.chars()).take_while(|(a, b)| a == b).count()
+            }
+            _ => 0,
+        };
+        let deletes = before_key.saturating_sub(kept);
+        if deletes > slack {
+            long_deletes.push(LongDelete {
+                rule: index,
+                text: formatter.format_rule(&keyboard.rules[index]),
+                deletes,
+            });
+        }
+    }
+
+    long_deletes.sort_by_key(|d| d.rule);
+    long_deletes
+}
@@ -0,0 +1,69 @@
+//! Rule analysis for keyboard authors
+//!
+//! Two complementary checks:
+//! - static shadowing detection: rules that can never match because a rule
+//!   checked earlier (see the priority order in the engine) matches every
+//!   input they would match
+//! - dynamic coverage: a corpus of typed key sequences is run through the
+//!   engine and the rules that fired are counted
+//!
+//! Rules deleting more text before the key than the engine's delete slack are
+//! reported as well, see [`find_long_deletes`].
+//!
+//! A sample text can also be typed to measure how many rules each key scans
+//! and which rules match most, see [`run_performance`].
+//!
+//! Rule indices in reports are 0-based positions in `Km2File::rules`, which
+//! follow the order of the rules in the source file.
+
+mod coverage;
+mod deletes;
+mod performance;
+mod shadowing;
+
+pub use coverage::{run_coverage, typed_key, us_character, CoverageReport, RuleCoverage};
+pub use deletes::{find_long_deletes, LongDelete};
+pub use performance::{run_performance, HotRule, PerformanceReport};
+pub use shadowing::{find_shadowed_rules, ShadowedRule};
+
+use crate::error::Result;
+use crate::{KeyMagicEngine, Km2File};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Combined static and dynamic analysis of a keyboard
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct AnalysisReport {
+    /// Number of rules in the keyboard
+    pub rule_count: usize,
+    /// Rules that can never match
+    pub shadowed: Vec<ShadowedRule>,
+    /// Rules whose delete is clamped when they rewrite text from the host
+    pub long_deletes: Vec<LongDelete>,
+    /// Corpus coverage, when a corpus was given
+    pub coverage: Option<CoverageReport>,
+}
+
+/// Analyzes a keyboard, optionally running a corpus of key sequences
+/// (one sequence per line) to measure rule coverage
+pub fn analyze_keyboard<'a, I>(keyboard: &Km2File, corpus: Option<I>) -> Result<AnalysisReport>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut engine = KeyMagicEngine::new(keyboard.clone())?;
+    let shadowed = find_shadowed_rules(&engine);
+    let long_deletes = find_long_deletes(&engine);
+    let coverage = match corpus {
+        Some(lines) => Some(run_coverage(&mut engine, lines)?),
+        None => None,
+    };
+
+    Ok(AnalysisReport {
+        rule_count: keyboard.rules.len(),
+        shadowed,
+        long_deletes,
+        coverage,
+    })
+}
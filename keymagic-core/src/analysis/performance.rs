@@ -0,0 +1,96 @@
+//! Matching cost of a keyboard over a sample text
+
+use crate::error::Result;
+use crate::km2::RuleFormatter;
+use crate::KeyMagicEngine;
+
+use super::typed_key;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// A frequently matched rule
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct HotRule {
+    /// Rule index
+    pub rule: usize,
+    /// The rule in KMS-like syntax
+    pub text: String,
+    /// Number of times the rule matched
+    pub hits: u32,
+    /// Position of the rule in the order rules are tried; a high position
+    /// for a hot rule means every key scans many rules before reaching it
+    pub priority: usize,
+}
+
+/// How much rule scanning typing a sample text takes
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct PerformanceReport {
+    /// Number of rules in the keyboard
+    pub rule_count: usize,
+    /// Number of keys typed
+    pub keys: u64,
+    /// Rules tried over all keys, recursive matching included
+    pub rules_scanned: u64,
+    /// Rules tried per key
+    pub average_scanned_per_key: f64,
+    /// Most matched rules, hottest first
+    pub hottest: Vec<HotRule>,
+}
+
+/// Types every line of `sample` into the engine with the rule histogram
+/// enabled, starting each line from an empty composition, and reports the
+/// `top` most matched rules
+///
+/// The histogram is switched off again afterwards.
+pub fn run_performance<'a, I>(engine: &mut KeyMagicEngine, sample: I, top: usize) -> Result<PerformanceReport>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    engine.set_rule_histogram_enabled(true);
+    let typed = type_sample(engine, sample);
+    let histogram = engine.rule_histogram().cloned().unwrap_or_default();
+    engine.set_rule_histogram_enabled(false);
+    engine.reset();
+    typed?;
+
+    let keyboard = engine.keyboard();
+    let formatter = RuleFormatter::new(&keyboard.strings);
+    let hottest = histogram
+        .top(top)
+        .into_iter()
+        .map(|(rule, hits)| HotRule {
+            rule,
+            text: formatter.format_rule(&keyboard.rules[rule]),
+            hits,
+            priority: engine.rule_priority(rule).unwrap_or(rule),
+        })
+        .collect();
+
+    Ok(PerformanceReport {
+        rule_count: keyboard.rules.len(),
+        keys: histogram.keys(),
+        rules_scanned: histogram.rules_scanned(),
+        average_scanned_per_key: histogram.average_scanned_per_key(),
+        hottest,
+    })
+}
+
+fn type_sample<'a, I>(engine: &mut KeyMagicEngine, sample: I) -> Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    for line in sample {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            continue;
+        }
+        engine.reset();
+        for ch in line.chars() {
+            engine.process_key(typed_key(ch))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,199 @@
+//! Static detection of rules shadowed by higher-priority rules
+//!
+//! The check is conservative: a rule is only reported when an earlier rule
+//! provably matches every input it matches. Each pattern is reduced to the
+//! states it requires, what triggers it (the typed character or an exact key
+//! combination) and one character class per matched character. A rule A then
+//! covers a rule B when A needs no state B lacks, both have the same trigger,
+//! and A's character classes contain B's over the suffix A matches.
+
+use std::collections::BTreeSet;
+
+use crate::engine::matching::{Pattern, PatternElement, VariableMatch};
+use crate::km2::RuleFormatter;
+use crate::types::StringTable;
+use crate::{KeyMagicEngine, VirtualKey};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// A rule that can never match
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ShadowedRule {
+    /// Index of the unreachable rule
+    pub rule: usize,
+    /// The unreachable rule in KMS-like syntax
+    pub text: String,
+    /// Index of the earlier rule that always wins
+    pub shadowed_by: usize,
+    /// The winning rule in KMS-like syntax
+    pub shadowed_by_text: String,
+}
+
+/// Set of characters a single pattern position accepts
+#[derive(Debug, Clone)]
+enum CharClass {
+    /// Any of these characters
+    OneOf(BTreeSet<char>),
+    /// Any character except these
+    NoneOf(BTreeSet<char>),
+    /// Printable ASCII except space (`ANY`)
+    PrintableAscii,
+}
+
+/// Characters accepted by `ANY`
+fn printable_ascii() -> std::ops::RangeInclusive<char> {
+    '!'..='~'
+}
+
+impl CharClass {
+    fn single(ch: char) -> Self {
+        CharClass::OneOf(BTreeSet::from([ch]))
+    }
+
+    /// Whether every character accepted by `self` is accepted by `other`
+    fn is_subset_of(&self, other: &CharClass) -> bool {
+        use CharClass::*;
+        match (self, other) {
+            (OneOf(a), OneOf(b)) => a.is_subset(b),
+            (OneOf(a), NoneOf(b)) => a.is_disjoint(b),
+            (OneOf(a), PrintableAscii) => a.iter().all(|c| printable_ascii().contains(c)),
+            (NoneOf(_), OneOf(_)) | (NoneOf(_), PrintableAscii) => false,
+            (NoneOf(a), NoneOf(b)) => b.is_subset(a),
+            (PrintableAscii, OneOf(b)) => printable_ascii().all(|c| b.contains(&c)),
+            (PrintableAscii, NoneOf(b)) => !b.iter().any(|c| printable_ascii().contains(c)),
+            (PrintableAscii, PrintableAscii) => true,
+        }
+    }
+}
+
+/// Exact key combination required by a VK pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeyCombination {
+    key: VirtualKey,
+    shift: bool,
+    ctrl: bool,
+    alt: bool,
+}
+
+impl KeyCombination {
+    /// Mirrors the matcher: exactly one primary key, modifiers matched exactly.
+    /// Returns `None` for combinations that can never match.
+    fn from_keys(keys: &[VirtualKey]) -> Option<Self> {
+        let mut primary = None;
+        let (mut shift, mut ctrl, mut alt) = (false, false, false);
+        for key in keys {
+            match key {
+                VirtualKey::Shift => shift = true,
+                VirtualKey::Control => ctrl = true,
+                VirtualKey::Menu => alt = true,
+                other if primary.is_none() => primary = Some(*other),
+                _ => return None,
+            }
+        }
+        primary.map(|key| Self { key, shift, ctrl, alt })
+    }
+}
+
+/// What has to be pressed for a pattern to match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    /// Matched against the composing text plus the typed character
+    /// (also eligible during recursive matching)
+    Text,
+    /// Matched against the composing text when this key combination is pressed
+    Key(KeyCombination),
+}
+
+/// Normalized form of a pattern used for the coverage comparison
+#[derive(Debug, Clone)]
+struct RuleShape {
+    states: BTreeSet<usize>,
+    trigger: Trigger,
+    text: Vec<CharClass>,
+}
+
+impl RuleShape {
+    /// Returns `None` for patterns that can never match
+    fn from_pattern(pattern: &Pattern, strings: &StringTable) -> Option<Self> {
+        let mut states = BTreeSet::new();
+        let mut trigger = Trigger::Text;
+        let mut text = Vec::new();
+
+        for element in &pattern.elements {
+            match element {
+                PatternElement::String(s) => text.extend(s.chars().map(CharClass::single)),
+                PatternElement::Variable(idx, var_match) => {
+                    let content = strings.get_str(*idx)?;
+                    match var_match {
+                        VariableMatch::Exact => text.extend(content.chars().map(CharClass::single)),
+                        VariableMatch::AnyOf => text.push(CharClass::OneOf(content.chars().collect())),
+                        VariableMatch::NotAnyOf => text.push(CharClass::NoneOf(content.chars().collect())),
+                    }
+                }
+                PatternElement::Any => text.push(CharClass::PrintableAscii),
+                PatternElement::State(idx) => {
+                    states.insert(*idx);
+                }
+                PatternElement::VirtualKey(keys) => {
+                    let combination = KeyCombination::from_keys(keys)?;
+                    match trigger {
+                        // Several key elements must all describe the same key press
+                        Trigger::Key(existing) if existing != combination => return None,
+                        _ => trigger = Trigger::Key(combination),
+                    }
+                }
+            }
+        }
+
+        Some(Self { states, trigger, text })
+    }
+
+    /// Whether `self` matches every input that `other` matches
+    fn covers(&self, other: &RuleShape) -> bool {
+        if !self.states.is_subset(&other.states)
+            || self.trigger != other.trigger
+            || self.text.len() > other.text.len()
+        {
+            return false;
+        }
+
+        // Both patterns are anchored at the end of the text
+        let offset = other.text.len() - self.text.len();
+        self.text
+            .iter()
+            .zip(&other.text[offset..])
+            .all(|(mine, theirs)| theirs.is_subset_of(mine))
+    }
+}
+
+/// Finds rules that can never match because an earlier rule in priority
+/// order always matches first
+pub fn find_shadowed_rules(engine: &KeyMagicEngine) -> Vec<ShadowedRule> {
+    let keyboard = engine.keyboard();
+    let formatter = RuleFormatter::new(&keyboard.strings);
+    let format = |index: usize| formatter.format_rule(&keyboard.rules[index]);
+
+    let mut checked: Vec<(usize, RuleShape)> = Vec::new();
+    let mut shadowed = Vec::new();
+
+    for (index, pattern) in engine.prioritized_rules() {
+        let Some(shape) = RuleShape::from_pattern(pattern, engine.strings()) else {
+            continue;
+        };
+
+        if let Some((winner, _)) = checked.iter().find(|(_, earlier)| earlier.covers(&shape)) {
+            shadowed.push(ShadowedRule {
+                rule: index,
+                text: format(index),
+                shadowed_by: *winner,
+                shadowed_by_text: format(*winner),
+            });
+        }
+        checked.push((index, shape));
+    }
+
+    shadowed.sort_by_key(|s| s.rule);
+    shadowed
+}
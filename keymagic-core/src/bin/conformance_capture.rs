@@ -0,0 +1,167 @@
+//! Captures expected conformance output from the legacy KeyMagic 2 engine
+//!
+//! Usage: conformance_capture <shim.dll> <keyboard.km2> <cases.txt>
+//!
+//! The legacy engine (libkeymagic) only has a C++ API, so it is driven
+//! through a thin shim DLL built against it that exports these C functions:
+//!
+//! ```c
+//! void* km2_engine_new(void);
+//! void  km2_engine_free(void* engine);
+//! int   km2_engine_load_keyboard(void* engine, const wchar_t* path);  // nonzero on success
+//! void  km2_engine_reset(void* engine);
+//! // keyval: character typed (0 if none), keycode: Windows VK, modifier: KeyMagic 2 modifier flags
+//! int   km2_engine_process_key(void* engine, int keyval, int keycode, int modifier);
+//! // Copies the context text (UTF-16, NUL-terminated); returns its length in UTF-16 units
+//! int   km2_engine_get_context_text(void* engine, wchar_t* buf, int buf_len);
+//! ```
+//!
+//! Every case in the case file is typed from a reset engine and the file is
+//! rewritten with the context text after each key. Comments in the case file
+//! are not preserved.
+
+#[cfg(windows)]
+mod legacy {
+    use std::ffi::{c_void, OsStr};
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    use keymagic_core::conformance::{ConformanceCase, KeyEvent};
+    use keymagic_core::VirtualKey;
+
+    type Handle = *mut c_void;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn LoadLibraryW(name: *const u16) -> Handle;
+        fn GetProcAddress(module: Handle, name: *const u8) -> *const c_void;
+    }
+
+    // KeyMagic 2 modifier flags
+    const SHIFT_MASK: i32 = 1 << 0;
+    const CTRL_MASK: i32 = 1 << 1;
+    const ALT_MASK: i32 = 1 << 2;
+
+    fn wide(s: &OsStr) -> Vec<u16> {
+        s.encode_wide().chain(Some(0)).collect()
+    }
+
+    /// Functions exported by the shim DLL
+    pub struct LegacyEngine {
+        engine: Handle,
+        free: extern "C" fn(Handle),
+        reset: extern "C" fn(Handle),
+        process_key: extern "C" fn(Handle, i32, i32, i32) -> i32,
+        get_context_text: extern "C" fn(Handle, *mut u16, i32) -> i32,
+    }
+
+    impl LegacyEngine {
+        pub fn load(shim: &Path, keyboard: &Path) -> Result<Self, String> {
+            unsafe {
+                let module = LoadLibraryW(wide(shim.as_os_str()).as_ptr());
+                if module.is_null() {
+                    return Err(format!("cannot load {}", shim.display()));
+                }
+                let symbol = |name: &str| -> Result<*const c_void, String> {
+                    let cname = format!("{}\0", name);
+                    let ptr = GetProcAddress(module, cname.as_ptr());
+                    if ptr.is_null() {
+                        Err(format!("{} does not export {}", shim.display(), name))
+                    } else {
+                        Ok(ptr)
+                    }
+                };
+
+                let new: extern "C" fn() -> Handle = std::mem::transmute(symbol("km2_engine_new")?);
+                let load: extern "C" fn(Handle, *const u16) -> i32 =
+                    std::mem::transmute(symbol("km2_engine_load_keyboard")?);
+                let free = std::mem::transmute(symbol("km2_engine_free")?);
+                let reset = std::mem::transmute(symbol("km2_engine_reset")?);
+                let process_key = std::mem::transmute(symbol("km2_engine_process_key")?);
+                let get_context_text = std::mem::transmute(symbol("km2_engine_get_context_text")?);
+                let engine = LegacyEngine { engine: new(), free, reset, process_key, get_context_text };
+                if load(engine.engine, wide(keyboard.as_os_str()).as_ptr()) == 0 {
+                    return Err(format!("legacy engine failed to load {}", keyboard.display()));
+                }
+                Ok(engine)
+            }
+        }
+
+        fn press(&self, key: &KeyEvent) {
+            let input = key.to_key_input();
+            let vk = VirtualKey::from_raw(input.key_code).map_or(0, |vk| vk.to_win_vk() as i32);
+            let mut modifier = 0;
+            if input.modifiers.shift {
+                modifier |= SHIFT_MASK;
+            }
+            if input.modifiers.ctrl {
+                modifier |= CTRL_MASK;
+            }
+            if input.modifiers.alt {
+                modifier |= ALT_MASK;
+            }
+            let keyval = input.character.map_or(0, |ch| ch as i32);
+            (self.process_key)(self.engine, keyval, vk, modifier);
+        }
+
+        fn context_text(&self) -> String {
+            let len = (self.get_context_text)(self.engine, std::ptr::null_mut(), 0);
+            let mut buf = vec![0u16; len.max(0) as usize + 1];
+            (self.get_context_text)(self.engine, buf.as_mut_ptr(), buf.len() as i32);
+            buf.truncate(len.max(0) as usize);
+            String::from_utf16_lossy(&buf)
+        }
+
+        /// Fills in the expected output of every step
+        pub fn capture(&self, cases: &mut [ConformanceCase]) {
+            for case in cases {
+                (self.reset)(self.engine);
+                for step in &mut case.steps {
+                    self.press(&step.key);
+                    step.expected = Some(self.context_text());
+                }
+            }
+        }
+    }
+
+    impl Drop for LegacyEngine {
+        fn drop(&mut self) {
+            (self.free)(self.engine);
+        }
+    }
+}
+
+#[cfg(windows)]
+fn main() {
+    use keymagic_core::conformance::{format_cases, parse_cases};
+    use std::path::PathBuf;
+
+    let args: Vec<PathBuf> = std::env::args_os().skip(1).map(PathBuf::from).collect();
+    let [shim, keyboard, cases_path] = args.as_slice() else {
+        eprintln!("Usage: conformance_capture <shim.dll> <keyboard.km2> <cases.txt>");
+        std::process::exit(2);
+    };
+
+    let run = || -> Result<usize, String> {
+        let text = std::fs::read_to_string(cases_path).map_err(|e| e.to_string())?;
+        let mut cases = parse_cases(&text).map_err(|e| e.to_string())?;
+        let engine = legacy::LegacyEngine::load(shim, keyboard)?;
+        engine.capture(&mut cases);
+        std::fs::write(cases_path, format_cases(&cases)).map_err(|e| e.to_string())?;
+        Ok(cases.len())
+    };
+
+    match run() {
+        Ok(count) => println!("Captured {} cases into {}", count, cases_path.display()),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("conformance_capture drives the legacy KeyMagic 2 DLL and only runs on Windows");
+    std::process::exit(1);
+}
@@ -0,0 +1,225 @@
+//! Dropping the stray characters of keys a text service ate
+//!
+//! Some UI frameworks call `TranslateMessage` on a key press before the text
+//! service sees it. When the service then eats the key, the `WM_CHAR` that
+//! was already posted still reaches the window, and the user gets the
+//! keyboard's output followed by the plain character of the key.
+//!
+//! The text service records every key press it eats in an [`EatenKeyTable`].
+//! In hosts known to double characters, a message hook asks the table about
+//! each `WM_CHAR`/`WM_UNICHAR` the thread takes from its queue; one with the
+//! scan code of a key eaten on the same thread within [`SUPPRESS_WINDOW`] is
+//! dropped, and each eaten key drops at most one character. The hook is
+//! installed in hosts on the opt-in list ([`host_listed`]) and in hosts
+//! where the service finds the paired character already queued when it eats
+//! a key.
+//!
+//! Dropped characters are counted in [`CharSuppressionStats`], which lives in
+//! named shared memory on Windows so the GUI can show it in its diagnostics.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::input_mode::host_matches;
+use crate::recorder::shared_block::{BlockLayout, SharedBlock};
+
+/// How long after an eaten key press its character is still dropped
+pub const SUPPRESS_WINDOW: Duration = Duration::from_millis(100);
+
+/// Eaten keys the table holds; the oldest go first when it is full
+pub const TABLE_CAPACITY: usize = 32;
+
+/// A key press the text service ate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EatenKey {
+    pub thread_id: u32,
+    /// Windows virtual key code
+    pub vk: u16,
+    /// Scan code with the extended flag in bit 8, as in bits 16-24 of the
+    /// message's `lParam`
+    pub scan_code: u16,
+    /// Tick count in milliseconds when the key was eaten
+    pub time_ms: u64,
+}
+
+/// The scan code and extended flag of a key or character message's `lParam`
+pub fn scan_code_from_lparam(lparam: isize) -> u16 {
+    ((lparam >> 16) & 0x1FF) as u16
+}
+
+/// Key presses eaten recently, waiting for a stray character to drop
+#[derive(Debug, Clone)]
+pub struct EatenKeyTable {
+    keys: VecDeque<EatenKey>,
+    window_ms: u64,
+}
+
+impl Default for EatenKeyTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EatenKeyTable {
+    /// An empty table matching within [`SUPPRESS_WINDOW`]
+    pub const fn new() -> Self {
+        Self::with_window(SUPPRESS_WINDOW)
+    }
+
+    pub const fn with_window(window: Duration) -> Self {
+        Self { keys: VecDeque::new(), window_ms: window.as_millis() as u64 }
+    }
+
+    /// Records an eaten key press
+    pub fn record(&mut self, key: EatenKey) {
+        self.expire(key.time_ms);
+        if self.keys.len() == TABLE_CAPACITY {
+            self.keys.pop_front();
+        }
+        self.keys.push_back(key);
+    }
+
+    /// Takes the eaten key a character with `scan_code` on `thread_id` at
+    /// `now_ms` belongs to, if any; the character should then be dropped
+    ///
+    /// The oldest match is taken, so a key typed twice drops two characters.
+    pub fn take_char(&mut self, thread_id: u32, scan_code: u16, now_ms: u64) -> Option<EatenKey> {
+        self.expire(now_ms);
+        let index = self.keys.iter().position(|key| key.thread_id == thread_id && key.scan_code == scan_code)?;
+        self.keys.remove(index)
+    }
+
+    /// Forgets keys older than the window at `now_ms`
+    ///
+    /// Tick counts only go forward; a key from later than `now_ms` is kept.
+    pub fn expire(&mut self, now_ms: u64) {
+        let window_ms = self.window_ms;
+        self.keys.retain(|key| now_ms.saturating_sub(key.time_ms) <= window_ms);
+    }
+
+    /// Forgets the keys of a thread, e.g. when its hook is removed
+    pub fn forget_thread(&mut self, thread_id: u32) {
+        self.keys.retain(|key| key.thread_id != thread_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Whether `host` is on the opt-in list, whose entries are matched like the
+/// input mode host lists
+pub fn host_listed<S: AsRef<str>>(host: &str, hosts: &[S]) -> bool {
+    hosts.iter().any(|pattern| host_matches(pattern.as_ref(), host))
+}
+
+/// Name of the section holding the counters on Windows
+#[cfg(windows)]
+pub const SECTION_NAME: &str = r"Local\KeyMagicCharSuppression";
+
+/// "KMCS" in little endian
+const BLOCK_MAGIC: u32 = 0x5343_4D4B;
+const BLOCK_VERSION: u32 = 1;
+
+/// The counters, laid out for shared memory
+#[repr(C)]
+struct Block {
+    magic: AtomicU32,
+    version: AtomicU32,
+    /// Hooks installed because the host is on the opt-in list
+    listed_hooks: AtomicU32,
+    /// Hooks installed because doubling was detected
+    detected_hooks: AtomicU32,
+    suppressed: AtomicU64,
+    /// Milliseconds since the Unix epoch; 0 before the first one
+    last_suppressed_at_ms: AtomicU64,
+}
+
+unsafe impl BlockLayout for Block {
+    fn initialize(&self) {
+        self.listed_hooks.store(0, Ordering::Relaxed);
+        self.detected_hooks.store(0, Ordering::Relaxed);
+        self.suppressed.store(0, Ordering::Relaxed);
+        self.last_suppressed_at_ms.store(0, Ordering::Relaxed);
+        self.version.store(BLOCK_VERSION, Ordering::Relaxed);
+        self.magic.store(BLOCK_MAGIC, Ordering::Release);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic.load(Ordering::Acquire) == BLOCK_MAGIC && self.version.load(Ordering::Relaxed) == BLOCK_VERSION
+    }
+}
+
+/// Why a host installed the message hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookReason {
+    /// The host is on the opt-in list
+    Listed,
+    /// The paired character was found queued after an eaten key
+    Detected,
+}
+
+/// The counters as read at one point
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CharSuppressionSnapshot {
+    /// Characters dropped since the counters were created
+    pub suppressed: u64,
+    pub listed_hooks: u32,
+    pub detected_hooks: u32,
+    /// Milliseconds since the Unix epoch
+    pub last_suppressed_at_ms: Option<u64>,
+}
+
+/// Handle to the suppression counters shared by all hosts
+pub struct CharSuppressionStats {
+    block: SharedBlock<Block>,
+}
+
+impl CharSuppressionStats {
+    /// A process-local block, for tests and platforms without shared state
+    pub fn in_memory() -> Self {
+        Self { block: SharedBlock::in_memory() }
+    }
+
+    /// Creates the shared block, or attaches to it if it already exists
+    #[cfg(windows)]
+    pub fn create_shared() -> std::io::Result<Self> {
+        Ok(Self { block: SharedBlock::create_shared(SECTION_NAME, "char suppression")? })
+    }
+
+    /// Counts a dropped character at `now_ms` (milliseconds since the Unix
+    /// epoch)
+    pub fn record_suppressed(&self, now_ms: u64) {
+        let block = &*self.block;
+        block.suppressed.fetch_add(1, Ordering::AcqRel);
+        block.last_suppressed_at_ms.store(now_ms.max(1), Ordering::Release);
+    }
+
+    /// Counts a hook a host installed
+    pub fn record_hook(&self, reason: HookReason) {
+        let counter = match reason {
+            HookReason::Listed => &self.block.listed_hooks,
+            HookReason::Detected => &self.block.detected_hooks,
+        };
+        counter.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn snapshot(&self) -> CharSuppressionSnapshot {
+        let block = &*self.block;
+        CharSuppressionSnapshot {
+            suppressed: block.suppressed.load(Ordering::Acquire),
+            listed_hooks: block.listed_hooks.load(Ordering::Acquire),
+            detected_hooks: block.detected_hooks.load(Ordering::Acquire),
+            last_suppressed_at_ms: Some(block.last_suppressed_at_ms.load(Ordering::Acquire)).filter(|&at| at != 0),
+        }
+    }
+}
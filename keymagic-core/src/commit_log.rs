@@ -0,0 +1,223 @@
+//! Committed text reported by text services
+//!
+//! The GUI keeps a short history of recently committed strings so they can
+//! be inserted again. Text services report each commit to a small ring of
+//! slots, which the GUI polls; nothing is reported unless the GUI turned the
+//! log on, and the ring only holds the last few commits.
+//!
+//! On Windows the ring lives in named shared memory. Elsewhere, and in
+//! tests, it is process-local. Other processes (the tray) can ask the GUI to
+//! forget its history by bumping the clear generation.
+
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+use crate::recorder::shared_block::{BlockLayout, SharedBlock};
+
+/// Name of the section holding the ring on Windows
+#[cfg(windows)]
+pub const SECTION_NAME: &str = r"Local\KeyMagicCommits";
+
+/// "KMCL" in little endian
+const BLOCK_MAGIC: u32 = 0x4C43_4D4B;
+const BLOCK_VERSION: u32 = 1;
+
+/// Commits kept in the ring until the GUI reads them
+pub const SLOT_COUNT: usize = 16;
+/// UTF-16 code units a commit may have; longer ones are not reported
+pub const TEXT_CAPACITY: usize = 256;
+
+/// Set in `flags` while hosts should report commits
+const FLAG_ENABLED: u32 = 1 << 0;
+
+/// Sequence number of a slot that is being written
+const BUSY: u64 = u64::MAX;
+
+/// One commit; `seq` works as a seqlock like the input recorder's ring
+#[repr(C)]
+struct Slot {
+    /// 0 when empty, `BUSY` while written
+    seq: AtomicU64,
+    len: AtomicU32,
+    _reserved: AtomicU32,
+    /// Two UTF-16 code units per word
+    text: [AtomicU32; TEXT_CAPACITY / 2],
+}
+
+#[repr(C)]
+struct Block {
+    magic: AtomicU32,
+    version: AtomicU32,
+    flags: AtomicU32,
+    /// Bumped to ask the GUI to forget its history
+    clear_generation: AtomicU32,
+    /// Sequence number of the last commit claimed (commits start at 1)
+    next_seq: AtomicU64,
+    slots: [Slot; SLOT_COUNT],
+}
+
+unsafe impl BlockLayout for Block {
+    fn initialize(&self) {
+        for slot in &self.slots {
+            slot.seq.store(0, Ordering::Relaxed);
+        }
+        self.flags.store(0, Ordering::Relaxed);
+        self.clear_generation.store(0, Ordering::Relaxed);
+        self.next_seq.store(0, Ordering::Relaxed);
+        self.version.store(BLOCK_VERSION, Ordering::Relaxed);
+        self.magic.store(BLOCK_MAGIC, Ordering::Release);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic.load(Ordering::Acquire) == BLOCK_MAGIC && self.version.load(Ordering::Relaxed) == BLOCK_VERSION
+    }
+}
+
+impl Block {
+    /// Writes `units` under the next sequence number; a writer that finds
+    /// its slot busy or a lap ahead drops the commit, as the recorder does
+    fn push(&self, units: &[u16]) -> u64 {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed) + 1;
+        let slot = &self.slots[((seq - 1) % SLOT_COUNT as u64) as usize];
+
+        let current = slot.seq.load(Ordering::Relaxed);
+        if current == BUSY
+            || current > seq
+            || slot.seq.compare_exchange(current, BUSY, Ordering::Relaxed, Ordering::Relaxed).is_err()
+        {
+            return seq;
+        }
+        fence(Ordering::Release);
+        for (word, pair) in slot.text.iter().zip(units.chunks(2)) {
+            let high = pair.get(1).copied().unwrap_or(0) as u32;
+            word.store(pair[0] as u32 | (high << 16), Ordering::Relaxed);
+        }
+        slot.len.store(units.len() as u32, Ordering::Relaxed);
+        slot.seq.store(seq, Ordering::Release);
+        seq
+    }
+
+    /// Empties every slot that is not being written
+    fn wipe(&self) {
+        for slot in &self.slots {
+            let current = slot.seq.load(Ordering::Relaxed);
+            if current == 0
+                || current == BUSY
+                || slot.seq.compare_exchange(current, BUSY, Ordering::Acquire, Ordering::Relaxed).is_err()
+            {
+                continue;
+            }
+            for word in &slot.text {
+                word.store(0, Ordering::Relaxed);
+            }
+            slot.len.store(0, Ordering::Relaxed);
+            slot.seq.store(0, Ordering::Release);
+        }
+    }
+
+    /// Copies a slot, or `None` if it is empty or changed while copied
+    fn read_slot(slot: &Slot) -> Option<CommittedText> {
+        let seq = slot.seq.load(Ordering::Acquire);
+        if seq == 0 || seq == BUSY {
+            return None;
+        }
+        let len = (slot.len.load(Ordering::Relaxed) as usize).min(TEXT_CAPACITY);
+        let units: Vec<u16> = slot
+            .text
+            .iter()
+            .flat_map(|word| {
+                let word = word.load(Ordering::Relaxed);
+                [word as u16, (word >> 16) as u16]
+            })
+            .take(len)
+            .collect();
+        fence(Ordering::Acquire);
+        (slot.seq.load(Ordering::Relaxed) == seq).then(|| CommittedText { seq, text: String::from_utf16_lossy(&units) })
+    }
+}
+
+/// A commit read back from the ring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommittedText {
+    pub seq: u64,
+    pub text: String,
+}
+
+/// Handle to the ring of reported commits
+pub struct CommitLog {
+    block: SharedBlock<Block>,
+}
+
+impl CommitLog {
+    /// A process-local ring, for tests and platforms without shared state
+    pub fn in_memory() -> Self {
+        Self { block: SharedBlock::in_memory() }
+    }
+
+    /// Creates the shared ring, or attaches to it if it already exists
+    ///
+    /// A new ring starts disabled, so hosts report nothing until the GUI
+    /// turns the log on.
+    #[cfg(windows)]
+    pub fn create_shared() -> std::io::Result<Self> {
+        Ok(Self { block: SharedBlock::create_shared(SECTION_NAME, "commit log")? })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.block.flags.load(Ordering::Acquire) & FLAG_ENABLED != 0
+    }
+
+    /// Turns reporting on or off for every host; turning it off also
+    /// empties the ring
+    pub fn set_enabled(&self, enabled: bool) {
+        let block = &*self.block;
+        block.flags.store(if enabled { FLAG_ENABLED } else { 0 }, Ordering::Release);
+        if !enabled {
+            block.wipe();
+        }
+    }
+
+    /// Reports committed text and returns its sequence number
+    ///
+    /// Nothing is reported while the log is off, for empty text, or for text
+    /// longer than [`TEXT_CAPACITY`], which could not be inserted again whole.
+    pub fn report(&self, text: &str) -> Option<u64> {
+        if !self.is_enabled() || text.is_empty() {
+            return None;
+        }
+        let units: Vec<u16> = text.encode_utf16().collect();
+        if units.len() > TEXT_CAPACITY {
+            return None;
+        }
+        Some(self.block.push(&units))
+    }
+
+    /// Sequence number of the last commit reported
+    pub fn last_seq(&self) -> u64 {
+        self.block.next_seq.load(Ordering::Acquire)
+    }
+
+    /// Commits reported after `after` that are still in the ring, oldest first
+    pub fn read_since(&self, after: u64) -> Vec<CommittedText> {
+        let mut commits: Vec<CommittedText> = self
+            .block
+            .slots
+            .iter()
+            .filter_map(Block::read_slot)
+            .filter(|commit| commit.seq > after)
+            .collect();
+        commits.sort_by_key(|commit| commit.seq);
+        commits
+    }
+
+    /// Empties the ring and asks the GUI to forget its history
+    pub fn request_clear(&self) {
+        let block = &*self.block;
+        block.wipe();
+        block.clear_generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Changes whenever a clear was requested
+    pub fn clear_generation(&self) -> u32 {
+        self.block.clear_generation.load(Ordering::Acquire)
+    }
+}
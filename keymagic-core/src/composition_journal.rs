@@ -0,0 +1,252 @@
+//! Composing text of each host, kept for when the host crashes
+//!
+//! Text being composed lives only in the host's engine; if the application
+//! crashes, it is gone. When the user opts in, hosts report their composing
+//! text here once it is [`MIN_CHARS`] characters or longer, and clear it when
+//! they commit. There is one slot per process holding its latest text only.
+//! The GUI copies the slots into an encrypted journal and offers the text of
+//! processes that went away without committing; see the GUI's
+//! `composition_journal` module.
+//!
+//! Nothing is reported until the GUI turns the block on. On Windows the
+//! block lives in named shared memory; elsewhere, and in tests, it is
+//! process-local.
+
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+use crate::recorder::shared_block::{BlockLayout, SharedBlock};
+
+/// Name of the section holding the slots on Windows
+#[cfg(windows)]
+pub const SECTION_NAME: &str = r"Local\KeyMagicCompositionJournal";
+
+/// "KMCJ" in little endian
+const BLOCK_MAGIC: u32 = 0x4A43_4D4B;
+const BLOCK_VERSION: u32 = 1;
+
+/// Processes with a composition kept at once
+pub const SLOT_COUNT: usize = 32;
+/// UTF-16 code units kept of a process name; longer names are cut
+pub const NAME_CAPACITY: usize = 64;
+/// UTF-16 code units kept of composing text; of longer text the end is kept
+pub const TEXT_CAPACITY: usize = 256;
+/// Shorter compositions are quick to type again and are not kept
+pub const MIN_CHARS: usize = 8;
+
+/// Set in `flags` while hosts should report
+const FLAG_ENABLED: u32 = 1 << 0;
+
+/// Sequence number of a slot that is being written
+const BUSY: u64 = u64::MAX;
+
+/// The latest composition of one process; `seq` works as a seqlock like the
+/// load log's
+#[repr(C)]
+struct Slot {
+    /// 0 when free, `BUSY` while written
+    seq: AtomicU64,
+    updated_ms: AtomicU64,
+    pid: AtomicU32,
+    name_len: AtomicU32,
+    text_len: AtomicU32,
+    _reserved: AtomicU32,
+    /// Two UTF-16 code units per word
+    name: [AtomicU32; NAME_CAPACITY / 2],
+    text: [AtomicU32; TEXT_CAPACITY / 2],
+}
+
+fn store_units(words: &[AtomicU32], units: &[u16]) {
+    for (word, pair) in words.iter().zip(units.chunks(2)) {
+        let high = pair.get(1).copied().unwrap_or(0) as u32;
+        word.store(pair[0] as u32 | (high << 16), Ordering::Relaxed);
+    }
+}
+
+fn load_units(words: &[AtomicU32], len: usize) -> Vec<u16> {
+    words
+        .iter()
+        .flat_map(|word| {
+            let word = word.load(Ordering::Relaxed);
+            [word as u16, (word >> 16) as u16]
+        })
+        .take(len)
+        .collect()
+}
+
+#[repr(C)]
+struct Block {
+    magic: AtomicU32,
+    version: AtomicU32,
+    flags: AtomicU32,
+    _reserved: AtomicU32,
+    /// Sequence number of the last report (reports start at 1)
+    next_seq: AtomicU64,
+    slots: [Slot; SLOT_COUNT],
+}
+
+unsafe impl BlockLayout for Block {
+    fn initialize(&self) {
+        self.wipe();
+        self.flags.store(0, Ordering::Relaxed);
+        self.next_seq.store(0, Ordering::Relaxed);
+        self.version.store(BLOCK_VERSION, Ordering::Relaxed);
+        self.magic.store(BLOCK_MAGIC, Ordering::Release);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic.load(Ordering::Acquire) == BLOCK_MAGIC && self.version.load(Ordering::Relaxed) == BLOCK_VERSION
+    }
+}
+
+impl Block {
+    fn wipe(&self) {
+        for slot in &self.slots {
+            slot.seq.store(0, Ordering::Relaxed);
+            slot.text_len.store(0, Ordering::Relaxed);
+            for word in &slot.text {
+                word.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The slot `pid` holds, if any
+    fn slot_of(&self, pid: u32) -> Option<&Slot> {
+        self.slots.iter().find(|slot| {
+            let seq = slot.seq.load(Ordering::Acquire);
+            seq != 0 && slot.pid.load(Ordering::Relaxed) == pid
+        })
+    }
+
+    /// Takes the slot of `pid`, or a free one, for writing; `None` if it is
+    /// being written already or every slot is taken
+    fn claim(&self, pid: u32) -> Option<&Slot> {
+        if let Some(slot) = self.slot_of(pid) {
+            let current = slot.seq.load(Ordering::Relaxed);
+            return (current != BUSY
+                && slot.seq.compare_exchange(current, BUSY, Ordering::Relaxed, Ordering::Relaxed).is_ok())
+            .then_some(slot);
+        }
+        self.slots
+            .iter()
+            .find(|slot| slot.seq.compare_exchange(0, BUSY, Ordering::Relaxed, Ordering::Relaxed).is_ok())
+    }
+
+    /// Copies a slot, or `None` if it is free or changed while copied
+    fn read_slot(slot: &Slot) -> Option<JournalSlot> {
+        let seq = slot.seq.load(Ordering::Acquire);
+        if seq == 0 || seq == BUSY {
+            return None;
+        }
+        let pid = slot.pid.load(Ordering::Relaxed);
+        let updated_ms = slot.updated_ms.load(Ordering::Relaxed);
+        let name = load_units(&slot.name, (slot.name_len.load(Ordering::Relaxed) as usize).min(NAME_CAPACITY));
+        let text = load_units(&slot.text, (slot.text_len.load(Ordering::Relaxed) as usize).min(TEXT_CAPACITY));
+        fence(Ordering::Acquire);
+        (slot.seq.load(Ordering::Relaxed) == seq).then(|| JournalSlot {
+            pid,
+            process: String::from_utf16_lossy(&name),
+            text: String::from_utf16_lossy(&text),
+            updated_ms,
+        })
+    }
+}
+
+/// The last end of `text` that fits [`TEXT_CAPACITY`], not starting inside
+/// a surrogate pair
+fn text_units(text: &str) -> Vec<u16> {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let mut start = units.len().saturating_sub(TEXT_CAPACITY);
+    if units.get(start).is_some_and(|unit| (0xDC00..=0xDFFF).contains(unit)) {
+        start += 1;
+    }
+    units[start..].to_vec()
+}
+
+/// A composition read back from the block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalSlot {
+    pub pid: u32,
+    /// Executable name of the host, cut to [`NAME_CAPACITY`]
+    pub process: String,
+    pub text: String,
+    /// When the host reported it, in milliseconds since the Unix epoch
+    pub updated_ms: u64,
+}
+
+/// Handle to the compositions kept for crash recovery
+pub struct CompositionJournal {
+    block: SharedBlock<Block>,
+}
+
+impl CompositionJournal {
+    /// A process-local block, for tests and platforms without shared state
+    pub fn in_memory() -> Self {
+        Self { block: SharedBlock::in_memory() }
+    }
+
+    /// Creates the shared block, or attaches to it if it already exists
+    ///
+    /// A new block starts disabled, so hosts report nothing until the GUI
+    /// turns the journal on.
+    #[cfg(windows)]
+    pub fn create_shared() -> std::io::Result<Self> {
+        Ok(Self { block: SharedBlock::create_shared(SECTION_NAME, "composition journal")? })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.block.flags.load(Ordering::Acquire) & FLAG_ENABLED != 0
+    }
+
+    /// Turns reporting on or off for every host; turning it off drops the
+    /// compositions kept
+    pub fn set_enabled(&self, enabled: bool) {
+        if enabled {
+            self.block.flags.fetch_or(FLAG_ENABLED, Ordering::AcqRel);
+        } else {
+            self.block.flags.fetch_and(!FLAG_ENABLED, Ordering::AcqRel);
+            self.block.wipe();
+        }
+    }
+
+    /// Keeps `text` as the composition of process `pid`, replacing the one
+    /// kept before; returns whether it was kept. Text shorter than
+    /// [`MIN_CHARS`] leaves the slot as it is.
+    pub fn report(&self, pid: u32, process: &str, text: &str, now_ms: u64) -> bool {
+        if !self.is_enabled() || text.chars().count() < MIN_CHARS {
+            return false;
+        }
+        let block = &*self.block;
+        let Some(slot) = block.claim(pid) else {
+            return false;
+        };
+        fence(Ordering::Release);
+        let name: Vec<u16> = process.encode_utf16().take(NAME_CAPACITY).collect();
+        let text = text_units(text);
+        slot.pid.store(pid, Ordering::Relaxed);
+        slot.updated_ms.store(now_ms, Ordering::Relaxed);
+        store_units(&slot.name, &name);
+        slot.name_len.store(name.len() as u32, Ordering::Relaxed);
+        store_units(&slot.text, &text);
+        slot.text_len.store(text.len() as u32, Ordering::Relaxed);
+        let seq = block.next_seq.fetch_add(1, Ordering::Relaxed) + 1;
+        slot.seq.store(seq, Ordering::Release);
+        true
+    }
+
+    /// Drops the composition of `pid`, once it was committed or the GUI has
+    /// taken it over; returns whether one was kept
+    pub fn clear(&self, pid: u32) -> bool {
+        let Some(slot) = self.block.slot_of(pid) else {
+            return false;
+        };
+        let current = slot.seq.load(Ordering::Relaxed);
+        current != BUSY && slot.seq.compare_exchange(current, 0, Ordering::AcqRel, Ordering::Relaxed).is_ok()
+    }
+
+    /// Compositions kept now, by process id
+    pub fn read_all(&self) -> Vec<JournalSlot> {
+        let mut slots: Vec<JournalSlot> = self.block.slots.iter().filter_map(Block::read_slot).collect();
+        slots.sort_by_key(|slot| slot.pid);
+        slots
+    }
+}
@@ -0,0 +1,154 @@
+//! Parsing and writing of conformance case files
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::analysis::typed_key;
+use crate::engine::{KeyInput, ModifierState};
+use crate::error::{Error, Result};
+use crate::types::virtual_keys::create_vk_map;
+use crate::VirtualKey;
+
+/// A key press of a conformance case
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyEvent {
+    /// A character typed on a US layout
+    Char(char),
+    /// A virtual key combination, modifiers included (`<VK_SHIFT & VK_KEY_A>`)
+    Combo(Vec<VirtualKey>),
+}
+
+impl KeyEvent {
+    /// Builds the engine input for this key press
+    pub fn to_key_input(&self) -> KeyInput {
+        match self {
+            KeyEvent::Char(ch) => typed_key(*ch),
+            KeyEvent::Combo(keys) => {
+                let has = |key: VirtualKey| keys.contains(&key);
+                let modifiers = ModifierState::new(has(VirtualKey::Shift), has(VirtualKey::Control), has(VirtualKey::Menu), false);
+                let primary = keys
+                    .iter()
+                    .copied()
+                    .find(|key| !matches!(key, VirtualKey::Shift | VirtualKey::Control | VirtualKey::Menu))
+                    .unwrap_or(keys[0]);
+                KeyInput::from_vk(primary as u16, modifiers)
+            }
+        }
+    }
+
+    fn parse(token: &str, vk_map: &HashMap<&'static str, VirtualKey>) -> std::result::Result<Self, String> {
+        if token == "SPACE" {
+            return Ok(KeyEvent::Char(' '));
+        }
+        if let Some(inner) = token.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+            let keys = inner
+                .split('&')
+                .map(|name| {
+                    let name = name.trim();
+                    vk_map.get(name).copied().ok_or_else(|| format!("unknown virtual key '{}'", name))
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            return Ok(KeyEvent::Combo(keys));
+        }
+
+        let mut chars = token.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Ok(KeyEvent::Char(ch)),
+            _ => Err(format!("expected a single character, SPACE or <VK_...>, found '{}'", token)),
+        }
+    }
+}
+
+impl fmt::Display for KeyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyEvent::Char(' ') => write!(f, "SPACE"),
+            KeyEvent::Char(ch) => write!(f, "{}", ch),
+            KeyEvent::Combo(keys) => {
+                let names: Vec<&str> = keys.iter().map(|key| key.to_kms_name()).collect();
+                write!(f, "<{}>", names.join(" & "))
+            }
+        }
+    }
+}
+
+/// One key of a case and the composing text expected after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub key: KeyEvent,
+    /// `None` until captured from the legacy engine
+    pub expected: Option<String>,
+    /// Line number in the case file (1-based)
+    pub line: usize,
+}
+
+/// A named key sequence, typed from an empty composition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceCase {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+impl ConformanceCase {
+    /// Returns true if every step has an expected output
+    pub fn is_captured(&self) -> bool {
+        self.steps.iter().all(|step| step.expected.is_some())
+    }
+}
+
+/// Parses a case file
+pub fn parse_cases(text: &str) -> Result<Vec<ConformanceCase>> {
+    let vk_map = create_vk_map();
+    let mut cases: Vec<ConformanceCase> = Vec::new();
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = raw_line.trim_end_matches('\r');
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = trimmed.strip_prefix("==") {
+            cases.push(ConformanceCase {
+                name: name.trim().to_string(),
+                steps: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some(case) = cases.last_mut() else {
+            return Err(Error::ParseError(format!("line {}: key outside of a case ('==' expected first)", line_no)));
+        };
+
+        // The expected text is kept verbatim, trailing spaces included
+        let (token, expected) = match trimmed.split_once(" =>") {
+            Some((token, rest)) => (token, Some(rest.strip_prefix(' ').unwrap_or(rest).to_string())),
+            None => (trimmed.trim_end(), None),
+        };
+        let key = KeyEvent::parse(token.trim(), &vk_map)
+            .map_err(|message| Error::ParseError(format!("line {}: {}", line_no, message)))?;
+        case.steps.push(Step { key, expected, line: line_no });
+    }
+
+    Ok(cases)
+}
+
+/// Writes cases back in the case file format
+pub fn format_cases(cases: &[ConformanceCase]) -> String {
+    let mut out = String::new();
+    for (i, case) in cases.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        out.push_str(&format!("== {}\n", case.name));
+        for step in &case.steps {
+            match &step.expected {
+                Some(expected) if expected.is_empty() => out.push_str(&format!("{} =>\n", step.key)),
+                Some(expected) => out.push_str(&format!("{} => {}\n", step.key, expected)),
+                None => out.push_str(&format!("{}\n", step.key)),
+            }
+        }
+    }
+    out
+}
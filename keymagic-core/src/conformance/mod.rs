@@ -0,0 +1,30 @@
+//! Conformance testing against the legacy KeyMagic 2 engine
+//!
+//! A conformance fixture is a keyboard plus a case file of key sequences with
+//! the composing text the legacy engine produced after every key. The runner
+//! types the same keys into `KeyMagicEngine` and reports the first key where
+//! the outputs diverge, together with the rules that fired for it.
+//!
+//! Case file format:
+//!
+//! ```text
+//! # Comments start with '#'
+//! == consonant with vowel sign
+//! k => က
+//! a => ကာ
+//! <VK_BACK> => က
+//! <VK_SHIFT & VK_KEY_A> =>
+//! SPACE
+//! ```
+//!
+//! `==` starts a case; every case begins with an empty composition. Each
+//! following line is one key: a single character typed on a US layout,
+//! `SPACE`, or a virtual key combination in KMS syntax. Everything after
+//! `=> ` is the expected composing text (possibly empty). Lines without `=>`
+//! have not been captured yet; the capture tool fills them in.
+
+mod fixture;
+mod runner;
+
+pub use fixture::{format_cases, parse_cases, ConformanceCase, KeyEvent, Step};
+pub use runner::{run_case, run_cases, CaseResult, Divergence, SuiteReport};
@@ -0,0 +1,164 @@
+//! Runs conformance cases against `KeyMagicEngine`
+
+use std::fmt;
+
+use super::fixture::{ConformanceCase, KeyEvent};
+use crate::error::Result;
+use crate::km2::RuleFormatter;
+use crate::KeyMagicEngine;
+
+/// The first key where the engine disagrees with the legacy output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the step in the case
+    pub step: usize,
+    /// Line number in the case file
+    pub line: usize,
+    pub key: KeyEvent,
+    /// Composing text before the key
+    pub before: String,
+    pub expected: String,
+    pub actual: String,
+    /// Rules applied for the key, as `#index: rule` (`#index (post): rule`
+    /// for post-rules), in application order
+    pub trace: Vec<String>,
+    /// States on when the key was pressed, in index order
+    pub states_before: Vec<usize>,
+    /// States the key left on for the next one
+    pub states_after: Vec<usize>,
+}
+
+/// States as the rule formatter writes them, or `none`
+fn format_states(states: &[usize]) -> String {
+    if states.is_empty() {
+        return "none".to_string();
+    }
+    states.iter().map(|state| format!("('state{}')", state)).collect::<Vec<_>>().join(" + ")
+}
+
+/// Outcome of one case
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseResult {
+    pub name: String,
+    /// Number of steps compared (captured steps only)
+    pub checked: usize,
+    pub divergence: Option<Divergence>,
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        self.divergence.is_none()
+    }
+}
+
+impl fmt::Display for CaseResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(d) = &self.divergence else {
+            return write!(f, "PASS {} ({} keys)", self.name, self.checked);
+        };
+        writeln!(f, "FAIL {} at key {} '{}' (line {})", self.name, d.step + 1, d.key, d.line)?;
+        writeln!(f, "  before:   {:?}", d.before)?;
+        writeln!(f, "  expected: {:?}", d.expected)?;
+        writeln!(f, "  actual:   {:?}", d.actual)?;
+        writeln!(f, "  states:   {} -> {}", format_states(&d.states_before), format_states(&d.states_after))?;
+        if d.trace.is_empty() {
+            write!(f, "  no rule matched")
+        } else {
+            write!(f, "  rules applied:")?;
+            for rule in &d.trace {
+                write!(f, "\n    {}", rule)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Results of a set of cases
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SuiteReport {
+    pub results: Vec<CaseResult>,
+    /// Cases skipped because none of their steps were captured
+    pub uncaptured: Vec<String>,
+}
+
+impl SuiteReport {
+    pub fn failures(&self) -> impl Iterator<Item = &CaseResult> {
+        self.results.iter().filter(|r| !r.passed())
+    }
+
+    pub fn passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+}
+
+impl fmt::Display for SuiteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            writeln!(f, "{}", result)?;
+        }
+        for name in &self.uncaptured {
+            writeln!(f, "SKIP {} (not captured)", name)?;
+        }
+        let failed = self.failures().count();
+        write!(f, "{} passed, {} failed, {} skipped", self.results.len() - failed, failed, self.uncaptured.len())
+    }
+}
+
+/// Types a case into the engine from an empty composition and stops at the
+/// first step whose composing text differs from the expected one. Steps that
+/// were not captured are typed but not compared.
+pub fn run_case(engine: &mut KeyMagicEngine, case: &ConformanceCase) -> Result<CaseResult> {
+    engine.reset();
+    let mut checked = 0;
+
+    for (index, step) in case.steps.iter().enumerate() {
+        let before = engine.composing_text().to_string();
+        let states_before = engine.active_states();
+        engine.process_key(step.key.to_key_input())?;
+
+        let Some(expected) = &step.expected else { continue };
+        checked += 1;
+        let actual = engine.composing_text();
+        if actual != expected {
+            let keyboard = engine.keyboard();
+            let formatter = RuleFormatter::new(&keyboard.strings);
+            let trace = engine
+                .last_matched_rules()
+                .iter()
+                .map(|&rule| {
+                    let post = if engine.is_post_rule(rule) { " (post)" } else { "" };
+                    format!("#{}{}: {}", rule, post, formatter.format_rule(&keyboard.rules[rule]))
+                })
+                .collect();
+            let divergence = Divergence {
+                step: index,
+                line: step.line,
+                key: step.key.clone(),
+                before,
+                expected: expected.clone(),
+                actual: actual.to_string(),
+                trace,
+                states_before,
+                states_after: engine.active_states(),
+            };
+            engine.reset();
+            return Ok(CaseResult { name: case.name.clone(), checked, divergence: Some(divergence) });
+        }
+    }
+
+    engine.reset();
+    Ok(CaseResult { name: case.name.clone(), checked, divergence: None })
+}
+
+/// Runs every captured case
+pub fn run_cases(engine: &mut KeyMagicEngine, cases: &[ConformanceCase]) -> Result<SuiteReport> {
+    let mut report = SuiteReport::default();
+    for case in cases {
+        if case.steps.iter().all(|step| step.expected.is_none()) {
+            report.uncaptured.push(case.name.clone());
+            continue;
+        }
+        report.results.push(run_case(engine, case)?);
+    }
+    Ok(report)
+}
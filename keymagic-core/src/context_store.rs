@@ -0,0 +1,182 @@
+//! Compositions kept per input context of a host process
+//!
+//! A text service sees one input context per text field, and an engine
+//! serves them all, so the composition of the context losing focus is saved
+//! and the one of the context gaining focus restored. Some hosts recreate
+//! their contexts under the user: an Electron renderer that reloads (Teams,
+//! Slack) destroys its context and creates a new one, and keys typed in
+//! between would go to the dead context and show up twice once the new one
+//! attaches. A context created within [`MIGRATION_WINDOW`] of another one
+//! of the same process being destroyed therefore takes over its composition,
+//! whichever of the two happened first.
+//!
+//! Contexts are identified by an opaque id chosen by the host. Compositions
+//! of destroyed contexts that nothing took over are dropped after the
+//! window; those of contexts the host forgot to destroy after
+//! [`SNAPSHOT_TTL`].
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::EngineSnapshot;
+
+/// How close the creation of a context and the destruction of another must
+/// be for the composition to move
+pub const MIGRATION_WINDOW: Duration = Duration::from_millis(500);
+
+/// How long the composition of a context that lost focus is kept
+pub const SNAPSHOT_TTL: Duration = Duration::from_secs(600);
+
+/// Opaque context id chosen by the host
+pub type ContextId = u64;
+
+#[derive(Debug)]
+struct Context {
+    process_id: u32,
+    snapshot: Option<EngineSnapshot>,
+    created_at: Instant,
+    /// Last focus, save or takeover
+    touched_at: Instant,
+    destroyed_at: Option<Instant>,
+    /// Focused since it was created
+    focused: bool,
+}
+
+/// Where the composition of a context went or came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    pub from: ContextId,
+    pub to: ContextId,
+}
+
+/// Compositions of the input contexts of host processes
+#[derive(Debug, Default)]
+pub struct ContextStore {
+    contexts: BTreeMap<ContextId, Context>,
+    /// Focused context of each process
+    focused: BTreeMap<u32, ContextId>,
+}
+
+impl ContextStore {
+    pub const fn new() -> Self {
+        Self { contexts: BTreeMap::new(), focused: BTreeMap::new() }
+    }
+
+    /// Registers a new context. It takes over the composition of a context
+    /// of the same process destroyed within the window; the most recent one
+    /// when there are several.
+    pub fn created(&mut self, id: ContextId, process_id: u32, now: Instant) -> Option<Migration> {
+        self.expire(now);
+        self.contexts.insert(id, Context {
+            process_id,
+            snapshot: None,
+            created_at: now,
+            touched_at: now,
+            destroyed_at: None,
+            focused: false,
+        });
+
+        let from = self.contexts.iter()
+            .filter(|(_, c)| c.process_id == process_id && c.snapshot.is_some())
+            .filter_map(|(&other, c)| c.destroyed_at.map(|at| (other, at)))
+            .filter(|&(_, at)| now.saturating_duration_since(at) <= MIGRATION_WINDOW)
+            .max_by_key(|&(_, at)| at)
+            .map(|(other, _)| other)?;
+        self.migrate(from, id, now)
+    }
+
+    /// Marks a context destroyed, saving `snapshot` as its composition when
+    /// it had focus. A context of the same process created within the
+    /// window and not focused yet takes the composition over.
+    pub fn destroyed(&mut self, id: ContextId, snapshot: Option<EngineSnapshot>, now: Instant) -> Option<Migration> {
+        self.expire(now);
+        let context = self.contexts.get_mut(&id)?;
+        if snapshot.is_some() {
+            context.snapshot = snapshot;
+        }
+        context.destroyed_at = Some(now);
+        let process_id = context.process_id;
+        if self.focused.get(&process_id) == Some(&id) {
+            self.focused.remove(&process_id);
+        }
+
+        let to = self.contexts.iter()
+            .filter(|(&other, c)| other != id && c.process_id == process_id && c.destroyed_at.is_none())
+            .filter(|(_, c)| !c.focused && c.snapshot.is_none())
+            .filter(|(_, c)| now.saturating_duration_since(c.created_at) <= MIGRATION_WINDOW)
+            .max_by_key(|(_, c)| c.created_at)
+            .map(|(&other, _)| other);
+        match to {
+            Some(to) => self.migrate(id, to, now),
+            None => None,
+        }
+    }
+
+    /// Moves the focus of the context's process to it. `snapshot` is the
+    /// composition of the context that had the focus, saved for when it
+    /// comes back. Returns the composition to continue in this context.
+    pub fn focused(
+        &mut self,
+        id: ContextId,
+        process_id: u32,
+        snapshot: Option<EngineSnapshot>,
+        now: Instant,
+    ) -> Option<EngineSnapshot> {
+        self.expire(now);
+        if let Some(previous) = self.focused.insert(process_id, id).filter(|&previous| previous != id) {
+            if let Some(context) = self.contexts.get_mut(&previous) {
+                context.snapshot = snapshot.filter(|s| !s.is_empty());
+                context.touched_at = now;
+            }
+        }
+        // Contexts created before the store was in use register on focus
+        let context = self.contexts.entry(id).or_insert_with(|| Context {
+            process_id,
+            snapshot: None,
+            created_at: now,
+            touched_at: now,
+            destroyed_at: None,
+            focused: false,
+        });
+        context.focused = true;
+        context.touched_at = now;
+        context.snapshot.take()
+    }
+
+    /// The focused context of a process
+    pub fn focused_context(&self, process_id: u32) -> Option<ContextId> {
+        self.focused.get(&process_id).copied()
+    }
+
+    /// The composing text saved for a context
+    pub fn saved_text(&self, id: ContextId) -> Option<&str> {
+        self.contexts.get(&id)?.snapshot.as_ref().map(EngineSnapshot::composing_text)
+    }
+
+    /// Number of contexts known, destroyed ones included until they expire
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+
+    /// Drops destroyed contexts after the window and saved compositions
+    /// nobody came back for after the TTL
+    pub fn expire(&mut self, now: Instant) {
+        let focused: Vec<ContextId> = self.focused.values().copied().collect();
+        self.contexts.retain(|id, context| match context.destroyed_at {
+            Some(at) => now.saturating_duration_since(at) <= MIGRATION_WINDOW,
+            None => focused.contains(id) || now.saturating_duration_since(context.touched_at) <= SNAPSHOT_TTL,
+        });
+    }
+
+    fn migrate(&mut self, from: ContextId, to: ContextId, now: Instant) -> Option<Migration> {
+        let snapshot = self.contexts.get_mut(&from)?.snapshot.take()?;
+        let context = self.contexts.get_mut(&to)?;
+        context.snapshot = Some(snapshot);
+        context.touched_at = now;
+        Some(Migration { from, to })
+    }
+}
@@ -1,69 +1,356 @@
 //! Main KeyMagic engine implementation
 
-use crate::types::{Km2File, Rule};
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::{Arc, OnceLock};
+
+use crate::types::{Km2File, LayoutOptions, LayoutOverrides, PostRules, Rule, RuleGroup, StringTable};
 use crate::engine::types::Element;
 use crate::engine::{
+    explain::{Explainer, Explanation, KeyOutcome, TriedRule},
+    histogram::RuleHistogram,
     input::KeyInput,
     output::EngineOutput,
     state::EngineState,
-    matching::{RuleMatcher, Pattern, MatchContext},
+    matching::{RuleMatcher, Pattern, MatchContext, RuleMask, PrefixIndex, PrefixResult},
     processing::{RuleProcessor, RecursiveProcessor, ActionGenerator, should_stop_recursion},
+    suggestion::{Suggestion, SuggestionProvider},
 };
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::km2::{Km2Loader, FALLBACK_KM2};
+use crate::transform::{create_transform, Transform, TransformId};
 use crate::VirtualKey;
 
+/// Characters a key may delete beyond what the engine emitted itself, see
+/// `KeyMagicEngine::set_delete_slack`
+const DEFAULT_DELETE_SLACK: usize = 8;
+
+/// Suggestions attached to an output unless `set_max_suggestions` says otherwise
+const DEFAULT_MAX_SUGGESTIONS: usize = 5;
+
+/// Characters of committed text kept as context for suggestions
+const SUGGESTION_CONTEXT_CHARS: usize = 64;
+
+/// How much of the engine state `KeyMagicEngine::reset_level` clears; each
+/// level clears everything the one before it does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetLevel {
+    /// The composing text and its caret, the undo history of smart
+    /// backspace, the count of characters the engine emitted (text synced
+    /// from the host included) and a pending notification. States on for
+    /// the next key survive, for a caret moved within the same field.
+    Composing,
+    /// Also the states on for the next key, for a new field
+    Context,
+    /// Also the rules reported by `last_matched_rules`, for a new keyboard.
+    /// Rule group switches, options and the rule histogram are settings,
+    /// not state, and are kept by every level.
+    Full,
+}
+
+/// Composition of an engine, saved to continue it later (e.g. in another
+/// input context) with `KeyMagicEngine::restore`
+#[derive(Debug, Clone)]
+pub struct EngineSnapshot {
+    state: EngineState,
+    history: VecDeque<EngineState>,
+}
+
+impl EngineSnapshot {
+    /// The composing text when the snapshot was taken
+    pub fn composing_text(&self) -> &str {
+        self.state.composing_text()
+    }
+
+    /// Whether there was nothing to continue: no composing text and no states
+    pub fn is_empty(&self) -> bool {
+        self.state.composing_text().is_empty() && self.state.active_states().is_empty()
+    }
+}
+
 /// Main KeyMagic engine for processing keyboard input
 pub struct KeyMagicEngine {
     /// Loaded keyboard layout
     keyboard: Km2File,
+    /// Layout options in effect: the keyboard's, with user overrides applied
+    options: LayoutOptions,
     /// Engine state
     state: EngineState,
-    /// Preprocessed rules with patterns
+    /// Preprocessed rules with patterns, in matching priority order
     rules: Vec<(Rule, Pattern)>,
-    /// Extracted strings for faster access
-    strings: Vec<String>,
-    /// History of engine states for undo functionality
-    state_history: Vec<EngineState>,
+    /// Index in `keyboard.rules` of each entry of `rules`
+    rule_order: Vec<usize>,
+    /// What of each rule can come from earlier keys, for `is_prefix`; built
+    /// on first use since it decodes the variables rules match
+    prefixes: OnceLock<PrefixIndex>,
+    /// Rules (indices into `keyboard.rules`) applied by the last processed key
+    last_matched_rules: Vec<usize>,
+    /// Named rule groups declared by the keyboard
+    rule_groups: Vec<RuleGroup>,
+    /// Names of the groups switched off by the user
+    disabled_groups: BTreeSet<String>,
+    /// Positions in `rules` skipped when matching a key: rules of disabled
+    /// groups and post-rules
+    disabled_rules: RuleMask,
+    /// Rules (indices into `keyboard.rules`) compiled from `@post` sections
+    post_rules: PostRules,
+    /// Positions in `rules` skipped by the post-rule pass, `None` if the
+    /// keyboard has no post-rules
+    post_pass_disabled: Option<RuleMask>,
+    /// The keyboard's strings; shares decoded entries with the keyboard
+    strings: StringTable,
+    /// History of engine states for smart backspace (oldest first, bounded)
+    state_history: VecDeque<EngineState>,
     /// Maximum number of states to keep in history
     max_history_size: usize,
+    /// Transform applied to text emitted to the host
+    output_transform: Option<Box<dyn Transform>>,
+    /// Keys handed back to the host unprocessed, one bit per `VirtualKey` code
+    passthrough_keys: u128,
+    /// Every key passes through, whatever `passthrough_keys` says; set for
+    /// the fallback keyboard
+    passthrough_all: bool,
+    /// Whether a passthrough key commits the composing text first
+    commit_on_passthrough: bool,
+    /// The host application's single-key shortcuts, passed through while
+    /// nothing is composed; one bit per `VirtualKey` code
+    shortcut_keys: u128,
+    /// Match statistics, collected only while enabled
+    histogram: Option<RuleHistogram>,
+    /// Characters a key may delete beyond those the engine emitted
+    delete_slack: usize,
+    /// Asked for suggestions after each processed key, if registered
+    suggestion_provider: Option<Arc<dyn SuggestionProvider>>,
+    /// Most suggestions attached to an output
+    max_suggestions: usize,
+    /// Suggestions of the last processed key, for `apply_suggestion`
+    suggestions: Vec<Suggestion>,
+    /// Tail of the text committed so far, the context of suggestions
+    suggestion_context: String,
 }
 
 impl KeyMagicEngine {
     /// Creates a new engine with the given keyboard layout
     pub fn new(keyboard: Km2File) -> Result<Self> {
-        // Extract strings from StringEntry
-        let strings: Vec<String> = keyboard.strings.iter()
-            .map(|entry| entry.value.clone())
-            .collect();
+        let strings = keyboard.strings.clone();
         
         // Preprocess and sort rules
-        let mut rules = Self::preprocess_rules(&keyboard)?;
-        Self::sort_rules(&mut rules);
+        let rules = Self::preprocess_rules(&keyboard)?;
+        let (rules, rule_order) = Self::sort_rules(rules);
+        let rule_groups = keyboard.metadata().rule_groups();
+        let post_rules = keyboard.metadata().post_rules();
 
-        Ok(Self {
+        let mut engine = Self {
+            options: keyboard.header.layout_options,
             keyboard,
             state: EngineState::new(),
             rules,
+            rule_order,
+            prefixes: OnceLock::new(),
+            last_matched_rules: Vec::new(),
+            rule_groups,
+            disabled_groups: BTreeSet::new(),
+            disabled_rules: RuleMask::new(),
+            post_rules,
+            post_pass_disabled: None,
             strings,
-            state_history: Vec::new(),
+            state_history: VecDeque::new(),
             max_history_size: 20,
-        })
+            output_transform: None,
+            passthrough_keys: 0,
+            passthrough_all: false,
+            commit_on_passthrough: false,
+            shortcut_keys: 0,
+            histogram: None,
+            delete_slack: DEFAULT_DELETE_SLACK,
+            suggestion_provider: None,
+            max_suggestions: DEFAULT_MAX_SUGGESTIONS,
+            suggestions: Vec::new(),
+            suggestion_context: String::new(),
+        };
+        engine.update_disabled_rules();
+        Ok(engine)
+    }
+
+    /// Creates an engine with the built-in fallback keyboard, which passes
+    /// every key through; for hosts whose keyboard failed to load
+    pub fn fallback() -> Self {
+        let keyboard = Km2Loader::load(FALLBACK_KM2).expect("built-in fallback keyboard loads");
+        let mut engine = Self::new(keyboard).expect("built-in fallback keyboard has no rules to reject");
+        // Without rules the engine would still compose typed characters
+        engine.passthrough_all = true;
+        engine
+    }
+
+    /// Creates an engine whose layout options are the keyboard's with
+    /// `overrides` applied
+    pub fn with_options(keyboard: Km2File, overrides: &LayoutOverrides) -> Result<Self> {
+        let options = keyboard.header.layout_options.with_overrides(overrides)?;
+        let mut engine = Self::new(keyboard)?;
+        engine.options = options;
+        Ok(engine)
     }
 
     /// Processes a key input and returns the engine output
     pub fn process_key(&mut self, input: KeyInput) -> Result<EngineOutput> {
-        Self::process_key_internal(&self.keyboard, &self.rules, &self.strings, input, &mut self.state, &mut self.state_history, self.max_history_size)
+        let mut matched = Vec::new();
+        self.last_matched_rules.clear();
+        if self.is_passthrough_key(input.key_code) || self.passes_as_shortcut(input.key_code) {
+            let committed = (self.commit_on_passthrough && self.suggestion_provider.is_some())
+                .then(|| self.state.composing_text().to_string());
+            let output = Self::pass_through(self.commit_on_passthrough, &mut self.state, &mut self.state_history, self.output_transform.as_deref());
+            if let Some(text) = committed {
+                self.note_commit(&text);
+            }
+            return Ok(self.attach_suggestions(output));
+        }
+        let mut scanned = 0;
+        let output = Self::process_key_internal(&self.options, &self.rules, &self.disabled_rules, self.post_pass_disabled.as_ref(), &self.strings, input, &mut self.state, &mut self.state_history, self.max_history_size, self.output_transform.as_deref(), self.delete_slack, &mut matched, &mut scanned)?;
+        self.last_matched_rules = matched.into_iter().map(|position| self.rule_order[position]).collect();
+        if let Some(histogram) = &mut self.histogram {
+            histogram.record(&self.last_matched_rules, scanned);
+        }
+        Ok(self.attach_suggestions(output))
+    }
+
+    /// Asks the provider, if any, for suggestions after a key; keys the
+    /// engine did not process drop the ones shown
+    fn attach_suggestions(&mut self, output: EngineOutput) -> EngineOutput {
+        let Some(provider) = &self.suggestion_provider else {
+            return output;
+        };
+        self.suggestions.clear();
+        if output.is_processed {
+            self.suggestions = provider.suggest(&self.suggestion_context, self.state.composing_text());
+            self.suggestions.truncate(self.max_suggestions);
+        }
+        output.with_suggestions(self.suggestions.clone())
     }
 
-    /// Processes a key input without modifying engine state (test/preview mode)
+    /// Tells the provider about committed text and keeps its tail as context
+    fn note_commit(&mut self, text: &str) {
+        let Some(provider) = &self.suggestion_provider else {
+            return;
+        };
+        if text.is_empty() {
+            return;
+        }
+        provider.committed(text);
+        self.suggestion_context.push_str(text);
+        let excess = self.suggestion_context.chars().count().saturating_sub(SUGGESTION_CONTEXT_CHARS);
+        if let Some((index, _)) = self.suggestion_context.char_indices().nth(excess) {
+            self.suggestion_context.drain(..index);
+        }
+    }
+
+    /// Processes a key input without modifying engine state (test/preview
+    /// mode); rules never raise notifications here
     pub fn process_key_test(&self, input: KeyInput) -> Result<EngineOutput> {
         let mut temp_state = self.state.clone();
         let mut temp_history = self.state_history.clone();
-        Self::process_key_internal(&self.keyboard, &self.rules, &self.strings, input, &mut temp_state, &mut temp_history, self.max_history_size)
+        self.process_key_detached(input, &mut temp_state, &mut temp_history, &mut Vec::new())
+    }
+
+    /// Processes a key input against a state owned by the caller; `matched`
+    /// receives the original indices of the applied rules. Nothing is shown
+    /// for such a key, so its output carries no notification.
+    pub(crate) fn process_key_detached(&self, input: KeyInput, state: &mut EngineState, history: &mut VecDeque<EngineState>, matched: &mut Vec<usize>) -> Result<EngineOutput> {
+        if self.is_passthrough_key(input.key_code) || (self.is_shortcut_key(input.key_code) && state.composing_text().is_empty()) {
+            return Ok(Self::pass_through(self.commit_on_passthrough, state, history, self.output_transform.as_deref()));
+        }
+        let mut positions = Vec::new();
+        let output = Self::process_key_internal(&self.options, &self.rules, &self.disabled_rules, self.post_pass_disabled.as_ref(), &self.strings, input, state, history, self.max_history_size, self.output_transform.as_deref(), self.delete_slack, &mut positions, &mut 0)?;
+        matched.extend(positions.into_iter().map(|position| self.rule_order[position]));
+        Ok(output.with_notification(None))
+    }
+
+    /// Whether the composition must go on after `key`: a later key may match
+    /// a rule using the text or states the key leaves. Otherwise hosts can
+    /// commit right away, telling by the result whether a rule matched.
+    ///
+    /// The key is tried against the current composing text and states without
+    /// changing them. Rules starting with a variable count for any character
+    /// of it, and rules needing states count whenever those states are on.
+    pub fn is_prefix(&self, key: &KeyInput) -> PrefixResult {
+        let mut state = self.state.clone();
+        let mut history = self.state_history.clone();
+        let mut matched = Vec::new();
+        if self.process_key_detached(key.clone(), &mut state, &mut history, &mut matched).is_err() {
+            return PrefixResult::PrefixOfLonger;
+        }
+
+        let ignore_ascii_case = self.options.case_insensitive_ascii == 1;
+        let prefixes = self.prefixes.get_or_init(|| PrefixIndex::new(&self.rules, &self.strings));
+        if prefixes.continues(state.composing_text(), state.active_states(), ignore_ascii_case, |position| self.is_rule_live(position)) {
+            PrefixResult::PrefixOfLonger
+        } else if matched.is_empty() {
+            PrefixResult::No
+        } else {
+            PrefixResult::CompleteMatchOnly
+        }
+    }
+
+    /// What the engine would do with `key` now, and why: the rule that takes
+    /// it, or the rules closest to taking it. Nothing is changed; the key is
+    /// tried against the current composing text and states.
+    pub fn explain_key(&self, key: &KeyInput) -> Explanation {
+        let passed = if self.is_passthrough_key(key.key_code) {
+            Some(KeyOutcome::PassedThrough)
+        } else if self.passes_as_shortcut(key.key_code) {
+            Some(KeyOutcome::HostShortcut)
+        } else {
+            None
+        };
+        let composing_text = self.state.composing_text();
+        let unmatched = if key.key_code == VirtualKey::Back as u16 && !composing_text.is_empty() {
+            KeyOutcome::Backspace
+        } else if key.character.is_some() {
+            if self.options.eat == 0 { KeyOutcome::Typed } else { KeyOutcome::Eaten }
+        } else {
+            KeyOutcome::Unprocessed
+        };
+
+        let ignore_ascii_case = self.options.case_insensitive_ascii == 1;
+        let explainer = Explainer::new(&self.keyboard, &self.strings, key, composing_text, self.state.active_states(), ignore_ascii_case);
+        let rules = self.prioritized_rules().map(|(index, pattern)| TriedRule {
+            index,
+            pattern,
+            disabled_group: self.rule_groups
+                .iter()
+                .find(|group| self.disabled_groups.contains(&group.name) && group.contains(index))
+                .map(|group| group.name.as_str()),
+        });
+        explainer.explain(rules, passed, unmatched)
+    }
+
+    /// Whether the rule at `position` in `rules` can match, in the key pass or
+    /// the post-rule pass
+    fn is_rule_live(&self, position: usize) -> bool {
+        !self.disabled_rules.is_disabled(position)
+            || self.post_pass_disabled.as_ref().is_some_and(|mask| !mask.is_disabled(position))
+    }
+
+    /// Output for a passthrough key: unprocessed, with the composing text
+    /// either kept or committed. The key still ends the states the previous
+    /// key left on, as every key does; a commit drops them even when nothing
+    /// was composed, so they never carry over into the next word.
+    fn pass_through(commit: bool, state: &mut EngineState, history: &mut VecDeque<EngineState>, transform: Option<&dyn Transform>) -> EngineOutput {
+        if commit {
+            state.reset();
+            history.clear();
+        } else {
+            state.clear_states();
+        }
+        let text = match transform {
+            Some(t) => t.apply(state.composing_text()),
+            None => state.composing_text().to_string(),
+        };
+        let caret = emitted_caret(state.composing_text(), state.composing_caret(), transform);
+        EngineOutput::none(text).with_caret(caret)
     }
 
     /// Internal key processing that works with a mutable state reference
-    fn process_key_internal(keyboard: &Km2File, rules: &[(Rule, Pattern)], strings: &[String], input: KeyInput, state: &mut EngineState, state_history: &mut Vec<EngineState>, max_history_size: usize) -> Result<EngineOutput> {
+    #[allow(clippy::too_many_arguments)]
+    fn process_key_internal(options: &LayoutOptions, rules: &[(Rule, Pattern)], disabled: &RuleMask, post_disabled: Option<&RuleMask>, strings: &StringTable, input: KeyInput, state: &mut EngineState, state_history: &mut VecDeque<EngineState>, max_history_size: usize, transform: Option<&dyn Transform>, delete_slack: usize, matched: &mut Vec<usize>, scanned: &mut usize) -> Result<EngineOutput> {
         // Store initial state for action generation
         let before_text = state.composing_text().to_string();
         
@@ -71,19 +358,23 @@ impl KeyMagicEngine {
         let state_before_processing = state.clone();
 
         // Create match context
+        let ignore_ascii_case = options.case_insensitive_ascii == 1;
         let context = MatchContext::for_key_input(
             state.composing_text(),
             &input,
             state.active_states(),
-        );
+        ).ignoring_ascii_case(ignore_ascii_case);
 
         // Track whether a rule was matched (input was processed)
         let is_processed: bool;
+        // Whether the engine itself deleted a character for backspace
+        let mut backspaced = false;
 
         // Try to find a matching rule
-        if let Some((rule, pattern, captures)) = RuleMatcher::find_match(rules, &context, strings) {
+        if let Some((position, rule, pattern, captures)) = RuleMatcher::find_match(rules, disabled, &context, strings, scanned) {
             // A rule matched, so the input was processed
             is_processed = true;
+            matched.push(position);
 
             // Calculate the matched length from the pattern
             let matched_len = pattern.calculate_match_length(strings).unwrap_or(0);
@@ -115,7 +406,11 @@ impl KeyMagicEngine {
                 RecursiveProcessor::process_recursive(
                     state,
                     rules,
+                    disabled,
                     strings,
+                    ignore_ascii_case,
+                    matched,
+                    scanned,
                 )?;
             }
         } else {
@@ -125,10 +420,10 @@ impl KeyMagicEngine {
             if input.key_code == VirtualKey::Back as u16
                 && !state.composing_text().is_empty() {
                 // Backspace key pressed, and composing buffer is not empty
-                if keyboard.header.layout_options.auto_bksp == 1 {
-                    if !state_history.is_empty() {
-                        // Restore from history (undo-like behavior)
-                        *state = state_history.pop().unwrap();
+                if options.auto_bksp == 1 {
+                    if let Some(previous) = state_history.pop_back() {
+                        // Restore from history, undoing the last processed key as a whole
+                        *state = previous;
                     } else {
                         // No history, delete one character backward
                         state.composing_buffer_mut().backspace();
@@ -139,12 +434,13 @@ impl KeyMagicEngine {
                     state.composing_buffer_mut().backspace();
                     is_processed = true;
                 }
+                backspaced = true;
             } else if let Some(ch) = input.character {
                 // if character is available, set is_processed to true
                 is_processed = true;
 
                 // append character if available & not eat_all_unused_keys
-                if keyboard.header.layout_options.eat == 0 {
+                if options.eat == 0 {
                     state.composing_buffer_mut().append(&ch.to_string());
                 } else {
                     // key is processed and eaten
@@ -158,27 +454,112 @@ impl KeyMagicEngine {
             state.clear_states();
         }
 
-        // Generate output action
-        let after_text = state.composing_text().to_string();
+        // Post-rules correct the composing text after every processed key.
+        // Backspace is left alone so corrections don't fight the deletion.
+        if let Some(post_disabled) = post_disabled {
+            if is_processed && !backspaced {
+                RecursiveProcessor::process_post_rules(state, rules, post_disabled, strings, ignore_ascii_case, matched, scanned)?;
+            }
+        }
+
+        // Generate output action against the text the host actually sees
+        let emit = |text: &str| match transform {
+            Some(t) => t.apply(text),
+            None => text.to_string(),
+        };
+        let before_text = emit(&before_text);
+        let mut after_text = emit(state.composing_text());
+
+        // A key may only delete what the engine emitted, plus the slack for
+        // rules rewriting the host's text around the cursor; a broken
+        // keyboard must not backspace through the user's document. Counted
+        // in emitted characters, the units of the action.
+        let emitted = before_text.chars().count().saturating_sub(emit(state_before_processing.synced_text()).chars().count());
+        let limit = emitted + delete_slack;
+        let clamped = ActionGenerator::deleted_text(&before_text, &after_text).chars().count() > limit;
+        if clamped {
+            after_text = clamp_edit(state, state_before_processing.composing_text(), &before_text, limit, &emit);
+        }
         let action = ActionGenerator::generate_action(&before_text, &after_text, true);
+        let deleted = ActionGenerator::deleted_text(&before_text, &after_text);
+        let kept = state_before_processing.composing_text().chars().count()
+            - ActionGenerator::deleted_text(state_before_processing.composing_text(), state.composing_text()).chars().count();
+        state.truncate_synced(kept);
 
-        // Record state in history (but not for backspace operations)
-        if input.key_code != VirtualKey::Back as u16 && is_processed {
-            state_history.push(state_before_processing);
+        // Record state in history (but not for backspace operations). Only keys
+        // that changed something are recorded, so every smart backspace has a
+        // visible effect; without smart backspace there is nothing to undo.
+        let changed = state_before_processing.composing_text() != state.composing_text()
+            || state_before_processing.active_states() != state.active_states();
+        if options.auto_bksp == 1
+            && input.key_code != VirtualKey::Back as u16
+            && is_processed
+            && changed
+        {
+            state_history.push_back(state_before_processing);
             
             // Maintain max history size
             if state_history.len() > max_history_size {
-                state_history.remove(0);
+                state_history.pop_front();
             }
         }
 
-        Ok(EngineOutput::new(after_text, action, is_processed))
+        let caret = emitted_caret(state.composing_text(), state.composing_caret(), transform);
+        Ok(EngineOutput::new(after_text, action, deleted, is_processed)
+            .with_caret(caret)
+            .with_notification(state.take_notification())
+            .with_clamped(clamped))
     }
 
-    /// Resets the engine state
+    /// Resets the engine state; same as `reset_level(ResetLevel::Full)`
     pub fn reset(&mut self) {
-        self.state.reset();
+        self.reset_level(ResetLevel::Full);
+    }
+
+    /// Clears the engine state up to `level`, see [`ResetLevel`]
+    pub fn reset_level(&mut self, level: ResetLevel) {
+        self.state.clear_composing();
         self.state_history.clear();
+        self.suggestions.clear();
+        if level == ResetLevel::Composing {
+            return;
+        }
+        self.state.clear_states();
+        if level == ResetLevel::Full {
+            self.last_matched_rules.clear();
+        }
+    }
+
+    /// Ends the composition: returns the composing text as emitted to the
+    /// host, for the host to commit, and resets the engine
+    pub fn flush(&mut self) -> String {
+        let text = self.emitted_text();
+        if self.suggestion_provider.is_some() {
+            let committed = self.state.composing_text().to_string();
+            self.note_commit(&committed);
+        }
+        self.reset();
+        text
+    }
+
+    /// States on for the next key, in index order. Keys clear them unless
+    /// the rule they match switches them on again; `reset`, `flush` and
+    /// `set_composing_text` clear them too, `reset_level(Composing)` does not.
+    pub fn active_states(&self) -> Vec<usize> {
+        let mut states: Vec<usize> = self.state.active_states().iter().copied().collect();
+        states.sort_unstable();
+        states
+    }
+
+    /// Names of the states in [`active_states`](Self::active_states), from
+    /// the source when the keyboard kept them and `state{index}` otherwise
+    pub fn active_state_names(&self) -> Vec<String> {
+        self.active_states().into_iter().map(|index| self.keyboard.state_name(index)).collect()
+    }
+
+    /// Number of keys that smart backspace can currently undo
+    pub fn undo_depth(&self) -> usize {
+        self.state_history.len()
     }
 
     /// Sets the composing text and resets states
@@ -186,18 +567,312 @@ impl KeyMagicEngine {
     pub fn set_composing_text(&mut self, text: String) {
         self.state.set_composing_text(text);
         self.state_history.clear();
+        self.suggestions.clear();
+    }
+
+    /// Saves the composition: composing text, caret, states and the history
+    /// smart backspace undoes
+    pub fn snapshot(&self) -> EngineSnapshot {
+        let mut state = self.state.clone();
+        // A message is shown once, by the key that produced it
+        state.take_notification();
+        EngineSnapshot { state, history: self.state_history.clone() }
+    }
+
+    /// Continues a composition saved with `snapshot`. States the loaded
+    /// keyboard does not use are harmless; they never match.
+    pub fn restore(&mut self, snapshot: EngineSnapshot) {
+        self.state = snapshot.state;
+        self.state_history = snapshot.history;
+        self.suggestions.clear();
     }
 
     /// Gets the current composing text
+    ///
+    /// The engine measures the composition in Unicode scalar values (`char`s);
+    /// hosts editing UTF-16 documents should use `EngineOutput::delete_utf16_units`
+    /// rather than deriving counts from this text themselves.
     pub fn composing_text(&self) -> &str {
         self.state.composing_text()
     }
 
+    /// Gets the caret position within the composing text, in characters.
+    /// Always at the end unless moved with `set_composing_caret`.
+    pub fn composing_caret(&self) -> usize {
+        self.state.composing_caret()
+    }
+
+    /// Moves the caret within the composing text (in characters, clamped to
+    /// the end). Edits made by rules put it back at the end.
+    pub fn set_composing_caret(&mut self, caret: usize) {
+        self.state.composing_buffer_mut().set_caret(caret);
+    }
+
+    /// Gets the caret position within `emitted_text`, in characters
+    pub fn emitted_caret(&self) -> usize {
+        emitted_caret(self.state.composing_text(), self.state.composing_caret(), self.output_transform.as_deref())
+    }
+
+    /// Gets the composing text as emitted to the host (after the output transform)
+    pub fn emitted_text(&self) -> String {
+        match &self.output_transform {
+            Some(t) => t.apply(self.state.composing_text()),
+            None => self.state.composing_text().to_string(),
+        }
+    }
+
+    /// Sets the transform applied to emitted text, or `None` for plain Unicode
+    ///
+    /// Matching still runs on the Unicode composing buffer; only the output
+    /// composing text and actions are transformed, on every key, so what
+    /// `flush` commits is the text the host already shows.
+    pub fn set_output_transform(&mut self, id: Option<TransformId>) -> Result<()> {
+        self.output_transform = match id {
+            Some(id) => Some(create_transform(id).ok_or(Error::TransformUnavailable(id.name()))?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Gets the active output transform, if any
+    pub fn output_transform(&self) -> Option<TransformId> {
+        self.output_transform.as_ref().map(|t| t.id())
+    }
+
     /// Gets the loaded keyboard layout
     pub fn keyboard(&self) -> &Km2File {
         &self.keyboard
     }
 
+    /// Layout options in effect; `keyboard().header` keeps the keyboard's own
+    pub fn layout_options(&self) -> LayoutOptions {
+        self.options
+    }
+
+    /// Replaces the layout options in effect, e.g. after the user changed an
+    /// override. The composing text is kept.
+    pub fn set_layout_options(&mut self, options: LayoutOptions) {
+        self.options = options;
+    }
+
+    /// Indices (into `keyboard().rules`) of the rules applied by the last
+    /// `process_key` call, in application order including recursive matches
+    pub fn last_matched_rules(&self) -> &[usize] {
+        &self.last_matched_rules
+    }
+
+    /// Named rule groups declared by the keyboard
+    pub fn rule_groups(&self) -> &[RuleGroup] {
+        &self.rule_groups
+    }
+
+    /// Returns true unless the group was switched off. Unknown groups count as enabled.
+    pub fn is_group_enabled(&self, name: &str) -> bool {
+        !self.disabled_groups.contains(name)
+    }
+
+    /// Switches the rules of a group on or off. Rules outside any group are
+    /// always enabled; a rule in several groups is off if any of them is.
+    pub fn set_group_enabled(&mut self, name: &str, enabled: bool) -> Result<()> {
+        if !self.rule_groups.iter().any(|group| group.name == name) {
+            return Err(Error::UnknownRuleGroup(name.to_string()));
+        }
+
+        let changed = if enabled {
+            self.disabled_groups.remove(name)
+        } else {
+            self.disabled_groups.insert(name.to_string())
+        };
+        if changed {
+            self.update_disabled_rules();
+        }
+        Ok(())
+    }
+
+    /// Sets the keys that always pass through to the host unprocessed,
+    /// whatever the modifiers and rules, replacing any set before
+    pub fn set_passthrough_keys(&mut self, keys: &[VirtualKey]) {
+        self.passthrough_keys = key_bits(keys);
+    }
+
+    /// Returns true if keys with this code (a `VirtualKey` value) pass through
+    pub fn is_passthrough_key(&self, key_code: u16) -> bool {
+        self.passthrough_all || has_key_bit(self.passthrough_keys, key_code)
+    }
+
+    /// Sets the host application's single-key shortcuts, replacing any set
+    /// before. Unlike passthrough keys they belong to the host, not the
+    /// keyboard, and only pass through while nothing is composed.
+    pub fn set_shortcut_keys(&mut self, keys: &[VirtualKey]) {
+        self.shortcut_keys = key_bits(keys);
+    }
+
+    /// Returns true if keys with this code (a `VirtualKey` value) are shortcuts
+    /// of the host application
+    pub fn is_shortcut_key(&self, key_code: u16) -> bool {
+        has_key_bit(self.shortcut_keys, key_code)
+    }
+
+    /// Whether a key goes to the host unprocessed as an application shortcut:
+    /// it is one of the shortcut keys and the composing text is empty, so the
+    /// key would start a composition rather than continue one
+    pub fn passes_as_shortcut(&self, key_code: u16) -> bool {
+        self.is_shortcut_key(key_code) && self.state.composing_text().is_empty()
+    }
+
+    /// Whether a passthrough key commits the composing text before it goes to
+    /// the host. The engine then forgets the text; hosts end their composition
+    /// as they do whenever the composing text becomes empty.
+    pub fn set_commit_on_passthrough(&mut self, commit: bool) {
+        self.commit_on_passthrough = commit;
+    }
+
+    pub fn commit_on_passthrough(&self) -> bool {
+        self.commit_on_passthrough
+    }
+
+    /// How many characters a key may delete beyond the text the engine
+    /// emitted since the composition started (8 by default). Text the host
+    /// hands over with `set_composing_text` is not the engine's, so rules
+    /// rewriting it are limited to the slack; larger deletes are cut short,
+    /// keeping the host's text in the composing text, and the output reports
+    /// `clamped`. Counts are compared in characters of the emitted text,
+    /// after any output transform, like the delete counts of actions.
+    pub fn set_delete_slack(&mut self, slack: usize) {
+        self.delete_slack = slack;
+    }
+
+    pub fn delete_slack(&self) -> usize {
+        self.delete_slack
+    }
+
+    /// Returns true if the rule at `index` (into `keyboard().rules`) is a
+    /// post-rule, applied to the composing text after each key
+    pub fn is_post_rule(&self, index: usize) -> bool {
+        self.post_rules.contains(index)
+    }
+
+    /// Position of a rule (index into `keyboard().rules`) in the order rules
+    /// are tried, 0 for the first
+    pub fn rule_priority(&self, index: usize) -> Option<usize> {
+        self.rule_order.iter().position(|&rule| rule == index)
+    }
+
+    /// Starts or stops collecting match statistics. Enabling starts from an
+    /// empty histogram; disabling drops it.
+    pub fn set_rule_histogram_enabled(&mut self, enabled: bool) {
+        self.histogram = enabled.then(|| RuleHistogram::new(self.keyboard.rules.len()));
+    }
+
+    /// Match statistics since they were enabled, `None` while disabled
+    pub fn rule_histogram(&self) -> Option<&RuleHistogram> {
+        self.histogram.as_ref()
+    }
+
+    /// Registers the provider asked for suggestions after each processed
+    /// key, or `None` to stop asking; see [`SuggestionProvider`]
+    pub fn set_suggestion_provider(&mut self, provider: Option<Arc<dyn SuggestionProvider>>) {
+        self.suggestion_provider = provider;
+        self.suggestions.clear();
+    }
+
+    pub fn has_suggestion_provider(&self) -> bool {
+        self.suggestion_provider.is_some()
+    }
+
+    /// Most suggestions attached to an output (5 by default)
+    pub fn set_max_suggestions(&mut self, max: usize) {
+        self.max_suggestions = max;
+        self.suggestions.truncate(max);
+    }
+
+    /// Suggestions attached to the output of the last key
+    pub fn suggestions(&self) -> &[Suggestion] {
+        &self.suggestions
+    }
+
+    /// Sets the text before the composition handed to the provider as
+    /// context, e.g. read from the document by the host. Text the engine
+    /// commits is appended to it.
+    pub fn set_suggestion_context(&mut self, text: &str) {
+        self.suggestion_context.clear();
+        let skip = text.chars().count().saturating_sub(SUGGESTION_CONTEXT_CHARS);
+        self.suggestion_context.extend(text.chars().skip(skip));
+    }
+
+    /// Replaces the end of the composing text with the suggestion at `index`
+    /// of the last output, as a key would: the output carries the edit for
+    /// the host and smart backspace undoes it. The states are cleared and
+    /// the suggestions dropped.
+    pub fn apply_suggestion(&mut self, index: usize) -> Result<EngineOutput> {
+        let suggestion = self.suggestions.get(index).cloned().ok_or(Error::InvalidSuggestionIndex(index))?;
+        let before = self.state.clone();
+        self.state.clear_states();
+        self.state.composing_buffer_mut().replace_from_end(suggestion.replace_chars, &suggestion.text);
+        let kept = before.composing_text().chars().count()
+            - ActionGenerator::deleted_text(before.composing_text(), self.state.composing_text()).chars().count();
+        self.state.truncate_synced(kept);
+
+        let transform = self.output_transform.as_deref();
+        let (before_text, after_text) = match transform {
+            Some(t) => (t.apply(before.composing_text()), t.apply(self.state.composing_text())),
+            None => (before.composing_text().to_string(), self.state.composing_text().to_string()),
+        };
+        let action = ActionGenerator::generate_action(&before_text, &after_text, true);
+        let deleted = ActionGenerator::deleted_text(&before_text, &after_text);
+        let caret = emitted_caret(self.state.composing_text(), self.state.composing_caret(), transform);
+        let output = EngineOutput::new(after_text, action, deleted, true).with_caret(caret);
+
+        if self.options.auto_bksp == 1 && before.composing_text() != self.state.composing_text() {
+            self.state_history.push_back(before);
+            if self.state_history.len() > self.max_history_size {
+                self.state_history.pop_front();
+            }
+        }
+        self.last_matched_rules.clear();
+        self.suggestions.clear();
+        Ok(output)
+    }
+
+    /// Rebuilds the rule masks from the disabled groups and post-rules
+    fn update_disabled_rules(&mut self) {
+        self.disabled_rules.clear();
+        let has_post_rules = !self.post_rules.is_empty();
+        let mut post_pass_disabled = RuleMask::new();
+        let disabled: Vec<&RuleGroup> = self.rule_groups
+            .iter()
+            .filter(|group| self.disabled_groups.contains(&group.name))
+            .collect();
+        if disabled.is_empty() && !has_post_rules {
+            self.post_pass_disabled = None;
+            return;
+        }
+        for (position, &index) in self.rule_order.iter().enumerate() {
+            let group_disabled = disabled.iter().any(|group| group.contains(index));
+            let is_post = self.post_rules.contains(index);
+            if group_disabled || is_post {
+                self.disabled_rules.disable(position);
+            }
+            if group_disabled || !is_post {
+                post_pass_disabled.disable(position);
+            }
+        }
+        self.post_pass_disabled = has_post_rules.then_some(post_pass_disabled);
+    }
+
+    /// Rules in matching priority order with their index in `keyboard().rules`
+    /// Post-rules are left out since they never compete for a key.
+    pub(crate) fn prioritized_rules(&self) -> impl Iterator<Item = (usize, &Pattern)> {
+        self.rule_order.iter().copied()
+            .zip(self.rules.iter().map(|(_, pattern)| pattern))
+            .filter(|(index, _)| !self.post_rules.contains(*index))
+    }
+
+    /// Variable contents used for matching
+    pub(crate) fn strings(&self) -> &StringTable {
+        &self.strings
+    }
+
     /// Preprocesses rules into patterns for efficient matching
     fn preprocess_rules(keyboard: &Km2File) -> Result<Vec<(Rule, Pattern)>> {
         keyboard.rules
@@ -213,9 +888,11 @@ impl KeyMagicEngine {
             .collect()
     }
 
-    /// Sorts rules by priority (state > VK > length)
-    fn sort_rules(rules: &mut [(Rule, Pattern)]) {
-        rules.sort_by(|a, b| {
+    /// Sorts rules by priority (state > VK > length), keeping source order for
+    /// equal priority. Also returns the original index of each sorted rule.
+    fn sort_rules(rules: Vec<(Rule, Pattern)>) -> (Vec<(Rule, Pattern)>, Vec<usize>) {
+        let mut indexed: Vec<(usize, (Rule, Pattern))> = rules.into_iter().enumerate().collect();
+        indexed.sort_by(|(_, a), (_, b)| {
             if a.1.has_priority_over(&b.1) {
                 std::cmp::Ordering::Less
             } else if b.1.has_priority_over(&a.1) {
@@ -224,9 +901,58 @@ impl KeyMagicEngine {
                 std::cmp::Ordering::Equal
             }
         });
+        indexed.into_iter().map(|(index, rule)| (rule, index)).unzip()
     }
 }
 
+/// Cuts short a key's edit that deletes more than `limit` characters of the
+/// emitted text `before_text`. The composing text is rebuilt from as little
+/// of the old one `before` as keeps the delete within the limit, followed by
+/// what the key inserted, so the engine holds exactly what the host will
+/// show; if no such text exists the key changes nothing. Returns the new
+/// emitted text.
+fn clamp_edit(state: &mut EngineState, before: &str, before_text: &str, limit: usize, emit: &dyn Fn(&str) -> String) -> String {
+    let after = state.composing_text().to_string();
+    let start = before.len() - ActionGenerator::deleted_text(before, &after).len();
+    let inserted = &after[start..];
+    // Keep one more character of the old text at a time until the delete fits
+    let (text, emitted) = before[start..]
+        .char_indices()
+        .map(|(index, ch)| format!("{}{}", &before[..start + index + ch.len_utf8()], inserted))
+        .map(|text| {
+            let emitted = emit(&text);
+            (text, emitted)
+        })
+        .find(|(_, emitted)| ActionGenerator::deleted_text(before_text, emitted).chars().count() <= limit)
+        .unwrap_or_else(|| (before.to_string(), before_text.to_string()));
+
+    state.composing_buffer_mut().replace_from_end(after.chars().count(), &text);
+    emitted
+}
+
+/// Maps a caret in the composing text to the text emitted after `transform`.
+/// A caret at the end stays at the end; otherwise the text before the caret
+/// is transformed on its own.
+fn emitted_caret(text: &str, caret: usize, transform: Option<&dyn Transform>) -> usize {
+    match transform {
+        Some(t) if caret >= text.chars().count() => t.apply(text).chars().count(),
+        Some(t) => t.apply(&text.chars().take(caret).collect::<String>()).chars().count(),
+        None => caret,
+    }
+}
+
+/// One bit per `VirtualKey` code; codes past the mask are dropped
+fn key_bits(keys: &[VirtualKey]) -> u128 {
+    keys.iter()
+        .map(|&key| key as u16)
+        .filter(|&code| code < u128::BITS as u16)
+        .fold(0, |bits, code| bits | (1 << code))
+}
+
+fn has_key_bit(bits: u128, key_code: u16) -> bool {
+    key_code < u128::BITS as u16 && bits & (1 << key_code) != 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
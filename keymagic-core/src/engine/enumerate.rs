@@ -0,0 +1,199 @@
+//! Enumeration of the outputs reachable by typing key sequences
+//!
+//! Used to generate documentation for a keyboard. Sequences are explored
+//! breadth-first from an empty composition. The state reached by every
+//! sequence is kept, so extending it by one key costs a single dry run.
+
+use std::collections::{HashSet, VecDeque};
+
+use super::engine::KeyMagicEngine;
+use super::state::EngineState;
+use crate::analysis::typed_key;
+use crate::conformance::KeyEvent;
+use crate::engine::KeyInput;
+use crate::VirtualKey;
+
+/// Unshifted characters of the keys on a US layout
+const US_KEYS: &str = "`1234567890-=qwertyuiop[]\\asdfghjkl;'zxcvbnm,./ ";
+
+/// Modifier combinations tried with every key besides plain typing
+const MODIFIER_SETS: &[&[VirtualKey]] = &[
+    &[VirtualKey::Control],
+    &[VirtualKey::Menu],
+    &[VirtualKey::Control, VirtualKey::Menu],
+    &[VirtualKey::Shift, VirtualKey::Control],
+    &[VirtualKey::Shift, VirtualKey::Menu],
+    &[VirtualKey::Shift, VirtualKey::Control, VirtualKey::Menu],
+];
+
+/// A key sequence and what it produces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputEntry {
+    /// Keys typed, starting from an empty composition
+    pub keys: Vec<KeyEvent>,
+    /// Composing text after the last key
+    pub output: String,
+    /// Rules (indices into `Km2File::rules`) applied by the last key
+    pub rules: Vec<usize>,
+}
+
+/// Counters for progress reporting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EnumerationProgress {
+    /// Depth currently being explored
+    pub depth: usize,
+    /// Sequences of the current depth expanded so far
+    pub expanded: usize,
+    /// Sequences of the current depth to expand
+    pub frontier: usize,
+    /// Key presses run through the engine
+    pub keys_tried: usize,
+}
+
+struct Node {
+    keys: Vec<KeyEvent>,
+    state: EngineState,
+    history: VecDeque<EngineState>,
+}
+
+/// Iterator over the entries of [`KeyMagicEngine::enumerate_outputs`]
+pub struct OutputEnumerator<'a> {
+    engine: &'a KeyMagicEngine,
+    max_depth: usize,
+    alphabet: Vec<(KeyEvent, KeyInput)>,
+    /// Output of each alphabet key typed on its own, if the engine processed it
+    single: Vec<Option<String>>,
+    frontier: Vec<Node>,
+    next: Vec<Node>,
+    pending: VecDeque<OutputEntry>,
+    progress: EnumerationProgress,
+}
+
+impl KeyMagicEngine {
+    /// Enumerates the key sequences of up to `max_depth` keys that make the
+    /// keyboard do something
+    ///
+    /// Single keys are listed when a rule handles them. Longer sequences
+    /// are listed only when their output differs from what their keys
+    /// produce one by one, i.e. when a rule combines them. Keys the engine
+    /// does not process, or that leave the state unchanged, end a sequence.
+    /// Keys are printable characters on a US layout and the same keys with
+    /// Ctrl and/or Alt. Keys that fail to process (e.g. runaway recursion)
+    /// are skipped.
+    pub fn enumerate_outputs(&self, max_depth: usize) -> OutputEnumerator<'_> {
+        let mut alphabet: Vec<(KeyEvent, KeyInput)> = (' '..='~')
+            .map(|ch| (KeyEvent::Char(ch), typed_key(ch)))
+            .collect();
+        for modifiers in MODIFIER_SETS {
+            for ch in US_KEYS.chars() {
+                if let Some(key) = VirtualKey::from_raw(typed_key(ch).key_code) {
+                    let mut combo = modifiers.to_vec();
+                    combo.push(key);
+                    let event = KeyEvent::Combo(combo);
+                    let input = event.to_key_input();
+                    alphabet.push((event, input));
+                }
+            }
+        }
+
+        let root = Node { keys: Vec::new(), state: EngineState::new(), history: VecDeque::new() };
+        let mut enumerator = OutputEnumerator {
+            engine: self,
+            max_depth,
+            single: Vec::new(),
+            alphabet,
+            frontier: Vec::new(),
+            next: Vec::new(),
+            pending: VecDeque::new(),
+            progress: EnumerationProgress::default(),
+        };
+        if max_depth > 0 {
+            enumerator.frontier.push(root);
+            enumerator.progress = EnumerationProgress { depth: 1, frontier: 1, ..Default::default() };
+        }
+        enumerator
+    }
+}
+
+impl OutputEnumerator<'_> {
+    pub fn progress(&self) -> EnumerationProgress {
+        self.progress
+    }
+
+    /// Tries every key after `node`, queueing entries and the nodes to expand next
+    fn expand(&mut self, node: &Node) {
+        let depth = node.keys.len() + 1;
+        let prefix_output = node.state.composing_text();
+
+        for index in 0..self.alphabet.len() {
+            let (event, input) = self.alphabet[index].clone();
+            let mut state = node.state.clone();
+            let mut history = node.history.clone();
+            let mut matched = Vec::new();
+            self.progress.keys_tried += 1;
+
+            let processed = self.engine
+                .process_key_detached(input, &mut state, &mut history, &mut matched)
+                .is_ok_and(|output| output.is_processed);
+            let changed = state.composing_text() != prefix_output || state.active_states() != node.state.active_states();
+            if depth == 1 {
+                self.single.push(if processed { Some(state.composing_text().to_string()) } else { None });
+            }
+            if !processed || !changed {
+                continue;
+            }
+
+            let listed = if depth == 1 {
+                !matched.is_empty()
+            } else {
+                // Typing the key on its own after the prefix would give this
+                let separate = self.single[index].as_deref().map(|single| format!("{}{}", prefix_output, single));
+                separate.as_deref() != Some(state.composing_text())
+            };
+
+            let mut keys = node.keys.clone();
+            keys.push(event);
+            if listed {
+                self.pending.push_back(OutputEntry {
+                    keys: keys.clone(),
+                    output: state.composing_text().to_string(),
+                    rules: dedup(matched),
+                });
+            }
+            if depth < self.max_depth {
+                self.next.push(Node { keys, state, history });
+            }
+        }
+    }
+}
+
+fn dedup(rules: Vec<usize>) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    rules.into_iter().filter(|rule| seen.insert(*rule)).collect()
+}
+
+impl Iterator for OutputEnumerator<'_> {
+    type Item = OutputEntry;
+
+    fn next(&mut self) -> Option<OutputEntry> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                return Some(entry);
+            }
+            if self.frontier.is_empty() {
+                if self.next.is_empty() {
+                    return None;
+                }
+                // Nodes are expanded from the back, so reverse to keep key order
+                self.frontier = std::mem::take(&mut self.next);
+                self.frontier.reverse();
+                self.progress.depth += 1;
+                self.progress.expanded = 0;
+                self.progress.frontier = self.frontier.len();
+            }
+            let node = self.frontier.pop()?;
+            self.expand(&node);
+            self.progress.expanded += 1;
+        }
+    }
+}
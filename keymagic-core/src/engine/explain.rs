@@ -0,0 +1,325 @@
+//! Why a key does what it does
+//!
+//! Keyboard authors testing a layout report keys that "do nothing" when no
+//! rule matches them, a state a rule needs is off, or the keyboard eats
+//! unused keys; a trace only covers keys a rule took.
+//! [`explain_key`](crate::KeyMagicEngine::explain_key) tries a key against
+//! every rule without changing anything and reports what the engine would
+//! do with it, how many rules are for the key at all, and the rules closest
+//! to matching with the element of each that failed.
+
+use std::collections::HashSet;
+
+use crate::engine::input::{KeyInput, ModifierState};
+use crate::engine::matching::{MatchContext, MatchFailure, Mismatch, Pattern, PatternElement, RuleMatcher, VariableMatch};
+use crate::km2::RuleFormatter;
+use crate::types::{IndexNames, Km2File, StringTable};
+use crate::VirtualKey;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Rules reported as near misses at most
+const MAX_CANDIDATES: usize = 5;
+
+/// What the engine does with a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(tag = "kind", rename_all = "snake_case"))]
+pub enum KeyOutcome {
+    /// A passthrough key: the application gets it, no rule is tried
+    PassedThrough,
+    /// One of the application's shortcut keys with nothing composed: the
+    /// application gets it, no rule is tried
+    HostShortcut,
+    /// A rule takes the key
+    RuleMatched {
+        /// Index in `keyboard().rules`
+        rule: usize,
+        /// The rule in KMS-like syntax
+        text: String,
+    },
+    /// No rule matches; the character is added as typed
+    Typed,
+    /// No rule matches and the keyboard eats unused keys: nothing is added
+    Eaten,
+    /// No rule matches; backspace deletes from the composing text
+    Backspace,
+    /// No rule matches and the key types no character: the application gets it
+    Unprocessed,
+}
+
+/// Why a rule does not take a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(tag = "kind", rename_all = "snake_case"))]
+pub enum RuleFailure {
+    /// The rule would match but its group is switched off
+    GroupDisabled { group: String },
+    /// The rule can never match: a key combination without exactly one main
+    /// key, or a variable missing from the file
+    Unmatchable,
+    /// The rule matches a typed character and the key types none
+    NoCharacter,
+    /// The text before the cursor is shorter than what the rule matches
+    ContextTooShort { needed: usize, available: usize },
+    /// The rule needs a state that is off
+    StateInactive { state: String },
+    /// The rule is for another key
+    KeyMismatch { expected: String },
+    /// The rule is for this key with other modifiers
+    ModifierMismatch { expected: String, pressed: String },
+    /// The text before the cursor, or the typed character, is not what the
+    /// element matches
+    ContextMismatch { expected: String, found: String },
+}
+
+/// A rule that does not take the key
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RuleCandidate {
+    /// Index in `keyboard().rules`
+    pub rule: usize,
+    /// The rule in KMS-like syntax
+    pub text: String,
+    /// Whether the rule is for this key: its key, or the last character it
+    /// matches, takes the key whatever the context and states
+    pub for_key: bool,
+    /// Index of the failing element on the rule's left side
+    pub element: usize,
+    pub failure: RuleFailure,
+}
+
+/// What the engine would do with a key, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Explanation {
+    pub outcome: KeyOutcome,
+    /// Rules for this key whatever the context and states; none means no
+    /// rule can ever take it
+    pub rules_for_key: usize,
+    /// Rules closest to taking the key, closest first, five at most; empty
+    /// when a rule takes it or none is tried
+    pub candidates: Vec<RuleCandidate>,
+}
+
+/// A rule as the engine tries it
+pub(crate) struct TriedRule<'a> {
+    /// Index in `keyboard().rules`
+    pub index: usize,
+    pub pattern: &'a Pattern,
+    /// The switched-off group keeping the rule from matching, if any
+    pub disabled_group: Option<&'a str>,
+}
+
+/// Tries one key against rules for an explanation
+pub(crate) struct Explainer<'a> {
+    keyboard: &'a Km2File,
+    strings: &'a StringTable,
+    formatter: RuleFormatter<'a>,
+    state_names: IndexNames,
+    variable_names: IndexNames,
+    key: &'a KeyInput,
+    context: MatchContext<'a>,
+}
+
+impl<'a> Explainer<'a> {
+    pub fn new(
+        keyboard: &'a Km2File,
+        strings: &'a StringTable,
+        key: &'a KeyInput,
+        composing_text: &'a str,
+        active_states: &'a HashSet<usize>,
+        ignore_ascii_case: bool,
+    ) -> Self {
+        let metadata = keyboard.metadata();
+        Self {
+            keyboard,
+            strings,
+            formatter: RuleFormatter::for_keyboard(keyboard),
+            state_names: metadata.state_names(),
+            variable_names: metadata.variable_names(),
+            key,
+            context: MatchContext::for_key_input(composing_text, key, active_states).ignoring_ascii_case(ignore_ascii_case),
+        }
+    }
+
+    /// Explains the key; `passed` is set for keys no rule is tried for, and
+    /// `unmatched` is what happens when no rule takes the key
+    pub fn explain(
+        &self,
+        rules: impl Iterator<Item = TriedRule<'a>>,
+        passed: Option<KeyOutcome>,
+        unmatched: KeyOutcome,
+    ) -> Explanation {
+        let mut rules_for_key = 0;
+        let mut matched = None;
+        let mut candidates = Vec::new();
+        for rule in rules {
+            let for_key = self.is_for_key(rule.pattern);
+            rules_for_key += for_key as usize;
+            if matched.is_some() {
+                continue;
+            }
+            let (element, failure) = match RuleMatcher::match_pattern(rule.pattern, &self.context, self.strings) {
+                Ok(_) => match rule.disabled_group {
+                    Some(group) => (rule.pattern.elements.len(), RuleFailure::GroupDisabled { group: group.to_string() }),
+                    None => {
+                        matched = Some(rule.index);
+                        continue;
+                    }
+                },
+                Err(failure) => (failure.element, self.describe_failure(rule.pattern, failure)),
+            };
+            candidates.push((rule.index, for_key, element, failure));
+        }
+
+        let outcome = match (passed, matched) {
+            (Some(passed), _) => passed,
+            (None, Some(rule)) => KeyOutcome::RuleMatched { rule, text: self.formatter.format_rule(&self.keyboard.rules[rule]) },
+            (None, None) => unmatched,
+        };
+        if !matches!(outcome, KeyOutcome::Typed | KeyOutcome::Eaten | KeyOutcome::Backspace | KeyOutcome::Unprocessed) {
+            candidates.clear();
+        }
+        // Rules for the key first, then those failing furthest along; the
+        // sort is stable, so ties stay in priority order
+        candidates.sort_by_key(|&(_, for_key, element, _)| (!for_key, std::cmp::Reverse(element)));
+        let candidates = candidates
+            .into_iter()
+            .take(MAX_CANDIDATES)
+            .map(|(rule, for_key, element, failure)| RuleCandidate {
+                rule,
+                text: self.formatter.format_rule(&self.keyboard.rules[rule]),
+                for_key,
+                element,
+                failure,
+            })
+            .collect();
+        Explanation { outcome, rules_for_key, candidates }
+    }
+
+    /// Whether the key part of a rule takes the key: the main key of its key
+    /// combination, or the last character it matches
+    fn is_for_key(&self, pattern: &Pattern) -> bool {
+        if pattern.has_vk() {
+            return pattern.elements.iter().any(|element| match element {
+                PatternElement::VirtualKey(keys) => keys
+                    .iter()
+                    .filter(|key| !is_modifier(key))
+                    .any(|key| VirtualKey::from_raw(self.key.key_code) == Some(*key)),
+                _ => false,
+            });
+        }
+        let Some(ch) = self.key.character else {
+            return false;
+        };
+        let last = pattern.elements.iter().rev().find(|element| !matches!(element, PatternElement::State(_)));
+        match last {
+            Some(PatternElement::String(s)) => s.chars().last().is_some_and(|last| self.same_char(last, ch)),
+            Some(PatternElement::Variable(index, how)) => {
+                let content = self.strings.get_str(*index).unwrap_or("");
+                match how {
+                    VariableMatch::Exact => content.ends_with(ch),
+                    VariableMatch::AnyOf => content.contains(ch),
+                    VariableMatch::NotAnyOf => !content.contains(ch),
+                }
+            }
+            Some(PatternElement::Any) => ch.is_ascii_graphic(),
+            _ => false,
+        }
+    }
+
+    fn same_char(&self, a: char, b: char) -> bool {
+        a == b || (self.context.ignore_ascii_case && a.eq_ignore_ascii_case(&b))
+    }
+
+    fn describe_failure(&self, pattern: &Pattern, failure: MatchFailure) -> RuleFailure {
+        let element = pattern.elements.get(failure.element);
+        match failure.mismatch {
+            Mismatch::Unmatchable => RuleFailure::Unmatchable,
+            Mismatch::NoCharacter => RuleFailure::NoCharacter,
+            Mismatch::TooShort { needed, available } => RuleFailure::ContextTooShort { needed, available },
+            Mismatch::State(index) => RuleFailure::StateInactive { state: self.state_name(index) },
+            Mismatch::Key => {
+                let expected = match element {
+                    Some(PatternElement::VirtualKey(keys)) => keys.iter().find(|key| !is_modifier(key)).map(|key| key.to_kms_name()),
+                    _ => None,
+                };
+                RuleFailure::KeyMismatch { expected: expected.unwrap_or_default().to_string() }
+            }
+            Mismatch::Modifiers => {
+                let keys = match element {
+                    Some(PatternElement::VirtualKey(keys)) => keys.as_slice(),
+                    _ => &[],
+                };
+                let required = ModifierState {
+                    shift: keys.contains(&VirtualKey::Shift),
+                    ctrl: keys.contains(&VirtualKey::Control),
+                    alt: keys.contains(&VirtualKey::Menu),
+                    caps_lock: false,
+                };
+                RuleFailure::ModifierMismatch { expected: modifier_names(&required), pressed: modifier_names(&self.key.modifiers) }
+            }
+            Mismatch::Text { at } => {
+                let text = RuleMatcher::text_to_match(pattern, &self.context).unwrap_or_default();
+                let len = match element {
+                    Some(PatternElement::String(s)) => s.chars().count(),
+                    Some(PatternElement::Variable(index, VariableMatch::Exact)) => {
+                        self.strings.get_str(*index).unwrap_or("").chars().count()
+                    }
+                    _ => 1,
+                };
+                let found = text.iter().skip(at).take(len).collect();
+                RuleFailure::ContextMismatch { expected: element.map(|e| self.describe_element(e)).unwrap_or_default(), found }
+            }
+        }
+    }
+
+    fn state_name(&self, index: usize) -> String {
+        match self.state_names.get(index) {
+            Some(name) => name.to_string(),
+            None => format!("state{}", index),
+        }
+    }
+
+    /// Name of the variable at `index` into the strings; the file counts
+    /// variables from 1
+    fn variable_name(&self, index: usize) -> String {
+        match self.variable_names.get(index + 1) {
+            Some(name) => format!("${}", name),
+            None => format!("$var{}", index + 1),
+        }
+    }
+
+    /// An element in KMS-like syntax
+    fn describe_element(&self, element: &PatternElement) -> String {
+        match element {
+            PatternElement::String(s) => format!("\"{}\"", s),
+            PatternElement::Variable(index, VariableMatch::Exact) => self.variable_name(*index),
+            PatternElement::Variable(index, VariableMatch::AnyOf) => format!("{}[*]", self.variable_name(*index)),
+            PatternElement::Variable(index, VariableMatch::NotAnyOf) => format!("{}[^]", self.variable_name(*index)),
+            PatternElement::Any => "ANY".to_string(),
+            PatternElement::State(index) => format!("('{}')", self.state_name(*index)),
+            PatternElement::VirtualKey(keys) => {
+                let names: Vec<&str> = keys.iter().map(VirtualKey::to_kms_name).collect();
+                format!("<{}>", names.join(" & "))
+            }
+        }
+    }
+}
+
+fn is_modifier(key: &VirtualKey) -> bool {
+    matches!(key, VirtualKey::Shift | VirtualKey::Control | VirtualKey::Menu)
+}
+
+/// Modifiers as `Ctrl+Shift`, or `none`
+fn modifier_names(modifiers: &ModifierState) -> String {
+    let names: Vec<&str> = [(modifiers.ctrl, "Ctrl"), (modifiers.alt, "Alt"), (modifiers.shift, "Shift")]
+        .into_iter()
+        .filter_map(|(on, name)| on.then_some(name))
+        .collect();
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join("+")
+    }
+}
@@ -0,0 +1,77 @@
+//! Rule match statistics
+
+/// Per-rule match counts and scan totals, collected by an engine while
+/// enabled with `KeyMagicEngine::set_rule_histogram_enabled`
+///
+/// Rules are tried in priority order until one matches, so a keyboard whose
+/// frequently used rules come late makes every key scan most of its rules.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleHistogram {
+    /// Matches per rule, indexed like `Km2File::rules`
+    hits: Vec<u32>,
+    /// Keys processed
+    keys: u64,
+    /// Rules tried for those keys, recursive and post-rule passes included
+    scanned: u64,
+}
+
+impl RuleHistogram {
+    /// Empty histogram for a keyboard with `rule_count` rules
+    pub fn new(rule_count: usize) -> Self {
+        Self {
+            hits: vec![0; rule_count],
+            keys: 0,
+            scanned: 0,
+        }
+    }
+
+    /// Records one processed key
+    pub(crate) fn record(&mut self, matched: &[usize], scanned: usize) {
+        self.keys += 1;
+        self.scanned += scanned as u64;
+        for &rule in matched {
+            if let Some(hits) = self.hits.get_mut(rule) {
+                *hits = hits.saturating_add(1);
+            }
+        }
+    }
+
+    /// Matches per rule, indexed like `Km2File::rules`
+    pub fn hits(&self) -> &[u32] {
+        &self.hits
+    }
+
+    /// Number of keys processed
+    pub fn keys(&self) -> u64 {
+        self.keys
+    }
+
+    /// Number of rules tried over all keys
+    pub fn rules_scanned(&self) -> u64 {
+        self.scanned
+    }
+
+    /// Rules tried per key, 0 before any key
+    pub fn average_scanned_per_key(&self) -> f64 {
+        if self.keys == 0 {
+            0.0
+        } else {
+            self.scanned as f64 / self.keys as f64
+        }
+    }
+
+    /// The `n` most matched rules as (rule index, hits), most matched first;
+    /// ties keep rule order and rules that never matched are left out
+    pub fn top(&self, n: usize) -> Vec<(usize, u32)> {
+        let mut entries: Vec<(usize, u32)> = self
+            .hits
+            .iter()
+            .enumerate()
+            .filter(|(_, &hits)| hits > 0)
+            .map(|(rule, &hits)| (rule, hits))
+            .collect();
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        entries.truncate(n);
+        entries
+    }
+}
@@ -13,6 +13,8 @@ pub struct MatchContext<'a> {
     pub active_states: &'a std::collections::HashSet<usize>,
     /// Whether this is a recursive match (no key input)
     pub is_recursive: bool,
+    /// Whether ASCII letters in string elements match either case
+    pub ignore_ascii_case: bool,
 }
 
 impl<'a> MatchContext<'a> {
@@ -27,6 +29,7 @@ impl<'a> MatchContext<'a> {
             key_input: Some(key_input),
             active_states,
             is_recursive: false,
+            ignore_ascii_case: false,
         }
     }
 
@@ -40,7 +43,15 @@ impl<'a> MatchContext<'a> {
             key_input: None,
             active_states,
             is_recursive: true,
+            ignore_ascii_case: false,
         }
     }
 
+    /// This context, matching ASCII letters of string elements in either case
+    /// when `ignore` is set (the `case_insensitive_ascii` layout option)
+    pub fn ignoring_ascii_case(mut self, ignore: bool) -> Self {
+        self.ignore_ascii_case = ignore;
+        self
+    }
+
 }
\ No newline at end of file
@@ -1,8 +1,8 @@
 //! Core rule matching logic
 
-use crate::types::Rule;
+use crate::types::{Rule, StringTable};
 use crate::VirtualKey;
-use super::{Pattern, PatternElement, MatchContext, CaptureManager};
+use super::{Pattern, PatternElement, MatchContext, CaptureManager, RuleMask};
 use super::pattern::VariableMatch;
 
 /// Handles rule matching
@@ -10,80 +10,69 @@ pub struct RuleMatcher;
 
 impl RuleMatcher {
     /// Finds the best matching rule for the given context
-    /// Returns the matched rule, pattern, and captures
+    /// Returns the position of the rule in `rules`, the matched rule, pattern, and captures.
+    /// Rules whose position is set in `disabled` are skipped; every rule tried
+    /// is counted in `scanned`.
     pub fn find_match<'a>(
         rules: &'a [(Rule, Pattern)],
+        disabled: &RuleMask,
         context: &MatchContext,
-        strings: &[String],
-    ) -> Option<(&'a Rule, &'a Pattern, CaptureManager)> {
-        for (rule, pattern) in rules {
-            if let Some(captures) = Self::try_match_pattern(pattern, context, strings) {
-                return Some((rule, pattern, captures));
+        strings: &StringTable,
+        scanned: &mut usize,
+    ) -> Option<(usize, &'a Rule, &'a Pattern, CaptureManager)> {
+        for (position, (rule, pattern)) in rules.iter().enumerate() {
+            if disabled.is_disabled(position) {
+                continue;
+            }
+            *scanned += 1;
+            if let Ok(captures) = Self::match_pattern(pattern, context, strings) {
+                return Some((position, rule, pattern, captures));
             }
         }
         None
     }
     
     /// Tries to match a pattern against the context
-    /// Returns captures
-    fn try_match_pattern(
+    /// Returns captures, or which element failed and why
+    pub fn match_pattern(
         pattern: &Pattern,
         context: &MatchContext,
-        strings: &[String],
-    ) -> Option<CaptureManager> {
+        strings: &StringTable,
+    ) -> Result<CaptureManager, MatchFailure> {
+        let fail = |element: usize, mismatch: Mismatch| Err(MatchFailure { element, mismatch });
+
         // Calculate the exact pattern length first
-        let pattern_len = pattern.calculate_match_length(strings)?;
-        
-        // Determine what text to match against based on pattern type and context
-        let text_to_match = if !context.is_recursive {
-            if pattern.has_vk() {
-                // Pattern has VK - match against composing text only, VK will be checked separately
-                context.composing_text.to_string()
-            } else {
-                // Pattern has no VK - match against composing text + character
-                let mut text = context.composing_text.to_string();
-                if let Some(input) = context.key_input {
-                    if let Some(ch) = input.character {
-                        text.push(ch);
-                    } else {
-                        // No character to match against
-                        return None;
-                    }
-                }
-                text
-            }
-        } else {
-            // Recursive matching - only use composing text
-            context.composing_text.to_string()
+        let Some(pattern_len) = pattern.calculate_match_length(strings) else {
+            return fail(0, Mismatch::Unmatchable);
         };
         
-        let text_chars: Vec<char> = text_to_match.chars().collect();
+        let Some(text_chars) = Self::text_to_match(pattern, context) else {
+            // No character to match against
+            return fail(0, Mismatch::NoCharacter);
+        };
         
         // Early return if text is shorter than pattern
         if text_chars.len() < pattern_len {
-            return None;
+            return fail(0, Mismatch::TooShort { needed: pattern_len, available: text_chars.len() });
         }
         
         // Match pattern from the end of the text
         let start_pos = text_chars.len().saturating_sub(pattern_len);
         let mut captures = CaptureManager::new();
         let mut text_pos = start_pos;
-        let mut match_success = true;
         
-        for element in &pattern.elements {
+        for (index, element) in pattern.elements.iter().enumerate() {
             match element {
                 PatternElement::State(state_idx) => {
                     // State must be active
                     if !context.active_states.contains(state_idx) {
-                        match_success = false;
-                        break;
+                        return fail(index, Mismatch::State(*state_idx));
                     }
                 }
                 PatternElement::VirtualKey(vks) => {
                     // Virtual keys only match in non-recursive context
                     if context.is_recursive {
-                        match_success = false;
-                        break;
+                        return fail(index, Mismatch::Key);
                     }
                     
                     // First, validate that there's exactly one primary key
@@ -104,8 +93,7 @@ impl RuleMatcher {
                     
                     // Skip this rule if it has 0 or more than 1 primary key
                     if primary_key_count != 1 {
-                        match_success = false;
-                        break;
+                        return fail(index, Mismatch::Unmatchable);
                     }
                     
                     // Check if all VKs in the combination match
@@ -116,15 +104,9 @@ impl RuleMatcher {
                         // Check if the primary key matches
                         if let Some(primary) = primary_vk {
                             // Convert key_input's vk_code to VirtualKey for comparison
-                            if let Some(input_vk) = VirtualKey::from_raw(vk_code) {
-                                if input_vk != *primary {
-                                    match_success = false;
-                                    break;
-                                }
-                            } else {
-                                // Unknown key code
-                                match_success = false;
-                                break;
+                            // (an unknown key code matches nothing)
+                            if VirtualKey::from_raw(vk_code) != Some(*primary) {
+                                return fail(index, Mismatch::Key);
                             }
                         }
                         
@@ -147,53 +129,52 @@ impl RuleMatcher {
                         if key_input.modifiers.shift != required_shift ||
                            key_input.modifiers.ctrl != required_ctrl ||
                            key_input.modifiers.alt != required_alt {
-                            match_success = false;
-                            break;
+                            return fail(index, Mismatch::Modifiers);
                         }
                     } else {
                         // No VK code in context
-                        match_success = false;
-                        break;
+                        return fail(index, Mismatch::Key);
                     }
                 }
                 PatternElement::String(s) => {
                     // Match string from composing text
                     let s_chars: Vec<char> = s.chars().collect();
                     if text_pos + s_chars.len() > text_chars.len() {
-                        match_success = false;
-                        break;
+                        return fail(index, Mismatch::Text { at: text_pos });
                     }
                     
-                    // Check if substring matches
-                    let matched_str: String = text_chars[text_pos..text_pos + s_chars.len()].iter().collect();
-                    if matched_str != *s {
-                        match_success = false;
-                        break;
+                    // Check if substring matches; the capture keeps the
+                    // text as typed, so back-references preserve its case
+                    let matched = &text_chars[text_pos..text_pos + s_chars.len()];
+                    let equal = if context.ignore_ascii_case {
+                        matched.iter().zip(&s_chars).all(|(a, b)| a.eq_ignore_ascii_case(b))
+                    } else {
+                        matched == s_chars.as_slice()
+                    };
+                    if !equal {
+                        return fail(index, Mismatch::Text { at: text_pos });
                     }
                     
                     // Capture the matched string
-                    captures.set_capture(captures.next_index(), matched_str);
+                    captures.set_capture(captures.next_index(), matched.iter().collect());
                     text_pos += s_chars.len();
                 }
                 PatternElement::Variable(var_idx, var_match) => {
                     // Get variable content
-                    let default_string = String::new();
-                    let var_content = strings.get(*var_idx).unwrap_or(&default_string);
+                    let var_content = strings.get_str(*var_idx).unwrap_or("");
                     
                     match var_match {
                         VariableMatch::Exact => {
                             // Match entire variable content
                             let var_chars: Vec<char> = var_content.chars().collect();
                             if text_pos + var_chars.len() > text_chars.len() {
-                                match_success = false;
-                                break;
+                                return fail(index, Mismatch::Text { at: text_pos });
                             }
                             
                             // Check if the text matches the variable content
                             let matched_str: String = text_chars[text_pos..text_pos + var_chars.len()].iter().collect();
                             if matched_str != *var_content {
-                                match_success = false;
-                                break;
+                                return fail(index, Mismatch::Text { at: text_pos });
                             }
                             
                             // Capture the matched content
@@ -203,8 +184,7 @@ impl RuleMatcher {
                         VariableMatch::AnyOf => {
                             // Match one character from variable
                             if text_pos >= text_chars.len() {
-                                match_success = false;
-                                break;
+                                return fail(index, Mismatch::Text { at: text_pos });
                             }
                             
                             let ch = text_chars[text_pos];
@@ -213,21 +193,18 @@ impl RuleMatcher {
                                 captures.set_capture_with_index(captures.next_index(), ch.to_string(), position);
                                 text_pos += 1;
                             } else {
-                                match_success = false;
-                                break;
+                                return fail(index, Mismatch::Text { at: text_pos });
                             }
                         }
                         VariableMatch::NotAnyOf => {
                             // Match one character NOT in variable
                             if text_pos >= text_chars.len() {
-                                match_success = false;
-                                break;
+                                return fail(index, Mismatch::Text { at: text_pos });
                             }
                             
                             let ch = text_chars[text_pos];
                             if var_content.chars().any(|c| c == ch) {
-                                match_success = false;
-                                break;
+                                return fail(index, Mismatch::Text { at: text_pos });
                             }
                             
                             // Capture the character
@@ -238,35 +215,69 @@ impl RuleMatcher {
                 }
                 PatternElement::Any => {
                     // Match any printable ASCII character
-                    if text_pos >= text_chars.len() {
-                        match_success = false;
-                        break;
-                    }
-                    
-                    let ch = text_chars[text_pos];
-                    if !is_printable_ascii(ch) {
-                        match_success = false;
-                        break;
+                    if text_pos >= text_chars.len() || !is_printable_ascii(text_chars[text_pos]) {
+                        return fail(index, Mismatch::Text { at: text_pos });
                     }
                     
                     // Capture the character
-                    captures.set_capture(captures.next_index(), ch.to_string());
+                    captures.set_capture(captures.next_index(), text_chars[text_pos].to_string());
                     text_pos += 1;
                 }
             }
         }
         
-        if match_success && text_pos == start_pos + pattern_len {
-            // We found a match!
-            return Some(captures);
+        if text_pos != start_pos + pattern_len {
+            return fail(pattern.elements.len().saturating_sub(1), Mismatch::Text { at: text_pos });
         }
         
-        None
+        // We found a match!
+        Ok(captures)
     }
     
+    /// The text a pattern is matched against, ending with the typed
+    /// character when the pattern has no VK; `None` when it needs a
+    /// character and the key types none
+    pub fn text_to_match(pattern: &Pattern, context: &MatchContext) -> Option<Vec<char>> {
+        let mut text: Vec<char> = context.composing_text.chars().collect();
+        // Patterns with a VK match against composing text only, the VK is
+        // checked separately; recursive matching only uses composing text
+        if !context.is_recursive && !pattern.has_vk() {
+            if let Some(input) = context.key_input {
+                text.push(input.character?);
+            }
+        }
+        Some(text)
+    }
+}
+
+/// Why a pattern did not match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The pattern can never match: a key combination without exactly one
+    /// main key, or a variable missing from the file
+    Unmatchable,
+    /// The pattern matches the typed character and the key types none
+    NoCharacter,
+    /// The text is shorter than what the pattern matches
+    TooShort { needed: usize, available: usize },
+    /// The state with this index is not on
+    State(usize),
+    /// Another key was pressed
+    Key,
+    /// The right key with other modifiers
+    Modifiers,
+    /// The text at this character position is not what the element matches
+    Text { at: usize },
+}
+
+/// The pattern element that failed to match, by index, and why
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchFailure {
+    pub element: usize,
+    pub mismatch: Mismatch,
 }
 
 /// Checks if a character is printable ASCII (0x20-0x7E excluding space)
-fn is_printable_ascii(ch: char) -> bool {
+pub(super) fn is_printable_ascii(ch: char) -> bool {
     matches!(ch, '!'..='~')
 }
\ No newline at end of file
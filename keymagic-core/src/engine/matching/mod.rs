@@ -4,8 +4,12 @@ mod matcher;
 mod pattern;
 mod context;
 mod capture;
+mod rule_mask;
+mod prefix;
 
-pub use matcher::RuleMatcher;
-pub use pattern::{Pattern, PatternElement};
+pub use matcher::{MatchFailure, Mismatch, RuleMatcher};
+pub use pattern::{Pattern, PatternElement, VariableMatch};
 pub use context::MatchContext;
-pub use capture::CaptureManager;
\ No newline at end of file
+pub use capture::CaptureManager;
+pub use rule_mask::RuleMask;
+pub use prefix::{PrefixIndex, PrefixResult};
\ No newline at end of file
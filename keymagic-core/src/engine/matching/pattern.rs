@@ -2,6 +2,7 @@
 
 use crate::engine::types::Element;
 use crate::types::opcodes::{FLAG_ANYOF, FLAG_NANYOF};
+use crate::types::StringTable;
 use crate::VirtualKey;
 
 /// Preprocessed pattern for efficient matching
@@ -185,8 +186,8 @@ impl Pattern {
         self.vk_count > 0
     }
 
-    /// Calculates the exact character length this pattern will match
-    pub fn calculate_match_length(&self, strings: &[String]) -> Option<usize> {
+    /// Calculates the exact length, in Unicode scalar values, this pattern will match
+    pub fn calculate_match_length(&self, strings: &StringTable) -> Option<usize> {
         let mut length = 0;
 
         for element in &self.elements {
@@ -198,7 +199,7 @@ impl Pattern {
                     match var_match {
                         VariableMatch::Exact => {
                             // Get variable content length
-                            let var_content = strings.get(*var_idx)?;
+                            let var_content = strings.get_str(*var_idx)?;
                             length += var_content.chars().count();
                         }
                         VariableMatch::AnyOf | VariableMatch::NotAnyOf => {
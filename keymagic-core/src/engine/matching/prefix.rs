@@ -0,0 +1,142 @@
+//! Index of the text rules match before their final key
+//!
+//! Hosts ask whether a key leaves anything a later key could build on; if
+//! not, they can commit at once instead of starting a composition. Rules are
+//! bucketed by the first character they match so a query only tries the rules
+//! that could start at each position of the composing text's tail.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::types::{Rule, StringTable};
+use super::{Pattern, PatternElement, VariableMatch};
+use super::matcher::is_printable_ascii;
+
+/// What a key leaves for the keys after it, see `KeyMagicEngine::is_prefix`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefixResult {
+    /// No rule matches the key and no rule can use the text it leaves
+    No,
+    /// A rule matches the key, and no rule can use the text it leaves
+    CompleteMatchOnly,
+    /// A later key may match a rule that uses the text or states the key leaves
+    PrefixOfLonger,
+}
+
+/// One character of a rule's text
+#[derive(Debug, Clone)]
+enum CharClass {
+    Char(char),
+    AnyOf(String),
+    NotAnyOf(String),
+    Any,
+}
+
+impl CharClass {
+    fn matches(&self, ch: char, ignore_ascii_case: bool) -> bool {
+        match self {
+            CharClass::Char(c) if ignore_ascii_case => c.eq_ignore_ascii_case(&ch),
+            CharClass::Char(c) => *c == ch,
+            CharClass::AnyOf(chars) => chars.contains(ch),
+            CharClass::NotAnyOf(chars) => !chars.contains(ch),
+            CharClass::Any => is_printable_ascii(ch),
+        }
+    }
+}
+
+/// The part of a rule that can come from text composed by earlier keys
+#[derive(Debug, Clone)]
+struct RulePrefix {
+    /// Position of the rule in matching priority order
+    position: usize,
+    /// Text matched before the rule's key: all of it for virtual key rules,
+    /// all but the last character, which the key types, for the others
+    classes: Vec<CharClass>,
+    /// States the rule needs
+    states: Vec<usize>,
+}
+
+/// Rule prefixes, bucketed by their first character
+#[derive(Debug, Clone, Default)]
+pub struct PrefixIndex {
+    rules: Vec<RulePrefix>,
+    /// Rules (into `rules`) by their first character, ASCII lowercased
+    by_first: HashMap<char, Vec<usize>>,
+    /// Rules whose first character is any of a set
+    open: Vec<usize>,
+    /// Rules that need a state
+    stateful: Vec<usize>,
+    /// Longest prefix of any rule, in characters
+    max_len: usize,
+}
+
+impl PrefixIndex {
+    /// Builds the index for rules in matching priority order
+    pub fn new(rules: &[(Rule, Pattern)], strings: &StringTable) -> Self {
+        let mut index = Self::default();
+        for (position, (_, pattern)) in rules.iter().enumerate() {
+            let mut classes = Vec::new();
+            let mut states = Vec::new();
+            for element in &pattern.elements {
+                match element {
+                    PatternElement::String(s) => classes.extend(s.chars().map(CharClass::Char)),
+                    PatternElement::Variable(idx, VariableMatch::Exact) => {
+                        classes.extend(strings.get_str(*idx).unwrap_or("").chars().map(CharClass::Char));
+                    }
+                    PatternElement::Variable(idx, VariableMatch::AnyOf) => {
+                        classes.push(CharClass::AnyOf(strings.get_str(*idx).unwrap_or("").to_string()));
+                    }
+                    PatternElement::Variable(idx, VariableMatch::NotAnyOf) => {
+                        classes.push(CharClass::NotAnyOf(strings.get_str(*idx).unwrap_or("").to_string()));
+                    }
+                    PatternElement::Any => classes.push(CharClass::Any),
+                    PatternElement::State(state) => states.push(*state),
+                    PatternElement::VirtualKey(_) => {}
+                }
+            }
+            if !pattern.has_vk() {
+                classes.pop();
+            }
+            if classes.is_empty() && states.is_empty() {
+                // Depends on nothing but the key itself
+                continue;
+            }
+
+            let slot = index.rules.len();
+            if !states.is_empty() {
+                index.stateful.push(slot);
+            } else {
+                match &classes[0] {
+                    CharClass::Char(c) => index.by_first.entry(c.to_ascii_lowercase()).or_default().push(slot),
+                    _ => index.open.push(slot),
+                }
+                index.max_len = index.max_len.max(classes.len());
+            }
+            index.rules.push(RulePrefix { position, classes, states });
+        }
+        index
+    }
+
+    /// Whether a later key may match a rule using the tail of `text` or one
+    /// of `states`. Only rules whose position `live` accepts count.
+    ///
+    /// A rule needing states that are all active counts whatever its text,
+    /// since the states only last until the next key.
+    pub fn continues(&self, text: &str, states: &HashSet<usize>, ignore_ascii_case: bool, live: impl Fn(usize) -> bool) -> bool {
+        let stateful = self.stateful.iter().map(|&slot| &self.rules[slot]);
+        if stateful.filter(|rule| live(rule.position)).any(|rule| rule.states.iter().all(|state| states.contains(state))) {
+            return true;
+        }
+
+        let chars: Vec<char> = text.chars().collect();
+        (1..=self.max_len.min(chars.len())).any(|len| {
+            let tail = &chars[chars.len() - len..];
+            let bucket = self.by_first.get(&tail[0].to_ascii_lowercase()).into_iter().flatten();
+            bucket.chain(&self.open).any(|&slot| {
+                let rule = &self.rules[slot];
+                rule.classes.len() >= len
+                    && live(rule.position)
+                    && rule.classes.iter().zip(tail).all(|(class, &ch)| class.matches(ch, ignore_ascii_case))
+            })
+        })
+    }
+}
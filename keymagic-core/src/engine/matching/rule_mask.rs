@@ -0,0 +1,36 @@
+//! Bitset of disabled rules
+
+/// Set of rule positions (in matching priority order) the matcher skips
+#[derive(Debug, Clone, Default)]
+pub struct RuleMask {
+    words: Vec<u64>,
+}
+
+impl RuleMask {
+    /// Creates an empty mask where every rule is enabled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the rule at `position` is disabled
+    #[inline]
+    pub fn is_disabled(&self, position: usize) -> bool {
+        self.words
+            .get(position / 64)
+            .is_some_and(|word| word & (1 << (position % 64)) != 0)
+    }
+
+    /// Marks the rule at `position` as disabled
+    pub fn disable(&mut self, position: usize) {
+        let word = position / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (position % 64);
+    }
+
+    /// Enables every rule
+    pub fn clear(&mut self) {
+        self.words.clear();
+    }
+}
@@ -4,21 +4,34 @@
 //! according to KeyMagic keyboard layout rules.
 
 mod engine;
+mod enumerate;
+mod explain;
+mod histogram;
+mod shared;
+mod slot;
 mod input;
 mod output;
 mod state;
-mod matching;
+pub(crate) mod matching;
 mod processing;
+mod suggestion;
 mod types;
 mod utils;
 
 #[cfg(test)]
 mod compat;
 
-pub use engine::KeyMagicEngine;
+pub use engine::{EngineSnapshot, KeyMagicEngine, ResetLevel};
+pub use shared::SharedEngine;
+pub use slot::EngineSlot;
+pub use enumerate::{EnumerationProgress, OutputEntry, OutputEnumerator};
+pub use explain::{Explanation, KeyOutcome, RuleCandidate, RuleFailure};
+pub use histogram::RuleHistogram;
 pub use input::{KeyInput, ModifierState};
 pub use output::{EngineOutput, ActionType};
-pub use types::{Element, Predefined};
-
-// Re-export error types
-pub use crate::error::{Error, Result};
\ No newline at end of file
+pub use matching::PrefixResult;
+pub use suggestion::{Suggestion, SuggestionProvider};
+#[cfg(feature = "recent-suggestions")]
+pub use suggestion::RecentCommitsProvider;
+#[cfg(feature = "ffi")]
+pub(crate) use output::utf16_offset;
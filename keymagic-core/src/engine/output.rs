@@ -1,5 +1,7 @@
 //! Output representation for the KeyMagic engine
 
+use super::suggestion::Suggestion;
+
 /// Result of processing a key input
 #[derive(Debug, Clone, PartialEq)]
 pub struct EngineOutput {
@@ -9,64 +11,114 @@ pub struct EngineOutput {
     pub action: ActionType,
     /// Whether the input was processed by the engine (matched a rule)
     pub is_processed: bool,
+    /// Number of Unicode scalar values (Rust `char`s) to delete before the
+    /// cursor; the same count carried by the action
+    pub delete_chars: usize,
+    /// Number of UTF-16 code units to delete before the cursor. Differs from
+    /// `delete_chars` when the deleted text contains characters outside the
+    /// Basic Multilingual Plane, which take two units (a surrogate pair).
+    pub delete_utf16_units: usize,
+    /// Caret position within `composing_text`, in characters (Unicode scalar
+    /// values); see `composing_caret_utf16` for UTF-16 hosts
+    pub composing_caret: usize,
+    /// Message a matched rule asked the host to show (`@notify`), e.g. that
+    /// the keyboard switched to a numbers mode
+    pub notification: Option<String>,
+    /// Whether the engine cut the delete short because it reached further
+    /// back than the keyboard could have typed; hosts may warn that the
+    /// keyboard misbehaves
+    pub clamped: bool,
+    /// Suggestions for the composing text from the registered
+    /// `SuggestionProvider`, best first; empty without one
+    pub suggestions: Vec<Suggestion>,
 }
 
-/// Types of actions the engine can output
+/// Types of actions the engine can output; new kinds of edits may be added,
+/// so matches need a fallback arm
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum ActionType {
     /// No action needed (e.g., state change only)
     None,
     /// Insert text at cursor
     Insert(String),
-    /// Delete characters before cursor
+    /// Delete characters (Unicode scalar values) before cursor
     BackspaceDelete(usize),
-    /// Delete characters then insert text
+    /// Delete characters (Unicode scalar values) then insert text
     BackspaceDeleteAndInsert(usize, String),
 }
 
 impl EngineOutput {
-    /// Creates a new engine output
-    pub fn new(composing_text: String, action: ActionType, is_processed: bool) -> Self {
+    /// Creates a new engine output; `deleted` is the text removed from
+    /// before the cursor, from which the delete counts are derived
+    pub fn new(composing_text: String, action: ActionType, deleted: &str, is_processed: bool) -> Self {
         Self {
-            composing_text,
             action,
             is_processed,
+            delete_chars: deleted.chars().count(),
+            delete_utf16_units: deleted.encode_utf16().count(),
+            composing_caret: composing_text.chars().count(),
+            composing_text,
+            notification: None,
+            clamped: false,
+            suggestions: Vec::new(),
         }
     }
 
+    /// Places the caret within the composing text (in characters)
+    pub fn with_caret(mut self, caret: usize) -> Self {
+        self.composing_caret = caret;
+        self
+    }
+
+    /// Attaches a message for the host to show
+    pub fn with_notification(mut self, notification: Option<String>) -> Self {
+        self.notification = notification;
+        self
+    }
+
+    /// Marks the delete as cut short
+    pub fn with_clamped(mut self, clamped: bool) -> Self {
+        self.clamped = clamped;
+        self
+    }
+
+    /// Attaches suggestions for the composing text
+    pub fn with_suggestions(mut self, suggestions: Vec<Suggestion>) -> Self {
+        self.suggestions = suggestions;
+        self
+    }
+
+    /// Caret position within `composing_text` in UTF-16 code units
+    pub fn composing_caret_utf16(&self) -> usize {
+        utf16_offset(&self.composing_text, self.composing_caret)
+    }
+
     /// Creates a no-action output
     pub fn none(composing_text: String) -> Self {
-        Self {
-            composing_text,
-            action: ActionType::None,
-            is_processed: false,
-        }
+        Self::new(composing_text, ActionType::None, "", false)
     }
 
     /// Creates an insert action output
     pub fn insert(composing_text: String, text: String) -> Self {
-        Self {
-            composing_text,
-            action: ActionType::Insert(text),
-            is_processed: true,
-        }
+        Self::new(composing_text, ActionType::Insert(text), "", true)
     }
 
-    /// Creates a delete action output
-    pub fn delete(composing_text: String, count: usize) -> Self {
-        Self {
-            composing_text,
-            action: ActionType::BackspaceDelete(count),
-            is_processed: true,
-        }
+    /// Creates an output deleting `deleted` from before the cursor
+    pub fn delete(composing_text: String, deleted: &str) -> Self {
+        let count = deleted.chars().count();
+        Self::new(composing_text, ActionType::BackspaceDelete(count), deleted, true)
     }
 
-    /// Creates a delete-and-insert action output
-    pub fn delete_and_insert(composing_text: String, delete_count: usize, insert_text: String) -> Self {
-        Self {
-            composing_text,
-            action: ActionType::BackspaceDeleteAndInsert(delete_count, insert_text),
-            is_processed: true,
-        }
+    /// Creates an output replacing `deleted` before the cursor with `insert_text`
+    pub fn delete_and_insert(composing_text: String, deleted: &str, insert_text: String) -> Self {
+        let count = deleted.chars().count();
+        Self::new(composing_text, ActionType::BackspaceDeleteAndInsert(count, insert_text), deleted, true)
     }
-}
\ No newline at end of file
+}
+
+/// Converts a character index into `text` to a UTF-16 code unit offset;
+/// indices past the end map to the end of the text
+pub(crate) fn utf16_offset(text: &str, char_index: usize) -> usize {
+    text.chars().take(char_index).map(char::len_utf16).sum()
+}
@@ -1,4 +1,8 @@
 //! Action generation from state changes
+//!
+//! Texts are compared by Unicode scalar value, so a change never splits a
+//! character; in particular a character outside the BMP is always deleted or
+//! kept as a whole, never as half of a UTF-16 surrogate pair.
 
 use crate::engine::output::ActionType;
 
@@ -17,18 +21,11 @@ impl ActionGenerator {
             return ActionType::None;
         }
 
-        let before_chars: Vec<char> = before.chars().collect();
-        let after_chars: Vec<char> = after.chars().collect();
-
-        // Find common prefix length
-        let common_prefix_len = before_chars.iter()
-            .zip(after_chars.iter())
-            .take_while(|(a, b)| a == b)
-            .count();
+        let prefix = Self::common_prefix_bytes(before, after);
 
         // Calculate what changed
-        let chars_to_delete = before_chars.len() - common_prefix_len;
-        let chars_to_insert: String = after_chars[common_prefix_len..].iter().collect();
+        let chars_to_delete = before[prefix..].chars().count();
+        let chars_to_insert = after[prefix..].to_string();
 
         match (chars_to_delete, chars_to_insert.is_empty()) {
             (0, false) => {
@@ -46,4 +43,20 @@ impl ActionGenerator {
             _ => ActionType::None,
         }
     }
-}
\ No newline at end of file
+
+    /// Returns the text at the end of `before` that the action for this
+    /// change deletes
+    pub fn deleted_text<'a>(before: &'a str, after: &str) -> &'a str {
+        &before[Self::common_prefix_bytes(before, after)..]
+    }
+
+    /// Byte length of the longest common prefix made of whole characters
+    fn common_prefix_bytes(before: &str, after: &str) -> usize {
+        before
+            .char_indices()
+            .zip(after.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((index, _), _)| index)
+            .unwrap_or_else(|| before.len().min(after.len()))
+    }
+}
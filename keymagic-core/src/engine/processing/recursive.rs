@@ -1,8 +1,8 @@
 //! Recursive rule processing
 
-use crate::types::Rule;
+use crate::types::{Rule, StringTable};
 use crate::engine::state::EngineState;
-use crate::engine::matching::{RuleMatcher, Pattern, MatchContext};
+use crate::engine::matching::{RuleMatcher, Pattern, MatchContext, RuleMask};
 use crate::engine::processing::RuleProcessor;
 use crate::Result;
 
@@ -13,10 +13,18 @@ pub struct RecursiveProcessor;
 
 impl RecursiveProcessor {
     /// Recursively applies rules until no more matches or stop condition is met
+    ///
+    /// The position of every applied rule is appended to `matched`, and the
+    /// rules tried are counted in `scanned`. `ignore_ascii_case` is the
+    /// keyboard's `case_insensitive_ascii` option.
     pub fn process_recursive(
         state: &mut EngineState,
         rules: &[(Rule, Pattern)],
-        strings: &[String],
+        disabled: &RuleMask,
+        strings: &StringTable,
+        ignore_ascii_case: bool,
+        matched: &mut Vec<usize>,
+        scanned: &mut usize,
     ) -> Result<()> {
         let mut depth = 0;
 
@@ -30,32 +38,19 @@ impl RecursiveProcessor {
             let context = MatchContext::for_recursive(
                 state.composing_text(),
                 state.active_states(),
-            );
+            ).ignoring_ascii_case(ignore_ascii_case);
 
             // Try to find a matching rule
-            if let Some((rule, pattern, captures)) = RuleMatcher::find_match(rules, &context, strings) {
+            if let Some((position, rule, pattern, captures)) = RuleMatcher::find_match(rules, disabled, &context, strings, scanned) {
+                matched.push(position);
+
                 // Get the pattern length
                 let matched_len = pattern.calculate_match_length(strings).unwrap_or(0);
                 // Apply the rule
                 let output = RuleProcessor::apply_rule(rule, state, &captures, strings)?;
-
-                // log rule
-                println!("Recursive Rule: {:?}", rule);
-                // log matched length
-                println!("Recursive Matched length: {}", matched_len);
-                // log pattern
-                println!("Recursive Pattern: {:?}", pattern);
-                // log captures
-                println!("Recursive Captures: {:?}", captures);
-                // log output
-                println!("Recursive Output: {}", output);
-                // log composing buffer
-                println!("Recursive Composing buffer: {}", state.composing_text());
                 
                 // Update composing buffer by replacing only the matched portion
                 state.composing_buffer_mut().replace_from_end(matched_len, &output);
-                // log composing buffer
-                println!("Recursive Composing buffer after: {}", state.composing_text());
                 
                 // Check stop conditions based on the output
                 if should_stop_recursion(&output) {
@@ -71,6 +66,45 @@ impl RecursiveProcessor {
 
         Ok(())
     }
+
+    /// Applies post-rules to the composing text until none matches or a
+    /// match leaves the text unchanged, at most `MAX_RECURSION_DEPTH` times
+    ///
+    /// `disabled` must skip every rule that is not an enabled post-rule. The
+    /// position of every applied rule is appended to `matched`, and the rules
+    /// tried are counted in `scanned`.
+    pub fn process_post_rules(
+        state: &mut EngineState,
+        rules: &[(Rule, Pattern)],
+        disabled: &RuleMask,
+        strings: &StringTable,
+        ignore_ascii_case: bool,
+        matched: &mut Vec<usize>,
+        scanned: &mut usize,
+    ) -> Result<()> {
+        for _ in 0..MAX_RECURSION_DEPTH {
+            let context = MatchContext::for_recursive(
+                state.composing_text(),
+                state.active_states(),
+            ).ignoring_ascii_case(ignore_ascii_case);
+            let Some((position, rule, pattern, captures)) = RuleMatcher::find_match(rules, disabled, &context, strings, scanned) else {
+                break;
+            };
+
+            let matched_len = pattern.calculate_match_length(strings).unwrap_or(0);
+            let before = state.composing_text().to_string();
+            let output = RuleProcessor::apply_rule(rule, state, &captures, strings)?;
+            state.composing_buffer_mut().replace_from_end(matched_len, &output);
+
+            // Fixpoint: the rule rewrote the text into itself
+            if state.composing_text() == before {
+                break;
+            }
+            matched.push(position);
+        }
+
+        Ok(())
+    }
 }
 
 /// Checks if recursion should stop based on the rule output
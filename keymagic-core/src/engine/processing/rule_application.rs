@@ -1,6 +1,6 @@
 //! Rule application logic
 
-use crate::types::Rule;
+use crate::types::{Rule, StringTable};
 use crate::engine::types::Element;
 use crate::engine::state::EngineState;
 use crate::engine::matching::CaptureManager;
@@ -15,7 +15,7 @@ impl RuleProcessor {
         rule: &Rule,
         state: &mut EngineState,
         captures: &CaptureManager,
-        strings: &[String],
+        strings: &StringTable,
     ) -> Result<String> {
         let mut output = String::new();
         
@@ -44,7 +44,7 @@ impl RuleProcessor {
                                     continue;
                                 };
                                 
-                                let var_content = strings.get(*var_idx)
+                                let var_content = strings.get_str(*var_idx)
                                     .ok_or_else(|| crate::error::Error::InvalidVariableIndex(*var_idx))?;
                                 
                                 // Get character at index
@@ -59,7 +59,7 @@ impl RuleProcessor {
                     }
                     
                     // Regular variable
-                    let var_content = strings.get(*var_idx)
+                    let var_content = strings.get_str(*var_idx)
                         .ok_or_else(|| crate::error::Error::InvalidVariableIndex(*var_idx))?;
                     output.push_str(var_content);
                 }
@@ -74,6 +74,13 @@ impl RuleProcessor {
                 Element::Switch(state_idx) => {
                     state.activate_state(*state_idx);
                 }
+                // Leaves the text alone; the message goes out with the output
+                Element::Notify(message) => {
+                    state.set_notification(message.clone());
+                }
+                // NULL produces no text; with nothing else on the RHS the
+                // matched text is replaced with nothing (see `Rule`)
+                Element::Predefined(_) => {}
                 _ => {}
             }
             i += 1;
@@ -0,0 +1,174 @@
+//! Thread-safe engine handle
+//!
+//! `KeyMagicEngine` is `Send + Sync` but all key processing needs `&mut self`.
+//! `SharedEngine` wraps it in an `Arc<RwLock<_>>` so several threads can hold
+//! the same engine: queries take a read lock, anything that changes state
+//! takes the write lock. Each call holds the lock for its whole duration, so
+//! concurrent calls are observed in some serial order.
+
+use std::sync::Arc;
+
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::engine::{EngineOutput, EngineSnapshot, KeyInput, KeyMagicEngine, ResetLevel, RuleHistogram, Suggestion, SuggestionProvider};
+use crate::error::Result;
+use crate::transform::TransformId;
+use crate::types::{Km2File, LayoutOptions};
+use crate::VirtualKey;
+
+/// Cloneable, thread-safe handle to a single engine instance
+#[derive(Clone)]
+pub struct SharedEngine {
+    inner: Arc<RwLock<KeyMagicEngine>>,
+}
+
+impl SharedEngine {
+    /// Wraps an existing engine
+    pub fn new(engine: KeyMagicEngine) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(engine)),
+        }
+    }
+
+    /// Creates an engine for the given keyboard layout
+    pub fn from_keyboard(keyboard: Km2File) -> Result<Self> {
+        Ok(Self::new(KeyMagicEngine::new(keyboard)?))
+    }
+
+    /// Processes a key input (write lock)
+    pub fn process_key(&self, input: KeyInput) -> Result<EngineOutput> {
+        self.inner.write().process_key(input)
+    }
+
+    /// Processes a key input without modifying engine state (read lock)
+    pub fn process_key_test(&self, input: KeyInput) -> Result<EngineOutput> {
+        self.inner.read().process_key_test(input)
+    }
+
+    /// Resets the engine state (write lock)
+    pub fn reset(&self) {
+        self.inner.write().reset();
+    }
+
+    /// Clears the engine state up to `level` (write lock)
+    pub fn reset_level(&self, level: ResetLevel) {
+        self.inner.write().reset_level(level);
+    }
+
+    /// Returns the emitted composing text and resets the engine, in one step
+    /// (write lock)
+    pub fn flush(&self) -> String {
+        self.inner.write().flush()
+    }
+
+    /// Sets the composing text and resets states (write lock)
+    pub fn set_composing_text(&self, text: String) {
+        self.inner.write().set_composing_text(text);
+    }
+
+    /// Saves the composition (read lock)
+    pub fn snapshot(&self) -> EngineSnapshot {
+        self.inner.read().snapshot()
+    }
+
+    /// Continues a saved composition (write lock)
+    pub fn restore(&self, snapshot: EngineSnapshot) {
+        self.inner.write().restore(snapshot);
+    }
+
+    /// Sets the transform applied to emitted text (write lock)
+    pub fn set_output_transform(&self, id: Option<TransformId>) -> Result<()> {
+        self.inner.write().set_output_transform(id)
+    }
+
+    /// Gets the current composing text (read lock)
+    pub fn composing_text(&self) -> String {
+        self.inner.read().composing_text().to_string()
+    }
+
+    /// Gets the composing text as emitted to the host (read lock)
+    pub fn emitted_text(&self) -> String {
+        self.inner.read().emitted_text()
+    }
+
+    /// Number of keys that smart backspace can currently undo (read lock)
+    pub fn undo_depth(&self) -> usize {
+        self.inner.read().undo_depth()
+    }
+
+    /// Gets the active output transform, if any (read lock)
+    pub fn output_transform(&self) -> Option<TransformId> {
+        self.inner.read().output_transform()
+    }
+
+    /// Layout options in effect (read lock)
+    pub fn layout_options(&self) -> LayoutOptions {
+        self.inner.read().layout_options()
+    }
+
+    /// Replaces the layout options in effect (write lock)
+    pub fn set_layout_options(&self, options: LayoutOptions) {
+        self.inner.write().set_layout_options(options);
+    }
+
+    /// Switches a rule group on or off (write lock)
+    pub fn set_group_enabled(&self, name: &str, enabled: bool) -> Result<()> {
+        self.inner.write().set_group_enabled(name, enabled)
+    }
+
+    /// Sets the keys that pass through unprocessed (write lock)
+    pub fn set_passthrough_keys(&self, keys: &[VirtualKey], commit_composing: bool) {
+        let mut engine = self.inner.write();
+        engine.set_passthrough_keys(keys);
+        engine.set_commit_on_passthrough(commit_composing);
+    }
+
+    /// Sets the host application's shortcut keys (write lock)
+    pub fn set_shortcut_keys(&self, keys: &[VirtualKey]) {
+        self.inner.write().set_shortcut_keys(keys);
+    }
+
+    /// Starts or stops collecting rule match statistics (write lock)
+    pub fn set_rule_histogram_enabled(&self, enabled: bool) {
+        self.inner.write().set_rule_histogram_enabled(enabled);
+    }
+
+    /// Copy of the rule match statistics, `None` while disabled
+    pub fn rule_histogram(&self) -> Option<RuleHistogram> {
+        self.inner.read().rule_histogram().cloned()
+    }
+
+    /// Registers the suggestion provider, or `None` to stop asking (write lock)
+    pub fn set_suggestion_provider(&self, provider: Option<Arc<dyn SuggestionProvider>>) {
+        self.inner.write().set_suggestion_provider(provider);
+    }
+
+    /// Copy of the suggestions of the last key (read lock)
+    pub fn suggestions(&self) -> Vec<Suggestion> {
+        self.inner.read().suggestions().to_vec()
+    }
+
+    /// Applies a suggestion of the last key (write lock)
+    pub fn apply_suggestion(&self, index: usize) -> Result<EngineOutput> {
+        self.inner.write().apply_suggestion(index)
+    }
+
+    /// Locks the engine for reading, e.g. to inspect the keyboard layout
+    pub fn read(&self) -> RwLockReadGuard<'_, KeyMagicEngine> {
+        self.inner.read()
+    }
+
+    /// Locks the engine for writing, for sequences that must not interleave
+    /// with other callers
+    pub fn write(&self) -> RwLockWriteGuard<'_, KeyMagicEngine> {
+        self.inner.write()
+    }
+}
+
+// The engine is moved into FFI handles and shared across GUI command threads;
+// keep it free of thread-bound types such as Rc or RefCell.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<KeyMagicEngine>();
+    assert_send_sync::<SharedEngine>();
+};
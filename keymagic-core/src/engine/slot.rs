@@ -0,0 +1,65 @@
+//! Slot holding the engine a host types with
+//!
+//! Every key loads the engine from the slot, while switching keyboards
+//! replaces it. Behind a lock a switch made keys wait, and a burst of keys
+//! held off the switch. `EngineSlot` publishes the engine through
+//! `arc_swap` instead: loading is a wait-free read, and a switch builds its
+//! engine beforehand and installs it with a single atomic swap.
+//!
+//! A key that loaded the previous engine finishes on it. Each loaded handle
+//! holds a reference, so the previous engine is dropped once the last key in
+//! flight is done with it. The new engine starts with nothing composing, as
+//! a freshly loaded keyboard always has; the host ends its composition when
+//! it switches keyboards. [`EngineSlot::replace`] hands back the previous
+//! engine for callers that flush it themselves.
+
+use arc_swap::ArcSwapOption;
+
+use crate::engine::SharedEngine;
+
+/// Atomically replaceable engine, empty until a keyboard is loaded
+#[derive(Default)]
+pub struct EngineSlot {
+    engine: ArcSwapOption<SharedEngine>,
+}
+
+impl EngineSlot {
+    /// A slot holding `engine`
+    pub fn new(engine: Option<SharedEngine>) -> Self {
+        Self {
+            engine: ArcSwapOption::from_pointee(engine),
+        }
+    }
+
+    /// The engine in the slot; never waits for a switch in progress
+    pub fn load(&self) -> Option<SharedEngine> {
+        self.engine.load().as_deref().cloned()
+    }
+
+    /// Installs `engine`, or empties the slot with `None`
+    pub fn store(&self, engine: Option<SharedEngine>) {
+        self.engine.store(engine.map(Into::into));
+    }
+
+    /// Installs `engine` and returns the one it replaced
+    pub fn replace(&self, engine: Option<SharedEngine>) -> Option<SharedEngine> {
+        self.engine.swap(engine.map(Into::into)).as_deref().cloned()
+    }
+
+    /// Whether a keyboard is loaded
+    pub fn is_loaded(&self) -> bool {
+        self.engine.load().is_some()
+    }
+}
+
+impl std::fmt::Debug for EngineSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EngineSlot").field("loaded", &self.is_loaded()).finish()
+    }
+}
+
+// Shared between the threads that type and the thread that switches
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<EngineSlot>();
+};
@@ -1,9 +1,14 @@
 //! Composing buffer management
 
 /// Manages the composing text buffer
+///
+/// The buffer tracks a caret as a character (Unicode scalar value) index.
+/// Every edit currently happens at the end of the text and leaves the caret
+/// there; hosts can move it with `set_caret`.
 #[derive(Debug, Clone)]
 pub struct ComposingBuffer {
     content: String,
+    caret: usize,
 }
 
 impl ComposingBuffer {
@@ -11,23 +16,27 @@ impl ComposingBuffer {
     pub fn new() -> Self {
         Self {
             content: String::new(),
+            caret: 0,
         }
     }
 
-    /// Creates a buffer from existing text
+    /// Creates a buffer from existing text, with the caret at the end
     pub fn from(text: String) -> Self {
-        Self { content: text }
+        let caret = text.chars().count();
+        Self { content: text, caret }
     }
 
     /// Clears the buffer
     pub fn clear(&mut self) {
         self.content.clear();
+        self.caret = 0;
     }
 
 
     /// Appends text to the buffer
     pub fn append(&mut self, text: &str) {
         self.content.push_str(text);
+        self.move_caret_to_end();
     }
 
     /// Gets the buffer content as a string slice
@@ -35,41 +44,45 @@ impl ComposingBuffer {
         &self.content
     }
 
+    /// Caret position in characters from the start of the text
+    pub fn caret(&self) -> usize {
+        self.caret
+    }
+
+    /// Moves the caret, clamped to the end of the text
+    pub fn set_caret(&mut self, caret: usize) {
+        self.caret = caret.min(self.content.chars().count());
+    }
+
+    fn move_caret_to_end(&mut self) {
+        self.caret = self.content.chars().count();
+    }
+
 
     /// Replaces characters from the end of the buffer
     /// 
     /// # Arguments
-    /// * `char_count` - Number of characters to remove from the end
+    /// * `char_count` - Number of characters (Unicode scalar values, not
+    ///   UTF-16 units) to remove from the end
     /// * `replacement` - Text to append after removing
     pub fn replace_from_end(&mut self, char_count: usize, replacement: &str) {
-        // Convert to char indices for proper Unicode handling
-        let chars: Vec<char> = self.content.chars().collect();
-        let total_chars = chars.len();
-        
-        if char_count >= total_chars {
-            // Replace entire content
-            self.content = replacement.to_string();
-        } else {
-            // Keep the prefix and replace the suffix
-            let keep_chars = total_chars - char_count;
-            self.content = chars.into_iter()
-                .take(keep_chars)
-                .collect::<String>() + replacement;
+        // Cut at a char boundary so no character is ever split
+        if char_count > 0 {
+            match self.content.char_indices().rev().nth(char_count - 1) {
+                Some((index, _)) => self.content.truncate(index),
+                // Replace entire content
+                None => self.content.clear(),
+            }
         }
+        self.content.push_str(replacement);
+        self.move_caret_to_end();
     }
     
-    /// Removes one character from the end of the buffer (backspace)
+    /// Removes one character (a whole Unicode scalar value) from the end of
+    /// the buffer (backspace)
     pub fn backspace(&mut self) {
-        if !self.content.is_empty() {
-            // Remove the last character, handling Unicode properly
-            let chars: Vec<char> = self.content.chars().collect();
-            let char_count = chars.len();
-            if char_count > 0 {
-                self.content = chars.into_iter()
-                    .take(char_count - 1)
-                    .collect();
-            }
-        }
+        self.content.pop();
+        self.move_caret_to_end();
     }
 }
 
@@ -10,6 +10,11 @@ pub struct EngineState {
     composing_buffer: ComposingBuffer,
     /// Active states (integer indices)
     active_states: HashSet<usize>,
+    /// Message of the last `@notify` applied during the current key
+    notification: Option<String>,
+    /// Characters at the start of the composing text that the host supplied
+    /// through `set_composing_text` and no key has deleted yet
+    synced_len: usize,
 }
 
 impl EngineState {
@@ -18,18 +23,29 @@ impl EngineState {
         Self {
             composing_buffer: ComposingBuffer::new(),
             active_states: HashSet::new(),
+            notification: None,
+            synced_len: 0,
         }
     }
 
     /// Resets the engine state completely
     pub fn reset(&mut self) {
+        self.clear_composing();
+        self.clear_states();
+    }
+
+    /// Clears the composing text and what is known about it, keeping the
+    /// active states
+    pub fn clear_composing(&mut self) {
         self.composing_buffer.clear();
-        self.active_states.clear();
+        self.notification = None;
+        self.synced_len = 0;
     }
 
     /// Sets the composing text and resets states
     /// Used for external synchronization
     pub fn set_composing_text(&mut self, text: String) {
+        self.synced_len = text.chars().count();
         self.composing_buffer = ComposingBuffer::from(text);
         self.active_states.clear();
     }
@@ -39,6 +55,25 @@ impl EngineState {
         self.composing_buffer.as_str()
     }
 
+    /// Gets the caret position within the composing text, in characters
+    pub fn composing_caret(&self) -> usize {
+        self.composing_buffer.caret()
+    }
+
+    /// Gets the start of the composing text that the host supplied; the
+    /// rest the engine produced itself
+    pub fn synced_text(&self) -> &str {
+        let text = self.composing_buffer.as_str();
+        let end = text.char_indices().nth(self.synced_len).map_or(text.len(), |(index, _)| index);
+        &text[..end]
+    }
+
+    /// Keeps at most `len` characters of the host's text as synced, after a
+    /// key replaced what followed them
+    pub fn truncate_synced(&mut self, len: usize) {
+        self.synced_len = self.synced_len.min(len);
+    }
+
     /// Gets a mutable reference to the composing buffer
     pub fn composing_buffer_mut(&mut self) -> &mut ComposingBuffer {
         &mut self.composing_buffer
@@ -59,6 +94,16 @@ impl EngineState {
     pub fn active_states(&self) -> &HashSet<usize> {
         &self.active_states
     }
+
+    /// Records a message for the host; a later one replaces it
+    pub fn set_notification(&mut self, message: String) {
+        self.notification = Some(message);
+    }
+
+    /// Takes the recorded message, leaving none
+    pub fn take_notification(&mut self) -> Option<String> {
+        self.notification.take()
+    }
 }
 
 impl Default for EngineState {
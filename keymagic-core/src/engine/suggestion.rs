@@ -0,0 +1,110 @@
+//! Hook for word suggestions
+//!
+//! The engine does not predict text itself. A host registers a
+//! `SuggestionProvider` with `KeyMagicEngine::set_suggestion_provider`; after
+//! every processed key the engine asks it for suggestions for the composing
+//! text and attaches them to the output. The host shows them and calls
+//! `KeyMagicEngine::apply_suggestion` for the one the user picks, which
+//! edits the composing text like a key would. Without a provider the engine
+//! does none of this.
+//!
+//! Providers see the composing text before any output transform, and the
+//! text committed before it as context.
+
+#[cfg(feature = "recent-suggestions")]
+use std::collections::VecDeque;
+#[cfg(feature = "recent-suggestions")]
+use std::sync::Mutex;
+
+/// Text that can replace the end of the composing text
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Suggestion {
+    /// Text inserted in place of the replaced characters
+    pub text: String,
+    /// Characters (Unicode scalar values) at the end of the composing text
+    /// that `text` replaces; counts past its start replace all of it
+    pub replace_chars: usize,
+}
+
+impl Suggestion {
+    pub fn new(text: impl Into<String>, replace_chars: usize) -> Self {
+        Self { text: text.into(), replace_chars }
+    }
+
+    /// A suggestion replacing the whole of `composing`
+    pub fn completing(text: impl Into<String>, composing: &str) -> Self {
+        Self::new(text, composing.chars().count())
+    }
+}
+
+/// Source of suggestions for the composing text
+///
+/// Called with the engine locked, so it should answer quickly.
+pub trait SuggestionProvider: Send + Sync {
+    /// Suggestions for `composing`, best first. `context` is text committed
+    /// before it, most recent last; it may be empty.
+    fn suggest(&self, context: &str, composing: &str) -> Vec<Suggestion>;
+
+    /// Text the engine committed, e.g. on `flush`, for providers learning
+    /// from what is typed
+    fn committed(&self, _text: &str) {}
+}
+
+/// Suggests words committed earlier in the session that start with the
+/// composing text, most recent first; a reference provider
+#[cfg(feature = "recent-suggestions")]
+#[derive(Debug)]
+pub struct RecentCommitsProvider {
+    /// Words most recent first, each kept once
+    words: Mutex<VecDeque<String>>,
+    capacity: usize,
+}
+
+#[cfg(feature = "recent-suggestions")]
+impl RecentCommitsProvider {
+    /// Words kept by `default`
+    pub const DEFAULT_CAPACITY: usize = 256;
+
+    /// Keeps at most `capacity` words, forgetting the least recent
+    pub fn new(capacity: usize) -> Self {
+        Self { words: Mutex::new(VecDeque::new()), capacity }
+    }
+
+    /// Words known, most recent first
+    pub fn words(&self) -> Vec<String> {
+        self.words.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(feature = "recent-suggestions")]
+impl Default for RecentCommitsProvider {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(feature = "recent-suggestions")]
+impl SuggestionProvider for RecentCommitsProvider {
+    fn suggest(&self, _context: &str, composing: &str) -> Vec<Suggestion> {
+        if composing.is_empty() {
+            return Vec::new();
+        }
+        self.words
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|word| word.len() > composing.len() && word.starts_with(composing))
+            .map(|word| Suggestion::completing(word.as_str(), composing))
+            .collect()
+    }
+
+    fn committed(&self, text: &str) {
+        let mut words = self.words.lock().unwrap();
+        for word in text.split_whitespace() {
+            words.retain(|known| known != word);
+            words.push_front(word.to_string());
+        }
+        words.truncate(self.capacity);
+    }
+}
@@ -13,6 +13,7 @@ pub enum Element {
     And,                        // Logical AND
     Any,                        // ANY keyword
     Switch(usize),              // State index (0-based)
+    Notify(String),             // Message for the host to show
 }
 
 /// Virtual key code type
@@ -20,14 +21,6 @@ pub enum Element {
 pub struct Predefined(pub u16);
 
 impl Predefined {
-    /// No key
-    pub const NONE: Self = Self(0);
-    
-    /// Creates from raw value
-    pub fn from_raw(value: u16) -> Self {
-        Self(value)
-    }
-    
     /// Gets the raw value
     pub fn raw(&self) -> u16 {
         self.0
@@ -57,6 +50,7 @@ impl From<BinaryFormatElement> for Element {
                 // Use state index as-is
                 Element::Switch(idx)
             }
+            BinaryFormatElement::Notify(message) => Element::Notify(message),
         }
     }
 }
\ No newline at end of file
@@ -1,12 +1,12 @@
 //! Utility functions and implementations for the engine
 
-use crate::types::{Km2File, FileHeader};
+use crate::types::{Km2File, FileHeader, StringTable};
 
 impl Default for Km2File {
     fn default() -> Self {
         Self {
             header: FileHeader::new(),
-            strings: Vec::new(),
+            strings: StringTable::new(),
             info: Vec::new(),
             rules: Vec::new(),
         }
@@ -0,0 +1,126 @@
+//! Keyboard and layout options for headless use, from the environment
+//!
+//! Tools that run the engine outside an input method (CI checks of keyboards
+//! against corpora, shell pipelines) take their settings from, lowest
+//! precedence first:
+//!
+//! - a config file named by `KEYMAGIC_CONFIG`, a JSON object such as
+//!   `{"keyboard": "myanmar3.km2", "options": {"auto_bksp": true}}`; a
+//!   relative keyboard path is relative to the file
+//! - `KEYMAGIC_KEYBOARD`, the path of a KM2 file
+//! - `KEYMAGIC_OPTIONS_JSON`, layout option overrides as a JSON object of
+//!   booleans, merged over the file's
+//!
+//! Command line arguments are applied on top with [`EngineConfig::merge`].
+
+use crate::error::{Error, Result};
+use crate::km2::Km2Loader;
+use crate::{paths, KeyMagicEngine, LayoutOverrides};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+/// Path of the config file
+pub const CONFIG_VAR: &str = "KEYMAGIC_CONFIG";
+/// Path of the keyboard file
+pub const KEYBOARD_VAR: &str = "KEYMAGIC_KEYBOARD";
+/// Layout option overrides as a JSON object
+pub const OPTIONS_VAR: &str = "KEYMAGIC_OPTIONS_JSON";
+
+/// Keyboard to load and the layout options to override
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineConfig {
+    pub keyboard: Option<PathBuf>,
+    pub options: LayoutOverrides,
+}
+
+impl EngineConfig {
+    /// Reads the config from the process environment
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Reads the config from variables looked up with `var`, so callers can
+    /// supply their own environment
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let set = |name: &str| var(name).filter(|value| !value.is_empty());
+        let mut config = match set(CONFIG_VAR) {
+            Some(path) => Self::from_file(Path::new(&path))?,
+            None => Self::default(),
+        };
+        if let Some(path) = set(KEYBOARD_VAR) {
+            config.keyboard = Some(PathBuf::from(path));
+        }
+        if let Some(json) = set(OPTIONS_VAR) {
+            let value = serde_json::from_str(&json).map_err(|e| invalid(OPTIONS_VAR, e))?;
+            let options = parse_options(&value).map_err(|e| invalid(OPTIONS_VAR, e))?;
+            config.options.extend(options);
+        }
+        Ok(config)
+    }
+
+    /// Reads a config file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(paths::to_extended_length(path))?;
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        parse_document(&text, base_dir).map_err(|e| invalid(&path.display().to_string(), e))
+    }
+
+    /// Parses a config file's contents; a relative keyboard path is joined
+    /// to `base_dir`
+    pub fn parse(text: &str, base_dir: &Path) -> Result<Self> {
+        parse_document(text, base_dir).map_err(Error::ParseError)
+    }
+
+    /// Applies `other` on top: its keyboard replaces this one's if set and
+    /// its options are merged over
+    pub fn merge(mut self, other: EngineConfig) -> Self {
+        if other.keyboard.is_some() {
+            self.keyboard = other.keyboard;
+        }
+        self.options.extend(other.options);
+        self
+    }
+
+    /// Loads the keyboard with the option overrides applied
+    pub fn load_engine(&self) -> Result<KeyMagicEngine> {
+        let path = self.keyboard.as_deref().ok_or_else(|| {
+            Error::ParseError(format!("no keyboard given; set {} or the keyboard of the config file", KEYBOARD_VAR))
+        })?;
+        let data = std::fs::read(paths::to_extended_length(path))?;
+        KeyMagicEngine::with_options(Km2Loader::load(&data)?, &self.options)
+    }
+}
+
+fn parse_document(text: &str, base_dir: &Path) -> std::result::Result<EngineConfig, String> {
+    let document: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let fields = document.as_object().ok_or("expected a JSON object")?;
+
+    let mut config = EngineConfig::default();
+    for (name, value) in fields {
+        match name.as_str() {
+            "keyboard" => {
+                let path = value.as_str().ok_or("\"keyboard\" must be a string")?;
+                config.keyboard = Some(base_dir.join(path));
+            }
+            "options" => config.options = parse_options(value)?,
+            other => return Err(format!("unknown field \"{}\"", other)),
+        }
+    }
+    Ok(config)
+}
+
+/// Reads layout option overrides from a JSON object of booleans
+fn parse_options(value: &Value) -> std::result::Result<LayoutOverrides, String> {
+    let object: &Map<String, Value> = value.as_object().ok_or("options must be a JSON object")?;
+    object
+        .iter()
+        .map(|(name, value)| match value.as_bool() {
+            Some(enabled) => Ok((name.clone(), enabled)),
+            None => Err(format!("option \"{}\" must be true or false", name)),
+        })
+        .collect()
+}
+
+fn invalid(source: &str, error: impl std::fmt::Display) -> Error {
+    Error::ParseError(format!("{}: {}", source, error))
+}
@@ -22,6 +22,21 @@ pub enum Error {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     
+    #[error("Output transform not available: {0}")]
+    TransformUnavailable(&'static str),
+    
+    #[error("Unknown rule group: {0}")]
+    UnknownRuleGroup(String),
+    
+    #[error("Unknown layout option: {0}")]
+    UnknownLayoutOption(String),
+    
+    #[error("Unknown key name: {0}")]
+    UnknownVirtualKey(String),
+    
+    #[error("Invalid suggestion index: {0}")]
+    InvalidSuggestionIndex(usize),
+    
     #[error("Parse error: {0}")]
     ParseError(String),
 }
@@ -3,22 +3,88 @@
 //! This module provides a C-compatible API that can be used from any language
 //! that supports C FFI (Python, C, C++, etc.) across all platforms.
 
-use crate::{KeyInput, KeyMagicEngine, VirtualKey, Km2File};
-use crate::engine::{ModifierState, ActionType};
-use crate::km2::Km2Loader;
+use crate::{KeyInput, KeyMagicEngine, KeyOutcome, PrefixResult, ResetLevel, RuleFailure, EngineSlot, SharedEngine, VirtualKey, Km2File};
+use crate::engine::{ModifierState, ActionType, EngineOutput};
+use crate::char_suppression::{host_listed, CharSuppressionStats, EatenKey, EatenKeyTable, HookReason};
+use crate::commit_log::CommitLog;
+use crate::composition_journal::CompositionJournal;
+use crate::context_store::ContextStore;
+use crate::hotkey::{DoubleTapDetector, SwitchDecision, SwitchLoopBreaker};
+use crate::input_latency::InputLatency;
+use crate::key_stats::{day_of, hash_keyboard_id, KeyStats};
+#[cfg(windows)]
+use crate::input_mode::InputModeState;
+use crate::input_mode::{host_shortcut_keys, resolve_input_mode, HostShortcuts, InputMode, InputModeResolution};
+use crate::km2::{content_hash, needs_reload, Km2Loader};
+#[cfg(windows)]
+use crate::load_log::LoadLog;
+use crate::paths;
+use crate::notification::{NotificationLimiter, NOTIFICATION_INTERVAL};
+use crate::processing_state::{GuiLiveness, GuiPresence, HostAction, HostProcessingState, ProcessingState, LIVENESS_CHECK_INTERVAL};
+use crate::shortcut_watch::ShortcutWatch;
+use crate::recorder::{InputRecorder, KeyEventRecord, MOD_ALT, MOD_CAPS_LOCK, MOD_CTRL, MOD_SHIFT};
+use crate::transform::TransformId;
+use crate::tray_icon::{draw_badge, render_tray_icon, Badge, IconImage, IconTheme};
+use std::collections::BTreeMap;
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_char, c_int};
+use std::os::raw::{c_char, c_int, c_uint};
+use std::path::Path;
 use std::ptr;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use parking_lot::Mutex;
 
 /// Opaque handle to a KeyMagic engine instance
+///
+/// The handle may be used from multiple threads. Which engine is loaded is
+/// published through an `EngineSlot`, so keys never wait for a keyboard
+/// being loaded; the engine itself is a `SharedEngine`, so calls are
+/// serialized by its own lock.
 pub struct EngineHandle {
-    engine: Mutex<Option<KeyMagicEngine>>,
+    engine: EngineSlot,
 }
 
-/// Result codes for FFI functions
+impl EngineHandle {
+    /// Returns the loaded engine; a call keeps using it even if another
+    /// thread loads a keyboard meanwhile
+    fn engine(&self) -> Option<SharedEngine> {
+        self.engine.load()
+    }
+
+    /// Installs `engine`, loaded from `keyboard` whose contents hash to
+    /// `hash` if known. A handle shared by several owners keeps the keyboard
+    /// it was acquired for.
+    fn set_engine(&self, engine: KeyMagicEngine, keyboard: String, hash: Option<String>) -> KeyMagicResult {
+        {
+            let mut instances = INSTANCES.lock();
+            if let Some(record) = instances.get_mut(&(self as *const Self as usize)) {
+                if record.refs > 1 && record.keyboard.as_deref() != Some(keyboard.as_str()) {
+                    return KeyMagicResult::ErrorInvalidParameter;
+                }
+                record.keyboard = Some(keyboard);
+                record.hash = hash;
+            }
+        }
+        self.engine.store(Some(SharedEngine::new(engine)));
+        KeyMagicResult::Success
+    }
+
+    /// Installs the built-in fallback engine. The handle keeps the keyboard
+    /// it was loaded for, so a shared handle stays shared, but forgets its
+    /// hash so `keymagic_engine_needs_reload` asks for another load.
+    fn set_fallback_engine(&self) {
+        if let Some(record) = INSTANCES.lock().get_mut(&(self as *const Self as usize)) {
+            record.keyboard.get_or_insert_with(|| FALLBACK_KEYBOARD.to_string());
+            record.hash = None;
+        }
+        self.engine.store(Some(SharedEngine::new(KeyMagicEngine::fallback())));
+    }
+}
+
+/// Result codes for FFI functions; more error codes may be added
 #[repr(C)]
 #[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub enum KeyMagicResult {
     Success = 0,
     ErrorInvalidHandle = -1,
@@ -35,31 +101,305 @@ pub struct ProcessKeyOutput {
     pub action_type: c_int,
     /// Text to insert (UTF-8 encoded, null-terminated)
     pub text: *mut c_char,
-    /// Number of characters to delete
+    /// Number of characters (Unicode scalar values) to delete
     pub delete_count: c_int,
     /// Current composing text
     pub composing_text: *mut c_char,
     /// Whether the key was processed by the engine (0=false, 1=true)
     pub is_processed: c_int,
+    /// Number of UTF-16 code units to delete; use this instead of
+    /// `delete_count` when editing UTF-16 text (Windows, macOS)
+    pub delete_utf16_count: c_int,
+    /// Caret position within the composing text, in UTF-16 code units
+    pub composing_caret_utf16: c_int,
+    /// Message a rule asked the host to show (UTF-8, null-terminated), or
+    /// NULL; never set for dry runs. Free with `keymagic_free_string`.
+    pub notification: *mut c_char,
+    /// Whether the engine cut the delete short because the keyboard asked to
+    /// delete more than it typed (0=false, 1=true); hosts may warn the user
+    pub delete_clamped: c_int,
+}
+
+/// Bookkeeping for one live engine handle
+struct InstanceRecord {
+    id: u64,
+    thread_id: u64,
+    /// Path of the loaded keyboard, `<memory>` for keyboards loaded from a buffer
+    keyboard: Option<String>,
+    /// Content hash of the loaded keyboard, see `keymagic_core::km2::hash`
+    hash: Option<String>,
+    /// Owners of a shared handle; the handle is freed when this drops to 0
+    refs: usize,
+}
+
+/// Live engine handles of this process, keyed by handle address
+static INSTANCES: Mutex<BTreeMap<usize, InstanceRecord>> = Mutex::new(BTreeMap::new());
+static NEXT_INSTANCE_ID: AtomicU64 = AtomicU64::new(1);
+static SHARE_ENGINES: AtomicBool = AtomicBool::new(false);
+/// Keyboard messages shown lately, shared by every handle of the process
+static NOTIFICATIONS: Mutex<NotificationLimiter> = Mutex::new(NotificationLimiter::new(NOTIFICATION_INTERVAL));
+
+/// Why the last keyboard load of this process failed; successful loads
+/// leave it alone
+static LAST_LOAD_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+const MEMORY_KEYBOARD: &str = "<memory>";
+const FALLBACK_KEYBOARD: &str = "<fallback>";
+
+/// Records why loading `keyboard` failed for `keymagic_engine_last_error`
+fn load_failed(keyboard: &str, reason: impl std::fmt::Display) -> KeyMagicResult {
+    *LAST_LOAD_ERROR.lock() = Some(format!("{}: {}", keyboard, reason));
+    KeyMagicResult::ErrorEngineFailure
+}
+
+#[cfg(windows)]
+fn current_thread_id() -> u64 {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThreadId() -> u32;
+    }
+    unsafe { GetCurrentThreadId() as u64 }
+}
+
+/// Whether this process is a packaged (UWP) app
+#[cfg(windows)]
+fn is_packaged_process() -> bool {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentPackageFullName(length: *mut u32, name: *mut u16) -> i32;
+    }
+    const APPMODEL_ERROR_NO_PACKAGE: i32 = 15700;
+    static PACKAGED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *PACKAGED.get_or_init(|| {
+        let mut length = 0;
+        unsafe { GetCurrentPackageFullName(&mut length, ptr::null_mut()) != APPMODEL_ERROR_NO_PACKAGE }
+    })
+}
+
+#[cfg(not(windows))]
+fn is_packaged_process() -> bool {
+    false
+}
+
+#[cfg(not(windows))]
+fn current_thread_id() -> u64 {
+    static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static THREAD_ID: u64 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    }
+    THREAD_ID.with(|id| *id)
+}
+
+fn register_instance(handle: *mut EngineHandle, keyboard: Option<String>) {
+    INSTANCES.lock().insert(handle as usize, InstanceRecord {
+        id: NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed),
+        thread_id: current_thread_id(),
+        keyboard,
+        hash: None,
+        refs: 1,
+    });
 }
 
 /// Creates a new engine instance
 #[no_mangle]
 pub extern "C" fn keymagic_engine_new() -> *mut EngineHandle {
-    let handle = Box::new(EngineHandle {
-        engine: Mutex::new(None),
-    });
-    Box::into_raw(handle)
+    let handle = Box::into_raw(Box::new(EngineHandle {
+        engine: EngineSlot::default(),
+    }));
+    register_instance(handle, None);
+    handle
 }
 
 /// Frees an engine instance
+///
+/// A handle returned by `keymagic_engine_acquire` more than once is only
+/// freed once every owner has called this.
 #[no_mangle]
 pub extern "C" fn keymagic_engine_free(handle: *mut EngineHandle) {
-    if !handle.is_null() {
-        unsafe {
-            let _ = Box::from_raw(handle);
+    if handle.is_null() {
+        return;
+    }
+
+    {
+        let mut instances = INSTANCES.lock();
+        if let Some(record) = instances.get_mut(&(handle as usize)) {
+            record.refs -= 1;
+            if record.refs > 0 {
+                return;
+            }
+            instances.remove(&(handle as usize));
+        }
+    }
+    unsafe {
+        let _ = Box::from_raw(handle);
+    }
+}
+
+/// Enables or disables engine sharing for this process
+///
+/// While enabled, `keymagic_engine_acquire` hands out the existing engine
+/// for a keyboard instead of creating a second one, so text services
+/// activated on several threads of one application share their composing
+/// state.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_set_sharing(enabled: c_int) {
+    SHARE_ENGINES.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Returns an engine with the keyboard at `km2_path` loaded, or NULL if the
+/// keyboard cannot be loaded
+///
+/// With sharing enabled, an engine already loaded with the same keyboard is
+/// returned with its reference count increased; otherwise this creates a new
+/// engine. Either way, release it with `keymagic_engine_free`.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_acquire(km2_path: *const c_char) -> *mut EngineHandle {
+    if km2_path.is_null() {
+        return ptr::null_mut();
+    }
+
+    match unsafe { CStr::from_ptr(km2_path) }.to_str() {
+        Ok(path) => acquire_engine(Path::new(path)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// `keymagic_engine_acquire` taking a null-terminated UTF-16 path
+#[no_mangle]
+pub extern "C" fn keymagic_engine_acquire_w(km2_path: *const u16) -> *mut EngineHandle {
+    if km2_path.is_null() {
+        return ptr::null_mut();
+    }
+
+    let units = unsafe {
+        let mut len = 0;
+        while *km2_path.add(len) != 0 {
+            len += 1;
+        }
+        std::slice::from_raw_parts(km2_path, len)
+    };
+    match paths::path_from_wide(units) {
+        Some(path) => acquire_engine(&path),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Whether a live handle has `keyboard` loaded from a file hashing to `hash`;
+/// an engine loaded before the file was replaced is not shared any more
+fn is_same_load(record: &InstanceRecord, keyboard: &str, hash: &str) -> bool {
+    record.keyboard.as_deref() == Some(keyboard) && record.hash.as_deref() == Some(hash)
+}
+
+fn shared_engine_for(keyboard: &str, hash: &str) -> Option<*mut EngineHandle> {
+    let mut instances = INSTANCES.lock();
+    let (&address, record) = instances
+        .iter_mut()
+        .find(|(_, record)| is_same_load(record, keyboard, hash))?;
+    record.refs += 1;
+    Some(address as *mut EngineHandle)
+}
+
+fn acquire_engine(path: &Path) -> *mut EngineHandle {
+    let keyboard = path.to_string_lossy().into_owned();
+    let km2_data = match std::fs::read(paths::to_extended_length(path)) {
+        Ok(data) => data,
+        Err(e) => {
+            load_failed(&keyboard, e);
+            return ptr::null_mut();
+        }
+    };
+    let hash = content_hash(&km2_data);
+    let sharing = SHARE_ENGINES.load(Ordering::Relaxed);
+    if sharing {
+        if let Some(handle) = shared_engine_for(&keyboard, &hash) {
+            return handle;
+        }
+    }
+
+    let handle = keymagic_engine_new();
+    if load_keyboard_data(unsafe { &*handle }, &km2_data, keyboard.clone()) != KeyMagicResult::Success {
+        keymagic_engine_free(handle);
+        return ptr::null_mut();
+    }
+
+    // Another thread may have loaded the same keyboard in the meantime
+    if sharing {
+        let mut instances = INSTANCES.lock();
+        let existing = instances
+            .iter()
+            .find(|(&address, record)| {
+                address != handle as usize && is_same_load(record, &keyboard, &hash)
+            })
+            .map(|(&address, _)| address);
+        if let Some(address) = existing {
+            if let Some(record) = instances.get_mut(&address) {
+                record.refs += 1;
+            }
+            drop(instances);
+            keymagic_engine_free(handle);
+            return address as *mut EngineHandle;
         }
     }
+    handle
+}
+
+/// Number of live engine handles in this process
+#[no_mangle]
+pub extern "C" fn keymagic_debug_instance_count() -> c_int {
+    INSTANCES.lock().len() as c_int
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Describes the live engine handles of this process as JSON
+///
+/// `{"sharing":false,"count":1,"instances":[{"id":1,"thread_id":4242,
+/// "keyboard":"C:\\...\\MyanSan.km2","hash":"9f86d0...","refs":1}]}`, ordered
+/// by `id`; `keyboard` and `hash` are null until a keyboard is loaded. The
+/// returned string must be freed with `keymagic_free_string`.
+#[no_mangle]
+pub extern "C" fn keymagic_debug_dump_instances() -> *mut c_char {
+    let instances = INSTANCES.lock();
+    let mut records: Vec<&InstanceRecord> = instances.values().collect();
+    records.sort_by_key(|record| record.id);
+
+    let entries: Vec<String> = records
+        .iter()
+        .map(|record| {
+            format!(
+                "{{\"id\":{},\"thread_id\":{},\"keyboard\":{},\"hash\":{},\"refs\":{}}}",
+                record.id,
+                record.thread_id,
+                record.keyboard.as_deref().map_or_else(|| "null".to_string(), json_string),
+                record.hash.as_deref().map_or_else(|| "null".to_string(), json_string),
+                record.refs
+            )
+        })
+        .collect();
+    let json = format!(
+        "{{\"sharing\":{},\"count\":{},\"instances\":[{}]}}",
+        SHARE_ENGINES.load(Ordering::Relaxed),
+        records.len(),
+        entries.join(",")
+    );
+
+    CString::new(json).map_or(ptr::null_mut(), CString::into_raw)
 }
 
 /// Loads a KM2 keyboard layout file
@@ -78,30 +418,88 @@ pub extern "C" fn keymagic_engine_load_keyboard(
         Err(_) => return KeyMagicResult::ErrorUtf8Conversion,
     };
 
-    let km2_data = match std::fs::read(path_str) {
-        Ok(data) => data,
-        Err(_) => return KeyMagicResult::ErrorEngineFailure,
+    load_keyboard_file(handle, Path::new(path_str))
+}
+
+/// Loads a KM2 keyboard layout file from a null-terminated UTF-16 path
+///
+/// Prefer this on Windows: the path is used as-is, so folder names that are
+/// not valid UTF-8 and paths longer than MAX_PATH load too.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_load_keyboard_w(
+    handle: *mut EngineHandle,
+    km2_path: *const u16,
+) -> KeyMagicResult {
+    if handle.is_null() || km2_path.is_null() {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+
+    let handle = unsafe { &*handle };
+    let units = unsafe {
+        let mut len = 0;
+        while *km2_path.add(len) != 0 {
+            len += 1;
+        }
+        std::slice::from_raw_parts(km2_path, len)
     };
+    let path = match paths::path_from_wide(units) {
+        Some(path) => path,
+        None => return KeyMagicResult::ErrorUtf8Conversion,
+    };
+
+    load_keyboard_file(handle, &path)
+}
+
+fn load_keyboard_file(handle: &EngineHandle, path: &Path) -> KeyMagicResult {
+    match std::fs::read(paths::to_extended_length(path)) {
+        Ok(km2_data) => load_keyboard_data(handle, &km2_data, path.to_string_lossy().into_owned()),
+        Err(e) => load_failed(&path.to_string_lossy(), e),
+    }
+}
 
-    let km2_file = match Km2Loader::load(&km2_data) {
+/// Loads a keyboard read from `keyboard`, a path or `<memory>`. Keyboards
+/// loaded from files are reported to the load log.
+fn load_keyboard_data(handle: &EngineHandle, km2_data: &[u8], keyboard: String) -> KeyMagicResult {
+    let km2_file = match Km2Loader::load(km2_data) {
         Ok(file) => file,
-        Err(_) => return KeyMagicResult::ErrorEngineFailure,
+        Err(e) => return load_failed(&keyboard, e),
+    };
+    let engine = match KeyMagicEngine::new(km2_file) {
+        Ok(engine) => engine,
+        Err(e) => return load_failed(&keyboard, e),
     };
 
-    match handle.engine.lock() {
-        Ok(mut engine_opt) => {
-            match KeyMagicEngine::new(km2_file) {
-                Ok(engine) => {
-                    *engine_opt = Some(engine);
-                    KeyMagicResult::Success
-                }
-                Err(_) => KeyMagicResult::ErrorEngineFailure,
-            }
+    let hash = content_hash(km2_data);
+    let from_file = keyboard != MEMORY_KEYBOARD;
+    let result = handle.set_engine(engine, keyboard.clone(), Some(hash.clone()));
+    match result {
+        KeyMagicResult::Success if from_file => report_load(&hash),
+        KeyMagicResult::Success => {}
+        _ => {
+            load_failed(&keyboard, "the engine is shared with another keyboard");
         }
-        Err(_) => KeyMagicResult::ErrorEngineFailure,
     }
+    result
+}
+
+/// Reports a keyboard load of this process to the shared load log
+#[cfg(windows)]
+fn report_load(hash: &str) {
+    static LOAD_LOG: std::sync::OnceLock<Option<LoadLog>> = std::sync::OnceLock::new();
+    let Some(log) = LOAD_LOG.get_or_init(|| LoadLog::create_shared().ok()) else {
+        return;
+    };
+    let process = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.file_name().map(|name| name.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    log.report(std::process::id(), &process, hash);
 }
 
+/// Nothing reads a load log outside Windows
+#[cfg(not(windows))]
+fn report_load(_hash: &str) {}
+
 /// Loads a keyboard from memory buffer
 #[no_mangle]
 pub extern "C" fn keymagic_engine_load_keyboard_from_memory(
@@ -116,23 +514,87 @@ pub extern "C" fn keymagic_engine_load_keyboard_from_memory(
     let handle = unsafe { &*handle };
     let data_slice = unsafe { std::slice::from_raw_parts(km2_data, data_len) };
     
-    let km2_file = match Km2Loader::load(data_slice) {
-        Ok(file) => file,
-        Err(_) => return KeyMagicResult::ErrorEngineFailure,
-    };
+    load_keyboard_data(handle, data_slice, MEMORY_KEYBOARD.to_string())
+}
 
-    match handle.engine.lock() {
-        Ok(mut engine_opt) => {
-            match KeyMagicEngine::new(km2_file) {
-                Ok(engine) => {
-                    *engine_opt = Some(engine);
-                    KeyMagicResult::Success
-                }
-                Err(_) => KeyMagicResult::ErrorEngineFailure,
-            }
-        }
-        Err(_) => KeyMagicResult::ErrorEngineFailure,
+/// Loads the built-in fallback keyboard, which passes every key through
+///
+/// Call this when loading the real keyboard failed, so the handle always has
+/// a working engine rather than none: every key comes back with
+/// `is_processed` 0. The reason of the failure is available from
+/// `keymagic_engine_last_error`, and `keymagic_engine_needs_reload` keeps
+/// asking for the real keyboard.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_load_fallback(handle: *mut EngineHandle) -> KeyMagicResult {
+    if handle.is_null() {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+    unsafe { &*handle }.set_fallback_engine();
+    KeyMagicResult::Success
+}
+
+/// Returns why the last keyboard load of this process failed, as
+/// "keyboard: reason", or null if none failed
+///
+/// Any thread may call this. Successful loads do not clear it, so the reason
+/// is still there after the fallback keyboard or another keyboard loaded.
+/// Free the result with `keymagic_free_string`.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_last_error() -> *mut c_char {
+    LAST_LOAD_ERROR
+        .lock()
+        .clone()
+        .and_then(|error| CString::new(error).ok())
+        .map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Content hash of the keyboard the handle has loaded, if any
+fn loaded_hash(handle: *mut EngineHandle) -> Option<String> {
+    INSTANCES.lock().get(&(handle as usize)).and_then(|record| record.hash.clone())
+}
+
+/// Whether a message raised by the keyboard of `handle` reaches the host;
+/// handles loading the same keyboard share its rate limit
+fn allow_notification(handle: *const EngineHandle) -> bool {
+    let keyboard = INSTANCES
+        .lock()
+        .get(&(handle as usize))
+        .and_then(|record| record.hash.clone().or_else(|| record.keyboard.clone()))
+        .unwrap_or_else(|| format!("{:p}", handle));
+    NOTIFICATIONS.lock().allow(&keyboard)
+}
+
+/// Returns the content hash of the keyboard file the engine loaded
+///
+/// The hash is taken when the keyboard is loaded, in the form the GUI
+/// records for installed keyboards (lowercase hex SHA-256), so a host can
+/// tell an engine loaded before its file was replaced. Returns null if the
+/// handle is invalid or no keyboard is loaded. Free the result with
+/// `keymagic_free_string`.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_get_loaded_hash(handle: *mut EngineHandle) -> *mut c_char {
+    if handle.is_null() {
+        return ptr::null_mut();
+    }
+    loaded_hash(handle)
+        .and_then(|hash| CString::new(hash).ok())
+        .map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Whether the engine should load its keyboard again because the file now
+/// hashes to `expected_hash`, as recorded by the GUI
+///
+/// Returns 1 to reload and 0 otherwise; an empty or missing expected hash
+/// is unknown and never asks for a reload.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_needs_reload(handle: *mut EngineHandle, expected_hash: *const c_char) -> c_int {
+    if handle.is_null() || expected_hash.is_null() {
+        return 0;
     }
+    let Ok(expected) = unsafe { CStr::from_ptr(expected_hash) }.to_str() else {
+        return 0;
+    };
+    needs_reload(loaded_hash(handle).as_deref(), expected) as c_int
 }
 
 /// Internal function to process key events (shared by normal and dry-run)
@@ -142,66 +604,79 @@ fn process_key_internal(
     dry_run: bool,
     output: &mut ProcessKeyOutput,
 ) -> KeyMagicResult {
-    // Initialize output
+    clear_output(output);
+
+    let Some(engine) = handle.engine() else {
+        return KeyMagicResult::ErrorNoKeyboard;
+    };
+
+    let result = if dry_run {
+        engine.process_key_test(key_input)
+    } else {
+        engine.process_key(key_input)
+    };
+
+    match result {
+        Ok(result) => {
+            write_output(handle, result, output);
+            KeyMagicResult::Success
+        }
+        Err(_) => KeyMagicResult::ErrorEngineFailure,
+    }
+}
+
+fn clear_output(output: &mut ProcessKeyOutput) {
     output.action_type = 0;
     output.text = ptr::null_mut();
     output.delete_count = 0;
     output.composing_text = ptr::null_mut();
     output.is_processed = 0;
+    output.delete_utf16_count = 0;
+    output.composing_caret_utf16 = 0;
+    output.notification = ptr::null_mut();
+    output.delete_clamped = 0;
+}
 
-    match handle.engine.lock() {
-        Ok(mut engine_opt) => {
-            if let Some(engine) = engine_opt.as_mut() {
-                let result = if dry_run {
-                    engine.process_key_test(key_input)
-                } else {
-                    engine.process_key(key_input)
-                };
-
-                match result {
-                    Ok(result) => {
-                        // Set composing text
-                        if let Ok(c_string) = CString::new(result.composing_text.clone()) {
-                            output.composing_text = c_string.into_raw();
-                        }
-                        
-                        // Process action
-                        match &result.action {
-                            ActionType::None => {
-                                output.action_type = 0;
-                            }
-                            ActionType::Insert(text) => {
-                                output.action_type = 1;
-                                if let Ok(c_string) = CString::new(text.clone()) {
-                                    output.text = c_string.into_raw();
-                                }
-                            }
-                            ActionType::BackspaceDelete(count) => {
-                                output.action_type = 2;
-                                output.delete_count = *count as c_int;
-                            }
-                            ActionType::BackspaceDeleteAndInsert(count, text) => {
-                                output.action_type = 3;
-                                output.delete_count = *count as c_int;
-                                if let Ok(c_string) = CString::new(text.clone()) {
-                                    output.text = c_string.into_raw();
-                                }
-                            }
-                        }
-                        
-                        // Set the is_processed flag
-                        output.is_processed = if result.is_processed { 1 } else { 0 };
-                        
-                        KeyMagicResult::Success
-                    }
-                    Err(_) => KeyMagicResult::ErrorEngineFailure,
-                }
-            } else {
-                KeyMagicResult::ErrorNoKeyboard
+fn write_output(handle: &EngineHandle, result: EngineOutput, output: &mut ProcessKeyOutput) {
+    // Set composing text
+    if let Ok(c_string) = CString::new(result.composing_text.clone()) {
+        output.composing_text = c_string.into_raw();
+    }
+    
+    // Process action
+    match &result.action {
+        ActionType::None => {
+            output.action_type = 0;
+        }
+        ActionType::Insert(text) => {
+            output.action_type = 1;
+            if let Ok(c_string) = CString::new(text.clone()) {
+                output.text = c_string.into_raw();
             }
         }
-        Err(_) => KeyMagicResult::ErrorEngineFailure,
+        ActionType::BackspaceDelete(count) => {
+            output.action_type = 2;
+            output.delete_count = *count as c_int;
+        }
+        ActionType::BackspaceDeleteAndInsert(count, text) => {
+            output.action_type = 3;
+            output.delete_count = *count as c_int;
+            if let Ok(c_string) = CString::new(text.clone()) {
+                output.text = c_string.into_raw();
+            }
+        }
+    }
+    
+    output.delete_utf16_count = result.delete_utf16_units as c_int;
+    output.composing_caret_utf16 = result.composing_caret_utf16() as c_int;
+    let notification = result.notification.filter(|_| allow_notification(handle));
+    if let Some(c_string) = notification.and_then(|message| CString::new(message).ok()) {
+        output.notification = c_string.into_raw();
     }
+
+    // Set the is_processed flag
+    output.is_processed = if result.is_processed { 1 } else { 0 };
+    output.delete_clamped = result.clamped as c_int;
 }
 
 /// Processes a key event
@@ -262,16 +737,59 @@ pub extern "C" fn keymagic_engine_reset(handle: *mut EngineHandle) -> KeyMagicRe
     }
 
     let handle = unsafe { &*handle };
-    match handle.engine.lock() {
-        Ok(mut engine_opt) => {
-            if let Some(engine) = engine_opt.as_mut() {
-                engine.reset();
-                KeyMagicResult::Success
-            } else {
-                KeyMagicResult::ErrorNoKeyboard
-            }
+    match handle.engine() {
+        Some(engine) => {
+            engine.reset();
+            KeyMagicResult::Success
         }
-        Err(_) => KeyMagicResult::ErrorEngineFailure,
+        None => KeyMagicResult::ErrorNoKeyboard,
+    }
+}
+
+/// Clears part of the engine state
+///
+/// level: 0=Composing (composing text, undo history and emitted count; the
+/// states on for the next key survive), 1=Context (also the states),
+/// 2=Full (also the last matched rules; same as `keymagic_engine_reset`).
+/// Returns ErrorInvalidParameter for any other level.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_reset_level(handle: *mut EngineHandle, level: c_int) -> KeyMagicResult {
+    if handle.is_null() {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+
+    let level = match level {
+        0 => ResetLevel::Composing,
+        1 => ResetLevel::Context,
+        2 => ResetLevel::Full,
+        _ => return KeyMagicResult::ErrorInvalidParameter,
+    };
+
+    let handle = unsafe { &*handle };
+    match handle.engine() {
+        Some(engine) => {
+            engine.reset_level(level);
+            KeyMagicResult::Success
+        }
+        None => KeyMagicResult::ErrorNoKeyboard,
+    }
+}
+
+/// Ends the composition and returns its text for the host to commit
+///
+/// Returns the composing text as emitted (after any output transform) and
+/// resets the engine, atomically. Free the result with `keymagic_free_string`.
+/// Returns null if the handle is invalid or no keyboard is loaded.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_flush(handle: *mut EngineHandle) -> *mut c_char {
+    if handle.is_null() {
+        return ptr::null_mut();
+    }
+
+    let handle = unsafe { &*handle };
+    match handle.engine() {
+        Some(engine) => CString::new(engine.flush()).map_or(ptr::null_mut(), CString::into_raw),
+        None => ptr::null_mut(),
     }
 }
 
@@ -285,20 +803,70 @@ pub extern "C" fn keymagic_engine_get_composition(
     }
 
     let handle = unsafe { &*handle };
-    match handle.engine.lock() {
-        Ok(engine_opt) => {
-            if let Some(engine) = engine_opt.as_ref() {
-                let composition = engine.composing_text();
-                match CString::new(composition) {
-                    Ok(c_string) => c_string.into_raw(),
-                    Err(_) => ptr::null_mut(),
-                }
-            } else {
-                ptr::null_mut()
+    match handle.engine() {
+        Some(engine) => {
+            // Report what the host has on screen, i.e. after any output transform
+            match CString::new(engine.emitted_text()) {
+                Ok(c_string) => c_string.into_raw(),
+                Err(_) => ptr::null_mut(),
             }
         }
-        Err(_) => ptr::null_mut(),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Gets the current composition string as UTF-16 together with the caret
+///
+/// Returns the number of UTF-16 code units needed to hold the composition
+/// including the terminating NUL, or 0 if the handle is invalid or no keyboard
+/// is loaded. At most `buf_len` units are written to `out_buf`, always
+/// NUL-terminated; if the buffer is too small the text is cut before the first
+/// character that does not fit, so a surrogate pair is never split. The caret
+/// position (in UTF-16 units of the full composition) is stored in
+/// `out_caret_index` when it is not null.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_get_composition_ex(
+    handle: *mut EngineHandle,
+    out_buf: *mut u16,
+    buf_len: usize,
+    out_caret_index: *mut c_int,
+) -> usize {
+    if handle.is_null() {
+        return 0;
     }
+
+    let handle = unsafe { &*handle };
+    let Some(engine) = handle.engine() else {
+        return 0;
+    };
+
+    // Read text and caret under one lock so they always belong together
+    let (text, caret) = {
+        let engine = engine.read();
+        (engine.emitted_text(), engine.emitted_caret())
+    };
+
+    if !out_caret_index.is_null() {
+        unsafe { *out_caret_index = crate::engine::utf16_offset(&text, caret) as c_int };
+    }
+
+    let required = text.encode_utf16().count() + 1;
+    if !out_buf.is_null() && buf_len > 0 {
+        let buf = unsafe { std::slice::from_raw_parts_mut(out_buf, buf_len) };
+        let mut written = 0;
+        for ch in text.chars() {
+            let len = ch.len_utf16();
+            // Keep one unit for the terminator
+            if written + len >= buf_len {
+                break;
+            }
+            ch.encode_utf16(&mut buf[written..written + len]);
+            written += len;
+        }
+        buf[written] = 0;
+    }
+
+    required
 }
 
 /// Sets the composition string
@@ -323,321 +891,1189 @@ pub extern "C" fn keymagic_engine_set_composition(
         }
     };
 
-    match handle.engine.lock() {
-        Ok(mut engine_opt) => {
-            if let Some(engine) = engine_opt.as_mut() {
-                engine.set_composing_text(text_str.to_string());
-                KeyMagicResult::Success
-            } else {
-                KeyMagicResult::ErrorNoKeyboard
-            }
+    match handle.engine() {
+        Some(engine) => {
+            engine.set_composing_text(text_str.to_string());
+            KeyMagicResult::Success
         }
-        Err(_) => KeyMagicResult::ErrorEngineFailure,
+        None => KeyMagicResult::ErrorNoKeyboard,
     }
 }
 
-/// Get library version
+/// Sets the transform applied to emitted text
+///
+/// transform_id: 0=None (Unicode), 1=Zawgyi. The transform belongs to the
+/// loaded keyboard and must be set again after loading another keyboard.
 #[no_mangle]
-pub extern "C" fn keymagic_get_version() -> *const c_char {
-    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+pub extern "C" fn keymagic_engine_set_output_transform(
+    handle: *mut EngineHandle,
+    transform_id: c_int,
+) -> KeyMagicResult {
+    if handle.is_null() {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+
+    let transform = match transform_id {
+        0 => None,
+        1 => Some(TransformId::Zawgyi),
+        _ => return KeyMagicResult::ErrorInvalidParameter,
+    };
+
+    let handle = unsafe { &*handle };
+    match handle.engine() {
+        Some(engine) => match engine.set_output_transform(transform) {
+            Ok(()) => KeyMagicResult::Success,
+            Err(_) => KeyMagicResult::ErrorEngineFailure,
+        },
+        None => KeyMagicResult::ErrorNoKeyboard,
+    }
 }
 
-/// Process a key event with Windows VK code
+/// Switches a named rule group on or off
+///
+/// Returns ErrorInvalidParameter if the keyboard has no group with that name.
+/// Like the output transform, toggles belong to the loaded keyboard.
 #[no_mangle]
-pub extern "C" fn keymagic_engine_process_key_win(
+pub extern "C" fn keymagic_engine_set_group_enabled(
     handle: *mut EngineHandle,
-    vk_code: c_int,        // Windows VK code (e.g., 0x41 for VK_A)
-    character: c_char,
-    shift: c_int,
-    ctrl: c_int,
-    alt: c_int,
-    caps_lock: c_int,
-    output: *mut ProcessKeyOutput,
+    name: *const c_char,
+    enabled: c_int,
 ) -> KeyMagicResult {
-    // Convert Windows VK code to VirtualKey, return error if unsupported
-    let virtual_key = match VirtualKey::from_win_vk(vk_code as u16) {
-        Some(vk) => vk,
-        None => return KeyMagicResult::ErrorInvalidParameter,
+    if handle.is_null() || name.is_null() {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+
+    let name = match unsafe { CStr::from_ptr(name) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return KeyMagicResult::ErrorUtf8Conversion,
     };
-    
-    // Forward the call to the regular process_key function
-    keymagic_engine_process_key(
-        handle,
-        virtual_key as i32,
-        character,
-        shift,
-        ctrl,
-        alt,
-        caps_lock,
-        output,
-    )
+
+    let handle = unsafe { &*handle };
+    match handle.engine() {
+        Some(engine) => match engine.set_group_enabled(name, enabled != 0) {
+            Ok(()) => KeyMagicResult::Success,
+            Err(_) => KeyMagicResult::ErrorInvalidParameter,
+        },
+        None => KeyMagicResult::ErrorNoKeyboard,
+    }
 }
 
-/// Process a key event in test mode (does not modify engine state)
+/// Overrides a layout option ("track_caps", "auto_bksp", "eat", "pos_based",
+/// "right_alt" or "case_insensitive_ascii") of the loaded keyboard
+///
+/// Returns ErrorInvalidParameter for unknown option names. Like rule group
+/// toggles, overrides belong to the loaded keyboard.
 #[no_mangle]
-pub extern "C" fn keymagic_engine_process_key_test(
+pub extern "C" fn keymagic_engine_set_layout_option(
     handle: *mut EngineHandle,
-    key_code: c_int,
-    character: c_char,
-    shift: c_int,
-    ctrl: c_int,
-    alt: c_int,
-    caps_lock: c_int,
-    output: *mut ProcessKeyOutput,
+    name: *const c_char,
+    value: c_int,
 ) -> KeyMagicResult {
-    if handle.is_null() || output.is_null() {
+    if handle.is_null() || name.is_null() {
         return KeyMagicResult::ErrorInvalidParameter;
     }
 
-    let handle = unsafe { &*handle };
-    let output_ref = unsafe { &mut *output };
-
-    // Convert character from c_char to Option<char>
-    let char_opt = if character == 0 {
-        None
-    } else {
-        Some(character as u8 as char)
+    let name = match unsafe { CStr::from_ptr(name) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return KeyMagicResult::ErrorUtf8Conversion,
     };
 
-    let key_input = KeyInput {
-        key_code: key_code as u16,
-        modifiers: ModifierState {
-            shift: shift != 0,
-            ctrl: ctrl != 0,
-            alt: alt != 0,
-            caps_lock: caps_lock != 0,
-        },
-        character: char_opt,
-    };
+    let handle = unsafe { &*handle };
+    match handle.engine() {
+        Some(engine) => {
+            let mut options = engine.layout_options();
+            match options.set(name, value != 0) {
+                Ok(()) => {
+                    engine.set_layout_options(options);
+                    KeyMagicResult::Success
+                }
+                Err(_) => KeyMagicResult::ErrorInvalidParameter,
+            }
+        }
+        None => KeyMagicResult::ErrorNoKeyboard,
+    }
+}
 
-    process_key_internal(handle, key_input, true, output_ref)
+/// Gets the number of rule groups of the loaded keyboard, or -1 on error
+#[no_mangle]
+pub extern "C" fn keymagic_engine_get_rule_group_count(handle: *mut EngineHandle) -> c_int {
+    if handle.is_null() {
+        return -1;
+    }
+
+    let handle = unsafe { &*handle };
+    match handle.engine() {
+        Some(engine) => engine.read().rule_groups().len() as c_int,
+        None => -1,
+    }
 }
 
-/// Process a key event in test mode with Windows VK code (does not modify engine state)
+/// Gets the name of the rule group at `index`
+/// Returns NULL if the index is out of range. Free with keymagic_free_string.
 #[no_mangle]
-pub extern "C" fn keymagic_engine_process_key_test_win(
+pub extern "C" fn keymagic_engine_get_rule_group_name(
     handle: *mut EngineHandle,
-    vk_code: c_int,        // Windows VK code (e.g., 0x41 for VK_A)
-    character: c_char,
-    shift: c_int,
-    ctrl: c_int,
-    alt: c_int,
-    caps_lock: c_int,
-    output: *mut ProcessKeyOutput,
-) -> KeyMagicResult {
-    // Convert Windows VK code to VirtualKey, return error if unsupported
-    let virtual_key = match VirtualKey::from_win_vk(vk_code as u16) {
-        Some(vk) => vk,
-        None => return KeyMagicResult::ErrorInvalidParameter,
+    index: c_int,
+) -> *mut c_char {
+    if handle.is_null() || index < 0 {
+        return ptr::null_mut();
+    }
+
+    let handle = unsafe { &*handle };
+    let Some(engine) = handle.engine() else {
+        return ptr::null_mut();
     };
-    
-    // Forward the call to the regular test function
-    keymagic_engine_process_key_test(
-        handle,
-        virtual_key as i32,
-        character,
-        shift,
-        ctrl,
-        alt,
-        caps_lock,
-        output,
-    )
+    let engine = engine.read();
+    engine
+        .rule_groups()
+        .get(index as usize)
+        .and_then(|group| CString::new(group.name.as_str()).ok())
+        .map_or(ptr::null_mut(), CString::into_raw)
 }
 
-/// Get the current composing text from the engine
-/// Returns a newly allocated C string that must be freed with keymagic_engine_free_string
+/// Starts (clearing any counts) or stops collecting rule match statistics
 #[no_mangle]
-pub extern "C" fn keymagic_engine_get_composing_text(engine: *mut KeyMagicEngine) -> *mut c_char {
-    if engine.is_null() {
-        return std::ptr::null_mut();
+pub extern "C" fn keymagic_engine_set_rule_histogram(handle: *mut EngineHandle, enabled: c_int) -> KeyMagicResult {
+    if handle.is_null() {
+        return KeyMagicResult::ErrorInvalidHandle;
     }
 
-    unsafe {
-        let engine_ref = &*engine;
-        match CString::new(engine_ref.composing_text()) {
-            Ok(c_string) => c_string.into_raw(),
-            Err(_) => std::ptr::null_mut(),
+    let handle = unsafe { &*handle };
+    match handle.engine() {
+        Some(engine) => {
+            engine.set_rule_histogram_enabled(enabled != 0);
+            KeyMagicResult::Success
         }
+        None => KeyMagicResult::ErrorNoKeyboard,
     }
 }
 
-/// Free a string allocated by the engine
-#[no_mangle]
-pub extern "C" fn keymagic_engine_free_string(str: *mut c_char) {
-    if !str.is_null() {
-        unsafe {
-            let _ = CString::from_raw(str);
-        }
-    }
+/// Entry of `keymagic_engine_get_rule_histogram`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RuleHistogramEntry {
+    /// Rule index, in source order
+    pub rule: c_int,
+    pub hits: u32,
 }
 
-/// Opaque handle for loaded KM2 file
-pub struct Km2FileHandle(Km2File);
-
-/// Load a KM2 file from path
-/// Returns NULL on failure
+/// Copies the `max_entries` most matched rules to `out_entries`, hottest
+/// first, and the number of keys and rules scanned to `out_keys` and
+/// `out_scanned` when they are not null
+///
+/// Returns the number of entries written, or -1 if the histogram is not
+/// enabled or the handle is invalid.
 #[no_mangle]
-pub extern "C" fn keymagic_km2_load(path: *const c_char) -> *mut Km2FileHandle {
-    if path.is_null() {
-        return std::ptr::null_mut();
+pub extern "C" fn keymagic_engine_get_rule_histogram(
+    handle: *mut EngineHandle,
+    out_entries: *mut RuleHistogramEntry,
+    max_entries: c_int,
+    out_keys: *mut u64,
+    out_scanned: *mut u64,
+) -> c_int {
+    if handle.is_null() || (out_entries.is_null() && max_entries > 0) {
+        return -1;
     }
 
-    let path_str = unsafe {
-        match CStr::from_ptr(path).to_str() {
-            Ok(s) => s,
-            Err(_) => return std::ptr::null_mut(),
-        }
+    let handle = unsafe { &*handle };
+    let Some(histogram) = handle.engine().and_then(|engine| engine.rule_histogram()) else {
+        return -1;
     };
 
-    match std::fs::read(path_str) {
-        Ok(data) => {
-            match crate::km2::Km2Loader::load(&data) {
-                Ok(km2) => Box::into_raw(Box::new(Km2FileHandle(km2))),
-                Err(_) => std::ptr::null_mut(),
-            }
+    unsafe {
+        if !out_keys.is_null() {
+            *out_keys = histogram.keys();
+        }
+        if !out_scanned.is_null() {
+            *out_scanned = histogram.rules_scanned();
         }
-        Err(_) => std::ptr::null_mut(),
     }
-}
 
-/// Free a loaded KM2 file
-#[no_mangle]
-pub extern "C" fn keymagic_km2_free(handle: *mut Km2FileHandle) {
-    if !handle.is_null() {
-        unsafe {
-            let _ = Box::from_raw(handle);
-        }
+    let top = histogram.top(max_entries.max(0) as usize);
+    for (i, &(rule, hits)) in top.iter().enumerate() {
+        unsafe { *out_entries.add(i) = RuleHistogramEntry { rule: rule as c_int, hits } };
     }
+    top.len() as c_int
 }
 
-/// Get keyboard name from KM2 file
-/// Returns a newly allocated C string that must be freed with keymagic_free_string
-/// Returns NULL if no name is defined
+/// Gets the texts of the suggestions attached to the last key, best first
+///
+/// Returns an array of `*out_count` UTF-8 strings, or NULL with a count of 0
+/// when there are none, e.g. without a suggestion provider. Free it with
+/// `keymagic_free_string_array`.
 #[no_mangle]
-pub extern "C" fn keymagic_km2_get_name(handle: *mut Km2FileHandle) -> *mut c_char {
+pub extern "C" fn keymagic_engine_get_suggestions(handle: *mut EngineHandle, out_count: *mut c_int) -> *mut *mut c_char {
+    if out_count.is_null() {
+        return ptr::null_mut();
+    }
+    unsafe { *out_count = 0 };
     if handle.is_null() {
-        return std::ptr::null_mut();
+        return ptr::null_mut();
+    }
+
+    let handle = unsafe { &*handle };
+    let Some(engine) = handle.engine() else {
+        return ptr::null_mut();
+    };
+    let suggestions = engine.suggestions();
+    if suggestions.is_empty() {
+        return ptr::null_mut();
     }
+    // Texts with a NUL are passed as empty strings so indices still match
+    let strings: Box<[*mut c_char]> = suggestions
+        .into_iter()
+        .map(|suggestion| CString::new(suggestion.text).unwrap_or_default().into_raw())
+        .collect();
+    unsafe { *out_count = strings.len() as c_int };
+    Box::into_raw(strings) as *mut *mut c_char
+}
 
+/// Frees an array returned by `keymagic_engine_get_suggestions`
+#[no_mangle]
+pub extern "C" fn keymagic_free_string_array(strings: *mut *mut c_char, count: c_int) {
+    if strings.is_null() || count <= 0 {
+        return;
+    }
     unsafe {
-        let km2 = &(*handle).0;
-        let metadata = km2.metadata();
-        
-        match metadata.name() {
-            Some(name) => match CString::new(name) {
-                Ok(c_string) => c_string.into_raw(),
-                Err(_) => std::ptr::null_mut(),
-            },
-            None => std::ptr::null_mut(),
+        let strings = Box::from_raw(ptr::slice_from_raw_parts_mut(strings, count as usize));
+        for &string in strings.iter() {
+            keymagic_free_string(string);
         }
     }
 }
 
-/// Get keyboard description from KM2 file
-/// Returns a newly allocated C string that must be freed with keymagic_free_string
-/// Returns NULL if no description is defined
+/// Replaces the end of the composing text with the suggestion at `index` of
+/// `keymagic_engine_get_suggestions`
+///
+/// Fills `output` as processing a key does. Returns ErrorInvalidParameter if
+/// there is no suggestion at `index`.
 #[no_mangle]
-pub extern "C" fn keymagic_km2_get_description(handle: *mut Km2FileHandle) -> *mut c_char {
-    if handle.is_null() {
-        return std::ptr::null_mut();
+pub extern "C" fn keymagic_engine_apply_suggestion(
+    handle: *mut EngineHandle,
+    index: c_int,
+    output: *mut ProcessKeyOutput,
+) -> KeyMagicResult {
+    if handle.is_null() || output.is_null() || index < 0 {
+        return KeyMagicResult::ErrorInvalidParameter;
     }
 
-    unsafe {
-        let km2 = &(*handle).0;
-        let metadata = km2.metadata();
-        
-        match metadata.description() {
-            Some(desc) => match CString::new(desc) {
-                Ok(c_string) => c_string.into_raw(),
-                Err(_) => std::ptr::null_mut(),
-            },
-            None => std::ptr::null_mut(),
+    let handle = unsafe { &*handle };
+    let output = unsafe { &mut *output };
+    clear_output(output);
+    let Some(engine) = handle.engine() else {
+        return KeyMagicResult::ErrorNoKeyboard;
+    };
+    match engine.apply_suggestion(index as usize) {
+        Ok(result) => {
+            write_output(handle, result, output);
+            KeyMagicResult::Success
         }
+        Err(_) => KeyMagicResult::ErrorInvalidParameter,
     }
 }
 
-/// Get hotkey from KM2 file
-/// Returns a newly allocated C string that must be freed with keymagic_free_string
-/// Returns NULL if no hotkey is defined
+/// Suggests words committed earlier with the same engine (`enabled` != 0),
+/// using the built-in provider, or stops suggesting
+#[cfg(feature = "recent-suggestions")]
 #[no_mangle]
-pub extern "C" fn keymagic_km2_get_hotkey(handle: *mut Km2FileHandle) -> *mut c_char {
+pub extern "C" fn keymagic_engine_set_recent_suggestions(handle: *mut EngineHandle, enabled: c_int) -> KeyMagicResult {
     if handle.is_null() {
-        return std::ptr::null_mut();
+        return KeyMagicResult::ErrorInvalidHandle;
     }
 
-    unsafe {
-        let km2 = &(*handle).0;
-        let metadata = km2.metadata();
-        
-        match metadata.hotkey() {
-            Some(hotkey) => match CString::new(hotkey) {
-                Ok(c_string) => c_string.into_raw(),
-                Err(_) => std::ptr::null_mut(),
-            },
-            None => std::ptr::null_mut(),
+    let handle = unsafe { &*handle };
+    match handle.engine() {
+        Some(engine) => {
+            let provider: Option<std::sync::Arc<dyn crate::SuggestionProvider>> =
+                (enabled != 0).then(|| std::sync::Arc::new(crate::RecentCommitsProvider::default()) as _);
+            engine.set_suggestion_provider(provider);
+            KeyMagicResult::Success
         }
+        None => KeyMagicResult::ErrorNoKeyboard,
     }
 }
 
-/// Parsed hotkey information for FFI
-#[repr(C)]
-pub struct HotkeyInfo {
-    pub key_code: c_int,       // VirtualKey as int
-    pub ctrl: c_int,           // 0 or 1
-    pub alt: c_int,            // 0 or 1
-    pub shift: c_int,          // 0 or 1
-    pub meta: c_int,           // 0 or 1
-}
+/// Passthrough key checks, see `keymagic_engine_check_passthrough`
+pub const KEYMAGIC_PASSTHROUGH_NONE: c_int = 0;
+pub const KEYMAGIC_PASSTHROUGH: c_int = 1;
+pub const KEYMAGIC_PASSTHROUGH_COMMIT: c_int = 2;
 
-/// Parse a hotkey string and return hotkey info
-/// Returns 1 on success, 0 on failure
+/// Sets keys (Windows VK codes) that always pass through unprocessed
+///
+/// With `commit_composing` set, a passthrough key first commits the
+/// composing text. Codes KeyMagic does not know are ignored. Like rule group
+/// toggles, the keys belong to the loaded keyboard.
 #[no_mangle]
-pub extern "C" fn keymagic_parse_hotkey(hotkey_str: *const c_char, info: *mut HotkeyInfo) -> c_int {
-    if hotkey_str.is_null() || info.is_null() {
-        return 0;
+pub extern "C" fn keymagic_engine_set_passthrough_keys(
+    handle: *mut EngineHandle,
+    vk_codes: *const c_int,
+    count: c_int,
+    commit_composing: c_int,
+) -> KeyMagicResult {
+    if handle.is_null() || (vk_codes.is_null() && count > 0) {
+        return KeyMagicResult::ErrorInvalidParameter;
     }
 
-    let hotkey_string = unsafe {
-        match CStr::from_ptr(hotkey_str).to_str() {
-            Ok(s) => s,
-            Err(_) => return 0,
-        }
-    };
+    let codes = if count > 0 { unsafe { std::slice::from_raw_parts(vk_codes, count as usize) } } else { &[] };
+    let keys: Vec<VirtualKey> = codes
+        .iter()
+        .filter_map(|&code| u16::try_from(code).ok().and_then(VirtualKey::from_win_vk))
+        .collect();
 
-    match crate::hotkey::HotkeyBinding::parse(hotkey_string) {
-        Ok(binding) => {
-            unsafe {
-                (*info).key_code = binding.key as c_int;
-                (*info).ctrl = if binding.ctrl { 1 } else { 0 };
-                (*info).alt = if binding.alt { 1 } else { 0 };
-                (*info).shift = if binding.shift { 1 } else { 0 };
-                (*info).meta = if binding.meta { 1 } else { 0 };
-            }
-            1
+    let handle = unsafe { &*handle };
+    match handle.engine() {
+        Some(engine) => {
+            engine.set_passthrough_keys(&keys, commit_composing != 0);
+            KeyMagicResult::Success
         }
-        Err(_) => 0,
+        None => KeyMagicResult::ErrorNoKeyboard,
     }
 }
 
-/// Get icon data from KM2 file
-/// If buffer is NULL, returns the required buffer size
-/// If buffer is not NULL, copies icon data to buffer and returns actual size copied
-/// Returns 0 if no icon is defined or on error
+/// Whether a key (Windows VK code) passes through, for hosts that filter
+/// keys before processing them
+///
+/// Returns KEYMAGIC_PASSTHROUGH_NONE for keys the keyboard handles,
+/// KEYMAGIC_PASSTHROUGH for keys that go to the application untouched, and
+/// KEYMAGIC_PASSTHROUGH_COMMIT when the key must be processed after all
+/// because it commits the current composing text first.
 #[no_mangle]
-pub extern "C" fn keymagic_km2_get_icon_data(
-    handle: *mut Km2FileHandle, 
-    buffer: *mut u8,
-    buffer_size: usize
-) -> usize {
+pub extern "C" fn keymagic_engine_check_passthrough(handle: *mut EngineHandle, vk_code: c_int) -> c_int {
     if handle.is_null() {
-        return 0;
+        return KEYMAGIC_PASSTHROUGH_NONE;
     }
+    let Some(key) = u16::try_from(vk_code).ok().and_then(VirtualKey::from_win_vk) else {
+        return KEYMAGIC_PASSTHROUGH_NONE;
+    };
 
-    unsafe {
-        let km2 = &(*handle).0;
-        let metadata = km2.metadata();
-        
-        match metadata.icon() {
+    let handle = unsafe { &*handle };
+    let Some(engine) = handle.engine() else {
+        return KEYMAGIC_PASSTHROUGH_NONE;
+    };
+    let engine = engine.read();
+    if engine.passes_as_shortcut(key as u16) {
+        KEYMAGIC_PASSTHROUGH
+    } else if !engine.is_passthrough_key(key as u16) {
+        KEYMAGIC_PASSTHROUGH_NONE
+    } else if engine.commit_on_passthrough() && !engine.composing_text().is_empty() {
+        KEYMAGIC_PASSTHROUGH_COMMIT
+    } else {
+        KEYMAGIC_PASSTHROUGH
+    }
+}
+
+/// Prefix checks, see `keymagic_engine_is_prefix`
+pub const KEYMAGIC_PREFIX_NO: c_int = 0;
+pub const KEYMAGIC_PREFIX_COMPLETE_MATCH_ONLY: c_int = 1;
+pub const KEYMAGIC_PREFIX_OF_LONGER: c_int = 2;
+
+/// Whether the composition must go on after a key, without processing it
+///
+/// Returns KEYMAGIC_PREFIX_OF_LONGER when a later key may match a rule using
+/// what the key leaves, KEYMAGIC_PREFIX_COMPLETE_MATCH_ONLY when a rule
+/// matches the key and nothing is left to build on, and KEYMAGIC_PREFIX_NO
+/// when no rule matches either. Hosts may commit at once unless the key is a
+/// prefix, so an invalid handle or missing keyboard reports one.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_is_prefix(
+    handle: *mut EngineHandle,
+    key_code: c_int,
+    character: c_char,
+    shift: c_int,
+    ctrl: c_int,
+    alt: c_int,
+    caps_lock: c_int,
+) -> c_int {
+    if handle.is_null() {
+        return KEYMAGIC_PREFIX_OF_LONGER;
+    }
+
+    let handle = unsafe { &*handle };
+    let Some(engine) = handle.engine() else {
+        return KEYMAGIC_PREFIX_OF_LONGER;
+    };
+
+    let key_input = KeyInput {
+        key_code: key_code as u16,
+        modifiers: ModifierState {
+            shift: shift != 0,
+            ctrl: ctrl != 0,
+            alt: alt != 0,
+            caps_lock: caps_lock != 0,
+        },
+        character: if character == 0 { None } else { Some(character as u8 as char) },
+    };
+
+    let result = engine.read().is_prefix(&key_input);
+    match result {
+        PrefixResult::No => KEYMAGIC_PREFIX_NO,
+        PrefixResult::CompleteMatchOnly => KEYMAGIC_PREFIX_COMPLETE_MATCH_ONLY,
+        PrefixResult::PrefixOfLonger => KEYMAGIC_PREFIX_OF_LONGER,
+    }
+}
+
+/// `keymagic_engine_is_prefix` with a Windows VK code
+#[no_mangle]
+pub extern "C" fn keymagic_engine_is_prefix_win(
+    handle: *mut EngineHandle,
+    vk_code: c_int,
+    character: c_char,
+    shift: c_int,
+    ctrl: c_int,
+    alt: c_int,
+    caps_lock: c_int,
+) -> c_int {
+    match VirtualKey::from_win_vk(vk_code as u16) {
+        Some(vk) => keymagic_engine_is_prefix(handle, vk as c_int, character, shift, ctrl, alt, caps_lock),
+        None => KEYMAGIC_PREFIX_OF_LONGER,
+    }
+}
+
+/// Explains what the engine would do with a key, and why, as JSON
+///
+/// `{"outcome":{"kind":"typed"},"rules_for_key":1,"candidates":[{"rule":3,
+/// "text":"\"ka\" => \"X\"","for_key":true,"element":0,"failure":{"kind":
+/// "context_mismatch","expected":"\"ka\"","found":"ga"}}]}`, the same shape
+/// as the serialized `Explanation`. Nothing is changed. Returns null for an
+/// invalid handle or when no keyboard is loaded; the returned string must be
+/// freed with `keymagic_free_string`.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_explain_key(
+    handle: *mut EngineHandle,
+    key_code: c_int,
+    character: c_char,
+    shift: c_int,
+    ctrl: c_int,
+    alt: c_int,
+    caps_lock: c_int,
+) -> *mut c_char {
+    if handle.is_null() {
+        return ptr::null_mut();
+    }
+
+    let handle = unsafe { &*handle };
+    let Some(engine) = handle.engine() else {
+        return ptr::null_mut();
+    };
+
+    let key_input = KeyInput {
+        key_code: key_code as u16,
+        modifiers: ModifierState {
+            shift: shift != 0,
+            ctrl: ctrl != 0,
+            alt: alt != 0,
+            caps_lock: caps_lock != 0,
+        },
+        character: if character == 0 { None } else { Some(character as u8 as char) },
+    };
+
+    let explanation = engine.read().explain_key(&key_input);
+    let candidates: Vec<String> = explanation
+        .candidates
+        .iter()
+        .map(|candidate| {
+            format!(
+                "{{\"rule\":{},\"text\":{},\"for_key\":{},\"element\":{},\"failure\":{}}}",
+                candidate.rule,
+                json_string(&candidate.text),
+                candidate.for_key,
+                candidate.element,
+                rule_failure_json(&candidate.failure)
+            )
+        })
+        .collect();
+    let json = format!(
+        "{{\"outcome\":{},\"rules_for_key\":{},\"candidates\":[{}]}}",
+        key_outcome_json(&explanation.outcome),
+        explanation.rules_for_key,
+        candidates.join(",")
+    );
+
+    CString::new(json).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// `keymagic_engine_explain_key` with a Windows VK code
+#[no_mangle]
+pub extern "C" fn keymagic_engine_explain_key_win(
+    handle: *mut EngineHandle,
+    vk_code: c_int,
+    character: c_char,
+    shift: c_int,
+    ctrl: c_int,
+    alt: c_int,
+    caps_lock: c_int,
+) -> *mut c_char {
+    match VirtualKey::from_win_vk(vk_code as u16) {
+        Some(vk) => keymagic_engine_explain_key(handle, vk as c_int, character, shift, ctrl, alt, caps_lock),
+        None => ptr::null_mut(),
+    }
+}
+
+fn key_outcome_json(outcome: &KeyOutcome) -> String {
+    let kind = match outcome {
+        KeyOutcome::RuleMatched { rule, text } => {
+            return format!("{{\"kind\":\"rule_matched\",\"rule\":{},\"text\":{}}}", rule, json_string(text));
+        }
+        KeyOutcome::PassedThrough => "passed_through",
+        KeyOutcome::HostShortcut => "host_shortcut",
+        KeyOutcome::Typed => "typed",
+        KeyOutcome::Eaten => "eaten",
+        KeyOutcome::Backspace => "backspace",
+        KeyOutcome::Unprocessed => "unprocessed",
+    };
+    format!("{{\"kind\":\"{}\"}}", kind)
+}
+
+fn rule_failure_json(failure: &RuleFailure) -> String {
+    match failure {
+        RuleFailure::GroupDisabled { group } => format!("{{\"kind\":\"group_disabled\",\"group\":{}}}", json_string(group)),
+        RuleFailure::Unmatchable => "{\"kind\":\"unmatchable\"}".to_string(),
+        RuleFailure::NoCharacter => "{\"kind\":\"no_character\"}".to_string(),
+        RuleFailure::ContextTooShort { needed, available } => {
+            format!("{{\"kind\":\"context_too_short\",\"needed\":{},\"available\":{}}}", needed, available)
+        }
+        RuleFailure::StateInactive { state } => format!("{{\"kind\":\"state_inactive\",\"state\":{}}}", json_string(state)),
+        RuleFailure::KeyMismatch { expected } => format!("{{\"kind\":\"key_mismatch\",\"expected\":{}}}", json_string(expected)),
+        RuleFailure::ModifierMismatch { expected, pressed } => format!(
+            "{{\"kind\":\"modifier_mismatch\",\"expected\":{},\"pressed\":{}}}",
+            json_string(expected),
+            json_string(pressed)
+        ),
+        RuleFailure::ContextMismatch { expected, found } => format!(
+            "{{\"kind\":\"context_mismatch\",\"expected\":{},\"found\":{}}}",
+            json_string(expected),
+            json_string(found)
+        ),
+    }
+}
+
+/// Windows VK code of a KMS key name such as "VK_ESCAPE", or 0 if unknown
+#[no_mangle]
+pub extern "C" fn keymagic_vk_from_name(name: *const c_char) -> c_int {
+    if name.is_null() {
+        return 0;
+    }
+    let Ok(name) = unsafe { CStr::from_ptr(name) }.to_str() else {
+        return 0;
+    };
+    match crate::parse_vk_names(&[name]) {
+        Ok(keys) => keys[0].to_win_vk() as c_int,
+        Err(_) => 0,
+    }
+}
+
+/// Get library version
+#[no_mangle]
+pub extern "C" fn keymagic_get_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Process a key event with Windows VK code
+#[no_mangle]
+pub extern "C" fn keymagic_engine_process_key_win(
+    handle: *mut EngineHandle,
+    vk_code: c_int,        // Windows VK code (e.g., 0x41 for VK_A)
+    character: c_char,
+    shift: c_int,
+    ctrl: c_int,
+    alt: c_int,
+    caps_lock: c_int,
+    output: *mut ProcessKeyOutput,
+) -> KeyMagicResult {
+    // Convert Windows VK code to VirtualKey, return error if unsupported
+    let virtual_key = match VirtualKey::from_win_vk(vk_code as u16) {
+        Some(vk) => vk,
+        None => return KeyMagicResult::ErrorInvalidParameter,
+    };
+    
+    // Forward the call to the regular process_key function
+    keymagic_engine_process_key(
+        handle,
+        virtual_key as i32,
+        character,
+        shift,
+        ctrl,
+        alt,
+        caps_lock,
+        output,
+    )
+}
+
+/// Process a key event in test mode (does not modify engine state)
+#[no_mangle]
+pub extern "C" fn keymagic_engine_process_key_test(
+    handle: *mut EngineHandle,
+    key_code: c_int,
+    character: c_char,
+    shift: c_int,
+    ctrl: c_int,
+    alt: c_int,
+    caps_lock: c_int,
+    output: *mut ProcessKeyOutput,
+) -> KeyMagicResult {
+    if handle.is_null() || output.is_null() {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+
+    let handle = unsafe { &*handle };
+    let output_ref = unsafe { &mut *output };
+
+    // Convert character from c_char to Option<char>
+    let char_opt = if character == 0 {
+        None
+    } else {
+        Some(character as u8 as char)
+    };
+
+    let key_input = KeyInput {
+        key_code: key_code as u16,
+        modifiers: ModifierState {
+            shift: shift != 0,
+            ctrl: ctrl != 0,
+            alt: alt != 0,
+            caps_lock: caps_lock != 0,
+        },
+        character: char_opt,
+    };
+
+    process_key_internal(handle, key_input, true, output_ref)
+}
+
+/// Process a key event in test mode with Windows VK code (does not modify engine state)
+#[no_mangle]
+pub extern "C" fn keymagic_engine_process_key_test_win(
+    handle: *mut EngineHandle,
+    vk_code: c_int,        // Windows VK code (e.g., 0x41 for VK_A)
+    character: c_char,
+    shift: c_int,
+    ctrl: c_int,
+    alt: c_int,
+    caps_lock: c_int,
+    output: *mut ProcessKeyOutput,
+) -> KeyMagicResult {
+    // Convert Windows VK code to VirtualKey, return error if unsupported
+    let virtual_key = match VirtualKey::from_win_vk(vk_code as u16) {
+        Some(vk) => vk,
+        None => return KeyMagicResult::ErrorInvalidParameter,
+    };
+    
+    // Forward the call to the regular test function
+    keymagic_engine_process_key_test(
+        handle,
+        virtual_key as i32,
+        character,
+        shift,
+        ctrl,
+        alt,
+        caps_lock,
+        output,
+    )
+}
+
+/// Get the current composing text from the engine
+/// Returns a newly allocated C string that must be freed with keymagic_engine_free_string
+#[no_mangle]
+pub extern "C" fn keymagic_engine_get_composing_text(engine: *mut KeyMagicEngine) -> *mut c_char {
+    if engine.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    unsafe {
+        let engine_ref = &*engine;
+        match CString::new(engine_ref.composing_text()) {
+            Ok(c_string) => c_string.into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// Free a string allocated by the engine
+#[no_mangle]
+pub extern "C" fn keymagic_engine_free_string(str: *mut c_char) {
+    if !str.is_null() {
+        unsafe {
+            let _ = CString::from_raw(str);
+        }
+    }
+}
+
+/// Opaque handle for loaded KM2 file
+pub struct Km2FileHandle(Km2File);
+
+/// Load a KM2 file from path
+/// Returns NULL on failure
+#[no_mangle]
+pub extern "C" fn keymagic_km2_load(path: *const c_char) -> *mut Km2FileHandle {
+    if path.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let path_str = unsafe {
+        match CStr::from_ptr(path).to_str() {
+            Ok(s) => s,
+            Err(_) => return std::ptr::null_mut(),
+        }
+    };
+
+    match std::fs::read(paths::to_extended_length(Path::new(path_str))) {
+        Ok(data) => {
+            match crate::km2::Km2Loader::load(&data) {
+                Ok(km2) => Box::into_raw(Box::new(Km2FileHandle(km2))),
+                Err(_) => std::ptr::null_mut(),
+            }
+        }
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Load a KM2 file from a memory buffer
+/// Returns NULL on failure
+#[no_mangle]
+pub extern "C" fn keymagic_km2_load_from_memory(km2_data: *const u8, data_len: usize) -> *mut Km2FileHandle {
+    if km2_data.is_null() || data_len == 0 {
+        return std::ptr::null_mut();
+    }
+
+    let data = unsafe { std::slice::from_raw_parts(km2_data, data_len) };
+    match crate::km2::Km2Loader::load(data) {
+        Ok(km2) => Box::into_raw(Box::new(Km2FileHandle(km2))),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Loads an already loaded KM2 file into an engine
+///
+/// The engine gets its own copy, so the KM2 handle may be freed or loaded
+/// into other engines afterwards.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_load_km2(
+    handle: *mut EngineHandle,
+    km2: *const Km2FileHandle,
+) -> KeyMagicResult {
+    if handle.is_null() || km2.is_null() {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+
+    let handle = unsafe { &*handle };
+    let km2 = unsafe { &(*km2).0 };
+    match KeyMagicEngine::new(km2.clone()) {
+        // Already parsed, so there are no file contents to hash
+        Ok(engine) => handle.set_engine(engine, MEMORY_KEYBOARD.to_string(), None),
+        Err(e) => load_failed(MEMORY_KEYBOARD, e),
+    }
+}
+
+/// Format version, table sizes and layout options of a KM2 file
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Km2Info {
+    pub major_version: c_int,
+    pub minor_version: c_int,
+    pub string_count: c_int,
+    pub info_count: c_int,
+    pub rule_count: c_int,
+    pub track_caps: c_int,
+    pub auto_bksp: c_int,
+    pub eat: c_int,
+    pub pos_based: c_int,
+    pub right_alt: c_int,
+}
+
+/// Fills `info` with the header information of a loaded KM2 file
+#[no_mangle]
+pub extern "C" fn keymagic_km2_get_info(handle: *mut Km2FileHandle, info: *mut Km2Info) -> KeyMagicResult {
+    if handle.is_null() || info.is_null() {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+
+    let km2 = unsafe { &(*handle).0 };
+    let header = km2.header;
+    let options = header.layout_options;
+    unsafe {
+        *info = Km2Info {
+            major_version: header.major_version as c_int,
+            minor_version: header.minor_version as c_int,
+            string_count: km2.strings.len() as c_int,
+            info_count: km2.info.len() as c_int,
+            rule_count: km2.rules.len() as c_int,
+            track_caps: options.track_caps as c_int,
+            auto_bksp: options.auto_bksp as c_int,
+            eat: options.eat as c_int,
+            pos_based: options.pos_based as c_int,
+            right_alt: options.right_alt as c_int,
+        };
+    }
+    KeyMagicResult::Success
+}
+
+/// Gets a layout option of a KM2 file by name, as accepted by
+/// `keymagic_engine_set_layout_option`: 1 if on, 0 if off, -1 for an unknown
+/// name or a NULL argument
+///
+/// Options added after `Km2Info` was fixed are only available here, so the
+/// struct callers allocate never grows.
+#[no_mangle]
+pub extern "C" fn keymagic_km2_get_layout_option(handle: *mut Km2FileHandle, name: *const c_char) -> c_int {
+    if handle.is_null() || name.is_null() {
+        return -1;
+    }
+    let Ok(name) = unsafe { CStr::from_ptr(name) }.to_str() else {
+        return -1;
+    };
+    let km2 = unsafe { &(*handle).0 };
+    km2.header.layout_options.get(name).map_or(-1, |value| value as c_int)
+}
+
+/// Free a loaded KM2 file
+#[no_mangle]
+pub extern "C" fn keymagic_km2_free(handle: *mut Km2FileHandle) {
+    if !handle.is_null() {
+        unsafe {
+            let _ = Box::from_raw(handle);
+        }
+    }
+}
+
+/// Get keyboard name from KM2 file
+/// Returns a newly allocated C string that must be freed with keymagic_free_string
+/// Returns NULL if no name is defined
+#[no_mangle]
+pub extern "C" fn keymagic_km2_get_name(handle: *mut Km2FileHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    unsafe {
+        let km2 = &(*handle).0;
+        let metadata = km2.metadata();
+        
+        match metadata.name() {
+            Some(name) => match CString::new(name) {
+                Ok(c_string) => c_string.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            },
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// Get keyboard description from KM2 file
+/// Returns a newly allocated C string that must be freed with keymagic_free_string
+/// Returns NULL if no description is defined
+#[no_mangle]
+pub extern "C" fn keymagic_km2_get_description(handle: *mut Km2FileHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    unsafe {
+        let km2 = &(*handle).0;
+        let metadata = km2.metadata();
+        
+        match metadata.description() {
+            Some(desc) => match CString::new(desc) {
+                Ok(c_string) => c_string.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            },
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// Get the font family the keyboard's output is meant for
+/// Returns a newly allocated C string that must be freed with keymagic_free_string
+/// Returns NULL if no font is declared
+#[no_mangle]
+pub extern "C" fn keymagic_km2_get_font_family(handle: *mut Km2FileHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    unsafe {
+        let km2 = &(*handle).0;
+        match km2.metadata().font_family() {
+            Some(family) => match CString::new(family) {
+                Ok(c_string) => c_string.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            },
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// Get hotkey from KM2 file
+/// Returns a newly allocated C string that must be freed with keymagic_free_string
+/// Returns NULL if no hotkey is defined
+#[no_mangle]
+pub extern "C" fn keymagic_km2_get_hotkey(handle: *mut Km2FileHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    unsafe {
+        let km2 = &(*handle).0;
+        let metadata = km2.metadata();
+        
+        match metadata.hotkey() {
+            Some(hotkey) => match CString::new(hotkey) {
+                Ok(c_string) => c_string.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            },
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// Get the language tags the keyboard declares, separated by commas
+/// Returns a newly allocated C string that must be freed with keymagic_free_string
+/// Returns NULL if no locale is declared
+#[no_mangle]
+pub extern "C" fn keymagic_km2_get_locales(handle: *mut Km2FileHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    unsafe {
+        let km2 = &(*handle).0;
+        let locales = km2.metadata().locales();
+        if locales.is_empty() {
+            return std::ptr::null_mut();
+        }
+        
+        let separator = crate::locale::LOCALE_SEPARATOR.to_string();
+        match CString::new(locales.join(&separator)) {
+            Ok(c_string) => c_string.into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// Get the preview text declared with `@SAMPLE`
+/// Returns a newly allocated C string that must be freed with keymagic_free_string
+/// Returns NULL if no sample is declared
+#[no_mangle]
+pub extern "C" fn keymagic_km2_get_sample_text(handle: *mut Km2FileHandle) -> *mut c_char {
+    if handle.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    unsafe {
+        let km2 = &(*handle).0;
+        match km2.metadata().sample_text() {
+            Some(sample) => match CString::new(sample) {
+                Ok(c_string) => c_string.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            },
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// Parsed hotkey information for FFI
+#[repr(C)]
+pub struct HotkeyInfo {
+    pub key_code: c_int,       // VirtualKey as int
+    pub ctrl: c_int,           // 0 or 1
+    pub alt: c_int,            // 0 or 1
+    pub shift: c_int,          // 0 or 1
+    pub meta: c_int,           // 0 or 1
+}
+
+/// Parse a hotkey string and return hotkey info
+/// Returns 1 on success, 0 on failure. Double-tap hotkeys are not key
+/// combinations and return 0; see `keymagic_parse_double_tap`.
+#[no_mangle]
+pub extern "C" fn keymagic_parse_hotkey(hotkey_str: *const c_char, info: *mut HotkeyInfo) -> c_int {
+    if hotkey_str.is_null() || info.is_null() {
+        return 0;
+    }
+
+    let hotkey_string = unsafe {
+        match CStr::from_ptr(hotkey_str).to_str() {
+            Ok(s) => s,
+            Err(_) => return 0,
+        }
+    };
+
+    match crate::hotkey::HotkeyBinding::parse(hotkey_string) {
+        Ok(crate::hotkey::HotkeyBinding::Combo(binding)) => {
+            unsafe {
+                (*info).key_code = binding.key as c_int;
+                (*info).ctrl = if binding.ctrl { 1 } else { 0 };
+                (*info).alt = if binding.alt { 1 } else { 0 };
+                (*info).shift = if binding.shift { 1 } else { 0 };
+                (*info).meta = if binding.meta { 1 } else { 0 };
+            }
+            1
+        }
+        _ => 0,
+    }
+}
+
+/// Parse a double-tap hotkey such as "Shift Shift" or "double:LShift"
+/// Returns 1 and the Windows VK code of the modifier (VK_SHIFT, VK_LSHIFT,
+/// ...) on success, 0 if the string is not a double-tap hotkey
+#[no_mangle]
+pub extern "C" fn keymagic_parse_double_tap(hotkey_str: *const c_char, vk_code: *mut c_int) -> c_int {
+    if hotkey_str.is_null() || vk_code.is_null() {
+        return 0;
+    }
+
+    let Ok(hotkey_string) = (unsafe { CStr::from_ptr(hotkey_str) }).to_str() else {
+        return 0;
+    };
+    match crate::hotkey::HotkeyBinding::parse(hotkey_string) {
+        Ok(crate::hotkey::HotkeyBinding::DoubleTap(key)) => {
+            unsafe { *vk_code = key.to_win_vk() as c_int };
+            1
+        }
+        _ => 0,
+    }
+}
+
+/// Opaque handle to a double-tap detector, fed with the key events of a
+/// host process
+pub struct DoubleTapHandle(Mutex<DoubleTapDetector>);
+
+/// Creates a double-tap detector; `window_ms` 0 uses the default of 300ms
+#[no_mangle]
+pub extern "C" fn keymagic_double_tap_new(window_ms: c_uint) -> *mut DoubleTapHandle {
+    let window_ms = match window_ms {
+        0 => crate::hotkey::DEFAULT_DOUBLE_TAP_WINDOW_MS,
+        ms => ms as u64,
+    };
+    Box::into_raw(Box::new(DoubleTapHandle(Mutex::new(DoubleTapDetector::new(window_ms)))))
+}
+
+/// Frees a double-tap detector
+#[no_mangle]
+pub extern "C" fn keymagic_double_tap_free(handle: *mut DoubleTapHandle) {
+    if !handle.is_null() {
+        unsafe {
+            let _ = Box::from_raw(handle);
+        }
+    }
+}
+
+/// Sets the modifiers to report, as Windows VK codes; unknown codes are skipped
+#[no_mangle]
+pub extern "C" fn keymagic_double_tap_set_keys(handle: *mut DoubleTapHandle, vk_codes: *const c_int, count: usize) {
+    if handle.is_null() || (vk_codes.is_null() && count > 0) {
+        return;
+    }
+
+    let codes = if count == 0 { &[][..] } else { unsafe { std::slice::from_raw_parts(vk_codes, count) } };
+    let keys = codes.iter().filter_map(|code| VirtualKey::from_win_vk(*code as u16)).collect();
+    unsafe { &*handle }.0.lock().set_keys(keys);
+}
+
+/// Feeds a key press; pass side-specific modifier codes (VK_LSHIFT, ...)
+/// where known. Returns the VK code given to `keymagic_double_tap_set_keys`
+/// when this press completes a double-tap, 0 otherwise.
+#[no_mangle]
+pub extern "C" fn keymagic_double_tap_key_down(handle: *mut DoubleTapHandle, vk_code: c_int, time_ms: u64) -> c_int {
+    if handle.is_null() {
+        return 0;
+    }
+
+    let mut detector = unsafe { &*handle }.0.lock();
+    match VirtualKey::from_win_vk(vk_code as u16) {
+        Some(key) => detector.key_down(key, time_ms).map_or(0, |key| key.to_win_vk() as c_int),
+        None => {
+            detector.interrupt();
+            0
+        }
+    }
+}
+
+/// Feeds a key release
+#[no_mangle]
+pub extern "C" fn keymagic_double_tap_key_up(handle: *mut DoubleTapHandle, vk_code: c_int, time_ms: u64) {
+    if handle.is_null() {
+        return;
+    }
+
+    if let Some(key) = VirtualKey::from_win_vk(vk_code as u16) {
+        unsafe { &*handle }.0.lock().key_up(key, time_ms);
+    }
+}
+
+/// Forgets held keys and a pending first tap, as after losing focus
+#[no_mangle]
+pub extern "C" fn keymagic_double_tap_reset(handle: *mut DoubleTapHandle) {
+    if !handle.is_null() {
+        unsafe { &*handle }.0.lock().reset();
+    }
+}
+
+/// Returns 1 if a key event with `extra_info` as its `dwExtraInfo` was
+/// injected by KeyMagic and must be skipped, 0 otherwise
+#[no_mangle]
+pub extern "C" fn keymagic_is_injected_input(extra_info: usize) -> c_int {
+    crate::hotkey::is_injected(extra_info) as c_int
+}
+
+/// Opaque handle to a keyboard switch loop breaker
+pub struct SwitchGuardHandle(Mutex<SwitchLoopBreaker>);
+
+/// Creates a loop breaker allowing `max_switches` switches within
+/// `window_ms` and suppressing switches for `cooldown_ms` after more; 0
+/// uses the default of each (5 switches, 1000ms, 2000ms)
+#[no_mangle]
+pub extern "C" fn keymagic_switch_guard_new(max_switches: c_uint, window_ms: c_uint, cooldown_ms: c_uint) -> *mut SwitchGuardHandle {
+    let or_default = |value: c_uint, default: u64| if value == 0 { default } else { value as u64 };
+    let breaker = SwitchLoopBreaker::new(
+        or_default(max_switches, crate::hotkey::SWITCH_LOOP_MAX_SWITCHES as u64) as usize,
+        or_default(window_ms, crate::hotkey::SWITCH_LOOP_WINDOW_MS),
+        or_default(cooldown_ms, crate::hotkey::SWITCH_LOOP_COOLDOWN_MS),
+    );
+    Box::into_raw(Box::new(SwitchGuardHandle(Mutex::new(breaker))))
+}
+
+/// Frees a loop breaker
+#[no_mangle]
+pub extern "C" fn keymagic_switch_guard_free(handle: *mut SwitchGuardHandle) {
+    if !handle.is_null() {
+        unsafe {
+            let _ = Box::from_raw(handle);
+        }
+    }
+}
+
+/// Asks whether to switch keyboards at `time_ms`. Returns 0 to switch, 1
+/// when this switch starts a cooldown (show the warning), 2 while one runs.
+#[no_mangle]
+pub extern "C" fn keymagic_switch_guard_check(handle: *mut SwitchGuardHandle, time_ms: u64) -> c_int {
+    if handle.is_null() {
+        return 0;
+    }
+
+    match unsafe { &*handle }.0.lock().check(time_ms) {
+        SwitchDecision::Allow => 0,
+        SwitchDecision::Trip => 1,
+        SwitchDecision::Suppress => 2,
+    }
+}
+
+/// Get icon data from KM2 file
+/// If buffer is NULL, returns the required buffer size
+/// If buffer is not NULL, copies icon data to buffer and returns actual size copied
+/// Returns 0 if no icon is defined or on error
+#[no_mangle]
+pub extern "C" fn keymagic_km2_get_icon_data(
+    handle: *mut Km2FileHandle, 
+    buffer: *mut u8,
+    buffer_size: usize
+) -> usize {
+    if handle.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let km2 = &(*handle).0;
+        let metadata = km2.metadata();
+        
+        match metadata.icon() {
             Some(icon_data) => {
                 let data_len = icon_data.len();
                 
@@ -655,133 +2091,1122 @@ pub extern "C" fn keymagic_km2_get_icon_data(
                     0
                 }
             }
-            None => 0,
+            None => 0,
+        }
+    }
+}
+
+/// Convert VirtualKey enum value to display string
+/// Returns a newly allocated C string that must be freed with keymagic_free_string
+/// Returns NULL if the key code is invalid
+#[no_mangle]
+pub extern "C" fn keymagic_virtual_key_to_string(key_code: c_int) -> *mut c_char {
+    // Try to convert the key_code to VirtualKey enum
+    // Since VirtualKey uses u16 internally, we need to check bounds
+    if key_code < 0 || key_code > u16::MAX as c_int {
+        return ptr::null_mut();
+    }
+    
+    // Find matching VirtualKey by iterating through all values
+    let virtual_key = match key_code as u16 {
+        2 => VirtualKey::Back,
+        3 => VirtualKey::Tab,
+        4 => VirtualKey::Return,
+        5 => VirtualKey::Shift,
+        6 => VirtualKey::Control,
+        7 => VirtualKey::Menu,
+        8 => VirtualKey::Pause,
+        9 => VirtualKey::Capital,
+        10 => VirtualKey::Kanji,
+        11 => VirtualKey::Escape,
+        12 => VirtualKey::Space,
+        13 => VirtualKey::Prior,
+        14 => VirtualKey::Next,
+        15 => VirtualKey::Delete,
+        16 => VirtualKey::Key0,
+        17 => VirtualKey::Key1,
+        18 => VirtualKey::Key2,
+        19 => VirtualKey::Key3,
+        20 => VirtualKey::Key4,
+        21 => VirtualKey::Key5,
+        22 => VirtualKey::Key6,
+        23 => VirtualKey::Key7,
+        24 => VirtualKey::Key8,
+        25 => VirtualKey::Key9,
+        26 => VirtualKey::KeyA,
+        27 => VirtualKey::KeyB,
+        28 => VirtualKey::KeyC,
+        29 => VirtualKey::KeyD,
+        30 => VirtualKey::KeyE,
+        31 => VirtualKey::KeyF,
+        32 => VirtualKey::KeyG,
+        33 => VirtualKey::KeyH,
+        34 => VirtualKey::KeyI,
+        35 => VirtualKey::KeyJ,
+        36 => VirtualKey::KeyK,
+        37 => VirtualKey::KeyL,
+        38 => VirtualKey::KeyM,
+        39 => VirtualKey::KeyN,
+        40 => VirtualKey::KeyO,
+        41 => VirtualKey::KeyP,
+        42 => VirtualKey::KeyQ,
+        43 => VirtualKey::KeyR,
+        44 => VirtualKey::KeyS,
+        45 => VirtualKey::KeyT,
+        46 => VirtualKey::KeyU,
+        47 => VirtualKey::KeyV,
+        48 => VirtualKey::KeyW,
+        49 => VirtualKey::KeyX,
+        50 => VirtualKey::KeyY,
+        51 => VirtualKey::KeyZ,
+        52 => VirtualKey::Numpad0,
+        53 => VirtualKey::Numpad1,
+        54 => VirtualKey::Numpad2,
+        55 => VirtualKey::Numpad3,
+        56 => VirtualKey::Numpad4,
+        57 => VirtualKey::Numpad5,
+        58 => VirtualKey::Numpad6,
+        59 => VirtualKey::Numpad7,
+        60 => VirtualKey::Numpad8,
+        61 => VirtualKey::Numpad9,
+        62 => VirtualKey::Multiply,
+        63 => VirtualKey::Add,
+        64 => VirtualKey::Separator,
+        65 => VirtualKey::Subtract,
+        66 => VirtualKey::Decimal,
+        67 => VirtualKey::Divide,
+        68 => VirtualKey::F1,
+        69 => VirtualKey::F2,
+        70 => VirtualKey::F3,
+        71 => VirtualKey::F4,
+        72 => VirtualKey::F5,
+        73 => VirtualKey::F6,
+        74 => VirtualKey::F7,
+        75 => VirtualKey::F8,
+        76 => VirtualKey::F9,
+        77 => VirtualKey::F10,
+        78 => VirtualKey::F11,
+        79 => VirtualKey::F12,
+        80 => VirtualKey::LShift,
+        81 => VirtualKey::RShift,
+        82 => VirtualKey::LControl,
+        83 => VirtualKey::RControl,
+        84 => VirtualKey::LMenu,
+        85 => VirtualKey::RMenu,
+        86 => VirtualKey::Oem1,
+        87 => VirtualKey::OemPlus,
+        88 => VirtualKey::OemComma,
+        89 => VirtualKey::OemMinus,
+        90 => VirtualKey::OemPeriod,
+        91 => VirtualKey::Oem2,
+        92 => VirtualKey::Oem3,
+        93 => VirtualKey::Oem4,
+        94 => VirtualKey::Oem5,
+        95 => VirtualKey::Oem6,
+        96 => VirtualKey::Oem7,
+        97 => VirtualKey::Oem8,
+        98 => VirtualKey::OemAx,
+        99 => VirtualKey::Oem102,
+        100 => VirtualKey::IcoHelp,
+        101 => VirtualKey::Ico00,
+        _ => return ptr::null_mut(),
+    };
+    
+    // Get display string from the enum method
+    let display_name = virtual_key.to_display_string();
+    
+    match CString::new(display_name) {
+        Ok(c_string) => c_string.into_raw(),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+
+/// Opaque handle to the input recorder of a host process
+///
+/// Attaches to the shared ring created by the GUI on first use and retries
+/// every few seconds while the GUI has not created it.
+pub struct RecorderHandle {
+    recorder: Mutex<Option<InputRecorder>>,
+    last_attempt: Mutex<Option<Instant>>,
+}
+
+const RECORDER_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+impl RecorderHandle {
+    fn attach(&self) {
+        let mut last_attempt = self.last_attempt.lock();
+        if last_attempt.is_some_and(|at| at.elapsed() < RECORDER_RETRY_INTERVAL) {
+            return;
+        }
+        *last_attempt = Some(Instant::now());
+        #[cfg(windows)]
+        if let Ok(recorder) = InputRecorder::open_shared() {
+            *self.recorder.lock() = Some(recorder);
+        }
+    }
+}
+
+/// Creates a recorder handle; recording is controlled by the GUI
+#[no_mangle]
+pub extern "C" fn keymagic_recorder_open() -> *mut RecorderHandle {
+    Box::into_raw(Box::new(RecorderHandle {
+        recorder: Mutex::new(None),
+        last_attempt: Mutex::new(None),
+    }))
+}
+
+/// Frees a recorder handle
+#[no_mangle]
+pub extern "C" fn keymagic_recorder_free(handle: *mut RecorderHandle) {
+    if !handle.is_null() {
+        unsafe {
+            let _ = Box::from_raw(handle);
+        }
+    }
+}
+
+/// Records a processed key if recording is on
+///
+/// `process_name` is a null-terminated UTF-16 string. Records store it as a
+/// hash; the table of processes of the recording keeps a display name
+/// derived from its stem. Returns the sequence number of the record, or 0 if nothing was
+/// recorded; log it to correlate engine traces with a recording.
+#[no_mangle]
+pub extern "C" fn keymagic_recorder_record(
+    handle: *mut RecorderHandle,
+    process_name: *const u16,
+    vk_code: c_int,
+    shift: c_int,
+    ctrl: c_int,
+    alt: c_int,
+    caps_lock: c_int,
+    output: *const ProcessKeyOutput,
+) -> u64 {
+    if handle.is_null() || output.is_null() {
+        return 0;
+    }
+
+    let handle = unsafe { &*handle };
+    if handle.recorder.lock().is_none() {
+        handle.attach();
+    }
+    let recorder = handle.recorder.lock();
+    let Some(recorder) = recorder.as_ref() else {
+        return 0;
+    };
+    if !recorder.is_recording() {
+        return 0;
+    }
+
+    let output = unsafe { &*output };
+    let process_hash = if process_name.is_null() {
+        0
+    } else {
+        let units = unsafe {
+            let mut len = 0;
+            while *process_name.add(len) != 0 {
+                len += 1;
+            }
+            std::slice::from_raw_parts(process_name, len)
+        };
+        recorder.note_process(&String::from_utf16_lossy(units), is_packaged_process())
+    };
+    let composing_text = if output.composing_text.is_null() {
+        ""
+    } else {
+        unsafe { CStr::from_ptr(output.composing_text) }.to_str().unwrap_or("")
+    };
+
+    let modifiers = [(shift, MOD_SHIFT), (ctrl, MOD_CTRL), (alt, MOD_ALT), (caps_lock, MOD_CAPS_LOCK)]
+        .iter()
+        .filter(|(pressed, _)| *pressed != 0)
+        .fold(0, |flags, (_, flag)| flags | flag);
+    let event = KeyEventRecord {
+        process_hash,
+        vk: vk_code as u16,
+        modifiers,
+        action_type: output.action_type as u8,
+        is_processed: output.is_processed != 0,
+        delete_count: output.delete_count.clamp(0, u16::MAX as c_int) as u16,
+        composing_text,
+    };
+    recorder.record(&event).unwrap_or(0)
+}
+
+/// Publishes the input mode of this process for the GUI
+///
+/// Attaches to the shared block on first use, retrying like the recorder.
+#[cfg(windows)]
+fn publish_input_mode(process: &str, resolution: InputModeResolution) {
+    static STATE: Mutex<Option<InputModeState>> = Mutex::new(None);
+    static LAST_ATTEMPT: Mutex<Option<Instant>> = Mutex::new(None);
+
+    let mut state = STATE.lock();
+    if state.is_none() {
+        let mut last_attempt = LAST_ATTEMPT.lock();
+        if last_attempt.is_some_and(|at| at.elapsed() < RECORDER_RETRY_INTERVAL) {
+            return;
+        }
+        *last_attempt = Some(Instant::now());
+        *state = InputModeState::create_shared().ok();
+    }
+    if let Some(state) = state.as_ref() {
+        state.publish_resolution(process, resolution);
+    }
+}
+
+#[cfg(not(windows))]
+fn publish_input_mode(_process: &str, _resolution: InputModeResolution) {}
+
+/// Reads a null-terminated UTF-16 string
+///
+/// # Safety
+/// `text` must be null or point to a null-terminated string.
+unsafe fn wide_to_string(text: *const u16) -> Option<String> {
+    if text.is_null() {
+        return None;
+    }
+    let mut len = 0;
+    while *text.add(len) != 0 {
+        len += 1;
+    }
+    Some(String::from_utf16_lossy(std::slice::from_raw_parts(text, len)))
+}
+
+/// Reads `count` null-terminated UTF-16 strings; null entries are skipped
+///
+/// # Safety
+/// `list` must be null or point to `count` entries valid for `wide_to_string`.
+unsafe fn wide_list(list: *const *const u16, count: c_int) -> Vec<String> {
+    if list.is_null() || count <= 0 {
+        return Vec::new();
+    }
+    std::slice::from_raw_parts(list, count as usize)
+        .iter()
+        .filter_map(|&entry| wide_to_string(entry))
+        .collect()
+}
+
+/// Input mode of a host, see `keymagic_core::input_mode`
+///
+/// `process_name` is a null-terminated UTF-16 string and the host lists are
+/// arrays of `count` such strings, with `*` and `?` wildcards. Returns 1 for
+/// composition mode and 0 for direct mode; hosts no entry matches get
+/// `default_composition`. On Windows the decision is also published for the
+/// GUI, which shows the mode of the foreground application.
+#[no_mangle]
+pub extern "C" fn keymagic_resolve_input_mode_w(
+    process_name: *const u16,
+    composition_hosts: *const *const u16,
+    composition_count: c_int,
+    direct_hosts: *const *const u16,
+    direct_count: c_int,
+    default_composition: c_int,
+) -> c_int {
+    let default = if default_composition != 0 { InputMode::Composition } else { InputMode::Direct };
+    let Some(process) = (unsafe { wide_to_string(process_name) }) else {
+        return default_composition;
+    };
+    let composition_hosts = unsafe { wide_list(composition_hosts, composition_count) };
+    let direct_hosts = unsafe { wide_list(direct_hosts, direct_count) };
+
+    let resolution = resolve_input_mode(&process, &composition_hosts, &direct_hosts, default);
+    let mode = resolution.mode;
+    publish_input_mode(&process, resolution);
+    (mode == InputMode::Composition) as c_int
+}
+
+/// Sets the shortcut keys of the host application, see
+/// `keymagic_core::input_mode::HostShortcuts`
+///
+/// `process_name` is a null-terminated UTF-16 string and `entries` an array
+/// of `count` such strings like `chrome.exe=VK_KEY_C,VK_KEY_J`. The keys of
+/// the most specific entry matching the process pass through unprocessed
+/// while nothing is composed, and `keymagic_engine_check_passthrough`
+/// reports them as KEYMAGIC_PASSTHROUGH. Malformed entries are skipped.
+/// Each keyboard load resets the keys.
+#[no_mangle]
+pub extern "C" fn keymagic_engine_set_shortcut_keys_w(
+    handle: *mut EngineHandle,
+    process_name: *const u16,
+    entries: *const *const u16,
+    count: c_int,
+) -> KeyMagicResult {
+    if handle.is_null() {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+    let Some(process) = (unsafe { wide_to_string(process_name) }) else {
+        return KeyMagicResult::ErrorInvalidParameter;
+    };
+    let entries: Vec<HostShortcuts> = unsafe { wide_list(entries, count) }
+        .iter()
+        .filter_map(|entry| HostShortcuts::parse(entry).ok())
+        .collect();
+
+    let handle = unsafe { &*handle };
+    match handle.engine() {
+        Some(engine) => {
+            engine.set_shortcut_keys(host_shortcut_keys(&process, &entries));
+            KeyMagicResult::Success
+        }
+        None => KeyMagicResult::ErrorNoKeyboard,
+    }
+}
+
+/// Host side of the key processing switch, see `keymagic_core::processing_state`
+pub struct ProcessingHandle {
+    state: Mutex<Option<ProcessingState>>,
+    host: Mutex<HostProcessingState>,
+    last_attempt: Mutex<Option<Instant>>,
+    liveness: Mutex<GuiLiveness>,
+    last_liveness_check: Mutex<Option<Instant>>,
+}
+
+/// Nothing to do
+pub const KEYMAGIC_PROCESSING_NONE: c_int = 0;
+/// Processing was switched back on
+pub const KEYMAGIC_PROCESSING_ENABLE: c_int = 1;
+/// Commit the composition, then call `keymagic_processing_finish_disable`
+pub const KEYMAGIC_PROCESSING_FLUSH: c_int = 2;
+
+impl ProcessingHandle {
+    fn attach(&self) {
+        let mut last_attempt = self.last_attempt.lock();
+        if last_attempt.is_some_and(|at| at.elapsed() < RECORDER_RETRY_INTERVAL) {
+            return;
+        }
+        *last_attempt = Some(Instant::now());
+        #[cfg(windows)]
+        if let Ok(state) = ProcessingState::create_shared() {
+            *self.state.lock() = Some(state);
+        }
+    }
+
+    /// Whether the GUI owning the switch still runs, looked at no more often
+    /// than `LIVENESS_CHECK_INTERVAL`
+    fn gui_alive(&self) -> bool {
+        let mut liveness = self.liveness.lock();
+        let mut last_check = self.last_liveness_check.lock();
+        if last_check.is_some_and(|at| at.elapsed() < LIVENESS_CHECK_INTERVAL) {
+            return !liveness.is_gone();
+        }
+        *last_check = Some(Instant::now());
+
+        if self.state.lock().is_none() {
+            self.attach();
+        }
+        let Some(heartbeat) = self.state.lock().as_ref().map(ProcessingState::last_heartbeat) else {
+            return !liveness.is_gone();
+        };
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_millis() as u64);
+        liveness.observe(heartbeat, now_ms, GuiPresence::exists())
+    }
+}
+
+/// Creates a handle on the key processing switch set by the GUI
+#[no_mangle]
+pub extern "C" fn keymagic_processing_open() -> *mut ProcessingHandle {
+    Box::into_raw(Box::new(ProcessingHandle {
+        state: Mutex::new(None),
+        host: Mutex::new(HostProcessingState::new()),
+        last_attempt: Mutex::new(None),
+        liveness: Mutex::new(GuiLiveness::new()),
+        last_liveness_check: Mutex::new(None),
+    }))
+}
+
+/// Frees a key processing handle
+#[no_mangle]
+pub extern "C" fn keymagic_processing_free(handle: *mut ProcessingHandle) {
+    if !handle.is_null() {
+        unsafe {
+            let _ = Box::from_raw(handle);
+        }
+    }
+}
+
+/// Reads the switch after the GUI signalled a change
+///
+/// Returns `KEYMAGIC_PROCESSING_FLUSH` when processing was switched off while
+/// this host is enabled: commit the composition, then pass `*request` to
+/// `keymagic_processing_finish_disable`. Requests that need no flush are
+/// acknowledged here.
+#[no_mangle]
+pub extern "C" fn keymagic_processing_observe(handle: *mut ProcessingHandle, request: *mut u64) -> c_int {
+    if handle.is_null() {
+        return KEYMAGIC_PROCESSING_NONE;
+    }
+
+    let handle = unsafe { &*handle };
+    if handle.state.lock().is_none() {
+        handle.attach();
+    }
+    let state = handle.state.lock();
+    let Some(state) = state.as_ref() else {
+        return KEYMAGIC_PROCESSING_NONE;
+    };
+
+    let action = handle.host.lock().observe(state.is_enabled(), state.request());
+    match action {
+        HostAction::None => KEYMAGIC_PROCESSING_NONE,
+        HostAction::Enable => KEYMAGIC_PROCESSING_ENABLE,
+        HostAction::Acknowledge { request } => {
+            state.acknowledge(request);
+            KEYMAGIC_PROCESSING_NONE
+        }
+        HostAction::FlushThenDisable { request: pending } => {
+            if !request.is_null() {
+                unsafe { *request = pending };
+            }
+            KEYMAGIC_PROCESSING_FLUSH
+        }
+    }
+}
+
+/// Stands down after the composition was committed for `request`
+///
+/// Returns 1 if the request was acknowledged, 0 if it was superseded or
+/// processing was switched back on meanwhile.
+#[no_mangle]
+pub extern "C" fn keymagic_processing_finish_disable(handle: *mut ProcessingHandle, request: u64) -> c_int {
+    if handle.is_null() {
+        return 0;
+    }
+
+    let handle = unsafe { &*handle };
+    let Some(request) = handle.host.lock().finish_disable(request) else {
+        return 0;
+    };
+    if let Some(state) = handle.state.lock().as_ref() {
+        state.acknowledge(request);
+    }
+    1
+}
+
+/// Whether this host should process keys; stays 1 until a flush finished
+///
+/// Also 0 while the GUI is gone: its heartbeat went stale without it
+/// holding its mutex, as after a crash.
+#[no_mangle]
+pub extern "C" fn keymagic_processing_is_enabled(handle: *mut ProcessingHandle) -> c_int {
+    if handle.is_null() {
+        return 1;
+    }
+    let handle = unsafe { &*handle };
+    let enabled = handle.host.lock().is_enabled();
+    (enabled && handle.gui_alive()) as c_int
+}
+
+/// Reports committed text for the GUI's history, see `keymagic_core::commit_log`
+pub struct CommitLogHandle {
+    log: Mutex<Option<CommitLog>>,
+    last_attempt: Mutex<Option<Instant>>,
+}
+
+impl CommitLogHandle {
+    fn attach(&self) {
+        let mut last_attempt = self.last_attempt.lock();
+        if last_attempt.is_some_and(|at| at.elapsed() < RECORDER_RETRY_INTERVAL) {
+            return;
+        }
+        *last_attempt = Some(Instant::now());
+        #[cfg(windows)]
+        if let Ok(log) = CommitLog::create_shared() {
+            *self.log.lock() = Some(log);
+        }
+    }
+
+    fn with_log<T>(&self, f: impl FnOnce(&CommitLog) -> T) -> Option<T> {
+        if self.log.lock().is_none() {
+            self.attach();
+        }
+        self.log.lock().as_ref().map(f)
+    }
+}
+
+/// Creates a commit log handle; reporting is turned on by the GUI
+#[no_mangle]
+pub extern "C" fn keymagic_commit_log_open() -> *mut CommitLogHandle {
+    Box::into_raw(Box::new(CommitLogHandle {
+        log: Mutex::new(None),
+        last_attempt: Mutex::new(None),
+    }))
+}
+
+/// Frees a commit log handle
+#[no_mangle]
+pub extern "C" fn keymagic_commit_log_free(handle: *mut CommitLogHandle) {
+    if !handle.is_null() {
+        unsafe {
+            let _ = Box::from_raw(handle);
+        }
+    }
+}
+
+/// Reports UTF-8 text committed to the document
+///
+/// Returns the sequence number of the commit, or 0 if it was not reported:
+/// the GUI has the history off, or the text is empty or too long.
+#[no_mangle]
+pub extern "C" fn keymagic_commit_log_report(handle: *mut CommitLogHandle, text: *const c_char) -> u64 {
+    if handle.is_null() || text.is_null() {
+        return 0;
+    }
+
+    let Ok(text) = (unsafe { CStr::from_ptr(text) }).to_str() else {
+        return 0;
+    };
+    unsafe { &*handle }.with_log(|log| log.report(text)).flatten().unwrap_or(0)
+}
+
+/// Asks the GUI to forget its history of committed text
+#[no_mangle]
+pub extern "C" fn keymagic_commit_log_clear(handle: *mut CommitLogHandle) {
+    if !handle.is_null() {
+        unsafe { &*handle }.with_log(CommitLog::request_clear);
+    }
+}
+
+/// Keeps the composing text of this process for crash recovery, see
+/// `keymagic_core::composition_journal`
+pub struct CompositionJournalHandle {
+    journal: Mutex<Option<CompositionJournal>>,
+    last_attempt: Mutex<Option<Instant>>,
+}
+
+impl CompositionJournalHandle {
+    fn attach(&self) {
+        let mut last_attempt = self.last_attempt.lock();
+        if last_attempt.is_some_and(|at| at.elapsed() < RECORDER_RETRY_INTERVAL) {
+            return;
+        }
+        *last_attempt = Some(Instant::now());
+        #[cfg(windows)]
+        if let Ok(journal) = CompositionJournal::create_shared() {
+            *self.journal.lock() = Some(journal);
+        }
+    }
+
+    fn with_journal<T>(&self, f: impl FnOnce(&CompositionJournal) -> T) -> Option<T> {
+        if self.journal.lock().is_none() {
+            self.attach();
+        }
+        self.journal.lock().as_ref().map(f)
+    }
+}
+
+/// Creates a composition journal handle; the GUI turns the journal on
+#[no_mangle]
+pub extern "C" fn keymagic_composition_journal_open() -> *mut CompositionJournalHandle {
+    Box::into_raw(Box::new(CompositionJournalHandle {
+        journal: Mutex::new(None),
+        last_attempt: Mutex::new(None),
+    }))
+}
+
+/// Frees a composition journal handle
+#[no_mangle]
+pub extern "C" fn keymagic_composition_journal_free(handle: *mut CompositionJournalHandle) {
+    if !handle.is_null() {
+        unsafe {
+            let _ = Box::from_raw(handle);
+        }
+    }
+}
+
+/// Keeps `text` (UTF-8) as this process's composing text, named by
+/// `process_name` (a null-terminated UTF-16 string); returns 1 if it was
+/// kept, which needs the user to have opted in and the text to be long enough
+#[no_mangle]
+pub extern "C" fn keymagic_composition_journal_report_w(
+    handle: *mut CompositionJournalHandle,
+    process_name: *const u16,
+    text: *const c_char,
+) -> c_int {
+    if handle.is_null() || text.is_null() {
+        return 0;
+    }
+    let handle = unsafe { &*handle };
+    // Skips converting the strings for every key while the journal is off
+    if !handle.with_journal(CompositionJournal::is_enabled).unwrap_or(false) {
+        return 0;
+    }
+    let Ok(text) = (unsafe { CStr::from_ptr(text) }).to_str() else {
+        return 0;
+    };
+    let process = unsafe { wide_to_string(process_name) }.unwrap_or_default();
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_millis() as u64);
+    handle
+        .with_journal(|journal| journal.report(std::process::id(), &process, text, now_ms))
+        .unwrap_or(false) as c_int
+}
+
+/// Drops this process's composing text once it was committed
+#[no_mangle]
+pub extern "C" fn keymagic_composition_journal_clear(handle: *mut CompositionJournalHandle) {
+    if !handle.is_null() {
+        unsafe { &*handle }.with_journal(|journal| journal.clear(std::process::id()));
+    }
+}
+
+/// Counts key presses for the GUI's heatmap, see `keymagic_core::key_stats`
+pub struct KeyStatsHandle {
+    stats: Mutex<Option<KeyStats>>,
+    last_attempt: Mutex<Option<Instant>>,
+    /// Kept for a block attached later
+    suppressed: AtomicBool,
+}
+
+impl KeyStatsHandle {
+    fn attach(&self) {
+        let mut last_attempt = self.last_attempt.lock();
+        if last_attempt.is_some_and(|at| at.elapsed() < RECORDER_RETRY_INTERVAL) {
+            return;
+        }
+        *last_attempt = Some(Instant::now());
+        #[cfg(windows)]
+        if let Ok(stats) = KeyStats::create_shared() {
+            stats.set_suppressed(self.suppressed.load(Ordering::Relaxed));
+            *self.stats.lock() = Some(stats);
+        }
+    }
+
+    fn with_stats<T>(&self, f: impl FnOnce(&KeyStats) -> T) -> Option<T> {
+        if self.stats.lock().is_none() {
+            self.attach();
+        }
+        self.stats.lock().as_ref().map(f)
+    }
+}
+
+/// Creates a key stats handle; counting is turned on by the GUI
+#[no_mangle]
+pub extern "C" fn keymagic_key_stats_open() -> *mut KeyStatsHandle {
+    Box::into_raw(Box::new(KeyStatsHandle {
+        stats: Mutex::new(None),
+        last_attempt: Mutex::new(None),
+        suppressed: AtomicBool::new(false),
+    }))
+}
+
+/// Frees a key stats handle
+#[no_mangle]
+pub extern "C" fn keymagic_key_stats_free(handle: *mut KeyStatsHandle) {
+    if !handle.is_null() {
+        unsafe {
+            let _ = Box::from_raw(handle);
+        }
+    }
+}
+
+/// Whether the user opted in to key statistics; hosts may skip work such as
+/// checking for a password field while it is 0
+#[no_mangle]
+pub extern "C" fn keymagic_key_stats_is_enabled(handle: *mut KeyStatsHandle) -> c_int {
+    if handle.is_null() {
+        return 0;
+    }
+    unsafe { &*handle }.with_stats(KeyStats::is_enabled).unwrap_or(false) as c_int
+}
+
+/// Stops (1) or resumes (0) counting in this process, for password fields
+#[no_mangle]
+pub extern "C" fn keymagic_key_stats_set_suppressed(handle: *mut KeyStatsHandle, suppressed: c_int) {
+    if handle.is_null() {
+        return;
+    }
+    let handle = unsafe { &*handle };
+    handle.suppressed.store(suppressed != 0, Ordering::Relaxed);
+    handle.with_stats(|stats| stats.set_suppressed(suppressed != 0));
+}
+
+/// Counts a press of `vk_code` with the keyboard `keyboard_id` (a
+/// null-terminated UTF-16 string) active; returns 1 if it was counted
+#[no_mangle]
+pub extern "C" fn keymagic_key_stats_record_w(handle: *mut KeyStatsHandle, keyboard_id: *const u16, vk_code: c_int) -> c_int {
+    if handle.is_null() || !(0..=0xFF).contains(&vk_code) {
+        return 0;
+    }
+    let handle = unsafe { &*handle };
+    // Skips converting the id for every key while counting is off
+    if !handle.with_stats(KeyStats::is_enabled).unwrap_or(false) {
+        return 0;
+    }
+    let Some(keyboard_id) = (unsafe { wide_to_string(keyboard_id) }) else {
+        return 0;
+    };
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_millis() as u64);
+    handle
+        .with_stats(|stats| stats.record(hash_keyboard_id(&keyboard_id), day_of(now_ms), vk_code as u8))
+        .unwrap_or(false) as c_int
+}
+
+/// Times a sample of keys for the GUI, see `keymagic_core::input_latency`
+pub struct InputLatencyHandle {
+    latency: Mutex<Option<InputLatency>>,
+    last_attempt: Mutex<Option<Instant>>,
+}
+
+impl InputLatencyHandle {
+    fn attach(&self) {
+        let mut last_attempt = self.last_attempt.lock();
+        if last_attempt.is_some_and(|at| at.elapsed() < RECORDER_RETRY_INTERVAL) {
+            return;
+        }
+        *last_attempt = Some(Instant::now());
+        #[cfg(windows)]
+        if let Ok(latency) = InputLatency::create_shared() {
+            *self.latency.lock() = Some(latency);
+        }
+    }
+
+    fn with_latency<T>(&self, f: impl FnOnce(&InputLatency) -> T) -> Option<T> {
+        if self.latency.lock().is_none() {
+            self.attach();
         }
+        self.latency.lock().as_ref().map(f)
     }
 }
 
-/// Convert VirtualKey enum value to display string
-/// Returns a newly allocated C string that must be freed with keymagic_free_string
-/// Returns NULL if the key code is invalid
+/// Creates an input latency handle
 #[no_mangle]
-pub extern "C" fn keymagic_virtual_key_to_string(key_code: c_int) -> *mut c_char {
-    // Try to convert the key_code to VirtualKey enum
-    // Since VirtualKey uses u16 internally, we need to check bounds
-    if key_code < 0 || key_code > u16::MAX as c_int {
-        return ptr::null_mut();
+pub extern "C" fn keymagic_input_latency_open() -> *mut InputLatencyHandle {
+    Box::into_raw(Box::new(InputLatencyHandle {
+        latency: Mutex::new(None),
+        last_attempt: Mutex::new(None),
+    }))
+}
+
+/// Frees an input latency handle
+#[no_mangle]
+pub extern "C" fn keymagic_input_latency_free(handle: *mut InputLatencyHandle) {
+    if !handle.is_null() {
+        unsafe {
+            let _ = Box::from_raw(handle);
+        }
     }
-    
-    // Find matching VirtualKey by iterating through all values
-    let virtual_key = match key_code as u16 {
-        2 => VirtualKey::Back,
-        3 => VirtualKey::Tab,
-        4 => VirtualKey::Return,
-        5 => VirtualKey::Shift,
-        6 => VirtualKey::Control,
-        7 => VirtualKey::Menu,
-        8 => VirtualKey::Pause,
-        9 => VirtualKey::Capital,
-        10 => VirtualKey::Kanji,
-        11 => VirtualKey::Escape,
-        12 => VirtualKey::Space,
-        13 => VirtualKey::Prior,
-        14 => VirtualKey::Next,
-        15 => VirtualKey::Delete,
-        16 => VirtualKey::Key0,
-        17 => VirtualKey::Key1,
-        18 => VirtualKey::Key2,
-        19 => VirtualKey::Key3,
-        20 => VirtualKey::Key4,
-        21 => VirtualKey::Key5,
-        22 => VirtualKey::Key6,
-        23 => VirtualKey::Key7,
-        24 => VirtualKey::Key8,
-        25 => VirtualKey::Key9,
-        26 => VirtualKey::KeyA,
-        27 => VirtualKey::KeyB,
-        28 => VirtualKey::KeyC,
-        29 => VirtualKey::KeyD,
-        30 => VirtualKey::KeyE,
-        31 => VirtualKey::KeyF,
-        32 => VirtualKey::KeyG,
-        33 => VirtualKey::KeyH,
-        34 => VirtualKey::KeyI,
-        35 => VirtualKey::KeyJ,
-        36 => VirtualKey::KeyK,
-        37 => VirtualKey::KeyL,
-        38 => VirtualKey::KeyM,
-        39 => VirtualKey::KeyN,
-        40 => VirtualKey::KeyO,
-        41 => VirtualKey::KeyP,
-        42 => VirtualKey::KeyQ,
-        43 => VirtualKey::KeyR,
-        44 => VirtualKey::KeyS,
-        45 => VirtualKey::KeyT,
-        46 => VirtualKey::KeyU,
-        47 => VirtualKey::KeyV,
-        48 => VirtualKey::KeyW,
-        49 => VirtualKey::KeyX,
-        50 => VirtualKey::KeyY,
-        51 => VirtualKey::KeyZ,
-        52 => VirtualKey::Numpad0,
-        53 => VirtualKey::Numpad1,
-        54 => VirtualKey::Numpad2,
-        55 => VirtualKey::Numpad3,
-        56 => VirtualKey::Numpad4,
-        57 => VirtualKey::Numpad5,
-        58 => VirtualKey::Numpad6,
-        59 => VirtualKey::Numpad7,
-        60 => VirtualKey::Numpad8,
-        61 => VirtualKey::Numpad9,
-        62 => VirtualKey::Multiply,
-        63 => VirtualKey::Add,
-        64 => VirtualKey::Separator,
-        65 => VirtualKey::Subtract,
-        66 => VirtualKey::Decimal,
-        67 => VirtualKey::Divide,
-        68 => VirtualKey::F1,
-        69 => VirtualKey::F2,
-        70 => VirtualKey::F3,
-        71 => VirtualKey::F4,
-        72 => VirtualKey::F5,
-        73 => VirtualKey::F6,
-        74 => VirtualKey::F7,
-        75 => VirtualKey::F8,
-        76 => VirtualKey::F9,
-        77 => VirtualKey::F10,
-        78 => VirtualKey::F11,
-        79 => VirtualKey::F12,
-        80 => VirtualKey::LShift,
-        81 => VirtualKey::RShift,
-        82 => VirtualKey::LControl,
-        83 => VirtualKey::RControl,
-        84 => VirtualKey::LMenu,
-        85 => VirtualKey::RMenu,
-        86 => VirtualKey::Oem1,
-        87 => VirtualKey::OemPlus,
-        88 => VirtualKey::OemComma,
-        89 => VirtualKey::OemMinus,
-        90 => VirtualKey::OemPeriod,
-        91 => VirtualKey::Oem2,
-        92 => VirtualKey::Oem3,
-        93 => VirtualKey::Oem4,
-        94 => VirtualKey::Oem5,
-        95 => VirtualKey::Oem6,
-        96 => VirtualKey::Oem7,
-        97 => VirtualKey::Oem8,
-        98 => VirtualKey::OemAx,
-        99 => VirtualKey::Oem102,
-        100 => VirtualKey::IcoHelp,
-        101 => VirtualKey::Ico00,
-        _ => return ptr::null_mut(),
+}
+
+/// Counts a key reaching the host; returns 1 if the host should time it
+/// and report the time with `keymagic_input_latency_record`
+#[no_mangle]
+pub extern "C" fn keymagic_input_latency_should_sample(handle: *mut InputLatencyHandle) -> c_int {
+    if handle.is_null() {
+        return 0;
+    }
+    unsafe { &*handle }.with_latency(InputLatency::should_sample).unwrap_or(false) as c_int
+}
+
+/// Adds the time in microseconds a sampled key took, from reaching the
+/// host to its output being in the document
+#[no_mangle]
+pub extern "C" fn keymagic_input_latency_record(handle: *mut InputLatencyHandle, micros: u64) {
+    if !handle.is_null() {
+        unsafe { &*handle }.with_latency(|latency| latency.record(micros));
+    }
+}
+
+/// Shortcuts the GUI watches for, see `keymagic_core::shortcut_watch`
+pub struct ShortcutWatchHandle {
+    watch: Mutex<Option<ShortcutWatch>>,
+    last_attempt: Mutex<Option<Instant>>,
+}
+
+impl ShortcutWatchHandle {
+    fn attach(&self) {
+        let mut last_attempt = self.last_attempt.lock();
+        if last_attempt.is_some_and(|at| at.elapsed() < RECORDER_RETRY_INTERVAL) {
+            return;
+        }
+        *last_attempt = Some(Instant::now());
+        #[cfg(windows)]
+        if let Ok(watch) = ShortcutWatch::create_shared() {
+            *self.watch.lock() = Some(watch);
+        }
+    }
+
+    fn with_watch<T>(&self, f: impl FnOnce(&ShortcutWatch) -> T) -> Option<T> {
+        if self.watch.lock().is_none() {
+            self.attach();
+        }
+        self.watch.lock().as_ref().map(f)
+    }
+}
+
+/// Creates a handle on the shortcuts the GUI watches for
+#[no_mangle]
+pub extern "C" fn keymagic_shortcut_watch_open() -> *mut ShortcutWatchHandle {
+    Box::into_raw(Box::new(ShortcutWatchHandle {
+        watch: Mutex::new(None),
+        last_attempt: Mutex::new(None),
+    }))
+}
+
+/// Frees a shortcut watch handle
+#[no_mangle]
+pub extern "C" fn keymagic_shortcut_watch_free(handle: *mut ShortcutWatchHandle) {
+    if !handle.is_null() {
+        unsafe {
+            let _ = Box::from_raw(handle);
+        }
+    }
+}
+
+/// The action of the watched shortcut a key (Windows VK code) fires with
+/// these modifiers, or 0 when the GUI does not watch for it
+///
+/// Sets `*swallow` to 1 when the key must be kept from the application; the
+/// host then commits its composing text before reporting the shortcut.
+#[no_mangle]
+pub extern "C" fn keymagic_shortcut_watch_find_win(
+    handle: *mut ShortcutWatchHandle,
+    vk_code: c_int,
+    shift: c_int,
+    ctrl: c_int,
+    alt: c_int,
+    swallow: *mut c_int,
+) -> c_uint {
+    if handle.is_null() {
+        return 0;
+    }
+    let Some(key) = u16::try_from(vk_code).ok().and_then(VirtualKey::from_win_vk) else {
+        return 0;
     };
-    
-    // Get display string from the enum method
-    let display_name = virtual_key.to_display_string();
-    
-    match CString::new(display_name) {
-        Ok(c_string) => c_string.into_raw(),
-        Err(_) => ptr::null_mut(),
+
+    let found = unsafe { &*handle }.with_watch(|watch| watch.find(key, ctrl != 0, alt != 0, shift != 0)).flatten();
+    let Some(shortcut) = found else {
+        return 0;
+    };
+    if !swallow.is_null() {
+        unsafe { *swallow = shortcut.swallow as c_int };
+    }
+    shortcut.action
+}
+
+/// Reports that a watched shortcut fired in this process
+///
+/// Returns the sequence number of the event, or 0 if it was not reported.
+#[no_mangle]
+pub extern "C" fn keymagic_shortcut_watch_report(handle: *mut ShortcutWatchHandle, action: c_uint) -> u64 {
+    if handle.is_null() || action == 0 {
+        return 0;
+    }
+    unsafe { &*handle }.with_watch(|watch| watch.report(action, std::process::id())).unwrap_or(0)
+}
+
+/// Compositions of this process's input contexts, see
+/// `keymagic_core::context_store`
+static CONTEXTS: Mutex<ContextStore> = Mutex::new(ContextStore::new());
+
+/// Registers an input context the host created; `context_id` is any value
+/// the host keeps unique among its live contexts
+///
+/// Returns 1 when the context took over the composition of a context
+/// destroyed just before; `keymagic_context_focused` restores it.
+#[no_mangle]
+pub extern "C" fn keymagic_context_created(context_id: u64) -> c_int {
+    CONTEXTS.lock().created(context_id, std::process::id(), Instant::now()).is_some() as c_int
+}
+
+/// Forgets an input context the host destroyed
+///
+/// When the context had the focus, the composition of `handle` (may be
+/// null) is kept for a context replacing it and the engine is reset.
+/// Returns 1 when a context created just before took the composition over.
+#[no_mangle]
+pub extern "C" fn keymagic_context_destroyed(handle: *mut EngineHandle, context_id: u64) -> c_int {
+    let mut contexts = CONTEXTS.lock();
+    let engine = (!handle.is_null()).then(|| unsafe { &*handle }.engine()).flatten();
+    let snapshot = match engine {
+        Some(engine) if contexts.focused_context(std::process::id()) == Some(context_id) => {
+            let mut engine = engine.write();
+            let snapshot = engine.snapshot();
+            engine.reset();
+            Some(snapshot)
+        }
+        _ => None,
+    };
+    contexts.destroyed(context_id, snapshot, Instant::now()).is_some() as c_int
+}
+
+/// Tells the engine which input context has the focus
+///
+/// The composition of the context that had the focus is saved and the one
+/// of `context_id` restored, or the engine reset when it has none. Returns 1
+/// when a composition was restored, 0 when not, and 0 without touching the
+/// engine when the context already had the focus.
+#[no_mangle]
+pub extern "C" fn keymagic_context_focused(handle: *mut EngineHandle, context_id: u64) -> c_int {
+    if handle.is_null() {
+        return 0;
+    }
+    let Some(engine) = unsafe { &*handle }.engine() else {
+        return 0;
+    };
+    let process_id = std::process::id();
+    let mut contexts = CONTEXTS.lock();
+    if contexts.focused_context(process_id) == Some(context_id) {
+        return 0;
+    }
+
+    let mut engine = engine.write();
+    let restored = contexts.focused(context_id, process_id, Some(engine.snapshot()), Instant::now());
+    match restored {
+        Some(snapshot) => {
+            engine.restore(snapshot);
+            1
+        }
+        None => {
+            engine.reset();
+            0
+        }
+    }
+}
+
+/// Key presses eaten in this process, see `keymagic_core::char_suppression`
+static EATEN_KEYS: Mutex<EatenKeyTable> = Mutex::new(EatenKeyTable::new());
+
+/// Runs `f` on the shared suppression counters
+///
+/// Attaches to the shared block on first use, retrying like the recorder.
+#[cfg(windows)]
+fn with_char_stats(f: impl FnOnce(&CharSuppressionStats)) {
+    static STATS: Mutex<Option<CharSuppressionStats>> = Mutex::new(None);
+    static LAST_ATTEMPT: Mutex<Option<Instant>> = Mutex::new(None);
+
+    let mut stats = STATS.lock();
+    if stats.is_none() {
+        let mut last_attempt = LAST_ATTEMPT.lock();
+        if last_attempt.is_some_and(|at| at.elapsed() < RECORDER_RETRY_INTERVAL) {
+            return;
+        }
+        *last_attempt = Some(Instant::now());
+        *stats = CharSuppressionStats::create_shared().ok();
+    }
+    if let Some(stats) = stats.as_ref() {
+        f(stats);
+    }
+}
+
+#[cfg(not(windows))]
+fn with_char_stats(_f: impl FnOnce(&CharSuppressionStats)) {}
+
+/// Records a key press the host ate, so its stray character can be dropped
+///
+/// `scan_code` is bits 16-24 of the key message's `lParam` (scan code and
+/// extended flag) and `time_ms` a tick count, as from `GetTickCount64`.
+#[no_mangle]
+pub extern "C" fn keymagic_char_filter_record_eaten(thread_id: c_uint, vk_code: c_int, scan_code: c_int, time_ms: u64) {
+    EATEN_KEYS.lock().record(EatenKey {
+        thread_id,
+        vk: vk_code as u16,
+        scan_code: scan_code as u16,
+        time_ms,
+    });
+}
+
+/// Whether a character message with `scan_code` on `thread_id` at `time_ms`
+/// belongs to a key the host ate; returns 1 when the host should drop it
+///
+/// Each eaten key drops one character. Dropped characters are counted for
+/// the GUI's diagnostics.
+#[no_mangle]
+pub extern "C" fn keymagic_char_filter_should_drop(thread_id: c_uint, scan_code: c_int, time_ms: u64) -> c_int {
+    if EATEN_KEYS.lock().take_char(thread_id, scan_code as u16, time_ms).is_none() {
+        return 0;
+    }
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_millis() as u64);
+    with_char_stats(|stats| stats.record_suppressed(now_ms));
+    1
+}
+
+/// Forgets the eaten keys of a thread whose hook was removed
+#[no_mangle]
+pub extern "C" fn keymagic_char_filter_forget_thread(thread_id: c_uint) {
+    EATEN_KEYS.lock().forget_thread(thread_id);
+}
+
+/// Whether the host is on the opt-in list of hosts that get the character
+/// filter hook
+///
+/// `process_name` is a null-terminated UTF-16 string and `hosts` an array of
+/// `count` such strings, matched like the input mode host lists.
+#[no_mangle]
+pub extern "C" fn keymagic_char_filter_host_listed_w(
+    process_name: *const u16,
+    hosts: *const *const u16,
+    count: c_int,
+) -> c_int {
+    let Some(process) = (unsafe { wide_to_string(process_name) }) else {
+        return 0;
+    };
+    host_listed(&process, &unsafe { wide_list(hosts, count) }) as c_int
+}
+
+/// Counts a character filter hook the host installed; `detected` is 1 when
+/// it found doubling itself, 0 when the host is on the opt-in list
+#[no_mangle]
+pub extern "C" fn keymagic_char_filter_hook_installed(detected: c_int) {
+    let reason = if detected != 0 { HookReason::Detected } else { HookReason::Listed };
+    with_char_stats(|stats| stats.record_hook(reason));
+}
+
+/// Largest tray icon the FFI renders, in pixels
+const MAX_TRAY_ICON_SIZE: c_int = 256;
+
+fn tray_icon_theme(light_theme: c_int) -> IconTheme {
+    IconTheme::for_taskbar(light_theme != 0)
+}
+
+/// Renders the monochrome tray icon, see `keymagic_core::tray_icon`
+///
+/// Writes `size * size` BGRA pixels (straight alpha, rows top to bottom) to
+/// `out_bgra`, which must hold `buf_len >= size * size * 4` bytes. The glyph
+/// is drawn for a light taskbar when `light_theme` is set, with the disabled
+/// badge when `processing_enabled` is 0.
+#[no_mangle]
+pub extern "C" fn keymagic_tray_render_icon(
+    size: c_int,
+    light_theme: c_int,
+    processing_enabled: c_int,
+    out_bgra: *mut u8,
+    buf_len: usize,
+) -> KeyMagicResult {
+    if out_bgra.is_null() || size <= 0 || size > MAX_TRAY_ICON_SIZE {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+    let image = render_tray_icon(size as u32, tray_icon_theme(light_theme), processing_enabled != 0);
+    let pixels = image.to_bgra();
+    if buf_len < pixels.len() {
+        return KeyMagicResult::ErrorInvalidParameter;
     }
+    unsafe { ptr::copy_nonoverlapping(pixels.as_ptr(), out_bgra, pixels.len()) };
+    KeyMagicResult::Success
 }
 
+/// Draws a badge (`KEYMAGIC_BADGE_*`) in place over `size * size` BGRA pixels
+#[no_mangle]
+pub extern "C" fn keymagic_tray_draw_badge(
+    bgra: *mut u8,
+    size: c_int,
+    light_theme: c_int,
+    badge: c_int,
+) -> KeyMagicResult {
+    let badge = match badge {
+        1 => Badge::Dot,
+        2 => Badge::Slash,
+        _ => return KeyMagicResult::ErrorInvalidParameter,
+    };
+    if bgra.is_null() || size <= 0 || size > MAX_TRAY_ICON_SIZE {
+        return KeyMagicResult::ErrorInvalidParameter;
+    }
+    let len = (size * size * 4) as usize;
+    let pixels = unsafe { std::slice::from_raw_parts_mut(bgra, len) };
+    let Some(mut image) = IconImage::from_bgra(size as u32, pixels) else {
+        return KeyMagicResult::ErrorInvalidParameter;
+    };
+    draw_badge(&mut image, badge, tray_icon_theme(light_theme));
+    pixels.copy_from_slice(&image.to_bgra());
+    KeyMagicResult::Success
+}
@@ -1,11 +1,99 @@
 //! Hotkey parsing and representation
 
-use crate::VirtualKey;
+use std::collections::VecDeque;
+
+use crate::analysis::typed_key;
 use crate::error::{Error, Result};
+use crate::{BinaryFormatElement, Km2File, Rule, VirtualKey};
+
+/// Default longest time between the two presses of a double-tap
+pub const DEFAULT_DOUBLE_TAP_WINDOW_MS: u64 = 300;
+
+/// `dwExtraInfo` of every key event KeyMagic injects with `SendInput`
+/// ("KMAG"). Hooks and key sinks skip events carrying it, so text a rule
+/// outputs is never processed again or taken for a hotkey.
+pub const INJECTED_INPUT_SIGNATURE: usize = 0x4B4D_4147;
+
+/// Most keyboard switches within [`SWITCH_LOOP_WINDOW_MS`] before further
+/// ones are taken for a loop, see [`SwitchLoopBreaker`]
+pub const SWITCH_LOOP_MAX_SWITCHES: usize = 5;
+pub const SWITCH_LOOP_WINDOW_MS: u64 = 1000;
+/// How long switching stays suppressed once a loop was detected
+pub const SWITCH_LOOP_COOLDOWN_MS: u64 = 2000;
+/// Shown when a loop was detected
+pub const SWITCH_LOOP_MESSAGE: &str = "Keyboard switching paused: too many switches in a second";
+
+/// Share of a layout's rules above which a modifier counts as heavily used,
+/// see [`modifier_share`]
+pub const HEAVY_MODIFIER_SHARE: f64 = 0.2;
+
+/// A parsed hotkey
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyBinding {
+    /// A key pressed with modifiers, like "CTRL+SHIFT+K"
+    Combo(KeyCombo),
+    /// A modifier pressed twice in a row, like "Shift Shift" or "double:LShift"
+    DoubleTap(VirtualKey),
+}
+
+impl HotkeyBinding {
+    /// Parse a hotkey string: a key combination (see [`KeyCombo::parse`]), a
+    /// modifier named twice ("Shift Shift") or "double:" and a modifier
+    /// ("double:LShift"). Double-taps take Shift, Ctrl and Alt, either side
+    /// or a given one.
+    pub fn parse(hotkey_str: &str) -> Result<Self> {
+        let trimmed = hotkey_str.trim();
+        if let Some(rest) = trimmed.get(..7).filter(|prefix| prefix.eq_ignore_ascii_case("double:")) {
+            let name = trimmed[rest.len()..].trim().to_uppercase();
+            return parse_double_tap_key(&name)
+                .map(HotkeyBinding::DoubleTap)
+                .ok_or_else(|| Error::ParseError(format!("Not a modifier that can be double-tapped: {}", name)));
+        }
+
+        let parts = split_parts(trimmed);
+        if let [first, second] = parts.as_slice() {
+            if first == second {
+                if let Some(key) = parse_double_tap_key(first) {
+                    return Ok(HotkeyBinding::DoubleTap(key));
+                }
+            }
+        }
+        KeyCombo::parse(trimmed).map(HotkeyBinding::Combo)
+    }
+
+    /// The key combination, unless this is a double-tap
+    pub fn combo(&self) -> Option<&KeyCombo> {
+        match self {
+            HotkeyBinding::Combo(combo) => Some(combo),
+            HotkeyBinding::DoubleTap(_) => None,
+        }
+    }
+
+    /// The modifier of a double-tap
+    pub fn double_tap_key(&self) -> Option<VirtualKey> {
+        match self {
+            HotkeyBinding::DoubleTap(key) => Some(*key),
+            HotkeyBinding::Combo(_) => None,
+        }
+    }
+
+    /// Whether the same key presses can trigger both: equal combinations, or
+    /// double-taps of a modifier where one of them takes either side
+    pub fn overlaps(&self, other: &HotkeyBinding) -> bool {
+        match (self, other) {
+            (HotkeyBinding::Combo(a), HotkeyBinding::Combo(b)) => a == b,
+            (HotkeyBinding::DoubleTap(a), HotkeyBinding::DoubleTap(b)) => {
+                let either_side = |key: VirtualKey| generic_modifier(key) == Some(key);
+                a == b || (generic_modifier(*a) == generic_modifier(*b) && (either_side(*a) || either_side(*b)))
+            }
+            _ => false,
+        }
+    }
+}
 
-/// Represents a parsed hotkey with key and modifier flags
+/// A key with modifier flags
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct HotkeyBinding {
+pub struct KeyCombo {
     /// The main key
     pub key: VirtualKey,
     /// Ctrl/Control modifier
@@ -18,14 +106,15 @@ pub struct HotkeyBinding {
     pub meta: bool,
 }
 
-impl HotkeyBinding {
+impl KeyCombo {
     /// Parse a hotkey string like "CTRL+SHIFT+A" or "ctrl shift a"
     /// 
     /// # Examples
     /// ```
-    /// use keymagic_core::hotkey::HotkeyBinding;
+    /// use keymagic_core::hotkey::KeyCombo;
+    /// use keymagic_core::VirtualKey;
     /// 
-    /// let hotkey = HotkeyBinding::parse("CTRL+SHIFT+K").unwrap();
+    /// let hotkey = KeyCombo::parse("CTRL+SHIFT+K").unwrap();
     /// assert_eq!(hotkey.ctrl, true);
     /// assert_eq!(hotkey.shift, true);
     /// assert_eq!(hotkey.key, VirtualKey::KeyK);
@@ -35,12 +124,7 @@ impl HotkeyBinding {
             return Err(Error::ParseError("Empty hotkey string".to_string()));
         }
 
-        // Split by + or space, trim each part, convert to uppercase
-        let parts: Vec<String> = hotkey_str
-            .split(|c| c == '+' || c == ' ')
-            .map(|s| s.trim().to_uppercase())
-            .filter(|s| !s.is_empty())
-            .collect();
+        let parts = split_parts(hotkey_str);
 
         if parts.is_empty() {
             return Err(Error::ParseError("No valid components in hotkey string".to_string()));
@@ -77,7 +161,7 @@ impl HotkeyBinding {
         }
 
         match key {
-            Some(k) => Ok(HotkeyBinding {
+            Some(k) => Ok(KeyCombo {
                 key: k,
                 ctrl,
                 alt,
@@ -89,6 +173,258 @@ impl HotkeyBinding {
     }
 }
 
+/// Split by + or space, trim each part, convert to uppercase
+fn split_parts(hotkey_str: &str) -> Vec<String> {
+    hotkey_str
+        .split(['+', ' '])
+        .map(|s| s.trim().to_uppercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Modifier named in a double-tap hotkey
+fn parse_double_tap_key(name: &str) -> Option<VirtualKey> {
+    match name {
+        "SHIFT" => Some(VirtualKey::Shift),
+        "LSHIFT" => Some(VirtualKey::LShift),
+        "RSHIFT" => Some(VirtualKey::RShift),
+        "CTRL" | "CONTROL" => Some(VirtualKey::Control),
+        "LCTRL" | "LCONTROL" => Some(VirtualKey::LControl),
+        "RCTRL" | "RCONTROL" => Some(VirtualKey::RControl),
+        "ALT" | "OPTION" => Some(VirtualKey::Menu),
+        "LALT" | "LOPTION" => Some(VirtualKey::LMenu),
+        "RALT" | "ROPTION" => Some(VirtualKey::RMenu),
+        _ => None,
+    }
+}
+
+/// Shift, Ctrl or Alt for either side of it, None for other keys
+fn generic_modifier(key: VirtualKey) -> Option<VirtualKey> {
+    match key {
+        VirtualKey::Shift | VirtualKey::LShift | VirtualKey::RShift => Some(VirtualKey::Shift),
+        VirtualKey::Control | VirtualKey::LControl | VirtualKey::RControl => Some(VirtualKey::Control),
+        VirtualKey::Menu | VirtualKey::LMenu | VirtualKey::RMenu => Some(VirtualKey::Menu),
+        _ => None,
+    }
+}
+
+/// Recognizes double-taps from key presses and releases.
+///
+/// A double-tap is a press, release and press again of the same modifier key,
+/// the second press at most the window after the first, with no other key
+/// pressed in between. It fires on the second press. Repeated presses of a
+/// held key (auto-repeat) are ignored, and after firing a third press starts
+/// a new double-tap. Times are in milliseconds from any fixed point.
+#[derive(Debug, Clone)]
+pub struct DoubleTapDetector {
+    window_ms: u64,
+    /// Double-tap keys to report, generic or side-specific modifiers
+    keys: Vec<VirtualKey>,
+    /// Keys currently down
+    held: Vec<VirtualKey>,
+    /// Key and time of the first tap, until another key is pressed
+    first: Option<(VirtualKey, u64)>,
+    /// Whether the first tap was released
+    released: bool,
+}
+
+impl DoubleTapDetector {
+    pub fn new(window_ms: u64) -> Self {
+        Self { window_ms, keys: Vec::new(), held: Vec::new(), first: None, released: false }
+    }
+
+    /// Sets the double-tap keys to report
+    pub fn set_keys(&mut self, keys: Vec<VirtualKey>) {
+        self.keys = keys;
+        self.first = None;
+    }
+
+    /// Forgets pressed keys and a pending first tap, as after losing focus
+    pub fn reset(&mut self) {
+        self.held.clear();
+        self.first = None;
+    }
+
+    /// A key without a `VirtualKey` was pressed; it ends a pending first tap
+    pub fn interrupt(&mut self) {
+        self.first = None;
+    }
+
+    /// A key was pressed. Returns the watched key of a completed double-tap.
+    pub fn key_down(&mut self, key: VirtualKey, time_ms: u64) -> Option<VirtualKey> {
+        if self.held.contains(&key) {
+            return None;
+        }
+        self.held.push(key);
+
+        if self.watched(key).is_none() {
+            self.first = None;
+            return None;
+        }
+        match self.first {
+            Some((first, pressed_at))
+                if first == key && self.released && time_ms.saturating_sub(pressed_at) <= self.window_ms =>
+            {
+                self.first = None;
+                self.watched(key)
+            }
+            _ => {
+                self.first = Some((key, time_ms));
+                self.released = false;
+                None
+            }
+        }
+    }
+
+    /// A key was released
+    pub fn key_up(&mut self, key: VirtualKey, _time_ms: u64) {
+        self.held.retain(|held| *held != key);
+        if matches!(self.first, Some((first, _)) if first == key) {
+            self.released = true;
+        }
+    }
+
+    /// Feeds a key event as a hook sees it, `extra_info` being the event's
+    /// `dwExtraInfo`. Events KeyMagic injected are ignored. Returns the
+    /// watched key of a completed double-tap.
+    pub fn hook_event(&mut self, key: VirtualKey, down: bool, time_ms: u64, extra_info: usize) -> Option<VirtualKey> {
+        if is_injected(extra_info) {
+            return None;
+        }
+        if down {
+            self.key_down(key, time_ms)
+        } else {
+            self.key_up(key, time_ms);
+            None
+        }
+    }
+
+    /// The watched key `key` counts for, preferring a side-specific one
+    fn watched(&self, key: VirtualKey) -> Option<VirtualKey> {
+        self.keys.iter().copied().find(|watched| *watched == key).or_else(|| {
+            let generic = generic_modifier(key)?;
+            self.keys.iter().copied().find(|watched| *watched == generic)
+        })
+    }
+}
+
+impl Default for DoubleTapDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_DOUBLE_TAP_WINDOW_MS)
+    }
+}
+
+/// Whether a key event with `extra_info` as its `dwExtraInfo` was injected
+/// by KeyMagic
+pub fn is_injected(extra_info: usize) -> bool {
+    extra_info == INJECTED_INPUT_SIGNATURE
+}
+
+/// What [`SwitchLoopBreaker::check`] decided about a keyboard switch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchDecision {
+    Allow,
+    /// Too many switches: this one and those following during the cooldown
+    /// are suppressed. Warn the user once.
+    Trip,
+    /// Suppressed during the cooldown
+    Suppress,
+}
+
+/// Breaks keyboard switch loops, such as injected input re-triggering a
+/// hotkey: more than `max_switches` switches within the window suppress
+/// further ones for the cooldown. Times are in milliseconds from any fixed
+/// point.
+#[derive(Debug, Clone)]
+pub struct SwitchLoopBreaker {
+    max_switches: usize,
+    window_ms: u64,
+    cooldown_ms: u64,
+    /// Times of the allowed switches within the window, oldest first
+    recent: VecDeque<u64>,
+    suppressed_until: Option<u64>,
+}
+
+impl SwitchLoopBreaker {
+    pub fn new(max_switches: usize, window_ms: u64, cooldown_ms: u64) -> Self {
+        Self { max_switches, window_ms, cooldown_ms, recent: VecDeque::new(), suppressed_until: None }
+    }
+
+    /// Decides about a switch at `time_ms`; allowed ones are counted
+    pub fn check(&mut self, time_ms: u64) -> SwitchDecision {
+        if self.is_suppressing(time_ms) {
+            return SwitchDecision::Suppress;
+        }
+        self.suppressed_until = None;
+        while self.recent.front().is_some_and(|&at| time_ms.saturating_sub(at) >= self.window_ms) {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= self.max_switches {
+            self.recent.clear();
+            self.suppressed_until = Some(time_ms + self.cooldown_ms);
+            return SwitchDecision::Trip;
+        }
+        self.recent.push_back(time_ms);
+        SwitchDecision::Allow
+    }
+
+    /// Whether switches at `time_ms` are suppressed
+    pub fn is_suppressing(&self, time_ms: u64) -> bool {
+        self.suppressed_until.is_some_and(|until| time_ms < until)
+    }
+
+    /// Forgets recent switches and ends a cooldown
+    pub fn reset(&mut self) {
+        self.recent.clear();
+        self.suppressed_until = None;
+    }
+}
+
+impl Default for SwitchLoopBreaker {
+    fn default() -> Self {
+        Self::new(SWITCH_LOOP_MAX_SWITCHES, SWITCH_LOOP_WINDOW_MS, SWITCH_LOOP_COOLDOWN_MS)
+    }
+}
+
+/// Share of the rules of `keyboard` typed with `modifier` held: rules whose
+/// key combination has it, or, for Shift, rules triggered by a character
+/// typed with Shift on a US layout. Between 0 and 1; 0 without rules.
+pub fn modifier_share(keyboard: &Km2File, modifier: VirtualKey) -> f64 {
+    let Some(modifier) = generic_modifier(modifier) else {
+        return 0.0;
+    };
+    if keyboard.rules.is_empty() {
+        return 0.0;
+    }
+    let uses = keyboard.rules.iter().filter(|rule| rule_uses_modifier(keyboard, rule, modifier)).count();
+    uses as f64 / keyboard.rules.len() as f64
+}
+
+fn rule_uses_modifier(keyboard: &Km2File, rule: &Rule, modifier: VirtualKey) -> bool {
+    let keys: Vec<VirtualKey> = rule.lhs.iter()
+        .filter_map(|element| match element {
+            BinaryFormatElement::Predefined(raw) => VirtualKey::from_raw(*raw),
+            _ => None,
+        })
+        .collect();
+    if !keys.is_empty() {
+        return keys.into_iter().any(|key| generic_modifier(key) == Some(modifier));
+    }
+    if modifier != VirtualKey::Shift {
+        return false;
+    }
+
+    // The character that triggers a text rule is the last one of its pattern
+    let typed_with_shift = |ch: char| typed_key(ch).modifiers.shift;
+    match rule.lhs.iter().rev().find(|element| !matches!(element, BinaryFormatElement::Modifier(_))) {
+        Some(BinaryFormatElement::String(text)) => text.chars().last().is_some_and(typed_with_shift),
+        Some(BinaryFormatElement::Variable(index)) => index.checked_sub(1)
+            .and_then(|index| keyboard.strings.get_str(index))
+            .is_some_and(|text| text.chars().any(typed_with_shift)),
+        _ => false,
+    }
+}
+
 /// Parse a key string to VirtualKey
 fn parse_key(key_str: &str) -> Result<VirtualKey> {
     match key_str {
@@ -182,7 +518,7 @@ mod tests {
 
     #[test]
     fn test_parse_simple_hotkey() {
-        let hotkey = HotkeyBinding::parse("ctrl+a").unwrap();
+        let hotkey = KeyCombo::parse("ctrl+a").unwrap();
         assert_eq!(hotkey.key, VirtualKey::KeyA);
         assert_eq!(hotkey.ctrl, true);
         assert_eq!(hotkey.alt, false);
@@ -192,7 +528,7 @@ mod tests {
 
     #[test]
     fn test_parse_multiple_modifiers() {
-        let hotkey = HotkeyBinding::parse("CTRL+SHIFT+ALT+K").unwrap();
+        let hotkey = KeyCombo::parse("CTRL+SHIFT+ALT+K").unwrap();
         assert_eq!(hotkey.key, VirtualKey::KeyK);
         assert_eq!(hotkey.ctrl, true);
         assert_eq!(hotkey.alt, true);
@@ -202,7 +538,7 @@ mod tests {
 
     #[test]
     fn test_parse_space_separated() {
-        let hotkey = HotkeyBinding::parse("ctrl shift k").unwrap();
+        let hotkey = KeyCombo::parse("ctrl shift k").unwrap();
         assert_eq!(hotkey.key, VirtualKey::KeyK);
         assert_eq!(hotkey.ctrl, true);
         assert_eq!(hotkey.shift, true);
@@ -210,7 +546,7 @@ mod tests {
 
     #[test]
     fn test_parse_mixed_separators() {
-        let hotkey = HotkeyBinding::parse("ctrl+shift k").unwrap();
+        let hotkey = KeyCombo::parse("ctrl+shift k").unwrap();
         assert_eq!(hotkey.key, VirtualKey::KeyK);
         assert_eq!(hotkey.ctrl, true);
         assert_eq!(hotkey.shift, true);
@@ -220,7 +556,7 @@ mod tests {
     fn test_parse_meta_variants() {
         let keys = vec!["meta+k", "cmd+k", "command+k", "win+k", "super+k"];
         for key_str in keys {
-            let hotkey = HotkeyBinding::parse(key_str).unwrap();
+            let hotkey = KeyCombo::parse(key_str).unwrap();
             assert_eq!(hotkey.key, VirtualKey::KeyK);
             assert_eq!(hotkey.meta, true);
         }
@@ -228,21 +564,21 @@ mod tests {
 
     #[test]
     fn test_parse_special_keys() {
-        let hotkey = HotkeyBinding::parse("ctrl+space").unwrap();
+        let hotkey = KeyCombo::parse("ctrl+space").unwrap();
         assert_eq!(hotkey.key, VirtualKey::Space);
         
-        let hotkey = HotkeyBinding::parse("ctrl+enter").unwrap();
+        let hotkey = KeyCombo::parse("ctrl+enter").unwrap();
         assert_eq!(hotkey.key, VirtualKey::Return);
         
-        let hotkey = HotkeyBinding::parse("ctrl+f1").unwrap();
+        let hotkey = KeyCombo::parse("ctrl+f1").unwrap();
         assert_eq!(hotkey.key, VirtualKey::F1);
     }
 
     #[test]
     fn test_parse_case_insensitive() {
-        let hotkey1 = HotkeyBinding::parse("CTRL+SHIFT+A").unwrap();
-        let hotkey2 = HotkeyBinding::parse("ctrl+shift+a").unwrap();
-        let hotkey3 = HotkeyBinding::parse("Ctrl+Shift+A").unwrap();
+        let hotkey1 = KeyCombo::parse("CTRL+SHIFT+A").unwrap();
+        let hotkey2 = KeyCombo::parse("ctrl+shift+a").unwrap();
+        let hotkey3 = KeyCombo::parse("Ctrl+Shift+A").unwrap();
         
         assert_eq!(hotkey1, hotkey2);
         assert_eq!(hotkey2, hotkey3);
@@ -256,4 +592,205 @@ mod tests {
         assert!(HotkeyBinding::parse("ctrl+unknown").is_err());
         assert!(HotkeyBinding::parse("ctrl+a+b").is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_double_tap() {
+        assert_eq!(HotkeyBinding::parse("Shift Shift").unwrap(), HotkeyBinding::DoubleTap(VirtualKey::Shift));
+        assert_eq!(HotkeyBinding::parse("ctrl+ctrl").unwrap(), HotkeyBinding::DoubleTap(VirtualKey::Control));
+        assert_eq!(HotkeyBinding::parse("double:LShift").unwrap(), HotkeyBinding::DoubleTap(VirtualKey::LShift));
+        assert_eq!(HotkeyBinding::parse("DOUBLE: ralt").unwrap(), HotkeyBinding::DoubleTap(VirtualKey::RMenu));
+        assert_eq!(HotkeyBinding::parse("ctrl+shift+k").unwrap().combo().unwrap().key, VirtualKey::KeyK);
+
+        assert!(HotkeyBinding::parse("double:K").is_err());
+        assert!(HotkeyBinding::parse("double:").is_err());
+        assert!(HotkeyBinding::parse("k k").is_err());
+        assert!(HotkeyBinding::parse("win win").is_err());
+    }
+
+    fn shift_detector() -> DoubleTapDetector {
+        let mut detector = DoubleTapDetector::default();
+        detector.set_keys(vec![VirtualKey::Shift, VirtualKey::RControl]);
+        detector
+    }
+
+    fn tap(detector: &mut DoubleTapDetector, key: VirtualKey, at: u64) -> Option<VirtualKey> {
+        let fired = detector.key_down(key, at);
+        detector.key_up(key, at + 50);
+        fired
+    }
+
+    #[test]
+    fn test_double_tap_within_window() {
+        let mut detector = shift_detector();
+        assert_eq!(tap(&mut detector, VirtualKey::LShift, 1000), None);
+        assert_eq!(tap(&mut detector, VirtualKey::LShift, 1300), Some(VirtualKey::Shift));
+        // A third tap starts over
+        assert_eq!(tap(&mut detector, VirtualKey::LShift, 1400), None);
+        assert_eq!(tap(&mut detector, VirtualKey::LShift, 1500), Some(VirtualKey::Shift));
+    }
+
+    #[test]
+    fn test_double_tap_too_slow() {
+        let mut detector = shift_detector();
+        tap(&mut detector, VirtualKey::RShift, 1000);
+        assert_eq!(tap(&mut detector, VirtualKey::RShift, 1301), None);
+        // The late tap counts as a new first tap
+        assert_eq!(tap(&mut detector, VirtualKey::RShift, 1500), Some(VirtualKey::Shift));
+    }
+
+    #[test]
+    fn test_double_tap_interrupted() {
+        let mut detector = shift_detector();
+        tap(&mut detector, VirtualKey::LShift, 1000);
+        tap(&mut detector, VirtualKey::KeyA, 1100);
+        assert_eq!(tap(&mut detector, VirtualKey::LShift, 1200), None);
+
+        // Shift held for Shift+A is not a tap
+        detector.key_down(VirtualKey::LShift, 2000);
+        tap(&mut detector, VirtualKey::KeyA, 2010);
+        detector.key_up(VirtualKey::LShift, 2100);
+        assert_eq!(tap(&mut detector, VirtualKey::LShift, 2200), None);
+
+        // Two different keys are not a double-tap
+        tap(&mut detector, VirtualKey::LShift, 3000);
+        assert_eq!(tap(&mut detector, VirtualKey::RShift, 3100), None);
+    }
+
+    #[test]
+    fn test_double_tap_ignores_key_repeat() {
+        let mut detector = shift_detector();
+        assert_eq!(detector.key_down(VirtualKey::LShift, 1000), None);
+        assert_eq!(detector.key_down(VirtualKey::LShift, 1030), None);
+        assert_eq!(detector.key_down(VirtualKey::LShift, 1060), None);
+        detector.key_up(VirtualKey::LShift, 1100);
+        assert_eq!(tap(&mut detector, VirtualKey::LShift, 1200), Some(VirtualKey::Shift));
+    }
+
+    #[test]
+    fn test_double_tap_side_specific_keys() {
+        let mut detector = shift_detector();
+        tap(&mut detector, VirtualKey::LControl, 1000);
+        assert_eq!(tap(&mut detector, VirtualKey::LControl, 1100), None);
+        tap(&mut detector, VirtualKey::RControl, 2000);
+        assert_eq!(tap(&mut detector, VirtualKey::RControl, 2100), Some(VirtualKey::RControl));
+    }
+
+    #[test]
+    fn test_double_tap_skips_injected_events() {
+        let mut detector = shift_detector();
+        let ours = INJECTED_INPUT_SIGNATURE;
+        // (key, down, time, extra info) as a hook sees them
+        let stream = [
+            (VirtualKey::LShift, true, 1000, 0),
+            (VirtualKey::LShift, false, 1050, 0),
+            // A rule's output injected between the taps
+            (VirtualKey::KeyA, true, 1100, ours),
+            (VirtualKey::KeyA, false, 1110, ours),
+            (VirtualKey::LShift, true, 1200, 0),
+        ];
+        let fired: Vec<_> = stream.iter().filter_map(|&(key, down, at, extra)| detector.hook_event(key, down, at, extra)).collect();
+        assert_eq!(fired, vec![VirtualKey::Shift]);
+
+        // Injected taps alone never fire
+        let mut detector = shift_detector();
+        for at in [3000, 3100, 3200] {
+            assert_eq!(detector.hook_event(VirtualKey::LShift, true, at, ours), None);
+            detector.hook_event(VirtualKey::LShift, false, at + 20, ours);
+        }
+
+        // Unmarked input interrupts as before
+        assert_eq!(detector.hook_event(VirtualKey::LShift, true, 4000, 0), None);
+        detector.hook_event(VirtualKey::LShift, false, 4020, 0);
+        detector.hook_event(VirtualKey::KeyA, true, 4050, 0);
+        detector.hook_event(VirtualKey::KeyA, false, 4060, 0);
+        assert_eq!(detector.hook_event(VirtualKey::LShift, true, 4100, 0), None);
+        assert!(!is_injected(0));
+        assert!(is_injected(INJECTED_INPUT_SIGNATURE));
+    }
+
+    #[test]
+    fn test_switch_loop_breaker_trips_and_recovers() {
+        let mut breaker = SwitchLoopBreaker::new(3, 1000, 2000);
+        for at in [0, 100, 200] {
+            assert_eq!(breaker.check(at), SwitchDecision::Allow);
+        }
+        // The fourth within a second trips, later ones are suppressed quietly
+        assert_eq!(breaker.check(300), SwitchDecision::Trip);
+        assert!(breaker.is_suppressing(300));
+        assert_eq!(breaker.check(1500), SwitchDecision::Suppress);
+        assert_eq!(breaker.check(2299), SwitchDecision::Suppress);
+
+        // After the cooldown the count starts over
+        assert!(!breaker.is_suppressing(2300));
+        for at in [2300, 2400, 2500] {
+            assert_eq!(breaker.check(at), SwitchDecision::Allow);
+        }
+        assert_eq!(breaker.check(2600), SwitchDecision::Trip);
+
+        breaker.reset();
+        assert_eq!(breaker.check(2700), SwitchDecision::Allow);
+    }
+
+    #[test]
+    fn test_switch_loop_breaker_allows_steady_switching() {
+        let mut breaker = SwitchLoopBreaker::default();
+        // Five a second, as fast as a user might, never trips
+        for at in (0..10_000).step_by(200) {
+            assert_eq!(breaker.check(at), SwitchDecision::Allow, "at {}", at);
+        }
+        // A sixth inside the window does
+        let mut breaker = SwitchLoopBreaker::default();
+        for at in 0..SWITCH_LOOP_MAX_SWITCHES as u64 {
+            assert_eq!(breaker.check(at * 10), SwitchDecision::Allow);
+        }
+        assert_eq!(breaker.check(999), SwitchDecision::Trip);
+    }
+
+    fn rule(lhs: Vec<BinaryFormatElement>) -> Rule {
+        Rule { lhs, rhs: vec![BinaryFormatElement::String("x".to_string())] }
+    }
+
+    #[test]
+    fn test_overlapping_bindings() {
+        let binding = |text: &str| HotkeyBinding::parse(text).unwrap();
+        assert!(binding("ctrl+shift+m").overlaps(&binding("SHIFT+CTRL+M")));
+        assert!(!binding("ctrl+shift+m").overlaps(&binding("ctrl+alt+shift+m")));
+        assert!(binding("Shift Shift").overlaps(&binding("double:LShift")));
+        assert!(binding("double:RShift").overlaps(&binding("Shift Shift")));
+        assert!(!binding("double:LShift").overlaps(&binding("double:RShift")));
+        assert!(!binding("Ctrl Ctrl").overlaps(&binding("Shift Shift")));
+        assert!(!binding("Shift Shift").overlaps(&binding("shift+a")));
+    }
+
+    #[test]
+    fn test_modifier_share() {
+        let shift = VirtualKey::Shift as u16;
+        let keyboard = Km2File {
+            header: crate::FileHeader::new(),
+            strings: vec![crate::StringEntry { value: "aB".to_string() }].into(),
+            info: Vec::new(),
+            rules: vec![
+                rule(vec![BinaryFormatElement::String("k".to_string())]),
+                rule(vec![BinaryFormatElement::String("kK".to_string())]),
+                rule(vec![BinaryFormatElement::String("@".to_string())]),
+                rule(vec![BinaryFormatElement::Variable(1), BinaryFormatElement::Modifier(0xF000)]),
+                rule(vec![
+                    BinaryFormatElement::Predefined(VirtualKey::LShift as u16),
+                    BinaryFormatElement::And,
+                    BinaryFormatElement::Predefined(VirtualKey::KeyA as u16),
+                ]),
+                rule(vec![
+                    BinaryFormatElement::Predefined(VirtualKey::Control as u16),
+                    BinaryFormatElement::And,
+                    BinaryFormatElement::Predefined(VirtualKey::KeyA as u16),
+                ]),
+                rule(vec![BinaryFormatElement::Predefined(VirtualKey::KeyK as u16)]),
+                rule(vec![BinaryFormatElement::Predefined(shift), BinaryFormatElement::Predefined(VirtualKey::KeyQ as u16)]),
+            ],
+        };
+        assert_eq!(modifier_share(&keyboard, VirtualKey::RShift), 5.0 / 8.0);
+        assert_eq!(modifier_share(&keyboard, VirtualKey::Control), 1.0 / 8.0);
+        assert_eq!(modifier_share(&keyboard, VirtualKey::Menu), 0.0);
+        assert_eq!(modifier_share(&keyboard, VirtualKey::KeyA), 0.0);
+    }
+}
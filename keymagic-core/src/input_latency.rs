@@ -0,0 +1,210 @@
+//! How long text services take to handle a key, measured in the field
+//!
+//! "Typing got slower after the update" cannot be checked without numbers.
+//! Text services time one key in [`SAMPLE_EVERY`], from the moment the key
+//! reaches them until its output is in the document, and add the time to a
+//! histogram of counters shared by all of them; the GUI reads percentiles
+//! from it. Only bucket counts are kept: no key, character or time of day,
+//! and nothing is written to disk.
+//!
+//! Buckets are log-linear, like an HDR histogram: exact below 16µs, then 16
+//! buckets per power of two, so a reported value is within 1/16 of the
+//! measured one. On Windows the counters live in named shared memory;
+//! elsewhere, and in tests, they are process-local.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::recorder::shared_block::{BlockLayout, SharedBlock};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Name of the section holding the counters on Windows
+#[cfg(windows)]
+pub const SECTION_NAME: &str = r"Local\KeyMagicInputLatency";
+
+/// "KMIL" in little endian
+const BLOCK_MAGIC: u32 = 0x4C49_4D4B;
+const BLOCK_VERSION: u32 = 1;
+
+/// One key in this many is timed
+pub const SAMPLE_EVERY: u64 = 50;
+
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// Times of 2^this µs (about 17 s) and more share the last bucket
+const MAX_EXPONENT: u32 = 24;
+
+/// Buckets in a histogram
+pub const BUCKET_COUNT: usize = SUB_BUCKETS * (MAX_EXPONENT - SUB_BUCKET_BITS + 1) as usize;
+
+/// Bucket a time in microseconds is counted in
+pub fn bucket_of(micros: u64) -> usize {
+    if micros < SUB_BUCKETS as u64 {
+        return micros as usize;
+    }
+    let exponent = 63 - micros.leading_zeros();
+    if exponent >= MAX_EXPONENT {
+        return BUCKET_COUNT - 1;
+    }
+    let sub_bucket = (micros >> (exponent - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    (exponent - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS + sub_bucket
+}
+
+/// Smallest and largest time in microseconds counted in a bucket; the last
+/// bucket also holds everything above its range
+pub fn bucket_bounds(bucket: usize) -> (u64, u64) {
+    if bucket < SUB_BUCKETS {
+        return (bucket as u64, bucket as u64);
+    }
+    let shift = (bucket / SUB_BUCKETS - 1) as u32;
+    let low = ((SUB_BUCKETS + bucket % SUB_BUCKETS) as u64) << shift;
+    (low, low + (1 << shift) - 1)
+}
+
+/// Counts of times per bucket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self { counts: vec![0; BUCKET_COUNT] }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, micros: u64) {
+        self.counts[bucket_of(micros)] += 1;
+    }
+
+    /// Times counted
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The time `percent` of the counted times are at or below, as the top
+    /// of its bucket; `None` while nothing was counted
+    pub fn percentile(&self, percent: f64) -> Option<u64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((percent.clamp(0.0, 100.0) / 100.0 * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.counts.iter().position(|&n| {
+            seen += n;
+            seen >= rank
+        })
+        .map(|bucket| bucket_bounds(bucket).1)
+    }
+
+    /// Top of the highest bucket with a count
+    pub fn max(&self) -> Option<u64> {
+        self.counts.iter().rposition(|&n| n != 0).map(|bucket| bucket_bounds(bucket).1)
+    }
+
+    /// Adds the counts of `other`
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+    }
+
+    /// Times counted since `earlier`, a snapshot of the same counters
+    pub fn since(&self, earlier: &LatencyHistogram) -> LatencyHistogram {
+        let counts = self.counts.iter().zip(&earlier.counts).map(|(now, then)| now.saturating_sub(*then)).collect();
+        LatencyHistogram { counts }
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            samples: self.count(),
+            p50_us: self.percentile(50.0),
+            p95_us: self.percentile(95.0),
+            p99_us: self.percentile(99.0),
+            max_us: self.max(),
+        }
+    }
+}
+
+/// Percentiles of a histogram, in microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LatencySummary {
+    pub samples: u64,
+    pub p50_us: Option<u64>,
+    pub p95_us: Option<u64>,
+    pub p99_us: Option<u64>,
+    pub max_us: Option<u64>,
+}
+
+#[repr(C)]
+struct Block {
+    magic: AtomicU32,
+    version: AtomicU32,
+    /// Keys seen, sampled or not
+    keys: AtomicU64,
+    buckets: [AtomicU64; BUCKET_COUNT],
+}
+
+unsafe impl BlockLayout for Block {
+    fn initialize(&self) {
+        self.keys.store(0, Ordering::Relaxed);
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.version.store(BLOCK_VERSION, Ordering::Relaxed);
+        self.magic.store(BLOCK_MAGIC, Ordering::Release);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic.load(Ordering::Acquire) == BLOCK_MAGIC && self.version.load(Ordering::Relaxed) == BLOCK_VERSION
+    }
+}
+
+/// Handle to the latency counters shared by all hosts
+pub struct InputLatency {
+    block: SharedBlock<Block>,
+}
+
+impl InputLatency {
+    /// A process-local block, for tests and platforms without shared state
+    pub fn in_memory() -> Self {
+        Self { block: SharedBlock::in_memory() }
+    }
+
+    /// Creates the shared block, or attaches to it if it already exists
+    #[cfg(windows)]
+    pub fn create_shared() -> std::io::Result<Self> {
+        Ok(Self { block: SharedBlock::create_shared(SECTION_NAME, "input latency")? })
+    }
+
+    /// Counts a key and tells whether to time it; one in [`SAMPLE_EVERY`]
+    /// across all hosts is
+    pub fn should_sample(&self) -> bool {
+        self.block.keys.fetch_add(1, Ordering::Relaxed).is_multiple_of(SAMPLE_EVERY)
+    }
+
+    /// Adds the time a sampled key took
+    pub fn record(&self, micros: u64) {
+        self.block.buckets[bucket_of(micros)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Keys seen, sampled or not
+    pub fn keys(&self) -> u64 {
+        self.block.keys.load(Ordering::Relaxed)
+    }
+
+    /// The counts as they are now
+    pub fn snapshot(&self) -> LatencyHistogram {
+        LatencyHistogram {
+            counts: self.block.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect(),
+        }
+    }
+}
@@ -0,0 +1,442 @@
+//! Effective input mode of host applications
+//!
+//! Text services either edit the document directly or keep the composing
+//! text in a composition, which some applications (Teams, Excel) need. The
+//! mode is configured with two lists of host patterns; [`resolve_input_mode`]
+//! decides which one applies to a host and why, so text services, the GUI
+//! and tests all agree.
+//!
+//! On Windows the text service of the focused application publishes its
+//! decision to a small block of named shared memory ([`InputModeState`]),
+//! which the GUI reads to show the mode of the foreground application.
+
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::recorder::shared_block::{BlockLayout, SharedBlock};
+use crate::{parse_vk_names, Error, Result, VirtualKey};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum InputMode {
+    /// Composing text is kept in a composition until committed
+    Composition,
+    /// Composing text is written into the document as it changes
+    Direct,
+}
+
+/// Host list an entry comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum HostList {
+    Composition,
+    Direct,
+}
+
+impl HostList {
+    /// Mode of the hosts on this list
+    pub fn mode(self) -> InputMode {
+        match self {
+            HostList::Composition => InputMode::Composition,
+            HostList::Direct => InputMode::Direct,
+        }
+    }
+}
+
+/// List entry that decided the mode of a host
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct HostMatch {
+    pub list: HostList,
+    /// The entry as written in the list
+    pub pattern: String,
+}
+
+/// Mode of a host and the entry it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct InputModeResolution {
+    pub mode: InputMode,
+    /// `None` when no entry matched and the platform default applies
+    pub matched: Option<HostMatch>,
+}
+
+/// Whether a host list entry matches a host
+///
+/// Hosts are process names on Windows and bundle or program names
+/// elsewhere. Matching ignores case and surrounding whitespace; `*` matches
+/// any run of characters and `?` a single one. Blank entries match nothing.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern: Vec<char> = pattern.trim().chars().flat_map(char::to_lowercase).collect();
+    let host: Vec<char> = host.trim().chars().flat_map(char::to_lowercase).collect();
+    if pattern.is_empty() {
+        return false;
+    }
+
+    // Greedy matching that backtracks to the last `*`
+    let (mut p, mut h) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while h < host.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, h));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == host[h] => {
+                p += 1;
+                h += 1;
+            }
+            _ => match star {
+                Some((star_p, star_h)) => {
+                    p = star_p + 1;
+                    h = star_h + 1;
+                    star = Some((star_p, star_h + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// How specific an entry is: exact names first, then by literal characters
+fn specificity(pattern: &str) -> (bool, usize) {
+    let pattern = pattern.trim();
+    let literals = pattern.chars().filter(|&c| c != '*' && c != '?').count();
+    (literals == pattern.chars().count(), literals)
+}
+
+/// Decides the input mode of `host`
+///
+/// When entries of both lists match, the most specific one wins: an exact
+/// name beats any wildcard, and a wildcard with more literal characters
+/// beats one with fewer, so `*` in one list can be narrowed by `chrome.exe`
+/// in the other. Ties go to the composition list, then to the earlier
+/// entry. Without a match the host gets `default`.
+pub fn resolve_input_mode<S: AsRef<str>>(
+    host: &str,
+    composition_hosts: &[S],
+    direct_hosts: &[S],
+    default: InputMode,
+) -> InputModeResolution {
+    let candidates = composition_hosts
+        .iter()
+        .map(|pattern| (HostList::Composition, pattern.as_ref()))
+        .chain(direct_hosts.iter().map(|pattern| (HostList::Direct, pattern.as_ref())));
+
+    let mut best: Option<(HostList, &str)> = None;
+    for (list, pattern) in candidates {
+        if !host_matches(pattern, host) {
+            continue;
+        }
+        if best.is_none_or(|(_, current)| specificity(pattern) > specificity(current)) {
+            best = Some((list, pattern));
+        }
+    }
+
+    match best {
+        Some((list, pattern)) => InputModeResolution {
+            mode: list.mode(),
+            matched: Some(HostMatch { list, pattern: pattern.to_string() }),
+        },
+        None => InputModeResolution { mode: default, matched: None },
+    }
+}
+
+/// Separates the host pattern from the key names in a shortcut entry
+pub const SHORTCUT_ENTRY_SEPARATOR: char = '=';
+
+/// Single-key shortcuts of the hosts an entry matches
+///
+/// Web applications and editors bind plain keys (`c` to compose in Gmail,
+/// `j`/`k` to move between items). While nothing is composed these keys go to
+/// the host unprocessed, see `KeyMagicEngine::passes_as_shortcut`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostShortcuts {
+    /// Host pattern, matched like the input mode host lists
+    pub pattern: String,
+    pub keys: Vec<VirtualKey>,
+}
+
+impl HostShortcuts {
+    /// Parses a settings entry such as `chrome.exe=VK_KEY_C,VK_KEY_J`
+    ///
+    /// Key names are read by [`parse_vk_names`]; the pattern is everything
+    /// before the last `=`.
+    pub fn parse(entry: &str) -> Result<Self> {
+        let (pattern, keys) = entry
+            .rsplit_once(SHORTCUT_ENTRY_SEPARATOR)
+            .ok_or_else(|| Error::ParseError(format!("Shortcut entry without keys: {}", entry)))?;
+        if pattern.trim().is_empty() {
+            return Err(Error::ParseError(format!("Shortcut entry without a host: {}", entry)));
+        }
+        let names: Vec<&str> = keys.split(',').filter(|name| !name.trim().is_empty()).collect();
+        Ok(Self { pattern: pattern.trim().to_string(), keys: parse_vk_names(&names)? })
+    }
+
+    /// The settings entry, with canonical key names
+    pub fn to_entry(&self) -> String {
+        let names: Vec<&str> = self.keys.iter().map(|key| key.to_kms_name()).collect();
+        format!("{}{}{}", self.pattern, SHORTCUT_ENTRY_SEPARATOR, names.join(","))
+    }
+}
+
+/// Shortcut keys of `host`, from the most specific matching entry
+///
+/// Entries are ranked like [`resolve_input_mode`] ranks host list entries,
+/// so `*=VK_KEY_J` can be narrowed for one host. Ties go to the earlier
+/// entry; hosts no entry matches have no shortcut keys.
+pub fn host_shortcut_keys<'a>(host: &str, entries: &'a [HostShortcuts]) -> &'a [VirtualKey] {
+    let mut best: Option<&HostShortcuts> = None;
+    for entry in entries {
+        if !host_matches(&entry.pattern, host) {
+            continue;
+        }
+        if best.is_none_or(|current| specificity(&entry.pattern) > specificity(&current.pattern)) {
+            best = Some(entry);
+        }
+    }
+    best.map_or(&[], |entry| entry.keys.as_slice())
+}
+
+/// Input mode in use by an application, as published by its text service
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct EffectiveInputMode {
+    pub process_id: u32,
+    /// Host name the lists were matched against
+    pub process: String,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub resolution: InputModeResolution,
+    /// Milliseconds since the Unix epoch
+    pub updated_at_ms: u64,
+}
+
+/// Name of the section holding the published mode on Windows
+#[cfg(windows)]
+pub const SECTION_NAME: &str = r"Local\KeyMagicInputMode";
+
+/// "KMIM" in little endian
+const BLOCK_MAGIC: u32 = 0x4D49_4D4B;
+const BLOCK_VERSION: u32 = 1;
+
+/// UTF-16 code units kept of the process name and of the pattern
+pub const NAME_CAPACITY: usize = 260;
+
+/// Writers waiting for a concurrent writer give up after this many tries
+const WRITE_ATTEMPTS: usize = 1000;
+/// Readers catching a write in progress give up after this many tries
+const READ_ATTEMPTS: usize = 100;
+
+/// The published mode, laid out for shared memory
+///
+/// Made of atomics only and guarded by a seqlock: `sequence` is odd while a
+/// writer fills the block. Several processes may publish; a writer claims
+/// the block by moving the sequence from even to odd.
+#[repr(C)]
+struct Block {
+    magic: AtomicU32,
+    version: AtomicU32,
+    sequence: AtomicU32,
+    /// Mode (1 composition, 2 direct) in the low byte, list in the next
+    mode: AtomicU32,
+    /// Process name length in the low half, pattern length in the high half
+    lengths: AtomicU32,
+    process_id: AtomicU32,
+    updated_at_ms: AtomicU64,
+    /// Two UTF-16 code units per word
+    process: [AtomicU32; NAME_CAPACITY / 2],
+    pattern: [AtomicU32; NAME_CAPACITY / 2],
+}
+
+fn write_text(words: &[AtomicU32], text: &str) -> u32 {
+    let units: Vec<u16> = text.encode_utf16().take(NAME_CAPACITY).collect();
+    for (word, pair) in words.iter().zip(units.chunks(2)) {
+        let high = pair.get(1).copied().unwrap_or(0) as u32;
+        word.store(pair[0] as u32 | (high << 16), Ordering::Relaxed);
+    }
+    units.len() as u32
+}
+
+fn read_text(words: &[AtomicU32], len: u32) -> String {
+    let units: Vec<u16> = words
+        .iter()
+        .flat_map(|word| {
+            let word = word.load(Ordering::Relaxed);
+            [word as u16, (word >> 16) as u16]
+        })
+        .take((len as usize).min(NAME_CAPACITY))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn encode_list(list: Option<HostList>) -> u32 {
+    match list {
+        None => 0,
+        Some(HostList::Composition) => 1,
+        Some(HostList::Direct) => 2,
+    }
+}
+
+unsafe impl BlockLayout for Block {
+    fn initialize(&self) {
+        self.sequence.store(0, Ordering::Relaxed);
+        self.mode.store(0, Ordering::Relaxed);
+        self.version.store(BLOCK_VERSION, Ordering::Relaxed);
+        self.magic.store(BLOCK_MAGIC, Ordering::Release);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic.load(Ordering::Acquire) == BLOCK_MAGIC && self.version.load(Ordering::Relaxed) == BLOCK_VERSION
+    }
+}
+
+impl Block {
+    fn write(&self, effective: &EffectiveInputMode) -> bool {
+        let mut claimed = None;
+        for _ in 0..WRITE_ATTEMPTS {
+            let sequence = self.sequence.load(Ordering::Relaxed);
+            if sequence.is_multiple_of(2)
+                && self
+                    .sequence
+                    .compare_exchange_weak(sequence, sequence + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                claimed = Some(sequence + 1);
+                break;
+            }
+            std::hint::spin_loop();
+        }
+        let Some(sequence) = claimed else {
+            return false;
+        };
+        fence(Ordering::Release);
+
+        let resolution = &effective.resolution;
+        let mode = match resolution.mode {
+            InputMode::Composition => 1,
+            InputMode::Direct => 2,
+        };
+        let pattern = resolution.matched.as_ref().map_or("", |m| m.pattern.as_str());
+        self.mode.store(mode | (encode_list(resolution.matched.as_ref().map(|m| m.list)) << 8), Ordering::Relaxed);
+        let process_len = write_text(&self.process, &effective.process);
+        let pattern_len = write_text(&self.pattern, pattern);
+        self.lengths.store(process_len | (pattern_len << 16), Ordering::Relaxed);
+        self.process_id.store(effective.process_id, Ordering::Relaxed);
+        self.updated_at_ms.store(effective.updated_at_ms, Ordering::Relaxed);
+
+        self.sequence.store(sequence + 1, Ordering::Release);
+        true
+    }
+
+    fn read(&self) -> Option<EffectiveInputMode> {
+        for _ in 0..READ_ATTEMPTS {
+            let before = self.sequence.load(Ordering::Acquire);
+            if !before.is_multiple_of(2) {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            let packed = self.mode.load(Ordering::Relaxed);
+            let lengths = self.lengths.load(Ordering::Relaxed);
+            let process = read_text(&self.process, lengths & 0xFFFF);
+            let pattern = read_text(&self.pattern, lengths >> 16);
+            let process_id = self.process_id.load(Ordering::Relaxed);
+            let updated_at_ms = self.updated_at_ms.load(Ordering::Relaxed);
+
+            fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) != before {
+                continue;
+            }
+
+            let mode = match packed & 0xFF {
+                1 => InputMode::Composition,
+                2 => InputMode::Direct,
+                // Nothing published yet
+                _ => return None,
+            };
+            let list = match (packed >> 8) & 0xFF {
+                1 => Some(HostList::Composition),
+                2 => Some(HostList::Direct),
+                _ => None,
+            };
+            return Some(EffectiveInputMode {
+                process_id,
+                process,
+                resolution: InputModeResolution {
+                    mode,
+                    matched: list.map(|list| HostMatch { list, pattern }),
+                },
+                updated_at_ms,
+            });
+        }
+        None
+    }
+}
+
+/// Handle to the published input mode
+pub struct InputModeState {
+    block: SharedBlock<Block>,
+}
+
+impl InputModeState {
+    /// A process-local block, for tests and platforms without shared state
+    pub fn in_memory() -> Self {
+        Self { block: SharedBlock::in_memory() }
+    }
+
+    /// Creates the shared block, or attaches to it if it already exists
+    ///
+    /// Both the GUI and the text services call this, so whichever starts
+    /// first creates it.
+    #[cfg(windows)]
+    pub fn create_shared() -> std::io::Result<Self> {
+        Ok(Self { block: SharedBlock::create_shared(SECTION_NAME, "input mode")? })
+    }
+
+    /// Publishes the mode of an application
+    ///
+    /// Returns false if nothing was written: the block already held the same
+    /// mode for the same process, or other writers kept it busy.
+    pub fn publish(&self, effective: &EffectiveInputMode) -> bool {
+        let block = &*self.block;
+        if let Some(current) = block.read() {
+            if current.process_id == effective.process_id
+                && current.process == effective.process
+                && current.resolution == effective.resolution
+            {
+                return false;
+            }
+        }
+        block.write(effective)
+    }
+
+    /// Publishes the mode of the current process
+    pub fn publish_resolution(&self, process: &str, resolution: InputModeResolution) -> bool {
+        self.publish(&EffectiveInputMode {
+            process_id: std::process::id(),
+            process: process.to_string(),
+            resolution,
+            updated_at_ms: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0),
+        })
+    }
+
+    /// The last published mode, or `None` before anything was published
+    pub fn read(&self) -> Option<EffectiveInputMode> {
+        let block = &*self.block;
+        if !block.is_valid() {
+            return None;
+        }
+        block.read()
+    }
+
+    /// Changes whenever a new mode is published; cheap enough to poll
+    pub fn sequence(&self) -> u32 {
+        self.block.sequence.load(Ordering::Acquire)
+    }
+}
@@ -0,0 +1,390 @@
+//! Key events exchanged with the GUI frontend, the tooling and test files
+//!
+//! A [`KeyEventDto`] names its key by KMS name (`"VK_KEY_A"`, the `VK_`
+//! prefix optional) or by Windows virtual key code, carries the character it
+//! types, if any, and the modifiers held. Converting it to a [`KeyInput`]
+//! checks the key, so a misspelled name fails with the name rather than
+//! typing nothing.
+//!
+//! Scenario files and tests write key events in a compact text form:
+//!
+//! - `a`, `က`: the character, typed the way a US layout types it
+//! - `{BKSP}`, `{VK_RETURN}`, `{KEY_A}`: a key by name, without a character;
+//!   `{<}` is the character `<` (likewise `>`, `{` and `}`)
+//! - `<Shift+k>`, `<Ctrl+Alt+{DELETE}>`: keys with modifiers held (`Shift`,
+//!   `Ctrl`, `Alt`, `Caps`); `<Shift+ka>` is Shift held for both `k` and `a`
+
+use crate::analysis::{typed_key, us_character};
+use crate::types::virtual_keys::{create_vk_map, parse_vk_names};
+use crate::{KeyInput, ModifierState, VirtualKey};
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A key, by KMS name or Windows virtual key code
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(untagged))]
+pub enum VkRef {
+    Name(String),
+    Code(u16),
+}
+
+impl VkRef {
+    /// The key this refers to
+    pub fn resolve(&self) -> Result<VirtualKey, KeyEventError> {
+        match self {
+            VkRef::Name(name) => parse_vk_names(&[key_alias(name).unwrap_or(name.as_str())])
+                .map(|keys| keys[0])
+                .map_err(|_| KeyEventError::UnknownKeyName(name.clone())),
+            VkRef::Code(code) => VirtualKey::from_win_vk(*code).ok_or(KeyEventError::UnknownKeyCode(*code)),
+        }
+    }
+}
+
+impl From<VirtualKey> for VkRef {
+    fn from(key: VirtualKey) -> Self {
+        VkRef::Name(key.to_kms_name().to_string())
+    }
+}
+
+/// Names scenario files use for keys whose KMS name is longer
+fn key_alias(name: &str) -> Option<&'static str> {
+    match name.trim().to_ascii_uppercase().as_str() {
+        "BKSP" | "BACKSPACE" => Some("VK_BACK"),
+        "DEL" => Some("VK_DELETE"),
+        "INS" => Some("VK_INSERT"),
+        "PGUP" => Some("VK_PRIOR"),
+        "PGDN" => Some("VK_NEXT"),
+        _ => None,
+    }
+}
+
+/// Error for a key event that names no key, an unknown key, or does not parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyEventError {
+    UnknownKeyName(String),
+    UnknownKeyCode(u16),
+    /// Neither a key nor a character
+    Empty,
+    /// A character held with modifiers must be on the US layout
+    NotOnUsLayout(char),
+    /// Compact text that does not parse; `offset` is in characters
+    Syntax { text: String, offset: usize, message: String },
+}
+
+impl fmt::Display for KeyEventError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyEventError::UnknownKeyName(name) => write!(f, "Unknown key name: {}", name),
+            KeyEventError::UnknownKeyCode(code) => write!(f, "Unknown virtual key code: {:#04x}", code),
+            KeyEventError::Empty => write!(f, "Key event has neither a key nor a character"),
+            KeyEventError::NotOnUsLayout(ch) => {
+                write!(f, "'{}' is not on the US layout and cannot be typed with modifiers", ch)
+            }
+            KeyEventError::Syntax { text, offset, message } => {
+                write!(f, "Invalid key text '{}' at character {}: {}", text, offset + 1, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeyEventError {}
+
+#[cfg(feature = "serde")]
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// A key press with the modifiers held
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KeyEventDto {
+    /// The key; character-only events are typed the way a US layout types them
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub vk: Option<VkRef>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub character: Option<char>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_false"))]
+    pub shift: bool,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_false"))]
+    pub ctrl: bool,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_false"))]
+    pub alt: bool,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_false"))]
+    pub caps_lock: bool,
+    /// Auto-repeat of a key held down
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_false"))]
+    pub repeat: bool,
+    /// When the key was pressed, in milliseconds; only kept for the caller
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub timestamp_ms: Option<u64>,
+}
+
+impl KeyEventDto {
+    /// A character typed without modifiers
+    pub fn typed(character: char) -> Self {
+        Self { character: Some(character), ..Self::default() }
+    }
+
+    /// A key pressed without modifiers and without a character
+    pub fn key(key: VirtualKey) -> Self {
+        Self { vk: Some(key.into()), ..Self::default() }
+    }
+
+    fn modifiers(&self) -> ModifierState {
+        ModifierState::new(self.shift, self.ctrl, self.alt, self.caps_lock)
+    }
+
+    fn has_modifiers(&self) -> bool {
+        self.shift || self.ctrl || self.alt || self.caps_lock
+    }
+
+    /// The key of the event, if it names one
+    pub fn virtual_key(&self) -> Result<Option<VirtualKey>, KeyEventError> {
+        self.vk.as_ref().map(VkRef::resolve).transpose()
+    }
+
+    /// Builds the engine input. A character without a key gets the key
+    /// and Shift state the US layout types it with.
+    pub fn to_key_input(&self) -> Result<KeyInput, KeyEventError> {
+        match (self.virtual_key()?, self.character) {
+            (Some(key), character) => Ok(KeyInput::new(key as u16, self.modifiers(), character)),
+            (None, Some(character)) => {
+                let mut input = typed_key(character);
+                let shift = input.modifiers.shift || self.shift;
+                input.modifiers = ModifierState { shift, ..self.modifiers() };
+                Ok(input)
+            }
+            (None, None) => Err(KeyEventError::Empty),
+        }
+    }
+
+    /// The event for an engine input, naming its key by KMS name
+    pub fn from_key_input(input: &KeyInput) -> Result<Self, KeyEventError> {
+        let vk = match input.key_code {
+            0 => None,
+            code => Some(virtual_key_for_code(code).ok_or(KeyEventError::UnknownKeyCode(code))?.into()),
+        };
+        if vk.is_none() && input.character.is_none() {
+            return Err(KeyEventError::Empty);
+        }
+        Ok(Self {
+            vk,
+            character: input.character,
+            shift: input.modifiers.shift,
+            ctrl: input.modifiers.ctrl,
+            alt: input.modifiers.alt,
+            caps_lock: input.modifiers.caps_lock,
+            ..Self::default()
+        })
+    }
+
+    /// Adds the character the key types on a US layout when the event has
+    /// none, as pressing the key on a keyboard would; Ctrl and Alt type no
+    /// character
+    pub fn with_us_character(mut self) -> Result<Self, KeyEventError> {
+        if self.character.is_none() && !self.ctrl && !self.alt {
+            if let Some(key) = self.virtual_key()? {
+                self.character = us_character(key, self.shift, self.caps_lock);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Compact text forms of this event, shortest first
+    fn text_candidates(&self) -> Vec<String> {
+        let key = self.virtual_key().ok().flatten();
+        let name = key.map(|key| format!("{{{}}}", short_name(key)));
+        let character = self.character.map(escape_char);
+        // Chords spell the key by its unshifted character; Shift is among the modifiers
+        let base = key.and_then(|key| us_character(key, false, false)).map(escape_char);
+        let mut candidates: Vec<String> = character.iter().chain(&name).cloned().collect();
+        let mut modifiers = String::new();
+        for (held, modifier) in [(self.shift, "Shift"), (self.ctrl, "Ctrl"), (self.alt, "Alt"), (self.caps_lock, "Caps")] {
+            if held {
+                modifiers.push_str(modifier);
+                modifiers.push('+');
+            }
+        }
+        if !modifiers.is_empty() {
+            for character in base.into_iter().chain(character) {
+                candidates.push(format!("<{}{}>", modifiers, character));
+            }
+            candidates.extend(name.map(|name| format!("<{}{}>", modifiers, name)));
+        }
+        candidates
+    }
+}
+
+/// Writes the shortest compact form that types the same key. An event whose
+/// character is not the one the US layout gives its key keeps only the key.
+impl fmt::Display for KeyEventDto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let candidates = self.text_candidates();
+        let input = self.to_key_input().ok();
+        let same_key = |text: &&String| {
+            let parsed = text.parse::<KeyEventDto>().ok();
+            input.is_some() && parsed.and_then(|event| event.to_key_input().ok()) == input
+        };
+        match candidates.iter().find(same_key).or(candidates.last()) {
+            Some(text) => f.write_str(text),
+            None => match &self.vk {
+                Some(VkRef::Name(name)) => write!(f, "{{{}}}", name),
+                Some(VkRef::Code(code)) => write!(f, "{{{:#04x}}}", code),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// Parses one key event in the compact text form
+impl FromStr for KeyEventDto {
+    type Err = KeyEventError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut events = parse_key_sequence(text)?;
+        match events.len() {
+            1 => Ok(events.remove(0)),
+            count => Err(syntax(text, 0, format!("expected one key, found {}", count))),
+        }
+    }
+}
+
+/// Parses a sequence of key events in the compact text form
+pub fn parse_key_sequence(text: &str) -> Result<Vec<KeyEventDto>, KeyEventError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut events = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        match chars[pos] {
+            '<' => {
+                let end = chars[pos..]
+                    .iter()
+                    .position(|&c| c == '>')
+                    .map(|len| pos + len)
+                    .ok_or_else(|| syntax(text, pos, "'<' is not closed; write {<} for the character".to_string()))?;
+                events.extend(parse_chord(text, &chars, pos + 1, end)?);
+                pos = end + 1;
+            }
+            '>' | '}' => return Err(syntax(text, pos, format!("unexpected '{}'; write {{{}}} for the character", chars[pos], chars[pos]))),
+            _ => {
+                let (item, next) = parse_item(text, &chars, pos, chars.len())?;
+                events.push(item.into_event(text, pos, &KeyEventDto::default())?);
+                pos = next;
+            }
+        }
+    }
+    Ok(events)
+}
+
+/// Writes key events in the compact text form
+pub fn format_key_sequence(events: &[KeyEventDto]) -> String {
+    events.iter().map(KeyEventDto::to_string).collect()
+}
+
+/// A character or a `{NAME}` inside or outside a chord
+enum Item {
+    Char(char),
+    Key(VirtualKey),
+}
+
+impl Item {
+    /// The event for this item with the modifiers of `held`
+    fn into_event(self, text: &str, pos: usize, held: &KeyEventDto) -> Result<KeyEventDto, KeyEventError> {
+        let event = KeyEventDto { shift: held.shift, ctrl: held.ctrl, alt: held.alt, caps_lock: held.caps_lock, ..KeyEventDto::default() };
+        match self {
+            Item::Key(key) => Ok(KeyEventDto { vk: Some(key.into()), ..event }),
+            Item::Char(ch) if !held.has_modifiers() => Ok(KeyEventDto { character: Some(ch), ..event }),
+            Item::Char(ch) => {
+                let typed = typed_key(ch);
+                let key = match typed.key_code {
+                    0 => return Err(KeyEventError::NotOnUsLayout(ch)),
+                    code => virtual_key_for_code(code).ok_or_else(|| syntax(text, pos, format!("no key for '{}'", ch)))?,
+                };
+                let shift = event.shift || typed.modifiers.shift;
+                let character = if event.ctrl || event.alt { None } else { us_character(key, shift, event.caps_lock) };
+                Ok(KeyEventDto { vk: Some(key.into()), character, shift, ..event })
+            }
+        }
+    }
+}
+
+/// Parses the item at `pos`, returning it and the position after it
+fn parse_item(text: &str, chars: &[char], pos: usize, end: usize) -> Result<(Item, usize), KeyEventError> {
+    if chars[pos] != '{' {
+        return Ok((Item::Char(chars[pos]), pos + 1));
+    }
+    // `{<}` and the like: a single escaped character
+    if pos + 2 < end && chars[pos + 2] == '}' {
+        return Ok((Item::Char(chars[pos + 1]), pos + 3));
+    }
+    let close = chars[pos..end]
+        .iter()
+        .position(|&c| c == '}')
+        .map(|len| pos + len)
+        .ok_or_else(|| syntax(text, pos, "'{' is not closed".to_string()))?;
+    let name: String = chars[pos + 1..close].iter().collect();
+    if name.trim().is_empty() {
+        return Err(syntax(text, pos, "empty key name".to_string()));
+    }
+    let key = VkRef::Name(name.trim().to_string()).resolve()?;
+    Ok((Item::Key(key), close + 1))
+}
+
+/// Parses the chord between `<` and `>`: modifiers, then the keys they are held for
+fn parse_chord(text: &str, chars: &[char], start: usize, end: usize) -> Result<Vec<KeyEventDto>, KeyEventError> {
+    let mut held = KeyEventDto::default();
+    let mut pos = start;
+    while let Some(plus) = chars[pos..end].iter().position(|&c| c == '+').map(|len| pos + len) {
+        let modifier: String = chars[pos..plus].iter().collect();
+        let flag = match modifier.trim().to_ascii_lowercase().as_str() {
+            "shift" => &mut held.shift,
+            "ctrl" | "control" => &mut held.ctrl,
+            "alt" | "menu" => &mut held.alt,
+            "caps" | "capslock" => &mut held.caps_lock,
+            // `<Shift++>` holds Shift for `+`
+            _ if pos > start => break,
+            _ => return Err(syntax(text, pos, format!("unknown modifier '{}'", modifier))),
+        };
+        *flag = true;
+        pos = plus + 1;
+    }
+    if pos == end {
+        return Err(syntax(text, pos, "no key after the modifiers".to_string()));
+    }
+
+    let mut events = Vec::new();
+    while pos < end {
+        let (item, next) = parse_item(text, chars, pos, end)?;
+        events.push(item.into_event(text, pos, &held)?);
+        pos = next;
+    }
+    Ok(events)
+}
+
+fn syntax(text: &str, offset: usize, message: String) -> KeyEventError {
+    KeyEventError::Syntax { text: text.to_string(), offset, message }
+}
+
+/// Writes `ch` so that it parses back as the character
+fn escape_char(ch: char) -> String {
+    match ch {
+        '<' | '>' | '{' | '}' => format!("{{{}}}", ch),
+        _ => ch.to_string(),
+    }
+}
+
+/// KMS name without the `VK_` prefix
+fn short_name(key: VirtualKey) -> &'static str {
+    let name = key.to_kms_name();
+    name.strip_prefix("VK_").unwrap_or(name)
+}
+
+/// The key with the given engine key code
+fn virtual_key_for_code(code: u16) -> Option<VirtualKey> {
+    create_vk_map()
+        .into_values()
+        .find(|key| *key as u16 == code)
+}
@@ -0,0 +1,491 @@
+//! Per-keyboard key press counts, for layout research
+//!
+//! People designing layouts want to know which physical keys are pressed
+//! most while a keyboard is active. Text services count each key they
+//! process in a block of counters, one slot of 256 per keyboard and day,
+//! which the GUI drains into a [`KeyStatsStore`]. Only the virtual key code
+//! is counted; no characters, text or order of presses.
+//!
+//! Counting is off until the user opts in and the GUI turns the block on.
+//! A host stops counting while its focus is in a password field, see
+//! [`KeyStats::set_suppressed`]. On Windows the block lives in named shared
+//! memory; elsewhere, and in tests, it is process-local.
+//!
+//! Days are UTC days since the Unix epoch. The store keeps daily buckets for
+//! [`DAILY_RETENTION_DAYS`] and merges older ones into monthly buckets.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use crate::recorder::shared_block::{BlockLayout, SharedBlock};
+use crate::types::virtual_keys::VirtualKey;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Name of the section holding the counters on Windows
+#[cfg(windows)]
+pub const SECTION_NAME: &str = r"Local\KeyMagicKeyStats";
+
+/// "KMKS" in little endian
+const BLOCK_MAGIC: u32 = 0x534B_4D4B;
+const BLOCK_VERSION: u32 = 1;
+
+/// Keyboard and day pairs counted until the GUI drains them
+pub const SLOT_COUNT: usize = 16;
+/// One counter per virtual key code
+pub const KEY_COUNT: usize = 256;
+
+/// Daily buckets older than this many days are merged into months
+pub const DAILY_RETENTION_DAYS: u32 = 30;
+
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Set in `flags` while hosts should count keys
+const FLAG_ENABLED: u32 = 1 << 0;
+
+/// Counters of one keyboard on one day
+#[repr(C)]
+struct Slot {
+    /// [`slot_key`] of the keyboard and day, 0 while free
+    key: AtomicU64,
+    counts: [AtomicU32; KEY_COUNT],
+}
+
+impl Slot {
+    /// Takes the counts, leaving zeros
+    fn take(&self, into: &mut [u64]) -> bool {
+        let mut any = false;
+        for (total, count) in into.iter_mut().zip(&self.counts) {
+            let count = count.swap(0, Ordering::AcqRel);
+            *total += count as u64;
+            any |= count != 0;
+        }
+        any
+    }
+}
+
+#[repr(C)]
+struct Block {
+    magic: AtomicU32,
+    version: AtomicU32,
+    flags: AtomicU32,
+    _reserved: AtomicU32,
+    slots: [Slot; SLOT_COUNT],
+}
+
+unsafe impl BlockLayout for Block {
+    fn initialize(&self) {
+        self.wipe();
+        self.flags.store(0, Ordering::Relaxed);
+        self.version.store(BLOCK_VERSION, Ordering::Relaxed);
+        self.magic.store(BLOCK_MAGIC, Ordering::Release);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic.load(Ordering::Acquire) == BLOCK_MAGIC && self.version.load(Ordering::Relaxed) == BLOCK_VERSION
+    }
+}
+
+impl Block {
+    fn wipe(&self) {
+        for slot in &self.slots {
+            slot.key.store(0, Ordering::Relaxed);
+            for count in &slot.counts {
+                count.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Slot key of a keyboard on a day; never 0, which marks a free slot
+fn slot_key(keyboard_hash: u32, day: u32) -> u64 {
+    ((keyboard_hash as u64) << 32 | day as u64) + 1
+}
+
+fn split_slot_key(key: u64) -> (u32, u32) {
+    let key = key - 1;
+    ((key >> 32) as u32, key as u32)
+}
+
+/// FNV-1a hash of a lowercased keyboard id, which is how the counters name
+/// a keyboard
+pub fn hash_keyboard_id(keyboard_id: &str) -> u32 {
+    keyboard_id
+        .chars()
+        .flat_map(char::to_lowercase)
+        .flat_map(|ch| ch.encode_utf16(&mut [0; 2]).to_vec())
+        .fold(0x811c_9dc5u32, |hash, unit| (hash ^ unit as u32).wrapping_mul(0x0100_0193))
+}
+
+/// UTC day of a time in milliseconds since the Unix epoch
+pub fn day_of(unix_ms: u64) -> u32 {
+    (unix_ms / MS_PER_DAY) as u32
+}
+
+/// Counts of one keyboard on one day taken from the block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DailyCounts {
+    pub keyboard_hash: u32,
+    pub day: u32,
+    /// Indexed by virtual key code, [`KEY_COUNT`] long
+    pub counts: Vec<u64>,
+}
+
+/// Handle to the key counters shared by all hosts
+///
+/// Whether this process is suppressed (a password field has focus) is kept
+/// in the handle, not the shared block, so one host never silences another.
+pub struct KeyStats {
+    block: SharedBlock<Block>,
+    suppressed: AtomicBool,
+    /// Slot the last key went to, tried first
+    last_slot: AtomicUsize,
+}
+
+impl KeyStats {
+    fn with_block(block: SharedBlock<Block>) -> Self {
+        Self {
+            block,
+            suppressed: AtomicBool::new(false),
+            last_slot: AtomicUsize::new(0),
+        }
+    }
+
+    /// A process-local block, for tests and platforms without shared state
+    pub fn in_memory() -> Self {
+        Self::with_block(SharedBlock::in_memory())
+    }
+
+    /// Creates the shared block, or attaches to it if it already exists
+    ///
+    /// A new block starts disabled, so hosts count nothing until the GUI
+    /// turns counting on.
+    #[cfg(windows)]
+    pub fn create_shared() -> std::io::Result<Self> {
+        Ok(Self::with_block(SharedBlock::create_shared(SECTION_NAME, "key stats")?))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.block.flags.load(Ordering::Acquire) & FLAG_ENABLED != 0
+    }
+
+    /// Turns counting on or off for every host; turning it off also drops
+    /// the counts not drained yet
+    pub fn set_enabled(&self, enabled: bool) {
+        let block = &*self.block;
+        block.flags.store(if enabled { FLAG_ENABLED } else { 0 }, Ordering::Release);
+        if !enabled {
+            block.wipe();
+        }
+    }
+
+    /// Stops this process counting, while a password field has focus
+    pub fn set_suppressed(&self, suppressed: bool) {
+        self.suppressed.store(suppressed, Ordering::Relaxed);
+    }
+
+    pub fn is_suppressed(&self) -> bool {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+
+    /// Slot of a keyboard and day, claiming a free one if needed
+    fn slot(&self, key: u64) -> Option<&Slot> {
+        let slots = &self.block.slots;
+        let hint = self.last_slot.load(Ordering::Relaxed) % SLOT_COUNT;
+        if slots[hint].key.load(Ordering::Acquire) == key {
+            return Some(&slots[hint]);
+        }
+        let index = slots.iter().position(|slot| slot.key.load(Ordering::Acquire) == key).or_else(|| {
+            slots.iter().position(|slot| {
+                match slot.key.compare_exchange(0, key, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(_) => true,
+                    // Another host claimed it for the same pair
+                    Err(current) => current == key,
+                }
+            })
+        })?;
+        self.last_slot.store(index, Ordering::Relaxed);
+        Some(&slots[index])
+    }
+
+    /// Counts a key press of `vk` with the keyboard on `day`; returns
+    /// whether it was counted
+    ///
+    /// Nothing is counted while counting is off or this process is
+    /// suppressed, or when every slot is taken by other pairs until the GUI
+    /// drains them.
+    pub fn record(&self, keyboard_hash: u32, day: u32, vk: u8) -> bool {
+        if !self.is_enabled() || self.is_suppressed() {
+            return false;
+        }
+        let Some(slot) = self.slot(slot_key(keyboard_hash, day)) else {
+            return false;
+        };
+        slot.counts[vk as usize].fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Takes the counts from the block, freeing the slots of days before
+    /// `today`
+    pub fn drain(&self, today: u32) -> Vec<DailyCounts> {
+        let mut taken: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for slot in &self.block.slots {
+            let key = slot.key.load(Ordering::Acquire);
+            if key == 0 {
+                continue;
+            }
+            let counts = taken.entry(key).or_insert_with(|| vec![0; KEY_COUNT]);
+            slot.take(counts);
+            if split_slot_key(key).1 < today
+                && slot.key.compare_exchange(key, 0, Ordering::AcqRel, Ordering::Relaxed).is_ok()
+            {
+                // Presses that found the slot before it was freed
+                slot.take(counts);
+            }
+        }
+        taken
+            .into_iter()
+            .filter(|(_, counts)| counts.iter().any(|&count| count != 0))
+            .map(|(key, counts)| {
+                let (keyboard_hash, day) = split_slot_key(key);
+                DailyCounts { keyboard_hash, day, counts }
+            })
+            .collect()
+    }
+}
+
+/// Days from 1970-01-01 to a civil date (proleptic Gregorian)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Civil date of a day since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// A day as `YYYY-MM-DD`
+pub fn format_day(day: u32) -> String {
+    let (year, month, day) = civil_from_days(day as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Parses `YYYY-MM-DD`; `None` for malformed dates and ones before 1970
+pub fn parse_day(date: &str) -> Option<u32> {
+    let mut parts = date.trim().splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    // Rejects days past the end of the month
+    (civil_from_days(days) == (year, month, day)).then_some(days).and_then(|days| u32::try_from(days).ok())
+}
+
+/// A period counts are kept for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "snake_case"))]
+pub enum Period {
+    /// UTC day since the Unix epoch
+    Day(u32),
+    /// Months since January 1970
+    Month(u32),
+}
+
+impl Period {
+    /// Month a day falls in
+    pub fn month_of(day: u32) -> Self {
+        let (year, month, _) = civil_from_days(day as i64);
+        Period::Month(((year - 1970) * 12) as u32 + month - 1)
+    }
+
+    /// First and last day of the period
+    pub fn days(&self) -> (u32, u32) {
+        match *self {
+            Period::Day(day) => (day, day),
+            Period::Month(month) => {
+                let (year, month) = (1970 + (month / 12) as i64, month % 12 + 1);
+                let first = days_from_civil(year, month, 1) as u32;
+                let next = if month == 12 { days_from_civil(year + 1, 1, 1) } else { days_from_civil(year, month + 1, 1) };
+                (first, next as u32 - 1)
+            }
+        }
+    }
+
+    /// `YYYY-MM-DD` for days, `YYYY-MM` for months
+    pub fn label(&self) -> String {
+        match self {
+            Period::Day(day) => format_day(*day),
+            Period::Month(_) => format_day(self.days().0)[..7].to_string(),
+        }
+    }
+}
+
+/// Counts of one keyboard over one period, as stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Bucket {
+    pub keyboard_hash: u32,
+    pub period: Period,
+    /// Indexed by virtual key code
+    pub counts: Vec<u64>,
+}
+
+/// Counts of one keyboard summed over a range of days
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct KeyHeatmap {
+    /// First and last day covered, which are widened to whole months when
+    /// the range takes in monthly buckets
+    pub from_day: u32,
+    pub to_day: u32,
+    /// Indexed by virtual key code, [`KEY_COUNT`] long
+    pub counts: Vec<u64>,
+    pub total: u64,
+}
+
+impl KeyHeatmap {
+    /// Keys pressed at least once, most pressed first; ties by key code
+    pub fn keys(&self) -> Vec<(u8, u64)> {
+        let mut keys: Vec<(u8, u64)> = (0..KEY_COUNT)
+            .filter(|&vk| self.counts[vk] != 0)
+            .map(|vk| (vk as u8, self.counts[vk]))
+            .collect();
+        keys.sort_by_key(|&(vk, count)| (std::cmp::Reverse(count), vk));
+        keys
+    }
+}
+
+/// KMS name of a virtual key, or its code in hex
+pub fn key_name(vk: u8) -> String {
+    VirtualKey::from_win_vk(vk as u16).map_or_else(|| format!("0x{:02X}", vk), |key| key.to_kms_name().to_string())
+}
+
+/// Counts drained from the block, by keyboard and period
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyStatsStore {
+    buckets: BTreeMap<(u32, Period), Vec<u64>>,
+}
+
+impl KeyStatsStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_buckets(buckets: impl IntoIterator<Item = Bucket>) -> Self {
+        let mut store = Self::new();
+        for bucket in buckets {
+            store.add(bucket.keyboard_hash, bucket.period, &bucket.counts);
+        }
+        store
+    }
+
+    /// The stored buckets, ordered by keyboard and period
+    pub fn buckets(&self) -> Vec<Bucket> {
+        self.buckets
+            .iter()
+            .map(|(&(keyboard_hash, period), counts)| Bucket { keyboard_hash, period, counts: counts.clone() })
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+    }
+
+    /// Adds counts to a bucket; counts past [`KEY_COUNT`] are ignored
+    pub fn add(&mut self, keyboard_hash: u32, period: Period, counts: &[u64]) {
+        let bucket = self.buckets.entry((keyboard_hash, period)).or_insert_with(|| vec![0; KEY_COUNT]);
+        for (total, count) in bucket.iter_mut().zip(counts) {
+            *total = total.saturating_add(*count);
+        }
+    }
+
+    /// Adds counts drained from the block to their daily buckets
+    pub fn merge(&mut self, drained: &[DailyCounts]) {
+        for daily in drained {
+            self.add(daily.keyboard_hash, Period::Day(daily.day), &daily.counts);
+        }
+    }
+
+    /// Merges daily buckets more than [`DAILY_RETENTION_DAYS`] before
+    /// `today` into their months; returns how many were merged
+    pub fn compact(&mut self, today: u32) -> usize {
+        let old: Vec<(u32, Period)> = self
+            .buckets
+            .keys()
+            .filter(|(_, period)| matches!(period, Period::Day(day) if today.saturating_sub(*day) > DAILY_RETENTION_DAYS))
+            .copied()
+            .collect();
+        for (keyboard_hash, period) in &old {
+            let Period::Day(day) = *period else { continue };
+            if let Some(counts) = self.buckets.remove(&(*keyboard_hash, *period)) {
+                self.add(*keyboard_hash, Period::month_of(day), &counts);
+            }
+        }
+        old.len()
+    }
+
+    /// Buckets of a keyboard overlapping `from_day..=to_day`
+    fn overlapping(&self, keyboard_hash: u32, from_day: u32, to_day: u32) -> impl Iterator<Item = (&Period, &Vec<u64>)> {
+        self.buckets
+            .range((keyboard_hash, Period::Day(0))..=(keyboard_hash, Period::Month(u32::MAX)))
+            .map(|((_, period), counts)| (period, counts))
+            .filter(move |(period, _)| {
+                let (first, last) = period.days();
+                first <= to_day && last >= from_day
+            })
+    }
+
+    /// Counts of a keyboard summed over `from_day..=to_day`
+    ///
+    /// A monthly bucket no longer knows its days, so one overlapping the
+    /// range is counted whole and the range reported is widened to it.
+    pub fn heatmap(&self, keyboard_hash: u32, from_day: u32, to_day: u32) -> KeyHeatmap {
+        let mut heatmap = KeyHeatmap { from_day, to_day, counts: vec![0; KEY_COUNT], total: 0 };
+        for (period, counts) in self.overlapping(keyboard_hash, from_day, to_day) {
+            let (first, last) = period.days();
+            heatmap.from_day = heatmap.from_day.min(first);
+            heatmap.to_day = heatmap.to_day.max(last);
+            for (total, count) in heatmap.counts.iter_mut().zip(counts) {
+                *total = total.saturating_add(*count);
+            }
+        }
+        heatmap.total = heatmap.counts.iter().fold(0u64, |total, count| total.saturating_add(*count));
+        heatmap
+    }
+
+    /// The buckets of a keyboard overlapping `from_day..=to_day` as CSV, one
+    /// row per period and key pressed: `period,vk,key,count`
+    pub fn to_csv(&self, keyboard_hash: u32, from_day: u32, to_day: u32) -> String {
+        let mut csv = String::from("period,vk,key,count\n");
+        for (period, counts) in self.overlapping(keyboard_hash, from_day, to_day) {
+            let label = period.label();
+            for (vk, &count) in counts.iter().enumerate().filter(|(_, &count)| count != 0) {
+                csv.push_str(&format!("{},{},{},{}\n", label, vk, key_name(vk as u8), count));
+            }
+        }
+        csv
+    }
+}
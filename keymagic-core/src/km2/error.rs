@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use super::features::IncompatibleKeyboard;
+
 #[derive(Error, Debug)]
 pub enum Km2Error {
     #[error("Invalid magic code: expected 'KMKL', got {0:?}")]
@@ -8,6 +10,12 @@ pub enum Km2Error {
     #[error("Unsupported version: {major}.{minor}")]
     UnsupportedVersion { major: u8, minor: u8 },
     
+    #[error("Keyboard file version {major}.{minor} is newer than this version of KeyMagic can read (up to {latest_major}.{latest_minor}); update KeyMagic to use this keyboard")]
+    NewerVersion { major: u8, minor: u8, latest_major: u8, latest_minor: u8 },
+    
+    #[error("{0}")]
+    IncompatibleKeyboard(IncompatibleKeyboard),
+    
     #[error("File too small: {0} bytes")]
     FileTooSmall(usize),
     
@@ -29,6 +37,12 @@ pub enum Km2Error {
     #[error("Invalid Predefined usage: VK keys must be preceded by AND operator")]
     InvalidPredefinedUsage,
     
+    #[error("Invalid keyboard JSON: {0}")]
+    InvalidJson(String),
+    
+    #[error("Unsupported keyboard JSON schema version: {0}")]
+    UnsupportedJsonSchema(u64),
+    
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
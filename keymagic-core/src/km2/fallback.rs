@@ -0,0 +1,32 @@
+//! Built-in keyboard that passes every key through
+//!
+//! A host whose keyboard fails to load loads this one instead, so it always
+//! has a working engine. It has no rules, and the engine
+//! `KeyMagicEngine::fallback` builds from it hands every key back to the
+//! application unprocessed. The bytes are [`FALLBACK_KMS`] compiled by
+//! kms2km2; a test keeps the two in step.
+
+/// Source of [`FALLBACK_KM2`]
+pub const FALLBACK_KMS: &str = r#"/*
+@NAME = "KeyMagic Fallback"
+@DESCRIPTION = "Passes every key through"
+@EAT_ALL_UNUSED_KEYS = "FALSE"
+@SMART_BACKSPACE = "FALSE"
+*/
+"#;
+
+/// The fallback keyboard as a km2 1.5 file
+pub const FALLBACK_KM2: &[u8] = &[
+    // Header: magic, version 1.5, 0 strings, 2 info entries, 0 rules
+    0x4b, 0x4d, 0x4b, 0x4c, 0x01, 0x05, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+    // Layout options: track caps, no smart backspace, no eat, right alt
+    0x01, 0x00, 0x00, 0x00, 0x01, 0x00,
+    // "name": "KeyMagic Fallback"
+    0x65, 0x6d, 0x61, 0x6e, 0x11, 0x00,
+    0x4b, 0x65, 0x79, 0x4d, 0x61, 0x67, 0x69, 0x63, 0x20, 0x46, 0x61, 0x6c,
+    0x6c, 0x62, 0x61, 0x63, 0x6b,
+    // "desc": "Passes every key through"
+    0x63, 0x73, 0x65, 0x64, 0x18, 0x00,
+    0x50, 0x61, 0x73, 0x73, 0x65, 0x73, 0x20, 0x65, 0x76, 0x65, 0x72, 0x79,
+    0x20, 0x6b, 0x65, 0x79, 0x20, 0x74, 0x68, 0x72, 0x6f, 0x75, 0x67, 0x68,
+];
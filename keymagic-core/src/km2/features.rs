@@ -0,0 +1,251 @@
+//! Format features a keyboard needs from the engine
+//!
+//! Older engines read files they do not fully understand: an info entry they
+//! do not know is skipped, so a keyboard using a newer feature would load and
+//! then type the wrong text. The compiler therefore records the features a
+//! keyboard uses in an `INFO_FEAT` entry, together with the oldest engine
+//! version that has all of them and their names. The loader refuses a file
+//! with a feature bit this engine does not know and reports it by the name
+//! stored in the file, e.g. "requires KeyMagic engine ≥ 0.3: uses Unicode
+//! ranges".
+//!
+//! Files without the entry are loaded as before.
+
+use std::fmt;
+
+use crate::types::{BinaryFormatElement, InfoEntry, Km2File, PostRules, INFO_FEAT, INFO_GRPL, INFO_GRPS, INFO_POST};
+
+/// A feature a keyboard file can use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Feature {
+    /// Bit in the feature bitmap
+    pub bit: u8,
+    /// Shown to users of engines that lack it
+    pub name: &'static str,
+    /// First engine version that has it
+    pub since: EngineVersion,
+}
+
+const V0_0_9: EngineVersion = EngineVersion::new(0, 0, 9);
+
+/// 32-bit counts and indices (km2 1.6)
+pub const WIDE_INDICES: Feature = Feature { bit: 0, name: "more than 65535 strings, rules or states", since: V0_0_9 };
+/// Rule groups users can turn off
+pub const RULE_GROUPS: Feature = Feature { bit: 1, name: "rule groups", since: V0_0_9 };
+pub const POST_RULES: Feature = Feature { bit: 2, name: "post-rules", since: V0_0_9 };
+/// `@notify` on the RHS
+pub const NOTIFY: Feature = Feature { bit: 3, name: "notifications", since: V0_0_9 };
+pub const CASE_INSENSITIVE_ASCII: Feature = Feature { bit: 4, name: "case-insensitive ASCII matching", since: V0_0_9 };
+
+/// Every feature this engine has
+pub const FEATURES: &[Feature] = &[WIDE_INDICES, RULE_GROUPS, POST_RULES, NOTIFY, CASE_INSENSITIVE_ASCII];
+
+/// An engine version, as in the crate version
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EngineVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl EngineVersion {
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self { major, minor, patch }
+    }
+
+    /// The version of this engine
+    pub fn current() -> Self {
+        let mut parts = crate::VERSION.split(['.', '-']).map(|part| part.parse().unwrap_or(0));
+        Self::new(parts.next().unwrap_or(0), parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+    }
+}
+
+impl fmt::Display for EngineVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// A set of features, as a bitmap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct FeatureSet(pub u64);
+
+impl FeatureSet {
+    /// The features this engine has
+    pub fn supported() -> Self {
+        FEATURES.iter().fold(Self::default(), |set, feature| set.with(*feature))
+    }
+
+    pub fn with(self, feature: Feature) -> Self {
+        Self(self.0 | 1 << feature.bit)
+    }
+
+    pub fn contains(self, feature: Feature) -> bool {
+        self.0 & 1 << feature.bit != 0
+    }
+
+    /// The features of `self` not in `other`
+    pub fn without(self, other: FeatureSet) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Bits set, lowest first
+    pub fn bits(self) -> impl Iterator<Item = u8> {
+        (0..64u8).filter(move |bit| self.0 & 1 << bit != 0)
+    }
+
+    /// Features of this engine in the set, in bit order
+    pub fn known(self) -> impl Iterator<Item = Feature> {
+        FEATURES.iter().copied().filter(move |feature| self.contains(*feature))
+    }
+
+    /// The oldest engine with all the known features of the set
+    pub fn required_version(self) -> EngineVersion {
+        self.known().map(|feature| feature.since).max().unwrap_or_default()
+    }
+}
+
+/// Features a keyboard uses
+pub fn used_features(km2: &Km2File) -> FeatureSet {
+    let metadata = km2.metadata();
+    let mut features = FeatureSet::default();
+    if km2.header.has_wide_indices() {
+        features = features.with(WIDE_INDICES);
+    }
+    if metadata.has(INFO_GRPS) || metadata.has(INFO_GRPL) {
+        features = features.with(RULE_GROUPS);
+    }
+    if metadata.get(INFO_POST).is_some_and(|data| !PostRules::decode(data).is_empty()) {
+        features = features.with(POST_RULES);
+    }
+    if km2.rules.iter().any(|rule| rule.rhs.iter().any(|element| matches!(element, BinaryFormatElement::Notify(_)))) {
+        features = features.with(NOTIFY);
+    }
+    if km2.header.layout_options.case_insensitive_ascii != 0 {
+        features = features.with(CASE_INSENSITIVE_ASCII);
+    }
+    features
+}
+
+/// What a keyboard needs from the engine, as kept in its `INFO_FEAT` entry
+///
+/// Layout (little-endian): the feature bitmap (u64), the required engine
+/// version (three u16), then per feature in bit order its bit (u8), name
+/// length in bytes (u16) and UTF-8 name. Engines that lack a feature can
+/// only name it from the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureRequirements {
+    pub features: FeatureSet,
+    pub engine: EngineVersion,
+    /// Names by bit
+    pub names: Vec<(u8, String)>,
+}
+
+impl FeatureRequirements {
+    /// Requirements of the features of this engine in `features`
+    pub fn of(features: FeatureSet) -> Self {
+        Self {
+            features,
+            engine: features.required_version(),
+            names: features.known().map(|feature| (feature.bit, feature.name.to_string())).collect(),
+        }
+    }
+
+    /// Name of the feature with `bit`, from the file or this engine
+    pub fn name(&self, bit: u8) -> String {
+        self.names
+            .iter()
+            .find(|(b, _)| *b == bit)
+            .map(|(_, name)| name.clone())
+            .or_else(|| FEATURES.iter().find(|feature| feature.bit == bit).map(|feature| feature.name.to_string()))
+            .unwrap_or_else(|| format!("feature {}", bit))
+    }
+
+    /// Fails when the keyboard uses a feature this engine lacks
+    pub fn check(&self) -> Result<(), IncompatibleKeyboard> {
+        let missing = self.features.without(FeatureSet::supported());
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(IncompatibleKeyboard {
+            required_features: self.features,
+            missing,
+            required_version: self.engine,
+            missing_names: missing.bits().map(|bit| self.name(bit)).collect(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.features.0.to_le_bytes());
+        for part in [self.engine.major, self.engine.minor, self.engine.patch] {
+            data.extend_from_slice(&part.to_le_bytes());
+        }
+        for (bit, name) in &self.names {
+            data.push(*bit);
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+        }
+        data
+    }
+
+    /// Decodes an `INFO_FEAT` entry; `None` if it is too short for the
+    /// bitmap. Missing or malformed names are left out.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let features = FeatureSet(u64::from_le_bytes(data.get(..8)?.try_into().ok()?));
+        let part = |index: usize| {
+            data.get(8 + index * 2..10 + index * 2)
+                .map_or(0, |bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        };
+        let engine = EngineVersion::new(part(0), part(1), part(2));
+
+        let mut names = Vec::new();
+        let mut rest = data.get(14..).unwrap_or_default();
+        while let [bit, len_lo, len_hi, tail @ ..] = rest {
+            let len = u16::from_le_bytes([*len_lo, *len_hi]) as usize;
+            let Some(name) = tail.get(..len).and_then(|bytes| std::str::from_utf8(bytes).ok()) else {
+                break;
+            };
+            names.push((*bit, name.to_string()));
+            rest = &tail[len..];
+        }
+        Some(Self { features, engine, names })
+    }
+
+    /// The `INFO_FEAT` entry for a keyboard, `None` if it uses no feature
+    pub fn entry_for(km2: &Km2File) -> Option<InfoEntry> {
+        let features = used_features(km2);
+        (!features.is_empty()).then(|| InfoEntry { id: *INFO_FEAT, data: Self::of(features).encode() })
+    }
+}
+
+/// A keyboard uses features this engine lacks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncompatibleKeyboard {
+    /// Every feature the keyboard uses
+    pub required_features: FeatureSet,
+    /// Those this engine lacks
+    pub missing: FeatureSet,
+    /// The oldest engine that has them, as the file says
+    pub required_version: EngineVersion,
+    /// Names of the missing features, in bit order
+    pub missing_names: Vec<String>,
+}
+
+impl fmt::Display for IncompatibleKeyboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "This keyboard requires KeyMagic engine ≥ {} (this is {}): uses {}",
+            self.required_version,
+            EngineVersion::current(),
+            self.missing_names.join(", ")
+        )
+    }
+}
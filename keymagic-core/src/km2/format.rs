@@ -0,0 +1,85 @@
+//! Definitions of the KM2 binary format shared by the loader and the writer
+//!
+//! Both sides take the magic code, the header layout of each version and the
+//! encoding of rule elements from here, so a change to the format is made
+//! once and the two cannot drift apart.
+
+pub use crate::types::opcodes::*;
+use crate::types::{BinaryFormatElement, KM2_LARGE_VERSION};
+
+/// First four bytes of every KM2 file
+pub const MAGIC: [u8; 4] = *b"KMKL";
+
+/// Files shorter than this are refused before their header is read
+pub const MIN_FILE_SIZE: usize = 12;
+
+/// Rule element opcodes and their names, as shown by dump tools
+pub const OPCODES: &[(u16, &str)] = &[
+    (OP_STRING, "STRING"),
+    (OP_VARIABLE, "VARIABLE"),
+    (OP_REFERENCE, "REFERENCE"),
+    (OP_PREDEFINED, "PREDEFINED"),
+    (OP_MODIFIER, "MODIFIER"),
+    (OP_AND, "AND"),
+    (OP_ANY, "ANY"),
+    (OP_SWITCH, "SWITCH"),
+    (OP_NOTIFY, "NOTIFY"),
+];
+
+/// Name of a rule element opcode, `None` for unknown ones
+pub fn opcode_name(opcode: u16) -> Option<&'static str> {
+    OPCODES.iter().find(|(op, _)| *op == opcode).map(|(_, name)| *name)
+}
+
+/// Whether the header of a version has an info count (1.4 and later)
+pub fn has_info_count(major: u8, minor: u8) -> bool {
+    major > 1 || minor >= 4
+}
+
+/// Whether the header of a version has rightAlt and the padding byte after
+/// it (1.5 and later)
+pub fn has_right_alt(major: u8, minor: u8) -> bool {
+    major > 1 || minor >= 5
+}
+
+/// Whether counts, variable indices and switch indices of a version are u32
+pub fn has_wide_indices(major: u8, minor: u8) -> bool {
+    major == 1 && minor >= KM2_LARGE_VERSION
+}
+
+/// Bytes of the header of a version
+pub fn header_size(major: u8, minor: u8) -> usize {
+    let count_size = if has_wide_indices(major, minor) { 4 } else { 2 };
+    let counts = if has_info_count(major, minor) { 3 } else { 2 };
+    // magic, version, counts, then four option bytes or five and padding
+    let options = if has_right_alt(major, minor) { 6 } else { 4 };
+    MAGIC.len() + 2 + counts * count_size + options
+}
+
+/// Opcode an element is written with
+pub fn element_opcode(element: &BinaryFormatElement) -> u16 {
+    match element {
+        BinaryFormatElement::String(_) => OP_STRING,
+        BinaryFormatElement::Variable(_) => OP_VARIABLE,
+        BinaryFormatElement::Reference(_) => OP_REFERENCE,
+        BinaryFormatElement::Predefined(_) => OP_PREDEFINED,
+        BinaryFormatElement::Modifier(_) => OP_MODIFIER,
+        BinaryFormatElement::And => OP_AND,
+        BinaryFormatElement::Any => OP_ANY,
+        BinaryFormatElement::Switch(_) => OP_SWITCH,
+        BinaryFormatElement::Notify(_) => OP_NOTIFY,
+    }
+}
+
+/// Size of an element in 16-bit units, opcode included; `wide` files take
+/// two units for variable and switch indices
+pub fn element_units(element: &BinaryFormatElement, wide: bool) -> usize {
+    match element {
+        // opcode + length + data
+        BinaryFormatElement::String(s) | BinaryFormatElement::Notify(s) => 2 + s.encode_utf16().count(),
+        BinaryFormatElement::Variable(_) | BinaryFormatElement::Switch(_) => 1 + if wide { 2 } else { 1 },
+        // opcode + parameter
+        BinaryFormatElement::Reference(_) | BinaryFormatElement::Predefined(_) | BinaryFormatElement::Modifier(_) => 2,
+        BinaryFormatElement::And | BinaryFormatElement::Any => 1,
+    }
+}
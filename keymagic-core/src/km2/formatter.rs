@@ -0,0 +1,169 @@
+//! Human-readable formatting of compiled KM2 rules
+//!
+//! Variables and states are rendered by their source names when the file
+//! kept them (see [`RuleFormatter::for_keyboard`]) and by index otherwise
+//! (`$var3`, `('state2')`). The output otherwise follows KMS syntax closely
+//! enough to be recognisable by keyboard authors.
+
+use crate::types::{BinaryFormatElement, IndexNames, Km2File, Rule, StringTable, VirtualKey};
+use crate::types::opcodes::{FLAG_ANYOF, FLAG_NANYOF, PREDEFINED_NULL};
+
+/// Formats rules into a KMS-like textual representation
+pub struct RuleFormatter<'a> {
+    strings: &'a StringTable,
+    state_names: IndexNames,
+    variable_names: IndexNames,
+}
+
+impl<'a> RuleFormatter<'a> {
+    /// Creates a formatter backed by the keyboard's string table
+    pub fn new(strings: &'a StringTable) -> Self {
+        Self { strings, state_names: IndexNames::default(), variable_names: IndexNames::default() }
+    }
+
+    /// Creates a formatter that uses the state and variable names the
+    /// keyboard kept, falling back to indices
+    pub fn for_keyboard(keyboard: &'a Km2File) -> Self {
+        let metadata = keyboard.metadata();
+        Self { strings: &keyboard.strings, state_names: metadata.state_names(), variable_names: metadata.variable_names() }
+    }
+
+    fn variable_name(&self, index: usize) -> String {
+        match self.variable_names.get(index) {
+            Some(name) => format!("${}", name),
+            None => format!("$var{}", index),
+        }
+    }
+
+    fn state_name(&self, index: usize) -> String {
+        match self.state_names.get(index) {
+            Some(name) => name.to_string(),
+            None => format!("state{}", index),
+        }
+    }
+
+    /// Formats a full rule as `lhs => rhs`
+    pub fn format_rule(&self, rule: &Rule) -> String {
+        format!("{} => {}", self.format_elements(&rule.lhs), self.format_elements(&rule.rhs))
+    }
+
+    /// Formats one side of a rule
+    pub fn format_elements(&self, elements: &[BinaryFormatElement]) -> String {
+        let mut parts = Vec::new();
+        let mut i = 0;
+
+        while i < elements.len() {
+            match &elements[i] {
+                BinaryFormatElement::String(s) => parts.push(quote(s)),
+                BinaryFormatElement::Variable(idx) => {
+                    let name = self.variable_name(*idx);
+                    match elements.get(i + 1) {
+                        Some(BinaryFormatElement::Modifier(m)) if *m == FLAG_ANYOF => {
+                            parts.push(format!("{}[*]", name));
+                            i += 1;
+                        }
+                        Some(BinaryFormatElement::Modifier(m)) if *m == FLAG_NANYOF => {
+                            parts.push(format!("{}[^]", name));
+                            i += 1;
+                        }
+                        Some(BinaryFormatElement::Modifier(m)) => {
+                            parts.push(format!("{}[${}]", name, m));
+                            i += 1;
+                        }
+                        _ => parts.push(name),
+                    }
+                }
+                BinaryFormatElement::Reference(idx) => parts.push(format!("${}", idx)),
+                BinaryFormatElement::And => {
+                    // Collect the VK combination following AND
+                    let mut keys = Vec::new();
+                    while let Some(BinaryFormatElement::Predefined(vk)) = elements.get(i + 1) {
+                        keys.push(vk_name(*vk));
+                        i += 1;
+                    }
+                    parts.push(format!("<{}>", keys.join(" & ")));
+                }
+                BinaryFormatElement::Predefined(vk) => {
+                    if *vk == PREDEFINED_NULL {
+                        parts.push("NULL".to_string());
+                    } else {
+                        parts.push(vk_name(*vk));
+                    }
+                }
+                BinaryFormatElement::Modifier(m) => parts.push(format!("[{}]", m)),
+                BinaryFormatElement::Any => parts.push("ANY".to_string()),
+                BinaryFormatElement::Switch(idx) => parts.push(format!("('{}')", self.state_name(*idx))),
+                BinaryFormatElement::Notify(message) => parts.push(format!("@notify {}", quote(message))),
+            }
+            i += 1;
+        }
+
+        if parts.is_empty() {
+            "\"\"".to_string()
+        } else {
+            parts.join(" + ")
+        }
+    }
+
+    /// Formats a variable (1-based index) together with its content
+    pub fn format_variable(&self, index: usize) -> Option<String> {
+        self.strings
+            .get(index.checked_sub(1)?)
+            .map(|entry| format!("{} = {}", self.variable_name(index), quote(&entry.value)))
+    }
+}
+
+fn vk_name(raw: u16) -> String {
+    VirtualKey::from_raw(raw)
+        .map(|vk| vk.to_kms_name().to_string())
+        .unwrap_or_else(|| format!("VK_{}", raw))
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::StringEntry;
+
+    #[test]
+    fn test_format_rule() {
+        let strings = StringTable::from(vec![StringEntry { value: "abc".to_string() }]);
+        let formatter = RuleFormatter::new(&strings);
+
+        let rule = Rule {
+            lhs: vec![
+                BinaryFormatElement::Variable(1),
+                BinaryFormatElement::Modifier(FLAG_ANYOF),
+                BinaryFormatElement::And,
+                BinaryFormatElement::Predefined(VirtualKey::Shift as u16),
+                BinaryFormatElement::Predefined(VirtualKey::KeyA as u16),
+            ],
+            rhs: vec![
+                BinaryFormatElement::String("x\"".to_string()),
+                BinaryFormatElement::Reference(1),
+                BinaryFormatElement::Switch(0),
+            ],
+        };
+
+        assert_eq!(
+            formatter.format_rule(&rule),
+            "$var1[*] + <VK_SHIFT & VK_KEY_A> => \"x\\\"\" + $1 + ('state0')"
+        );
+        assert_eq!(formatter.format_variable(1).as_deref(), Some("$var1 = \"abc\""));
+        assert_eq!(formatter.format_variable(0), None);
+    }
+}
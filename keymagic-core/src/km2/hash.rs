@@ -0,0 +1,27 @@
+//! Content hashes of keyboard files
+//!
+//! The GUI records the hash of every installed keyboard file, and engines
+//! remember the hash of the file they were loaded from. A host whose engine
+//! was loaded before the file was replaced, by an update of a bundled
+//! keyboard for instance, sees the two differ and loads the keyboard again.
+
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of a keyboard file as lowercase hex, the form the GUI records
+pub fn content_hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Whether an engine loaded from a file hashing to `loaded` should load the
+/// keyboard again, now that its file is recorded as hashing to `expected`
+///
+/// An empty `expected` hash is unknown, as recorded by GUI versions that
+/// did not hash keyboards, and never asks for a reload. An engine without a
+/// loaded hash has nothing to compare and is reloaded.
+pub fn needs_reload(loaded: Option<&str>, expected: &str) -> bool {
+    let expected = expected.trim();
+    if expected.is_empty() {
+        return false;
+    }
+    !loaded.is_some_and(|loaded| loaded.eq_ignore_ascii_case(expected))
+}
@@ -0,0 +1,116 @@
+//! Lossless JSON representation of a KM2 keyboard
+//!
+//! Used by external layout editors. Converting a loaded file to JSON and
+//! back gives the same `Km2File`, so writing it again produces the same
+//! bytes. Header counts are not stored; they follow from the arrays.
+
+use crate::types::{FileHeader, InfoEntry, Km2File, LayoutOptions, Rule, StringEntry, KM2_LATEST_VERSION, KM2_LEGACY_LIMIT};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::error::{Km2Error, Result};
+
+/// Schema version written to and accepted from JSON documents
+pub const KM2_JSON_SCHEMA_VERSION: u32 = 1;
+
+/// KM2 format version of the keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormatVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+/// A keyboard as a JSON document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Km2Json {
+    pub schema_version: u32,
+    pub format_version: FormatVersion,
+    pub layout_options: LayoutOptions,
+    pub strings: Vec<StringEntry>,
+    pub info: Vec<InfoEntry>,
+    pub rules: Vec<Rule>,
+    /// Top-level fields this version does not know, kept so that a document
+    /// read and written again by an editor loses nothing. They have no place
+    /// in a KM2 file and are dropped by `into_km2`.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Km2Json {
+    pub fn from_km2(km2: &Km2File) -> Self {
+        Self {
+            schema_version: KM2_JSON_SCHEMA_VERSION,
+            format_version: FormatVersion {
+                major: km2.header.major_version,
+                minor: km2.header.minor_version,
+            },
+            layout_options: km2.header.layout_options,
+            strings: km2.strings.iter().cloned().collect(),
+            info: km2.info.clone(),
+            rules: km2.rules.clone(),
+            extra: Map::new(),
+        }
+    }
+
+    /// Parses a document, checking the schema version before anything else
+    pub fn parse(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json).map_err(|e| Km2Error::InvalidJson(e.to_string()))?;
+        let version = value
+            .get("schema_version")
+            .ok_or_else(|| Km2Error::InvalidJson("missing schema_version".to_string()))?
+            .as_u64()
+            .ok_or_else(|| Km2Error::InvalidJson("schema_version is not a number".to_string()))?;
+        if version == 0 || version > KM2_JSON_SCHEMA_VERSION as u64 {
+            return Err(Km2Error::UnsupportedJsonSchema(version));
+        }
+        serde_json::from_value(value).map_err(|e| Km2Error::InvalidJson(e.to_string()))
+    }
+
+    pub fn into_km2(self) -> Result<Km2File> {
+        let FormatVersion { major, minor } = self.format_version;
+        if major > 1 || minor > KM2_LATEST_VERSION {
+            return Err(Km2Error::NewerVersion { major, minor, latest_major: 1, latest_minor: KM2_LATEST_VERSION });
+        }
+        if major != 1 || minor < 3 {
+            return Err(Km2Error::UnsupportedVersion { major, minor });
+        }
+        if minor == 3 && !self.info.is_empty() {
+            return Err(Km2Error::InvalidJson("format 1.3 has no info entries".to_string()));
+        }
+        let limit = if minor >= 6 { u32::MAX as usize } else { KM2_LEGACY_LIMIT };
+        let count = |what: &str, len: usize| {
+            if len > limit {
+                return Err(Km2Error::InvalidJson(format!("too many {} for format {}.{}: {}", what, major, minor, len)));
+            }
+            Ok(len as u32)
+        };
+
+        let header = FileHeader {
+            magic_code: super::format::MAGIC,
+            major_version: major,
+            minor_version: minor,
+            string_count: count("strings", self.strings.len())?,
+            info_count: count("info entries", self.info.len())?,
+            rule_count: count("rules", self.rules.len())?,
+            layout_options: self.layout_options,
+        };
+        Ok(Km2File {
+            header,
+            strings: self.strings.into(),
+            info: self.info,
+            rules: self.rules,
+        })
+    }
+}
+
+impl Km2File {
+    /// Pretty-printed JSON document for the keyboard, see [`Km2Json`]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&Km2Json::from_km2(self)).expect("KM2 JSON serialization cannot fail")
+    }
+
+    /// Reads a keyboard from a JSON document written by [`Km2File::to_json`]
+    pub fn from_json(json: &str) -> Result<Km2File> {
+        Km2Json::parse(json)?.into_km2()
+    }
+}
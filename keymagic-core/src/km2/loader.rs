@@ -1,6 +1,7 @@
-use crate::types::{FileHeader, FileHeader_1_3, FileHeader_1_4, Km2File, StringEntry, InfoEntry, Rule, BinaryFormatElement, LayoutOptions};
-use crate::types::opcodes::*;
+use crate::types::{FileHeader, FileHeader_1_3, FileHeader_1_4, Km2File, StringTable, InfoEntry, Rule, BinaryFormatElement, LayoutOptions, INFO_FEAT, INFO_OPTS, KM2_LATEST_VERSION};
 use super::error::{Km2Error, Result};
+use super::features::FeatureRequirements;
+use super::format::*;
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::io::{Cursor, Read, Seek, SeekFrom};
 
@@ -12,24 +13,24 @@ impl Km2Loader {
         let mut cursor = Cursor::new(data);
         
         // Read header
-        let header = Self::read_header(&mut cursor)?;
-        
-        // Validate version (we support 1.3, 1.4, and 1.5)
-        if header.major_version != 1 || header.minor_version < 3 || header.minor_version > 5 {
-            return Err(Km2Error::UnsupportedVersion {
-                major: header.major_version,
-                minor: header.minor_version,
-            });
-        }
+        let mut header = Self::read_header(&mut cursor)?;
         
         // Read strings
         let strings = Self::read_strings(&mut cursor, header.string_count as usize)?;
         
         // Read info entries
         let info = Self::read_info(&mut cursor, header.info_count as usize)?;
+        if let Some(entry) = info.iter().find(|entry| &entry.id == INFO_OPTS) {
+            header.layout_options.apply_extended_entry(&entry.data);
+        }
+        // Refuse keyboards that need features this engine lacks before
+        // reading rules it may not understand
+        if let Some(requirements) = info.iter().find(|entry| &entry.id == INFO_FEAT).and_then(|entry| FeatureRequirements::decode(&entry.data)) {
+            requirements.check().map_err(Km2Error::IncompatibleKeyboard)?;
+        }
         
         // Read rules
-        let rules = Self::read_rules(&mut cursor, header.rule_count as usize)?;
+        let rules = Self::read_rules(&mut cursor, header.rule_count as usize, header.has_wide_indices())?;
         
         Ok(Km2File {
             header,
@@ -41,37 +42,41 @@ impl Km2Loader {
     
     /// Read file header
     fn read_header(cursor: &mut Cursor<&[u8]>) -> Result<FileHeader> {
-        if cursor.get_ref().len() < 12 {
+        if cursor.get_ref().len() < MIN_FILE_SIZE {
             return Err(Km2Error::FileTooSmall(cursor.get_ref().len()));
         }
         
         let mut magic_code = [0u8; 4];
         cursor.read_exact(&mut magic_code)?;
         
-        if &magic_code != b"KMKL" {
+        if magic_code != MAGIC {
             return Err(Km2Error::InvalidMagicCode(magic_code));
         }
         
         let major_version = cursor.read_u8()?;
         let minor_version = cursor.read_u8()?;
         
-        // Handle different versions
+        // Handle different versions (we support 1.3 to 1.6)
         let header = match (major_version, minor_version) {
             (1, 3) => Self::read_header_v1_3(cursor)?,
             (1, 4) => Self::read_header_v1_4(cursor)?,
             (1, 5) => Self::read_header_v1_5(cursor)?,
+            (1, 6) => Self::read_header_v1_6(cursor)?,
+            // The counts of newer files may not be where we would read them
+            (major, minor) if major > 1 || minor > KM2_LATEST_VERSION => {
+                return Err(Km2Error::NewerVersion {
+                    major: major_version,
+                    minor: minor_version,
+                    latest_major: 1,
+                    latest_minor: KM2_LATEST_VERSION,
+                });
+            }
+            // Very old version, not supported
             _ => {
-                // For unknown versions, try to read as v1.5
-                // but allow older versions we haven't explicitly handled
-                if major_version == 1 && minor_version < 3 {
-                    // Very old version, not supported
-                    return Err(Km2Error::UnsupportedVersion {
-                        major: major_version,
-                        minor: minor_version,
-                    });
-                }
-                // Newer version, try to read as v1.5
-                Self::read_header_v1_5(cursor)?
+                return Err(Km2Error::UnsupportedVersion {
+                    major: major_version,
+                    minor: minor_version,
+                });
             }
         };
         
@@ -96,15 +101,16 @@ impl Km2Loader {
             magic_code: header_1_3.magic_code,
             major_version: header_1_3.major_version,
             minor_version: header_1_3.minor_version,
-            string_count: header_1_3.string_count,
+            string_count: header_1_3.string_count as u32,
             info_count: 0, // v1.3 doesn't have info section
-            rule_count: header_1_3.rule_count,
+            rule_count: header_1_3.rule_count as u32,
             layout_options: LayoutOptions {
                 track_caps: header_1_3.layout_options.track_caps,
                 auto_bksp: header_1_3.layout_options.auto_bksp,
                 eat: header_1_3.layout_options.eat,
                 pos_based: header_1_3.layout_options.pos_based,
                 right_alt: 1, // Default to true for older versions
+                case_insensitive_ascii: 0,
             },
         })
     }
@@ -127,21 +133,34 @@ impl Km2Loader {
             magic_code: header_1_4.magic_code,
             major_version: header_1_4.major_version,
             minor_version: header_1_4.minor_version,
-            string_count: header_1_4.string_count,
-            info_count: header_1_4.info_count,
-            rule_count: header_1_4.rule_count,
+            string_count: header_1_4.string_count as u32,
+            info_count: header_1_4.info_count as u32,
+            rule_count: header_1_4.rule_count as u32,
             layout_options: LayoutOptions {
                 track_caps: header_1_4.layout_options.track_caps,
                 auto_bksp: header_1_4.layout_options.auto_bksp,
                 eat: header_1_4.layout_options.eat,
                 pos_based: header_1_4.layout_options.pos_based,
                 right_alt: 1, // Default to true for older versions
+                case_insensitive_ascii: 0,
             },
         })
     }
     
-    /// Read version 1.5 header (current version)
+    /// Read version 1.5 header
     fn read_header_v1_5(cursor: &mut Cursor<&[u8]>) -> Result<FileHeader> {
+        Self::read_header_with_counts(cursor, |cursor| Ok(cursor.read_u16::<LittleEndian>()? as u32))
+    }
+    
+    /// Read version 1.6 header: the 1.5 layout with u32 counts
+    fn read_header_v1_6(cursor: &mut Cursor<&[u8]>) -> Result<FileHeader> {
+        Self::read_header_with_counts(cursor, |cursor| Ok(cursor.read_u32::<LittleEndian>()?))
+    }
+    
+    fn read_header_with_counts(
+        cursor: &mut Cursor<&[u8]>,
+        read_count: fn(&mut Cursor<&[u8]>) -> Result<u32>,
+    ) -> Result<FileHeader> {
         // Reset to start if we've already read version bytes
         cursor.seek(SeekFrom::Start(0))?;
         
@@ -150,9 +169,9 @@ impl Km2Loader {
         
         let major_version = cursor.read_u8()?;
         let minor_version = cursor.read_u8()?;
-        let string_count = cursor.read_u16::<LittleEndian>()?;
-        let info_count = cursor.read_u16::<LittleEndian>()?;
-        let rule_count = cursor.read_u16::<LittleEndian>()?;
+        let string_count = read_count(cursor)?;
+        let info_count = read_count(cursor)?;
+        let rule_count = read_count(cursor)?;
         
         let layout_options = LayoutOptions {
             track_caps: cursor.read_u8()?,
@@ -160,6 +179,7 @@ impl Km2Loader {
             eat: cursor.read_u8()?,
             pos_based: cursor.read_u8()?,
             right_alt: cursor.read_u8()?,
+            case_insensitive_ascii: 0, // from the opts info entry
         };
         
         // Skip C++ struct padding byte
@@ -176,30 +196,39 @@ impl Km2Loader {
         })
     }
     
-    /// Read string table
-    fn read_strings(cursor: &mut Cursor<&[u8]>, count: usize) -> Result<Vec<StringEntry>> {
-        let mut strings = Vec::with_capacity(count);
+    /// Room to reserve for `count` entries of at least `min_size` bytes, so a
+    /// count from a damaged file cannot reserve more than the file could hold
+    fn capacity_for(cursor: &Cursor<&[u8]>, count: usize, min_size: usize) -> usize {
+        let remaining = cursor.get_ref().len().saturating_sub(cursor.position() as usize);
+        count.min(remaining / min_size)
+    }
+    
+    /// Read string table. The entries are only checked here; they are
+    /// decoded when first used (see `StringTable`).
+    fn read_strings(cursor: &mut Cursor<&[u8]>, count: usize) -> Result<StringTable> {
+        let mut raw = Vec::new();
+        let mut spans = Vec::with_capacity(Self::capacity_for(cursor, count, 2));
         
         for _ in 0..count {
             let length = cursor.read_u16::<LittleEndian>()? as usize;
-            let mut utf16_data = vec![0u16; length];
+            let start = raw.len();
+            raw.resize(start + length * 2, 0);
+            cursor.read_exact(&mut raw[start..])?;
             
-            for i in 0..length {
-                utf16_data[i] = cursor.read_u16::<LittleEndian>()?;
+            let units = raw[start..].chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+            if char::decode_utf16(units).any(|c| c.is_err()) {
+                return Err(Km2Error::InvalidUtf16(cursor.position() as usize));
             }
             
-            let value = String::from_utf16(&utf16_data)
-                .map_err(|_| Km2Error::InvalidUtf16(cursor.position() as usize))?;
-            
-            strings.push(StringEntry { value });
+            spans.push(start..raw.len());
         }
         
-        Ok(strings)
+        Ok(StringTable::from_utf16le(raw, spans))
     }
     
     /// Read info section
     fn read_info(cursor: &mut Cursor<&[u8]>, count: usize) -> Result<Vec<InfoEntry>> {
-        let mut info = Vec::with_capacity(count);
+        let mut info = Vec::with_capacity(Self::capacity_for(cursor, count, 6));
         
         for _ in 0..count {
             let mut id = [0u8; 4];
@@ -215,14 +244,14 @@ impl Km2Loader {
         Ok(info)
     }
     
-    /// Read rules section
-    fn read_rules(cursor: &mut Cursor<&[u8]>, count: usize) -> Result<Vec<Rule>> {
-        let mut rules = Vec::with_capacity(count);
+    /// Read rules section; `wide` files have 32-bit variable and switch indices
+    fn read_rules(cursor: &mut Cursor<&[u8]>, count: usize, wide: bool) -> Result<Vec<Rule>> {
+        let mut rules = Vec::with_capacity(Self::capacity_for(cursor, count, 4));
         
         for i in 0..count {
             // Read LHS (size is in 16-bit units, convert to bytes)
             let lhs_len = cursor.read_u16::<LittleEndian>()? as usize;
-            let lhs = Self::read_rule_elements(cursor, lhs_len * 2)
+            let lhs = Self::read_rule_elements(cursor, lhs_len * 2, wide)
                 .map_err(|_| Km2Error::InvalidRule(i))?;
             
             // Validate LHS: Predefined elements must be preceded by AND
@@ -231,7 +260,7 @@ impl Km2Loader {
             
             // Read RHS (size is in 16-bit units, convert to bytes)
             let rhs_len = cursor.read_u16::<LittleEndian>()? as usize;
-            let rhs = Self::read_rule_elements(cursor, rhs_len * 2)
+            let rhs = Self::read_rule_elements(cursor, rhs_len * 2, wide)
                 .map_err(|_| Km2Error::InvalidRule(i))?;
             
             rules.push(Rule { lhs, rhs });
@@ -241,7 +270,23 @@ impl Km2Loader {
     }
     
     /// Read rule elements
-    fn read_rule_elements(cursor: &mut Cursor<&[u8]>, byte_len: usize) -> Result<Vec<BinaryFormatElement>> {
+    fn read_rule_elements(cursor: &mut Cursor<&[u8]>, byte_len: usize, wide: bool) -> Result<Vec<BinaryFormatElement>> {
+        let read_index = |cursor: &mut Cursor<&[u8]>| -> Result<usize> {
+            Ok(if wide {
+                cursor.read_u32::<LittleEndian>()? as usize
+            } else {
+                cursor.read_u16::<LittleEndian>()? as usize
+            })
+        };
+        let read_utf16 = |cursor: &mut Cursor<&[u8]>| -> Result<String> {
+            let length = cursor.read_u16::<LittleEndian>()? as usize;
+            let mut utf16_data = vec![0u16; length];
+            for unit in utf16_data.iter_mut() {
+                *unit = cursor.read_u16::<LittleEndian>()?;
+            }
+            String::from_utf16(&utf16_data)
+                .map_err(|_| Km2Error::InvalidUtf16(cursor.position() as usize))
+        };
         let start_pos = cursor.position() as usize;
         let mut elements = Vec::new();
         
@@ -249,18 +294,9 @@ impl Km2Loader {
             let opcode = cursor.read_u16::<LittleEndian>()?;
             
             let element = match opcode {
-                OP_STRING => {
-                    let length = cursor.read_u16::<LittleEndian>()? as usize;
-                    let mut utf16_data = vec![0u16; length];
-                    for i in 0..length {
-                        utf16_data[i] = cursor.read_u16::<LittleEndian>()?;
-                    }
-                    let value = String::from_utf16(&utf16_data)
-                        .map_err(|_| Km2Error::InvalidUtf16(cursor.position() as usize))?;
-                    BinaryFormatElement::String(value)
-                }
+                OP_STRING => BinaryFormatElement::String(read_utf16(cursor)?),
                 OP_VARIABLE => {
-                    let index = cursor.read_u16::<LittleEndian>()? as usize;
+                    let index = read_index(cursor)?;
                     BinaryFormatElement::Variable(index)
                 }
                 OP_REFERENCE => {
@@ -282,9 +318,10 @@ impl Km2Loader {
                     BinaryFormatElement::Any
                 }
                 OP_SWITCH => {
-                    let state_index = cursor.read_u16::<LittleEndian>()? as usize;
+                    let state_index = read_index(cursor)?;
                     BinaryFormatElement::Switch(state_index)
                 }
+                OP_NOTIFY => BinaryFormatElement::Notify(read_utf16(cursor)?),
                 _ => return Err(Km2Error::InvalidOpcode(opcode))
             };
             
@@ -351,6 +388,28 @@ mod tests {
         assert_eq!(km2.rules.len(), 0);
     }
     
+    #[test]
+    fn test_load_empty_v1_6() {
+        let mut data = vec![];
+        data.extend_from_slice(b"KMKL"); // magic
+        data.push(1); // major version
+        data.push(6); // minor version
+        data.extend_from_slice(&0u32.to_le_bytes()); // string count
+        data.extend_from_slice(&0u32.to_le_bytes()); // info count
+        data.extend_from_slice(&0u32.to_le_bytes()); // rule count
+        data.extend_from_slice(&[1, 0, 0, 0, 1]); // layout options
+        data.push(0); // padding byte
+        
+        let km2 = Km2Loader::load(&data).unwrap();
+        assert!(km2.header.has_wide_indices());
+        assert_eq!(km2.header.layout_options.right_alt, 1);
+        assert_eq!(km2.rules.len(), 0);
+        
+        // A damaged count is not trusted for reservations
+        data[14..18].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Km2Loader::load(&data).is_err());
+    }
+    
     #[test]
     fn test_invalid_magic() {
         let mut data = vec![];
@@ -1,5 +1,20 @@
 pub mod loader;
 pub mod error;
+pub mod fallback;
+pub mod formatter;
+pub mod features;
+pub mod hash;
+pub mod format;
+pub mod writer;
+#[cfg(feature = "json")]
+pub mod json;
 
 pub use loader::Km2Loader;
-pub use error::Km2Error;
\ No newline at end of file
+pub use error::Km2Error;
+pub use fallback::{FALLBACK_KM2, FALLBACK_KMS};
+pub use formatter::RuleFormatter;
+pub use features::{used_features, EngineVersion, FeatureRequirements, FeatureSet, IncompatibleKeyboard};
+pub use hash::{content_hash, needs_reload};
+pub use writer::{check_format_limits, Km2Writer};
+#[cfg(feature = "json")]
+pub use json::{Km2Json, KM2_JSON_SCHEMA_VERSION};
@@ -0,0 +1,200 @@
+//! Writes keyboards in the KM2 binary format read by [`Km2Loader`](super::Km2Loader)
+
+use crate::types::km2::*;
+use crate::KmsError;
+use super::format::{self, element_opcode, element_units};
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io::Write;
+
+/// Checks that the keyboard fits the format version in its header, so
+/// nothing is truncated when it is written
+pub fn check_format_limits(km2: &Km2File) -> std::result::Result<(), KmsError> {
+    let header = km2.header;
+    if header.has_wide_indices() {
+        return Ok(());
+    }
+    let too_large = |what: &str, count: usize| {
+        if count > KM2_LEGACY_LIMIT {
+            return Err(KmsError::TooLargeForVersion {
+                what: what.to_string(),
+                count,
+                limit: KM2_LEGACY_LIMIT,
+                major: header.major_version,
+                minor: header.minor_version,
+            });
+        }
+        Ok(())
+    };
+    too_large("strings", km2.strings.len())?;
+    too_large("info entries", km2.info.len())?;
+    too_large("rules", km2.rules.len())?;
+
+    // Variable indices are 1-based and so covered by the string count
+    too_large("switch states", km2.state_count())
+}
+
+fn utf16_len(what: impl FnOnce() -> String, s: &str) -> std::result::Result<u16, KmsError> {
+    let count = s.encode_utf16().count();
+    u16::try_from(count).map_err(|_| KmsError::TooLong { what: what(), count, limit: u16::MAX as usize })
+}
+
+pub struct Km2Writer<W: Write> {
+    writer: W,
+    /// Variable and switch indices are u32 (1.6 and later)
+    wide: bool,
+}
+
+impl<W: Write> Km2Writer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, wide: false }
+    }
+
+    pub fn write_km2_file(mut self, km2: &Km2File) -> std::result::Result<(), KmsError> {
+        check_format_limits(km2)?;
+        self.wide = km2.header.has_wide_indices();
+        
+        // Write header
+        self.write_header(&km2.header)?;
+        
+        // Write strings
+        for string in &km2.strings {
+            self.write_string(&string.value)?;
+        }
+        
+        // Write info entries
+        for info in &km2.info {
+            self.write_info(info)?;
+        }
+        
+        // Write rules
+        for (index, rule) in km2.rules.iter().enumerate() {
+            self.write_rule(index, rule)?;
+        }
+        
+        Ok(())
+    }
+
+    fn write_header(&mut self, header: &FileHeader) -> std::result::Result<(), KmsError> {
+        // Magic code
+        self.writer.write_all(&header.magic_code)?;
+        
+        // Version
+        self.writer.write_u8(header.major_version)?;
+        self.writer.write_u8(header.minor_version)?;
+        
+        // Older files keep their header layout: 1.3 has no info count and
+        // neither 1.3 nor 1.4 has rightAlt or the padding byte
+        let (major, minor) = (header.major_version, header.minor_version);
+        
+        // Counts, u32 from 1.6 on (check_format_limits made sure the
+        // counts of older versions fit)
+        let wide = header.has_wide_indices();
+        let mut write_count = |count: u32| {
+            if wide {
+                self.writer.write_u32::<LittleEndian>(count)
+            } else {
+                self.writer.write_u16::<LittleEndian>(count as u16)
+            }
+        };
+        write_count(header.string_count)?;
+        if format::has_info_count(major, minor) {
+            write_count(header.info_count)?;
+        }
+        write_count(header.rule_count)?;
+        
+        // Layout options
+        self.writer.write_u8(header.layout_options.track_caps)?;
+        self.writer.write_u8(header.layout_options.auto_bksp)?;
+        self.writer.write_u8(header.layout_options.eat)?;
+        self.writer.write_u8(header.layout_options.pos_based)?;
+        if !format::has_right_alt(major, minor) {
+            return Ok(());
+        }
+        self.writer.write_u8(header.layout_options.right_alt)?;
+        
+        // Padding byte to match C++ struct alignment
+        self.writer.write_u8(0)?;
+        
+        Ok(())
+    }
+
+    fn write_string(&mut self, s: &str) -> std::result::Result<(), KmsError> {
+        // Write length (number of UTF-16 code units)
+        let len = utf16_len(|| "A string".to_string(), s)?;
+        self.writer.write_u16::<LittleEndian>(len)?;
+        
+        // Write UTF-16LE data
+        for code_unit in s.encode_utf16() {
+            self.writer.write_u16::<LittleEndian>(code_unit)?;
+        }
+        
+        Ok(())
+    }
+
+    fn write_info(&mut self, info: &InfoEntry) -> std::result::Result<(), KmsError> {
+        // Write ID (4 bytes)
+        self.writer.write_all(&info.id)?;
+        
+        // Write length
+        self.writer.write_u16::<LittleEndian>(info.data.len() as u16)?;
+        
+        // Write data
+        self.writer.write_all(&info.data)?;
+        
+        Ok(())
+    }
+
+    fn write_rule(&mut self, index: usize, rule: &Rule) -> std::result::Result<(), KmsError> {
+        // Write LHS
+        self.write_rule_elements(index, "left", &rule.lhs)?;
+        
+        // Write RHS
+        self.write_rule_elements(index, "right", &rule.rhs)?;
+        
+        Ok(())
+    }
+
+    fn write_rule_elements(&mut self, index: usize, side: &str, elements: &[BinaryFormatElement]) -> std::result::Result<(), KmsError> {
+        // Calculate total size in opcodes
+        let count: usize = elements.iter().map(|elem| element_units(elem, self.wide)).sum();
+        let size = u16::try_from(count).map_err(|_| KmsError::TooLong {
+            what: format!("The {} side of rule {}", side, index + 1),
+            count,
+            limit: u16::MAX as usize,
+        })?;
+        
+        // Write size
+        self.writer.write_u16::<LittleEndian>(size)?;
+        
+        // Write elements
+        for elem in elements {
+            self.write_rule_element(elem)?;
+        }
+        
+        Ok(())
+    }
+
+    fn write_index(&mut self, index: usize) -> std::result::Result<(), KmsError> {
+        if self.wide {
+            self.writer.write_u32::<LittleEndian>(index as u32)?;
+        } else {
+            self.writer.write_u16::<LittleEndian>(index as u16)?;
+        }
+        Ok(())
+    }
+
+    fn write_rule_element(&mut self, elem: &BinaryFormatElement) -> std::result::Result<(), KmsError> {
+        self.writer.write_u16::<LittleEndian>(element_opcode(elem))?;
+        match elem {
+            BinaryFormatElement::String(s) | BinaryFormatElement::Notify(s) => self.write_string(s)?,
+            BinaryFormatElement::Variable(idx) | BinaryFormatElement::Switch(idx) => self.write_index(*idx)?,
+            BinaryFormatElement::Reference(idx) => self.writer.write_u16::<LittleEndian>(*idx as u16)?,
+            BinaryFormatElement::Predefined(param) | BinaryFormatElement::Modifier(param) => {
+                self.writer.write_u16::<LittleEndian>(*param)?;
+            }
+            BinaryFormatElement::And | BinaryFormatElement::Any => {}
+        }
+        
+        Ok(())
+    }
+}
@@ -1,15 +1,72 @@
+//! KeyMagic engine: loads KM2 keyboard files and turns key presses into text
+//!
+//! The crate root exports the engine, its input and output, keyboard files
+//! and the error types. The rest of the API is reached through its module,
+//! e.g. [`hotkey`], [`types::km2`] for the KM2 data model or `ffi` for the
+//! C interface used by the input methods. State the GUI and the input methods
+//! share (`input_mode`, `recorder`, `commit_log` and the like) is behind the
+//! `host-ipc` feature.
+
 pub mod types;
 pub mod error;
 pub mod km2;
-pub mod engine;
+pub(crate) mod engine;
+#[cfg(feature = "ffi")]
 pub mod ffi;
 pub mod hotkey;
+pub mod locale;
+pub mod transform;
+pub mod analysis;
+pub mod conformance;
+#[cfg(feature = "host-ipc")]
+pub mod paths;
+#[cfg(feature = "host-ipc")]
+pub mod recorder;
+#[cfg(feature = "host-ipc")]
+pub mod input_mode;
+#[cfg(feature = "host-ipc")]
+pub mod key_event;
+#[cfg(feature = "host-ipc")]
+pub mod processing_state;
+#[cfg(feature = "host-ipc")]
+pub mod notification;
+#[cfg(feature = "host-ipc")]
+pub mod commit_log;
+#[cfg(feature = "host-ipc")]
+pub mod composition_journal;
+#[cfg(feature = "host-ipc")]
+pub mod context_store;
+#[cfg(feature = "host-ipc")]
+pub mod load_log;
+#[cfg(feature = "host-ipc")]
+pub mod shortcut_watch;
+#[cfg(feature = "host-ipc")]
+pub mod char_suppression;
+#[cfg(feature = "host-ipc")]
+pub mod key_stats;
+#[cfg(feature = "host-ipc")]
+pub mod input_latency;
+#[cfg(feature = "host-ipc")]
+pub mod tray_icon;
+#[cfg(feature = "env-config")]
+pub mod env_config;
 
-pub use types::*;
+// Crate-internal shorthand for the KM2 data model; outside the crate it is
+// `types::km2`, `types::opcodes` and so on
+pub(crate) use types::*;
 
-// Re-export commonly used types
-pub use types::km2::{Km2File, Rule, BinaryFormatElement, InfoEntry, FileHeader, LayoutOptions, StringEntry, Metadata};
-pub use types::errors::KmsError;
+pub use engine::{
+    ActionType, EngineOutput, EngineSnapshot, EnumerationProgress, Explanation, KeyInput, KeyMagicEngine, KeyOutcome,
+    ModifierState, OutputEntry, OutputEnumerator, PrefixResult, ResetLevel, RuleCandidate, RuleFailure, RuleHistogram,
+    SharedEngine, EngineSlot, Suggestion, SuggestionProvider,
+};
+#[cfg(feature = "recent-suggestions")]
+pub use engine::RecentCommitsProvider;
+pub use types::km2::Km2File;
 pub use types::virtual_keys::VirtualKey;
+pub use km2::{Km2Error, Km2Loader};
+pub use types::errors::KmsError;
 pub use error::{Error, Result};
-pub use engine::{KeyMagicEngine, KeyInput, EngineOutput};
\ No newline at end of file
+
+/// Version of this library
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
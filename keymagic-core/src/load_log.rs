@@ -0,0 +1,199 @@
+//! Keyboards loaded by text services
+//!
+//! Each host reports the hash of the keyboard file its engine loaded, so the
+//! GUI can tell which running hosts still use an older version of a keyboard
+//! (see [`crate::km2::hash`]). Reports go to a small ring of slots; the newest
+//! report of a process is the keyboard it has loaded now.
+//!
+//! On Windows the ring lives in named shared memory. Elsewhere, and in
+//! tests, it is process-local.
+
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+use crate::recorder::shared_block::{BlockLayout, SharedBlock};
+
+/// Name of the section holding the ring on Windows
+#[cfg(windows)]
+pub const SECTION_NAME: &str = r"Local\KeyMagicLoads";
+
+/// "KMLL" in little endian
+const BLOCK_MAGIC: u32 = 0x4C4C_4D4B;
+const BLOCK_VERSION: u32 = 1;
+
+/// Reports kept in the ring
+pub const SLOT_COUNT: usize = 32;
+/// UTF-16 code units kept of a process name; longer names are cut
+pub const NAME_CAPACITY: usize = 64;
+/// Bytes of a SHA-256 hash
+const HASH_BYTES: usize = 32;
+
+/// Sequence number of a slot that is being written
+const BUSY: u64 = u64::MAX;
+
+/// One report; `seq` works as a seqlock like the commit log's
+#[repr(C)]
+struct Slot {
+    /// 0 when empty, `BUSY` while written
+    seq: AtomicU64,
+    pid: AtomicU32,
+    name_len: AtomicU32,
+    /// Two UTF-16 code units per word
+    name: [AtomicU32; NAME_CAPACITY / 2],
+    /// Hash bytes, four per word in little endian
+    hash: [AtomicU32; HASH_BYTES / 4],
+}
+
+#[repr(C)]
+struct Block {
+    magic: AtomicU32,
+    version: AtomicU32,
+    /// Sequence number of the last report claimed (reports start at 1)
+    next_seq: AtomicU64,
+    slots: [Slot; SLOT_COUNT],
+}
+
+unsafe impl BlockLayout for Block {
+    fn initialize(&self) {
+        for slot in &self.slots {
+            slot.seq.store(0, Ordering::Relaxed);
+        }
+        self.next_seq.store(0, Ordering::Relaxed);
+        self.version.store(BLOCK_VERSION, Ordering::Relaxed);
+        self.magic.store(BLOCK_MAGIC, Ordering::Release);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic.load(Ordering::Acquire) == BLOCK_MAGIC && self.version.load(Ordering::Relaxed) == BLOCK_VERSION
+    }
+}
+
+impl Block {
+    /// Writes a report under the next sequence number; a writer that finds
+    /// its slot busy or a lap ahead drops it, as the commit log does
+    fn push(&self, pid: u32, name: &[u16], hash: &[u8; HASH_BYTES]) -> u64 {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed) + 1;
+        let slot = &self.slots[((seq - 1) % SLOT_COUNT as u64) as usize];
+
+        let current = slot.seq.load(Ordering::Relaxed);
+        if current == BUSY
+            || current > seq
+            || slot.seq.compare_exchange(current, BUSY, Ordering::Relaxed, Ordering::Relaxed).is_err()
+        {
+            return seq;
+        }
+        fence(Ordering::Release);
+        slot.pid.store(pid, Ordering::Relaxed);
+        for (word, pair) in slot.name.iter().zip(name.chunks(2)) {
+            let high = pair.get(1).copied().unwrap_or(0) as u32;
+            word.store(pair[0] as u32 | (high << 16), Ordering::Relaxed);
+        }
+        slot.name_len.store(name.len() as u32, Ordering::Relaxed);
+        for (word, bytes) in slot.hash.iter().zip(hash.chunks(4)) {
+            word.store(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]), Ordering::Relaxed);
+        }
+        slot.seq.store(seq, Ordering::Release);
+        seq
+    }
+
+    /// Copies a slot, or `None` if it is empty or changed while copied
+    fn read_slot(slot: &Slot) -> Option<KeyboardLoad> {
+        let seq = slot.seq.load(Ordering::Acquire);
+        if seq == 0 || seq == BUSY {
+            return None;
+        }
+        let pid = slot.pid.load(Ordering::Relaxed);
+        let len = (slot.name_len.load(Ordering::Relaxed) as usize).min(NAME_CAPACITY);
+        let name: Vec<u16> = slot
+            .name
+            .iter()
+            .flat_map(|word| {
+                let word = word.load(Ordering::Relaxed);
+                [word as u16, (word >> 16) as u16]
+            })
+            .take(len)
+            .collect();
+        let hash: String = slot
+            .hash
+            .iter()
+            .flat_map(|word| word.load(Ordering::Relaxed).to_le_bytes())
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        fence(Ordering::Acquire);
+        (slot.seq.load(Ordering::Relaxed) == seq).then(|| KeyboardLoad {
+            seq,
+            pid,
+            process: String::from_utf16_lossy(&name),
+            hash,
+        })
+    }
+}
+
+/// A report read back from the ring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboardLoad {
+    pub seq: u64,
+    pub pid: u32,
+    /// Executable name of the host, cut to [`NAME_CAPACITY`]
+    pub process: String,
+    /// Hash of the loaded keyboard file, as [`crate::km2::content_hash`] writes it
+    pub hash: String,
+}
+
+/// Parses a hash written by `content_hash`
+fn parse_hash(hex: &str) -> Option<[u8; HASH_BYTES]> {
+    let hex = hex.trim();
+    if hex.len() != HASH_BYTES * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0; HASH_BYTES];
+    for (byte, pair) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// Handle to the ring of keyboard loads
+pub struct LoadLog {
+    block: SharedBlock<Block>,
+}
+
+impl LoadLog {
+    /// A process-local ring, for tests and platforms without shared state
+    pub fn in_memory() -> Self {
+        Self { block: SharedBlock::in_memory() }
+    }
+
+    /// Creates the shared ring, or attaches to it if it already exists
+    #[cfg(windows)]
+    pub fn create_shared() -> std::io::Result<Self> {
+        Ok(Self { block: SharedBlock::create_shared(SECTION_NAME, "load log")? })
+    }
+
+    /// Reports that process `pid` named `process` loaded a keyboard file
+    /// hashing to `hash`, and returns the report's sequence number. Nothing
+    /// is reported for a hash `content_hash` could not have written.
+    pub fn report(&self, pid: u32, process: &str, hash: &str) -> Option<u64> {
+        let hash = parse_hash(hash)?;
+        let name: Vec<u16> = process.encode_utf16().take(NAME_CAPACITY).collect();
+        Some(self.block.push(pid, &name, &hash))
+    }
+
+    /// Reports still in the ring, oldest first
+    pub fn read_all(&self) -> Vec<KeyboardLoad> {
+        let mut loads: Vec<KeyboardLoad> = self.block.slots.iter().filter_map(Block::read_slot).collect();
+        loads.sort_by_key(|load| load.seq);
+        loads
+    }
+
+    /// The newest report of each process still in the ring, by process id
+    pub fn latest_by_host(&self) -> Vec<KeyboardLoad> {
+        let mut latest: Vec<KeyboardLoad> = Vec::new();
+        for load in self.read_all().into_iter().rev() {
+            if !latest.iter().any(|newer| newer.pid == load.pid) {
+                latest.push(load);
+            }
+        }
+        latest.sort_by_key(|load| load.pid);
+        latest
+    }
+}
@@ -0,0 +1,126 @@
+//! Language tags a keyboard declares with `@LOCALE`
+//!
+//! Tags follow the shape of BCP 47: a language subtag, then optional script,
+//! region, variant, extension and private use subtags. Only the shape is
+//! checked; whether a subtag is registered is left to the operating system.
+
+/// Separator of the tags stored in the `INFO_LOCL` entry
+pub const LOCALE_SEPARATOR: char = ',';
+
+/// Checks a tag and returns notes on anything unusual about it, such as
+/// non-canonical case, or why it is not a tag at all
+pub fn check_locale_tag(tag: &str) -> std::result::Result<Vec<String>, String> {
+    if tag.is_empty() {
+        return Err("empty tag".to_string());
+    }
+    let subtags: Vec<&str> = tag.split('-').collect();
+    if let Some(empty) = subtags.iter().position(|s| s.is_empty()) {
+        return Err(format!("empty subtag at position {}", empty + 1));
+    }
+    if let Some(bad) = subtags.iter().find(|s| !s.bytes().all(|b| b.is_ascii_alphanumeric())) {
+        return Err(format!("subtag \"{}\" has characters other than letters and digits", bad));
+    }
+
+    let mut notes = Vec::new();
+    let mut rest = subtags.as_slice();
+    let language = rest[0];
+    if language.eq_ignore_ascii_case("x") {
+        check_private_use(&rest[1..])?;
+        notes.push("private use tags are not understood by the operating system".to_string());
+        return Ok(notes);
+    }
+    if !is_alpha(language) || !(2..=8).contains(&language.len()) {
+        return Err(format!("language subtag \"{}\" must be 2 to 8 letters", language));
+    }
+    if language.len() > 3 {
+        notes.push(format!("language subtag \"{}\" is not a 2 or 3 letter code", language));
+    }
+    rest = &rest[1..];
+
+    if let Some((script, tail)) = rest.split_first() {
+        if script.len() == 4 && is_alpha(script) {
+            rest = tail;
+        }
+    }
+    if let Some((region, tail)) = rest.split_first() {
+        if (region.len() == 2 && is_alpha(region)) || (region.len() == 3 && is_digit(region)) {
+            rest = tail;
+        }
+    }
+    while let Some((variant, tail)) = rest.split_first() {
+        let is_variant = (5..=8).contains(&variant.len())
+            || (variant.len() == 4 && variant.as_bytes()[0].is_ascii_digit());
+        if !is_variant {
+            break;
+        }
+        rest = tail;
+    }
+    while let Some((singleton, tail)) = rest.split_first() {
+        if singleton.len() != 1 {
+            return Err(format!("unexpected subtag \"{}\"", singleton));
+        }
+        if singleton.eq_ignore_ascii_case("x") {
+            check_private_use(tail)?;
+            notes.push("private use subtags are not understood by the operating system".to_string());
+            rest = &[];
+            break;
+        }
+        let count = tail.iter().take_while(|s| (2..=8).contains(&s.len())).count();
+        if count == 0 {
+            return Err(format!("extension \"{}\" has no subtags", singleton));
+        }
+        notes.push(format!("extension \"{}\" is not used for keyboard languages", singleton));
+        rest = &tail[count..];
+    }
+    if let Some(extra) = rest.first() {
+        return Err(format!("unexpected subtag \"{}\"", extra));
+    }
+
+    let canonical = canonical_locale_tag(tag);
+    if canonical != tag {
+        notes.push(format!("usually written as \"{}\"", canonical));
+    }
+    Ok(notes)
+}
+
+/// A tag in its usual case: language lower case, script title case and
+/// region upper case, e.g. "shn-Mymr-MM"
+pub fn canonical_locale_tag(tag: &str) -> String {
+    // Everything from the first extension or private use singleton on is lower case
+    let mut extension = false;
+    tag.split('-')
+        .enumerate()
+        .map(|(i, subtag)| {
+            extension |= subtag.len() == 1;
+            if i == 0 || extension {
+                subtag.to_ascii_lowercase()
+            } else if subtag.len() == 4 && is_alpha(subtag) {
+                let (first, rest) = subtag.split_at(1);
+                first.to_ascii_uppercase() + &rest.to_ascii_lowercase()
+            } else if subtag.len() == 2 && is_alpha(subtag) {
+                subtag.to_ascii_uppercase()
+            } else {
+                subtag.to_ascii_lowercase()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn check_private_use(subtags: &[&str]) -> std::result::Result<(), String> {
+    if subtags.is_empty() {
+        return Err("private use has no subtags".to_string());
+    }
+    match subtags.iter().find(|s| s.len() > 8) {
+        Some(long) => Err(format!("private use subtag \"{}\" is longer than 8 characters", long)),
+        None => Ok(()),
+    }
+}
+
+fn is_alpha(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_alphabetic())
+}
+
+fn is_digit(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_digit())
+}
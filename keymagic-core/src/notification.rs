@@ -0,0 +1,55 @@
+//! Rate limit for the messages keyboards show through the HUD
+//!
+//! A rule can ask the host to show a message (`@notify "Numbers mode"`),
+//! typically when it switches the keyboard into another mode. A key held
+//! down, or a rule that fires on every key, would flood the HUD, so each
+//! keyboard gets at most one message per [`NOTIFICATION_INTERVAL`]; messages
+//! within the interval are dropped, not queued. The C interface applies the
+//! limit before a message reaches the host, keyed by the keyboard's content
+//! hash, so every text service in a process shares it.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Shortest time between two messages of one keyboard
+pub const NOTIFICATION_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Decides which keyboard messages are shown
+#[derive(Debug)]
+pub struct NotificationLimiter {
+    interval: Duration,
+    /// When each keyboard last had a message shown
+    last_shown: BTreeMap<String, Instant>,
+}
+
+impl NotificationLimiter {
+    /// A limiter letting one message per keyboard through every `interval`
+    pub const fn new(interval: Duration) -> Self {
+        Self { interval, last_shown: BTreeMap::new() }
+    }
+
+    /// Whether a message of `keyboard` raised now is shown
+    pub fn allow(&mut self, keyboard: &str) -> bool {
+        self.allow_at(keyboard, Instant::now())
+    }
+
+    /// Whether a message of `keyboard` raised at `now` is shown; a shown
+    /// message starts a new interval, a dropped one does not
+    pub fn allow_at(&mut self, keyboard: &str, now: Instant) -> bool {
+        let interval = self.interval;
+        let elapsed = |last: &Instant| now.saturating_duration_since(*last) >= interval;
+        if self.last_shown.get(keyboard).is_some_and(|last| !elapsed(last)) {
+            return false;
+        }
+        // Forget keyboards whose interval is over so the map stays small
+        self.last_shown.retain(|_, last| !elapsed(last));
+        self.last_shown.insert(keyboard.to_string(), now);
+        true
+    }
+}
+
+impl Default for NotificationLimiter {
+    fn default() -> Self {
+        Self::new(NOTIFICATION_INTERVAL)
+    }
+}
@@ -0,0 +1,87 @@
+//! Keyboard file paths that survive non-ASCII folders and long paths
+//!
+//! Windows limits ordinary paths to MAX_PATH (260) UTF-16 units. Paths with
+//! the extended-length prefix (`\\?\`) may be up to ~32k units long, but are
+//! passed to the file system as-is: they must be absolute and use
+//! backslashes only.
+
+use std::path::{Path, PathBuf};
+
+const BACKSLASH: u16 = b'\\' as u16;
+const SLASH: u16 = b'/' as u16;
+const COLON: u16 = b':' as u16;
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().collect()
+}
+
+fn is_separator(unit: u16) -> bool {
+    unit == BACKSLASH || unit == SLASH
+}
+
+fn is_drive_letter(unit: u16) -> bool {
+    (b'A' as u16..=b'Z' as u16).contains(&unit) || (b'a' as u16..=b'z' as u16).contains(&unit)
+}
+
+/// Adds the extended-length prefix to an absolute Windows path in UTF-16
+///
+/// `C:\dir` becomes `\\?\C:\dir` and `\\server\share` becomes
+/// `\\?\UNC\server\share`. Forward slashes are turned into backslashes.
+/// Already prefixed and relative paths are returned unchanged.
+pub fn to_extended_length_wide(path: &[u16]) -> Vec<u16> {
+    let verbatim = wide(r"\\?\");
+    let device = wide(r"\\.\");
+    if path.starts_with(&verbatim) || path.starts_with(&device) {
+        return path.to_vec();
+    }
+
+    let normalized: Vec<u16> = path.iter().map(|&unit| if unit == SLASH { BACKSLASH } else { unit }).collect();
+    let is_drive_absolute = normalized.len() >= 3
+        && is_drive_letter(normalized[0])
+        && normalized[1] == COLON
+        && is_separator(normalized[2]);
+    let is_unc = normalized.len() > 2 && normalized[0] == BACKSLASH && normalized[1] == BACKSLASH;
+
+    if is_drive_absolute {
+        [verbatim, normalized].concat()
+    } else if is_unc {
+        [wide(r"\\?\UNC\"), normalized[2..].to_vec()].concat()
+    } else {
+        path.to_vec()
+    }
+}
+
+/// Adds the extended-length prefix to an absolute path on Windows, so it can
+/// be opened whatever its length; other platforms return the path unchanged
+pub fn to_extended_length(path: &Path) -> PathBuf {
+    #[cfg(windows)]
+    {
+        use std::ffi::OsString;
+        use std::os::windows::ffi::{OsStrExt, OsStringExt};
+
+        let units: Vec<u16> = path.as_os_str().encode_wide().collect();
+        PathBuf::from(OsString::from_wide(&to_extended_length_wide(&units)))
+    }
+    #[cfg(not(windows))]
+    {
+        path.to_path_buf()
+    }
+}
+
+/// Builds a path from UTF-16 code units
+///
+/// On Windows any sequence is a valid path, unpaired surrogates included.
+/// Elsewhere the units must be valid UTF-16.
+pub fn path_from_wide(units: &[u16]) -> Option<PathBuf> {
+    #[cfg(windows)]
+    {
+        use std::ffi::OsString;
+        use std::os::windows::ffi::OsStringExt;
+
+        Some(PathBuf::from(OsString::from_wide(units)))
+    }
+    #[cfg(not(windows))]
+    {
+        String::from_utf16(units).ok().map(PathBuf::from)
+    }
+}
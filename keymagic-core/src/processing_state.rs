@@ -0,0 +1,368 @@
+//! Key processing switch shared between the GUI and text services
+//!
+//! Turning processing off while the user is composing must not drop the
+//! composing text. The GUI does not switch hosts off directly; it asks them
+//! to stand down and waits briefly for the answer:
+//!
+//! 1. The GUI clears the enabled flag together with a new request number
+//!    ([`ProcessingState::request_disable`]) and signals the hosts.
+//! 2. Each host feeds what it reads to [`HostProcessingState::observe`]. An
+//!    enabled host is told to flush its engine and commit the text first;
+//!    only once that is done ([`HostProcessingState::finish_disable`]) does it
+//!    stop processing keys and acknowledge the request.
+//! 3. The GUI waits for the acknowledgement for a bounded time
+//!    ([`ProcessingState::wait_for_ack`]), so it can say the composition is
+//!    still being finished instead of hanging on a busy host.
+//!
+//! On Windows the switch lives in a small block of named shared memory.
+//!
+//! A GUI that crashes or is killed cannot switch processing off, so it also
+//! proves it is alive: it writes a heartbeat into the block every
+//! [`HEARTBEAT_INTERVAL`] ([`ProcessingState::beat`]) and holds a named mutex
+//! ([`GuiPresence`]) that the system releases when it exits. Hosts check at
+//! most every [`LIVENESS_CHECK_INTERVAL`] and stop processing keys once the
+//! heartbeat is older than [`HEARTBEAT_STALE_AFTER`] and the mutex is gone
+//! ([`GuiLiveness`]). Processing resumes only when a heartbeat fresher than
+//! [`HEARTBEAT_FRESH_WITHIN`] shows up again, so a GUI restarting quickly
+//! does not switch hosts off and a dying one does not switch them on and
+//! off. A block without any heartbeat, such as one created by a host before
+//! the GUI ever ran, leaves processing alone.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::recorder::shared_block::{BlockLayout, SharedBlock};
+
+/// How long the GUI waits for a host to acknowledge a disable request
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Interval at which [`ProcessingState::wait_for_ack`] polls the block
+const ACK_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Name of the section holding the switch on Windows
+#[cfg(windows)]
+pub const SECTION_NAME: &str = r"Local\KeyMagicProcessing";
+
+/// Name of the mutex the GUI holds while it runs, on Windows
+#[cfg(windows)]
+pub const GUI_MUTEX_NAME: &str = r"Local\KeyMagicGuiAlive";
+
+/// How often the GUI writes its heartbeat
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+/// Age after which a heartbeat counts as stale
+pub const HEARTBEAT_STALE_AFTER: Duration = Duration::from_secs(15);
+/// Age a heartbeat must be under for hosts to trust a GUI they gave up on
+pub const HEARTBEAT_FRESH_WITHIN: Duration = Duration::from_secs(6);
+/// How often hosts look at the heartbeat
+pub const LIVENESS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// "KMPS" in little endian
+const BLOCK_MAGIC: u32 = 0x5350_4D4B;
+const BLOCK_VERSION: u32 = 2;
+
+/// The switch, laid out for shared memory
+///
+/// `request`, `enabled` and `heartbeat` are only ever written by the GUI,
+/// `ack` by the hosts, so plain atomic stores are enough.
+#[repr(C)]
+struct Block {
+    magic: AtomicU32,
+    version: AtomicU32,
+    /// 1 while processing is enabled
+    enabled: AtomicU32,
+    _reserved: AtomicU32,
+    /// Number of the last disable request
+    request: AtomicU64,
+    /// Highest request a host finished
+    ack: AtomicU64,
+    /// Wall clock time of the GUI's last heartbeat in milliseconds since the
+    /// Unix epoch; 0 before the first one
+    heartbeat: AtomicU64,
+}
+
+unsafe impl BlockLayout for Block {
+    fn initialize(&self) {
+        self.enabled.store(1, Ordering::Relaxed);
+        self.request.store(0, Ordering::Relaxed);
+        self.ack.store(0, Ordering::Relaxed);
+        self.heartbeat.store(0, Ordering::Relaxed);
+        self.version.store(BLOCK_VERSION, Ordering::Relaxed);
+        self.magic.store(BLOCK_MAGIC, Ordering::Release);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic.load(Ordering::Acquire) == BLOCK_MAGIC && self.version.load(Ordering::Relaxed) == BLOCK_VERSION
+    }
+}
+
+/// Handle to the shared key processing switch
+pub struct ProcessingState {
+    block: SharedBlock<Block>,
+}
+
+impl ProcessingState {
+    /// A process-local block, for tests and platforms without shared state
+    pub fn in_memory() -> Self {
+        Self { block: SharedBlock::in_memory() }
+    }
+
+    /// Creates the shared block, or attaches to it if it already exists
+    ///
+    /// A new block starts enabled; the GUI publishes its setting on startup.
+    #[cfg(windows)]
+    pub fn create_shared() -> std::io::Result<Self> {
+        Ok(Self { block: SharedBlock::create_shared(SECTION_NAME, "processing")? })
+    }
+
+    /// Whether processing is switched on
+    pub fn is_enabled(&self) -> bool {
+        self.block.enabled.load(Ordering::Acquire) != 0
+    }
+
+    /// Number of the last disable request
+    pub fn request(&self) -> u64 {
+        self.block.request.load(Ordering::Acquire)
+    }
+
+    /// Switches processing on
+    pub fn enable(&self) {
+        self.block.enabled.store(1, Ordering::Release);
+    }
+
+    /// Switches processing off and returns the request hosts acknowledge
+    ///
+    /// The request number is published before the flag, so a host that sees
+    /// processing off always sees the request that turned it off.
+    pub fn request_disable(&self) -> u64 {
+        let block = &*self.block;
+        let request = block.request.fetch_add(1, Ordering::AcqRel) + 1;
+        block.enabled.store(0, Ordering::Release);
+        request
+    }
+
+    /// Records that a host flushed and stood down for `request`
+    pub fn acknowledge(&self, request: u64) {
+        self.block.ack.fetch_max(request, Ordering::AcqRel);
+    }
+
+    /// Whether a host acknowledged `request` or a later one
+    pub fn acknowledged(&self, request: u64) -> bool {
+        self.block.ack.load(Ordering::Acquire) >= request
+    }
+
+    /// Records that the GUI is alive at `now_ms` (milliseconds since the Unix
+    /// epoch) and returns the previous heartbeat, if there was one
+    pub fn beat(&self, now_ms: u64) -> Option<u64> {
+        Some(self.block.heartbeat.swap(now_ms.max(1), Ordering::AcqRel)).filter(|&previous| previous != 0)
+    }
+
+    /// The GUI's last heartbeat, `None` if it never wrote one
+    pub fn last_heartbeat(&self) -> Option<u64> {
+        Some(self.block.heartbeat.load(Ordering::Acquire)).filter(|&heartbeat| heartbeat != 0)
+    }
+
+    /// Waits up to `timeout` for `request` to be acknowledged
+    ///
+    /// Returns false on timeout; the hosts still finish in the background.
+    pub fn wait_for_ack(&self, request: u64, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.acknowledged(request) {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            std::thread::sleep(ACK_POLL_INTERVAL.min(deadline - now));
+        }
+    }
+}
+
+/// What a host has to do after reading the switch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostAction {
+    /// Nothing changed
+    None,
+    /// Processing was switched back on
+    Enable,
+    /// Flush the engine and commit its text, then call
+    /// [`HostProcessingState::finish_disable`]
+    FlushThenDisable { request: u64 },
+    /// Already standing down; acknowledge right away
+    Acknowledge { request: u64 },
+}
+
+/// Where a single host is in the disable handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostProcessingState {
+    enabled: bool,
+    /// Request being flushed for
+    pending: Option<u64>,
+    /// Last request acknowledged
+    handled: u64,
+}
+
+impl Default for HostProcessingState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HostProcessingState {
+    /// A host that starts out processing keys
+    pub fn new() -> Self {
+        Self { enabled: true, pending: None, handled: 0 }
+    }
+
+    /// Whether the host should keep processing keys
+    ///
+    /// Stays true while a flush is pending, so keys typed meanwhile still
+    /// reach the engine that is about to be flushed.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Request being flushed for, if any
+    pub fn pending(&self) -> Option<u64> {
+        self.pending
+    }
+
+    /// Feeds the switch as read from [`ProcessingState`]
+    pub fn observe(&mut self, enabled: bool, request: u64) -> HostAction {
+        if enabled {
+            // Switched back on before the flush finished: keep going
+            self.pending = None;
+            if self.enabled {
+                return HostAction::None;
+            }
+            self.enabled = true;
+            return HostAction::Enable;
+        }
+
+        if request <= self.handled || self.pending == Some(request) {
+            return HostAction::None;
+        }
+        if self.enabled {
+            // A newer request replaces one still being flushed
+            self.pending = Some(request);
+            HostAction::FlushThenDisable { request }
+        } else {
+            self.handled = request;
+            HostAction::Acknowledge { request }
+        }
+    }
+
+    /// Stands down once the flush for `request` is done
+    ///
+    /// Returns the request to acknowledge, or `None` when it was superseded
+    /// or processing was switched back on in the meantime.
+    pub fn finish_disable(&mut self, request: u64) -> Option<u64> {
+        if self.pending != Some(request) {
+            return None;
+        }
+        self.pending = None;
+        self.enabled = false;
+        self.handled = request;
+        Some(request)
+    }
+}
+
+/// How recent the GUI's heartbeat is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeartbeatStatus {
+    /// The GUI never wrote one
+    Missing,
+    /// Written within two intervals
+    Fresh { age: Duration },
+    /// Overdue, but not yet stale
+    Late { age: Duration },
+    /// Older than [`HEARTBEAT_STALE_AFTER`]
+    Stale { age: Duration },
+}
+
+impl HeartbeatStatus {
+    /// Classifies `heartbeat` (as from [`ProcessingState::last_heartbeat`])
+    /// at `now_ms`; a heartbeat from the future is fresh
+    pub fn at(heartbeat: Option<u64>, now_ms: u64) -> Self {
+        let Some(heartbeat) = heartbeat else {
+            return HeartbeatStatus::Missing;
+        };
+        let age = Duration::from_millis(now_ms.saturating_sub(heartbeat));
+        if age > HEARTBEAT_STALE_AFTER {
+            HeartbeatStatus::Stale { age }
+        } else if age > HEARTBEAT_INTERVAL * 2 {
+            HeartbeatStatus::Late { age }
+        } else {
+            HeartbeatStatus::Fresh { age }
+        }
+    }
+
+    /// Age of the heartbeat, if there is one
+    pub fn age(&self) -> Option<Duration> {
+        match *self {
+            HeartbeatStatus::Missing => None,
+            HeartbeatStatus::Fresh { age } | HeartbeatStatus::Late { age } | HeartbeatStatus::Stale { age } => Some(age),
+        }
+    }
+}
+
+/// A host's view of whether the GUI is still running
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GuiLiveness {
+    gone: bool,
+}
+
+impl GuiLiveness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the host last decided the GUI is gone
+    pub fn is_gone(&self) -> bool {
+        self.gone
+    }
+
+    /// Updates the decision from the heartbeat and whether the GUI's mutex
+    /// exists, and returns whether the GUI counts as alive
+    pub fn observe(&mut self, heartbeat: Option<u64>, now_ms: u64, gui_mutex_exists: bool) -> bool {
+        let status = HeartbeatStatus::at(heartbeat, now_ms);
+        self.gone = match status {
+            HeartbeatStatus::Missing => false,
+            _ if self.gone => status.age().is_some_and(|age| age > HEARTBEAT_FRESH_WITHIN),
+            HeartbeatStatus::Stale { .. } => !gui_mutex_exists,
+            HeartbeatStatus::Fresh { .. } | HeartbeatStatus::Late { .. } => false,
+        };
+        !self.gone
+    }
+}
+
+/// The named mutex the GUI holds while it runs
+///
+/// Hosts only see it on Windows; elsewhere this does nothing.
+pub struct GuiPresence {
+    #[cfg(windows)]
+    _mutex: crate::recorder::shared_memory::NamedMutex,
+}
+
+impl GuiPresence {
+    /// Takes the mutex for the lifetime of the value
+    pub fn hold() -> std::io::Result<Self> {
+        Ok(Self {
+            #[cfg(windows)]
+            _mutex: crate::recorder::shared_memory::NamedMutex::create(GUI_MUTEX_NAME)?,
+        })
+    }
+
+    /// Whether a GUI holds the mutex; always false off Windows
+    pub fn exists() -> bool {
+        #[cfg(windows)]
+        {
+            crate::recorder::shared_memory::NamedMutex::exists(GUI_MUTEX_NAME)
+        }
+        #[cfg(not(windows))]
+        {
+            false
+        }
+    }
+}
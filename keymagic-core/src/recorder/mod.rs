@@ -0,0 +1,323 @@
+//! Opt-in recorder of input events for bug reports
+//!
+//! While recording, hosts append one compact record per processed key to a
+//! fixed-size ring: when and in which process (as a hash) the key was
+//! pressed, the key and modifiers, and what the engine did with it. The
+//! composing text is only stored in verbose mode, and then truncated.
+//!
+//! On Windows the ring lives in named shared memory: the GUI creates it and
+//! turns recording on and off, and the text service of every application
+//! appends to it. Elsewhere, and in tests, the ring is process-local.
+//!
+//! Records are numbered in the order they were claimed. Hosts that trace
+//! the engine can log the number returned by [`InputRecorder::record`] to
+//! correlate their trace with an exported recording.
+//!
+//! Hosts also note their process with [`InputRecorder::note_process`], so
+//! users can tell which hash is which application before sharing a
+//! recording; see [`processes`](self::processes).
+
+pub mod processes;
+mod ring;
+pub(crate) mod shared_block;
+#[cfg(windows)]
+pub(crate) mod shared_memory;
+
+pub use processes::{display_name, summarize_processes, ActionCounts, ProcessEntry, ProcessSummary};
+pub use ring::{region_size, NAME_CAPACITY, PROCESS_CAPACITY, TEXT_CAPACITY};
+#[cfg(windows)]
+pub use shared_memory::SECTION_NAME;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::{ProcessData, Ring, SlotData, FLAG_RECORDING, FLAG_VERBOSE, PROCESS_UWP};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Records kept when no capacity is given
+pub const DEFAULT_CAPACITY: usize = 4096;
+/// Capacities outside this range are clamped
+pub const MIN_CAPACITY: usize = 64;
+pub const MAX_CAPACITY: usize = 65536;
+
+/// Modifier flags of a record
+pub const MOD_SHIFT: u16 = 1 << 0;
+pub const MOD_CTRL: u16 = 1 << 1;
+pub const MOD_ALT: u16 = 1 << 2;
+pub const MOD_CAPS_LOCK: u16 = 1 << 3;
+
+/// A processed key, as handed to the recorder by a host
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyEventRecord<'a> {
+    /// Hash of the process name, see [`hash_process_name`]
+    pub process_hash: u32,
+    /// Windows virtual key code
+    pub vk: u16,
+    /// `MOD_*` flags
+    pub modifiers: u16,
+    /// `ActionType` as numbered in the FFI
+    pub action_type: u8,
+    pub is_processed: bool,
+    pub delete_count: u16,
+    /// Composing text after the key; only stored in verbose mode
+    pub composing_text: &'a str,
+}
+
+/// A record read back from the ring
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct InputRecord {
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub process_hash: u32,
+    pub vk: u16,
+    pub modifiers: u16,
+    pub action_type: u8,
+    pub is_processed: bool,
+    pub delete_count: u16,
+    /// Length of the composing text in UTF-16 code units
+    pub composing_len: u16,
+    /// Start of the composing text, recorded in verbose mode only
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub text: Option<String>,
+}
+
+/// Contents of the ring at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RecordingSnapshot {
+    pub capacity: usize,
+    pub recording: bool,
+    pub verbose: bool,
+    /// Milliseconds since the Unix epoch when recording was last started
+    pub started_at_ms: u64,
+    /// Records claimed since the ring was created
+    pub total_recorded: u64,
+    /// Records lost to wraparound or caught mid-write
+    pub dropped: u64,
+    /// Oldest first
+    pub records: Vec<InputRecord>,
+    /// Processes seen since recording started, first seen first
+    pub processes: Vec<ProcessEntry>,
+}
+
+impl RecordingSnapshot {
+    /// Per-process counts of the records, see [`summarize_processes`]
+    pub fn process_summary(&self) -> Vec<ProcessSummary> {
+        summarize_processes(&self.records, &self.processes)
+    }
+}
+
+/// FNV-1a hash of a lowercased process name, so recordings show which
+/// events came from the same application without naming it
+pub fn hash_process_name(name: &str) -> u32 {
+    name.chars()
+        .flat_map(char::to_lowercase)
+        .flat_map(|ch| ch.encode_utf16(&mut [0; 2]).to_vec())
+        .fold(0x811c_9dc5u32, |hash, unit| {
+            (hash ^ unit as u32).wrapping_mul(0x0100_0193)
+        })
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+enum Storage {
+    Heap { words: Box<[AtomicU64]>, capacity: usize },
+    #[cfg(windows)]
+    Shared(shared_memory::SharedSection),
+}
+
+/// Handle to a ring of input records
+pub struct InputRecorder {
+    storage: Storage,
+}
+
+impl InputRecorder {
+    /// A process-local recorder with room for `capacity` records
+    pub fn in_memory(capacity: usize) -> Self {
+        let capacity = capacity.clamp(MIN_CAPACITY, MAX_CAPACITY);
+        let words = (0..region_size(capacity) / 8).map(|_| AtomicU64::new(0)).collect();
+        let recorder = Self { storage: Storage::Heap { words, capacity } };
+        recorder.ring().initialize();
+        recorder
+    }
+
+    /// Creates the shared ring, or attaches to it if it already exists
+    #[cfg(windows)]
+    pub fn create_shared(capacity: usize) -> std::io::Result<Self> {
+        let section = shared_memory::SharedSection::create(capacity.clamp(MIN_CAPACITY, MAX_CAPACITY))?;
+        Ok(Self { storage: Storage::Shared(section) })
+    }
+
+    /// Attaches to the shared ring created by the GUI
+    #[cfg(windows)]
+    pub fn open_shared() -> std::io::Result<Self> {
+        Ok(Self { storage: Storage::Shared(shared_memory::SharedSection::open()?) })
+    }
+
+    fn ring(&self) -> Ring<'_> {
+        match &self.storage {
+            // The words are 8-byte aligned and only accessed as atomics
+            Storage::Heap { words, capacity } => unsafe { Ring::from_raw(words.as_ptr() as *const u8, *capacity) },
+            #[cfg(windows)]
+            Storage::Shared(section) => section.ring(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring().slots.len()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.ring().flags() & FLAG_RECORDING != 0
+    }
+
+    pub fn is_verbose(&self) -> bool {
+        self.ring().flags() & FLAG_VERBOSE != 0
+    }
+
+    /// Starts recording; `verbose` also stores the start of the composing text
+    pub fn start(&self, verbose: bool) {
+        let header = self.ring().header;
+        header.started_at_ms.store(now_ms(), Ordering::Relaxed);
+        let flags = FLAG_RECORDING | if verbose { FLAG_VERBOSE } else { 0 };
+        header.flags.store(flags, Ordering::Release);
+    }
+
+    /// Stops recording; the records stay until `clear` or the next `start`
+    pub fn stop(&self) {
+        self.ring().header.flags.store(0, Ordering::Release);
+    }
+
+    /// Forgets all records
+    pub fn clear(&self) {
+        let ring = self.ring();
+        let flags = ring.flags();
+        let started_at = ring.header.started_at_ms.load(Ordering::Relaxed);
+        ring.initialize();
+        ring.header.started_at_ms.store(started_at, Ordering::Relaxed);
+        ring.header.flags.store(flags, Ordering::Release);
+    }
+
+    /// Appends `event` if recording is on and returns its sequence number
+    pub fn record(&self, event: &KeyEventRecord<'_>) -> Option<u64> {
+        let ring = self.ring();
+        let flags = ring.flags();
+        if flags & FLAG_RECORDING == 0 {
+            return None;
+        }
+
+        let composing: Vec<u16> = event.composing_text.encode_utf16().collect();
+        let mut text = [0u32; TEXT_CAPACITY / 2];
+        let mut text_len = 0;
+        if flags & FLAG_VERBOSE != 0 {
+            text_len = composing.len().min(TEXT_CAPACITY);
+            for (i, unit) in composing[..text_len].iter().enumerate() {
+                text[i / 2] |= (*unit as u32) << (16 * (i % 2));
+            }
+        }
+
+        let data = SlotData {
+            seq: 0,
+            timestamp_ms: now_ms(),
+            process_hash: event.process_hash,
+            key: event.vk as u32 | (event.modifiers as u32) << 16,
+            action: event.action_type as u32 | (event.is_processed as u32) << 8 | (text_len as u32) << 16,
+            counts: event.delete_count as u32 | (composing.len().min(u16::MAX as usize) as u32) << 16,
+            text,
+        };
+        Some(ring.push(&data))
+    }
+
+    /// Adds the process `process_name` to the table of the recording, if
+    /// recording is on, and returns its hash. Hosts call it with every
+    /// record; a process already in the table only has its last use
+    /// updated.
+    pub fn note_process(&self, process_name: &str, is_uwp: bool) -> u32 {
+        let process_hash = hash_process_name(process_name);
+        let ring = self.ring();
+        if ring.flags() & FLAG_RECORDING == 0 {
+            return process_hash;
+        }
+
+        let mut name = [0u32; NAME_CAPACITY / 2];
+        for (i, unit) in display_name(process_name).encode_utf16().take(NAME_CAPACITY).enumerate() {
+            name[i / 2] |= (unit as u32) << (16 * (i % 2));
+        }
+        let now = now_ms();
+        ring.note_process(&ProcessData {
+            hash: process_hash,
+            flags: if is_uwp { PROCESS_UWP } else { 0 },
+            first_seen_ms: now,
+            last_seen_ms: now,
+            name,
+        });
+        process_hash
+    }
+
+    /// Copies the records currently in the ring
+    pub fn snapshot(&self) -> RecordingSnapshot {
+        let ring = self.ring();
+        let flags = ring.flags();
+        let total_recorded = ring.header.next_seq.load(Ordering::Acquire);
+        let records: Vec<InputRecord> = ring.snapshot().into_iter().map(decode).collect();
+        RecordingSnapshot {
+            capacity: ring.slots.len(),
+            recording: flags & FLAG_RECORDING != 0,
+            verbose: flags & FLAG_VERBOSE != 0,
+            started_at_ms: ring.header.started_at_ms.load(Ordering::Relaxed),
+            total_recorded,
+            dropped: total_recorded.saturating_sub(records.len() as u64),
+            records,
+            processes: decode_processes(ring.processes()),
+        }
+    }
+}
+
+fn decode(data: SlotData) -> InputRecord {
+    let text_len = ((data.action >> 16) as usize).min(TEXT_CAPACITY);
+    let text = (text_len > 0).then(|| {
+        let units: Vec<u16> = (0..text_len).map(|i| (data.text[i / 2] >> (16 * (i % 2))) as u16).collect();
+        String::from_utf16_lossy(&units)
+    });
+    InputRecord {
+        seq: data.seq,
+        timestamp_ms: data.timestamp_ms,
+        process_hash: data.process_hash,
+        vk: data.key as u16,
+        modifiers: (data.key >> 16) as u16,
+        action_type: data.action as u8,
+        is_processed: (data.action >> 8) & 1 != 0,
+        delete_count: data.counts as u16,
+        composing_len: (data.counts >> 16) as u16,
+        text,
+    }
+}
+
+/// Entries of the process table in the order first seen, one per process;
+/// a process added twice by racing writers keeps its first sighting
+fn decode_processes(mut table: Vec<ProcessData>) -> Vec<ProcessEntry> {
+    table.sort_by_key(|data| data.first_seen_ms);
+    let mut processes: Vec<ProcessEntry> = Vec::with_capacity(table.len());
+    for data in table {
+        if processes.iter().any(|process| process.process_hash == data.hash) {
+            continue;
+        }
+        let units: Vec<u16> = (0..NAME_CAPACITY)
+            .map(|i| (data.name[i / 2] >> (16 * (i % 2))) as u16)
+            .take_while(|&unit| unit != 0)
+            .collect();
+        processes.push(ProcessEntry {
+            process_hash: data.hash,
+            first_seen_ms: data.first_seen_ms,
+            name: String::from_utf16_lossy(&units),
+            is_uwp: data.flags & PROCESS_UWP != 0,
+        });
+    }
+    processes
+}
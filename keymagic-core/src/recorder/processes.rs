@@ -0,0 +1,114 @@
+//! Processes of a recording
+//!
+//! Records only carry a hash of the process name, which is all a shared bug
+//! report should reveal. To let users check that the application they are
+//! reporting was captured, the ring also keeps a small table mapping each
+//! hash to a display name: the executable stem, reduced to ASCII letters,
+//! digits and `-_.` and cut to [`NAME_CAPACITY`] units, so paths and user
+//! names in them never reach the table.
+
+use std::collections::BTreeMap;
+
+use super::ring::NAME_CAPACITY;
+use super::InputRecord;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Display name for a process: the executable stem without its path or
+/// `.exe`, with anything but ASCII letters, digits and `-_.` replaced by
+/// `_`, cut to `NAME_CAPACITY` characters
+pub fn display_name(process_name: &str) -> String {
+    let file = process_name.rsplit(['/', '\\']).next().unwrap_or(process_name);
+    let stem = match file.len().checked_sub(4) {
+        Some(dot) if file.is_char_boundary(dot) && file[dot..].eq_ignore_ascii_case(".exe") => &file[..dot],
+        _ => file,
+    };
+    stem.chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.') { ch } else { '_' })
+        .take(NAME_CAPACITY)
+        .collect()
+}
+
+/// A process seen while recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ProcessEntry {
+    pub process_hash: u32,
+    /// Milliseconds since the Unix epoch when it was first recorded
+    pub first_seen_ms: u64,
+    /// See [`display_name`]
+    pub name: String,
+    /// Whether it is a packaged (UWP) app
+    pub is_uwp: bool,
+}
+
+/// Records of one process by `action_type`, as numbered in the FFI
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ActionCounts {
+    pub none: u64,
+    pub insert: u64,
+    pub delete: u64,
+    pub delete_and_insert: u64,
+    /// Action types this version does not know
+    pub other: u64,
+}
+
+impl ActionCounts {
+    fn add(&mut self, action_type: u8) {
+        match action_type {
+            0 => self.none += 1,
+            1 => self.insert += 1,
+            2 => self.delete += 1,
+            3 => self.delete_and_insert += 1,
+            _ => self.other += 1,
+        }
+    }
+}
+
+/// What a recording holds for one process
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ProcessSummary {
+    pub process_hash: u32,
+    /// `None` for records whose process left the table or never made it in
+    pub name: Option<String>,
+    pub is_uwp: bool,
+    pub first_seen_ms: Option<u64>,
+    pub events: u64,
+    /// Events the engine processed
+    pub processed: u64,
+    pub actions: ActionCounts,
+    /// Timestamp of the newest event
+    pub last_event_ms: u64,
+}
+
+/// Per-process counts of `records`, most events first; ties go to the
+/// process seen first
+pub fn summarize_processes(records: &[InputRecord], processes: &[ProcessEntry]) -> Vec<ProcessSummary> {
+    let mut summaries: BTreeMap<u32, ProcessSummary> = BTreeMap::new();
+    for record in records {
+        let summary = summaries.entry(record.process_hash).or_insert_with(|| {
+            let entry = processes.iter().find(|process| process.process_hash == record.process_hash);
+            ProcessSummary {
+                process_hash: record.process_hash,
+                name: entry.map(|process| process.name.clone()),
+                is_uwp: entry.is_some_and(|process| process.is_uwp),
+                first_seen_ms: entry.map(|process| process.first_seen_ms),
+                events: 0,
+                processed: 0,
+                actions: ActionCounts::default(),
+                last_event_ms: 0,
+            }
+        });
+        summary.events += 1;
+        summary.processed += record.is_processed as u64;
+        summary.actions.add(record.action_type);
+        summary.last_event_ms = summary.last_event_ms.max(record.timestamp_ms);
+    }
+
+    let mut summaries: Vec<ProcessSummary> = summaries.into_values().collect();
+    summaries.sort_by_key(|summary| (std::cmp::Reverse(summary.events), summary.first_seen_ms.unwrap_or(u64::MAX)));
+    summaries
+}
@@ -0,0 +1,297 @@
+//! Lock-free ring of fixed-size records
+//!
+//! The ring lives in memory that may be shared between processes, so it is
+//! made of atomics only and holds no pointers. Every slot carries the
+//! sequence number of its record and works as a seqlock: a writer marks the
+//! slot busy, fills it and then publishes the sequence number; a reader
+//! copies the slot and keeps it only if the sequence number was published
+//! before and unchanged after the copy.
+//!
+//! Between the header and the slots sits a small table of the processes
+//! seen while recording. Its entries are claimed with a busy flag and
+//! published by their hash, so readers skip entries being written.
+
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+/// "KMIR" in little endian
+pub const RING_MAGIC: u32 = 0x5249_4D4B;
+pub const RING_VERSION: u32 = 2;
+
+/// Set in `RingHeader::flags` while events are recorded
+pub const FLAG_RECORDING: u32 = 1 << 0;
+/// Set in `RingHeader::flags` when composing text may be recorded
+pub const FLAG_VERBOSE: u32 = 1 << 1;
+
+/// UTF-16 code units of composing text kept per record in verbose mode
+pub const TEXT_CAPACITY: usize = 16;
+
+/// UTF-16 code units of a process display name
+pub const NAME_CAPACITY: usize = 20;
+/// Processes the table holds; the one seen least recently makes room
+pub const PROCESS_CAPACITY: usize = 32;
+
+/// Set in `ProcessSlot::flags` for packaged (UWP) apps
+pub const PROCESS_UWP: u32 = 1 << 0;
+/// Set in `ProcessSlot::flags` while the entry is written
+const PROCESS_BUSY: u32 = 1 << 31;
+
+/// Sequence number of a slot that is being written
+const BUSY: u64 = u64::MAX;
+
+/// Start of the shared region (32 bytes)
+#[repr(C)]
+pub struct RingHeader {
+    pub magic: AtomicU32,
+    pub version: AtomicU32,
+    /// Number of slots following the header
+    pub capacity: AtomicU32,
+    pub flags: AtomicU32,
+    /// Sequence number of the last record claimed (records start at 1)
+    pub next_seq: AtomicU64,
+    /// Milliseconds since the Unix epoch when recording was last started
+    pub started_at_ms: AtomicU64,
+}
+
+/// One record (64 bytes)
+#[repr(C)]
+pub struct RingSlot {
+    /// 0 when empty, `BUSY` while written
+    pub seq: AtomicU64,
+    pub timestamp_ms: AtomicU64,
+    pub process_hash: AtomicU32,
+    /// Windows VK code in the low half, modifier flags in the high half
+    pub key: AtomicU32,
+    /// Action type, processed flag (bit 8) and text length (bits 16..)
+    pub action: AtomicU32,
+    /// Delete count in the low half, composing length in the high half
+    pub counts: AtomicU32,
+    /// Composing text, two UTF-16 code units per word (verbose only)
+    pub text: [AtomicU32; TEXT_CAPACITY / 2],
+}
+
+/// A process seen while recording (64 bytes)
+#[repr(C)]
+pub struct ProcessSlot {
+    /// Hash of the process name; 0 while empty or being written
+    pub hash: AtomicU32,
+    /// `PROCESS_*` flags
+    pub flags: AtomicU32,
+    pub first_seen_ms: AtomicU64,
+    pub last_seen_ms: AtomicU64,
+    /// Display name, two UTF-16 code units per word, NUL-padded
+    pub name: [AtomicU32; NAME_CAPACITY / 2],
+}
+
+/// Bytes before the first slot
+const SLOTS_OFFSET: usize = std::mem::size_of::<RingHeader>() + PROCESS_CAPACITY * std::mem::size_of::<ProcessSlot>();
+
+/// Bytes needed for a ring of `capacity` slots
+pub const fn region_size(capacity: usize) -> usize {
+    SLOTS_OFFSET + capacity * std::mem::size_of::<RingSlot>()
+}
+
+/// Raw contents of a process entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProcessData {
+    pub hash: u32,
+    pub flags: u32,
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+    pub name: [u32; NAME_CAPACITY / 2],
+}
+
+/// Raw contents of a slot, as written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SlotData {
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    pub process_hash: u32,
+    pub key: u32,
+    pub action: u32,
+    pub counts: u32,
+    pub text: [u32; TEXT_CAPACITY / 2],
+}
+
+/// A ring laid over a header and its slots
+#[derive(Clone, Copy)]
+pub struct Ring<'a> {
+    pub header: &'a RingHeader,
+    pub processes: &'a [ProcessSlot],
+    pub slots: &'a [RingSlot],
+}
+
+impl<'a> Ring<'a> {
+    /// Views a region of at least `region_size(capacity)` bytes
+    ///
+    /// # Safety
+    /// `base` must be 8-byte aligned, valid for the size above for `'a`,
+    /// and only accessed through atomics while viewed.
+    pub unsafe fn from_raw(base: *const u8, capacity: usize) -> Ring<'a> {
+        let header = &*(base as *const RingHeader);
+        let processes_base = base.add(std::mem::size_of::<RingHeader>()) as *const ProcessSlot;
+        let slots_base = base.add(SLOTS_OFFSET) as *const RingSlot;
+        Ring {
+            header,
+            processes: std::slice::from_raw_parts(processes_base, PROCESS_CAPACITY),
+            slots: std::slice::from_raw_parts(slots_base, capacity),
+        }
+    }
+
+    /// Writes a fresh header; existing records are discarded
+    pub fn initialize(&self) {
+        for slot in self.slots {
+            slot.seq.store(0, Ordering::Relaxed);
+        }
+        for process in self.processes {
+            process.hash.store(0, Ordering::Relaxed);
+            process.flags.store(0, Ordering::Relaxed);
+        }
+        self.header.flags.store(0, Ordering::Relaxed);
+        self.header.next_seq.store(0, Ordering::Relaxed);
+        self.header.started_at_ms.store(0, Ordering::Relaxed);
+        self.header.capacity.store(self.slots.len() as u32, Ordering::Relaxed);
+        self.header.version.store(RING_VERSION, Ordering::Relaxed);
+        self.header.magic.store(RING_MAGIC, Ordering::Release);
+    }
+
+    /// Whether the header was written by `initialize` for this layout
+    #[cfg(windows)]
+    pub fn is_valid(&self) -> bool {
+        self.header.magic.load(Ordering::Acquire) == RING_MAGIC
+            && self.header.version.load(Ordering::Relaxed) == RING_VERSION
+            && self.header.capacity.load(Ordering::Relaxed) as usize == self.slots.len()
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.header.flags.load(Ordering::Acquire)
+    }
+
+    /// Claims the next sequence number and writes `data` under it
+    ///
+    /// Writers never wait for each other. A slot is written by one writer at
+    /// a time: a writer that finds its slot busy, or already holding a newer
+    /// record because it fell a full lap behind, drops its record. Dropped
+    /// records still use up their sequence number.
+    pub fn push(&self, data: &SlotData) -> u64 {
+        let seq = self.header.next_seq.fetch_add(1, Ordering::Relaxed) + 1;
+        let slot = &self.slots[((seq - 1) % self.slots.len() as u64) as usize];
+
+        let current = slot.seq.load(Ordering::Relaxed);
+        if current == BUSY
+            || current > seq
+            || slot.seq.compare_exchange(current, BUSY, Ordering::Relaxed, Ordering::Relaxed).is_err()
+        {
+            return seq;
+        }
+        fence(Ordering::Release);
+        slot.timestamp_ms.store(data.timestamp_ms, Ordering::Relaxed);
+        slot.process_hash.store(data.process_hash, Ordering::Relaxed);
+        slot.key.store(data.key, Ordering::Relaxed);
+        slot.action.store(data.action, Ordering::Relaxed);
+        slot.counts.store(data.counts, Ordering::Relaxed);
+        for (word, value) in slot.text.iter().zip(data.text) {
+            word.store(value, Ordering::Relaxed);
+        }
+        slot.seq.store(seq, Ordering::Release);
+        seq
+    }
+
+    /// Copies a slot, or `None` if it is empty or changed while copied
+    fn read_slot(slot: &RingSlot) -> Option<SlotData> {
+        let seq = slot.seq.load(Ordering::Acquire);
+        if seq == 0 || seq == BUSY {
+            return None;
+        }
+        let mut data = SlotData {
+            seq,
+            timestamp_ms: slot.timestamp_ms.load(Ordering::Relaxed),
+            process_hash: slot.process_hash.load(Ordering::Relaxed),
+            key: slot.key.load(Ordering::Relaxed),
+            action: slot.action.load(Ordering::Relaxed),
+            counts: slot.counts.load(Ordering::Relaxed),
+            text: [0; TEXT_CAPACITY / 2],
+        };
+        for (value, word) in data.text.iter_mut().zip(&slot.text) {
+            *value = word.load(Ordering::Relaxed);
+        }
+        fence(Ordering::Acquire);
+        (slot.seq.load(Ordering::Relaxed) == seq).then_some(data)
+    }
+
+    /// Notes that the process `data.hash` was seen at `data.last_seen_ms`,
+    /// adding it with `data` if the table lacks it
+    ///
+    /// Like `push` this never waits: an entry another writer holds is left
+    /// alone, and an add that loses a race is dropped and retried by the
+    /// next record. Two threads adding the same process at once may both
+    /// succeed; readers merge such entries.
+    pub fn note_process(&self, data: &ProcessData) {
+        let mut victim: Option<(&ProcessSlot, u32, u64)> = None;
+        for process in self.processes {
+            let flags = process.flags.load(Ordering::Acquire);
+            let hash = process.hash.load(Ordering::Acquire);
+            if hash == data.hash && flags & PROCESS_BUSY == 0 {
+                process.last_seen_ms.fetch_max(data.last_seen_ms, Ordering::Relaxed);
+                return;
+            }
+            if flags & PROCESS_BUSY != 0 {
+                continue;
+            }
+            // Empty entries first, then the one seen least recently
+            let seen = if hash == 0 { 0 } else { process.last_seen_ms.load(Ordering::Relaxed) };
+            if victim.is_none_or(|(_, _, oldest)| seen < oldest) {
+                victim = Some((process, flags, seen));
+            }
+        }
+
+        let Some((process, flags, _)) = victim else {
+            return;
+        };
+        if process.flags.compare_exchange(flags, PROCESS_BUSY, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return;
+        }
+        process.hash.store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+        process.first_seen_ms.store(data.first_seen_ms, Ordering::Relaxed);
+        process.last_seen_ms.store(data.last_seen_ms, Ordering::Relaxed);
+        for (word, value) in process.name.iter().zip(data.name) {
+            word.store(value, Ordering::Relaxed);
+        }
+        process.flags.store(data.flags & !PROCESS_BUSY, Ordering::Release);
+        process.hash.store(data.hash, Ordering::Release);
+    }
+
+    /// Copies a process entry, or `None` if it is empty or changed while
+    /// copied
+    fn read_process(process: &ProcessSlot) -> Option<ProcessData> {
+        let hash = process.hash.load(Ordering::Acquire);
+        let flags = process.flags.load(Ordering::Acquire);
+        if hash == 0 || flags & PROCESS_BUSY != 0 {
+            return None;
+        }
+        let mut data = ProcessData {
+            hash,
+            flags,
+            first_seen_ms: process.first_seen_ms.load(Ordering::Relaxed),
+            last_seen_ms: process.last_seen_ms.load(Ordering::Relaxed),
+            name: [0; NAME_CAPACITY / 2],
+        };
+        for (value, word) in data.name.iter_mut().zip(&process.name) {
+            *value = word.load(Ordering::Relaxed);
+        }
+        fence(Ordering::Acquire);
+        (process.flags.load(Ordering::Relaxed) == flags && process.hash.load(Ordering::Relaxed) == hash).then_some(data)
+    }
+
+    /// Consistent process entries, in table order
+    pub fn processes(&self) -> Vec<ProcessData> {
+        self.processes.iter().filter_map(Self::read_process).collect()
+    }
+
+    /// Consistent records currently in the ring, oldest first
+    pub fn snapshot(&self) -> Vec<SlotData> {
+        let mut records: Vec<SlotData> = self.slots.iter().filter_map(Self::read_slot).collect();
+        records.sort_by_key(|record| record.seq);
+        records
+    }
+}
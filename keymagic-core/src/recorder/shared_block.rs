@@ -0,0 +1,111 @@
+//! Fixed blocks of atomics shared by the GUI and the text services
+//!
+//! Several kinds of state (the input mode, the processing switch, logs and
+//! counters) each live in one `#[repr(C)]` block of atomic integers. On
+//! Windows the block is a named [`Section`](super::shared_memory::Section)
+//! that every host maps; elsewhere, and in tests, it is process-local.
+//! [`SharedBlock`] holds either and dereferences to the block.
+
+use std::ops::Deref;
+
+/// Layout of a block that may be mapped by several processes
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]`, made of atomic integers only, so that
+/// all zeros is a valid value and concurrent access from other processes is
+/// sound.
+pub(crate) unsafe trait BlockLayout {
+    /// Writes the header of a new block; the magic last
+    fn initialize(&self);
+
+    /// Whether the block has the magic and version this build writes
+    fn is_valid(&self) -> bool;
+}
+
+enum Storage<T> {
+    Heap(Box<T>),
+    #[cfg(windows)]
+    Shared(super::shared_memory::Section),
+}
+
+/// A block in named shared memory, or a process-local one
+pub(crate) struct SharedBlock<T: BlockLayout> {
+    storage: Storage<T>,
+}
+
+impl<T: BlockLayout> SharedBlock<T> {
+    /// A process-local block, for tests and platforms without shared state
+    pub(crate) fn in_memory() -> Self {
+        // All zeros is valid, as `BlockLayout` requires
+        let block: Box<T> = unsafe { Box::new(std::mem::zeroed()) };
+        block.initialize();
+        Self { storage: Storage::Heap(block) }
+    }
+
+    /// Creates the section `name`, or attaches to it if it already exists;
+    /// `what` names the block in errors
+    ///
+    /// A new section is initialized; an existing one must hold a valid block.
+    #[cfg(windows)]
+    pub(crate) fn create_shared(name: &str, what: &str) -> std::io::Result<Self> {
+        use std::io::{Error, ErrorKind};
+
+        let size = std::mem::size_of::<T>();
+        let (section, created) = super::shared_memory::Section::create(name, size)?;
+        if section.size() < size {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} section is too small", what)));
+        }
+        let block = Self { storage: Storage::Shared(section) };
+        if created {
+            block.initialize();
+        } else if !block.is_valid() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} section has an unknown layout", what)));
+        }
+        Ok(block)
+    }
+}
+
+impl<T: BlockLayout> Deref for SharedBlock<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match &self.storage {
+            Storage::Heap(block) => block,
+            // The view is page aligned, large enough, and only accessed as atomics
+            #[cfg(windows)]
+            Storage::Shared(section) => unsafe { &*(section.as_ptr() as *const T) },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[repr(C)]
+    struct Counter {
+        magic: AtomicU32,
+        count: AtomicU32,
+    }
+
+    unsafe impl BlockLayout for Counter {
+        fn initialize(&self) {
+            self.count.store(0, Ordering::Relaxed);
+            self.magic.store(0x4B4D, Ordering::Release);
+        }
+
+        fn is_valid(&self) -> bool {
+            self.magic.load(Ordering::Acquire) == 0x4B4D
+        }
+    }
+
+    #[test]
+    fn test_in_memory_block_is_initialized() {
+        let block = SharedBlock::<Counter>::in_memory();
+        assert!(block.is_valid());
+        block.count.fetch_add(2, Ordering::Relaxed);
+        assert_eq!(block.count.load(Ordering::Relaxed), 2);
+    }
+}
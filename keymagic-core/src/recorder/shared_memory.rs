@@ -0,0 +1,278 @@
+//! Named shared memory holding the ring on Windows
+//!
+//! The GUI creates the section; text services loaded into applications open
+//! it. It lives in the session's `Local\` namespace and is writable from
+//! low-integrity and AppContainer processes, where many browsers run.
+//! [`Section`] is also used for other state shared with the text services.
+
+use std::ffi::c_void;
+use std::io;
+
+use super::ring::{region_size, Ring};
+
+type Handle = *mut c_void;
+
+const INVALID_HANDLE_VALUE: Handle = -1isize as Handle;
+const PAGE_READWRITE: u32 = 0x04;
+const FILE_MAP_WRITE: u32 = 0x0002;
+const FILE_MAP_READ: u32 = 0x0004;
+const ERROR_ALREADY_EXISTS: i32 = 183;
+const ERROR_FILE_NOT_FOUND: i32 = 2;
+const SYNCHRONIZE: u32 = 0x0010_0000;
+const SDDL_REVISION_1: u32 = 1;
+
+/// Name of the section shared by the GUI and the text services
+pub const SECTION_NAME: &str = r"Local\KeyMagicInputRecorder";
+
+/// Owner, SYSTEM and AppContainers get full access; low integrity may write.
+/// Also used for named mutexes.
+const SECTION_SDDL: &str = "D:P(A;;GA;;;SY)(A;;GA;;;OW)(A;;GA;;;AC)S:(ML;;NW;;;LW)";
+
+#[repr(C)]
+struct SecurityAttributes {
+    length: u32,
+    security_descriptor: *mut c_void,
+    inherit_handle: i32,
+}
+
+#[repr(C)]
+struct MemoryBasicInformation {
+    base_address: *mut c_void,
+    allocation_base: *mut c_void,
+    allocation_protect: u32,
+    #[cfg(target_pointer_width = "64")]
+    partition_id: u16,
+    region_size: usize,
+    state: u32,
+    protect: u32,
+    kind: u32,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn CreateFileMappingW(file: Handle, attributes: *const SecurityAttributes, protect: u32, size_high: u32, size_low: u32, name: *const u16) -> Handle;
+    fn OpenFileMappingW(access: u32, inherit: i32, name: *const u16) -> Handle;
+    fn MapViewOfFile(mapping: Handle, access: u32, offset_high: u32, offset_low: u32, bytes: usize) -> *mut c_void;
+    fn UnmapViewOfFile(base: *const c_void) -> i32;
+    fn VirtualQuery(address: *const c_void, info: *mut MemoryBasicInformation, length: usize) -> usize;
+    fn CloseHandle(handle: Handle) -> i32;
+    fn CreateMutexW(attributes: *const SecurityAttributes, initial_owner: i32, name: *const u16) -> Handle;
+    fn OpenMutexW(access: u32, inherit: i32, name: *const u16) -> Handle;
+    fn LocalFree(memory: *mut c_void) -> *mut c_void;
+}
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn ConvertStringSecurityDescriptorToSecurityDescriptorW(sddl: *const u16, revision: u32, descriptor: *mut *mut c_void, size: *mut u32) -> i32;
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// A mapped view of a named section, writable by the text services
+pub(crate) struct Section {
+    mapping: Handle,
+    view: *mut c_void,
+    /// Size of the view, rounded up to whole pages
+    size: usize,
+}
+
+// The view is only accessed through atomics
+unsafe impl Send for Section {}
+unsafe impl Sync for Section {}
+
+impl Section {
+    /// Creates a section of at least `size` bytes, or opens it if it exists
+    ///
+    /// Returns whether the section was created; a new section is zeroed.
+    pub(crate) fn create(name: &str, size: usize) -> io::Result<(Self, bool)> {
+        let name = wide(name);
+        let sddl = wide(SECTION_SDDL);
+        unsafe {
+            let mut descriptor = std::ptr::null_mut();
+            if ConvertStringSecurityDescriptorToSecurityDescriptorW(sddl.as_ptr(), SDDL_REVISION_1, &mut descriptor, std::ptr::null_mut()) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let attributes = SecurityAttributes {
+                length: std::mem::size_of::<SecurityAttributes>() as u32,
+                security_descriptor: descriptor,
+                inherit_handle: 0,
+            };
+            let mapping = CreateFileMappingW(INVALID_HANDLE_VALUE, &attributes, PAGE_READWRITE, 0, size as u32, name.as_ptr());
+            let error = io::Error::last_os_error();
+            LocalFree(descriptor);
+            if mapping.is_null() {
+                return Err(error);
+            }
+            let created = error.raw_os_error() != Some(ERROR_ALREADY_EXISTS);
+            Ok((Self::map(mapping)?, created))
+        }
+    }
+
+    /// Opens an existing section
+    pub(crate) fn open(name: &str) -> io::Result<Self> {
+        let name = wide(name);
+        unsafe {
+            let mapping = OpenFileMappingW(FILE_MAP_READ | FILE_MAP_WRITE, 0, name.as_ptr());
+            if mapping.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Self::map(mapping)
+        }
+    }
+
+    /// Maps the whole section
+    unsafe fn map(mapping: Handle) -> io::Result<Self> {
+        let view = MapViewOfFile(mapping, FILE_MAP_READ | FILE_MAP_WRITE, 0, 0, 0);
+        if view.is_null() {
+            let error = io::Error::last_os_error();
+            CloseHandle(mapping);
+            return Err(error);
+        }
+
+        let mut info: MemoryBasicInformation = std::mem::zeroed();
+        if VirtualQuery(view, &mut info, std::mem::size_of::<MemoryBasicInformation>()) == 0 {
+            let error = io::Error::last_os_error();
+            UnmapViewOfFile(view);
+            CloseHandle(mapping);
+            return Err(error);
+        }
+        Ok(Self { mapping, view, size: info.region_size })
+    }
+
+    /// Start of the view; page aligned
+    pub(crate) fn as_ptr(&self) -> *const u8 {
+        self.view as *const u8
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for Section {
+    fn drop(&mut self) {
+        unsafe {
+            UnmapViewOfFile(self.view);
+            CloseHandle(self.mapping);
+        }
+    }
+}
+
+/// A named mutex held for the lifetime of the value
+///
+/// The system releases it when the process exits, crashes included, so
+/// other processes can tell whether its creator is still running.
+pub(crate) struct NamedMutex {
+    handle: Handle,
+}
+
+// Only the handle is kept; it is never waited on
+unsafe impl Send for NamedMutex {}
+unsafe impl Sync for NamedMutex {}
+
+impl NamedMutex {
+    /// Creates the mutex, or opens it if another process created it
+    pub(crate) fn create(name: &str) -> io::Result<Self> {
+        let name = wide(name);
+        let sddl = wide(SECTION_SDDL);
+        unsafe {
+            let mut descriptor = std::ptr::null_mut();
+            if ConvertStringSecurityDescriptorToSecurityDescriptorW(sddl.as_ptr(), SDDL_REVISION_1, &mut descriptor, std::ptr::null_mut()) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let attributes = SecurityAttributes {
+                length: std::mem::size_of::<SecurityAttributes>() as u32,
+                security_descriptor: descriptor,
+                inherit_handle: 0,
+            };
+            let handle = CreateMutexW(&attributes, 0, name.as_ptr());
+            let error = io::Error::last_os_error();
+            LocalFree(descriptor);
+            if handle.is_null() {
+                return Err(error);
+            }
+            Ok(Self { handle })
+        }
+    }
+
+    /// Whether some process holds a handle to the mutex `name`
+    ///
+    /// A mutex this process may not open still exists, so only "not found"
+    /// counts as absent.
+    pub(crate) fn exists(name: &str) -> bool {
+        let name = wide(name);
+        unsafe {
+            let handle = OpenMutexW(SYNCHRONIZE, 0, name.as_ptr());
+            if handle.is_null() {
+                return io::Error::last_os_error().raw_os_error() != Some(ERROR_FILE_NOT_FOUND);
+            }
+            CloseHandle(handle);
+            true
+        }
+    }
+}
+
+impl Drop for NamedMutex {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.handle);
+        }
+    }
+}
+
+/// The recorder's section
+pub struct SharedSection {
+    section: Section,
+    capacity: usize,
+}
+
+impl SharedSection {
+    /// Creates the section for `capacity` records, or opens it if it exists
+    ///
+    /// A new section starts with an empty, stopped ring.
+    pub fn create(capacity: usize) -> io::Result<Self> {
+        let (section, created) = Section::create(SECTION_NAME, region_size(capacity))?;
+        let mut section = Self::with_slots(section);
+        if created {
+            section.capacity = capacity.min(section.capacity);
+            section.ring().initialize();
+        } else {
+            // Created by an earlier run, possibly with another capacity
+            section.adopt_capacity()?;
+        }
+        Ok(section)
+    }
+
+    /// Opens the section created by the GUI
+    pub fn open() -> io::Result<Self> {
+        let mut section = Self::with_slots(Section::open(SECTION_NAME)?);
+        section.adopt_capacity()?;
+        Ok(section)
+    }
+
+    /// Derives the capacity from the size of the view
+    fn with_slots(section: Section) -> Self {
+        // The view is rounded up to whole pages; count the slots that fit
+        let capacity = section.size().saturating_sub(region_size(0)) / (region_size(1) - region_size(0));
+        Self { section, capacity }
+    }
+
+    /// Takes the capacity from the header, checking the ring fits the view
+    fn adopt_capacity(&mut self) -> io::Result<()> {
+        let fits = self.capacity;
+        let header = unsafe { Ring::from_raw(self.section.as_ptr(), 0) }.header;
+        self.capacity = header.capacity.load(std::sync::atomic::Ordering::Relaxed) as usize;
+        if self.capacity > fits || !self.ring().is_valid() {
+            self.capacity = 0;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "input recorder section has an unknown layout"));
+        }
+        Ok(())
+    }
+
+    /// The ring over the mapped view
+    pub fn ring(&self) -> Ring<'_> {
+        unsafe { Ring::from_raw(self.section.as_ptr(), self.capacity) }
+    }
+}
@@ -0,0 +1,276 @@
+//! Shortcuts the GUI watches for through the text services
+//!
+//! A system-wide hotkey conflicts with some applications, but every key
+//! already passes through a text service. The GUI publishes a short list of
+//! key combinations it is interested in; a text service checks keys the
+//! keyboard leaves alone against the list and reports a match to a small
+//! ring of events, which the GUI polls. A shortcut may ask for its key to be
+//! swallowed, in which case the host commits any composing text first.
+//!
+//! On Windows the list and the ring live in named shared memory. Elsewhere,
+//! and in tests, they are process-local.
+
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+use crate::hotkey::KeyCombo;
+use crate::recorder::shared_block::{BlockLayout, SharedBlock};
+use crate::VirtualKey;
+
+/// Name of the section holding the list on Windows
+#[cfg(windows)]
+pub const SECTION_NAME: &str = r"Local\KeyMagicShortcuts";
+
+/// "KMSW" in little endian
+const BLOCK_MAGIC: u32 = 0x5753_4D4B;
+const BLOCK_VERSION: u32 = 1;
+
+/// Shortcuts the list holds; later ones are not published
+pub const MAX_SHORTCUTS: usize = 16;
+/// Events kept in the ring until the GUI reads them
+pub const EVENT_SLOTS: usize = 16;
+
+const MOD_CTRL: u32 = 1 << 0;
+const MOD_ALT: u32 = 1 << 1;
+const MOD_SHIFT: u32 = 1 << 2;
+const MOD_META: u32 = 1 << 3;
+
+/// Set in an entry's `flags` when the host keeps the key from the application
+const FLAG_SWALLOW: u32 = 1 << 0;
+
+/// Sequence number of a slot that is being written
+const BUSY: u64 = u64::MAX;
+
+/// Tries a reader makes while the GUI rewrites the list
+const READ_ATTEMPTS: usize = 4;
+
+#[repr(C)]
+struct Entry {
+    /// Key code in the low half, modifier bits in the high half
+    combo: AtomicU32,
+    /// Chosen by the GUI, never 0
+    action: AtomicU32,
+    flags: AtomicU32,
+    _reserved: AtomicU32,
+}
+
+/// One fired shortcut; `seq` works as a seqlock like the commit log's
+#[repr(C)]
+struct Slot {
+    /// 0 when empty, `BUSY` while written
+    seq: AtomicU64,
+    action: AtomicU32,
+    process_id: AtomicU32,
+}
+
+#[repr(C)]
+struct Block {
+    magic: AtomicU32,
+    version: AtomicU32,
+    /// Odd while the GUI rewrites the list
+    list_seq: AtomicU32,
+    count: AtomicU32,
+    entries: [Entry; MAX_SHORTCUTS],
+    /// Sequence number of the last event claimed (events start at 1)
+    next_seq: AtomicU64,
+    slots: [Slot; EVENT_SLOTS],
+}
+
+fn encode(combo: &KeyCombo) -> u32 {
+    let mut modifiers = 0;
+    for (set, bit) in [(combo.ctrl, MOD_CTRL), (combo.alt, MOD_ALT), (combo.shift, MOD_SHIFT), (combo.meta, MOD_META)] {
+        if set {
+            modifiers |= bit;
+        }
+    }
+    combo.key as u32 | (modifiers << 16)
+}
+
+fn decode(value: u32) -> Option<KeyCombo> {
+    let modifiers = value >> 16;
+    Some(KeyCombo {
+        key: VirtualKey::from_raw(value as u16)?,
+        ctrl: modifiers & MOD_CTRL != 0,
+        alt: modifiers & MOD_ALT != 0,
+        shift: modifiers & MOD_SHIFT != 0,
+        meta: modifiers & MOD_META != 0,
+    })
+}
+
+unsafe impl BlockLayout for Block {
+    fn initialize(&self) {
+        for entry in &self.entries {
+            entry.combo.store(0, Ordering::Relaxed);
+            entry.action.store(0, Ordering::Relaxed);
+            entry.flags.store(0, Ordering::Relaxed);
+        }
+        for slot in &self.slots {
+            slot.seq.store(0, Ordering::Relaxed);
+        }
+        self.list_seq.store(0, Ordering::Relaxed);
+        self.count.store(0, Ordering::Relaxed);
+        self.next_seq.store(0, Ordering::Relaxed);
+        self.version.store(BLOCK_VERSION, Ordering::Relaxed);
+        self.magic.store(BLOCK_MAGIC, Ordering::Release);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic.load(Ordering::Acquire) == BLOCK_MAGIC && self.version.load(Ordering::Relaxed) == BLOCK_VERSION
+    }
+}
+
+impl Block {
+    /// Copies the list, or `None` if the GUI kept rewriting it
+    fn read_list(&self) -> Option<Vec<WatchedShortcut>> {
+        for _ in 0..READ_ATTEMPTS {
+            let seq = self.list_seq.load(Ordering::Acquire);
+            if seq % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let count = (self.count.load(Ordering::Relaxed) as usize).min(MAX_SHORTCUTS);
+            let shortcuts: Vec<(u32, u32, u32)> = self.entries[..count]
+                .iter()
+                .map(|entry| {
+                    (entry.combo.load(Ordering::Relaxed), entry.action.load(Ordering::Relaxed), entry.flags.load(Ordering::Relaxed))
+                })
+                .collect();
+            fence(Ordering::Acquire);
+            if self.list_seq.load(Ordering::Relaxed) == seq {
+                return Some(
+                    shortcuts
+                        .into_iter()
+                        .filter_map(|(combo, action, flags)| {
+                            Some(WatchedShortcut { combo: decode(combo)?, action, swallow: flags & FLAG_SWALLOW != 0 })
+                        })
+                        .collect(),
+                );
+            }
+        }
+        None
+    }
+
+    /// Writes the event under the next sequence number; a writer that finds
+    /// its slot busy or a lap ahead drops the event, as the commit log does
+    fn push(&self, action: u32, process_id: u32) -> u64 {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed) + 1;
+        let slot = &self.slots[((seq - 1) % EVENT_SLOTS as u64) as usize];
+
+        let current = slot.seq.load(Ordering::Relaxed);
+        if current == BUSY
+            || current > seq
+            || slot.seq.compare_exchange(current, BUSY, Ordering::Relaxed, Ordering::Relaxed).is_err()
+        {
+            return seq;
+        }
+        fence(Ordering::Release);
+        slot.action.store(action, Ordering::Relaxed);
+        slot.process_id.store(process_id, Ordering::Relaxed);
+        slot.seq.store(seq, Ordering::Release);
+        seq
+    }
+
+    /// Copies a slot, or `None` if it is empty or changed while copied
+    fn read_slot(slot: &Slot) -> Option<ShortcutEvent> {
+        let seq = slot.seq.load(Ordering::Acquire);
+        if seq == 0 || seq == BUSY {
+            return None;
+        }
+        let action = slot.action.load(Ordering::Relaxed);
+        let process_id = slot.process_id.load(Ordering::Relaxed);
+        fence(Ordering::Acquire);
+        (slot.seq.load(Ordering::Relaxed) == seq).then_some(ShortcutEvent { seq, action, process_id })
+    }
+}
+
+/// A key combination the GUI watches for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchedShortcut {
+    pub combo: KeyCombo,
+    /// What the GUI does when it fires; 0 is not a valid action
+    pub action: u32,
+    /// Whether the host keeps the key from the application
+    pub swallow: bool,
+}
+
+/// A shortcut reported by a host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShortcutEvent {
+    pub seq: u64,
+    pub action: u32,
+    /// Process the key was pressed in
+    pub process_id: u32,
+}
+
+/// Handle to the shared list of watched shortcuts
+pub struct ShortcutWatch {
+    block: SharedBlock<Block>,
+}
+
+impl ShortcutWatch {
+    /// A process-local block, for tests and platforms without shared state
+    pub fn in_memory() -> Self {
+        Self { block: SharedBlock::in_memory() }
+    }
+
+    /// Creates the shared block, or attaches to it if it already exists
+    ///
+    /// A new block watches for nothing until the GUI publishes its list.
+    #[cfg(windows)]
+    pub fn create_shared() -> std::io::Result<Self> {
+        Ok(Self { block: SharedBlock::create_shared(SECTION_NAME, "shortcut")? })
+    }
+
+    /// Replaces the list; only the GUI writes it. Shortcuts past
+    /// [`MAX_SHORTCUTS`] and those with action 0 are left out.
+    pub fn set_shortcuts(&self, shortcuts: &[WatchedShortcut]) {
+        let block = &*self.block;
+        let shortcuts: Vec<&WatchedShortcut> = shortcuts.iter().filter(|shortcut| shortcut.action != 0).take(MAX_SHORTCUTS).collect();
+
+        block.list_seq.fetch_add(1, Ordering::AcqRel);
+        fence(Ordering::Release);
+        for (entry, shortcut) in block.entries.iter().zip(&shortcuts) {
+            entry.combo.store(encode(&shortcut.combo), Ordering::Relaxed);
+            entry.action.store(shortcut.action, Ordering::Relaxed);
+            entry.flags.store(if shortcut.swallow { FLAG_SWALLOW } else { 0 }, Ordering::Relaxed);
+        }
+        block.count.store(shortcuts.len() as u32, Ordering::Relaxed);
+        block.list_seq.fetch_add(1, Ordering::Release);
+    }
+
+    /// The published list; empty while the GUI is rewriting it
+    pub fn shortcuts(&self) -> Vec<WatchedShortcut> {
+        self.block.read_list().unwrap_or_default()
+    }
+
+    /// The shortcut a key pressed with these modifiers fires, if any
+    ///
+    /// Hosts only see Shift, Ctrl and Alt, so shortcuts with the Win or
+    /// Command key never match here.
+    pub fn find(&self, key: VirtualKey, ctrl: bool, alt: bool, shift: bool) -> Option<WatchedShortcut> {
+        let pressed = KeyCombo { key, ctrl, alt, shift, meta: false };
+        self.block.read_list()?.into_iter().find(|shortcut| shortcut.combo == pressed)
+    }
+
+    /// Reports that a shortcut fired and returns the event's sequence number
+    pub fn report(&self, action: u32, process_id: u32) -> u64 {
+        self.block.push(action, process_id)
+    }
+
+    /// Sequence number of the last event reported
+    pub fn last_seq(&self) -> u64 {
+        self.block.next_seq.load(Ordering::Acquire)
+    }
+
+    /// Events reported after `after` that are still in the ring, oldest first
+    pub fn read_since(&self, after: u64) -> Vec<ShortcutEvent> {
+        let mut events: Vec<ShortcutEvent> = self
+            .block
+            .slots
+            .iter()
+            .filter_map(Block::read_slot)
+            .filter(|event| event.seq > after)
+            .collect();
+        events.sort_by_key(|event| event.seq);
+        events
+    }
+}
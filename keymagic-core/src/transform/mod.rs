@@ -0,0 +1,60 @@
+//! Output transforms applied to text emitted by the engine
+//!
+//! Rule matching always runs against the Unicode composing buffer. A
+//! transform only rewrites what is handed to the host (the output composing
+//! text and the insert/delete actions), so delete counts are computed against
+//! the transformed text the host actually has on screen.
+//!
+//! The composing text is transformed on every key, not only when it is
+//! committed: hosts typing straight into the document show it as it is
+//! composed, and committing leaves that text in place. `flush` therefore
+//! returns the composing text exactly as last emitted; there is no separate
+//! transform step at commit.
+
+#[cfg(feature = "zawgyi")]
+pub mod zawgyi;
+
+/// Identifies a built-in output transform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransformId {
+    /// Unicode to Zawgyi-One legacy encoding
+    Zawgyi,
+}
+
+impl TransformId {
+    /// Parses a transform name as stored in configuration ("zawgyi")
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "zawgyi" => Some(TransformId::Zawgyi),
+            _ => None,
+        }
+    }
+
+    /// Returns the configuration name of the transform
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransformId::Zawgyi => "zawgyi",
+        }
+    }
+}
+
+/// A text transform applied to engine output
+pub trait Transform: Send + Sync {
+    /// Identifier of this transform
+    fn id(&self) -> TransformId;
+
+    /// Transforms a complete Unicode string
+    fn apply(&self, text: &str) -> String;
+}
+
+/// Creates the built-in transform for the given id
+///
+/// Returns `None` when the transform was not compiled in.
+pub fn create_transform(id: TransformId) -> Option<Box<dyn Transform>> {
+    match id {
+        #[cfg(feature = "zawgyi")]
+        TransformId::Zawgyi => Some(Box::new(zawgyi::ZawgyiTransform)),
+        #[cfg(not(feature = "zawgyi"))]
+        TransformId::Zawgyi => None,
+    }
+}
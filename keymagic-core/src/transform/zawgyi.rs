@@ -0,0 +1,303 @@
+//! Unicode to Zawgyi-One conversion
+//!
+//! Zawgyi stores Myanmar text in visual order and uses dedicated code points
+//! for stacked consonants, kinzi and contextual glyph variants. Conversion is
+//! done per syllable: the Unicode syllable is parsed into its components and
+//! re-emitted in Zawgyi order.
+
+use super::{Transform, TransformId};
+
+/// Built-in Unicode to Zawgyi transform
+pub struct ZawgyiTransform;
+
+impl Transform for ZawgyiTransform {
+    fn id(&self) -> TransformId {
+        TransformId::Zawgyi
+    }
+
+    fn apply(&self, text: &str) -> String {
+        unicode_to_zawgyi(text)
+    }
+}
+
+const VIRAMA: char = '\u{1039}';
+const ASAT: char = '\u{103A}';
+const NGA: char = '\u{1004}';
+
+/// Subscript (stacked) consonant forms
+const STACKED: &[(char, char)] = &[
+    ('\u{1000}', '\u{1060}'),
+    ('\u{1001}', '\u{1061}'),
+    ('\u{1002}', '\u{1062}'),
+    ('\u{1003}', '\u{1063}'),
+    ('\u{1005}', '\u{1065}'),
+    ('\u{1006}', '\u{1066}'),
+    ('\u{1007}', '\u{1068}'),
+    ('\u{1008}', '\u{1069}'),
+    ('\u{100B}', '\u{106C}'),
+    ('\u{100C}', '\u{106D}'),
+    ('\u{100F}', '\u{1070}'),
+    ('\u{1010}', '\u{1071}'),
+    ('\u{1011}', '\u{1073}'),
+    ('\u{1012}', '\u{1075}'),
+    ('\u{1013}', '\u{1076}'),
+    ('\u{1014}', '\u{1077}'),
+    ('\u{1015}', '\u{1078}'),
+    ('\u{1016}', '\u{1079}'),
+    ('\u{1017}', '\u{107A}'),
+    ('\u{1018}', '\u{107B}'),
+    ('\u{1019}', '\u{107C}'),
+    ('\u{101C}', '\u{1085}'),
+];
+
+fn stacked_form(ch: char) -> Option<char> {
+    STACKED.iter().find(|(uni, _)| *uni == ch).map(|(_, zg)| *zg)
+}
+
+fn is_consonant(ch: char) -> bool {
+    ('\u{1000}'..='\u{1021}').contains(&ch)
+}
+
+/// Combining marks that attach to the preceding base character
+fn is_mark(ch: char) -> bool {
+    ('\u{102B}'..='\u{1032}').contains(&ch) || ('\u{1036}'..='\u{103E}').contains(&ch)
+}
+
+/// Components of a single Unicode syllable
+#[derive(Default)]
+struct Syllable {
+    base: Option<char>,
+    kinzi: bool,
+    stacks: Vec<char>,
+    medial_y: bool,
+    medial_r: bool,
+    medial_w: bool,
+    medial_h: bool,
+    vowel_e: bool,
+    upper: Option<char>,
+    lower: Option<char>,
+    aa: Option<char>,
+    anusvara: bool,
+    asat: bool,
+    dot_below: bool,
+    visarga: bool,
+    /// Marks that do not fit the canonical structure, emitted unchanged
+    extra: Vec<char>,
+}
+
+impl Syllable {
+    /// Records a mark, returning the number of characters consumed
+    fn push_mark(&mut self, chars: &[char], i: usize) -> usize {
+        let ch = chars[i];
+
+        if ch == VIRAMA {
+            if let Some(stacked) = chars.get(i + 1).and_then(|c| stacked_form(*c)) {
+                self.stacks.push(stacked);
+                return 2;
+            }
+            self.extra.push(ch);
+            return 1;
+        }
+
+        let slot_taken = match ch {
+            '\u{103B}' => std::mem::replace(&mut self.medial_y, true),
+            '\u{103C}' => std::mem::replace(&mut self.medial_r, true),
+            '\u{103D}' => std::mem::replace(&mut self.medial_w, true),
+            '\u{103E}' => std::mem::replace(&mut self.medial_h, true),
+            '\u{1031}' => std::mem::replace(&mut self.vowel_e, true),
+            '\u{102D}' | '\u{102E}' | '\u{1032}' => self.upper.replace(ch).is_some(),
+            '\u{102F}' | '\u{1030}' => self.lower.replace(ch).is_some(),
+            '\u{102B}' | '\u{102C}' => self.aa.replace(ch).is_some(),
+            '\u{1036}' => std::mem::replace(&mut self.anusvara, true),
+            '\u{1037}' => std::mem::replace(&mut self.dot_below, true),
+            '\u{1038}' => std::mem::replace(&mut self.visarga, true),
+            ASAT => std::mem::replace(&mut self.asat, true),
+            _ => true,
+        };
+        if slot_taken {
+            self.extra.push(ch);
+        }
+        1
+    }
+
+    fn emit(mut self, out: &mut String) {
+        if self.vowel_e {
+            out.push('\u{1031}');
+        }
+        if self.medial_r {
+            out.push('\u{103B}');
+        }
+
+        let has_lower = !self.stacks.is_empty() || self.lower.is_some() || self.medial_w || self.medial_h;
+        if let Some(base) = self.base {
+            let zg = match base {
+                '\u{1025}' if self.upper == Some('\u{102E}') => {
+                    self.upper = None;
+                    '\u{1026}'
+                }
+                '\u{1014}' if has_lower => '\u{108F}',
+                '\u{101B}' if has_lower => '\u{1090}',
+                '\u{100A}' if has_lower => '\u{106B}',
+                '\u{103F}' => '\u{1086}',
+                c => c,
+            };
+            out.push(zg);
+        }
+
+        if self.kinzi {
+            let kinzi = match self.upper {
+                Some('\u{102D}') => {
+                    self.upper = None;
+                    '\u{108B}'
+                }
+                Some('\u{102E}') => {
+                    self.upper = None;
+                    '\u{108C}'
+                }
+                _ if self.anusvara => {
+                    self.anusvara = false;
+                    '\u{108D}'
+                }
+                _ => '\u{1064}',
+            };
+            out.push(kinzi);
+        }
+
+        let long_lower = self.medial_r || !self.stacks.is_empty();
+        out.extend(self.stacks.iter());
+
+        if self.medial_y {
+            out.push('\u{103A}');
+        }
+        if self.medial_w && self.medial_h {
+            out.push('\u{108A}');
+        } else if self.medial_w {
+            out.push('\u{103C}');
+        } else if self.medial_h {
+            match self.lower {
+                Some('\u{102F}') => {
+                    self.lower = None;
+                    out.push('\u{1088}');
+                }
+                Some('\u{1030}') => {
+                    self.lower = None;
+                    out.push('\u{1089}');
+                }
+                _ => out.push('\u{103D}'),
+            }
+        }
+
+        if let Some(upper) = self.upper {
+            out.push(upper);
+        }
+        if let Some(lower) = self.lower {
+            out.push(match lower {
+                '\u{102F}' if long_lower => '\u{1033}',
+                '\u{1030}' if long_lower => '\u{1034}',
+                c => c,
+            });
+        }
+        match self.aa {
+            Some('\u{102B}') if self.asat => {
+                self.asat = false;
+                out.push('\u{105A}');
+            }
+            Some(aa) => out.push(aa),
+            None => {}
+        }
+        if self.anusvara {
+            out.push('\u{1036}');
+        }
+        if self.asat {
+            out.push('\u{1039}');
+        }
+        if self.dot_below {
+            out.push('\u{1037}');
+        }
+        if self.visarga {
+            out.push('\u{1038}');
+        }
+        out.extend(self.extra.iter());
+    }
+}
+
+/// Converts Unicode Myanmar text to Zawgyi-One encoding
+///
+/// Characters outside the Myanmar block are passed through unchanged.
+pub fn unicode_to_zawgyi(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let mut syllable = Syllable::default();
+
+        // Kinzi (nga + asat + virama) belongs to the following consonant
+        if chars[i] == NGA
+            && chars.get(i + 1) == Some(&ASAT)
+            && chars.get(i + 2) == Some(&VIRAMA)
+            && chars.get(i + 3).is_some_and(|c| is_consonant(*c))
+        {
+            syllable.kinzi = true;
+            syllable.base = Some(chars[i + 3]);
+            i += 4;
+        } else if !is_mark(chars[i]) {
+            syllable.base = Some(chars[i]);
+            i += 1;
+        }
+
+        while i < chars.len() && is_mark(chars[i]) {
+            i += syllable.push_mark(&chars, i);
+        }
+
+        syllable.emit(&mut out);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zg(text: &str) -> Vec<u32> {
+        unicode_to_zawgyi(text).chars().map(|c| c as u32).collect()
+    }
+
+    #[test]
+    fn test_known_syllables() {
+        // ကျောင်း
+        assert_eq!(zg("\u{1000}\u{103B}\u{1031}\u{102C}\u{1004}\u{103A}\u{1038}"),
+            vec![0x1031, 0x1000, 0x103A, 0x102C, 0x1004, 0x1039, 0x1038]);
+        // မြန်မာ
+        assert_eq!(zg("\u{1019}\u{103C}\u{1014}\u{103A}\u{1019}\u{102C}"),
+            vec![0x103B, 0x1019, 0x1014, 0x1039, 0x1019, 0x102C]);
+        // အင်္ဂလိပ်
+        assert_eq!(zg("\u{1021}\u{1004}\u{103A}\u{1039}\u{1002}\u{101C}\u{102D}\u{1015}\u{103A}"),
+            vec![0x1021, 0x1002, 0x1064, 0x101C, 0x102D, 0x1015, 0x1039]);
+        // ကြွေ
+        assert_eq!(zg("\u{1000}\u{103C}\u{103D}\u{1031}"),
+            vec![0x1031, 0x103B, 0x1000, 0x103C]);
+        // ဦး
+        assert_eq!(zg("\u{1025}\u{102E}\u{1038}"), vec![0x1026, 0x1038]);
+        // မန္တလေး
+        assert_eq!(zg("\u{1019}\u{1014}\u{1039}\u{1010}\u{101C}\u{1031}\u{1038}"),
+            vec![0x1019, 0x108F, 0x1071, 0x1031, 0x101C, 0x1038]);
+        // မှု
+        assert_eq!(zg("\u{1019}\u{103E}\u{102F}"), vec![0x1019, 0x1088]);
+    }
+
+    #[test]
+    fn test_non_myanmar_text_is_unchanged() {
+        assert_eq!(unicode_to_zawgyi("hello, world 123"), "hello, world 123");
+        assert_eq!(unicode_to_zawgyi(""), "");
+    }
+
+    #[test]
+    fn test_incomplete_sequences() {
+        // A lone vowel sign E without a consonant yet
+        assert_eq!(zg("\u{1031}"), vec![0x1031]);
+        // Kinzi prefix before its consonant has been typed
+        assert_eq!(zg("\u{1004}\u{103A}\u{1039}"), vec![0x1004, 0x1039, 0x1039]);
+    }
+}
@@ -0,0 +1,314 @@
+//! Monochrome tray icons
+//!
+//! The tray shows a keyboard glyph in one solid color so it stays readable
+//! on both light and dark taskbars, and marks the icon with a badge while key
+//! processing is disabled. The glyph is a small vector description rendered
+//! with supersampling at whatever size the shell asks for; badges are drawn
+//! over the glyph or over a keyboard's own icon.
+//!
+//! Images are RGBA with straight alpha, rows top to bottom. Hosts convert
+//! them to native icons (`HICON` on Windows) and cache the result.
+
+/// Taskbar appearance the icon is drawn for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IconTheme {
+    /// Light taskbar, dark glyph
+    Light,
+    /// Dark taskbar, light glyph
+    Dark,
+}
+
+impl IconTheme {
+    /// Theme for a taskbar, from whether the system uses the light theme
+    /// (`SystemUsesLightTheme` on Windows, the effective appearance on macOS)
+    pub fn for_taskbar(uses_light_theme: bool) -> Self {
+        if uses_light_theme {
+            IconTheme::Light
+        } else {
+            IconTheme::Dark
+        }
+    }
+
+    /// Color of the glyph and badges
+    pub fn foreground(self) -> [u8; 3] {
+        match self {
+            IconTheme::Light => [0x1C, 0x1C, 0x1C],
+            IconTheme::Dark => [0xFF, 0xFF, 0xFF],
+        }
+    }
+}
+
+/// Mark drawn over an icon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Badge {
+    /// Small dot in the bottom-right corner
+    Dot,
+    /// Diagonal stroke across the icon
+    Slash,
+}
+
+impl Badge {
+    /// Badge shown for a key processing state, `None` while enabled
+    pub fn for_processing(enabled: bool) -> Option<Badge> {
+        if enabled {
+            None
+        } else {
+            Some(Badge::Slash)
+        }
+    }
+}
+
+/// Square RGBA image with straight alpha
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IconImage {
+    size: u32,
+    pixels: Vec<u8>,
+}
+
+impl IconImage {
+    /// Fully transparent image
+    pub fn new(size: u32) -> Self {
+        IconImage {
+            size,
+            pixels: vec![0; (size * size * 4) as usize],
+        }
+    }
+
+    /// Image from BGRA pixels, as used by Windows DIBs and GDI+; `None` if the
+    /// buffer does not hold exactly `size * size` pixels
+    pub fn from_bgra(size: u32, data: &[u8]) -> Option<Self> {
+        if data.len() != (size * size * 4) as usize {
+            return None;
+        }
+        let mut pixels = data.to_vec();
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        Some(IconImage { size, pixels })
+    }
+
+    /// Pixels as BGRA
+    pub fn to_bgra(&self) -> Vec<u8> {
+        let mut data = self.pixels.clone();
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        data
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Pixels as RGBA
+    pub fn as_rgba(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// RGBA value of a pixel
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = self.offset(x, y);
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        ((y * self.size + x) * 4) as usize
+    }
+
+    /// Paints `color` over a pixel with the given coverage (source-over)
+    fn blend(&mut self, x: u32, y: u32, color: [u8; 3], coverage: f32) {
+        if coverage <= 0.0 {
+            return;
+        }
+        let i = self.offset(x, y);
+        let src_a = coverage.min(1.0);
+        let dst_a = self.pixels[i + 3] as f32 / 255.0;
+        let out_a = src_a + dst_a * (1.0 - src_a);
+        for (channel, &src) in self.pixels[i..i + 3].iter_mut().zip(color.iter()) {
+            let out = (src as f32 * src_a + *channel as f32 * dst_a * (1.0 - src_a)) / out_a;
+            *channel = out.round() as u8;
+        }
+        self.pixels[i + 3] = (out_a * 255.0).round() as u8;
+    }
+
+    /// Erases a pixel by the given coverage so a badge keeps a gap around it
+    fn knock_out(&mut self, x: u32, y: u32, coverage: f32) {
+        if coverage <= 0.0 {
+            return;
+        }
+        let i = self.offset(x, y);
+        let alpha = self.pixels[i + 3] as f32 * (1.0 - coverage.min(1.0));
+        self.pixels[i + 3] = alpha.round() as u8;
+    }
+
+    /// Applies `paint` to every pixel with the coverage of `shape`
+    fn fill(&mut self, shape: impl Fn(f32, f32) -> bool, mut paint: impl FnMut(&mut Self, u32, u32, f32)) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let coverage = coverage(x, y, &shape);
+                if coverage > 0.0 {
+                    paint(self, x, y, coverage);
+                }
+            }
+        }
+    }
+}
+
+/// Samples per pixel along each axis
+const SUPERSAMPLING: u32 = 4;
+
+/// Fraction of a pixel covered by a shape, in pixel coordinates
+fn coverage(x: u32, y: u32, inside: impl Fn(f32, f32) -> bool) -> f32 {
+    let mut hits = 0;
+    for sy in 0..SUPERSAMPLING {
+        for sx in 0..SUPERSAMPLING {
+            let px = x as f32 + (sx as f32 + 0.5) / SUPERSAMPLING as f32;
+            let py = y as f32 + (sy as f32 + 0.5) / SUPERSAMPLING as f32;
+            if inside(px, py) {
+                hits += 1;
+            }
+        }
+    }
+    hits as f32 / (SUPERSAMPLING * SUPERSAMPLING) as f32
+}
+
+/// Side of the square the glyph is designed on
+const GLYPH_GRID: f32 = 32.0;
+
+/// Part of the glyph, in grid units
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    /// Filled rounded rectangle
+    Rect { x: f32, y: f32, w: f32, h: f32, r: f32 },
+    /// Outline of a rounded rectangle, drawn inside its bounds
+    Frame { x: f32, y: f32, w: f32, h: f32, r: f32, stroke: f32 },
+}
+
+/// Keyboard: a frame with two staggered rows of keys above a space bar
+///
+/// Edges sit on even grid units so the glyph stays crisp at 16 pixels.
+const KEYBOARD_GLYPH: &[Shape] = &[
+    Shape::Frame { x: 2.0, y: 6.0, w: 28.0, h: 18.0, r: 3.0, stroke: 2.0 },
+    Shape::Rect { x: 6.0, y: 10.0, w: 2.0, h: 2.0, r: 0.0 },
+    Shape::Rect { x: 10.0, y: 10.0, w: 2.0, h: 2.0, r: 0.0 },
+    Shape::Rect { x: 14.0, y: 10.0, w: 2.0, h: 2.0, r: 0.0 },
+    Shape::Rect { x: 18.0, y: 10.0, w: 2.0, h: 2.0, r: 0.0 },
+    Shape::Rect { x: 22.0, y: 10.0, w: 2.0, h: 2.0, r: 0.0 },
+    Shape::Rect { x: 8.0, y: 14.0, w: 2.0, h: 2.0, r: 0.0 },
+    Shape::Rect { x: 12.0, y: 14.0, w: 2.0, h: 2.0, r: 0.0 },
+    Shape::Rect { x: 16.0, y: 14.0, w: 2.0, h: 2.0, r: 0.0 },
+    Shape::Rect { x: 20.0, y: 14.0, w: 2.0, h: 2.0, r: 0.0 },
+    Shape::Rect { x: 24.0, y: 14.0, w: 2.0, h: 2.0, r: 0.0 },
+    Shape::Rect { x: 10.0, y: 18.0, w: 12.0, h: 2.0, r: 0.0 },
+];
+
+fn in_rounded_rect(px: f32, py: f32, x: f32, y: f32, w: f32, h: f32, r: f32) -> bool {
+    if px < x || px > x + w || py < y || py > y + h {
+        return false;
+    }
+    let (cx, cy) = (x + w / 2.0, y + h / 2.0);
+    let r = r.min(w / 2.0).min(h / 2.0);
+    let dx = ((px - cx).abs() - (w / 2.0 - r)).max(0.0);
+    let dy = ((py - cy).abs() - (h / 2.0 - r)).max(0.0);
+    dx * dx + dy * dy <= r * r
+}
+
+impl Shape {
+    fn contains(&self, px: f32, py: f32) -> bool {
+        match *self {
+            Shape::Rect { x, y, w, h, r } => in_rounded_rect(px, py, x, y, w, h, r),
+            Shape::Frame { x, y, w, h, r, stroke } => {
+                in_rounded_rect(px, py, x, y, w, h, r)
+                    && !in_rounded_rect(
+                        px,
+                        py,
+                        x + stroke,
+                        y + stroke,
+                        w - 2.0 * stroke,
+                        h - 2.0 * stroke,
+                        (r - stroke).max(0.0),
+                    )
+            }
+        }
+    }
+}
+
+/// Renders the keyboard glyph in the theme's color
+pub fn render_glyph(size: u32, theme: IconTheme) -> IconImage {
+    let mut image = IconImage::new(size);
+    if size == 0 {
+        return image;
+    }
+    let scale = GLYPH_GRID / size as f32;
+    let color = theme.foreground();
+    image.fill(
+        |px, py| KEYBOARD_GLYPH.iter().any(|shape| shape.contains(px * scale, py * scale)),
+        |image, x, y, coverage| image.blend(x, y, color, coverage),
+    );
+    image
+}
+
+/// Distance from a point to the segment `a`-`b`
+fn distance_to_segment(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len2).clamp(0.0, 1.0)
+    };
+    let (qx, qy) = (a.0 + t * dx, a.1 + t * dy);
+    ((p.0 - qx).powi(2) + (p.1 - qy).powi(2)).sqrt()
+}
+
+/// Draws a badge over an icon in the theme's color
+///
+/// The pixels around the badge are cleared first so it stays visible over
+/// any artwork, including keyboards' own colored icons.
+pub fn draw_badge(image: &mut IconImage, badge: Badge, theme: IconTheme) {
+    let size = image.size as f32;
+    if size == 0.0 {
+        return;
+    }
+    let color = theme.foreground();
+    // Never thinner than one and a half pixels, or it disappears at 16px
+    let stroke = (size * 0.1).max(1.5);
+    let gap = (size * 0.07).max(1.0);
+
+    match badge {
+        Badge::Slash => {
+            let a = (size * 0.12, size * 0.12);
+            let b = (size * 0.88, size * 0.88);
+            let half = stroke / 2.0;
+            image.fill(
+                |px, py| distance_to_segment((px, py), a, b) <= half + gap,
+                |image, x, y, coverage| image.knock_out(x, y, coverage),
+            );
+            image.fill(
+                |px, py| distance_to_segment((px, py), a, b) <= half,
+                |image, x, y, coverage| image.blend(x, y, color, coverage),
+            );
+        }
+        Badge::Dot => {
+            let radius = (size * 0.18).max(2.0);
+            let center = (size - radius - size * 0.02, size - radius - size * 0.02);
+            image.fill(
+                |px, py| distance_to_segment((px, py), center, center) <= radius + gap,
+                |image, x, y, coverage| image.knock_out(x, y, coverage),
+            );
+            image.fill(
+                |px, py| distance_to_segment((px, py), center, center) <= radius,
+                |image, x, y, coverage| image.blend(x, y, color, coverage),
+            );
+        }
+    }
+}
+
+/// Renders the tray icon for a theme and key processing state
+pub fn render_tray_icon(size: u32, theme: IconTheme, processing_enabled: bool) -> IconImage {
+    let mut image = render_glyph(size, theme);
+    if let Some(badge) = Badge::for_processing(processing_enabled) {
+        draw_badge(&mut image, badge, theme);
+    }
+    image
+}
@@ -28,4 +28,13 @@ pub enum KmsError {
     
     #[error("Binary write error: {0}")]
     BinaryWrite(String),
+    
+    #[error("Keyboard has {count} {what}, more than km2 {major}.{minor} can hold ({limit}); compile for km2 1.6")]
+    TooLargeForVersion { what: String, count: usize, limit: usize, major: u8, minor: u8 },
+    
+    #[error("{what} has {count} UTF-16 code units, more than km2 allows ({limit})")]
+    TooLong { what: String, count: usize, limit: usize },
+    
+    #[error("Cannot compile for km2 {major}.{minor}; supported targets are 1.5 and 1.6")]
+    UnsupportedTarget { major: u8, minor: u8 },
 }
\ No newline at end of file
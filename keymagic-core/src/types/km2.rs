@@ -1,5 +1,12 @@
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::{Error, Result};
+
+use super::string_table::StringTable;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 // Version 1.3 layout options (without rightAlt)
 #[repr(C, packed)]
@@ -36,27 +43,40 @@ pub struct FileHeader_1_4 {
     pub layout_options: LayoutOptions_1_3, // same as 1.3 layout options
 }
 
-// Current version (1.5) with full features
+/// Minor version of files with 32-bit counts and string indices
+pub const KM2_LARGE_VERSION: u8 = 6;
+/// Newest minor version this crate reads and writes
+pub const KM2_LATEST_VERSION: u8 = KM2_LARGE_VERSION;
+/// Most strings, info entries or rules a file before 1.6 can hold
+pub const KM2_LEGACY_LIMIT: usize = u16::MAX as usize;
+
+// Current version (1.5/1.6) with full features. Counts are u16 in files
+// before 1.6, u32 from 1.6 on.
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct FileHeader {
     pub magic_code: [u8; 4],    // "KMKL"
     pub major_version: u8,      // 1
-    pub minor_version: u8,      // 5
-    pub string_count: u16,
-    pub info_count: u16,
-    pub rule_count: u16,
+    pub minor_version: u8,      // 5 or 6
+    pub string_count: u32,
+    pub info_count: u32,
+    pub rule_count: u32,
     pub layout_options: LayoutOptions,
 }
 
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LayoutOptions {
     pub track_caps: u8,         // 0 or 1
     pub auto_bksp: u8,          // 0 or 1
     pub eat: u8,                // 0 or 1
     pub pos_based: u8,          // 0 or 1
     pub right_alt: u8,          // 0 or 1 (v1.5+)
+    /// ASCII letters in LHS strings match either case; kept in the `opts`
+    /// info entry rather than the header (0 or 1)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub case_insensitive_ascii: u8,
 }
 
 impl Default for LayoutOptions {
@@ -67,21 +87,145 @@ impl Default for LayoutOptions {
             eat: 0,         // false
             pos_based: 0,   // false
             right_alt: 1,   // true
+            case_insensitive_ascii: 0, // false
         }
     }
 }
 
+/// User choices replacing layout options of a keyboard, by option name
+pub type LayoutOverrides = BTreeMap<String, bool>;
+
+impl LayoutOptions {
+    /// Option names, as used by [`LayoutOptions::get`] and `LayoutOverrides`
+    pub const NAMES: [&'static str; 6] = ["track_caps", "auto_bksp", "eat", "pos_based", "right_alt", "case_insensitive_ascii"];
+
+    /// Value of an option, `None` for unknown names
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let value = match name {
+            "track_caps" => self.track_caps,
+            "auto_bksp" => self.auto_bksp,
+            "eat" => self.eat,
+            "pos_based" => self.pos_based,
+            "right_alt" => self.right_alt,
+            "case_insensitive_ascii" => self.case_insensitive_ascii,
+            _ => return None,
+        };
+        Some(value != 0)
+    }
+
+    pub fn set(&mut self, name: &str, value: bool) -> Result<()> {
+        let value = value as u8;
+        match name {
+            "track_caps" => self.track_caps = value,
+            "auto_bksp" => self.auto_bksp = value,
+            "eat" => self.eat = value,
+            "pos_based" => self.pos_based = value,
+            "right_alt" => self.right_alt = value,
+            "case_insensitive_ascii" => self.case_insensitive_ascii = value,
+            _ => return Err(Error::UnknownLayoutOption(name.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Options that do not fit the header, as the flags of the `opts` info
+    /// entry
+    pub fn extended_flags(&self) -> u32 {
+        if self.case_insensitive_ascii != 0 { OPT_CASE_INSENSITIVE_ASCII } else { 0 }
+    }
+
+    pub fn set_extended_flags(&mut self, flags: u32) {
+        self.case_insensitive_ascii = (flags & OPT_CASE_INSENSITIVE_ASCII != 0) as u8;
+    }
+
+    /// The `opts` info entry, `None` while no extended option is set
+    pub fn extended_entry(&self) -> Option<InfoEntry> {
+        let flags = self.extended_flags();
+        (flags != 0).then(|| InfoEntry { id: *INFO_OPTS, data: flags.to_le_bytes().to_vec() })
+    }
+
+    /// Reads the flags of an `opts` entry; missing bytes are zero and flags
+    /// this version does not know are ignored
+    pub fn apply_extended_entry(&mut self, data: &[u8]) {
+        let mut bytes = [0u8; 4];
+        let len = data.len().min(4);
+        bytes[..len].copy_from_slice(&data[..len]);
+        self.set_extended_flags(u32::from_le_bytes(bytes));
+    }
+
+    /// These options with the overrides applied; fails on the first unknown
+    /// option name
+    pub fn with_overrides(mut self, overrides: &LayoutOverrides) -> Result<Self> {
+        for (name, &value) in overrides {
+            self.set(name, value)?;
+        }
+        Ok(self)
+    }
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
 pub struct StringEntry {
     pub value: String,
 }
 
+/// An info entry; in serialized form the ID reads in natural order
+/// ("name" for `INFO_NAME`) and the data is base64
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct InfoEntry {
+    #[cfg_attr(feature = "serde", serde(with = "info_serde::id"))]
     pub id: [u8; 4],
+    #[cfg_attr(feature = "serde", serde(with = "info_serde::data"))]
     pub data: Vec<u8>,
 }
 
+#[cfg(feature = "serde")]
+mod info_serde {
+    pub mod id {
+        use serde::de::Error;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        /// Printable IDs as their four characters, others as the hex value
+        /// of the little-endian u32 ("0x656d616e")
+        pub fn serialize<S: Serializer>(id: &[u8; 4], serializer: S) -> Result<S::Ok, S::Error> {
+            if id.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+                let name: String = id.iter().rev().map(|&b| b as char).collect();
+                serializer.serialize_str(&name)
+            } else {
+                serializer.serialize_str(&format!("{:#010x}", u32::from_le_bytes(*id)))
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 4], D::Error> {
+            let text = String::deserialize(deserializer)?;
+            if let Some(hex) = text.strip_prefix("0x").filter(|hex| hex.len() == 8) {
+                let value = u32::from_str_radix(hex, 16).map_err(D::Error::custom)?;
+                return Ok(value.to_le_bytes());
+            }
+            match text.as_bytes() {
+                &[a, b, c, d] if text.is_ascii() => Ok([d, c, b, a]),
+                _ => Err(D::Error::custom(format!("invalid info ID \"{}\"", text))),
+            }
+        }
+    }
+
+    pub mod data {
+        use base64::engine::general_purpose::STANDARD;
+        use base64::Engine;
+        use serde::de::Error;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&STANDARD.encode(data))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+            let text = String::deserialize(deserializer)?;
+            STANDARD.decode(text).map_err(D::Error::custom)
+        }
+    }
+}
+
 /// A wrapper around keyboard metadata info entries that provides convenient access methods
 #[derive(Debug, Clone, Default)]
 pub struct Metadata {
@@ -141,6 +285,52 @@ impl Metadata {
             .map(|data| data.as_slice())
     }
     
+    /// Get the named rule groups, empty if the keyboard defines none
+    pub fn rule_groups(&self) -> Vec<RuleGroup> {
+        match self.get(INFO_GRPL) {
+            Some(data) => RuleGroup::decode_list_wide(data),
+            None => self.get(INFO_GRPS).and_then(|data| RuleGroup::decode_list(data)),
+        }
+        .unwrap_or_default()
+    }
+
+    /// Get the rules compiled from `@post` sections, empty if there are none
+    pub fn post_rules(&self) -> PostRules {
+        self.get(INFO_POST)
+            .map(|data| PostRules::decode(data))
+            .unwrap_or_default()
+    }
+    
+    /// Get the language tags the keyboard is for, in declaration order
+    pub fn locales(&self) -> Vec<String> {
+        self.get_string(INFO_LOCL)
+            .map(|text| {
+                text.split(crate::locale::LOCALE_SEPARATOR)
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    
+    /// Get the sample text that shows what the keyboard types, if it declares one
+    pub fn sample_text(&self) -> Option<String> {
+        self.get_string(INFO_SMPL)
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    }
+    
+    /// Get the source names of the states, empty if the file has none
+    pub fn state_names(&self) -> IndexNames {
+        self.get(INFO_STNM).and_then(|data| IndexNames::decode(data)).unwrap_or_default()
+    }
+
+    /// Get the source names of the variables, empty if the file has none
+    pub fn variable_names(&self) -> IndexNames {
+        self.get(INFO_VRNM).and_then(|data| IndexNames::decode(data)).unwrap_or_default()
+    }
+    
     /// Check if a specific info entry exists
     pub fn has(&self, id: &[u8; 4]) -> bool {
         self.entries.contains_key(id)
@@ -162,7 +352,238 @@ impl Metadata {
     }
 }
 
+/// A named set of rules that can be switched off at runtime
+///
+/// Ranges are half-open and index into `Km2File::rules` in file order. A group
+/// declared in several `@group` blocks has one range per block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleGroup {
+    pub name: String,
+    pub ranges: Vec<std::ops::Range<usize>>,
+}
+
+impl RuleGroup {
+    /// Returns true if the rule at `index` belongs to this group
+    pub fn contains(&self, index: usize) -> bool {
+        self.ranges.iter().any(|range| range.contains(&index))
+    }
+
+    /// Number of rules in the group
+    pub fn rule_count(&self) -> usize {
+        self.ranges.iter().map(|range| range.len()).sum()
+    }
+
+    /// Whether every range fits the `INFO_GRPS` layout
+    pub fn fits_list(groups: &[RuleGroup]) -> bool {
+        groups.iter().flat_map(|group| &group.ranges).all(|range| range.end <= KM2_LEGACY_LIMIT)
+    }
+
+    /// Encodes groups as the data of an `INFO_GRPS` entry
+    ///
+    /// Layout (little-endian): group count (u16), then per group the name
+    /// length in bytes (u16), the UTF-8 name, the range count (u16) and each
+    /// range as start and end rule index (u16 each).
+    pub fn encode_list(groups: &[RuleGroup]) -> Vec<u8> {
+        Self::encode_with_index_width(groups, 2)
+    }
+
+    /// Encodes groups as the data of an `INFO_GRPL` entry, for keyboards
+    /// with more rules than `INFO_GRPS` can index: the same layout with
+    /// start and end rule index as u32.
+    pub fn encode_list_wide(groups: &[RuleGroup]) -> Vec<u8> {
+        Self::encode_with_index_width(groups, 4)
+    }
+
+    fn encode_with_index_width(groups: &[RuleGroup], width: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(groups.len() as u16).to_le_bytes());
+        for group in groups {
+            data.extend_from_slice(&(group.name.len() as u16).to_le_bytes());
+            data.extend_from_slice(group.name.as_bytes());
+            data.extend_from_slice(&(group.ranges.len() as u16).to_le_bytes());
+            for range in &group.ranges {
+                data.extend_from_slice(&(range.start as u32).to_le_bytes()[..width]);
+                data.extend_from_slice(&(range.end as u32).to_le_bytes()[..width]);
+            }
+        }
+        data
+    }
+
+    /// Decodes the data of an `INFO_GRPS` entry, `None` if it is malformed
+    pub fn decode_list(data: &[u8]) -> Option<Vec<RuleGroup>> {
+        Self::decode_with_index_width(data, 2)
+    }
+
+    /// Decodes the data of an `INFO_GRPL` entry, `None` if it is malformed
+    pub fn decode_list_wide(data: &[u8]) -> Option<Vec<RuleGroup>> {
+        Self::decode_with_index_width(data, 4)
+    }
+
+    fn decode_with_index_width(mut data: &[u8], width: usize) -> Option<Vec<RuleGroup>> {
+        fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            if data.len() < len {
+                return None;
+            }
+            let (head, tail) = data.split_at(len);
+            *data = tail;
+            Some(head)
+        }
+        fn take_uint(data: &mut &[u8], width: usize) -> Option<usize> {
+            let mut bytes = [0u8; 4];
+            bytes[..width].copy_from_slice(take(data, width)?);
+            Some(u32::from_le_bytes(bytes) as usize)
+        }
+
+        let count = take_uint(&mut data, 2)?;
+        let mut groups = Vec::with_capacity(count);
+        for _ in 0..count {
+            let name_len = take_uint(&mut data, 2)?;
+            let name = std::str::from_utf8(take(&mut data, name_len)?).ok()?.to_string();
+            let range_count = take_uint(&mut data, 2)?;
+            let mut ranges = Vec::with_capacity(range_count);
+            for _ in 0..range_count {
+                let start = take_uint(&mut data, width)?;
+                let end = take_uint(&mut data, width)?;
+                if start > end {
+                    return None;
+                }
+                ranges.push(start..end);
+            }
+            groups.push(RuleGroup { name, ranges });
+        }
+        Some(groups)
+    }
+}
+
+/// Source names of the indices rules refer to
+///
+/// Rules refer to states by 0-based index and to variables by 1-based index
+/// into the string table; compilers may keep the names they had in the
+/// source in `INFO_STNM` and `INFO_VRNM` entries. Variables with the same
+/// value share a string, so an index can have several names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexNames {
+    entries: Vec<(usize, String)>,
+}
+
+impl IndexNames {
+    /// Names in the order given; an index may appear more than once
+    pub fn new(entries: Vec<(usize, String)>) -> Self {
+        Self { entries }
+    }
+
+    /// The first name of `index`
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.iter().find(|(i, _)| *i == index).map(|(_, name)| name.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.entries.iter().map(|(index, name)| (*index, name.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Encodes the names as the data of an info entry
+    ///
+    /// Layout (little-endian): entry count (u32), then per entry the index
+    /// (u32), the name length in bytes (u16) and the UTF-8 name.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for (index, name) in &self.entries {
+            data.extend_from_slice(&(*index as u32).to_le_bytes());
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+        }
+        data
+    }
+
+    /// Decodes the data of an info entry, `None` if it is malformed
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            if data.len() < len {
+                return None;
+            }
+            let (head, tail) = data.split_at(len);
+            *data = tail;
+            Some(head)
+        }
+
+        let mut data = data;
+        let count = u32::from_le_bytes(take(&mut data, 4)?.try_into().ok()?) as usize;
+        // Each entry takes at least six bytes; a larger count is corrupt
+        let mut entries = Vec::with_capacity(count.min(data.len() / 6));
+        for _ in 0..count {
+            let index = u32::from_le_bytes(take(&mut data, 4)?.try_into().ok()?) as usize;
+            let len = u16::from_le_bytes(take(&mut data, 2)?.try_into().ok()?) as usize;
+            let name = std::str::from_utf8(take(&mut data, len)?).ok()?.to_string();
+            entries.push((index, name));
+        }
+        Some(Self { entries })
+    }
+}
+
+/// Flags the rules compiled from `@post` sections
+///
+/// Post-rules have no key on the LHS. They match the composing text after
+/// every key instead of competing with the other rules for the key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PostRules {
+    bits: Vec<u8>,
+}
+
+impl PostRules {
+    /// Flags, by index into `Km2File::rules`, of the rules that are post-rules
+    pub fn from_flags(flags: impl IntoIterator<Item = bool>) -> Self {
+        let mut bits = Vec::new();
+        for (index, flag) in flags.into_iter().enumerate() {
+            if index % 8 == 0 {
+                bits.push(0);
+            }
+            if flag {
+                *bits.last_mut().unwrap() |= 1 << (index % 8);
+            }
+        }
+        while bits.last() == Some(&0) {
+            bits.pop();
+        }
+        Self { bits }
+    }
+
+    /// Returns true if the rule at `index` is a post-rule
+    pub fn contains(&self, index: usize) -> bool {
+        self.bits.get(index / 8).is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+    }
+
+    /// Returns true if no rule is a post-rule
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&byte| byte == 0)
+    }
+
+    /// Encodes the flags as the data of an `INFO_POST` entry: one bit per
+    /// rule in file order, least significant bit first. Trailing rules that
+    /// are not post-rules may be left out.
+    pub fn encode(&self) -> Vec<u8> {
+        self.bits.clone()
+    }
+
+    /// Decodes the data of an `INFO_POST` entry
+    pub fn decode(data: &[u8]) -> Self {
+        Self { bits: data.to_vec() }
+    }
+}
+
+/// A compiled rule: the text matched by `lhs` is replaced with `rhs`
+///
+/// NULL (`Predefined(PREDEFINED_NULL)`) produces no text. A RHS that
+/// produces no text at all (NULL alone, `""`, or a variable holding `""`)
+/// deletes the matched text, inserts nothing and ends recursive matching.
+/// Next to other elements NULL has no effect: `NULL + "x"` and `"x" + NULL`
+/// both output "x". The compiler writes NULL for empty string outputs.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Rule {
     pub lhs: Vec<BinaryFormatElement>,
     pub rhs: Vec<BinaryFormatElement>,
@@ -170,22 +591,26 @@ pub struct Rule {
 
 /// Binary format element from KM2 file
 /// These directly represent the opcodes and data from the compiled KM2 format
+///
+/// Serialized as `{"type": "string", "value": "ka"}`, `{"type": "and"}`, etc.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(tag = "type", content = "value", rename_all = "snake_case"))]
 pub enum BinaryFormatElement {
     String(String),
     Variable(usize),            // 1-based index into strings table
     Reference(usize),           // Back-reference ($1, $2, etc.)
-    Predefined(u16),           // Virtual key code
+    Predefined(u16),           // Virtual key code on the LHS, PREDEFINED_NULL on the RHS
     Modifier(u16),             // Modifier flags (FLAG_ANYOF, FLAG_NANYOF, or numeric index)
     And,                       // Logical AND for combining keys
     Any,                       // ANY keyword - matches any character
     Switch(usize),             // State switch (0-based integer ID)
+    Notify(String),            // Message for the host to show, RHS only
 }
 
 #[derive(Debug, Clone)]
 pub struct Km2File {
     pub header: FileHeader,
-    pub strings: Vec<StringEntry>,
+    pub strings: StringTable,
     pub info: Vec<InfoEntry>,
     pub rules: Vec<Rule>,
 }
@@ -195,12 +620,108 @@ impl Km2File {
     pub fn metadata(&self) -> Metadata {
         Metadata::new(self.info.clone())
     }
+
+    /// Sets an info entry's data, replacing the entry in place or adding it
+    /// at the end, and keeps the header's info count in step. Version 1.3
+    /// files have no info section and become 1.4 files.
+    pub fn set_info(&mut self, id: &[u8; 4], data: Vec<u8>) {
+        match self.info.iter_mut().find(|entry| &entry.id == id) {
+            Some(entry) => entry.data = data,
+            None => self.info.push(InfoEntry { id: *id, data }),
+        }
+        if self.header.major_version == 1 && self.header.minor_version < 4 {
+            self.header.minor_version = 4;
+        }
+        self.header.info_count = self.info.len() as u32;
+    }
+
+    /// Number of states the rules switch on or test: one more than the
+    /// highest state index
+    pub fn state_count(&self) -> usize {
+        self.rules
+            .iter()
+            .flat_map(|rule| rule.lhs.iter().chain(&rule.rhs))
+            .filter_map(|element| match element {
+                BinaryFormatElement::Switch(index) => Some(index + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Name of the state with `index`, from the source when the file kept
+    /// it and `state{index}` otherwise
+    pub fn state_name(&self, index: usize) -> String {
+        self.metadata().state_names().get(index).map_or_else(|| format!("state{}", index), str::to_string)
+    }
+
+    /// Names of the states the keyboard defines, in index order
+    ///
+    /// Files compiled without names give `state0`, `state1`, ...
+    pub fn states(&self) -> Vec<String> {
+        let names = self.metadata().state_names();
+        (0..self.state_count())
+            .map(|index| names.get(index).map_or_else(|| format!("state{}", index), str::to_string))
+            .collect()
+    }
+
+    /// Variables as (name, value) pairs
+    ///
+    /// Files that kept the names list every variable of the source in
+    /// declaration order. Others list the variables rules refer to, by
+    /// string index, named `var{index}`; variables only used inside other
+    /// variables were inlined and cannot be listed then.
+    pub fn variables(&self) -> Vec<(String, String)> {
+        let value = |index: usize| index.checked_sub(1).and_then(|i| self.strings.get(i)).map(|entry| entry.value.clone());
+        let names = self.metadata().variable_names();
+        if !names.is_empty() {
+            return names.iter().filter_map(|(index, name)| Some((name.to_string(), value(index)?))).collect();
+        }
+
+        let mut indices: Vec<usize> = self
+            .rules
+            .iter()
+            .flat_map(|rule| rule.lhs.iter().chain(&rule.rhs))
+            .filter_map(|element| match element {
+                BinaryFormatElement::Variable(index) => Some(*index),
+                _ => None,
+            })
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        indices.into_iter().filter_map(|index| Some((format!("var{}", index), value(index)?))).collect()
+    }
+
+    /// The keyboard in the KM2 binary format, as written by `Km2Writer`
+    pub fn to_bytes(&self) -> std::result::Result<Vec<u8>, super::errors::KmsError> {
+        let mut data = Vec::new();
+        crate::km2::Km2Writer::new(&mut data).write_km2_file(self)?;
+        Ok(data)
+    }
+
+    /// Reads a keyboard from the KM2 binary format, as `Km2Loader::load`
+    pub fn from_bytes(data: &[u8]) -> crate::km2::error::Result<Self> {
+        crate::km2::Km2Loader::load(data)
+    }
+
+    /// Removes an info entry; returns whether there was one
+    pub fn remove_info(&mut self, id: &[u8; 4]) -> bool {
+        let count = self.info.len();
+        self.info.retain(|entry| &entry.id != id);
+        self.header.info_count = self.info.len() as u32;
+        self.info.len() != count
+    }
 }
 
 impl FileHeader {
+    /// Whether counts, variable indices and switch indices are 32-bit
+    pub fn has_wide_indices(&self) -> bool {
+        crate::km2::format::has_wide_indices(self.major_version, self.minor_version)
+    }
+
     pub fn new() -> Self {
         FileHeader {
-            magic_code: *b"KMKL",
+            magic_code: crate::km2::format::MAGIC,
             major_version: 1,
             minor_version: 5,
             string_count: 0,
@@ -216,4 +737,16 @@ pub const INFO_NAME: &[u8; 4] = b"eman"; // 'name' in little-endian
 pub const INFO_DESC: &[u8; 4] = b"csed"; // 'desc' in little-endian
 pub const INFO_FONT: &[u8; 4] = b"tnof"; // 'font' in little-endian
 pub const INFO_ICON: &[u8; 4] = b"noci"; // 'icon' in little-endian
-pub const INFO_HTKY: &[u8; 4] = b"ykth"; // 'htky' in little-endian
\ No newline at end of file
+pub const INFO_HTKY: &[u8; 4] = b"ykth"; // 'htky' in little-endian
+pub const INFO_GRPS: &[u8; 4] = b"sprg"; // 'grps' in little-endian
+pub const INFO_POST: &[u8; 4] = b"tsop"; // 'post' in little-endian
+pub const INFO_GRPL: &[u8; 4] = b"lprg"; // 'grpl' in little-endian
+pub const INFO_LOCL: &[u8; 4] = b"lcol"; // 'locl' in little-endian
+pub const INFO_OPTS: &[u8; 4] = b"stpo"; // 'opts' in little-endian
+pub const INFO_SMPL: &[u8; 4] = b"lpms"; // 'smpl' in little-endian
+pub const INFO_STNM: &[u8; 4] = b"mnts"; // 'stnm' in little-endian
+pub const INFO_VRNM: &[u8; 4] = b"mnrv"; // 'vrnm' in little-endian
+pub const INFO_FEAT: &[u8; 4] = b"taef"; // 'feat' in little-endian
+
+/// `opts` flag: ASCII letters in LHS strings match either case
+pub const OPT_CASE_INSENSITIVE_ASCII: u32 = 1;
\ No newline at end of file
@@ -2,8 +2,10 @@ pub mod km2;
 pub mod opcodes;
 pub mod virtual_keys;
 pub mod errors;
+pub mod string_table;
 
 pub use km2::*;
 pub use opcodes::*;
 pub use virtual_keys::*;
-pub use errors::*;
\ No newline at end of file
+pub use errors::*;
+pub use string_table::StringTable;
\ No newline at end of file
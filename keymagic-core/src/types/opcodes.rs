@@ -7,6 +7,13 @@ pub const OP_MODIFIER: u16 = 0x00F4;
 pub const OP_AND: u16 = 0x00F6;
 pub const OP_ANY: u16 = 0x00F8;
 pub const OP_SWITCH: u16 = 0x00F9;
+pub const OP_NOTIFY: u16 = 0x00FA;
+
+// Longest message an OP_NOTIFY may carry, in characters
+pub const MAX_NOTIFY_LEN: usize = 64;
+
+// OP_PREDEFINED value for NULL, the only one allowed on the RHS
+pub const PREDEFINED_NULL: u16 = 0x0001;
 
 // Modifier flags (used with OP_MODIFIER)
 pub const FLAG_ANYOF: u16 = 0x00F5;   // Match any character from variable
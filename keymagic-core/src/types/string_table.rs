@@ -0,0 +1,159 @@
+//! String table of a keyboard, decoded on first use
+//!
+//! A loaded keyboard keeps the UTF-16 strings of the file as one buffer with
+//! the byte range of each entry. An entry is decoded the first time it is
+//! read and kept, so a keystroke only pays for the variables its rules use.
+//! Clones share the buffer and the decoded entries: engines created from the
+//! same layout decode each string once between them, from any thread.
+
+use std::fmt;
+use std::ops::{Index, Range};
+use std::sync::{Arc, OnceLock};
+
+use super::km2::StringEntry;
+
+/// The strings of a keyboard, indexed like a `Vec<StringEntry>`
+#[derive(Clone, Default)]
+pub struct StringTable {
+    inner: Arc<Inner>,
+}
+
+#[derive(Clone, Default)]
+struct Inner {
+    /// UTF-16LE code units of the loaded entries, back to back
+    raw: Box<[u8]>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    /// Bytes of the entry in `raw`; empty for entries added after loading
+    span: Range<usize>,
+    decoded: OnceLock<StringEntry>,
+}
+
+impl StringTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A table over `raw`, the UTF-16LE entries of a file, with the byte
+    /// range of each entry. The entries must already be valid UTF-16.
+    pub(crate) fn from_utf16le(raw: Vec<u8>, spans: Vec<Range<usize>>) -> Self {
+        let entries = spans.into_iter()
+            .map(|span| Entry { span, decoded: OnceLock::new() })
+            .collect();
+        Self { inner: Arc::new(Inner { raw: raw.into_boxed_slice(), entries }) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.entries.is_empty()
+    }
+
+    /// Entry `index` (0-based), decoding it if it was not read before
+    pub fn get(&self, index: usize) -> Option<&StringEntry> {
+        self.inner.entries.get(index).map(|entry| self.decode(entry))
+    }
+
+    /// Text of entry `index` (0-based)
+    pub fn get_str(&self, index: usize) -> Option<&str> {
+        self.get(index).map(|entry| entry.value.as_str())
+    }
+
+    /// All entries in order, decoding the ones not read before
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { table: self, entries: self.inner.entries.iter() }
+    }
+
+    /// Entries decoded so far, without decoding any
+    pub fn decoded(&self) -> impl Iterator<Item = &StringEntry> + '_ {
+        self.inner.entries.iter().filter_map(|entry| entry.decoded.get())
+    }
+
+    /// Appends an entry. A table shared with clones is copied first.
+    pub fn push(&mut self, value: StringEntry) {
+        Arc::make_mut(&mut self.inner).entries.push(Entry {
+            span: 0..0,
+            decoded: OnceLock::from(value),
+        });
+    }
+
+    fn decode<'a>(&'a self, entry: &'a Entry) -> &'a StringEntry {
+        entry.decoded.get_or_init(|| {
+            let units = self.inner.raw[entry.span.clone()]
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+            // Validated by the loader, so nothing is replaced
+            let value = char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect();
+            StringEntry { value }
+        })
+    }
+}
+
+impl Index<usize> for StringTable {
+    type Output = StringEntry;
+
+    fn index(&self, index: usize) -> &StringEntry {
+        match self.get(index) {
+            Some(entry) => entry,
+            None => panic!("string index {} out of range for {} strings", index, self.len()),
+        }
+    }
+}
+
+/// Iterator over the entries of a [`StringTable`]
+pub struct Iter<'a> {
+    table: &'a StringTable,
+    entries: std::slice::Iter<'a, Entry>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a StringEntry;
+
+    fn next(&mut self) -> Option<&'a StringEntry> {
+        self.entries.next().map(|entry| self.table.decode(entry))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a StringTable {
+    type Item = &'a StringEntry;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl FromIterator<StringEntry> for StringTable {
+    fn from_iter<I: IntoIterator<Item = StringEntry>>(entries: I) -> Self {
+        let mut table = Self::new();
+        for entry in entries {
+            table.push(entry);
+        }
+        table
+    }
+}
+
+impl From<Vec<StringEntry>> for StringTable {
+    fn from(entries: Vec<StringEntry>) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl fmt::Debug for StringTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
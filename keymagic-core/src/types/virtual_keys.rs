@@ -242,6 +242,112 @@ impl VirtualKey {
         }
     }
 
+    /// Convert this VirtualKey to its canonical KMS script name (e.g. `VK_KEY_A`)
+    pub fn to_kms_name(&self) -> &'static str {
+        match self {
+            VirtualKey::Back => "VK_BACK",
+            VirtualKey::Tab => "VK_TAB",
+            VirtualKey::Return => "VK_RETURN",
+            VirtualKey::Shift => "VK_SHIFT",
+            VirtualKey::Control => "VK_CONTROL",
+            VirtualKey::Menu => "VK_MENU",
+            VirtualKey::Pause => "VK_PAUSE",
+            VirtualKey::Capital => "VK_CAPITAL",
+            VirtualKey::Kanji => "VK_KANJI",
+            VirtualKey::Escape => "VK_ESCAPE",
+            VirtualKey::Space => "VK_SPACE",
+            VirtualKey::Prior => "VK_PRIOR",
+            VirtualKey::Next => "VK_NEXT",
+            VirtualKey::Delete => "VK_DELETE",
+            VirtualKey::Key0 => "VK_KEY_0",
+            VirtualKey::Key1 => "VK_KEY_1",
+            VirtualKey::Key2 => "VK_KEY_2",
+            VirtualKey::Key3 => "VK_KEY_3",
+            VirtualKey::Key4 => "VK_KEY_4",
+            VirtualKey::Key5 => "VK_KEY_5",
+            VirtualKey::Key6 => "VK_KEY_6",
+            VirtualKey::Key7 => "VK_KEY_7",
+            VirtualKey::Key8 => "VK_KEY_8",
+            VirtualKey::Key9 => "VK_KEY_9",
+            VirtualKey::KeyA => "VK_KEY_A",
+            VirtualKey::KeyB => "VK_KEY_B",
+            VirtualKey::KeyC => "VK_KEY_C",
+            VirtualKey::KeyD => "VK_KEY_D",
+            VirtualKey::KeyE => "VK_KEY_E",
+            VirtualKey::KeyF => "VK_KEY_F",
+            VirtualKey::KeyG => "VK_KEY_G",
+            VirtualKey::KeyH => "VK_KEY_H",
+            VirtualKey::KeyI => "VK_KEY_I",
+            VirtualKey::KeyJ => "VK_KEY_J",
+            VirtualKey::KeyK => "VK_KEY_K",
+            VirtualKey::KeyL => "VK_KEY_L",
+            VirtualKey::KeyM => "VK_KEY_M",
+            VirtualKey::KeyN => "VK_KEY_N",
+            VirtualKey::KeyO => "VK_KEY_O",
+            VirtualKey::KeyP => "VK_KEY_P",
+            VirtualKey::KeyQ => "VK_KEY_Q",
+            VirtualKey::KeyR => "VK_KEY_R",
+            VirtualKey::KeyS => "VK_KEY_S",
+            VirtualKey::KeyT => "VK_KEY_T",
+            VirtualKey::KeyU => "VK_KEY_U",
+            VirtualKey::KeyV => "VK_KEY_V",
+            VirtualKey::KeyW => "VK_KEY_W",
+            VirtualKey::KeyX => "VK_KEY_X",
+            VirtualKey::KeyY => "VK_KEY_Y",
+            VirtualKey::KeyZ => "VK_KEY_Z",
+            VirtualKey::Numpad0 => "VK_NUMPAD0",
+            VirtualKey::Numpad1 => "VK_NUMPAD1",
+            VirtualKey::Numpad2 => "VK_NUMPAD2",
+            VirtualKey::Numpad3 => "VK_NUMPAD3",
+            VirtualKey::Numpad4 => "VK_NUMPAD4",
+            VirtualKey::Numpad5 => "VK_NUMPAD5",
+            VirtualKey::Numpad6 => "VK_NUMPAD6",
+            VirtualKey::Numpad7 => "VK_NUMPAD7",
+            VirtualKey::Numpad8 => "VK_NUMPAD8",
+            VirtualKey::Numpad9 => "VK_NUMPAD9",
+            VirtualKey::Multiply => "VK_MULTIPLY",
+            VirtualKey::Add => "VK_ADD",
+            VirtualKey::Separator => "VK_SEPARATOR",
+            VirtualKey::Subtract => "VK_SUBTRACT",
+            VirtualKey::Decimal => "VK_DECIMAL",
+            VirtualKey::Divide => "VK_DIVIDE",
+            VirtualKey::F1 => "VK_F1",
+            VirtualKey::F2 => "VK_F2",
+            VirtualKey::F3 => "VK_F3",
+            VirtualKey::F4 => "VK_F4",
+            VirtualKey::F5 => "VK_F5",
+            VirtualKey::F6 => "VK_F6",
+            VirtualKey::F7 => "VK_F7",
+            VirtualKey::F8 => "VK_F8",
+            VirtualKey::F9 => "VK_F9",
+            VirtualKey::F10 => "VK_F10",
+            VirtualKey::F11 => "VK_F11",
+            VirtualKey::F12 => "VK_F12",
+            VirtualKey::LShift => "VK_LSHIFT",
+            VirtualKey::RShift => "VK_RSHIFT",
+            VirtualKey::LControl => "VK_LCONTROL",
+            VirtualKey::RControl => "VK_RCONTROL",
+            VirtualKey::LMenu => "VK_LMENU",
+            VirtualKey::RMenu => "VK_RMENU",
+            VirtualKey::Oem1 => "VK_OEM_1",
+            VirtualKey::OemPlus => "VK_OEM_PLUS",
+            VirtualKey::OemComma => "VK_OEM_COMMA",
+            VirtualKey::OemMinus => "VK_OEM_MINUS",
+            VirtualKey::OemPeriod => "VK_OEM_PERIOD",
+            VirtualKey::Oem2 => "VK_OEM_2",
+            VirtualKey::Oem3 => "VK_OEM_3",
+            VirtualKey::Oem4 => "VK_OEM_4",
+            VirtualKey::Oem5 => "VK_OEM_5",
+            VirtualKey::Oem6 => "VK_OEM_6",
+            VirtualKey::Oem7 => "VK_OEM_7",
+            VirtualKey::Oem8 => "VK_OEM_8",
+            VirtualKey::OemAx => "VK_OEM_AX",
+            VirtualKey::Oem102 => "VK_OEM_102",
+            VirtualKey::IcoHelp => "VK_ICO_HELP",
+            VirtualKey::Ico00 => "VK_ICO_00",
+        }
+    }
+
     /// Convert this VirtualKey to Windows Virtual Key code
     pub fn to_win_vk(&self) -> u16 {
         match self {
@@ -594,6 +700,25 @@ impl VirtualKey {
     }
 }
 
+/// Parses KMS key names such as `VK_ESCAPE` or `VK_KEY_W`
+///
+/// Names are case-insensitive and the `VK_` prefix may be left out.
+pub fn parse_vk_names<S: AsRef<str>>(names: &[S]) -> crate::Result<Vec<VirtualKey>> {
+    let vk_map = create_vk_map();
+    names
+        .iter()
+        .map(|name| {
+            let name = name.as_ref().trim();
+            let upper = name.to_ascii_uppercase();
+            vk_map
+                .get(upper.as_str())
+                .or_else(|| vk_map.get(format!("VK_{}", upper).as_str()))
+                .copied()
+                .ok_or_else(|| crate::Error::UnknownVirtualKey(name.to_string()))
+        })
+        .collect()
+}
+
 pub fn create_vk_map() -> HashMap<&'static str, VirtualKey> {
     let mut map = HashMap::new();
 
@@ -3,8 +3,8 @@
 mod common;
 use common::*;
 
-use keymagic_core::BinaryFormatElement;
-use keymagic_core::engine::ActionType;
+use keymagic_core::types::km2::BinaryFormatElement;
+use keymagic_core::ActionType;
 use keymagic_core::types::km2::{Km2File, LayoutOptions};
 use kms2km2::VirtualKey;
 
@@ -1,6 +1,6 @@
 //! Tests for backspace history functionality
 
-use keymagic_core::{KeyMagicEngine, Km2File, VirtualKey};
+use keymagic_core::{KeyMagicEngine, Km2File, VirtualKey, ActionType};
 
 mod common;
 use common::*;
@@ -159,7 +159,7 @@ fn test_process_key_test_with_backspace_history() {
 
 #[test]
 fn test_backspace_history_with_rules() {
-    use keymagic_core::{Rule, BinaryFormatElement, StringEntry};
+    use keymagic_core::types::km2::{Rule, BinaryFormatElement, StringEntry};
     
     // Create a keyboard with a rule "ka" => "က"
     let mut keyboard = Km2File::default();
@@ -223,4 +223,110 @@ fn test_backspace_history_not_recorded_for_backspace() {
     // Backspace should restore to "ab" (not "abc" since that backspace wasn't recorded)
     process_key(&mut engine, backspace_input).unwrap();
     assert_eq!(get_composing_text(&engine), "ab");
-}
\ No newline at end of file
+}
+/// Smart backspace keyboard with a multi-key rule producing a whole cluster
+const SMART_BACKSPACE_KMS: &str = r#"
+/*
+@SMART_BACKSPACE = "TRUE"
+*/
+"gyaung" => "ကျောင်း"
+"k" => "က"
+"#;
+
+#[test]
+fn test_smart_backspace_undoes_multi_key_rule() {
+    let mut engine = create_engine(SMART_BACKSPACE_KMS).unwrap();
+    let backspace_input = key_input_from_vk(VirtualKey::Back);
+
+    for ch in "gyaung".chars() {
+        process_char(&mut engine, ch).unwrap();
+    }
+    assert_eq!(get_composing_text(&engine), "ကျောင်း");
+
+    // One backspace removes the whole cluster emitted by the rule,
+    // restoring the text typed before the final key
+    let output = process_key(&mut engine, backspace_input).unwrap();
+    assert_eq!(output.composing_text, "gyaun");
+    assert_eq!(output.action, ActionType::BackspaceDeleteAndInsert(7, "gyaun".to_string()));
+    assert!(output.is_processed);
+}
+
+#[test]
+fn test_smart_backspace_with_mixed_rule_and_passthrough() {
+    let mut engine = create_engine(SMART_BACKSPACE_KMS).unwrap();
+    let backspace_input = key_input_from_vk(VirtualKey::Back);
+
+    // 'k' matches a rule, '1' and 'x' pass through unchanged
+    process_char(&mut engine, 'k').unwrap();
+    process_char(&mut engine, '1').unwrap();
+    process_char(&mut engine, 'k').unwrap();
+    process_char(&mut engine, 'x').unwrap();
+    assert_eq!(get_composing_text(&engine), "က1ကx");
+    assert_eq!(engine.undo_depth(), 4);
+
+    let output = process_key(&mut engine, backspace_input.clone()).unwrap();
+    assert_eq!(output.action, ActionType::BackspaceDelete(1));
+    assert_eq!(output.composing_text, "က1က");
+
+    let output = process_key(&mut engine, backspace_input.clone()).unwrap();
+    assert_eq!(output.action, ActionType::BackspaceDelete(1));
+    assert_eq!(output.composing_text, "က1");
+    assert_eq!(engine.undo_depth(), 2);
+}
+
+#[test]
+fn test_smart_backspace_repeated_down_to_empty() {
+    let mut engine = create_engine(SMART_BACKSPACE_KMS).unwrap();
+    let backspace_input = key_input_from_vk(VirtualKey::Back);
+
+    for ch in "kkk".chars() {
+        process_char(&mut engine, ch).unwrap();
+    }
+
+    for expected in ["ကက", "က", ""] {
+        let output = process_key(&mut engine, backspace_input.clone()).unwrap();
+        assert_eq!(output.composing_text, expected);
+        assert!(output.is_processed);
+    }
+    assert_eq!(engine.undo_depth(), 0);
+
+    // With nothing left to undo, backspace goes to the host
+    let output = process_key(&mut engine, backspace_input).unwrap();
+    assert_eq!(output.action, ActionType::None);
+    assert!(!output.is_processed);
+}
+
+#[test]
+fn test_history_not_recorded_without_smart_backspace() {
+    let mut engine = create_engine(r#""k" => "က""#).unwrap();
+
+    process_char(&mut engine, 'k').unwrap();
+    process_char(&mut engine, 'a').unwrap();
+    assert_eq!(engine.undo_depth(), 0);
+
+    // Plain backspace still deletes one character
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Back)).unwrap();
+    assert_eq!(output.composing_text, "က");
+}
+
+#[test]
+fn test_unchanged_keys_are_not_recorded() {
+    // Every unused key is eaten, leaving the composing text untouched
+    let mut engine = create_engine(r#"
+/*
+@SMART_BACKSPACE = "TRUE"
+@EAT_ALL_UNUSED_KEYS = "TRUE"
+*/
+"k" => "က"
+"#).unwrap();
+
+    process_char(&mut engine, 'k').unwrap();
+    process_char(&mut engine, 'z').unwrap();
+    assert_eq!(get_composing_text(&engine), "က");
+    assert_eq!(engine.undo_depth(), 1);
+
+    // The first backspace has a visible effect instead of undoing the eaten key
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Back)).unwrap();
+    assert_eq!(output.composing_text, "");
+    assert_eq!(output.action, ActionType::BackspaceDelete(1));
+}
@@ -1,8 +1,8 @@
 mod common;
 
 use common::*;
-use keymagic_core::BinaryFormatElement;
-use keymagic_core::engine::ActionType;
+use keymagic_core::types::km2::BinaryFormatElement;
+use keymagic_core::ActionType;
 
 #[test]
 fn test_simple_string_mapping() {
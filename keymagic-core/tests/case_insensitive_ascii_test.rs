@@ -0,0 +1,104 @@
+//! Tests for the case_insensitive_ascii layout option
+
+use keymagic_core::{ModifierState, KeyInput, KeyMagicEngine, VirtualKey};
+use keymagic_core::km2::Km2Loader;
+use keymagic_core::types::km2::{LayoutOverrides, INFO_OPTS};
+
+mod common;
+use common::*;
+
+const KMS: &str = r#"
+/*
+@CASE_INSENSITIVE_ASCII = "TRUE"
+*/
+
+"ka" => "က"
+"q" + "x" => $1 + "!"
+"é" => "e"
+<VK_KEY_B> => "ဗ"
+"#;
+
+fn type_text(engine: &mut KeyMagicEngine, text: &str) -> String {
+    process_string(engine, text).unwrap();
+    engine.composing_text().to_string()
+}
+
+fn case_sensitive(kms: &str) -> String {
+    kms.replace(r#"@CASE_INSENSITIVE_ASCII = "TRUE""#, "")
+}
+
+#[test]
+fn test_option_is_compiled_into_layout_options() {
+    let km2 = kms2km2::compile_kms(KMS).unwrap();
+    assert_eq!(km2.header.layout_options.case_insensitive_ascii, 1);
+    assert_eq!(km2.metadata().get(INFO_OPTS), Some(&vec![1, 0, 0, 0]));
+
+    // The header has no room for it; the loader reads it back from the entry
+    let loaded = Km2Loader::load(&create_km2_binary(&km2).unwrap()).unwrap();
+    assert_eq!(loaded.header.layout_options.case_insensitive_ascii, 1);
+    assert_eq!(loaded.header.layout_options.get("case_insensitive_ascii"), Some(true));
+
+    // Lower case spelling; off by default
+    let km2 = kms2km2::compile_kms("/*\n@case_insensitive_ascii = \"true\"\n*/\n\"a\" => \"b\"").unwrap();
+    assert_eq!(km2.header.layout_options.case_insensitive_ascii, 1);
+    let km2 = kms2km2::compile_kms(&case_sensitive(KMS)).unwrap();
+    assert_eq!(km2.header.layout_options.case_insensitive_ascii, 0);
+    assert!(!km2.metadata().has(INFO_OPTS));
+}
+
+#[test]
+fn test_mixed_case_input() {
+    for text in ["ka", "Ka", "kA", "KA"] {
+        let mut engine = create_engine(KMS).unwrap();
+        assert_eq!(type_text(&mut engine, text), "က", "typed {:?}", text);
+    }
+
+    let mut engine = create_engine(&case_sensitive(KMS)).unwrap();
+    assert_eq!(type_text(&mut engine, "KA"), "KA");
+}
+
+#[test]
+fn test_back_references_keep_typed_case() {
+    let mut engine = create_engine(KMS).unwrap();
+    assert_eq!(type_text(&mut engine, "QX"), "Q!");
+    engine.reset();
+    assert_eq!(type_text(&mut engine, "qX"), "q!");
+}
+
+#[test]
+fn test_non_ascii_and_virtual_keys_are_unaffected() {
+    let mut engine = create_engine(KMS).unwrap();
+    assert_eq!(type_text(&mut engine, "É"), "É");
+    engine.reset();
+    assert_eq!(type_text(&mut engine, "é"), "e");
+
+    // VK rules still need the exact modifiers
+    engine.reset();
+    let output = process_key(&mut engine, key_input_with_modifiers(VirtualKey::KeyB, Some('B'), true, false, false)).unwrap();
+    assert_eq!(output.composing_text, "B");
+    engine.reset();
+    let output = process_key(&mut engine, key_input_vk_char(VirtualKey::KeyB, 'b')).unwrap();
+    assert_eq!(output.composing_text, "ဗ");
+}
+
+#[test]
+fn test_caps_lock_input() {
+    // Caps Lock turns the typed characters upper case, whether or not the
+    // keyboard tracks it
+    let caps_lock = |vk: VirtualKey, ch: char| KeyInput::new(vk as u16, ModifierState::new(false, false, false, true), Some(ch));
+    for track_caps in ["TRUE", "FALSE"] {
+        let kms = KMS.replace("*/", &format!("@TRACK_CAPSLOCK = \"{}\"\n*/", track_caps));
+        let mut engine = create_engine(&kms).unwrap();
+        process_key(&mut engine, caps_lock(VirtualKey::KeyK, 'K')).unwrap();
+        let output = process_key(&mut engine, caps_lock(VirtualKey::KeyA, 'A')).unwrap();
+        assert_eq!(output.composing_text, "က", "TRACK_CAPSLOCK {}", track_caps);
+    }
+}
+
+#[test]
+fn test_option_can_be_overridden() {
+    let km2 = kms2km2::compile_kms(KMS).unwrap();
+    let overrides = LayoutOverrides::from([("case_insensitive_ascii".to_string(), false)]);
+    let mut engine = KeyMagicEngine::with_options(km2, &overrides).unwrap();
+    assert_eq!(type_text(&mut engine, "KA"), "KA");
+}
@@ -0,0 +1,132 @@
+//! Matching stray characters to eaten key presses
+#![cfg(feature = "host-ipc")]
+
+use keymagic_core::char_suppression::*;
+use std::time::Duration;
+
+const THREAD: u32 = 100;
+const SCAN_K: u16 = 0x25;
+const SCAN_A: u16 = 0x1E;
+
+fn eaten(thread_id: u32, scan_code: u16, time_ms: u64) -> EatenKey {
+    EatenKey { thread_id, vk: 0x41, scan_code, time_ms }
+}
+
+#[test]
+fn test_scan_code_from_lparam() {
+    // Repeat count 1, scan code 0x25
+    assert_eq!(scan_code_from_lparam(0x0025_0001), SCAN_K);
+    // Extended keys keep their flag in bit 8
+    assert_eq!(scan_code_from_lparam(0x0148_0001), 0x148);
+    // Transition and previous state bits are not part of it
+    assert_eq!(scan_code_from_lparam(0xC025_0001u32 as i32 as isize), SCAN_K);
+}
+
+#[test]
+fn test_char_of_eaten_key_is_taken_once() {
+    let mut table = EatenKeyTable::new();
+    table.record(eaten(THREAD, SCAN_K, 1000));
+
+    assert_eq!(table.take_char(THREAD, SCAN_K, 1010), Some(eaten(THREAD, SCAN_K, 1000)));
+    assert_eq!(table.take_char(THREAD, SCAN_K, 1011), None);
+    assert!(table.is_empty());
+}
+
+#[test]
+fn test_other_keys_and_threads_do_not_match() {
+    let mut table = EatenKeyTable::new();
+    table.record(eaten(THREAD, SCAN_K, 1000));
+
+    assert_eq!(table.take_char(THREAD, SCAN_A, 1010), None);
+    assert_eq!(table.take_char(THREAD + 1, SCAN_K, 1010), None);
+    assert_eq!(table.len(), 1);
+}
+
+#[test]
+fn test_key_typed_twice_drops_two_chars() {
+    let mut table = EatenKeyTable::new();
+    table.record(eaten(THREAD, SCAN_K, 1000));
+    table.record(eaten(THREAD, SCAN_K, 1020));
+
+    assert_eq!(table.take_char(THREAD, SCAN_K, 1030).map(|key| key.time_ms), Some(1000));
+    assert_eq!(table.take_char(THREAD, SCAN_K, 1031).map(|key| key.time_ms), Some(1020));
+    assert_eq!(table.take_char(THREAD, SCAN_K, 1032), None);
+}
+
+#[test]
+fn test_keys_expire_after_window() {
+    let window = SUPPRESS_WINDOW.as_millis() as u64;
+    let mut table = EatenKeyTable::new();
+    table.record(eaten(THREAD, SCAN_K, 1000));
+    assert!(table.take_char(THREAD, SCAN_K, 1000 + window).is_some());
+
+    table.record(eaten(THREAD, SCAN_K, 2000));
+    assert_eq!(table.take_char(THREAD, SCAN_K, 2001 + window), None);
+    assert!(table.is_empty());
+}
+
+#[test]
+fn test_custom_window_and_clock_going_back() {
+    let mut table = EatenKeyTable::with_window(Duration::from_millis(10));
+    table.record(eaten(THREAD, SCAN_K, 1000));
+    table.record(eaten(THREAD, SCAN_A, 1005));
+
+    table.expire(1012);
+    assert_eq!(table.len(), 1);
+
+    // A tick count before the key does not expire it
+    assert!(table.take_char(THREAD, SCAN_A, 900).is_some());
+}
+
+#[test]
+fn test_recording_expires_old_keys_and_caps_the_table() {
+    let mut table = EatenKeyTable::new();
+    table.record(eaten(THREAD, SCAN_A, 0));
+    table.record(eaten(THREAD, SCAN_K, 10_000));
+    assert_eq!(table.len(), 1);
+
+    for i in 0..TABLE_CAPACITY as u64 + 5 {
+        table.record(eaten(THREAD, SCAN_K, 10_000 + i));
+    }
+    assert_eq!(table.len(), TABLE_CAPACITY);
+    // The oldest went first
+    assert_eq!(table.take_char(THREAD, SCAN_K, 10_050).map(|key| key.time_ms), Some(10_005));
+}
+
+#[test]
+fn test_forget_thread() {
+    let mut table = EatenKeyTable::new();
+    table.record(eaten(THREAD, SCAN_K, 1000));
+    table.record(eaten(THREAD + 1, SCAN_K, 1000));
+
+    table.forget_thread(THREAD);
+    assert_eq!(table.take_char(THREAD, SCAN_K, 1001), None);
+    assert!(table.take_char(THREAD + 1, SCAN_K, 1001).is_some());
+}
+
+#[test]
+fn test_host_listed() {
+    let hosts = ["slack.exe", "*.electron.exe"];
+    assert!(host_listed("Slack.exe", &hosts));
+    assert!(host_listed("app.electron.exe", &hosts));
+    assert!(!host_listed("notepad.exe", &hosts));
+    assert!(!host_listed::<&str>("slack.exe", &[]));
+}
+
+#[test]
+fn test_stats_count_suppressed_chars_and_hooks() {
+    let stats = CharSuppressionStats::in_memory();
+    assert_eq!(stats.snapshot(), CharSuppressionSnapshot::default());
+
+    stats.record_hook(HookReason::Listed);
+    stats.record_hook(HookReason::Detected);
+    stats.record_hook(HookReason::Detected);
+    stats.record_suppressed(5_000);
+    stats.record_suppressed(6_000);
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.suppressed, 2);
+    assert_eq!(snapshot.listed_hooks, 1);
+    assert_eq!(snapshot.detected_hooks, 2);
+    assert_eq!(snapshot.last_suppressed_at_ms, Some(6_000));
+}
@@ -0,0 +1,70 @@
+//! Ring of committed text reported by text services
+#![cfg(feature = "host-ipc")]
+
+use keymagic_core::commit_log::*;
+
+fn texts(commits: &[CommittedText]) -> Vec<&str> {
+    commits.iter().map(|commit| commit.text.as_str()).collect()
+}
+
+#[test]
+fn test_nothing_is_reported_while_off() {
+    let log = CommitLog::in_memory();
+    assert!(!log.is_enabled());
+    assert_eq!(log.report("မင်္ဂလာပါ"), None);
+    assert_eq!(log.last_seq(), 0);
+    assert!(log.read_since(0).is_empty());
+}
+
+#[test]
+fn test_commits_are_read_in_order() {
+    let log = CommitLog::in_memory();
+    log.set_enabled(true);
+    assert_eq!(log.report("မင်္ဂလာပါ"), Some(1));
+    assert_eq!(log.report(""), None);
+    assert_eq!(log.report("😀 ok"), Some(2));
+
+    assert_eq!(texts(&log.read_since(0)), vec!["မင်္ဂလာပါ", "😀 ok"]);
+    assert_eq!(texts(&log.read_since(1)), vec!["😀 ok"]);
+    assert!(log.read_since(log.last_seq()).is_empty());
+}
+
+#[test]
+fn test_long_commits_are_not_reported() {
+    let log = CommitLog::in_memory();
+    log.set_enabled(true);
+    let longest = "က".repeat(TEXT_CAPACITY);
+    assert!(log.report(&longest).is_some());
+    assert_eq!(log.report(&format!("{}က", longest)), None);
+    assert_eq!(texts(&log.read_since(0)), vec![longest.as_str()]);
+}
+
+#[test]
+fn test_ring_keeps_the_latest_commits() {
+    let log = CommitLog::in_memory();
+    log.set_enabled(true);
+    for i in 0..SLOT_COUNT + 3 {
+        log.report(&i.to_string());
+    }
+    let commits = log.read_since(0);
+    assert_eq!(commits.len(), SLOT_COUNT);
+    assert_eq!(commits[0].text, "3");
+    assert_eq!(commits.last().unwrap().seq, (SLOT_COUNT + 3) as u64);
+}
+
+#[test]
+fn test_clear_and_disable_empty_the_ring() {
+    let log = CommitLog::in_memory();
+    log.set_enabled(true);
+    log.report("secret");
+    let generation = log.clear_generation();
+    log.request_clear();
+    assert_ne!(log.clear_generation(), generation);
+    assert!(log.read_since(0).is_empty());
+
+    log.report("again");
+    log.set_enabled(false);
+    assert!(log.read_since(0).is_empty());
+    // Sequence numbers keep counting, so readers never see a commit twice
+    assert_eq!(log.last_seq(), 2);
+}
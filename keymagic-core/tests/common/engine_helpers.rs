@@ -1,8 +1,6 @@
 //! Helper functions for testing with the new engine API
 
-use keymagic_core::{KeyMagicEngine, KeyInput, EngineOutput};
-use keymagic_core::engine::{ModifierState, ActionType};
-use keymagic_core::VirtualKey;
+use keymagic_core::{KeyMagicEngine, KeyInput, EngineOutput, ModifierState, ActionType, VirtualKey};
 use keymagic_core::km2::Km2Loader;
 
 /// Create an engine from KMS rules string
@@ -12,7 +10,7 @@ pub fn create_engine(kms_rules: &str) -> Result<KeyMagicEngine, Box<dyn std::err
     
     // Convert to binary
     let mut buffer = Vec::new();
-    let writer = kms2km2::binary::Km2Writer::new(&mut buffer);
+    let writer = keymagic_core::km2::Km2Writer::new(&mut buffer);
     writer.write_km2_file(&km2_file)?;
     
     // Load the binary and create engine
@@ -1,13 +1,15 @@
-use keymagic_core::{Km2File, FileHeader, LayoutOptions, InfoEntry, StringEntry, Rule, BinaryFormatElement};
+use keymagic_core::Km2File;
+use keymagic_core::types::km2::{FileHeader, LayoutOptions, InfoEntry, StringEntry, Rule, BinaryFormatElement};
+use keymagic_core::types::string_table::StringTable;
 
 #[cfg(test)]
 pub mod engine_helpers;
 
 /// Creates a KM2 binary file from a Km2File struct
 pub fn create_km2_binary(km2: &Km2File) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    // Use kms2km2's Km2Writer for writing binary data
+    // Use the engine's own Km2Writer for writing binary data
     let mut buffer = Vec::new();
-    let writer = kms2km2::binary::Km2Writer::new(&mut buffer);
+    let writer = keymagic_core::km2::Km2Writer::new(&mut buffer);
     writer.write_km2_file(km2).map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
     Ok(buffer)
 }
@@ -16,7 +18,7 @@ pub fn create_km2_binary(km2: &Km2File) -> Result<Vec<u8>, Box<dyn std::error::E
 pub fn create_basic_km2() -> Km2File {
     Km2File {
         header: FileHeader::new(),
-        strings: vec![],
+        strings: StringTable::new(),
         info: vec![],
         rules: vec![],
     }
@@ -38,7 +40,7 @@ pub fn add_info_text(km2: &mut Km2File, id: &str, text: &str) {
         id: id.as_bytes().try_into().unwrap_or([0; 4]),
         data,
     });
-    km2.header.info_count = km2.info.len() as u16;
+    km2.header.info_count = km2.info.len() as u32;
 }
 
 /// Adds a string to the strings table and returns its 1-based index
@@ -47,7 +49,7 @@ pub fn add_string(km2: &mut Km2File, value: &str) -> usize {
     km2.strings.push(StringEntry {
         value: value.to_string(),
     });
-    km2.header.string_count = km2.strings.len() as u16;
+    km2.header.string_count = km2.strings.len() as u32;
     km2.strings.len() // Return 1-based index
 }
 
@@ -55,7 +57,7 @@ pub fn add_string(km2: &mut Km2File, value: &str) -> usize {
 #[allow(dead_code)]
 pub fn add_rule(km2: &mut Km2File, lhs: Vec<BinaryFormatElement>, rhs: Vec<BinaryFormatElement>) {
     km2.rules.push(Rule { lhs, rhs });
-    km2.header.rule_count = km2.rules.len() as u16;
+    km2.header.rule_count = km2.rules.len() as u32;
 }
 
 /// Decode UTF-8 text from bytes
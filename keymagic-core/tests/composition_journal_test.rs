@@ -0,0 +1,97 @@
+//! Composing text kept per host for crash recovery
+#![cfg(feature = "host-ipc")]
+
+use keymagic_core::composition_journal::*;
+
+const LONG: &str = "မင်္ဂလာပါခင်ဗျာ";
+
+#[test]
+fn test_nothing_is_kept_while_off() {
+    let journal = CompositionJournal::in_memory();
+    assert!(!journal.is_enabled());
+    assert!(!journal.report(1, "notepad.exe", LONG, 1_000));
+    assert!(journal.read_all().is_empty());
+
+    // Turning it off drops what was kept
+    journal.set_enabled(true);
+    assert!(journal.report(1, "notepad.exe", LONG, 1_000));
+    journal.set_enabled(false);
+    assert!(journal.read_all().is_empty());
+}
+
+#[test]
+fn test_short_compositions_are_not_kept() {
+    let journal = CompositionJournal::in_memory();
+    journal.set_enabled(true);
+    assert!(!journal.report(1, "notepad.exe", "မင်္ဂလာ", 1_000));
+    assert!(journal.read_all().is_empty());
+
+    assert!(journal.report(1, "notepad.exe", LONG, 2_000));
+    // Shrinking below the threshold keeps the longer text
+    assert!(!journal.report(1, "notepad.exe", "မ", 3_000));
+    assert_eq!(journal.read_all()[0].text, LONG);
+}
+
+#[test]
+fn test_latest_composition_per_process() {
+    let journal = CompositionJournal::in_memory();
+    journal.set_enabled(true);
+    journal.report(20, "winword.exe", "abcdefgh", 1_000);
+    journal.report(10, "notepad.exe", LONG, 2_000);
+    journal.report(20, "winword.exe", "abcdefghij", 3_000);
+
+    assert_eq!(
+        journal.read_all(),
+        vec![
+            JournalSlot { pid: 10, process: "notepad.exe".to_string(), text: LONG.to_string(), updated_ms: 2_000 },
+            JournalSlot { pid: 20, process: "winword.exe".to_string(), text: "abcdefghij".to_string(), updated_ms: 3_000 },
+        ]
+    );
+
+    assert!(journal.clear(20));
+    assert!(!journal.clear(20));
+    assert_eq!(journal.read_all().len(), 1);
+    // A cleared process takes a slot again
+    assert!(journal.report(20, "winword.exe", "klmnopqr", 4_000));
+    assert_eq!(journal.read_all().len(), 2);
+}
+
+#[test]
+fn test_slots_run_out() {
+    let journal = CompositionJournal::in_memory();
+    journal.set_enabled(true);
+    for pid in 0..SLOT_COUNT as u32 {
+        assert!(journal.report(pid, "app.exe", LONG, 1_000));
+    }
+    assert!(!journal.report(999, "app.exe", LONG, 1_000));
+    // Processes with a slot keep updating it
+    assert!(journal.report(3, "app.exe", "abcdefghijk", 2_000));
+
+    journal.clear(0);
+    assert!(journal.report(999, "app.exe", LONG, 3_000));
+}
+
+#[test]
+fn test_long_text_keeps_its_end() {
+    let journal = CompositionJournal::in_memory();
+    journal.set_enabled(true);
+    let text: String = "0123456789".repeat(30);
+    journal.report(1, "notepad.exe", &text, 1_000);
+    assert_eq!(journal.read_all()[0].text, text[text.len() - TEXT_CAPACITY..]);
+
+    // A surrogate pair cut in half is dropped
+    let text = format!("{}{}", "😀".repeat(TEXT_CAPACITY / 2), "a");
+    journal.report(1, "notepad.exe", &text, 2_000);
+    let kept = &journal.read_all()[0].text;
+    assert_eq!(kept.chars().filter(|&ch| ch == '😀').count(), TEXT_CAPACITY / 2 - 1);
+    assert!(kept.ends_with('a') && !kept.contains('\u{FFFD}'));
+}
+
+#[test]
+fn test_process_names_are_cut() {
+    let journal = CompositionJournal::in_memory();
+    journal.set_enabled(true);
+    let name = "x".repeat(NAME_CAPACITY + 10);
+    journal.report(1, &name, LONG, 1_000);
+    assert_eq!(journal.read_all()[0].process.len(), NAME_CAPACITY);
+}
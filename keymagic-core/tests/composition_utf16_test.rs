@@ -0,0 +1,172 @@
+//! Tests for the composing caret and the UTF-16 composition API
+#![cfg(feature = "ffi")]
+
+use keymagic_core::ffi::*;
+use keymagic_core::transform::TransformId;
+use keymagic_core::VirtualKey;
+use std::ptr;
+
+mod common;
+use common::*;
+
+const KMS: &str = r#"
+"a" => "😀"
+"k" => "က"
+"y" => "ျ"
+"#;
+
+fn empty_output() -> ProcessKeyOutput {
+    ProcessKeyOutput {
+        action_type: 0,
+        text: ptr::null_mut(),
+        delete_count: 0,
+        composing_text: ptr::null_mut(),
+        is_processed: 0,
+        delete_utf16_count: 0,
+        composing_caret_utf16: 0,
+        notification: ptr::null_mut(),
+        delete_clamped: 0,
+    }
+}
+
+fn load_handle(kms: &str) -> *mut EngineHandle {
+    let binary = create_km2_binary(&kms2km2::compile_kms(kms).unwrap()).unwrap();
+    let handle = keymagic_engine_new();
+    let result = keymagic_engine_load_keyboard_from_memory(handle, binary.as_ptr(), binary.len());
+    assert_eq!(result, KeyMagicResult::Success);
+    handle
+}
+
+fn type_key(handle: *mut EngineHandle, key: char) -> ProcessKeyOutput {
+    let mut output = empty_output();
+    let result = keymagic_engine_process_key(
+        handle,
+        VirtualKey::from_win_vk(key.to_ascii_uppercase() as u16).unwrap() as i32,
+        key as std::os::raw::c_char,
+        0, 0, 0, 0,
+        &mut output,
+    );
+    assert_eq!(result, KeyMagicResult::Success);
+    keymagic_free_string(output.text);
+    keymagic_free_string(output.composing_text);
+    output
+}
+
+#[test]
+fn test_caret_follows_edits() {
+    let mut engine = create_engine(KMS).unwrap();
+
+    let output = process_char(&mut engine, 'k').unwrap();
+    assert_eq!(output.composing_caret, 1);
+    assert_eq!(output.composing_caret_utf16(), 1);
+
+    let output = process_char(&mut engine, 'a').unwrap();
+    assert_eq!(output.composing_caret, 2);
+    assert_eq!(output.composing_caret_utf16(), 3);
+    assert_eq!(engine.composing_caret(), 2);
+
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Back)).unwrap();
+    assert_eq!(output.composing_caret, 1);
+
+    engine.reset();
+    assert_eq!(engine.composing_caret(), 0);
+}
+
+#[test]
+fn test_set_composing_caret() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_composing_text("က😀".to_string());
+    assert_eq!(engine.composing_caret(), 2);
+
+    engine.set_composing_caret(1);
+    assert_eq!(engine.composing_caret(), 1);
+    engine.set_composing_caret(10);
+    assert_eq!(engine.composing_caret(), 2);
+
+    // Edits made by rules put the caret back at the end
+    engine.set_composing_caret(0);
+    let output = process_char(&mut engine, 'k').unwrap();
+    assert_eq!(output.composing_text, "က😀က");
+    assert_eq!(output.composing_caret, 3);
+}
+
+#[test]
+fn test_emitted_caret_with_transform() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_output_transform(Some(TransformId::Zawgyi)).unwrap();
+
+    // Medial ya is placed before the consonant in Zawgyi; the caret stays at the end
+    process_char(&mut engine, 'k').unwrap();
+    let output = process_char(&mut engine, 'y').unwrap();
+    assert_eq!(output.composing_caret, output.composing_text.chars().count());
+    assert_eq!(engine.emitted_caret(), engine.emitted_text().chars().count());
+}
+
+#[test]
+fn test_process_key_output_reports_caret() {
+    let handle = load_handle(KMS);
+    assert_eq!(type_key(handle, 'k').composing_caret_utf16, 1);
+    assert_eq!(type_key(handle, 'a').composing_caret_utf16, 3);
+    keymagic_engine_free(handle);
+}
+
+#[test]
+fn test_get_composition_ex() {
+    let handle = load_handle(KMS);
+    type_key(handle, 'k');
+    type_key(handle, 'a');
+
+    // Query the required length first
+    let mut caret = -1;
+    let required = keymagic_engine_get_composition_ex(handle, ptr::null_mut(), 0, &mut caret);
+    assert_eq!(required, 4);
+    assert_eq!(caret, 3);
+
+    let mut buf = vec![0xFFFFu16; required];
+    assert_eq!(keymagic_engine_get_composition_ex(handle, buf.as_mut_ptr(), buf.len(), ptr::null_mut()), required);
+    let expected: Vec<u16> = "က😀\0".encode_utf16().collect();
+    assert_eq!(buf, expected);
+
+    keymagic_engine_free(handle);
+}
+
+#[test]
+fn test_get_composition_ex_small_buffer_never_splits_pairs() {
+    let handle = load_handle(KMS);
+    type_key(handle, 'k');
+    type_key(handle, 'a');
+
+    // Room for "က" and one half of the emoji: the pair is left out entirely
+    for len in [2, 3] {
+        let mut buf = vec![0xFFFFu16; 4];
+        let required = keymagic_engine_get_composition_ex(handle, buf.as_mut_ptr(), len, ptr::null_mut());
+        assert_eq!(required, 4);
+        assert_eq!(&buf[..2], &[0x1000, 0]);
+        assert!(buf[2..].iter().all(|unit| *unit == 0xFFFF), "wrote past the terminator");
+    }
+
+    // A single unit only holds the terminator
+    let mut buf = [0xFFFFu16; 1];
+    keymagic_engine_get_composition_ex(handle, buf.as_mut_ptr(), 1, ptr::null_mut());
+    assert_eq!(buf, [0]);
+
+    keymagic_engine_free(handle);
+}
+
+#[test]
+fn test_get_composition_ex_errors() {
+    let mut caret = 7;
+    assert_eq!(keymagic_engine_get_composition_ex(ptr::null_mut(), ptr::null_mut(), 0, &mut caret), 0);
+
+    // No keyboard loaded
+    let handle = keymagic_engine_new();
+    assert_eq!(keymagic_engine_get_composition_ex(handle, ptr::null_mut(), 0, &mut caret), 0);
+    assert_eq!(caret, 7);
+    keymagic_engine_free(handle);
+
+    // An empty composition still needs room for the terminator
+    let handle = load_handle(KMS);
+    assert_eq!(keymagic_engine_get_composition_ex(handle, ptr::null_mut(), 0, &mut caret), 1);
+    assert_eq!(caret, 0);
+    keymagic_engine_free(handle);
+}
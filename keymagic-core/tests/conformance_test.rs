@@ -0,0 +1,131 @@
+//! Runs the conformance fixtures under tests/conformance/
+
+use keymagic_core::conformance::*;
+use keymagic_core::km2::Km2Loader;
+use keymagic_core::{KeyMagicEngine, Km2File, VirtualKey};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+mod common;
+use common::*;
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("conformance")
+}
+
+fn load_fixture_keyboard(dir: &Path) -> Km2File {
+    let km2 = dir.join("keyboard.km2");
+    if km2.exists() {
+        return Km2Loader::load(&fs::read(&km2).unwrap()).unwrap();
+    }
+    kms2km2::compile_kms_file(&dir.join("keyboard.kms"))
+        .unwrap_or_else(|e| panic!("{}: {}", dir.display(), e))
+}
+
+#[test]
+fn test_conformance_fixtures() {
+    let mut dirs: Vec<PathBuf> = fs::read_dir(fixtures_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.join("cases.txt").exists())
+        .collect();
+    dirs.sort();
+    assert!(!dirs.is_empty(), "no conformance fixtures found");
+
+    let mut failed = Vec::new();
+    for dir in dirs {
+        let cases = parse_cases(&fs::read_to_string(dir.join("cases.txt")).unwrap())
+            .unwrap_or_else(|e| panic!("{}: {}", dir.display(), e));
+        let mut engine = KeyMagicEngine::new(load_fixture_keyboard(&dir)).unwrap();
+        let report = run_cases(&mut engine, &cases).unwrap();
+
+        let name = dir.file_name().unwrap().to_string_lossy().into_owned();
+        println!("[{}]\n{}\n", name, report);
+        if !report.passed() {
+            failed.push(name);
+        }
+    }
+    assert!(failed.is_empty(), "conformance failures in: {}", failed.join(", "));
+}
+
+#[test]
+fn test_parse_and_format_round_trip() {
+    let text = "# comment\n== case one\nk => က\n<VK_SHIFT & VK_KEY_A> =>\nSPACE\n= => =\n";
+    let cases = parse_cases(text).unwrap();
+    assert_eq!(cases.len(), 1);
+    let steps = &cases[0].steps;
+    assert_eq!(steps[0].key, KeyEvent::Char('k'));
+    assert_eq!(steps[0].expected.as_deref(), Some("က"));
+    assert_eq!(steps[1].key, KeyEvent::Combo(vec![VirtualKey::Shift, VirtualKey::KeyA]));
+    assert_eq!(steps[1].expected.as_deref(), Some(""));
+    assert_eq!(steps[2].key, KeyEvent::Char(' '));
+    assert_eq!(steps[2].expected, None);
+    assert_eq!(steps[3].expected.as_deref(), Some("="));
+    assert_eq!(steps[3].line, 6);
+    assert!(!cases[0].is_captured());
+
+    assert_eq!(parse_cases(&format_cases(&cases)).unwrap()[0].steps.len(), 4);
+}
+
+#[test]
+fn test_parse_errors_name_the_line() {
+    let err = parse_cases("k => က").unwrap_err().to_string();
+    assert!(err.contains("line 1"), "{}", err);
+
+    let err = parse_cases("== a\n<VK_NOPE> => x").unwrap_err().to_string();
+    assert!(err.contains("line 2") && err.contains("VK_NOPE"), "{}", err);
+
+    assert!(parse_cases("== a\nab => x").is_err());
+}
+
+#[test]
+fn test_divergence_reports_rule_trace() {
+    let mut engine = create_engine("\"k\" => U1000\nU1000 + \"a\" => U1000 + U102C").unwrap();
+    let cases = parse_cases("== wrong\nk => က\na => ကါ\nk => x\n").unwrap();
+
+    let result = run_case(&mut engine, &cases[0]).unwrap();
+    let divergence = result.divergence.clone().unwrap();
+    assert_eq!(divergence.step, 1);
+    assert_eq!(divergence.line, 3);
+    assert_eq!(divergence.before, "က");
+    assert_eq!(divergence.actual, "ကာ");
+    assert_eq!(divergence.trace.len(), 1);
+    assert!(divergence.trace[0].starts_with("#1: "), "{:?}", divergence.trace);
+
+    let printed = result.to_string();
+    assert!(printed.starts_with("FAIL wrong at key 2 'a' (line 3)"), "{}", printed);
+    assert!(printed.contains("rules applied"));
+
+    // The engine is left clean for the next case
+    assert_eq!(engine.composing_text(), "");
+}
+
+#[test]
+fn test_divergence_reports_state_transitions() {
+    let mut engine = create_engine("\"k\" => U1000 + ('s')\n('s') + \"a\" => U1000 + U102C + ('s')").unwrap();
+    let cases = parse_cases("== states\nk => က\na => ကါ\n").unwrap();
+
+    let divergence = run_case(&mut engine, &cases[0]).unwrap().divergence.unwrap();
+    assert_eq!(divergence.states_before, vec![0]);
+    assert_eq!(divergence.states_after, vec![0]);
+
+    let mut engine = create_engine("\"k\" => U1000 + ('s')\n\"a\" => U102C").unwrap();
+    let result = run_case(&mut engine, &cases[0]).unwrap();
+    let divergence = result.divergence.clone().unwrap();
+    assert_eq!(divergence.states_before, vec![0]);
+    assert!(divergence.states_after.is_empty());
+    let printed = result.to_string();
+    assert!(printed.contains("states:   ('state0') -> none"), "{}", printed);
+    assert!(engine.active_states().is_empty());
+}
+
+#[test]
+fn test_uncaptured_cases_are_skipped() {
+    let mut engine = create_engine("\"k\" => U1000").unwrap();
+    let cases = parse_cases("== new\nk\nk\n== partly\nk => က\nk\n").unwrap();
+    let report = run_cases(&mut engine, &cases).unwrap();
+    assert_eq!(report.uncaptured, vec!["new".to_string()]);
+    assert_eq!(report.results.len(), 1);
+    assert_eq!(report.results[0].checked, 1);
+    assert!(report.passed());
+}
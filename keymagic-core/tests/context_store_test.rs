@@ -0,0 +1,228 @@
+//! Compositions kept per input context, moved to contexts that replace
+//! destroyed ones
+#![cfg(feature = "host-ipc")]
+
+mod common;
+use common::*;
+
+use keymagic_core::context_store::*;
+use keymagic_core::{EngineSnapshot, KeyInput, KeyMagicEngine, Km2File};
+use std::time::{Duration, Instant};
+
+const PROCESS: u32 = 100;
+const OTHER_PROCESS: u32 = 200;
+
+fn typed(text: &str) -> EngineSnapshot {
+    let mut engine = KeyMagicEngine::new(Km2File::default()).unwrap();
+    for c in text.chars() {
+        engine.process_key(KeyInput::from_char(c)).unwrap();
+    }
+    engine.snapshot()
+}
+
+fn ms(start: Instant, millis: u64) -> Instant {
+    start + Duration::from_millis(millis)
+}
+
+#[test]
+fn test_focus_saves_and_restores_compositions() {
+    let start = Instant::now();
+    let mut store = ContextStore::new();
+    store.created(1, PROCESS, start);
+    store.created(2, PROCESS, start);
+
+    assert!(store.focused(1, PROCESS, None, ms(start, 1)).is_none());
+    assert!(store.focused(2, PROCESS, Some(typed("ab")), ms(start, 2)).is_none());
+    assert_eq!(store.saved_text(1), Some("ab"));
+
+    let restored = store.focused(1, PROCESS, Some(typed("")), ms(start, 3)).unwrap();
+    assert_eq!(restored.composing_text(), "ab");
+    // An empty composition is not kept
+    assert_eq!(store.saved_text(2), None);
+    assert_eq!(store.focused_context(PROCESS), Some(1));
+}
+
+#[test]
+fn test_new_context_takes_over_within_window() {
+    let start = Instant::now();
+    let mut store = ContextStore::new();
+    store.created(1, PROCESS, start);
+    store.focused(1, PROCESS, None, start);
+
+    // The renderer reloads: the old context goes, a new one comes
+    assert_eq!(store.destroyed(1, Some(typed("ka")), ms(start, 100)), None);
+    assert_eq!(store.focused_context(PROCESS), None);
+    let migration = store.created(2, PROCESS, ms(start, 400));
+    assert_eq!(migration, Some(Migration { from: 1, to: 2 }));
+    assert_eq!(store.focused(2, PROCESS, None, ms(start, 450)).unwrap().composing_text(), "ka");
+
+    // Nothing is left to restore twice
+    assert_eq!(store.saved_text(1), None);
+    assert_eq!(store.created(3, PROCESS, ms(start, 500)), None);
+}
+
+#[test]
+fn test_new_context_created_before_the_old_one_goes() {
+    let start = Instant::now();
+    let mut store = ContextStore::new();
+    store.created(1, PROCESS, start);
+    store.focused(1, PROCESS, None, start);
+
+    store.created(2, PROCESS, ms(start, 1000));
+    let migration = store.destroyed(1, Some(typed("ka")), ms(start, 1200));
+    assert_eq!(migration, Some(Migration { from: 1, to: 2 }));
+    assert_eq!(store.saved_text(2), Some("ka"));
+}
+
+#[test]
+fn test_no_takeover_after_the_window() {
+    let start = Instant::now();
+    let mut store = ContextStore::new();
+    store.created(1, PROCESS, start);
+    store.focused(1, PROCESS, None, start);
+    store.destroyed(1, Some(typed("ka")), start);
+    assert_eq!(store.created(2, PROCESS, ms(start, 501)), None);
+    assert!(store.focused(2, PROCESS, None, ms(start, 502)).is_none());
+
+    // Nor for a context created long before, or already in use
+    let mut store = ContextStore::new();
+    store.created(1, PROCESS, start);
+    store.created(2, PROCESS, start);
+    store.focused(1, PROCESS, None, ms(start, 600));
+    assert_eq!(store.destroyed(1, Some(typed("ka")), ms(start, 700)), None);
+
+    let mut store = ContextStore::new();
+    store.created(1, PROCESS, start);
+    store.focused(1, PROCESS, None, start);
+    store.created(2, PROCESS, ms(start, 10));
+    store.focused(2, PROCESS, Some(typed("ka")), ms(start, 20));
+    store.focused(1, PROCESS, Some(typed("")), ms(start, 30));
+    assert_eq!(store.destroyed(1, Some(typed("ga")), ms(start, 40)), None);
+}
+
+#[test]
+fn test_contexts_of_other_processes_are_left_alone() {
+    let start = Instant::now();
+    let mut store = ContextStore::new();
+    store.created(1, PROCESS, start);
+    store.created(2, PROCESS, start);
+    store.created(3, OTHER_PROCESS, start);
+    store.focused(1, PROCESS, None, start);
+    store.focused(3, OTHER_PROCESS, None, start);
+
+    // Two contexts of the process went; the newest composition moves
+    store.focused(2, PROCESS, Some(typed("ka")), ms(start, 10));
+    store.destroyed(1, None, ms(start, 20));
+    store.destroyed(2, Some(typed("ga")), ms(start, 30));
+    assert_eq!(store.created(4, OTHER_PROCESS, ms(start, 40)), None);
+    assert_eq!(store.created(5, PROCESS, ms(start, 50)), Some(Migration { from: 2, to: 5 }));
+    assert_eq!(store.saved_text(5), Some("ga"));
+    assert_eq!(store.focused_context(OTHER_PROCESS), Some(3));
+}
+
+#[test]
+fn test_orphaned_snapshots_expire() {
+    let start = Instant::now();
+    let mut store = ContextStore::new();
+    store.created(1, PROCESS, start);
+    store.created(2, PROCESS, start);
+    store.focused(1, PROCESS, None, start);
+    store.focused(2, PROCESS, Some(typed("ka")), start);
+    store.destroyed(2, Some(typed("ga")), ms(start, 10));
+
+    // The destroyed context goes after the window
+    store.expire(ms(start, 600));
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.saved_text(1), Some("ka"));
+
+    // A context nobody came back to goes after the TTL, unless focused
+    store.created(3, PROCESS, ms(start, 700));
+    store.focused(3, PROCESS, None, ms(start, 700));
+    store.expire(start + SNAPSHOT_TTL + Duration::from_secs(1));
+    assert_eq!(store.saved_text(1), None);
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.focused_context(PROCESS), Some(3));
+    assert!(!store.is_empty());
+}
+
+#[test]
+fn test_snapshot_continues_the_composition() {
+    let mut engine = create_engine(r#"
+        /* @SMART_BACKSPACE = "TRUE" */
+        "ka" => "က"
+    "#).unwrap();
+    process_string(&mut engine, "ka").unwrap();
+    let snapshot = engine.snapshot();
+    assert_eq!(snapshot.composing_text(), "က");
+    assert!(!snapshot.is_empty());
+
+    engine.reset();
+    assert!(engine.snapshot().is_empty());
+    engine.restore(snapshot);
+    assert_eq!(engine.composing_text(), "က");
+
+    // Smart backspace undoes the keys typed before the snapshot
+    assert_eq!(engine.undo_depth(), 2);
+    engine.process_key(key_input_from_vk(keymagic_core::VirtualKey::Back)).unwrap();
+    assert_eq!(engine.composing_text(), "k");
+}
+
+#[cfg(feature = "ffi")]
+mod bridge {
+    use super::*;
+    use keymagic_core::ffi::*;
+    use std::ffi::CStr;
+
+    fn composition(engine: *mut EngineHandle) -> String {
+        let text = keymagic_engine_get_composition(engine);
+        let composition = unsafe { CStr::from_ptr(text) }.to_str().unwrap().to_string();
+        keymagic_free_string(text);
+        composition
+    }
+
+    fn type_text(engine: *mut EngineHandle, text: &str) {
+        for key in text.chars() {
+            let mut output = unsafe { std::mem::zeroed::<ProcessKeyOutput>() };
+            let result = keymagic_engine_process_key(engine, 0, key as i8, 0, 0, 0, 0, &mut output);
+            assert_eq!(result, KeyMagicResult::Success);
+            keymagic_free_string(output.text);
+            keymagic_free_string(output.composing_text);
+            keymagic_free_string(output.notification);
+        }
+    }
+
+    #[test]
+    fn test_bridge_moves_the_composition_to_a_recreated_context() {
+        let binary = create_km2_binary(&kms2km2::compile_kms(r#""ka" => "က""#).unwrap()).unwrap();
+        let engine = keymagic_engine_new();
+        let result = keymagic_engine_load_keyboard_from_memory(engine, binary.as_ptr(), binary.len());
+        assert_eq!(result, KeyMagicResult::Success);
+
+        // Ids unlikely to clash with other tests of this process
+        let (first, second, reloaded) = (0xC0DE_0001, 0xC0DE_0002, 0xC0DE_0003);
+        assert_eq!(keymagic_context_created(first), 0);
+        assert_eq!(keymagic_context_created(second), 0);
+        assert_eq!(keymagic_context_focused(engine, first), 0);
+        type_text(engine, "k");
+        // Focusing the same context again leaves the engine alone
+        assert_eq!(keymagic_context_focused(engine, first), 0);
+        assert_eq!(composition(engine), "k");
+
+        assert_eq!(keymagic_context_focused(engine, second), 0);
+        assert_eq!(composition(engine), "");
+        assert_eq!(keymagic_context_focused(engine, first), 1);
+        assert_eq!(composition(engine), "k");
+
+        // The renderer reloads
+        assert_eq!(keymagic_context_destroyed(engine, first), 0);
+        assert_eq!(composition(engine), "");
+        assert_eq!(keymagic_context_created(reloaded), 1);
+        assert_eq!(keymagic_context_focused(engine, reloaded), 1);
+        type_text(engine, "a");
+        assert_eq!(composition(engine), "က");
+
+        keymagic_context_destroyed(engine, second);
+        keymagic_context_destroyed(engine, reloaded);
+        keymagic_engine_free(engine);
+    }
+}
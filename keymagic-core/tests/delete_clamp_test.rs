@@ -0,0 +1,232 @@
+//! Deletes reaching past the text the engine emitted are cut short
+
+mod common;
+use common::*;
+
+use keymagic_core::ActionType;
+
+/// Typing `z` after the alphabet replaces all of it
+const GREEDY_KMS: &str = r#"
+"abcdefghijklmnopz" => "!"
+"#;
+
+/// A reordering keyboard: the vowel sign typed first moves after the
+/// consonant, and a medial typed later goes between them
+const REORDER_KMS: &str = r#"
+"a" => "ေ"
+"ေ" + "u" => "ကေ"
+"ေ" + "c" => "ငေ"
+"ကေ" + "j" => "ကြေ"
+"ငေ" + "j" => "ငြေ"
+"#;
+
+#[test]
+fn test_delete_of_host_text_is_clamped() {
+    let mut engine = create_engine(GREEDY_KMS).unwrap();
+    engine.set_composing_text("abcdefghijklmnop".to_string());
+
+    let output = process_char(&mut engine, 'z').unwrap();
+    assert!(output.clamped);
+    // Only the slack is deleted; the rule's text still goes in
+    assert_eq!(output.action, ActionType::BackspaceDeleteAndInsert(8, "!".to_string()));
+    assert_eq!(output.delete_chars, 8);
+    assert_eq!(output.delete_utf16_units, 8);
+    // The host keeps what was not deleted, and so does the engine
+    assert_eq!(output.composing_text, "abcdefgh!");
+    assert_eq!(engine.composing_text(), "abcdefgh!");
+
+    // The next key edits the text the host has
+    let output = process_char(&mut engine, 'q').unwrap();
+    assert!(!output.clamped);
+    assert_eq!(output.action, ActionType::Insert("q".to_string()));
+    assert_eq!(output.composing_text, "abcdefgh!q");
+}
+
+#[test]
+fn test_delete_of_typed_text_is_not_clamped() {
+    let mut engine = create_engine(GREEDY_KMS).unwrap();
+    process_string(&mut engine, "abcdefghijklmnop").unwrap();
+
+    let output = process_char(&mut engine, 'z').unwrap();
+    assert!(!output.clamped);
+    assert_eq!(output.action, ActionType::BackspaceDeleteAndInsert(16, "!".to_string()));
+}
+
+#[test]
+fn test_typed_text_after_host_text_counts() {
+    let mut engine = create_engine(GREEDY_KMS).unwrap();
+    engine.set_composing_text("abcdef".to_string());
+    process_string(&mut engine, "ghijklmnop").unwrap();
+
+    // Ten typed characters and the slack cover all sixteen
+    let output = process_char(&mut engine, 'z').unwrap();
+    assert!(!output.clamped);
+    assert_eq!(output.delete_chars, 16);
+
+    // Reset forgets the host's text
+    engine.reset();
+    process_string(&mut engine, "abcdefghijklmnop").unwrap();
+    assert!(!process_char(&mut engine, 'z').unwrap().clamped);
+}
+
+#[test]
+fn test_delete_slack_is_configurable() {
+    let mut engine = create_engine(GREEDY_KMS).unwrap();
+    assert_eq!(engine.delete_slack(), 8);
+
+    engine.set_delete_slack(16);
+    engine.set_composing_text("abcdefghijklmnop".to_string());
+    let output = process_char(&mut engine, 'z').unwrap();
+    assert!(!output.clamped);
+    assert_eq!(output.delete_chars, 16);
+
+    // Without slack nothing of the host's text is deleted
+    engine.set_delete_slack(0);
+    engine.set_composing_text("abcdefghijklmnop".to_string());
+    let output = process_char(&mut engine, 'z').unwrap();
+    assert!(output.clamped);
+    assert_eq!(output.action, ActionType::Insert("!".to_string()));
+    assert_eq!(output.delete_utf16_units, 0);
+}
+
+#[test]
+fn test_reordering_never_clamps() {
+    let mut engine = create_engine(REORDER_KMS).unwrap();
+    for key in "aujacj".chars() {
+        assert!(!process_char(&mut engine, key).unwrap().clamped);
+    }
+    assert_eq!(engine.composing_text(), "ကြေငြေ");
+
+    // Reordering text the host synced back, as after moving the cursor
+    for context in ["ေ", "ကေ", "ကြေငေ"] {
+        engine.set_composing_text(context.to_string());
+        for key in "uj".chars() {
+            assert!(!process_char(&mut engine, key).unwrap().clamped);
+        }
+    }
+
+    // Smart backspace undoes the keys without clamping
+    engine.reset();
+    process_string(&mut engine, "auj").unwrap();
+    for _ in 0..3 {
+        assert!(!engine.process_key(key_input_from_vk(keymagic_core::VirtualKey::Back)).unwrap().clamped);
+    }
+}
+
+#[cfg(feature = "zawgyi")]
+mod zawgyi {
+    use super::*;
+    use keymagic_core::transform::zawgyi::unicode_to_zawgyi;
+    use keymagic_core::transform::TransformId;
+
+    /// Applies an engine action to a simulated host text buffer
+    fn apply_action(host: &mut String, action: &ActionType) {
+        let delete = |host: &mut String, count: usize| {
+            assert!(count <= host.chars().count(), "delete count exceeds host text");
+            let keep = host.chars().count() - count;
+            *host = host.chars().take(keep).collect();
+        };
+        match action {
+            ActionType::None => {}
+            ActionType::Insert(text) => host.push_str(text),
+            ActionType::BackspaceDelete(count) => delete(host, *count),
+            ActionType::BackspaceDeleteAndInsert(count, text) => {
+                delete(host, *count);
+                host.push_str(text);
+            }
+            other => panic!("unexpected action {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reordering_through_zawgyi_never_clamps() {
+        let mut engine = create_engine(REORDER_KMS).unwrap();
+        engine.set_output_transform(Some(TransformId::Zawgyi)).unwrap();
+        // Without slack any delete counted in the wrong units would trip
+        engine.set_delete_slack(0);
+
+        let mut host = String::new();
+        for key in "aujacj".chars() {
+            let output = process_char(&mut engine, key).unwrap();
+            assert!(!output.clamped, "clamped at {:?}", key);
+            apply_action(&mut host, &output.action);
+            assert_eq!(host, output.composing_text);
+        }
+        assert_eq!(engine.composing_text(), "ကြေငြေ");
+        assert_eq!(host, unicode_to_zawgyi("ကြေငြေ"));
+
+        // Smart backspace undoes the keys without clamping
+        for _ in 0..6 {
+            let output = engine.process_key(key_input_from_vk(keymagic_core::VirtualKey::Back)).unwrap();
+            assert!(!output.clamped);
+            apply_action(&mut host, &output.action);
+            assert_eq!(host, output.composing_text);
+        }
+        assert_eq!(host, "");
+    }
+
+    #[test]
+    fn test_clamped_delete_keeps_engine_and_host_in_step() {
+        let mut engine = create_engine(r#""ကြေ" + "x" => "!""#).unwrap();
+        engine.set_output_transform(Some(TransformId::Zawgyi)).unwrap();
+        engine.set_delete_slack(0);
+        engine.set_composing_text("ကြေ".to_string());
+        let mut host = engine.emitted_text();
+
+        // The rule would delete the host's syllable; it stays, reordered as
+        // the host shows it, and the engine composes after it
+        let output = process_char(&mut engine, 'x').unwrap();
+        assert!(output.clamped);
+        assert_eq!(output.action, ActionType::Insert("!".to_string()));
+        apply_action(&mut host, &output.action);
+        assert_eq!(host, output.composing_text);
+        assert_eq!(engine.composing_text(), "ကြေ!");
+        assert_eq!(output.composing_text, unicode_to_zawgyi("ကြေ!"));
+    }
+}
+
+#[cfg(feature = "ffi")]
+mod bridge {
+    use super::*;
+    use keymagic_core::ffi::*;
+    use std::ffi::CString;
+    use std::ptr;
+
+    fn press(engine: *mut EngineHandle, key: char) -> ProcessKeyOutput {
+        let mut output = ProcessKeyOutput {
+            action_type: 0,
+            text: ptr::null_mut(),
+            delete_count: 0,
+            composing_text: ptr::null_mut(),
+            is_processed: 0,
+            delete_utf16_count: 0,
+            composing_caret_utf16: 0,
+            notification: ptr::null_mut(),
+            delete_clamped: 0,
+        };
+        let result = keymagic_engine_process_key(engine, 0, key as i8, 0, 0, 0, 0, &mut output);
+        assert_eq!(result, KeyMagicResult::Success);
+        keymagic_free_string(output.text);
+        keymagic_free_string(output.composing_text);
+        keymagic_free_string(output.notification);
+        output
+    }
+
+    #[test]
+    fn test_bridge_reports_clamped_deletes() {
+        let binary = create_km2_binary(&kms2km2::compile_kms(GREEDY_KMS).unwrap()).unwrap();
+        let engine = keymagic_engine_new();
+        let result = keymagic_engine_load_keyboard_from_memory(engine, binary.as_ptr(), binary.len());
+        assert_eq!(result, KeyMagicResult::Success);
+
+        let context = CString::new("abcdefghijklmnop").unwrap();
+        assert_eq!(keymagic_engine_set_composition(engine, context.as_ptr()), KeyMagicResult::Success);
+        let output = press(engine, 'z');
+        assert_eq!(output.delete_clamped, 1);
+        assert_eq!(output.delete_count, 8);
+        assert_eq!(output.delete_utf16_count, 8);
+
+        assert_eq!(press(engine, 'a').delete_clamped, 0);
+        keymagic_engine_free(engine);
+    }
+}
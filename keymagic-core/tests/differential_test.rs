@@ -0,0 +1,272 @@
+//! Differential tests between `process_key_test` and `process_key`
+//!
+//! Previews rely on `process_key_test` returning exactly what the next
+//! `process_key` will and leaving the engine untouched. Random key sequences
+//! are run over the fixture keyboards twice: once with a test call before
+//! every real call and once with real calls only. Both runs must agree, and
+//! every test call must predict the real call that follows it.
+//!
+//! Sequences are seeded; a failure prints the seed and a shrunk sequence
+//! with the rules each key matched. `KEYMAGIC_FUZZ_SEED` replays one seed.
+
+mod common;
+use common::*;
+
+use keymagic_core::{ModifierState, EngineOutput, KeyInput, KeyMagicEngine, VirtualKey};
+use keymagic_core::types::km2::{Km2File, LayoutOverrides};
+
+/// Keyboards covering the rule features with history or state
+const FIXTURES: &[(&str, &str)] = &[
+    ("reordering", r#"
+$consK = "kKgGc"
+$consU = "ကခဂဃစ"
+$consK[*] => $consU[$1]
+"a" => "ာ"
+"e" => "ေ"
+"ေ" + $consU[*] => $2 + "ေ"
+"ာ" + "ာ" => "ါ"
+"kh" => "ခ"
+"#),
+    ("states", r#"
+<VK_OEM_3> => ('zg')
+('zg') + "1" => "၁"
+('zg') + "2" => "၂"
+"1" => "1"
+('zg') + "k" => $1 + $1
+"k" => "က"
+"#),
+    ("virtual_keys", r#"
+<VK_SHIFT & VK_KEY_A> => "အ"
+<VK_CTRL & VK_KEY_K> => "ဉ"
+"က" + <VK_SPACE> => "ကျ"
+"k" => "က"
+"a" => "ာ"
+"#),
+];
+
+/// Option sets each fixture runs under, smart backspace being the one that
+/// keeps history
+const OPTION_SETS: &[&[(&str, bool)]] = &[&[], &[("auto_bksp", true)], &[("eat", true)]];
+
+/// Keys the sequences are drawn from: (key, character, shift, ctrl)
+const KEYS: &[(VirtualKey, Option<char>, bool, bool)] = &[
+    (VirtualKey::KeyK, Some('k'), false, false),
+    (VirtualKey::KeyK, Some('K'), true, false),
+    (VirtualKey::KeyK, None, false, true),
+    (VirtualKey::KeyA, Some('a'), false, false),
+    (VirtualKey::KeyA, Some('A'), true, false),
+    (VirtualKey::KeyE, Some('e'), false, false),
+    (VirtualKey::KeyG, Some('g'), false, false),
+    (VirtualKey::KeyH, Some('h'), false, false),
+    (VirtualKey::KeyC, Some('c'), false, false),
+    (VirtualKey::KeyX, Some('x'), false, false),
+    (VirtualKey::Key1, Some('1'), false, false),
+    (VirtualKey::Key2, Some('2'), false, false),
+    (VirtualKey::Oem3, Some('`'), false, false),
+    (VirtualKey::Space, Some(' '), false, false),
+    (VirtualKey::Back, None, false, false),
+    (VirtualKey::Back, None, false, false),
+    (VirtualKey::Escape, None, false, false),
+    (VirtualKey::Return, None, false, false),
+];
+
+/// xorshift64*, enough for reproducible sequences without a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn key_sequence(seed: u64, len: usize) -> Vec<KeyInput> {
+    let mut rng = Rng::new(seed);
+    (0..len)
+        .map(|_| {
+            let (vk, ch, shift, ctrl) = KEYS[rng.below(KEYS.len())];
+            KeyInput::new(vk as u16, ModifierState::new(shift, ctrl, false, false), ch)
+        })
+        .collect()
+}
+
+struct Fixture {
+    name: String,
+    keyboard: Km2File,
+    overrides: LayoutOverrides,
+}
+
+impl Fixture {
+    fn engine(&self) -> KeyMagicEngine {
+        KeyMagicEngine::with_options(self.keyboard.clone(), &self.overrides).unwrap()
+    }
+}
+
+fn fixtures() -> Vec<Fixture> {
+    let mut fixtures = Vec::new();
+    for (name, kms) in FIXTURES {
+        let keyboard = kms2km2::compile_kms(kms).unwrap_or_else(|e| panic!("fixture {} does not compile: {}", name, e));
+        for options in OPTION_SETS {
+            let overrides: LayoutOverrides = options.iter().map(|(option, value)| (option.to_string(), *value)).collect();
+            fixtures.push(Fixture { name: format!("{} {:?}", name, options), keyboard: keyboard.clone(), overrides });
+        }
+    }
+    fixtures
+}
+
+type Outcome = Result<EngineOutput, String>;
+
+/// What is left in the engine after a run
+#[derive(Debug, PartialEq)]
+struct FinalState {
+    composing_text: String,
+    composing_caret: usize,
+    emitted_text: String,
+    undo_depth: usize,
+}
+
+fn final_state(engine: &KeyMagicEngine) -> FinalState {
+    FinalState {
+        composing_text: engine.composing_text().to_string(),
+        composing_caret: engine.composing_caret(),
+        emitted_text: engine.emitted_text(),
+        undo_depth: engine.undo_depth(),
+    }
+}
+
+fn real(engine: &mut KeyMagicEngine, key: &KeyInput) -> Outcome {
+    engine.process_key(key.clone()).map_err(|e| e.to_string())
+}
+
+/// First disagreement between the two runs of `keys`, if any
+fn check(fixture: &Fixture, keys: &[KeyInput]) -> Option<String> {
+    let mut plain = fixture.engine();
+    let mut interleaved = fixture.engine();
+    for (i, key) in keys.iter().enumerate() {
+        let predicted = interleaved.process_key_test(key.clone()).map_err(|e| e.to_string());
+        let with_test = real(&mut interleaved, key);
+        let without_test = real(&mut plain, key);
+        if predicted != with_test {
+            return Some(format!("key {}: test call returned {:?}, real call {:?}", i, predicted, with_test));
+        }
+        if with_test != without_test {
+            return Some(format!("key {}: real call returned {:?} after a test call, {:?} without", i, with_test, without_test));
+        }
+    }
+    let (with_test, without_test) = (final_state(&interleaved), final_state(&plain));
+    (with_test != without_test)
+        .then(|| format!("final state {:?} after test calls, {:?} without", with_test, without_test))
+}
+
+/// Drops keys from a failing sequence while it keeps failing
+fn shrink(mut keys: Vec<KeyInput>, fails: impl Fn(&[KeyInput]) -> bool) -> Vec<KeyInput> {
+    let mut chunk = keys.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < keys.len() {
+            let mut candidate = keys.clone();
+            candidate.drain(start..(start + chunk).min(keys.len()));
+            if fails(&candidate) {
+                keys = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+    keys
+}
+
+/// Replays `keys` on the real path with the rules each key matched
+fn rule_trace(fixture: &Fixture, keys: &[KeyInput]) -> String {
+    let mut engine = fixture.engine();
+    let mut trace = String::new();
+    for key in keys {
+        let outcome = real(&mut engine, key);
+        trace.push_str(&format!(
+            "  vk={:#04x} char={:?} shift={} ctrl={} -> rules {:?}, {:?}\n",
+            key.key_code,
+            key.character,
+            key.modifiers.shift,
+            key.modifiers.ctrl,
+            engine.last_matched_rules(),
+            outcome.map(|output| (output.action, output.composing_text)),
+        ));
+    }
+    trace
+}
+
+fn run(seeds: impl Iterator<Item = u64>, len: usize) {
+    let fixtures = fixtures();
+    let seeds: Vec<u64> = match std::env::var("KEYMAGIC_FUZZ_SEED") {
+        Ok(seed) => vec![seed.parse().expect("KEYMAGIC_FUZZ_SEED must be a number")],
+        Err(_) => seeds.collect(),
+    };
+    for seed in seeds {
+        let keys = key_sequence(seed, len);
+        for fixture in &fixtures {
+            if check(fixture, &keys).is_some() {
+                let keys = shrink(keys.clone(), |keys| check(fixture, keys).is_some());
+                panic!(
+                    "{} disagrees with seed {} ({} keys after shrinking): {}\n{}",
+                    fixture.name,
+                    seed,
+                    keys.len(),
+                    check(fixture, &keys).unwrap(),
+                    rule_trace(fixture, &keys),
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_fixtures_compile() {
+    assert_eq!(fixtures().len(), FIXTURES.len() * OPTION_SETS.len());
+    let mut engine = fixtures().remove(0).engine();
+    let output = engine.process_key(key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+    assert_eq!(output.composing_text, "က");
+}
+
+#[test]
+fn test_sequences_are_reproducible() {
+    assert_eq!(key_sequence(7, 50), key_sequence(7, 50));
+    assert_ne!(key_sequence(7, 50), key_sequence(8, 50));
+}
+
+#[test]
+fn test_shrink_keeps_the_failure() {
+    // A made-up failure stands in for an engine bug
+    let keys = key_sequence(3, 60);
+    let fails = |keys: &[KeyInput]| keys.iter().filter(|key| key.key_code == VirtualKey::Back as u16).count() >= 2;
+    assert!(fails(&keys));
+    let shrunk = shrink(keys, fails);
+    assert_eq!(shrunk.len(), 2);
+    assert!(fails(&shrunk));
+
+    let fixture = fixtures().remove(0);
+    assert!(rule_trace(&fixture, &shrunk).lines().count() == 2);
+}
+
+/// Quick version that always runs
+#[test]
+fn test_process_key_test_agrees_smoke() {
+    run(0..8, 200);
+}
+
+/// Thorough version; run with `cargo test -- --ignored`
+#[test]
+#[ignore]
+fn test_process_key_test_agrees_exhaustive() {
+    run(0..300, 1000);
+}
@@ -0,0 +1,178 @@
+//! Engine instance tracking and per-keyboard sharing
+#![cfg(feature = "ffi")]
+
+use keymagic_core::ffi::*;
+use keymagic_core::km2::content_hash;
+use std::ffi::{CStr, CString};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
+
+mod common;
+use common::*;
+
+/// The instance registry and sharing mode are process-wide
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn serial() -> std::sync::MutexGuard<'static, ()> {
+    SERIAL.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write_keyboard(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("keymagic_instances_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join(format!("{}.km2", name));
+    std::fs::write(&file, create_km2_binary(&create_basic_km2()).unwrap()).unwrap();
+    file
+}
+
+fn acquire(path: &Path) -> *mut EngineHandle {
+    let path = CString::new(path.to_str().unwrap()).unwrap();
+    keymagic_engine_acquire(path.as_ptr())
+}
+
+fn dump() -> String {
+    let json = keymagic_debug_dump_instances();
+    assert!(!json.is_null());
+    let text = unsafe { CStr::from_ptr(json) }.to_str().unwrap().to_string();
+    keymagic_free_string(json);
+    text
+}
+
+fn composition(handle: *mut EngineHandle) -> String {
+    let text = keymagic_engine_get_composition(handle);
+    let composition = unsafe { CStr::from_ptr(text) }.to_str().unwrap().to_string();
+    keymagic_free_string(text);
+    composition
+}
+
+#[test]
+fn test_instances_are_counted() {
+    let _guard = serial();
+    let before = keymagic_debug_instance_count();
+
+    let first = keymagic_engine_new();
+    let second = keymagic_engine_new();
+    assert_eq!(keymagic_debug_instance_count(), before + 2);
+
+    keymagic_engine_free(first);
+    keymagic_engine_free(second);
+    assert_eq!(keymagic_debug_instance_count(), before);
+}
+
+#[test]
+fn test_acquire_without_sharing_creates_separate_engines() {
+    let _guard = serial();
+    keymagic_engine_set_sharing(0);
+    let path = write_keyboard("separate");
+
+    let first = acquire(&path);
+    let second = acquire(&path);
+    assert!(!first.is_null() && !second.is_null());
+    assert_ne!(first, second);
+
+    keymagic_engine_free(first);
+    keymagic_engine_free(second);
+}
+
+#[test]
+fn test_shared_engine_is_refcounted() {
+    let _guard = serial();
+    keymagic_engine_set_sharing(1);
+    let path = write_keyboard("shared");
+    let before = keymagic_debug_instance_count();
+
+    let first = acquire(&path);
+    let second = acquire(&path);
+    assert!(!first.is_null());
+    assert_eq!(first, second);
+    assert_eq!(keymagic_debug_instance_count(), before + 1);
+
+    // Both owners see the same composing state
+    assert_eq!(keymagic_engine_set_composition(first, c"ka".as_ptr()), KeyMagicResult::Success);
+    assert_eq!(composition(second), "ka");
+
+    // A shared handle keeps its keyboard
+    let memory = create_km2_binary(&create_basic_km2()).unwrap();
+    assert_eq!(
+        keymagic_engine_load_keyboard_from_memory(first, memory.as_ptr(), memory.len()),
+        KeyMagicResult::ErrorInvalidParameter
+    );
+
+    // The first free only drops a reference
+    keymagic_engine_free(first);
+    assert_eq!(keymagic_debug_instance_count(), before + 1);
+    assert_eq!(composition(second), "ka");
+
+    keymagic_engine_free(second);
+    assert_eq!(keymagic_debug_instance_count(), before);
+    keymagic_engine_set_sharing(0);
+}
+
+#[test]
+fn test_replaced_keyboard_is_not_shared() {
+    let _guard = serial();
+    keymagic_engine_set_sharing(1);
+    let path = write_keyboard("replaced");
+    let before = keymagic_debug_instance_count();
+
+    let stale = acquire(&path);
+    assert!(!stale.is_null());
+
+    // An update replaces the file while the engine is loaded
+    let mut km2 = create_basic_km2();
+    add_info_text(&mut km2, "name", "Updated");
+    std::fs::write(&path, create_km2_binary(&km2).unwrap()).unwrap();
+
+    let fresh = acquire(&path);
+    assert!(!fresh.is_null());
+    assert_ne!(fresh, stale);
+    assert_eq!(keymagic_debug_instance_count(), before + 2);
+    assert_eq!(acquire(&path), fresh);
+
+    keymagic_engine_free(fresh);
+    keymagic_engine_free(fresh);
+    keymagic_engine_free(stale);
+    assert_eq!(keymagic_debug_instance_count(), before);
+    keymagic_engine_set_sharing(0);
+}
+
+#[test]
+fn test_acquire_missing_keyboard_fails() {
+    let _guard = serial();
+    let before = keymagic_debug_instance_count();
+    let missing = std::env::temp_dir().join("keymagic_instances_missing.km2");
+
+    assert!(acquire(&missing).is_null());
+    assert!(keymagic_engine_acquire(ptr::null()).is_null());
+    assert_eq!(keymagic_debug_instance_count(), before);
+}
+
+#[test]
+fn test_dump_format() {
+    let _guard = serial();
+    keymagic_engine_set_sharing(1);
+    let path = write_keyboard("dump");
+
+    let loaded = acquire(&path);
+    let shared = acquire(&path);
+    let empty = keymagic_engine_new();
+    let dump = dump();
+
+    let keyboard = path.to_str().unwrap().replace('\\', "\\\\").replace('"', "\\\"");
+    let hash = content_hash(&std::fs::read(&path).unwrap());
+    assert!(dump.starts_with("{\"sharing\":true,\"count\":"), "{}", dump);
+    assert!(
+        dump.contains(&format!("\"keyboard\":\"{}\",\"hash\":\"{}\",\"refs\":2}}", keyboard, hash)),
+        "{}",
+        dump
+    );
+    assert!(dump.contains("\"keyboard\":null,\"hash\":null,\"refs\":1}"), "{}", dump);
+    assert!(dump.contains(",\"instances\":[{\"id\":"), "{}", dump);
+    assert!(dump.ends_with("}]}"), "{}", dump);
+
+    keymagic_engine_free(loaded);
+    keymagic_engine_free(shared);
+    keymagic_engine_free(empty);
+    keymagic_engine_set_sharing(0);
+}
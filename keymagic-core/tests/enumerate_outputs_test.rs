@@ -0,0 +1,88 @@
+//! Tests for enumerating key outputs
+
+use keymagic_core::conformance::KeyEvent;
+use keymagic_core::{OutputEntry, KeyMagicEngine, VirtualKey};
+
+fn engine(kms: &str) -> KeyMagicEngine {
+    KeyMagicEngine::new(kms2km2::compile_kms(kms).unwrap()).unwrap()
+}
+
+fn keys(entry: &OutputEntry) -> String {
+    entry.keys.iter().map(|key| key.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+fn listing(engine: &KeyMagicEngine, depth: usize) -> Vec<(String, String)> {
+    engine.enumerate_outputs(depth).map(|entry| (keys(&entry), entry.output)).collect()
+}
+
+#[test]
+fn test_single_keys_handled_by_rules() {
+    let engine = engine(r#"
+"k" => "က"
+"K" => "ဃ"
+<VK_CTRL & VK_ALT & VK_KEY_M> => "မ"
+"#);
+
+    assert_eq!(
+        listing(&engine, 1),
+        vec![
+            ("K".to_string(), "ဃ".to_string()),
+            ("k".to_string(), "က".to_string()),
+            ("<VK_CONTROL & VK_MENU & VK_KEY_M>".to_string(), "မ".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_sequences_are_listed_only_when_rules_combine_keys() {
+    let engine = engine(r#"
+"k" => "က"
+"က" + "a" => "ကာ"
+"th" => "θ"
+"#);
+
+    let listed = listing(&engine, 2);
+    assert!(listed.contains(&("k a".to_string(), "ကာ".to_string())));
+    assert!(listed.contains(&("t h".to_string(), "θ".to_string())));
+    // Typed one after the other without a rule joining them
+    assert!(!listed.iter().any(|(keys, _)| keys == "k k"));
+    // Plain typing is not a keyboard output
+    assert!(!listed.iter().any(|(keys, _)| keys == "a" || keys == "t" || keys == "a t"));
+}
+
+#[test]
+fn test_entries_report_matched_rules() {
+    let engine = engine(r#"
+"x" => "ခ"
+"k" => "က"
+"#);
+
+    let entries: Vec<OutputEntry> = engine.enumerate_outputs(1).collect();
+    let k = entries.iter().find(|entry| entry.keys == vec![KeyEvent::Char('k')]).unwrap();
+    assert_eq!(k.rules, vec![1]);
+}
+
+#[test]
+fn test_depth_and_progress() {
+    let engine = engine(r#""th" => "θ""#);
+
+    assert_eq!(engine.enumerate_outputs(0).count(), 0);
+    assert_eq!(listing(&engine, 1), Vec::<(String, String)>::new());
+
+    let mut enumerator = engine.enumerate_outputs(2);
+    assert_eq!(enumerator.next().map(|entry| keys(&entry)), Some("t h".to_string()));
+    let progress = enumerator.progress();
+    assert_eq!(progress.depth, 2);
+    assert!(progress.keys_tried > 0);
+    assert_eq!(enumerator.next(), None);
+}
+
+#[test]
+fn test_keys_engine_does_not_process_end_the_sequence() {
+    // Only composing text changes extend a sequence, so Ctrl combinations
+    // the keyboard ignores are never explored further
+    let engine = engine(r#"<VK_CTRL & VK_KEY_K> => "က""#);
+    let entries: Vec<OutputEntry> = engine.enumerate_outputs(2).collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].keys, vec![KeyEvent::Combo(vec![VirtualKey::Control, VirtualKey::KeyK])]);
+}
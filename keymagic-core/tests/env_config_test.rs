@@ -0,0 +1,96 @@
+//! Tests for reading the keyboard and layout options from the environment
+#![cfg(feature = "env-config")]
+
+use keymagic_core::env_config::{EngineConfig, CONFIG_VAR, KEYBOARD_VAR, OPTIONS_VAR};
+use keymagic_core::types::km2::LayoutOverrides;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+mod common;
+use common::*;
+
+fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let map: HashMap<String, String> = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    move |name| map.get(name).cloned()
+}
+
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("keymagic-env-config-{}-{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_environment_variables() {
+    let config = EngineConfig::from_vars(vars(&[
+        (KEYBOARD_VAR, "/keyboards/myanmar3.km2"),
+        (OPTIONS_VAR, r#"{"auto_bksp": true, "eat": false}"#),
+    ]))
+    .unwrap();
+    assert_eq!(config.keyboard.as_deref(), Some(Path::new("/keyboards/myanmar3.km2")));
+    assert_eq!(config.options, LayoutOverrides::from([("auto_bksp".to_string(), true), ("eat".to_string(), false)]));
+
+    // Unset and empty variables give nothing
+    assert_eq!(EngineConfig::from_vars(vars(&[(KEYBOARD_VAR, "")])).unwrap(), EngineConfig::default());
+
+    for bad in ["not json", "[true]", r#"{"auto_bksp": "yes"}"#] {
+        let error = EngineConfig::from_vars(vars(&[(OPTIONS_VAR, bad)])).unwrap_err();
+        assert!(error.to_string().contains(OPTIONS_VAR), "{}", error);
+    }
+}
+
+#[test]
+fn test_config_file() {
+    let dir = temp_dir("file");
+    let file = dir.join("keymagic.json");
+    std::fs::write(&file, r#"{"keyboard": "myanmar3.km2", "options": {"auto_bksp": true, "eat": true}}"#).unwrap();
+
+    let config = EngineConfig::from_file(&file).unwrap();
+    assert_eq!(config.keyboard, Some(dir.join("myanmar3.km2")));
+
+    // Variables go over the file
+    let config = EngineConfig::from_vars(vars(&[
+        (CONFIG_VAR, file.to_str().unwrap()),
+        (OPTIONS_VAR, r#"{"eat": false}"#),
+    ]))
+    .unwrap();
+    assert_eq!(config.keyboard, Some(dir.join("myanmar3.km2")));
+    assert_eq!(config.options, LayoutOverrides::from([("auto_bksp".to_string(), true), ("eat".to_string(), false)]));
+
+    assert!(EngineConfig::parse(r#"{"keybaord": "a.km2"}"#, &dir).is_err());
+    assert!(EngineConfig::parse(r#"{"keyboard": 1}"#, &dir).is_err());
+    assert!(EngineConfig::from_file(&dir.join("missing.json")).is_err());
+}
+
+#[test]
+fn test_merge_prefers_the_override() {
+    let base = EngineConfig {
+        keyboard: Some(PathBuf::from("a.km2")),
+        options: LayoutOverrides::from([("eat".to_string(), true), ("auto_bksp".to_string(), true)]),
+    };
+    let merged = base.clone().merge(EngineConfig {
+        keyboard: None,
+        options: LayoutOverrides::from([("eat".to_string(), false)]),
+    });
+    assert_eq!(merged.keyboard, base.keyboard);
+    assert_eq!(merged.options, LayoutOverrides::from([("eat".to_string(), false), ("auto_bksp".to_string(), true)]));
+
+    let merged = base.merge(EngineConfig { keyboard: Some(PathBuf::from("b.km2")), options: LayoutOverrides::new() });
+    assert_eq!(merged.keyboard.as_deref(), Some(Path::new("b.km2")));
+}
+
+#[test]
+fn test_load_engine() {
+    let dir = temp_dir("engine");
+    let path = dir.join("test.km2");
+    let km2 = kms2km2::compile_kms(r#""ka" => "က""#).unwrap();
+    std::fs::write(&path, create_km2_binary(&km2).unwrap()).unwrap();
+
+    let config = EngineConfig { keyboard: Some(path.clone()), options: LayoutOverrides::from([("auto_bksp".to_string(), true)]) };
+    let engine = config.load_engine().unwrap();
+    assert_eq!(engine.layout_options().auto_bksp, 1);
+
+    let unknown = EngineConfig { keyboard: Some(path), options: LayoutOverrides::from([("smart_quotes".to_string(), true)]) };
+    assert!(unknown.load_engine().is_err());
+    assert!(EngineConfig::default().load_engine().is_err());
+}
@@ -0,0 +1,204 @@
+//! Tests for explaining what the engine does with a key, and why
+
+use keymagic_core::{KeyOutcome, RuleFailure, VirtualKey};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_matching_rule_is_reported() {
+    let mut engine = create_engine(r#""ka" => "X""#).unwrap();
+    engine.set_composing_text("k".to_string());
+
+    let explanation = engine.explain_key(&key_input_vk_char(VirtualKey::KeyA, 'a'));
+    assert_eq!(explanation.outcome, KeyOutcome::RuleMatched { rule: 0, text: r#""ka" => "X""#.to_string() });
+    assert_eq!(explanation.rules_for_key, 1);
+    assert!(explanation.candidates.is_empty());
+}
+
+#[test]
+fn test_context_too_short_and_mismatch() {
+    let mut engine = create_engine(r#""ka" => "X""#).unwrap();
+    let key = key_input_vk_char(VirtualKey::KeyA, 'a');
+
+    let explanation = engine.explain_key(&key);
+    assert_eq!(explanation.outcome, KeyOutcome::Typed);
+    assert_eq!(explanation.rules_for_key, 1);
+    let candidate = &explanation.candidates[0];
+    assert!(candidate.for_key);
+    assert_eq!(candidate.failure, RuleFailure::ContextTooShort { needed: 2, available: 1 });
+
+    engine.set_composing_text("g".to_string());
+    let explanation = engine.explain_key(&key);
+    assert_eq!(
+        explanation.candidates[0].failure,
+        RuleFailure::ContextMismatch { expected: r#""ka""#.to_string(), found: "ga".to_string() }
+    );
+    // Explaining changes nothing
+    assert_eq!(engine.composing_text(), "g");
+}
+
+#[test]
+fn test_variable_mismatch_names_the_variable() {
+    let mut engine = create_engine("$cons = \"ကခ\"\n$cons[*] + \"a\" => $1 + \"ာ\"").unwrap();
+    engine.set_composing_text("ဂ".to_string());
+
+    let explanation = engine.explain_key(&key_input_vk_char(VirtualKey::KeyA, 'a'));
+    let candidate = &explanation.candidates[0];
+    assert_eq!(candidate.element, 0);
+    assert_eq!(candidate.failure, RuleFailure::ContextMismatch { expected: "$cons[*]".to_string(), found: "ဂ".to_string() });
+}
+
+#[test]
+fn test_inactive_state() {
+    let engine = create_engine("\"k\" => U1000 + ('zg')\n('zg') + \"a\" => \"X\"").unwrap();
+
+    let explanation = engine.explain_key(&key_input_vk_char(VirtualKey::KeyA, 'a'));
+    assert_eq!(explanation.outcome, KeyOutcome::Typed);
+    assert_eq!(explanation.rules_for_key, 1);
+    assert_eq!(explanation.candidates[0].rule, 1);
+    assert_eq!(explanation.candidates[0].failure, RuleFailure::StateInactive { state: "zg".to_string() });
+}
+
+#[test]
+fn test_key_and_modifier_mismatch() {
+    let engine = create_engine("<VK_SHIFT & VK_KEY_A> => \"A\"\n<VK_KEY_B> => \"B\"").unwrap();
+
+    let explanation = engine.explain_key(&key_input_vk_char(VirtualKey::KeyA, 'a'));
+    assert_eq!(explanation.outcome, KeyOutcome::Typed);
+    assert_eq!(explanation.rules_for_key, 1);
+    let failures: Vec<(bool, &RuleFailure)> = explanation.candidates.iter().map(|c| (c.for_key, &c.failure)).collect();
+    assert_eq!(
+        failures,
+        vec![
+            (true, &RuleFailure::ModifierMismatch { expected: "Shift".to_string(), pressed: "none".to_string() }),
+            (false, &RuleFailure::KeyMismatch { expected: "VK_KEY_B".to_string() }),
+        ]
+    );
+}
+
+#[test]
+fn test_key_without_character() {
+    let engine = create_engine(r#""a" => "X""#).unwrap();
+
+    let explanation = engine.explain_key(&key_input_from_vk(VirtualKey::F1));
+    assert_eq!(explanation.outcome, KeyOutcome::Unprocessed);
+    assert_eq!(explanation.rules_for_key, 0);
+    assert_eq!(explanation.candidates[0].failure, RuleFailure::NoCharacter);
+}
+
+#[test]
+fn test_unmatchable_rule() {
+    let engine = create_engine("<VK_KEY_A & VK_KEY_B> => \"X\"").unwrap();
+
+    let explanation = engine.explain_key(&key_input_vk_char(VirtualKey::KeyA, 'a'));
+    assert_eq!(explanation.outcome, KeyOutcome::Typed);
+    assert_eq!(explanation.candidates[0].failure, RuleFailure::Unmatchable);
+}
+
+#[test]
+fn test_disabled_group() {
+    let mut engine = create_engine("@group \"symbols\"\n\".\" => \"။\"\n@endgroup").unwrap();
+    engine.set_group_enabled("symbols", false).unwrap();
+
+    let explanation = engine.explain_key(&key_input_vk_char(VirtualKey::OemPeriod, '.'));
+    assert_eq!(explanation.outcome, KeyOutcome::Typed);
+    assert_eq!(explanation.candidates[0].failure, RuleFailure::GroupDisabled { group: "symbols".to_string() });
+
+    engine.set_group_enabled("symbols", true).unwrap();
+    let explanation = engine.explain_key(&key_input_vk_char(VirtualKey::OemPeriod, '.'));
+    assert!(matches!(explanation.outcome, KeyOutcome::RuleMatched { rule: 0, .. }));
+}
+
+#[test]
+fn test_unmatched_outcomes() {
+    let mut engine = create_engine(r#""ka" => "X""#).unwrap();
+    engine.set_composing_text("k".to_string());
+    assert_eq!(engine.explain_key(&key_input_from_vk(VirtualKey::Back)).outcome, KeyOutcome::Backspace);
+
+    let mut options = engine.layout_options();
+    options.eat = 1;
+    engine.set_layout_options(options);
+    assert_eq!(engine.explain_key(&key_input_vk_char(VirtualKey::KeyQ, 'q')).outcome, KeyOutcome::Eaten);
+}
+
+#[test]
+fn test_keys_passed_to_the_host() {
+    let mut engine = create_engine(r#""a" => "X""#).unwrap();
+    engine.set_passthrough_keys(&[VirtualKey::Escape]);
+    engine.set_shortcut_keys(&[VirtualKey::KeyA]);
+
+    let explanation = engine.explain_key(&key_input_from_vk(VirtualKey::Escape));
+    assert_eq!(explanation.outcome, KeyOutcome::PassedThrough);
+    assert!(explanation.candidates.is_empty());
+
+    let key = key_input_vk_char(VirtualKey::KeyA, 'a');
+    let explanation = engine.explain_key(&key);
+    assert_eq!(explanation.outcome, KeyOutcome::HostShortcut);
+    assert_eq!(explanation.rules_for_key, 1);
+    assert!(explanation.candidates.is_empty());
+
+    // Shortcuts only pass while nothing is composed
+    engine.set_composing_text("k".to_string());
+    assert!(matches!(engine.explain_key(&key).outcome, KeyOutcome::RuleMatched { rule: 0, .. }));
+}
+
+#[test]
+fn test_candidates_are_ranked_and_capped() {
+    let kms = r#"
+"x" => "1"
+"y" => "2"
+"z" => "3"
+"w" => "4"
+"v" => "5"
+"u" => "6"
+"pqa" => "7"
+"qa" => "8"
+"#;
+    let mut engine = create_engine(kms).unwrap();
+    engine.set_composing_text("pr".to_string());
+
+    let explanation = engine.explain_key(&key_input_vk_char(VirtualKey::KeyA, 'a'));
+    assert_eq!(explanation.outcome, KeyOutcome::Typed);
+    assert_eq!(explanation.rules_for_key, 2);
+    assert_eq!(explanation.candidates.len(), 5);
+    // Rules for the key first, the one failing furthest along ahead
+    let rules: Vec<usize> = explanation.candidates.iter().map(|c| c.rule).collect();
+    assert_eq!(&rules[..2], &[6, 7]);
+    assert_eq!(explanation.candidates[0].failure, RuleFailure::ContextMismatch { expected: r#""pqa""#.to_string(), found: "pra".to_string() });
+    assert!(explanation.candidates[2..].iter().all(|c| !c.for_key));
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn test_ffi_explain_key() {
+    use keymagic_core::ffi::*;
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+
+    let binary = create_km2_binary(&kms2km2::compile_kms("\"k\" + \"a\" => \"X\"\n<VK_SHIFT & VK_KEY_A> => \"Y\"").unwrap()).unwrap();
+    let handle = keymagic_engine_new();
+    assert!(keymagic_engine_explain_key_win(handle, 0x41, b'a' as c_char, 0, 0, 0, 0).is_null());
+
+    let result = keymagic_engine_load_keyboard_from_memory(handle, binary.as_ptr(), binary.len());
+    assert_eq!(result, KeyMagicResult::Success);
+    let json = keymagic_engine_explain_key_win(handle, 0x41, b'a' as c_char, 0, 0, 0, 0);
+    let text = unsafe { CStr::from_ptr(json) }.to_str().unwrap().to_string();
+    keymagic_free_string(json);
+    assert_eq!(
+        text,
+        concat!(
+            r#"{"outcome":{"kind":"typed"},"rules_for_key":2,"candidates":["#,
+            r#"{"rule":1,"text":"<VK_SHIFT & VK_KEY_A> => \"Y\"","for_key":true,"element":0,"failure":{"kind":"modifier_mismatch","expected":"Shift","pressed":"none"}},"#,
+            r#"{"rule":0,"text":"\"k\" + \"a\" => \"X\"","for_key":true,"element":0,"failure":{"kind":"context_too_short","needed":2,"available":1}}]}"#
+        )
+    );
+
+    let json = keymagic_engine_explain_key(handle, VirtualKey::KeyA as i32, b'A' as c_char, 1, 0, 0, 0);
+    let text = unsafe { CStr::from_ptr(json) }.to_str().unwrap().to_string();
+    keymagic_free_string(json);
+    assert!(text.starts_with(r#"{"outcome":{"kind":"rule_matched","rule":1,"#), "{}", text);
+    assert!(keymagic_engine_explain_key(std::ptr::null_mut(), 0x41, 0, 0, 0, 0, 0).is_null());
+
+    keymagic_engine_free(handle);
+}
@@ -0,0 +1,114 @@
+//! Tests for the built-in fallback keyboard and load error reporting
+
+mod common;
+use common::*;
+
+use keymagic_core::km2::{FALLBACK_KM2, FALLBACK_KMS};
+use keymagic_core::types::virtual_keys::create_vk_map;
+use keymagic_core::{ActionType, KeyInput, KeyMagicEngine, ModifierState};
+
+#[test]
+fn test_fallback_bytes_match_the_source() {
+    let compiled = create_km2_binary(&kms2km2::compile_kms(FALLBACK_KMS).unwrap()).unwrap();
+    assert_eq!(compiled, FALLBACK_KM2);
+
+    let engine = KeyMagicEngine::fallback();
+    assert_eq!(engine.keyboard().metadata().name().as_deref(), Some("KeyMagic Fallback"));
+    assert!(engine.keyboard().rules.is_empty());
+}
+
+#[test]
+fn test_fallback_passes_every_key_through() {
+    let mut engine = KeyMagicEngine::fallback();
+    let modifiers = [
+        ModifierState::new(false, false, false, false),
+        ModifierState::new(true, false, false, false),
+        ModifierState::new(false, true, false, false),
+        ModifierState::new(false, false, true, false),
+        ModifierState::new(true, true, true, true),
+    ];
+
+    let mut inputs = Vec::new();
+    for key in create_vk_map().into_values() {
+        for modifiers in modifiers {
+            inputs.push(KeyInput::from_vk(key as u16, modifiers));
+        }
+    }
+    for ch in (' '..='~').chain("ကာ\u{1F600}".chars()) {
+        inputs.push(key_input_from_char(ch));
+    }
+    inputs.push(key_input_from_vk(keymagic_core::VirtualKey::Back));
+
+    for input in inputs {
+        let output = engine.process_key(input.clone()).unwrap();
+        assert!(!output.is_processed, "{:?} was processed", input);
+        assert_eq!(output.action, ActionType::None, "{:?}", input);
+        assert_eq!(output.composing_text, "", "{:?}", input);
+    }
+    assert!(engine.last_matched_rules().is_empty());
+}
+
+#[cfg(feature = "ffi")]
+mod bridge {
+    use super::*;
+    use keymagic_core::ffi::*;
+    use std::ffi::{CStr, CString};
+
+    fn last_error() -> Option<String> {
+        let error = keymagic_engine_last_error();
+        if error.is_null() {
+            return None;
+        }
+        let text = unsafe { CStr::from_ptr(error) }.to_str().unwrap().to_string();
+        keymagic_free_string(error);
+        Some(text)
+    }
+
+    fn process(engine: *mut EngineHandle, key_code: i32, character: char) -> i32 {
+        let mut output = unsafe { std::mem::zeroed::<ProcessKeyOutput>() };
+        let result = keymagic_engine_process_key(engine, key_code, character as i8, 0, 0, 0, 0, &mut output);
+        assert_eq!(result, KeyMagicResult::Success);
+        keymagic_free_string(output.text);
+        keymagic_free_string(output.composing_text);
+        keymagic_free_string(output.notification);
+        assert_eq!(output.action_type, 0);
+        output.is_processed
+    }
+
+    #[test]
+    fn test_failed_load_falls_back_and_keeps_the_error() {
+        let engine = keymagic_engine_new();
+
+        let missing = CString::new("/nonexistent/keymagic/missing.km2").unwrap();
+        assert_eq!(keymagic_engine_load_keyboard(engine, missing.as_ptr()), KeyMagicResult::ErrorEngineFailure);
+        assert!(last_error().unwrap().starts_with("/nonexistent/keymagic/missing.km2: "));
+
+        let garbage = b"not a keyboard";
+        let result = keymagic_engine_load_keyboard_from_memory(engine, garbage.as_ptr(), garbage.len());
+        assert_eq!(result, KeyMagicResult::ErrorEngineFailure);
+        let error = last_error().unwrap();
+        assert!(error.starts_with("<memory>: "), "{}", error);
+
+        // Without a keyboard there is no engine at all
+        let mut output = unsafe { std::mem::zeroed::<ProcessKeyOutput>() };
+        let result = keymagic_engine_process_key(engine, 0x41, b'a' as i8, 0, 0, 0, 0, &mut output);
+        assert_eq!(result, KeyMagicResult::ErrorNoKeyboard);
+
+        assert_eq!(keymagic_engine_load_fallback(engine), KeyMagicResult::Success);
+        for (key_code, character) in [(0x41, 'a'), (0x4B, 'k'), (0x20, ' '), (0x08, '\0'), (0x0D, '\0')] {
+            assert_eq!(process(engine, key_code, character), 0);
+        }
+        // The real keyboard is still wanted
+        let hash = CString::new("abc123").unwrap();
+        assert_eq!(keymagic_engine_needs_reload(engine, hash.as_ptr()), 1);
+
+        // A later successful load leaves the reason alone
+        let binary = create_km2_binary(&kms2km2::compile_kms(r#""k" => "က""#).unwrap()).unwrap();
+        let result = keymagic_engine_load_keyboard_from_memory(engine, binary.as_ptr(), binary.len());
+        assert_eq!(result, KeyMagicResult::Success);
+        assert_eq!(last_error(), Some(error));
+
+        assert_eq!(keymagic_engine_load_fallback(std::ptr::null_mut()), KeyMagicResult::ErrorInvalidParameter);
+        keymagic_engine_free(engine);
+    }
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use common::*;
+use keymagic_core::km2::features::{self, FEATURES, NOTIFY, POST_RULES, RULE_GROUPS, WIDE_INDICES};
+use keymagic_core::km2::{used_features, EngineVersion, FeatureRequirements, FeatureSet, Km2Error, Km2Loader};
+use keymagic_core::types::km2::{BinaryFormatElement, PostRules, INFO_FEAT, INFO_POST};
+
+fn keyboard_with_notify() -> keymagic_core::Km2File {
+    let mut km2 = create_basic_km2();
+    add_rule(&mut km2,
+        vec![BinaryFormatElement::String("n".to_string())],
+        vec![BinaryFormatElement::Notify("Numbers".to_string())]
+    );
+    km2
+}
+
+#[test]
+fn test_used_features() {
+    let mut km2 = keyboard_with_notify();
+    assert_eq!(used_features(&km2), FeatureSet::default().with(NOTIFY));
+
+    km2.set_info(INFO_POST, PostRules::from_flags([true]).encode());
+    km2.header.minor_version = 6;
+    let features = used_features(&km2);
+    assert!(features.contains(POST_RULES));
+    assert!(features.contains(WIDE_INDICES));
+    assert!(!features.contains(RULE_GROUPS));
+
+    // An empty post-rule entry marks no rule
+    km2.set_info(INFO_POST, Vec::new());
+    assert!(!used_features(&km2).contains(POST_RULES));
+
+    assert!(used_features(&create_basic_km2()).is_empty());
+}
+
+#[test]
+fn test_requirements_round_trip() {
+    let requirements = FeatureRequirements::of(FeatureSet::default().with(NOTIFY).with(POST_RULES));
+    assert_eq!(requirements.engine, NOTIFY.since.max(POST_RULES.since));
+    assert_eq!(requirements.names, vec![(POST_RULES.bit, POST_RULES.name.to_string()), (NOTIFY.bit, NOTIFY.name.to_string())]);
+
+    let decoded = FeatureRequirements::decode(&requirements.encode()).unwrap();
+    assert_eq!(decoded, requirements);
+    assert!(decoded.check().is_ok());
+
+    // Only the bitmap is needed; torn names are dropped
+    let data = requirements.encode();
+    let decoded = FeatureRequirements::decode(&data[..data.len() - 1]).unwrap();
+    assert_eq!(decoded.features, requirements.features);
+    assert_eq!(decoded.names.len(), 1);
+    assert_eq!(FeatureRequirements::decode(&data[..7]), None);
+}
+
+#[test]
+fn test_supported_features_are_known() {
+    let supported = FeatureSet::supported();
+    assert!(FEATURES.iter().all(|feature| supported.contains(*feature)));
+    assert_eq!(supported.required_version(), FEATURES.iter().map(|feature| feature.since).max().unwrap());
+    assert!(supported.required_version() <= EngineVersion::current());
+}
+
+/// A file from a future compiler, using bit 40 on top of notifications
+fn future_keyboard() -> Vec<u8> {
+    let mut km2 = keyboard_with_notify();
+    let requirements = FeatureRequirements {
+        features: FeatureSet(1 << 40).with(NOTIFY),
+        engine: EngineVersion::new(0, 3, 0),
+        names: vec![(NOTIFY.bit, NOTIFY.name.to_string()), (40, "Unicode ranges".to_string())],
+    };
+    km2.set_info(INFO_FEAT, requirements.encode());
+    create_km2_binary(&km2).unwrap()
+}
+
+#[test]
+fn test_unknown_feature_is_refused_by_name() {
+    let err = Km2Loader::load(&future_keyboard()).unwrap_err();
+    let Km2Error::IncompatibleKeyboard(incompatible) = &err else {
+        panic!("expected an incompatible keyboard, got {:?}", err);
+    };
+    assert_eq!(incompatible.missing, FeatureSet(1 << 40));
+    assert!(incompatible.required_features.contains(NOTIFY));
+    assert_eq!(incompatible.required_version, EngineVersion::new(0, 3, 0));
+    assert_eq!(incompatible.missing_names, vec!["Unicode ranges".to_string()]);
+    assert!(err.to_string().starts_with("This keyboard requires KeyMagic engine ≥ 0.3.0"), "{}", err);
+    assert!(err.to_string().ends_with("uses Unicode ranges"), "{}", err);
+}
+
+#[test]
+fn test_unknown_feature_without_name() {
+    let requirements = FeatureRequirements { features: FeatureSet(1 << 63), ..Default::default() };
+    let err = requirements.check().unwrap_err();
+    assert_eq!(err.missing_names, vec!["feature 63".to_string()]);
+}
+
+#[test]
+fn test_files_with_known_or_no_features_load() {
+    let mut km2 = keyboard_with_notify();
+    assert!(Km2Loader::load(&create_km2_binary(&km2).unwrap()).is_ok());
+
+    let entry = FeatureRequirements::entry_for(&km2).unwrap();
+    km2.set_info(&entry.id, entry.data);
+    assert!(Km2Loader::load(&create_km2_binary(&km2).unwrap()).is_ok());
+    assert!(features::FeatureRequirements::entry_for(&create_basic_km2()).is_none());
+}
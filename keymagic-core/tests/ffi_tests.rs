@@ -1,4 +1,5 @@
 //! FFI tests for keymagic-core
+#![cfg(feature = "ffi")]
 
 use keymagic_core::ffi::*;
 use std::ffi::{CStr, CString};
@@ -57,6 +58,10 @@ fn test_process_key_test_does_not_modify_engine() {
             delete_count: 0,
             composing_text: ptr::null_mut(),
             is_processed: 0,
+            delete_utf16_count: 0,
+            composing_caret_utf16: 0,
+            notification: ptr::null_mut(),
+            delete_clamped: 0,
         };
         
         let result = keymagic_engine_process_key(
@@ -82,6 +87,10 @@ fn test_process_key_test_does_not_modify_engine() {
             delete_count: 0,
             composing_text: ptr::null_mut(),
             is_processed: 0,
+            delete_utf16_count: 0,
+            composing_caret_utf16: 0,
+            notification: ptr::null_mut(),
+            delete_clamped: 0,
         };
         
         let test_result = keymagic_engine_process_key_test(
@@ -141,6 +150,10 @@ fn test_process_key_test_win_vk() {
             delete_count: 0,
             composing_text: ptr::null_mut(),
             is_processed: 0,
+            delete_utf16_count: 0,
+            composing_caret_utf16: 0,
+            notification: ptr::null_mut(),
+            delete_clamped: 0,
         };
         
         let test_result = keymagic_engine_process_key_test_win(
@@ -168,4 +181,131 @@ fn test_process_key_test_win_vk() {
         keymagic_free_string(final_composition);
         keymagic_engine_free(engine);
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_km2_sample_text() {
+    unsafe {
+        let mut km2_data = create_basic_km2();
+        let binary = create_km2_binary(&km2_data).unwrap();
+        let km2 = keymagic_km2_load_from_memory(binary.as_ptr(), binary.len());
+        assert!(keymagic_km2_get_sample_text(km2).is_null());
+        keymagic_km2_free(km2);
+
+        add_info_text(&mut km2_data, "lpms", "မြန်မာ");
+        let binary = create_km2_binary(&km2_data).unwrap();
+        let km2 = keymagic_km2_load_from_memory(binary.as_ptr(), binary.len());
+        let sample = keymagic_km2_get_sample_text(km2);
+        assert!(!sample.is_null());
+        assert_eq!(CStr::from_ptr(sample).to_str().unwrap(), "မြန်မာ");
+        keymagic_free_string(sample);
+        keymagic_km2_free(km2);
+
+        assert!(keymagic_km2_get_sample_text(ptr::null_mut()).is_null());
+    }
+}
+
+#[test]
+fn test_km2_font_family() {
+    unsafe {
+        let mut km2_data = create_basic_km2();
+        let binary = create_km2_binary(&km2_data).unwrap();
+        let km2 = keymagic_km2_load_from_memory(binary.as_ptr(), binary.len());
+        assert!(keymagic_km2_get_font_family(km2).is_null());
+        keymagic_km2_free(km2);
+
+        add_info_text(&mut km2_data, "tnof", "Pyidaungsu");
+        let binary = create_km2_binary(&km2_data).unwrap();
+        let km2 = keymagic_km2_load_from_memory(binary.as_ptr(), binary.len());
+        let family = keymagic_km2_get_font_family(km2);
+        assert!(!family.is_null());
+        assert_eq!(CStr::from_ptr(family).to_str().unwrap(), "Pyidaungsu");
+        keymagic_free_string(family);
+        keymagic_km2_free(km2);
+
+        assert!(keymagic_km2_get_font_family(ptr::null_mut()).is_null());
+    }
+}
+
+#[test]
+fn test_km2_handle_from_memory_loads_into_engines() {
+    unsafe {
+        let mut km2_data = create_basic_km2();
+        // Info ids are stored little-endian
+        add_info_text(&mut km2_data, "eman", "Memory Keyboard");
+        let binary = create_km2_binary(&km2_data).unwrap();
+
+        assert!(keymagic_km2_load_from_memory(ptr::null(), 0).is_null());
+        assert!(keymagic_km2_load_from_memory(b"nope".as_ptr(), 4).is_null());
+
+        let km2 = keymagic_km2_load_from_memory(binary.as_ptr(), binary.len());
+        assert!(!km2.is_null());
+
+        let name = keymagic_km2_get_name(km2);
+        assert!(!name.is_null());
+        assert_eq!(CStr::from_ptr(name).to_str().unwrap(), "Memory Keyboard");
+        keymagic_free_string(name);
+
+        let mut info = Km2Info::default();
+        assert_eq!(keymagic_km2_get_info(km2, &mut info), KeyMagicResult::Success);
+        assert_eq!((info.major_version, info.minor_version), (1, 5));
+        assert_eq!((info.info_count, info.rule_count), (1, 0));
+        assert_eq!(info.track_caps, 1);
+        let option = |name: &str| {
+            let name = CString::new(name).unwrap();
+            keymagic_km2_get_layout_option(km2, name.as_ptr())
+        };
+        assert_eq!(option("track_caps"), 1);
+        assert_eq!(option("case_insensitive_ascii"), 0);
+        assert_eq!(option("no_such_option"), -1);
+        assert_eq!(keymagic_km2_get_layout_option(km2, ptr::null()), -1);
+
+        // Each engine keeps its own copy, so the handle can go first
+        let first = keymagic_engine_new();
+        let second = keymagic_engine_new();
+        assert_eq!(keymagic_engine_load_km2(first, km2), KeyMagicResult::Success);
+        assert_eq!(keymagic_engine_load_km2(second, km2), KeyMagicResult::Success);
+        keymagic_km2_free(km2);
+        assert_eq!(keymagic_engine_load_km2(first, ptr::null()), KeyMagicResult::ErrorInvalidParameter);
+
+        let mut output = ProcessKeyOutput {
+            action_type: 0,
+            text: ptr::null_mut(),
+            delete_count: 0,
+            composing_text: ptr::null_mut(),
+            is_processed: 0,
+            delete_utf16_count: 0,
+            composing_caret_utf16: 0,
+            notification: ptr::null_mut(),
+            delete_clamped: 0,
+        };
+        let result = keymagic_engine_process_key(first, 97, b'a' as i8, 0, 0, 0, 0, &mut output);
+        assert_eq!(result, KeyMagicResult::Success);
+        keymagic_free_string(output.text);
+        keymagic_free_string(output.composing_text);
+
+        let composition = keymagic_engine_get_composition(second);
+        assert_eq!(CStr::from_ptr(composition).to_str().unwrap(), "");
+        keymagic_free_string(composition);
+
+        keymagic_engine_free(first);
+        keymagic_engine_free(second);
+    }
+}
+
+#[test]
+fn test_injected_input_and_switch_guard() {
+    assert_eq!(keymagic_is_injected_input(keymagic_core::hotkey::INJECTED_INPUT_SIGNATURE), 1);
+    assert_eq!(keymagic_is_injected_input(0), 0);
+
+    let guard = keymagic_switch_guard_new(2, 0, 500);
+    assert!(!guard.is_null());
+    assert_eq!(keymagic_switch_guard_check(guard, 1000), 0);
+    assert_eq!(keymagic_switch_guard_check(guard, 1100), 0);
+    assert_eq!(keymagic_switch_guard_check(guard, 1200), 1);
+    assert_eq!(keymagic_switch_guard_check(guard, 1300), 2);
+    assert_eq!(keymagic_switch_guard_check(guard, 1700), 0);
+    keymagic_switch_guard_free(guard);
+
+    assert_eq!(keymagic_switch_guard_check(ptr::null_mut(), 0), 0);
+    keymagic_switch_guard_free(ptr::null_mut());
+}
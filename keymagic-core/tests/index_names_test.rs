@@ -0,0 +1,91 @@
+mod common;
+
+use common::*;
+use keymagic_core::km2::{Km2Loader, RuleFormatter};
+use keymagic_core::types::km2::{BinaryFormatElement, IndexNames, INFO_STNM, INFO_VRNM};
+use keymagic_core::VirtualKey;
+
+/// `<VK_OEM_3>` switches state 0 and `$cons[*] + "a"` uses variable 1
+fn keyboard_with_state_and_variable() -> keymagic_core::Km2File {
+    let mut km2 = create_basic_km2();
+    let cons = add_string(&mut km2, "ကခ");
+    add_rule(&mut km2,
+        vec![BinaryFormatElement::And, BinaryFormatElement::Predefined(VirtualKey::Oem3 as u16)],
+        vec![BinaryFormatElement::Switch(0)]
+    );
+    add_rule(&mut km2,
+        vec![BinaryFormatElement::Switch(0), BinaryFormatElement::String("1".to_string())],
+        vec![BinaryFormatElement::String("၁".to_string())]
+    );
+    add_rule(&mut km2,
+        vec![
+            BinaryFormatElement::Variable(cons),
+            BinaryFormatElement::Modifier(keymagic_core::types::opcodes::FLAG_ANYOF),
+            BinaryFormatElement::String("a".to_string()),
+        ],
+        vec![BinaryFormatElement::Reference(1), BinaryFormatElement::String("ာ".to_string())]
+    );
+    km2
+}
+
+#[test]
+fn test_index_names_encoding() {
+    let names = IndexNames::new(vec![(0, "zg".to_string()), (3, "မြန်မာ".to_string())]);
+    let decoded = IndexNames::decode(&names.encode()).unwrap();
+    assert_eq!(decoded, names);
+    assert_eq!(decoded.get(3), Some("မြန်မာ"));
+    assert_eq!(decoded.get(1), None);
+
+    // Truncated data is rejected rather than read past
+    let data = names.encode();
+    assert_eq!(IndexNames::decode(&data[..data.len() - 1]), None);
+    assert_eq!(IndexNames::decode(&[0xFF, 0xFF, 0xFF, 0xFF]), None);
+}
+
+#[test]
+fn test_names_are_loaded() {
+    let mut km2 = keyboard_with_state_and_variable();
+    km2.set_info(INFO_STNM, IndexNames::new(vec![(0, "zg".to_string())]).encode());
+    km2.set_info(INFO_VRNM, IndexNames::new(vec![(1, "cons".to_string())]).encode());
+
+    let loaded = Km2Loader::load(&create_km2_binary(&km2).unwrap()).unwrap();
+    assert_eq!(loaded.states(), vec!["zg".to_string()]);
+    assert_eq!(loaded.variables(), vec![("cons".to_string(), "ကခ".to_string())]);
+
+    let formatter = RuleFormatter::for_keyboard(&loaded);
+    assert_eq!(formatter.format_rule(&loaded.rules[1]), "('zg') + \"1\" => \"၁\"");
+    assert_eq!(formatter.format_rule(&loaded.rules[2]), "$cons[*] + \"a\" => $1 + \"ာ\"");
+    assert_eq!(formatter.format_variable(1).as_deref(), Some("$cons = \"ကခ\""));
+}
+
+#[test]
+fn test_files_without_names_get_synthesized_ones() {
+    let km2 = keyboard_with_state_and_variable();
+    let loaded = Km2Loader::load(&create_km2_binary(&km2).unwrap()).unwrap();
+
+    assert_eq!(loaded.states(), vec!["state0".to_string()]);
+    assert_eq!(loaded.state_name(4), "state4");
+    assert_eq!(loaded.variables(), vec![("var1".to_string(), "ကခ".to_string())]);
+    assert_eq!(RuleFormatter::for_keyboard(&loaded).format_rule(&loaded.rules[1]), "('state0') + \"1\" => \"၁\"");
+
+    let empty = create_basic_km2();
+    assert!(empty.states().is_empty());
+    assert!(empty.variables().is_empty());
+}
+
+#[test]
+fn test_engine_names_active_states() {
+    let mut km2 = keyboard_with_state_and_variable();
+    let plain = create_km2_binary(&km2).unwrap();
+    km2.set_info(INFO_STNM, IndexNames::new(vec![(0, "zg".to_string())]).encode());
+    let named = create_km2_binary(&km2).unwrap();
+
+    for (binary, expected) in [(named, "zg"), (plain, "state0")] {
+        let mut engine = create_engine_from_binary(&binary).unwrap();
+        assert!(engine.active_state_names().is_empty());
+
+        process_key(&mut engine, key_input_from_vk(VirtualKey::Oem3)).unwrap();
+        assert_eq!(engine.active_states(), vec![0]);
+        assert_eq!(engine.active_state_names(), vec![expected.to_string()]);
+    }
+}
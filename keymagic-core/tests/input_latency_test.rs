@@ -0,0 +1,110 @@
+//! Sampled key handling times and their percentiles
+#![cfg(feature = "host-ipc")]
+
+use keymagic_core::input_latency::*;
+
+#[test]
+fn test_buckets_cover_every_time_once() {
+    // Consecutive, without gaps or overlaps
+    let mut next = 0;
+    for bucket in 0..BUCKET_COUNT {
+        let (low, high) = bucket_bounds(bucket);
+        assert_eq!(low, next, "bucket {}", bucket);
+        assert!(high >= low);
+        assert_eq!(bucket_of(low), bucket);
+        assert_eq!(bucket_of(high), bucket);
+        next = high + 1;
+    }
+    assert_eq!(bucket_of(next), BUCKET_COUNT - 1);
+    assert_eq!(bucket_of(u64::MAX), BUCKET_COUNT - 1);
+}
+
+#[test]
+fn test_buckets_are_within_a_sixteenth() {
+    for micros in [0, 1, 15, 16, 17, 31, 32, 100, 999, 1_000, 4_095, 65_536, 1_000_000, 9_999_999] {
+        let (low, high) = bucket_bounds(bucket_of(micros));
+        assert!(low <= micros && micros <= high, "{}", micros);
+        assert!((high - low) * 16 <= micros.max(1), "{}: {}..{}", micros, low, high);
+    }
+    assert_eq!(bucket_bounds(bucket_of(7)), (7, 7));
+    assert_eq!(bucket_bounds(bucket_of(50)), (50, 51));
+    assert_eq!(bucket_bounds(bucket_of(1_000)), (992, 1_023));
+}
+
+#[test]
+fn test_percentiles() {
+    let mut histogram = LatencyHistogram::new();
+    assert_eq!(histogram.percentile(50.0), None);
+    assert_eq!(histogram.summary(), LatencySummary::default());
+
+    for micros in 1..=100 {
+        histogram.record(micros);
+    }
+    assert_eq!(histogram.count(), 100);
+    assert_eq!(histogram.percentile(0.0), Some(1));
+    assert_eq!(histogram.percentile(10.0), Some(10));
+    assert_eq!(histogram.percentile(50.0), Some(51));
+    assert_eq!(histogram.percentile(95.0), Some(95));
+    assert_eq!(histogram.percentile(100.0), Some(103));
+    assert_eq!(histogram.max(), Some(103));
+
+    let summary = histogram.summary();
+    assert_eq!(summary.samples, 100);
+    assert_eq!((summary.p50_us, summary.p95_us, summary.p99_us), (Some(51), Some(95), Some(99)));
+}
+
+#[test]
+fn test_a_slow_tail_shows_in_high_percentiles_only() {
+    let mut histogram = LatencyHistogram::new();
+    for _ in 0..98 {
+        histogram.record(500);
+    }
+    histogram.record(80_000);
+    histogram.record(2_000_000);
+    assert_eq!(histogram.percentile(50.0), Some(511));
+    assert_eq!(histogram.percentile(95.0), Some(511));
+    assert_eq!(histogram.percentile(99.0), Some(81_919));
+    assert_eq!(histogram.max(), Some(2_031_615));
+}
+
+#[test]
+fn test_merge_and_since() {
+    let mut first = LatencyHistogram::new();
+    first.record(10);
+    first.record(1_000);
+    let mut second = LatencyHistogram::new();
+    second.record(10);
+
+    let mut total = first.clone();
+    total.merge(&second);
+    assert_eq!(total.count(), 3);
+    assert_eq!(total.since(&first), second);
+    assert_eq!(total.since(&total).count(), 0);
+    // Counters that went down, e.g. after a reset, count as none
+    assert_eq!(first.since(&total).count(), 0);
+}
+
+#[test]
+fn test_one_key_in_sample_every_is_timed() {
+    let latency = InputLatency::in_memory();
+    let sampled = (0..SAMPLE_EVERY * 4).filter(|_| latency.should_sample()).count();
+    assert_eq!(sampled, 4);
+    assert_eq!(latency.keys(), SAMPLE_EVERY * 4);
+}
+
+#[test]
+fn test_shared_counters_snapshot() {
+    let latency = InputLatency::in_memory();
+    let before = latency.snapshot();
+    latency.record(300);
+    latency.record(300);
+    latency.record(40_000);
+    let after = latency.snapshot();
+    assert_eq!(after.count(), 3);
+
+    let mut expected = LatencyHistogram::new();
+    expected.record(300);
+    expected.record(300);
+    expected.record(40_000);
+    assert_eq!(after.since(&before), expected);
+}
@@ -0,0 +1,289 @@
+//! Host list matching, input mode resolution and the published mode
+#![cfg(feature = "ffi")]
+
+use keymagic_core::ffi::keymagic_resolve_input_mode_w;
+use keymagic_core::input_mode::*;
+use keymagic_core::{Error, VirtualKey};
+use std::ptr;
+
+const NONE: &[&str] = &[];
+
+fn resolve(host: &str, composition: &[&str], direct: &[&str], default: InputMode) -> (InputMode, Option<(HostList, String)>) {
+    let resolution = resolve_input_mode(host, composition, direct, default);
+    (resolution.mode, resolution.matched.map(|m| (m.list, m.pattern)))
+}
+
+#[test]
+fn test_exact_names_ignore_case_and_whitespace() {
+    assert!(host_matches("excel.exe", "excel.exe"));
+    assert!(host_matches("EXCEL.EXE", "excel.exe"));
+    assert!(host_matches("excel.exe", "Excel.EXE"));
+    assert!(host_matches("  excel.exe ", "excel.exe"));
+    assert!(!host_matches("excel.exe", "excel.exe2"));
+    assert!(!host_matches("excel.exe", "xexcel.exe"));
+    assert!(!host_matches("excel", "excel.exe"));
+}
+
+#[test]
+fn test_blank_entries_match_nothing() {
+    assert!(!host_matches("", "excel.exe"));
+    assert!(!host_matches("   ", "excel.exe"));
+    assert!(!host_matches("", ""));
+}
+
+#[test]
+fn test_star_wildcard() {
+    assert!(host_matches("*", "chrome.exe"));
+    assert!(host_matches("*", ""));
+    assert!(host_matches("*chrome*", "chrome.exe"));
+    assert!(host_matches("*chrome*", "googlechrome.exe"));
+    assert!(host_matches("*chrome*", "CHROME"));
+    assert!(host_matches("chrome*", "chrome.exe"));
+    assert!(!host_matches("chrome*", "googlechrome.exe"));
+    assert!(host_matches("*.exe", "winword.exe"));
+    assert!(!host_matches("*.exe", "winword.com"));
+    assert!(host_matches("ms-*.exe", "ms-teams.exe"));
+    assert!(host_matches("a**b", "ab"));
+    assert!(host_matches("*a*b*", "xxaxxbxx"));
+    assert!(!host_matches("*a*b*", "xxbxxaxx"));
+}
+
+#[test]
+fn test_star_backtracks() {
+    // The first `o` tried for `*o` is not the one that completes the match
+    assert!(host_matches("*o.exe", "foo.exe"));
+    assert!(host_matches("*aab", "aaaab"));
+    assert!(!host_matches("*aab", "aaaba"));
+}
+
+#[test]
+fn test_question_mark_matches_one_character() {
+    assert!(host_matches("excel.ex?", "excel.exe"));
+    assert!(host_matches("?xcel.exe", "excel.exe"));
+    assert!(!host_matches("excel.exe?", "excel.exe"));
+    assert!(!host_matches("?", ""));
+    assert!(host_matches("?*", "a"));
+}
+
+#[test]
+fn test_non_ascii_hosts() {
+    assert!(host_matches("ÉDITEUR.exe", "éditeur.exe"));
+    assert!(host_matches("*မြန်မာ*", "မြန်မာစာ.exe"));
+    assert!(host_matches("?.exe", "က.exe"));
+}
+
+#[test]
+fn test_no_match_uses_default() {
+    assert_eq!(resolve("notepad.exe", NONE, NONE, InputMode::Direct), (InputMode::Direct, None));
+    assert_eq!(resolve("notepad.exe", NONE, NONE, InputMode::Composition), (InputMode::Composition, None));
+    assert_eq!(
+        resolve("notepad.exe", &["excel.exe"], &["chrome.exe"], InputMode::Direct),
+        (InputMode::Direct, None)
+    );
+}
+
+#[test]
+fn test_each_list_sets_its_mode() {
+    assert_eq!(
+        resolve("excel.exe", &["ms-teams.exe", "excel.exe"], NONE, InputMode::Direct),
+        (InputMode::Composition, Some((HostList::Composition, "excel.exe".to_string())))
+    );
+    assert_eq!(
+        resolve("chrome.exe", NONE, &["*chrome*"], InputMode::Composition),
+        (InputMode::Direct, Some((HostList::Direct, "*chrome*".to_string())))
+    );
+}
+
+#[test]
+fn test_entries_matching_the_default_are_still_reported() {
+    assert_eq!(
+        resolve("chrome.exe", NONE, &["chrome.exe"], InputMode::Direct),
+        (InputMode::Direct, Some((HostList::Direct, "chrome.exe".to_string())))
+    );
+}
+
+#[test]
+fn test_reported_pattern_is_the_entry_as_written() {
+    assert_eq!(
+        resolve("excel.exe", &[" Excel.EXE "], NONE, InputMode::Direct).1,
+        Some((HostList::Composition, " Excel.EXE ".to_string()))
+    );
+}
+
+#[test]
+fn test_exact_name_beats_wildcard() {
+    assert_eq!(
+        resolve("chrome.exe", &["*"], &["chrome.exe"], InputMode::Direct),
+        (InputMode::Direct, Some((HostList::Direct, "chrome.exe".to_string())))
+    );
+    assert_eq!(
+        resolve("chrome.exe", &["chrome.exe"], &["*chrome.exe*"], InputMode::Direct),
+        (InputMode::Composition, Some((HostList::Composition, "chrome.exe".to_string())))
+    );
+}
+
+#[test]
+fn test_longer_wildcard_beats_shorter() {
+    assert_eq!(
+        resolve("msedgewebview2.exe", &["*webview*"], &["msedge*"], InputMode::Direct),
+        (InputMode::Composition, Some((HostList::Composition, "*webview*".to_string())))
+    );
+    assert_eq!(
+        resolve("chrome.exe", &["*.exe"], &["*chrome*"], InputMode::Direct),
+        (InputMode::Direct, Some((HostList::Direct, "*chrome*".to_string())))
+    );
+}
+
+#[test]
+fn test_ties_go_to_composition_then_earlier_entry() {
+    assert_eq!(
+        resolve("excel.exe", &["EXCEL.EXE"], &["excel.exe"], InputMode::Direct).1,
+        Some((HostList::Composition, "EXCEL.EXE".to_string()))
+    );
+    assert_eq!(
+        resolve("excel.exe", NONE, &["*cel*", "*exc*"], InputMode::Composition).1,
+        Some((HostList::Direct, "*cel*".to_string()))
+    );
+}
+
+fn published(process: &str, mode: InputMode, matched: Option<(HostList, &str)>) -> EffectiveInputMode {
+    EffectiveInputMode {
+        process_id: 42,
+        process: process.to_string(),
+        resolution: InputModeResolution {
+            mode,
+            matched: matched.map(|(list, pattern)| HostMatch { list, pattern: pattern.to_string() }),
+        },
+        updated_at_ms: 1_700_000_000_000,
+    }
+}
+
+#[test]
+fn test_state_is_empty_until_published() {
+    let state = InputModeState::in_memory();
+    assert_eq!(state.read(), None);
+    assert_eq!(state.sequence(), 0);
+}
+
+#[test]
+fn test_state_round_trip() {
+    let state = InputModeState::in_memory();
+
+    let chrome = published("chrome.exe", InputMode::Direct, Some((HostList::Direct, "*chrome*")));
+    assert!(state.publish(&chrome));
+    assert_eq!(state.read(), Some(chrome));
+
+    let notepad = published("notepad.exe", InputMode::Composition, None);
+    let sequence = state.sequence();
+    assert!(state.publish(&notepad));
+    assert_ne!(state.sequence(), sequence);
+    assert_eq!(state.read(), Some(notepad));
+}
+
+#[test]
+fn test_state_skips_unchanged_modes() {
+    let state = InputModeState::in_memory();
+    let excel = published("excel.exe", InputMode::Composition, Some((HostList::Composition, "excel.exe")));
+    assert!(state.publish(&excel));
+    let sequence = state.sequence();
+
+    let later = EffectiveInputMode { updated_at_ms: excel.updated_at_ms + 1000, ..excel.clone() };
+    assert!(!state.publish(&later));
+    assert_eq!(state.sequence(), sequence);
+    assert_eq!(state.read(), Some(excel));
+}
+
+#[test]
+fn test_state_truncates_long_names() {
+    let state = InputModeState::in_memory();
+    let long = "x".repeat(NAME_CAPACITY + 40);
+    assert!(state.publish(&published(&long, InputMode::Direct, Some((HostList::Direct, &long)))));
+
+    let read = state.read().unwrap();
+    assert_eq!(read.process.len(), NAME_CAPACITY);
+    assert_eq!(read.resolution.matched.unwrap().pattern.len(), NAME_CAPACITY);
+}
+
+#[test]
+fn test_state_keeps_non_ascii_names() {
+    let state = InputModeState::in_memory();
+    let effective = published("မြန်မာ.exe", InputMode::Composition, Some((HostList::Composition, "*မြန်*")));
+    assert!(state.publish(&effective));
+    assert_eq!(state.read(), Some(effective));
+}
+
+#[test]
+fn test_state_concurrent_writers_and_readers() {
+    let state = InputModeState::in_memory();
+    let modes = [
+        published("a.exe", InputMode::Direct, None),
+        published("bbbbbbbbbbbb.exe", InputMode::Composition, Some((HostList::Composition, "b*"))),
+    ];
+
+    std::thread::scope(|scope| {
+        for effective in &modes {
+            let state = &state;
+            scope.spawn(move || {
+                for i in 0..2000 {
+                    let effective = EffectiveInputMode { process_id: i, ..effective.clone() };
+                    state.publish(&effective);
+                }
+            });
+        }
+        // Readers never see a mix of two writes
+        for _ in 0..2000 {
+            if let Some(read) = state.read() {
+                let expected = modes.iter().find(|m| m.process == read.process).expect("torn process name");
+                assert_eq!(read.resolution, expected.resolution);
+            }
+        }
+    });
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+#[test]
+fn test_ffi_resolve() {
+    let process = wide("Chrome.exe");
+    let composition = [wide("excel.exe"), wide("*")];
+    let direct = [wide("*chrome*")];
+    let composition_ptrs: Vec<*const u16> = composition.iter().map(|s| s.as_ptr()).collect();
+    let direct_ptrs: Vec<*const u16> = direct.iter().map(|s| s.as_ptr()).collect();
+
+    let mode = keymagic_resolve_input_mode_w(process.as_ptr(), composition_ptrs.as_ptr(), 2, direct_ptrs.as_ptr(), 1, 1);
+    assert_eq!(mode, 0);
+    let mode = keymagic_resolve_input_mode_w(process.as_ptr(), composition_ptrs.as_ptr(), 2, ptr::null(), 0, 0);
+    assert_eq!(mode, 1);
+    let mode = keymagic_resolve_input_mode_w(process.as_ptr(), ptr::null(), 0, ptr::null(), 0, 1);
+    assert_eq!(mode, 1);
+    let mode = keymagic_resolve_input_mode_w(ptr::null(), composition_ptrs.as_ptr(), 2, ptr::null(), 0, 0);
+    assert_eq!(mode, 0);
+}
+
+#[test]
+fn test_shortcut_entries() {
+    let entry = HostShortcuts::parse(" chrome.exe = key_c, VK_KEY_J ,").unwrap();
+    assert_eq!(entry.pattern, "chrome.exe");
+    assert_eq!(entry.keys, vec![VirtualKey::KeyC, VirtualKey::KeyJ]);
+    assert_eq!(entry.to_entry(), "chrome.exe=VK_KEY_C,VK_KEY_J");
+    assert_eq!(HostShortcuts::parse(&entry.to_entry()).unwrap(), entry);
+
+    assert!(HostShortcuts::parse("a=b=VK_KEY_C").is_ok_and(|entry| entry.pattern == "a=b"));
+    assert!(matches!(HostShortcuts::parse("chrome.exe"), Err(Error::ParseError(_))));
+    assert!(matches!(HostShortcuts::parse(" =VK_KEY_C"), Err(Error::ParseError(_))));
+    assert!(matches!(HostShortcuts::parse("chrome.exe=VK_NOPE"), Err(Error::UnknownVirtualKey(_))));
+}
+
+#[test]
+fn test_shortcut_keys_of_most_specific_entry() {
+    let entries: Vec<HostShortcuts> = ["*=VK_KEY_J", "chrome.exe=VK_KEY_C", "*.exe=VK_KEY_K"]
+        .iter()
+        .map(|entry| HostShortcuts::parse(entry).unwrap())
+        .collect();
+    assert_eq!(host_shortcut_keys("Chrome.exe", &entries), &[VirtualKey::KeyC]);
+    assert_eq!(host_shortcut_keys("code.exe", &entries), &[VirtualKey::KeyK]);
+    assert_eq!(host_shortcut_keys("com.google.Chrome", &entries), &[VirtualKey::KeyJ]);
+    assert!(host_shortcut_keys("chrome", &entries[1..]).is_empty());
+}
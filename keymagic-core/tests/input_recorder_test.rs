@@ -0,0 +1,306 @@
+//! Tests for the input event recorder ring
+#![cfg(feature = "ffi")]
+
+use keymagic_core::ffi::*;
+use keymagic_core::recorder::*;
+use std::sync::Arc;
+
+fn key(vk: u16, text: &str) -> KeyEventRecord<'_> {
+    KeyEventRecord {
+        process_hash: hash_process_name("notepad.exe"),
+        vk,
+        modifiers: MOD_SHIFT,
+        action_type: 1,
+        is_processed: true,
+        delete_count: 0,
+        composing_text: text,
+    }
+}
+
+#[test]
+fn test_nothing_is_recorded_until_started() {
+    let recorder = InputRecorder::in_memory(DEFAULT_CAPACITY);
+    assert!(!recorder.is_recording());
+    assert_eq!(recorder.record(&key(0x4B, "က")), None);
+
+    recorder.start(false);
+    assert_eq!(recorder.record(&key(0x4B, "က")), Some(1));
+    recorder.stop();
+    assert_eq!(recorder.record(&key(0x4B, "က")), None);
+
+    // Stopping keeps what was recorded
+    let snapshot = recorder.snapshot();
+    assert!(!snapshot.recording);
+    assert_eq!(snapshot.records.len(), 1);
+}
+
+#[test]
+fn test_text_is_only_kept_in_verbose_mode() {
+    let recorder = InputRecorder::in_memory(DEFAULT_CAPACITY);
+    recorder.start(false);
+    recorder.record(&key(0x4B, "ကာ"));
+    let record = &recorder.snapshot().records[0];
+    assert_eq!(record.text, None);
+    assert_eq!(record.composing_len, 2);
+    assert_eq!(record.vk, 0x4B);
+    assert_eq!(record.modifiers, MOD_SHIFT);
+    assert_eq!(record.action_type, 1);
+    assert!(record.is_processed);
+    assert_eq!(record.process_hash, hash_process_name("NOTEPAD.EXE"));
+
+    recorder.clear();
+    recorder.start(true);
+    let long = "မြန်မာစာကိုရိုက်နေပါသည်";
+    recorder.record(&key(0x4B, "ကာ"));
+    recorder.record(&key(0x4B, long));
+    let snapshot = recorder.snapshot();
+    assert!(snapshot.verbose);
+    assert_eq!(snapshot.records[0].text.as_deref(), Some("ကာ"));
+    let truncated: Vec<u16> = long.encode_utf16().take(TEXT_CAPACITY).collect();
+    assert_eq!(snapshot.records[1].text, Some(String::from_utf16(&truncated).unwrap()));
+    assert_eq!(snapshot.records[1].composing_len as usize, long.encode_utf16().count());
+}
+
+#[test]
+fn test_wraparound_keeps_newest_records() {
+    let recorder = InputRecorder::in_memory(MIN_CAPACITY);
+    assert_eq!(recorder.capacity(), MIN_CAPACITY);
+    recorder.start(false);
+    for i in 0..(MIN_CAPACITY as u16 * 3 + 5) {
+        recorder.record(&key(i, ""));
+    }
+
+    let snapshot = recorder.snapshot();
+    let total = MIN_CAPACITY as u64 * 3 + 5;
+    assert_eq!(snapshot.total_recorded, total);
+    assert_eq!(snapshot.records.len(), MIN_CAPACITY);
+    assert_eq!(snapshot.dropped, total - MIN_CAPACITY as u64);
+    let seqs: Vec<u64> = snapshot.records.iter().map(|record| record.seq).collect();
+    let expected: Vec<u64> = (total - MIN_CAPACITY as u64 + 1..=total).collect();
+    assert_eq!(seqs, expected);
+    // Each record still holds the event written under its number
+    assert!(snapshot.records.iter().all(|record| record.vk as u64 == record.seq - 1));
+}
+
+#[test]
+fn test_capacity_is_clamped() {
+    assert_eq!(InputRecorder::in_memory(0).capacity(), MIN_CAPACITY);
+    assert_eq!(InputRecorder::in_memory(usize::MAX).capacity(), MAX_CAPACITY);
+    assert_eq!(region_size(DEFAULT_CAPACITY), 32 + PROCESS_CAPACITY * 64 + DEFAULT_CAPACITY * 64);
+}
+
+#[test]
+fn test_concurrent_writers_and_reader() {
+    const THREADS: u64 = 8;
+    const PER_THREAD: u64 = 2000;
+
+    let recorder = Arc::new(InputRecorder::in_memory(256));
+    recorder.start(false);
+
+    let writers: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            let recorder = Arc::clone(&recorder);
+            std::thread::spawn(move || {
+                for i in 0..PER_THREAD {
+                    // Fields derived from each other so torn records show up
+                    let vk = (thread * PER_THREAD + i) as u16;
+                    let event = KeyEventRecord { process_hash: vk as u32 * 3, delete_count: vk, ..key(vk, "") };
+                    recorder.record(&event);
+                }
+            })
+        })
+        .collect();
+
+    let reader = {
+        let recorder = Arc::clone(&recorder);
+        std::thread::spawn(move || {
+            for _ in 0..50 {
+                for record in recorder.snapshot().records {
+                    assert_eq!(record.process_hash, record.vk as u32 * 3);
+                    assert_eq!(record.delete_count, record.vk);
+                }
+            }
+        })
+    };
+
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    reader.join().unwrap();
+
+    let snapshot = recorder.snapshot();
+    assert_eq!(snapshot.total_recorded, THREADS * PER_THREAD);
+    // Writers that collide on a slot drop their record instead of mixing
+    // it with another, so a few slots may be empty or hold an older record
+    assert!(snapshot.records.len() <= 256);
+    assert!(snapshot.records.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+    assert_eq!(snapshot.dropped, THREADS * PER_THREAD - snapshot.records.len() as u64);
+}
+
+#[test]
+fn test_display_name_is_redacted_stem() {
+    assert_eq!(display_name("Telegram.exe"), "Telegram");
+    assert_eq!(display_name("C:\\Users\\Aung\\AppData\\Telegram Desktop\\Telegram.EXE"), "Telegram");
+    assert_eq!(display_name("/usr/bin/gedit"), "gedit");
+    assert_eq!(display_name("my app (2).exe"), "my_app__2_");
+    assert_eq!(display_name("မြန်မာ.exe"), "______");
+    assert_eq!(display_name("averyveryverylongexecutablename.exe").len(), NAME_CAPACITY);
+    assert_eq!(display_name("a.exe.bak"), "a.exe.bak");
+}
+
+#[test]
+fn test_processes_are_noted_while_recording() {
+    let recorder = InputRecorder::in_memory(MIN_CAPACITY);
+    let hash = recorder.note_process("notepad.exe", false);
+    assert_eq!(hash, hash_process_name("notepad.exe"));
+    assert!(recorder.snapshot().processes.is_empty());
+
+    recorder.start(false);
+    recorder.note_process("notepad.exe", false);
+    recorder.note_process("Telegram.exe", true);
+    recorder.note_process("NOTEPAD.EXE", false);
+    let processes = recorder.snapshot().processes;
+    assert_eq!(processes.len(), 2);
+    assert_eq!(processes[0].name, "notepad");
+    assert_eq!(processes[0].process_hash, hash);
+    assert!(!processes[0].is_uwp);
+    assert_eq!(processes[1].name, "Telegram");
+    assert!(processes[1].is_uwp);
+    assert!(processes[0].first_seen_ms <= processes[1].first_seen_ms);
+
+    // The table belongs to the session
+    recorder.clear();
+    assert!(recorder.snapshot().processes.is_empty());
+}
+
+#[test]
+fn test_process_table_evicts_least_recently_seen() {
+    let recorder = InputRecorder::in_memory(MIN_CAPACITY);
+    recorder.start(false);
+    for i in 0..PROCESS_CAPACITY {
+        recorder.note_process(&format!("app{}.exe", i), false);
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    // app0 is seen again, so app1 is the least recent
+    recorder.note_process("app0.exe", false);
+    std::thread::sleep(std::time::Duration::from_millis(1));
+    recorder.note_process("late.exe", false);
+
+    let names: Vec<String> = recorder.snapshot().processes.into_iter().map(|process| process.name).collect();
+    assert_eq!(names.len(), PROCESS_CAPACITY);
+    assert!(names.contains(&"app0".to_string()));
+    assert!(!names.contains(&"app1".to_string()));
+    assert_eq!(names.last().map(String::as_str), Some("late"));
+}
+
+#[test]
+fn test_process_summary() {
+    let recorder = InputRecorder::in_memory(MIN_CAPACITY);
+    recorder.start(false);
+    let telegram = recorder.note_process("Telegram.exe", false);
+    let notepad = recorder.note_process("notepad.exe", false);
+    for (process_hash, action_type, is_processed) in [
+        (telegram, 1, true),
+        (telegram, 3, true),
+        (notepad, 1, true),
+        (telegram, 0, false),
+        (telegram, 2, true),
+        (7, 9, true),
+    ] {
+        recorder.record(&KeyEventRecord { process_hash, action_type, is_processed, ..key(0x4B, "") });
+    }
+
+    let summary = recorder.snapshot().process_summary();
+    assert_eq!(summary.len(), 3);
+    assert_eq!(summary[0].name.as_deref(), Some("Telegram"));
+    assert_eq!(summary[0].events, 4);
+    assert_eq!(summary[0].processed, 3);
+    assert_eq!(summary[0].actions, ActionCounts { none: 1, insert: 1, delete: 1, delete_and_insert: 1, other: 0 });
+    assert!(summary[0].first_seen_ms.is_some());
+    // Ties go to the process seen first
+    assert_eq!(summary[1].name.as_deref(), Some("notepad"));
+    assert_eq!(summary[1].events, 1);
+    // A hash without a table entry is still counted
+    assert_eq!(summary[2].process_hash, 7);
+    assert_eq!(summary[2].name, None);
+    assert_eq!(summary[2].actions.other, 1);
+}
+
+#[test]
+fn test_summary_of_synthetic_records() {
+    let record = |seq, process_hash, action_type| InputRecord {
+        seq,
+        timestamp_ms: seq * 10,
+        process_hash,
+        vk: 0x41,
+        modifiers: 0,
+        action_type,
+        is_processed: true,
+        delete_count: 0,
+        composing_len: 0,
+        text: None,
+    };
+    let processes = [ProcessEntry { process_hash: 2, first_seen_ms: 5, name: "b".to_string(), is_uwp: true }];
+    let records = [record(1, 1, 1), record(2, 2, 1), record(3, 2, 2)];
+
+    let summary = summarize_processes(&records, &processes);
+    assert_eq!(summary.iter().map(|s| s.process_hash).collect::<Vec<_>>(), vec![2, 1]);
+    assert!(summary[0].is_uwp);
+    assert_eq!(summary[0].last_event_ms, 30);
+    assert_eq!(summary[0].first_seen_ms, Some(5));
+    assert!(summarize_processes(&[], &processes).is_empty());
+}
+
+#[test]
+fn test_concurrent_process_notes() {
+    let recorder = Arc::new(InputRecorder::in_memory(MIN_CAPACITY));
+    recorder.start(false);
+    let writers: Vec<_> = (0..8)
+        .map(|thread| {
+            let recorder = Arc::clone(&recorder);
+            std::thread::spawn(move || {
+                for i in 0..500 {
+                    recorder.note_process(&format!("p{}.exe", (thread * 7 + i) % 48), thread % 2 == 0);
+                }
+            })
+        })
+        .collect();
+    for _ in 0..50 {
+        for process in recorder.snapshot().processes {
+            // Entries are never torn: the name belongs to the hash
+            assert_eq!(process.process_hash, hash_process_name(&format!("{}.exe", process.name)));
+        }
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    let processes = recorder.snapshot().processes;
+    assert!(processes.len() <= PROCESS_CAPACITY);
+    let mut hashes: Vec<u32> = processes.iter().map(|process| process.process_hash).collect();
+    hashes.dedup();
+    assert_eq!(hashes.len(), processes.len());
+}
+
+#[test]
+fn test_ffi_records_nothing_without_shared_ring() {
+    let recorder = keymagic_recorder_open();
+    assert!(!recorder.is_null());
+    let output = ProcessKeyOutput {
+        action_type: 1,
+        text: std::ptr::null_mut(),
+        delete_count: 0,
+        composing_text: std::ptr::null_mut(),
+        is_processed: 1,
+        delete_utf16_count: 0,
+        composing_caret_utf16: 0,
+        notification: std::ptr::null_mut(),
+        delete_clamped: 0,
+    };
+    let name: Vec<u16> = "notepad.exe".encode_utf16().chain([0]).collect();
+    // The GUI never created the ring here, so there is nothing to write to
+    if !cfg!(windows) {
+        assert_eq!(keymagic_recorder_record(recorder, name.as_ptr(), 0x4B, 0, 0, 0, 0, &output), 0);
+    }
+    assert_eq!(keymagic_recorder_record(recorder, name.as_ptr(), 0x4B, 0, 0, 0, 0, std::ptr::null()), 0);
+    keymagic_recorder_free(recorder);
+}
@@ -0,0 +1,166 @@
+//! Tests for key events and their compact text form
+#![cfg(feature = "host-ipc")]
+
+use keymagic_core::analysis::{typed_key, us_character};
+use keymagic_core::key_event::*;
+use keymagic_core::{KeyInput, ModifierState, VirtualKey};
+
+fn parse(text: &str) -> Vec<KeyEventDto> {
+    parse_key_sequence(text).unwrap()
+}
+
+#[test]
+fn test_plain_characters_and_names() {
+    assert_eq!(parse("ak"), vec![KeyEventDto::typed('a'), KeyEventDto::typed('k')]);
+    assert_eq!(parse("က "), vec![KeyEventDto::typed('က'), KeyEventDto::typed(' ')]);
+    assert_eq!(parse("{BKSP}"), vec![KeyEventDto::key(VirtualKey::Back)]);
+    assert_eq!(parse("{vk_return}{KEY_A}"), vec![KeyEventDto::key(VirtualKey::Return), KeyEventDto::key(VirtualKey::KeyA)]);
+    assert_eq!(parse("{<}{}}{{}{>}"), "<}{>".chars().map(KeyEventDto::typed).collect::<Vec<_>>());
+    assert_eq!(parse(""), vec![]);
+}
+
+#[test]
+fn test_chords() {
+    let events = parse("<Shift+ka>");
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].vk, Some(VkRef::Name("VK_KEY_K".to_string())));
+    assert_eq!(events[0].character, Some('K'));
+    assert!(events[0].shift);
+    assert_eq!(events[1].character, Some('A'));
+
+    let event: KeyEventDto = "<Ctrl+Alt+{DEL}>".parse().unwrap();
+    assert_eq!(event.vk, Some(VkRef::Name("VK_DELETE".to_string())));
+    assert!(event.ctrl && event.alt && !event.shift);
+    assert_eq!(event.character, None);
+
+    // Ctrl types no character, Caps Lock only changes letters
+    assert_eq!("<Ctrl+s>".parse::<KeyEventDto>().unwrap().character, None);
+    assert_eq!("<Caps+a>".parse::<KeyEventDto>().unwrap().character, Some('A'));
+    assert_eq!("<Caps+1>".parse::<KeyEventDto>().unwrap().character, Some('1'));
+    assert_eq!("<Shift+Caps+a>".parse::<KeyEventDto>().unwrap().character, Some('a'));
+
+    // Characters that need Shift keep it
+    let event: KeyEventDto = "<Ctrl+!>".parse().unwrap();
+    assert_eq!(event.vk, Some(VkRef::Name("VK_KEY_1".to_string())));
+    assert!(event.shift && event.ctrl);
+
+    let event: KeyEventDto = "<Shift++>".parse().unwrap();
+    assert_eq!(event.character, Some('+'));
+    assert_eq!(event.vk, Some(VkRef::Name("VK_OEM_PLUS".to_string())));
+}
+
+#[test]
+fn test_parse_errors() {
+    let unknown = parse_key_sequence("a{NOPE}").unwrap_err();
+    assert_eq!(unknown, KeyEventError::UnknownKeyName("NOPE".to_string()));
+    assert_eq!(unknown.to_string(), "Unknown key name: NOPE");
+
+    let cases = [("<Shift+k", 0), ("ab>", 2), ("{BKSP", 0), ("<Super+k>", 1), ("<Shift+>", 7), ("{}", 0), ("x}", 1)];
+    for (text, expected) in cases {
+        match parse_key_sequence(text) {
+            Err(KeyEventError::Syntax { offset, .. }) => assert_eq!(offset, expected, "{}", text),
+            other => panic!("{}: {:?}", text, other),
+        }
+    }
+    assert_eq!(parse_key_sequence("<Shift+က>").unwrap_err(), KeyEventError::NotOnUsLayout('က'));
+    assert!(matches!("ab".parse::<KeyEventDto>(), Err(KeyEventError::Syntax { .. })));
+}
+
+#[test]
+fn test_key_input_conversion() {
+    // Characters are typed with the key and Shift state of the US layout
+    let input = KeyEventDto::typed('K').to_key_input().unwrap();
+    assert_eq!(input, typed_key('K'));
+    assert!(input.modifiers.shift);
+
+    let input = KeyEventDto::typed('က').to_key_input().unwrap();
+    assert_eq!(input, KeyInput::from_char('က'));
+
+    let event = KeyEventDto { vk: Some(VkRef::Code(0x08)), ctrl: true, ..KeyEventDto::default() };
+    let input = event.to_key_input().unwrap();
+    assert_eq!(input, KeyInput::new(VirtualKey::Back as u16, ModifierState::new(false, true, false, false), None));
+
+    let back = KeyEventDto::from_key_input(&input).unwrap();
+    assert_eq!(back.vk, Some(VkRef::Name("VK_BACK".to_string())));
+    assert_eq!(back.to_key_input().unwrap(), input);
+
+    for ch in "aZ9)~ ကာ".chars() {
+        let input = typed_key(ch);
+        assert_eq!(KeyEventDto::from_key_input(&input).unwrap().to_key_input().unwrap(), input, "{:?}", ch);
+    }
+
+    let unknown = KeyEventDto { vk: Some(VkRef::Code(0xFFFF)), ..KeyEventDto::default() };
+    assert_eq!(unknown.to_key_input().unwrap_err(), KeyEventError::UnknownKeyCode(0xFFFF));
+    let unknown = KeyEventDto { vk: Some(VkRef::Name("VK_NOPE".to_string())), ..KeyEventDto::default() };
+    assert_eq!(unknown.to_key_input().unwrap_err(), KeyEventError::UnknownKeyName("VK_NOPE".to_string()));
+    assert_eq!(KeyEventDto::default().to_key_input().unwrap_err(), KeyEventError::Empty);
+    assert_eq!(KeyEventDto::from_key_input(&KeyInput::new(0, ModifierState::default(), None)).unwrap_err(), KeyEventError::Empty);
+}
+
+#[test]
+fn test_us_character_is_added_to_keys() {
+    let event = KeyEventDto { vk: Some(VkRef::Code(0x4B)), shift: true, ..KeyEventDto::default() };
+    assert_eq!(event.with_us_character().unwrap().character, Some('K'));
+
+    let event = KeyEventDto { vk: Some(VkRef::Code(0x4B)), ctrl: true, ..KeyEventDto::default() };
+    assert_eq!(event.with_us_character().unwrap().character, None);
+    assert_eq!(KeyEventDto::key(VirtualKey::Back).with_us_character().unwrap().character, None);
+    assert_eq!(KeyEventDto::typed('x').with_us_character().unwrap().character, Some('x'));
+}
+
+#[test]
+fn test_text_round_trip() {
+    for text in ["a", "K", "က", " ", "{<}", "{BACK}", "{KEY_A}", "<Shift+Ctrl+1>", "<Ctrl+s>", "<Ctrl+Alt+{DELETE}>", "<Shift+{KEY_Z}>", "<Caps+a>"] {
+        let event: KeyEventDto = text.parse().unwrap();
+        assert_eq!(event.to_string(), text);
+    }
+
+    // Equivalent spellings are written in the shortest form
+    let events = parse("<Shift+ka>{bksp}{VK_SPACE}");
+    assert_eq!(format_key_sequence(&events), "KA{BACK}{SPACE}");
+    assert_eq!(KeyEventDto::from_key_input(&typed_key('K')).unwrap().to_string(), "K");
+    assert_eq!(KeyEventDto { vk: Some(VkRef::Code(0x41)), character: Some('a'), ..KeyEventDto::default() }.to_string(), "a");
+
+    let events = parse("ka{BKSP}<Shift+{KEY_Z}>{{}");
+    assert_eq!(parse(&format_key_sequence(&events)), events);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_serialization() {
+    use serde_json::json;
+
+    let event: KeyEventDto = "<Shift+k>".parse().unwrap();
+    let value = serde_json::to_value(&event).unwrap();
+    assert_eq!(value, json!({ "vk": "VK_KEY_K", "character": "K", "shift": true }));
+    assert_eq!(serde_json::from_value::<KeyEventDto>(value).unwrap(), event);
+
+    let event: KeyEventDto =
+        serde_json::from_value(json!({ "vk": 8, "ctrl": true, "repeat": true, "timestamp_ms": 1500 })).unwrap();
+    assert_eq!(event.vk, Some(VkRef::Code(8)));
+    assert!(event.ctrl && event.repeat);
+    assert_eq!(event.timestamp_ms, Some(1500));
+    assert_eq!(serde_json::to_value(&event).unwrap(), json!({ "vk": 8, "ctrl": true, "repeat": true, "timestamp_ms": 1500 }));
+
+    assert_eq!(serde_json::from_value::<KeyEventDto>(json!({ "character": "က" })).unwrap(), KeyEventDto::typed('က'));
+    assert!(serde_json::from_value::<KeyEventDto>(json!({ "character": "ab" })).is_err());
+    assert!(serde_json::from_value::<KeyEventDto>(json!({ "vk": true })).is_err());
+}
+
+#[test]
+fn test_us_character() {
+    assert_eq!(us_character(VirtualKey::KeyA, false, false), Some('a'));
+    assert_eq!(us_character(VirtualKey::KeyA, true, true), Some('a'));
+    assert_eq!(us_character(VirtualKey::Key2, true, false), Some('@'));
+    assert_eq!(us_character(VirtualKey::Key2, false, true), Some('2'));
+    assert_eq!(us_character(VirtualKey::Oem7, true, false), Some('"'));
+    assert_eq!(us_character(VirtualKey::Space, true, false), Some(' '));
+    assert_eq!(us_character(VirtualKey::Back, false, false), None);
+
+    // Every typed character comes back from its key
+    for ch in '!'..='~' {
+        let input = typed_key(ch);
+        let key = KeyEventDto::from_key_input(&input).unwrap().virtual_key().unwrap().unwrap();
+        assert_eq!(us_character(key, input.modifiers.shift, false), Some(ch), "{:?}", ch);
+    }
+}
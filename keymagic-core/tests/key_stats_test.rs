@@ -0,0 +1,217 @@
+//! Per-keyboard key press counts for the layout heatmap
+#![cfg(feature = "host-ipc")]
+
+use keymagic_core::key_stats::*;
+
+const VK_A: u8 = 0x41;
+const VK_SPACE: u8 = 0x20;
+
+fn day(date: &str) -> u32 {
+    parse_day(date).unwrap()
+}
+
+fn counts(pairs: &[(u8, u64)]) -> Vec<u64> {
+    let mut counts = vec![0; KEY_COUNT];
+    for &(vk, count) in pairs {
+        counts[vk as usize] = count;
+    }
+    counts
+}
+
+#[test]
+fn test_nothing_is_counted_while_off() {
+    let stats = KeyStats::in_memory();
+    assert!(!stats.is_enabled());
+    let keyboard = hash_keyboard_id("myanmar3");
+    for vk in 0..=255u8 {
+        assert!(!stats.record(keyboard, 20_000, vk));
+    }
+    assert!(stats.drain(u32::MAX).is_empty());
+
+    // Turning it off drops what was not drained
+    stats.set_enabled(true);
+    assert!(stats.record(keyboard, 20_000, VK_A));
+    stats.set_enabled(false);
+    assert!(!stats.record(keyboard, 20_000, VK_A));
+    assert!(stats.drain(u32::MAX).is_empty());
+}
+
+#[test]
+fn test_nothing_is_counted_while_suppressed() {
+    let stats = KeyStats::in_memory();
+    stats.set_enabled(true);
+    stats.set_suppressed(true);
+    assert!(!stats.record(1, 20_000, VK_A));
+    assert!(stats.drain(u32::MAX).is_empty());
+
+    stats.set_suppressed(false);
+    assert!(stats.record(1, 20_000, VK_A));
+    assert_eq!(stats.drain(u32::MAX).len(), 1);
+}
+
+#[test]
+fn test_drain_takes_counts_per_keyboard_and_day() {
+    let stats = KeyStats::in_memory();
+    stats.set_enabled(true);
+    let (zawgyi, unicode) = (hash_keyboard_id("Zawgyi"), hash_keyboard_id("myanmar3"));
+    for _ in 0..3 {
+        stats.record(zawgyi, 100, VK_A);
+    }
+    stats.record(zawgyi, 100, VK_SPACE);
+    stats.record(unicode, 100, VK_A);
+    stats.record(zawgyi, 101, VK_A);
+
+    let mut expected = vec![
+        DailyCounts { keyboard_hash: zawgyi, day: 100, counts: counts(&[(VK_A, 3), (VK_SPACE, 1)]) },
+        DailyCounts { keyboard_hash: zawgyi, day: 101, counts: counts(&[(VK_A, 1)]) },
+        DailyCounts { keyboard_hash: unicode, day: 100, counts: counts(&[(VK_A, 1)]) },
+    ];
+    // Drained in order of keyboard hash, then day
+    expected.sort_by_key(|daily| (daily.keyboard_hash, daily.day));
+    assert_eq!(stats.drain(101), expected);
+    assert!(stats.drain(101).is_empty());
+}
+
+#[test]
+fn test_drain_frees_the_slots_of_past_days() {
+    let stats = KeyStats::in_memory();
+    stats.set_enabled(true);
+    for keyboard in 0..SLOT_COUNT as u32 {
+        assert!(stats.record(keyboard, 100, VK_A));
+    }
+    // Every slot is taken
+    assert!(!stats.record(99, 100, VK_A));
+
+    // Draining on the same day keeps the slots
+    assert_eq!(stats.drain(100).len(), SLOT_COUNT);
+    assert!(!stats.record(99, 100, VK_A));
+
+    assert!(stats.drain(101).is_empty());
+    assert!(stats.record(99, 101, VK_A));
+    assert_eq!(stats.drain(101), vec![DailyCounts { keyboard_hash: 99, day: 101, counts: counts(&[(VK_A, 1)]) }]);
+}
+
+#[test]
+fn test_keyboard_ids_hash_without_case() {
+    assert_eq!(hash_keyboard_id("Myanmar3"), hash_keyboard_id("myanmar3"));
+    assert_ne!(hash_keyboard_id("myanmar3"), hash_keyboard_id("zawgyi"));
+}
+
+#[test]
+fn test_dates() {
+    assert_eq!(day("1970-01-01"), 0);
+    assert_eq!(day("2026-10-16"), 20_742);
+    assert_eq!(format_day(20_742), "2026-10-16");
+    assert_eq!(day_of(20_742 * 86_400_000 + 86_399_999), 20_742);
+    for date in ["2024-02-29", "2000-02-29", "2025-12-31", "2026-01-01"] {
+        assert_eq!(format_day(day(date)), date);
+    }
+    for date in ["2025-02-29", "1900-02-29", "2026-13-01", "2026-04-31", "2026-00-10", "1969-12-31", "today", "2026-10"] {
+        assert_eq!(parse_day(date), None, "{}", date);
+    }
+}
+
+#[test]
+fn test_month_buckets_cover_whole_months() {
+    let feb = Period::month_of(day("2024-02-10"));
+    assert_eq!(feb, Period::month_of(day("2024-02-29")));
+    assert_ne!(feb, Period::month_of(day("2024-03-01")));
+    assert_eq!(feb.days(), (day("2024-02-01"), day("2024-02-29")));
+    assert_eq!(feb.label(), "2024-02");
+
+    let dec = Period::month_of(day("2025-12-31"));
+    assert_eq!(dec.days(), (day("2025-12-01"), day("2025-12-31")));
+    assert_eq!(Period::Day(day("2025-12-31")).label(), "2025-12-31");
+    assert_eq!(Period::month_of(0), Period::Month(0));
+}
+
+#[test]
+fn test_heatmap_sums_days_in_range() {
+    let mut store = KeyStatsStore::new();
+    let keyboard = hash_keyboard_id("myanmar3");
+    store.add(keyboard, Period::Day(day("2026-10-01")), &counts(&[(VK_A, 5), (VK_SPACE, 2)]));
+    store.add(keyboard, Period::Day(day("2026-10-02")), &counts(&[(VK_A, 1)]));
+    store.add(keyboard, Period::Day(day("2026-10-02")), &counts(&[(VK_SPACE, 4)]));
+    store.add(keyboard, Period::Day(day("2026-10-05")), &counts(&[(VK_A, 100)]));
+    store.add(hash_keyboard_id("zawgyi"), Period::Day(day("2026-10-02")), &counts(&[(VK_A, 7)]));
+
+    let heatmap = store.heatmap(keyboard, day("2026-10-01"), day("2026-10-02"));
+    assert_eq!(heatmap.counts, counts(&[(VK_A, 6), (VK_SPACE, 6)]));
+    assert_eq!(heatmap.total, 12);
+    assert_eq!((heatmap.from_day, heatmap.to_day), (day("2026-10-01"), day("2026-10-02")));
+    assert_eq!(heatmap.keys(), vec![(VK_SPACE, 6), (VK_A, 6)]);
+
+    let all = store.heatmap(keyboard, 0, u32::MAX);
+    assert_eq!(all.total, 112);
+    assert_eq!(all.keys()[0], (VK_A, 106));
+
+    assert_eq!(store.heatmap(keyboard, day("2026-10-03"), day("2026-10-04")).total, 0);
+    assert_eq!(store.heatmap(hash_keyboard_id("other"), 0, u32::MAX).total, 0);
+}
+
+#[test]
+fn test_compaction_merges_old_days_into_months() {
+    let mut store = KeyStatsStore::new();
+    let today = day("2026-10-16");
+    store.add(1, Period::Day(day("2026-08-30")), &counts(&[(VK_A, 1)]));
+    store.add(1, Period::Day(day("2026-08-31")), &counts(&[(VK_A, 2)]));
+    store.add(1, Period::Day(day("2026-09-01")), &counts(&[(VK_SPACE, 3)]));
+    // 30 days old stays a day, 31 is merged
+    store.add(1, Period::Day(today - DAILY_RETENTION_DAYS), &counts(&[(VK_A, 4)]));
+    store.add(1, Period::Day(today - DAILY_RETENTION_DAYS - 1), &counts(&[(VK_A, 8)]));
+    store.add(2, Period::Day(day("2026-08-31")), &counts(&[(VK_A, 16)]));
+    let before = store.heatmap(1, 0, u32::MAX);
+
+    assert_eq!(store.compact(today), 5);
+    assert_eq!(store.compact(today), 0);
+    let periods: Vec<(u32, Period)> = store.buckets().iter().map(|bucket| (bucket.keyboard_hash, bucket.period)).collect();
+    assert_eq!(
+        periods,
+        vec![
+            (1, Period::Day(today - DAILY_RETENTION_DAYS)),
+            (1, Period::month_of(day("2026-08-01"))),
+            (1, Period::month_of(day("2026-09-01"))),
+            (2, Period::month_of(day("2026-08-01"))),
+        ]
+    );
+    // Totals are kept
+    assert_eq!(store.heatmap(1, 0, u32::MAX).counts, before.counts);
+
+    // A month overlapping the range counts whole, and widens it
+    let heatmap = store.heatmap(1, day("2026-08-31"), day("2026-08-31"));
+    assert_eq!(heatmap.counts, counts(&[(VK_A, 3)]));
+    assert_eq!((heatmap.from_day, heatmap.to_day), (day("2026-08-01"), day("2026-08-31")));
+}
+
+#[test]
+fn test_store_merges_drained_counts_and_round_trips() {
+    let stats = KeyStats::in_memory();
+    stats.set_enabled(true);
+    let mut store = KeyStatsStore::new();
+    for round in 0..3 {
+        for _ in 0..=round {
+            stats.record(7, 200, VK_A);
+        }
+        store.merge(&stats.drain(200));
+    }
+    assert_eq!(store.heatmap(7, 200, 200).counts, counts(&[(VK_A, 6)]));
+    assert_eq!(KeyStatsStore::from_buckets(store.buckets()), store);
+}
+
+#[test]
+fn test_csv_has_a_row_per_period_and_key() {
+    let mut store = KeyStatsStore::new();
+    store.add(1, Period::month_of(day("2026-08-01")), &counts(&[(VK_A, 3)]));
+    store.add(1, Period::Day(day("2026-10-02")), &counts(&[(VK_SPACE, 2), (VK_A, 1), (0xFF, 1)]));
+    store.add(2, Period::Day(day("2026-10-02")), &counts(&[(VK_A, 9)]));
+
+    assert_eq!(
+        store.to_csv(1, 0, u32::MAX),
+        "period,vk,key,count\n\
+         2026-10-02,32,VK_SPACE,2\n\
+         2026-10-02,65,VK_KEY_A,1\n\
+         2026-10-02,255,0xFF,1\n\
+         2026-08,65,VK_KEY_A,3\n"
+    );
+    assert_eq!(store.to_csv(1, day("2026-10-03"), u32::MAX), "period,vk,key,count\n");
+}
@@ -0,0 +1,86 @@
+//! Content hashes of keyboard files and the reload decision of hosts
+
+use keymagic_core::km2::{content_hash, needs_reload};
+
+mod common;
+use common::*;
+
+#[test]
+fn test_hash_is_stable_lowercase_sha256() {
+    assert_eq!(content_hash(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+    // Writing the same keyboard again gives the same file, and the same hash
+    let first = create_km2_binary(&create_basic_km2()).unwrap();
+    let second = create_km2_binary(&create_basic_km2()).unwrap();
+    assert_eq!(content_hash(&first), content_hash(&second));
+
+    let mut km2 = create_basic_km2();
+    add_info_text(&mut km2, "name", "Updated");
+    assert_ne!(content_hash(&create_km2_binary(&km2).unwrap()), content_hash(&first));
+}
+
+#[test]
+fn test_reload_decision() {
+    let hash = content_hash(b"keyboard");
+    let other = content_hash(b"updated keyboard");
+
+    assert!(!needs_reload(Some(&hash), &hash));
+    assert!(!needs_reload(Some(&hash), &hash.to_uppercase()));
+    assert!(needs_reload(Some(&hash), &other));
+
+    // Unknown expected hashes never reload
+    assert!(!needs_reload(Some(&hash), ""));
+    assert!(!needs_reload(None, "  "));
+    // Nothing loaded to compare
+    assert!(needs_reload(None, &hash));
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn test_ffi_loaded_hash() {
+    use keymagic_core::ffi::*;
+    use std::ffi::{CStr, CString};
+
+    fn loaded_hash(handle: *mut EngineHandle) -> Option<String> {
+        let hash = keymagic_engine_get_loaded_hash(handle);
+        if hash.is_null() {
+            return None;
+        }
+        let text = unsafe { CStr::from_ptr(hash) }.to_str().unwrap().to_string();
+        keymagic_free_string(hash);
+        Some(text)
+    }
+
+    let binary = create_km2_binary(&create_basic_km2()).unwrap();
+    let expected = CString::new(content_hash(&binary)).unwrap();
+    let handle = keymagic_engine_new();
+    assert_eq!(loaded_hash(handle), None);
+    assert_eq!(keymagic_engine_needs_reload(handle, expected.as_ptr()), 1);
+
+    let dir = std::env::temp_dir().join(format!("keymagic_hash_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("basic.km2");
+    std::fs::write(&path, &binary).unwrap();
+    let c_path = CString::new(path.to_str().unwrap()).unwrap();
+    assert_eq!(keymagic_engine_load_keyboard(handle, c_path.as_ptr()), KeyMagicResult::Success);
+    assert_eq!(loaded_hash(handle).as_deref(), expected.to_str().ok());
+    assert_eq!(keymagic_engine_needs_reload(handle, expected.as_ptr()), 0);
+    assert_eq!(keymagic_engine_needs_reload(handle, c"".as_ptr()), 0);
+    assert_eq!(keymagic_engine_needs_reload(handle, std::ptr::null()), 0);
+
+    // The file is replaced; the engine keeps the hash of what it loaded
+    let mut km2 = create_basic_km2();
+    add_info_text(&mut km2, "name", "Updated");
+    let updated = create_km2_binary(&km2).unwrap();
+    std::fs::write(&path, &updated).unwrap();
+    let replaced = CString::new(content_hash(&updated)).unwrap();
+    assert_eq!(keymagic_engine_needs_reload(handle, replaced.as_ptr()), 1);
+
+    // Keyboards loaded from memory are hashed too
+    assert_eq!(keymagic_engine_load_keyboard_from_memory(handle, updated.as_ptr(), updated.len()), KeyMagicResult::Success);
+    assert_eq!(keymagic_engine_needs_reload(handle, replaced.as_ptr()), 0);
+
+    keymagic_engine_free(handle);
+    assert_eq!(loaded_hash(std::ptr::null_mut()), None);
+    let _ = std::fs::remove_dir_all(&dir);
+}
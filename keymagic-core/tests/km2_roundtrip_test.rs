@@ -0,0 +1,269 @@
+//! Round trips through the KM2 writer and loader
+//!
+//! Random valid keyboards of every supported version are written with
+//! `Km2File::to_bytes` and read back with `Km2File::from_bytes`; the result
+//! must be the keyboard written, and writing it again must give the same
+//! bytes. `KEYMAGIC_FUZZ_SEED` replays one seed.
+
+use keymagic_core::km2::format;
+use keymagic_core::km2::Km2Writer;
+use keymagic_core::types::km2::{BinaryFormatElement, FileHeader, InfoEntry, Km2File, LayoutOptions, Rule, StringEntry};
+use keymagic_core::types::opcodes::{FLAG_ANYOF, FLAG_NANYOF};
+use keymagic_core::types::string_table::StringTable;
+use keymagic_core::types::{INFO_FEAT, INFO_OPTS};
+
+/// xorshift64*, enough for reproducible keyboards without a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn flag(&mut self) -> u8 {
+        self.below(2) as u8
+    }
+}
+
+/// Characters strings are made of: ASCII, Myanmar and outside the BMP
+const CHARS: &[char] = &['a', 'K', '1', ' ', '"', 'က', 'ာ', 'ေ', '်', '\u{200B}', '😀', '𝐀'];
+
+fn random_string(rng: &mut Rng, max_len: usize) -> String {
+    (0..rng.below(max_len + 1)).map(|_| CHARS[rng.below(CHARS.len())]).collect()
+}
+
+/// An index that fits the version: u32 in wide files, u16 otherwise. One
+/// below the largest, as variables are 1-based and states count from 0.
+fn random_index(rng: &mut Rng, wide: bool) -> usize {
+    match rng.below(4) {
+        0 if wide => 0x1_0000 + rng.below(1 << 20),
+        0 => u16::MAX as usize - 1,
+        _ => rng.below(64),
+    }
+}
+
+/// Elements that may appear anywhere but after `And`
+fn random_element(rng: &mut Rng, wide: bool) -> BinaryFormatElement {
+    match rng.below(7) {
+        0 => BinaryFormatElement::String(random_string(rng, 8)),
+        1 => BinaryFormatElement::Variable(1 + random_index(rng, wide)),
+        2 => BinaryFormatElement::Reference(rng.below(10)),
+        3 => BinaryFormatElement::Modifier([FLAG_ANYOF, FLAG_NANYOF, rng.below(10) as u16][rng.below(3)]),
+        4 => BinaryFormatElement::Any,
+        5 => BinaryFormatElement::Switch(random_index(rng, wide)),
+        _ => BinaryFormatElement::Notify(random_string(rng, 16)),
+    }
+}
+
+/// A LHS the loader accepts: virtual keys only in `And` sequences
+fn random_lhs(rng: &mut Rng, wide: bool) -> Vec<BinaryFormatElement> {
+    let mut lhs = Vec::new();
+    for _ in 0..rng.below(5) {
+        if rng.below(4) == 0 {
+            lhs.push(BinaryFormatElement::And);
+            for _ in 0..1 + rng.below(3) {
+                lhs.push(BinaryFormatElement::Predefined(rng.below(0x100) as u16));
+            }
+        } else {
+            lhs.push(random_element(rng, wide));
+        }
+    }
+    lhs
+}
+
+fn random_rhs(rng: &mut Rng, wide: bool) -> Vec<BinaryFormatElement> {
+    (0..rng.below(4))
+        .map(|_| match rng.below(6) {
+            0 => BinaryFormatElement::Predefined(rng.below(0x100) as u16),
+            1 => BinaryFormatElement::And,
+            _ => random_element(rng, wide),
+        })
+        .collect()
+}
+
+/// Info entries the loader does not interpret, so they come back as written
+fn random_info(rng: &mut Rng) -> InfoEntry {
+    loop {
+        let id = (rng.next() as u32).to_le_bytes();
+        if &id != INFO_OPTS && &id != INFO_FEAT {
+            let data = (0..rng.below(40)).map(|_| rng.next() as u8).collect();
+            return InfoEntry { id, data };
+        }
+    }
+}
+
+fn random_km2(seed: u64) -> Km2File {
+    let mut rng = Rng::new(seed);
+    let minor = 3 + rng.below(4) as u8;
+    let wide = format::has_wide_indices(1, minor);
+
+    let mut strings = StringTable::new();
+    for _ in 0..rng.below(8) {
+        strings.push(StringEntry { value: random_string(&mut rng, 12) });
+    }
+    let info: Vec<InfoEntry> = if format::has_info_count(1, minor) {
+        (0..rng.below(5)).map(|_| random_info(&mut rng)).collect()
+    } else {
+        Vec::new()
+    };
+    let rules: Vec<Rule> = (0..rng.below(12))
+        .map(|_| Rule { lhs: random_lhs(&mut rng, wide), rhs: random_rhs(&mut rng, wide) })
+        .collect();
+
+    let layout_options = LayoutOptions {
+        track_caps: rng.flag(),
+        auto_bksp: rng.flag(),
+        eat: rng.flag(),
+        pos_based: rng.flag(),
+        // Older headers have no room for it and read as on
+        right_alt: if format::has_right_alt(1, minor) { rng.flag() } else { 1 },
+        // Kept in the opts entry, which random info never holds
+        case_insensitive_ascii: 0,
+    };
+    let header = FileHeader {
+        minor_version: minor,
+        string_count: strings.len() as u32,
+        info_count: info.len() as u32,
+        rule_count: rules.len() as u32,
+        layout_options,
+        ..FileHeader::new()
+    };
+    Km2File { header, strings, info, rules }
+}
+
+/// Everything the file holds, in a comparable form
+fn describe(km2: &Km2File) -> String {
+    let header = km2.header;
+    let (string_count, info_count, rule_count) = (header.string_count, header.info_count, header.rule_count);
+    let options = header.layout_options;
+    format!(
+        "{:?} {}.{} {} {} {} {:?}\n{:?}\n{:?}\n{:?}",
+        header.magic_code,
+        header.major_version,
+        header.minor_version,
+        string_count,
+        info_count,
+        rule_count,
+        [options.track_caps, options.auto_bksp, options.eat, options.pos_based, options.right_alt, options.case_insensitive_ascii],
+        km2.strings.iter().map(|entry| entry.value.clone()).collect::<Vec<_>>(),
+        km2.info.iter().map(|entry| (entry.id, entry.data.clone())).collect::<Vec<_>>(),
+        km2.rules,
+    )
+}
+
+fn seeds(default: std::ops::Range<u64>) -> Vec<u64> {
+    match std::env::var("KEYMAGIC_FUZZ_SEED") {
+        Ok(seed) => vec![seed.parse().expect("KEYMAGIC_FUZZ_SEED must be a number")],
+        Err(_) => default.collect(),
+    }
+}
+
+#[test]
+fn test_random_keyboards_round_trip() {
+    let mut versions = [false; 4];
+    for seed in seeds(0..500) {
+        let km2 = random_km2(seed);
+        versions[km2.header.minor_version as usize - 3] = true;
+
+        let bytes = km2.to_bytes().unwrap_or_else(|e| panic!("seed {}: writing failed: {}", seed, e));
+        let loaded = Km2File::from_bytes(&bytes).unwrap_or_else(|e| panic!("seed {}: loading failed: {}", seed, e));
+        assert_eq!(describe(&loaded), describe(&km2), "seed {}", seed);
+        assert_eq!(loaded.to_bytes().unwrap(), bytes, "seed {}: bytes differ when written again", seed);
+    }
+    if std::env::var("KEYMAGIC_FUZZ_SEED").is_err() {
+        assert_eq!(versions, [true; 4], "every version was generated");
+    }
+}
+
+#[test]
+fn test_compiled_keyboard_round_trips() {
+    let km2 = kms2km2::compile_kms(
+        r#"
+/*
+@NAME = "Round trip"
+@TRACK_CAPSLOCK = "false"
+*/
+$cons = "ကခဂ"
+$keys = "kKg"
+$keys[*] => $cons[$1]
+<VK_SHIFT & VK_KEY_A> => "အ" + ('alt')
+('alt') + "1" => "၁"
+"#,
+    )
+    .unwrap();
+    let bytes = km2.to_bytes().unwrap();
+    assert_eq!(describe(&Km2File::from_bytes(&bytes).unwrap()), describe(&km2));
+}
+
+#[test]
+fn test_header_sizes_match_format() {
+    let mut km2 = random_km2(0);
+    km2.strings = StringTable::new();
+    km2.info.clear();
+    km2.rules.clear();
+    km2.header.string_count = 0;
+    km2.header.info_count = 0;
+    km2.header.rule_count = 0;
+
+    let mut sizes = Vec::new();
+    for minor in 3..=6 {
+        km2.header.minor_version = minor;
+        let size = km2.to_bytes().unwrap().len();
+        assert_eq!(size, format::header_size(1, minor), "1.{}", minor);
+        sizes.push(size);
+    }
+    assert_eq!(sizes, vec![14, 16, 18, 24]);
+    assert!(sizes[0] >= format::MIN_FILE_SIZE);
+}
+
+#[test]
+fn test_element_units_match_written_size() {
+    let mut rng = Rng::new(42);
+    for wide in [false, true] {
+        for _ in 0..200 {
+            let element = random_element(&mut rng, wide);
+            let mut km2 = random_km2(0);
+            km2.header.minor_version = if wide { 6 } else { 5 };
+            km2.info.clear();
+            km2.header.info_count = 0;
+            km2.rules = vec![Rule { lhs: vec![], rhs: vec![element.clone()] }];
+            km2.header.rule_count = 1;
+
+            let empty = Km2File { rules: vec![Rule { lhs: vec![], rhs: vec![] }], ..km2.clone() };
+            let grown = km2.to_bytes().unwrap().len() - empty.to_bytes().unwrap().len();
+            assert_eq!(grown, format::element_units(&element, wide) * 2, "{:?}", element);
+        }
+    }
+}
+
+#[test]
+fn test_kms2km2_writer_is_the_core_writer() {
+    let km2 = random_km2(1);
+    let mut via_compiler = Vec::new();
+    kms2km2::binary::Km2Writer::new(&mut via_compiler).write_km2_file(&km2).unwrap();
+    let mut via_core = Vec::new();
+    Km2Writer::new(&mut via_core).write_km2_file(&km2).unwrap();
+    assert_eq!(via_compiler, via_core);
+    assert_eq!(via_core, km2.to_bytes().unwrap());
+}
+
+#[test]
+fn test_opcode_names() {
+    assert_eq!(format::opcode_name(format::OP_STRING), Some("STRING"));
+    assert_eq!(format::opcode_name(format::OP_NOTIFY), Some("NOTIFY"));
+    assert_eq!(format::opcode_name(0x1234), None);
+    for element in [BinaryFormatElement::And, BinaryFormatElement::Any, BinaryFormatElement::Switch(0)] {
+        assert!(format::opcode_name(format::element_opcode(&element)).is_some());
+    }
+}
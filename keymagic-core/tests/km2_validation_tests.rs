@@ -1,7 +1,9 @@
 mod common;
 
 use common::*;
-use keymagic_core::{BinaryFormatElement, VirtualKey, km2::{Km2Loader, Km2Error}};
+use keymagic_core::types::km2::BinaryFormatElement;
+use keymagic_core::VirtualKey;
+use keymagic_core::km2::{Km2Loader, Km2Error};
 
 #[test]
 fn test_invalid_predefined_usage() {
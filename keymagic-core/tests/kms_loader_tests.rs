@@ -110,7 +110,7 @@ fn test_myansan_kms_to_km2_binary() {
 
 #[test]
 fn test_myansan_kms_to_km2_bytes() {
-    use kms2km2::binary::Km2Writer;
+    use keymagic_core::km2::Km2Writer;
     
     // Path to MyanSan.kms
     let kms_path = fixtures_dir().join("MyanSan.kms");
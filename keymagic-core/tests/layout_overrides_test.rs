@@ -0,0 +1,88 @@
+//! Tests for user overrides of layout options
+
+mod common;
+use common::*;
+
+use keymagic_core::{ActionType, Error, KeyMagicEngine};
+use keymagic_core::types::km2::{Km2File, LayoutOptions, LayoutOverrides};
+use keymagic_core::types::km2::BinaryFormatElement;
+use kms2km2::VirtualKey;
+
+/// "ka" => "က" with smart backspace off
+fn create_test_keyboard() -> Km2File {
+    let mut keyboard = create_basic_km2();
+    add_rule(&mut keyboard,
+        vec![BinaryFormatElement::String("ka".to_string())],
+        vec![BinaryFormatElement::String("က".to_string())]
+    );
+    keyboard
+}
+
+fn overrides(entries: &[(&str, bool)]) -> LayoutOverrides {
+    entries.iter().map(|(name, value)| (name.to_string(), *value)).collect()
+}
+
+#[test]
+fn test_overrides_win_over_keyboard_options() {
+    let options = LayoutOptions::default()
+        .with_overrides(&overrides(&[("track_caps", false), ("auto_bksp", true)]))
+        .unwrap();
+    assert_eq!(options.get("track_caps"), Some(false));
+    assert_eq!(options.get("auto_bksp"), Some(true));
+    // Options without an override keep the keyboard's value
+    assert_eq!(options.get("right_alt"), Some(true));
+    assert_eq!(options.get("eat"), Some(false));
+}
+
+#[test]
+fn test_unknown_option_is_rejected() {
+    let err = LayoutOptions::default().with_overrides(&overrides(&[("smart_quotes", true)])).unwrap_err();
+    assert!(matches!(err, Error::UnknownLayoutOption(name) if name == "smart_quotes"));
+    assert_eq!(LayoutOptions::default().get("smart_quotes"), None);
+
+    let result = KeyMagicEngine::with_options(create_test_keyboard(), &overrides(&[("smart_quotes", true)]));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_every_name_round_trips() {
+    for name in LayoutOptions::NAMES {
+        let mut options = LayoutOptions::default();
+        options.set(name, true).unwrap();
+        assert_eq!(options.get(name), Some(true), "{}", name);
+        options.set(name, false).unwrap();
+        assert_eq!(options.get(name), Some(false), "{}", name);
+    }
+}
+
+#[test]
+fn test_engine_uses_overridden_smart_backspace() {
+    let mut engine = KeyMagicEngine::with_options(create_test_keyboard(), &overrides(&[("auto_bksp", true)])).unwrap();
+    assert_eq!(engine.layout_options().auto_bksp, 1);
+    // The keyboard itself is left as loaded
+    assert_eq!(engine.keyboard().header.layout_options.auto_bksp, 0);
+
+    engine.process_key(key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+    engine.process_key(key_input_vk_char(VirtualKey::KeyA, 'a')).unwrap();
+    let output = engine.process_key(key_input_from_vk(VirtualKey::Back)).unwrap();
+    assert_eq!(output.composing_text, "k");
+    assert_eq!(output.action, ActionType::BackspaceDeleteAndInsert(1, "k".to_string()));
+}
+
+#[test]
+fn test_set_layout_options_keeps_composing_text() {
+    let mut engine = KeyMagicEngine::new(create_test_keyboard()).unwrap();
+    engine.process_key(key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+    engine.process_key(key_input_vk_char(VirtualKey::KeyA, 'a')).unwrap();
+
+    let mut options = engine.layout_options();
+    options.set("auto_bksp", true).unwrap();
+    engine.set_layout_options(options);
+    assert_eq!(engine.composing_text(), "က");
+
+    // Keys typed from now on can be undone
+    engine.process_key(key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+    engine.process_key(key_input_vk_char(VirtualKey::KeyA, 'a')).unwrap();
+    let output = engine.process_key(key_input_from_vk(VirtualKey::Back)).unwrap();
+    assert_eq!(output.composing_text, "ကk");
+}
@@ -0,0 +1,54 @@
+//! Ring of keyboard loads reported by text services
+#![cfg(feature = "host-ipc")]
+
+use keymagic_core::km2::content_hash;
+use keymagic_core::load_log::*;
+
+#[test]
+fn test_loads_are_read_in_order() {
+    let log = LoadLog::in_memory();
+    let hash = content_hash(b"keyboard");
+    assert!(log.read_all().is_empty());
+
+    assert_eq!(log.report(4242, "notepad.exe", &hash), Some(1));
+    assert_eq!(log.report(4242, "notepad.exe", "not a hash"), None);
+    assert_eq!(log.report(7, "ms-teams.exe", &hash.to_uppercase()), Some(2));
+
+    let loads = log.read_all();
+    assert_eq!(loads.len(), 2);
+    assert_eq!(loads[0], KeyboardLoad { seq: 1, pid: 4242, process: "notepad.exe".to_string(), hash: hash.clone() });
+    // Read back in the form content_hash writes
+    assert_eq!(loads[1].hash, hash);
+}
+
+#[test]
+fn test_latest_load_per_host() {
+    let log = LoadLog::in_memory();
+    let old = content_hash(b"old keyboard");
+    let new = content_hash(b"new keyboard");
+    log.report(4242, "notepad.exe", &old);
+    log.report(7, "winword.exe", &old);
+    log.report(4242, "notepad.exe", &new);
+
+    let latest = log.latest_by_host();
+    let hosts: Vec<(u32, &str)> = latest.iter().map(|load| (load.pid, load.hash.as_str())).collect();
+    assert_eq!(hosts, vec![(7, old.as_str()), (4242, new.as_str())]);
+}
+
+#[test]
+fn test_ring_keeps_newest_and_cuts_long_names() {
+    let log = LoadLog::in_memory();
+    let hash = content_hash(b"keyboard");
+    for pid in 0..(SLOT_COUNT as u32 + 3) {
+        log.report(pid, "host.exe", &hash);
+    }
+    let loads = log.read_all();
+    assert_eq!(loads.len(), SLOT_COUNT);
+    assert_eq!(loads[0].pid, 3);
+
+    let long = format!("{}.exe", "ကခ".repeat(NAME_CAPACITY));
+    log.report(1, &long, &hash);
+    let cut: String = long.chars().take(NAME_CAPACITY).collect();
+    let host = log.latest_by_host().into_iter().find(|load| load.pid == 1).unwrap();
+    assert_eq!(host.process, cut);
+}
@@ -0,0 +1,35 @@
+//! Tests for the shape check of `@LOCALE` tags
+
+use keymagic_core::locale::{canonical_locale_tag, check_locale_tag};
+
+#[test]
+fn test_well_formed_tags() {
+    for tag in ["my", "shn-MM", "my-Mymr-MM", "ksw", "es-419", "sl-rozaj-biske", "de-CH-1996"] {
+        assert_eq!(check_locale_tag(tag), Ok(vec![]), "{}", tag);
+    }
+}
+
+#[test]
+fn test_malformed_tags() {
+    for tag in ["", "m", "my_MM", "my-", "-MM", "123", "my-MM-ab", "my-u", "x", "my-x-waytoolongsubtag", "မြန်မာ"] {
+        assert!(check_locale_tag(tag).is_err(), "{}", tag);
+    }
+}
+
+#[test]
+fn test_unusual_tags_have_notes() {
+    let notes = check_locale_tag("MY-mm").unwrap();
+    assert_eq!(notes, vec!["usually written as \"my-MM\"".to_string()]);
+
+    assert_eq!(check_locale_tag("burmese").unwrap().len(), 1);
+    assert_eq!(check_locale_tag("x-keymagic").unwrap().len(), 1);
+    assert_eq!(check_locale_tag("my-MM-x-zawgyi").unwrap().len(), 1);
+    assert_eq!(check_locale_tag("my-u-nu-mymr").unwrap().len(), 1);
+}
+
+#[test]
+fn test_canonical_case() {
+    assert_eq!(canonical_locale_tag("SHN-mymr-mm"), "shn-Mymr-MM");
+    assert_eq!(canonical_locale_tag("es-419"), "es-419");
+    assert_eq!(canonical_locale_tag("my-u-NU-mymr"), "my-u-nu-mymr");
+}
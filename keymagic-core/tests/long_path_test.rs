@@ -0,0 +1,80 @@
+//! Loading keyboards from deep, non-ASCII folders
+#![cfg(feature = "ffi")]
+
+use keymagic_core::ffi::*;
+use keymagic_core::paths::{to_extended_length, to_extended_length_wide};
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+
+mod common;
+use common::*;
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().collect()
+}
+
+/// Writes a basic keyboard under nested Burmese-named folders whose full path
+/// is longer than MAX_PATH
+fn write_deep_keyboard(test_name: &str) -> (PathBuf, PathBuf) {
+    let root = std::env::temp_dir().join(format!("keymagic_{}_{}", test_name, std::process::id()));
+    let mut dir = root.clone();
+    while dir.to_string_lossy().encode_utf16().count() < 300 {
+        dir.push("မြန်မာ ကီးဘုတ် ဖိုင်တွဲ");
+    }
+    std::fs::create_dir_all(to_extended_length(&dir)).unwrap();
+
+    let file = dir.join("ဇော်ဂျီ.km2");
+    let binary = create_km2_binary(&create_basic_km2()).unwrap();
+    std::fs::write(to_extended_length(&file), binary).unwrap();
+    assert!(file.to_string_lossy().encode_utf16().count() > 260);
+    (root, file)
+}
+
+fn remove(root: &Path) {
+    let _ = std::fs::remove_dir_all(to_extended_length(root));
+}
+
+#[test]
+fn test_load_keyboard_from_deep_unicode_path() {
+    let (root, file) = write_deep_keyboard("utf8");
+    let path = CString::new(file.to_str().unwrap()).unwrap();
+
+    let engine = keymagic_engine_new();
+    assert_eq!(keymagic_engine_load_keyboard(engine, path.as_ptr()), KeyMagicResult::Success);
+    keymagic_engine_free(engine);
+
+    let km2 = keymagic_km2_load(path.as_ptr());
+    assert!(!km2.is_null());
+    keymagic_km2_free(km2);
+
+    remove(&root);
+}
+
+#[test]
+fn test_load_keyboard_from_wide_path() {
+    let (root, file) = write_deep_keyboard("wide");
+    let mut path = wide(file.to_str().unwrap());
+    path.push(0);
+
+    let engine = keymagic_engine_new();
+    assert_eq!(keymagic_engine_load_keyboard_w(engine, path.as_ptr()), KeyMagicResult::Success);
+
+    let missing: Vec<u16> = wide("no_such_keyboard.km2").into_iter().chain([0]).collect();
+    assert_eq!(keymagic_engine_load_keyboard_w(engine, missing.as_ptr()), KeyMagicResult::ErrorEngineFailure);
+    assert_eq!(keymagic_engine_load_keyboard_w(engine, std::ptr::null()), KeyMagicResult::ErrorInvalidParameter);
+    keymagic_engine_free(engine);
+
+    remove(&root);
+}
+
+#[test]
+fn test_extended_length_prefix() {
+    assert_eq!(to_extended_length_wide(&wide(r"C:\Users\a\b.km2")), wide(r"\\?\C:\Users\a\b.km2"));
+    assert_eq!(to_extended_length_wide(&wide("c:/Users/a/b.km2")), wide(r"\\?\c:\Users\a\b.km2"));
+    assert_eq!(to_extended_length_wide(&wide(r"\\server\share\b.km2")), wide(r"\\?\UNC\server\share\b.km2"));
+
+    // Already prefixed, device and relative paths are left alone
+    for path in [r"\\?\C:\a.km2", r"\\.\pipe\x", r"keyboards\a.km2", r"C:a.km2", "/tmp/a.km2"] {
+        assert_eq!(to_extended_length_wide(&wide(path)), wide(path), "{}", path);
+    }
+}
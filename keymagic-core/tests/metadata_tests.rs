@@ -1,7 +1,8 @@
 mod common;
 
 use common::*;
-use keymagic_core::{LayoutOptions, km2::Km2Loader};
+use keymagic_core::types::km2::LayoutOptions;
+use keymagic_core::km2::Km2Loader;
 
 #[test]
 fn test_metadata_name() {
@@ -60,6 +61,7 @@ fn test_layout_options_track_capslock() {
         eat: 0,
         pos_based: 0,
         right_alt: 0,
+        case_insensitive_ascii: 0,
     };
     
     let km2 = create_km2_with_options(options);
@@ -79,6 +81,7 @@ fn test_layout_options_smart_backspace() {
         eat: 0,
         pos_based: 0,
         right_alt: 0,
+        case_insensitive_ascii: 0,
     };
     
     let km2 = create_km2_with_options(options);
@@ -98,6 +101,7 @@ fn test_layout_options_eat_all_unused_keys() {
         eat: 1,
         pos_based: 0,
         right_alt: 0,
+        case_insensitive_ascii: 0,
     };
     
     let km2 = create_km2_with_options(options);
@@ -116,6 +120,7 @@ fn test_layout_options_us_layout_based() {
         eat: 0,
         pos_based: 1,
         right_alt: 0,
+        case_insensitive_ascii: 0,
     };
     
     let km2 = create_km2_with_options(options);
@@ -134,6 +139,7 @@ fn test_layout_options_treat_ctrl_alt_as_ralt() {
         eat: 0,
         pos_based: 0,
         right_alt: 1,
+        case_insensitive_ascii: 0,
     };
     
     let km2 = create_km2_with_options(options);
@@ -157,6 +163,7 @@ fn test_multiple_metadata_entries() {
         eat: 0,
         pos_based: 1,
         right_alt: 0,
+        case_insensitive_ascii: 0,
     };
     
     let binary = create_km2_binary(&km2).unwrap();
@@ -181,4 +188,53 @@ fn test_default_layout_options() {
     assert_eq!(loaded.header.layout_options.eat, 0);         // Default: false
     assert_eq!(loaded.header.layout_options.pos_based, 0);   // Default: false
     assert_eq!(loaded.header.layout_options.right_alt, 1);   // Default: true
-}
\ No newline at end of file
+}
+
+/// Bytes of the rules section, which ends the file
+fn rule_bytes(km2: &keymagic_core::Km2File) -> Vec<u8> {
+    let full = create_km2_binary(km2).unwrap();
+    let mut without_rules = km2.clone();
+    without_rules.rules.clear();
+    without_rules.header.rule_count = 0;
+    full[create_km2_binary(&without_rules).unwrap().len()..].to_vec()
+}
+
+#[test]
+fn test_set_info_changes_only_that_entry() {
+    use keymagic_core::types::km2::{INFO_DESC, INFO_HTKY, INFO_NAME};
+
+    let km2 = kms2km2::compile_kms(r#"
+        /*
+        @NAME = "Myanmr"
+        @DESCRIPTION = "Myanmar keyboard"
+        @FONTFAMILY = "Myanmar3"
+        */
+        $cons = "ကခဂ"
+        "k" => "က"
+        $cons[*] + "h" => $1 + "ှ"
+    "#).unwrap();
+    let binary = create_km2_binary(&km2).unwrap();
+    let original = Km2Loader::load(&binary).unwrap();
+
+    let mut edited = original.clone();
+    edited.set_info(INFO_NAME, "Myanmar".as_bytes().to_vec());
+    edited.set_info(INFO_HTKY, "CTRL+SHIFT+M".as_bytes().to_vec());
+    assert!(edited.remove_info(INFO_DESC));
+    assert!(!edited.remove_info(INFO_DESC));
+
+    let loaded = Km2Loader::load(&create_km2_binary(&edited).unwrap()).unwrap();
+    let metadata = loaded.metadata();
+    assert_eq!(metadata.name().as_deref(), Some("Myanmar"));
+    assert_eq!(metadata.hotkey().as_deref(), Some("CTRL+SHIFT+M"));
+    assert_eq!(metadata.description(), None);
+    assert_eq!(metadata.font_family().as_deref(), Some("Myanmar3"));
+    assert_eq!({ loaded.header.info_count }, loaded.info.len() as u32);
+
+    // The name keeps its place; the new hotkey goes last
+    let ids: Vec<_> = original.info.iter().map(|e| e.id).filter(|id| id != INFO_DESC).chain([*INFO_HTKY]).collect();
+    assert_eq!(loaded.info.iter().map(|e| e.id).collect::<Vec<_>>(), ids);
+
+    // Strings and rules are written exactly as before
+    assert_eq!(loaded.strings.len(), original.strings.len());
+    assert_eq!(rule_bytes(&loaded), rule_bytes(&original));
+}
@@ -1,8 +1,7 @@
 mod common;
 
 use common::*;
-use keymagic_core::engine::{ModifierState, KeyInput};
-use keymagic_core::VirtualKey;
+use keymagic_core::{ModifierState, KeyInput, VirtualKey};
 
 #[test]
 fn test_exact_modifier_matching() {
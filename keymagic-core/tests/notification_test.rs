@@ -0,0 +1,140 @@
+//! Keyboard messages raised by `@notify` and their rate limit
+#![cfg(feature = "host-ipc")]
+
+mod common;
+use common::*;
+
+use keymagic_core::notification::{NotificationLimiter, NOTIFICATION_INTERVAL};
+use std::time::{Duration, Instant};
+
+/// F2 switches to numbers and says so; digits typed in numbers mode are Myanmar
+const NUMBERS_KMS: &str = r#"
+<VK_F2> => ('numbers') + @notify "Numbers mode"
+('numbers') + "1" => "၁" + ('numbers')
+"#;
+
+fn numbers_mode_key() -> keymagic_core::KeyInput {
+    key_input_from_vk(keymagic_core::VirtualKey::F2)
+}
+
+#[test]
+fn test_rule_raises_its_notification() {
+    let mut engine = create_engine(NUMBERS_KMS).unwrap();
+    process_string(&mut engine, "a").unwrap();
+
+    let output = engine.process_key(numbers_mode_key()).unwrap();
+    assert_eq!(output.notification.as_deref(), Some("Numbers mode"));
+    assert!(output.is_processed);
+    // The message leaves the text alone
+    assert_eq!(output.composing_text, "a");
+    assert_eq!(output.action, keymagic_core::ActionType::None);
+
+    // Only the key whose rule raised it carries the message
+    let output = process_char(&mut engine, '1').unwrap();
+    assert_eq!(output.composing_text, "a၁");
+    assert_eq!(output.notification, None);
+}
+
+#[test]
+fn test_no_notification_in_process_key_test() {
+    let mut engine = create_engine(NUMBERS_KMS).unwrap();
+
+    let output = engine.process_key_test(numbers_mode_key()).unwrap();
+    assert!(output.is_processed);
+    assert_eq!(output.notification, None);
+
+    // Nor is it held back for the next real key
+    let output = process_char(&mut engine, 'x').unwrap();
+    assert_eq!(output.notification, None);
+    let output = engine.process_key(numbers_mode_key()).unwrap();
+    assert_eq!(output.notification.as_deref(), Some("Numbers mode"));
+}
+
+#[test]
+fn test_limiter_shows_one_message_per_interval() {
+    let mut limiter = NotificationLimiter::default();
+    let start = Instant::now();
+    let at = |ms: u64| start + Duration::from_millis(ms);
+
+    assert!(limiter.allow_at("myanmar", at(0)));
+    assert!(!limiter.allow_at("myanmar", at(100)));
+    assert!(!limiter.allow_at("myanmar", at(499)));
+    assert!(limiter.allow_at("myanmar", at(500)));
+    // Dropped messages do not push the next one back
+    assert!(!limiter.allow_at("myanmar", at(900)));
+    assert!(limiter.allow_at("myanmar", at(1000)));
+    assert_eq!(NOTIFICATION_INTERVAL, Duration::from_millis(500));
+}
+
+#[test]
+fn test_limiter_is_per_keyboard() {
+    let mut limiter = NotificationLimiter::new(Duration::from_millis(500));
+    let start = Instant::now();
+
+    assert!(limiter.allow_at("myanmar", start));
+    assert!(limiter.allow_at("zawgyi", start + Duration::from_millis(10)));
+    assert!(!limiter.allow_at("zawgyi", start + Duration::from_millis(20)));
+    assert!(!limiter.allow_at("myanmar", start + Duration::from_millis(20)));
+}
+
+#[cfg(feature = "ffi")]
+mod bridge {
+    use super::*;
+    use keymagic_core::ffi::*;
+    use std::ffi::CStr;
+    use std::ptr;
+
+    fn press(engine: *mut EngineHandle, dry_run: bool) -> Option<String> {
+        let mut output = ProcessKeyOutput {
+            action_type: 0,
+            text: ptr::null_mut(),
+            delete_count: 0,
+            composing_text: ptr::null_mut(),
+            is_processed: 0,
+            delete_utf16_count: 0,
+            composing_caret_utf16: 0,
+            notification: ptr::null_mut(),
+            delete_clamped: 0,
+        };
+        let key = keymagic_core::VirtualKey::F2 as i32;
+        let result = if dry_run {
+            keymagic_engine_process_key_test(engine, key, 0, 0, 0, 0, 0, &mut output)
+        } else {
+            keymagic_engine_process_key(engine, key, 0, 0, 0, 0, 0, &mut output)
+        };
+        assert_eq!(result, KeyMagicResult::Success);
+        unsafe {
+            let notification = (!output.notification.is_null())
+                .then(|| CStr::from_ptr(output.notification).to_str().unwrap().to_string());
+            keymagic_free_string(output.text);
+            keymagic_free_string(output.composing_text);
+            keymagic_free_string(output.notification);
+            notification
+        }
+    }
+
+    #[test]
+    fn test_bridge_rate_limits_notifications_per_keyboard() {
+        // A keyboard of its own: the limit is shared by every handle of the
+        // process loading the same keyboard
+        let binary = create_km2_binary(&kms2km2::compile_kms(&format!("{}\n\"bridge\" => \"test\"", NUMBERS_KMS)).unwrap()).unwrap();
+        let first = keymagic_engine_new();
+        let second = keymagic_engine_new();
+        for engine in [first, second] {
+            let result = keymagic_engine_load_keyboard_from_memory(engine, binary.as_ptr(), binary.len());
+            assert_eq!(result, KeyMagicResult::Success);
+        }
+
+        // Dry runs never carry a message, nor use up the interval
+        assert_eq!(press(first, true), None);
+        assert_eq!(press(first, false).as_deref(), Some("Numbers mode"));
+        assert_eq!(press(first, false), None);
+        assert_eq!(press(second, false), None);
+
+        std::thread::sleep(NOTIFICATION_INTERVAL + Duration::from_millis(50));
+        assert_eq!(press(second, false).as_deref(), Some("Numbers mode"));
+
+        keymagic_engine_free(first);
+        keymagic_engine_free(second);
+    }
+}
@@ -0,0 +1,94 @@
+//! Semantics of NULL and empty outputs on the RHS
+//!
+//! A RHS that produces no text deletes the matched text and inserts nothing;
+//! NULL next to other elements has no effect.
+
+use keymagic_core::types::km2::BinaryFormatElement;
+use keymagic_core::types::opcodes::PREDEFINED_NULL;
+use keymagic_core::{ActionType, EngineOutput, KeyMagicEngine, VirtualKey};
+
+mod common;
+use common::*;
+
+/// Output of the last of the keys typed for `text`
+fn type_text(engine: &mut KeyMagicEngine, text: &str) -> EngineOutput {
+    process_string(engine, text).unwrap().pop().unwrap()
+}
+
+fn deleted_match() -> EngineOutput {
+    EngineOutput::new(String::new(), ActionType::BackspaceDelete(2), "ab", true)
+}
+
+fn replaced_match() -> EngineOutput {
+    EngineOutput::new("x".to_string(), ActionType::BackspaceDeleteAndInsert(2, "x".to_string()), "ab", true)
+}
+
+#[test]
+fn test_null_alone_deletes_match() {
+    let mut engine = create_engine(r#""abc" => NULL"#).unwrap();
+    assert_eq!(type_text(&mut engine, "abc"), deleted_match());
+}
+
+#[test]
+fn test_null_before_text() {
+    let mut engine = create_engine(r#""abc" => NULL + "x""#).unwrap();
+    assert_eq!(type_text(&mut engine, "abc"), replaced_match());
+}
+
+#[test]
+fn test_null_after_text() {
+    let mut engine = create_engine(r#""abc" => "x" + NULL"#).unwrap();
+    assert_eq!(type_text(&mut engine, "abc"), replaced_match());
+}
+
+#[test]
+fn test_empty_string_compiles_to_null() {
+    let km2 = kms2km2::compile_kms(r#""abc" => """#).unwrap();
+    assert!(matches!(km2.rules[0].rhs[..], [BinaryFormatElement::Predefined(PREDEFINED_NULL)]));
+
+    let mut engine = create_engine(r#""abc" => """#).unwrap();
+    assert_eq!(type_text(&mut engine, "abc"), deleted_match());
+}
+
+#[test]
+fn test_empty_variable_deletes_match() {
+    let mut engine = create_engine("$empty = \"\"\n\"abc\" => $empty").unwrap();
+    assert_eq!(type_text(&mut engine, "abc"), deleted_match());
+}
+
+#[test]
+fn test_null_with_virtual_key() {
+    let mut engine = create_engine(r#""ab" + <VK_BACK> => NULL"#).unwrap();
+    type_text(&mut engine, "ab");
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Back)).unwrap();
+    assert_eq!(output, deleted_match());
+
+    // Only the key matched: the key is swallowed and the text stays
+    let mut engine = create_engine(r#"<VK_KEY_Q> => NULL"#).unwrap();
+    type_text(&mut engine, "ab");
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::KeyQ)).unwrap();
+    assert_eq!(output, EngineOutput::new("ab".to_string(), ActionType::None, "", true));
+}
+
+#[test]
+fn test_null_in_recursive_match() {
+    let mut engine = create_engine("\"a\" => \"bc\"\n\"bc\" => NULL").unwrap();
+    let output = process_char(&mut engine, 'a').unwrap();
+    assert_eq!(output, EngineOutput::new(String::new(), ActionType::None, "", true));
+    assert_eq!(engine.last_matched_rules(), &[0, 1]);
+}
+
+#[test]
+fn test_legacy_empty_string_output() {
+    // Existing files compiled before NULL normalization keep "" as an empty
+    // string, which behaves exactly like NULL
+    let mut km2 = create_basic_km2();
+    add_rule(
+        &mut km2,
+        vec![BinaryFormatElement::String("abc".to_string())],
+        vec![BinaryFormatElement::String(String::new())],
+    );
+    let data = create_km2_binary(&km2).unwrap();
+    let mut engine = create_engine_from_binary(&data).unwrap();
+    assert_eq!(type_text(&mut engine, "abc"), deleted_match());
+}
@@ -0,0 +1,138 @@
+//! Tests for output transforms (Unicode to Zawgyi)
+#![cfg(feature = "zawgyi")]
+
+mod common;
+use common::engine_helpers::*;
+
+use keymagic_core::ActionType;
+use keymagic_core::transform::zawgyi::unicode_to_zawgyi;
+use keymagic_core::transform::TransformId;
+
+/// Applies an engine action to a simulated host text buffer
+fn apply_action(buffer: &mut Vec<char>, action: &ActionType) {
+    match action {
+        ActionType::None => {}
+        ActionType::Insert(text) => buffer.extend(text.chars()),
+        ActionType::BackspaceDelete(count) => {
+            assert!(*count <= buffer.len(), "delete count exceeds host text");
+            buffer.truncate(buffer.len() - count);
+        }
+        ActionType::BackspaceDeleteAndInsert(count, text) => {
+            assert!(*count <= buffer.len(), "delete count exceeds host text");
+            buffer.truncate(buffer.len() - count);
+            buffer.extend(text.chars());
+        }
+        other => panic!("unexpected action {:?}", other),
+    }
+}
+
+const MYANMAR_KMS: &str = r#"
+    "k" => U1000
+    "r" => U103C
+    "y" => U103B
+    "e" => U1031
+    "m" => U1019
+    "n" => U1014
+    "f" => U103A
+    "a" => U102C
+    "t" => U1010
+    "F" => U1039
+    "l" => U101C
+    ";" => U1038
+"#;
+
+#[test]
+fn test_composing_text_is_transformed() {
+    let mut engine = create_engine(MYANMAR_KMS).unwrap();
+    engine.set_output_transform(Some(TransformId::Zawgyi)).unwrap();
+    assert_eq!(engine.output_transform(), Some(TransformId::Zawgyi));
+
+    // ကြ typed in Unicode order: the medial ra moves before the consonant
+    process_char(&mut engine, 'k').unwrap();
+    let output = process_char(&mut engine, 'r').unwrap();
+    assert_eq!(output.composing_text, "\u{103B}\u{1000}");
+    assert_eq!(output.action, ActionType::BackspaceDeleteAndInsert(1, "\u{103B}\u{1000}".to_string()));
+
+    // The internal buffer stays Unicode for rule matching
+    assert_eq!(engine.composing_text(), "\u{1000}\u{103C}");
+    assert_eq!(engine.emitted_text(), "\u{103B}\u{1000}");
+}
+
+#[test]
+fn test_host_buffer_matches_transformed_text() {
+    let mut engine = create_engine(MYANMAR_KMS).unwrap();
+    engine.set_output_transform(Some(TransformId::Zawgyi)).unwrap();
+
+    // ကျောင်း typed in Unicode storage order, then မန္တလေး
+    let mut host = Vec::new();
+    for ch in "kyeankf;mnFtle;".chars() {
+        let output = process_char(&mut engine, ch).unwrap();
+        apply_action(&mut host, &output.action);
+        assert_eq!(host.iter().collect::<String>(), output.composing_text);
+        assert_eq!(output.composing_text, unicode_to_zawgyi(engine.composing_text()));
+    }
+
+    // Backspace keeps the host text in sync as well
+    for _ in 0..4 {
+        let output = process_key(&mut engine, key_input_from_vk(keymagic_core::VirtualKey::Back)).unwrap();
+        apply_action(&mut host, &output.action);
+        assert_eq!(host.iter().collect::<String>(), output.composing_text);
+    }
+}
+
+#[test]
+fn test_clearing_transform_restores_unicode() {
+    let mut engine = create_engine(MYANMAR_KMS).unwrap();
+    engine.set_output_transform(Some(TransformId::Zawgyi)).unwrap();
+    process_char(&mut engine, 'k').unwrap();
+
+    engine.set_output_transform(None).unwrap();
+    assert_eq!(engine.output_transform(), None);
+
+    let output = process_char(&mut engine, 'r').unwrap();
+    assert_eq!(output.composing_text, "\u{1000}\u{103C}");
+    assert_eq!(output.action, ActionType::Insert("\u{103C}".to_string()));
+}
+
+#[test]
+fn test_ascii_output_is_identity() {
+    let mut engine = create_engine(r#""x" => "y""#).unwrap();
+    engine.set_output_transform(Some(TransformId::Zawgyi)).unwrap();
+
+    for ch in "abc".chars() {
+        process_char(&mut engine, ch).unwrap();
+    }
+    let output = process_char(&mut engine, 'x').unwrap();
+    assert_eq!(output.composing_text, "abcy");
+    assert_eq!(output.action, ActionType::Insert("y".to_string()));
+}
+
+#[test]
+fn test_process_key_test_uses_transform() {
+    let mut engine = create_engine(MYANMAR_KMS).unwrap();
+    engine.set_output_transform(Some(TransformId::Zawgyi)).unwrap();
+    process_char(&mut engine, 'k').unwrap();
+
+    let preview = engine.process_key_test(key_input_from_char('e')).unwrap();
+    assert_eq!(preview.composing_text, "\u{1031}\u{1000}");
+    assert_eq!(engine.composing_text(), "\u{1000}");
+}
+
+#[test]
+fn test_commit_is_the_text_already_emitted() {
+    let mut engine = create_engine(MYANMAR_KMS).unwrap();
+    engine.set_output_transform(Some(TransformId::Zawgyi)).unwrap();
+
+    // The host shows transformed text while composing; committing it must
+    // not transform it a second time
+    let mut host = Vec::new();
+    let mut shown = String::new();
+    for ch in "kyea".chars() {
+        let output = process_char(&mut engine, ch).unwrap();
+        apply_action(&mut host, &output.action);
+        shown = output.composing_text;
+    }
+    assert_eq!(host.iter().collect::<String>(), shown);
+    assert_eq!(engine.flush(), shown);
+    assert_eq!(engine.composing_text(), "");
+}
@@ -0,0 +1,248 @@
+//! Tests for keys that always pass through to the application unprocessed,
+//! and for host shortcut keys that pass through while nothing is composed
+#![cfg(feature = "ffi")]
+
+use keymagic_core::ffi::*;
+use keymagic_core::{ActionType, Error, VirtualKey};
+use keymagic_core::types::virtual_keys::parse_vk_names;
+use std::ffi::{CStr, CString};
+
+mod common;
+use common::*;
+
+const KMS: &str = r#"
+"k" => "က"
+<VK_ESCAPE> => "!"
+<VK_KEY_W> => "ဝ"
+<VK_SHIFT & VK_KEY_W> => "ဝါ"
+"#;
+
+#[test]
+fn test_parse_key_names() {
+    let keys = parse_vk_names(&["VK_ESCAPE", "vk_f1", " KEY_W ", "TAB"]).unwrap();
+    assert_eq!(keys, vec![VirtualKey::Escape, VirtualKey::F1, VirtualKey::KeyW, VirtualKey::Tab]);
+    assert_eq!(parse_vk_names::<&str>(&[]).unwrap(), vec![]);
+}
+
+#[test]
+fn test_parse_invalid_key_names() {
+    for name in ["VK_NOPE", "", "W", "VK_KEY_WW", "ESC APE"] {
+        match parse_vk_names(&["VK_ESCAPE", name]) {
+            Err(Error::UnknownVirtualKey(reported)) => assert_eq!(reported, name.trim()),
+            other => panic!("{:?} parsed as {:?}", name, other),
+        }
+    }
+}
+
+#[test]
+fn test_passthrough_key_skips_rules() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_passthrough_keys(&[VirtualKey::Escape, VirtualKey::KeyW]);
+    assert!(engine.is_passthrough_key(VirtualKey::Escape as u16));
+    assert!(!engine.is_passthrough_key(VirtualKey::KeyK as u16));
+
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Escape)).unwrap();
+    assert!(!output.is_processed);
+    assert_eq!(output.action, ActionType::None);
+    assert!(engine.last_matched_rules().is_empty());
+
+    // Whatever the modifiers or character
+    let output = process_key(&mut engine, key_input_with_modifiers(VirtualKey::KeyW, Some('W'), true, false, false)).unwrap();
+    assert!(!output.is_processed);
+    let output = process_key(&mut engine, key_input_vk_char(VirtualKey::KeyW, 'w')).unwrap();
+    assert!(!output.is_processed);
+    assert_eq!(engine.composing_text(), "");
+
+    // Other keys are still handled
+    let output = process_key(&mut engine, key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+    assert!(output.is_processed);
+    assert_eq!(engine.composing_text(), "က");
+}
+
+#[test]
+fn test_setting_keys_replaces_previous_set() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_passthrough_keys(&[VirtualKey::Escape]);
+    engine.set_passthrough_keys(&[VirtualKey::KeyW]);
+    assert!(!engine.is_passthrough_key(VirtualKey::Escape as u16));
+    assert!(process_key(&mut engine, key_input_from_vk(VirtualKey::Escape)).unwrap().is_processed);
+
+    engine.set_passthrough_keys(&[]);
+    assert!(process_key(&mut engine, key_input_vk_char(VirtualKey::KeyW, 'w')).unwrap().is_processed);
+}
+
+#[test]
+fn test_passthrough_keeps_composing_text() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_passthrough_keys(&[VirtualKey::Escape]);
+    process_key(&mut engine, key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Escape)).unwrap();
+    assert!(!output.is_processed);
+    assert_eq!(output.action, ActionType::None);
+    assert_eq!(output.composing_text, "က");
+    assert_eq!(engine.composing_text(), "က");
+}
+
+#[test]
+fn test_passthrough_commits_composing_text() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_passthrough_keys(&[VirtualKey::Escape]);
+    engine.set_commit_on_passthrough(true);
+    process_key(&mut engine, key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+    assert_eq!(engine.undo_depth(), 0);
+
+    // The text stays in the document; the engine only forgets it
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Escape)).unwrap();
+    assert!(!output.is_processed);
+    assert_eq!(output.action, ActionType::None);
+    assert_eq!(output.composing_text, "");
+    assert_eq!(engine.composing_text(), "");
+
+    // Nothing to commit: still a plain passthrough
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Escape)).unwrap();
+    assert!(!output.is_processed);
+}
+
+#[test]
+fn test_passthrough_in_test_mode() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_passthrough_keys(&[VirtualKey::Escape]);
+    engine.set_commit_on_passthrough(true);
+    process_key(&mut engine, key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+
+    let output = engine.process_key_test(key_input_from_vk(VirtualKey::Escape)).unwrap();
+    assert!(!output.is_processed);
+    assert_eq!(output.composing_text, "");
+    assert_eq!(engine.composing_text(), "က");
+}
+
+#[test]
+fn test_ffi_passthrough_keys() {
+    let binary = create_km2_binary(&kms2km2::compile_kms(KMS).unwrap()).unwrap();
+    let handle = keymagic_engine_new();
+    let codes = [0x1B, 0x57, 0xFFFF, -1];
+    assert_eq!(keymagic_engine_set_passthrough_keys(handle, codes.as_ptr(), 2, 1), KeyMagicResult::ErrorNoKeyboard);
+    assert_eq!(keymagic_engine_check_passthrough(handle, 0x1B), KEYMAGIC_PASSTHROUGH_NONE);
+
+    let result = keymagic_engine_load_keyboard_from_memory(handle, binary.as_ptr(), binary.len());
+    assert_eq!(result, KeyMagicResult::Success);
+    // Unknown codes are ignored
+    assert_eq!(keymagic_engine_set_passthrough_keys(handle, codes.as_ptr(), 4, 1), KeyMagicResult::Success);
+    assert_eq!(keymagic_engine_set_passthrough_keys(handle, std::ptr::null(), 1, 1), KeyMagicResult::ErrorInvalidParameter);
+
+    assert_eq!(keymagic_engine_check_passthrough(handle, 0x1B), KEYMAGIC_PASSTHROUGH);
+    assert_eq!(keymagic_engine_check_passthrough(handle, 0x4B), KEYMAGIC_PASSTHROUGH_NONE);
+
+    let mut output = empty_output();
+    keymagic_engine_process_key_win(handle, 0x4B, b'k' as std::os::raw::c_char, 0, 0, 0, 0, &mut output);
+    free_output(&output);
+    assert_eq!(keymagic_engine_check_passthrough(handle, 0x1B), KEYMAGIC_PASSTHROUGH_COMMIT);
+
+    let result = keymagic_engine_process_key_win(handle, 0x1B, 0, 0, 0, 0, 0, &mut output);
+    assert_eq!(result, KeyMagicResult::Success);
+    assert_eq!(output.is_processed, 0);
+    assert_eq!(unsafe { CStr::from_ptr(output.composing_text) }.to_str().unwrap(), "");
+    free_output(&output);
+
+    assert_eq!(keymagic_engine_set_passthrough_keys(handle, std::ptr::null(), 0, 0), KeyMagicResult::Success);
+    assert_eq!(keymagic_engine_check_passthrough(handle, 0x1B), KEYMAGIC_PASSTHROUGH_NONE);
+    keymagic_engine_free(handle);
+}
+
+#[test]
+fn test_shortcut_keys_pass_only_while_nothing_is_composed() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_shortcut_keys(&[VirtualKey::KeyK]);
+    assert!(engine.is_shortcut_key(VirtualKey::KeyK as u16));
+    assert!(!engine.is_passthrough_key(VirtualKey::KeyK as u16));
+
+    // Empty composition: the key would start one, so it goes to the host
+    assert!(engine.passes_as_shortcut(VirtualKey::KeyK as u16));
+    assert!(!engine.process_key_test(key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap().is_processed);
+    let output = process_key(&mut engine, key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+    assert!(!output.is_processed);
+    assert_eq!(output.action, ActionType::None);
+    assert_eq!(engine.composing_text(), "");
+
+    // While composing the key is typed as usual
+    process_key(&mut engine, key_input_vk_char(VirtualKey::KeyW, 'w')).unwrap();
+    assert!(!engine.passes_as_shortcut(VirtualKey::KeyK as u16));
+    assert!(engine.process_key_test(key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap().is_processed);
+    let output = process_key(&mut engine, key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+    assert!(output.is_processed);
+    assert_eq!(engine.composing_text(), "ဝက");
+
+    engine.reset();
+    assert!(engine.passes_as_shortcut(VirtualKey::KeyK as u16));
+    engine.set_shortcut_keys(&[]);
+    assert!(!engine.passes_as_shortcut(VirtualKey::KeyK as u16));
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+#[test]
+fn test_ffi_shortcut_keys() {
+    let binary = create_km2_binary(&kms2km2::compile_kms(KMS).unwrap()).unwrap();
+    let handle = keymagic_engine_new();
+    let process = wide("Chrome.exe");
+    let entries = [wide("*=VK_KEY_W"), wide("chrome.exe=VK_KEY_K,VK_NOPE"), wide("chrome.exe=VK_KEY_K"), wide("no keys")];
+    let pointers: Vec<*const u16> = entries.iter().map(|entry| entry.as_ptr()).collect();
+    let set = |handle, count| keymagic_engine_set_shortcut_keys_w(handle, process.as_ptr(), pointers.as_ptr(), count);
+    assert_eq!(set(handle, 4), KeyMagicResult::ErrorNoKeyboard);
+
+    let result = keymagic_engine_load_keyboard_from_memory(handle, binary.as_ptr(), binary.len());
+    assert_eq!(result, KeyMagicResult::Success);
+    // The exact name beats the wildcard; malformed entries are skipped
+    assert_eq!(set(handle, 4), KeyMagicResult::Success);
+    assert_eq!(keymagic_engine_check_passthrough(handle, 0x4B), KEYMAGIC_PASSTHROUGH);
+    assert_eq!(keymagic_engine_check_passthrough(handle, 0x57), KEYMAGIC_PASSTHROUGH_NONE);
+
+    let mut output = empty_output();
+    keymagic_engine_process_key_win(handle, 0x57, b'w' as std::os::raw::c_char, 0, 0, 0, 0, &mut output);
+    free_output(&output);
+    assert_eq!(keymagic_engine_check_passthrough(handle, 0x4B), KEYMAGIC_PASSTHROUGH_NONE);
+
+    assert_eq!(set(handle, 1), KeyMagicResult::Success);
+    keymagic_engine_reset(handle);
+    assert_eq!(keymagic_engine_check_passthrough(handle, 0x57), KEYMAGIC_PASSTHROUGH);
+    assert_eq!(
+        keymagic_engine_set_shortcut_keys_w(handle, std::ptr::null(), pointers.as_ptr(), 1),
+        KeyMagicResult::ErrorInvalidParameter
+    );
+    keymagic_engine_free(handle);
+}
+
+fn empty_output() -> ProcessKeyOutput {
+    ProcessKeyOutput {
+        action_type: 0,
+        text: std::ptr::null_mut(),
+        delete_count: 0,
+        composing_text: std::ptr::null_mut(),
+        is_processed: 0,
+        delete_utf16_count: 0,
+        composing_caret_utf16: 0,
+        notification: std::ptr::null_mut(),
+        delete_clamped: 0,
+    }
+}
+
+fn free_output(output: &ProcessKeyOutput) {
+    if !output.text.is_null() {
+        keymagic_free_string(output.text);
+    }
+    if !output.composing_text.is_null() {
+        keymagic_free_string(output.composing_text);
+    }
+}
+
+#[test]
+fn test_ffi_key_names() {
+    let name = |text: &str| CString::new(text).unwrap();
+    assert_eq!(keymagic_vk_from_name(name("VK_ESCAPE").as_ptr()), 0x1B);
+    assert_eq!(keymagic_vk_from_name(name("key_w").as_ptr()), 0x57);
+    assert_eq!(keymagic_vk_from_name(name("VK_NOPE").as_ptr()), 0);
+    assert_eq!(keymagic_vk_from_name(std::ptr::null()), 0);
+}
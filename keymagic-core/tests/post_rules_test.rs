@@ -0,0 +1,195 @@
+//! Tests for `@post` rules, applied to the composing text after every key
+
+use keymagic_core::conformance::{parse_cases, run_case};
+use keymagic_core::{KeyMagicEngine, KmsError, VirtualKey};
+use keymagic_core::types::km2::PostRules;
+
+mod common;
+use common::*;
+
+const POST_KMS: &str = r#"
+"f" => U103A
+";" => U1038
+"k" => U1000
+
+@post
+// Visarga typed before asat
+U1038 + U103A => U103A + U1038
+@endpost
+
+"x" => "y"
+"#;
+
+fn type_text(engine: &mut KeyMagicEngine, text: &str) -> String {
+    for ch in text.chars() {
+        process_char(engine, ch).unwrap();
+    }
+    engine.composing_text().to_string()
+}
+
+#[test]
+fn test_post_rules_are_compiled_into_metadata() {
+    let km2 = kms2km2::compile_kms(POST_KMS).unwrap();
+    let post = km2.metadata().post_rules();
+    assert_eq!(post, PostRules::from_flags([false, false, false, true]));
+    assert!(post.contains(3));
+    assert!(!post.contains(4));
+
+    let engine = create_engine(POST_KMS).unwrap();
+    assert!(engine.is_post_rule(3));
+    assert!(!engine.is_post_rule(0));
+
+    let plain = kms2km2::compile_kms("\"a\" => \"b\"").unwrap();
+    assert!(plain.metadata().post_rules().is_empty());
+    assert!(!plain.metadata().has(keymagic_core::types::km2::INFO_POST));
+}
+
+#[test]
+fn test_flags_encoding() {
+    let flags = PostRules::from_flags((0..20).map(|i| i == 0 || i == 9));
+    assert_eq!(flags.encode(), vec![0b0000_0001, 0b0000_0010]);
+    assert_eq!(PostRules::decode(&flags.encode()), flags);
+    // Trailing rules that are not post-rules are left out
+    assert_eq!(PostRules::from_flags([true, false, false]).encode(), vec![1]);
+    assert!(PostRules::from_flags([false; 12]).is_empty());
+}
+
+#[test]
+fn test_post_rule_applies_after_key() {
+    let mut engine = create_engine(POST_KMS).unwrap();
+    assert_eq!(type_text(&mut engine, "k;"), "\u{1000}\u{1038}");
+    let output = process_char(&mut engine, 'f').unwrap();
+    assert_eq!(output.composing_text, "\u{1000}\u{103A}\u{1038}");
+    assert_eq!(engine.last_matched_rules(), &[0, 3]);
+    assert!(engine.is_post_rule(engine.last_matched_rules()[1]));
+}
+
+#[test]
+fn test_post_rules_never_match_as_key_rules() {
+    // Without the post pass "ab" would be typed as "b" by the normal rules
+    let mut engine = create_engine("@post\n\"ab\" => \"X\"\n@endpost\n\"a\" + \"b\" => \"b\"").unwrap();
+    let output = process_char(&mut engine, 'a').unwrap();
+    assert_eq!(output.composing_text, "a");
+    // The key rule wins and the post rule no longer sees "ab"
+    assert_eq!(type_text(&mut engine, "b"), "b");
+    assert_eq!(engine.last_matched_rules(), &[1]);
+}
+
+#[test]
+fn test_post_rules_run_after_normal_rules() {
+    let kms = r#"
+"ab" => "X"
+@post
+"X" => "Y"
+"kY" => "Z"
+@endpost
+"#;
+    let mut engine = create_engine(kms).unwrap();
+    assert_eq!(type_text(&mut engine, "ab"), "Y");
+    assert_eq!(engine.last_matched_rules(), &[0, 1]);
+
+    // Unmatched characters are appended first, then corrected in a chain
+    engine.reset();
+    assert_eq!(type_text(&mut engine, "kab"), "Z");
+    assert_eq!(engine.last_matched_rules(), &[0, 1, 2]);
+}
+
+#[test]
+fn test_post_rules_follow_priority_order() {
+    let kms = "@post\n\"b\" => \"1\"\n\"ab\" => \"2\"\n@endpost";
+    let mut engine = create_engine(kms).unwrap();
+    // The longer pattern is tried first, as for other rules
+    assert_eq!(type_text(&mut engine, "ab"), "2");
+}
+
+#[test]
+fn test_fixpoint_terminates() {
+    // A rule that rewrites the text into itself stops the pass at once
+    let mut engine = create_engine("@post\n\"x\" => \"x\"\n@endpost").unwrap();
+    assert_eq!(type_text(&mut engine, "x"), "x");
+    assert!(engine.last_matched_rules().is_empty());
+
+    // Rules that undo each other are cut off by the recursion guard
+    let mut engine = create_engine("@post\n\"ab\" => \"ba\"\n\"ba\" => \"ab\"\n@endpost").unwrap();
+    let text = type_text(&mut engine, "ab");
+    assert!(text == "ab" || text == "ba");
+    assert_eq!(engine.last_matched_rules().len(), 10);
+}
+
+#[test]
+fn test_backspace_does_not_run_post_rules() {
+    let mut engine = create_engine("@post\n\"ab\" => \"X\"\n@endpost").unwrap();
+    engine.set_composing_text("abc".to_string());
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Back)).unwrap();
+    assert!(output.is_processed);
+    assert_eq!(output.composing_text, "ab");
+    assert!(engine.last_matched_rules().is_empty());
+}
+
+#[test]
+fn test_smart_backspace_undoes_correction_with_key() {
+    let kms = format!("/*\n@SMART_BACKSPACE = \"TRUE\"\n*/\n{}", POST_KMS);
+    let mut engine = create_engine(&kms).unwrap();
+    assert_eq!(type_text(&mut engine, ";f"), "\u{103A}\u{1038}");
+
+    // The key and its correction are one step
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Back)).unwrap();
+    assert_eq!(output.composing_text, "\u{1038}");
+}
+
+#[test]
+fn test_post_rules_respect_disabled_groups() {
+    let kms = "@group \"fixes\"\n@post\n\"ab\" => \"X\"\n@endpost\n@endgroup";
+    let mut engine = create_engine(kms).unwrap();
+    engine.set_group_enabled("fixes", false).unwrap();
+    assert_eq!(type_text(&mut engine, "ab"), "ab");
+    engine.set_group_enabled("fixes", true).unwrap();
+    engine.reset();
+    assert_eq!(type_text(&mut engine, "ab"), "X");
+}
+
+#[test]
+fn test_test_mode_matches_processing() {
+    let mut engine = create_engine(POST_KMS).unwrap();
+    type_text(&mut engine, ";");
+    let preview = engine.process_key_test(key_input_from_char('f')).unwrap();
+    assert_eq!(preview.composing_text, "\u{103A}\u{1038}");
+    assert_eq!(engine.composing_text(), "\u{1038}");
+}
+
+#[test]
+fn test_trace_marks_post_rules() {
+    let mut engine = create_engine(POST_KMS).unwrap();
+    let cases = parse_cases("== visarga\n; => း\nf => ်\n").unwrap();
+    let result = run_case(&mut engine, &cases[0]).unwrap();
+    let divergence = result.divergence.unwrap();
+    assert_eq!(divergence.actual, "\u{103A}\u{1038}");
+    assert_eq!(divergence.trace.len(), 2);
+    assert!(divergence.trace[0].starts_with("#0: "), "{}", divergence.trace[0]);
+    assert!(divergence.trace[1].starts_with("#3 (post): "), "{}", divergence.trace[1]);
+}
+
+fn compile_error(kms: &str) -> (usize, String) {
+    match kms2km2::compile_kms(kms) {
+        Err(KmsError::Parse { line, message }) => (line, message),
+        other => panic!("{:?} compiled to {:?}", kms, other.map(|km2| km2.rules.len())),
+    }
+}
+
+#[test]
+fn test_post_rules_cannot_match_keys() {
+    let (line, message) = compile_error("\"a\" => \"b\"\n@post\n\"x\" + <VK_SHIFT & VK_KEY_A> => \"y\"\n@endpost");
+    assert_eq!(line, 3);
+    assert!(message.contains("<VK_SHIFT & VK_KEY_A>"), "{}", message);
+    assert!(message.contains("@post"), "{}", message);
+
+    let (_, message) = compile_error("@post\n('on') => \"y\"\n@endpost");
+    assert!(message.contains("composing text"), "{}", message);
+}
+
+#[test]
+fn test_unbalanced_post_sections_fail_to_compile() {
+    assert!(compile_error("@post\n\"x\" => \"y\"").1.contains("Missing @endpost"));
+    assert!(compile_error("\"x\" => \"y\"\n@endpost").1.contains("without matching"));
+    assert!(compile_error("@post\n@post\n\"x\" => \"y\"\n@endpost\n@endpost").1.contains("nested"));
+}
@@ -0,0 +1,112 @@
+//! Tests for asking whether a key leaves text or states a later rule can use
+
+use keymagic_core::{KeyMagicEngine, PrefixResult, VirtualKey};
+
+mod common;
+use common::*;
+
+const PREFIX_KMS: &str = r#"
+$cons = U1000 + U1001 + U1002
+
+"k" => U1000
+"x" => "y"
+"s" + "h" => U101B
+$cons[*] + "j" => $1 + U103B
+"n" + <VK_KEY_M> => U100F
+<VK_SHIFT & VK_KEY_W> => U101D
+
+"z" => ('zg')
+('zg') + "a" => U1021
+
+@group "autocorrect"
+"y" + "y" => U101A
+@endgroup
+"#;
+
+fn prefix(engine: &KeyMagicEngine, ch: char) -> PrefixResult {
+    engine.is_prefix(&key_input_from_char(ch))
+}
+
+#[test]
+fn test_single_key_rules() {
+    let mut engine = create_engine(PREFIX_KMS).unwrap();
+    engine.set_group_enabled("autocorrect", false).unwrap();
+
+    assert_eq!(prefix(&engine, 'x'), PrefixResult::CompleteMatchOnly);
+    assert_eq!(prefix(&engine, 'q'), PrefixResult::No);
+    let shift_w = key_input_with_modifiers(VirtualKey::KeyW, Some('W'), true, false, false);
+    assert_eq!(engine.is_prefix(&shift_w), PrefixResult::CompleteMatchOnly);
+
+    // Nothing is processed
+    assert_eq!(engine.composing_text(), "");
+    assert!(engine.last_matched_rules().is_empty());
+}
+
+#[test]
+fn test_multi_key_sequences() {
+    let mut engine = create_engine(PREFIX_KMS).unwrap();
+
+    assert_eq!(prefix(&engine, 's'), PrefixResult::PrefixOfLonger);
+    process_char(&mut engine, 's').unwrap();
+    assert_eq!(prefix(&engine, 'h'), PrefixResult::CompleteMatchOnly);
+    process_char(&mut engine, 'h').unwrap();
+    assert_eq!(engine.composing_text(), "\u{101B}");
+
+    // Text a virtual key rule matches before its key
+    engine.reset();
+    assert_eq!(prefix(&engine, 'n'), PrefixResult::PrefixOfLonger);
+
+    // A rule in a group leaves its first key a prefix only while enabled
+    engine.reset();
+    assert_eq!(prefix(&engine, 'x'), PrefixResult::PrefixOfLonger);
+    engine.set_group_enabled("autocorrect", false).unwrap();
+    assert_eq!(prefix(&engine, 'x'), PrefixResult::CompleteMatchOnly);
+}
+
+#[test]
+fn test_variable_initial_rules() {
+    let engine = create_engine(PREFIX_KMS).unwrap();
+
+    // Any consonant the variable holds may start the rule
+    assert_eq!(prefix(&engine, 'k'), PrefixResult::PrefixOfLonger);
+
+    let mut engine = create_engine(PREFIX_KMS).unwrap();
+    engine.set_composing_text("\u{1001}".to_string());
+    assert_eq!(prefix(&engine, 'q'), PrefixResult::No);
+    // A key no rule takes leaves the consonant in place
+    assert_eq!(engine.is_prefix(&key_input_from_vk(VirtualKey::Escape)), PrefixResult::PrefixOfLonger);
+}
+
+#[test]
+fn test_state_dependent_rules() {
+    let mut engine = create_engine(PREFIX_KMS).unwrap();
+
+    // The state only lasts until the next key, which may use it
+    assert_eq!(prefix(&engine, 'z'), PrefixResult::PrefixOfLonger);
+    assert_eq!(prefix(&engine, 'a'), PrefixResult::No);
+
+    process_char(&mut engine, 'z').unwrap();
+    assert_eq!(prefix(&engine, 'a'), PrefixResult::CompleteMatchOnly);
+    assert_eq!(prefix(&engine, 'q'), PrefixResult::No);
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn test_ffi_is_prefix() {
+    use keymagic_core::ffi::*;
+    use std::os::raw::c_char;
+
+    let binary = create_km2_binary(&kms2km2::compile_kms(PREFIX_KMS).unwrap()).unwrap();
+    let handle = keymagic_engine_new();
+    assert_eq!(keymagic_engine_is_prefix_win(handle, 0x51, b'q' as c_char, 0, 0, 0, 0), KEYMAGIC_PREFIX_OF_LONGER);
+
+    let result = keymagic_engine_load_keyboard_from_memory(handle, binary.as_ptr(), binary.len());
+    assert_eq!(result, KeyMagicResult::Success);
+    assert_eq!(keymagic_engine_is_prefix_win(handle, 0x51, b'q' as c_char, 0, 0, 0, 0), KEYMAGIC_PREFIX_NO);
+    assert_eq!(keymagic_engine_is_prefix_win(handle, 0x53, b's' as c_char, 0, 0, 0, 0), KEYMAGIC_PREFIX_OF_LONGER);
+    assert_eq!(keymagic_engine_is_prefix_win(handle, 0x57, b'W' as c_char, 1, 0, 0, 0), KEYMAGIC_PREFIX_COMPLETE_MATCH_ONLY);
+    assert_eq!(keymagic_engine_is_prefix(handle, VirtualKey::KeyS as i32, b's' as c_char, 0, 0, 0, 0), KEYMAGIC_PREFIX_OF_LONGER);
+    assert_eq!(keymagic_engine_is_prefix(std::ptr::null_mut(), 0x53, b's' as c_char, 0, 0, 0, 0), KEYMAGIC_PREFIX_OF_LONGER);
+
+    keymagic_engine_free(handle);
+}
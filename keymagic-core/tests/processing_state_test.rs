@@ -0,0 +1,222 @@
+//! Disable handshake between the GUI and text services
+#![cfg(feature = "host-ipc")]
+
+mod common;
+use common::*;
+
+use keymagic_core::processing_state::*;
+use keymagic_core::{SharedEngine, VirtualKey};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs one host round against the switch, flushing right away
+fn host_round(state: &ProcessingState, host: &mut HostProcessingState) -> HostAction {
+    let action = host.observe(state.is_enabled(), state.request());
+    match action {
+        HostAction::FlushThenDisable { request } => {
+            if let Some(request) = host.finish_disable(request) {
+                state.acknowledge(request);
+            }
+        }
+        HostAction::Acknowledge { request } => state.acknowledge(request),
+        _ => {}
+    }
+    action
+}
+
+#[test]
+fn test_new_state_is_enabled() {
+    let state = ProcessingState::in_memory();
+    assert!(state.is_enabled());
+    assert_eq!(state.request(), 0);
+    assert!(state.acknowledged(0));
+    assert!(HostProcessingState::new().is_enabled());
+}
+
+#[test]
+fn test_enabled_host_flushes_before_disabling() {
+    let state = ProcessingState::in_memory();
+    let mut host = HostProcessingState::new();
+
+    let request = state.request_disable();
+    assert_eq!(request, 1);
+    assert!(!state.is_enabled());
+    assert!(!state.acknowledged(request));
+
+    assert_eq!(host.observe(false, request), HostAction::FlushThenDisable { request });
+    // Keys keep reaching the engine until the flush is done
+    assert!(host.is_enabled());
+    assert_eq!(host.pending(), Some(request));
+    // The same request seen again while flushing is not flushed twice
+    assert_eq!(host.observe(false, request), HostAction::None);
+
+    assert_eq!(host.finish_disable(request), Some(request));
+    assert!(!host.is_enabled());
+    assert_eq!(host.pending(), None);
+    state.acknowledge(request);
+    assert!(state.acknowledged(request));
+
+    assert_eq!(host.observe(false, request), HostAction::None);
+}
+
+#[test]
+fn test_disabled_host_acknowledges_without_flush() {
+    let state = ProcessingState::in_memory();
+    let mut host = HostProcessingState::new();
+    let first = state.request_disable();
+    host_round(&state, &mut host);
+
+    let second = state.request_disable();
+    assert_eq!(host_round(&state, &mut host), HostAction::Acknowledge { request: second });
+    assert!(state.acknowledged(second));
+    assert!(state.acknowledged(first));
+}
+
+#[test]
+fn test_enable_switches_host_back_on() {
+    let state = ProcessingState::in_memory();
+    let mut host = HostProcessingState::new();
+    state.request_disable();
+    host_round(&state, &mut host);
+
+    state.enable();
+    assert_eq!(host_round(&state, &mut host), HostAction::Enable);
+    assert!(host.is_enabled());
+    assert_eq!(host_round(&state, &mut host), HostAction::None);
+}
+
+#[test]
+fn test_enable_during_flush_keeps_processing() {
+    let mut host = HostProcessingState::new();
+    assert_eq!(host.observe(false, 1), HostAction::FlushThenDisable { request: 1 });
+    // Switched back on before the flush finished
+    assert_eq!(host.observe(true, 1), HostAction::None);
+    assert_eq!(host.finish_disable(1), None);
+    assert!(host.is_enabled());
+}
+
+#[test]
+fn test_newer_request_supersedes_pending_flush() {
+    let mut host = HostProcessingState::new();
+    assert_eq!(host.observe(false, 1), HostAction::FlushThenDisable { request: 1 });
+    assert_eq!(host.observe(false, 2), HostAction::FlushThenDisable { request: 2 });
+    assert_eq!(host.finish_disable(1), None);
+    assert!(host.is_enabled());
+    assert_eq!(host.finish_disable(2), Some(2));
+    assert!(!host.is_enabled());
+    // Stale requests are ignored
+    assert_eq!(host.observe(false, 1), HostAction::None);
+}
+
+#[test]
+fn test_acknowledgements_only_move_forward() {
+    let state = ProcessingState::in_memory();
+    let first = state.request_disable();
+    let second = state.request_disable();
+    state.acknowledge(second);
+    state.acknowledge(first);
+    assert!(state.acknowledged(first));
+    assert!(state.acknowledged(second));
+    assert!(!state.acknowledged(second + 1));
+}
+
+#[test]
+fn test_wait_for_ack_times_out() {
+    let state = ProcessingState::in_memory();
+    let request = state.request_disable();
+    let started = Instant::now();
+    assert!(!state.wait_for_ack(request, Duration::from_millis(30)));
+    assert!(started.elapsed() >= Duration::from_millis(30));
+}
+
+#[test]
+fn test_wait_for_ack_sees_host_on_another_thread() {
+    let state = Arc::new(ProcessingState::in_memory());
+    let request = state.request_disable();
+    let host = {
+        let state = state.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let mut host = HostProcessingState::new();
+            host_round(&state, &mut host)
+        })
+    };
+    assert!(state.wait_for_ack(request, DEFAULT_ACK_TIMEOUT * 5));
+    assert_eq!(host.join().unwrap(), HostAction::FlushThenDisable { request });
+}
+
+#[test]
+fn test_flush_returns_composition_and_resets() {
+    let engine = SharedEngine::new(create_engine(r#""ka" => "က""#).unwrap());
+    engine.process_key(key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+    engine.process_key(key_input_vk_char(VirtualKey::KeyA, 'a')).unwrap();
+    assert_eq!(engine.composing_text(), "က");
+
+    assert_eq!(engine.flush(), "က");
+    assert_eq!(engine.composing_text(), "");
+    assert_eq!(engine.flush(), "");
+}
+
+#[test]
+fn test_heartbeat_is_recorded() {
+    let state = ProcessingState::in_memory();
+    assert_eq!(state.last_heartbeat(), None);
+    assert_eq!(state.beat(1_000), None);
+    assert_eq!(state.beat(3_000), Some(1_000));
+    assert_eq!(state.last_heartbeat(), Some(3_000));
+}
+
+#[test]
+fn test_heartbeat_status() {
+    let ms = |duration: Duration| duration.as_millis() as u64;
+    let now = 100_000;
+    assert_eq!(HeartbeatStatus::at(None, now), HeartbeatStatus::Missing);
+    assert_eq!(HeartbeatStatus::at(Some(now - 1_000), now), HeartbeatStatus::Fresh { age: Duration::from_secs(1) });
+    // A clock set back makes the heartbeat look like it is from the future
+    assert_eq!(HeartbeatStatus::at(Some(now + 5_000), now), HeartbeatStatus::Fresh { age: Duration::ZERO });
+
+    let late = now - ms(HEARTBEAT_INTERVAL * 3);
+    assert!(matches!(HeartbeatStatus::at(Some(late), now), HeartbeatStatus::Late { .. }));
+    let stale = now - ms(HEARTBEAT_STALE_AFTER) - 1;
+    assert_eq!(HeartbeatStatus::at(Some(stale), now).age(), Some(HEARTBEAT_STALE_AFTER + Duration::from_millis(1)));
+    assert!(matches!(HeartbeatStatus::at(Some(stale), now), HeartbeatStatus::Stale { .. }));
+}
+
+#[test]
+fn test_gui_liveness_needs_stale_heartbeat_and_no_mutex() {
+    let stale_age = HEARTBEAT_STALE_AFTER.as_millis() as u64 + 1_000;
+    let now = 1_000_000;
+    let mut liveness = GuiLiveness::new();
+
+    // No heartbeat at all: the GUI never ran, processing is left alone
+    assert!(liveness.observe(None, now, false));
+    assert!(liveness.observe(Some(now - 1_000), now, false));
+    // A stale heartbeat while the mutex is held is a busy GUI, not a dead one
+    assert!(liveness.observe(Some(now - stale_age), now, true));
+    assert!(!liveness.is_gone());
+
+    assert!(!liveness.observe(Some(now - stale_age), now, false));
+    assert!(liveness.is_gone());
+}
+
+#[test]
+fn test_gui_liveness_hysteresis() {
+    let now = 1_000_000;
+    let stale = now - HEARTBEAT_STALE_AFTER.as_millis() as u64 - 1;
+    let mut liveness = GuiLiveness::new();
+    assert!(!liveness.observe(Some(stale), now, false));
+
+    // A restarting GUI holds its mutex before it beats: still gone
+    assert!(!liveness.observe(Some(stale), now, true));
+    // A heartbeat that is merely not stale is not enough either
+    let between = now - HEARTBEAT_FRESH_WITHIN.as_millis() as u64 - 1_000;
+    assert!(!liveness.observe(Some(between), now, true));
+    // A fresh one brings processing back
+    assert!(liveness.observe(Some(now - 500), now, false));
+    assert!(!liveness.is_gone());
+
+    // A restart shorter than the stale age never switches processing off
+    let restart = now - (HEARTBEAT_STALE_AFTER.as_millis() as u64) / 2;
+    assert!(liveness.observe(Some(restart), now, false));
+}
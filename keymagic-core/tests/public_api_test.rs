@@ -0,0 +1,95 @@
+//! Snapshot of the public API at the crate root and the public modules
+//!
+//! Each item, signature and module third parties build on is named here, so a
+//! change that would break them fails to compile. Changing this file means the
+//! next release needs a semver-major version bump.
+
+use keymagic_core::{
+    ActionType, EngineOutput, EnumerationProgress, Error, KeyInput, KeyMagicEngine, Km2Error, Km2File, Km2Loader,
+    KmsError, ModifierState, OutputEntry, OutputEnumerator, Result, RuleHistogram, SharedEngine, VirtualKey,
+    VERSION,
+};
+use keymagic_core::types::km2::LayoutOverrides;
+
+#[test]
+fn test_engine_signatures() {
+    let _: fn(Km2File) -> Result<KeyMagicEngine> = KeyMagicEngine::new;
+    let _: fn(Km2File, &LayoutOverrides) -> Result<KeyMagicEngine> = KeyMagicEngine::with_options;
+    let _: fn(&mut KeyMagicEngine, KeyInput) -> Result<EngineOutput> = KeyMagicEngine::process_key;
+    let _: fn(&mut KeyMagicEngine) = KeyMagicEngine::reset;
+    let _: fn(&mut KeyMagicEngine) -> String = KeyMagicEngine::flush;
+    let _: fn(&KeyMagicEngine) -> &str = KeyMagicEngine::composing_text;
+    let _: fn(&mut KeyMagicEngine, String) = KeyMagicEngine::set_composing_text;
+    let _: fn(&KeyMagicEngine) -> &Km2File = KeyMagicEngine::keyboard;
+    let _: fn(&KeyMagicEngine) -> Option<&RuleHistogram> = KeyMagicEngine::rule_histogram;
+
+    let _: fn(KeyMagicEngine) -> SharedEngine = SharedEngine::new;
+    let _: fn(Km2File) -> Result<SharedEngine> = SharedEngine::from_keyboard;
+    let _: fn(&SharedEngine, KeyInput) -> Result<EngineOutput> = SharedEngine::process_key;
+
+    let _: fn(&[u8]) -> std::result::Result<Km2File, Km2Error> = Km2Loader::load;
+    let _: fn(&KeyMagicEngine, usize) -> OutputEnumerator<'_> = KeyMagicEngine::enumerate_outputs;
+    let _: fn(&OutputEnumerator<'_>) -> EnumerationProgress = |enumerator| enumerator.progress();
+    let _: &str = VERSION;
+}
+
+#[test]
+fn test_input_and_output_types() {
+    let _: fn(u16, ModifierState, Option<char>) -> KeyInput = KeyInput::new;
+    let _: fn(char) -> KeyInput = KeyInput::from_char;
+    let _: fn(bool, bool, bool, bool) -> ModifierState = ModifierState::new;
+
+    let input = KeyInput { key_code: VirtualKey::KeyA as u16, modifiers: ModifierState::default(), character: Some('a') };
+    let ModifierState { shift: _, ctrl: _, alt: _, caps_lock: _ } = input.modifiers;
+
+    let output = EngineOutput::new(String::new(), ActionType::BackspaceDelete(1), "a", true);
+    let EngineOutput { composing_text: _, action, is_processed: _, delete_chars: _, delete_utf16_units: _, composing_caret: _, notification: _, clamped: _, suggestions: _ } =
+        output;
+    // ActionType is non-exhaustive, so matches outside the crate need a
+    // fallback arm
+    match action {
+        ActionType::None | ActionType::Insert(_) | ActionType::BackspaceDelete(_) => {}
+        ActionType::BackspaceDeleteAndInsert(_, _) => {}
+        _ => {}
+    }
+}
+
+#[test]
+fn test_error_types() {
+    let error: Error = Km2Error::FileTooSmall(0).into();
+    assert!(matches!(error, Error::Km2Error(_)));
+    let _: fn(KmsError) -> Box<dyn std::error::Error> = |e| Box::new(e);
+    let _: Option<OutputEntry> = None;
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn test_ffi_result_codes() {
+    use keymagic_core::ffi::KeyMagicResult;
+
+    // Values are part of the C ABI
+    assert_eq!(KeyMagicResult::Success as i32, 0);
+    assert_eq!(KeyMagicResult::ErrorInvalidHandle as i32, -1);
+    assert_eq!(KeyMagicResult::ErrorInvalidParameter as i32, -2);
+    assert_eq!(KeyMagicResult::ErrorEngineFailure as i32, -3);
+    assert_eq!(KeyMagicResult::ErrorUtf8Conversion as i32, -4);
+    assert_eq!(KeyMagicResult::ErrorNoKeyboard as i32, -5);
+}
+
+// Public modules; `engine` is private and reached through the root exports
+#[allow(unused_imports)]
+mod public_modules {
+    use keymagic_core::{analysis, conformance, error, hotkey, km2, locale, transform, types};
+
+    #[cfg(feature = "ffi")]
+    use keymagic_core::ffi;
+    #[cfg(feature = "env-config")]
+    use keymagic_core::env_config;
+
+    // State shared by the GUI and the input methods
+    #[cfg(feature = "host-ipc")]
+    use keymagic_core::{
+        char_suppression, commit_log, composition_journal, context_store, input_latency, input_mode, key_event,
+        key_stats, load_log, notification, paths, processing_state, recorder, shortcut_watch, tray_icon,
+    };
+}
@@ -0,0 +1,121 @@
+//! Tests for the engine reset levels
+
+mod common;
+use common::*;
+
+use keymagic_core::{KeyMagicEngine, ResetLevel, VirtualKey};
+
+const KEYBOARD: &str = r#"/*
+@SMART_BACKSPACE = "TRUE"
+*/
+< VK_F2 > => ('numbers')
+('numbers') + "1" => "၁"
+"k" => "က"
+@group "extra"
+"y" => "ျ"
+@endgroup
+"#;
+
+/// An engine with synced host text, a composition, undo history, a state on
+/// and the rules of the last key
+fn engine_mid_composition() -> KeyMagicEngine {
+    let mut engine = KeyMagicEngine::new(kms2km2::compile_kms(KEYBOARD).unwrap()).unwrap();
+    engine.set_rule_histogram_enabled(true);
+    engine.set_group_enabled("extra", false).unwrap();
+
+    engine.set_composing_text("ab".to_string());
+    process_char(&mut engine, 'k').unwrap();
+    process_key(&mut engine, key_input_from_vk(VirtualKey::F2)).unwrap();
+
+    assert_eq!(engine.composing_text(), "abက");
+    assert_eq!(engine.composing_caret(), 3);
+    assert_eq!(engine.undo_depth(), 2);
+    assert_eq!(engine.active_states(), vec![0]);
+    assert_eq!(engine.last_matched_rules(), &[0]);
+    engine
+}
+
+/// Settings every level keeps
+fn assert_settings_kept(engine: &KeyMagicEngine) {
+    assert!(!engine.is_group_enabled("extra"));
+    let histogram = engine.rule_histogram().unwrap();
+    assert_eq!(histogram.keys(), 2);
+    assert_eq!(histogram.hits()[..3], [1, 0, 1]);
+}
+
+#[test]
+fn test_composing_level_keeps_states() {
+    let mut engine = engine_mid_composition();
+    engine.reset_level(ResetLevel::Composing);
+
+    assert_eq!(engine.composing_text(), "");
+    assert_eq!(engine.composing_caret(), 0);
+    assert_eq!(engine.undo_depth(), 0);
+    assert_eq!(engine.active_states(), vec![0]);
+    assert_eq!(engine.last_matched_rules(), &[0]);
+    assert_settings_kept(&engine);
+
+    // The state still applies to the next key
+    let output = process_char(&mut engine, '1').unwrap();
+    assert_eq!(output.composing_text, "၁");
+    // Only what the engine typed since may be deleted: the synced "ab" is gone
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Back)).unwrap();
+    assert_eq!(output.composing_text, "");
+    assert_eq!(output.delete_chars, 1);
+}
+
+#[test]
+fn test_context_level_clears_states() {
+    let mut engine = engine_mid_composition();
+    engine.reset_level(ResetLevel::Context);
+
+    assert_eq!(engine.composing_text(), "");
+    assert_eq!(engine.undo_depth(), 0);
+    assert!(engine.active_states().is_empty());
+    assert_eq!(engine.last_matched_rules(), &[0]);
+    assert_settings_kept(&engine);
+
+    let output = process_char(&mut engine, '1').unwrap();
+    assert_ne!(output.composing_text, "၁");
+}
+
+#[test]
+fn test_full_level_is_reset() {
+    let mut engine = engine_mid_composition();
+    engine.reset_level(ResetLevel::Full);
+
+    assert_eq!(engine.composing_text(), "");
+    assert_eq!(engine.undo_depth(), 0);
+    assert!(engine.active_states().is_empty());
+    assert!(engine.last_matched_rules().is_empty());
+    assert_settings_kept(&engine);
+
+    let mut reset = engine_mid_composition();
+    reset.reset();
+    assert_eq!(reset.composing_text(), engine.composing_text());
+    assert_eq!(reset.active_states(), engine.active_states());
+    assert_eq!(reset.last_matched_rules(), engine.last_matched_rules());
+}
+
+#[cfg(feature = "ffi")]
+mod bridge {
+    use super::*;
+    use keymagic_core::ffi::*;
+
+    #[test]
+    fn test_reset_level_through_ffi() {
+        let binary = create_km2_binary(&kms2km2::compile_kms(KEYBOARD).unwrap()).unwrap();
+        let engine = keymagic_engine_new();
+        assert_eq!(keymagic_engine_reset_level(engine, 0), KeyMagicResult::ErrorNoKeyboard);
+        let result = keymagic_engine_load_keyboard_from_memory(engine, binary.as_ptr(), binary.len());
+        assert_eq!(result, KeyMagicResult::Success);
+
+        for level in 0..=2 {
+            assert_eq!(keymagic_engine_reset_level(engine, level), KeyMagicResult::Success);
+        }
+        assert_eq!(keymagic_engine_reset_level(engine, 3), KeyMagicResult::ErrorInvalidParameter);
+        assert_eq!(keymagic_engine_reset_level(engine, -1), KeyMagicResult::ErrorInvalidParameter);
+        assert_eq!(keymagic_engine_reset_level(std::ptr::null_mut(), 0), KeyMagicResult::ErrorInvalidParameter);
+        keymagic_engine_free(engine);
+    }
+}
@@ -0,0 +1,233 @@
+//! Tests for rule shadowing detection, long deletes and corpus coverage
+
+use keymagic_core::analysis::{analyze_keyboard, find_long_deletes, find_shadowed_rules, run_coverage};
+use keymagic_core::KeyMagicEngine;
+
+fn engine(kms: &str) -> KeyMagicEngine {
+    KeyMagicEngine::new(kms2km2::compile_kms(kms).unwrap()).unwrap()
+}
+
+/// Returns (rule, shadowed_by) pairs
+fn shadowed(kms: &str) -> Vec<(usize, usize)> {
+    find_shadowed_rules(&engine(kms))
+        .into_iter()
+        .map(|s| (s.rule, s.shadowed_by))
+        .collect()
+}
+
+#[test]
+fn test_duplicate_rule_is_shadowed() {
+    let kms = r#"
+        "a" => "1"
+        "b" => "2"
+        "a" => "3"
+    "#;
+    assert_eq!(shadowed(kms), vec![(2, 0)]);
+
+    let report = find_shadowed_rules(&engine(kms));
+    assert_eq!(report[0].text, r#""a" => "3""#);
+    assert_eq!(report[0].shadowed_by_text, r#""a" => "1""#);
+}
+
+#[test]
+fn test_longer_rules_are_not_shadowed_by_shorter_ones() {
+    // "ka" is checked before "a" because longer patterns have priority
+    assert!(shadowed(r#"
+        "a" => "1"
+        "ka" => "2"
+    "#).is_empty());
+
+    // A longer pattern can shadow a shorter rule of equal priority only if it
+    // always matches, which it cannot
+    assert!(shadowed(r#"
+        "ka" => "1"
+        "a" => "2"
+    "#).is_empty());
+}
+
+#[test]
+fn test_any_shadowing() {
+    // ANY matches every printable ASCII character, so a later single
+    // character rule of the same length never wins
+    assert_eq!(shadowed(r#"
+        ANY => $1
+        "b" => "B"
+        U1000 => "k"
+    "#), vec![(1, 0)]);
+
+    // The specific rule listed first is fine, and ANY still matches other keys
+    assert!(shadowed(r#"
+        "b" => "B"
+        ANY => $1
+    "#).is_empty());
+
+    // ANY suffix covers a longer literal with the same trailing class
+    assert_eq!(shadowed(r#"
+        "x" + ANY => "1"
+        "x" + "y" => "2"
+    "#), vec![(1, 0)]);
+}
+
+#[test]
+fn test_variable_shadowing() {
+    // [*] covers every character of the variable
+    assert_eq!(shadowed(r#"
+        $v = "abc"
+        $v[*] => "x"
+        "b" => "y"
+        "d" => "z"
+    "#), vec![(1, 0)]);
+
+    // [^] covers characters outside the variable and other negations of supersets
+    assert_eq!(shadowed(r#"
+        $v = "abc"
+        $w = "ab"
+        $v[^] => "x"
+        "d" => "y"
+        "a" => "z"
+        $w[^] => "w"
+    "#), vec![(1, 0)]);
+
+    // An exact variable is the same as its literal content, but only the
+    // literal counts towards the length priority, so the literal wins
+    assert_eq!(shadowed(r#"
+        $w = "ab"
+        $w => "1"
+        "ab" => "2"
+    "#), vec![(0, 1)]);
+
+    // A subset variable is covered, a superset is not
+    assert_eq!(shadowed(r#"
+        $big = "abc"
+        $small = "ab"
+        $huge = "abcd"
+        $big[*] => "1"
+        $small[*] => "2"
+        $huge[*] => "3"
+    "#), vec![(1, 0)]);
+}
+
+#[test]
+fn test_vk_combination_shadowing() {
+    // Modifier order does not matter
+    assert_eq!(shadowed(r#"
+        <VK_SHIFT & VK_KEY_A> => "1"
+        <VK_KEY_A & VK_SHIFT> => "2"
+    "#), vec![(1, 0)]);
+
+    // Modifiers are matched exactly, so a plain key is a different combination
+    assert!(shadowed(r#"
+        <VK_SHIFT & VK_KEY_A> => "1"
+        <VK_KEY_A> => "2"
+        <VK_CTRL & VK_KEY_A> => "3"
+    "#).is_empty());
+
+    // A key rule never shadows a character rule: character input is matched
+    // differently and may arrive without a key code
+    assert!(shadowed(r#"
+        <VK_KEY_A> => "1"
+        "a" => "2"
+    "#).is_empty());
+
+    // Same key with a longer context is still reachable
+    assert!(shadowed(r#"
+        <VK_KEY_A> => "1"
+        "x" + <VK_KEY_A> => "2"
+    "#).is_empty());
+}
+
+#[test]
+fn test_state_shadowing() {
+    // Rules with a state are checked first and only match while it is active
+    assert!(shadowed(r#"
+        "a" => "1"
+        ('s') + "a" => "2"
+    "#).is_empty());
+
+    // A rule needing fewer states covers one needing more at equal priority
+    assert_eq!(shadowed(r#"
+        ('s') + "a" => "1"
+        ('s') + "a" => "2"
+        ('t') + "a" => "3"
+    "#), vec![(1, 0)]);
+}
+
+#[test]
+fn test_last_matched_rules_includes_recursive_matches() {
+    let mut engine = engine(r#"
+        "k" => U1000
+        "a" => U102C
+        U1000 + U102C => U1000 + U102B
+    "#);
+
+    engine.process_key(keymagic_core::KeyInput::from_char('k')).unwrap();
+    assert_eq!(engine.last_matched_rules(), &[0]);
+
+    // "a" produces U102C, which then matches rule 2 recursively
+    engine.process_key(keymagic_core::KeyInput::from_char('a')).unwrap();
+    assert_eq!(engine.last_matched_rules(), &[1, 2]);
+
+    // Unmatched keys clear the list
+    engine.process_key(keymagic_core::KeyInput::from_char('z')).unwrap();
+    assert!(engine.last_matched_rules().is_empty());
+}
+
+#[test]
+fn test_corpus_coverage() {
+    let mut engine = engine(r#"
+        "k" => U1000
+        "a" => U102C
+        "x" => "y"
+        <VK_SHIFT & VK_KEY_Q> => "Q!"
+        U1000 + U102C => U1000 + U102B
+    "#);
+
+    let report = run_coverage(&mut engine, ["ka", "kk", "", "Q"]).unwrap();
+    assert_eq!(report.lines, 3);
+    assert_eq!(report.keys, 5);
+
+    let hits: Vec<usize> = report.rules.iter().map(|r| r.hits).collect();
+    assert_eq!(hits, vec![3, 1, 0, 1, 1]);
+    assert_eq!(report.unhit, vec![2]);
+    assert_eq!(report.covered_percent, 80.0);
+
+    // Coverage runs leave the engine empty
+    assert_eq!(engine.composing_text(), "");
+}
+
+#[test]
+fn test_long_deletes() {
+    let kms = r#"
+        "abcdefghijklmnopz" => "!"
+        "abcdefghijklmnop" + <VK_KEY_Q> => "abcdefghijklmnopq"
+        "ေ" + "u" => "ကေ"
+        "abcdefghi" + <VK_KEY_W> => "!"
+    "#;
+    let report = find_long_deletes(&engine(kms));
+    let found: Vec<_> = report.iter().map(|d| (d.rule, d.deletes)).collect();
+    // The typed `z` is no text before the key; a kept prefix is not deleted
+    assert_eq!(found, vec![(0, 16), (3, 9)]);
+    assert_eq!(report[0].text, r#""abcdefghijklmnopz" => "!""#);
+
+    let keyboard = kms2km2::compile_kms(kms).unwrap();
+    let report = analyze_keyboard(&keyboard, None::<Vec<&str>>).unwrap();
+    assert_eq!(report.long_deletes.len(), 2);
+}
+
+#[test]
+fn test_analyze_keyboard() {
+    let keyboard = kms2km2::compile_kms(r#"
+        "a" => "1"
+        "a" => "2"
+    "#).unwrap();
+
+    let report = analyze_keyboard(&keyboard, None::<Vec<&str>>).unwrap();
+    assert_eq!(report.rule_count, 2);
+    assert_eq!(report.shadowed.len(), 1);
+    assert!(report.coverage.is_none());
+
+    let report = analyze_keyboard(&keyboard, Some(["a", "a"])).unwrap();
+    let coverage = report.coverage.unwrap();
+    assert_eq!(coverage.unhit, vec![1]);
+    assert_eq!(coverage.rules[0].hits, 2);
+}
@@ -0,0 +1,166 @@
+//! Tests for named rule groups that can be switched off at runtime
+#![cfg(feature = "ffi")]
+
+use keymagic_core::ffi::*;
+use keymagic_core::Error;
+use keymagic_core::types::km2::{InfoEntry, RuleGroup, INFO_GRPL, INFO_GRPS};
+use std::ffi::{CStr, CString};
+
+mod common;
+use common::*;
+
+const GROUPED_KMS: &str = r#"
+"k" => "က"
+
+@group "autocorrect"
+"teh" => "the"
+"က" + "a" => "ကာ"
+@endgroup
+
+"x" => "ခ"
+
+@group "symbols"
+"." => "။"
+@endgroup
+
+@group "autocorrect"
+"adn" => "and"
+@endgroup
+"#;
+
+fn type_text(engine: &mut keymagic_core::KeyMagicEngine, text: &str) -> String {
+    for ch in text.chars() {
+        process_char(engine, ch).unwrap();
+    }
+    engine.composing_text().to_string()
+}
+
+#[test]
+fn test_groups_are_compiled_into_metadata() {
+    let km2 = kms2km2::compile_kms(GROUPED_KMS).unwrap();
+    assert_eq!(
+        km2.metadata().rule_groups(),
+        vec![
+            RuleGroup { name: "autocorrect".to_string(), ranges: vec![1..3, 5..6] },
+            RuleGroup { name: "symbols".to_string(), ranges: std::iter::once(4..5).collect() },
+        ]
+    );
+
+    let engine = create_engine(GROUPED_KMS).unwrap();
+    let groups = engine.rule_groups();
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].rule_count(), 3);
+    assert!(groups.iter().all(|group| engine.is_group_enabled(&group.name)));
+}
+
+#[test]
+fn test_keyboard_without_groups() {
+    let mut engine = create_engine(r#""a" => "b""#).unwrap();
+    assert!(engine.rule_groups().is_empty());
+    assert!(matches!(engine.set_group_enabled("autocorrect", false), Err(Error::UnknownRuleGroup(_))));
+}
+
+#[test]
+fn test_disabled_group_stops_matching() {
+    let mut engine = create_engine(GROUPED_KMS).unwrap();
+    assert_eq!(type_text(&mut engine, "teh"), "the");
+    engine.reset();
+
+    engine.set_group_enabled("autocorrect", false).unwrap();
+    assert!(!engine.is_group_enabled("autocorrect"));
+
+    // Both blocks of the group are off
+    assert_eq!(type_text(&mut engine, "teh"), "teh");
+    engine.reset();
+    assert_eq!(type_text(&mut engine, "adn"), "adn");
+    engine.reset();
+    assert_eq!(type_text(&mut engine, "ka"), "ကa");
+    engine.reset();
+
+    // Ungrouped rules and other groups are unaffected
+    assert_eq!(type_text(&mut engine, "x."), "ခ။");
+    engine.reset();
+
+    engine.set_group_enabled("autocorrect", true).unwrap();
+    assert_eq!(type_text(&mut engine, "ka"), "ကာ");
+}
+
+#[test]
+fn test_disabled_group_is_skipped_in_recursion() {
+    let kms = r#"
+"a" => "က"
+@group "chain"
+"က" => "ခ"
+@endgroup
+"#;
+    let mut engine = create_engine(kms).unwrap();
+    assert_eq!(type_text(&mut engine, "a"), "ခ");
+    engine.reset();
+
+    engine.set_group_enabled("chain", false).unwrap();
+    assert_eq!(type_text(&mut engine, "a"), "က");
+}
+
+#[test]
+fn test_group_toggles_survive_reset() {
+    let mut engine = create_engine(GROUPED_KMS).unwrap();
+    engine.set_group_enabled("symbols", false).unwrap();
+    engine.reset();
+    assert_eq!(type_text(&mut engine, "."), ".");
+}
+
+#[test]
+fn test_unbalanced_groups_fail_to_compile() {
+    for kms in [
+        "@group \"a\"\n\"x\" => \"y\"",
+        "\"x\" => \"y\"\n@endgroup",
+        "@group \"a\"\n@group \"b\"\n\"x\" => \"y\"\n@endgroup\n@endgroup",
+        "@group \"\"\n\"x\" => \"y\"\n@endgroup",
+    ] {
+        assert!(kms2km2::compile_kms(kms).is_err(), "compiled: {}", kms);
+    }
+}
+
+#[test]
+fn test_malformed_group_data_is_ignored() {
+    let groups = vec![RuleGroup { name: "a".to_string(), ranges: vec![0..2, 3..5] }];
+    let data = RuleGroup::encode_list(&groups);
+    assert_eq!(RuleGroup::decode_list(&data), Some(groups));
+    assert_eq!(RuleGroup::decode_list(&data[..data.len() - 1]), None);
+}
+
+#[test]
+fn test_wide_group_entry_indexes_past_old_limit() {
+    let groups = vec![RuleGroup { name: "big".to_string(), ranges: vec![3..5, 70_000..70_010] }];
+    assert!(!RuleGroup::fits_list(&groups));
+    let data = RuleGroup::encode_list_wide(&groups);
+    assert_eq!(RuleGroup::decode_list_wide(&data), Some(groups.clone()));
+
+    // The wide entry wins over the old one
+    let mut km2 = create_basic_km2();
+    km2.info.push(InfoEntry { id: *INFO_GRPS, data: RuleGroup::encode_list(&groups[..0]) });
+    km2.info.push(InfoEntry { id: *INFO_GRPL, data });
+    assert_eq!(km2.metadata().rule_groups(), groups);
+}
+
+#[test]
+fn test_ffi_rule_groups() {
+    let binary = create_km2_binary(&kms2km2::compile_kms(GROUPED_KMS).unwrap()).unwrap();
+    let handle = keymagic_engine_new();
+    assert_eq!(keymagic_engine_get_rule_group_count(handle), -1);
+    let result = keymagic_engine_load_keyboard_from_memory(handle, binary.as_ptr(), binary.len());
+    assert_eq!(result, KeyMagicResult::Success);
+
+    assert_eq!(keymagic_engine_get_rule_group_count(handle), 2);
+    let name = keymagic_engine_get_rule_group_name(handle, 1);
+    assert_eq!(unsafe { CStr::from_ptr(name) }.to_str().unwrap(), "symbols");
+    keymagic_free_string(name);
+    assert!(keymagic_engine_get_rule_group_name(handle, 2).is_null());
+
+    let symbols = CString::new("symbols").unwrap();
+    let unknown = CString::new("unknown").unwrap();
+    assert_eq!(keymagic_engine_set_group_enabled(handle, symbols.as_ptr(), 0), KeyMagicResult::Success);
+    assert_eq!(keymagic_engine_set_group_enabled(handle, unknown.as_ptr(), 0), KeyMagicResult::ErrorInvalidParameter);
+
+    keymagic_engine_free(handle);
+}
@@ -0,0 +1,177 @@
+//! Tests for rule match statistics
+#![cfg(feature = "ffi")]
+
+use keymagic_core::analysis::run_performance;
+use keymagic_core::ffi::*;
+use std::ptr;
+
+mod common;
+use common::*;
+
+// Rules are tried longest pattern first, so "Ka" comes before the single
+// characters although it is declared last. Single ASCII outputs end
+// recursive matching, so every key makes a single pass.
+const KMS: &str = r#"
+"x" => "X"
+"k" => "K"
+"a" => "A"
+"Ka" => "C"
+"#;
+
+#[test]
+fn test_histogram_disabled_by_default() {
+    let mut engine = create_engine(KMS).unwrap();
+    process_char(&mut engine, 'k').unwrap();
+    assert!(engine.rule_histogram().is_none());
+}
+
+#[test]
+fn test_histogram_counts_hits_and_scans() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_rule_histogram_enabled(true);
+    let histogram = engine.rule_histogram().unwrap();
+    assert_eq!(histogram.hits(), &[0, 0, 0, 0]);
+    assert_eq!(histogram.average_scanned_per_key(), 0.0);
+
+    // "Ka" is tried first and does not match, then "x", then "k"
+    process_char(&mut engine, 'k').unwrap();
+    let histogram = engine.rule_histogram().unwrap();
+    assert_eq!(histogram.keys(), 1);
+    assert_eq!(histogram.rules_scanned(), 3);
+    assert_eq!(histogram.hits(), &[0, 1, 0, 0]);
+
+    // "Ka" matches at once
+    process_char(&mut engine, 'a').unwrap();
+    // Nothing matches "q", every rule is tried
+    process_char(&mut engine, 'q').unwrap();
+    let histogram = engine.rule_histogram().unwrap();
+    assert_eq!(histogram.keys(), 3);
+    assert_eq!(histogram.rules_scanned(), 3 + 1 + 4);
+    assert_eq!(histogram.hits(), &[0, 1, 0, 1]);
+    assert!((histogram.average_scanned_per_key() - 8.0 / 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_histogram_counts_recursive_matches() {
+    let mut engine = create_engine("\"a\" => \"bc\"\n\"bc\" => \"d\"").unwrap();
+    engine.set_rule_histogram_enabled(true);
+    process_char(&mut engine, 'a').unwrap();
+    let histogram = engine.rule_histogram().unwrap();
+    assert_eq!(histogram.hits(), &[1, 1]);
+    // Key pass: "bc", "a"; recursion: "bc", whose output "d" ends it
+    assert_eq!(histogram.rules_scanned(), 3);
+}
+
+#[test]
+fn test_disabled_groups_are_not_scanned() {
+    let kms = "@group \"extra\"\n\"q\" => \"Q\"\n\"w\" => \"W\"\n@endgroup\n\"k\" => \"K\"";
+    let mut engine = create_engine(kms).unwrap();
+    engine.set_group_enabled("extra", false).unwrap();
+    engine.set_rule_histogram_enabled(true);
+    process_char(&mut engine, 'k').unwrap();
+    assert_eq!(engine.rule_histogram().unwrap().rules_scanned(), 1);
+}
+
+#[test]
+fn test_preview_is_not_counted() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_rule_histogram_enabled(true);
+    engine.process_key_test(key_input_from_char('k')).unwrap();
+    assert_eq!(engine.rule_histogram().unwrap().keys(), 0);
+}
+
+#[test]
+fn test_enabling_again_starts_over() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_rule_histogram_enabled(true);
+    process_char(&mut engine, 'k').unwrap();
+    engine.set_rule_histogram_enabled(false);
+    assert!(engine.rule_histogram().is_none());
+    engine.set_rule_histogram_enabled(true);
+    assert_eq!(engine.rule_histogram().unwrap().keys(), 0);
+}
+
+#[test]
+fn test_top_entries() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_rule_histogram_enabled(true);
+    for ch in "xkxkax".chars() {
+        process_char(&mut engine, ch).unwrap();
+    }
+    let histogram = engine.rule_histogram().unwrap();
+    assert_eq!(histogram.top(10), vec![(0, 3), (1, 2), (3, 1)]);
+    assert_eq!(histogram.top(1), vec![(0, 3)]);
+    assert!(histogram.top(0).is_empty());
+}
+
+#[test]
+fn test_rule_priority() {
+    let engine = create_engine(KMS).unwrap();
+    assert_eq!(engine.rule_priority(3), Some(0));
+    assert_eq!(engine.rule_priority(0), Some(1));
+    assert_eq!(engine.rule_priority(4), None);
+}
+
+#[test]
+fn test_performance_report() {
+    let mut engine = create_engine(KMS).unwrap();
+    let report = run_performance(&mut engine, "kax\n\nxx\n".lines(), 2).unwrap();
+
+    assert_eq!(report.rule_count, 4);
+    assert_eq!(report.keys, 5);
+    // k: 3, a: 1 ("Ka"), x: 2, x: 2, x: 2
+    assert_eq!(report.rules_scanned, 10);
+    assert!((report.average_scanned_per_key - 2.0).abs() < 1e-9);
+
+    assert_eq!(report.hottest.len(), 2);
+    assert_eq!(report.hottest[0].rule, 0);
+    assert_eq!(report.hottest[0].hits, 3);
+    assert_eq!(report.hottest[0].text, "\"x\" => \"X\"");
+    assert_eq!(report.hottest[0].priority, 1);
+    assert_eq!(report.hottest[1].rule, 1);
+
+    // The engine is left as it was
+    assert!(engine.rule_histogram().is_none());
+    assert_eq!(engine.composing_text(), "");
+}
+
+#[test]
+fn test_ffi_histogram() {
+    let km2 = kms2km2::compile_kms(KMS).unwrap();
+    let data = create_km2_binary(&km2).unwrap();
+    unsafe {
+        let handle = keymagic_engine_new();
+        assert_eq!(
+            keymagic_engine_get_rule_histogram(handle, ptr::null_mut(), 0, ptr::null_mut(), ptr::null_mut()),
+            -1
+        );
+        assert_eq!(
+            keymagic_engine_load_keyboard_from_memory(handle, data.as_ptr(), data.len()),
+            KeyMagicResult::Success
+        );
+        assert_eq!(
+            keymagic_engine_get_rule_histogram(handle, ptr::null_mut(), 0, ptr::null_mut(), ptr::null_mut()),
+            -1
+        );
+        assert_eq!(keymagic_engine_set_rule_histogram(handle, 1), KeyMagicResult::Success);
+
+        let mut output = std::mem::zeroed::<ProcessKeyOutput>();
+        for (vk, ch) in [(0x58, b'x'), (0x58, b'x'), (0x4B, b'k')] {
+            keymagic_engine_process_key_win(handle, vk, ch as i8, 0, 0, 0, 0, &mut output);
+        }
+
+        let mut entries = [RuleHistogramEntry::default(); 4];
+        let (mut keys, mut scanned) = (0u64, 0u64);
+        let count = keymagic_engine_get_rule_histogram(handle, entries.as_mut_ptr(), 1, &mut keys, &mut scanned);
+        assert_eq!(count, 1);
+        assert_eq!((entries[0].rule, entries[0].hits), (0, 2));
+        assert_eq!(keys, 3);
+        assert_eq!(scanned, 2 + 2 + 3);
+
+        let count = keymagic_engine_get_rule_histogram(handle, entries.as_mut_ptr(), 4, ptr::null_mut(), ptr::null_mut());
+        assert_eq!(count, 2);
+        assert_eq!((entries[1].rule, entries[1].hits), (1, 1));
+
+        keymagic_engine_free(handle);
+    }
+}
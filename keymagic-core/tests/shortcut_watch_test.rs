@@ -0,0 +1,76 @@
+//! Shortcuts the GUI watches for through the text services
+#![cfg(feature = "host-ipc")]
+
+use keymagic_core::hotkey::KeyCombo;
+use keymagic_core::shortcut_watch::*;
+use keymagic_core::VirtualKey;
+
+fn shortcut(hotkey: &str, action: u32, swallow: bool) -> WatchedShortcut {
+    WatchedShortcut { combo: KeyCombo::parse(hotkey).unwrap(), action, swallow }
+}
+
+#[test]
+fn test_keys_match_exact_modifiers() {
+    let watch = ShortcutWatch::in_memory();
+    assert_eq!(watch.find(VirtualKey::Space, true, false, true), None);
+
+    let picker = shortcut("CTRL+SHIFT+SPACE", 1, true);
+    watch.set_shortcuts(&[picker, shortcut("ALT+K", 2, false)]);
+    assert_eq!(watch.shortcuts(), vec![picker, shortcut("ALT+K", 2, false)]);
+
+    assert_eq!(watch.find(VirtualKey::Space, true, false, true), Some(picker));
+    assert_eq!(watch.find(VirtualKey::Space, true, false, false), None);
+    assert_eq!(watch.find(VirtualKey::Space, true, true, true), None);
+    assert_eq!(watch.find(VirtualKey::KeyK, false, true, false).map(|found| found.action), Some(2));
+}
+
+#[test]
+fn test_list_is_replaced_and_bounded() {
+    let watch = ShortcutWatch::in_memory();
+    watch.set_shortcuts(&[shortcut("CTRL+SHIFT+SPACE", 1, true)]);
+    watch.set_shortcuts(&[shortcut("CTRL+J", 3, false)]);
+    assert_eq!(watch.find(VirtualKey::Space, true, false, true), None);
+    assert!(watch.find(VirtualKey::KeyJ, true, false, false).is_some());
+
+    // Action 0 means nothing, and the list only holds so many
+    let many: Vec<WatchedShortcut> = (0..MAX_SHORTCUTS as u32 + 2).map(|action| shortcut("CTRL+J", action, false)).collect();
+    watch.set_shortcuts(&many);
+    let published = watch.shortcuts();
+    assert_eq!(published.len(), MAX_SHORTCUTS);
+    assert_eq!(published[0].action, 1);
+}
+
+#[test]
+fn test_win_key_shortcuts_never_match_in_hosts() {
+    let watch = ShortcutWatch::in_memory();
+    watch.set_shortcuts(&[shortcut("WIN+SPACE", 1, true)]);
+    assert_eq!(watch.shortcuts().len(), 1);
+    assert_eq!(watch.find(VirtualKey::Space, false, false, false), None);
+}
+
+#[test]
+fn test_events_are_read_in_order() {
+    let watch = ShortcutWatch::in_memory();
+    assert_eq!(watch.report(1, 100), 1);
+    assert_eq!(watch.report(2, 200), 2);
+
+    let events = watch.read_since(0);
+    assert_eq!(events, vec![
+        ShortcutEvent { seq: 1, action: 1, process_id: 100 },
+        ShortcutEvent { seq: 2, action: 2, process_id: 200 },
+    ]);
+    assert_eq!(watch.read_since(1).len(), 1);
+    assert!(watch.read_since(watch.last_seq()).is_empty());
+}
+
+#[test]
+fn test_ring_keeps_the_latest_events() {
+    let watch = ShortcutWatch::in_memory();
+    for action in 1..=EVENT_SLOTS as u32 + 3 {
+        watch.report(action, 1);
+    }
+    let events = watch.read_since(0);
+    assert_eq!(events.len(), EVENT_SLOTS);
+    assert_eq!(events[0].action, 4);
+    assert_eq!(events.last().unwrap().seq, (EVENT_SLOTS + 3) as u64);
+}
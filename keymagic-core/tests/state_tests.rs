@@ -1,8 +1,8 @@
 mod common;
 
 use common::*;
-use keymagic_core::{BinaryFormatElement, VirtualKey};
-use keymagic_core::engine::ActionType;
+use keymagic_core::types::km2::BinaryFormatElement;
+use keymagic_core::{VirtualKey, ActionType};
 
 #[test]
 fn test_basic_state_toggle() {
@@ -354,4 +354,85 @@ fn test_state_priority_in_rule_sorting() {
     // Type 't' - should match state rule even though it's shorter
     let result = process_char(&mut engine, 't').unwrap();
     assert_eq!(result.action, ActionType::Insert("state".to_string()));
-}
\ No newline at end of file
+}
+/// "k" leaves ('s') on for the next key, and "z" turns it on without typing;
+/// "1" types "၁" only while it is on
+const STATE_KMS: &str = r#"
+"k" => "က" + ('s')
+"z" => ('s')
+('s') + "1" => "၁"
+"#;
+
+#[test]
+fn test_states_do_not_survive_a_space_commit() {
+    let mut engine = create_engine(STATE_KMS).unwrap();
+    process_char(&mut engine, 'k').unwrap();
+    assert_eq!(engine.active_states(), vec![0]);
+
+    // Space matches no rule and ends the state; the host commits and resets
+    let result = process_char(&mut engine, ' ').unwrap();
+    assert_eq!(result.composing_text, "က ");
+    assert!(engine.active_states().is_empty());
+    engine.reset();
+
+    // The first key of the next word does not see the state
+    let result = process_char(&mut engine, '1').unwrap();
+    assert_eq!(result.composing_text, "1");
+}
+
+#[test]
+fn test_committing_passthrough_clears_states_with_nothing_composed() {
+    let mut engine = create_engine(STATE_KMS).unwrap();
+    engine.set_passthrough_keys(&[VirtualKey::Space]);
+    engine.set_commit_on_passthrough(true);
+
+    process_char(&mut engine, 'z').unwrap();
+    assert_eq!(engine.composing_text(), "");
+    assert_eq!(engine.active_states(), vec![0]);
+
+    let result = process_key(&mut engine, key_input_vk_char(VirtualKey::Space, ' ')).unwrap();
+    assert!(!result.is_processed);
+    assert!(engine.active_states().is_empty());
+    assert_eq!(engine.undo_depth(), 0);
+
+    let result = process_char(&mut engine, '1').unwrap();
+    assert_eq!(result.composing_text, "1");
+}
+
+#[test]
+fn test_passthrough_key_ends_states_but_keeps_the_text() {
+    let mut engine = create_engine(STATE_KMS).unwrap();
+    engine.set_passthrough_keys(&[VirtualKey::Space]);
+
+    process_char(&mut engine, 'k').unwrap();
+    let result = process_key(&mut engine, key_input_vk_char(VirtualKey::Space, ' ')).unwrap();
+    assert!(!result.is_processed);
+    assert_eq!(result.composing_text, "က");
+    assert!(engine.active_states().is_empty());
+
+    let result = process_char(&mut engine, '1').unwrap();
+    assert_eq!(result.composing_text, "က1");
+}
+
+#[test]
+fn test_host_resets_clear_states() {
+    let mut engine = create_engine(STATE_KMS).unwrap();
+
+    process_char(&mut engine, 'k').unwrap();
+    engine.reset();
+    assert!(engine.active_states().is_empty());
+
+    process_char(&mut engine, 'k').unwrap();
+    assert_eq!(engine.flush(), "က");
+    assert!(engine.active_states().is_empty());
+
+    process_char(&mut engine, 'k').unwrap();
+    engine.set_composing_text("က".to_string());
+    assert!(engine.active_states().is_empty());
+    assert_eq!(process_char(&mut engine, '1').unwrap().composing_text, "က1");
+
+    // A key in test mode leaves the states alone
+    process_char(&mut engine, 'k').unwrap();
+    engine.process_key_test(key_input_vk_char(VirtualKey::Space, ' ')).unwrap();
+    assert_eq!(engine.active_states(), vec![0]);
+}
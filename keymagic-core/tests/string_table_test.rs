@@ -0,0 +1,102 @@
+//! Lazy decoding of the string table of a loaded keyboard
+
+mod common;
+use common::*;
+
+use keymagic_core::km2::{Km2Error, Km2Loader};
+use keymagic_core::{KeyMagicEngine, VirtualKey};
+use keymagic_core::types::km2::StringEntry;
+use keymagic_core::types::string_table::StringTable;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+const KMS: &str = r#"
+$cons = "ကခဂ"
+$vowels = "ာိီ"
+$medials = "ျြ"
+$cons[*] + $vowels[*] => $1 + $2
+"k" => "က"
+"#;
+
+fn load(kms: &str) -> keymagic_core::Km2File {
+    let mut buffer = Vec::new();
+    keymagic_core::km2::Km2Writer::new(&mut buffer)
+        .write_km2_file(&kms2km2::compile_kms(kms).unwrap())
+        .unwrap();
+    Km2Loader::load(&buffer).unwrap()
+}
+
+fn decoded(table: &StringTable) -> Vec<&str> {
+    table.decoded().map(|entry| entry.value.as_str()).collect()
+}
+
+#[test]
+fn test_strings_are_decoded_on_first_use() {
+    let km2 = load(KMS);
+    assert_eq!(km2.strings.len(), 3);
+    assert!(decoded(&km2.strings).is_empty());
+
+    assert_eq!(km2.strings.get_str(1), Some("ာိီ"));
+    assert_eq!(decoded(&km2.strings), vec!["ာိီ"]);
+    assert!(km2.strings.get(3).is_none());
+
+    let all: Vec<&str> = km2.strings.iter().map(|entry| entry.value.as_str()).collect();
+    assert_eq!(all, vec!["ကခဂ", "ာိီ", "ျြ"]);
+    assert_eq!(decoded(&km2.strings).len(), 3);
+}
+
+#[test]
+fn test_engine_decodes_only_what_rules_use() {
+    let km2 = load(KMS);
+    let mut engine = KeyMagicEngine::new(km2.clone()).unwrap();
+    assert!(decoded(&km2.strings).is_empty());
+
+    engine.process_key(key_input_vk_char(VirtualKey::KeyK, 'k')).unwrap();
+    assert!(decoded(&km2.strings).is_empty());
+    let output = engine.process_key(key_input_vk_char(VirtualKey::KeyA, 'ာ')).unwrap();
+    assert_eq!(output.composing_text, "ကာ");
+    // The engine shares the keyboard's table; `$medials` is never used
+    assert_eq!(decoded(&km2.strings), vec!["ကခဂ", "ာိီ"]);
+}
+
+#[test]
+fn test_push_does_not_change_clones() {
+    let km2 = load(KMS);
+    let mut edited = km2.strings.clone();
+    edited.push(StringEntry { value: "new".to_string() });
+    assert_eq!(edited.len(), 4);
+    assert_eq!(km2.strings.len(), 3);
+    assert_eq!(edited[3].value, "new");
+    assert_eq!(edited[0].value, "ကခဂ");
+}
+
+#[test]
+fn test_concurrent_first_use() {
+    let km2 = load(KMS);
+    let barrier = Arc::new(Barrier::new(8));
+    let workers: Vec<_> = (0..8)
+        .map(|_| {
+            let strings = km2.strings.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                (0..strings.len()).map(|i| strings[i].value.clone()).collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    for worker in workers {
+        assert_eq!(worker.join().unwrap(), vec!["ကခဂ", "ာိီ", "ျြ"]);
+    }
+    assert_eq!(decoded(&km2.strings).len(), 3);
+}
+
+#[test]
+fn test_invalid_utf16_fails_at_load() {
+    let mut km2 = create_basic_km2();
+    add_string(&mut km2, "ab");
+    let mut binary = create_km2_binary(&km2).unwrap();
+    // Replace 'a' with a lone high surrogate
+    let at = binary.windows(4).position(|w| w == [b'a', 0, b'b', 0]).unwrap();
+    binary[at..at + 2].copy_from_slice(&0xD800u16.to_le_bytes());
+    assert!(matches!(Km2Loader::load(&binary), Err(Km2Error::InvalidUtf16(_))));
+}
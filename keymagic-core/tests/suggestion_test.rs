@@ -0,0 +1,289 @@
+//! Tests for the suggestion provider hook
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use keymagic_core::{ActionType, Error, Suggestion, SuggestionProvider, VirtualKey};
+
+mod common;
+use common::*;
+
+const KMS: &str = r#"
+"k" => "က"
+"a" => "ာ"
+"#;
+
+/// Suggests fixed texts completing the composing text and records its calls
+#[derive(Default)]
+struct FixedProvider {
+    texts: Vec<&'static str>,
+    calls: AtomicUsize,
+    seen: Mutex<Vec<(String, String)>>,
+    committed: Mutex<Vec<String>>,
+}
+
+impl FixedProvider {
+    fn new(texts: &[&'static str]) -> Arc<Self> {
+        Arc::new(Self { texts: texts.to_vec(), ..Self::default() })
+    }
+}
+
+impl SuggestionProvider for FixedProvider {
+    fn suggest(&self, context: &str, composing: &str) -> Vec<Suggestion> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.seen.lock().unwrap().push((context.to_string(), composing.to_string()));
+        self.texts.iter().map(|text| Suggestion::completing(*text, composing)).collect()
+    }
+
+    fn committed(&self, text: &str) {
+        self.committed.lock().unwrap().push(text.to_string());
+    }
+}
+
+#[test]
+fn test_no_provider_attaches_nothing() {
+    let mut engine = create_engine(KMS).unwrap();
+    assert!(!engine.has_suggestion_provider());
+    for output in process_string(&mut engine, "kak").unwrap() {
+        assert!(output.suggestions.is_empty());
+        // Nothing was even allocated for them
+        assert_eq!(output.suggestions.capacity(), 0);
+    }
+    assert!(engine.suggestions().is_empty());
+    assert!(matches!(engine.apply_suggestion(0), Err(Error::InvalidSuggestionIndex(0))));
+    assert_eq!(engine.composing_text(), "ကာက");
+}
+
+#[test]
+fn test_provider_results_are_attached() {
+    let mut engine = create_engine(KMS).unwrap();
+    let provider = FixedProvider::new(&["ကား", "ကာလ"]);
+    engine.set_suggestion_provider(Some(provider.clone()));
+
+    let output = process_char(&mut engine, 'k').unwrap();
+    assert_eq!(output.suggestions, vec![Suggestion::new("ကား", 1), Suggestion::new("ကာလ", 1)]);
+    let output = process_char(&mut engine, 'a').unwrap();
+    assert_eq!(output.suggestions[0], Suggestion::new("ကား", 2));
+    assert_eq!(engine.suggestions(), output.suggestions.as_slice());
+
+    assert_eq!(provider.calls.load(Ordering::Relaxed), 2);
+    assert_eq!(
+        *provider.seen.lock().unwrap(),
+        vec![(String::new(), "က".to_string()), (String::new(), "ကာ".to_string())]
+    );
+}
+
+#[test]
+fn test_suggestions_are_capped() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_suggestion_provider(Some(FixedProvider::new(&["a", "b", "c", "d", "e", "f", "g"])));
+    assert_eq!(process_char(&mut engine, 'k').unwrap().suggestions.len(), 5);
+
+    engine.set_max_suggestions(2);
+    assert_eq!(engine.suggestions().len(), 2);
+    let output = process_char(&mut engine, 'a').unwrap();
+    assert_eq!(output.suggestions.iter().map(|s| s.text.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+}
+
+#[test]
+fn test_apply_suggestion_edits_composing_text() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_suggestion_provider(Some(FixedProvider::new(&["ကား", "ခ"])));
+    process_string(&mut engine, "ka").unwrap();
+
+    // Completing keeps the common start
+    let output = engine.apply_suggestion(0).unwrap();
+    assert_eq!(output.composing_text, "ကား");
+    assert_eq!(output.action, ActionType::Insert("း".to_string()));
+    assert!(output.is_processed);
+    assert!(output.suggestions.is_empty());
+    assert!(engine.suggestions().is_empty());
+    assert!(matches!(engine.apply_suggestion(0), Err(Error::InvalidSuggestionIndex(0))));
+
+    process_char(&mut engine, 'k').unwrap();
+    let output = engine.apply_suggestion(1).unwrap();
+    assert_eq!(output.composing_text, "ခ");
+    assert_eq!(output.action, ActionType::BackspaceDeleteAndInsert(4, "ခ".to_string()));
+    assert_eq!(output.delete_chars, 4);
+    assert_eq!(engine.composing_text(), "ခ");
+}
+
+#[test]
+fn test_apply_suggestion_replaces_only_the_end() {
+    struct LastChar;
+    impl SuggestionProvider for LastChar {
+        fn suggest(&self, _context: &str, _composing: &str) -> Vec<Suggestion> {
+            vec![Suggestion::new("ဂ", 1)]
+        }
+    }
+
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_suggestion_provider(Some(Arc::new(LastChar)));
+    process_string(&mut engine, "kak").unwrap();
+    let output = engine.apply_suggestion(0).unwrap();
+    assert_eq!(output.composing_text, "ကာဂ");
+    assert_eq!(output.action, ActionType::BackspaceDeleteAndInsert(1, "ဂ".to_string()));
+}
+
+#[test]
+fn test_smart_backspace_undoes_applied_suggestion() {
+    let mut engine = create_engine(KMS).unwrap();
+    let mut options = engine.layout_options();
+    options.auto_bksp = 1;
+    engine.set_layout_options(options);
+    engine.set_suggestion_provider(Some(FixedProvider::new(&["ကား"])));
+
+    process_string(&mut engine, "ka").unwrap();
+    engine.apply_suggestion(0).unwrap();
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::Back)).unwrap();
+    assert_eq!(output.composing_text, "ကာ");
+}
+
+#[test]
+fn test_commits_reach_provider_as_context() {
+    let mut engine = create_engine(KMS).unwrap();
+    let provider = FixedProvider::new(&[]);
+    engine.set_suggestion_provider(Some(provider.clone()));
+
+    process_string(&mut engine, "ka").unwrap();
+    assert_eq!(engine.flush(), "ကာ");
+    process_char(&mut engine, 'k').unwrap();
+
+    assert_eq!(*provider.committed.lock().unwrap(), vec!["ကာ".to_string()]);
+    let seen = provider.seen.lock().unwrap();
+    assert_eq!(seen.last(), Some(&("ကာ".to_string(), "က".to_string())));
+}
+
+#[test]
+fn test_suggestions_dropped_by_reset_and_unprocessed_keys() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_suggestion_provider(Some(FixedProvider::new(&["ကား"])));
+
+    process_char(&mut engine, 'k').unwrap();
+    engine.reset();
+    assert!(engine.suggestions().is_empty());
+
+    process_char(&mut engine, 'k').unwrap();
+    let output = process_key(&mut engine, key_input_from_vk(VirtualKey::F1)).unwrap();
+    assert!(!output.is_processed);
+    assert!(output.suggestions.is_empty());
+    assert!(engine.suggestions().is_empty());
+}
+
+#[test]
+fn test_preview_does_not_ask_provider() {
+    let mut engine = create_engine(KMS).unwrap();
+    let provider = FixedProvider::new(&["ကား"]);
+    engine.set_suggestion_provider(Some(provider.clone()));
+    let output = engine.process_key_test(key_input_from_char('k')).unwrap();
+    assert!(output.suggestions.is_empty());
+    assert_eq!(provider.calls.load(Ordering::Relaxed), 0);
+}
+
+#[cfg(feature = "recent-suggestions")]
+#[test]
+fn test_recent_commits_provider() {
+    use keymagic_core::RecentCommitsProvider;
+
+    let provider = RecentCommitsProvider::new(3);
+    provider.committed("ကား ကာလ");
+    provider.committed("ကို");
+    provider.committed("ကား");
+    assert_eq!(provider.words(), vec!["ကား", "ကို", "ကာလ"]);
+
+    // Most recent first, the composing text itself left out
+    let texts = |composing| provider.suggest("", composing).into_iter().map(|s| s.text).collect::<Vec<_>>();
+    assert_eq!(texts("က"), vec!["ကား", "ကို", "ကာလ"]);
+    assert_eq!(texts("ကာ"), vec!["ကား", "ကာလ"]);
+    assert_eq!(texts("ကား"), Vec::<String>::new());
+    assert!(provider.suggest("", "").is_empty());
+
+    provider.committed("ခ");
+    assert_eq!(provider.words(), vec!["ခ", "ကား", "ကို"]);
+}
+
+#[cfg(feature = "recent-suggestions")]
+#[test]
+fn test_recent_commits_through_engine() {
+    let mut engine = create_engine(KMS).unwrap();
+    engine.set_suggestion_provider(Some(Arc::new(keymagic_core::RecentCommitsProvider::default())));
+    process_string(&mut engine, "kak").unwrap();
+    engine.flush();
+
+    let output = process_char(&mut engine, 'k').unwrap();
+    assert_eq!(output.suggestions, vec![Suggestion::new("ကာက", 1)]);
+    assert_eq!(engine.apply_suggestion(0).unwrap().composing_text, "ကာက");
+}
+
+#[cfg(feature = "ffi")]
+mod ffi {
+    use super::*;
+    use keymagic_core::ffi::*;
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+
+    unsafe fn load() -> *mut EngineHandle {
+        let km2 = kms2km2::compile_kms(KMS).unwrap();
+        let data = create_km2_binary(&km2).unwrap();
+        let handle = keymagic_engine_new();
+        assert_eq!(
+            keymagic_engine_load_keyboard_from_memory(handle, data.as_ptr(), data.len()),
+            KeyMagicResult::Success
+        );
+        handle
+    }
+
+    unsafe fn type_keys(handle: *mut EngineHandle, keys: &[u8]) {
+        let mut output = std::mem::zeroed::<ProcessKeyOutput>();
+        for &key in keys {
+            keymagic_engine_process_key_win(handle, key.to_ascii_uppercase() as i32, key as c_char, 0, 0, 0, 0, &mut output);
+        }
+    }
+
+    #[test]
+    fn test_ffi_without_provider() {
+        unsafe {
+            let handle = load();
+            type_keys(handle, b"k");
+
+            let mut count = -1;
+            assert!(keymagic_engine_get_suggestions(handle, &mut count).is_null());
+            assert_eq!(count, 0);
+            let mut output = std::mem::zeroed::<ProcessKeyOutput>();
+            assert_eq!(keymagic_engine_apply_suggestion(handle, 0, &mut output), KeyMagicResult::ErrorInvalidParameter);
+            assert_eq!(keymagic_engine_apply_suggestion(handle, -1, &mut output), KeyMagicResult::ErrorInvalidParameter);
+            keymagic_engine_free(handle);
+        }
+    }
+
+    #[cfg(feature = "recent-suggestions")]
+    #[test]
+    fn test_ffi_recent_suggestions() {
+        unsafe {
+            let handle = load();
+            assert_eq!(keymagic_engine_set_recent_suggestions(handle, 1), KeyMagicResult::Success);
+            type_keys(handle, b"ka");
+            keymagic_free_string(keymagic_engine_flush(handle));
+            type_keys(handle, b"k");
+
+            let mut count = 0;
+            let strings = keymagic_engine_get_suggestions(handle, &mut count);
+            assert_eq!(count, 1);
+            assert_eq!(CStr::from_ptr(*strings).to_str().unwrap(), "ကာ");
+            keymagic_free_string_array(strings, count);
+
+            let mut output = std::mem::zeroed::<ProcessKeyOutput>();
+            assert_eq!(keymagic_engine_apply_suggestion(handle, 0, &mut output), KeyMagicResult::Success);
+            assert_eq!(output.action_type, 1);
+            assert_eq!(CStr::from_ptr(output.text).to_str().unwrap(), "ာ");
+            assert_eq!(CStr::from_ptr(output.composing_text).to_str().unwrap(), "ကာ");
+            keymagic_free_string(output.text);
+            keymagic_free_string(output.composing_text);
+
+            assert_eq!(keymagic_engine_set_recent_suggestions(handle, 0), KeyMagicResult::Success);
+            type_keys(handle, b"k");
+            assert!(keymagic_engine_get_suggestions(handle, &mut count).is_null());
+            keymagic_engine_free(handle);
+        }
+    }
+}
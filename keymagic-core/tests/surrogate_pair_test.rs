@@ -0,0 +1,184 @@
+//! Tests for characters outside the BMP, which take a UTF-16 surrogate pair
+#![cfg(feature = "ffi")]
+
+use keymagic_core::{ActionType, EngineOutput, KeyMagicEngine, VirtualKey};
+use keymagic_core::ffi::*;
+use std::ffi::CStr;
+use std::ptr;
+
+mod common;
+use common::*;
+
+const EMOJI_KMS: &str = r#"
+"a" => "😀"
+"😀" + "x" => "𝐀"
+"k" => "က"
+"😀" + "😀" => "🎉"
+"🎉" + "b" => "ခ"
+"#;
+
+const SMART_BACKSPACE_EMOJI_KMS: &str = r#"
+/*
+@SMART_BACKSPACE = "TRUE"
+*/
+"a" => "😀"
+"s" => "𝐀𝐁"
+"k" => "က"
+"#;
+
+/// Asserts that applying the output's UTF-16 delete count to `before` and
+/// appending the inserted text yields the new composing text exactly
+fn assert_utf16_edit(before: &str, output: &EngineOutput) {
+    let mut units: Vec<u16> = before.encode_utf16().collect();
+    assert!(output.delete_utf16_units <= units.len());
+    units.truncate(units.len() - output.delete_utf16_units);
+
+    // Never cut between a high and a low surrogate
+    if let Some(last) = units.last() {
+        assert!(!(0xD800..0xDC00).contains(last), "split surrogate pair in {:?}", before);
+    }
+
+    let inserted = match &output.action {
+        ActionType::Insert(text) | ActionType::BackspaceDeleteAndInsert(_, text) => text.as_str(),
+        _ => "",
+    };
+    units.extend(inserted.encode_utf16());
+    assert_eq!(String::from_utf16(&units).unwrap(), output.composing_text);
+}
+
+fn process_checked(engine: &mut KeyMagicEngine, input: keymagic_core::KeyInput) -> EngineOutput {
+    let before = engine.composing_text().to_string();
+    let output = process_key(engine, input).unwrap();
+    assert_utf16_edit(&before, &output);
+    output
+}
+
+#[test]
+fn test_insert_astral_character() {
+    let mut engine = create_engine(EMOJI_KMS).unwrap();
+
+    let output = process_checked(&mut engine, key_input_from_char('a'));
+    assert_eq!(output.action, ActionType::Insert("😀".to_string()));
+    assert_eq!(output.delete_chars, 0);
+    assert_eq!(output.delete_utf16_units, 0);
+}
+
+#[test]
+fn test_replace_astral_character() {
+    let mut engine = create_engine(EMOJI_KMS).unwrap();
+    process_checked(&mut engine, key_input_from_char('k'));
+    process_checked(&mut engine, key_input_from_char('a'));
+
+    let output = process_checked(&mut engine, key_input_from_char('x'));
+    assert_eq!(output.action, ActionType::BackspaceDeleteAndInsert(1, "𝐀".to_string()));
+    assert_eq!(output.delete_chars, 1);
+    assert_eq!(output.delete_utf16_units, 2);
+    assert_eq!(output.composing_text, "က𝐀");
+}
+
+#[test]
+fn test_recursive_match_on_astral_characters() {
+    let mut engine = create_engine(EMOJI_KMS).unwrap();
+    process_checked(&mut engine, key_input_from_char('a'));
+
+    // The second emoji immediately combines with the first one
+    let output = process_checked(&mut engine, key_input_from_char('a'));
+    assert_eq!(output.composing_text, "🎉");
+    assert_eq!(output.action, ActionType::BackspaceDeleteAndInsert(1, "🎉".to_string()));
+    assert_eq!(output.delete_utf16_units, 2);
+
+    // Astral output replaced by a BMP character
+    let output = process_checked(&mut engine, key_input_from_char('b'));
+    assert_eq!(output.composing_text, "ခ");
+    assert_eq!(output.delete_chars, 1);
+    assert_eq!(output.delete_utf16_units, 2);
+}
+
+#[test]
+fn test_bmp_counts_are_equal() {
+    let mut engine = create_engine(EMOJI_KMS).unwrap();
+    process_checked(&mut engine, key_input_from_char('k'));
+
+    let output = process_checked(&mut engine, key_input_from_vk(VirtualKey::Back));
+    assert_eq!(output.action, ActionType::BackspaceDelete(1));
+    assert_eq!(output.delete_chars, 1);
+    assert_eq!(output.delete_utf16_units, 1);
+}
+
+#[test]
+fn test_repeated_backspace_never_splits_a_pair() {
+    let mut engine = create_engine(EMOJI_KMS).unwrap();
+    for ch in "akaka".chars() {
+        process_checked(&mut engine, key_input_from_char(ch));
+    }
+    assert_eq!(engine.composing_text(), "😀က😀က😀");
+
+    let mut deleted_units = Vec::new();
+    while !engine.composing_text().is_empty() {
+        let output = process_checked(&mut engine, key_input_from_vk(VirtualKey::Back));
+        assert_eq!(output.delete_chars, 1);
+        deleted_units.push(output.delete_utf16_units);
+    }
+    assert_eq!(deleted_units, vec![2, 1, 2, 1, 2]);
+}
+
+#[test]
+fn test_smart_backspace_with_astral_characters() {
+    let mut engine = create_engine(SMART_BACKSPACE_EMOJI_KMS).unwrap();
+    for ch in "aska".chars() {
+        process_checked(&mut engine, key_input_from_char(ch));
+    }
+    assert_eq!(engine.composing_text(), "😀𝐀𝐁က😀");
+
+    let mut deleted = Vec::new();
+    while !engine.composing_text().is_empty() {
+        let output = process_checked(&mut engine, key_input_from_vk(VirtualKey::Back));
+        deleted.push((output.delete_chars, output.delete_utf16_units));
+    }
+    // Smart backspace undoes one key at a time, "s" produced two astral characters
+    assert_eq!(deleted, vec![(1, 2), (1, 1), (2, 4), (1, 2)]);
+}
+
+#[test]
+fn test_ffi_reports_utf16_delete_count() {
+    let binary = create_km2_binary(&kms2km2::compile_kms(EMOJI_KMS).unwrap()).unwrap();
+
+    unsafe {
+        let engine = keymagic_engine_new();
+        let result = keymagic_engine_load_keyboard_from_memory(engine, binary.as_ptr(), binary.len());
+        assert_eq!(result, KeyMagicResult::Success);
+
+        let mut output = ProcessKeyOutput {
+            action_type: 0,
+            text: ptr::null_mut(),
+            delete_count: 0,
+            composing_text: ptr::null_mut(),
+            is_processed: 0,
+            delete_utf16_count: 0,
+            composing_caret_utf16: 0,
+            notification: ptr::null_mut(),
+            delete_clamped: 0,
+        };
+
+        for (key, delete_count, delete_utf16_count) in [('a', 0, 0), ('x', 1, 2)] {
+            let result = keymagic_engine_process_key(
+                engine,
+                VirtualKey::from_win_vk(key.to_ascii_uppercase() as u16).unwrap() as i32,
+                key as std::os::raw::c_char,
+                0, 0, 0, 0,
+                &mut output,
+            );
+            assert_eq!(result, KeyMagicResult::Success);
+            assert_eq!(output.delete_count, delete_count);
+            assert_eq!(output.delete_utf16_count, delete_utf16_count);
+            keymagic_free_string(output.text);
+            keymagic_free_string(output.composing_text);
+        }
+
+        let composition = keymagic_engine_get_composition(engine);
+        assert_eq!(CStr::from_ptr(composition).to_str().unwrap(), "𝐀");
+        keymagic_free_string(composition);
+
+        keymagic_engine_free(engine);
+    }
+}
@@ -117,6 +117,10 @@ class ProcessKeyOutput(ctypes.Structure):
         ("delete_count", ctypes.c_int),
         ("composing_text", ctypes.POINTER(ctypes.c_char)),
         ("is_processed", ctypes.c_int),
+        ("delete_utf16_count", ctypes.c_int),
+        ("composing_caret_utf16", ctypes.c_int),
+        ("notification", ctypes.POINTER(ctypes.c_char)),
+        ("delete_clamped", ctypes.c_int),
     ]
 
 # Define function signatures
@@ -0,0 +1,414 @@
+//! Multi-threaded stress tests for SharedEngine and the FFI engine handle
+#![cfg(feature = "ffi")]
+
+mod common;
+use common::*;
+
+use keymagic_core::ffi::*;
+use keymagic_core::{EngineSlot, KeyMagicEngine, SharedEngine, VirtualKey};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 8;
+const KEYS_PER_THREAD: usize = 200;
+
+const UPPERCASE_KMS: &str = r#"
+    "a" => "A"
+    "b" => "B"
+    "c" => "C"
+    "d" => "D"
+    "e" => "E"
+    "f" => "F"
+    "g" => "G"
+    "h" => "H"
+"#;
+
+/// Types the same keys as `UPPERCASE_KMS` into digits, so output shows
+/// which of the two keyboards produced it
+const DIGITS_KMS: &str = r#"
+    "a" => "1"
+    "b" => "2"
+    "c" => "3"
+    "d" => "4"
+    "e" => "5"
+    "f" => "6"
+    "g" => "7"
+    "h" => "8"
+"#;
+
+/// Keyboard switches made while another thread types
+const SWAPS: usize = 5_000;
+/// Keys typed at least, however quickly the switches finish
+const MIN_KEYS: usize = 100;
+/// Longest a key may take while keyboards are switched; far above the
+/// microseconds a key takes, but catches a key stuck behind a switch
+const MAX_KEY_LATENCY: Duration = Duration::from_millis(500);
+
+/// Runs `f` on a separate thread and fails if it does not finish in time
+fn run_with_timeout<F: FnOnce() + Send + 'static>(f: F) {
+    let (tx, rx) = mpsc::channel();
+    let worker = thread::spawn(move || {
+        f();
+        let _ = tx.send(());
+    });
+    match rx.recv_timeout(Duration::from_secs(60)) {
+        Ok(()) => worker.join().unwrap(),
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            // The worker panicked; surface its panic
+            worker.join().unwrap();
+        }
+        Err(mpsc::RecvTimeoutError::Timeout) => panic!("engine calls deadlocked"),
+    }
+}
+
+fn shared_engine() -> SharedEngine {
+    SharedEngine::new(create_engine(UPPERCASE_KMS).unwrap())
+}
+
+fn thread_char(index: usize) -> char {
+    (b'a' + index as u8) as char
+}
+
+#[test]
+fn test_engine_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<KeyMagicEngine>();
+    assert_send_sync::<SharedEngine>();
+}
+
+#[test]
+fn test_concurrent_process_key_is_serialized() {
+    run_with_timeout(|| {
+        let engine = shared_engine();
+        let barrier = Arc::new(Barrier::new(THREADS));
+
+        let workers: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let engine = engine.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for _ in 0..KEYS_PER_THREAD {
+                        let before = engine.composing_text().chars().count();
+                        let output = engine.process_key(key_input_from_char(thread_char(i))).unwrap();
+                        // Every key appends exactly one character, whatever ran in between
+                        assert!(output.composing_text.chars().count() > before);
+                        assert!(output.composing_text.ends_with(thread_char(i).to_ascii_uppercase()));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // No key was lost or applied twice
+        let text = engine.composing_text();
+        assert_eq!(text.chars().count(), THREADS * KEYS_PER_THREAD);
+        for i in 0..THREADS {
+            let upper = thread_char(i).to_ascii_uppercase();
+            assert_eq!(text.chars().filter(|c| *c == upper).count(), KEYS_PER_THREAD);
+        }
+    });
+}
+
+#[test]
+fn test_concurrent_process_reset_and_read() {
+    run_with_timeout(|| {
+        let engine = shared_engine();
+        let barrier = Arc::new(Barrier::new(THREADS + 2));
+        let mut workers = Vec::new();
+
+        for i in 0..THREADS {
+            let engine = engine.clone();
+            let barrier = barrier.clone();
+            workers.push(thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..KEYS_PER_THREAD {
+                    engine.process_key(key_input_from_char(thread_char(i))).unwrap();
+                }
+            }));
+        }
+
+        let resetter = engine.clone();
+        let reset_barrier = barrier.clone();
+        workers.push(thread::spawn(move || {
+            reset_barrier.wait();
+            for _ in 0..KEYS_PER_THREAD {
+                resetter.reset();
+                thread::yield_now();
+            }
+        }));
+
+        let reader = engine.clone();
+        let read_barrier = barrier.clone();
+        workers.push(thread::spawn(move || {
+            read_barrier.wait();
+            for _ in 0..KEYS_PER_THREAD {
+                // Readers only ever see whole states produced by the rules
+                let text = reader.composing_text();
+                assert!(text.chars().all(|c| ('A'..='H').contains(&c)), "torn state: {:?}", text);
+                assert!(reader.emitted_text().chars().all(|c| ('A'..='H').contains(&c)));
+                let _ = reader.undo_depth();
+            }
+        }));
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // The engine is still fully usable afterwards
+        engine.reset();
+        assert_eq!(engine.composing_text(), "");
+        let output = engine.process_key(key_input_from_char('a')).unwrap();
+        assert_eq!(output.composing_text, "A");
+    });
+}
+
+#[test]
+fn test_process_key_test_does_not_race_with_writers() {
+    run_with_timeout(|| {
+        let engine = shared_engine();
+        engine.process_key(key_input_from_char('a')).unwrap();
+
+        let workers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let engine = engine.clone();
+                thread::spawn(move || {
+                    for _ in 0..KEYS_PER_THREAD {
+                        let preview = engine.process_key_test(key_input_from_char('b')).unwrap();
+                        assert!(preview.composing_text.ends_with('B'));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // Previews never touched the shared state
+        assert_eq!(engine.composing_text(), "A");
+    });
+}
+
+#[test]
+fn test_write_guard_groups_calls() {
+    let engine = shared_engine();
+    {
+        let mut guard = engine.write();
+        guard.process_key(key_input_from_char('a')).unwrap();
+        guard.process_key(key_input_from_vk(VirtualKey::Back)).unwrap();
+    }
+    assert_eq!(engine.composing_text(), "");
+    assert!(engine.read().keyboard().rules.len() >= 8);
+}
+
+/// Raw handle that can be moved to other threads; the FFI handle is thread-safe
+#[derive(Clone, Copy)]
+struct SendHandle(*mut EngineHandle);
+unsafe impl Send for SendHandle {}
+
+#[test]
+fn test_ffi_handle_concurrent_use() {
+    let handle = SendHandle(keymagic_engine_new());
+    let binary = create_km2_binary(&create_basic_km2()).unwrap();
+    let result = keymagic_engine_load_keyboard_from_memory(handle.0, binary.as_ptr(), binary.len());
+    assert_eq!(result, KeyMagicResult::Success);
+
+    run_with_timeout(move || {
+        let workers: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let binary = binary.clone();
+                thread::spawn(move || {
+                    let handle = handle;
+                    for n in 0..KEYS_PER_THREAD {
+                        let mut output = ProcessKeyOutput {
+                            action_type: 0,
+                            text: std::ptr::null_mut(),
+                            delete_count: 0,
+                            composing_text: std::ptr::null_mut(),
+                            is_processed: 0,
+                            delete_utf16_count: 0,
+                            composing_caret_utf16: 0,
+                            notification: std::ptr::null_mut(),
+                            delete_clamped: 0,
+                        };
+                        let result = keymagic_engine_process_key(
+                            handle.0,
+                            VirtualKey::KeyA as i32,
+                            b'a' as std::os::raw::c_char,
+                            0, 0, 0, 0,
+                            &mut output,
+                        );
+                        assert_eq!(result, KeyMagicResult::Success);
+                        keymagic_free_string(output.text);
+                        keymagic_free_string(output.composing_text);
+
+                        match (i, n % 50) {
+                            // Reloading swaps the engine while others are processing
+                            (0, 0) => {
+                                let result = keymagic_engine_load_keyboard_from_memory(
+                                    handle.0, binary.as_ptr(), binary.len());
+                                assert_eq!(result, KeyMagicResult::Success);
+                            }
+                            (1, _) => {
+                                assert_eq!(keymagic_engine_reset(handle.0), KeyMagicResult::Success);
+                            }
+                            _ => {
+                                keymagic_free_string(keymagic_engine_get_composition(handle.0));
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    });
+
+    keymagic_engine_free(handle.0);
+}
+
+/// Composing text made by only one of the two keyboards
+fn from_one_keyboard(text: &str) -> bool {
+    text.chars().all(|c| ('A'..='H').contains(&c)) || text.chars().all(|c| ('1'..='8').contains(&c))
+}
+
+#[test]
+fn test_slot_swaps_while_typing() {
+    run_with_timeout(|| {
+        let slot = Arc::new(EngineSlot::new(Some(shared_engine())));
+        let digits = SharedEngine::new(create_engine(DIGITS_KMS).unwrap());
+        let done = Arc::new(AtomicBool::new(false));
+
+        let typist = {
+            let slot = slot.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut keys = 0;
+                let mut slowest = Duration::ZERO;
+                while keys < MIN_KEYS || !done.load(Ordering::Acquire) {
+                    let started = Instant::now();
+                    let engine = slot.load().expect("the slot is never emptied");
+                    if keys % 20 == 0 {
+                        engine.reset();
+                    }
+                    let output = engine.process_key(key_input_from_char(thread_char(keys % 8))).unwrap();
+                    slowest = slowest.max(started.elapsed());
+                    // Each key runs on one engine from start to end
+                    assert!(from_one_keyboard(&output.composing_text), "torn output: {:?}", output.composing_text);
+                    keys += 1;
+                }
+                (keys, slowest)
+            })
+        };
+
+        let uppercase = slot.load().unwrap();
+        for n in 0..SWAPS {
+            // Built before the switch, installed in one step
+            let next = if n % 2 == 0 { digits.clone() } else { uppercase.clone() };
+            slot.store(Some(next));
+            if n % 100 == 0 {
+                thread::yield_now();
+            }
+        }
+        done.store(true, Ordering::Release);
+
+        let (keys, slowest) = typist.join().unwrap();
+        assert!(keys >= MIN_KEYS);
+        assert!(slowest < MAX_KEY_LATENCY, "a key took {:?}", slowest);
+    });
+}
+
+#[test]
+fn test_replaced_engine_outlives_keys_in_flight() {
+    let slot = EngineSlot::new(Some(shared_engine()));
+    let in_flight = slot.load().unwrap();
+    in_flight.process_key(key_input_from_char('a')).unwrap();
+
+    let digits = SharedEngine::new(create_engine(DIGITS_KMS).unwrap());
+    let previous = slot.replace(Some(digits)).unwrap();
+
+    // The key that loaded the old engine finishes on it
+    let output = in_flight.process_key(key_input_from_char('b')).unwrap();
+    assert_eq!(output.composing_text, "AB");
+    // The new engine starts with nothing composing; the old text can still be flushed
+    assert_eq!(slot.load().unwrap().composing_text(), "");
+    assert_eq!(previous.flush(), "AB");
+
+    slot.store(None);
+    assert!(!slot.is_loaded());
+    assert!(slot.load().is_none());
+}
+
+#[test]
+fn test_ffi_keyboard_switches_while_typing() {
+    let handle = SendHandle(keymagic_engine_new());
+    let uppercase = create_km2_binary(&kms2km2::compile_kms(UPPERCASE_KMS).unwrap()).unwrap();
+    let digits = create_km2_binary(&kms2km2::compile_kms(DIGITS_KMS).unwrap()).unwrap();
+    assert_eq!(
+        keymagic_engine_load_keyboard_from_memory(handle.0, uppercase.as_ptr(), uppercase.len()),
+        KeyMagicResult::Success
+    );
+
+    run_with_timeout(move || {
+        let done = Arc::new(AtomicBool::new(false));
+        let typist = {
+            let done = done.clone();
+            thread::spawn(move || {
+                let handle = handle;
+                let mut keys = 0;
+                let mut slowest = Duration::ZERO;
+                while keys < MIN_KEYS || !done.load(Ordering::Acquire) {
+                    let mut output = ProcessKeyOutput {
+                        action_type: 0,
+                        text: std::ptr::null_mut(),
+                        delete_count: 0,
+                        composing_text: std::ptr::null_mut(),
+                        is_processed: 0,
+                        delete_utf16_count: 0,
+                        composing_caret_utf16: 0,
+                        notification: std::ptr::null_mut(),
+                        delete_clamped: 0,
+                    };
+                    let started = Instant::now();
+                    let result = keymagic_engine_process_key(
+                        handle.0,
+                        VirtualKey::KeyA as i32,
+                        b'a' as std::os::raw::c_char,
+                        0, 0, 0, 0,
+                        &mut output,
+                    );
+                    slowest = slowest.max(started.elapsed());
+                    assert_eq!(result, KeyMagicResult::Success);
+                    let composing = unsafe { std::ffi::CStr::from_ptr(output.composing_text) }.to_str().unwrap().to_string();
+                    assert!(from_one_keyboard(&composing), "torn output: {:?}", composing);
+                    keymagic_free_string(output.text);
+                    keymagic_free_string(output.composing_text);
+                    if keys % 20 == 0 {
+                        assert_eq!(keymagic_engine_reset(handle.0), KeyMagicResult::Success);
+                    }
+                    keys += 1;
+                }
+                slowest
+            })
+        };
+
+        let handle = handle;
+        for n in 0..SWAPS {
+            let binary = if n % 2 == 0 { &digits } else { &uppercase };
+            let result = keymagic_engine_load_keyboard_from_memory(handle.0, binary.as_ptr(), binary.len());
+            assert_eq!(result, KeyMagicResult::Success);
+        }
+        done.store(true, Ordering::Release);
+
+        let slowest = typist.join().unwrap();
+        assert!(slowest < MAX_KEY_LATENCY, "a key took {:?}", slowest);
+    });
+
+    keymagic_engine_free(handle.0);
+}
@@ -0,0 +1,196 @@
+//! Tests for tray icon rendering
+#![cfg(feature = "ffi")]
+
+use keymagic_core::ffi::{keymagic_tray_draw_badge, keymagic_tray_render_icon, KeyMagicResult};
+use keymagic_core::tray_icon::*;
+
+const SIZES: [u32; 4] = [16, 20, 24, 32];
+
+fn opaque_pixels(image: &IconImage) -> usize {
+    image.as_rgba().chunks_exact(4).filter(|p| p[3] == 255).count()
+}
+
+fn covered_pixels(image: &IconImage) -> usize {
+    image.as_rgba().chunks_exact(4).filter(|p| p[3] > 0).count()
+}
+
+#[test]
+fn test_theme_from_taskbar() {
+    assert_eq!(IconTheme::for_taskbar(true), IconTheme::Light);
+    assert_eq!(IconTheme::for_taskbar(false), IconTheme::Dark);
+    assert_eq!(IconTheme::Dark.foreground(), [0xFF, 0xFF, 0xFF]);
+    assert_ne!(IconTheme::Light.foreground(), IconTheme::Dark.foreground());
+}
+
+#[test]
+fn test_glyph_is_monochrome() {
+    for theme in [IconTheme::Light, IconTheme::Dark] {
+        for size in SIZES {
+            let image = render_glyph(size, theme);
+            assert_eq!(image.size(), size);
+            assert_eq!(image.as_rgba().len(), (size * size * 4) as usize);
+
+            // Every drawn pixel is the theme color; only alpha varies
+            let [r, g, b] = theme.foreground();
+            for pixel in image.as_rgba().chunks_exact(4).filter(|p| p[3] > 0) {
+                assert_eq!(&pixel[..3], &[r, g, b], "size {}", size);
+            }
+            assert!(covered_pixels(&image) > (size * size / 8) as usize, "size {}", size);
+        }
+    }
+}
+
+#[test]
+fn test_glyph_shape() {
+    let image = render_glyph(32, IconTheme::Dark);
+    // Corners and the space above the keyboard stay transparent
+    for (x, y) in [(0, 0), (31, 0), (0, 31), (31, 31), (16, 2)] {
+        assert_eq!(image.pixel(x, y)[3], 0, "({}, {})", x, y);
+    }
+    // Frame edges are solid
+    assert_eq!(image.pixel(16, 6)[3], 255);
+    assert_eq!(image.pixel(2, 16)[3], 255);
+    // Gap between the frame and the keys
+    assert_eq!(image.pixel(16, 9)[3], 0);
+    // A key and the space bar
+    assert_eq!(image.pixel(6, 10)[3], 255);
+    assert_eq!(image.pixel(16, 18)[3], 255);
+}
+
+#[test]
+fn test_glyph_is_crisp_at_small_sizes() {
+    let image = render_glyph(16, IconTheme::Light);
+    // Only the rounded corners of the frame are blended
+    let partial = image.as_rgba().chunks_exact(4).filter(|p| p[3] > 0 && p[3] < 255).count();
+    assert!(partial > 0);
+    assert!(partial <= 8, "{} blended pixels", partial);
+}
+
+#[test]
+fn test_zero_size() {
+    let mut image = render_glyph(0, IconTheme::Dark);
+    draw_badge(&mut image, Badge::Slash, IconTheme::Dark);
+    assert!(image.as_rgba().is_empty());
+}
+
+#[test]
+fn test_badge_for_processing_state() {
+    assert_eq!(Badge::for_processing(true), None);
+    assert_eq!(Badge::for_processing(false), Some(Badge::Slash));
+
+    for size in SIZES {
+        let enabled = render_tray_icon(size, IconTheme::Dark, true);
+        let disabled = render_tray_icon(size, IconTheme::Dark, false);
+        assert_eq!(enabled, render_glyph(size, IconTheme::Dark));
+        assert_ne!(enabled, disabled, "size {}", size);
+    }
+}
+
+#[test]
+fn test_slash_badge() {
+    let mut image = IconImage::new(32);
+    draw_badge(&mut image, Badge::Slash, IconTheme::Light);
+    let [r, g, b] = IconTheme::Light.foreground();
+
+    // Drawn along the diagonal, nowhere near the other corners
+    assert_eq!(image.pixel(16, 16), [r, g, b, 255]);
+    assert_eq!(image.pixel(6, 6)[3], 255);
+    assert_eq!(image.pixel(26, 26)[3], 255);
+    assert_eq!(image.pixel(28, 3)[3], 0);
+    assert_eq!(image.pixel(3, 28)[3], 0);
+}
+
+#[test]
+fn test_slash_keeps_gap_over_artwork() {
+    // Opaque red square standing in for a keyboard's own icon
+    let size = 32;
+    let red: Vec<u8> = (0..size * size).flat_map(|_| [0, 0, 255, 255]).collect();
+    let mut image = IconImage::from_bgra(size, &red).unwrap();
+    draw_badge(&mut image, Badge::Slash, IconTheme::Dark);
+
+    assert_eq!(image.pixel(16, 16), [255, 255, 255, 255]);
+    // Next to the stroke the artwork is erased, further away it is untouched
+    assert_eq!(image.pixel(18, 14)[3], 0);
+    assert_eq!(image.pixel(28, 3), [255, 0, 0, 255]);
+    assert_eq!(image.pixel(3, 28), [255, 0, 0, 255]);
+}
+
+#[test]
+fn test_dot_badge() {
+    for size in SIZES {
+        let mut image = render_glyph(size, IconTheme::Dark);
+        let before = image.clone();
+        draw_badge(&mut image, Badge::Dot, IconTheme::Dark);
+
+        // Only the bottom-right quarter changes
+        let half = size / 2;
+        for y in 0..size {
+            for x in 0..size {
+                if x < half || y < half {
+                    assert_eq!(image.pixel(x, y), before.pixel(x, y), "size {} ({}, {})", size, x, y);
+                }
+            }
+        }
+        let end = size - 1;
+        let center = end - (size as f32 * 0.2) as u32;
+        assert_eq!(image.pixel(center, center), [255, 255, 255, 255], "size {}", size);
+    }
+}
+
+#[test]
+fn test_badge_is_visible_at_small_sizes() {
+    let mut image = IconImage::new(16);
+    draw_badge(&mut image, Badge::Slash, IconTheme::Dark);
+    assert!(opaque_pixels(&image) >= 12);
+    assert!(covered_pixels(&image) > opaque_pixels(&image));
+}
+
+#[test]
+fn test_bgra_round_trip() {
+    let image = render_tray_icon(24, IconTheme::Light, false);
+    let bgra = image.to_bgra();
+    assert_eq!(IconImage::from_bgra(24, &bgra), Some(image.clone()));
+
+    // Red and blue are swapped, green and alpha stay
+    let rgba = image.as_rgba();
+    for (a, b) in rgba.chunks_exact(4).zip(bgra.chunks_exact(4)) {
+        assert_eq!([a[2], a[1], a[0], a[3]], [b[0], b[1], b[2], b[3]]);
+    }
+
+    assert_eq!(IconImage::from_bgra(24, &bgra[4..]), None);
+}
+
+#[test]
+fn test_ffi_render_icon() {
+    let size = 16;
+    let mut buf = vec![0u8; 16 * 16 * 4];
+    let result = keymagic_tray_render_icon(size, 1, 0, buf.as_mut_ptr(), buf.len());
+    assert_eq!(result, KeyMagicResult::Success);
+    assert_eq!(buf, render_tray_icon(16, IconTheme::Light, false).to_bgra());
+
+    // Buffer too small and sizes out of range
+    assert_eq!(
+        keymagic_tray_render_icon(size, 1, 0, buf.as_mut_ptr(), buf.len() - 1),
+        KeyMagicResult::ErrorInvalidParameter
+    );
+    assert_eq!(
+        keymagic_tray_render_icon(0, 1, 0, buf.as_mut_ptr(), buf.len()),
+        KeyMagicResult::ErrorInvalidParameter
+    );
+    assert_eq!(
+        keymagic_tray_render_icon(size, 1, 0, std::ptr::null_mut(), 0),
+        KeyMagicResult::ErrorInvalidParameter
+    );
+}
+
+#[test]
+fn test_ffi_draw_badge() {
+    let mut buf = render_glyph(20, IconTheme::Dark).to_bgra();
+    assert_eq!(keymagic_tray_draw_badge(buf.as_mut_ptr(), 20, 0, 2), KeyMagicResult::Success);
+    assert_eq!(buf, render_tray_icon(20, IconTheme::Dark, false).to_bgra());
+
+    assert_eq!(
+        keymagic_tray_draw_badge(buf.as_mut_ptr(), 20, 0, 7),
+        KeyMagicResult::ErrorInvalidParameter
+    );
+}
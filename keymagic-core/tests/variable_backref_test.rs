@@ -1,7 +1,7 @@
 mod common;
 use common::*;
-use keymagic_core::engine::ActionType;
-use keymagic_core::BinaryFormatElement;
+use keymagic_core::ActionType;
+use keymagic_core::types::km2::BinaryFormatElement;
 use keymagic_core::types::opcodes::FLAG_ANYOF;
 
 #[test]
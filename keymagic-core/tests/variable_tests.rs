@@ -1,8 +1,9 @@
 mod common;
 
 use common::*;
-use keymagic_core::{BinaryFormatElement, km2::Km2Loader};
-use keymagic_core::engine::ActionType;
+use keymagic_core::types::km2::BinaryFormatElement;
+use keymagic_core::km2::Km2Loader;
+use keymagic_core::ActionType;
 use keymagic_core::types::opcodes::FLAG_ANYOF;
 
 #[test]
@@ -1,8 +1,8 @@
 mod common;
 
 use common::*;
-use keymagic_core::{BinaryFormatElement, VirtualKey};
-use keymagic_core::engine::ActionType;
+use keymagic_core::types::km2::BinaryFormatElement;
+use keymagic_core::{VirtualKey, ActionType};
 
 #[test]
 fn test_virtual_key_with_modifiers() {
@@ -1,7 +1,7 @@
 mod common;
 
 use common::*;
-use keymagic_core::engine::ActionType;
+use keymagic_core::ActionType;
 use std::path::PathBuf;
 
 /// Get the path to the fixtures directory
@@ -44,6 +44,7 @@ keymagic_config_load(const gchar* config_path)
     config->last_update_check = NULL;
     config->last_scanned_version = NULL;
     config->update_remind_after = NULL;
+    config->key_processing_enabled = TRUE;
     config->active_keyboard = NULL;
     config->last_used = NULL;
     config->installed_keyboards = NULL;
@@ -82,6 +83,11 @@ keymagic_config_load(const gchar* config_path)
             config->update_remind_after = g_strdup(datum.u.s);
             free(datum.u.s);
         }
+        
+        datum = toml_bool_in(general, "key_processing_enabled");
+        if (datum.ok) {
+            config->key_processing_enabled = datum.u.b ? TRUE : FALSE;
+        }
     }
     
     /* Parse [keyboards] section */
@@ -425,6 +431,8 @@ keymagic_config_save(const gchar* config_path, const KeyMagicConfig* config)
         g_string_append_printf(toml_str, "update_remind_after = \"%s\"\n", 
                               config->update_remind_after);
     }
+    g_string_append_printf(toml_str, "key_processing_enabled = %s\n", 
+                          config->key_processing_enabled ? "true" : "false");
     g_string_append(toml_str, "\n");
     
     /* Keyboards section */
@@ -506,3 +514,176 @@ keymagic_config_save(const gchar* config_path, const KeyMagicConfig* config)
     return success;
 }
 
+
+/**
+ * Check whether a line is a table header such as [keyboards] or [[keyboards.installed]]
+ */
+static gboolean
+is_table_header(const gchar* line)
+{
+    while (*line == ' ' || *line == '\t') line++;
+    return *line == '[';
+}
+
+/**
+ * Check whether a line assigns the given bare key
+ */
+static gboolean
+line_sets_key(const gchar* line, const gchar* key)
+{
+    while (*line == ' ' || *line == '\t') line++;
+    
+    gsize key_len = strlen(key);
+    if (strncmp(line, key, key_len) != 0) {
+        return FALSE;
+    }
+    
+    line += key_len;
+    while (*line == ' ' || *line == '\t') line++;
+    return *line == '=';
+}
+
+/**
+ * Set one key of a table in the configuration file
+ * 
+ * Edits the file text in place so tables and keys this parser does not know
+ * about survive. The key is replaced within the table, added right after the
+ * table header when missing, and the table is appended when the file has none.
+ * 
+ * @param config_path Path to config.toml file
+ * @param table Table name (e.g. "keyboards")
+ * @param key Bare key within the table
+ * @param value TOML literal to assign
+ * @return TRUE on success, FALSE on error
+ */
+static gboolean
+config_set_value(const gchar* config_path, const gchar* table, const gchar* key, const gchar* value)
+{
+    gchar* contents = NULL;
+    GError* error = NULL;
+    
+    if (!g_file_get_contents(config_path, &contents, NULL, &error)) {
+        /* A missing file starts out empty */
+        if (!g_error_matches(error, G_FILE_ERROR, G_FILE_ERROR_NOENT)) {
+            g_warning("%s: Failed to read config file: %s", LOG_TAG, error->message);
+            g_error_free(error);
+            return FALSE;
+        }
+        g_error_free(error);
+        error = NULL;
+        contents = g_strdup("");
+    }
+    
+    gchar* header = g_strdup_printf("[%s]", table);
+    gchar** lines = g_strsplit(contents, "\n", -1);
+    guint n_lines = g_strv_length(lines);
+    
+    /* Drop the empty piece after the final newline */
+    if (n_lines > 0 && *lines[n_lines - 1] == '\0') {
+        n_lines--;
+    }
+    
+    /* Find the table header and the key's line within that table */
+    gint header_line = -1;
+    gint key_line = -1;
+    for (guint i = 0; i < n_lines; i++) {
+        if (is_table_header(lines[i])) {
+            if (header_line >= 0) {
+                break;
+            }
+            gchar* trimmed = g_strstrip(g_strdup(lines[i]));
+            if (g_strcmp0(trimmed, header) == 0) {
+                header_line = (gint)i;
+            }
+            g_free(trimmed);
+        } else if (header_line >= 0 && line_sets_key(lines[i], key)) {
+            key_line = (gint)i;
+            break;
+        }
+    }
+    
+    GString* output = g_string_new("");
+    for (guint i = 0; i < n_lines; i++) {
+        if ((gint)i == key_line) {
+            g_string_append_printf(output, "%s = %s\n", key, value);
+            continue;
+        }
+        g_string_append_printf(output, "%s\n", lines[i]);
+        if ((gint)i == header_line && key_line < 0) {
+            g_string_append_printf(output, "%s = %s\n", key, value);
+        }
+    }
+    
+    if (header_line < 0) {
+        if (output->len > 0) {
+            g_string_append(output, "\n");
+        }
+        g_string_append_printf(output, "%s\n%s = %s\n", header, key, value);
+    }
+    
+    gboolean success = g_file_set_contents(config_path, output->str, -1, &error);
+    if (!success) {
+        g_warning("%s: Failed to write config file: %s", LOG_TAG, 
+                  error ? error->message : "Unknown error");
+        if (error) g_error_free(error);
+    } else {
+        g_debug("%s: Set %s.%s in %s", LOG_TAG, table, key, config_path);
+    }
+    
+    g_string_free(output, TRUE);
+    g_strfreev(lines);
+    g_free(header);
+    g_free(contents);
+    
+    return success;
+}
+
+/**
+ * Quote a string as a TOML basic string
+ */
+static gchar*
+toml_quote(const gchar* str)
+{
+    GString* quoted = g_string_new("\"");
+    
+    for (const gchar* p = str; *p; p++) {
+        switch (*p) {
+            case '"':  g_string_append(quoted, "\\\""); break;
+            case '\\': g_string_append(quoted, "\\\\"); break;
+            case '\n': g_string_append(quoted, "\\n"); break;
+            case '\t': g_string_append(quoted, "\\t"); break;
+            default:   g_string_append_c(quoted, *p); break;
+        }
+    }
+    
+    g_string_append_c(quoted, '"');
+    return g_string_free(quoted, FALSE);
+}
+
+/**
+ * Set keyboards.active in the configuration file
+ */
+gboolean
+keymagic_config_set_active_keyboard(const gchar* config_path, const gchar* keyboard_id)
+{
+    g_return_val_if_fail(config_path != NULL, FALSE);
+    g_return_val_if_fail(keyboard_id != NULL, FALSE);
+    
+    gchar* value = toml_quote(keyboard_id);
+    gboolean success = config_set_value(config_path, "keyboards", "active", value);
+    g_free(value);
+    
+    return success;
+}
+
+/**
+ * Set general.key_processing_enabled in the configuration file
+ */
+gboolean
+keymagic_config_set_key_processing_enabled(const gchar* config_path, gboolean enabled)
+{
+    g_return_val_if_fail(config_path != NULL, FALSE);
+    
+    return config_set_value(config_path, "general", "key_processing_enabled",
+                            enabled ? "true" : "false");
+}
@@ -31,6 +31,7 @@ typedef struct {
     gchar* last_update_check;           /* ISO 8601 timestamp or NULL */
     gchar* last_scanned_version;        /* Last scanned app version or NULL */
     gchar* update_remind_after;         /* ISO 8601 timestamp or NULL */
+    gboolean key_processing_enabled;    /* FALSE while the user switched KeyMagic off */
     
     /* Keyboard settings */
     gchar* active_keyboard;             /* keyboards.active - ID of current keyboard */
@@ -106,6 +107,30 @@ void keymagic_config_free_keyboard(InstalledKeyboard* keyboard);
  */
 gboolean keymagic_config_save(const gchar* config_path, const KeyMagicConfig* config);
 
+/**
+ * Set keyboards.active in the configuration file
+ * 
+ * Only that key is rewritten; everything else the GUI stores in the file
+ * is kept as is.
+ * 
+ * @param config_path Path to config.toml file
+ * @param keyboard_id Keyboard ID to make active
+ * @return TRUE on success, FALSE on error
+ */
+gboolean keymagic_config_set_active_keyboard(const gchar* config_path, const gchar* keyboard_id);
+
+/**
+ * Set general.key_processing_enabled in the configuration file
+ * 
+ * Only that key is rewritten; everything else the GUI stores in the file
+ * is kept as is.
+ * 
+ * @param config_path Path to config.toml file
+ * @param enabled Whether key processing is on
+ * @return TRUE on success, FALSE on error
+ */
+gboolean keymagic_config_set_key_processing_enabled(const gchar* config_path, gboolean enabled);
+
 G_END_DECLS
 
 #endif /* KEYMAGIC_CONFIG_H */
\ No newline at end of file
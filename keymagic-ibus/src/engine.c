@@ -1,5 +1,6 @@
 #include "engine.h"
 #include "config.h"
+#include "properties.h"
 #include "ffi_bridge.h"
 #include "keycode_map.h"
 #include <string.h>
@@ -34,6 +35,22 @@ static gpointer create_hotkey_hash(guint modifiers, guint keyval);
 /* Timeout callback for hiding auxiliary text */
 static gboolean aux_text_timeout_cb(gpointer user_data);
 
+/* Shows a short message in the auxiliary text */
+static void show_aux_message(KeyMagicEngine* engine, const gchar* message);
+
+/* Property panel operations */
+static void panel_register_properties(gpointer user_data, IBusPropList* props);
+static void panel_update_property(gpointer user_data, IBusProperty* prop);
+static gboolean panel_load_keyboard(gpointer user_data, const gchar* keyboard_id);
+static void panel_set_processing_enabled(gpointer user_data, gboolean enabled);
+
+static const KeyMagicPanelOps panel_ops = {
+    panel_register_properties,
+    panel_update_property,
+    panel_load_keyboard,
+    panel_set_processing_enabled,
+};
+
 /* Engine method implementations */
 static void keymagic_engine_class_init(KeyMagicEngineClass* klass);
 static void keymagic_engine_init(KeyMagicEngine* engine);
@@ -91,14 +108,13 @@ keymagic_engine_init(KeyMagicEngine* engine)
     engine->config_monitor = NULL;
     engine->keyboard_load_failed = FALSE;
     engine->keyboard_changed = FALSE;
+    engine->processing_enabled = TRUE;
     engine->preedit_text = NULL;
     engine->preedit_visible = FALSE;
     engine->preedit_cursor_pos = 0;
     
     /* Initialize property management */
-    engine->prop_list = NULL;
-    engine->keyboard_properties = g_hash_table_new_full(g_str_hash, g_str_equal,
-                                                        g_free, g_free);
+    engine->properties = NULL;
     engine->keyboard_hotkeys = g_hash_table_new_full(g_direct_hash, g_direct_equal,
                                                      NULL, g_free);
     
@@ -127,6 +143,11 @@ keymagic_engine_init(KeyMagicEngine* engine)
         g_object_unref(config_file);
     }
     
+    /* Set up the language bar menu */
+    gchar* keyboards_dir = keymagic_config_get_keyboards_dir();
+    engine->properties = keymagic_properties_new(&panel_ops, engine, engine->config_path, keyboards_dir);
+    g_free(keyboards_dir);
+    
     /* Load initial configuration */
     keymagic_engine_load_config(engine);
 }
@@ -165,13 +186,8 @@ keymagic_engine_finalize(GObject* object)
     keymagic_engine_clear_preedit(engine);
     
     /* Cleanup property management */
-    if (engine->prop_list) {
-        engine->prop_list = NULL;
-    }
-    if (engine->keyboard_properties) {
-        g_hash_table_destroy(engine->keyboard_properties);
-        engine->keyboard_properties = NULL;
-    }
+    keymagic_properties_free(engine->properties);
+    engine->properties = NULL;
     if (engine->keyboard_hotkeys) {
         g_hash_table_destroy(engine->keyboard_hotkeys);
         engine->keyboard_hotkeys = NULL;
@@ -220,6 +236,11 @@ keymagic_engine_load_config(KeyMagicEngine* engine)
                 engine->active_keyboard_id ? engine->active_keyboard_id : "(none)");
     }
     
+    /* Check if key processing was switched on or off */
+    if (engine->processing_enabled != config->key_processing_enabled) {
+        panel_set_processing_enabled(engine, config->key_processing_enabled);
+    }
+    
     keymagic_config_free(config);
     return TRUE;
 }
@@ -374,20 +395,11 @@ keymagic_engine_process_key_event(IBusEngine* ibus_engine, guint keyval,
         if (keyboard_id) {
             g_debug("%s: Hotkey matched for keyboard: %s", LOG_TAG, keyboard_id);
             
-            /* Switch to the keyboard */
-            if (g_strcmp0(keyboard_id, engine->active_keyboard_id) != 0) {
-                /* Commit any pending preedit text before switching */
-                if (engine->preedit_visible && engine->preedit_text) {
-                    keymagic_engine_commit_preedit(engine);
-                }
-                
-                /* Update active keyboard */
-                g_free(engine->active_keyboard_id);
-                engine->active_keyboard_id = g_strdup(keyboard_id);
-                engine->keyboard_changed = TRUE;
-                
-                /* Load the keyboard immediately */
-                if (keymagic_ibus_engine_load_keyboard(engine, keyboard_id)) {
+            /* Switch to the keyboard, or turn KeyMagic back on with it */
+            if (g_strcmp0(keyboard_id, engine->active_keyboard_id) != 0 ||
+                !engine->processing_enabled) {
+                /* Loads the keyboard and saves the selection to the config file */
+                if (keymagic_properties_select_keyboard(engine->properties, keyboard_id)) {
                     
                     /* Show notification using auxiliary text */
                     gchar* message = NULL;
@@ -399,6 +411,7 @@ keymagic_engine_process_key_event(IBusEngine* ibus_engine, guint keyval,
                         if (kb_info && kb_info->name) {
                             message = g_strdup_printf("Switched to: %s", kb_info->name);
                         }
+                        keymagic_config_free(config);
                     }
                     
                     /* Fallback to keyboard ID if no display name found */
@@ -406,26 +419,8 @@ keymagic_engine_process_key_event(IBusEngine* ibus_engine, guint keyval,
                         message = g_strdup_printf("Switched to: %s", keyboard_id);
                     }
                     
-                    IBusText* text = ibus_text_new_from_string(message);
-                    ibus_engine_update_auxiliary_text((IBusEngine*)engine, text, TRUE);
+                    show_aux_message(engine, message);
                     g_free(message);
-                    
-                    /* Cancel any existing timeout */
-                    if (engine->aux_text_timeout_id > 0) {
-                        g_source_remove(engine->aux_text_timeout_id);
-                    }
-                    
-                    /* Hide notification after 2 seconds */
-                    engine->aux_text_timeout_id = g_timeout_add_seconds(2, 
-                        aux_text_timeout_cb, engine);
-                    
-                    /* Update configuration file */
-                    if (config) {
-                        g_free(config->active_keyboard);
-                        config->active_keyboard = g_strdup(keyboard_id);
-                        keymagic_config_save(engine->config_path, config);
-                        keymagic_config_free(config);
-                    }
                 }
             }
             
@@ -434,6 +429,11 @@ keymagic_engine_process_key_event(IBusEngine* ibus_engine, guint keyval,
         }
     }
     
+    /* Pass everything through while KeyMagic is switched off */
+    if (!engine->processing_enabled) {
+        return FALSE;
+    }
+    
     /* Load keyboard on-demand if needed */
     if (engine->keyboard_changed && engine->active_keyboard_id) {
         keymagic_ibus_engine_load_keyboard(engine, engine->active_keyboard_id);
@@ -500,6 +500,14 @@ keymagic_engine_process_key_event(IBusEngine* ibus_engine, guint keyval,
         }
     }
     
+    /* Message raised by a rule, already rate limited by the engine; a
+     * clamped delete means the keyboard misbehaves */
+    if (result.delete_clamped) {
+        show_aux_message(engine, "Keyboard misbehaving: it tried to delete text it did not type");
+    } else if (result.notification) {
+        show_aux_message(engine, result.notification);
+    }
+    
     gboolean consumed = result.is_processed;
     keymagic_ffi_free_result(&result);
     
@@ -519,6 +527,9 @@ keymagic_engine_focus_in(IBusEngine* ibus_engine)
     /* Reload config in case it changed while inactive */
     keymagic_engine_load_config(engine);
     
+    /* The panel shows the focused engine's properties */
+    keymagic_properties_register(engine->properties);
+    
     /* Clear any stale state */
     keymagic_engine_clear_preedit(engine);
     if (engine->km_engine) {
@@ -752,6 +763,25 @@ keymagic_engine_should_commit(guint keyval, gboolean is_processed, const gchar*
     }
 }
 
+/**
+ * Show a message in the auxiliary text, hiding it after 2 seconds
+ */
+static void
+show_aux_message(KeyMagicEngine* engine, const gchar* message)
+{
+    IBusText* text = ibus_text_new_from_string(message);
+    ibus_engine_update_auxiliary_text((IBusEngine*)engine, text, TRUE);
+    
+    /* Cancel any existing timeout */
+    if (engine->aux_text_timeout_id > 0) {
+        g_source_remove(engine->aux_text_timeout_id);
+    }
+    
+    /* Hide notification after 2 seconds */
+    engine->aux_text_timeout_id = g_timeout_add_seconds(2, 
+        aux_text_timeout_cb, engine);
+}
+
 /**
  * Timeout callback for hiding auxiliary text
  */
@@ -793,8 +823,9 @@ config_file_changed_cb(GFileMonitor* monitor G_GNUC_UNUSED, GFile* file,
         g_debug("%s: Config file changed: %s", LOG_TAG, file_path ? file_path : "(unknown)");
         g_free(file_path);
         
-        /* Reload configuration */
+        /* Reload configuration and follow it in the language bar */
         keymagic_engine_load_config(engine);
+        keymagic_engine_update_properties(engine);
     }
 }
 
@@ -865,87 +896,7 @@ create_hotkey_hash(guint modifiers, guint keyval)
 }
 
 /**
- * Create an IBus property for a keyboard
- * 
- * @param kb Keyboard information
- * @param prop_key Property key string
- * @param keyboards_dir Path to keyboards directory (for loading KM2 metadata)
- * @param is_active Whether this keyboard is currently active
- * @return New IBusProperty or NULL on error
- */
-static IBusProperty*
-create_keyboard_property(InstalledKeyboard* kb, const gchar* prop_key, 
-                        const gchar* keyboards_dir, gboolean is_active)
-{
-    g_return_val_if_fail(kb != NULL, NULL);
-    g_return_val_if_fail(prop_key != NULL, NULL);
-    
-    /* Use name from config or fallback to ID */
-    const gchar* display_name = kb->name ? kb->name : kb->id;
-    
-    /* Determine hotkey to use */
-    gchar* hotkey_to_use = NULL;
-    gboolean hotkey_from_km2 = FALSE;
-    
-    if (kb->hotkey == NULL) {
-        /* Hotkey not set in config - try to get from KM2 file */
-        gchar* km2_path = NULL;
-        if (kb->filename && keyboards_dir) {
-            km2_path = g_build_filename(keyboards_dir, kb->filename, NULL);
-        }
-        
-        if (km2_path && g_file_test(km2_path, G_FILE_TEST_EXISTS)) {
-            void* km2_handle = keymagic_ffi_km2_load(km2_path);
-            if (km2_handle) {
-                hotkey_to_use = keymagic_ffi_km2_get_hotkey(km2_handle);
-                hotkey_from_km2 = TRUE;
-                keymagic_ffi_km2_free(km2_handle);
-            }
-        }
-        g_free(km2_path);
-    } else if (strlen(kb->hotkey) > 0) {
-        /* Hotkey explicitly set in config */
-        hotkey_to_use = g_strdup(kb->hotkey);
-    }
-    /* else: kb->hotkey is empty string - user disabled hotkey */
-    
-    /* Create IBus property */
-    IBusText* label = ibus_text_new_from_string(display_name);
-    IBusText* tooltip = NULL;
-    
-    if (hotkey_to_use && strlen(hotkey_to_use) > 0) {
-        gchar* tooltip_str = g_strdup_printf("%s (%s)", display_name, hotkey_to_use);
-        tooltip = ibus_text_new_from_string(tooltip_str);
-        g_free(tooltip_str);
-    } else {
-        tooltip = ibus_text_new_from_string(display_name);
-    }
-    
-    /* Determine property state (checked if active) */
-    IBusPropState state = is_active ? PROP_STATE_CHECKED : PROP_STATE_UNCHECKED;
-    
-    /* Create property with radio button type for exclusive selection */
-    IBusProperty* property = ibus_property_new(prop_key,
-                                              PROP_TYPE_RADIO,
-                                              label,
-                                              NULL,  /* icon */
-                                              tooltip,
-                                              TRUE,  /* sensitive */
-                                              TRUE,  /* visible */
-                                              state,
-                                              NULL); /* sub_props */
-    
-    g_debug("%s: Created keyboard property: %s (hotkey: %s%s)", LOG_TAG, display_name,
-            hotkey_to_use ? hotkey_to_use : "none",
-            hotkey_from_km2 ? " [from KM2]" : "");
-    
-    g_free(hotkey_to_use);
-    
-    return property;
-}
-
-/**
- * Update IBus properties for all available keyboards
+ * Update hotkeys and the language bar for all installed keyboards
  */
 void
 keymagic_engine_update_properties(KeyMagicEngine* engine)
@@ -954,12 +905,6 @@ keymagic_engine_update_properties(KeyMagicEngine* engine)
     
     g_debug("%s: Updating keyboard properties with hotkeys", LOG_TAG);
     
-    /* Create new property list */
-    engine->prop_list = ibus_prop_list_new();
-    
-    /* Clear keyboard properties mapping */
-    g_hash_table_remove_all(engine->keyboard_properties);
-    
     /* Clear hotkey mappings */
     g_hash_table_remove_all(engine->keyboard_hotkeys);
     
@@ -967,140 +912,71 @@ keymagic_engine_update_properties(KeyMagicEngine* engine)
     KeyMagicConfig* config = keymagic_config_load(engine->config_path);
     if (!config) {
         g_warning("%s: Failed to load config for keyboard properties", LOG_TAG);
-        return;
     }
     
-    gint keyboard_count = 0;
-    gchar* keyboards_dir = keymagic_config_get_keyboards_dir();
+    gint hotkey_count = 0;
     
-    /* First, try to use installed keyboards from config */
-    if (config->installed_keyboards) {
+    if (config && config->installed_keyboards) {
+        gchar* keyboards_dir = keymagic_config_get_keyboards_dir();
         GList* iter;
         for (iter = config->installed_keyboards; iter != NULL; iter = iter->next) {
             InstalledKeyboard* kb = (InstalledKeyboard*)iter->data;
             if (!kb || !kb->id) continue;
             
-            /* Create property key */
-            gchar* prop_key = g_strdup_printf("keyboard.%s", kb->id);
-            
-            /* Check if this is the active keyboard */
-            gboolean is_active = (engine->active_keyboard_id && 
-                                 g_strcmp0(kb->id, engine->active_keyboard_id) == 0);
-            
-            /* Create property using shared function */
-            IBusProperty* property = create_keyboard_property(kb, prop_key, keyboards_dir, is_active);
-            if (property) {
-                /* Add to property list */
-                ibus_prop_list_append(engine->prop_list, property);
-                
-                /* Map property key to keyboard ID */
-                g_hash_table_insert(engine->keyboard_properties, 
-                                   g_strdup(prop_key), 
-                                   g_strdup(kb->id));
-                
-                /* Register hotkey if available */
-                gchar* hotkey_str = NULL;
-                if (kb->hotkey == NULL) {
-                    /* Try to get from KM2 file */
-                    gchar* km2_path = NULL;
-                    if (kb->filename && keyboards_dir) {
-                        km2_path = g_build_filename(keyboards_dir, kb->filename, NULL);
-                    }
-                    
-                    if (km2_path && g_file_test(km2_path, G_FILE_TEST_EXISTS)) {
-                        void* km2_handle = keymagic_ffi_km2_load(km2_path);
-                        if (km2_handle) {
-                            hotkey_str = keymagic_ffi_km2_get_hotkey(km2_handle);
-                            keymagic_ffi_km2_free(km2_handle);
-                        }
-                    }
-                    g_free(km2_path);
-                } else if (strlen(kb->hotkey) > 0) {
-                    hotkey_str = g_strdup(kb->hotkey);
+            /* Parse and register hotkey if available */
+            gchar* hotkey_str = keymagic_properties_resolve_hotkey(kb, keyboards_dir);
+            if (hotkey_str) {
+                guint modifiers, keyval;
+                if (parse_hotkey_string(hotkey_str, &modifiers, &keyval)) {
+                    gpointer hotkey_hash = create_hotkey_hash(modifiers, keyval);
+                    g_hash_table_insert(engine->keyboard_hotkeys, 
+                                       hotkey_hash, 
+                                       g_strdup(kb->id));
+                    g_debug("%s: Registered hotkey %s for keyboard %s - keyval=0x%X (%u), modifiers=0x%X, hash=%p", 
+                            LOG_TAG, hotkey_str, kb->id, keyval, keyval, modifiers, hotkey_hash);
+                    hotkey_count++;
+                } else {
+                    g_warning("%s: Failed to parse hotkey '%s' for keyboard %s", 
+                              LOG_TAG, hotkey_str, kb->id);
                 }
-                
-                /* Parse and register hotkey */
-                if (hotkey_str && strlen(hotkey_str) > 0) {
-                    guint modifiers, keyval;
-                    if (parse_hotkey_string(hotkey_str, &modifiers, &keyval)) {
-                        gpointer hotkey_hash = create_hotkey_hash(modifiers, keyval);
-                        g_hash_table_insert(engine->keyboard_hotkeys, 
-                                           hotkey_hash, 
-                                           g_strdup(kb->id));
-                        g_debug("%s: Registered hotkey %s for keyboard %s - keyval=0x%X (%u), modifiers=0x%X, hash=%p", 
-                                LOG_TAG, hotkey_str, kb->id, keyval, keyval, modifiers, hotkey_hash);
-                    } else {
-                        g_warning("%s: Failed to parse hotkey '%s' for keyboard %s", 
-                                  LOG_TAG, hotkey_str, kb->id);
-                    }
-                }
-                g_free(hotkey_str);
-                
-                keyboard_count++;
             }
-            
-            g_free(prop_key);
+            g_free(hotkey_str);
         }
+        g_free(keyboards_dir);
     }
     
-    g_free(keyboards_dir);
+    /* Register the menu, or update what changed in it */
+    keymagic_properties_sync(engine->properties, config);
     keymagic_config_free(config);
     
-    /* Add separator if we have keyboards */
-    if (keyboard_count > 0) {
-        IBusProperty* separator = ibus_property_new("separator",
-                                                   PROP_TYPE_SEPARATOR,
-                                                   NULL,
-                                                   NULL,
-                                                   NULL,
-                                                   TRUE,
-                                                   TRUE,
-                                                   PROP_STATE_UNCHECKED,
-                                                   NULL);
-        ibus_prop_list_append(engine->prop_list, separator);
-    }
-    
-    /* Add "Open Configurator" menu item */
-    IBusText* configurator_label = ibus_text_new_from_string("Open KeyMagic...");
-    IBusText* configurator_tooltip = ibus_text_new_from_string("Open KeyMagic window");
-    
-    IBusProperty* configurator_prop = ibus_property_new("open-configurator",
-                                                       PROP_TYPE_NORMAL,
-                                                       configurator_label,
-                                                       NULL,  /* icon */
-                                                       configurator_tooltip,
-                                                       TRUE,  /* sensitive */
-                                                       TRUE,  /* visible */
-                                                       PROP_STATE_UNCHECKED,
-                                                       NULL); /* sub_props */
-    
-    ibus_prop_list_append(engine->prop_list, configurator_prop);
-    
-    /* Register properties with IBus */
-    ibus_engine_register_properties((IBusEngine*)engine, engine->prop_list);
-    g_debug("%s: Registered %d keyboard properties plus configurator menu", LOG_TAG, keyboard_count);
+    g_debug("%s: Registered %d keyboard hotkeys", LOG_TAG, hotkey_count);
 }
 
 /**
- * Handle property activation (keyboard switching)
+ * Show the property list in the panel
  */
-void
-keymagic_engine_activate_property(KeyMagicEngine* engine, const gchar* prop_name)
+static void
+panel_register_properties(gpointer user_data, IBusPropList* props)
 {
-    g_return_if_fail(KEYMAGIC_IS_ENGINE(engine));
-    g_return_if_fail(prop_name != NULL);
-    
-    g_debug("%s: Property activated: %s", LOG_TAG, prop_name);
-    
-    if (!engine->keyboard_properties)
-        return;
-        
-    /* Look up keyboard ID from property name */
-    const gchar* keyboard_id = g_hash_table_lookup(engine->keyboard_properties, prop_name);
-    if (!keyboard_id) {
-        g_debug("%s: Unknown property: %s", LOG_TAG, prop_name);
-        return;
-    }
+    ibus_engine_register_properties(IBUS_ENGINE(user_data), props);
+}
+
+/**
+ * Refresh one property in the panel
+ */
+static void
+panel_update_property(gpointer user_data, IBusProperty* prop)
+{
+    ibus_engine_update_property(IBUS_ENGINE(user_data), prop);
+}
+
+/**
+ * Switch the engine to a keyboard picked in the panel or by hotkey
+ */
+static gboolean
+panel_load_keyboard(gpointer user_data, const gchar* keyboard_id)
+{
+    KeyMagicEngine* engine = KEYMAGIC_ENGINE(user_data);
     
     g_debug("%s: Switching to keyboard: %s", LOG_TAG, keyboard_id);
     
@@ -1109,61 +985,32 @@ keymagic_engine_activate_property(KeyMagicEngine* engine, const gchar* prop_name
         keymagic_engine_commit_preedit(engine);
     }
     
-    /* Update active keyboard in configuration */
+    /* Update active keyboard */
     g_free(engine->active_keyboard_id);
     engine->active_keyboard_id = g_strdup(keyboard_id);
     engine->keyboard_changed = TRUE;
     
     /* Load the keyboard immediately */
-    if (keymagic_ibus_engine_load_keyboard(engine, keyboard_id)) {
-        /* Load config to update properties and save active keyboard */
-        KeyMagicConfig* config = keymagic_config_load(engine->config_path);
-        if (config) {
-            /* Update property states to reflect new selection */
-            if (engine->prop_list) {
-                gchar* keyboards_dir = keymagic_config_get_keyboards_dir();
-                
-                /* IBusPropList is opaque, so we iterate through our hash table instead */
-                GHashTableIter iter;
-                gpointer key, value;
-                g_hash_table_iter_init(&iter, engine->keyboard_properties);
-                
-                while (g_hash_table_iter_next(&iter, &key, &value)) {
-                    const gchar* prop_key = (const gchar*)key;
-                    const gchar* kb_id = (const gchar*)value;
-                    
-                    /* Find keyboard info from config */
-                    InstalledKeyboard* kb_info = keymagic_config_get_keyboard_info(config, kb_id);
-                    if (kb_info) {
-                        /* Check if this keyboard is now active */
-                        gboolean is_active = (g_strcmp0(prop_key, prop_name) == 0);
-                        
-                        /* Create property using shared function */
-                        IBusProperty* prop = create_keyboard_property(kb_info, prop_key, 
-                                                                     keyboards_dir, is_active);
-                        if (prop) {
-                            /* Update this property */
-                            ibus_engine_update_property((IBusEngine*)engine, prop);
-                            g_object_unref(prop);
-                        }
-                    }
-                }
-                
-                g_free(keyboards_dir);
-            }
-            
-            /* Update active keyboard and save configuration */
-            g_free(config->active_keyboard);
-            config->active_keyboard = g_strdup(keyboard_id);
-            if (!keymagic_config_save(engine->config_path, config)) {
-                g_warning("%s: Failed to persist keyboard selection to config", LOG_TAG);
-            }
-            
-            keymagic_config_free(config);
-        } else {
-            g_warning("%s: Failed to load config for keyboard update", LOG_TAG);
-        }
+    return keymagic_ibus_engine_load_keyboard(engine, keyboard_id);
+}
+
+/**
+ * Turn key processing on or off
+ */
+static void
+panel_set_processing_enabled(gpointer user_data, gboolean enabled)
+{
+    KeyMagicEngine* engine = KEYMAGIC_ENGINE(user_data);
+    
+    g_debug("%s: Key processing %s", LOG_TAG, enabled ? "on" : "off");
+    
+    /* Finish the composition so nothing is left behind in preedit */
+    keymagic_engine_commit_preedit(engine);
+    if (engine->km_engine) {
+        keymagic_ffi_reset_engine(engine->km_engine);
     }
+    
+    engine->processing_enabled = enabled;
 }
 
 /**
@@ -1180,7 +1027,7 @@ keymagic_engine_property_activate(IBusEngine* ibus_engine,
             LOG_TAG, prop_name, prop_state);
     
     /* Handle special menu items */
-    if (g_strcmp0(prop_name, "open-configurator") == 0) {
+    if (g_strcmp0(prop_name, KEYMAGIC_PROP_CONFIGURATOR) == 0) {
         g_debug("%s: Opening KeyMagic configurator", LOG_TAG);
         
         /* Launch the KeyMagic configurator */
@@ -1204,9 +1051,9 @@ keymagic_engine_property_activate(IBusEngine* ibus_engine,
             g_debug("%s: Successfully spawned KeyMagic configurator", LOG_TAG);
         }
     }
-    /* Handle radio button properties (keyboard selection) */
-    else if (prop_state == PROP_STATE_CHECKED) {
-        keymagic_engine_activate_property(engine, prop_name);
+    /* Handle keyboard selection and the "Off" toggle */
+    else if (!keymagic_properties_activate(engine->properties, prop_name, prop_state)) {
+        g_debug("%s: Unknown property: %s", LOG_TAG, prop_name);
     }
     
     /* Call parent class method */
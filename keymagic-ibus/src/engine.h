@@ -4,6 +4,7 @@
 #include <ibus.h>
 #include <glib.h>
 #include <gio/gio.h>
+#include "properties.h"
 
 G_BEGIN_DECLS
 
@@ -35,15 +36,15 @@ struct _KeyMagicEngine {
     /* State management */
     gboolean keyboard_load_failed;      /* TRUE if current keyboard failed to load */
     gboolean keyboard_changed;          /* TRUE if config indicates keyboard change */
+    gboolean processing_enabled;        /* FALSE while the user switched KeyMagic off */
     
     /* Preedit text management */
     IBusText* preedit_text;             /* Current preedit text being composed */
     gboolean preedit_visible;           /* Whether preedit is currently shown */
     guint preedit_cursor_pos;           /* Cursor position in preedit text */
     
-    /* Property management for keyboard switching */
-    IBusPropList* prop_list;            /* List of properties (keyboards with hotkeys) */
-    GHashTable* keyboard_properties;    /* Maps property key to keyboard ID */
+    /* Property panel for keyboard switching */
+    KeyMagicProperties* properties;     /* Language bar menu */
     
     /* Hotkey management */
     GHashTable* keyboard_hotkeys;       /* Maps hotkey (modifiers|keyval) to keyboard ID */
@@ -78,7 +79,6 @@ gboolean keymagic_engine_should_commit(guint keyval, gboolean is_processed, cons
 
 /* Property/hotkey management */
 void keymagic_engine_update_properties(KeyMagicEngine* engine);
-void keymagic_engine_activate_property(KeyMagicEngine* engine, const gchar* prop_name);
 
 G_END_DECLS
 
@@ -72,6 +72,10 @@ typedef struct {
     int delete_count;
     char* composing_text;
     int is_processed;
+    int delete_utf16_count;
+    int composing_caret_utf16;
+    char* notification;
+    int delete_clamped;
 } RustProcessKeyOutput;
 
 /* HotkeyInfo structure from Rust FFI */
@@ -226,10 +230,13 @@ keymagic_ffi_process_key(EngineHandle* engine, guint keyval, guint keycode,
     result->is_processed = rust_output.is_processed ? TRUE : FALSE;
     result->action_type = rust_output.action_type;
     result->delete_count = rust_output.delete_count;
+    result->notification = rust_output.notification ? g_strdup(rust_output.notification) : NULL;
+    result->delete_clamped = rust_output.delete_clamped ? TRUE : FALSE;
     
     /* Free Rust-allocated strings */
     if (rust_output.text) keymagic_engine_free_string(rust_output.text);
     if (rust_output.composing_text) keymagic_engine_free_string(rust_output.composing_text);
+    if (rust_output.notification) keymagic_engine_free_string(rust_output.notification);
     
     LOG_FFI_RESULT(result->text, result->composing_text, result->is_processed,
                    result->action_type, result->delete_count);
@@ -320,6 +327,7 @@ keymagic_ffi_free_result(KeyProcessingResult* result)
     
     g_free(result->text);
     g_free(result->composing_text);
+    g_free(result->notification);
     
     /* Clear structure */
     memset(result, 0, sizeof(KeyProcessingResult));
@@ -25,6 +25,8 @@ typedef struct {
     gboolean is_processed;          /* TRUE if engine handled the key */
     gint action_type;               /* Action type (Insert, Backspace, etc.) */
     gint delete_count;              /* Number of characters to delete */
+    gchar* notification;            /* Message a rule asked to show (may be NULL) */
+    gboolean delete_clamped;        /* Whether the engine cut a delete short */
 } KeyProcessingResult;
 
 /**
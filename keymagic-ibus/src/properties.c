@@ -0,0 +1,478 @@
+#include "properties.h"
+#include "ffi_bridge.h"
+#include <string.h>
+
+/* Logging tag */
+#define LOG_TAG "KeyMagicProperties"
+
+/* Panel icon (installed to the pixmaps directory) */
+#define KEYMAGIC_ICON "keymagic3"
+
+/**
+ * Create a property panel
+ */
+KeyMagicProperties*
+keymagic_properties_new(const KeyMagicPanelOps* ops, gpointer user_data,
+                        const gchar* config_path, const gchar* keyboards_dir)
+{
+    g_return_val_if_fail(ops != NULL, NULL);
+
+    KeyMagicProperties* props = g_new0(KeyMagicProperties, 1);
+    props->ops = ops;
+    props->user_data = user_data;
+    props->config_path = g_strdup(config_path);
+    props->keyboards_dir = g_strdup(keyboards_dir);
+    props->prop_list = NULL;
+    props->keyboard_properties = g_hash_table_new_full(g_str_hash, g_str_equal, g_free, g_free);
+    props->keyboard_names = g_hash_table_new_full(g_str_hash, g_str_equal, g_free, g_free);
+    props->keyboards_signature = NULL;
+    props->active_keyboard_id = NULL;
+    props->processing_enabled = TRUE;
+
+    return props;
+}
+
+/**
+ * Free a property panel
+ */
+void
+keymagic_properties_free(KeyMagicProperties* props)
+{
+    if (!props) {
+        return;
+    }
+
+    if (props->prop_list) {
+        g_object_unref(props->prop_list);
+    }
+    g_hash_table_destroy(props->keyboard_properties);
+    g_hash_table_destroy(props->keyboard_names);
+    g_free(props->keyboards_signature);
+    g_free(props->active_keyboard_id);
+    g_free(props->config_path);
+    g_free(props->keyboards_dir);
+    g_free(props);
+}
+
+/**
+ * Resolve the hotkey of an installed keyboard
+ */
+gchar*
+keymagic_properties_resolve_hotkey(const InstalledKeyboard* kb, const gchar* keyboards_dir)
+{
+    g_return_val_if_fail(kb != NULL, NULL);
+
+    if (kb->hotkey) {
+        /* Empty string means the user disabled the hotkey */
+        return strlen(kb->hotkey) > 0 ? g_strdup(kb->hotkey) : NULL;
+    }
+
+    /* Hotkey not set in config - try to get from KM2 file */
+    gchar* hotkey = NULL;
+    gchar* km2_path = NULL;
+    if (kb->filename && keyboards_dir) {
+        km2_path = g_build_filename(keyboards_dir, kb->filename, NULL);
+    }
+
+    if (km2_path && g_file_test(km2_path, G_FILE_TEST_EXISTS)) {
+        void* km2_handle = keymagic_ffi_km2_load(km2_path);
+        if (km2_handle) {
+            hotkey = keymagic_ffi_km2_get_hotkey(km2_handle);
+            keymagic_ffi_km2_free(km2_handle);
+        }
+    }
+    g_free(km2_path);
+
+    if (hotkey && strlen(hotkey) == 0) {
+        g_free(hotkey);
+        hotkey = NULL;
+    }
+    return hotkey;
+}
+
+/**
+ * Describe the installed keyboards the property list depends on
+ *
+ * Hotkeys from KM2 files are covered by the file hash, so comparing
+ * signatures does not need to open any keyboard.
+ */
+static gchar*
+keyboards_signature(const KeyMagicConfig* config)
+{
+    GString* signature = g_string_new("");
+
+    if (config) {
+        GList* iter;
+        for (iter = config->installed_keyboards; iter != NULL; iter = iter->next) {
+            InstalledKeyboard* kb = (InstalledKeyboard*)iter->data;
+            if (!kb || !kb->id) continue;
+
+            g_string_append_printf(signature, "%s\x1f%s\x1f%s\x1f%s\x1f%s\x1e",
+                                   kb->id,
+                                   kb->name ? kb->name : "",
+                                   kb->filename ? kb->filename : "",
+                                   kb->hash ? kb->hash : "",
+                                   kb->hotkey ? kb->hotkey : "\x01");
+        }
+    }
+
+    return g_string_free(signature, FALSE);
+}
+
+/**
+ * Create the radio property for a keyboard
+ */
+static IBusProperty*
+create_keyboard_property(const InstalledKeyboard* kb, const gchar* prop_key,
+                         const gchar* hotkey, gboolean is_active)
+{
+    /* Use name from config or fallback to ID */
+    const gchar* display_name = kb->name ? kb->name : kb->id;
+
+    IBusText* label = ibus_text_new_from_string(display_name);
+    IBusText* tooltip = NULL;
+
+    if (hotkey) {
+        gchar* tooltip_str = g_strdup_printf("%s (%s)", display_name, hotkey);
+        tooltip = ibus_text_new_from_string(tooltip_str);
+        g_free(tooltip_str);
+    } else {
+        tooltip = ibus_text_new_from_string(display_name);
+    }
+
+    return ibus_property_new(prop_key,
+                             PROP_TYPE_RADIO,
+                             label,
+                             NULL,  /* icon */
+                             tooltip,
+                             TRUE,  /* sensitive */
+                             TRUE,  /* visible */
+                             is_active ? PROP_STATE_CHECKED : PROP_STATE_UNCHECKED,
+                             NULL); /* sub_props */
+}
+
+static IBusProperty*
+create_separator(const gchar* key)
+{
+    return ibus_property_new(key, PROP_TYPE_SEPARATOR, NULL, NULL, NULL,
+                             TRUE, TRUE, PROP_STATE_UNCHECKED, NULL);
+}
+
+/**
+ * Label of the menu: the active keyboard, or "Off" while processing is off
+ */
+static const gchar*
+menu_label(KeyMagicProperties* props)
+{
+    if (!props->processing_enabled) {
+        return "Off";
+    }
+
+    const gchar* name = NULL;
+    if (props->active_keyboard_id) {
+        name = g_hash_table_lookup(props->keyboard_names, props->active_keyboard_id);
+    }
+    return name ? name : "KeyMagic";
+}
+
+static void
+set_menu_label(KeyMagicProperties* props, IBusProperty* menu)
+{
+    const gchar* label = menu_label(props);
+    gchar* tooltip = g_strdup_printf("KeyMagic: %s", label);
+
+    ibus_property_set_label(menu, ibus_text_new_from_string(label));
+    ibus_property_set_tooltip(menu, ibus_text_new_from_string(tooltip));
+
+    g_free(tooltip);
+}
+
+/**
+ * Build the property list from the configuration
+ */
+static void
+build_prop_list(KeyMagicProperties* props, const KeyMagicConfig* config)
+{
+    if (props->prop_list) {
+        g_object_unref(props->prop_list);
+    }
+    g_hash_table_remove_all(props->keyboard_properties);
+    g_hash_table_remove_all(props->keyboard_names);
+
+    IBusPropList* items = ibus_prop_list_new();
+    gint keyboard_count = 0;
+
+    if (config) {
+        GList* iter;
+        for (iter = config->installed_keyboards; iter != NULL; iter = iter->next) {
+            InstalledKeyboard* kb = (InstalledKeyboard*)iter->data;
+            if (!kb || !kb->id) continue;
+
+            gchar* prop_key = g_strconcat(KEYMAGIC_PROP_KEYBOARD_PREFIX, kb->id, NULL);
+            gchar* hotkey = keymagic_properties_resolve_hotkey(kb, props->keyboards_dir);
+            gboolean is_active = g_strcmp0(kb->id, props->active_keyboard_id) == 0;
+
+            ibus_prop_list_append(items, create_keyboard_property(kb, prop_key, hotkey, is_active));
+            g_hash_table_insert(props->keyboard_properties, g_strdup(prop_key), g_strdup(kb->id));
+            g_hash_table_insert(props->keyboard_names, g_strdup(kb->id),
+                                g_strdup(kb->name ? kb->name : kb->id));
+
+            g_free(hotkey);
+            g_free(prop_key);
+            keyboard_count++;
+        }
+    }
+
+    if (keyboard_count > 0) {
+        ibus_prop_list_append(items, create_separator("separator-keyboards"));
+    }
+
+    /* "Off" mirrors general.key_processing_enabled */
+    ibus_prop_list_append(items,
+        ibus_property_new(KEYMAGIC_PROP_OFF,
+                          PROP_TYPE_TOGGLE,
+                          ibus_text_new_from_string("Off"),
+                          NULL,  /* icon */
+                          ibus_text_new_from_string("Pass keys through without KeyMagic"),
+                          TRUE,  /* sensitive */
+                          TRUE,  /* visible */
+                          props->processing_enabled ? PROP_STATE_UNCHECKED : PROP_STATE_CHECKED,
+                          NULL));
+
+    ibus_prop_list_append(items, create_separator("separator-configurator"));
+
+    /* "Open Configurator" menu item */
+    ibus_prop_list_append(items,
+        ibus_property_new(KEYMAGIC_PROP_CONFIGURATOR,
+                          PROP_TYPE_NORMAL,
+                          ibus_text_new_from_string("Open KeyMagic..."),
+                          NULL,  /* icon */
+                          ibus_text_new_from_string("Open KeyMagic window"),
+                          TRUE,  /* sensitive */
+                          TRUE,  /* visible */
+                          PROP_STATE_UNCHECKED,
+                          NULL));
+
+    IBusProperty* menu = ibus_property_new(KEYMAGIC_PROP_MENU,
+                                           PROP_TYPE_MENU,
+                                           NULL,  /* label, set below */
+                                           KEYMAGIC_ICON,
+                                           NULL,  /* tooltip, set below */
+                                           TRUE,  /* sensitive */
+                                           TRUE,  /* visible */
+                                           PROP_STATE_UNCHECKED,
+                                           items);
+    set_menu_label(props, menu);
+
+    props->prop_list = ibus_prop_list_new();
+    g_object_ref_sink(props->prop_list);
+    ibus_prop_list_append(props->prop_list, menu);
+
+    g_debug("%s: Built menu with %d keyboards", LOG_TAG, keyboard_count);
+}
+
+static IBusProperty*
+get_menu(KeyMagicProperties* props)
+{
+    return props->prop_list ? ibus_prop_list_get(props->prop_list, 0) : NULL;
+}
+
+/**
+ * Find a menu item by key
+ */
+static IBusProperty*
+find_property(KeyMagicProperties* props, const gchar* key)
+{
+    IBusProperty* menu = get_menu(props);
+    if (!menu) {
+        return NULL;
+    }
+
+    IBusPropList* items = ibus_property_get_sub_props(menu);
+    IBusProperty* prop;
+    for (guint i = 0; (prop = ibus_prop_list_get(items, i)) != NULL; i++) {
+        if (g_strcmp0(ibus_property_get_key(prop), key) == 0) {
+            return prop;
+        }
+    }
+    return NULL;
+}
+
+static void
+set_item_state(KeyMagicProperties* props, IBusProperty* prop, IBusPropState state)
+{
+    if (ibus_property_get_state(prop) != state) {
+        ibus_property_set_state(prop, state);
+        props->ops->update_property(props->user_data, prop);
+    }
+}
+
+/**
+ * Show a new active keyboard or processing state in the panel
+ */
+static void
+apply_state(KeyMagicProperties* props, const gchar* active_keyboard_id, gboolean enabled)
+{
+    if (g_strcmp0(props->active_keyboard_id, active_keyboard_id) == 0 &&
+        props->processing_enabled == enabled) {
+        return;
+    }
+
+    gchar* active = g_strdup(active_keyboard_id);
+    g_free(props->active_keyboard_id);
+    props->active_keyboard_id = active;
+    props->processing_enabled = enabled;
+
+    IBusProperty* menu = get_menu(props);
+    if (!menu) {
+        return;
+    }
+
+    IBusPropList* items = ibus_property_get_sub_props(menu);
+    IBusProperty* prop;
+    for (guint i = 0; (prop = ibus_prop_list_get(items, i)) != NULL; i++) {
+        const gchar* key = ibus_property_get_key(prop);
+
+        if (g_strcmp0(key, KEYMAGIC_PROP_OFF) == 0) {
+            set_item_state(props, prop, enabled ? PROP_STATE_UNCHECKED : PROP_STATE_CHECKED);
+            continue;
+        }
+
+        const gchar* keyboard_id = g_hash_table_lookup(props->keyboard_properties, key);
+        if (keyboard_id) {
+            gboolean is_active = g_strcmp0(keyboard_id, active) == 0;
+            set_item_state(props, prop, is_active ? PROP_STATE_CHECKED : PROP_STATE_UNCHECKED);
+        }
+    }
+
+    set_menu_label(props, menu);
+    props->ops->update_property(props->user_data, menu);
+
+    g_debug("%s: Panel shows %s", LOG_TAG, menu_label(props));
+}
+
+/**
+ * Bring the panel in line with the configuration
+ */
+void
+keymagic_properties_sync(KeyMagicProperties* props, const KeyMagicConfig* config)
+{
+    g_return_if_fail(props != NULL);
+
+    const gchar* active = config ? config->active_keyboard : NULL;
+    gboolean enabled = config ? config->key_processing_enabled : TRUE;
+    gchar* signature = keyboards_signature(config);
+
+    if (props->prop_list && g_strcmp0(signature, props->keyboards_signature) == 0) {
+        /* Same keyboards, only the selection may have changed */
+        apply_state(props, active, enabled);
+        g_free(signature);
+        return;
+    }
+
+    gchar* active_copy = g_strdup(active);
+    g_free(props->active_keyboard_id);
+    props->active_keyboard_id = active_copy;
+    props->processing_enabled = enabled;
+
+    g_free(props->keyboards_signature);
+    props->keyboards_signature = signature;
+
+    build_prop_list(props, config);
+    keymagic_properties_register(props);
+}
+
+/**
+ * Register the current property list again
+ */
+void
+keymagic_properties_register(KeyMagicProperties* props)
+{
+    g_return_if_fail(props != NULL);
+
+    if (props->prop_list) {
+        props->ops->register_properties(props->user_data, props->prop_list);
+    }
+}
+
+/**
+ * Switch to a keyboard
+ */
+gboolean
+keymagic_properties_select_keyboard(KeyMagicProperties* props, const gchar* keyboard_id)
+{
+    g_return_val_if_fail(props != NULL, FALSE);
+    g_return_val_if_fail(keyboard_id != NULL, FALSE);
+
+    if (!props->ops->load_keyboard(props->user_data, keyboard_id)) {
+        g_warning("%s: Failed to switch to keyboard: %s", LOG_TAG, keyboard_id);
+        return FALSE;
+    }
+
+    if (!keymagic_config_set_active_keyboard(props->config_path, keyboard_id)) {
+        g_warning("%s: Failed to persist keyboard selection to config", LOG_TAG);
+    }
+
+    /* Choosing a keyboard means typing with it */
+    if (!props->processing_enabled) {
+        props->ops->set_processing_enabled(props->user_data, TRUE);
+        if (!keymagic_config_set_key_processing_enabled(props->config_path, TRUE)) {
+            g_warning("%s: Failed to persist key processing state to config", LOG_TAG);
+        }
+    }
+
+    apply_state(props, keyboard_id, TRUE);
+    return TRUE;
+}
+
+/**
+ * Handle a property activated in the panel
+ */
+gboolean
+keymagic_properties_activate(KeyMagicProperties* props, const gchar* prop_name, guint prop_state)
+{
+    g_return_val_if_fail(props != NULL, FALSE);
+    g_return_val_if_fail(prop_name != NULL, FALSE);
+
+    if (g_strcmp0(prop_name, KEYMAGIC_PROP_OFF) == 0) {
+        gboolean enabled = prop_state != PROP_STATE_CHECKED;
+        if (enabled != props->processing_enabled) {
+            props->ops->set_processing_enabled(props->user_data, enabled);
+            if (!keymagic_config_set_key_processing_enabled(props->config_path, enabled)) {
+                g_warning("%s: Failed to persist key processing state to config", LOG_TAG);
+            }
+            apply_state(props, props->active_keyboard_id, enabled);
+        }
+        return TRUE;
+    }
+
+    const gchar* keyboard_id = g_hash_table_lookup(props->keyboard_properties, prop_name);
+    if (!keyboard_id) {
+        return FALSE;
+    }
+
+    /* Radio items also report being unchecked when another one is picked */
+    if (prop_state != PROP_STATE_CHECKED) {
+        return TRUE;
+    }
+
+    gchar* id = g_strdup(keyboard_id);
+    if (!keymagic_properties_select_keyboard(props, id)) {
+        /* The panel already moved the check mark, put it back */
+        IBusProperty* prop = find_property(props, prop_name);
+        if (prop) {
+            props->ops->update_property(props->user_data, prop);
+        }
+        if (props->active_keyboard_id) {
+            gchar* active_key = g_strconcat(KEYMAGIC_PROP_KEYBOARD_PREFIX, props->active_keyboard_id, NULL);
+            prop = find_property(props, active_key);
+            if (prop) {
+                props->ops->update_property(props->user_data, prop);
+            }
+            g_free(active_key);
+        }
+    }
+    g_free(id);
+
+    return TRUE;
+}
@@ -0,0 +1,131 @@
+#ifndef KEYMAGIC_PROPERTIES_H
+#define KEYMAGIC_PROPERTIES_H
+
+#include <ibus.h>
+#include <glib.h>
+#include "config.h"
+
+G_BEGIN_DECLS
+
+/* Property keys */
+#define KEYMAGIC_PROP_MENU              "keymagic-menu"
+#define KEYMAGIC_PROP_OFF               "keymagic-off"
+#define KEYMAGIC_PROP_CONFIGURATOR      "open-configurator"
+#define KEYMAGIC_PROP_KEYBOARD_PREFIX   "keyboard."
+
+/**
+ * Panel Operations
+ *
+ * Calls into IBus and the engine made by the property panel. The engine
+ * forwards them to its IBus connection; tests record them instead.
+ */
+typedef struct {
+    /* Replace the whole property list shown by the panel */
+    void (*register_properties)(gpointer user_data, IBusPropList* props);
+    /* Refresh one property the panel already shows */
+    void (*update_property)(gpointer user_data, IBusProperty* prop);
+    /* Load the keyboard's km2 into the engine, FALSE if it failed */
+    gboolean (*load_keyboard)(gpointer user_data, const gchar* keyboard_id);
+    /* Turn key processing on or off */
+    void (*set_processing_enabled)(gpointer user_data, gboolean enabled);
+} KeyMagicPanelOps;
+
+/**
+ * KeyMagic Property Panel
+ *
+ * Language bar menu with one radio item per installed keyboard and an "Off"
+ * toggle. The menu label follows the active keyboard. Selections are written
+ * back to the configuration file the GUI reads.
+ */
+typedef struct {
+    const KeyMagicPanelOps* ops;
+    gpointer user_data;
+
+    gchar* config_path;                 /* Path to config.toml file */
+    gchar* keyboards_dir;               /* Directory holding .km2 files */
+
+    IBusPropList* prop_list;            /* Registered properties, NULL until synced */
+    GHashTable* keyboard_properties;    /* Maps property key to keyboard ID */
+    GHashTable* keyboard_names;         /* Maps keyboard ID to display name */
+    gchar* keyboards_signature;         /* Installed keyboards the list was built from */
+
+    gchar* active_keyboard_id;          /* Keyboard shown as selected */
+    gboolean processing_enabled;        /* FALSE while "Off" is checked */
+} KeyMagicProperties;
+
+/**
+ * Create a property panel
+ *
+ * @param ops Panel operations (must outlive the panel)
+ * @param user_data Passed to every operation
+ * @param config_path Path to config.toml file
+ * @param keyboards_dir Directory holding .km2 files
+ * @return New panel, nothing is registered until the first sync
+ */
+KeyMagicProperties* keymagic_properties_new(const KeyMagicPanelOps* ops, gpointer user_data,
+                                            const gchar* config_path, const gchar* keyboards_dir);
+
+/**
+ * Free a property panel
+ *
+ * @param props Panel to free
+ */
+void keymagic_properties_free(KeyMagicProperties* props);
+
+/**
+ * Bring the panel in line with the configuration
+ *
+ * Registers a new property list when the installed keyboards changed and
+ * otherwise only updates the properties whose state changed.
+ *
+ * @param props Property panel
+ * @param config Loaded configuration or NULL if there is none
+ */
+void keymagic_properties_sync(KeyMagicProperties* props, const KeyMagicConfig* config);
+
+/**
+ * Register the current property list again
+ *
+ * The panel shows the properties of whichever engine has focus, so they are
+ * registered again on focus in.
+ *
+ * @param props Property panel
+ */
+void keymagic_properties_register(KeyMagicProperties* props);
+
+/**
+ * Switch to a keyboard
+ *
+ * Loads the keyboard, turns key processing back on, writes both to the
+ * configuration file and updates the panel.
+ *
+ * @param props Property panel
+ * @param keyboard_id Keyboard ID to switch to
+ * @return TRUE if the keyboard was loaded
+ */
+gboolean keymagic_properties_select_keyboard(KeyMagicProperties* props, const gchar* keyboard_id);
+
+/**
+ * Handle a property activated in the panel
+ *
+ * @param props Property panel
+ * @param prop_name Activated property key
+ * @param prop_state New property state
+ * @return TRUE if the property belongs to the panel
+ */
+gboolean keymagic_properties_activate(KeyMagicProperties* props, const gchar* prop_name,
+                                      guint prop_state);
+
+/**
+ * Resolve the hotkey of an installed keyboard
+ *
+ * @param kb Keyboard information
+ * @param keyboards_dir Directory holding .km2 files
+ * @return Hotkey from the config, or the KM2 default when the config has
+ *         none; NULL when the keyboard has no hotkey (caller must free)
+ */
+gchar* keymagic_properties_resolve_hotkey(const InstalledKeyboard* kb, const gchar* keyboards_dir);
+
+G_END_DECLS
+
+#endif /* KEYMAGIC_PROPERTIES_H */
@@ -0,0 +1,469 @@
+/*
+ * Tests for the language bar properties
+ *
+ * The IBus connection is replaced by panel operations that record what the
+ * engine would send, so no IBus daemon is needed.
+ */
+
+#include "properties.h"
+#include "config.h"
+#include <glib/gstdio.h>
+#include <string.h>
+
+static const gchar* TWO_KEYBOARDS_CONFIG =
+    "[general]\n"
+    "start_with_system = false\n"
+    "check_for_updates = true\n"
+    "\n"
+    "[keyboards]\n"
+    "active = \"myanmar3\"\n"
+    "last_used = []\n"
+    "\n"
+    "[[keyboards.installed]]\n"
+    "id = \"myanmar3\"\n"
+    "name = \"Myanmar3\"\n"
+    "filename = \"myanmar3.km2\"\n"
+    "hotkey = \"CTRL+SHIFT+M\"\n"
+    "hash = \"aaaa\"\n"
+    "enabled = true\n"
+    "output_encoding = \"unicode\"\n"
+    "\n"
+    "[[keyboards.installed]]\n"
+    "id = \"zawcode\"\n"
+    "name = \"ZawCode\"\n"
+    "filename = \"zawcode.km2\"\n"
+    "hotkey = \"\"\n"
+    "hash = \"bbbb\"\n"
+    "\n"
+    "[composition_mode]\n"
+    "enabled_hosts = []\n"
+    "\n"
+    "[profiles.school]\n"
+    "keyboard = \"zawcode\"\n";
+
+/* Records the calls a panel makes */
+typedef struct {
+    gchar* dir;
+    gchar* config_path;
+    KeyMagicProperties* props;
+
+    guint register_count;
+    IBusPropList* registered;           /* Last registered list */
+    GPtrArray* updated;                 /* Keys of updated properties */
+    GPtrArray* loaded;                  /* Keyboards loaded into the engine */
+    gboolean load_result;               /* What load_keyboard returns */
+    gboolean processing_enabled;
+    guint processing_calls;
+} Fixture;
+
+static void
+mock_register_properties(gpointer user_data, IBusPropList* props)
+{
+    Fixture* fixture = user_data;
+    fixture->register_count++;
+    g_set_object(&fixture->registered, props);
+}
+
+static void
+mock_update_property(gpointer user_data, IBusProperty* prop)
+{
+    Fixture* fixture = user_data;
+    g_ptr_array_add(fixture->updated, g_strdup(ibus_property_get_key(prop)));
+}
+
+static gboolean
+mock_load_keyboard(gpointer user_data, const gchar* keyboard_id)
+{
+    Fixture* fixture = user_data;
+    g_ptr_array_add(fixture->loaded, g_strdup(keyboard_id));
+    return fixture->load_result;
+}
+
+static void
+mock_set_processing_enabled(gpointer user_data, gboolean enabled)
+{
+    Fixture* fixture = user_data;
+    fixture->processing_enabled = enabled;
+    fixture->processing_calls++;
+}
+
+static const KeyMagicPanelOps mock_ops = {
+    mock_register_properties,
+    mock_update_property,
+    mock_load_keyboard,
+    mock_set_processing_enabled,
+};
+
+static void
+write_config(Fixture* fixture, const gchar* contents)
+{
+    g_assert_true(g_file_set_contents(fixture->config_path, contents, -1, NULL));
+}
+
+/* Loads the config file and syncs the panel with it, like the file watcher */
+static void
+sync_from_file(Fixture* fixture)
+{
+    KeyMagicConfig* config = keymagic_config_load(fixture->config_path);
+    g_assert_nonnull(config);
+    keymagic_properties_sync(fixture->props, config);
+    keymagic_config_free(config);
+}
+
+static gchar*
+read_config(Fixture* fixture)
+{
+    gchar* contents = NULL;
+    g_assert_true(g_file_get_contents(fixture->config_path, &contents, NULL, NULL));
+    return contents;
+}
+
+static void
+fixture_set_up(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    fixture->dir = g_dir_make_tmp("keymagic-ibus-test-XXXXXX", NULL);
+    g_assert_nonnull(fixture->dir);
+    fixture->config_path = g_build_filename(fixture->dir, "config.toml", NULL);
+    fixture->props = keymagic_properties_new(&mock_ops, fixture, fixture->config_path, fixture->dir);
+
+    fixture->register_count = 0;
+    fixture->registered = NULL;
+    fixture->updated = g_ptr_array_new_with_free_func(g_free);
+    fixture->loaded = g_ptr_array_new_with_free_func(g_free);
+    fixture->load_result = TRUE;
+    fixture->processing_enabled = TRUE;
+    fixture->processing_calls = 0;
+
+    write_config(fixture, TWO_KEYBOARDS_CONFIG);
+}
+
+static void
+fixture_tear_down(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    keymagic_properties_free(fixture->props);
+    g_clear_object(&fixture->registered);
+    g_ptr_array_free(fixture->updated, TRUE);
+    g_ptr_array_free(fixture->loaded, TRUE);
+
+    g_unlink(fixture->config_path);
+    g_rmdir(fixture->dir);
+    g_free(fixture->config_path);
+    g_free(fixture->dir);
+}
+
+static IBusProperty*
+registered_menu(Fixture* fixture)
+{
+    g_assert_nonnull(fixture->registered);
+    IBusProperty* menu = ibus_prop_list_get(fixture->registered, 0);
+    g_assert_nonnull(menu);
+    g_assert_cmpstr(ibus_property_get_key(menu), ==, KEYMAGIC_PROP_MENU);
+    return menu;
+}
+
+static IBusProperty*
+menu_item(Fixture* fixture, const gchar* key)
+{
+    IBusPropList* items = ibus_property_get_sub_props(registered_menu(fixture));
+    IBusProperty* prop;
+    for (guint i = 0; (prop = ibus_prop_list_get(items, i)) != NULL; i++) {
+        if (g_strcmp0(ibus_property_get_key(prop), key) == 0) {
+            return prop;
+        }
+    }
+    return NULL;
+}
+
+static const gchar*
+menu_label(Fixture* fixture)
+{
+    return ibus_text_get_text(ibus_property_get_label(registered_menu(fixture)));
+}
+
+static gboolean
+was_updated(Fixture* fixture, const gchar* key)
+{
+    for (guint i = 0; i < fixture->updated->len; i++) {
+        if (g_strcmp0(g_ptr_array_index(fixture->updated, i), key) == 0) {
+            return TRUE;
+        }
+    }
+    return FALSE;
+}
+
+static void
+test_menu_lists_installed_keyboards(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    sync_from_file(fixture);
+    g_assert_cmpuint(fixture->register_count, ==, 1);
+    g_assert_cmpstr(menu_label(fixture), ==, "Myanmar3");
+    g_assert_cmpint(ibus_property_get_prop_type(registered_menu(fixture)), ==, PROP_TYPE_MENU);
+
+    IBusPropList* items = ibus_property_get_sub_props(registered_menu(fixture));
+    const gchar* expected[] = {
+        "keyboard.myanmar3", "keyboard.zawcode", "separator-keyboards",
+        KEYMAGIC_PROP_OFF, "separator-configurator", KEYMAGIC_PROP_CONFIGURATOR,
+    };
+    for (guint i = 0; i < G_N_ELEMENTS(expected); i++) {
+        IBusProperty* prop = ibus_prop_list_get(items, i);
+        g_assert_nonnull(prop);
+        g_assert_cmpstr(ibus_property_get_key(prop), ==, expected[i]);
+    }
+    g_assert_null(ibus_prop_list_get(items, G_N_ELEMENTS(expected)));
+
+    IBusProperty* myanmar3 = menu_item(fixture, "keyboard.myanmar3");
+    g_assert_cmpint(ibus_property_get_prop_type(myanmar3), ==, PROP_TYPE_RADIO);
+    g_assert_cmpint(ibus_property_get_state(myanmar3), ==, PROP_STATE_CHECKED);
+    g_assert_cmpstr(ibus_text_get_text(ibus_property_get_tooltip(myanmar3)), ==, "Myanmar3 (CTRL+SHIFT+M)");
+
+    /* An empty hotkey means the user turned it off */
+    IBusProperty* zawcode = menu_item(fixture, "keyboard.zawcode");
+    g_assert_cmpint(ibus_property_get_state(zawcode), ==, PROP_STATE_UNCHECKED);
+    g_assert_cmpstr(ibus_text_get_text(ibus_property_get_tooltip(zawcode)), ==, "ZawCode");
+
+    IBusProperty* off = menu_item(fixture, KEYMAGIC_PROP_OFF);
+    g_assert_cmpint(ibus_property_get_prop_type(off), ==, PROP_TYPE_TOGGLE);
+    g_assert_cmpint(ibus_property_get_state(off), ==, PROP_STATE_UNCHECKED);
+}
+
+static void
+test_menu_without_keyboards(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    keymagic_properties_sync(fixture->props, NULL);
+    g_assert_cmpuint(fixture->register_count, ==, 1);
+    g_assert_cmpstr(menu_label(fixture), ==, "KeyMagic");
+
+    IBusPropList* items = ibus_property_get_sub_props(registered_menu(fixture));
+    g_assert_cmpstr(ibus_property_get_key(ibus_prop_list_get(items, 0)), ==, KEYMAGIC_PROP_OFF);
+}
+
+static void
+test_config_change_updates_selection(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    sync_from_file(fixture);
+
+    /* The GUI switched keyboards */
+    g_assert_true(keymagic_config_set_active_keyboard(fixture->config_path, "zawcode"));
+    sync_from_file(fixture);
+
+    /* Same keyboards: the panel is updated, not rebuilt */
+    g_assert_cmpuint(fixture->register_count, ==, 1);
+    g_assert_true(was_updated(fixture, "keyboard.myanmar3"));
+    g_assert_true(was_updated(fixture, "keyboard.zawcode"));
+    g_assert_true(was_updated(fixture, KEYMAGIC_PROP_MENU));
+    g_assert_false(was_updated(fixture, KEYMAGIC_PROP_OFF));
+
+    g_assert_cmpstr(menu_label(fixture), ==, "ZawCode");
+    g_assert_cmpint(ibus_property_get_state(menu_item(fixture, "keyboard.zawcode")), ==, PROP_STATE_CHECKED);
+    g_assert_cmpint(ibus_property_get_state(menu_item(fixture, "keyboard.myanmar3")), ==, PROP_STATE_UNCHECKED);
+
+    /* Nothing changed, nothing is sent */
+    g_ptr_array_set_size(fixture->updated, 0);
+    sync_from_file(fixture);
+    g_assert_cmpuint(fixture->updated->len, ==, 0);
+    g_assert_cmpuint(fixture->register_count, ==, 1);
+}
+
+static void
+test_config_change_mirrors_processing_flag(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    sync_from_file(fixture);
+
+    g_assert_true(keymagic_config_set_key_processing_enabled(fixture->config_path, FALSE));
+    sync_from_file(fixture);
+    g_assert_cmpstr(menu_label(fixture), ==, "Off");
+    g_assert_cmpint(ibus_property_get_state(menu_item(fixture, KEYMAGIC_PROP_OFF)), ==, PROP_STATE_CHECKED);
+    g_assert_true(was_updated(fixture, KEYMAGIC_PROP_OFF));
+
+    /* The selected keyboard stays checked while off */
+    g_assert_cmpint(ibus_property_get_state(menu_item(fixture, "keyboard.myanmar3")), ==, PROP_STATE_CHECKED);
+
+    g_assert_true(keymagic_config_set_key_processing_enabled(fixture->config_path, TRUE));
+    sync_from_file(fixture);
+    g_assert_cmpstr(menu_label(fixture), ==, "Myanmar3");
+    g_assert_cmpint(ibus_property_get_state(menu_item(fixture, KEYMAGIC_PROP_OFF)), ==, PROP_STATE_UNCHECKED);
+}
+
+static void
+test_installed_keyboards_change_rebuilds_menu(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    sync_from_file(fixture);
+
+    gchar* contents = read_config(fixture);
+    gchar** parts = g_strsplit(contents, "name = \"ZawCode\"", 2);
+    gchar* renamed = g_strjoinv("name = \"Zawgyi Code\"", parts);
+    write_config(fixture, renamed);
+    sync_from_file(fixture);
+
+    g_assert_cmpuint(fixture->register_count, ==, 2);
+    g_assert_cmpstr(ibus_text_get_text(ibus_property_get_label(menu_item(fixture, "keyboard.zawcode"))),
+                    ==, "Zawgyi Code");
+
+    g_free(renamed);
+    g_strfreev(parts);
+    g_free(contents);
+}
+
+static void
+test_activate_keyboard(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    sync_from_file(fixture);
+
+    g_assert_true(keymagic_properties_activate(fixture->props, "keyboard.zawcode", PROP_STATE_CHECKED));
+    g_assert_cmpuint(fixture->loaded->len, ==, 1);
+    g_assert_cmpstr(g_ptr_array_index(fixture->loaded, 0), ==, "zawcode");
+    g_assert_cmpstr(menu_label(fixture), ==, "ZawCode");
+    g_assert_true(was_updated(fixture, KEYMAGIC_PROP_MENU));
+
+    /* The choice is written back for the GUI */
+    KeyMagicConfig* config = keymagic_config_load(fixture->config_path);
+    g_assert_cmpstr(config->active_keyboard, ==, "zawcode");
+    g_assert_cmpuint(g_list_length(config->installed_keyboards), ==, 2);
+    keymagic_config_free(config);
+
+    /* Settings this engine does not read survive the write */
+    gchar* contents = read_config(fixture);
+    g_assert_nonnull(strstr(contents, "output_encoding = \"unicode\"\n"));
+    g_assert_nonnull(strstr(contents, "[profiles.school]\nkeyboard = \"zawcode\"\n"));
+    g_assert_null(strstr(contents, "active = \"myanmar3\""));
+    g_free(contents);
+
+    /* The file watcher sees our own write and has nothing to do */
+    g_ptr_array_set_size(fixture->updated, 0);
+    sync_from_file(fixture);
+    g_assert_cmpuint(fixture->updated->len, ==, 0);
+    g_assert_cmpuint(fixture->register_count, ==, 1);
+
+    /* The radio item being unchecked is only a notification */
+    g_assert_true(keymagic_properties_activate(fixture->props, "keyboard.myanmar3", PROP_STATE_UNCHECKED));
+    g_assert_cmpuint(fixture->loaded->len, ==, 1);
+}
+
+static void
+test_activate_keyboard_that_fails_to_load(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    sync_from_file(fixture);
+    fixture->load_result = FALSE;
+
+    g_assert_true(keymagic_properties_activate(fixture->props, "keyboard.zawcode", PROP_STATE_CHECKED));
+    g_assert_cmpuint(fixture->loaded->len, ==, 1);
+
+    /* The panel gets the old selection back and the config is untouched */
+    g_assert_true(was_updated(fixture, "keyboard.zawcode"));
+    g_assert_true(was_updated(fixture, "keyboard.myanmar3"));
+    g_assert_cmpint(ibus_property_get_state(menu_item(fixture, "keyboard.zawcode")), ==, PROP_STATE_UNCHECKED);
+    g_assert_cmpstr(menu_label(fixture), ==, "Myanmar3");
+
+    gchar* contents = read_config(fixture);
+    g_assert_cmpstr(contents, ==, TWO_KEYBOARDS_CONFIG);
+    g_free(contents);
+}
+
+static void
+test_activate_off(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    sync_from_file(fixture);
+
+    g_assert_true(keymagic_properties_activate(fixture->props, KEYMAGIC_PROP_OFF, PROP_STATE_CHECKED));
+    g_assert_false(fixture->processing_enabled);
+    g_assert_cmpstr(menu_label(fixture), ==, "Off");
+
+    KeyMagicConfig* config = keymagic_config_load(fixture->config_path);
+    g_assert_false(config->key_processing_enabled);
+    g_assert_cmpstr(config->active_keyboard, ==, "myanmar3");
+    keymagic_config_free(config);
+
+    g_assert_true(keymagic_properties_activate(fixture->props, KEYMAGIC_PROP_OFF, PROP_STATE_UNCHECKED));
+    g_assert_true(fixture->processing_enabled);
+    g_assert_cmpuint(fixture->processing_calls, ==, 2);
+    g_assert_cmpstr(menu_label(fixture), ==, "Myanmar3");
+
+    config = keymagic_config_load(fixture->config_path);
+    g_assert_true(config->key_processing_enabled);
+    keymagic_config_free(config);
+}
+
+static void
+test_selecting_keyboard_turns_processing_on(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    g_assert_true(keymagic_config_set_key_processing_enabled(fixture->config_path, FALSE));
+    sync_from_file(fixture);
+    g_assert_cmpstr(menu_label(fixture), ==, "Off");
+
+    g_assert_true(keymagic_properties_select_keyboard(fixture->props, "myanmar3"));
+    g_assert_true(fixture->processing_enabled);
+    g_assert_cmpuint(fixture->processing_calls, ==, 1);
+    g_assert_cmpstr(menu_label(fixture), ==, "Myanmar3");
+    g_assert_cmpint(ibus_property_get_state(menu_item(fixture, KEYMAGIC_PROP_OFF)), ==, PROP_STATE_UNCHECKED);
+
+    KeyMagicConfig* config = keymagic_config_load(fixture->config_path);
+    g_assert_true(config->key_processing_enabled);
+    keymagic_config_free(config);
+}
+
+static void
+test_activate_unknown_property(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    sync_from_file(fixture);
+
+    g_assert_false(keymagic_properties_activate(fixture->props, KEYMAGIC_PROP_CONFIGURATOR, PROP_STATE_UNCHECKED));
+    g_assert_false(keymagic_properties_activate(fixture->props, "keyboard.missing", PROP_STATE_CHECKED));
+    g_assert_cmpuint(fixture->loaded->len, ==, 0);
+    g_assert_cmpuint(fixture->updated->len, ==, 0);
+}
+
+static void
+test_write_back_adds_missing_keys(Fixture* fixture, gconstpointer data G_GNUC_UNUSED)
+{
+    write_config(fixture, "[general]\ncheck_for_updates = true\n\n[keyboards]\nlast_used = []\n");
+
+    g_assert_true(keymagic_config_set_active_keyboard(fixture->config_path, "my\"board"));
+    g_assert_true(keymagic_config_set_key_processing_enabled(fixture->config_path, FALSE));
+
+    gchar* contents = read_config(fixture);
+    g_assert_cmpstr(contents, ==,
+                    "[general]\n"
+                    "key_processing_enabled = false\n"
+                    "check_for_updates = true\n"
+                    "\n"
+                    "[keyboards]\n"
+                    "active = \"my\\\"board\"\n"
+                    "last_used = []\n");
+    g_free(contents);
+
+    KeyMagicConfig* config = keymagic_config_load(fixture->config_path);
+    g_assert_cmpstr(config->active_keyboard, ==, "my\"board");
+    keymagic_config_free(config);
+
+    /* A file without the table gets one appended */
+    write_config(fixture, "[general]\ncheck_for_updates = true\n");
+    g_assert_true(keymagic_config_set_active_keyboard(fixture->config_path, "myanmar3"));
+    contents = read_config(fixture);
+    g_assert_cmpstr(contents, ==, "[general]\ncheck_for_updates = true\n\n[keyboards]\nactive = \"myanmar3\"\n");
+    g_free(contents);
+}
+
+int
+main(int argc, char** argv)
+{
+    g_test_init(&argc, &argv, NULL);
+
+#define ADD_TEST(path, func) \
+    g_test_add(path, Fixture, NULL, fixture_set_up, func, fixture_tear_down)
+
+    ADD_TEST("/properties/menu-lists-installed-keyboards", test_menu_lists_installed_keyboards);
+    ADD_TEST("/properties/menu-without-keyboards", test_menu_without_keyboards);
+    ADD_TEST("/properties/config-change-updates-selection", test_config_change_updates_selection);
+    ADD_TEST("/properties/config-change-mirrors-processing-flag", test_config_change_mirrors_processing_flag);
+    ADD_TEST("/properties/installed-keyboards-change-rebuilds-menu", test_installed_keyboards_change_rebuilds_menu);
+    ADD_TEST("/properties/activate-keyboard", test_activate_keyboard);
+    ADD_TEST("/properties/activate-keyboard-that-fails-to-load", test_activate_keyboard_that_fails_to_load);
+    ADD_TEST("/properties/activate-off", test_activate_off);
+    ADD_TEST("/properties/selecting-keyboard-turns-processing-on", test_selecting_keyboard_turns_processing_on);
+    ADD_TEST("/properties/activate-unknown-property", test_activate_unknown_property);
+    ADD_TEST("/config/write-back-adds-missing-keys", test_write_back_adds_missing_keys);
+
+#undef ADD_TEST
+
+    return g_test_run();
+}
@@ -1,7 +1,7 @@
 import Foundation
 
 /// KeyMagic configuration management for macOS IMK
-/// Reads configuration from the same location as GUI: ~/Library/Preferences/net.keymagic/config.plist
+/// Reads configuration from the same location as GUI: ~/Library/Application Support/KeyMagic/config.plist
 public class KMConfiguration {
     // Pure Swift structs with Codable for automatic serialization
     private struct Config: Codable {
@@ -52,6 +52,14 @@ public class KMConfiguration {
         var filename: String
         var hotkey: String?
         var hash: String
+        // Kept so that saving from the IMK does not drop GUI settings
+        var enabled: Bool?
+        var outputEncoding: String?
+        
+        private enum CodingKeys: String, CodingKey {
+            case id, name, filename, hotkey, hash, enabled
+            case outputEncoding = "output_encoding"
+        }
     }
     
     private struct CompositionModeConfig: Codable {
@@ -73,11 +81,16 @@ public class KMConfiguration {
     // MARK: - Singleton
     public static let shared = KMConfiguration()
     
+    /// Distributed notification posted by the GUI when the active keyboard changes.
+    /// The notification object is the new keyboard id.
+    public static let keyboardChangedNotification = NSNotification.Name("org.keymagic.inputmethod.KeyboardChanged")
+    
     // MARK: - Properties
     private let configDir: URL
     private let dataDir: URL
     private let keyboardsDir: URL
     private let configPath: URL
+    private let legacyConfigPath: URL
     private var config: Config?
     
     // MARK: - Public Properties
@@ -138,23 +151,40 @@ public class KMConfiguration {
         // Setup directories following GUI convention
         let libraryDir = FileManager.default.urls(for: .libraryDirectory, in: .userDomainMask).first!
         
-        // Config: ~/Library/Preferences/net.keymagic/
-        self.configDir = libraryDir.appendingPathComponent("Preferences/net.keymagic")
-        self.configPath = configDir.appendingPathComponent("config.plist")
-        
-        // Data: ~/Library/Application Support/KeyMagic/
+        // Config and data: ~/Library/Application Support/KeyMagic/
         self.dataDir = libraryDir.appendingPathComponent("Application Support/KeyMagic")
+        self.configDir = dataDir
+        self.configPath = configDir.appendingPathComponent("config.plist")
         self.keyboardsDir = dataDir.appendingPathComponent("Keyboards")
         
+        // Older versions kept the config in ~/Library/Preferences/net.keymagic/
+        self.legacyConfigPath = libraryDir.appendingPathComponent("Preferences/net.keymagic/config.plist")
         
         // Create directories if needed
         createDirectoriesIfNeeded()
         
         // Load initial config
+        migrateLegacyConfigIfNeeded()
         loadConfig()
         
         // Monitor config file changes
         startMonitoringConfigChanges()
+        startObservingKeyboardChanges()
+    }
+    
+    private func migrateLegacyConfigIfNeeded() {
+        let fileManager = FileManager.default
+        guard !fileManager.fileExists(atPath: configPath.path),
+              fileManager.fileExists(atPath: legacyConfigPath.path) else {
+            return
+        }
+        
+        do {
+            try fileManager.copyItem(at: legacyConfigPath, to: configPath)
+            NSLog("KeyMagic: Migrated config from \(legacyConfigPath.path)")
+        } catch {
+            NSLog("KeyMagic: Failed to migrate legacy config: \(error)")
+        }
     }
     
     // MARK: - Directory Management
@@ -193,7 +223,7 @@ public class KMConfiguration {
             let encoder = PropertyListEncoder()
             encoder.outputFormat = .binary
             let data = try encoder.encode(config)
-            try data.write(to: configPath)
+            try data.write(to: configPath, options: .atomic)
         } catch {
             NSLog("KeyMagic: Failed to save config: \(error)")
         }
@@ -315,19 +345,23 @@ public class KMConfiguration {
         
         configMonitor = DispatchSource.makeFileSystemObjectSource(
             fileDescriptor: fd,
-            eventMask: [.write, .rename],
+            eventMask: [.write, .rename, .delete],
             queue: .main
         )
         
         configMonitor?.setEventHandler { [weak self] in
+            guard let self = self else { return }
             NSLog("KeyMagic: Config file changed, reloading...")
-            self?.loadConfig()
             
-            // Post notification for other components
-            NotificationCenter.default.post(
-                name: NSNotification.Name("KMConfigurationChanged"),
-                object: nil
-            )
+            // The GUI replaces the file atomically, which leaves this descriptor
+            // pointing at the old file; watch the new one from now on
+            if let events = self.configMonitor?.data, !events.isDisjoint(with: [.rename, .delete]) {
+                self.configMonitor?.cancel()
+                self.startMonitoringConfigChanges()
+            }
+            
+            self.loadConfig()
+            self.postConfigurationChanged()
         }
         
         configMonitor?.setCancelHandler {
@@ -336,4 +370,28 @@ public class KMConfiguration {
         
         configMonitor?.resume()
     }
+    
+    private func postConfigurationChanged() {
+        // Post notification for other components
+        NotificationCenter.default.post(
+            name: NSNotification.Name("KMConfigurationChanged"),
+            object: nil
+        )
+    }
+    
+    // MARK: - GUI Notifications
+    private func startObservingKeyboardChanges() {
+        DistributedNotificationCenter.default().addObserver(
+            forName: KMConfiguration.keyboardChangedNotification,
+            object: nil,
+            queue: .main
+        ) { [weak self] notification in
+            guard let self = self, let keyboardId = notification.object as? String else { return }
+            NSLog("KeyMagic: GUI switched keyboard to: \(keyboardId)")
+            
+            // The GUI saves the config after notifying; apply the switch right away
+            self.config?.keyboards.active = keyboardId
+            self.postConfigurationChanged()
+        }
+    }
 }
\ No newline at end of file
@@ -242,6 +242,14 @@ class KMInputController: IMKInputController {
                 }
             }
             
+            // Message a rule raised, already rate limited by the engine; a
+            // clamped delete means the keyboard misbehaves
+            if output.delete_clamped != 0 {
+                showTransientNotification("Keyboard misbehaving: it tried to delete text it did not type")
+            } else if let notificationPtr = output.notification {
+                showTransientNotification(String(cString: notificationPtr))
+            }
+            
             // Free allocated strings
             if let text = output.text {
                 keymagic_free_string(text)
@@ -249,6 +257,9 @@ class KMInputController: IMKInputController {
             if let composingText = output.composing_text {
                 keymagic_free_string(composingText)
             }
+            if let notification = output.notification {
+                keymagic_free_string(notification)
+            }
             
             let processed = output.is_processed != 0
             
@@ -290,27 +301,27 @@ class KMInputController: IMKInputController {
         let client = sender
         
         // Handle text replacement (delete + insert)
-        if output.delete_count > 0 || output.text != nil {
+        if output.delete_utf16_count > 0 || output.text != nil {
             let textToInsert = output.text != nil ? String(cString: output.text!) : ""
             
-            if output.delete_count > 0 {
-                LOG_DEBUG("Direct mode - replacing \(output.delete_count) characters with '\(textToInsert)'")
+            if output.delete_utf16_count > 0 {
+                LOG_DEBUG("Direct mode - replacing \(output.delete_utf16_count) UTF-16 units with '\(textToInsert)'")
                 
                 // Get current selection/cursor position
                 let currentRange = client.selectedRange()
                 LOG_DEBUG("Current range: location=\(currentRange.location), length=\(currentRange.length)")
                 
-                if currentRange.location != NSNotFound && currentRange.location >= output.delete_count {
+                if currentRange.location != NSNotFound && currentRange.location >= output.delete_utf16_count {
                     // Calculate range to replace (delete characters before cursor)
                     let replacementRange = NSRange(
-                        location: currentRange.location - Int(output.delete_count),
-                        length: Int(output.delete_count)
+                        location: currentRange.location - Int(output.delete_utf16_count),
+                        length: Int(output.delete_utf16_count)
                     )
                     LOG_DEBUG("Replacement range: location=\(replacementRange.location), length=\(replacementRange.length)")
                     
                     if textToInsert.isEmpty {
                         // For pure deletion, use our helper method
-                        deleteCharacters(count: UInt32(output.delete_count), 
+                        deleteCharacters(count: UInt32(output.delete_utf16_count), 
                                        currentRange: currentRange, 
                                        replacementRange: replacementRange, 
                                        client: client)
@@ -325,7 +336,7 @@ class KMInputController: IMKInputController {
                         client.insertText(textToInsert, replacementRange: NSRange(location: NSNotFound, length: 0))
                     }
                     // Set flag since we couldn't perform the deletion properly
-                    if output.delete_count > 0 && textToInsert.isEmpty {
+                    if output.delete_utf16_count > 0 && textToInsert.isEmpty {
                         deleteFailedLastTime = true
                     }
                 }
@@ -358,7 +369,7 @@ class KMInputController: IMKInputController {
         } else {
             // Just update preedit display
             LOG_TEXT("Updating marked text with", composingText)
-            updateMarkedText(composingText, client: sender)
+            updateMarkedText(composingText, caret: Int(output.composing_caret_utf16), client: sender)
         }
     }
     
@@ -620,7 +631,7 @@ class KMInputController: IMKInputController {
         }
     }
     
-    private func updateMarkedText(_ text: String, client sender: (IMKTextInput & NSObjectProtocol)) {
+    private func updateMarkedText(_ text: String, caret: Int? = nil, client sender: (IMKTextInput & NSObjectProtocol)) {
         let client = sender
         
         let attributes: [NSAttributedString.Key: Any] = [
@@ -629,15 +640,16 @@ class KMInputController: IMKInputController {
         ]
         
         let markedText = NSAttributedString(string: text, attributes: attributes)
-        // Use UTF-16 count for NSRange
+        // NSRange is in UTF-16 units; the engine reports its caret in the same unit
         let utf16Count = text.utf16.count
-        let selectionRange = NSRange(location: utf16Count, length: 0)
+        let caretLocation = min(max(caret ?? utf16Count, 0), utf16Count)
+        let selectionRange = NSRange(location: caretLocation, length: 0)
         let replacementRange = NSRange(location: NSNotFound, length: 0)
         
         client.setMarkedText(markedText, selectionRange: selectionRange, replacementRange: replacementRange)
         
         LOG_TEXT("Updated marked text", text)
-        LOG_DEBUG("Cursor at \(utf16Count)")
+        LOG_DEBUG("Cursor at \(caretLocation)")
     }
     
     private func commitText(_ text: String, client sender: (IMKTextInput & NSObjectProtocol)) {
@@ -1057,12 +1069,17 @@ class KMInputController: IMKInputController {
             keyboardName = name
         }
         
+        showTransientNotification("Switched to: \(keyboardName)")
+    }
+    
+    /// Shows a short message, e.g. a keyboard switch or a message raised by a rule
+    private func showTransientNotification(_ message: String) {
         // For Input Methods, we'll use the old NSUserNotification API which doesn't require permissions
         // This works well for transient notifications that don't need user interaction
         let notification = NSUserNotification()
         notification.title = "KeyMagic 3"
-        notification.informativeText = "Switched to: \(keyboardName)"
-        notification.soundName = nil // No sound for keyboard switches
+        notification.informativeText = message
+        notification.soundName = nil // No sound for status messages
         
         // Set notification to disappear automatically after a short time
         notification.hasActionButton = false
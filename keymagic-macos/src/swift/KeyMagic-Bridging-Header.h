@@ -35,6 +35,10 @@ typedef struct {
     int delete_count;
     char* composing_text;
     int is_processed;
+    int delete_utf16_count;
+    int composing_caret_utf16;
+    char* notification;
+    int delete_clamped;
 } ProcessKeyOutput;
 
 // FFI functions from keymagic-core
@@ -46,6 +50,8 @@ extern KeyMagicResult keymagic_engine_process_key(EngineHandle* engine, int key_
                                                    ProcessKeyOutput* output);
 extern KeyMagicResult keymagic_engine_reset(EngineHandle* engine);
 extern char* keymagic_engine_get_composition(EngineHandle* engine);
+extern size_t keymagic_engine_get_composition_ex(EngineHandle* engine, uint16_t* out_buf, size_t buf_len,
+                                                 int* out_caret_index);
 extern KeyMagicResult keymagic_engine_set_composition(EngineHandle* engine, const char* text);
 extern void keymagic_free_string(char* str);
 
@@ -1,10 +1,18 @@
 use anyhow::Result;
 use crate::commands::AppInfo;
 
+#[cfg(any(target_os = "windows", test))]
+mod guard;
+mod icon_cache;
+mod icon_loader;
+
+pub use icon_cache::{IconSource, ICON_CACHE_DIR};
+pub use icon_loader::{AppIconReady, AppIconService};
+
 #[cfg(target_os = "windows")]
 mod windows;
 #[cfg(target_os = "windows")]
-pub use windows::get_running_apps;
+pub use windows::{extract_icon, get_running_apps};
 
 #[cfg(target_os = "macos")]
 mod macos;
@@ -16,4 +24,10 @@ pub fn get_running_apps() -> Result<Vec<AppInfo>> {
     // Linux implementation placeholder
     // Could use /proc filesystem or D-Bus
     Ok(vec![])
-}
\ No newline at end of file
+}
+
+/// Apps on other platforms come with their icons
+#[cfg(not(target_os = "windows"))]
+pub fn extract_icon(_source: &IconSource) -> Option<String> {
+    None
+}
@@ -0,0 +1,64 @@
+//! Releases a handle when it goes out of scope
+//!
+//! Icon extraction holds several GDI handles at once and can bail out between
+//! any two calls; wrapping each handle as soon as it is acquired releases it
+//! on every path, in the reverse order of acquisition.
+
+use std::ops::Deref;
+
+pub struct Guard<T, F: FnOnce(T)> {
+    value: Option<T>,
+    release: Option<F>,
+}
+
+impl<T, F: FnOnce(T)> Guard<T, F> {
+    pub fn new(value: T, release: F) -> Self {
+        Self {
+            value: Some(value),
+            release: Some(release),
+        }
+    }
+}
+
+impl<T, F: FnOnce(T)> Deref for Guard<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().expect("guarded value is only taken on drop")
+    }
+}
+
+impl<T, F: FnOnce(T)> Drop for Guard<T, F> {
+    fn drop(&mut self) {
+        if let (Some(value), Some(release)) = (self.value.take(), self.release.take()) {
+            release(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_releases_in_reverse_order_on_early_return() {
+        let released = RefCell::new(Vec::new());
+
+        let acquire = |fail: bool| -> Option<u32> {
+            let first = Guard::new(1, |handle| released.borrow_mut().push(handle));
+            let second = Guard::new(2, |handle| released.borrow_mut().push(handle));
+            if fail {
+                return None;
+            }
+            Some(*first + *second)
+        };
+
+        assert_eq!(acquire(true), None);
+        assert_eq!(*released.borrow(), vec![2, 1]);
+
+        released.borrow_mut().clear();
+        assert_eq!(acquire(false), Some(3));
+        assert_eq!(*released.borrow(), vec![2, 1]);
+    }
+}
@@ -0,0 +1,249 @@
+//! On-disk cache of app icons
+//!
+//! Icons are stored as PNG files named after a hash of the file they came
+//! from and that file's modification time, so an updated executable gets a
+//! new entry and its old one is removed. The oldest entries are pruned once
+//! the cache holds more than its capacity.
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Directory under the data directory holding the cache
+pub const ICON_CACHE_DIR: &str = "app_icons";
+
+/// Entries kept before the oldest are pruned
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// Where the icon of an app comes from
+// Only the Windows enumerator leaves icons to be loaded later
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IconSource {
+    /// Icon resource of an executable
+    Executable(PathBuf),
+    /// Logo named by the AppxManifest.xml next to a packaged app's executable
+    PackageManifest(PathBuf),
+}
+
+impl IconSource {
+    /// Executable the icon belongs to
+    pub fn path(&self) -> &Path {
+        match self {
+            IconSource::Executable(path) | IconSource::PackageManifest(path) => path,
+        }
+    }
+}
+
+/// Identifies one version of a source's icon
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    source_hash: String,
+    modified: u128,
+}
+
+impl CacheKey {
+    fn file_name(&self) -> String {
+        format!("{}-{:x}.png", self.source_hash, self.modified)
+    }
+}
+
+pub struct IconCache {
+    dir: PathBuf,
+    capacity: usize,
+}
+
+impl IconCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self::with_capacity(dir, DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(dir: impl Into<PathBuf>, capacity: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            capacity,
+        }
+    }
+
+    /// Key of the source's current icon, or None when its file can't be read
+    pub fn key(&self, source: &IconSource) -> Option<CacheKey> {
+        let modified = fs::metadata(source.path()).and_then(|metadata| metadata.modified()).ok()?;
+        let modified = modified.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+
+        let kind: &[u8] = match source {
+            IconSource::Executable(_) => b"exe",
+            IconSource::PackageManifest(_) => b"appx",
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(kind);
+        hasher.update(source.path().to_string_lossy().as_bytes());
+        let mut source_hash = format!("{:x}", hasher.finalize());
+        source_hash.truncate(16);
+
+        Some(CacheKey { source_hash, modified })
+    }
+
+    /// Cached icon, base64 encoded
+    pub fn get(&self, key: &CacheKey) -> Option<String> {
+        fs::read(self.dir.join(key.file_name()))
+            .ok()
+            .map(|png| STANDARD.encode(png))
+    }
+
+    /// Stores a base64 encoded icon, replacing older versions of the same source
+    pub fn put(&self, key: &CacheKey, icon_base64: &str) -> Result<()> {
+        let png = STANDARD.decode(icon_base64).context("Icon is not valid base64")?;
+        fs::create_dir_all(&self.dir).context("Failed to create icon cache directory")?;
+
+        let file_name = key.file_name();
+        let stale_prefix = format!("{}-", key.source_hash);
+        for (path, _) in self.entries() {
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if name.starts_with(&stale_prefix) && name != file_name {
+                let _ = fs::remove_file(&path);
+            }
+        }
+
+        fs::write(self.dir.join(&file_name), png).context("Failed to write cached icon")?;
+        self.prune();
+        Ok(())
+    }
+
+    fn entries(&self) -> Vec<(PathBuf, std::time::SystemTime)> {
+        let Ok(read_dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        read_dir
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("png"))
+            .filter_map(|path| {
+                let written = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+                Some((path, written))
+            })
+            .collect()
+    }
+
+    fn prune(&self) {
+        let mut entries = self.entries();
+        if entries.len() <= self.capacity {
+            return;
+        }
+        entries.sort_by_key(|(_, written)| *written);
+        let excess = entries.len() - self.capacity;
+        for (path, _) in entries.into_iter().take(excess) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic_icon_cache_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn set_modified(path: &Path, seconds: u64) {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)).unwrap();
+    }
+
+    fn write_exe(dir: &Path, name: &str, seconds: u64) -> IconSource {
+        let path = dir.join(name);
+        fs::write(&path, b"MZ").unwrap();
+        set_modified(&path, seconds);
+        IconSource::Executable(path)
+    }
+
+    /// Number of icons in a cache directory
+    pub(crate) fn cached_icons(dir: &Path) -> usize {
+        fs::read_dir(dir).map_or(0, |entries| entries.count())
+    }
+
+    fn icon(byte: u8) -> String {
+        STANDARD.encode([0x89, b'P', b'N', b'G', byte])
+    }
+
+    #[test]
+    fn test_key_follows_path_and_modification_time() {
+        let dir = test_dir("key");
+        let cache = IconCache::new(dir.join("cache"));
+        let notepad = write_exe(&dir, "notepad.exe", 1_000);
+        let word = write_exe(&dir, "winword.exe", 1_000);
+
+        let key = cache.key(&notepad).unwrap();
+        assert_eq!(cache.key(&notepad), Some(key.clone()));
+        assert_ne!(cache.key(&word), Some(key.clone()));
+
+        // The same executable as a packaged app has its own entry
+        let packaged = IconSource::PackageManifest(notepad.path().to_path_buf());
+        assert_ne!(cache.key(&packaged), Some(key.clone()));
+
+        set_modified(notepad.path(), 2_000);
+        assert_ne!(cache.key(&notepad), Some(key));
+
+        // Nothing to key a missing executable on
+        assert_eq!(cache.key(&IconSource::Executable(dir.join("missing.exe"))), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_updated_executable_invalidates_entry() {
+        let dir = test_dir("invalidate");
+        let cache = IconCache::new(dir.join("cache"));
+        let notepad = write_exe(&dir, "notepad.exe", 1_000);
+
+        let old_key = cache.key(&notepad).unwrap();
+        assert_eq!(cache.get(&old_key), None);
+        cache.put(&old_key, &icon(1)).unwrap();
+        assert_eq!(cache.get(&old_key), Some(icon(1)));
+
+        // An update misses the cache and replaces the old entry when stored
+        set_modified(notepad.path(), 2_000);
+        let new_key = cache.key(&notepad).unwrap();
+        assert_eq!(cache.get(&new_key), None);
+        cache.put(&new_key, &icon(2)).unwrap();
+        assert_eq!(cache.get(&new_key), Some(icon(2)));
+        assert_eq!(cache.get(&old_key), None);
+        assert_eq!(cached_icons(&dir.join("cache")), 1);
+
+        assert!(cache.put(&new_key, "not base64!").is_err());
+        assert_eq!(cache.get(&new_key), Some(icon(2)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_oldest_entries_are_pruned() {
+        let dir = test_dir("prune");
+        let cache = IconCache::with_capacity(dir.join("cache"), 3);
+
+        let keys: Vec<_> = (0..5)
+            .map(|i| {
+                let source = write_exe(&dir, &format!("app{}.exe", i), 1_000);
+                let key = cache.key(&source).unwrap();
+                cache.put(&key, &icon(i as u8)).unwrap();
+                // Entries age by when they were written
+                set_modified(&dir.join("cache").join(key.file_name()), 1_000 + i);
+                key
+            })
+            .collect();
+
+        assert_eq!(cached_icons(&dir.join("cache")), 3);
+        assert_eq!(cache.get(&keys[0]), None);
+        assert_eq!(cache.get(&keys[1]), None);
+        assert_eq!(cache.get(&keys[4]), Some(icon(4)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,414 @@
+//! Loads app icons for the app picker in the background
+//!
+//! Extracting and PNG-encoding an icon takes long enough that doing it for
+//! every running app blocks the picker, so the app list is returned with only
+//! the icons already cached. The rest are extracted by a few worker threads
+//! and reported one by one as they complete.
+
+use super::icon_cache::{CacheKey, IconCache, IconSource};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Most worker threads extracting icons at once
+pub const MAX_WORKERS: usize = 4;
+
+/// An icon finished loading in the background
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AppIconReady {
+    pub identifier: String,
+    pub icon_base64: String,
+}
+
+type Extractor = dyn Fn(&IconSource) -> Option<String> + Send + Sync;
+type Notifier = dyn Fn(AppIconReady) + Send + Sync;
+
+struct Job {
+    identifier: String,
+    source: IconSource,
+}
+
+struct Shared {
+    cache: IconCache,
+    extract: Box<Extractor>,
+    notify: Box<Notifier>,
+    /// Where each listed app's icon comes from
+    sources: Mutex<HashMap<String, IconSource>>,
+    /// Icons loaded since startup
+    icons: Mutex<HashMap<String, String>>,
+    /// Apps queued or being extracted
+    pending: Mutex<HashSet<String>>,
+}
+
+impl Shared {
+    /// Cached icon, without extracting it, and the disk cache key it was
+    /// looked up under
+    fn cached(&self, identifier: &str, source: &IconSource) -> (Option<CacheKey>, Option<String>) {
+        if let Some(icon) = self.icons.lock().unwrap().get(identifier) {
+            return (None, Some(icon.clone()));
+        }
+        let key = self.cache.key(source);
+        let icon = key.as_ref().and_then(|key| self.cache.get(key));
+        if let Some(icon) = &icon {
+            self.remember(identifier, icon);
+        }
+        (key, icon)
+    }
+
+    /// Icon from the cache, extracting and caching it on a miss
+    fn load(&self, identifier: &str, source: &IconSource) -> Option<String> {
+        let (key, icon) = self.cached(identifier, source);
+        if icon.is_some() {
+            return icon;
+        }
+
+        let icon = (self.extract)(source)?;
+        if let Some(key) = key {
+            if let Err(e) = self.cache.put(&key, &icon) {
+                log::warn!("Failed to cache icon of {}: {}", identifier, e);
+            }
+        }
+        self.remember(identifier, &icon);
+        Some(icon)
+    }
+
+    fn remember(&self, identifier: &str, icon: &str) {
+        self.icons.lock().unwrap().insert(identifier.to_string(), icon.to_string());
+    }
+
+    fn run(&self, job: Job) {
+        let icon = self.load(&job.identifier, &job.source);
+        self.pending.lock().unwrap().remove(&job.identifier);
+        match icon {
+            Some(icon_base64) => (self.notify)(AppIconReady {
+                identifier: job.identifier,
+                icon_base64,
+            }),
+            None => log::debug!("No icon for {} ({:?})", job.identifier, job.source),
+        }
+    }
+}
+
+pub struct AppIconService {
+    shared: Arc<Shared>,
+    queue: Mutex<Option<Sender<Job>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl AppIconService {
+    /// Creates the service with icons cached in `cache_dir`.
+    ///
+    /// `extract` runs on the worker threads; `notify` is called from them for
+    /// each icon that finished loading.
+    pub fn new(
+        cache_dir: impl Into<PathBuf>,
+        extract: impl Fn(&IconSource) -> Option<String> + Send + Sync + 'static,
+        notify: impl Fn(AppIconReady) + Send + Sync + 'static,
+    ) -> Self {
+        let workers = std::thread::available_parallelism()
+            .map_or(1, |count| count.get())
+            .min(MAX_WORKERS);
+        Self::with_workers(IconCache::new(cache_dir), workers, extract, notify)
+    }
+
+    fn with_workers(
+        cache: IconCache,
+        workers: usize,
+        extract: impl Fn(&IconSource) -> Option<String> + Send + Sync + 'static,
+        notify: impl Fn(AppIconReady) + Send + Sync + 'static,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            cache,
+            extract: Box::new(extract),
+            notify: Box::new(notify),
+            sources: Mutex::new(HashMap::new()),
+            icons: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashSet::new()),
+        });
+
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..workers.max(1))
+            .map(|index| {
+                let shared = shared.clone();
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("app-icons-{}", index))
+                    .spawn(move || work(&shared, &receiver))
+                    .expect("Failed to start icon worker")
+            })
+            .collect();
+
+        Self {
+            shared,
+            queue: Mutex::new(Some(sender)),
+            workers,
+        }
+    }
+
+    /// Icon of a listed app if it is already cached. Otherwise it is queued
+    /// for extraction and reported to `notify` once loaded.
+    pub fn request(&self, identifier: &str, source: IconSource) -> Option<String> {
+        let previous = self
+            .shared
+            .sources
+            .lock()
+            .unwrap()
+            .insert(identifier.to_string(), source.clone());
+        if previous.is_some_and(|previous| previous != source) {
+            // Another executable now goes by this name
+            self.shared.icons.lock().unwrap().remove(identifier);
+        }
+
+        if let (_, Some(icon)) = self.shared.cached(identifier, &source) {
+            return Some(icon);
+        }
+
+        if !self.shared.pending.lock().unwrap().insert(identifier.to_string()) {
+            return None;
+        }
+        let job = Job {
+            identifier: identifier.to_string(),
+            source,
+        };
+        if let Some(queue) = self.queue.lock().unwrap().as_ref() {
+            let _ = queue.send(job);
+        }
+        None
+    }
+
+    /// Icon of a listed app, extracting it on this thread if no worker has
+    /// got to it yet. None for apps that were never listed or have no icon.
+    pub fn icon(&self, identifier: &str) -> Option<String> {
+        let source = self.shared.sources.lock().unwrap().get(identifier).cloned()?;
+        self.shared.load(identifier, &source)
+    }
+}
+
+impl Drop for AppIconService {
+    fn drop(&mut self) {
+        // Closing the queue lets the workers finish what they hold and exit
+        self.queue.lock().unwrap().take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(shared: &Shared, receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        shared.run(job);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::guard::Guard;
+    use super::super::icon_cache::tests::cached_icons;
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic_icon_loader_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_exe(dir: &Path, name: &str) -> IconSource {
+        let path = dir.join(name);
+        fs::write(&path, b"MZ").unwrap();
+        IconSource::Executable(path)
+    }
+
+    /// Icon encoding the executable's file name
+    fn fake_icon(source: &IconSource) -> String {
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+        STANDARD.encode(source.path().file_name().unwrap().to_string_lossy().as_bytes())
+    }
+
+    fn collect_events() -> (Arc<Mutex<Vec<AppIconReady>>>, impl Fn(AppIconReady) + Send + Sync) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        (events, move |ready| sink.lock().unwrap().push(ready))
+    }
+
+    fn wait_for(mut done: impl FnMut() -> bool) {
+        for _ in 0..500 {
+            if done() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("timed out waiting for icons");
+    }
+
+    #[test]
+    fn test_misses_are_loaded_in_background() {
+        let dir = test_dir("background");
+        let extracted = Arc::new(AtomicUsize::new(0));
+        let (events, notify) = collect_events();
+
+        let counter = extracted.clone();
+        let service = AppIconService::new(
+            dir.join("cache"),
+            move |source| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Some(fake_icon(source))
+            },
+            notify,
+        );
+
+        let notepad = write_exe(&dir, "notepad.exe");
+        assert_eq!(service.request("notepad.exe", notepad.clone()), None);
+        wait_for(|| events.lock().unwrap().len() == 1);
+        assert_eq!(
+            events.lock().unwrap()[0],
+            AppIconReady {
+                identifier: "notepad.exe".to_string(),
+                icon_base64: fake_icon(&notepad),
+            }
+        );
+
+        // Listed again, the icon comes back right away
+        assert_eq!(service.request("notepad.exe", notepad.clone()), Some(fake_icon(&notepad)));
+        assert_eq!(service.icon("notepad.exe"), Some(fake_icon(&notepad)));
+        drop(service);
+        assert_eq!(extracted.load(Ordering::SeqCst), 1);
+        assert_eq!(events.lock().unwrap().len(), 1);
+
+        // A restart finds it in the disk cache
+        let counter = extracted.clone();
+        let (_, notify) = collect_events();
+        let service = AppIconService::new(
+            dir.join("cache"),
+            move |source| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Some(fake_icon(source))
+            },
+            notify,
+        );
+        assert_eq!(service.request("notepad.exe", notepad.clone()), Some(fake_icon(&notepad)));
+        assert_eq!(extracted.load(Ordering::SeqCst), 1);
+
+        drop(service);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pull_extracts_on_calling_thread() {
+        let dir = test_dir("pull");
+        let (events, notify) = collect_events();
+        // No workers pick up the queued job while the test pulls it
+        let (gate, opened) = mpsc::channel::<()>();
+        let opened = Mutex::new(opened);
+        let service = AppIconService::with_workers(
+            IconCache::new(dir.join("cache")),
+            1,
+            move |source| {
+                if std::thread::current().name().is_some_and(|name| name.starts_with("app-icons")) {
+                    let _ = opened.lock().unwrap().recv();
+                }
+                Some(fake_icon(source))
+            },
+            notify,
+        );
+
+        let word = write_exe(&dir, "winword.exe");
+        assert_eq!(service.icon("winword.exe"), None);
+        assert_eq!(service.request("winword.exe", word.clone()), None);
+        assert_eq!(service.icon("winword.exe"), Some(fake_icon(&word)));
+
+        drop(gate);
+        wait_for(|| events.lock().unwrap().len() == 1);
+        drop(service);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apps_without_icons_are_not_reported() {
+        let dir = test_dir("no_icon");
+        let (events, notify) = collect_events();
+        let service = AppIconService::new(dir.join("cache"), |_| None, notify);
+
+        let tool = write_exe(&dir, "tool.exe");
+        assert_eq!(service.request("tool.exe", tool), None);
+        assert_eq!(service.icon("tool.exe"), None);
+
+        drop(service);
+        assert!(events.lock().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_many_apps_release_every_handle() {
+        const APPS: usize = 600;
+        const CAPACITY: usize = 64;
+
+        let dir = test_dir("stress");
+        let live_handles = Arc::new(AtomicUsize::new(0));
+        let peak_handles = Arc::new(AtomicUsize::new(0));
+        let (events, notify) = collect_events();
+
+        let (live, peak) = (live_handles.clone(), peak_handles.clone());
+        let service = AppIconService::with_workers(
+            IconCache::with_capacity(dir.join("cache"), CAPACITY),
+            MAX_WORKERS,
+            move |source| {
+                // Three handles per icon like the GDI path, bailing out
+                // between them for some apps
+                let acquire = || {
+                    let count = live.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(count, Ordering::SeqCst);
+                    Guard::new((), |_| {
+                        live.fetch_sub(1, Ordering::SeqCst);
+                    })
+                };
+                let name = source.path().file_name()?.to_str()?;
+                let number: usize = name.trim_start_matches("app").trim_end_matches(".exe").parse().ok()?;
+                let _icon = acquire();
+                let _dc = acquire();
+                if number.is_multiple_of(7) {
+                    return None;
+                }
+                let _bitmap = acquire();
+                Some(fake_icon(source))
+            },
+            notify,
+        );
+
+        for round in 0..2 {
+            for number in 0..APPS {
+                let name = format!("app{}.exe", number);
+                let source = if round == 0 {
+                    write_exe(&dir, &name)
+                } else {
+                    IconSource::Executable(dir.join(&name))
+                };
+                service.request(&name, source);
+            }
+        }
+
+        let with_icons = APPS - APPS.div_ceil(7);
+        wait_for(|| events.lock().unwrap().len() >= with_icons);
+        drop(service);
+
+        assert_eq!(live_handles.load(Ordering::SeqCst), 0);
+        assert!(peak_handles.load(Ordering::SeqCst) <= MAX_WORKERS * 3);
+        // Each app is reported once even though it was listed twice
+        assert_eq!(events.lock().unwrap().len(), with_icons);
+        assert_eq!(cached_icons(&dir.join("cache")), CAPACITY);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -60,6 +60,7 @@ pub fn get_running_apps() -> Result<Vec<AppInfo>> {
                 identifier: bundle_id.clone(),
                 icon_base64,
                 is_running: true,
+                icon_source: None,
             });
             
             seen_identifiers.insert(bundle_id);
@@ -1,5 +1,7 @@
 use anyhow::Result;
 use crate::commands::AppInfo;
+use super::guard::Guard;
+use super::IconSource;
 use std::collections::{HashSet, HashMap};
 use std::path::{Path, PathBuf};
 use std::fs;
@@ -230,21 +232,13 @@ fn get_uwp_app_info_from_frame_window(frame_hwnd: HWND, window_title: &str) -> O
             .unwrap_or("unknown")
             .to_lowercase();
         
-        // Extract icon from UWP app manifest
-        debug!("Extracting icon for UWP app: {} ({})", display_name, process_path);
-        let icon_base64 = extract_uwp_logo_from_manifest(&process_path);
-        
-        if icon_base64.is_some() {
-            debug!("Successfully extracted icon for {} from manifest", display_name);
-        } else {
-            warn!("Failed to extract icon for {} from manifest", display_name);
-        }
-        
+        // The icon comes from the UWP app manifest, loaded in the background
         Some(AppInfo {
             display_name,
             identifier,
-            icon_base64,
+            icon_base64: None,
             is_running: true,
+            icon_source: Some(IconSource::PackageManifest(PathBuf::from(process_path))),
         })
     }
 }
@@ -272,21 +266,13 @@ fn get_win32_app_info(pid: u32) -> Option<AppInfo> {
         // Extract display name
         let display_name = extract_display_name(&process_path);
         
-        // Extract icon
-        debug!("Extracting icon for Win32 app: {} ({})", display_name, process_path);
-        let icon_base64 = extract_icon_as_base64(&process_path);
-        
-        if icon_base64.is_some() {
-            debug!("Successfully extracted icon for {}", display_name);
-        } else {
-            warn!("Failed to extract icon for {} at path: {}", display_name, process_path);
-        }
-        
+        // The icon is extracted in the background
         Some(AppInfo {
             display_name,
             identifier: exe_name,
-            icon_base64,
+            icon_base64: None,
             is_running: true,
+            icon_source: Some(IconSource::Executable(PathBuf::from(process_path))),
         })
     }
 }
@@ -539,16 +525,51 @@ fn clean_display_name(name: &str) -> String {
         .join(" ")
 }
 
+/// Extracts the icon of an app listed by `get_running_apps`, base64 encoded.
+/// Runs on the icon worker threads.
+pub fn extract_icon(source: &IconSource) -> Option<String> {
+    let path = source.path().to_str()?;
+    let icon = match source {
+        IconSource::Executable(_) => {
+            ensure_com_initialized();
+            extract_icon_as_base64(path)
+        }
+        IconSource::PackageManifest(_) => extract_uwp_logo_from_manifest(path),
+    };
+    
+    if icon.is_some() {
+        debug!("Extracted icon from {}", path);
+    } else {
+        warn!("Failed to extract icon from {}", path);
+    }
+    icon
+}
+
+/// SHGetFileInfoW needs COM on the calling thread. The apartment is left when
+/// the thread exits.
+fn ensure_com_initialized() {
+    use windows::Win32::System::Com::{CoInitializeEx, CoUninitialize, COINIT_APARTMENTTHREADED};
+    
+    struct Apartment(bool);
+    
+    impl Drop for Apartment {
+        fn drop(&mut self) {
+            if self.0 {
+                unsafe { CoUninitialize() };
+            }
+        }
+    }
+    
+    thread_local! {
+        static APARTMENT: Apartment = Apartment(unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED) }.is_ok());
+    }
+    APARTMENT.with(|_| {});
+}
+
 fn extract_icon_as_base64(exe_path: &str) -> Option<String> {
     use windows::Win32::UI::Shell::{SHGetFileInfoW, SHFILEINFOW, SHGFI_ICON, SHGFI_LARGEICON};
-    use windows::Win32::UI::WindowsAndMessaging::{DestroyIcon, GetIconInfo, ICONINFO};
-    use windows::Win32::Graphics::Gdi::{
-        CreateCompatibleDC, CreateDIBSection, SelectObject, DeleteDC, DeleteObject,
-        GetDC, ReleaseDC, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS,
-        BITMAP, GetObjectW, BitBlt, SRCCOPY
-    };
+    use windows::Win32::UI::WindowsAndMessaging::DestroyIcon;
     use windows::core::PCWSTR;
-    use base64::{Engine as _, engine::general_purpose::STANDARD};
     use std::ffi::OsStr;
     use std::os::windows::ffi::OsStrExt;
     
@@ -573,20 +594,57 @@ fn extract_icon_as_base64(exe_path: &str) -> Option<String> {
             return None;
         }
         
-        // Get icon info
-        let mut icon_info = ICONINFO::default();
-        if GetIconInfo(file_info.hIcon, &mut icon_info).is_err() {
-            let _ = DestroyIcon(file_info.hIcon);
-            return None;
+        let icon = Guard::new(file_info.hIcon, |icon| {
+            let _ = DestroyIcon(icon);
+        });
+        encode_icon(*icon)
+    }
+}
+
+/// Renders an icon into a 32-bit bitmap and encodes it as a base64 PNG.
+///
+/// Every handle is wrapped in a guard as soon as it is acquired, so the
+/// early returns release them too.
+fn encode_icon(icon: HICON) -> Option<String> {
+    use windows::Win32::UI::WindowsAndMessaging::{GetIconInfo, ICONINFO};
+    use windows::Win32::Graphics::Gdi::{
+        CreateCompatibleDC, CreateDIBSection, SelectObject, DeleteDC, DeleteObject,
+        GetDC, ReleaseDC, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS,
+        BITMAP, GetObjectW, BitBlt, SRCCOPY, HBITMAP, HDC, HGDIOBJ
+    };
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+    use image::{ImageBuffer, Rgba};
+    
+    let delete_bitmap = |bitmap: HBITMAP| {
+        if !bitmap.0.is_null() {
+            unsafe {
+                let _ = DeleteObject(bitmap);
+            }
         }
+    };
+    let delete_dc = |dc: HDC| unsafe {
+        let _ = DeleteDC(dc);
+    };
+    // Puts back what was selected into a DC before the guarded object
+    let select = |dc: HDC, object: HGDIOBJ| unsafe {
+        Guard::new(SelectObject(dc, object), move |previous| {
+            SelectObject(dc, previous);
+        })
+    };
+    
+    let (width, height, pixel_data) = unsafe {
+        // GetIconInfo hands out copies of the icon's bitmaps
+        let mut icon_info = ICONINFO::default();
+        GetIconInfo(icon, &mut icon_info).ok()?;
+        let color = Guard::new(icon_info.hbmColor, delete_bitmap);
+        let mask = Guard::new(icon_info.hbmMask, delete_bitmap);
         
         // Use color bitmap if available, otherwise use mask
-        let bitmap_handle = if !icon_info.hbmColor.0.is_null() {
-            icon_info.hbmColor
-        } else if !icon_info.hbmMask.0.is_null() {
-            icon_info.hbmMask
+        let bitmap_handle = if !color.0.is_null() {
+            *color
+        } else if !mask.0.is_null() {
+            *mask
         } else {
-            let _ = DestroyIcon(file_info.hIcon);
             return None;
         };
         
@@ -597,19 +655,17 @@ fn extract_icon_as_base64(exe_path: &str) -> Option<String> {
             std::mem::size_of::<BITMAP>() as i32,
             Some(&mut bitmap as *mut _ as *mut _)
         ) == 0 {
-            if !icon_info.hbmColor.0.is_null() {
-                let _ = DeleteObject(icon_info.hbmColor);
-            }
-            if !icon_info.hbmMask.0.is_null() {
-                let _ = DeleteObject(icon_info.hbmMask);
-            }
-            let _ = DestroyIcon(file_info.hIcon);
             return None;
         }
         
         // Create DIB section to copy icon data
-        let screen_dc = GetDC(None);
-        let mem_dc = CreateCompatibleDC(screen_dc);
+        let screen_dc = Guard::new(GetDC(None), |dc| {
+            ReleaseDC(None, dc);
+        });
+        let mem_dc = Guard::new(CreateCompatibleDC(*screen_dc), delete_dc);
+        if mem_dc.0.is_null() {
+            return None;
+        }
         
         // Prepare bitmap info for 32-bit RGBA
         let bmp_info = BITMAPINFO {
@@ -631,42 +687,34 @@ fn extract_icon_as_base64(exe_path: &str) -> Option<String> {
         
         let mut bits_ptr = std::ptr::null_mut();
         let dib = CreateDIBSection(
-            mem_dc,
+            *mem_dc,
             &bmp_info,
             DIB_RGB_COLORS,
             &mut bits_ptr,
             None,
             0
         ).ok()?;
-        
+        let dib = Guard::new(dib, delete_bitmap);
         if dib.0.is_null() || bits_ptr.is_null() {
-            let _ = DeleteDC(mem_dc);
-            let _ = ReleaseDC(None, screen_dc);
-            if !icon_info.hbmColor.0.is_null() {
-                let _ = DeleteObject(icon_info.hbmColor);
-            }
-            if !icon_info.hbmMask.0.is_null() {
-                let _ = DeleteObject(icon_info.hbmMask);
-            }
-            let _ = DestroyIcon(file_info.hIcon);
             return None;
         }
         
-        // Select the DIB into memory DC and copy icon data
-        let old_bmp = SelectObject(mem_dc, dib);
-        
-        // Create source DC and select the icon bitmap
-        let src_dc = CreateCompatibleDC(screen_dc);
-        let old_src_bmp = SelectObject(src_dc, bitmap_handle);
+        // Select the DIB into memory DC and the icon bitmap into a source DC
+        let _dib_selection = select(*mem_dc, (*dib).into());
+        let src_dc = Guard::new(CreateCompatibleDC(*screen_dc), delete_dc);
+        if src_dc.0.is_null() {
+            return None;
+        }
+        let _src_selection = select(*src_dc, bitmap_handle.into());
         
         // Copy the bitmap data
         let _ = BitBlt(
-            mem_dc,
+            *mem_dc,
             0,
             0,
             bitmap.bmWidth,
             bitmap.bmHeight,
-            src_dc,
+            *src_dc,
             0,
             0,
             SRCCOPY,
@@ -674,58 +722,30 @@ fn extract_icon_as_base64(exe_path: &str) -> Option<String> {
         
         // Calculate bitmap size
         let width = bitmap.bmWidth as usize;
-        let height = bitmap.bmHeight.abs() as usize;
-        let row_size = width * 4; // 32 bits per pixel
-        let total_size = row_size * height;
+        let height = bitmap.bmHeight.unsigned_abs() as usize;
+        let total_size = width * 4 * height; // 32 bits per pixel
         
         // Copy bitmap data
         let mut pixel_data = vec![0u8; total_size];
         std::ptr::copy_nonoverlapping(bits_ptr as *const u8, pixel_data.as_mut_ptr(), total_size);
         
-        // Convert BGRA to RGBA
-        for i in (0..total_size).step_by(4) {
-            pixel_data.swap(i, i + 2); // Swap B and R
-        }
-        
-        // Clean up GDI objects
-        SelectObject(src_dc, old_src_bmp);
-        let _ = DeleteDC(src_dc);
-        SelectObject(mem_dc, old_bmp);
-        let _ = DeleteObject(dib);
-        let _ = DeleteDC(mem_dc);
-        let _ = ReleaseDC(None, screen_dc);
-        if !icon_info.hbmColor.0.is_null() {
-            let _ = DeleteObject(icon_info.hbmColor);
-        }
-        if !icon_info.hbmMask.0.is_null() {
-            let _ = DeleteObject(icon_info.hbmMask);
-        }
-        let _ = DestroyIcon(file_info.hIcon);
-        
-        // Convert to PNG using image crate
-        use image::{ImageBuffer, Rgba};
-        
-        // Try to create image buffer
-        let img = match ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(
-            width as u32,
-            height as u32,
-            pixel_data,
-        ) {
-            Some(img) => img,
-            None => return None,
-        };
-        
-        // Encode as PNG
-        let mut png_data = Vec::new();
-        let mut cursor = std::io::Cursor::new(&mut png_data);
-        match img.write_to(&mut cursor, image::ImageFormat::Png) {
-            Ok(_) => {},
-            Err(_) => return None,
-        }
-        
-        // Convert to base64
-        Some(STANDARD.encode(png_data))
+        // Guards release the selections, DCs and bitmaps in reverse order here
+        (width, height, pixel_data)
+    };
+    
+    // Convert BGRA to RGBA
+    let mut pixel_data = pixel_data;
+    for pixel in pixel_data.chunks_exact_mut(4) {
+        pixel.swap(0, 2); // Swap B and R
     }
+    
+    // Convert to PNG using image crate
+    let img = ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(width as u32, height as u32, pixel_data)?;
+    let mut png_data = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut png_data), image::ImageFormat::Png).ok()?;
+    
+    // Convert to base64
+    Some(STANDARD.encode(png_data))
 }
 
 // Structure for enum callback data
@@ -800,13 +820,7 @@ unsafe extern "system" fn enum_child_callback(hwnd: HWND, lparam: windows::Win32
 // Not currently used - we're using manifest extraction only
 #[allow(dead_code)]
 fn extract_icon_from_window(hwnd: HWND) -> Option<String> {
-    use windows::Win32::UI::WindowsAndMessaging::{DestroyIcon, GetIconInfo, ICONINFO, CopyIcon};
-    use windows::Win32::Graphics::Gdi::{
-        CreateCompatibleDC, CreateDIBSection, SelectObject, DeleteDC, DeleteObject,
-        GetDC, ReleaseDC, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS,
-        BITMAP, GetObjectW, BitBlt, SRCCOPY
-    };
-    use base64::{Engine as _, engine::general_purpose::STANDARD};
+    use windows::Win32::UI::WindowsAndMessaging::{DestroyIcon, CopyIcon};
     
     unsafe {
         // Try to get icon from window using SendMessage with WM_GETICON
@@ -844,157 +858,10 @@ fn extract_icon_from_window(hwnd: HWND) -> Option<String> {
         }
         
         // Copy the icon to ensure we own it
-        let owned_icon = CopyIcon(icon_handle).ok()?;
-        
-        // Get icon info
-        let mut icon_info = ICONINFO::default();
-        if GetIconInfo(owned_icon, &mut icon_info).is_err() {
-            let _ = DestroyIcon(owned_icon);
-            return None;
-        }
-        
-        // Use color bitmap if available
-        let bitmap_handle = if !icon_info.hbmColor.0.is_null() {
-            icon_info.hbmColor
-        } else {
-            let _ = DestroyIcon(owned_icon);
-            return None;
-        };
-        
-        // Get bitmap info
-        let mut bitmap = BITMAP::default();
-        if GetObjectW(
-            bitmap_handle,
-            std::mem::size_of::<BITMAP>() as i32,
-            Some(&mut bitmap as *mut _ as *mut _)
-        ) == 0 {
-            if !icon_info.hbmColor.0.is_null() {
-                let _ = DeleteObject(icon_info.hbmColor);
-            }
-            if !icon_info.hbmMask.0.is_null() {
-                let _ = DeleteObject(icon_info.hbmMask);
-            }
-            let _ = DestroyIcon(owned_icon);
-            return None;
-        }
-        
-        // Create DIB section to copy icon data
-        let screen_dc = GetDC(None);
-        let mem_dc = CreateCompatibleDC(screen_dc);
-        
-        let bmp_info = BITMAPINFO {
-            bmiHeader: BITMAPINFOHEADER {
-                biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
-                biWidth: bitmap.bmWidth,
-                biHeight: -bitmap.bmHeight, // Negative for top-down
-                biPlanes: 1,
-                biBitCount: 32,
-                biCompression: BI_RGB.0,
-                biSizeImage: 0,
-                biXPelsPerMeter: 0,
-                biYPelsPerMeter: 0,
-                biClrUsed: 0,
-                biClrImportant: 0,
-            },
-            ..Default::default()
-        };
-        
-        let mut bits_ptr = std::ptr::null_mut();
-        let dib = CreateDIBSection(
-            mem_dc,
-            &bmp_info,
-            DIB_RGB_COLORS,
-            &mut bits_ptr,
-            None,
-            0
-        ).ok()?;
-        
-        if dib.0.is_null() || bits_ptr.is_null() {
-            let _ = DeleteDC(mem_dc);
-            let _ = ReleaseDC(None, screen_dc);
-            if !icon_info.hbmColor.0.is_null() {
-                let _ = DeleteObject(icon_info.hbmColor);
-            }
-            if !icon_info.hbmMask.0.is_null() {
-                let _ = DeleteObject(icon_info.hbmMask);
-            }
-            let _ = DestroyIcon(owned_icon);
-            return None;
-        }
-        
-        // Select the DIB into memory DC and copy icon data
-        let old_bmp = SelectObject(mem_dc, dib);
-        
-        // Create source DC and select the icon bitmap
-        let src_dc = CreateCompatibleDC(screen_dc);
-        let old_src_bmp = SelectObject(src_dc, bitmap_handle);
-        
-        // Copy the bitmap data
-        let _ = BitBlt(
-            mem_dc,
-            0,
-            0,
-            bitmap.bmWidth,
-            bitmap.bmHeight,
-            src_dc,
-            0,
-            0,
-            SRCCOPY,
-        );
-        
-        // Calculate bitmap size
-        let width = bitmap.bmWidth as usize;
-        let height = bitmap.bmHeight.abs() as usize;
-        let row_size = width * 4; // 32 bits per pixel
-        let total_size = row_size * height;
-        
-        // Copy bitmap data
-        let mut pixel_data = vec![0u8; total_size];
-        std::ptr::copy_nonoverlapping(bits_ptr as *const u8, pixel_data.as_mut_ptr(), total_size);
-        
-        // Convert BGRA to RGBA
-        for i in (0..total_size).step_by(4) {
-            pixel_data.swap(i, i + 2); // Swap B and R
-        }
-        
-        // Clean up GDI objects
-        SelectObject(src_dc, old_src_bmp);
-        let _ = DeleteDC(src_dc);
-        SelectObject(mem_dc, old_bmp);
-        let _ = DeleteObject(dib);
-        let _ = DeleteDC(mem_dc);
-        let _ = ReleaseDC(None, screen_dc);
-        if !icon_info.hbmColor.0.is_null() {
-            let _ = DeleteObject(icon_info.hbmColor);
-        }
-        if !icon_info.hbmMask.0.is_null() {
-            let _ = DeleteObject(icon_info.hbmMask);
-        }
-        let _ = DestroyIcon(owned_icon);
-        
-        // Convert to PNG using image crate
-        use image::{ImageBuffer, Rgba};
-        
-        // Try to create image buffer
-        let img = match ImageBuffer::<Rgba<u8>, Vec<u8>>::from_raw(
-            width as u32,
-            height as u32,
-            pixel_data,
-        ) {
-            Some(img) => img,
-            None => return None,
-        };
-        
-        // Encode as PNG
-        let mut png_data = Vec::new();
-        let mut cursor = std::io::Cursor::new(&mut png_data);
-        match img.write_to(&mut cursor, image::ImageFormat::Png) {
-            Ok(_) => {},
-            Err(_) => return None,
-        }
-        
-        // Convert to base64
-        Some(STANDARD.encode(png_data))
+        let owned_icon = Guard::new(CopyIcon(icon_handle).ok()?, |icon| {
+            let _ = DestroyIcon(icon);
+        });
+        encode_icon(*owned_icon)
     }
 }
 
@@ -0,0 +1,535 @@
+//! Error type returned by Tauri commands
+//!
+//! Errors reach the frontend as `{ code, message, details }` so it can branch
+//! on `code` instead of matching message text. The frontend gives these
+//! objects a `toString()` that returns `message`.
+
+use keymagic_core::{Error as EngineError, KmsError};
+use keymagic_core::km2::Km2Error;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::ErrorKind;
+
+use crate::core::{
+    ActivationFailure, InvalidLanguageKey, InvalidOptionValue, KeyEventError, KeyboardActivationError, KeyboardFileReadOnly,
+    KeyboardNotFound, KeyboardTooLarge, PreviewSessionNotFound, ProfileNotFound, SnippetLimitExceeded,
+    TooManyPreviewSessions,
+};
+use crate::keyboard_download::KeyboardDownloadError;
+use crate::privileged::PrivilegedActionError;
+
+/// Stable error codes the frontend can rely on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    NotFound,
+    InvalidInput,
+    ElevationRequired,
+    ElevationCancelled,
+    IoError,
+    EngineError,
+    Conflict,
+    Unsupported,
+    /// The keyboard file cannot be changed; the UI offers to duplicate it
+    ReadOnly,
+    /// Anything not classified above
+    Internal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandError {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+pub type CommandResult<T> = Result<T, CommandError>;
+
+impl CommandError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), details: None }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidInput, message)
+    }
+
+    pub fn unsupported(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Unsupported, message)
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// Prefixes the message, keeping code and details
+    pub fn context(mut self, context: impl fmt::Display) -> Self {
+        self.message = format!("{}: {}", context, self.message);
+        self
+    }
+
+    /// Classifies `err` and keeps its message
+    fn classify(err: &(dyn std::error::Error + 'static), message: String) -> Self {
+        let (code, details) = classify(err).unwrap_or((ErrorCode::Internal, None));
+        Self { code, message, details }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+fn classify_io(err: &std::io::Error) -> (ErrorCode, Option<Value>) {
+    let code = match err.kind() {
+        ErrorKind::NotFound => ErrorCode::NotFound,
+        // Registry keys under HKLM and system folders need an elevated process
+        ErrorKind::PermissionDenied => ErrorCode::ElevationRequired,
+        ErrorKind::AlreadyExists => ErrorCode::Conflict,
+        ErrorKind::Unsupported => ErrorCode::Unsupported,
+        ErrorKind::InvalidInput | ErrorKind::InvalidData => ErrorCode::InvalidInput,
+        _ => ErrorCode::IoError,
+    };
+    (code, err.raw_os_error().map(|os_error| json!({ "os_error": os_error })))
+}
+
+fn classify_km2(err: &Km2Error) -> (ErrorCode, Option<Value>) {
+    match err {
+        Km2Error::Io(e) => classify_io(e),
+        Km2Error::UnsupportedVersion { major, minor } => {
+            (ErrorCode::Unsupported, Some(json!({ "version": format!("{}.{}", major, minor) })))
+        }
+        Km2Error::NewerVersion { major, minor, .. } => {
+            (ErrorCode::Unsupported, Some(json!({ "version": format!("{}.{}", major, minor), "newer": true })))
+        }
+        Km2Error::UnsupportedJsonSchema(version) => {
+            (ErrorCode::Unsupported, Some(json!({ "schema_version": version })))
+        }
+        Km2Error::IncompatibleKeyboard(incompatible) => (
+            ErrorCode::Unsupported,
+            Some(json!({
+                "required_version": incompatible.required_version.to_string(),
+                "missing_features": incompatible.missing_names,
+            })),
+        ),
+        _ => (ErrorCode::InvalidInput, None),
+    }
+}
+
+fn classify_engine(err: &EngineError) -> (ErrorCode, Option<Value>) {
+    match err {
+        EngineError::Io(e) => classify_io(e),
+        EngineError::Km2Error(e) => classify_km2(e),
+        EngineError::UnknownRuleGroup(group) => (ErrorCode::NotFound, Some(json!({ "group": group }))),
+        EngineError::TransformUnavailable(_) => (ErrorCode::Unsupported, None),
+        EngineError::ParseError(_) => (ErrorCode::InvalidInput, None),
+        EngineError::UnknownVirtualKey(key) => (ErrorCode::InvalidInput, Some(json!({ "key": key }))),
+        EngineError::UnknownLayoutOption(name) => (ErrorCode::InvalidInput, Some(json!({ "option": name }))),
+        _ => (ErrorCode::EngineError, None),
+    }
+}
+
+fn classify_key_event(err: &KeyEventError) -> (ErrorCode, Option<Value>) {
+    let details = match err {
+        KeyEventError::UnknownKeyName(name) => Some(json!({ "key": name })),
+        KeyEventError::UnknownKeyCode(code) => Some(json!({ "code": code })),
+        KeyEventError::NotOnUsLayout(ch) => Some(json!({ "character": ch })),
+        KeyEventError::Syntax { text, offset, .. } => Some(json!({ "text": text, "offset": offset })),
+        KeyEventError::Empty => None,
+    };
+    (ErrorCode::InvalidInput, details)
+}
+
+fn classify_kms(err: &KmsError) -> (ErrorCode, Option<Value>) {
+    match err {
+        KmsError::Io(e) => classify_io(e),
+        KmsError::Parse { line, .. } => (ErrorCode::InvalidInput, Some(json!({ "line": line }))),
+        KmsError::IncludeNotFound(path) => (ErrorCode::NotFound, Some(json!({ "include": path }))),
+        KmsError::BinaryWrite(_) => (ErrorCode::IoError, None),
+        KmsError::TooLargeForVersion { what, count, limit, .. } => {
+            (ErrorCode::InvalidInput, Some(json!({ "what": what, "count": count, "limit": limit })))
+        }
+        _ => (ErrorCode::InvalidInput, None),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn classify_windows(err: &windows::core::Error) -> (ErrorCode, Option<Value>) {
+    let code = if err.code() == windows::Win32::Foundation::E_ACCESSDENIED {
+        ErrorCode::ElevationRequired
+    } else {
+        ErrorCode::IoError
+    };
+    (code, Some(json!({ "hresult": err.code().0 })))
+}
+
+fn classify_activation(err: &KeyboardActivationError) -> (ErrorCode, Option<Value>) {
+    let code = match err.reason {
+        ActivationFailure::FileMissing => ErrorCode::NotFound,
+        ActivationFailure::Unreadable => ErrorCode::IoError,
+        ActivationFailure::Invalid => ErrorCode::InvalidInput,
+    };
+    (code, Some(json!({ "keyboard_id": err.keyboard_id, "issues": err.issues })))
+}
+
+fn classify_download(err: &KeyboardDownloadError) -> (ErrorCode, Option<Value>) {
+    match err {
+        KeyboardDownloadError::InvalidUrl(_) | KeyboardDownloadError::InvalidChecksum(_) => {
+            (ErrorCode::InvalidInput, None)
+        }
+        KeyboardDownloadError::InsecureUrl(_) => (ErrorCode::Unsupported, None),
+        KeyboardDownloadError::Http(_) => (ErrorCode::IoError, None),
+        KeyboardDownloadError::TooLarge { limit } => (ErrorCode::InvalidInput, Some(json!({ "max_size": limit }))),
+        KeyboardDownloadError::ChecksumMismatch { expected, actual } => {
+            (ErrorCode::InvalidInput, Some(json!({ "expected_sha256": expected, "actual_sha256": actual })))
+        }
+        KeyboardDownloadError::InvalidKeyboard(issue) => (ErrorCode::InvalidInput, Some(json!({ "issues": [issue] }))),
+    }
+}
+
+fn classify_privileged(err: &PrivilegedActionError) -> (ErrorCode, Option<Value>) {
+    match err {
+        PrivilegedActionError::UnsupportedScheme(scheme) => (ErrorCode::Unsupported, Some(json!({ "scheme": scheme }))),
+        PrivilegedActionError::ChecksumMismatch { expected, actual } => {
+            (ErrorCode::InvalidInput, Some(json!({ "expected_sha256": expected, "actual_sha256": actual })))
+        }
+        PrivilegedActionError::InvalidLanguage(code) => (ErrorCode::InvalidInput, Some(json!({ "language": code }))),
+        _ => (ErrorCode::InvalidInput, None),
+    }
+}
+
+/// Code of the first error in the chain that has a known type
+fn classify(err: &(dyn std::error::Error + 'static)) -> Option<(ErrorCode, Option<Value>)> {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(e) = err.downcast_ref::<CommandError>() {
+            return Some((e.code, e.details.clone()));
+        }
+        if let Some(e) = err.downcast_ref::<KeyboardNotFound>() {
+            return Some((ErrorCode::NotFound, Some(json!({ "keyboard_id": e.0 }))));
+        }
+        if let Some(e) = err.downcast_ref::<ProfileNotFound>() {
+            return Some((ErrorCode::NotFound, Some(json!({ "profile": e.0 }))));
+        }
+        if let Some(e) = err.downcast_ref::<KeyboardFileReadOnly>() {
+            return Some((ErrorCode::ReadOnly, Some(json!({ "keyboard_id": e.keyboard_id, "path": e.path }))));
+        }
+        if let Some(e) = err.downcast_ref::<KeyboardActivationError>() {
+            return Some(classify_activation(e));
+        }
+        if let Some(e) = err.downcast_ref::<KeyboardTooLarge>() {
+            let max_size = crate::core::embedded_keyboards::MAX_EMBEDDED_SIZE;
+            return Some((ErrorCode::InvalidInput, Some(json!({ "keyboard_id": e.keyboard_id, "size": e.size, "max_size": max_size }))));
+        }
+        if let Some(e) = err.downcast_ref::<InvalidOptionValue>() {
+            return Some((ErrorCode::InvalidInput, Some(json!({ "option": e.name, "value": e.value }))));
+        }
+        if let Some(e) = err.downcast_ref::<InvalidLanguageKey>() {
+            return Some((ErrorCode::InvalidInput, Some(json!({ "language": e.0 }))));
+        }
+        if let Some(e) = err.downcast_ref::<SnippetLimitExceeded>() {
+            return Some((ErrorCode::InvalidInput, Some(json!({ "what": e.what, "count": e.count, "limit": e.limit }))));
+        }
+        if let Some(e) = err.downcast_ref::<PreviewSessionNotFound>() {
+            return Some((ErrorCode::NotFound, Some(json!({ "session_id": e.0 }))));
+        }
+        if err.is::<TooManyPreviewSessions>() {
+            return Some((ErrorCode::Conflict, Some(json!({ "limit": crate::core::preview_session::MAX_SESSIONS }))));
+        }
+        if let Some(e) = err.downcast_ref::<KeyEventError>() {
+            return Some(classify_key_event(e));
+        }
+        if let Some(e) = err.downcast_ref::<KeyboardDownloadError>() {
+            return Some(classify_download(e));
+        }
+        if let Some(e) = err.downcast_ref::<PrivilegedActionError>() {
+            return Some(classify_privileged(e));
+        }
+        if let Some(e) = err.downcast_ref::<EngineError>() {
+            return Some(classify_engine(e));
+        }
+        if let Some(e) = err.downcast_ref::<Km2Error>() {
+            return Some(classify_km2(e));
+        }
+        if let Some(e) = err.downcast_ref::<KmsError>() {
+            return Some(classify_kms(e));
+        }
+        if let Some(e) = err.downcast_ref::<std::io::Error>() {
+            return Some(classify_io(e));
+        }
+        #[cfg(target_os = "windows")]
+        if let Some(e) = err.downcast_ref::<windows::core::Error>() {
+            return Some(classify_windows(e));
+        }
+        current = err.source();
+    }
+    None
+}
+
+impl From<anyhow::Error> for CommandError {
+    fn from(err: anyhow::Error) -> Self {
+        // `{:#}` keeps the context chain: "Failed to parse keyboard file: Invalid magic code ..."
+        let message = format!("{:#}", err);
+        Self::classify(err.as_ref(), message)
+    }
+}
+
+impl From<EngineError> for CommandError {
+    fn from(err: EngineError) -> Self {
+        let message = err.to_string();
+        Self::classify(&err, message)
+    }
+}
+
+impl From<Km2Error> for CommandError {
+    fn from(err: Km2Error) -> Self {
+        let message = err.to_string();
+        Self::classify(&err, message)
+    }
+}
+
+impl From<KmsError> for CommandError {
+    fn from(err: KmsError) -> Self {
+        let message = err.to_string();
+        Self::classify(&err, message)
+    }
+}
+
+impl From<PrivilegedActionError> for CommandError {
+    fn from(err: PrivilegedActionError) -> Self {
+        let message = err.to_string();
+        Self::classify(&err, message)
+    }
+}
+
+impl From<std::io::Error> for CommandError {
+    fn from(err: std::io::Error) -> Self {
+        let message = err.to_string();
+        Self::classify(&err, message)
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl From<windows::core::Error> for CommandError {
+    fn from(err: windows::core::Error) -> Self {
+        let message = err.to_string();
+        Self::classify(&err, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+    use keymagic_core::km2::{EngineVersion, FeatureSet, IncompatibleKeyboard};
+
+    #[test]
+    fn test_keyboard_not_found() {
+        let err: anyhow::Error = KeyboardNotFound("zawgyi".to_string()).into();
+        let err = CommandError::from(err);
+        assert_eq!(err.code, ErrorCode::NotFound);
+        assert_eq!(err.message, "Keyboard not found: zawgyi");
+        assert_eq!(err.details, Some(json!({ "keyboard_id": "zawgyi" })));
+    }
+
+    #[test]
+    fn test_preview_session_errors() {
+        let err = CommandError::from(anyhow::Error::from(PreviewSessionNotFound("preview-3".to_string())));
+        assert_eq!(err.code, ErrorCode::NotFound);
+        assert_eq!(err.details, Some(json!({ "session_id": "preview-3" })));
+
+        let err = CommandError::from(anyhow::Error::from(TooManyPreviewSessions));
+        assert_eq!(err.code, ErrorCode::Conflict);
+    }
+
+    #[test]
+    fn test_context_is_kept_and_chain_is_searched() {
+        let err = Err::<(), _>(Km2Error::InvalidMagicCode(*b"ABCD"))
+            .context("Failed to parse keyboard file")
+            .unwrap_err();
+        let err = CommandError::from(err);
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert!(err.message.starts_with("Failed to parse keyboard file: Invalid magic code"), "{}", err.message);
+
+        let err = CommandError::from(EngineError::Km2Error(Km2Error::UnsupportedVersion { major: 9, minor: 0 }));
+        assert_eq!(err.code, ErrorCode::Unsupported);
+        assert_eq!(err.details, Some(json!({ "version": "9.0" })));
+
+        let err = CommandError::from(Km2Error::NewerVersion { major: 1, minor: 7, latest_major: 1, latest_minor: 6 });
+        assert_eq!(err.code, ErrorCode::Unsupported);
+        assert_eq!(err.details, Some(json!({ "version": "1.7", "newer": true })));
+
+        let err = CommandError::from(Km2Error::UnsupportedJsonSchema(2));
+        assert_eq!(err.code, ErrorCode::Unsupported);
+        assert_eq!(err.details, Some(json!({ "schema_version": 2 })));
+
+        let err = CommandError::from(Km2Error::IncompatibleKeyboard(IncompatibleKeyboard {
+            required_features: FeatureSet(1 << 40),
+            missing: FeatureSet(1 << 40),
+            required_version: EngineVersion::new(0, 3, 0),
+            missing_names: vec!["Unicode ranges".to_string()],
+        }));
+        assert_eq!(err.code, ErrorCode::Unsupported);
+        assert!(err.message.starts_with("This keyboard requires KeyMagic engine ≥ 0.3.0"), "{}", err.message);
+        assert_eq!(err.details, Some(json!({ "required_version": "0.3.0", "missing_features": ["Unicode ranges"] })));
+    }
+
+    #[test]
+    fn test_download_errors() {
+        let err = CommandError::from(anyhow::Error::new(KeyboardDownloadError::ChecksumMismatch {
+            expected: "aa".to_string(),
+            actual: "bb".to_string(),
+        }));
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert_eq!(err.details, Some(json!({ "expected_sha256": "aa", "actual_sha256": "bb" })));
+
+        let err = CommandError::from(anyhow::Error::new(KeyboardDownloadError::InsecureUrl("http://x".to_string())));
+        assert_eq!(err.code, ErrorCode::Unsupported);
+    }
+
+    #[test]
+    fn test_privileged_action_errors() {
+        let err = CommandError::from(PrivilegedActionError::UnsupportedScheme("file".to_string()));
+        assert_eq!(err.code, ErrorCode::Unsupported);
+        assert_eq!(err.details, Some(json!({ "scheme": "file" })));
+        assert_eq!(err.message, "URLs with the 'file' scheme cannot be opened");
+
+        let err = CommandError::from(PrivilegedActionError::UncPath("\\\\server\\setup.exe".into()));
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        let err = CommandError::from(PrivilegedActionError::InvalidLanguage("x;y".to_string()));
+        assert_eq!(err.details, Some(json!({ "language": "x;y" })));
+    }
+
+    #[test]
+    fn test_io_errors() {
+        let code = |kind| CommandError::from(std::io::Error::new(kind, "x")).code;
+        assert_eq!(code(ErrorKind::NotFound), ErrorCode::NotFound);
+        assert_eq!(code(ErrorKind::PermissionDenied), ErrorCode::ElevationRequired);
+        assert_eq!(code(ErrorKind::AlreadyExists), ErrorCode::Conflict);
+        assert_eq!(code(ErrorKind::InvalidData), ErrorCode::InvalidInput);
+        assert_eq!(code(ErrorKind::Interrupted), ErrorCode::IoError);
+
+        let err = CommandError::from(std::io::Error::from_raw_os_error(5));
+        assert_eq!(err.details, Some(json!({ "os_error": 5 })));
+
+        // Also found behind anyhow context
+        let err = Err::<(), _>(std::io::Error::new(ErrorKind::NotFound, "gone")).context("Failed to read keyboard file");
+        assert_eq!(CommandError::from(err.unwrap_err()).code, ErrorCode::NotFound);
+    }
+
+    #[test]
+    fn test_engine_errors() {
+        let code = |err| CommandError::from(err).code;
+        assert_eq!(code(EngineError::RecursionDepthExceeded), ErrorCode::EngineError);
+        assert_eq!(code(EngineError::InvalidStateIndex(3)), ErrorCode::EngineError);
+        assert_eq!(code(EngineError::TransformUnavailable("zawgyi")), ErrorCode::Unsupported);
+        assert_eq!(code(EngineError::UnknownRuleGroup("x".to_string())), ErrorCode::NotFound);
+        assert_eq!(code(EngineError::ParseError("x".to_string())), ErrorCode::InvalidInput);
+    }
+
+    #[test]
+    fn test_unknown_key_name() {
+        let err = CommandError::from(anyhow::Error::from(EngineError::UnknownVirtualKey("VK_NOPE".to_string())));
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert_eq!(err.details, Some(json!({ "key": "VK_NOPE" })));
+        assert_eq!(err.message, "Unknown key name: VK_NOPE");
+    }
+
+    #[test]
+    fn test_invalid_keyboard_option() {
+        let err = CommandError::from(anyhow::Error::from(EngineError::UnknownLayoutOption("smart".to_string())));
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert_eq!(err.details, Some(json!({ "option": "smart" })));
+
+        let err = CommandError::from(anyhow::Error::from(InvalidOptionValue { name: "eat".to_string(), value: json!("on") }));
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert_eq!(err.details, Some(json!({ "option": "eat", "value": "on" })));
+        assert_eq!(err.message, "Option eat must be true or false, not \"on\"");
+    }
+
+    #[test]
+    fn test_read_only_keyboard_file() {
+        let err = anyhow::Error::from(KeyboardFileReadOnly {
+            keyboard_id: "myanmar3".to_string(),
+            path: std::path::PathBuf::from("/keyboards/myanmar3.km2"),
+        });
+        let err = CommandError::from(err.context("Failed to save keyboard"));
+        assert_eq!(err.code, ErrorCode::ReadOnly);
+        assert_eq!(err.details, Some(json!({ "keyboard_id": "myanmar3", "path": "/keyboards/myanmar3.km2" })));
+        assert!(err.message.contains("duplicate keyboard myanmar3"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_keyboard_too_large_to_embed() {
+        let err = anyhow::Error::from(KeyboardTooLarge { keyboard_id: "big".to_string(), size: 300_000 });
+        let err = CommandError::from(err.context("Failed to store keyboard"));
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert_eq!(err.details, Some(json!({ "keyboard_id": "big", "size": 300_000, "max_size": 262_144 })));
+        assert!(err.message.contains("smaller than 256 KB"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_snippet_limit() {
+        let err = anyhow::Error::from(SnippetLimitExceeded { what: "rules", count: 2500, limit: 2000 });
+        let err = CommandError::from(err.context("Failed to test snippet"));
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert_eq!(err.details, Some(json!({ "what": "rules", "count": 2500, "limit": 2000 })));
+    }
+
+    #[test]
+    fn test_key_event_errors() {
+        let err = CommandError::from(anyhow::Error::from(KeyEventError::UnknownKeyName("VK_NOPE".to_string())));
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert_eq!(err.details, Some(json!({ "key": "VK_NOPE" })));
+
+        let err = "<Super+k>".parse::<crate::core::KeyEventDto>().unwrap_err();
+        let err = CommandError::from(anyhow::Error::from(err).context("Failed to test snippet"));
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert_eq!(err.details, Some(json!({ "text": "<Super+k>", "offset": 1 })));
+    }
+
+    #[test]
+    fn test_kms_errors() {
+        let err = CommandError::from(KmsError::Parse { line: 12, message: "unexpected token".to_string() });
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert_eq!(err.details, Some(json!({ "line": 12 })));
+        assert_eq!(err.message, "Parse error at line 12: unexpected token");
+
+        assert_eq!(CommandError::from(KmsError::IncludeNotFound("a.kms".to_string())).code, ErrorCode::NotFound);
+        assert_eq!(CommandError::from(KmsError::UndefinedVariable("x".to_string())).code, ErrorCode::InvalidInput);
+        assert_eq!(CommandError::from(KmsError::BinaryWrite("x".to_string())).code, ErrorCode::IoError);
+    }
+
+    #[test]
+    fn test_unclassified_and_nested_command_errors() {
+        assert_eq!(CommandError::from(anyhow!("something odd")).code, ErrorCode::Internal);
+
+        let inner = CommandError::invalid_input("bad hotkey").with_details(json!({ "hotkey": "X" }));
+        let err = CommandError::from(anyhow::Error::new(inner).context("Failed to save"));
+        assert_eq!(err.code, ErrorCode::InvalidInput);
+        assert_eq!(err.message, "Failed to save: bad hotkey");
+        assert_eq!(err.details, Some(json!({ "hotkey": "X" })));
+    }
+
+    #[test]
+    fn test_serialization() {
+        let err = CommandError::new(ErrorCode::ElevationRequired, "Access is denied").context("Failed to update language profiles");
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            json!({ "code": "ELEVATION_REQUIRED", "message": "Failed to update language profiles: Access is denied" })
+        );
+        assert_eq!(err.to_string(), err.message);
+        let round_trip: CommandError = serde_json::from_value(serde_json::to_value(&err).unwrap()).unwrap();
+        assert_eq!(round_trip, err);
+    }
+}
@@ -0,0 +1,207 @@
+//! What the keyboard commands do, without Tauri
+//!
+//! The commands in `commands` take their state from Tauri and hand it to the
+//! functions here, which only need a [`KeyboardManager`] and somewhere to
+//! send events. Tests drive them against the mock platform and read the
+//! events back from a [`RecordedEvents`].
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+use keymagic_core::Explanation;
+
+use crate::command_error::{CommandError, CommandResult};
+use crate::core::{
+    HotkeyConflictInfo, ImportedKeyboard, KeyEventDto, KeyMapping, KeyboardActivationError, KeyboardInfo, KeyboardManager,
+    KeyboardNotFound, KeyboardStorage, PreviewFont,
+};
+use crate::hotkey::HotkeyManager;
+
+/// Where commands send events for the UI; the app handle in the app
+pub trait EventSink {
+    fn emit_value(&self, event: &str, payload: Value);
+}
+
+impl dyn EventSink + '_ {
+    /// Sends `payload` as `event`; one that cannot be serialized is logged
+    /// and dropped, like a failed emit
+    pub fn emit<T: Serialize + ?Sized>(&self, event: &str, payload: &T) {
+        match serde_json::to_value(payload) {
+            Ok(payload) => self.emit_value(event, payload),
+            Err(e) => log::warn!("Event {} not sent: {}", event, e),
+        }
+    }
+}
+
+/// Keeps the events sent to it, for tests
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Default)]
+pub struct RecordedEvents {
+    events: std::sync::Mutex<Vec<(String, Value)>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl RecordedEvents {
+    /// Events sent since the last call, oldest first
+    pub fn take(&self) -> Vec<(String, Value)> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl EventSink for RecordedEvents {
+    fn emit_value(&self, event: &str, payload: Value) {
+        self.events.lock().unwrap().push((event.to_string(), payload));
+    }
+}
+
+/// Keys of a keyboard for the layout view
+#[derive(Debug, Serialize)]
+pub struct KeyboardLayoutData {
+    pub keyboard_name: String,
+    pub keyboard_id: String,
+    pub keys: HashMap<String, KeyMapping>,
+    /// Font to draw the keys with, and the one to suggest when it is missing
+    pub font: PreviewFont,
+    /// Names of the states the keyboard switches on, in index order
+    pub states: Vec<String>,
+    /// Variables as (name, value) pairs
+    pub variables: Vec<(String, String)>,
+}
+
+pub(crate) fn keyboard_not_found(keyboard_id: &str) -> CommandError {
+    anyhow::Error::new(KeyboardNotFound(keyboard_id.to_string())).into()
+}
+
+pub fn get_keyboards(manager: &KeyboardManager) -> Vec<KeyboardInfo> {
+    // A keyboard file may have been restored or replaced since the last look
+    manager.refresh_keyboard_statuses();
+    manager.get_keyboards()
+}
+
+pub fn set_active_keyboard(manager: &KeyboardManager, events: &dyn EventSink, keyboard_id: &str) -> CommandResult<()> {
+    manager
+        .set_active_keyboard(keyboard_id)
+        .map_err(|e| activation_failed(events, e))?;
+
+    // Emit event to notify all UI components
+    events.emit("active_keyboard_changed", keyboard_id);
+
+    Ok(())
+}
+
+/// Tells the UI why a keyboard could not be activated
+pub fn activation_failed(events: &dyn EventSink, err: anyhow::Error) -> CommandError {
+    if let Some(failure) = err.downcast_ref::<KeyboardActivationError>() {
+        events.emit("keyboard_activation_failed", failure);
+    }
+    CommandError::from(err)
+}
+
+pub fn get_keyboard_layout(manager: &KeyboardManager, keyboard_id: &str) -> CommandResult<KeyboardLayoutData> {
+    let keyboard = manager.get_keyboard(keyboard_id).ok_or_else(|| keyboard_not_found(keyboard_id))?;
+
+    // Load the keyboard file to get the actual engine
+    let layout = manager.load_keyboard(&keyboard)
+        .map_err(|e| CommandError::from(e).context("Failed to load keyboard file"))?;
+    let font = manager.preview_font(&layout);
+    let states = layout.states();
+    let variables = layout.variables();
+
+    // Create a temporary engine for this keyboard
+    let mut engine = keymagic_core::KeyMagicEngine::new(layout)
+        .map_err(|e| CommandError::from(e).context("Failed to create engine"))?;
+
+    let keys = crate::core::layout_preview::compute_key_map(&mut engine);
+
+    Ok(KeyboardLayoutData {
+        keyboard_name: keyboard.name,
+        keyboard_id: keyboard.id,
+        keys,
+        font,
+        states,
+        variables,
+    })
+}
+
+/// What a keyboard does with a key after `composing_text`, and why; for
+/// keys that seem to do nothing
+pub fn explain_key(
+    manager: &KeyboardManager,
+    keyboard_id: &str,
+    key: &KeyEventDto,
+    composing_text: Option<&str>,
+) -> CommandResult<Explanation> {
+    let keyboard = manager.get_keyboard(keyboard_id).ok_or_else(|| keyboard_not_found(keyboard_id))?;
+    let input = key.to_key_input().map_err(|e| CommandError::from(anyhow::Error::from(e)))?;
+
+    let layout = manager.load_keyboard(&keyboard)
+        .map_err(|e| CommandError::from(e).context("Failed to load keyboard file"))?;
+    let mut engine = keymagic_core::KeyMagicEngine::new(layout)
+        .map_err(|e| CommandError::from(e).context("Failed to create engine"))?;
+    if let Some(text) = composing_text {
+        engine.set_composing_text(text.to_string());
+    }
+    Ok(engine.explain_key(&input))
+}
+
+pub fn import_keyboard(
+    manager: &KeyboardManager,
+    hotkey_manager: &HotkeyManager,
+    file_path: &Path,
+) -> CommandResult<ImportedKeyboard> {
+    let imported = manager
+        .import_keyboard_reporting(file_path, |hotkey| hotkey_manager.validate_hotkey(hotkey, None).is_ok())?;
+    imported_keyboard(manager, imported)
+}
+
+/// Adds the language profiles to offer to an import result. Failing to read
+/// them only costs the suggestion, the keyboard is already imported.
+pub fn imported_keyboard(
+    manager: &KeyboardManager,
+    (keyboard, hotkey_conflict): (KeyboardInfo, Option<HotkeyConflictInfo>),
+) -> CommandResult<ImportedKeyboard> {
+    let suggested_languages = manager.suggested_languages(&keyboard).unwrap_or_else(|e| {
+        log::warn!("Could not suggest language profiles for {}: {:#}", keyboard.id, e);
+        Vec::new()
+    });
+    Ok(ImportedKeyboard { keyboard, suggested_languages, hotkey_conflict })
+}
+
+/// Removes a keyboard; removing the active one leaves none active, which
+/// the UI hears as `active_keyboard_changed` with `null`
+pub fn remove_keyboard(manager: &KeyboardManager, events: &dyn EventSink, keyboard_id: &str) -> CommandResult<()> {
+    let was_active = manager.get_active_keyboard().as_deref() == Some(keyboard_id);
+    manager.remove_keyboard(keyboard_id).map_err(CommandError::from)?;
+    if was_active {
+        events.emit("active_keyboard_changed", &None::<String>);
+    }
+    Ok(())
+}
+
+/// Embeds a keyboard in the platform store for when its file is missing,
+/// or goes back to the file alone; returns the keyboard as it is now
+pub fn set_keyboard_storage(
+    manager: &KeyboardManager,
+    keyboard_id: &str,
+    storage: KeyboardStorage,
+) -> CommandResult<KeyboardInfo> {
+    manager
+        .set_keyboard_storage(keyboard_id, storage)
+        .map_err(|e| CommandError::from(e).context("Failed to change where the keyboard is stored"))
+}
+
+pub fn update_hotkey(
+    manager: &KeyboardManager,
+    hotkey_manager: &HotkeyManager,
+    keyboard_id: &str,
+    hotkey: Option<String>,
+) -> CommandResult<()> {
+    manager
+        .update_hotkey(keyboard_id, hotkey)
+        .map_err(CommandError::from)?;
+    hotkey_manager.register_all_hotkeys(manager, false)?;
+    Ok(())
+}
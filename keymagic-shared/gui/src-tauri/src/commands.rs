@@ -1,12 +1,40 @@
-use crate::core::{KeyboardInfo, KeyboardManager};
-use crate::hotkey::HotkeyManager;
-use crate::platform::PlatformInfo;
-use keymagic_core::{KeyInput, VirtualKey};
-use keymagic_core::engine::ModifierState;
+use crate::command_error::{CommandError, CommandResult, ErrorCode};
+use crate::command_logic::{self, keyboard_not_found, EventSink};
+use crate::app_enumerator::AppIconService;
+use crate::commit_history::{self, CommitHistoryMonitor, HistoryOptions};
+use crate::composition_journal::{self, CompositionJournalMonitor, UnrecoveredComposition};
+use crate::core::{
+    BundledKeyboard, HotkeyActivation, ImportedKeyboard, KeyEventDto, KeyboardDiff,
+    KeyboardField, KeyboardFilter, KeyboardIcon, KeyboardInfo, KeyboardManager, KeyboardNotFound, KeyboardOptions,
+    KeyboardPage, KeyboardSort, KeyboardStatusDetail, KeyboardStorage, MetadataChanges, PassthroughKeysInfo,
+    PreviewOutput, PreviewSessions, ProfileApplied, ProfileInfo, RepairReport, RuleGroupInfo, SnippetResult, SwitchAnnouncementInfo, TemporaryKeyboardInfo,
+};
+use crate::core::temporary_keyboard::DEFAULT_TRIAL_DURATION;
+use crate::diagnostics::{self, BundleOptions, DiagnosticBundle, SystemInfo};
+use crate::file_manager::{self, RevealError};
+use crate::hook_shortcuts::{self, HookShortcut, HookShortcutMonitor, ShortcutAction};
+use crate::hotkey::{HotkeyManager, HotkeyOutcome, HotkeyRegistration};
+use crate::http::{self, ProxySettings, ProxySettingsInfo};
+use crate::install;
+use crate::input_latency::{self, InputLatencyMonitor, InputLatencyStats};
+use crate::input_mode::{InputModeInfo, InputModeMonitor};
+use crate::input_recording::{InputRecording, RecordingStatus};
+use crate::key_stats::{self, DateRange, KeyHeatmapInfo, KeyStatsMonitor};
+use crate::keyboard_download::{self, DownloadOptions};
+use crate::legacy_keymagic::{self, LegacyDetection, MigrationReport, MigrationSelection};
+use crate::privileged::{self, ElevatedAction};
+use crate::soft_keyboard::{self, SharedFocusHistory, SoftKeyResult};
+use crate::temp_file;
+use crate::platform::{HostMode, LanguageAction, LanguageActivationConfig, OutputEncoding, PlatformInfo, ProfileOverrides};
+use crate::version::Version;
+use keymagic_core::analysis::{AnalysisReport, PerformanceReport};
+use keymagic_core::hotkey::HotkeyBinding;
+use keymagic_core::recorder::ProcessSummary;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tauri::{AppHandle, Emitter, Manager, State};
 
 pub type AppState = Arc<KeyboardManager>;
@@ -14,32 +42,73 @@ pub type AppState = Arc<KeyboardManager>;
 // Re-export UpdateInfo from updater module
 pub use crate::updater::UpdateInfo;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct KeyMapping {
-    pub shifted: Option<String>,
-    pub unshifted: Option<String>,
-}
+pub use crate::command_logic::KeyboardLayoutData;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct KeyboardLayoutData {
-    pub keyboard_name: String,
-    pub keyboard_id: String,
-    pub keys: HashMap<String, KeyMapping>,
+impl EventSink for AppHandle {
+    fn emit_value(&self, event: &str, payload: serde_json::Value) {
+        let _ = Emitter::emit(self, event, payload);
+    }
 }
 
 #[tauri::command]
-pub fn get_platform_info(state: State<AppState>) -> Result<PlatformInfo, String> {
+pub fn get_platform_info(state: State<AppState>) -> CommandResult<PlatformInfo> {
     // Get platform info from the keyboard manager
     Ok(state.get_platform_info())
 }
 
 #[tauri::command]
-pub fn get_keyboards(state: State<AppState>) -> Result<Vec<KeyboardInfo>, String> {
-    Ok(state.get_keyboards())
+pub fn get_keyboards(state: State<AppState>) -> CommandResult<Vec<KeyboardInfo>> {
+    Ok(command_logic::get_keyboards(&state))
+}
+
+#[tauri::command]
+pub fn query_keyboards(
+    state: State<AppState>,
+    filter: Option<KeyboardFilter>,
+    page: usize,
+    page_size: usize,
+    sort: Option<KeyboardSort>,
+) -> CommandResult<KeyboardPage> {
+    if page == 0 {
+        state.refresh_keyboard_statuses();
+    }
+    Ok(state.query_keyboards(
+        &filter.unwrap_or_default(),
+        page,
+        page_size,
+        sort.unwrap_or_default(),
+    ))
 }
 
 #[tauri::command]
-pub fn get_active_keyboard(state: State<AppState>) -> Result<Option<String>, String> {
+pub fn get_keyboard_status_detail(state: State<AppState>, keyboard_id: String) -> CommandResult<KeyboardStatusDetail> {
+    state.keyboard_status_detail(&keyboard_id).map_err(CommandError::from)
+}
+
+/// Stores a copy of a keyboard in the registry, for machines that lose
+/// keyboard files between sessions, or removes it
+#[tauri::command]
+pub fn set_keyboard_storage(
+    state: State<AppState>,
+    keyboard_id: String,
+    storage: KeyboardStorage,
+) -> CommandResult<KeyboardInfo> {
+    command_logic::set_keyboard_storage(&state, &keyboard_id, storage)
+}
+
+#[tauri::command]
+pub fn get_keyboard_icon(
+    state: State<AppState>,
+    keyboard_id: String,
+    etag: Option<String>,
+) -> CommandResult<KeyboardIcon> {
+    state
+        .get_keyboard_icon(&keyboard_id, etag.as_deref())
+        .map_err(CommandError::from)
+}
+
+#[tauri::command]
+pub fn get_active_keyboard(state: State<AppState>) -> CommandResult<Option<String>> {
     Ok(state.get_active_keyboard())
 }
 
@@ -48,198 +117,783 @@ pub fn set_active_keyboard(
     app: AppHandle,
     state: State<AppState>,
     keyboard_id: String,
-) -> Result<(), String> {
-    state
-        .set_active_keyboard(&keyboard_id)
-        .map_err(|e| e.to_string())?;
-    
-    
-    // Emit event to notify all UI components
-    let _ = app.emit("active_keyboard_changed", &keyboard_id);
-    
+) -> CommandResult<()> {
+    command_logic::set_active_keyboard(&state, &app, &keyboard_id)
+}
+
+/// Tries a keyboard file out system-wide without installing or saving it.
+/// The trial ends by itself after `duration_secs` (a minute by default).
+#[tauri::command]
+pub fn activate_temporary_keyboard(
+    app: AppHandle,
+    state: State<AppState>,
+    path: String,
+    duration_secs: Option<u64>,
+) -> CommandResult<TemporaryKeyboardInfo> {
+    let duration = duration_secs.map(Duration::from_secs).unwrap_or(DEFAULT_TRIAL_DURATION);
+    let info = state
+        .activate_temporary_keyboard(Path::new(&path), duration)
+        .map_err(|e| CommandError::from(e).context("Failed to try out keyboard"))?;
+    let _ = app.emit("temporary_keyboard_changed", Some(&info));
+
+    // The token keeps this timer from ending a later trial
+    let manager = state.inner().clone();
+    let token = info.token.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(duration);
+        match manager.end_temporary_activation(&token) {
+            Ok(true) => {
+                log::info!("Temporary keyboard expired");
+                let _ = app.emit("temporary_keyboard_changed", None::<TemporaryKeyboardInfo>);
+            }
+            Ok(false) => {}
+            Err(e) => log::warn!("Failed to end temporary keyboard: {}", e),
+        }
+    });
+
+    Ok(info)
+}
+
+/// Goes back to the active keyboard; false if the trial had already ended
+#[tauri::command]
+pub fn end_temporary_activation(app: AppHandle, state: State<AppState>, token: String) -> CommandResult<bool> {
+    let ended = state
+        .end_temporary_activation(&token)
+        .map_err(|e| CommandError::from(e).context("Failed to end temporary keyboard"))?;
+    if ended {
+        let _ = app.emit("temporary_keyboard_changed", None::<TemporaryKeyboardInfo>);
+    }
+    Ok(ended)
+}
+
+#[tauri::command]
+pub fn get_temporary_keyboard(state: State<AppState>) -> Option<TemporaryKeyboardInfo> {
+    state.temporary_keyboard()
+}
+
+/// Called when a keyboard hotkey is pressed; respects the global on/off state
+#[tauri::command]
+pub fn activate_keyboard_by_hotkey(
+    app: AppHandle,
+    state: State<AppState>,
+    keyboard_id: String,
+) -> CommandResult<HotkeyActivation> {
+    let activation = state
+        .activate_keyboard_by_hotkey(&keyboard_id)
+        .map_err(|e| command_logic::activation_failed(&app, e))?;
+
+    if !matches!(activation, HotkeyActivation::Pending { .. } | HotkeyActivation::Suppressed) {
+        let _ = app.emit("active_keyboard_changed", &keyboard_id);
+    }
+    if activation == HotkeyActivation::Enabled {
+        let _ = app.emit("key_processing_changed", true);
+    }
+
+    Ok(activation)
+}
+
+/// Called by an input method backend that detected a double-tap hotkey such
+/// as "Shift Shift"; runs what the hotkey is bound to. `extra_info` is the
+/// `dwExtraInfo` of the key event that completed it, where the backend has
+/// one; taps KeyMagic injected itself are ignored. Returns None when nothing
+/// is bound to it.
+#[tauri::command]
+pub fn trigger_double_tap(
+    app: AppHandle,
+    state: State<AppState>,
+    hotkey_manager: State<Arc<HotkeyManager>>,
+    hotkey: String,
+    extra_info: Option<usize>,
+) -> CommandResult<Option<HotkeyOutcome>> {
+    let key = HotkeyBinding::parse(&hotkey)
+        .ok()
+        .and_then(|binding| binding.double_tap_key())
+        .ok_or_else(|| CommandError::invalid_input(format!("Not a double-tap hotkey: {}", hotkey)))?;
+    let Some(action) = hotkey_manager.hook_double_tap(key, extra_info.unwrap_or(0)) else {
+        return Ok(None);
+    };
+
+    let outcome = hotkey_manager.dispatch(&state, &action).map_err(|e| command_logic::activation_failed(&app, e))?;
+    match &outcome {
+        HotkeyOutcome::Processing { enabled, activated } => {
+            let _ = app.emit("key_processing_changed", enabled);
+            if let Some(keyboard_id) = activated {
+                let _ = app.emit("active_keyboard_changed", keyboard_id);
+            }
+        }
+        HotkeyOutcome::Keyboard { keyboard_id, activation } => {
+            if !matches!(activation, HotkeyActivation::Pending { .. } | HotkeyActivation::Suppressed) {
+                let _ = app.emit("active_keyboard_changed", keyboard_id);
+            }
+            if *activation == HotkeyActivation::Enabled {
+                let _ = app.emit("key_processing_changed", true);
+            }
+        }
+    }
+    Ok(Some(outcome))
+}
+
+#[tauri::command]
+pub fn get_key_processing_enabled(state: State<AppState>) -> CommandResult<bool> {
+    Ok(state.is_key_processing_enabled())
+}
+
+#[tauri::command]
+pub fn set_key_processing_enabled(
+    app: AppHandle,
+    state: State<AppState>,
+    enabled: bool,
+) -> CommandResult<()> {
+    let activated = state
+        .set_key_processing_enabled(enabled)?;
+
+    let _ = app.emit("key_processing_changed", enabled);
+    if let Some(keyboard_id) = activated {
+        let _ = app.emit("active_keyboard_changed", &keyboard_id);
+    }
+
     Ok(())
 }
 
+/// Whether a keyboard hotkey also turns key processing on (off by default)
 #[tauri::command]
-pub fn scan_keyboards(state: State<AppState>) -> Result<Vec<KeyboardInfo>, String> {
-    state.scan_keyboards().map_err(|e| e.to_string())
+pub fn set_auto_enable_on_keyboard_hotkey(state: State<AppState>, enabled: bool) -> CommandResult<()> {
+    state.get_platform()
+        .set_setting("auto_enable_on_keyboard_hotkey", if enabled { "true" } else { "false" })
+        .map_err(CommandError::from)
+}
+
+#[tauri::command]
+pub fn scan_keyboards(state: State<AppState>) -> CommandResult<Vec<KeyboardInfo>> {
+    state.scan_keyboards().map_err(CommandError::from)
 }
 
 #[tauri::command]
 pub fn get_keyboard_layout(
     state: State<AppState>,
     keyboard_id: String,
-) -> Result<KeyboardLayoutData, String> {
-    let keyboards = state.get_keyboards();
-    let keyboard = keyboards
-        .iter()
-        .find(|k| k.id == keyboard_id)
-        .ok_or_else(|| format!("Keyboard not found: {}", keyboard_id))?;
-
-    // Load the keyboard file to get the actual engine
-    let layout = state.load_keyboard_file(&keyboard.path)
-        .map_err(|e| format!("Failed to load keyboard file: {}", e))?;
-    
-    // Create a temporary engine for this keyboard
+) -> CommandResult<KeyboardLayoutData> {
+    command_logic::get_keyboard_layout(&state, &keyboard_id)
+}
+
+/// What a keyboard does with a key typed after `composing_text`, and why;
+/// behind the layout window's "Why?" mode
+#[tauri::command]
+pub fn explain_key(
+    state: State<AppState>,
+    keyboard_id: String,
+    key_event: KeyEventDto,
+    composing_text: Option<String>,
+) -> CommandResult<keymagic_core::Explanation> {
+    command_logic::explain_key(&state, &keyboard_id, &key_event, composing_text.as_deref())
+}
+
+/// Sends a key from the on-screen keyboard to the previously focused
+/// application and returns the action produced by the active keyboard for the UI to mirror
+#[tauri::command]
+pub fn send_virtual_key(
+    state: State<AppState>,
+    focus_history: State<SharedFocusHistory>,
+    key: KeyEventDto,
+) -> CommandResult<SoftKeyResult> {
+    let engine = state
+        .get_engine()
+        .ok_or_else(|| CommandError::not_found("No active keyboard"))?;
+
+    let mut result = soft_keyboard::mirror_key(&mut engine.write(), &key)?;
+
+    result.injected = soft_keyboard::deliver_key(&focus_history, &key)
+        .map_err(|e| CommandError::from(e).context("Failed to send key"))?;
+    Ok(result)
+}
+
+/// Recently committed text, newest first; empty when the history is off.
+/// Changes are also emitted as `commit_history_changed`.
+#[tauri::command]
+pub fn get_commit_history(monitor: State<Arc<CommitHistoryMonitor>>) -> CommandResult<Vec<String>> {
+    Ok(monitor.entries())
+}
+
+/// Types an entry of the commit history into the previously focused
+/// application; returns false where text cannot be injected
+#[tauri::command]
+pub fn reinsert_commit(
+    monitor: State<Arc<CommitHistoryMonitor>>,
+    focus_history: State<SharedFocusHistory>,
+    index: usize,
+) -> CommandResult<bool> {
+    let text = monitor
+        .get(index)
+        .ok_or_else(|| CommandError::not_found(format!("No commit history entry {}", index)))?;
+    soft_keyboard::deliver_text(&focus_history, &text)
+        .map_err(|e| CommandError::from(e).context("Failed to insert text"))
+}
+
+#[tauri::command]
+pub fn clear_commit_history(app: AppHandle, monitor: State<Arc<CommitHistoryMonitor>>) -> CommandResult<()> {
+    monitor.clear();
+    let _ = app.emit("commit_history_changed", Vec::<String>::new());
+    Ok(())
+}
+
+/// Sets how many strings are kept (0 turns the history off) and whether
+/// they are kept across restarts
+#[tauri::command]
+pub fn set_commit_history_options(
+    app: AppHandle,
+    state: State<AppState>,
+    monitor: State<Arc<CommitHistoryMonitor>>,
+    size: usize,
+    persist: bool,
+) -> CommandResult<()> {
+    let options = HistoryOptions { size: size.min(commit_history::MAX_SIZE), persist };
+    let platform = state.get_platform();
+    platform.set_setting(commit_history::SIZE_SETTING, &options.size.to_string())?;
+    platform.set_setting(commit_history::PERSIST_SETTING, if options.persist { "true" } else { "false" })?;
+    monitor.configure(options);
+    let _ = app.emit("commit_history_changed", monitor.entries());
+    Ok(())
+}
+
+/// Whether key presses are counted for the layout heatmap
+#[tauri::command]
+pub fn get_key_stats_enabled(monitor: State<Arc<KeyStatsMonitor>>) -> CommandResult<bool> {
+    Ok(monitor.is_enabled())
+}
+
+/// Opts in to or out of counting key presses; counts already kept stay
+/// until cleared
+#[tauri::command]
+pub fn set_key_stats_enabled(
+    state: State<AppState>,
+    monitor: State<Arc<KeyStatsMonitor>>,
+    enabled: bool,
+) -> CommandResult<()> {
+    state
+        .get_platform()
+        .set_setting(key_stats::ENABLED_SETTING, if enabled { "true" } else { "false" })?;
+    monitor.configure(enabled);
+    Ok(())
+}
+
+/// Percentiles of the time text services took per key since the GUI
+/// started, from a sample of keys
+#[tauri::command]
+pub fn get_input_latency_stats(monitor: State<Arc<InputLatencyMonitor>>) -> CommandResult<InputLatencyStats> {
+    Ok(monitor.stats())
+}
+
+/// Sets the 95th percentile latency above which, sustained, the HUD
+/// suggests running diagnostics
+#[tauri::command]
+pub fn set_input_latency_threshold(
+    state: State<AppState>,
+    monitor: State<Arc<InputLatencyMonitor>>,
+    threshold_ms: u64,
+) -> CommandResult<()> {
+    if threshold_ms == 0 {
+        return Err(CommandError::invalid_input("The latency threshold must be at least 1 ms"));
+    }
+    state
+        .get_platform()
+        .set_setting(input_latency::THRESHOLD_SETTING, &threshold_ms.to_string())?;
+    monitor.configure(threshold_ms);
+    Ok(())
+}
+
+/// Whether text being composed is journaled for recovery after a crash
+#[tauri::command]
+pub fn get_composition_journal_enabled(monitor: State<Arc<CompositionJournalMonitor>>) -> CommandResult<bool> {
+    Ok(monitor.is_enabled())
+}
+
+/// Opts in to or out of journaling compositions; opting out deletes the
+/// journal and its key
+#[tauri::command]
+pub fn set_composition_journal_enabled(
+    state: State<AppState>,
+    monitor: State<Arc<CompositionJournalMonitor>>,
+    enabled: bool,
+) -> CommandResult<()> {
+    if enabled && !monitor.is_available() {
+        return Err(CommandError::invalid_input("Compositions cannot be journaled on this platform"));
+    }
+    state
+        .get_platform()
+        .set_setting(composition_journal::ENABLED_SETTING, if enabled { "true" } else { "false" })?;
+    monitor.configure(enabled)?;
+    Ok(())
+}
+
+/// Text left by applications that closed or crashed while it was being
+/// composed, from the last 24 hours
+#[tauri::command]
+pub fn get_unrecovered_compositions(
+    monitor: State<Arc<CompositionJournalMonitor>>,
+) -> CommandResult<Vec<UnrecoveredComposition>> {
+    Ok(monitor.unrecovered())
+}
+
+/// Forgets an unrecovered composition once the user took or declined it
+#[tauri::command]
+pub fn dismiss_unrecovered_composition(
+    monitor: State<Arc<CompositionJournalMonitor>>,
+    id: String,
+) -> CommandResult<bool> {
+    Ok(monitor.dismiss(&id)?)
+}
+
+/// Presses of each key while `keyboard_id` was active over `date_range`
+#[tauri::command]
+pub fn get_key_heatmap(
+    monitor: State<Arc<KeyStatsMonitor>>,
+    keyboard_id: String,
+    date_range: DateRange,
+) -> CommandResult<KeyHeatmapInfo> {
+    monitor.heatmap(&keyboard_id, &date_range).map_err(CommandError::invalid_input)
+}
+
+/// Writes the counts of `keyboard_id` over `date_range` to `path` as CSV,
+/// one row per day or month and key
+#[tauri::command]
+pub fn export_key_heatmap_csv(
+    monitor: State<Arc<KeyStatsMonitor>>,
+    keyboard_id: String,
+    date_range: DateRange,
+    path: String,
+) -> CommandResult<()> {
+    let csv = monitor.csv(&keyboard_id, &date_range).map_err(CommandError::invalid_input)?;
+    temp_file::write_atomic(Path::new(&path), csv).map_err(|e| CommandError::from(e).context(format!("Failed to write {}", path)))
+}
+
+/// Forgets every key press counted
+#[tauri::command]
+pub fn clear_key_stats(monitor: State<Arc<KeyStatsMonitor>>) -> CommandResult<()> {
+    monitor.clear().map_err(CommandError::from)
+}
+
+/// Shortcuts the text services catch for the GUI, for applications that
+/// lose their own shortcuts to system-wide hotkeys
+#[tauri::command]
+pub fn get_hook_shortcuts(monitor: State<Arc<HookShortcutMonitor>>) -> CommandResult<Vec<HookShortcut>> {
+    Ok(monitor.shortcuts())
+}
+
+/// Has the text services catch `combo` when the active keyboard does not
+/// use it. Firing is emitted as `hook_shortcut_triggered`. With `swallow`
+/// (the default) the application never sees the key.
+#[tauri::command]
+pub fn register_global_shortcut_via_hook(
+    state: State<AppState>,
+    monitor: State<Arc<HookShortcutMonitor>>,
+    combo: String,
+    action: ShortcutAction,
+    swallow: Option<bool>,
+) -> CommandResult<Vec<HookShortcut>> {
+    let shortcuts = monitor
+        .register(&combo, action, swallow.unwrap_or(true))
+        .map_err(|e| CommandError::invalid_input(e.to_string()))?;
+    save_hook_shortcuts(&state, &shortcuts)?;
+    Ok(shortcuts)
+}
+
+#[tauri::command]
+pub fn unregister_global_shortcut_via_hook(
+    state: State<AppState>,
+    monitor: State<Arc<HookShortcutMonitor>>,
+    combo: String,
+) -> CommandResult<Vec<HookShortcut>> {
+    let shortcuts = monitor
+        .unregister(&combo)
+        .map_err(|e| CommandError::invalid_input(e.to_string()))?;
+    save_hook_shortcuts(&state, &shortcuts)?;
+    Ok(shortcuts)
+}
+
+fn save_hook_shortcuts(state: &AppState, shortcuts: &[HookShortcut]) -> CommandResult<()> {
+    let json = serde_json::to_string(shortcuts).map_err(anyhow::Error::from)?;
+    state.get_platform().set_setting(hook_shortcuts::SETTING, &json)?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn diff_keyboards(
+    state: State<AppState>,
+    path_a: String,
+    path_b: String,
+) -> CommandResult<KeyboardDiff> {
+    let old = state.load_keyboard_file(&PathBuf::from(&path_a))
+        .map_err(|e| CommandError::from(e).context("Failed to load keyboard file"))?;
+    let new = state.load_keyboard_file(&PathBuf::from(&path_b))
+        .map_err(|e| CommandError::from(e).context("Failed to load keyboard file"))?;
+
+    crate::core::keyboard_diff::diff_layouts(&old, &new)
+        .map_err(|e| CommandError::from(e).context("Failed to diff keyboards"))
+}
+
+/// Reports unreachable rules of an installed keyboard and, when a corpus of
+/// typed key sequences (one per line) is given, which rules it exercises
+#[tauri::command]
+pub fn analyze_keyboard_rules(
+    state: State<AppState>,
+    keyboard_id: String,
+    corpus: Option<String>,
+) -> CommandResult<AnalysisReport> {
+    let keyboard = state
+        .get_keyboard(&keyboard_id)
+        .ok_or_else(|| keyboard_not_found(&keyboard_id))?;
+    let layout = state.load_keyboard(&keyboard)
+        .map_err(|e| CommandError::from(e).context("Failed to load keyboard file"))?;
+
+    keymagic_core::analysis::analyze_keyboard(&layout, corpus.as_deref().map(str::lines))
+        .map_err(|e| CommandError::from(e).context("Failed to analyze keyboard"))
+}
+
+/// Hottest rules listed by `analyze_keyboard_performance`
+const PERFORMANCE_HOT_RULES: usize = 20;
+
+/// Types a sample text (one run per line) with an installed keyboard and
+/// reports how many rules each key scans and which rules match most
+#[tauri::command]
+pub fn analyze_keyboard_performance(
+    state: State<AppState>,
+    keyboard_id: String,
+    sample_text: String,
+) -> CommandResult<PerformanceReport> {
+    let keyboard = state
+        .get_keyboard(&keyboard_id)
+        .ok_or_else(|| keyboard_not_found(&keyboard_id))?;
+    let layout = state.load_keyboard(&keyboard)
+        .map_err(|e| CommandError::from(e).context("Failed to load keyboard file"))?;
+
     let mut engine = keymagic_core::KeyMagicEngine::new(layout)
-        .map_err(|e| format!("Failed to create engine: {}", e))?;
-    
-    let mut keys = HashMap::new();
-    
-    // Helper function to get output for a key with specific modifiers
-    let mut get_key_output = |vk: VirtualKey, modifiers: ModifierState, character: Option<char>| -> Option<String> {
-        // Reset engine state before each test
-        engine.reset();
-        
-        let input = KeyInput::new(vk as u16, modifiers, character);
-        match engine.process_key_test(input) {
-            Ok(output) => {
-                if output.composing_text.is_empty() {
-                    None
-                } else {
-                    Some(output.composing_text)
-                }
-            }
-            Err(_) => None,
-        }
-    };
+        .map_err(|e| CommandError::from(e).context("Failed to create engine"))?;
+    keymagic_core::analysis::run_performance(&mut engine, sample_text.lines(), PERFORMANCE_HOT_RULES)
+        .map_err(|e| CommandError::from(e).context("Failed to analyze keyboard performance"))
+}
 
-    // Define key mappings from VirtualKey to DOM key names with their default characters
-    let key_mappings = [
-        // Number row (unshifted, shifted characters)
-        (VirtualKey::Oem3, "Backquote", '`', '~'),
-        (VirtualKey::Key1, "Digit1", '1', '!'),
-        (VirtualKey::Key2, "Digit2", '2', '@'),
-        (VirtualKey::Key3, "Digit3", '3', '#'),
-        (VirtualKey::Key4, "Digit4", '4', '$'),
-        (VirtualKey::Key5, "Digit5", '5', '%'),
-        (VirtualKey::Key6, "Digit6", '6', '^'),
-        (VirtualKey::Key7, "Digit7", '7', '&'),
-        (VirtualKey::Key8, "Digit8", '8', '*'),
-        (VirtualKey::Key9, "Digit9", '9', '('),
-        (VirtualKey::Key0, "Digit0", '0', ')'),
-        (VirtualKey::OemMinus, "Minus", '-', '_'),
-        (VirtualKey::OemPlus, "Equal", '=', '+'),
-        
-        // Top row (QWERTY)
-        (VirtualKey::KeyQ, "KeyQ", 'q', 'Q'),
-        (VirtualKey::KeyW, "KeyW", 'w', 'W'),
-        (VirtualKey::KeyE, "KeyE", 'e', 'E'),
-        (VirtualKey::KeyR, "KeyR", 'r', 'R'),
-        (VirtualKey::KeyT, "KeyT", 't', 'T'),
-        (VirtualKey::KeyY, "KeyY", 'y', 'Y'),
-        (VirtualKey::KeyU, "KeyU", 'u', 'U'),
-        (VirtualKey::KeyI, "KeyI", 'i', 'I'),
-        (VirtualKey::KeyO, "KeyO", 'o', 'O'),
-        (VirtualKey::KeyP, "KeyP", 'p', 'P'),
-        (VirtualKey::Oem4, "BracketLeft", '[', '{'),
-        (VirtualKey::Oem6, "BracketRight", ']', '}'),
-        (VirtualKey::Oem5, "Backslash", '\\', '|'),
-        
-        // Home row (ASDF)
-        (VirtualKey::KeyA, "KeyA", 'a', 'A'),
-        (VirtualKey::KeyS, "KeyS", 's', 'S'),
-        (VirtualKey::KeyD, "KeyD", 'd', 'D'),
-        (VirtualKey::KeyF, "KeyF", 'f', 'F'),
-        (VirtualKey::KeyG, "KeyG", 'g', 'G'),
-        (VirtualKey::KeyH, "KeyH", 'h', 'H'),
-        (VirtualKey::KeyJ, "KeyJ", 'j', 'J'),
-        (VirtualKey::KeyK, "KeyK", 'k', 'K'),
-        (VirtualKey::KeyL, "KeyL", 'l', 'L'),
-        (VirtualKey::Oem1, "Semicolon", ';', ':'),
-        (VirtualKey::Oem7, "Quote", '\'', '"'),
-        
-        // Bottom row (ZXCV)
-        (VirtualKey::KeyZ, "KeyZ", 'z', 'Z'),
-        (VirtualKey::KeyX, "KeyX", 'x', 'X'),
-        (VirtualKey::KeyC, "KeyC", 'c', 'C'),
-        (VirtualKey::KeyV, "KeyV", 'v', 'V'),
-        (VirtualKey::KeyB, "KeyB", 'b', 'B'),
-        (VirtualKey::KeyN, "KeyN", 'n', 'N'),
-        (VirtualKey::KeyM, "KeyM", 'm', 'M'),
-        (VirtualKey::OemComma, "Comma", ',', '<'),
-        (VirtualKey::OemPeriod, "Period", '.', '>'),
-        (VirtualKey::Oem2, "Slash", '/', '?'),
-        
-        // Space
-        (VirtualKey::Space, "Space", ' ', ' '),
-    ];
-
-    // Generate key mappings using the engine
-    for (vk, dom_key, unshifted_char, shifted_char) in key_mappings.iter() {
-        let unshifted = get_key_output(*vk, ModifierState::new(false, false, false, false), Some(*unshifted_char));
-        let shifted = get_key_output(*vk, ModifierState::new(true, false, false, false), Some(*shifted_char));
-        
-        keys.insert(dom_key.to_string(), KeyMapping {
-            shifted,
-            unshifted,
+/// Longest key sequence `enumerate_keyboard_outputs` explores; every extra
+/// key multiplies the work by the number of keys tried
+const MAX_ENUMERATION_DEPTH: usize = 3;
+/// Entries returned by `enumerate_keyboard_outputs` at most
+const MAX_ENUMERATED_OUTPUTS: usize = 5000;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyOutputEntry {
+    /// Keys in KMS notation ("k", "SPACE", "<VK_CONTROL & VK_KEY_K>")
+    pub keys: Vec<String>,
+    pub output: String,
+    pub rules: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EnumerationProgressEvent<'a> {
+    keyboard_id: &'a str,
+    depth: usize,
+    max_depth: usize,
+    expanded: usize,
+    frontier: usize,
+    found: usize,
+}
+
+/// Lists the key sequences of up to `depth` keys that the keyboard turns into
+/// output, for documentation. Progress is emitted as `enumerate_outputs_progress`.
+#[tauri::command]
+pub async fn enumerate_keyboard_outputs(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    keyboard_id: String,
+    depth: usize,
+) -> CommandResult<Vec<KeyOutputEntry>> {
+    if depth > MAX_ENUMERATION_DEPTH {
+        return Err(CommandError::invalid_input(format!(
+            "Depth {} is too deep; at most {} keys are supported",
+            depth, MAX_ENUMERATION_DEPTH
+        ))
+        .with_details(serde_json::json!({ "max_depth": MAX_ENUMERATION_DEPTH })));
+    }
+
+    let keyboard = state
+        .get_keyboard(&keyboard_id)
+        .ok_or_else(|| keyboard_not_found(&keyboard_id))?;
+    let layout = state.load_keyboard(&keyboard)
+        .map_err(|e| CommandError::from(e).context("Failed to load keyboard file"))?;
+    let engine = keymagic_core::KeyMagicEngine::new(layout)
+        .map_err(|e| CommandError::from(e).context("Failed to create engine"))?;
+
+    let mut entries = Vec::new();
+    let mut enumerator = engine.enumerate_outputs(depth);
+    let mut last_expanded = None;
+    while let Some(entry) = enumerator.next() {
+        entries.push(KeyOutputEntry {
+            keys: entry.keys.iter().map(|key| key.to_string()).collect(),
+            output: entry.output,
+            rules: entry.rules,
         });
+        if entries.len() >= MAX_ENUMERATED_OUTPUTS {
+            break;
+        }
+
+        // One event per expanded sequence is plenty
+        let progress = enumerator.progress();
+        if last_expanded != Some((progress.depth, progress.expanded)) {
+            last_expanded = Some((progress.depth, progress.expanded));
+            let _ = app.emit("enumerate_outputs_progress", EnumerationProgressEvent {
+                keyboard_id: &keyboard_id,
+                depth: progress.depth,
+                max_depth: depth,
+                expanded: progress.expanded,
+                frontier: progress.frontier,
+                found: entries.len(),
+            });
+        }
     }
+    Ok(entries)
+}
 
-    Ok(KeyboardLayoutData {
-        keyboard_name: keyboard.name.clone(),
-        keyboard_id: keyboard.id.clone(),
-        keys,
-    })
+#[tauri::command]
+pub fn diff_installed_keyboard(
+    state: State<AppState>,
+    keyboard_id: String,
+    bundled_path: String,
+) -> CommandResult<KeyboardDiff> {
+    let keyboard = state
+        .get_keyboard(&keyboard_id)
+        .ok_or_else(|| keyboard_not_found(&keyboard_id))?;
+
+    diff_keyboards(state, keyboard.path.to_string_lossy().to_string(), bundled_path)
 }
 
 #[tauri::command]
 pub fn import_keyboard(
-    app: AppHandle,
     state: State<AppState>,
+    hotkey_manager: State<Arc<HotkeyManager>>,
     file_path: PathBuf,
-) -> Result<KeyboardInfo, String> {
-    let keyboard_info = state
-        .import_keyboard(&file_path)
-        .map_err(|e| e.to_string())?;
-    
-    
-    Ok(keyboard_info)
+) -> CommandResult<ImportedKeyboard> {
+    command_logic::import_keyboard(&state, &hotkey_manager, &file_path)
+}
+
+/// Downloads a keyboard and imports it. Progress is emitted as
+/// `keyboard_download_progress`. Plain HTTP is refused unless the
+/// `allow_insecure_keyboard_downloads` developer setting is on.
+#[tauri::command]
+pub async fn import_keyboard_from_url(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    hotkey_manager: State<'_, Arc<HotkeyManager>>,
+    url: String,
+    expected_sha256: Option<String>,
+) -> CommandResult<ImportedKeyboard> {
+    let options = DownloadOptions {
+        allow_insecure: state.get_platform().get_setting("allow_insecure_keyboard_downloads")?.as_deref() == Some("true"),
+        ..Default::default()
+    };
+    let downloaded = keyboard_download::download_keyboard(&url, expected_sha256.as_deref(), options, |progress| {
+        let _ = app.emit("keyboard_download_progress", progress);
+    })
+    .await
+    .map_err(anyhow::Error::new)?;
+
+    // The temporary download is removed when `downloaded` goes out of scope
+    let imported = state
+        .import_keyboard_reporting(downloaded.path(), |hotkey| hotkey_manager.validate_hotkey(hotkey, None).is_ok())?;
+    log::info!("Imported keyboard {} from {} (SHA-256 {})", imported.0.id, url, downloaded.sha256);
+    command_logic::imported_keyboard(&state, imported)
+}
+
+#[tauri::command]
+pub fn remove_keyboard(
+    app: AppHandle,
+    state: State<AppState>,
+    keyboard_id: String,
+) -> CommandResult<()> {
+    command_logic::remove_keyboard(&state, &app, &keyboard_id)
+}
+
+/// Reconciles the keyboard registrations with the files on disk. Unregistered
+/// files are imported when `import_unregistered` is set (the user confirmed)
+/// or the auto-import setting allows it; otherwise they are only reported.
+#[tauri::command]
+pub fn repair_keyboard_store(
+    state: State<AppState>,
+    import_unregistered: Option<bool>,
+) -> CommandResult<RepairReport> {
+    let mut policy = state.repair_policy();
+    if let Some(import) = import_unregistered {
+        policy.import_unregistered = import;
+    }
+    state
+        .repair_keyboard_store(policy)
+        .map_err(|e| CommandError::from(e).context("Failed to repair keyboard store"))
+}
+
+/// Hashes every keyboard file again instead of trusting the cached hashes,
+/// and repairs the store with the result; for support
+#[tauri::command]
+pub fn force_rehash_keyboards(state: State<AppState>) -> CommandResult<RepairReport> {
+    state
+        .force_rehash_keyboards()
+        .map_err(|e| CommandError::from(e).context("Failed to rehash keyboards"))
+}
+
+#[tauri::command]
+pub fn update_hotkey(
+    state: State<AppState>,
+    hotkey_manager: State<Arc<HotkeyManager>>,
+    keyboard_id: String,
+    hotkey: Option<String>,
+) -> CommandResult<()> {
+    command_logic::update_hotkey(&state, &hotkey_manager, &keyboard_id, hotkey)
+}
+
+/// Writes a new name, description or hotkey into the keyboard's km2 file;
+/// fails with `READ_ONLY` for keyboards that have to be duplicated first
+#[tauri::command]
+pub fn rewrite_keyboard_metadata(
+    state: State<AppState>,
+    hotkey_manager: State<Arc<HotkeyManager>>,
+    keyboard_id: String,
+    changes: MetadataChanges,
+) -> CommandResult<KeyboardInfo> {
+    let keyboard = state.rewrite_keyboard_metadata(&keyboard_id, &changes)?;
+    // The keyboard's own hotkey may have changed
+    hotkey_manager.register_all_hotkeys(&state, false)?;
+    Ok(keyboard)
+}
+
+/// Keeps reloads caused by other processes from overwriting a field while
+/// the user edits it; pair with `end_keyboard_edit` when the editor closes
+#[tauri::command]
+pub fn begin_keyboard_edit(state: State<AppState>, keyboard_id: String, field: KeyboardField) -> CommandResult<()> {
+    state
+        .begin_keyboard_edit(&keyboard_id, field)
+        .map_err(CommandError::from)
+}
+
+#[tauri::command]
+pub fn end_keyboard_edit(state: State<AppState>, keyboard_id: String, field: KeyboardField) {
+    state.end_keyboard_edit(&keyboard_id, field);
+}
+
+#[tauri::command]
+pub fn set_output_encoding(
+    state: State<AppState>,
+    keyboard_id: String,
+    encoding: OutputEncoding,
+) -> CommandResult<()> {
+    state
+        .update_output_encoding(&keyboard_id, encoding)
+        .map_err(CommandError::from)
+}
+
+#[tauri::command]
+pub fn get_rule_groups(state: State<AppState>, keyboard_id: String) -> CommandResult<Vec<RuleGroupInfo>> {
+    state
+        .get_rule_groups(&keyboard_id)
+        .map_err(CommandError::from)
+}
+
+#[tauri::command]
+pub fn set_rule_group_enabled(
+    state: State<AppState>,
+    keyboard_id: String,
+    group: String,
+    enabled: bool,
+) -> CommandResult<()> {
+    state
+        .set_rule_group_enabled(&keyboard_id, &group, enabled)
+        .map_err(CommandError::from)
+}
+
+#[tauri::command]
+pub fn get_passthrough_keys(state: State<AppState>, keyboard_id: String) -> CommandResult<PassthroughKeysInfo> {
+    state
+        .get_passthrough_keys(&keyboard_id)
+        .map_err(CommandError::from)
+}
+
+#[tauri::command]
+pub fn set_passthrough_keys(
+    state: State<AppState>,
+    keyboard_id: String,
+    keys: Vec<String>,
+    commit_before_passthrough: bool,
+) -> CommandResult<()> {
+    state
+        .set_passthrough_keys(&keyboard_id, &keys, commit_before_passthrough)
+        .map_err(CommandError::from)
+}
+
+#[tauri::command]
+pub fn get_keyboard_options(state: State<AppState>, keyboard_id: String) -> CommandResult<KeyboardOptions> {
+    state
+        .get_keyboard_options(&keyboard_id)
+        .map_err(CommandError::from)
 }
 
+/// Overrides a layout option of a keyboard; `null` goes back to the value
+/// from the keyboard file
 #[tauri::command]
-pub fn remove_keyboard(
-    app: AppHandle,
+pub fn set_keyboard_option(
     state: State<AppState>,
     keyboard_id: String,
-) -> Result<(), String> {
-    
+    name: String,
+    value: Option<serde_json::Value>,
+) -> CommandResult<KeyboardOptions> {
     state
-        .remove_keyboard(&keyboard_id)
-        .map_err(|e| e.to_string())
+        .set_keyboard_option(&keyboard_id, &name, value)
+        .map_err(CommandError::from)
 }
 
+/// Whether keyboard switches are announced to screen readers
 #[tauri::command]
-pub fn update_hotkey(
-    state: State<AppState>,
-    keyboard_id: String,
-    hotkey: Option<String>,
-) -> Result<(), String> {
-    // Update the keyboard hotkey
+pub fn get_switch_announcement(state: State<AppState>) -> SwitchAnnouncementInfo {
+    state.switch_announcement()
+}
+
+/// `None` goes back to announcing only while a screen reader runs
+#[tauri::command]
+pub fn set_switch_announcement(state: State<AppState>, enabled: Option<bool>) -> CommandResult<SwitchAnnouncementInfo> {
     state
-        .update_hotkey(&keyboard_id, hotkey)
-        .map_err(|e| e.to_string())
+        .set_switch_announcement(enabled)
+        .map_err(|e| CommandError::from(e).context("Failed to save announcement setting"))?;
+    Ok(state.switch_announcement())
 }
 
 
 #[tauri::command]
-pub fn validate_hotkey(app: AppHandle, hotkey: String) -> Result<(), String> {
+pub fn validate_hotkey(app: AppHandle, state: State<AppState>, hotkey: String) -> CommandResult<()> {
     // Empty hotkey is always valid
     if hotkey.is_empty() {
         return Ok(());
     }
-    
+
+    // Double-taps are checked against the modifiers the active keyboard uses
+    let layout = state
+        .get_active_keyboard()
+        .and_then(|keyboard_id| state.get_keyboard(&keyboard_id))
+        .and_then(|keyboard| state.load_keyboard(&keyboard).ok());
     if let Some(hotkey_manager) = app.try_state::<Arc<HotkeyManager>>() {
         hotkey_manager
-            .validate_hotkey(&hotkey)
-            .map_err(|e| e.to_string())
+            .validate_hotkey(&hotkey, layout.as_ref())
+            .map_err(|e| CommandError::invalid_input(e.to_string()))
     } else {
-        Err("Hotkey manager not available".to_string())
+        Err(CommandError::new(ErrorCode::Internal, "Hotkey manager not available"))
     }
 }
 
 
 
+/// Drops and registers all hotkeys again, as after resume from sleep;
+/// for support when hotkeys stopped working
+#[tauri::command]
+pub fn force_reregister_hotkeys(
+    state: State<AppState>,
+    hotkey_manager: State<Arc<HotkeyManager>>,
+) -> CommandResult<HotkeyRegistration> {
+    Ok(hotkey_manager.force_reregister(&state)?)
+}
+
 #[tauri::command]
 pub async fn check_for_updates() -> Result<Option<UpdateInfo>, String> {
     match crate::updater::check_for_updates_async().await {
@@ -252,6 +906,35 @@ pub async fn check_for_updates() -> Result<Option<UpdateInfo>, String> {
     }
 }
 
+#[tauri::command]
+pub fn get_proxy_settings(state: State<AppState>) -> CommandResult<ProxySettingsInfo> {
+    Ok(ProxySettings::load(state.get_platform()).info())
+}
+
+/// Sets the proxy for update checks and downloads; no URL goes back to the
+/// system proxy. A `password` of `None` keeps the saved one.
+#[tauri::command]
+pub fn set_proxy_settings(
+    state: State<AppState>,
+    url: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+) -> CommandResult<ProxySettingsInfo> {
+    let platform = state.get_platform();
+    let non_empty = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+    let url = non_empty(url);
+    let username = non_empty(username).filter(|_| url.is_some());
+    let password = match password {
+        Some(password) => Some(password).filter(|p| !p.is_empty()),
+        None => ProxySettings::load(platform).password,
+    }
+    .filter(|_| username.is_some());
+    let settings = ProxySettings { url, username, password };
+    http::set_proxy(settings.clone()).map_err(|e| CommandError::invalid_input(e.to_string()))?;
+    settings.save(platform)?;
+    Ok(settings.info())
+}
+
 #[tauri::command]
 pub fn restart_app(app_handle: tauri::AppHandle) -> Result<(), String> {
     app_handle.restart();
@@ -264,147 +947,209 @@ pub fn quit_app(app_handle: tauri::AppHandle) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub fn open_keyboards_folder(_state: State<AppState>) -> Result<(), String> {
-    // This would need access to the platform instance to get keyboards_dir
-    // For now, we'll use a placeholder
-    #[cfg(target_os = "windows")]
-    {
-        std::process::Command::new("explorer")
-            .arg("C:\\ProgramData\\KeyMagic\\Keyboards")
-            .spawn()
-            .map_err(|e| e.to_string())?;
-    }
-    
-    #[cfg(target_os = "linux")]
-    {
-        std::process::Command::new("xdg-open")
-            .arg("/usr/share/keymagic/keyboards")
-            .spawn()
-            .map_err(|e| e.to_string())?;
-    }
-    
-    #[cfg(target_os = "macos")]
-    {
-        std::process::Command::new("open")
-            .arg("/Library/Application Support/KeyMagic/Keyboards")
-            .spawn()
-            .map_err(|e| e.to_string())?;
-    }
-    
-    Ok(())
+pub fn open_keyboards_folder(state: State<AppState>) -> Result<(), RevealError> {
+    file_manager::open_folder(&state.get_platform().get_keyboards_dir())
+}
+
+#[tauri::command]
+pub fn reveal_keyboard_file(state: State<AppState>, keyboard_id: String) -> Result<(), RevealError> {
+    let keyboard = state
+        .get_keyboard(&keyboard_id)
+        .ok_or(RevealError::KeyboardNotFound { keyboard_id })?;
+    file_manager::reveal_file(&keyboard.path)
 }
 
 #[tauri::command]
-pub fn get_composition_mode_hosts(state: State<AppState>) -> Result<Vec<String>, String> {
-    let config = state.get_config();
-    Ok(config.composition_mode.enabled_hosts.clone())
+pub fn get_composition_mode_hosts(state: State<AppState>) -> CommandResult<Vec<String>> {
+    Ok(state.mode_hosts(HostMode::Composition))
 }
 
 #[tauri::command]
 pub fn set_composition_mode_hosts(
     state: State<AppState>,
     hosts: Vec<String>,
-) -> Result<(), String> {
-    let mut config = state.get_config();
-    config.composition_mode.enabled_hosts = hosts;
-    state.save_config(&config).map_err(|e| e.to_string())
+) -> CommandResult<()> {
+    Ok(state.set_mode_hosts(HostMode::Composition, hosts)?)
+}
+
+#[tauri::command]
+pub fn list_profiles(state: State<AppState>) -> CommandResult<Vec<ProfileInfo>> {
+    Ok(state.list_profiles()?)
+}
+
+/// Saves the current keyboards, host lists and HUD settings under `name`
+#[tauri::command]
+pub fn save_current_as_profile(state: State<AppState>, name: String) -> CommandResult<ProfileOverrides> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(CommandError::invalid_input("Profile name cannot be empty"));
+    }
+    Ok(state.save_current_as_profile(name)?)
+}
+
+/// Applies a profile; the UI reloads everything on `profile_applied`
+#[tauri::command]
+pub fn apply_profile(app: AppHandle, state: State<AppState>, name: String) -> CommandResult<ProfileApplied> {
+    let applied = state.apply_profile(&name)?;
+    let _ = app.emit("profile_applied", &applied);
+    Ok(applied)
+}
+
+#[tauri::command]
+pub fn delete_profile(state: State<AppState>, name: String) -> CommandResult<()> {
+    Ok(state.delete_profile(&name)?)
 }
 
 
 
 // Version info
+/// Starts recording input events from all applications for a bug report;
+/// `verbose` also records the start of the composing text
 #[tauri::command]
-pub fn get_app_version() -> Result<String, String> {
-    Ok(env!("CARGO_PKG_VERSION").to_string())
+pub fn start_input_recording(
+    recording: State<InputRecording>,
+    verbose: Option<bool>,
+) -> CommandResult<RecordingStatus> {
+    recording.start(verbose.unwrap_or(false)).map_err(CommandError::from)
 }
 
-// Import wizard commands
 #[tauri::command]
-pub fn should_scan_bundled_keyboards(state: State<AppState>) -> Result<bool, String> {
-    // Check if we need to scan bundled keyboards based on version
-    let current_version = env!("CARGO_PKG_VERSION");
-    
-    let config = state.get_platform()
-        .load_config()
-        .map_err(|e| e.to_string())?;
-    
-    match &config.general.last_scanned_version {
-        Some(last_version) => {
-            // Use the compare_versions function from platform module
-            Ok(crate::platform::compare_versions(current_version, last_version))
-        }
-        None => {
-            // First run, should scan
-            Ok(true)
-        }
-    }
+pub fn stop_input_recording(recording: State<InputRecording>) -> CommandResult<RecordingStatus> {
+    recording.stop().map_err(CommandError::from)
+}
+
+#[tauri::command]
+pub fn get_input_recording_status(recording: State<InputRecording>) -> CommandResult<Option<RecordingStatus>> {
+    Ok(recording.status())
 }
 
+/// Writes the recorded events to `path` as JSON and returns how many were written
 #[tauri::command]
-pub fn get_bundled_keyboards(state: State<AppState>) -> Result<Vec<BundledKeyboard>, String> {
+pub fn export_input_recording(recording: State<InputRecording>, path: String) -> CommandResult<usize> {
+    recording
+        .export(&PathBuf::from(path), env!("CARGO_PKG_VERSION"))
+        .map_err(CommandError::from)
+}
+
+/// Events per process in the input recording, most first, with how the
+/// engine handled them; lets users check the application they report was
+/// captured before exporting
+#[tauri::command]
+pub fn get_recording_process_summary(recording: State<InputRecording>) -> CommandResult<Vec<ProcessSummary>> {
+    Ok(recording.process_summary())
+}
+
+/// Bundles diagnostics for a problem report into a zip in the Downloads
+/// folder and returns its path. A part that cannot be collected is listed in
+/// the manifest's `errors` instead of failing the bundle; see `diagnostics`
+/// for what is redacted.
+#[tauri::command]
+pub fn create_diagnostic_bundle(
+    app: AppHandle,
+    state: State<AppState>,
+    recording: State<InputRecording>,
+    latency: State<Arc<InputLatencyMonitor>>,
+    include_keyboard: bool,
+    include_logs: bool,
+    reveal_process_names: Option<bool>,
+) -> CommandResult<PathBuf> {
     let platform = state.get_platform();
-    let bundled_path = match platform.get_bundled_keyboards_path() {
-        Some(path) => path,
-        None => return Ok(vec![]),
+    let options = BundleOptions {
+        include_keyboard,
+        include_logs,
+        reveal_process_names: reveal_process_names.unwrap_or(false),
     };
-    
-    let mut bundled_keyboards = Vec::new();
-    let installed_keyboards = state.get_keyboards();
-    
-    if bundled_path.exists() {
-        match std::fs::read_dir(&bundled_path) {
-            Ok(entries) => {
-                for entry in entries.filter_map(Result::ok) {
-                    let path = entry.path();
-                    if path.extension().and_then(|s| s.to_str()) == Some("km2") {
-                        let id = path.file_stem()
-                            .and_then(|s| s.to_str())
-                            .unwrap_or("unknown")
-                            .to_string();
-                        
-                        // Try to load the keyboard to get proper name, icon, and check for updates
-                        let (name, icon_data, bundled_hash) = match state.load_keyboard_file(&path) {
-                            Ok(layout) => {
-                                let metadata = layout.metadata();
-                                let name = metadata.name().unwrap_or(id.clone());
-                                let icon_data = metadata.icon().map(|data| data.to_vec());
-                                let hash = state.calculate_file_hash(&path).unwrap_or_default();
-                                (name, icon_data, hash)
-                            }
-                            Err(_) => (id.clone(), None, String::new())
-                        };
-                        
-                        // Check if already installed and compare hashes
-                        let status = if let Some(installed) = installed_keyboards.iter().find(|k| k.name == name) {
-                            if bundled_hash.is_empty() {
-                                "Installed"  // Can't compare, assume installed
-                            } else if installed.hash == bundled_hash {
-                                "Unchanged"  // Same hash, up to date
-                            } else {
-                                // Check if the installed file was modified after installation
-                                // For now, just mark as "Updated" (in real implementation, 
-                                // we'd check if user modified the file)
-                                "Updated"  // Hash mismatch means bundled version is newer
-                            }
-                        } else {
-                            "New"
-                        }.to_string();
-                        
-                        bundled_keyboards.push(BundledKeyboard {
-                            id: id.clone(),
-                            name,
-                            status,
-                            icon_data,
-                            bundled_path: path.to_string_lossy().to_string(),
-                        });
-                    }
-                }
-            }
-            Err(_) => {}
+    let home = dirs::home_dir();
+    let home = home.as_deref();
+    let app_version = Version::current().to_string();
+    let system = SystemInfo {
+        app_version: app_version.clone(),
+        core_version: keymagic_core::VERSION.to_string(),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        os_version: platform.os_version(),
+        features: platform.get_platform_info().features,
+    };
+    let mut bundle = DiagnosticBundle::new(system, options);
+
+    bundle.collect("keyboard", || {
+        let Some(id) = state.get_active_keyboard() else {
+            return Ok(Vec::new());
+        };
+        let keyboard = state.get_keyboard(&id).ok_or_else(|| KeyboardNotFound(id.clone()))?;
+        let km2 = state.load_keyboard(&keyboard)?;
+        let mut files = diagnostics::json_file("keyboard.json", &diagnostics::keyboard_summary(&keyboard, &km2))?;
+        if include_keyboard {
+            files.push((format!("keyboard/{}", keyboard.filename), state.keyboard_data(&keyboard)?));
         }
+        Ok(files)
+    });
+    bundle.collect("config", || {
+        let config = platform.load_config()?;
+        diagnostics::json_file("config.json", &diagnostics::redact_config(&config, home, options.reveal_process_names)?)
+    });
+    bundle.collect("keyboard_store", || diagnostics::json_file("keyboard_store.json", &state.store_discrepancies()?));
+    bundle.collect("install", || {
+        diagnostics::json_file("install.json", &diagnostics::install_report(&install::verify_install(Ok(platform)), home)?)
+    });
+    bundle.collect("heartbeat", || diagnostics::json_file("heartbeat.json", &state.heartbeat_report()?));
+    bundle.collect("char_suppression", || {
+        diagnostics::json_file("char_suppression.json", &state.char_suppression_stats()?)
+    });
+    bundle.collect("input_latency", || diagnostics::json_file("input_latency.json", &latency.stats()));
+    bundle.collect("hosts", || {
+        let loads = read_keyboard_loads()?;
+        diagnostics::json_file("hosts.json", &diagnostics::host_loads(&loads, &state.get_keyboards(), options.reveal_process_names))
+    });
+    if include_logs {
+        bundle.collect("logs", || diagnostics::collect_logs(&app.path().app_log_dir()?, home));
     }
-    
-    Ok(bundled_keyboards)
+    bundle.collect("input_recording", || {
+        Ok(recording
+            .last_recording_json(&app_version, options.reveal_process_names)?
+            .map(|json| vec![("input_recording.json".to_string(), json.into_bytes())])
+            .unwrap_or_default())
+    });
+
+    let created_at_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let path = diagnostics::bundle_path(dirs::download_dir(), &platform.get_data_dir(), created_at_ms);
+    bundle
+        .write_zip(&path, created_at_ms)
+        .map_err(|e| CommandError::from(e).context("Failed to write the diagnostic bundle"))?;
+    log::info!("Wrote diagnostic bundle {} ({} collector errors)", path.display(), bundle.errors().len());
+    Ok(path)
+}
+
+/// What each text service host last loaded, from the load log they share
+#[cfg(target_os = "windows")]
+fn read_keyboard_loads() -> anyhow::Result<Vec<keymagic_core::load_log::KeyboardLoad>> {
+    let log = keymagic_core::load_log::LoadLog::create_shared()
+        .map_err(|e| anyhow::anyhow!("Load log unavailable: {}", e))?;
+    Ok(log.latest_by_host())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn read_keyboard_loads() -> anyhow::Result<Vec<keymagic_core::load_log::KeyboardLoad>> {
+    Err(anyhow::anyhow!("Hosts do not report keyboard loads on this platform"))
+}
+
+#[tauri::command]
+pub fn get_app_version() -> Result<String, String> {
+    Ok(env!("CARGO_PKG_VERSION").to_string())
+}
+
+// Import wizard commands
+#[tauri::command]
+pub fn should_scan_bundled_keyboards(state: State<AppState>) -> CommandResult<bool> {
+    Ok(state.should_scan_bundled_keyboards()?)
+}
+
+#[tauri::command]
+pub fn get_bundled_keyboards(state: State<AppState>) -> CommandResult<Vec<BundledKeyboard>> {
+    Ok(state.get_bundled_keyboards()?)
 }
 
 #[tauri::command]
@@ -412,115 +1157,125 @@ pub fn import_bundled_keyboard(
     state: State<AppState>,
     bundled_path: String,
     keyboard_status: String,
-    app_handle: tauri::AppHandle,
-) -> Result<KeyboardInfo, String> {
-    let keyboard_file = std::path::PathBuf::from(&bundled_path);
+) -> CommandResult<KeyboardInfo> {
+    let keyboard_file = PathBuf::from(&bundled_path);
     if !keyboard_file.exists() {
-        return Err(format!("Bundled keyboard file not found: {}", bundled_path));
-    }
-    
-    // Check if this is an update (keyboard with same name already exists)
-    if keyboard_status == "Updated" {
-        // First, read the bundled keyboard to get its name
-        match state.load_keyboard_file(&keyboard_file) {
-            Ok(layout) => {
-                let metadata = layout.metadata();
-                let keyboard_id = keyboard_file.file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("unknown")
-                    .to_string();
-                let name = metadata.name().unwrap_or(keyboard_id);
-                
-                // Find existing keyboard with the same name
-                if let Some(existing_keyboard) = state.get_keyboard_by_name(&name) {
-                    // Remove the old keyboard
-                    state.remove_keyboard(&existing_keyboard.id)
-                        .map_err(|e| format!("Failed to remove old keyboard: {}", e))?;
-                }
-            }
-            Err(e) => {
-                return Err(format!("Failed to read bundled keyboard: {}", e));
-            }
-        }
+        return Err(CommandError::not_found(format!("Bundled keyboard file not found: {}", bundled_path)));
     }
-    
-    // Import the new/updated keyboard
-    let keyboard_info = state.import_keyboard(&keyboard_file)
-        .map_err(|e| format!("Failed to import keyboard: {}", e))?;
-    
-    
-    
-    Ok(keyboard_info)
+    Ok(state.import_bundled_keyboard(&keyboard_file, keyboard_status == "Updated")?)
 }
 
 #[tauri::command]
-pub fn mark_bundled_keyboards_scanned(state: State<AppState>) -> Result<(), String> {
-    // Update the last scanned version to current version
-    let mut config = state.get_platform()
-        .load_config()
-        .map_err(|e| e.to_string())?;
-    
-    config.general.last_scanned_version = Some(env!("CARGO_PKG_VERSION").to_string());
-    
-    state.get_platform()
-        .save_config(&config)
-        .map_err(|e| e.to_string())
+pub fn mark_bundled_keyboards_scanned(state: State<AppState>) -> CommandResult<()> {
+    Ok(state.mark_bundled_keyboards_scanned()?)
+}
+
+/// Layouts left by a KeyMagic 2 install, and whether migration was offered
+#[tauri::command]
+pub fn detect_legacy_keymagic(state: State<AppState>) -> CommandResult<LegacyDetection> {
+    Ok(legacy_keymagic::detect(&state, &legacy_keymagic::system_locations())?)
+}
+
+/// Imports the selected KeyMagic 2 layouts; an empty selection declines.
+/// Either way migration is not offered again.
+#[tauri::command]
+pub fn migrate_legacy_keyboards(
+    app: AppHandle,
+    state: State<AppState>,
+    selection: MigrationSelection,
+) -> CommandResult<MigrationReport> {
+    let report = legacy_keymagic::migrate(&state, &legacy_keymagic::system_locations(), &selection)?;
+    if let Some(keyboard_id) = &report.activated {
+        let _ = app.emit("active_keyboard_changed", keyboard_id);
+    }
+    Ok(report)
 }
 
 // Settings commands
 #[tauri::command]
-pub fn get_setting(state: State<AppState>, key: String) -> Result<String, String> {
+pub fn get_setting(state: State<AppState>, key: String) -> CommandResult<String> {
     Ok(state.get_platform()
-        .get_setting(&key)
-        .map_err(|e| e.to_string())?
+        .get_setting(&key)?
         .unwrap_or_else(|| "".to_string()))
 }
 
 #[tauri::command]
-pub fn set_setting(state: State<AppState>, key: String, value: String) -> Result<(), String> {
+pub fn set_setting(state: State<AppState>, key: String, value: String) -> CommandResult<()> {
     state.get_platform()
         .set_setting(&key, &value)
-        .map_err(|e| e.to_string())
+        .map_err(CommandError::from)
 }
 
 // Update reminder commands
 #[tauri::command]
-pub fn get_update_remind_after(state: State<AppState>) -> Result<Option<String>, String> {
+pub fn get_update_remind_after(state: State<AppState>) -> CommandResult<Option<String>> {
     let config = state.get_platform()
-        .load_config()
-        .map_err(|e| e.to_string())?;
+        .load_config()?;
     Ok(config.general.update_remind_after)
 }
 
 #[tauri::command]
-pub fn set_update_remind_after(state: State<AppState>, value: Option<String>) -> Result<(), String> {
+pub fn set_update_remind_after(state: State<AppState>, value: Option<String>) -> CommandResult<()> {
     let mut config = state.get_platform()
-        .load_config()
-        .map_err(|e| e.to_string())?;
+        .load_config()?;
     config.general.update_remind_after = value;
     state.get_platform()
         .save_config(&config)
-        .map_err(|e| e.to_string())
+        .map_err(CommandError::from)
+}
+
+// Privileged actions: each need has its own command with checked parameters
+#[tauri::command]
+pub fn open_url(app: AppHandle, url: String) -> CommandResult<()> {
+    use tauri_plugin_opener::OpenerExt;
+
+    let url = privileged::validate_url(&url)?;
+    app.opener()
+        .open_url(url.as_str(), None::<&str>)
+        .map_err(|e| CommandError::new(ErrorCode::IoError, e.to_string()).context("Failed to open URL"))
 }
 
-// Process management (Windows-specific, but we'll make it work cross-platform)
+/// Opens the Windows dialog for input language hotkeys
 #[tauri::command]
-pub fn run_command(command: String, args: Vec<String>) -> Result<(), String> {
+pub fn open_input_settings() -> CommandResult<()> {
     #[cfg(target_os = "windows")]
     {
-        std::process::Command::new(command)
-            .args(args)
+        std::process::Command::new("rundll32.exe")
+            .args(["Shell32.dll,Control_RunDLL", "input.dll,,{C07337D3-DB2C-4D0B-9A93-B722A6C106E2}{HOTKEYS}"])
             .spawn()
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| CommandError::from(e).context("Failed to open input settings"))?;
         Ok(())
     }
-    
+
     #[cfg(not(target_os = "windows"))]
-    {
-        let _ = command;
-        let _ = args;
-        Err("This command is only available on Windows".to_string())
+    Err(CommandError::unsupported("This feature is only available on Windows"))
+}
+
+/// Downloads the installer of the latest release and verifies its checksum;
+/// returns the path to pass to `launch_installer`
+#[tauri::command]
+pub async fn download_update() -> CommandResult<String> {
+    let info = crate::updater::check_for_updates_async().await?;
+    if !info.update_available {
+        return Err(CommandError::new(ErrorCode::Conflict, "KeyMagic is up to date"));
     }
+    let path = crate::updater::download_installer(&info).await?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Runs an installer the updater downloaded and verified
+#[tauri::command]
+pub fn launch_installer(app: AppHandle, path: String) -> CommandResult<()> {
+    use tauri_plugin_opener::OpenerExt;
+
+    let installer = privileged::verify_installer(
+        std::path::Path::new(&path),
+        &crate::updater::installer_dir(),
+        crate::updater::verified_installer_sha256,
+    )?;
+    app.opener()
+        .open_path(installer.to_string_lossy(), None::<&str>)
+        .map_err(|e| CommandError::new(ErrorCode::IoError, e.to_string()).context("Failed to launch installer"))
 }
 
 // Composition mode host management
@@ -528,77 +1283,187 @@ pub fn run_command(command: String, args: Vec<String>) -> Result<(), String> {
 pub fn add_composition_mode_host(
     state: State<AppState>,
     host_name: String,
-) -> Result<(), String> {
-    let mut config = state.get_config();
-    
-    // Add host if not already in list
-    if !config.composition_mode.enabled_hosts.contains(&host_name) {
-        config.composition_mode.enabled_hosts.push(host_name);
-        state.save_config(&config).map_err(|e| e.to_string())?;
-    }
-    
-    Ok(())
+) -> CommandResult<()> {
+    Ok(state.add_mode_host(HostMode::Composition, &host_name)?)
 }
 
 #[tauri::command]
 pub fn remove_composition_mode_host(
     state: State<AppState>,
     host_name: String,
-) -> Result<(), String> {
-    let mut config = state.get_config();
-    
-    // Remove host from list
-    config.composition_mode.enabled_hosts.retain(|h| h != &host_name);
-    state.save_config(&config).map_err(|e| e.to_string())?;
-    
-    Ok(())
+) -> CommandResult<()> {
+    Ok(state.remove_mode_host(HostMode::Composition, &host_name)?)
+}
+
+/// Input mode of the last application used, as decided by its text service;
+/// `None` until one has been focused or where modes are not published.
+/// Changes are also emitted as `input_mode_changed`.
+#[tauri::command]
+pub fn get_effective_input_mode(monitor: State<Arc<InputModeMonitor>>) -> CommandResult<Option<InputModeInfo>> {
+    Ok(monitor.current())
 }
 
 // Direct mode host management
 #[tauri::command]
-pub fn get_direct_mode_hosts(state: State<AppState>) -> Result<Vec<String>, String> {
-    let config = state.get_config();
-    Ok(config.direct_mode.enabled_hosts.clone())
+pub fn get_direct_mode_hosts(state: State<AppState>) -> CommandResult<Vec<String>> {
+    Ok(state.mode_hosts(HostMode::Direct))
 }
 
 #[tauri::command]
 pub fn add_direct_mode_host(
     state: State<AppState>,
     host_name: String,
-) -> Result<(), String> {
-    let mut config = state.get_config();
-    
-    // Add host if not already in list
-    if !config.direct_mode.enabled_hosts.contains(&host_name) {
-        config.direct_mode.enabled_hosts.push(host_name);
-        state.save_config(&config).map_err(|e| e.to_string())?;
-    }
-    
-    Ok(())
+) -> CommandResult<()> {
+    Ok(state.add_mode_host(HostMode::Direct, &host_name)?)
 }
 
 #[tauri::command]
 pub fn remove_direct_mode_host(
     state: State<AppState>,
     host_name: String,
-) -> Result<(), String> {
-    let mut config = state.get_config();
-    
-    // Remove host from list
-    config.direct_mode.enabled_hosts.retain(|h| h != &host_name);
-    state.save_config(&config).map_err(|e| e.to_string())?;
-    
+) -> CommandResult<()> {
+    Ok(state.remove_mode_host(HostMode::Direct, &host_name)?)
+}
+
+// Shortcut passthrough key management
+#[tauri::command]
+pub fn get_shortcut_passthrough_keys(state: State<AppState>) -> CommandResult<BTreeMap<String, Vec<String>>> {
+    Ok(state.get_shortcut_passthrough_keys())
+}
+
+/// Sets the keys an application uses as single-key shortcuts; they reach it
+/// unprocessed while nothing is composed. Returns the saved key names.
+#[tauri::command]
+pub fn set_shortcut_passthrough_keys(
+    state: State<AppState>,
+    host_name: String,
+    keys: Vec<String>,
+) -> CommandResult<Vec<String>> {
+    if host_name.trim().is_empty() {
+        return Err(CommandError::invalid_input("Host name is empty"));
+    }
+    state
+        .set_shortcut_passthrough_keys(&host_name, &keys)
+        .map_err(|e| CommandError::from(e).context("Failed to set shortcut keys"))
+}
+
+#[tauri::command]
+pub fn remove_shortcut_passthrough_host(
+    state: State<AppState>,
+    host_name: String,
+) -> CommandResult<()> {
+    state.set_shortcut_passthrough_keys(&host_name, &[])?;
+    Ok(())
+}
+
+/// Switches made by `hud_test_burst`
+const HUD_TEST_SWITCHES: usize = 20;
+const HUD_TEST_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Debug builds: switches between the enabled keyboards 20 times in two
+/// seconds, ending on the active one, to check that switch notifications
+/// coalesce. Returns the keyboards switched to, in order.
+#[tauri::command]
+pub async fn hud_test_burst(app: AppHandle, state: State<'_, AppState>) -> CommandResult<Vec<String>> {
+    if !cfg!(debug_assertions) {
+        return Err(CommandError::unsupported("The HUD burst test is only in debug builds"));
+    }
+    let keyboards: Vec<String> = state
+        .get_keyboards()
+        .into_iter()
+        .filter(|kb| kb.enabled && !kb.status.is_blocked())
+        .map(|kb| kb.id)
+        .collect();
+    let original = state.get_active_keyboard().ok_or_else(|| CommandError::invalid_input("No keyboard is active"))?;
+    let Some(start) = keyboards.iter().position(|id| *id == original) else {
+        return Err(CommandError::invalid_input("The active keyboard is not enabled"));
+    };
+    if keyboards.len() < 2 {
+        return Err(CommandError::invalid_input("Enable at least two keyboards"));
+    }
+
+    // Cycle away from the active keyboard and come back to it last
+    let mut targets: Vec<String> = (1..HUD_TEST_SWITCHES).map(|i| keyboards[(start + i) % keyboards.len()].clone()).collect();
+    targets.push(original);
+
+    let manager = Arc::clone(&state);
+    let switched = targets.clone();
+    tauri::async_runtime::spawn_blocking(move || -> anyhow::Result<()> {
+        for (i, keyboard_id) in switched.iter().enumerate() {
+            if i > 0 {
+                std::thread::sleep(HUD_TEST_INTERVAL);
+            }
+            manager.set_active_keyboard(keyboard_id)?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| CommandError::new(ErrorCode::Internal, format!("HUD burst test failed: {}", e)))??;
+
+    if let Some(last) = targets.last() {
+        let _ = app.emit("active_keyboard_changed", last);
+    }
+    Ok(targets)
+}
+
+// Character filter hosts, see keymagic_core::char_suppression
+#[tauri::command]
+pub fn get_char_suppression_hosts(state: State<AppState>) -> CommandResult<Vec<String>> {
+    Ok(state.char_suppression_hosts())
+}
+
+/// Sets the applications whose stray characters of eaten keys the text
+/// service drops, for frameworks that type each character twice. Returns
+/// the saved list.
+#[tauri::command]
+pub fn set_char_suppression_hosts(state: State<AppState>, hosts: Vec<String>) -> CommandResult<Vec<String>> {
+    state
+        .set_char_suppression_hosts(&hosts)
+        .map_err(|e| CommandError::from(e).context("Failed to set the character filter hosts"))
+}
+
+/// Language rules and whether key processing follows the input language of
+/// the focused window. Changes made by the rules are emitted as
+/// `language_activation_applied`.
+#[tauri::command]
+pub fn get_language_activation(state: State<AppState>) -> CommandResult<LanguageActivationConfig> {
+    Ok(state.language_activation()?)
+}
+
+/// Turns language activation on or off; only Windows reports input languages
+#[tauri::command]
+pub fn set_language_activation_enabled(state: State<AppState>, enabled: bool) -> CommandResult<()> {
+    if enabled && cfg!(not(target_os = "windows")) {
+        return Err(CommandError::unsupported("Language activation is only available on Windows"));
+    }
+    state.set_language_activation_enabled(enabled)?;
+    #[cfg(target_os = "windows")]
+    crate::input_language::reapply();
+    Ok(())
+}
+
+/// Replaces the language rules, keyed by LANGID ("0409"), locale name
+/// ("en-US") or language ("en")
+#[tauri::command]
+pub fn set_language_activation_rules(
+    state: State<AppState>,
+    rules: BTreeMap<String, LanguageAction>,
+) -> CommandResult<()> {
+    state.set_language_activation_rules(rules)
+        .map_err(|e| CommandError::from(e).context("Failed to save language rules"))?;
+    #[cfg(target_os = "windows")]
+    crate::input_language::reapply();
     Ok(())
 }
 
 // Language profile commands (Windows-specific features)
 #[tauri::command]
-pub fn get_supported_languages(_state: State<AppState>) -> Result<Vec<(String, String)>, String> {
+pub fn get_supported_languages(_state: State<AppState>) -> CommandResult<Vec<(String, String)>> {
     #[cfg(target_os = "windows")]
     {
         Ok(crate::windows_languages::get_all_languages())
     }
-    
+
     #[cfg(not(target_os = "windows"))]
     {
         // Fallback for non-Windows platforms
@@ -622,25 +1487,25 @@ pub fn get_supported_languages(_state: State<AppState>) -> Result<Vec<(String, S
 
 #[tauri::command]
 #[allow(unused_variables)]
-pub fn get_enabled_languages(state: State<AppState>) -> Result<Vec<String>, String> {
+pub fn get_enabled_languages(state: State<AppState>) -> CommandResult<Vec<String>> {
     #[cfg(target_os = "windows")]
     {
         state.get_platform()
             .get_enabled_languages()
-            .map_err(|e| e.to_string())
+            .map_err(CommandError::from)
     }
-    
+
     #[cfg(not(target_os = "windows"))]
     Ok(vec!["en-US".to_string()])
 }
 
 #[tauri::command]
-pub fn search_languages(_state: State<AppState>, query: String) -> Result<Vec<(String, String)>, String> {
+pub fn search_languages(_state: State<AppState>, query: String) -> CommandResult<Vec<(String, String)>> {
     #[cfg(target_os = "windows")]
     {
         Ok(crate::windows_languages::search_languages(&query))
     }
-    
+
     #[cfg(not(target_os = "windows"))]
     {
         // For non-Windows, just filter the default list
@@ -660,43 +1525,43 @@ pub fn search_languages(_state: State<AppState>, query: String) -> Result<Vec<(S
 pub fn set_enabled_languages(
     state: State<AppState>,
     languages: Vec<String>,
-) -> Result<(), String> {
+) -> CommandResult<()> {
     #[cfg(target_os = "windows")]
     {
         // First update platform storage
         state.get_platform()
-            .set_enabled_languages(&languages)
-            .map_err(|e| e.to_string())?;
+            .set_enabled_languages(&languages)?;
         
         // Try to update TSF language profiles directly first
         match crate::language_profiles::update_language_profiles(&languages) {
             Ok(_) => Ok(()),
             Err(e) => {
-                // If it fails (likely due to permissions), return a special error
-                Err(format!("ELEVATION_REQUIRED: {}", e))
+                // If it fails (likely due to permissions), the frontend retries elevated
+                Err(CommandError::new(ErrorCode::ElevationRequired, format!("{:#}", e)))
             }
         }
     }
-    
+
     #[cfg(not(target_os = "windows"))]
     Ok(())
 }
 
+/// Relaunches KeyMagic elevated to do `action`, waiting for it to finish
 #[tauri::command]
-pub fn apply_language_changes_elevated(
-    _state: State<AppState>,
-    languages: Vec<String>,
-) -> Result<(), String> {
+pub fn restart_elevated(action: ElevatedAction) -> CommandResult<()> {
+    action.validate()?;
+
     #[cfg(target_os = "windows")]
     {
         use std::env;
         
         // Get the path to our own executable
         let exe_path = env::current_exe()
-            .map_err(|e| format!("Failed to get executable path: {}", e))?;
+            .map_err(|e| CommandError::from(e).context("Failed to get executable path"))?;
         
-        // Join languages with commas
-        let languages_str = languages.join(",");
+        // PowerShell single-quoted strings only need quotes doubled
+        let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+        let args: Vec<String> = action.args().iter().map(|arg| quote(arg)).collect();
         
         // Launch elevated process with hidden window
         use std::os::windows::process::CommandExt;
@@ -704,36 +1569,33 @@ pub fn apply_language_changes_elevated(
         const CREATE_NO_WINDOW: u32 = 0x08000000;
         
         let output = Command::new("powershell")
-            .args(&[
+            .args([
                 "-WindowStyle", "Hidden",
                 "-Command",
                 &format!(
-                    "Start-Process '{}' -ArgumentList '--update-languages','{}' -Verb RunAs -Wait -WindowStyle Hidden",
-                    exe_path.display(),
-                    languages_str
+                    "Start-Process {} -ArgumentList {} -Verb RunAs -Wait -WindowStyle Hidden",
+                    quote(&exe_path.to_string_lossy()),
+                    args.join(",")
                 ),
             ])
             .creation_flags(CREATE_NO_WINDOW)
             .output()
-            .map_err(|e| format!("Failed to launch elevated process: {}", e))?;
+            .map_err(|e| CommandError::from(e).context("Failed to launch elevated process"))?;
         
         if output.status.success() {
             Ok(())
         } else {
             let stderr = String::from_utf8_lossy(&output.stderr);
             if stderr.contains("canceled") {
-                Err("ELEVATION_CANCELLED".to_string())
+                Err(CommandError::new(ErrorCode::ElevationCancelled, "The elevation prompt was cancelled"))
             } else {
-                Err(format!("Failed to apply language changes: {}", stderr))
+                Err(CommandError::new(ErrorCode::IoError, format!("The elevated process failed: {}", stderr)))
             }
         }
     }
-    
+
     #[cfg(not(target_os = "windows"))]
-    {
-        let _ = languages;
-        Err("This feature is only available on Windows".to_string())
-    }
+    Err(CommandError::unsupported("This feature is only available on Windows"))
 }
 
 // Update checking - legacy alias for check_for_updates
@@ -743,113 +1605,258 @@ pub async fn check_for_update() -> Result<Option<UpdateInfo>, String> {
 }
 
 // KMS to KM2 converter commands
+
+/// Converts a KMS file; failed `@test` lines are logged as warnings unless
+/// `skip_tests` is set
 #[tauri::command]
 pub fn convert_kms_to_km2(
     input_path: String,
     output_path: String,
-) -> Result<(), String> {
+    skip_tests: Option<bool>,
+) -> CommandResult<()> {
     let input = std::path::PathBuf::from(&input_path);
     let output = std::path::PathBuf::from(&output_path);
-    
+
     // Ensure input file exists
     if !input.exists() {
-        return Err(format!("Input file not found: {}", input_path));
+        return Err(CommandError::not_found(format!("Input file not found: {}", input_path)));
     }
-    
+
     // Ensure it's a file, not a directory
     if input.is_dir() {
-        return Err(format!("Input path is a directory, not a file: {}", input_path));
+        return Err(CommandError::invalid_input(format!("Input path is a directory, not a file: {}", input_path)));
     }
-    
+
     // Ensure input has .kms extension
     if input.extension().and_then(|s| s.to_str()) != Some("kms") {
-        return Err("Input file must have .kms extension".to_string());
+        return Err(CommandError::invalid_input("Input file must have .kms extension"));
     }
-    
+
     // Convert using kms2km2 crate
-    kms2km2::convert_kms_to_km2(&input, &output)
-        .map_err(|e| format!("Conversion failed: {}", e))
+    let options = kms2km2::CompileOptions { skip_tests: skip_tests.unwrap_or(false), ..Default::default() };
+    let (km2, warnings) = kms2km2::compile_kms_file_with_options(&input, options)
+        .map_err(|e| CommandError::from(e).context("Conversion failed"))?;
+    write_km2_file(&km2, &output)
+        .map_err(|e| CommandError::from(e).context("Conversion failed"))?;
+    for warning in warnings {
+        log::warn!("{}: {}", input_path, warning);
+    }
+    Ok(())
+}
+
+/// Writes a keyboard in one step, so a failed conversion never leaves half
+/// a file where the user's keyboard was
+fn write_km2_file(km2: &keymagic_core::Km2File, path: &Path) -> anyhow::Result<()> {
+    let mut data = Vec::new();
+    kms2km2::binary::Km2Writer::new(&mut data).write_km2_file(km2)?;
+    temp_file::write_atomic(path, data)?;
+    Ok(())
+}
+
+/// Writes a KM2 file as JSON for the web-based layout editor
+#[tauri::command]
+pub fn export_km2_json(
+    input_path: String,
+    output_path: String,
+) -> CommandResult<()> {
+    let data = std::fs::read(&input_path)
+        .map_err(|e| CommandError::from(e).context("Failed to read keyboard file"))?;
+    let km2 = keymagic_core::km2::Km2Loader::load(&data)
+        .map_err(|e| CommandError::from(e).context("Failed to load keyboard file"))?;
+    temp_file::write_atomic(Path::new(&output_path), km2.to_json())
+        .map_err(|e| CommandError::from(e).context("Failed to write JSON file"))
+}
+
+/// Writes a KM2 file from JSON exported with `export_km2_json`
+#[tauri::command]
+pub fn import_km2_json(
+    input_path: String,
+    output_path: String,
+) -> CommandResult<()> {
+    let json = std::fs::read_to_string(&input_path)
+        .map_err(|e| CommandError::from(e).context("Failed to read JSON file"))?;
+    let km2 = keymagic_core::Km2File::from_json(&json)
+        .map_err(|e| CommandError::from(e).context("Invalid keyboard JSON"))?;
+    write_km2_file(&km2, Path::new(&output_path))
+        .map_err(|e| CommandError::from(e).context("Failed to write keyboard file"))
+}
+
+/// A construct `convert_kmn_file` left out or approximated
+#[derive(Debug, Serialize)]
+pub struct KmnConversionIssue {
+    pub line: usize,
+    /// "skipped" or "approximated"
+    pub kind: &'static str,
+    pub construct: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KmnConversionResult {
+    pub rules_converted: usize,
+    pub rules_skipped: usize,
+    pub issues: Vec<KmnConversionIssue>,
+}
+
+/// Converts a Keyman source (.kmn) to KMS and reports what did not convert
+#[tauri::command]
+pub fn convert_kmn_file(
+    input_path: String,
+    output_path: String,
+) -> CommandResult<KmnConversionResult> {
+    use kms2km2::kmn::IssueKind;
+
+    let report = kms2km2::kmn::convert_kmn_file(std::path::Path::new(&input_path), std::path::Path::new(&output_path))
+        .map_err(|e| CommandError::from(e).context("Conversion failed"))?;
+    let issues = report
+        .issues
+        .into_iter()
+        .map(|issue| KmnConversionIssue {
+            line: issue.line,
+            kind: match issue.kind {
+                IssueKind::Skipped => "skipped",
+                IssueKind::Approximated => "approximated",
+            },
+            construct: issue.construct,
+            message: issue.message,
+        })
+        .collect();
+    Ok(KmnConversionResult {
+        rules_converted: report.rules_converted,
+        rules_skipped: report.rules_skipped,
+        issues,
+    })
 }
 
+/// Compiles a KMS file and describes it, with a line per warning; failed
+/// `@test` lines are warnings too unless `skip_tests` is set
 #[tauri::command]
 pub fn validate_kms_file(
     file_path: String,
-) -> Result<String, String> {
+    skip_tests: Option<bool>,
+) -> CommandResult<String> {
     use std::fs;
-    
+
     let path = std::path::PathBuf::from(&file_path);
-    
+
     // Debug: Log the path
     log::info!("Validating KMS file at path: {:?}", path);
-    
+
     // Ensure file exists
     if !path.exists() {
-        return Err(format!("File not found: {}", file_path));
+        return Err(CommandError::not_found(format!("File not found: {}", file_path)));
     }
-    
+
     // Get file metadata
     let metadata = match fs::metadata(&path) {
         Ok(m) => m,
-        Err(e) => return Err(format!("Failed to get file metadata: {}", e))
+        Err(e) => return Err(CommandError::from(e).context("Failed to get file metadata"))
     };
-    
+
     // Log file type
     log::info!("File type - is_file: {}, is_dir: {}, is_symlink: {}", 
         metadata.is_file(), 
         metadata.is_dir(), 
         metadata.file_type().is_symlink()
     );
-    
+
     // Ensure it's a file, not a directory
     if metadata.is_dir() {
-        return Err(format!("Path is a directory, not a file: {}", file_path));
+        return Err(CommandError::invalid_input(format!("Path is a directory, not a file: {}", file_path)));
     }
-    
+
     // Ensure it has .kms extension
     if path.extension().and_then(|s| s.to_str()) != Some("kms") {
-        return Err("File must have .kms extension".to_string());
+        return Err(CommandError::invalid_input("File must have .kms extension"));
     }
-    
+
     // Try to read the file content first to debug
     match fs::read_to_string(&path) {
         Ok(content) => {
             log::info!("Successfully read file, content length: {} bytes", content.len());
         }
         Err(e) => {
-            return Err(format!("Failed to read file: {} (os error: {:?})", e, e.raw_os_error()));
+            return Err(CommandError::from(e).context("Failed to read file"));
         }
     }
-    
+
     // Try to compile the KMS file to validate it
-    match kms2km2::compile_kms_file(&path) {
-        Ok(km2_file) => {
+    let options = kms2km2::CompileOptions { skip_tests: skip_tests.unwrap_or(false), ..Default::default() };
+    match kms2km2::compile_kms_file_with_options(&path, options) {
+        Ok((km2_file, warnings)) => {
             // Extract metadata for validation result
             let metadata = km2_file.metadata();
             let name = metadata.name().unwrap_or("Unnamed Keyboard".to_string());
             let description = metadata.description().unwrap_or("No description".to_string());
             
-            Ok(format!("Valid KMS file\nName: {}\nDescription: {}", name, description))
+            let mut result = format!("Valid KMS file\nName: {}\nDescription: {}", name, description);
+            for warning in warnings {
+                result.push_str(&format!("\nWarning: {}", warning));
+            }
+            Ok(result)
         }
-        Err(e) => Err(format!("Invalid KMS file: {}", e))
+        Err(e) => Err(CommandError::from(e).context("Invalid KMS file"))
     }
 }
 
+/// Compiles a KMS snippet in memory and types keys into it, for the rules
+/// playground. Compile errors come back as diagnostics in the result.
+#[tauri::command]
+pub fn test_kms_snippet(
+    kms_source: String,
+    key_events: Vec<KeyEventDto>,
+) -> CommandResult<SnippetResult> {
+    crate::core::kms_playground::run_snippet(&kms_source, &key_events)
+        .map_err(|e| CommandError::from(e).context("Failed to test snippet"))
+}
+
+/// Opens a preview session typing into a fresh engine for the keyboard.
+/// Keys go in with `send_preview_keys` or the `preview_keys` event, and
+/// each one's output comes back on `on_output` in the order typed.
+#[tauri::command]
+pub fn start_preview_session(
+    state: State<AppState>,
+    sessions: State<Arc<PreviewSessions>>,
+    keyboard_id: String,
+    on_output: tauri::ipc::Channel<PreviewOutput>,
+) -> CommandResult<String> {
+    let engine = state
+        .preview_engine(&keyboard_id)
+        .map_err(|e| CommandError::from(e).context("Failed to load keyboard for preview"))?;
+    let session_id = sessions
+        .start(engine, Box::new(move |output| {
+            if let Err(e) = on_output.send(output) {
+                log::debug!("Failed to send preview output: {}", e);
+            }
+        }))
+        .map_err(|e| CommandError::from(anyhow::Error::from(e)))?;
+    Ok(session_id)
+}
+
+/// Queues keys for a preview session behind those sent before
+#[tauri::command]
+pub fn send_preview_keys(
+    sessions: State<Arc<PreviewSessions>>,
+    session_id: String,
+    keys: Vec<KeyEventDto>,
+) -> CommandResult<()> {
+    sessions.send(&session_id, keys).map_err(|e| CommandError::from(anyhow::Error::from(e)))
+}
+
+/// Ends a preview session; false if it had already ended
+#[tauri::command]
+pub fn end_preview_session(sessions: State<Arc<PreviewSessions>>, session_id: String) -> CommandResult<bool> {
+    Ok(sessions.end(&session_id))
+}
+
 #[tauri::command]
 pub fn convert_kms_file(
     input_path: String,
     output_path: String,
-) -> Result<(), String> {
+    skip_tests: Option<bool>,
+) -> CommandResult<()> {
     // Use the existing convert_kms_to_km2 function
-    convert_kms_to_km2(input_path, output_path)
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BundledKeyboard {
-    pub id: String,
-    pub name: String,
-    pub status: String, // "New", "Updated", "Installed", "Unchanged", "Modified"
-    pub icon_data: Option<Vec<u8>>,
-    pub bundled_path: String,
+    convert_kms_to_km2(input_path, output_path, skip_tests)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -858,10 +1865,34 @@ pub struct AppInfo {
     pub identifier: String, // exe name on Windows, bundle ID on macOS
     pub icon_base64: Option<String>,
     pub is_running: bool,
+    /// Where to load the icon from when it is not in `icon_base64` yet
+    #[serde(skip)]
+    pub icon_source: Option<crate::app_enumerator::IconSource>,
+}
+
+/// Lists running apps for the app picker. Icons that are not cached yet are
+/// left out and sent as `app_icon_ready` events once loaded.
+#[tauri::command]
+pub fn get_running_apps(icons: State<'_, Arc<AppIconService>>) -> Result<Vec<AppInfo>, String> {
+    let mut apps = crate::app_enumerator::get_running_apps()
+        .map_err(|e| e.to_string())?;
+    for app in &mut apps {
+        if let Some(source) = app.icon_source.clone() {
+            app.icon_base64 = icons.request(&app.identifier, source);
+        }
+    }
+    Ok(apps)
 }
 
+/// Icon of an app listed by `get_running_apps`, for callers that don't wait
+/// for `app_icon_ready`
 #[tauri::command]
-pub fn get_running_apps() -> Result<Vec<AppInfo>, String> {
-    crate::app_enumerator::get_running_apps()
-        .map_err(|e| e.to_string())
+pub async fn get_app_icon(
+    icons: State<'_, Arc<AppIconService>>,
+    identifier: String,
+) -> CommandResult<Option<String>> {
+    let icons = Arc::clone(&icons);
+    tauri::async_runtime::spawn_blocking(move || icons.icon(&identifier))
+        .await
+        .map_err(|e| CommandError::new(ErrorCode::Internal, format!("Failed to load app icon: {}", e)))
 }
\ No newline at end of file
@@ -0,0 +1,383 @@
+//! Recently committed text, for inserting it again
+//!
+//! Text services report each commit to `keymagic_core::commit_log`; the GUI
+//! polls the ring and keeps the last few distinct strings, newest first. The
+//! history is kept in memory only unless the user opts in to keeping it
+//! across restarts. Reporting is switched off entirely when the history size
+//! is 0, and the tray can clear the history through the shared ring.
+
+use anyhow::{Context, Result};
+use keymagic_core::commit_log::CommitLog;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Setting with the number of strings kept; 0 turns the history off
+pub const SIZE_SETTING: &str = "commit_history_size";
+/// Setting that keeps the history across restarts when "true"
+pub const PERSIST_SETTING: &str = "commit_history_persist";
+
+pub const DEFAULT_SIZE: usize = 10;
+pub const MAX_SIZE: usize = 50;
+
+/// File in the data directory holding a kept history
+pub const HISTORY_FILE: &str = "commit_history.json";
+
+/// Size and persistence read from the settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryOptions {
+    pub size: usize,
+    pub persist: bool,
+}
+
+impl HistoryOptions {
+    /// Parses the stored values; missing or unreadable ones use the defaults
+    pub fn from_settings(size: Option<&str>, persist: Option<&str>) -> Self {
+        Self {
+            size: size
+                .and_then(|size| size.trim().parse().ok())
+                .map_or(DEFAULT_SIZE, |size: usize| size.min(MAX_SIZE)),
+            persist: persist == Some("true"),
+        }
+    }
+}
+
+/// Bounded list of distinct strings, newest first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommitHistory {
+    entries: VecDeque<String>,
+    capacity: usize,
+}
+
+impl CommitHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Adds a string at the front; one already in the history moves there.
+    /// Returns false when it was ignored (blank, or the history is off).
+    pub fn push(&mut self, text: &str) -> bool {
+        if self.capacity == 0 || text.trim().is_empty() {
+            return false;
+        }
+        self.entries.retain(|entry| entry != text);
+        self.entries.push_front(text.to_string());
+        self.entries.truncate(self.capacity);
+        true
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.iter().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Changes the bound, dropping the oldest entries that no longer fit
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.entries.truncate(capacity);
+    }
+}
+
+fn load(path: &Path) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).context("Failed to parse the kept commit history")
+}
+
+fn save(path: &Path, entries: &[String]) -> Result<()> {
+    let contents = serde_json::to_string(entries)?;
+    std::fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(target_os = "windows")]
+fn open_log() -> std::io::Result<CommitLog> {
+    CommitLog::create_shared()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn open_log() -> std::io::Result<CommitLog> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Committed text is only reported on Windows",
+    ))
+}
+
+struct MonitorState {
+    history: CommitHistory,
+    /// Last commit taken from the ring
+    last_seq: u64,
+    clear_generation: u32,
+    /// Where the history is kept, when the user opted in
+    persist_path: Option<PathBuf>,
+}
+
+impl MonitorState {
+    fn save(&self) {
+        if let Some(path) = &self.persist_path {
+            if let Err(e) = save(path, &self.history.entries()) {
+                log::warn!("Failed to keep the commit history: {:#}", e);
+            }
+        }
+    }
+}
+
+/// The GUI's history of committed text, fed from the shared ring
+pub struct CommitHistoryMonitor {
+    log: Option<CommitLog>,
+    /// Where a kept history lives, whether or not keeping is on
+    history_file: PathBuf,
+    state: Mutex<MonitorState>,
+}
+
+impl CommitHistoryMonitor {
+    /// Attaches to the shared ring; without one the history stays empty
+    pub fn new(options: HistoryOptions, data_dir: &Path) -> Self {
+        let log = open_log()
+            .map_err(|e| log::debug!("Commit history unavailable: {}", e))
+            .ok();
+        Self::with_log(log, options, data_dir.join(HISTORY_FILE))
+    }
+
+    fn with_log(log: Option<CommitLog>, options: HistoryOptions, history_file: PathBuf) -> Self {
+        let monitor = Self {
+            // Commits made before the GUI started are not picked up
+            state: Mutex::new(MonitorState {
+                history: CommitHistory::new(0),
+                last_seq: log.as_ref().map_or(0, CommitLog::last_seq),
+                clear_generation: log.as_ref().map_or(0, CommitLog::clear_generation),
+                persist_path: None,
+            }),
+            log,
+            history_file,
+        };
+        monitor.configure(options);
+        monitor
+    }
+
+    /// Applies new options: turns reporting on or off, and loads, starts
+    /// or removes the kept history
+    pub fn configure(&self, options: HistoryOptions) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(log) = &self.log {
+            log.set_enabled(options.size > 0);
+        }
+        state.history.set_capacity(options.size);
+
+        let persist = options.persist && options.size > 0;
+        if persist && state.persist_path.is_none() {
+            if state.history.is_empty() && self.history_file.exists() {
+                match load(&self.history_file) {
+                    // Stored newest first; push oldest first to keep the order
+                    Ok(entries) => entries.iter().rev().for_each(|entry| {
+                        state.history.push(entry);
+                    }),
+                    Err(e) => log::warn!("{:#}", e),
+                }
+            }
+            state.persist_path = Some(self.history_file.clone());
+            state.save();
+        } else if !persist && state.persist_path.take().is_some() {
+            if let Err(e) = std::fs::remove_file(&self.history_file) {
+                log::warn!("Failed to remove {}: {}", self.history_file.display(), e);
+            }
+        }
+    }
+
+    /// Strings in the history, newest first
+    pub fn entries(&self) -> Vec<String> {
+        self.poll();
+        self.state.lock().unwrap().history.entries()
+    }
+
+    pub fn get(&self, index: usize) -> Option<String> {
+        self.poll();
+        self.state.lock().unwrap().history.get(index).map(str::to_string)
+    }
+
+    /// Forgets the history, including commits still in the ring
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(log) = &self.log {
+            log.request_clear();
+            state.clear_generation = log.clear_generation();
+            state.last_seq = log.last_seq();
+        }
+        state.history.clear();
+        state.save();
+    }
+
+    /// Takes new commits and clear requests from the ring; returns whether
+    /// the history changed
+    fn poll(&self) -> bool {
+        let Some(log) = &self.log else {
+            return false;
+        };
+        let mut state = self.state.lock().unwrap();
+        let mut changed = false;
+
+        let generation = log.clear_generation();
+        if generation != state.clear_generation {
+            state.clear_generation = generation;
+            changed |= !state.history.is_empty();
+            state.history.clear();
+        }
+        for commit in log.read_since(state.last_seq) {
+            state.last_seq = commit.seq;
+            changed |= state.history.push(&commit.text);
+        }
+        state.last_seq = state.last_seq.max(log.last_seq());
+
+        if changed {
+            state.save();
+        }
+        changed
+    }
+
+    /// Calls `on_change` with the history from a background thread whenever
+    /// it changes
+    pub fn watch(self: &Arc<Self>, on_change: impl Fn(Vec<String>) + Send + 'static) {
+        if self.log.is_none() {
+            return;
+        }
+        let monitor = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if monitor.poll() {
+                on_change(monitor.state.lock().unwrap().history.entries());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic-commit-history-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn options(size: usize, persist: bool) -> HistoryOptions {
+        HistoryOptions { size, persist }
+    }
+
+    fn monitor(size: usize, persist: bool, dir: &Path) -> CommitHistoryMonitor {
+        CommitHistoryMonitor::with_log(Some(CommitLog::in_memory()), options(size, persist), dir.join(HISTORY_FILE))
+    }
+
+    #[test]
+    fn test_options_from_settings() {
+        assert_eq!(HistoryOptions::from_settings(None, None), options(DEFAULT_SIZE, false));
+        assert_eq!(HistoryOptions::from_settings(Some("0"), Some("true")), options(0, true));
+        assert_eq!(HistoryOptions::from_settings(Some("500"), Some("false")), options(MAX_SIZE, false));
+        assert_eq!(HistoryOptions::from_settings(Some("many"), None), options(DEFAULT_SIZE, false));
+    }
+
+    #[test]
+    fn test_history_is_bounded_and_distinct() {
+        let mut history = CommitHistory::new(3);
+        for text in ["က", "ခ", "ဂ", "ခ", "ဃ"] {
+            assert!(history.push(text));
+        }
+        assert_eq!(history.entries(), vec!["ဃ", "ခ", "ဂ"]);
+        assert_eq!(history.get(1), Some("ခ"));
+        assert_eq!(history.get(3), None);
+
+        assert!(!history.push(" "));
+        history.set_capacity(1);
+        assert_eq!(history.entries(), vec!["ဃ"]);
+        history.set_capacity(0);
+        assert!(!history.push("င"));
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn test_monitor_takes_commits_from_ring() {
+        let dir = test_dir("ring");
+        let monitor = monitor(5, false, &dir);
+        let log = monitor.log.as_ref().unwrap();
+        assert!(log.is_enabled());
+
+        log.report("မင်္ဂလာပါ");
+        log.report("ok");
+        assert!(monitor.poll());
+        assert!(!monitor.poll());
+        assert_eq!(monitor.entries(), vec!["ok", "မင်္ဂလာပါ"]);
+        assert_eq!(monitor.get(1).as_deref(), Some("မင်္ဂလာပါ"));
+        // Nothing is written unless the user opted in
+        assert!(!&dir.join(HISTORY_FILE).exists());
+    }
+
+    #[test]
+    fn test_size_zero_turns_reporting_off() {
+        let dir = test_dir("off");
+        let monitor = monitor(5, false, &dir);
+        monitor.log.as_ref().unwrap().report("one");
+        assert_eq!(monitor.entries(), vec!["one"]);
+
+        monitor.configure(options(0, false));
+        let log = monitor.log.as_ref().unwrap();
+        assert!(!log.is_enabled());
+        assert_eq!(log.report("two"), None);
+        assert!(monitor.entries().is_empty());
+    }
+
+    #[test]
+    fn test_tray_clear_empties_history() {
+        let dir = test_dir("clear");
+        let monitor = monitor(5, false, &dir);
+        let log = monitor.log.as_ref().unwrap();
+        log.report("secret");
+        assert_eq!(monitor.entries(), vec!["secret"]);
+
+        // The tray bumps the clear generation of the shared ring
+        log.request_clear();
+        assert!(monitor.poll());
+        assert!(monitor.entries().is_empty());
+
+        log.report("again");
+        monitor.clear();
+        assert!(monitor.entries().is_empty());
+        assert!(!monitor.poll());
+    }
+
+    #[test]
+    fn test_history_is_kept_only_when_opted_in() {
+        let dir = test_dir("kept");
+        let path = dir.join(HISTORY_FILE);
+        let monitor = monitor(5, true, &dir);
+        monitor.log.as_ref().unwrap().report("first");
+        monitor.log.as_ref().unwrap().report("second");
+        monitor.poll();
+        assert_eq!(load(&path).unwrap(), vec!["second", "first"]);
+
+        // A new session loads the kept history in order
+        let restarted = self::monitor(5, true, &dir);
+        assert_eq!(restarted.entries(), vec!["second", "first"]);
+
+        // Opting out removes the file
+        restarted.configure(options(5, false));
+        assert!(!path.exists());
+        assert_eq!(restarted.entries(), vec!["second", "first"]);
+    }
+}
@@ -0,0 +1,785 @@
+//! Recovery of text being composed when an application crashed
+//!
+//! When the user opts in, text services keep their composing text in
+//! `keymagic_core::composition_journal`. The GUI copies it into a journal in
+//! the data directory every few seconds, so it also survives the GUI or the
+//! whole session going down. A process that disappears without committing
+//! leaves its entry behind, and the UI offers to copy the text.
+//!
+//! The journal is encrypted with AES-256-GCM under a key kept in the
+//! platform's credential store: protected with DPAPI on Windows, in the
+//! Keychain on macOS. Entries expire after [`ENTRY_TTL`]; turning the
+//! setting off deletes the journal and its key.
+
+use anyhow::{anyhow, bail, Context, Result};
+use keymagic_core::composition_journal::{CompositionJournal, JournalSlot};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Setting that turns the journal on when "true"
+pub const ENABLED_SETTING: &str = "composition_journal_enabled";
+
+/// File in the data directory holding the encrypted journal
+pub const JOURNAL_FILE: &str = "composition_journal.bin";
+
+/// Name of the journal key in the credential store
+pub const KEY_NAME: &str = "composition_journal_key";
+
+/// Entries older than this are dropped, recovered or not
+pub const ENTRY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Start of a journal file, also authenticated with the contents
+const FILE_MAGIC: &[u8; 4] = b"KMJ1";
+const KEY_LEN: usize = 32;
+
+/// Where secrets such as the journal key are kept
+pub trait CredentialStore: Send + Sync {
+    fn load(&self, name: &str) -> Result<Option<Vec<u8>>>;
+    fn save(&self, name: &str, secret: &[u8]) -> Result<()>;
+    /// Deleting a secret that does not exist succeeds
+    fn delete(&self, name: &str) -> Result<()>;
+}
+
+/// Secrets encrypted with DPAPI for the current user, in files next to the
+/// journal
+#[cfg(target_os = "windows")]
+pub struct DpapiCredentialStore {
+    dir: PathBuf,
+}
+
+#[cfg(target_os = "windows")]
+impl DpapiCredentialStore {
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.dpapi", name))
+    }
+
+    fn transform(data: &[u8], protect: bool) -> Result<Vec<u8>> {
+        use windows::Win32::Foundation::{LocalFree, HLOCAL};
+        use windows::Win32::Security::Cryptography::{CryptProtectData, CryptUnprotectData, CRYPT_INTEGER_BLOB};
+
+        let input = CRYPT_INTEGER_BLOB { cbData: data.len() as u32, pbData: data.as_ptr() as *mut u8 };
+        let mut output = CRYPT_INTEGER_BLOB::default();
+        unsafe {
+            if protect {
+                CryptProtectData(&input, windows::core::w!("KeyMagic"), None, None, None, 0, &mut output)?;
+            } else {
+                CryptUnprotectData(&input, None, None, None, None, 0, &mut output)?;
+            }
+            let result = std::slice::from_raw_parts(output.pbData, output.cbData as usize).to_vec();
+            let _ = LocalFree(HLOCAL(output.pbData as *mut _));
+            Ok(result)
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl CredentialStore for DpapiCredentialStore {
+    fn load(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.path(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let protected = std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::transform(&protected, false).map(Some).context("Failed to unprotect the journal key")
+    }
+
+    fn save(&self, name: &str, secret: &[u8]) -> Result<()> {
+        let protected = Self::transform(secret, true).context("Failed to protect the journal key")?;
+        let path = self.path(name);
+        std::fs::write(&path, protected).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        match std::fs::remove_file(self.path(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Secrets as generic passwords in the login Keychain, through `security`
+#[cfg(target_os = "macos")]
+pub struct KeychainCredentialStore;
+
+#[cfg(target_os = "macos")]
+impl KeychainCredentialStore {
+    const SERVICE: &'static str = "org.keymagic.gui";
+
+    fn security(args: &[&str]) -> Result<std::process::Output> {
+        std::process::Command::new("/usr/bin/security")
+            .args(args)
+            .output()
+            .context("Failed to run security")
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl CredentialStore for KeychainCredentialStore {
+    fn load(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let output = Self::security(&["find-generic-password", "-s", Self::SERVICE, "-a", name, "-w"])?;
+        if !output.status.success() {
+            // Exit status 44: the item does not exist
+            return match output.status.code() {
+                Some(44) => Ok(None),
+                _ => Err(anyhow!("Failed to read the Keychain: {}", String::from_utf8_lossy(&output.stderr).trim())),
+            };
+        }
+        let hex = String::from_utf8_lossy(&output.stdout);
+        decode_hex(hex.trim()).map(Some).ok_or_else(|| anyhow!("The Keychain item {} is not a key", name))
+    }
+
+    fn save(&self, name: &str, secret: &[u8]) -> Result<()> {
+        let hex: String = secret.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let output = Self::security(&["add-generic-password", "-U", "-s", Self::SERVICE, "-a", name, "-w", &hex])?;
+        if !output.status.success() {
+            bail!("Failed to write the Keychain: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        let output = Self::security(&["delete-generic-password", "-s", Self::SERVICE, "-a", name])?;
+        match output.status.code() {
+            Some(0) | Some(44) => Ok(()),
+            _ => bail!("Failed to delete from the Keychain: {}", String::from_utf8_lossy(&output.stderr).trim()),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+/// The credential store of this platform, if it has one
+#[allow(unused_variables)]
+pub fn platform_credentials(data_dir: &Path) -> Option<Arc<dyn CredentialStore>> {
+    #[cfg(target_os = "windows")]
+    return Some(Arc::new(DpapiCredentialStore { dir: data_dir.to_path_buf() }));
+    #[cfg(target_os = "macos")]
+    return Some(Arc::new(KeychainCredentialStore));
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    None
+}
+
+/// Encrypts the journal with a key from a credential store
+struct JournalCipher {
+    key: LessSafeKey,
+    random: SystemRandom,
+}
+
+impl JournalCipher {
+    /// Uses the stored key, creating one if there is none
+    fn from_store(store: &dyn CredentialStore) -> Result<Self> {
+        let random = SystemRandom::new();
+        let key = match store.load(KEY_NAME)? {
+            Some(key) if key.len() == KEY_LEN => key,
+            _ => {
+                let mut key = vec![0; KEY_LEN];
+                random.fill(&mut key).map_err(|_| anyhow!("Failed to generate the journal key"))?;
+                store.save(KEY_NAME, &key)?;
+                key
+            }
+        };
+        let key = UnboundKey::new(&AES_256_GCM, &key).map_err(|_| anyhow!("Invalid journal key"))?;
+        Ok(Self { key: LessSafeKey::new(key), random })
+    }
+
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        self.random.fill(&mut nonce).map_err(|_| anyhow!("Failed to generate a nonce"))?;
+        let mut sealed = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(FILE_MAGIC), &mut sealed)
+            .map_err(|_| anyhow!("Failed to encrypt the journal"))?;
+        Ok([FILE_MAGIC.as_slice(), &nonce, &sealed].concat())
+    }
+
+    fn open(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let Some(rest) = data.strip_prefix(FILE_MAGIC.as_slice()) else {
+            bail!("Not a composition journal");
+        };
+        if rest.len() < NONCE_LEN {
+            bail!("The composition journal is truncated");
+        }
+        let (nonce, sealed) = rest.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("Invalid nonce"))?;
+        let mut sealed = sealed.to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::from(FILE_MAGIC), &mut sealed)
+            .map_err(|_| anyhow!("The composition journal cannot be decrypted with the stored key"))?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+/// A composition in the journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub pid: u32,
+    pub process: String,
+    pub text: String,
+    /// When the host last reported it, in milliseconds since the Unix epoch
+    pub updated_ms: u64,
+}
+
+impl JournalEntry {
+    fn from_slot(slot: &JournalSlot) -> Self {
+        Self { pid: slot.pid, process: slot.process.clone(), text: slot.text.clone(), updated_ms: slot.updated_ms }
+    }
+
+    fn id(&self) -> String {
+        format!("{}-{}", self.pid, self.updated_ms)
+    }
+}
+
+/// Text left by a process that went away without committing it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnrecoveredComposition {
+    /// Passed back to dismiss it
+    pub id: String,
+    /// Display name of the process, e.g. `Notepad`
+    pub process: String,
+    pub text: String,
+    pub timestamp_ms: u64,
+}
+
+/// Display name of a process from its executable name
+fn display_name(process: &str) -> String {
+    let name = process.rsplit(['\\', '/']).next().unwrap_or(process);
+    let name = name.strip_suffix(".exe").or_else(|| name.strip_suffix(".EXE")).unwrap_or(name);
+    let mut chars = name.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+/// The journal's entries and how they follow the hosts' slots
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    /// Takes in the slots read now; returns whether the entries changed and
+    /// the processes whose slots can be freed since they went away
+    fn sync(&mut self, slots: &[JournalSlot], is_alive: &dyn Fn(u32) -> bool, now_ms: u64) -> (bool, Vec<u32>) {
+        let before = self.entries.clone();
+        let mut released = Vec::new();
+        for slot in slots {
+            self.entries.retain(|entry| entry.pid != slot.pid);
+            self.entries.push(JournalEntry::from_slot(slot));
+            if !is_alive(slot.pid) {
+                released.push(slot.pid);
+            }
+        }
+        // A live process without a slot committed its text
+        self.entries
+            .retain(|entry| slots.iter().any(|slot| slot.pid == entry.pid) || !is_alive(entry.pid));
+        self.expire(now_ms);
+        self.entries.sort_by_key(|entry| (entry.updated_ms, entry.pid));
+        (self.entries != before, released)
+    }
+
+    /// Drops entries older than [`ENTRY_TTL`]; returns how many
+    fn expire(&mut self, now_ms: u64) -> usize {
+        let before = self.entries.len();
+        let ttl_ms = ENTRY_TTL.as_millis() as u64;
+        self.entries.retain(|entry| now_ms.saturating_sub(entry.updated_ms) < ttl_ms);
+        before - self.entries.len()
+    }
+
+    fn unrecovered(&self, is_alive: &dyn Fn(u32) -> bool) -> Vec<UnrecoveredComposition> {
+        self.entries
+            .iter()
+            .filter(|entry| !is_alive(entry.pid))
+            .map(|entry| UnrecoveredComposition {
+                id: entry.id(),
+                process: display_name(&entry.process),
+                text: entry.text.clone(),
+                timestamp_ms: entry.updated_ms,
+            })
+            .collect()
+    }
+
+    fn dismiss(&mut self, id: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.id() != id);
+        self.entries.len() != before
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_millis() as u64)
+}
+
+#[cfg(target_os = "windows")]
+fn open_journal() -> std::io::Result<CompositionJournal> {
+    CompositionJournal::create_shared()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn open_journal() -> std::io::Result<CompositionJournal> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Compositions are only journaled on Windows",
+    ))
+}
+
+#[cfg(target_os = "windows")]
+fn is_process_alive(pid: u32) -> bool {
+    use windows::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+    use windows::Win32::System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
+
+    unsafe {
+        let Ok(handle) = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) else {
+            // Gone, or not ours to look at; either way its entry is kept
+            return false;
+        };
+        let mut code = 0;
+        let alive = GetExitCodeProcess(handle, &mut code).is_ok() && code == STILL_ACTIVE.0 as u32;
+        let _ = CloseHandle(handle);
+        alive
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_process_alive(_pid: u32) -> bool {
+    false
+}
+
+type AliveCheck = Box<dyn Fn(u32) -> bool + Send + Sync>;
+
+struct MonitorState {
+    journal: Journal,
+    cipher: Option<JournalCipher>,
+    /// Whether the journal file was read, which happens on first use
+    loaded: bool,
+}
+
+/// The GUI's composition journal, fed from the hosts' slots
+pub struct CompositionJournalMonitor {
+    slots: Option<CompositionJournal>,
+    credentials: Option<Arc<dyn CredentialStore>>,
+    journal_file: PathBuf,
+    is_alive: AliveCheck,
+    enabled: Mutex<bool>,
+    state: Mutex<MonitorState>,
+}
+
+impl CompositionJournalMonitor {
+    /// Attaches to the hosts' slots; without them, or a credential store,
+    /// nothing is journaled
+    pub fn new(enabled: bool, data_dir: &Path) -> Self {
+        let slots = open_journal()
+            .map_err(|e| log::debug!("Composition journal unavailable: {}", e))
+            .ok();
+        Self::with_parts(
+            slots,
+            platform_credentials(data_dir),
+            data_dir.join(JOURNAL_FILE),
+            Box::new(is_process_alive),
+            enabled,
+        )
+    }
+
+    fn with_parts(
+        slots: Option<CompositionJournal>,
+        credentials: Option<Arc<dyn CredentialStore>>,
+        journal_file: PathBuf,
+        is_alive: AliveCheck,
+        enabled: bool,
+    ) -> Self {
+        let monitor = Self {
+            slots,
+            credentials,
+            journal_file,
+            is_alive,
+            enabled: Mutex::new(false),
+            state: Mutex::new(MonitorState { journal: Journal::default(), cipher: None, loaded: false }),
+        };
+        if let Err(e) = monitor.configure(enabled) {
+            log::warn!("{:#}", e);
+        }
+        monitor
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        *self.enabled.lock().unwrap()
+    }
+
+    /// Turns journaling on or off in every host; off deletes the journal
+    /// and its key
+    pub fn configure(&self, enabled: bool) -> Result<()> {
+        if enabled && self.credentials.is_none() {
+            bail!("There is no credential store to keep the journal key in");
+        }
+        *self.enabled.lock().unwrap() = enabled;
+        if let Some(slots) = &self.slots {
+            slots.set_enabled(enabled);
+        }
+        if enabled {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        *state = MonitorState { journal: Journal::default(), cipher: None, loaded: true };
+        if self.journal_file.exists() {
+            std::fs::remove_file(&self.journal_file)
+                .with_context(|| format!("Failed to remove {}", self.journal_file.display()))?;
+        }
+        if let Some(credentials) = &self.credentials {
+            credentials.delete(KEY_NAME)?;
+        }
+        Ok(())
+    }
+
+    fn cipher<'a>(&self, state: &'a mut MonitorState) -> Result<&'a JournalCipher> {
+        if state.cipher.is_none() {
+            let credentials = self.credentials.as_deref().ok_or_else(|| anyhow!("No credential store"))?;
+            state.cipher = Some(JournalCipher::from_store(credentials)?);
+        }
+        Ok(state.cipher.as_ref().unwrap())
+    }
+
+    fn lock_loaded(&self) -> std::sync::MutexGuard<'_, MonitorState> {
+        let mut state = self.state.lock().unwrap();
+        if !state.loaded {
+            state.loaded = true;
+            if self.journal_file.exists() {
+                match self.load(&mut state) {
+                    Ok(entries) => state.journal.entries = entries,
+                    Err(e) => {
+                        // Unreadable without its key; nothing can be recovered
+                        log::warn!("Discarding the composition journal: {:#}", e);
+                        let _ = std::fs::remove_file(&self.journal_file);
+                    }
+                }
+            }
+        }
+        state
+    }
+
+    fn load(&self, state: &mut MonitorState) -> Result<Vec<JournalEntry>> {
+        let data = std::fs::read(&self.journal_file)
+            .with_context(|| format!("Failed to read {}", self.journal_file.display()))?;
+        let plaintext = self.cipher(state)?.open(&data)?;
+        serde_json::from_slice(&plaintext).context("Failed to parse the composition journal")
+    }
+
+    fn save(&self, state: &mut MonitorState) -> Result<()> {
+        if state.journal.entries.is_empty() {
+            if self.journal_file.exists() {
+                std::fs::remove_file(&self.journal_file)
+                    .with_context(|| format!("Failed to remove {}", self.journal_file.display()))?;
+            }
+            return Ok(());
+        }
+        let plaintext = serde_json::to_vec(&state.journal.entries)?;
+        let sealed = self.cipher(state)?.seal(&plaintext)?;
+        std::fs::write(&self.journal_file, sealed)
+            .with_context(|| format!("Failed to write {}", self.journal_file.display()))
+    }
+
+    /// Copies the hosts' slots into the journal and saves it if anything
+    /// changed; returns whether it did
+    fn poll_at(&self, now_ms: u64) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let slots = self.slots.as_ref().map(CompositionJournal::read_all).unwrap_or_default();
+        let mut state = self.lock_loaded();
+        let (changed, released) = state.journal.sync(&slots, &self.is_alive, now_ms);
+        if changed {
+            if let Err(e) = self.save(&mut state) {
+                log::warn!("Failed to keep the composition journal: {:#}", e);
+            }
+        }
+        // Kept in the journal now, so the slots can go to other processes
+        if let Some(block) = &self.slots {
+            for pid in released {
+                block.clear(pid);
+            }
+        }
+        changed
+    }
+
+    /// Text of processes that went away without committing it, oldest first
+    pub fn unrecovered(&self) -> Vec<UnrecoveredComposition> {
+        self.unrecovered_at(now_ms())
+    }
+
+    fn unrecovered_at(&self, now_ms: u64) -> Vec<UnrecoveredComposition> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        self.poll_at(now_ms);
+        self.lock_loaded().journal.unrecovered(&self.is_alive)
+    }
+
+    /// Forgets an entry once the user took or declined its text
+    pub fn dismiss(&self, id: &str) -> Result<bool> {
+        let mut state = self.lock_loaded();
+        if !state.journal.dismiss(id) {
+            return Ok(false);
+        }
+        self.save(&mut state)?;
+        Ok(true)
+    }
+
+    /// Copies the slots from a background thread while journaling is on
+    pub fn watch(self: &Arc<Self>) {
+        if self.slots.is_none() {
+            return;
+        }
+        let monitor = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(POLL_INTERVAL);
+            monitor.poll_at(now_ms());
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+
+    const HOUR_MS: u64 = 60 * 60 * 1000;
+    const LONG: &str = "မင်္ဂလာပါခင်ဗျာ";
+
+    /// Credential store in memory, standing in for DPAPI and the Keychain
+    #[derive(Default)]
+    struct MockCredentials {
+        secrets: Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    impl CredentialStore for MockCredentials {
+        fn load(&self, name: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.secrets.lock().unwrap().get(name).cloned())
+        }
+
+        fn save(&self, name: &str, secret: &[u8]) -> Result<()> {
+            self.secrets.lock().unwrap().insert(name.to_string(), secret.to_vec());
+            Ok(())
+        }
+
+        fn delete(&self, name: &str) -> Result<()> {
+            self.secrets.lock().unwrap().remove(name);
+            Ok(())
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic-composition-journal-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    struct Harness {
+        monitor: CompositionJournalMonitor,
+        credentials: Arc<MockCredentials>,
+        alive: Arc<Mutex<HashSet<u32>>>,
+        dir: PathBuf,
+    }
+
+    impl Harness {
+        fn new(name: &str) -> Self {
+            let dir = test_dir(name);
+            let credentials = Arc::new(MockCredentials::default());
+            let alive = Arc::new(Mutex::new(HashSet::new()));
+            let monitor = Self::monitor(&dir, &credentials, &alive, CompositionJournal::in_memory());
+            Self { monitor, credentials, alive, dir }
+        }
+
+        fn monitor(
+            dir: &Path,
+            credentials: &Arc<MockCredentials>,
+            alive: &Arc<Mutex<HashSet<u32>>>,
+            slots: CompositionJournal,
+        ) -> CompositionJournalMonitor {
+            let alive = alive.clone();
+            CompositionJournalMonitor::with_parts(
+                Some(slots),
+                Some(credentials.clone() as Arc<dyn CredentialStore>),
+                dir.join(JOURNAL_FILE),
+                Box::new(move |pid| alive.lock().unwrap().contains(&pid)),
+                true,
+            )
+        }
+
+        /// The GUI starting again over the same files and key
+        fn restart(&mut self) {
+            self.monitor = Self::monitor(&self.dir, &self.credentials, &self.alive, CompositionJournal::in_memory());
+        }
+
+        fn slots(&self) -> &CompositionJournal {
+            self.monitor.slots.as_ref().unwrap()
+        }
+
+        fn start(&self, pid: u32) {
+            self.alive.lock().unwrap().insert(pid);
+        }
+
+        fn crash(&self, pid: u32) {
+            self.alive.lock().unwrap().remove(&pid);
+        }
+    }
+
+    #[test]
+    fn test_crashed_process_leaves_its_text() {
+        let harness = Harness::new("crash");
+        harness.start(10);
+        harness.start(20);
+        harness.slots().report(10, "notepad.exe", LONG, 1_000);
+        harness.slots().report(20, "winword.exe", "abcdefghij", 2_000);
+        assert!(harness.monitor.poll_at(3_000));
+        assert!(harness.monitor.unrecovered_at(3_000).is_empty());
+
+        harness.crash(10);
+        let unrecovered = harness.monitor.unrecovered_at(4_000);
+        assert_eq!(
+            unrecovered,
+            vec![UnrecoveredComposition {
+                id: "10-1000".to_string(),
+                process: "Notepad".to_string(),
+                text: LONG.to_string(),
+                timestamp_ms: 1_000,
+            }]
+        );
+        // Its slot went back to the hosts
+        assert_eq!(harness.slots().read_all().len(), 1);
+
+        assert!(harness.monitor.dismiss("10-1000").unwrap());
+        assert!(!harness.monitor.dismiss("10-1000").unwrap());
+        assert!(harness.monitor.unrecovered_at(5_000).is_empty());
+    }
+
+    #[test]
+    fn test_committed_text_leaves_the_journal() {
+        let harness = Harness::new("commit");
+        harness.start(10);
+        harness.slots().report(10, "notepad.exe", LONG, 1_000);
+        harness.monitor.poll_at(1_000);
+        assert!(harness.dir.join(JOURNAL_FILE).exists());
+
+        harness.slots().clear(10);
+        assert!(harness.monitor.poll_at(2_000));
+        harness.crash(10);
+        assert!(harness.monitor.unrecovered_at(3_000).is_empty());
+        assert!(!harness.dir.join(JOURNAL_FILE).exists());
+    }
+
+    #[test]
+    fn test_journal_survives_a_restart_encrypted() {
+        let mut harness = Harness::new("restart");
+        harness.start(10);
+        harness.slots().report(10, "chrome.exe", LONG, 1_000);
+        harness.monitor.poll_at(1_000);
+
+        let data = std::fs::read(harness.dir.join(JOURNAL_FILE)).unwrap();
+        assert!(data.starts_with(FILE_MAGIC));
+        let plaintext = String::from_utf8_lossy(&data);
+        assert!(!plaintext.contains(LONG) && !plaintext.contains("chrome"));
+
+        // The whole session went down
+        harness.crash(10);
+        harness.restart();
+        let unrecovered = harness.monitor.unrecovered_at(2_000);
+        assert_eq!(unrecovered.len(), 1);
+        assert_eq!((unrecovered[0].process.as_str(), unrecovered[0].text.as_str()), ("Chrome", LONG));
+    }
+
+    #[test]
+    fn test_journal_without_its_key_is_discarded() {
+        let mut harness = Harness::new("lost-key");
+        harness.start(10);
+        harness.slots().report(10, "notepad.exe", LONG, 1_000);
+        harness.monitor.poll_at(1_000);
+        harness.crash(10);
+
+        harness.credentials.delete(KEY_NAME).unwrap();
+        harness.restart();
+        assert!(harness.monitor.unrecovered_at(2_000).is_empty());
+        assert!(!harness.dir.join(JOURNAL_FILE).exists());
+    }
+
+    #[test]
+    fn test_tampered_journal_is_rejected() {
+        let credentials = MockCredentials::default();
+        let cipher = JournalCipher::from_store(&credentials).unwrap();
+        let mut sealed = cipher.seal(b"[]").unwrap();
+        assert_eq!(cipher.open(&sealed).unwrap(), b"[]");
+        // Same key from the store
+        assert_eq!(JournalCipher::from_store(&credentials).unwrap().open(&sealed).unwrap(), b"[]");
+
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(cipher.open(&sealed).is_err());
+        assert!(cipher.open(b"KMJ1").is_err());
+        assert!(cipher.open(b"nonsense").is_err());
+    }
+
+    #[test]
+    fn test_entries_expire_after_a_day() {
+        let harness = Harness::new("expiry");
+        harness.slots().report(10, "notepad.exe", LONG, HOUR_MS);
+        harness.slots().report(20, "winword.exe", LONG, 10 * HOUR_MS);
+        let now = HOUR_MS + ENTRY_TTL.as_millis() as u64;
+        let unrecovered = harness.monitor.unrecovered_at(now);
+        assert_eq!(unrecovered.len(), 1);
+        assert_eq!(unrecovered[0].id, format!("20-{}", 10 * HOUR_MS));
+        assert!(harness.monitor.unrecovered_at(now + 9 * HOUR_MS).is_empty());
+    }
+
+    #[test]
+    fn test_turning_off_deletes_journal_and_key() {
+        let harness = Harness::new("off");
+        harness.slots().report(10, "notepad.exe", LONG, 1_000);
+        harness.monitor.poll_at(1_000);
+        assert!(harness.credentials.load(KEY_NAME).unwrap().is_some());
+
+        harness.monitor.configure(false).unwrap();
+        assert!(!harness.slots().is_enabled());
+        assert!(!harness.dir.join(JOURNAL_FILE).exists());
+        assert!(harness.credentials.load(KEY_NAME).unwrap().is_none());
+        assert!(harness.monitor.unrecovered_at(2_000).is_empty());
+        // Hosts report nothing while off
+        assert!(!harness.slots().report(10, "notepad.exe", LONG, 3_000));
+    }
+
+    #[test]
+    fn test_no_credential_store() {
+        let dir = test_dir("no-store");
+        let monitor = CompositionJournalMonitor::with_parts(
+            Some(CompositionJournal::in_memory()),
+            None,
+            dir.join(JOURNAL_FILE),
+            Box::new(|_| false),
+            true,
+        );
+        assert!(!monitor.is_available());
+        assert!(!monitor.is_enabled());
+        assert!(monitor.configure(true).is_err());
+    }
+
+    #[test]
+    fn test_display_names() {
+        assert_eq!(display_name("notepad.exe"), "Notepad");
+        assert_eq!(display_name(r"C:\Windows\WINWORD.EXE"), "WINWORD");
+        assert_eq!(display_name(""), "");
+    }
+}
@@ -0,0 +1,120 @@
+//! Screen reader announcements for keyboard switches
+//!
+//! The visual HUD is invisible to screen reader users, so switches are also
+//! spoken. Unless the user chose otherwise this follows whether a screen
+//! reader is running.
+
+use std::time::{Duration, Instant};
+
+/// Setting holding "true" or "false"; unset means follow the screen reader
+pub const ANNOUNCE_KEYBOARD_SWITCH_SETTING: &str = "announce_keyboard_switch";
+
+/// How long a screen reader check is trusted. Readers are started and
+/// stopped rarely, while switches can come several times a second.
+pub const DETECTION_TTL: Duration = Duration::from_secs(30);
+
+pub fn keyboard_switch_announcement(keyboard_name: &str) -> String {
+    format!("Keyboard: {}", keyboard_name)
+}
+
+/// Explicit choice stored in the setting, `None` when unset or unreadable
+pub fn parse_announce_setting(value: Option<&str>) -> Option<bool> {
+    match value?.trim() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether to announce, given the stored setting and a screen reader check.
+/// The check only runs when the user has not chosen; if the platform cannot
+/// tell, nothing is announced.
+pub fn should_announce(setting: Option<&str>, screen_reader_active: impl FnOnce() -> Option<bool>) -> bool {
+    parse_announce_setting(setting).unwrap_or_else(|| screen_reader_active().unwrap_or(false))
+}
+
+/// Last screen reader check and when it was made
+#[derive(Debug, Default)]
+pub struct ScreenReaderCache {
+    last: Option<(Instant, Option<bool>)>,
+}
+
+impl ScreenReaderCache {
+    /// Cached result, or the result of `detect` once the cached one is older
+    /// than [`DETECTION_TTL`]. "Cannot tell" is cached too.
+    pub fn get(&mut self, now: Instant, detect: impl FnOnce() -> Option<bool>) -> Option<bool> {
+        match self.last {
+            Some((checked, result)) if now.saturating_duration_since(checked) < DETECTION_TTL => result,
+            _ => {
+                let result = detect();
+                self.last = Some((now, result));
+                result
+            }
+        }
+    }
+
+    /// Forgets the cached result so the next call checks again
+    pub fn invalidate(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_announcement_text() {
+        assert_eq!(keyboard_switch_announcement("Myanmar3"), "Keyboard: Myanmar3");
+        assert_eq!(keyboard_switch_announcement("ပိုးယိုး"), "Keyboard: ပိုးယိုး");
+    }
+
+    #[test]
+    fn test_explicit_setting_wins_without_detection() {
+        let detect = || -> Option<bool> { panic!("detection must not run") };
+        assert!(should_announce(Some("true"), detect));
+        assert!(!should_announce(Some("false"), detect));
+    }
+
+    #[test]
+    fn test_unset_follows_detection() {
+        assert!(should_announce(None, || Some(true)));
+        assert!(!should_announce(None, || Some(false)));
+        // Unknown platforms stay quiet
+        assert!(!should_announce(None, || None));
+        // Anything unrecognised counts as unset
+        assert!(should_announce(Some("yes"), || Some(true)));
+        assert!(!should_announce(Some(""), || None));
+    }
+
+    #[test]
+    fn test_cache_reuses_result_within_ttl() {
+        let calls = Cell::new(0);
+        let detect = || {
+            calls.set(calls.get() + 1);
+            Some(true)
+        };
+        let mut cache = ScreenReaderCache::default();
+        let start = Instant::now();
+
+        assert_eq!(cache.get(start, detect), Some(true));
+        assert_eq!(cache.get(start + Duration::from_secs(5), detect), Some(true));
+        assert_eq!(calls.get(), 1);
+
+        assert_eq!(cache.get(start + DETECTION_TTL, detect), Some(true));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_cache_keeps_unknown_and_invalidates() {
+        let mut cache = ScreenReaderCache::default();
+        let start = Instant::now();
+        assert_eq!(cache.get(start, || None), None);
+        // An unknown result is not retried on every switch
+        assert_eq!(cache.get(start, || Some(true)), None);
+
+        cache.invalidate();
+        assert_eq!(cache.get(start, || Some(true)), Some(true));
+    }
+}
@@ -0,0 +1,53 @@
+//! Keyboards shipped with the app, offered for import on first run and
+//! after upgrades
+//!
+//! A bundled keyboard is matched to an installed one by name, or by the
+//! hash the installed file had before the user edited its metadata; when
+//! both are present the file hashes tell whether the bundled copy is newer.
+
+use crate::version::{InvalidVersion, Version};
+use serde::{Deserialize, Serialize};
+
+use super::keyboard_manager::KeyboardInfo;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundledKeyboard {
+    pub id: String,
+    pub name: String,
+    pub status: String, // "New", "Updated", "Installed", "Unchanged", "Modified"
+    pub icon_data: Option<Vec<u8>>,
+    pub bundled_path: String,
+}
+
+/// The installed keyboard a bundled one corresponds to; `hash` is empty
+/// when the bundled file could not be read. Of several matches, one with the
+/// same content wins, then one edited from the same content.
+pub fn bundled_match<'a>(installed: &'a [KeyboardInfo], name: &str, hash: &str) -> Option<&'a KeyboardInfo> {
+    let edited_from = |k: &KeyboardInfo| !hash.is_empty() && k.original_hash.as_deref() == Some(hash);
+    installed
+        .iter()
+        .filter(|k| k.name == name || edited_from(k))
+        .min_by_key(|k| (k.hash != hash, !edited_from(k), &k.id))
+}
+
+/// Status of a bundled keyboard given the installed keyboards
+pub fn bundled_status(installed: &[KeyboardInfo], name: &str, hash: &str) -> &'static str {
+    match bundled_match(installed, name, hash) {
+        None => "New",
+        Some(_) if hash.is_empty() => "Installed", // Can't compare, assume installed
+        Some(keyboard) if keyboard.hash == hash => "Unchanged",
+        // The user edited the metadata of this very version
+        Some(keyboard) if keyboard.original_hash.as_deref() == Some(hash) => "Modified",
+        // Otherwise a hash mismatch means the bundled version is newer
+        Some(_) => "Updated",
+    }
+}
+
+/// Whether bundled keyboards should be offered again, given the version
+/// that last scanned them
+pub fn should_scan(last_scanned_version: Option<&str>, current: &Version) -> Result<bool, InvalidVersion> {
+    match last_scanned_version {
+        Some(last_version) => Ok(*current > Version::parse(last_version)?),
+        None => Ok(true), // First run
+    }
+}
@@ -0,0 +1,133 @@
+//! Keyboards kept in the platform store instead of a file
+//!
+//! Some managed machines forbid writing to the keyboards folder, or strip
+//! loose files from roaming profiles between sessions, so keyboards vanish
+//! after a sign-out. There a keyboard can be embedded: a copy of its km2 data
+//! is stored with its registration (the `Data` value of its registry key on
+//! Windows). The file is still preferred while it is there; the copy is only
+//! read when the file is missing, by the GUI and the text service alike.
+//!
+//! The registry is not meant for large values, so only keyboards smaller
+//! than [`MAX_EMBEDDED_SIZE`] can be embedded.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Keyboards must be smaller than this to be embedded
+pub const MAX_EMBEDDED_SIZE: usize = 256 * 1024;
+
+/// Where a keyboard's data is kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyboardStorage {
+    /// Only the file in the keyboards folder
+    #[default]
+    File,
+    /// The file, and a copy in the platform store for when it is missing
+    Embedded,
+}
+
+/// Error for a keyboard too large to be embedded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboardTooLarge {
+    pub keyboard_id: String,
+    pub size: usize,
+}
+
+impl std::fmt::Display for KeyboardTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Keyboard {} is {} KB; only keyboards smaller than {} KB can be stored in the registry",
+            self.keyboard_id,
+            self.size.div_ceil(1024),
+            MAX_EMBEDDED_SIZE / 1024
+        )
+    }
+}
+
+impl std::error::Error for KeyboardTooLarge {}
+
+/// Fails with `KeyboardTooLarge` unless `data` is small enough to embed
+pub fn check_size(keyboard_id: &str, data: &[u8]) -> Result<(), KeyboardTooLarge> {
+    if data.len() >= MAX_EMBEDDED_SIZE {
+        return Err(KeyboardTooLarge { keyboard_id: keyboard_id.to_string(), size: data.len() });
+    }
+    Ok(())
+}
+
+/// Reads the keyboard file at `path`, or the copy `embedded` returns if the
+/// file is missing; also returns which of the two was read
+pub fn read_keyboard_data(
+    path: &Path,
+    embedded: impl FnOnce() -> Option<Vec<u8>>,
+) -> Result<(Vec<u8>, KeyboardStorage)> {
+    match fs::read(path) {
+        Ok(data) => Ok((data, KeyboardStorage::File)),
+        Err(e) if e.kind() == ErrorKind::NotFound => match embedded() {
+            Some(data) => Ok((data, KeyboardStorage::Embedded)),
+            None => Err(e).context("Failed to read keyboard file"),
+        },
+        Err(e) => Err(e).context("Failed to read keyboard file"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic-embedded-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_file_is_preferred_over_the_copy() {
+        let dir = test_dir("prefer");
+        let path = dir.join("keyboard.km2");
+        fs::write(&path, b"file").unwrap();
+
+        let mut asked = false;
+        let (data, source) = read_keyboard_data(&path, || {
+            asked = true;
+            Some(b"copy".to_vec())
+        })
+        .unwrap();
+        assert_eq!((data.as_slice(), source), (&b"file"[..], KeyboardStorage::File));
+        assert!(!asked);
+
+        fs::remove_file(&path).unwrap();
+        let (data, source) = read_keyboard_data(&path, || Some(b"copy".to_vec())).unwrap();
+        assert_eq!((data.as_slice(), source), (&b"copy"[..], KeyboardStorage::Embedded));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_file_without_copy() {
+        let dir = test_dir("missing");
+        let path = dir.join("keyboard.km2");
+        let err = read_keyboard_data(&path, || None).unwrap_err();
+        let io = err.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(io.kind(), ErrorKind::NotFound);
+
+        // Only a missing file falls back; a folder in the way is an error
+        fs::create_dir_all(&path).unwrap();
+        assert!(read_keyboard_data(&path, || Some(b"copy".to_vec())).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_size_cap() {
+        assert!(check_size("small", &vec![0; MAX_EMBEDDED_SIZE - 1]).is_ok());
+        let err = check_size("large", &vec![0; MAX_EMBEDDED_SIZE + 1]).unwrap_err();
+        assert_eq!(err, KeyboardTooLarge { keyboard_id: "large".to_string(), size: MAX_EMBEDDED_SIZE + 1 });
+        assert_eq!(err.to_string(), "Keyboard large is 257 KB; only keyboards smaller than 256 KB can be stored in the registry");
+        assert!(check_size("exact", &vec![0; MAX_EMBEDDED_SIZE]).is_err());
+    }
+}
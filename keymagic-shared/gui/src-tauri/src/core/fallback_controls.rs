@@ -0,0 +1,289 @@
+//! Core controls for when the webview UI cannot load
+//!
+//! The settings UI is a webview; a broken WebView2 runtime or WebKitGTK
+//! leaves the window blank and the user with no way to switch keyboards.
+//! The frontend emits [`FRONTEND_READY_EVENT`] once its script runs, and a
+//! [`FrontendWatchdog`] waits [`FRONTEND_READY_TIMEOUT`] for it. Without it
+//! (or with [`NATIVE_UI_FLAG`] on the command line) a native window takes
+//! over with the basics: the keyboard list, activating a keyboard, turning
+//! key processing on and off, opening the keyboards folder and quitting.
+//!
+//! The window only draws a [`FallbackView`] and sends [`FallbackAction`]s;
+//! applying them goes through the same manager calls as the commands, and
+//! [`FallbackOutcome::events`] lists the events the commands emit.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use super::KeyboardManager;
+
+/// Event the frontend emits once its script has started
+pub const FRONTEND_READY_EVENT: &str = "frontend_ready";
+
+/// How long the webview gets to emit [`FRONTEND_READY_EVENT`]
+pub const FRONTEND_READY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Command line flag that opens the native window instead of the webview
+pub const NATIVE_UI_FLAG: &str = "--native-ui";
+
+/// Whether the command line asks for the native window
+pub fn native_ui_requested(args: &[String]) -> bool {
+    args.iter().skip(1).any(|arg| arg == NATIVE_UI_FLAG)
+}
+
+/// Waits for the frontend to report that it loaded
+#[derive(Debug, Default)]
+pub struct FrontendWatchdog {
+    ready: Mutex<bool>,
+    signal: Condvar,
+}
+
+impl FrontendWatchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the frontend loaded and wakes the waiting thread
+    pub fn mark_ready(&self) {
+        *self.ready.lock().unwrap() = true;
+        self.signal.notify_all();
+    }
+
+    pub fn is_ready(&self) -> bool {
+        *self.ready.lock().unwrap()
+    }
+
+    /// Blocks until the frontend loads or `timeout` passes; true when it loaded
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let ready = self.ready.lock().unwrap();
+        let (ready, _) = self.signal.wait_timeout_while(ready, timeout, |ready| !*ready).unwrap();
+        *ready
+    }
+}
+
+/// A keyboard as the native window lists it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FallbackKeyboard {
+    pub id: String,
+    pub name: String,
+    pub is_active: bool,
+    pub enabled: bool,
+}
+
+impl FallbackKeyboard {
+    /// List entry text; disabled keyboards say so
+    pub fn label(&self) -> String {
+        if self.enabled {
+            self.name.clone()
+        } else {
+            format!("{} (disabled)", self.name)
+        }
+    }
+}
+
+/// Everything the native window shows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FallbackView {
+    pub keyboards: Vec<FallbackKeyboard>,
+    pub key_processing_enabled: bool,
+}
+
+impl FallbackView {
+    pub fn load(manager: &KeyboardManager) -> Self {
+        let keyboards = manager
+            .get_keyboards()
+            .into_iter()
+            .map(|kb| FallbackKeyboard { id: kb.id, name: kb.name, is_active: kb.is_active, enabled: kb.enabled })
+            .collect();
+        Self { keyboards, key_processing_enabled: manager.is_key_processing_enabled() }
+    }
+
+    /// Index of the active keyboard in the list
+    pub fn active_index(&self) -> Option<usize> {
+        self.keyboards.iter().position(|kb| kb.is_active)
+    }
+
+    /// Caption of the button that toggles key processing
+    pub fn toggle_label(&self) -> &'static str {
+        if self.key_processing_enabled {
+            "Turn Key Processing Off"
+        } else {
+            "Turn Key Processing On"
+        }
+    }
+}
+
+/// What the user asked the native window for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FallbackAction {
+    Activate(String),
+    ToggleKeyProcessing,
+    OpenKeyboardsFolder,
+    Quit,
+}
+
+/// What applying an action changed, for the window to act on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FallbackOutcome {
+    ActiveKeyboardChanged(String),
+    /// Turning processing on can activate a keyboard as well
+    KeyProcessingChanged { enabled: bool, activated: Option<String> },
+    /// The folder to open in the file manager
+    OpenFolder(PathBuf),
+    Quit,
+}
+
+impl FallbackAction {
+    /// Makes the change through the manager, as the matching command does
+    pub fn apply(self, manager: &KeyboardManager) -> Result<FallbackOutcome> {
+        Ok(match self {
+            FallbackAction::Activate(keyboard_id) => {
+                manager.set_active_keyboard(&keyboard_id)?;
+                FallbackOutcome::ActiveKeyboardChanged(keyboard_id)
+            }
+            FallbackAction::ToggleKeyProcessing => {
+                let enabled = !manager.is_key_processing_enabled();
+                let activated = manager.set_key_processing_enabled(enabled)?;
+                FallbackOutcome::KeyProcessingChanged { enabled, activated }
+            }
+            FallbackAction::OpenKeyboardsFolder => {
+                FallbackOutcome::OpenFolder(manager.get_platform().get_keyboards_dir())
+            }
+            FallbackAction::Quit => FallbackOutcome::Quit,
+        })
+    }
+}
+
+impl FallbackOutcome {
+    /// Events the commands emit for the same change, in order, so a webview
+    /// that loads late still shows the right state
+    pub fn events(&self) -> Vec<(&'static str, Value)> {
+        match self {
+            FallbackOutcome::ActiveKeyboardChanged(keyboard_id) => vec![("active_keyboard_changed", json!(keyboard_id))],
+            FallbackOutcome::KeyProcessingChanged { enabled, activated } => {
+                let mut events = vec![("key_processing_changed", json!(enabled))];
+                if let Some(keyboard_id) = activated {
+                    events.push(("active_keyboard_changed", json!(keyboard_id)));
+                }
+                events
+            }
+            FallbackOutcome::OpenFolder(_) | FallbackOutcome::Quit => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::MockPlatform;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    fn manager(name: &str) -> KeyboardManager {
+        let platform = MockPlatform::builder(&format!("fallback-{}", name))
+            .keyboard("myanmar3")
+            .keyboard("zawgyi")
+            .active("myanmar3")
+            .build();
+        let manager = KeyboardManager::new(Box::new(platform));
+        manager.initialize().unwrap();
+        manager
+    }
+
+    #[test]
+    fn test_native_ui_flag() {
+        let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(native_ui_requested(&args(&["keymagic", "--native-ui"])));
+        assert!(!native_ui_requested(&args(&["keymagic"])));
+        // The program path is not a flag
+        assert!(!native_ui_requested(&args(&["--native-ui"])));
+    }
+
+    #[test]
+    fn test_watchdog_times_out_without_the_frontend() {
+        let watchdog = FrontendWatchdog::new();
+        let start = Instant::now();
+        assert!(!watchdog.wait(Duration::from_millis(50)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(!watchdog.is_ready());
+    }
+
+    #[test]
+    fn test_watchdog_wakes_when_the_frontend_loads() {
+        let watchdog = Arc::new(FrontendWatchdog::new());
+        let frontend = {
+            let watchdog = watchdog.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                watchdog.mark_ready();
+            })
+        };
+        assert!(watchdog.wait(Duration::from_secs(10)));
+        frontend.join().unwrap();
+        // Ready stays ready
+        assert!(watchdog.wait(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_view_lists_keyboards_and_processing() {
+        let manager = manager("view");
+        let view = FallbackView::load(&manager);
+        let ids: Vec<_> = view.keyboards.iter().map(|kb| kb.id.as_str()).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&"myanmar3") && ids.contains(&"zawgyi"));
+        let active = &view.keyboards[view.active_index().unwrap()];
+        assert_eq!(active.id, "myanmar3");
+        assert_eq!(active.label(), active.name);
+        let disabled = FallbackKeyboard { enabled: false, ..active.clone() };
+        assert_eq!(disabled.label(), format!("{} (disabled)", active.name));
+        assert_eq!(view.key_processing_enabled, manager.is_key_processing_enabled());
+    }
+
+    #[test]
+    fn test_activate_goes_through_the_manager() {
+        let manager = manager("activate");
+        let outcome = FallbackAction::Activate("zawgyi".into()).apply(&manager).unwrap();
+        assert_eq!(outcome, FallbackOutcome::ActiveKeyboardChanged("zawgyi".into()));
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("zawgyi"));
+        assert_eq!(outcome.events(), vec![("active_keyboard_changed", json!("zawgyi"))]);
+
+        let view = FallbackView::load(&manager);
+        assert_eq!(view.keyboards[view.active_index().unwrap()].id, "zawgyi");
+
+        // Unknown keyboards are refused and change nothing
+        assert!(FallbackAction::Activate("missing".into()).apply(&manager).is_err());
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("zawgyi"));
+    }
+
+    #[test]
+    fn test_toggle_flips_key_processing() {
+        let manager = manager("toggle");
+        let before = manager.is_key_processing_enabled();
+
+        let outcome = FallbackAction::ToggleKeyProcessing.apply(&manager).unwrap();
+        assert_eq!(manager.is_key_processing_enabled(), !before);
+        let FallbackOutcome::KeyProcessingChanged { enabled, .. } = &outcome else {
+            panic!("unexpected outcome {:?}", outcome);
+        };
+        assert_eq!(*enabled, !before);
+        assert_eq!(outcome.events()[0], ("key_processing_changed", json!(!before)));
+        let label = if before { "Turn Key Processing On" } else { "Turn Key Processing Off" };
+        assert_eq!(FallbackView::load(&manager).toggle_label(), label);
+
+        FallbackAction::ToggleKeyProcessing.apply(&manager).unwrap();
+        assert_eq!(manager.is_key_processing_enabled(), before);
+    }
+
+    #[test]
+    fn test_folder_and_quit_leave_state_alone() {
+        let manager = manager("folder");
+        let outcome = FallbackAction::OpenKeyboardsFolder.apply(&manager).unwrap();
+        assert_eq!(outcome, FallbackOutcome::OpenFolder(manager.get_platform().get_keyboards_dir()));
+        assert!(outcome.events().is_empty());
+        assert_eq!(FallbackAction::Quit.apply(&manager).unwrap(), FallbackOutcome::Quit);
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("myanmar3"));
+    }
+}
@@ -0,0 +1,221 @@
+//! Keyboard file hashes, cached by file size and modification time
+//!
+//! Every keyboard file is hashed at startup to check the store and the
+//! bundled keyboards, which reads them all and takes seconds on slow disks.
+//! The hashes are kept in the [`SETTING`] setting with the size and
+//! modification time of the file they were taken from, and a file that
+//! still has both is not read again. Files whose modification time the
+//! platform does not report, reports in the future, or reports within
+//! [`MTIME_GRANULARITY`] of now (another write could land in the same tick)
+//! are hashed every time instead. A cache that does not parse is dropped and
+//! rebuilt.
+
+use anyhow::Result;
+use keymagic_core::km2::content_hash;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Setting holding the cache as JSON
+pub const SETTING: &str = "keyboard_file_hashes";
+
+/// Coarsest modification time resolution of the file systems keyboards live
+/// on (FAT has 2 seconds)
+pub const MTIME_GRANULARITY: Duration = Duration::from_secs(2);
+
+/// Size and modification time of a file, in milliseconds since the epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub size: u64,
+    pub mtime_ms: u64,
+}
+
+impl FileStamp {
+    /// Stamp of a file, or `None` when its modification time cannot tell a
+    /// change apart at `now`
+    pub fn of(metadata: &fs::Metadata, now: SystemTime) -> Option<Self> {
+        let modified = metadata.modified().ok()?;
+        if modified == UNIX_EPOCH || modified + MTIME_GRANULARITY > now {
+            return None;
+        }
+        let mtime_ms = modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as u64;
+        Some(Self { size: metadata.len(), mtime_ms })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedHash {
+    #[serde(flatten)]
+    stamp: FileStamp,
+    hash: String,
+}
+
+/// Hashes by file path
+#[derive(Debug, Default)]
+pub struct FileHashCache {
+    entries: BTreeMap<String, CachedHash>,
+    /// Changed since loaded or saved
+    dirty: bool,
+}
+
+impl FileHashCache {
+    /// Parses the setting; a missing or corrupt one gives an empty cache
+    pub fn from_setting(json: Option<&str>) -> Self {
+        let entries = match json.map(serde_json::from_str) {
+            Some(Ok(entries)) => entries,
+            Some(Err(e)) => {
+                log::warn!("Keyboard file hash cache is corrupt, hashing every file again: {}", e);
+                return Self { entries: BTreeMap::new(), dirty: true };
+            }
+            None => BTreeMap::new(),
+        };
+        Self { entries, dirty: false }
+    }
+
+    pub fn to_setting(&self) -> String {
+        serde_json::to_string(&self.entries).unwrap_or_default()
+    }
+
+    /// Hash of the file at `path`, read again only when its stamp changed
+    pub fn hash(&mut self, path: &Path, now: SystemTime) -> Result<String> {
+        let key = path.to_string_lossy().into_owned();
+        let stamp = FileStamp::of(&fs::metadata(path)?, now);
+        if let (Some(stamp), Some(cached)) = (stamp, self.entries.get(&key)) {
+            if cached.stamp == stamp {
+                return Ok(cached.hash.clone());
+            }
+        }
+
+        let hash = content_hash(&fs::read(path)?);
+        match stamp {
+            Some(stamp) => {
+                self.entries.insert(key, CachedHash { stamp, hash: hash.clone() });
+                self.dirty = true;
+            }
+            None => {
+                self.dirty |= self.entries.remove(&key).is_some();
+            }
+        }
+        Ok(hash)
+    }
+
+    pub fn clear(&mut self) {
+        self.dirty |= !self.entries.is_empty();
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the cache changed since the last call, dropping the entries
+    /// of files that are gone first
+    pub fn take_changes(&mut self) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| Path::new(path).exists());
+        let changed = self.dirty || self.entries.len() != before;
+        self.dirty = false;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic-file-hashes-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        set_mtime(&path, 1_000);
+        path
+    }
+
+    /// Dates the file `secs` seconds after the epoch
+    fn set_mtime(path: &Path, secs: u64) {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+    }
+
+    fn now() -> SystemTime {
+        SystemTime::now()
+    }
+
+    #[test]
+    fn test_unchanged_files_are_not_read_again() {
+        let path = temp_file("hit.km2", b"keyboard");
+        let mut cache = FileHashCache::default();
+        assert_eq!(cache.hash(&path, now()).unwrap(), content_hash(b"keyboard"));
+        assert!(cache.take_changes());
+
+        // A planted hash shows the file is not read
+        let key = path.to_string_lossy().into_owned();
+        cache.entries.get_mut(&key).unwrap().hash = "cached".to_string();
+        assert_eq!(cache.hash(&path, now()).unwrap(), "cached");
+        assert!(!cache.take_changes());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_modification_time_change_rehashes() {
+        let path = temp_file("mtime.km2", b"keyboard");
+        let mut cache = FileHashCache::default();
+        cache.hash(&path, now()).unwrap();
+        let key = path.to_string_lossy().into_owned();
+        cache.entries.get_mut(&key).unwrap().hash = "stale".to_string();
+
+        set_mtime(&path, 2_000);
+        assert_eq!(cache.hash(&path, now()).unwrap(), content_hash(b"keyboard"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_same_size_content_change_is_seen() {
+        let path = temp_file("content.km2", b"version1");
+        let mut cache = FileHashCache::default();
+        assert_eq!(cache.hash(&path, now()).unwrap(), content_hash(b"version1"));
+
+        fs::write(&path, b"version2").unwrap();
+        set_mtime(&path, 1_500);
+        assert_eq!(cache.hash(&path, now()).unwrap(), content_hash(b"version2"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recent_modification_times_are_not_trusted() {
+        let path = temp_file("recent.km2", b"keyboard");
+        let mut cache = FileHashCache::default();
+        // Seen from just after the write, the file may still change within the tick
+        let just_after = UNIX_EPOCH + Duration::from_secs(1_001);
+        assert_eq!(cache.hash(&path, just_after).unwrap(), content_hash(b"keyboard"));
+        assert!(cache.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_setting_is_rebuilt() {
+        let path = temp_file("corrupt.km2", b"keyboard");
+        let mut cache = FileHashCache::from_setting(Some("{not json"));
+        assert!(cache.is_empty());
+        assert_eq!(cache.hash(&path, now()).unwrap(), content_hash(b"keyboard"));
+        assert!(cache.take_changes());
+
+        let reloaded = FileHashCache::from_setting(Some(&cache.to_setting()));
+        assert_eq!(reloaded.len(), 1);
+        assert_eq!(reloaded.entries, cache.entries);
+
+        // Entries of files that are gone are dropped
+        fs::remove_file(&path).unwrap();
+        let mut reloaded = reloaded;
+        assert!(reloaded.take_changes());
+        assert!(reloaded.is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Clashes between a keyboard's own hotkey and hotkeys already in use
+//!
+//! Keyboards often declare hotkeys like Ctrl+Shift+M that another installed
+//! keyboard, the on/off hotkey or the system already owns. An import leaves
+//! such a hotkey off and reports the clash, with a free variant to offer.
+
+use keymagic_core::hotkey::HotkeyBinding;
+use keymagic_core::VirtualKey;
+use serde::Serialize;
+
+/// Shortcuts of the system and of most applications, with what they do
+pub const RESERVED_HOTKEYS: &[(&str, &str)] = &[
+    ("CTRL+SHIFT+ESC", "Task Manager"),
+    ("CTRL+ALT+DELETE", "Security screen"),
+    ("ALT+F4", "Close window"),
+    ("ALT+TAB", "Switch windows"),
+    ("META+L", "Lock screen"),
+    ("CTRL+META+Q", "Lock screen"),
+    ("META+SPACE", "Input source or search"),
+    ("CTRL+ALT+T", "Open terminal"),
+    ("CTRL+A", "Select all"),
+    ("CTRL+C", "Copy"),
+    ("CTRL+V", "Paste"),
+    ("CTRL+X", "Cut"),
+    ("CTRL+Z", "Undo"),
+    ("CTRL+S", "Save"),
+];
+
+/// What a hotkey is already used for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HotkeyConflict {
+    /// Another keyboard's hotkey
+    Keyboard { keyboard_id: String, name: String },
+    /// The hotkey turning key processing on and off
+    OnOff,
+    /// A system or application shortcut
+    Reserved { description: String },
+}
+
+/// A keyboard's own hotkey left off at import because it is taken
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HotkeyConflictInfo {
+    pub hotkey: String,
+    pub conflict: HotkeyConflict,
+    /// Free variant of the hotkey to offer instead
+    pub proposed: Option<String>,
+}
+
+/// Hotkeys taken, reserved shortcuts included
+#[derive(Debug, Clone)]
+pub struct HotkeysInUse {
+    taken: Vec<(HotkeyBinding, HotkeyConflict)>,
+}
+
+impl HotkeysInUse {
+    /// Starts with the reserved shortcuts
+    pub fn new() -> Self {
+        let mut in_use = Self { taken: Vec::new() };
+        for (hotkey, description) in RESERVED_HOTKEYS {
+            in_use.add(hotkey, HotkeyConflict::Reserved { description: description.to_string() });
+        }
+        in_use
+    }
+
+    /// Marks a hotkey as taken; ones that do not parse cannot clash and are
+    /// skipped
+    pub fn add(&mut self, hotkey: &str, conflict: HotkeyConflict) {
+        if let Ok(binding) = HotkeyBinding::parse(hotkey) {
+            self.taken.push((binding, conflict));
+        }
+    }
+
+    /// What `hotkey` would clash with; the first use added wins
+    pub fn conflict(&self, hotkey: &str) -> Option<HotkeyConflict> {
+        let binding = HotkeyBinding::parse(hotkey).ok()?;
+        self.taken
+            .iter()
+            .find(|(taken, _)| taken.overlaps(&binding))
+            .map(|(_, conflict)| conflict.clone())
+    }
+
+    /// The first variant of `hotkey` that is free and passes `is_valid`:
+    /// combinations get Alt, Shift or Ctrl added, one and then two at a
+    /// time; double-taps move to one side of the modifier
+    pub fn propose_free(&self, hotkey: &str, is_valid: impl Fn(&str) -> bool) -> Option<String> {
+        variants(hotkey)
+            .into_iter()
+            .find(|candidate| self.conflict(candidate).is_none() && is_valid(candidate))
+    }
+}
+
+impl Default for HotkeysInUse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Candidate replacements for `hotkey`, in the order they are offered
+fn variants(hotkey: &str) -> Vec<String> {
+    match HotkeyBinding::parse(hotkey) {
+        Ok(HotkeyBinding::Combo(combo)) => {
+            // The key as written, so names like "OEM_1" stay as they were
+            let Some(key) = hotkey
+                .split(['+', ' '])
+                .map(|part| part.trim().to_uppercase())
+                .filter(|part| !part.is_empty())
+                .find(|part| !is_modifier_name(part))
+            else {
+                return Vec::new();
+            };
+            let held = [combo.ctrl, combo.alt, combo.shift];
+            let missing: Vec<usize> = [1, 2, 0].into_iter().filter(|&i| !held[i]).collect();
+
+            let mut added: Vec<Vec<usize>> = missing.iter().map(|&i| vec![i]).collect();
+            for (n, &first) in missing.iter().enumerate() {
+                for &second in &missing[n + 1..] {
+                    added.push(vec![first, second]);
+                }
+            }
+            added
+                .into_iter()
+                .map(|extra| {
+                    let mut modifiers = held;
+                    for i in extra {
+                        modifiers[i] = true;
+                    }
+                    let mut parts: Vec<&str> = ["CTRL", "ALT", "SHIFT"]
+                        .into_iter()
+                        .zip(modifiers)
+                        .filter_map(|(name, on)| on.then_some(name))
+                        .collect();
+                    if combo.meta {
+                        parts.push("META");
+                    }
+                    parts.push(&key);
+                    parts.join("+")
+                })
+                .collect()
+        }
+        Ok(HotkeyBinding::DoubleTap(key)) => {
+            let sides = match key {
+                VirtualKey::Shift | VirtualKey::LShift | VirtualKey::RShift => [VirtualKey::RShift, VirtualKey::LShift],
+                VirtualKey::Control | VirtualKey::LControl | VirtualKey::RControl => {
+                    [VirtualKey::RControl, VirtualKey::LControl]
+                }
+                _ => [VirtualKey::RMenu, VirtualKey::LMenu],
+            };
+            sides
+                .into_iter()
+                .filter(|side| *side != key)
+                .map(|side| format!("double:{}", side.to_display_string()))
+                .collect()
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+fn is_modifier_name(part: &str) -> bool {
+    matches!(
+        part,
+        "CTRL" | "CONTROL" | "ALT" | "OPTION" | "SHIFT" | "META" | "CMD" | "COMMAND" | "WIN" | "SUPER"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyboard(id: &str) -> HotkeyConflict {
+        HotkeyConflict::Keyboard { keyboard_id: id.to_string(), name: id.to_string() }
+    }
+
+    fn crowded() -> HotkeysInUse {
+        let mut in_use = HotkeysInUse::new();
+        in_use.add("CTRL+SHIFT+M", keyboard("myanmar3"));
+        in_use.add("CTRL+ALT+SHIFT+M", keyboard("zawgyi"));
+        in_use.add("CTRL+ALT+M", keyboard("pali"));
+        in_use.add("Shift Shift", HotkeyConflict::OnOff);
+        in_use.add("not a hotkey", keyboard("broken"));
+        in_use
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let in_use = crowded();
+        assert_eq!(in_use.conflict("ctrl shift m"), Some(keyboard("myanmar3")));
+        assert_eq!(in_use.conflict("double:LShift"), Some(HotkeyConflict::OnOff));
+        assert_eq!(
+            in_use.conflict("Ctrl+Shift+Esc"),
+            Some(HotkeyConflict::Reserved { description: "Task Manager".to_string() })
+        );
+        assert_eq!(in_use.conflict("CTRL+SHIFT+K"), None);
+        assert_eq!(in_use.conflict("Ctrl Ctrl"), None);
+        assert_eq!(in_use.conflict("not a hotkey"), None);
+    }
+
+    #[test]
+    fn test_proposals_add_modifiers() {
+        let in_use = HotkeysInUse::new();
+        assert_eq!(in_use.propose_free("CTRL+SHIFT+Z", |_| true), Some("CTRL+ALT+SHIFT+Z".to_string()));
+        assert_eq!(in_use.propose_free("ctrl+c", |_| true), Some("CTRL+ALT+C".to_string()));
+
+        // Alt+M, Shift+M and Ctrl+M are taken or refused in turn
+        let in_use = crowded();
+        assert_eq!(in_use.propose_free("CTRL+SHIFT+M", |_| true), None);
+        assert_eq!(in_use.propose_free("M", |_| true), Some("ALT+M".to_string()));
+        assert_eq!(in_use.propose_free("M", |hotkey| !hotkey.starts_with("ALT")), Some("SHIFT+M".to_string()));
+        assert_eq!(in_use.propose_free("ALT+M", |hotkey| hotkey != "ALT+SHIFT+M"), None);
+        assert_eq!(in_use.propose_free("ALT+M", |_| true), Some("ALT+SHIFT+M".to_string()));
+    }
+
+    #[test]
+    fn test_proposals_keep_meta_and_key_names() {
+        let in_use = HotkeysInUse::new();
+        assert_eq!(in_use.propose_free("META+L", |_| true), Some("ALT+META+L".to_string()));
+        assert_eq!(in_use.propose_free("ctrl+f5", |_| true), Some("CTRL+ALT+F5".to_string()));
+        assert_eq!(in_use.propose_free("invalid+", |_| true), None);
+    }
+
+    #[test]
+    fn test_double_tap_proposals() {
+        let mut in_use = HotkeysInUse::new();
+        in_use.add("double:RShift", HotkeyConflict::OnOff);
+        assert_eq!(in_use.propose_free("Shift Shift", |_| true), Some("double:LShift".to_string()));
+        assert_eq!(in_use.propose_free("Ctrl Ctrl", |_| true), Some("double:RCtrl".to_string()));
+
+        // Either side of a taken generic double-tap clashes with it
+        let in_use = crowded();
+        assert_eq!(in_use.propose_free("double:RShift", |_| true), None);
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::Result;
+use keymagic_core::processing_state::{HeartbeatStatus, ProcessingState, HEARTBEAT_INTERVAL, HEARTBEAT_STALE_AFTER};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// What happened when a keyboard hotkey was pressed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HotkeyActivation {
+    /// The keyboard is now active
+    Switched,
+    /// Key processing was off and has been turned on for the keyboard
+    Enabled,
+    /// Key processing is off; the keyboard becomes active once it is turned on
+    Pending { message: String },
+    /// Too many switches in a row, as in a hotkey loop; nothing changed
+    Suppressed,
+}
+
+/// How the manager should handle a keyboard hotkey
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyDecision {
+    Switch,
+    EnableAndSwitch,
+    Defer,
+}
+
+/// Global on/off state of key processing and the keyboard requested while off
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyProcessingState {
+    enabled: bool,
+    pending: Option<String>,
+}
+
+impl Default for KeyProcessingState {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+impl KeyProcessingState {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, pending: None }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Keyboard to activate when processing is turned back on
+    pub fn pending_keyboard(&self) -> Option<&str> {
+        self.pending.as_deref()
+    }
+
+    /// Handles a hotkey for `keyboard_id`; while disabled the keyboard is
+    /// remembered unless `auto_enable` turns processing on
+    pub fn keyboard_hotkey(&mut self, keyboard_id: &str, auto_enable: bool) -> HotkeyDecision {
+        if self.enabled {
+            return HotkeyDecision::Switch;
+        }
+        if auto_enable {
+            self.enabled = true;
+            self.pending = None;
+            return HotkeyDecision::EnableAndSwitch;
+        }
+        self.pending = Some(keyboard_id.to_string());
+        HotkeyDecision::Defer
+    }
+
+    /// Turns processing on or off; turning it on hands back the pending keyboard
+    pub fn set_enabled(&mut self, enabled: bool) -> Option<String> {
+        self.enabled = enabled;
+        if enabled {
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+
+    /// Forgets the pending keyboard if it is `keyboard_id` (e.g. it was removed)
+    pub fn forget_keyboard(&mut self, keyboard_id: &str) {
+        if self.pending.as_deref() == Some(keyboard_id) {
+            self.pending = None;
+        }
+    }
+}
+
+/// HUD text shown when a keyboard hotkey is pressed while processing is off
+pub fn disabled_hotkey_message(on_off_hotkey: Option<&str>) -> String {
+    match on_off_hotkey.filter(|hotkey| !hotkey.is_empty()) {
+        Some(hotkey) => format!("KeyMagic is off — press {} to enable", hotkey),
+        None => "KeyMagic is off — turn it on in KeyMagic to enable".to_string(),
+    }
+}
+
+/// HUD text shown while an input method is still committing its composition
+pub const FINISHING_COMPOSITION_MESSAGE: &str = "Finishing composition…";
+
+/// Flips the switch the input methods read. Turning processing off waits up
+/// to `timeout` for one of them to commit its composition and stand down;
+/// returns false if none confirmed in time. `signal` wakes them up.
+pub fn switch_input_methods(
+    state: &ProcessingState,
+    enabled: bool,
+    timeout: Duration,
+    signal: impl FnOnce() -> Result<()>,
+) -> Result<bool> {
+    if enabled {
+        state.enable();
+        signal()?;
+        return Ok(true);
+    }
+    let request = state.request_disable();
+    signal()?;
+    Ok(state.wait_for_ack(request, timeout))
+}
+
+/// Health of the heartbeat input methods watch to tell the GUI is running,
+/// for diagnostic bundles
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HeartbeatReport {
+    /// "missing", "fresh", "late" or "stale"; "unsupported" where input
+    /// methods do not watch it
+    pub status: &'static str,
+    pub age_ms: Option<u64>,
+    pub interval_ms: u64,
+    pub stale_after_ms: u64,
+}
+
+impl HeartbeatReport {
+    /// Report for the last heartbeat read from the switch, `None` where
+    /// the platform has none
+    pub fn new(heartbeat: Option<Option<u64>>, now_ms: u64) -> Self {
+        let (status, age) = match heartbeat.map(|heartbeat| HeartbeatStatus::at(heartbeat, now_ms)) {
+            None => ("unsupported", None),
+            Some(status @ HeartbeatStatus::Missing) => ("missing", status.age()),
+            Some(status @ HeartbeatStatus::Fresh { .. }) => ("fresh", status.age()),
+            Some(status @ HeartbeatStatus::Late { .. }) => ("late", status.age()),
+            Some(status @ HeartbeatStatus::Stale { .. }) => ("stale", status.age()),
+        };
+        Self {
+            status,
+            age_ms: age.map(|age| age.as_millis() as u64),
+            interval_ms: HEARTBEAT_INTERVAL.as_millis() as u64,
+            stale_after_ms: HEARTBEAT_STALE_AFTER.as_millis() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keymagic_core::processing_state::{HostAction, HostProcessingState};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_hotkey_while_enabled_switches() {
+        let mut state = KeyProcessingState::default();
+        assert_eq!(state.keyboard_hotkey("zawgyi", false), HotkeyDecision::Switch);
+        assert_eq!(state.pending_keyboard(), None);
+    }
+
+    #[test]
+    fn test_pending_keyboard_is_handed_off_when_enabled() {
+        let mut state = KeyProcessingState::new(false);
+        assert_eq!(state.keyboard_hotkey("zawgyi", false), HotkeyDecision::Defer);
+        assert_eq!(state.keyboard_hotkey("myanmar3", false), HotkeyDecision::Defer);
+        assert_eq!(state.pending_keyboard(), Some("myanmar3"));
+        assert!(!state.is_enabled());
+
+        assert_eq!(state.set_enabled(true), Some("myanmar3".to_string()));
+        assert!(state.is_enabled());
+        assert_eq!(state.pending_keyboard(), None);
+
+        // Nothing is handed off twice
+        assert_eq!(state.set_enabled(false), None);
+        assert_eq!(state.set_enabled(true), None);
+    }
+
+    #[test]
+    fn test_disabling_keeps_pending_keyboard() {
+        let mut state = KeyProcessingState::new(false);
+        state.keyboard_hotkey("zawgyi", false);
+        assert_eq!(state.set_enabled(false), None);
+        assert_eq!(state.pending_keyboard(), Some("zawgyi"));
+    }
+
+    #[test]
+    fn test_auto_enable() {
+        let mut state = KeyProcessingState::new(false);
+        state.keyboard_hotkey("zawgyi", false);
+        assert_eq!(state.keyboard_hotkey("myanmar3", true), HotkeyDecision::EnableAndSwitch);
+        assert!(state.is_enabled());
+        assert_eq!(state.pending_keyboard(), None);
+    }
+
+    #[test]
+    fn test_forget_removed_keyboard() {
+        let mut state = KeyProcessingState::new(false);
+        state.keyboard_hotkey("zawgyi", false);
+        state.forget_keyboard("myanmar3");
+        assert_eq!(state.pending_keyboard(), Some("zawgyi"));
+        state.forget_keyboard("zawgyi");
+        assert_eq!(state.set_enabled(true), None);
+    }
+
+    #[test]
+    fn test_disabled_hotkey_message() {
+        assert_eq!(
+            disabled_hotkey_message(Some("Ctrl+Shift+Space")),
+            "KeyMagic is off — press Ctrl+Shift+Space to enable"
+        );
+        assert_eq!(disabled_hotkey_message(Some("")), disabled_hotkey_message(None));
+    }
+
+    #[test]
+    fn test_activation_serialization() {
+        let json = serde_json::to_value(HotkeyActivation::Pending { message: "off".to_string() }).unwrap();
+        assert_eq!(json["kind"], "pending");
+        assert_eq!(json["message"], "off");
+    }
+
+    #[test]
+    fn test_switch_waits_for_host() {
+        let state = Arc::new(ProcessingState::in_memory());
+        let host_state = state.clone();
+        let confirmed = switch_input_methods(&state, false, Duration::from_secs(5), move || {
+            // The host answers the signal from its own thread
+            std::thread::spawn(move || {
+                let mut host = HostProcessingState::new();
+                if let HostAction::FlushThenDisable { request } = host.observe(host_state.is_enabled(), host_state.request()) {
+                    host_state.acknowledge(host.finish_disable(request).unwrap());
+                }
+            });
+            Ok(())
+        })
+        .unwrap();
+        assert!(confirmed);
+        assert!(!state.is_enabled());
+
+        assert!(switch_input_methods(&state, true, Duration::ZERO, || Ok(())).unwrap());
+        assert!(state.is_enabled());
+    }
+
+    #[test]
+    fn test_switch_times_out_without_host() {
+        let state = ProcessingState::in_memory();
+        assert!(!switch_input_methods(&state, false, Duration::from_millis(10), || Ok(())).unwrap());
+        // Processing is off all the same; the host finishes when it wakes up
+        assert!(!state.is_enabled());
+        assert!(switch_input_methods(&state, false, Duration::ZERO, || Err(anyhow::anyhow!("no event"))).is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_report() {
+        let report = HeartbeatReport::new(Some(Some(9_000)), 10_000);
+        assert_eq!(report.status, "fresh");
+        assert_eq!(report.age_ms, Some(1_000));
+        assert_eq!(report.interval_ms, 2_000);
+
+        let stale = 10_000 + HEARTBEAT_STALE_AFTER.as_millis() as u64 + 1;
+        assert_eq!(HeartbeatReport::new(Some(Some(10_000)), stale).status, "stale");
+        assert_eq!(HeartbeatReport::new(Some(Some(10_000)), 15_000).status, "late");
+
+        let missing = HeartbeatReport::new(Some(None), 10_000);
+        assert_eq!(missing.status, "missing");
+        assert_eq!(missing.age_ms, None);
+        assert_eq!(HeartbeatReport::new(None, 10_000).status, "unsupported");
+    }
+}
@@ -0,0 +1,144 @@
+//! Loading a keyboard before it becomes active
+//!
+//! Text services load the active keyboard lazily in every application, so a
+//! keyboard file that does not load only shows up as typing that stopped
+//! working. Activation therefore loads and validates the file in the GUI
+//! first and refuses keyboards that fail, keeping the previous one active.
+
+use keymagic_core::km2::Km2Loader;
+use keymagic_core::{KeyMagicEngine, Km2File};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Why a keyboard failed to load on activation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivationFailure {
+    /// The keyboard file is gone
+    FileMissing,
+    /// The file exists but could not be read
+    Unreadable,
+    /// The file is not a keyboard the engine can run
+    Invalid,
+}
+
+/// Error for a keyboard that was not activated because its file failed to
+/// load; sent to the UI as the `keyboard_activation_failed` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyboardActivationError {
+    pub keyboard_id: String,
+    pub path: PathBuf,
+    pub reason: ActivationFailure,
+    /// Problems found while loading, most specific last
+    pub issues: Vec<String>,
+}
+
+impl std::fmt::Display for KeyboardActivationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what = match self.reason {
+            ActivationFailure::FileMissing => "its file is missing",
+            ActivationFailure::Unreadable => "its file could not be read",
+            ActivationFailure::Invalid => "its file is not a valid keyboard",
+        };
+        write!(f, "Keyboard {} was not activated because {}", self.keyboard_id, what)?;
+        if let Some(issue) = self.issues.last() {
+            write!(f, ": {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for KeyboardActivationError {}
+
+impl KeyboardActivationError {
+    fn new(keyboard_id: &str, path: &Path, reason: ActivationFailure, err: &(dyn std::error::Error + 'static)) -> Self {
+        Self { keyboard_id: keyboard_id.to_string(), path: path.to_path_buf(), reason, issues: error_chain(err) }
+    }
+}
+
+/// The messages of `err` and the errors under it, most specific last
+pub fn error_chain(err: &(dyn std::error::Error + 'static)) -> Vec<String> {
+    let mut issues = Vec::new();
+    let mut current = Some(err);
+    while let Some(err) = current {
+        issues.push(err.to_string());
+        current = err.source();
+    }
+    issues
+}
+
+struct CachedLayout {
+    modified: Option<SystemTime>,
+    len: u64,
+    layout: Arc<Km2File>,
+}
+
+/// Layouts that passed activation, reused while their file is unchanged
+#[derive(Default)]
+pub struct LayoutCache {
+    entries: HashMap<String, CachedLayout>,
+}
+
+impl LayoutCache {
+    /// Loads the keyboard file at `path` and builds an engine for it,
+    /// reusing the parsed layout if the file did not change. A missing file
+    /// is replaced by the copy `embedded` returns, if any.
+    pub fn prewarm(
+        &mut self,
+        keyboard_id: &str,
+        path: &Path,
+        embedded: impl FnOnce() -> Option<Vec<u8>>,
+    ) -> Result<KeyMagicEngine, KeyboardActivationError> {
+        let fail = |reason, err: &(dyn std::error::Error + 'static)| {
+            KeyboardActivationError::new(keyboard_id, path, reason, err)
+        };
+
+        let file_meta = match fs::metadata(path) {
+            Ok(file_meta) => file_meta,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // Embedded copies are small, and parsed again each time
+                self.entries.remove(keyboard_id);
+                let data = embedded().ok_or_else(|| fail(ActivationFailure::FileMissing, &e))?;
+                let layout = Km2Loader::load(&data).map_err(|e| fail(ActivationFailure::Invalid, &e))?;
+                return KeyMagicEngine::new(layout).map_err(|e| fail(ActivationFailure::Invalid, &e));
+            }
+            Err(e) => return Err(fail(io_failure(&e), &e)),
+        };
+        let modified = file_meta.modified().ok();
+        let len = file_meta.len();
+        let fresh = self
+            .entries
+            .get(keyboard_id)
+            .is_some_and(|cached| cached.modified == modified && cached.len == len);
+
+        let layout = if fresh {
+            self.entries[keyboard_id].layout.clone()
+        } else {
+            self.entries.remove(keyboard_id);
+            let data = fs::read(path).map_err(|e| fail(io_failure(&e), &e))?;
+            Arc::new(Km2Loader::load(&data).map_err(|e| fail(ActivationFailure::Invalid, &e))?)
+        };
+
+        let engine = KeyMagicEngine::new((*layout).clone()).map_err(|e| fail(ActivationFailure::Invalid, &e))?;
+        if !fresh {
+            self.entries.insert(keyboard_id.to_string(), CachedLayout { modified, len, layout });
+        }
+        Ok(engine)
+    }
+
+    pub fn invalidate(&mut self, keyboard_id: &str) {
+        self.entries.remove(keyboard_id);
+    }
+}
+
+fn io_failure(err: &std::io::Error) -> ActivationFailure {
+    if err.kind() == std::io::ErrorKind::NotFound {
+        ActivationFailure::FileMissing
+    } else {
+        ActivationFailure::Unreadable
+    }
+}
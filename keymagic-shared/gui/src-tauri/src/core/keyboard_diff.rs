@@ -0,0 +1,283 @@
+use anyhow::Result;
+use keymagic_core::km2::RuleFormatter;
+use keymagic_core::{KeyMagicEngine, Km2File};
+use keymagic_core::types::km2::Metadata;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::layout_preview::{compute_key_map, KeyMapping};
+
+/// A single scalar field that differs between two keyboard versions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// A rule whose LHS exists in both versions but produces a different RHS
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModifiedRule {
+    pub lhs: String,
+    pub old_rhs: String,
+    pub new_rhs: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<ModifiedRule>,
+}
+
+/// Output change for one key/shift combination on the layout preview
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyOutputChange {
+    pub key: String,
+    pub shifted: bool,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyboardDiff {
+    pub metadata: Vec<FieldChange>,
+    pub layout_options: Vec<FieldChange>,
+    pub variables: Vec<FieldChange>,
+    pub rules: RuleDiff,
+    pub keys: Vec<KeyOutputChange>,
+}
+
+impl KeyboardDiff {
+    /// Returns true when no difference was detected
+    pub fn is_empty(&self) -> bool {
+        self.metadata.is_empty()
+            && self.layout_options.is_empty()
+            && self.variables.is_empty()
+            && self.rules.added.is_empty()
+            && self.rules.removed.is_empty()
+            && self.rules.modified.is_empty()
+            && self.keys.is_empty()
+    }
+}
+
+/// Computes a structured diff between two loaded keyboard layouts
+pub fn diff_layouts(old: &Km2File, new: &Km2File) -> Result<KeyboardDiff> {
+    let mut old_engine = KeyMagicEngine::new(old.clone())?;
+    let mut new_engine = KeyMagicEngine::new(new.clone())?;
+    let old_keys = compute_key_map(&mut old_engine);
+    let new_keys = compute_key_map(&mut new_engine);
+
+    Ok(KeyboardDiff {
+        metadata: diff_metadata(&old.metadata(), &new.metadata()),
+        layout_options: diff_layout_options(old, new),
+        variables: diff_variables(old, new),
+        rules: diff_rules(old, new),
+        keys: diff_key_maps(&old_keys, &new_keys),
+    })
+}
+
+fn push_change(changes: &mut Vec<FieldChange>, field: &str, old: Option<String>, new: Option<String>) {
+    if old != new {
+        changes.push(FieldChange {
+            field: field.to_string(),
+            old,
+            new,
+        });
+    }
+}
+
+fn diff_metadata(old: &Metadata, new: &Metadata) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    push_change(&mut changes, "name", old.name(), new.name());
+    push_change(&mut changes, "description", old.description(), new.description());
+    push_change(&mut changes, "font_family", old.font_family(), new.font_family());
+    push_change(&mut changes, "hotkey", old.hotkey(), new.hotkey());
+
+    // Icons are binary, so only report their presence and size
+    let describe_icon = |icon: Option<&[u8]>| icon.map(|data| format!("{} bytes", data.len()));
+    if old.icon() != new.icon() {
+        changes.push(FieldChange {
+            field: "icon".to_string(),
+            old: describe_icon(old.icon()),
+            new: describe_icon(new.icon()),
+        });
+    }
+    changes
+}
+
+fn diff_layout_options(old: &Km2File, new: &Km2File) -> Vec<FieldChange> {
+    // Copy out of the packed header before borrowing
+    let old_opts = old.header.layout_options;
+    let new_opts = new.header.layout_options;
+    let flag = |v: u8| Some((v != 0).to_string());
+
+    let mut changes = Vec::new();
+    push_change(&mut changes, "track_caps", flag(old_opts.track_caps), flag(new_opts.track_caps));
+    push_change(&mut changes, "auto_bksp", flag(old_opts.auto_bksp), flag(new_opts.auto_bksp));
+    push_change(&mut changes, "eat", flag(old_opts.eat), flag(new_opts.eat));
+    push_change(&mut changes, "pos_based", flag(old_opts.pos_based), flag(new_opts.pos_based));
+    push_change(&mut changes, "right_alt", flag(old_opts.right_alt), flag(new_opts.right_alt));
+    changes
+}
+
+fn diff_variables(old: &Km2File, new: &Km2File) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    for i in 0..old.strings.len().max(new.strings.len()) {
+        push_change(
+            &mut changes,
+            &format!("$var{}", i + 1),
+            old.strings.get(i).map(|s| s.value.clone()),
+            new.strings.get(i).map(|s| s.value.clone()),
+        );
+    }
+    changes
+}
+
+/// Groups formatted rules by LHS, keeping every RHS so duplicate LHS are preserved
+fn rules_by_lhs(km2: &Km2File) -> BTreeMap<String, Vec<String>> {
+    let formatter = RuleFormatter::new(&km2.strings);
+    let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for rule in &km2.rules {
+        map.entry(formatter.format_elements(&rule.lhs))
+            .or_default()
+            .push(formatter.format_elements(&rule.rhs));
+    }
+    map
+}
+
+fn diff_rules(old: &Km2File, new: &Km2File) -> RuleDiff {
+    let old_rules = rules_by_lhs(old);
+    let new_rules = rules_by_lhs(new);
+    let mut diff = RuleDiff::default();
+
+    for (lhs, old_rhs_list) in &old_rules {
+        match new_rules.get(lhs) {
+            None => {
+                for rhs in old_rhs_list {
+                    diff.removed.push(format!("{} => {}", lhs, rhs));
+                }
+            }
+            Some(new_rhs_list) => {
+                // Pair up RHS entries that changed; leftovers are additions/removals
+                let old_only: Vec<&String> = old_rhs_list.iter().filter(|r| !new_rhs_list.contains(r)).collect();
+                let new_only: Vec<&String> = new_rhs_list.iter().filter(|r| !old_rhs_list.contains(r)).collect();
+                for pair in old_only.iter().zip(new_only.iter()) {
+                    diff.modified.push(ModifiedRule {
+                        lhs: lhs.clone(),
+                        old_rhs: pair.0.to_string(),
+                        new_rhs: pair.1.to_string(),
+                    });
+                }
+                for rhs in old_only.iter().skip(new_only.len()) {
+                    diff.removed.push(format!("{} => {}", lhs, rhs));
+                }
+                for rhs in new_only.iter().skip(old_only.len()) {
+                    diff.added.push(format!("{} => {}", lhs, rhs));
+                }
+            }
+        }
+    }
+
+    for (lhs, new_rhs_list) in &new_rules {
+        if !old_rules.contains_key(lhs) {
+            for rhs in new_rhs_list {
+                diff.added.push(format!("{} => {}", lhs, rhs));
+            }
+        }
+    }
+
+    diff
+}
+
+fn diff_key_maps(
+    old: &std::collections::HashMap<String, KeyMapping>,
+    new: &std::collections::HashMap<String, KeyMapping>,
+) -> Vec<KeyOutputChange> {
+    let mut changes = Vec::new();
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    for key in keys {
+        let old_mapping = old.get(key);
+        let new_mapping = new.get(key);
+        let old_unshifted = old_mapping.and_then(|m| m.unshifted.clone());
+        let new_unshifted = new_mapping.and_then(|m| m.unshifted.clone());
+        let old_shifted = old_mapping.and_then(|m| m.shifted.clone());
+        let new_shifted = new_mapping.and_then(|m| m.shifted.clone());
+
+        if old_unshifted != new_unshifted {
+            changes.push(KeyOutputChange {
+                key: key.clone(),
+                shifted: false,
+                old: old_unshifted,
+                new: new_unshifted,
+            });
+        }
+        if old_shifted != new_shifted {
+            changes.push(KeyOutputChange {
+                key: key.clone(),
+                shifted: true,
+                old: old_shifted,
+                new: new_shifted,
+            });
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn load_fixture(name: &str) -> Km2File {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join(name);
+        kms2km2::compile_kms_file(&path).expect("fixture should compile")
+    }
+
+    #[test]
+    fn test_identical_keyboards_have_empty_diff() {
+        let v1 = load_fixture("diff_keyboard_v1.kms");
+        let diff = diff_layouts(&v1, &v1).unwrap();
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn test_detects_changes_between_versions() {
+        let v1 = load_fixture("diff_keyboard_v1.kms");
+        let v2 = load_fixture("diff_keyboard_v2.kms");
+        let diff = diff_layouts(&v1, &v2).unwrap();
+
+        // Description changed, name did not
+        assert!(diff.metadata.iter().any(|c| c.field == "description"));
+        assert!(!diff.metadata.iter().any(|c| c.field == "name"));
+
+        // auto_bksp was turned on
+        assert_eq!(diff.layout_options, vec![FieldChange {
+            field: "auto_bksp".to_string(),
+            old: Some("false".to_string()),
+            new: Some("true".to_string()),
+        }]);
+
+        // 'k' output changed, 'x' removed, 'z' added
+        assert!(diff.rules.modified.iter().any(|r| r.lhs == "\"k\"" && r.new_rhs == "\"ခ\""));
+        assert!(diff.rules.removed.iter().any(|r| r.starts_with("\"x\" =>")));
+        assert!(diff.rules.added.iter().any(|r| r.starts_with("\"z\" =>")));
+
+        // The key map reflects the same changes
+        assert!(diff.keys.contains(&KeyOutputChange {
+            key: "KeyK".to_string(),
+            shifted: false,
+            old: Some("က".to_string()),
+            new: Some("ခ".to_string()),
+        }));
+        assert!(diff.keys.iter().any(|c| c.key == "KeyZ" && !c.shifted && c.new.as_deref() == Some("ဇ")));
+        assert!(!diff.keys.iter().any(|c| c.key == "KeyG"));
+    }
+}
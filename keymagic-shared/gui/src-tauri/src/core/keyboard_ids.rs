@@ -0,0 +1,244 @@
+//! Keyboard ids that stay unique when km2 files share a name
+//!
+//! Ids used to be the stem of the imported file, so `myanmar.km2` from two
+//! authors collided. An id is now the keyboard's name as a slug followed by
+//! the start of its content hash, e.g. `myanmar3-1a2b3c4d`; the file in the
+//! keyboards directory is named after the id. Ids are only derived at import
+//! (and once when migrating an old install); every later lookup goes through
+//! the id the manager stored.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::platform::{Config, LanguageAction};
+
+/// Setting recording which id scheme the installed keyboards use
+pub const ID_SCHEME_SETTING: &str = "keyboard_id_scheme";
+/// Value of [`ID_SCHEME_SETTING`] once ids are slug plus hash
+pub const ID_SCHEME: &str = "2";
+
+/// Hash digits in an id, widened only when two hashes share a prefix
+const HASH_DIGITS: [usize; 3] = [8, 12, 16];
+/// Longest slug kept, so ids stay usable as file names and registry keys
+const MAX_SLUG_LEN: usize = 40;
+
+/// Lower case ASCII letters and digits, other runs of characters as one `-`
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for ch in text.chars() {
+        if ch.is_ascii_alphanumeric() {
+            slug.push(ch.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(MAX_SLUG_LEN);
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Slug of a keyboard: from its name, else from its file name (names in
+/// Myanmar script have no ASCII), else "keyboard"
+pub fn keyboard_slug(name: &str, file_stem: &str) -> String {
+    [slugify(name), slugify(file_stem)]
+        .into_iter()
+        .find(|slug| !slug.is_empty())
+        .unwrap_or_else(|| "keyboard".to_string())
+}
+
+/// Id for a keyboard with this slug and content hash, given the ids that
+/// are `taken`
+///
+/// The hash prefix is widened when another keyboard holds the shorter id;
+/// only the same content under the same slug falls back to a counter. The
+/// result depends on nothing but the arguments.
+pub fn unique_id(slug: &str, hash: &str, taken: impl Fn(&str) -> bool) -> String {
+    let hash = hash.to_ascii_lowercase();
+    let candidate = |digits: usize| match &hash[..digits.min(hash.len())] {
+        "" => slug.to_string(),
+        prefix => format!("{}-{}", slug, prefix),
+    };
+    if let Some(id) = HASH_DIGITS.iter().map(|digits| candidate(*digits)).find(|id| !taken(id)) {
+        return id;
+    }
+    let shortest = candidate(HASH_DIGITS[0]);
+    (2..).map(|counter| format!("{}-{}", shortest, counter)).find(|id| !taken(id)).unwrap()
+}
+
+/// Gives the installed keyboards ids of the current scheme and rewrites every
+/// reference to them: the active keyboard, the recently used list, profiles
+/// and language rules. Hotkeys and other per-keyboard settings move with the
+/// entries. File names are kept, so the files stay where they are.
+///
+/// `hashes` holds the content hash of each keyboard file by old id; a
+/// keyboard whose file could not be hashed keeps its id. Entries are taken
+/// in order of their old id, so duplicates resolve the same way every time.
+/// Returns the old id of each renamed keyboard with its new one.
+pub fn migrate_ids(config: &mut Config, hashes: &HashMap<String, String>) -> BTreeMap<String, String> {
+    let mut order: Vec<usize> = (0..config.keyboards.installed.len()).collect();
+    order.sort_by(|a, b| config.keyboards.installed[*a].id.cmp(&config.keyboards.installed[*b].id));
+
+    let mut taken: BTreeSet<String> = config.keyboards.installed
+        .iter()
+        .filter(|keyboard| !hashes.contains_key(&keyboard.id))
+        .map(|keyboard| keyboard.id.clone())
+        .collect();
+    let mut renamed = BTreeMap::new();
+    for index in order {
+        let keyboard = &mut config.keyboards.installed[index];
+        let Some(hash) = hashes.get(&keyboard.id).filter(|hash| !hash.is_empty()) else {
+            continue;
+        };
+        let stem = keyboard.filename.strip_suffix(".km2").unwrap_or(&keyboard.filename);
+        let id = unique_id(&keyboard_slug(&keyboard.name, stem), hash, |candidate| taken.contains(candidate));
+        taken.insert(id.clone());
+        if id != keyboard.id {
+            renamed.insert(std::mem::replace(&mut keyboard.id, id.clone()), id);
+        }
+    }
+
+    let rename = |id: &mut String| {
+        if let Some(new_id) = renamed.get(id.as_str()) {
+            *id = new_id.clone();
+        }
+    };
+    config.keyboards.active.iter_mut().for_each(rename);
+    config.keyboards.last_used.iter_mut().for_each(rename);
+    for profile in config.profiles.values_mut() {
+        profile.active_keyboard.iter_mut().for_each(rename);
+        profile.enabled_keyboards.iter_mut().flatten().for_each(rename);
+    }
+    for action in config.language_activation.rules.values_mut() {
+        if let LanguageAction::Keyboard { keyboard_id } = action {
+            rename(keyboard_id);
+        }
+    }
+    renamed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::{
+        GeneralConfig, InstalledKeyboard, KeyboardsConfig, OutputEncoding, ProfileOverrides,
+    };
+
+    fn installed(id: &str, name: &str, filename: &str) -> InstalledKeyboard {
+        InstalledKeyboard {
+            id: id.to_string(),
+            name: name.to_string(),
+            filename: filename.to_string(),
+            hotkey: None,
+            hash: String::new(),
+            enabled: true,
+            output_encoding: OutputEncoding::default(),
+            disabled_groups: Vec::new(),
+            passthrough_keys: Vec::new(),
+            commit_before_passthrough: false,
+            options_overrides: HashMap::new(),
+            original_hash: None,
+            sample_text: None,
+        }
+    }
+
+    fn config(installed: Vec<InstalledKeyboard>, active: Option<&str>) -> Config {
+        Config {
+            general: GeneralConfig {
+                start_with_system: false,
+                check_for_updates: false,
+                last_update_check: None,
+                last_scanned_version: None,
+                update_remind_after: None,
+                key_processing_enabled: None,
+            },
+            keyboards: KeyboardsConfig { active: active.map(str::to_string), last_used: Vec::new(), installed },
+            composition_mode: Default::default(),
+            direct_mode: Default::default(),
+            shortcut_passthrough: Default::default(),
+            char_suppression: Default::default(),
+            profiles: Default::default(),
+            active_profile: None,
+            language_activation: Default::default(),
+        }
+    }
+
+    fn hashes(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries.iter().map(|(id, hash)| (id.to_string(), hash.to_string())).collect()
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Myanmar3 Unicode"), "myanmar3-unicode");
+        assert_eq!(slugify("  Zawgyi (Win) "), "zawgyi-win");
+        assert_eq!(slugify("ပိုး"), "");
+        assert_eq!(slugify(&"a".repeat(60)).len(), MAX_SLUG_LEN);
+        assert_eq!(keyboard_slug("ပိုး", "Pyidaungsu.v2"), "pyidaungsu-v2");
+        assert_eq!(keyboard_slug("ပိုး", "ပိုး"), "keyboard");
+    }
+
+    #[test]
+    fn test_unique_id_widens_the_hash_then_counts() {
+        let hash = "1A2B3C4D5E6F7A8B9C";
+        assert_eq!(unique_id("myanmar", hash, |_| false), "myanmar-1a2b3c4d");
+
+        let taken = ["myanmar-1a2b3c4d"];
+        assert_eq!(unique_id("myanmar", hash, |id| taken.contains(&id)), "myanmar-1a2b3c4d5e6f");
+
+        let taken = ["myanmar-1a2b3c4d", "myanmar-1a2b3c4d5e6f", "myanmar-1a2b3c4d5e6f7a8b", "myanmar-1a2b3c4d-2"];
+        assert_eq!(unique_id("myanmar", hash, |id| taken.contains(&id)), "myanmar-1a2b3c4d-3");
+    }
+
+    #[test]
+    fn test_migration_renames_every_reference() {
+        let mut keyboard = installed("myanmar", "Myanmar", "myanmar.km2");
+        keyboard.hotkey = Some("CTRL+SHIFT+M".to_string());
+        let mut config = config(vec![keyboard, installed("zawgyi", "Zawgyi", "zawgyi.km2")], Some("myanmar"));
+        config.keyboards.last_used = vec!["zawgyi".to_string(), "myanmar".to_string()];
+        config.profiles.insert("work".to_string(), ProfileOverrides {
+            active_keyboard: Some("zawgyi".to_string()),
+            enabled_keyboards: Some(vec!["myanmar".to_string(), "gone".to_string()]),
+            ..Default::default()
+        });
+        config.language_activation.rules.insert("my".to_string(), LanguageAction::Keyboard { keyboard_id: "myanmar".to_string() });
+
+        let renamed = migrate_ids(&mut config, &hashes(&[("myanmar", "aaaaaaaa11"), ("zawgyi", "bbbbbbbb22")]));
+        assert_eq!(renamed.get("myanmar").map(String::as_str), Some("myanmar-aaaaaaaa"));
+        assert_eq!(renamed.get("zawgyi").map(String::as_str), Some("zawgyi-bbbbbbbb"));
+
+        let myanmar = &config.keyboards.installed[0];
+        assert_eq!(myanmar.id, "myanmar-aaaaaaaa");
+        assert_eq!(myanmar.filename, "myanmar.km2");
+        assert_eq!(myanmar.hotkey.as_deref(), Some("CTRL+SHIFT+M"));
+        assert_eq!(config.keyboards.active.as_deref(), Some("myanmar-aaaaaaaa"));
+        assert_eq!(config.keyboards.last_used, vec!["zawgyi-bbbbbbbb", "myanmar-aaaaaaaa"]);
+        let profile = &config.profiles["work"];
+        assert_eq!(profile.active_keyboard.as_deref(), Some("zawgyi-bbbbbbbb"));
+        assert_eq!(profile.enabled_keyboards.as_deref(), Some(&["myanmar-aaaaaaaa".to_string(), "gone".to_string()][..]));
+        assert_eq!(
+            config.language_activation.rules["my"],
+            LanguageAction::Keyboard { keyboard_id: "myanmar-aaaaaaaa".to_string() }
+        );
+    }
+
+    #[test]
+    fn test_migration_of_duplicates_is_deterministic() {
+        // A "(1)" copy of the same file, listed before the original
+        let entries = vec![
+            installed("myanmar_1f_203", "Myanmar", "myanmar_1f_203.km2"),
+            installed("myanmar", "Myanmar", "myanmar.km2"),
+            installed("broken", "Broken", "broken.km2"),
+        ];
+        let hashes = hashes(&[("myanmar", "cccccccc"), ("myanmar_1f_203", "cccccccc")]);
+        let mut first = config(entries.clone(), None);
+        let mut reversed = config(entries.into_iter().rev().collect(), None);
+        migrate_ids(&mut first, &hashes);
+        migrate_ids(&mut reversed, &hashes);
+
+        let ids = |config: &Config| -> BTreeMap<String, String> {
+            config.keyboards.installed.iter().map(|kb| (kb.filename.clone(), kb.id.clone())).collect()
+        };
+        assert_eq!(ids(&first), ids(&reversed));
+        assert_eq!(ids(&first)["myanmar.km2"], "myanmar-cccccccc");
+        assert_eq!(ids(&first)["myanmar_1f_203.km2"], "myanmar-cccccccc-2");
+        // Without a hash the keyboard keeps its id
+        assert_eq!(ids(&first)["broken.km2"], "broken");
+    }
+}
@@ -1,12 +1,52 @@
 use anyhow::{anyhow, Context, Result};
-use keymagic_core::{KeyMagicEngine, Km2File, km2::Km2Loader};
+use keymagic_core::{km2::Km2Loader, EngineSlot, Km2File, SharedEngine, VirtualKey};
+use keymagic_core::types::virtual_keys::parse_vk_names;
+use keymagic_core::char_suppression::CharSuppressionSnapshot;
+use keymagic_core::hotkey::{SwitchDecision, SwitchLoopBreaker, SWITCH_LOOP_MESSAGE};
+use keymagic_core::processing_state::{HeartbeatStatus, DEFAULT_ACK_TIMEOUT};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::platform::{InstalledKeyboard, Platform};
+use crate::platform::{
+    HostMode, InstalledKeyboard, LanguageAction, LanguageActivationConfig, OutputEncoding, Platform, ProfileOverrides,
+    PROFILE_SETTINGS,
+};
+use crate::temp_file;
+use crate::version::Version;
+use super::accessibility::{self, ScreenReaderCache, ANNOUNCE_KEYBOARD_SWITCH_SETTING};
+use super::bundled_keyboards::{bundled_match, bundled_status, should_scan, BundledKeyboard};
+use super::embedded_keyboards::{self, read_keyboard_data, KeyboardStorage};
+use super::file_hashes::{self, FileHashCache};
+use super::hotkey_conflicts::{HotkeyConflict, HotkeyConflictInfo, HotkeysInUse};
+use super::keyboard_activation::LayoutCache;
+use super::keyboard_ids;
+use super::keyboard_metadata::{self, MetadataChanges};
+use super::keyboard_options::{self, KeyboardOptions};
+use super::keyboard_query::{
+    detect_languages, languages_to_enable, query_keyboards, IconCache, KeyboardFilter, KeyboardIcon, KeyboardPage, KeyboardSort, NameIndex,
+};
+use super::keyboard_status::{
+    self, validate_data, validate_file, FileCheck, FileCheckCache, KeyboardStatus, KeyboardStatusDetail,
+};
+use super::keyboard_store::{
+    classify, plan_repairs, Discrepancy, KeyboardRegistrations, RepairAction, RepairPolicy, RepairReport, StoreEntry,
+    StoreFile, StoreSnapshot,
+};
+use super::keyboard_sync::{merge_external, KeyboardField, KeyboardsChanged, PendingEdits};
+use super::key_processing::{
+    disabled_hotkey_message, HeartbeatReport, HotkeyActivation, HotkeyDecision, KeyProcessingState,
+    FINISHING_COMPOSITION_MESSAGE,
+};
+use super::language_activation::{activation_message, resolve, validate_rules, InputLanguage};
+use super::layout_preview::SampleTextCache;
+use super::notification::NotificationManager;
+use super::preview_font::{resolve_preview_font, PreviewFont};
+use super::temporary_keyboard::{temporary_keyboard_message, TemporaryKeyboard, TemporaryKeyboardInfo};
 
 mod base64_serde {
     use serde::{Deserialize, Deserializer, Serializer};
@@ -48,22 +88,195 @@ pub struct KeyboardInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub default_hotkey: Option<String>,
     pub hash: String,
+    /// Hash the file had before its metadata was first edited in the app,
+    /// to tell a bundled keyboard the user renamed from an update
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_hash: Option<String>,
     pub is_active: bool,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Whether the keyboard can be used, and if not, why
+    #[serde(default)]
+    pub status: KeyboardStatus,
+    /// Whether a copy is embedded in the platform store for when the file
+    /// is missing
+    #[serde(default)]
+    pub storage: KeyboardStorage,
+    /// Languages guessed from the scripts the keyboard outputs
+    #[serde(default)]
+    pub languages: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    /// Preview text shown under the name: the keyboard's `@SAMPLE`, or
+    /// generated from its first letter keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_text: Option<String>,
+    /// Font the keyboard's output is meant for (`@FONTFAMILY`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub font_family: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", with = "base64_serde")]
     pub icon_data: Option<Vec<u8>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub display_hotkey: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub default_display_hotkey: Option<String>,
+    #[serde(default)]
+    pub output_encoding: OutputEncoding,
+    /// Rule groups the user switched off
+    #[serde(default)]
+    pub disabled_groups: Vec<String>,
+    /// Keys (e.g. "VK_ESCAPE") that always go to the application
+    #[serde(default)]
+    pub passthrough_keys: Vec<String>,
+    /// Commit the composing text before a passthrough key
+    #[serde(default)]
+    pub commit_before_passthrough: bool,
+    /// Layout options set by the user, by option name
+    #[serde(default)]
+    pub options_overrides: HashMap<String, serde_json::Value>,
 }
 
+impl KeyboardInfo {
+    /// Brings the status in line with the enabled and active flags
+    fn update_status(&mut self) {
+        self.status = self.status.with_flags(self.enabled, self.is_active);
+    }
+    
+    /// The hotkey that switches to this keyboard: the user's if set, else the
+    /// keyboard's own; an empty custom hotkey means none
+    pub fn hotkey_in_effect(&self) -> Option<&str> {
+        match self.hotkey.as_deref() {
+            Some("") => None,
+            Some(hotkey) => Some(hotkey),
+            None => self.default_hotkey.as_deref(),
+        }
+    }
+}
+
+/// An imported keyboard and the language profiles it declares that are not
+/// enabled yet, so the UI can offer to enable them and to pick another
+/// hotkey if its own was taken
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedKeyboard {
+    #[serde(flatten)]
+    pub keyboard: KeyboardInfo,
+    pub suggested_languages: Vec<String>,
+    /// Set when the keyboard's own hotkey was taken and left off
+    pub hotkey_conflict: Option<HotkeyConflictInfo>,
+}
+
+/// Whether keyboard switches are spoken, and why
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchAnnouncementInfo {
+    /// Effective value
+    pub enabled: bool,
+    /// The user's choice, `None` to follow the screen reader
+    pub setting: Option<bool>,
+    /// `None` when the platform cannot tell
+    pub screen_reader_active: Option<bool>,
+}
+
+/// Keys a keyboard never handles
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PassthroughKeysInfo {
+    pub keys: Vec<String>,
+    pub commit_before_passthrough: bool,
+}
+
+/// A named rule group of a keyboard and whether the user left it on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleGroupInfo {
+    pub name: String,
+    pub rule_count: usize,
+    pub enabled: bool,
+}
+
+/// A saved profile as listed in the UI
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileInfo {
+    pub name: String,
+    pub overrides: ProfileOverrides,
+    /// Whether this profile was applied last
+    pub active: bool,
+}
+
+/// Outcome of applying a profile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProfileApplied {
+    pub name: String,
+    pub active_keyboard: Option<String>,
+    /// Keyboards named by the profile that are not installed or failed to load
+    pub skipped_keyboards: Vec<String>,
+}
+
+/// A language rule that changed key processing or the active keyboard;
+/// sent to the UI as the `language_activation_applied` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LanguageActivated {
+    pub language: InputLanguage,
+    /// Rule key that matched the language
+    pub rule: String,
+    pub action: LanguageAction,
+    pub key_processing_enabled: bool,
+    pub active_keyboard: Option<String>,
+    /// Shown in the HUD
+    pub message: String,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Error for a keyboard id that is not installed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboardNotFound(pub String);
+
+impl std::fmt::Display for KeyboardNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Keyboard not found: {}", self.0)
+    }
+}
+
+impl std::error::Error for KeyboardNotFound {}
+
+/// Error for a profile name that is not saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileNotFound(pub String);
+
+impl std::fmt::Display for ProfileNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Profile not found: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProfileNotFound {}
+
 pub struct KeyboardManager {
     platform: Box<dyn Platform>,
     keyboards: Arc<Mutex<HashMap<String, KeyboardInfo>>>,
     active_keyboard: Arc<Mutex<Option<String>>>,
-    engine: Arc<Mutex<Option<KeyMagicEngine>>>,
+    /// Engine of the active keyboard; loading it never waits for a switch
+    engine: EngineSlot,
+    name_index: Mutex<NameIndex>,
+    icon_cache: Mutex<IconCache>,
+    layout_cache: Mutex<LayoutCache>,
+    sample_texts: Mutex<SampleTextCache>,
+    key_processing: Mutex<KeyProcessingState>,
+    /// Suppresses hotkey switches past a burst of them
+    switch_guard: Mutex<SwitchLoopBreaker>,
+    notifications: NotificationManager,
+    /// Keyboard fields with local changes not saved yet, left alone by reloads
+    pending_edits: Mutex<PendingEdits>,
+    /// Completed keyboard saves, to spot reloads that read the store before one
+    keyboard_saves: AtomicU64,
+    screen_reader: Mutex<ScreenReaderCache>,
+    /// Keyboard tried out for the session, ahead of the active one
+    temporary: Mutex<TemporaryKeyboard>,
+    /// Keyboard file hashes, read from the settings on first use
+    file_hashes: Mutex<Option<FileHashCache>>,
+    /// Results of checking that keyboard files load, read from the settings
+    /// on first use
+    file_checks: Mutex<Option<FileCheckCache>>,
 }
 
 impl KeyboardManager {
@@ -72,7 +285,20 @@ impl KeyboardManager {
             platform,
             keyboards: Arc::new(Mutex::new(HashMap::new())),
             active_keyboard: Arc::new(Mutex::new(None)),
-            engine: Arc::new(Mutex::new(None)),
+            engine: EngineSlot::default(),
+            name_index: Mutex::new(NameIndex::default()),
+            icon_cache: Mutex::new(IconCache::default()),
+            layout_cache: Mutex::new(LayoutCache::default()),
+            sample_texts: Mutex::new(SampleTextCache::default()),
+            key_processing: Mutex::new(KeyProcessingState::default()),
+            switch_guard: Mutex::new(SwitchLoopBreaker::default()),
+            notifications: NotificationManager::new(),
+            pending_edits: Mutex::new(PendingEdits::default()),
+            keyboard_saves: AtomicU64::new(0),
+            screen_reader: Mutex::new(ScreenReaderCache::default()),
+            temporary: Mutex::new(TemporaryKeyboard::default()),
+            file_hashes: Mutex::new(None),
+            file_checks: Mutex::new(None),
         }
     }
     
@@ -80,6 +306,10 @@ impl KeyboardManager {
         &*self.platform
     }
     
+    pub fn notifications(&self) -> &NotificationManager {
+        &self.notifications
+    }
+    
     pub fn get_platform_info(&self) -> crate::platform::PlatformInfo {
         self.platform.get_platform_info()
     }
@@ -93,6 +323,7 @@ impl KeyboardManager {
                     last_update_check: None,
                     last_scanned_version: None,
                     update_remind_after: None,
+                    key_processing_enabled: None,
                 },
                 keyboards: crate::platform::KeyboardsConfig {
                     active: None,
@@ -101,6 +332,11 @@ impl KeyboardManager {
                 },
                 composition_mode: Default::default(),
                 direct_mode: Default::default(),
+                shortcut_passthrough: Default::default(),
+                char_suppression: Default::default(),
+                profiles: Default::default(),
+                active_profile: None,
+                language_activation: Default::default(),
             }
         })
     }
@@ -111,60 +347,128 @@ impl KeyboardManager {
     
     pub fn initialize(&self) -> Result<()> {
         // Load config
-        let config = self.platform.load_config()?;
+        let mut config = self.platform.load_config()?;
+        self.migrate_keyboard_ids(&mut config)?;
         
         // Load keyboards from config
         let mut keyboards = self.keyboards.lock().unwrap();
         for installed in &config.keyboards.installed {
-            let path = self.platform.get_keyboards_dir().join(&installed.filename);
-            if path.exists() {
-                // Load the keyboard file to get metadata
-                let (description, icon_data, default_hotkey) = if let Ok(layout) = self.load_keyboard_file(&path) {
-                    let metadata = layout.metadata();
-                    (
-                        metadata.description().map(|s| s.to_string()),
-                        metadata.icon().map(|data| data.to_vec()),
-                        metadata.hotkey()
-                    )
-                } else {
-                    (None, None, None)
-                };
-                
-                // Normalize hotkeys for display
-                let display_hotkey = installed.hotkey.as_ref()
-                    .map(|h| self.platform.normalize_hotkey_for_display(h));
-                let default_display_hotkey = default_hotkey.as_ref()
-                    .map(|h| self.platform.normalize_hotkey_for_display(h));
-                
-                keyboards.insert(
-                    installed.id.clone(),
-                    KeyboardInfo {
-                        id: installed.id.clone(),
-                        name: installed.name.clone(),
-                        filename: installed.filename.clone(),
-                        path,
-                        hotkey: installed.hotkey.clone(),
-                        default_hotkey,
-                        hash: installed.hash.clone(),
-                        is_active: false,
-                        description,
-                        icon_data,
-                        display_hotkey,
-                        default_display_hotkey,
-                    },
-                );
-            }
-        }
-        
-        // Set active keyboard
+            keyboards.insert(installed.id.clone(), self.keyboard_info_from_installed(installed));
+        }
+        
+        drop(keyboards);
+        self.rebuild_name_index();
+        
+        // A trial left behind by a crash must not outlive the session
+        if let Err(e) = self.platform.set_temporary_keyboard(None) {
+            log::warn!("Failed to clear the temporary keyboard: {}", e);
+        }
+        
+        let processing_enabled = !self.setting_is("key_processing_enabled", "false");
+        *self.key_processing.lock().unwrap() = KeyProcessingState::new(processing_enabled);
+        // Input methods stopped processing keys if the last session crashed;
+        // the first heartbeat and the switch below bring them back
+        if let Some(previous) = self.beat_input_methods() {
+            if let HeartbeatStatus::Stale { age } = HeartbeatStatus::at(Some(previous), unix_time_ms()) {
+                log::warn!("KeyMagic did not exit cleanly {:?} ago; resuming key processing in input methods", age);
+            }
+        }
+        // Input methods start out enabled; bring them in line without waiting
+        if let Err(e) = self.platform.set_input_method_processing(processing_enabled, Duration::ZERO) {
+            log::warn!("Failed to switch key processing in input methods: {}", e);
+        }
+        
+        self.save_file_hashes();
+        self.save_file_checks();
+        
+        // Set active keyboard. A keyboard that cannot be loaded is left for
+        // the store repair to clear instead of failing startup.
         if let Some(active_id) = config.keyboards.active {
-            drop(keyboards); // Release lock before calling set_active_keyboard
-            self.set_active_keyboard(&active_id)?;
+            if let Err(e) = self.set_active_keyboard(&active_id) {
+                log::warn!("Failed to activate keyboard {}: {}", active_id, e);
+            }
         }
         
         Ok(())
     }
     
+    /// Moves keyboards installed with file-stem ids to ids that cannot
+    /// collide (see `keyboard_ids`), once per install
+    fn migrate_keyboard_ids(&self, config: &mut crate::platform::Config) -> Result<()> {
+        if self.setting_is(keyboard_ids::ID_SCHEME_SETTING, keyboard_ids::ID_SCHEME) {
+            return Ok(());
+        }
+        let keyboards_dir = self.platform.get_keyboards_dir();
+        let hashes: HashMap<String, String> = config.keyboards.installed
+            .iter()
+            .filter_map(|installed| {
+                let hash = self.calculate_file_hash(&keyboards_dir.join(&installed.filename)).ok()?;
+                Some((installed.id.clone(), hash))
+            })
+            .collect();
+        let renamed = keyboard_ids::migrate_ids(config, &hashes);
+        if !renamed.is_empty() {
+            log::info!("Keyboard ids migrated: {:?}", renamed);
+            self.platform.save_config(config)?;
+        }
+        self.platform.set_setting(keyboard_ids::ID_SCHEME_SETTING, keyboard_ids::ID_SCHEME)
+    }
+    
+    /// Builds the keyboard info of a configured keyboard. One whose file is
+    /// missing is kept, as unavailable, so that it is back once the file is.
+    fn keyboard_info_from_installed(&self, installed: &InstalledKeyboard) -> KeyboardInfo {
+        let path = self.platform.get_keyboards_dir().join(&installed.filename);
+        let status = KeyboardStatus::of(installed.enabled, false, &path, &self.check_file(&installed.id, &path));
+        
+        // Load the keyboard file to get metadata
+        let (description, sample_text, font_family, icon_data, default_hotkey, languages) = if let Ok(layout) = self.load_installed(&installed.id, &path) {
+            let metadata = layout.metadata();
+            (
+                metadata.description().map(|s| s.to_string()),
+                self.sample_text(&installed.id, &layout, &installed.hash),
+                metadata.font_family(),
+                metadata.icon().map(|data| data.to_vec()),
+                metadata.hotkey(),
+                detect_languages(&layout),
+            )
+        } else {
+            (None, None, None, None, None, Vec::new())
+        };
+        
+        // Normalize hotkeys for display
+        let display_hotkey = installed.hotkey.as_ref()
+            .map(|h| self.platform.normalize_hotkey_for_display(h));
+        let default_display_hotkey = default_hotkey.as_ref()
+            .map(|h| self.platform.normalize_hotkey_for_display(h));
+        
+        KeyboardInfo {
+            id: installed.id.clone(),
+            name: installed.name.clone(),
+            filename: installed.filename.clone(),
+            path,
+            hotkey: installed.hotkey.clone(),
+            default_hotkey,
+            hash: installed.hash.clone(),
+            original_hash: installed.original_hash.clone(),
+            is_active: false,
+            enabled: installed.enabled,
+            status,
+            storage: self.keyboard_storage(&installed.id),
+            languages,
+            description,
+            sample_text,
+            font_family,
+            icon_data,
+            display_hotkey,
+            default_display_hotkey,
+            output_encoding: installed.output_encoding,
+            disabled_groups: installed.disabled_groups.clone(),
+            passthrough_keys: installed.passthrough_keys.clone(),
+            commit_before_passthrough: installed.commit_before_passthrough,
+            options_overrides: installed.options_overrides.clone(),
+        }
+    }
+    
     pub fn scan_keyboards(&self) -> Result<Vec<KeyboardInfo>> {
         let keyboard_files = self.platform.get_keyboard_files()?;
         let mut found_keyboards = Vec::new();
@@ -176,17 +480,19 @@ impl KeyboardManager {
                     .unwrap_or("unknown.km2")
                     .to_string();
                 
-                let id = path.file_stem()
+                let file_stem = path.file_stem()
                     .and_then(|n| n.to_str())
-                    .unwrap_or("unknown")
-                    .to_string();
+                    .unwrap_or("unknown");
                 
                 let metadata = layout.metadata();
-                let name = metadata.name().unwrap_or(id.clone());
+                let name = metadata.name().unwrap_or_else(|| file_stem.to_string());
                 let description = metadata.description().map(|s| s.to_string());
                 let icon_data = metadata.icon().map(|data| data.to_vec());
                 let default_hotkey = metadata.hotkey();
                 let hash = self.calculate_file_hash(&path)?;
+                let id = self.keyboard_id_for_file(&filename, &name, file_stem, &hash);
+                let sample_text = self.sample_text(&id, &layout, &hash);
+                let storage = self.keyboard_storage(&id);
                 
                 // Normalize default hotkey for display
                 let default_display_hotkey = default_hotkey.as_ref()
@@ -200,11 +506,23 @@ impl KeyboardManager {
                     hotkey: None,  // User customization, initially None
                     default_hotkey,
                     hash,
+                    original_hash: None,
                     is_active: false,
+                    enabled: true,
+                    status: KeyboardStatus::Enabled,
+                    storage,
+                    languages: detect_languages(&layout),
+                    sample_text,
+                    font_family: metadata.font_family(),
                     description,
                     icon_data,
                     display_hotkey: None,  // No custom hotkey initially
                     default_display_hotkey,
+                    output_encoding: OutputEncoding::default(),
+                    disabled_groups: Vec::new(),
+                    passthrough_keys: Vec::new(),
+                    commit_before_passthrough: false,
+                    options_overrides: HashMap::new(),
                 });
             }
         }
@@ -212,10 +530,23 @@ impl KeyboardManager {
         Ok(found_keyboards)
     }
     
+    /// Id of the keyboard registered with a file in the keyboards folder,
+    /// else the id importing the file would give it
+    fn keyboard_id_for_file(&self, filename: &str, name: &str, file_stem: &str, hash: &str) -> String {
+        let keyboards = self.keyboards.lock().unwrap();
+        match keyboards.values().find(|kb| kb.filename.eq_ignore_ascii_case(filename)) {
+            Some(keyboard) => keyboard.id.clone(),
+            None => keyboard_ids::unique_id(&keyboard_ids::keyboard_slug(name, file_stem), hash, |id| keyboards.contains_key(id)),
+        }
+    }
+    
     pub fn add_keyboard(&self, keyboard_info: KeyboardInfo) -> Result<()> {
         let mut keyboards = self.keyboards.lock().unwrap();
         keyboards.insert(keyboard_info.id.clone(), keyboard_info.clone());
         drop(keyboards);
+        self.rebuild_name_index();
+        self.icon_cache.lock().unwrap().invalidate(&keyboard_info.id);
+        self.layout_cache.lock().unwrap().invalidate(&keyboard_info.id);
         
         // Update config
         self.save_keyboards_to_config()?;
@@ -227,14 +558,18 @@ impl KeyboardManager {
         let mut keyboards = self.keyboards.lock().unwrap();
         keyboards.remove(keyboard_id);
         drop(keyboards);
+        self.rebuild_name_index();
+        self.icon_cache.lock().unwrap().invalidate(keyboard_id);
+        self.layout_cache.lock().unwrap().invalidate(keyboard_id);
         
         // If this was the active keyboard, clear it
         let mut active = self.active_keyboard.lock().unwrap();
         if active.as_ref() == Some(&keyboard_id.to_string()) {
             *active = None;
-            *self.engine.lock().unwrap() = None;
+            self.engine.store(None);
         }
         drop(active);
+        self.key_processing.lock().unwrap().forget_keyboard(keyboard_id);
         
         // Update config
         self.save_keyboards_to_config()?;
@@ -242,16 +577,17 @@ impl KeyboardManager {
         Ok(())
     }
     
+    /// Makes a keyboard the active one. The keyboard file is loaded first;
+    /// if that fails a `KeyboardActivationError` is returned and the
+    /// previous keyboard stays active.
     pub fn set_active_keyboard(&self, keyboard_id: &str) -> Result<()> {
         let keyboards = self.keyboards.lock().unwrap();
         
         if let Some(keyboard_info) = keyboards.get(keyboard_id) {
-            let layout = self.load_keyboard_file(&keyboard_info.path)?;
-            
             // Update engine
-            let mut engine_lock = self.engine.lock().unwrap();
-            *engine_lock = Some(KeyMagicEngine::new(layout)?);
-            drop(engine_lock);
+            let engine = self.build_engine(keyboard_info)?;
+            self.engine.store(Some(engine));
+            let name = keyboard_info.name.clone();
             
             // Update active keyboard
             let mut active = self.active_keyboard.lock().unwrap();
@@ -262,200 +598,2348 @@ impl KeyboardManager {
             drop(keyboards);
             self.update_active_flags(keyboard_id)?;
             
+            // A normal switch ends any trial
+            self.end_any_temporary_activation()?;
+            
             // Notify platform
             self.platform.switch_keyboard(keyboard_id)?;
             
             // Update config
             self.save_keyboards_to_config()?;
             
+            if self.switch_announcement().enabled {
+                self.notifications.show_keyboard_switch(&name);
+            }
+            
             Ok(())
         } else {
-            Err(anyhow::anyhow!("Keyboard not found: {}", keyboard_id))
+            Err(KeyboardNotFound(keyboard_id.to_string()).into())
         }
     }
     
-    pub fn get_active_keyboard(&self) -> Option<String> {
-        self.active_keyboard.lock().unwrap().clone()
+    /// Whether keyboard switches are announced to screen readers. The
+    /// screen reader check is cached, see `accessibility::DETECTION_TTL`.
+    pub fn switch_announcement(&self) -> SwitchAnnouncementInfo {
+        let stored = self.platform.get_setting(ANNOUNCE_KEYBOARD_SWITCH_SETTING).ok().flatten();
+        let setting = accessibility::parse_announce_setting(stored.as_deref());
+        let screen_reader_active = self.screen_reader.lock().unwrap()
+            .get(Instant::now(), || self.platform.screen_reader_active());
+        let enabled = accessibility::should_announce(stored.as_deref(), || screen_reader_active);
+        SwitchAnnouncementInfo { enabled, setting, screen_reader_active }
     }
     
-    pub fn get_keyboards(&self) -> Vec<KeyboardInfo> {
-        self.keyboards.lock().unwrap().values().cloned().collect()
+    /// Turns switch announcements on or off, or back to following the screen
+    /// reader with `None`
+    pub fn set_switch_announcement(&self, enabled: Option<bool>) -> Result<()> {
+        let value = enabled.map(|on| on.to_string()).unwrap_or_default();
+        self.platform.set_setting(ANNOUNCE_KEYBOARD_SWITCH_SETTING, &value)?;
+        // Check again, the user may just have started a screen reader
+        self.screen_reader.lock().unwrap().invalidate();
+        Ok(())
     }
     
-    pub fn get_keyboard(&self, keyboard_id: &str) -> Option<KeyboardInfo> {
-        self.keyboards.lock().unwrap().get(keyboard_id).cloned()
+    /// Engine for a keyboard with the user's encoding and rule group choices
+    fn build_engine(&self, keyboard_info: &KeyboardInfo) -> Result<SharedEngine> {
+        let mut engine = self.layout_cache.lock().unwrap()
+            .prewarm(&keyboard_info.id, &keyboard_info.path, || self.embedded_copy(&keyboard_info.id))?;
+        engine.set_output_transform(keyboard_info.output_encoding.transform_id())?;
+        for group in &keyboard_info.disabled_groups {
+            // Groups dropped by a newer version of the keyboard are ignored
+            let _ = engine.set_group_enabled(group, false);
+        }
+        // Names were checked when saved; ones edited in by hand are ignored
+        let passthrough_keys: Vec<VirtualKey> = keyboard_info.passthrough_keys.iter()
+            .filter_map(|name| parse_vk_names(std::slice::from_ref(name)).ok())
+            .flatten()
+            .collect();
+        engine.set_passthrough_keys(&passthrough_keys);
+        engine.set_commit_on_passthrough(keyboard_info.commit_before_passthrough);
+        let defaults = engine.keyboard().header.layout_options;
+        engine.set_layout_options(keyboard_options::effective_options(defaults, &keyboard_info.options_overrides));
+        Ok(SharedEngine::new(engine))
     }
     
-    pub fn get_keyboard_by_name(&self, name: &str) -> Option<KeyboardInfo> {
-        self.keyboards.lock().unwrap()
-            .values()
-            .find(|kb| kb.name == name)
-            .cloned()
+    /// Hands the active keyboard to the platform again and signals the
+    /// input method, for when the session changed under it (resume, unlock)
+    pub fn reassert_shared_state(&self) -> Result<()> {
+        match self.get_active_keyboard() {
+            Some(keyboard_id) => self.platform.switch_keyboard(&keyboard_id),
+            None => self.platform.notify_keyboards_changed(),
+        }
     }
     
-    pub fn get_engine(&self) -> Arc<Mutex<Option<KeyMagicEngine>>> {
-        self.engine.clone()
+    pub fn is_key_processing_enabled(&self) -> bool {
+        self.key_processing.lock().unwrap().is_enabled()
     }
     
-    pub fn update_hotkey(&self, keyboard_id: &str, hotkey: Option<String>) -> Result<()> {
-        let mut keyboards = self.keyboards.lock().unwrap();
-        if let Some(keyboard) = keyboards.get_mut(keyboard_id) {
-            // Update hotkey and its normalized display version
-            keyboard.hotkey = hotkey.clone();
-            keyboard.display_hotkey = hotkey.as_ref()
-                .map(|h| self.platform.normalize_hotkey_for_display(h));
-        }
-        drop(keyboards);
+    /// Turns key processing on or off. Turning it on activates the keyboard
+    /// whose hotkey was pressed while it was off, which is returned.
+    /// Turning it off first lets input methods commit the composition.
+    pub fn set_key_processing_enabled(&self, enabled: bool) -> Result<Option<String>> {
+        self.platform.set_setting("key_processing_enabled", if enabled { "true" } else { "false" })?;
+        let pending = self.key_processing.lock().unwrap().set_enabled(enabled);
+        self.switch_input_methods(enabled);
         
-        self.save_keyboards_to_config()?;
-        Ok(())
+        match pending {
+            Some(keyboard_id) => match self.set_active_keyboard(&keyboard_id) {
+                Ok(()) => Ok(Some(keyboard_id)),
+                Err(e) => {
+                    log::warn!("Failed to activate pending keyboard {}: {}", keyboard_id, e);
+                    Ok(None)
+                }
+            },
+            None => Ok(None),
+        }
     }
     
-    pub fn import_keyboard(&self, file_path: &Path) -> Result<KeyboardInfo> {
-        // Load the keyboard to validate it
-        let layout = self.load_keyboard_file(file_path)?;
-        
-        // Generate keyboard info
-        let original_filename = file_path.file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown.km2")
-            .to_string();
-        
-        let original_id = file_path.file_stem()
-            .and_then(|n| n.to_str())
-            .unwrap_or("unknown")
-            .to_string();
+    /// Handles a keyboard hotkey. While key processing is off the keyboard is
+    /// kept pending and the HUD tells the user how to turn processing on,
+    /// unless the `auto_enable_on_keyboard_hotkey` setting is set. Past a
+    /// burst of switches further ones are suppressed for a while, see
+    /// `SwitchLoopBreaker`.
+    pub fn activate_keyboard_by_hotkey(&self, keyboard_id: &str) -> Result<HotkeyActivation> {
+        if self.get_keyboard(keyboard_id).is_none() {
+            return Err(KeyboardNotFound(keyboard_id.to_string()).into());
+        }
         
-        let metadata = layout.metadata();
-        let name = metadata.name().unwrap_or(original_id.clone());
-        let description = metadata.description().map(|s| s.to_string());
-        let icon_data = metadata.icon().map(|data| data.to_vec());
-        let default_hotkey = metadata.hotkey();
-        let hash = self.calculate_file_hash(file_path)?;
+        // A rule output or injected key re-triggering hotkeys must not
+        // switch keyboards back and forth without end
+        match self.switch_guard.lock().unwrap().check(unix_time_ms()) {
+            SwitchDecision::Allow => {}
+            SwitchDecision::Trip => {
+                log::warn!("Too many keyboard switches in a second; suppressing hotkey switches for a while");
+                self.notifications.show_hud(SWITCH_LOOP_MESSAGE);
+                return Ok(HotkeyActivation::Suppressed);
+            }
+            SwitchDecision::Suppress => return Ok(HotkeyActivation::Suppressed),
+        }
         
-        // Generate unique ID and filename if a file with same name exists
-        let keyboards_dir = self.platform.get_keyboards_dir();
-        let mut final_id = original_id.clone();
-        let mut final_filename = original_filename.clone();
-        let mut dest_path = keyboards_dir.join(&final_filename);
-        
-        if dest_path.exists() && dest_path != file_path {
-            // Generate a unique ID using timestamp and random component
-            use std::time::{SystemTime, UNIX_EPOCH};
-            let timestamp = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .map(|d| d.as_secs())
-                .unwrap_or(0);
-            
-            // Simple random number (not cryptographically secure, but sufficient for this use case)
-            let random_part = std::process::id() ^ (timestamp as u32);
-            
-            final_id = format!("{}_{:x}_{}", original_id, random_part, timestamp % 1000);
-            final_filename = format!("{}_{:x}_{}.km2", 
-                original_id, random_part, timestamp % 1000);
-            dest_path = keyboards_dir.join(&final_filename);
-            
-            // In the unlikely event this still exists, add a counter
-            let mut counter = 1;
-            while dest_path.exists() && counter < 100 {
-                final_id = format!("{}_{}_{:x}_{}", original_id, counter, random_part, timestamp % 1000);
-                final_filename = format!("{}_{}_{:x}_{}.km2", 
-                    original_id, counter, random_part, timestamp % 1000);
-                dest_path = keyboards_dir.join(&final_filename);
-                counter += 1;
+        let auto_enable = self.setting_is("auto_enable_on_keyboard_hotkey", "true");
+        let decision = self.key_processing.lock().unwrap().keyboard_hotkey(keyboard_id, auto_enable);
+        match decision {
+            HotkeyDecision::Switch => {
+                self.set_active_keyboard(keyboard_id)?;
+                Ok(HotkeyActivation::Switched)
             }
-            
-            if dest_path.exists() {
-                return Err(anyhow!("Unable to generate unique keyboard filename"));
+            HotkeyDecision::EnableAndSwitch => {
+                self.platform.set_setting("key_processing_enabled", "true")?;
+                self.switch_input_methods(true);
+                self.set_active_keyboard(keyboard_id)?;
+                Ok(HotkeyActivation::Enabled)
+            }
+            HotkeyDecision::Defer => {
+                let on_off_hotkey = self.platform.get_setting("on_off_hotkey")
+                    .ok()
+                    .flatten()
+                    .map(|hotkey| self.platform.normalize_hotkey_for_display(&hotkey));
+                let message = disabled_hotkey_message(on_off_hotkey.as_deref());
+                self.notifications.show_hud(&message);
+                Ok(HotkeyActivation::Pending { message })
             }
         }
-        
-        // Copy to keyboards directory
-        if dest_path != file_path {
-            fs::create_dir_all(dest_path.parent().unwrap())?;
-            fs::copy(file_path, &dest_path)?;
+    }
+    
+    /// Writes the heartbeat that keeps input methods processing keys, see
+    /// `keymagic_core::processing_state`; returns the previous one
+    pub fn beat_input_methods(&self) -> Option<u64> {
+        match self.platform.beat_input_methods(unix_time_ms()) {
+            Ok(previous) => previous,
+            Err(e) => {
+                log::warn!("Failed to write the heartbeat for input methods: {}", e);
+                None
+            }
         }
-        
-        // Normalize default hotkey for display
-        let default_display_hotkey = default_hotkey.as_ref()
-            .map(|h| self.platform.normalize_hotkey_for_display(h));
-        
-        let keyboard_info = KeyboardInfo {
-            id: final_id.clone(),
-            name,
-            filename: final_filename,
-            path: dest_path,
-            hotkey: None,  // User customization, initially None
-            default_hotkey,
-            hash,
-            is_active: false,
-            description,
-            icon_data,
-            display_hotkey: None,  // No custom hotkey initially
-            default_display_hotkey,
-        };
-        
-        // Add to manager
-        self.add_keyboard(keyboard_info.clone())?;
-        
-        Ok(keyboard_info)
     }
     
-    pub fn load_keyboard_file(&self, path: &Path) -> Result<Km2File> {
-        let data = fs::read(path)
-            .context("Failed to read keyboard file")?;
-        
-        Km2Loader::load(&data)
-            .context("Failed to parse keyboard file")
+    /// Health of the heartbeat, for diagnostic bundles
+    pub fn heartbeat_report(&self) -> Result<HeartbeatReport> {
+        Ok(HeartbeatReport::new(self.platform.input_method_heartbeat()?, unix_time_ms()))
     }
     
-    pub fn calculate_file_hash(&self, path: &Path) -> Result<String> {
-        use std::io::Read;
-        use sha2::{Sha256, Digest};
-        
-        let mut file = fs::File::open(path)?;
-        let mut hasher = Sha256::new();
-        let mut buffer = [0; 8192];
-        
-        loop {
-            let bytes_read = file.read(&mut buffer)?;
-            if bytes_read == 0 {
-                break;
+    /// Hands key processing to the input methods. Turning it off waits
+    /// briefly for them to commit the composition; if that takes longer the
+    /// HUD says so and they finish in the background.
+    fn switch_input_methods(&self, enabled: bool) {
+        match self.platform.set_input_method_processing(enabled, DEFAULT_ACK_TIMEOUT) {
+            Ok(true) => {}
+            Ok(false) => {
+                log::info!("Input methods did not confirm the composition was committed in time");
+                self.notifications.show_hud(FINISHING_COMPOSITION_MESSAGE);
             }
-            hasher.update(&buffer[..bytes_read]);
+            Err(e) => log::warn!("Failed to switch key processing in input methods: {}", e),
         }
-        
-        Ok(format!("{:x}", hasher.finalize()))
     }
     
-    fn update_active_flags(&self, active_id: &str) -> Result<()> {
-        let mut keyboards = self.keyboards.lock().unwrap();
-        for (id, keyboard) in keyboards.iter_mut() {
-            keyboard.is_active = id == active_id;
-        }
-        Ok(())
+    /// Keyboard to activate once key processing is turned on
+    pub fn pending_keyboard(&self) -> Option<String> {
+        self.key_processing.lock().unwrap().pending_keyboard().map(str::to_string)
     }
     
-    fn save_keyboards_to_config(&self) -> Result<()> {
+    pub fn language_activation(&self) -> Result<LanguageActivationConfig> {
+        Ok(self.platform.load_config()?.language_activation)
+    }
+    
+    pub fn set_language_activation_enabled(&self, enabled: bool) -> Result<()> {
+        let mut config = self.platform.load_config()?;
+        config.language_activation.enabled = enabled;
+        self.platform.save_config(&config)
+    }
+    
+    /// Replaces the language rules. Keys must be LANGIDs, locale names or
+    /// languages and keyboards must be installed.
+    pub fn set_language_activation_rules(&self, rules: BTreeMap<String, LanguageAction>) -> Result<()> {
+        validate_rules(rules.keys())?;
+        for action in rules.values() {
+            if let LanguageAction::Keyboard { keyboard_id } = action {
+                if self.get_keyboard(keyboard_id).is_none() {
+                    return Err(KeyboardNotFound(keyboard_id.clone()).into());
+                }
+            }
+        }
         let mut config = self.platform.load_config()?;
+        config.language_activation.rules = rules;
+        self.platform.save_config(&config)
+    }
+    
+    /// Applies the rule for the input language of the focused window while
+    /// language activation is on. Goes through the same paths as the on/off
+    /// hotkey and keyboard activation, and shows a HUD message when
+    /// something changed, which is returned.
+    pub fn apply_input_language(&self, language: &InputLanguage) -> Result<Option<LanguageActivated>> {
+        let config = self.platform.load_config()?.language_activation;
+        if !config.enabled {
+            return Ok(None);
+        }
+        let Some((rule, action)) = resolve(&config, language) else {
+            return Ok(None);
+        };
         
-        // Update active keyboard
-        config.keyboards.active = self.active_keyboard.lock().unwrap().clone();
+        let was_enabled = self.is_key_processing_enabled();
+        let previous = self.get_active_keyboard();
+        let mut keyboard_name = None;
+        match action {
+            LanguageAction::Enable => {
+                if !was_enabled {
+                    self.set_key_processing_enabled(true)?;
+                }
+            }
+            LanguageAction::Disable => {
+                if was_enabled {
+                    self.set_key_processing_enabled(false)?;
+                }
+            }
+            LanguageAction::Keyboard { keyboard_id } => {
+                let keyboard = self.get_keyboard(keyboard_id)
+                    .ok_or_else(|| KeyboardNotFound(keyboard_id.clone()))?;
+                keyboard_name = Some(keyboard.name);
+                if !was_enabled {
+                    self.set_key_processing_enabled(true)?;
+                }
+                if self.get_active_keyboard().as_deref() != Some(keyboard_id) {
+                    self.set_active_keyboard(keyboard_id)?;
+                }
+            }
+        }
         
-        // Update installed keyboards
-        let keyboards = self.keyboards.lock().unwrap();
-        config.keyboards.installed = keyboards
+        let key_processing_enabled = self.is_key_processing_enabled();
+        let active_keyboard = self.get_active_keyboard();
+        if key_processing_enabled == was_enabled && active_keyboard == previous {
+            return Ok(None);
+        }
+        let message = activation_message(language, action, keyboard_name.as_deref());
+        self.notifications.show_hud(&message);
+        Ok(Some(LanguageActivated {
+            language: language.clone(),
+            rule: rule.to_string(),
+            action: action.clone(),
+            key_processing_enabled,
+            active_keyboard,
+            message,
+        }))
+    }
+    
+    fn setting_is(&self, key: &str, value: &str) -> bool {
+        self.platform.get_setting(key)
+            .ok()
+            .flatten()
+            .is_some_and(|v| v == value)
+    }
+    
+    pub fn get_active_keyboard(&self) -> Option<String> {
+        self.active_keyboard.lock().unwrap().clone()
+    }
+    
+    pub fn get_keyboards(&self) -> Vec<KeyboardInfo> {
+        self.keyboards.lock().unwrap().values().cloned().collect()
+    }
+    
+    pub fn get_keyboard(&self, keyboard_id: &str) -> Option<KeyboardInfo> {
+        self.keyboards.lock().unwrap().get(keyboard_id).cloned()
+    }
+    
+    /// The keyboard called `name`; of several, the one with the smallest id
+    pub fn get_keyboard_by_name(&self, name: &str) -> Option<KeyboardInfo> {
+        self.keyboards.lock().unwrap()
             .values()
-            .map(|kb| InstalledKeyboard {
-                id: kb.id.clone(),
-                name: kb.name.clone(),
-                filename: kb.filename.clone(),
-                hotkey: kb.hotkey.clone(),
-                hash: kb.hash.clone(),
-            })
-            .collect();
+            .filter(|kb| kb.name == name)
+            .min_by(|a, b| a.id.cmp(&b.id))
+            .cloned()
+    }
+    
+    /// An installed keyboard with this name and file content
+    fn installed_copy(&self, name: &str, hash: &str) -> Option<KeyboardInfo> {
+        self.keyboards.lock().unwrap()
+            .values()
+            .filter(|kb| kb.name == name && kb.hash.eq_ignore_ascii_case(hash))
+            .min_by(|a, b| a.id.cmp(&b.id))
+            .cloned()
+    }
+    
+    /// Filters, sorts and pages the cached keyboard list (see `keyboard_query`)
+    pub fn query_keyboards(&self, filter: &KeyboardFilter, page: usize, page_size: usize, sort: KeyboardSort) -> KeyboardPage {
+        let keyboards = self.keyboards.lock().unwrap();
+        let index = self.name_index.lock().unwrap();
+        query_keyboards(keyboards.values(), &index, filter, page, page_size, sort)
+    }
+    
+    /// Loads a keyboard's icon lazily; data is omitted if `if_none_match` is still current
+    pub fn get_keyboard_icon(&self, keyboard_id: &str, if_none_match: Option<&str>) -> Result<KeyboardIcon> {
+        let path = self.get_keyboard(keyboard_id)
+            .map(|kb| kb.path)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        self.icon_cache.lock().unwrap().get(keyboard_id, &path, if_none_match)
+    }
+    
+    fn rebuild_name_index(&self) {
+        let keyboards = self.keyboards.lock().unwrap();
+        *self.name_index.lock().unwrap() = NameIndex::build(keyboards.values());
+    }
+    
+    /// Returns the engine in use, shared with other callers: the keyboard
+    /// being tried out, else the active keyboard's
+    pub fn get_engine(&self) -> Option<SharedEngine> {
+        self.temporary.lock().unwrap().engine().or_else(|| self.saved_engine())
+    }
+    
+    /// Engine of the active keyboard, also while another one is tried out
+    fn saved_engine(&self) -> Option<SharedEngine> {
+        self.engine.load()
+    }
+
+    /// A new engine for an installed keyboard, set up as it would be when
+    /// active but not shared with anything else
+    pub fn preview_engine(&self, keyboard_id: &str) -> Result<SharedEngine> {
+        let keyboard = self.get_keyboard(keyboard_id)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        self.build_engine(&keyboard)
+    }
+    
+    /// Loads a keyboard file and uses it ahead of the active keyboard until
+    /// the returned token ends the trial; nothing is installed or saved.
+    /// Expiry after `duration` is up to the caller.
+    pub fn activate_temporary_keyboard(&self, path: &Path, duration: Duration) -> Result<TemporaryKeyboardInfo> {
+        let layout = self.load_keyboard_file(path)?;
+        let name = layout.metadata().name()
+            .or_else(|| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        let engine = SharedEngine::from_keyboard(layout)?;
         
-        self.platform.save_config(&config)?;
-        Ok(())
+        // Held throughout so a concurrent end or switch sees the platform
+        // and the trial in step
+        let mut temporary = self.temporary.lock().unwrap();
+        self.platform.set_temporary_keyboard(Some(path))?;
+        let info = temporary.begin(name, path.to_path_buf(), engine, duration);
+        drop(temporary);
+        
+        self.notifications.show_hud(&temporary_keyboard_message(&info.name));
+        if self.switch_announcement().enabled {
+            self.notifications.show_keyboard_switch(&temporary_keyboard_message(&info.name));
+        }
+        Ok(info)
+    }
+    
+    /// Ends the trial `token` started and goes back to the active keyboard;
+    /// false if that trial already ended, was replaced or a switch ended it
+    pub fn end_temporary_activation(&self, token: &str) -> Result<bool> {
+        let mut temporary = self.temporary.lock().unwrap();
+        if !temporary.end(token) {
+            return Ok(false);
+        }
+        self.platform.set_temporary_keyboard(None)?;
+        Ok(true)
     }
-}
\ No newline at end of file
+    
+    /// Ends whatever trial is running, for a normal switch or on exit
+    pub fn end_any_temporary_activation(&self) -> Result<bool> {
+        let mut temporary = self.temporary.lock().unwrap();
+        if !temporary.clear() {
+            return Ok(false);
+        }
+        self.platform.set_temporary_keyboard(None)?;
+        Ok(true)
+    }
+    
+    pub fn temporary_keyboard(&self) -> Option<TemporaryKeyboardInfo> {
+        self.temporary.lock().unwrap().info().cloned()
+    }
+    
+    pub fn update_hotkey(&self, keyboard_id: &str, hotkey: Option<String>) -> Result<()> {
+        self.edit_field(keyboard_id, KeyboardField::Hotkey, || {
+            let mut keyboards = self.keyboards.lock().unwrap();
+            if let Some(keyboard) = keyboards.get_mut(keyboard_id) {
+                // Update hotkey and its normalized display version
+                keyboard.hotkey = hotkey.clone();
+                keyboard.display_hotkey = hotkey.as_ref()
+                    .map(|h| self.platform.normalize_hotkey_for_display(h));
+            }
+            drop(keyboards);
+            
+            self.save_keyboards_to_config()
+        })
+    }
+    
+    /// Writes a new name, description or hotkey into a keyboard's km2 file.
+    /// The new file hash is saved with the keyboard, the hash it had before
+    /// the first edit is kept for comparing with bundled keyboards, and the
+    /// input method reloads the keyboard if it is active.
+    pub fn rewrite_keyboard_metadata(&self, keyboard_id: &str, changes: &MetadataChanges) -> Result<KeyboardInfo> {
+        let keyboard = self.get_keyboard(keyboard_id)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        keyboard_metadata::check_writable(keyboard_id, &keyboard.path, &self.platform.get_keyboards_dir())?;
+
+        self.edit_field(keyboard_id, KeyboardField::Name, || {
+            self.edit_field(keyboard_id, KeyboardField::Hash, || self.apply_metadata(&keyboard, changes))
+        })?;
+        self.get_keyboard(keyboard_id)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()).into())
+    }
+
+    fn apply_metadata(&self, keyboard: &KeyboardInfo, changes: &MetadataChanges) -> Result<()> {
+        let old_hash = self.calculate_file_hash(&keyboard.path)?;
+        let mut layout = self.load_keyboard_file(&keyboard.path)?;
+        keyboard_metadata::apply_changes(&mut layout, changes)?;
+        keyboard_metadata::write_atomic(&keyboard.id, &keyboard.path, &layout)?;
+        let hash = self.calculate_file_hash(&keyboard.path)?;
+
+        let metadata = layout.metadata();
+        let mut keyboards = self.keyboards.lock().unwrap();
+        let Some(stored) = keyboards.get_mut(&keyboard.id) else {
+            return Err(KeyboardNotFound(keyboard.id.clone()).into());
+        };
+        if let Some(name) = metadata.name() {
+            stored.name = name;
+        }
+        stored.description = metadata.description();
+        stored.default_hotkey = metadata.hotkey();
+        stored.default_display_hotkey = stored.default_hotkey.as_ref()
+            .map(|h| self.platform.normalize_hotkey_for_display(h));
+        if stored.original_hash.is_none() && old_hash != hash {
+            stored.original_hash = Some(old_hash);
+        }
+        stored.hash = hash;
+        drop(keyboards);
+        self.rebuild_name_index();
+        self.icon_cache.lock().unwrap().invalidate(&keyboard.id);
+        self.layout_cache.lock().unwrap().invalidate(&keyboard.id);
+
+        self.save_keyboards_to_config()?;
+
+        if self.get_active_keyboard().as_deref() == Some(keyboard.id.as_str()) {
+            if let Some(info) = self.get_keyboard(&keyboard.id) {
+                self.engine.store(Some(self.build_engine(&info)?));
+            }
+            self.platform.notify_ime_update(&keyboard.id)?;
+        }
+        Ok(())
+    }
+
+    /// Marks a field as being edited in the UI so that reloads triggered by
+    /// other processes leave it alone until `end_keyboard_edit`
+    pub fn begin_keyboard_edit(&self, keyboard_id: &str, field: KeyboardField) -> Result<()> {
+        if self.get_keyboard(keyboard_id).is_none() {
+            return Err(KeyboardNotFound(keyboard_id.to_string()).into());
+        }
+        self.pending_edits.lock().unwrap().begin(keyboard_id, field);
+        Ok(())
+    }
+    
+    pub fn end_keyboard_edit(&self, keyboard_id: &str, field: KeyboardField) {
+        self.pending_edits.lock().unwrap().end(keyboard_id, field);
+    }
+    
+    /// Runs a local change with the field marked as pending, so a reload
+    /// racing the save cannot revert it
+    fn edit_field<T>(&self, keyboard_id: &str, field: KeyboardField, edit: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.pending_edits.lock().unwrap().begin(keyboard_id, field);
+        let result = edit();
+        self.pending_edits.lock().unwrap().end(keyboard_id, field);
+        result
+    }
+    
+    /// Takes keyboard changes other processes made to the platform store,
+    /// keeping fields with pending local edits
+    pub fn reload_external_changes(&self) -> Result<KeyboardsChanged> {
+        let saves = self.keyboard_saves.load(Ordering::SeqCst);
+        let config = self.platform.load_config()?;
+        
+        let active = self.get_active_keyboard();
+        let pending = self.pending_edits.lock().unwrap();
+        if self.keyboard_saves.load(Ordering::SeqCst) != saves {
+            // Our own save may have landed after the read; the change
+            // notification it caused reloads again
+            return Ok(KeyboardsChanged::default());
+        }
+        let mut keyboards = self.keyboards.lock().unwrap();
+        let changes = merge_external(&mut keyboards, &config.keyboards.installed, &pending, |installed| {
+            Some(self.keyboard_info_from_installed(installed))
+        });
+        if let Some(keyboard) = active.filter(|id| changes.added.contains(id)).and_then(|id| keyboards.get_mut(&id)) {
+            keyboard.is_active = true;
+        }
+        keyboards.values_mut().for_each(KeyboardInfo::update_status);
+        drop(keyboards);
+        drop(pending);
+        
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        let mut pending = self.pending_edits.lock().unwrap();
+        for id in &changes.removed {
+            pending.forget(id);
+        }
+        drop(pending);
+        for id in changes.removed.iter().map(String::as_str).chain(changes.files_changed()) {
+            self.icon_cache.lock().unwrap().invalidate(id);
+            self.layout_cache.lock().unwrap().invalidate(id);
+        }
+        self.rebuild_name_index();
+        Ok(changes)
+    }
+    
+    pub fn update_output_encoding(&self, keyboard_id: &str, encoding: OutputEncoding) -> Result<()> {
+        self.edit_field(keyboard_id, KeyboardField::OutputEncoding, || self.apply_output_encoding(keyboard_id, encoding))
+    }
+    
+    fn apply_output_encoding(&self, keyboard_id: &str, encoding: OutputEncoding) -> Result<()> {
+        let mut keyboards = self.keyboards.lock().unwrap();
+        let keyboard = keyboards.get_mut(keyboard_id)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        keyboard.output_encoding = encoding;
+        drop(keyboards);
+
+        // Apply immediately if this keyboard is the one loaded in the engine
+        if self.get_active_keyboard().as_deref() == Some(keyboard_id) {
+            if let Some(engine) = self.saved_engine() {
+                engine.set_output_transform(encoding.transform_id())?;
+            }
+        }
+
+        self.save_keyboards_to_config()?;
+        Ok(())
+    }
+    
+    /// Lists the rule groups of a keyboard with the user's toggles
+    pub fn get_rule_groups(&self, keyboard_id: &str) -> Result<Vec<RuleGroupInfo>> {
+        let keyboard = self.get_keyboard(keyboard_id)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        let layout = self.load_keyboard(&keyboard)?;
+
+        Ok(layout.metadata().rule_groups()
+            .into_iter()
+            .map(|group| RuleGroupInfo {
+                enabled: !keyboard.disabled_groups.contains(&group.name),
+                rule_count: group.rule_count(),
+                name: group.name,
+            })
+            .collect())
+    }
+
+    pub fn set_rule_group_enabled(&self, keyboard_id: &str, group: &str, enabled: bool) -> Result<()> {
+        self.edit_field(keyboard_id, KeyboardField::DisabledGroups, || self.apply_rule_group_enabled(keyboard_id, group, enabled))
+    }
+    
+    fn apply_rule_group_enabled(&self, keyboard_id: &str, group: &str, enabled: bool) -> Result<()> {
+        let groups = self.get_rule_groups(keyboard_id)?;
+        if !groups.iter().any(|g| g.name == group) {
+            return Err(anyhow!("Keyboard {} has no rule group named {}", keyboard_id, group));
+        }
+
+        let mut keyboards = self.keyboards.lock().unwrap();
+        let keyboard = keyboards.get_mut(keyboard_id)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        keyboard.disabled_groups.retain(|name| name != group);
+        if !enabled {
+            keyboard.disabled_groups.push(group.to_string());
+        }
+        drop(keyboards);
+
+        // Apply immediately if this keyboard is the one loaded in the engine
+        if self.get_active_keyboard().as_deref() == Some(keyboard_id) {
+            if let Some(engine) = self.saved_engine() {
+                engine.set_group_enabled(group, enabled)?;
+            }
+        }
+
+        self.save_keyboards_to_config()?;
+        Ok(())
+    }
+
+    pub fn get_passthrough_keys(&self, keyboard_id: &str) -> Result<PassthroughKeysInfo> {
+        let keyboard = self.get_keyboard(keyboard_id)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        Ok(PassthroughKeysInfo {
+            keys: keyboard.passthrough_keys,
+            commit_before_passthrough: keyboard.commit_before_passthrough,
+        })
+    }
+
+    /// Replaces the passthrough keys of a keyboard; names are saved in their
+    /// canonical `VK_` form and an unknown name rejects the whole list
+    pub fn set_passthrough_keys(&self, keyboard_id: &str, keys: &[String], commit_before_passthrough: bool) -> Result<()> {
+        self.edit_field(keyboard_id, KeyboardField::PassthroughKeys, || {
+            self.apply_passthrough_keys(keyboard_id, keys, commit_before_passthrough)
+        })
+    }
+    
+    fn apply_passthrough_keys(&self, keyboard_id: &str, keys: &[String], commit_before_passthrough: bool) -> Result<()> {
+        let parsed = parse_key_list(keys)?;
+
+        let mut keyboards = self.keyboards.lock().unwrap();
+        let keyboard = keyboards.get_mut(keyboard_id)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        keyboard.passthrough_keys = parsed.iter().map(|key| key.to_kms_name().to_string()).collect();
+        keyboard.commit_before_passthrough = commit_before_passthrough;
+        drop(keyboards);
+
+        // Apply immediately if this keyboard is the one loaded in the engine
+        if self.get_active_keyboard().as_deref() == Some(keyboard_id) {
+            if let Some(engine) = self.saved_engine() {
+                engine.set_passthrough_keys(&parsed, commit_before_passthrough);
+            }
+        }
+
+        self.save_keyboards_to_config()?;
+        Ok(())
+    }
+
+    /// Hosts a per-application input mode is turned on for
+    pub fn mode_hosts(&self, mode: HostMode) -> Vec<String> {
+        self.get_config().mode_hosts(mode).clone()
+    }
+
+    pub fn set_mode_hosts(&self, mode: HostMode, hosts: Vec<String>) -> Result<()> {
+        let mut config = self.get_config();
+        *config.mode_hosts_mut(mode) = hosts;
+        self.save_config(&config)
+    }
+
+    /// Turns a mode on for a host; a host already in the list is left as is
+    pub fn add_mode_host(&self, mode: HostMode, host: &str) -> Result<()> {
+        let mut config = self.get_config();
+        let hosts = config.mode_hosts_mut(mode);
+        if !hosts.iter().any(|h| h == host) {
+            hosts.push(host.to_string());
+            self.save_config(&config)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_mode_host(&self, mode: HostMode, host: &str) -> Result<()> {
+        let mut config = self.get_config();
+        config.mode_hosts_mut(mode).retain(|h| h != host);
+        self.save_config(&config)
+    }
+
+    /// Single-key shortcut keys of applications, by host pattern
+    pub fn get_shortcut_passthrough_keys(&self) -> BTreeMap<String, Vec<String>> {
+        self.get_config().shortcut_passthrough.hosts
+    }
+
+    /// Replaces the shortcut keys of a host pattern, which pass through
+    /// unprocessed while nothing is composed. Names are saved like
+    /// passthrough keys; an empty list removes the host.
+    pub fn set_shortcut_passthrough_keys(&self, host: &str, keys: &[String]) -> Result<Vec<String>> {
+        let host = host.trim();
+        if host.is_empty() {
+            return Err(anyhow!("Host name is empty"));
+        }
+        let names: Vec<String> = parse_key_list(keys)?.iter().map(|key| key.to_kms_name().to_string()).collect();
+
+        let mut config = self.get_config();
+        if names.is_empty() {
+            config.shortcut_passthrough.hosts.remove(host);
+        } else {
+            config.shortcut_passthrough.hosts.insert(host.to_string(), names.clone());
+        }
+        self.save_config(&config)?;
+        Ok(names)
+    }
+
+    /// Host patterns the text service drops stray characters of eaten keys
+    /// in, see `keymagic_core::char_suppression`
+    pub fn char_suppression_hosts(&self) -> Vec<String> {
+        self.get_config().char_suppression.enabled_hosts
+    }
+
+    /// Replaces the character filter hosts and returns the saved list;
+    /// entries are trimmed, blank and repeated ones dropped
+    pub fn set_char_suppression_hosts(&self, hosts: &[String]) -> Result<Vec<String>> {
+        let mut saved: Vec<String> = Vec::new();
+        for host in hosts.iter().map(|host| host.trim()).filter(|host| !host.is_empty()) {
+            if !saved.iter().any(|h| h.eq_ignore_ascii_case(host)) {
+                saved.push(host.to_string());
+            }
+        }
+
+        let mut config = self.get_config();
+        config.char_suppression.enabled_hosts = saved.clone();
+        self.save_config(&config)?;
+        Ok(saved)
+    }
+
+    /// Stray characters the input methods dropped so far
+    pub fn char_suppression_stats(&self) -> Result<Option<CharSuppressionSnapshot>> {
+        self.platform.char_suppression_stats()
+    }
+    
+    /// Layout options of a keyboard: the km2 defaults, the user's overrides
+    /// and the values in effect
+    pub fn get_keyboard_options(&self, keyboard_id: &str) -> Result<KeyboardOptions> {
+        let keyboard = self.get_keyboard(keyboard_id)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        let layout = self.load_keyboard(&keyboard)?;
+        Ok(keyboard_options::keyboard_options(layout.header.layout_options, &keyboard.options_overrides))
+    }
+
+    /// Overrides a layout option of a keyboard, or goes back to the km2 value
+    /// with `None`
+    pub fn set_keyboard_option(&self, keyboard_id: &str, name: &str, value: Option<serde_json::Value>) -> Result<KeyboardOptions> {
+        self.edit_field(keyboard_id, KeyboardField::OptionsOverrides, || {
+            self.apply_keyboard_option(keyboard_id, name, value)
+        })?;
+        self.get_keyboard_options(keyboard_id)
+    }
+    
+    fn apply_keyboard_option(&self, keyboard_id: &str, name: &str, value: Option<serde_json::Value>) -> Result<()> {
+        if let Some(value) = &value {
+            keyboard_options::validate_override(name, value)?;
+        }
+        let mut keyboards = self.keyboards.lock().unwrap();
+        let keyboard = keyboards.get_mut(keyboard_id)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        match value {
+            Some(value) => keyboard.options_overrides.insert(name.to_string(), value),
+            None => keyboard.options_overrides.remove(name),
+        };
+        let overrides = keyboard.options_overrides.clone();
+        drop(keyboards);
+
+        // Apply immediately if this keyboard is the one loaded in the engine
+        if self.get_active_keyboard().as_deref() == Some(keyboard_id) {
+            if let Some(engine) = self.saved_engine() {
+                let defaults = engine.read().keyboard().header.layout_options;
+                engine.set_layout_options(keyboard_options::effective_options(defaults, &overrides));
+            }
+        }
+
+        self.save_keyboards_to_config()?;
+        Ok(())
+    }
+    
+    /// Saved profiles, sorted by name
+    pub fn list_profiles(&self) -> Result<Vec<ProfileInfo>> {
+        let config = self.platform.load_config()?;
+        let mut profiles: Vec<ProfileInfo> = config.profiles
+            .into_iter()
+            .map(|(name, overrides)| ProfileInfo {
+                active: config.active_profile.as_deref() == Some(name.as_str()),
+                name,
+                overrides,
+            })
+            .collect();
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(profiles)
+    }
+    
+    /// Saves the active and enabled keyboards, the host mode lists and the
+    /// `PROFILE_SETTINGS` as a profile, replacing one of the same name
+    pub fn save_current_as_profile(&self, name: &str) -> Result<ProfileOverrides> {
+        let mut config = self.platform.load_config()?;
+        let mut enabled_keyboards: Vec<String> = self.keyboards.lock().unwrap()
+            .values()
+            .filter(|keyboard| keyboard.enabled)
+            .map(|keyboard| keyboard.id.clone())
+            .collect();
+        enabled_keyboards.sort();
+        let settings = PROFILE_SETTINGS.iter()
+            .filter_map(|key| {
+                let value = self.platform.get_setting(key).ok().flatten()?;
+                Some((key.to_string(), value))
+            })
+            .collect();
+        
+        let overrides = ProfileOverrides {
+            active_keyboard: self.get_active_keyboard(),
+            enabled_keyboards: Some(enabled_keyboards),
+            composition_mode_hosts: Some(config.composition_mode.enabled_hosts.clone()),
+            direct_mode_hosts: Some(config.direct_mode.enabled_hosts.clone()),
+            settings,
+        };
+        config.profiles.insert(name.to_string(), overrides.clone());
+        config.active_profile = Some(name.to_string());
+        self.platform.save_config(&config)?;
+        Ok(overrides)
+    }
+    
+    pub fn delete_profile(&self, name: &str) -> Result<()> {
+        let mut config = self.platform.load_config()?;
+        if config.profiles.remove(name).is_none() {
+            return Err(ProfileNotFound(name.to_string()).into());
+        }
+        if config.active_profile.as_deref() == Some(name) {
+            config.active_profile = None;
+        }
+        self.platform.save_config(&config)
+    }
+    
+    /// Applies a profile as one change. The keyboard it activates is loaded
+    /// before anything changes; keyboards that are not installed or fail to
+    /// load are skipped and reported, and the rest of the profile applies.
+    pub fn apply_profile(&self, name: &str) -> Result<ProfileApplied> {
+        let mut config = self.platform.load_config()?;
+        let overrides = config.profiles.get(name)
+            .cloned()
+            .ok_or_else(|| ProfileNotFound(name.to_string()))?;
+        let mut skipped_keyboards = Vec::new();
+        
+        let mut keyboards = self.keyboards.lock().unwrap();
+        let activation = match &overrides.active_keyboard {
+            Some(id) => match keyboards.get(id).map(|keyboard| self.build_engine(keyboard)) {
+                Some(Ok(engine)) => Some((id.clone(), engine)),
+                Some(Err(e)) => {
+                    log::warn!("Profile {} skips keyboard {}: {:#}", name, id, e);
+                    skipped_keyboards.push(id.clone());
+                    None
+                }
+                None => {
+                    skipped_keyboards.push(id.clone());
+                    None
+                }
+            },
+            None => None,
+        };
+        let activated = activation.as_ref().map(|(id, _)| id.clone());
+        
+        if let Some(enabled) = &overrides.enabled_keyboards {
+            for id in enabled {
+                if !keyboards.contains_key(id) && !skipped_keyboards.contains(id) {
+                    skipped_keyboards.push(id.clone());
+                }
+            }
+            for (id, keyboard) in keyboards.iter_mut() {
+                keyboard.enabled = enabled.contains(id) || activated.as_ref() == Some(id);
+            }
+        }
+        if let Some((id, engine)) = activation {
+            for (keyboard_id, keyboard) in keyboards.iter_mut() {
+                keyboard.is_active = *keyboard_id == id;
+            }
+            self.engine.store(Some(engine));
+            *self.active_keyboard.lock().unwrap() = Some(id);
+        }
+        keyboards.values_mut().for_each(KeyboardInfo::update_status);
+        drop(keyboards);
+        
+        if let Some(hosts) = &overrides.composition_mode_hosts {
+            config.composition_mode.enabled_hosts = hosts.clone();
+        }
+        if let Some(hosts) = &overrides.direct_mode_hosts {
+            config.direct_mode.enabled_hosts = hosts.clone();
+        }
+        config.active_profile = Some(name.to_string());
+        self.write_keyboards(&mut config);
+        self.platform.save_config(&config)?;
+        
+        for (key, value) in &overrides.settings {
+            self.platform.set_setting(key, value)?;
+        }
+        if let Some(id) = &activated {
+            self.end_any_temporary_activation()?;
+            self.platform.switch_keyboard(id)?;
+        }
+        
+        Ok(ProfileApplied {
+            name: name.to_string(),
+            active_keyboard: self.get_active_keyboard(),
+            skipped_keyboards,
+        })
+    }
+    
+    pub fn import_keyboard(&self, file_path: &Path) -> Result<KeyboardInfo> {
+        self.import_keyboard_reporting(file_path, |_| true).map(|(keyboard, _)| keyboard)
+    }
+    
+    /// Imports a keyboard; if its own hotkey is already in use it is left
+    /// off and the clash is returned, with a free variant passing `is_valid`
+    pub fn import_keyboard_reporting(
+        &self,
+        file_path: &Path,
+        is_valid: impl Fn(&str) -> bool,
+    ) -> Result<(KeyboardInfo, Option<HotkeyConflictInfo>)> {
+        // Load the keyboard to validate it
+        let layout = self.load_keyboard_file(file_path)?;
+        
+        let file_stem = file_path.file_stem()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        
+        let metadata = layout.metadata();
+        let name = metadata.name().unwrap_or_else(|| file_stem.to_string());
+        let description = metadata.description().map(|s| s.to_string());
+        let icon_data = metadata.icon().map(|data| data.to_vec());
+        let default_hotkey = metadata.hotkey();
+        let hash = self.calculate_file_hash(file_path)?;
+        
+        // The same keyboard imported again is not installed twice
+        if let Some(existing) = self.installed_copy(&name, &hash) {
+            log::info!("{} is already installed as keyboard {}", file_path.display(), existing.id);
+            return Ok((existing, None));
+        }
+        
+        // The id comes from the name and content, never from the file name
+        // alone, so files of the same name from different authors do not
+        // collide. A file already in the keyboards folder keeps its name.
+        let keyboards_dir = self.platform.get_keyboards_dir();
+        let in_keyboards_dir = file_path.parent() == Some(keyboards_dir.as_path());
+        let slug = keyboard_ids::keyboard_slug(&name, file_stem);
+        let final_id = {
+            let keyboards = self.keyboards.lock().unwrap();
+            keyboard_ids::unique_id(&slug, &hash, |id| {
+                keyboards.contains_key(id) || (!in_keyboards_dir && keyboards_dir.join(format!("{}.km2", id)).exists())
+            })
+        };
+        if let Some(other) = self.get_keyboards().into_iter().find(|kb| kb.name == name || kb.filename == format!("{}.km2", file_stem)) {
+            log::info!(
+                "Keyboard {} from {} shares its name or file name with keyboard {}",
+                final_id,
+                file_path.display(),
+                other.id
+            );
+        }
+        let (final_filename, dest_path) = if in_keyboards_dir {
+            let filename = file_path.file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| anyhow!("Keyboard file name is not valid Unicode"))?
+                .to_string();
+            (filename, file_path.to_path_buf())
+        } else {
+            // Ids are ASCII slugs, valid file names on every OS
+            let filename = format!("{}.km2", final_id);
+            let dest_path = keyboards_dir.join(&filename);
+            (filename, dest_path)
+        };
+        
+        // Copy to keyboards directory
+        if dest_path != file_path {
+            temp_file::copy_atomic(file_path, &dest_path)?;
+        }
+        
+        // Normalize default hotkey for display
+        let default_display_hotkey = default_hotkey.as_ref()
+            .map(|h| self.platform.normalize_hotkey_for_display(h));
+        
+        // A hotkey that is taken is switched off with an empty custom hotkey
+        let hotkey_conflict = match &default_hotkey {
+            Some(hotkey) => {
+                let in_use = self.hotkeys_in_use(None)?;
+                in_use.conflict(hotkey).map(|conflict| HotkeyConflictInfo {
+                    hotkey: hotkey.clone(),
+                    conflict,
+                    proposed: in_use.propose_free(hotkey, &is_valid),
+                })
+            }
+            None => None,
+        };
+        if let Some(info) = &hotkey_conflict {
+            log::info!("Hotkey {} of imported keyboard {} is in use, leaving it off", info.hotkey, final_id);
+        }
+        
+        let sample_text = self.sample_text(&final_id, &layout, &hash);
+        let keyboard_info = KeyboardInfo {
+            id: final_id.clone(),
+            name,
+            filename: final_filename,
+            path: dest_path,
+            hotkey: hotkey_conflict.as_ref().map(|_| String::new()),  // User customization, initially None
+            default_hotkey,
+            hash,
+            original_hash: None,
+            is_active: false,
+            enabled: true,
+            status: KeyboardStatus::Enabled,
+            storage: self.keyboard_storage(&final_id),
+            languages: detect_languages(&layout),
+            sample_text,
+            font_family: layout.metadata().font_family(),
+            description,
+            icon_data,
+            display_hotkey: None,  // No custom hotkey initially
+            default_display_hotkey,
+            output_encoding: OutputEncoding::default(),
+            disabled_groups: Vec::new(),
+            passthrough_keys: Vec::new(),
+            commit_before_passthrough: false,
+            options_overrides: HashMap::new(),
+        };
+        
+        // Add to manager
+        self.add_keyboard(keyboard_info.clone())?;
+        
+        Ok((keyboard_info, hotkey_conflict))
+    }
+    
+    /// Hotkeys that are taken: those of the keyboards other than
+    /// `except_keyboard`, the on/off hotkey and the reserved shortcuts
+    pub fn hotkeys_in_use(&self, except_keyboard: Option<&str>) -> Result<HotkeysInUse> {
+        let mut in_use = HotkeysInUse::new();
+        if let Some(on_off) = self.platform.get_setting("on_off_hotkey")? {
+            in_use.add(&on_off, HotkeyConflict::OnOff);
+        }
+        for keyboard in self.get_keyboards() {
+            if Some(keyboard.id.as_str()) == except_keyboard {
+                continue;
+            }
+            if let Some(hotkey) = keyboard.hotkey_in_effect() {
+                in_use.add(hotkey, HotkeyConflict::Keyboard {
+                    keyboard_id: keyboard.id.clone(),
+                    name: keyboard.name.clone(),
+                });
+            }
+        }
+        Ok(in_use)
+    }
+    
+    /// Whether bundled keyboards are new to this version of the app and
+    /// should be offered for import
+    pub fn should_scan_bundled_keyboards(&self) -> Result<bool> {
+        let config = self.platform.load_config()?;
+        match should_scan(config.general.last_scanned_version.as_deref(), &Version::current()) {
+            Ok(scan) => Ok(scan),
+            Err(e) => {
+                // A damaged value must not hide new bundled keyboards
+                log::warn!("Rescanning bundled keyboards: {}", e);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Keyboards shipped with the app and how they compare to the installed
+    /// ones; empty where the platform bundles none
+    pub fn get_bundled_keyboards(&self) -> Result<Vec<BundledKeyboard>> {
+        let bundled_path = match self.platform.get_bundled_keyboards_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Vec::new()),
+        };
+        let entries = match fs::read_dir(&bundled_path) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let installed = self.get_keyboards();
+        let mut bundled_keyboards = Vec::new();
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("km2") {
+                continue;
+            }
+            let file_stem = path.file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("unknown");
+
+            // Load the keyboard for its proper name and icon and to check for updates
+            let (name, icon_data, hash) = match self.load_keyboard_file(&path) {
+                Ok(layout) => {
+                    let metadata = layout.metadata();
+                    let name = metadata.name().unwrap_or_else(|| file_stem.to_string());
+                    let icon_data = metadata.icon().map(|data| data.to_vec());
+                    let hash = self.calculate_file_hash(&path).unwrap_or_default();
+                    (name, icon_data, hash)
+                }
+                Err(_) => (file_stem.to_string(), None, String::new()),
+            };
+            // The installed keyboard it updates, else the id it would get
+            let id = match bundled_match(&installed, &name, &hash) {
+                Some(installed) => installed.id.clone(),
+                None => keyboard_ids::unique_id(&keyboard_ids::keyboard_slug(&name, file_stem), &hash, |_| false),
+            };
+
+            bundled_keyboards.push(BundledKeyboard {
+                status: bundled_status(&installed, &name, &hash).to_string(),
+                id,
+                name,
+                icon_data,
+                bundled_path: path.to_string_lossy().to_string(),
+            });
+        }
+        self.save_file_hashes();
+
+        Ok(bundled_keyboards)
+    }
+
+    /// Imports a bundled keyboard; an update replaces the installed keyboard
+    /// of the same name
+    pub fn import_bundled_keyboard(&self, path: &Path, update: bool) -> Result<KeyboardInfo> {
+        if update {
+            let layout = self.load_keyboard_file(path).context("Failed to read bundled keyboard")?;
+            let file_stem = path.file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("unknown")
+                .to_string();
+            let name = layout.metadata().name().unwrap_or(file_stem);
+            let hash = self.calculate_file_hash(path)?;
+            if let Some(existing) = bundled_match(&self.get_keyboards(), &name, &hash).cloned() {
+                self.remove_keyboard(&existing.id).context("Failed to remove old keyboard")?;
+            }
+        }
+        self.import_keyboard(path).context("Failed to import keyboard")
+    }
+
+    /// Records that this version of the app has offered its bundled keyboards
+    pub fn mark_bundled_keyboards_scanned(&self) -> Result<()> {
+        let mut config = self.platform.load_config()?;
+        config.general.last_scanned_version = Some(Version::current().to_string());
+        self.platform.save_config(&config)
+    }
+
+    /// Locales `keyboard` declares that are not enabled language profiles;
+    /// empty where the platform has no language profiles
+    pub fn suggested_languages(&self, keyboard: &KeyboardInfo) -> Result<Vec<String>> {
+        if !self.platform.get_platform_info().features.language_profiles {
+            return Ok(Vec::new());
+        }
+        let declared = self.load_keyboard(keyboard)?.metadata().locales();
+        Ok(languages_to_enable(&declared, &self.platform.get_enabled_languages()?))
+    }
+    
+    /// Disagreements between the platform registrations, the keyboard files
+    /// and the loaded keyboards, without repairing anything
+    pub fn store_discrepancies(&self) -> Result<Vec<Discrepancy>> {
+        Ok(classify(&self.store_snapshot()?))
+    }
+    
+    /// Reconciles the platform registrations, the keyboard files and the loaded
+    /// keyboards (see `keyboard_store`), applying the repairs `policy` allows
+    pub fn repair_keyboard_store(&self, policy: RepairPolicy) -> Result<RepairReport> {
+        let discrepancies = classify(&self.store_snapshot()?);
+        self.save_file_hashes();
+        let plan = plan_repairs(&discrepancies, policy);
+        let mut report = RepairReport {
+            discrepancies,
+            needs_confirmation: plan.needs_confirmation,
+            ..Default::default()
+        };
+        if plan.actions.is_empty() {
+            return Ok(report);
+        }
+        
+        let installed = self.platform.load_config()?.keyboards.installed;
+        for action in plan.actions {
+            match self.apply_repair(&action, &installed) {
+                Ok(()) => report.fixed.push(action),
+                Err(e) => report.errors.push(format!("{:?}: {}", action, e)),
+            }
+        }
+        self.rebuild_name_index();
+        
+        // Rewrites every registration from the repaired keyboard list
+        self.save_keyboards_to_config()?;
+        self.platform.notify_keyboards_changed()?;
+        
+        Ok(report)
+    }
+    
+    /// Repair policy from the user's settings; importing unregistered files
+    /// needs the `auto_import_keyboards` setting
+    pub fn repair_policy(&self) -> RepairPolicy {
+        let import_unregistered = self.setting_is("auto_import_keyboards", "true");
+        RepairPolicy { import_unregistered }
+    }
+    
+    fn store_snapshot(&self) -> Result<StoreSnapshot> {
+        let files = self.platform.get_keyboard_files()?
+            .into_iter()
+            .filter_map(|path| {
+                let filename = path.file_name()?.to_str()?.to_string();
+                let hash = self.calculate_file_hash(&path).ok()?;
+                Some(StoreFile { filename, hash })
+            })
+            .collect();
+        
+        let entries = self.keyboards.lock().unwrap()
+            .values()
+            .map(|kb| StoreEntry {
+                id: kb.id.clone(),
+                filename: Some(kb.filename.clone()),
+                hash: kb.hash.clone(),
+            })
+            .collect();
+        let config = KeyboardRegistrations {
+            entries,
+            active: self.get_active_keyboard(),
+        };
+        let registry = self.platform.keyboard_registrations()?;
+        
+        let ids: BTreeSet<&str> = registry.entries.iter().chain(&config.entries).map(|e| e.id.as_str()).collect();
+        let embedded = ids.into_iter()
+            .filter_map(|id| {
+                let data = self.embedded_copy(id)?;
+                Some((id.to_string(), keymagic_core::km2::content_hash(&data)))
+            })
+            .collect();
+        
+        Ok(StoreSnapshot {
+            registry,
+            config,
+            files,
+            embedded,
+        })
+    }
+    
+    fn apply_repair(&self, action: &RepairAction, installed: &[InstalledKeyboard]) -> Result<()> {
+        match action {
+            RepairAction::Remove { id } => {
+                self.keyboards.lock().unwrap().remove(id);
+                self.icon_cache.lock().unwrap().invalidate(id);
+                self.layout_cache.lock().unwrap().invalidate(id);
+            }
+            RepairAction::UpdateHash { id, hash } => {
+                if let Some(keyboard) = self.keyboards.lock().unwrap().get_mut(id) {
+                    keyboard.hash = hash.clone();
+                }
+            }
+            RepairAction::RefreshEmbedded { id } => {
+                let path = self.get_keyboard(id)
+                    .map(|kb| kb.path)
+                    .or_else(|| installed.iter().find(|kb| &kb.id == id).map(|kb| self.platform.get_keyboards_dir().join(&kb.filename)))
+                    .ok_or_else(|| KeyboardNotFound(id.clone()))?;
+                let data = fs::read(&path).context("Failed to read keyboard file")?;
+                embedded_keyboards::check_size(id, &data)?;
+                self.platform.set_embedded_keyboard(id, Some(&data))?;
+            }
+            RepairAction::ClearActive { id } => {
+                let mut active = self.active_keyboard.lock().unwrap();
+                if active.as_deref() == Some(id.as_str()) {
+                    *active = None;
+                    self.engine.store(None);
+                }
+            }
+            RepairAction::Load { id } => {
+                let keyboard = installed.iter()
+                    .find(|kb| &kb.id == id)
+                    .map(|kb| self.keyboard_info_from_installed(kb))
+                    .ok_or_else(|| KeyboardNotFound(id.clone()))?;
+                self.keyboards.lock().unwrap().insert(id.clone(), keyboard);
+            }
+            RepairAction::Register { .. } => {
+                // Written back by the save that follows the repairs
+            }
+            RepairAction::Import { filename } => {
+                self.import_keyboard(&self.platform.get_keyboards_dir().join(filename))?;
+            }
+        }
+        Ok(())
+    }
+    
+    pub fn load_keyboard_file(&self, path: &Path) -> Result<Km2File> {
+        let data = fs::read(path)
+            .context("Failed to read keyboard file")?;
+        
+        Km2Loader::load(&data)
+            .context("Failed to parse keyboard file")
+    }
+    
+    /// Loads an installed keyboard from its file, or from its embedded copy
+    /// if the file is missing
+    pub fn load_keyboard(&self, keyboard: &KeyboardInfo) -> Result<Km2File> {
+        self.load_installed(&keyboard.id, &keyboard.path)
+    }
+    
+    /// Contents of an installed keyboard's file, or of its embedded copy if
+    /// the file is missing
+    pub fn keyboard_data(&self, keyboard: &KeyboardInfo) -> Result<Vec<u8>> {
+        let (data, _) = read_keyboard_data(&keyboard.path, || self.embedded_copy(&keyboard.id))?;
+        Ok(data)
+    }
+    
+    fn load_installed(&self, keyboard_id: &str, path: &Path) -> Result<Km2File> {
+        let (data, _) = read_keyboard_data(path, || self.embedded_copy(keyboard_id))?;
+        Km2Loader::load(&data)
+            .context("Failed to parse keyboard file")
+    }
+    
+    /// The keyboard's copy in the platform store (see `embedded_keyboards`);
+    /// one that cannot be read counts as none
+    fn embedded_copy(&self, keyboard_id: &str) -> Option<Vec<u8>> {
+        self.platform.embedded_keyboard(keyboard_id).unwrap_or_else(|e| {
+            log::warn!("Failed to read the embedded copy of {}: {:#}", keyboard_id, e);
+            None
+        })
+    }
+    
+    fn keyboard_storage(&self, keyboard_id: &str) -> KeyboardStorage {
+        match self.embedded_copy(keyboard_id) {
+            Some(_) => KeyboardStorage::Embedded,
+            None => KeyboardStorage::File,
+        }
+    }
+    
+    /// Embeds a copy of a keyboard in the platform store, or removes it (see
+    /// `embedded_keyboards`). The file stays either way; going back to file
+    /// storage writes it from the copy first if it is missing.
+    pub fn set_keyboard_storage(&self, keyboard_id: &str, storage: KeyboardStorage) -> Result<KeyboardInfo> {
+        let keyboard = self.get_keyboard(keyboard_id).ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        let (data, source) = read_keyboard_data(&keyboard.path, || self.embedded_copy(keyboard_id))?;
+        match storage {
+            KeyboardStorage::Embedded => {
+                embedded_keyboards::check_size(keyboard_id, &data)?;
+                Km2Loader::load(&data).context("Failed to parse keyboard file")?;
+                self.platform.set_embedded_keyboard(keyboard_id, Some(&data))?;
+            }
+            KeyboardStorage::File => {
+                if source == KeyboardStorage::Embedded {
+                    temp_file::write_durable(&keyboard.path, &data)
+                        .with_context(|| format!("Failed to write {}", keyboard.path.display()))?;
+                }
+                self.platform.set_embedded_keyboard(keyboard_id, None)?;
+            }
+        }
+        
+        let check = self.check_file(keyboard_id, &keyboard.path);
+        let mut keyboards = self.keyboards.lock().unwrap();
+        let keyboard = keyboards.get_mut(keyboard_id).ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        keyboard.storage = storage;
+        keyboard.status = KeyboardStatus::of(keyboard.enabled, keyboard.is_active, &keyboard.path, &check);
+        let keyboard = keyboard.clone();
+        drop(keyboards);
+        self.save_file_hashes();
+        self.save_file_checks();
+        Ok(keyboard)
+    }
+    
+    /// Preview text of a keyboard, cached while its file hash is unchanged
+    fn sample_text(&self, keyboard_id: &str, layout: &Km2File, hash: &str) -> Option<String> {
+        self.sample_texts.lock().unwrap().get(keyboard_id, hash, layout)
+    }
+    
+    /// Font to preview a keyboard's output with, checked against the
+    /// installed fonts
+    pub fn preview_font(&self, layout: &Km2File) -> PreviewFont {
+        let installed = self.platform.installed_font_families();
+        resolve_preview_font(layout.metadata().font_family().as_deref(), installed.as_deref())
+    }
+    
+    /// SHA-256 of a keyboard file as lowercase hex, the same as
+    /// `keymagic_core::km2::content_hash` so hosts can compare their engines
+    /// against it. Files unchanged since they were last hashed are not read
+    /// again (see `file_hashes`).
+    pub fn calculate_file_hash(&self, path: &Path) -> Result<String> {
+        let mut file_hashes = self.file_hashes.lock().unwrap();
+        let cache = file_hashes.get_or_insert_with(|| {
+            let setting = self.platform.get_setting(file_hashes::SETTING).ok().flatten();
+            FileHashCache::from_setting(setting.as_deref())
+        });
+        cache.hash(path, SystemTime::now())
+    }
+    
+    /// Writes the file hash cache back to the settings if it changed
+    fn save_file_hashes(&self) {
+        let mut file_hashes = self.file_hashes.lock().unwrap();
+        let Some(cache) = file_hashes.as_mut() else {
+            return;
+        };
+        if !cache.take_changes() {
+            return;
+        }
+        if let Err(e) = self.platform.set_setting(file_hashes::SETTING, &cache.to_setting()) {
+            log::warn!("Failed to save keyboard file hashes: {}", e);
+        }
+    }
+    
+    /// Whether the keyboard file at `path` is there and loads. A file is
+    /// only loaded again once its hash changed (see `keyboard_status`).
+    fn check_file(&self, keyboard_id: &str, path: &Path) -> FileCheck {
+        let hash = match self.calculate_file_hash(path) {
+            Ok(hash) => hash,
+            Err(_) if !path.exists() => return self.check_embedded(keyboard_id),
+            Err(e) => return FileCheck::Found { hash: None, issues: e.chain().map(|e| e.to_string()).collect() },
+        };
+        let issues = self.cached_issues(keyboard_id, &hash, || validate_file(path));
+        FileCheck::Found { hash: Some(hash), issues }
+    }
+    
+    /// Checks the embedded copy standing in for a missing keyboard file
+    fn check_embedded(&self, keyboard_id: &str) -> FileCheck {
+        let Some(data) = self.embedded_copy(keyboard_id) else {
+            return FileCheck::Missing;
+        };
+        let hash = keymagic_core::km2::content_hash(&data);
+        let issues = self.cached_issues(keyboard_id, &hash, || validate_data(&data));
+        FileCheck::Embedded { hash, issues }
+    }
+    
+    fn cached_issues(&self, keyboard_id: &str, hash: &str, validate: impl FnOnce() -> Vec<String>) -> Vec<String> {
+        let mut file_checks = self.file_checks.lock().unwrap();
+        let cache = file_checks.get_or_insert_with(|| {
+            let setting = self.platform.get_setting(keyboard_status::SETTING).ok().flatten();
+            FileCheckCache::from_setting(setting.as_deref())
+        });
+        cache.issues(keyboard_id, hash, validate)
+    }
+    
+    /// Writes the file check results back to the settings if they changed,
+    /// dropping those of keyboards that are gone
+    fn save_file_checks(&self) {
+        let ids: Vec<String> = self.keyboards.lock().unwrap().keys().cloned().collect();
+        let mut file_checks = self.file_checks.lock().unwrap();
+        let Some(cache) = file_checks.as_mut() else {
+            return;
+        };
+        cache.retain(&ids.iter().map(String::as_str).collect::<Vec<_>>());
+        if !cache.take_changes() {
+            return;
+        }
+        if let Err(e) = self.platform.set_setting(keyboard_status::SETTING, &cache.to_setting()) {
+            log::warn!("Failed to save keyboard file checks: {}", e);
+        }
+    }
+    
+    /// Checks every keyboard file again and updates the statuses, e.g. for
+    /// a file that was restored or replaced while the app ran
+    pub fn refresh_keyboard_statuses(&self) {
+        let keyboards: Vec<(String, PathBuf)> = self.keyboards.lock().unwrap()
+            .values()
+            .map(|kb| (kb.id.clone(), kb.path.clone()))
+            .collect();
+        let checks: Vec<(String, FileCheck)> = keyboards.into_iter()
+            .map(|(id, path)| {
+                let check = self.check_file(&id, &path);
+                (id, check)
+            })
+            .collect();
+        
+        let mut keyboards = self.keyboards.lock().unwrap();
+        for (id, check) in checks {
+            if let Some(keyboard) = keyboards.get_mut(&id) {
+                keyboard.status = KeyboardStatus::of(keyboard.enabled, keyboard.is_active, &keyboard.path, &check);
+            }
+        }
+        drop(keyboards);
+        self.save_file_hashes();
+        self.save_file_checks();
+    }
+    
+    /// Why a keyboard has its status, checking its file again first
+    pub fn keyboard_status_detail(&self, keyboard_id: &str) -> Result<KeyboardStatusDetail> {
+        let path = self.get_keyboard(keyboard_id)
+            .map(|kb| kb.path)
+            .ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        let check = self.check_file(keyboard_id, &path);
+        let storage = self.keyboard_storage(keyboard_id);
+        
+        let mut keyboards = self.keyboards.lock().unwrap();
+        let keyboard = keyboards.get_mut(keyboard_id).ok_or_else(|| KeyboardNotFound(keyboard_id.to_string()))?;
+        keyboard.status = KeyboardStatus::of(keyboard.enabled, keyboard.is_active, &path, &check);
+        keyboard.storage = storage;
+        let status = keyboard.status.clone();
+        drop(keyboards);
+        self.save_file_hashes();
+        self.save_file_checks();
+        
+        Ok(KeyboardStatusDetail {
+            keyboard_id: keyboard_id.to_string(),
+            message: status.message(),
+            file_hash: check.hash().map(str::to_string),
+            status,
+            storage,
+            from_embedded: matches!(check, FileCheck::Embedded { .. }),
+        })
+    }
+    
+    /// Forgets the cached file hashes and checks the store with every
+    /// keyboard file hashed again
+    pub fn force_rehash_keyboards(&self) -> Result<RepairReport> {
+        self.file_hashes.lock().unwrap().get_or_insert_with(FileHashCache::default).clear();
+        self.repair_keyboard_store(self.repair_policy())
+    }
+    
+    fn update_active_flags(&self, active_id: &str) -> Result<()> {
+        let mut keyboards = self.keyboards.lock().unwrap();
+        for (id, keyboard) in keyboards.iter_mut() {
+            keyboard.is_active = id == active_id;
+            keyboard.update_status();
+        }
+        Ok(())
+    }
+    
+    fn save_keyboards_to_config(&self) -> Result<()> {
+        let mut config = self.platform.load_config()?;
+        self.write_keyboards(&mut config);
+        self.platform.save_config(&config)?;
+        self.keyboard_saves.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+    
+    /// Copies the active and installed keyboards into `config`
+    fn write_keyboards(&self, config: &mut crate::platform::Config) {
+        // Update active keyboard
+        config.keyboards.active = self.active_keyboard.lock().unwrap().clone();
+        
+        // Update installed keyboards
+        let keyboards = self.keyboards.lock().unwrap();
+        config.keyboards.installed = keyboards
+            .values()
+            .map(|kb| InstalledKeyboard {
+                id: kb.id.clone(),
+                name: kb.name.clone(),
+                filename: kb.filename.clone(),
+                hotkey: kb.hotkey.clone(),
+                hash: kb.hash.clone(),
+                original_hash: kb.original_hash.clone(),
+                enabled: kb.enabled,
+                output_encoding: kb.output_encoding,
+                disabled_groups: kb.disabled_groups.clone(),
+                passthrough_keys: kb.passthrough_keys.clone(),
+                commit_before_passthrough: kb.commit_before_passthrough,
+                options_overrides: kb.options_overrides.clone(),
+                sample_text: kb.sample_text.clone(),
+            })
+            .collect();
+    }
+}
+
+fn unix_time_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_millis() as u64)
+}
+
+/// Parses key names for passthrough and shortcut keys, dropping repeats; an
+/// unknown name rejects the whole list
+fn parse_key_list(keys: &[String]) -> Result<Vec<VirtualKey>> {
+    let mut parsed: Vec<VirtualKey> = Vec::new();
+    for key in parse_vk_names(keys)? {
+        if !parsed.contains(&key) {
+            parsed.push(key);
+        }
+    }
+    Ok(parsed)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::keyboard_activation::{ActivationFailure, KeyboardActivationError};
+    use super::super::language_activation::InvalidLanguageKey;
+    use crate::platform::MockPlatform;
+    use keymagic_core::processing_state::{GuiLiveness, HostAction, HostProcessingState, ProcessingState};
+
+    fn manager_with_keyboards(name: &str, ids: &[&str], settings: &[(&str, &str)]) -> KeyboardManager {
+        manager_with_switch(name, ids, settings, None)
+    }
+
+    fn manager_with_switch(
+        name: &str,
+        ids: &[&str],
+        settings: &[(&str, &str)],
+        processing: Option<Arc<ProcessingState>>,
+    ) -> KeyboardManager {
+        let mut builder = MockPlatform::builder(&format!("manager-{}", name)).active(ids[0]);
+        for id in ids {
+            builder = builder.keyboard(id);
+        }
+        for (key, value) in settings {
+            builder = builder.setting(key, value);
+        }
+        if let Some(state) = processing {
+            builder = builder.processing(state);
+        }
+
+        let manager = KeyboardManager::new(Box::new(builder.build()));
+        manager.initialize().unwrap();
+        manager
+    }
+
+    #[test]
+    fn test_keyboard_option_overrides() {
+        let manager = manager_with_keyboards("options", &["myanmar3", "zawgyi"], &[]);
+        let options = manager.get_keyboard_options("myanmar3").unwrap();
+        assert_eq!(options.defaults, options.effective);
+        assert!(options.overrides.is_empty());
+
+        let options = manager.set_keyboard_option("myanmar3", "auto_bksp", Some(serde_json::json!(true))).unwrap();
+        assert!(!options.defaults["auto_bksp"]);
+        assert!(options.effective["auto_bksp"]);
+        // The active keyboard's engine picks it up at once
+        assert_eq!(manager.get_engine().unwrap().layout_options().auto_bksp, 1);
+        let config = manager.get_platform().load_config().unwrap();
+        let saved = config.keyboards.installed.iter().find(|kb| kb.id == "myanmar3").unwrap();
+        assert_eq!(saved.options_overrides["auto_bksp"], serde_json::json!(true));
+
+        // Bad names and values are rejected and change nothing
+        assert!(manager.set_keyboard_option("myanmar3", "smart_quotes", Some(serde_json::json!(true))).is_err());
+        assert!(manager.set_keyboard_option("myanmar3", "eat", Some(serde_json::json!("yes"))).is_err());
+        assert_eq!(manager.get_keyboard_options("myanmar3").unwrap().overrides.len(), 1);
+
+        // Overrides survive switching keyboards
+        manager.set_active_keyboard("zawgyi").unwrap();
+        assert_eq!(manager.get_engine().unwrap().layout_options().auto_bksp, 0);
+        manager.set_active_keyboard("myanmar3").unwrap();
+        assert_eq!(manager.get_engine().unwrap().layout_options().auto_bksp, 1);
+
+        let options = manager.set_keyboard_option("myanmar3", "auto_bksp", None).unwrap();
+        assert!(options.overrides.is_empty());
+        assert_eq!(manager.get_engine().unwrap().layout_options().auto_bksp, 0);
+    }
+
+    #[test]
+    fn test_keyboards_carry_sample_text() {
+        let platform = MockPlatform::builder("manager-sample")
+            .keyboard_from_kms("declared", "// @SAMPLE = \"မြန်မာ\"\n\"q\" => \"ဆ\"")
+            .keyboard_from_kms("generated", "\"q\" => \"ဆ\"\n\"w\" => \"တ\"")
+            .keyboard("plain")
+            .build();
+        let manager = KeyboardManager::new(Box::new(platform));
+        manager.initialize().unwrap();
+
+        let sample = |id: &str| {
+            let keyboards = manager.get_keyboards();
+            keyboards.iter().find(|kb| kb.id == id).unwrap().sample_text.clone()
+        };
+        assert_eq!(sample("declared").as_deref(), Some("မြန်မာ"));
+        assert_eq!(sample("generated").as_deref(), Some("ဆတ"));
+        assert_eq!(sample("plain"), None);
+
+        // Saved for the text services' switch notification
+        manager.set_active_keyboard("generated").unwrap();
+        let config = manager.get_platform().load_config().unwrap();
+        let saved = config.keyboards.installed.iter().find(|kb| kb.id == "generated").unwrap();
+        assert_eq!(saved.sample_text.as_deref(), Some("ဆတ"));
+    }
+
+    #[test]
+    fn test_keyboard_switch_is_announced() {
+        let manager = manager_with_keyboards("announce", &["myanmar3", "zawgyi"], &[]);
+        let spoken = Arc::new(Mutex::new(Vec::new()));
+        let sink = spoken.clone();
+        manager.notifications().set_announcer(move |message| sink.lock().unwrap().push(message.to_string()));
+
+        // The test platform cannot detect a screen reader, so nothing is said
+        let info = manager.switch_announcement();
+        assert!(!info.enabled);
+        assert_eq!(info.screen_reader_active, None);
+        manager.set_active_keyboard("zawgyi").unwrap();
+        assert!(spoken.lock().unwrap().is_empty());
+
+        manager.set_switch_announcement(Some(true)).unwrap();
+        manager.set_active_keyboard("zawgyi").unwrap();
+        assert_eq!(*spoken.lock().unwrap(), vec!["Keyboard: zawgyi".to_string()]);
+
+        manager.set_switch_announcement(Some(false)).unwrap();
+        manager.set_active_keyboard("myanmar3").unwrap();
+        assert_eq!(spoken.lock().unwrap().len(), 1);
+
+        manager.set_switch_announcement(None).unwrap();
+        assert_eq!(manager.switch_announcement().setting, None);
+    }
+
+    /// Writes a keyboard named "Trial" next to the installed ones without installing it
+    fn write_trial_keyboard(manager: &KeyboardManager) -> PathBuf {
+        let path = manager.get_platform().get_keyboards_dir().join("trial.km2");
+        let mut buffer = Vec::new();
+        kms2km2::binary::Km2Writer::new(&mut buffer)
+            .write_km2_file(&kms2km2::compile_kms("/*\n@NAME = \"Trial\"\n*/\n\"k\" => \"ခ\"").unwrap())
+            .unwrap();
+        fs::write(&path, &buffer).unwrap();
+        path
+    }
+
+    fn engine_name(manager: &KeyboardManager) -> Option<String> {
+        manager.get_engine().unwrap().read().keyboard().metadata().name()
+    }
+
+    #[test]
+    fn test_temporary_keyboard_is_not_saved() {
+        let manager = manager_with_keyboards("trial", &["myanmar3"], &[]);
+        let hud = Arc::new(Mutex::new(Vec::new()));
+        let sink = hud.clone();
+        manager.notifications().set_hud_sink(move |message| sink.lock().unwrap().push(message.to_string()));
+        let path = write_trial_keyboard(&manager);
+        let config_before = manager.get_platform().load_config().unwrap();
+
+        let info = manager.activate_temporary_keyboard(&path, Duration::from_secs(60)).unwrap();
+        assert_eq!(info.name, "Trial");
+        assert_eq!(engine_name(&manager).as_deref(), Some("Trial"));
+        assert_eq!(manager.temporary_keyboard(), Some(info.clone()));
+        assert_eq!(*hud.lock().unwrap(), vec!["Trial (temporary)".to_string()]);
+        // Neither the active keyboard nor the installed list changes
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("myanmar3"));
+        assert_eq!(manager.get_keyboards().len(), 1);
+        let config = manager.get_platform().load_config().unwrap();
+        assert_eq!(config.keyboards.active, config_before.keyboards.active);
+        assert_eq!(config.keyboards.installed.len(), 1);
+
+        assert!(manager.end_temporary_activation(&info.token).unwrap());
+        assert!(manager.temporary_keyboard().is_none());
+        assert_eq!(engine_name(&manager), None);
+        // A late expiry does nothing
+        assert!(!manager.end_temporary_activation(&info.token).unwrap());
+    }
+
+    #[test]
+    fn test_switch_ends_temporary_keyboard() {
+        let manager = manager_with_keyboards("trial-switch", &["myanmar3", "zawgyi"], &[]);
+        let path = write_trial_keyboard(&manager);
+
+        let first = manager.activate_temporary_keyboard(&path, Duration::from_secs(60)).unwrap();
+        let second = manager.activate_temporary_keyboard(&path, Duration::from_secs(60)).unwrap();
+        // The first trial's expiry must not end the second one
+        assert!(!manager.end_temporary_activation(&first.token).unwrap());
+        assert_eq!(manager.temporary_keyboard(), Some(second.clone()));
+
+        // Settings edits during the trial apply to the active keyboard's engine
+        manager.set_keyboard_option("myanmar3", "auto_bksp", Some(serde_json::json!(true))).unwrap();
+        assert_eq!(manager.get_engine().unwrap().layout_options().auto_bksp, 0);
+
+        // A normal switch wins over the trial
+        manager.set_active_keyboard("zawgyi").unwrap();
+        assert!(manager.temporary_keyboard().is_none());
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("zawgyi"));
+        assert!(!manager.end_temporary_activation(&second.token).unwrap());
+        assert!(!manager.end_any_temporary_activation().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_temporary_keyboard_keeps_active() {
+        let manager = manager_with_keyboards("trial-invalid", &["myanmar3"], &[]);
+        let path = manager.get_platform().get_keyboards_dir().join("broken.km2");
+        fs::write(&path, b"not a keyboard").unwrap();
+        assert!(manager.activate_temporary_keyboard(&path, Duration::from_secs(60)).is_err());
+        assert!(manager.temporary_keyboard().is_none());
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("myanmar3"));
+    }
+
+    #[test]
+    fn test_hotkey_switch_loop_is_broken() {
+        let manager = manager_with_keyboards("loop", &["myanmar3", "zawgyi"], &[("key_processing_enabled", "true")]);
+        let huds = Arc::new(Mutex::new(Vec::new()));
+        let sink = huds.clone();
+        manager.notifications().set_hud_sink(move |message| sink.lock().unwrap().push(message.to_string()));
+
+        // A burst as from a hotkey re-triggered by its own output
+        let ids = ["zawgyi", "myanmar3"];
+        for id in ids.iter().cycle().take(keymagic_core::hotkey::SWITCH_LOOP_MAX_SWITCHES) {
+            assert_eq!(manager.activate_keyboard_by_hotkey(id).unwrap(), HotkeyActivation::Switched);
+        }
+        let active = manager.get_active_keyboard();
+        for _ in 0..3 {
+            assert_eq!(manager.activate_keyboard_by_hotkey("myanmar3").unwrap(), HotkeyActivation::Suppressed);
+            assert_eq!(manager.activate_keyboard_by_hotkey("zawgyi").unwrap(), HotkeyActivation::Suppressed);
+        }
+        assert_eq!(manager.get_active_keyboard(), active);
+        // Warned once
+        assert_eq!(huds.lock().unwrap().iter().filter(|hud| *hud == SWITCH_LOOP_MESSAGE).count(), 1);
+    }
+
+    #[test]
+    fn test_hotkey_while_processing_disabled_is_handed_off() {
+        let manager = manager_with_keyboards(
+            "pending",
+            &["myanmar3", "zawgyi"],
+            &[("key_processing_enabled", "false"), ("on_off_hotkey", "CTRL+SPACE")],
+        );
+        let huds = Arc::new(Mutex::new(Vec::new()));
+        let sink = huds.clone();
+        manager.notifications().set_hud_sink(move |message| sink.lock().unwrap().push(message.to_string()));
+        assert!(!manager.is_key_processing_enabled());
+
+        let activation = manager.activate_keyboard_by_hotkey("zawgyi").unwrap();
+        let message = "KeyMagic is off — press Ctrl+Space to enable".to_string();
+        assert_eq!(activation, HotkeyActivation::Pending { message: message.clone() });
+        assert_eq!(*huds.lock().unwrap(), vec![message]);
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("myanmar3"));
+        assert_eq!(manager.pending_keyboard().as_deref(), Some("zawgyi"));
+
+        assert_eq!(manager.set_key_processing_enabled(true).unwrap().as_deref(), Some("zawgyi"));
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("zawgyi"));
+        assert_eq!(manager.pending_keyboard(), None);
+        assert_eq!(manager.get_platform().get_setting("key_processing_enabled").unwrap().as_deref(), Some("true"));
+
+        assert!(manager.activate_keyboard_by_hotkey("missing").is_err());
+    }
+
+    #[test]
+    fn test_disabling_processing_waits_for_input_method() {
+        let state = Arc::new(ProcessingState::in_memory());
+        let manager = manager_with_switch("switch", &["myanmar3"], &[], Some(state.clone()));
+        let huds = Arc::new(Mutex::new(Vec::new()));
+        let sink = huds.clone();
+        manager.notifications().set_hud_sink(move |message| sink.lock().unwrap().push(message.to_string()));
+
+        // An input method commits its composition before standing down
+        let host_state = state.clone();
+        let host = std::thread::spawn(move || {
+            let mut host = HostProcessingState::new();
+            loop {
+                if let HostAction::FlushThenDisable { request } = host.observe(host_state.is_enabled(), host_state.request()) {
+                    host_state.acknowledge(host.finish_disable(request).unwrap());
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+        assert_eq!(manager.set_key_processing_enabled(false).unwrap(), None);
+        host.join().unwrap();
+        assert!(!state.is_enabled());
+        assert!(huds.lock().unwrap().is_empty());
+
+        // Nobody answers now; the HUD says the composition is being finished
+        manager.set_key_processing_enabled(true).unwrap();
+        assert!(state.is_enabled());
+        manager.set_key_processing_enabled(false).unwrap();
+        assert!(!state.is_enabled());
+        assert!(!manager.is_key_processing_enabled());
+        assert_eq!(*huds.lock().unwrap(), vec![FINISHING_COMPOSITION_MESSAGE.to_string()]);
+    }
+
+    #[test]
+    fn test_start_after_crash_resumes_input_methods() {
+        // The last session crashed long ago with processing switched off
+        let state = Arc::new(ProcessingState::in_memory());
+        state.request_disable();
+        state.beat(1_000);
+        let mut liveness = GuiLiveness::new();
+        assert!(!liveness.observe(state.last_heartbeat(), unix_time_ms(), false));
+
+        let manager = manager_with_switch("heartbeat", &["myanmar3"], &[], Some(state.clone()));
+        assert!(state.is_enabled());
+        assert!(liveness.observe(state.last_heartbeat(), unix_time_ms(), false));
+        assert_eq!(manager.heartbeat_report().unwrap().status, "fresh");
+
+        let before = state.last_heartbeat().unwrap();
+        assert_eq!(manager.beat_input_methods(), Some(before));
+        assert!(state.last_heartbeat().unwrap() >= before);
+    }
+
+    #[test]
+    fn test_hotkey_auto_enables_processing() {
+        let manager = manager_with_keyboards(
+            "auto-enable",
+            &["myanmar3", "zawgyi"],
+            &[("key_processing_enabled", "false"), ("auto_enable_on_keyboard_hotkey", "true")],
+        );
+        assert_eq!(manager.activate_keyboard_by_hotkey("zawgyi").unwrap(), HotkeyActivation::Enabled);
+        assert!(manager.is_key_processing_enabled());
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("zawgyi"));
+
+        assert_eq!(manager.activate_keyboard_by_hotkey("myanmar3").unwrap(), HotkeyActivation::Switched);
+    }
+
+    #[test]
+    fn test_removed_pending_keyboard_is_not_activated() {
+        let manager = manager_with_keyboards("removed", &["myanmar3", "zawgyi"], &[("key_processing_enabled", "false")]);
+        manager.activate_keyboard_by_hotkey("zawgyi").unwrap();
+        manager.remove_keyboard("zawgyi").unwrap();
+        assert_eq!(manager.set_key_processing_enabled(true).unwrap(), None);
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("myanmar3"));
+    }
+
+
+    #[test]
+    fn test_external_changes_keep_pending_edits() {
+        let manager = manager_with_keyboards("external", &["myanmar3", "zawgyi"], &[]);
+        manager.begin_keyboard_edit("myanmar3", KeyboardField::Hotkey).unwrap();
+        assert!(manager.begin_keyboard_edit("missing", KeyboardField::Hotkey).is_err());
+
+        // Another process writes the store while the hotkey is being edited
+        let mut config = manager.get_platform().load_config().unwrap();
+        for keyboard in &mut config.keyboards.installed {
+            keyboard.hotkey = Some("CTRL+SHIFT+M".to_string());
+            keyboard.enabled = false;
+        }
+        manager.get_platform().save_config(&config).unwrap();
+
+        let changes = manager.reload_external_changes().unwrap();
+        assert_eq!(changes.changed["myanmar3"], vec![KeyboardField::Enabled]);
+        assert_eq!(changes.changed["zawgyi"], vec![KeyboardField::Hotkey, KeyboardField::Enabled]);
+        let myanmar3 = manager.get_keyboard("myanmar3").unwrap();
+        assert_eq!(myanmar3.hotkey, None);
+        assert!(!myanmar3.enabled);
+        assert!(myanmar3.is_active);
+        assert_eq!(manager.get_keyboard("zawgyi").unwrap().display_hotkey.as_deref(), Some("Ctrl+SHIFT+M"));
+
+        // Saving the edit ends it; the stored value is ours from then on
+        manager.end_keyboard_edit("myanmar3", KeyboardField::Hotkey);
+        manager.update_hotkey("myanmar3", Some("CTRL+K".to_string())).unwrap();
+        assert!(manager.reload_external_changes().unwrap().is_empty());
+        assert_eq!(manager.get_keyboard("myanmar3").unwrap().hotkey.as_deref(), Some("CTRL+K"));
+    }
+
+    #[test]
+    fn test_input_language_switches_processing() {
+        let manager = manager_with_keyboards("language", &["myanmar3", "zawgyi"], &[("key_processing_enabled", "true")]);
+        let huds = Arc::new(Mutex::new(Vec::new()));
+        let sink = huds.clone();
+        manager.notifications().set_hud_sink(move |message| sink.lock().unwrap().push(message.to_string()));
+        let english = InputLanguage::new(0x0409, Some("en-US"));
+        let myanmar = InputLanguage::new(0x0455, Some("my-MM"));
+
+        let rules = BTreeMap::from([
+            ("en".to_string(), LanguageAction::Disable),
+            ("my-MM".to_string(), LanguageAction::Keyboard { keyboard_id: "zawgyi".into() }),
+        ]);
+        manager.set_language_activation_rules(rules).unwrap();
+        // Nothing happens until the feature is turned on
+        assert_eq!(manager.apply_input_language(&english).unwrap(), None);
+        manager.set_language_activation_enabled(true).unwrap();
+
+        let applied = manager.apply_input_language(&english).unwrap().unwrap();
+        assert_eq!(applied.rule, "en");
+        assert!(!applied.key_processing_enabled);
+        assert!(!manager.is_key_processing_enabled());
+        assert_eq!(manager.get_platform().get_setting("key_processing_enabled").unwrap().as_deref(), Some("false"));
+        // Already off
+        assert_eq!(manager.apply_input_language(&english).unwrap(), None);
+
+        let applied = manager.apply_input_language(&myanmar).unwrap().unwrap();
+        assert!(applied.key_processing_enabled);
+        assert_eq!(applied.active_keyboard.as_deref(), Some("zawgyi"));
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some("zawgyi"));
+        assert_eq!(*huds.lock().unwrap(), vec!["KeyMagic off for en-US", "zawgyi for my-MM"]);
+
+        // Languages without a rule leave everything as it is
+        assert_eq!(manager.apply_input_language(&InputLanguage::new(0x0411, Some("ja-JP"))).unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_language_rules_are_rejected() {
+        let manager = manager_with_keyboards("language-rules", &["myanmar3"], &[]);
+        let invalid_key = BTreeMap::from([("english".to_string(), LanguageAction::Disable)]);
+        assert!(manager.set_language_activation_rules(invalid_key).unwrap_err().is::<InvalidLanguageKey>());
+        let missing = BTreeMap::from([("0455".to_string(), LanguageAction::Keyboard { keyboard_id: "missing".into() })]);
+        assert!(manager.set_language_activation_rules(missing).unwrap_err().is::<KeyboardNotFound>());
+        assert!(manager.language_activation().unwrap().rules.is_empty());
+    }
+
+    fn assert_still_active(manager: &KeyboardManager, keyboard_id: &str) {
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some(keyboard_id));
+        assert_eq!(manager.get_config().keyboards.active.as_deref(), Some(keyboard_id));
+        let flagged: Vec<String> = manager.get_keyboards().into_iter()
+            .filter(|keyboard| keyboard.is_active)
+            .map(|keyboard| keyboard.id)
+            .collect();
+        assert_eq!(flagged, vec![keyboard_id.to_string()]);
+    }
+
+    #[test]
+    fn test_activating_corrupt_keyboard_keeps_previous() {
+        let manager = manager_with_keyboards("corrupt", &["myanmar3", "zawgyi"], &[]);
+        let path = manager.get_keyboard("zawgyi").unwrap().path;
+        fs::write(&path, b"KMKL not really a keyboard").unwrap();
+
+        let err = manager.set_active_keyboard("zawgyi").unwrap_err();
+        let failure = err.downcast_ref::<KeyboardActivationError>().unwrap();
+        assert_eq!(failure.keyboard_id, "zawgyi");
+        assert_eq!(failure.reason, ActivationFailure::Invalid);
+        assert!(!failure.issues.is_empty());
+        assert_still_active(&manager, "myanmar3");
+    }
+
+    #[test]
+    fn test_activating_keyboard_with_missing_file_keeps_previous() {
+        let manager = manager_with_keyboards("missing-file", &["myanmar3", "zawgyi"], &[]);
+        fs::remove_file(manager.get_keyboard("zawgyi").unwrap().path).unwrap();
+
+        let err = manager.set_active_keyboard("zawgyi").unwrap_err();
+        let failure = err.downcast_ref::<KeyboardActivationError>().unwrap();
+        assert_eq!(failure.reason, ActivationFailure::FileMissing);
+        assert_still_active(&manager, "myanmar3");
+
+        // A hotkey for it fails the same way
+        assert!(manager.activate_keyboard_by_hotkey("zawgyi").is_err());
+        assert_still_active(&manager, "myanmar3");
+    }
+
+    #[test]
+    fn test_activation_prewarms_engine() {
+        let manager = manager_with_keyboards("prewarm", &["myanmar3", "zawgyi"], &[]);
+
+        manager.set_active_keyboard("zawgyi").unwrap();
+        assert_still_active(&manager, "zawgyi");
+        assert!(manager.get_engine().is_some());
+
+        // The cached layout is dropped once the file changes
+        let path = manager.get_keyboard("myanmar3").unwrap().path;
+        manager.set_active_keyboard("myanmar3").unwrap();
+        fs::write(&path, b"truncated").unwrap();
+        assert!(manager.set_active_keyboard("zawgyi").is_ok());
+        assert!(manager.set_active_keyboard("myanmar3").is_err());
+        assert_still_active(&manager, "zawgyi");
+    }
+
+    /// What a profile controls, as currently configured
+    #[derive(Debug, PartialEq)]
+    struct ProfileState {
+        active: Option<String>,
+        configured_active: Option<String>,
+        enabled: Vec<(String, bool)>,
+        composition_mode_hosts: Vec<String>,
+        direct_mode_hosts: Vec<String>,
+        settings: Vec<Option<String>>,
+    }
+
+    fn profile_state(manager: &KeyboardManager) -> ProfileState {
+        let mut enabled: Vec<(String, bool)> = manager.get_keyboards().into_iter()
+            .map(|keyboard| (keyboard.id, keyboard.enabled))
+            .collect();
+        enabled.sort();
+        let config = manager.get_config();
+        ProfileState {
+            active: manager.get_active_keyboard(),
+            configured_active: config.keyboards.active,
+            enabled,
+            composition_mode_hosts: config.composition_mode.enabled_hosts,
+            direct_mode_hosts: config.direct_mode.enabled_hosts,
+            settings: PROFILE_SETTINGS.iter()
+                .map(|key| manager.get_platform().get_setting(key).unwrap())
+                .collect(),
+        }
+    }
+
+    fn set_enabled(manager: &KeyboardManager, keyboard_id: &str, enabled: bool) {
+        manager.keyboards.lock().unwrap().get_mut(keyboard_id).unwrap().enabled = enabled;
+        manager.save_keyboards_to_config().unwrap();
+    }
+
+    #[test]
+    fn test_profile_round_trip() {
+        let manager = manager_with_keyboards("profile-round-trip", &["myanmar3", "zawgyi", "pali"], &[]);
+        manager.set_active_keyboard("zawgyi").unwrap();
+        set_enabled(&manager, "pali", false);
+        let mut config = manager.get_config();
+        config.composition_mode.enabled_hosts = vec!["winword.exe".to_string()];
+        manager.save_config(&config).unwrap();
+        manager.get_platform().set_setting("hud_enabled", "false").unwrap();
+
+        manager.save_current_as_profile("work").unwrap();
+        let saved = profile_state(&manager);
+
+        // Change everything the profile covers
+        manager.set_active_keyboard("myanmar3").unwrap();
+        set_enabled(&manager, "pali", true);
+        set_enabled(&manager, "zawgyi", false);
+        let mut changed = manager.get_config();
+        changed.composition_mode.enabled_hosts.clear();
+        changed.direct_mode.enabled_hosts = vec!["com.apple.Notes".to_string()];
+        manager.save_config(&changed).unwrap();
+        manager.get_platform().set_setting("hud_enabled", "true").unwrap();
+
+        let applied = manager.apply_profile("work").unwrap();
+        assert_eq!(applied.active_keyboard.as_deref(), Some("zawgyi"));
+        assert!(applied.skipped_keyboards.is_empty());
+
+        assert_eq!(profile_state(&manager), saved);
+        assert_eq!(manager.get_config().active_profile.as_deref(), Some("work"));
+        assert!(manager.get_engine().is_some());
+    }
+
+    #[test]
+    fn test_profile_with_missing_keyboard_applies_the_rest() {
+        let manager = manager_with_keyboards("profile-missing", &["myanmar3", "zawgyi"], &[]);
+        let mut config = manager.get_config();
+        config.profiles.insert("chat".to_string(), ProfileOverrides {
+            active_keyboard: Some("shan".to_string()),
+            enabled_keyboards: Some(vec!["zawgyi".to_string(), "shan".to_string()]),
+            direct_mode_hosts: Some(vec!["ru.keepcoder.Telegram".to_string()]),
+            ..Default::default()
+        });
+        manager.save_config(&config).unwrap();
+
+        let applied = manager.apply_profile("chat").unwrap();
+        assert_eq!(applied.skipped_keyboards, vec!["shan".to_string()]);
+        assert_eq!(applied.active_keyboard.as_deref(), Some("myanmar3"));
+        assert_still_active(&manager, "myanmar3");
+        assert!(manager.get_keyboard("zawgyi").unwrap().enabled);
+        assert!(!manager.get_keyboard("myanmar3").unwrap().enabled);
+        assert_eq!(manager.get_config().direct_mode.enabled_hosts, vec!["ru.keepcoder.Telegram".to_string()]);
+
+        let err = manager.apply_profile("gone").unwrap_err();
+        assert!(err.downcast_ref::<ProfileNotFound>().is_some());
+        manager.delete_profile("chat").unwrap();
+        assert!(manager.list_profiles().unwrap().is_empty());
+        assert_eq!(manager.get_config().active_profile, None);
+    }
+
+    #[test]
+    fn test_shortcut_passthrough_keys() {
+        let manager = manager_with_keyboards("shortcut-keys", &["myanmar3"], &[]);
+        let keys = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+
+        let saved = manager.set_shortcut_passthrough_keys(" chrome.exe ", &keys(&["key_c", "vk_key_j", "VK_KEY_C"])).unwrap();
+        assert_eq!(saved, keys(&["VK_KEY_C", "VK_KEY_J"]));
+        assert_eq!(manager.get_shortcut_passthrough_keys().get("chrome.exe"), Some(&saved));
+
+        // An unknown name keeps the keys set before
+        let err = manager.set_shortcut_passthrough_keys("chrome.exe", &keys(&["VK_NOPE"])).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(keymagic_core::Error::UnknownVirtualKey(_))));
+        assert_eq!(manager.get_shortcut_passthrough_keys()["chrome.exe"], saved);
+        assert!(manager.set_shortcut_passthrough_keys("  ", &keys(&["KEY_C"])).is_err());
+
+        manager.set_shortcut_passthrough_keys("chrome.exe", &[]).unwrap();
+        assert!(manager.get_shortcut_passthrough_keys().is_empty());
+    }
+
+    #[test]
+    fn test_char_suppression_hosts() {
+        let manager = manager_with_keyboards("char-suppression", &["myanmar3"], &[]);
+        let hosts = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+
+        let saved = manager.set_char_suppression_hosts(&hosts(&[" slack.exe ", "", "Slack.exe", "*.electron.exe"])).unwrap();
+        assert_eq!(saved, hosts(&["slack.exe", "*.electron.exe"]));
+        assert_eq!(manager.char_suppression_hosts(), saved);
+
+        manager.set_char_suppression_hosts(&[]).unwrap();
+        assert!(manager.char_suppression_hosts().is_empty());
+        // The mock platform has no input methods to count for
+        assert_eq!(manager.char_suppression_stats().unwrap(), None);
+    }
+
+    #[test]
+    fn test_rewrite_keyboard_metadata() {
+        let manager = manager_with_keyboards("rewrite-metadata", &["myanmar3", "zawgyi"], &[]);
+        let before = manager.get_keyboard("myanmar3").unwrap();
+        let file_hash = manager.calculate_file_hash(&before.path).unwrap();
+
+        let changes = MetadataChanges {
+            name: Some("Myanmar Unicode".into()),
+            description: Some("Types Unicode".into()),
+            hotkey: Some("CTRL+SHIFT+U".into()),
+        };
+        let keyboard = manager.rewrite_keyboard_metadata("myanmar3", &changes).unwrap();
+        assert_eq!(keyboard.name, "Myanmar Unicode");
+        assert_eq!(keyboard.description.as_deref(), Some("Types Unicode"));
+        assert_eq!(keyboard.default_hotkey.as_deref(), Some("CTRL+SHIFT+U"));
+        assert_eq!(keyboard.hash, manager.calculate_file_hash(&keyboard.path).unwrap());
+        assert_eq!(keyboard.original_hash.as_deref(), Some(file_hash.as_str()));
+        assert_eq!(manager.get_keyboard_by_name("Myanmar Unicode").unwrap().id, "myanmar3");
+
+        // The active keyboard's engine and the saved config follow the file
+        assert_eq!(engine_name(&manager).as_deref(), Some("Myanmar Unicode"));
+        let saved = manager.get_config().keyboards.installed.into_iter().find(|kb| kb.id == "myanmar3").unwrap();
+        assert_eq!((saved.name.as_str(), &saved.hash), ("Myanmar Unicode", &keyboard.hash));
+        assert_eq!(saved.original_hash, keyboard.original_hash);
+
+        // Later edits keep the hash from before the first one
+        let changes = MetadataChanges { description: Some(String::new()), ..Default::default() };
+        let keyboard = manager.rewrite_keyboard_metadata("myanmar3", &changes).unwrap();
+        assert_eq!(keyboard.description, None);
+        assert_eq!(keyboard.original_hash.as_deref(), Some(file_hash.as_str()));
+
+        // Invalid edits leave the file alone
+        let hash = keyboard.hash.clone();
+        let changes = MetadataChanges { name: Some(String::new()), ..Default::default() };
+        assert!(manager.rewrite_keyboard_metadata("myanmar3", &changes).is_err());
+        assert_eq!(manager.calculate_file_hash(&keyboard.path).unwrap(), hash);
+        let err = manager.rewrite_keyboard_metadata("missing", &MetadataChanges::default()).unwrap_err();
+        assert!(err.downcast_ref::<KeyboardNotFound>().is_some());
+    }
+
+    #[test]
+    fn test_read_only_keyboard_is_not_rewritten() {
+        let manager = manager_with_keyboards("rewrite-read-only", &["myanmar3"], &[]);
+        let path = manager.get_keyboard("myanmar3").unwrap().path;
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+
+        let changes = MetadataChanges { name: Some("Renamed".into()), ..Default::default() };
+        let err = manager.rewrite_keyboard_metadata("myanmar3", &changes).unwrap_err();
+        let err = err.downcast_ref::<super::super::KeyboardFileReadOnly>().unwrap();
+        assert_eq!((err.keyboard_id.as_str(), &err.path), ("myanmar3", &path));
+        assert_eq!(manager.get_keyboard("myanmar3").unwrap().name, "myanmar3");
+    }
+
+    #[test]
+    fn test_file_hashes_are_cached_until_forced() {
+        let manager = manager_with_keyboards("file-hashes", &["myanmar3"], &[(file_hashes::SETTING, "{corrupt")]);
+        let path = manager.get_keyboard("myanmar3").unwrap().path;
+        let set_mtime = |secs: u64| {
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(std::time::UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+        };
+        set_mtime(1_000);
+
+        // The mock registers keyboards without a hash
+        let report = manager.repair_keyboard_store(RepairPolicy::default()).unwrap();
+        let hash = manager.get_keyboard("myanmar3").unwrap().hash;
+        assert!(report.fixed.contains(&RepairAction::UpdateHash { id: "myanmar3".into(), hash: hash.clone() }));
+        let setting = manager.get_platform().get_setting(file_hashes::SETTING).unwrap().unwrap();
+        assert!(setting.contains(&hash), "{}", setting);
+
+        // A copy that kept the old size and date goes unnoticed...
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(&path, &data).unwrap();
+        set_mtime(1_000);
+        assert!(manager.repair_keyboard_store(RepairPolicy::default()).unwrap().is_clean());
+
+        // ...until the hashes are taken again
+        let report = manager.force_rehash_keyboards().unwrap();
+        let rehashed = keymagic_core::km2::content_hash(&data);
+        assert!(report.fixed.contains(&RepairAction::UpdateHash { id: "myanmar3".into(), hash: rehashed.clone() }));
+        assert_eq!(manager.get_keyboard("myanmar3").unwrap().hash, rehashed);
+        assert_eq!(manager.calculate_file_hash(&path).unwrap(), rehashed);
+    }
+
+    fn status(manager: &KeyboardManager, keyboard_id: &str) -> KeyboardStatus {
+        manager.refresh_keyboard_statuses();
+        manager.get_keyboard(keyboard_id).unwrap().status
+    }
+
+    #[test]
+    fn test_keyboard_status_follows_flags() {
+        let manager = manager_with_keyboards("status-flags", &["myanmar3", "zawgyi"], &[]);
+        assert_eq!(status(&manager, "myanmar3"), KeyboardStatus::Active);
+        assert_eq!(status(&manager, "zawgyi"), KeyboardStatus::Enabled);
+
+        manager.set_active_keyboard("zawgyi").unwrap();
+        assert_eq!(manager.get_keyboard("myanmar3").unwrap().status, KeyboardStatus::Enabled);
+        assert_eq!(manager.get_keyboard("zawgyi").unwrap().status, KeyboardStatus::Active);
+
+        set_enabled(&manager, "myanmar3", false);
+        assert_eq!(status(&manager, "myanmar3"), KeyboardStatus::DisabledByUser);
+    }
+
+    #[test]
+    fn test_keyboard_status_follows_its_file() {
+        let manager = manager_with_keyboards("status-file", &["myanmar3", "zawgyi"], &[]);
+        let path = manager.get_keyboard("zawgyi").unwrap().path;
+        let data = fs::read(&path).unwrap();
+
+        // A missing file makes the keyboard unavailable, and restoring it clears that
+        fs::remove_file(&path).unwrap();
+        assert_eq!(status(&manager, "zawgyi"), KeyboardStatus::Unavailable { path: path.clone() });
+        let detail = manager.keyboard_status_detail("zawgyi").unwrap();
+        assert_eq!(detail.file_hash, None);
+        assert!(detail.message.contains("missing"), "{}", detail.message);
+        fs::write(&path, &data).unwrap();
+        assert_eq!(status(&manager, "zawgyi"), KeyboardStatus::Enabled);
+
+        // A changed file is checked again
+        fs::write(&path, b"KMKL not really a keyboard").unwrap();
+        let KeyboardStatus::BlockedValidation { issues } = status(&manager, "zawgyi") else {
+            panic!("not blocked");
+        };
+        assert!(!issues.is_empty());
+        let detail = manager.keyboard_status_detail("zawgyi").unwrap();
+        assert_eq!(detail.status, KeyboardStatus::BlockedValidation { issues });
+        assert_eq!(detail.file_hash.as_deref(), Some(keymagic_core::km2::content_hash(b"KMKL not really a keyboard").as_str()));
+
+        // The result is kept with the hash for the next start
+        let setting = manager.get_platform().get_setting(keyboard_status::SETTING).unwrap().unwrap();
+        assert!(setting.contains(detail.file_hash.as_deref().unwrap()), "{}", setting);
+
+        fs::write(&path, &data).unwrap();
+        assert_eq!(status(&manager, "zawgyi"), KeyboardStatus::Enabled);
+        assert!(manager.keyboard_status_detail("missing").is_err());
+    }
+
+    #[test]
+    fn test_keyboard_with_missing_file_is_kept() {
+        let platform = MockPlatform::builder("manager-status-start").active("myanmar3").keyboard("myanmar3").keyboard("zawgyi").build();
+        let path = platform.get_keyboards_dir().join("zawgyi.km2");
+        fs::remove_file(&path).unwrap();
+        let manager = KeyboardManager::new(Box::new(platform));
+        manager.initialize().unwrap();
+
+        assert_eq!(manager.get_keyboard("zawgyi").unwrap().status, KeyboardStatus::Unavailable { path });
+        // Saving the keyboards does not drop it
+        manager.set_active_keyboard("myanmar3").unwrap();
+        let config = manager.get_platform().load_config().unwrap();
+        assert!(config.keyboards.installed.iter().any(|kb| kb.id == "zawgyi"));
+    }
+
+    #[test]
+    fn test_embedded_copy_stands_in_for_missing_file() {
+        let manager = manager_with_keyboards("embedded", &["myanmar3", "zawgyi"], &[]);
+        let path = manager.get_keyboard("zawgyi").unwrap().path;
+        let data = fs::read(&path).unwrap();
+
+        let keyboard = manager.set_keyboard_storage("zawgyi", KeyboardStorage::Embedded).unwrap();
+        assert_eq!(keyboard.storage, KeyboardStorage::Embedded);
+        assert_eq!(manager.get_platform().embedded_keyboard("zawgyi").unwrap(), Some(data.clone()));
+
+        // The file is gone, as after a roaming profile sync; the copy is used
+        fs::remove_file(&path).unwrap();
+        assert_eq!(status(&manager, "zawgyi"), KeyboardStatus::Enabled);
+        let detail = manager.keyboard_status_detail("zawgyi").unwrap();
+        assert!(detail.from_embedded);
+        assert_eq!(detail.storage, KeyboardStorage::Embedded);
+        assert_eq!(detail.file_hash, Some(keymagic_core::km2::content_hash(&data)));
+        manager.set_active_keyboard("zawgyi").unwrap();
+        assert!(manager.get_engine().is_some());
+        assert_eq!(manager.keyboard_data(&keyboard).unwrap(), data);
+        // Not an orphan for the integrity check either
+        let discrepancies = manager.store_discrepancies().unwrap();
+        assert!(
+            !discrepancies.iter().any(|d| matches!(d, Discrepancy::OrphanEntry { .. } | Discrepancy::DanglingActive { .. })),
+            "{:?}",
+            discrepancies
+        );
+
+        // Back to the file alone writes the file from the copy
+        let keyboard = manager.set_keyboard_storage("zawgyi", KeyboardStorage::File).unwrap();
+        assert_eq!(keyboard.storage, KeyboardStorage::File);
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert_eq!(manager.get_platform().embedded_keyboard("zawgyi").unwrap(), None);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(status(&manager, "zawgyi"), KeyboardStatus::Unavailable { path: path.clone() });
+        assert!(manager.set_keyboard_storage("zawgyi", KeyboardStorage::Embedded).is_err());
+    }
+
+    #[test]
+    fn test_embedding_checks_the_keyboard() {
+        let manager = manager_with_keyboards("embedded-cap", &["myanmar3", "zawgyi"], &[]);
+        let path = manager.get_keyboard("zawgyi").unwrap().path;
+
+        fs::write(&path, vec![0u8; embedded_keyboards::MAX_EMBEDDED_SIZE]).unwrap();
+        let err = manager.set_keyboard_storage("zawgyi", KeyboardStorage::Embedded).unwrap_err();
+        let too_large = err.downcast_ref::<embedded_keyboards::KeyboardTooLarge>().unwrap();
+        assert_eq!(too_large.size, embedded_keyboards::MAX_EMBEDDED_SIZE);
+
+        fs::write(&path, b"KMKL not really a keyboard").unwrap();
+        assert!(manager.set_keyboard_storage("zawgyi", KeyboardStorage::Embedded).is_err());
+        assert_eq!(manager.get_platform().embedded_keyboard("zawgyi").unwrap(), None);
+        assert_eq!(manager.get_keyboard("zawgyi").unwrap().storage, KeyboardStorage::File);
+        assert!(manager.set_keyboard_storage("missing", KeyboardStorage::Embedded).is_err());
+    }
+
+    #[test]
+    fn test_repair_refreshes_stale_copy_and_removal_drops_it() {
+        let manager = manager_with_keyboards("embedded-repair", &["myanmar3", "zawgyi"], &[]);
+        manager.set_keyboard_storage("zawgyi", KeyboardStorage::Embedded).unwrap();
+        let path = manager.get_keyboard("zawgyi").unwrap().path;
+
+        // The file was updated behind the copy's back
+        let updated = crate::platform::compile_keyboard(r#""z" => "ဇ""#).unwrap();
+        fs::write(&path, &updated).unwrap();
+        let discrepancies = manager.store_discrepancies().unwrap();
+        assert!(discrepancies.contains(&Discrepancy::StaleEmbedded { id: "zawgyi".to_string() }), "{:?}", discrepancies);
+
+        let report = manager.repair_keyboard_store(RepairPolicy::default()).unwrap();
+        assert!(report.fixed.contains(&RepairAction::RefreshEmbedded { id: "zawgyi".to_string() }), "{:?}", report);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(manager.get_platform().embedded_keyboard("zawgyi").unwrap(), Some(updated));
+        assert!(manager.store_discrepancies().unwrap().is_empty());
+
+        manager.remove_keyboard("zawgyi").unwrap();
+        assert_eq!(manager.get_platform().embedded_keyboard("zawgyi").unwrap(), None);
+    }
+}
@@ -0,0 +1,203 @@
+//! Editing a keyboard's name, description and hotkey in its km2 file
+//!
+//! Only the info entries change; strings and rules are written back as they
+//! were loaded. The file is replaced atomically so the input method never
+//! reads half of it. Keyboards outside the user's keyboards folder (the
+//! system-wide ones) are never rewritten; the user is asked to duplicate
+//! them first.
+
+use anyhow::{anyhow, Result};
+use keymagic_core::hotkey::HotkeyBinding;
+use keymagic_core::types::km2::{INFO_DESC, INFO_HTKY, INFO_NAME};
+use keymagic_core::Km2File;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::temp_file;
+
+/// Metadata edits; `None` leaves a field alone and an empty description or
+/// hotkey removes it from the file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataChanges {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub hotkey: Option<String>,
+}
+
+/// Error for a keyboard file that cannot be rewritten in place
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboardFileReadOnly {
+    pub keyboard_id: String,
+    pub path: PathBuf,
+}
+
+impl std::fmt::Display for KeyboardFileReadOnly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} cannot be changed; duplicate keyboard {} to your keyboards folder and edit the copy",
+            self.path.display(),
+            self.keyboard_id
+        )
+    }
+}
+
+impl std::error::Error for KeyboardFileReadOnly {}
+
+/// Applies `changes` to the info entries of `layout`
+pub fn apply_changes(layout: &mut Km2File, changes: &MetadataChanges) -> Result<()> {
+    if let Some(name) = &changes.name {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow!("Keyboard name cannot be empty"));
+        }
+        layout.set_info(INFO_NAME, name.as_bytes().to_vec());
+    }
+    if let Some(description) = &changes.description {
+        set_or_remove(layout, INFO_DESC, description.trim());
+    }
+    if let Some(hotkey) = &changes.hotkey {
+        let hotkey = hotkey.trim();
+        if !hotkey.is_empty() {
+            HotkeyBinding::parse(hotkey).map_err(|e| anyhow!("Invalid hotkey {}: {}", hotkey, e))?;
+        }
+        set_or_remove(layout, INFO_HTKY, hotkey);
+    }
+    Ok(())
+}
+
+fn set_or_remove(layout: &mut Km2File, id: &[u8; 4], value: &str) {
+    if value.is_empty() {
+        layout.remove_info(id);
+    } else {
+        layout.set_info(id, value.as_bytes().to_vec());
+    }
+}
+
+/// Fails with `KeyboardFileReadOnly` unless `path` is a writable file in
+/// `keyboards_dir`
+pub fn check_writable(keyboard_id: &str, path: &Path, keyboards_dir: &Path) -> Result<()> {
+    let read_only = || KeyboardFileReadOnly { keyboard_id: keyboard_id.to_string(), path: path.to_path_buf() };
+    if path.parent() != Some(keyboards_dir) {
+        return Err(read_only().into());
+    }
+    if fs::metadata(path)?.permissions().readonly() {
+        return Err(read_only().into());
+    }
+    Ok(())
+}
+
+/// Writes `layout` to a temporary file next to `path` and renames it over
+/// `path`, on disk before this returns; a denied write is reported as
+/// `KeyboardFileReadOnly`
+pub fn write_atomic(keyboard_id: &str, path: &Path, layout: &Km2File) -> Result<()> {
+    let mut data = Vec::new();
+    kms2km2::binary::Km2Writer::new(&mut data).write_km2_file(layout)?;
+
+    match temp_file::write_durable(path, &data) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            if e.kind() == ErrorKind::PermissionDenied {
+                Err(KeyboardFileReadOnly { keyboard_id: keyboard_id.to_string(), path: path.to_path_buf() }.into())
+            } else {
+                Err(e.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keymagic_core::km2::Km2Loader;
+
+    const KMS: &str = r#"
+        /*
+        @NAME = "Myanmar"
+        @DESCRIPTION = "Unicode keyboard"
+        @HOTKEY = "CTRL+SHIFT+M"
+        */
+        "k" => "က"
+    "#;
+
+    fn layout() -> Km2File {
+        kms2km2::compile_kms(KMS).unwrap()
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let mut layout = layout();
+        let changes = MetadataChanges { name: Some(" Burmese ".into()), description: Some(String::new()), hotkey: None };
+        apply_changes(&mut layout, &changes).unwrap();
+        let metadata = layout.metadata();
+        assert_eq!(metadata.name().as_deref(), Some("Burmese"));
+        assert_eq!(metadata.description(), None);
+        assert_eq!(metadata.hotkey().as_deref(), Some("CTRL+SHIFT+M"));
+    }
+
+    #[test]
+    fn test_invalid_changes_are_rejected() {
+        let mut layout = layout();
+        let empty_name = MetadataChanges { name: Some("  ".into()), ..Default::default() };
+        assert!(apply_changes(&mut layout, &empty_name).is_err());
+        let bad_hotkey = MetadataChanges { hotkey: Some("CTRL+NOPE".into()), ..Default::default() };
+        assert!(apply_changes(&mut layout, &bad_hotkey).is_err());
+        assert_eq!(layout.metadata().hotkey().as_deref(), Some("CTRL+SHIFT+M"));
+    }
+
+    #[test]
+    fn test_write_atomic_round_trips() {
+        let dir = std::env::temp_dir().join(format!("keymagic-metadata-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("myanmar.km2");
+
+        let mut layout = layout();
+        write_atomic("myanmar", &path, &layout).unwrap();
+        check_writable("myanmar", &path, &dir).unwrap();
+        layout.set_info(INFO_NAME, b"Burmese".to_vec());
+        write_atomic("myanmar", &path, &layout).unwrap();
+
+        let loaded = Km2Loader::load(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(loaded.metadata().name().as_deref(), Some("Burmese"));
+        assert!(!path.with_extension("km2.tmp").exists());
+        assert!(crate::temp_file::leftovers(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_leaves_no_temporary_file() {
+        let dir = std::env::temp_dir().join(format!("keymagic-metadata-blocked-{}", std::process::id()));
+        // A folder where the keyboard should be fails the rename
+        let path = dir.join("myanmar.km2");
+        fs::create_dir_all(path.join("inside")).unwrap();
+
+        assert!(write_atomic("myanmar", &path, &layout()).is_err());
+        assert!(crate::temp_file::leftovers(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_outside_the_keyboards_folder_are_read_only() {
+        let dir = std::env::temp_dir().join(format!("keymagic-metadata-ro-{}", std::process::id()));
+        fs::create_dir_all(dir.join("system")).unwrap();
+        let path = dir.join("system").join("myanmar.km2");
+        fs::write(&path, b"").unwrap();
+
+        let err = check_writable("myanmar", &path, &dir).unwrap_err();
+        let err = err.downcast_ref::<KeyboardFileReadOnly>().unwrap();
+        assert_eq!(err.path, path);
+        assert!(err.to_string().contains("duplicate keyboard myanmar"));
+
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+        let inside = dir.join("system");
+        assert!(check_writable("myanmar", &path, &inside).unwrap_err().is::<KeyboardFileReadOnly>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
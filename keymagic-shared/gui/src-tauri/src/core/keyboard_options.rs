@@ -0,0 +1,141 @@
+//! Engine options of a keyboard: the km2 defaults with the user's overrides
+//!
+//! Overrides are stored by option name as JSON values so that options of
+//! other types can be added later; all current ones are booleans.
+
+use anyhow::Result;
+use keymagic_core::Error as EngineError;
+use keymagic_core::types::km2::{LayoutOptions, LayoutOverrides};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// Error for an override value of the wrong type
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidOptionValue {
+    pub name: String,
+    pub value: Value,
+}
+
+impl std::fmt::Display for InvalidOptionValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Option {} must be true or false, not {}", self.name, self.value)
+    }
+}
+
+impl std::error::Error for InvalidOptionValue {}
+
+/// Options of a keyboard as shown in the settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyboardOptions {
+    /// Values from the km2 file
+    pub defaults: BTreeMap<String, bool>,
+    /// The user's choices
+    pub overrides: HashMap<String, Value>,
+    /// Values the engine uses
+    pub effective: BTreeMap<String, bool>,
+}
+
+/// Checks an override before it is saved and returns its value
+pub fn validate_override(name: &str, value: &Value) -> Result<bool> {
+    if !LayoutOptions::NAMES.contains(&name) {
+        return Err(EngineError::UnknownLayoutOption(name.to_string()).into());
+    }
+    value.as_bool().ok_or_else(|| {
+        InvalidOptionValue { name: name.to_string(), value: value.clone() }.into()
+    })
+}
+
+/// Overrides the engine understands; entries edited into the config by hand
+/// with unknown names or wrong types are ignored
+pub fn layout_overrides(overrides: &HashMap<String, Value>) -> LayoutOverrides {
+    overrides
+        .iter()
+        .filter_map(|(name, value)| validate_override(name, value).ok().map(|value| (name.clone(), value)))
+        .collect()
+}
+
+/// Layout options the engine uses for a keyboard
+pub fn effective_options(defaults: LayoutOptions, overrides: &HashMap<String, Value>) -> LayoutOptions {
+    defaults
+        .with_overrides(&layout_overrides(overrides))
+        .expect("overrides are validated")
+}
+
+pub fn keyboard_options(defaults: LayoutOptions, overrides: &HashMap<String, Value>) -> KeyboardOptions {
+    let effective = effective_options(defaults, overrides);
+    let values = |options: LayoutOptions| -> BTreeMap<String, bool> {
+        LayoutOptions::NAMES
+            .iter()
+            .filter_map(|&name| options.get(name).map(|value| (name.to_string(), value)))
+            .collect()
+    };
+    KeyboardOptions {
+        defaults: values(defaults),
+        overrides: overrides.clone(),
+        effective: values(effective),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn defaults() -> LayoutOptions {
+        LayoutOptions { track_caps: 1, auto_bksp: 0, eat: 0, pos_based: 0, right_alt: 1, case_insensitive_ascii: 0 }
+    }
+
+    #[test]
+    fn test_overrides_win_over_defaults() {
+        let overrides = HashMap::from([
+            ("auto_bksp".to_string(), json!(true)),
+            ("track_caps".to_string(), json!(false)),
+        ]);
+        let options = keyboard_options(defaults(), &overrides);
+        assert!(!options.defaults["auto_bksp"]);
+        assert!(options.defaults["track_caps"]);
+        assert!(options.effective["auto_bksp"]);
+        assert!(!options.effective["track_caps"]);
+        // Options without an override keep the keyboard's value
+        assert!(options.effective["right_alt"]);
+        assert!(!options.effective["eat"]);
+        assert_eq!(options.effective.len(), LayoutOptions::NAMES.len());
+    }
+
+    #[test]
+    fn test_override_equal_to_default_is_kept() {
+        let overrides = HashMap::from([("right_alt".to_string(), json!(true))]);
+        let options = keyboard_options(defaults(), &overrides);
+        assert_eq!(options.overrides.len(), 1);
+        assert!(options.effective["right_alt"]);
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(validate_override("eat", &json!(true)).unwrap());
+        assert!(!validate_override("eat", &json!(false)).unwrap());
+
+        let err = validate_override("smart_quotes", &json!(true)).unwrap_err();
+        assert!(matches!(err.downcast_ref::<EngineError>(), Some(EngineError::UnknownLayoutOption(name)) if name == "smart_quotes"));
+
+        let err = validate_override("eat", &json!(1)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<InvalidOptionValue>(),
+            Some(&InvalidOptionValue { name: "eat".to_string(), value: json!(1) })
+        );
+    }
+
+    #[test]
+    fn test_invalid_stored_overrides_are_ignored() {
+        let overrides = HashMap::from([
+            ("eat".to_string(), json!("yes")),
+            ("smart_quotes".to_string(), json!(true)),
+            ("pos_based".to_string(), json!(true)),
+        ]);
+        assert_eq!(layout_overrides(&overrides), LayoutOverrides::from([("pos_based".to_string(), true)]));
+        let effective = effective_options(defaults(), &overrides);
+        assert_eq!(effective.get("eat"), Some(false));
+        assert_eq!(effective.get("pos_based"), Some(true));
+    }
+}
@@ -0,0 +1,490 @@
+use anyhow::{Context, Result};
+use keymagic_core::km2::Km2Loader;
+use keymagic_core::types::km2::BinaryFormatElement;
+use keymagic_core::Km2File;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::keyboard_manager::KeyboardInfo;
+use super::keyboard_status::KeyboardStatus;
+
+/// Filter applied by `query_keyboards`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyboardFilter {
+    /// Case-insensitive substring of the keyboard name
+    pub text: Option<String>,
+    pub enabled_only: bool,
+    /// Language code as reported in `KeyboardInfo::languages` (e.g. "my")
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyboardSortKey {
+    #[default]
+    Name,
+    Id,
+    Hotkey,
+    Active,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyboardSort {
+    pub key: KeyboardSortKey,
+    pub descending: bool,
+}
+
+/// Lightweight keyboard row for list rendering (no icon or description payload)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyboardRow {
+    pub id: String,
+    pub name: String,
+    pub hotkey: Option<String>,
+    pub enabled: bool,
+    pub is_active: bool,
+    pub status: KeyboardStatus,
+    pub has_icon: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyboardPage {
+    pub rows: Vec<KeyboardRow>,
+    /// Number of keyboards matching the filter across all pages
+    pub total: usize,
+    pub page: usize,
+    pub page_size: usize,
+}
+
+/// Keyboard names lowercased once, so text filtering does not re-fold every query
+#[derive(Debug, Default)]
+pub struct NameIndex {
+    entries: HashMap<String, String>,
+}
+
+impl NameIndex {
+    pub fn build<'a>(keyboards: impl IntoIterator<Item = &'a KeyboardInfo>) -> Self {
+        Self {
+            entries: keyboards
+                .into_iter()
+                .map(|kb| (kb.id.clone(), kb.name.to_lowercase()))
+                .collect(),
+        }
+    }
+
+    /// Returns true if the keyboard's name contains `needle` (already lowercased)
+    fn matches(&self, keyboard: &KeyboardInfo, needle: &str) -> bool {
+        match self.entries.get(&keyboard.id) {
+            Some(name) => name.contains(needle),
+            None => keyboard.name.to_lowercase().contains(needle),
+        }
+    }
+}
+
+/// Filters, sorts and pages keyboards. `page` is zero-based.
+///
+/// Ties on the sort key are broken by id so that paging is stable between calls.
+pub fn query_keyboards<'a>(
+    keyboards: impl IntoIterator<Item = &'a KeyboardInfo>,
+    index: &NameIndex,
+    filter: &KeyboardFilter,
+    page: usize,
+    page_size: usize,
+    sort: KeyboardSort,
+) -> KeyboardPage {
+    let needle = filter
+        .text
+        .as_deref()
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty());
+    let language = filter.language.as_deref().map(|l| l.to_lowercase());
+
+    let mut matched: Vec<&KeyboardInfo> = keyboards
+        .into_iter()
+        .filter(|kb| !filter.enabled_only || kb.enabled)
+        .filter(|kb| needle.as_deref().is_none_or(|n| index.matches(kb, n)))
+        .filter(|kb| {
+            language
+                .as_deref()
+                .is_none_or(|lang| kb.languages.iter().any(|l| l.eq_ignore_ascii_case(lang)))
+        })
+        .collect();
+
+    matched.sort_by(|a, b| {
+        let ordering = match sort.key {
+            KeyboardSortKey::Name => index_name(index, a).cmp(index_name(index, b)),
+            KeyboardSortKey::Id => std::cmp::Ordering::Equal,
+            KeyboardSortKey::Hotkey => effective_hotkey(a).cmp(&effective_hotkey(b)),
+            KeyboardSortKey::Active => b.is_active.cmp(&a.is_active),
+        };
+        let ordering = ordering.then_with(|| a.id.cmp(&b.id));
+        if sort.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+
+    let page_size = page_size.max(1);
+    let total = matched.len();
+    let rows = matched
+        .into_iter()
+        .skip(page.saturating_mul(page_size))
+        .take(page_size)
+        .map(|kb| KeyboardRow {
+            id: kb.id.clone(),
+            name: kb.name.clone(),
+            hotkey: effective_hotkey(kb).map(|h| h.to_string()),
+            enabled: kb.enabled,
+            is_active: kb.is_active,
+            status: kb.status.clone(),
+            has_icon: kb.icon_data.is_some(),
+        })
+        .collect();
+
+    KeyboardPage {
+        rows,
+        total,
+        page,
+        page_size,
+    }
+}
+
+fn index_name<'a>(index: &'a NameIndex, keyboard: &'a KeyboardInfo) -> &'a str {
+    index.entries.get(&keyboard.id).map(String::as_str).unwrap_or(&keyboard.name)
+}
+
+/// The hotkey shown to the user: the custom one if set, otherwise the keyboard default
+fn effective_hotkey(keyboard: &KeyboardInfo) -> Option<&str> {
+    match &keyboard.hotkey {
+        Some(hotkey) if hotkey.is_empty() => None,
+        Some(_) => keyboard.display_hotkey.as_deref().or(keyboard.hotkey.as_deref()),
+        None => keyboard
+            .default_display_hotkey
+            .as_deref()
+            .or(keyboard.default_hotkey.as_deref()),
+    }
+}
+
+/// Unicode blocks used to guess which languages a keyboard produces
+const SCRIPT_LANGUAGES: &[(u32, u32, &str)] = &[
+    (0x0900, 0x097F, "hi"),
+    (0x0980, 0x09FF, "bn"),
+    (0x0B80, 0x0BFF, "ta"),
+    (0x0D80, 0x0DFF, "si"),
+    (0x0E00, 0x0E7F, "th"),
+    (0x0E80, 0x0EFF, "lo"),
+    (0x0F00, 0x0FFF, "bo"),
+    (0x1000, 0x109F, "my"),
+    (0x10A0, 0x10FF, "ka"),
+    (0x1200, 0x137F, "am"),
+    (0x1780, 0x17FF, "km"),
+    (0xA9E0, 0xA9FF, "my"),
+    (0xAA60, 0xAA7F, "my"),
+];
+
+/// The languages a keyboard targets: the locales it declares, otherwise a
+/// guess from the scripts of its output strings
+pub fn detect_languages(layout: &Km2File) -> Vec<String> {
+    let declared = layout.metadata().locales();
+    if !declared.is_empty() {
+        return declared;
+    }
+
+    let outputs = layout
+        .rules
+        .iter()
+        .flat_map(|rule| rule.rhs.iter())
+        .filter_map(|element| match element {
+            BinaryFormatElement::String(s) => Some(s.as_str()),
+            _ => None,
+        })
+        .chain(layout.strings.iter().map(|entry| entry.value.as_str()));
+
+    let mut languages: Vec<String> = Vec::new();
+    for ch in outputs.flat_map(str::chars) {
+        let code = ch as u32;
+        if let Some((_, _, lang)) = SCRIPT_LANGUAGES.iter().find(|(lo, hi, _)| (*lo..=*hi).contains(&code)) {
+            if !languages.iter().any(|l| l == lang) {
+                languages.push(lang.to_string());
+            }
+        }
+    }
+    languages
+}
+
+/// Declared locales that are not among the enabled language profiles.
+/// Tags are compared ignoring case, as "shn-mm" and "shn-MM" are the same profile.
+pub fn languages_to_enable(declared: &[String], enabled: &[String]) -> Vec<String> {
+    let mut missing: Vec<String> = Vec::new();
+    for locale in declared {
+        let known = |tag: &String| tag.eq_ignore_ascii_case(locale);
+        if !enabled.iter().any(known) && !missing.iter().any(known) {
+            missing.push(locale.clone());
+        }
+    }
+    missing
+}
+
+/// Icon payload returned by `get_keyboard_icon`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyboardIcon {
+    /// Content hash of the icon; unchanged icons keep the same etag
+    pub etag: String,
+    /// Base64 icon data, omitted when the caller's etag is still current
+    pub data: Option<String>,
+    /// True when the keyboard has no icon at all
+    pub missing: bool,
+}
+
+struct CachedIcon {
+    modified: Option<SystemTime>,
+    len: u64,
+    etag: String,
+    data: Option<Vec<u8>>,
+}
+
+/// Icons loaded on demand, invalidated when the keyboard file changes on disk
+#[derive(Default)]
+pub struct IconCache {
+    entries: HashMap<String, CachedIcon>,
+}
+
+impl IconCache {
+    /// Returns the icon for a keyboard file, reloading it if the file changed.
+    /// When `if_none_match` equals the current etag the data is omitted.
+    pub fn get(&mut self, keyboard_id: &str, path: &Path, if_none_match: Option<&str>) -> Result<KeyboardIcon> {
+        let file_meta = fs::metadata(path).context("Failed to read keyboard file")?;
+        let modified = file_meta.modified().ok();
+        let len = file_meta.len();
+
+        let fresh = self
+            .entries
+            .get(keyboard_id)
+            .is_some_and(|cached| cached.modified == modified && cached.len == len);
+
+        if !fresh {
+            let data = fs::read(path).context("Failed to read keyboard file")?;
+            let layout = Km2Loader::load(&data).context("Failed to parse keyboard file")?;
+            let icon = layout.metadata().icon().map(|icon| icon.to_vec());
+            self.entries.insert(
+                keyboard_id.to_string(),
+                CachedIcon {
+                    modified,
+                    len,
+                    etag: icon_etag(icon.as_deref()),
+                    data: icon,
+                },
+            );
+        }
+
+        let cached = &self.entries[keyboard_id];
+        let unchanged = if_none_match == Some(cached.etag.as_str());
+        Ok(KeyboardIcon {
+            etag: cached.etag.clone(),
+            data: if unchanged {
+                None
+            } else {
+                cached.data.as_ref().map(|bytes| {
+                    use base64::{engine::general_purpose::STANDARD, Engine as _};
+                    STANDARD.encode(bytes)
+                })
+            },
+            missing: cached.data.is_none(),
+        })
+    }
+
+    pub fn invalidate(&mut self, keyboard_id: &str) {
+        self.entries.remove(keyboard_id);
+    }
+}
+
+fn icon_etag(icon: Option<&[u8]>) -> String {
+    use sha2::{Digest, Sha256};
+
+    match icon {
+        Some(bytes) => {
+            let digest = Sha256::digest(bytes);
+            digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+        }
+        None => "none".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn keyboard(id: &str, name: &str) -> KeyboardInfo {
+        KeyboardInfo {
+            id: id.to_string(),
+            name: name.to_string(),
+            filename: format!("{}.km2", id),
+            path: PathBuf::from(format!("{}.km2", id)),
+            hotkey: None,
+            default_hotkey: None,
+            hash: String::new(),
+            original_hash: None,
+            is_active: false,
+            enabled: true,
+            status: Default::default(),
+            storage: Default::default(),
+            languages: vec![],
+            description: None,
+            sample_text: None,
+            font_family: None,
+            icon_data: None,
+            display_hotkey: None,
+            default_display_hotkey: None,
+            output_encoding: Default::default(),
+            disabled_groups: vec![],
+            passthrough_keys: vec![],
+            commit_before_passthrough: false,
+            options_overrides: HashMap::new(),
+        }
+    }
+
+    fn ids(page: &KeyboardPage) -> Vec<&str> {
+        page.rows.iter().map(|r| r.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_paging_edges() {
+        let keyboards: Vec<KeyboardInfo> = (0..5).map(|i| keyboard(&format!("kb{}", i), &format!("Keyboard {}", i))).collect();
+        let index = NameIndex::build(&keyboards);
+        let filter = KeyboardFilter::default();
+        let sort = KeyboardSort::default();
+
+        let first = query_keyboards(&keyboards, &index, &filter, 0, 2, sort);
+        assert_eq!(ids(&first), vec!["kb0", "kb1"]);
+        assert_eq!(first.total, 5);
+
+        // Last page is partial
+        let last = query_keyboards(&keyboards, &index, &filter, 2, 2, sort);
+        assert_eq!(ids(&last), vec!["kb4"]);
+
+        // Past the end is empty but still reports the total
+        let past = query_keyboards(&keyboards, &index, &filter, 3, 2, sort);
+        assert!(past.rows.is_empty());
+        assert_eq!(past.total, 5);
+
+        // Zero page size is treated as one
+        let zero = query_keyboards(&keyboards, &index, &filter, 0, 0, sort);
+        assert_eq!(zero.rows.len(), 1);
+
+        // Huge page numbers must not overflow
+        let huge = query_keyboards(&keyboards, &index, &filter, usize::MAX, 10, sort);
+        assert!(huge.rows.is_empty());
+    }
+
+    #[test]
+    fn test_equal_sort_keys_page_deterministically() {
+        // All names equal: ordering must fall back to id so pages never overlap
+        let keyboards: Vec<KeyboardInfo> = ["c", "a", "d", "b"].iter().map(|id| keyboard(id, "Same")).collect();
+        let index = NameIndex::build(&keyboards);
+        let filter = KeyboardFilter::default();
+        let sort = KeyboardSort { key: KeyboardSortKey::Name, descending: false };
+
+        let mut seen = Vec::new();
+        for page in 0..2 {
+            let result = query_keyboards(keyboards.iter().rev(), &index, &filter, page, 2, sort);
+            seen.extend(result.rows.into_iter().map(|r| r.id));
+        }
+        assert_eq!(seen, vec!["a", "b", "c", "d"]);
+
+        let desc = KeyboardSort { key: KeyboardSortKey::Name, descending: true };
+        let result = query_keyboards(&keyboards, &index, &filter, 0, 4, desc);
+        assert_eq!(ids(&result), vec!["d", "c", "b", "a"]);
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut keyboards = vec![
+            keyboard("zawcode", "ZawCode"),
+            keyboard("pyidaungsu", "Pyidaungsu"),
+            keyboard("thai", "Thai Kedmanee"),
+        ];
+        keyboards[0].languages = vec!["my".to_string()];
+        keyboards[1].languages = vec!["my".to_string()];
+        keyboards[1].enabled = false;
+        keyboards[2].languages = vec!["th".to_string()];
+        let index = NameIndex::build(&keyboards);
+        let sort = KeyboardSort::default();
+
+        let text = KeyboardFilter { text: Some("  ZAW ".to_string()), ..Default::default() };
+        assert_eq!(ids(&query_keyboards(&keyboards, &index, &text, 0, 10, sort)), vec!["zawcode"]);
+
+        let lang = KeyboardFilter { language: Some("MY".to_string()), ..Default::default() };
+        assert_eq!(ids(&query_keyboards(&keyboards, &index, &lang, 0, 10, sort)), vec!["pyidaungsu", "zawcode"]);
+
+        let enabled = KeyboardFilter { enabled_only: true, language: Some("my".to_string()), ..Default::default() };
+        assert_eq!(ids(&query_keyboards(&keyboards, &index, &enabled, 0, 10, sort)), vec!["zawcode"]);
+    }
+
+    fn write_keyboard(path: &Path, icon: Option<&[u8]>) {
+        let mut km2 = kms2km2::compile_kms(r#""k" => "က""#).unwrap();
+        if let Some(icon) = icon {
+            km2.info.push(keymagic_core::types::km2::InfoEntry { id: *keymagic_core::types::km2::INFO_ICON, data: icon.to_vec() });
+            km2.header.info_count = km2.info.len() as u32;
+        }
+        let mut buffer = Vec::new();
+        kms2km2::binary::Km2Writer::new(&mut buffer).write_km2_file(&km2).unwrap();
+        fs::write(path, buffer).unwrap();
+    }
+
+    #[test]
+    fn test_icon_cache_invalidated_when_file_changes() {
+        let dir = std::env::temp_dir().join(format!("keymagic-icon-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("icon.km2");
+        let mut cache = IconCache::default();
+
+        write_keyboard(&path, Some(b"first icon"));
+        let first = cache.get("kb", &path, None).unwrap();
+        assert!(first.data.is_some());
+        assert!(!first.missing);
+
+        // Same etag: data is omitted
+        let again = cache.get("kb", &path, Some(&first.etag)).unwrap();
+        assert_eq!(again.etag, first.etag);
+        assert!(again.data.is_none());
+
+        // Rewriting the file with a different icon yields a new etag and data
+        write_keyboard(&path, Some(b"second, longer icon"));
+        let changed = cache.get("kb", &path, Some(&first.etag)).unwrap();
+        assert_ne!(changed.etag, first.etag);
+        assert!(changed.data.is_some());
+
+        // Removing the icon is reported as missing
+        write_keyboard(&path, None);
+        let missing = cache.get("kb", &path, None).unwrap();
+        assert!(missing.missing);
+        assert!(missing.data.is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_detect_languages() {
+        let km2 = kms2km2::compile_kms("$cons = \"ကခ\"\n\"a\" => \"ก\"").unwrap();
+        assert_eq!(detect_languages(&km2), vec!["th".to_string(), "my".to_string()]);
+
+        // Declared locales win over the guess
+        let km2 = kms2km2::compile_kms("// @LOCALE = \"shn-MM\"\n\"a\" => \"ႁ\"").unwrap();
+        assert_eq!(detect_languages(&km2), vec!["shn-MM".to_string()]);
+    }
+
+    #[test]
+    fn test_languages_to_enable() {
+        let strings = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+        let declared = strings(&["shn-MM", "my-MM", "SHN-mm"]);
+        assert_eq!(languages_to_enable(&declared, &strings(&["en-US", "my-mm"])), strings(&["shn-MM"]));
+        assert!(languages_to_enable(&declared, &strings(&["shn-mm", "my-MM"])).is_empty());
+        assert!(languages_to_enable(&[], &strings(&["en-US"])).is_empty());
+    }
+}
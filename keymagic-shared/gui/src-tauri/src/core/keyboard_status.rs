@@ -0,0 +1,285 @@
+//! Why a keyboard can or cannot be used, in one place
+//!
+//! A keyboard is listed with a [`KeyboardStatus`]: active, enabled, disabled
+//! by the user, or blocked because its file is gone or does not load. File
+//! problems come first, since a keyboard that cannot load is unusable
+//! whatever the user chose for it.
+//!
+//! Checking that a file loads means parsing it and building an engine, which
+//! is too slow to repeat for every keyboard each time the list is shown. The
+//! results are kept in the [`SETTING`] setting against the file hash, so they
+//! survive restarts and a file is checked again only once its hash changes.
+//! Whether the file exists is checked every time; it costs one `stat`.
+//!
+//! A keyboard whose file is missing but that has an embedded copy (see
+//! `embedded_keyboards`) is checked against the copy, which is what the
+//! text service loads then.
+
+use keymagic_core::km2::Km2Loader;
+use keymagic_core::KeyMagicEngine;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::embedded_keyboards::KeyboardStorage;
+use super::keyboard_activation::error_chain;
+
+/// Setting holding the check results as JSON
+pub const SETTING: &str = "keyboard_file_checks";
+
+/// Whether a keyboard can be used, and if not, why
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum KeyboardStatus {
+    /// The keyboard in use
+    Active,
+    /// Ready to be switched to
+    #[default]
+    Enabled,
+    /// Turned off by the user or a profile
+    DisabledByUser,
+    /// The file does not load; `issues` are the problems found, most
+    /// specific last
+    BlockedValidation { issues: Vec<String> },
+    /// The file is missing, e.g. on a drive that is not mounted
+    Unavailable { path: PathBuf },
+}
+
+/// What checking a keyboard file found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileCheck {
+    Missing,
+    /// The file has content `hash` (`None` if it could not be read) and
+    /// `issues`, empty when it loads
+    Found { hash: Option<String>, issues: Vec<String> },
+    /// The file is missing; its embedded copy has content `hash` and
+    /// `issues`, empty when it loads
+    Embedded { hash: String, issues: Vec<String> },
+}
+
+impl FileCheck {
+    pub fn hash(&self) -> Option<&str> {
+        match self {
+            FileCheck::Found { hash, .. } => hash.as_deref(),
+            FileCheck::Embedded { hash, .. } => Some(hash),
+            FileCheck::Missing => None,
+        }
+    }
+}
+
+impl KeyboardStatus {
+    /// Status of a keyboard at `path` from the user's choices and what
+    /// checking its file found
+    pub fn of(enabled: bool, is_active: bool, path: &Path, check: &FileCheck) -> Self {
+        match check {
+            FileCheck::Missing => KeyboardStatus::Unavailable { path: path.to_path_buf() },
+            FileCheck::Found { issues, .. } | FileCheck::Embedded { issues, .. } if !issues.is_empty() => {
+                KeyboardStatus::BlockedValidation { issues: issues.clone() }
+            }
+            FileCheck::Found { .. } | FileCheck::Embedded { .. } => Self::from_flags(enabled, is_active),
+        }
+    }
+
+    fn from_flags(enabled: bool, is_active: bool) -> Self {
+        if is_active {
+            KeyboardStatus::Active
+        } else if enabled {
+            KeyboardStatus::Enabled
+        } else {
+            KeyboardStatus::DisabledByUser
+        }
+    }
+
+    /// The status after the user's choices changed; a blocked keyboard
+    /// stays blocked until its file is checked again
+    pub fn with_flags(&self, enabled: bool, is_active: bool) -> Self {
+        if self.is_blocked() {
+            return self.clone();
+        }
+        Self::from_flags(enabled, is_active)
+    }
+
+    /// Whether the keyboard cannot be used because of its file
+    pub fn is_blocked(&self) -> bool {
+        matches!(self, KeyboardStatus::BlockedValidation { .. } | KeyboardStatus::Unavailable { .. })
+    }
+
+    /// A sentence explaining the status to the user
+    pub fn message(&self) -> String {
+        match self {
+            KeyboardStatus::Active => "In use".to_string(),
+            KeyboardStatus::Enabled => "Ready to use".to_string(),
+            KeyboardStatus::DisabledByUser => "Turned off".to_string(),
+            KeyboardStatus::BlockedValidation { issues } => match issues.last() {
+                Some(issue) => format!("The keyboard file does not load: {}", issue),
+                None => "The keyboard file does not load".to_string(),
+            },
+            KeyboardStatus::Unavailable { path } => format!("The keyboard file is missing: {}", path.display()),
+        }
+    }
+}
+
+/// Everything known about a keyboard's status, for the details UI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyboardStatusDetail {
+    pub keyboard_id: String,
+    pub status: KeyboardStatus,
+    pub message: String,
+    /// Hash of the file the status was checked against; `None` when the
+    /// file is missing
+    pub file_hash: Option<String>,
+    pub storage: KeyboardStorage,
+    /// The file is missing and the status is that of the embedded copy
+    pub from_embedded: bool,
+}
+
+/// Problems that keep the keyboard file at `path` from running, most
+/// specific last; empty when it loads
+pub fn validate_file(path: &Path) -> Vec<String> {
+    match fs::read(path) {
+        Ok(data) => validate_data(&data),
+        Err(e) => error_chain(&e),
+    }
+}
+
+/// Problems that keep keyboard `data` from running, like `validate_file`
+pub fn validate_data(data: &[u8]) -> Vec<String> {
+    let layout = match Km2Loader::load(data) {
+        Ok(layout) => layout,
+        Err(e) => return error_chain(&e),
+    };
+    match KeyMagicEngine::new(layout) {
+        Ok(_) => Vec::new(),
+        Err(e) => error_chain(&e),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CheckedFile {
+    hash: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    issues: Vec<String>,
+}
+
+/// File check results by keyboard id
+#[derive(Debug, Default)]
+pub struct FileCheckCache {
+    entries: BTreeMap<String, CheckedFile>,
+    /// Changed since loaded or saved
+    dirty: bool,
+}
+
+impl FileCheckCache {
+    /// Parses the setting; a missing or corrupt one gives an empty cache
+    pub fn from_setting(json: Option<&str>) -> Self {
+        let entries = match json.map(serde_json::from_str) {
+            Some(Ok(entries)) => entries,
+            Some(Err(e)) => {
+                log::warn!("Keyboard file check cache is corrupt, checking every file again: {}", e);
+                return Self { entries: BTreeMap::new(), dirty: true };
+            }
+            None => BTreeMap::new(),
+        };
+        Self { entries, dirty: false }
+    }
+
+    pub fn to_setting(&self) -> String {
+        serde_json::to_string(&self.entries).unwrap_or_default()
+    }
+
+    /// Problems of the keyboard file with content `hash`, checked with
+    /// `validate` unless it was checked at that hash before
+    pub fn issues(&mut self, keyboard_id: &str, hash: &str, validate: impl FnOnce() -> Vec<String>) -> Vec<String> {
+        if let Some(checked) = self.entries.get(keyboard_id).filter(|checked| checked.hash == hash) {
+            return checked.issues.clone();
+        }
+        let issues = validate();
+        self.entries.insert(keyboard_id.to_string(), CheckedFile { hash: hash.to_string(), issues: issues.clone() });
+        self.dirty = true;
+        issues
+    }
+
+    /// Drops the results of keyboards not in `keyboard_ids`
+    pub fn retain(&mut self, keyboard_ids: &[&str]) {
+        let before = self.entries.len();
+        self.entries.retain(|id, _| keyboard_ids.contains(&id.as_str()));
+        self.dirty |= self.entries.len() != before;
+    }
+
+    /// Whether the cache changed since the last call
+    pub fn take_changes(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_check() -> Vec<String> {
+        panic!("checked again")
+    }
+
+    #[test]
+    fn test_file_problems_come_first() {
+        let path = Path::new("/keyboards/a.km2");
+        let broken = vec!["bad header".to_string()];
+        let fine = FileCheck::Found { hash: Some("aa".to_string()), issues: Vec::new() };
+        let invalid = FileCheck::Found { hash: Some("bb".to_string()), issues: broken.clone() };
+        assert_eq!(KeyboardStatus::of(true, true, path, &fine), KeyboardStatus::Active);
+        assert_eq!(KeyboardStatus::of(true, false, path, &fine), KeyboardStatus::Enabled);
+        assert_eq!(KeyboardStatus::of(false, false, path, &fine), KeyboardStatus::DisabledByUser);
+        assert_eq!(KeyboardStatus::of(true, true, path, &invalid), KeyboardStatus::BlockedValidation { issues: broken.clone() });
+        assert_eq!(
+            KeyboardStatus::of(false, true, path, &FileCheck::Missing),
+            KeyboardStatus::Unavailable { path: path.to_path_buf() }
+        );
+        // A missing file with an embedded copy is as good as the copy
+        let embedded = FileCheck::Embedded { hash: "cc".to_string(), issues: Vec::new() };
+        assert_eq!(KeyboardStatus::of(true, true, path, &embedded), KeyboardStatus::Active);
+        assert_eq!(embedded.hash(), Some("cc"));
+        let invalid_copy = FileCheck::Embedded { hash: "dd".to_string(), issues: broken.clone() };
+        assert_eq!(KeyboardStatus::of(true, false, path, &invalid_copy), KeyboardStatus::BlockedValidation { issues: broken.clone() });
+
+        let blocked = KeyboardStatus::BlockedValidation { issues: broken };
+        assert_eq!(blocked.with_flags(true, true), blocked);
+        assert_eq!(KeyboardStatus::Active.with_flags(false, false), KeyboardStatus::DisabledByUser);
+    }
+
+    #[test]
+    fn test_status_serializes_with_kind() {
+        let status = KeyboardStatus::Unavailable { path: PathBuf::from("a.km2") };
+        assert_eq!(serde_json::to_value(&status).unwrap(), serde_json::json!({"kind": "unavailable", "path": "a.km2"}));
+        assert_eq!(serde_json::to_value(KeyboardStatus::DisabledByUser).unwrap(), serde_json::json!({"kind": "disabled_by_user"}));
+    }
+
+    #[test]
+    fn test_checks_are_kept_per_hash() {
+        let mut cache = FileCheckCache::from_setting(None);
+        assert_eq!(cache.issues("a", "h1", || vec!["bad".to_string()]), vec!["bad".to_string()]);
+        assert_eq!(cache.issues("b", "h2", Vec::new), Vec::<String>::new());
+        assert!(cache.take_changes());
+
+        // Survives a restart without checking again
+        let mut cache = FileCheckCache::from_setting(Some(&cache.to_setting()));
+        assert_eq!(cache.issues("a", "h1", no_check), vec!["bad".to_string()]);
+        assert!(cache.issues("b", "h2", no_check).is_empty());
+        assert!(!cache.take_changes());
+
+        // A new hash is checked again
+        assert!(cache.issues("a", "h3", Vec::new).is_empty());
+        assert!(cache.take_changes());
+
+        cache.retain(&["a"]);
+        assert!(cache.take_changes());
+        assert_eq!(cache.issues("b", "h2", || vec!["gone".to_string()]), vec!["gone".to_string()]);
+    }
+
+    #[test]
+    fn test_corrupt_setting_is_rebuilt() {
+        let mut cache = FileCheckCache::from_setting(Some("{corrupt"));
+        assert!(cache.take_changes());
+        assert_eq!(cache.to_setting(), "{}");
+    }
+}
@@ -0,0 +1,390 @@
+//! Integrity check for the keyboard store
+//!
+//! Keyboards are known to three places that can drift apart: the platform's
+//! registrations (the registry on Windows), the km2 files in the keyboards
+//! directory, and the GUI's in-memory keyboard list. Classification and repair
+//! planning are pure functions over a snapshot of all three; gathering the
+//! snapshot and applying the plan is done by `KeyboardManager`.
+//!
+//! A keyboard with an embedded copy (see `embedded_keyboards`) is not an
+//! orphan when its file is missing: the copy stands in for the file, and its
+//! hash for the file's. While the file is there the copy has to match it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// A keyboard registration as stored in one of the sources
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoreEntry {
+    pub id: String,
+    /// File name inside the keyboards directory; `None` if the registration has none
+    pub filename: Option<String>,
+    pub hash: String,
+}
+
+/// A km2 file found in the keyboards directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoreFile {
+    pub filename: String,
+    pub hash: String,
+}
+
+/// Keyboard registrations of the platform store
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyboardRegistrations {
+    pub entries: Vec<StoreEntry>,
+    pub active: Option<String>,
+}
+
+/// State of all three sources at one point in time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StoreSnapshot {
+    pub registry: KeyboardRegistrations,
+    pub config: KeyboardRegistrations,
+    pub files: Vec<StoreFile>,
+    /// Hashes of the embedded keyboard copies, by keyboard id
+    pub embedded: BTreeMap<String, String>,
+}
+
+/// A disagreement between the sources
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Discrepancy {
+    /// A registered keyboard whose file is missing, with no embedded copy
+    OrphanEntry { id: String, filename: Option<String> },
+    /// A km2 file no keyboard refers to
+    UnregisteredFile { filename: String },
+    /// The stored hash does not match the file on disk
+    HashMismatch { id: String, stored: String, actual: String },
+    /// The embedded copy differs from the file on disk
+    StaleEmbedded { id: String },
+    /// The active keyboard does not exist or has no file
+    DanglingActive { id: String },
+    /// Registered in the platform store but unknown to the GUI
+    NotLoaded { id: String },
+    /// Known to the GUI but missing from the platform store
+    NotRegistered { id: String },
+}
+
+/// A change made to bring the sources back in line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RepairAction {
+    /// Remove the registration from every source
+    Remove { id: String },
+    /// Store the hash of the file on disk
+    UpdateHash { id: String, hash: String },
+    /// Embed the file on disk again
+    RefreshEmbedded { id: String },
+    /// Unset an active keyboard that cannot be loaded
+    ClearActive { id: String },
+    /// Load a platform registration into the GUI
+    Load { id: String },
+    /// Write a GUI keyboard back to the platform store
+    Register { id: String },
+    /// Register an unregistered file as a new keyboard
+    Import { filename: String },
+}
+
+/// What the repair is allowed to do without asking
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RepairPolicy {
+    /// Import unregistered files instead of reporting them
+    pub import_unregistered: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RepairPlan {
+    pub actions: Vec<RepairAction>,
+    /// Discrepancies left for the user to decide
+    pub needs_confirmation: Vec<Discrepancy>,
+}
+
+/// Result of a repair run, returned to the UI
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepairReport {
+    pub discrepancies: Vec<Discrepancy>,
+    pub fixed: Vec<RepairAction>,
+    pub needs_confirmation: Vec<Discrepancy>,
+    /// Repairs that were planned but failed to apply
+    pub errors: Vec<String>,
+}
+
+impl RepairReport {
+    /// Returns true when the sources were already consistent
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// File names are compared case-insensitively, as on Windows and macOS
+fn file_key(filename: &str) -> String {
+    filename.to_lowercase()
+}
+
+/// Finds every disagreement between the sources, in a stable order
+pub fn classify(snapshot: &StoreSnapshot) -> Vec<Discrepancy> {
+    let files: HashMap<String, &StoreFile> = snapshot
+        .files
+        .iter()
+        .map(|file| (file_key(&file.filename), file))
+        .collect();
+    let registry: HashMap<&str, &StoreEntry> =
+        snapshot.registry.entries.iter().map(|e| (e.id.as_str(), e)).collect();
+    let config: HashMap<&str, &StoreEntry> =
+        snapshot.config.entries.iter().map(|e| (e.id.as_str(), e)).collect();
+    let ids: BTreeSet<&str> = registry.keys().chain(config.keys()).copied().collect();
+
+    let mut discrepancies = Vec::new();
+    let mut referenced = BTreeSet::new();
+    let mut valid = BTreeSet::new();
+
+    for id in ids {
+        let entries: Vec<&StoreEntry> = [registry.get(id), config.get(id)].into_iter().flatten().copied().collect();
+        let filename = entries.iter().find_map(|e| e.filename.clone());
+        if let Some(ref name) = filename {
+            referenced.insert(file_key(name));
+        }
+
+        let file = filename.as_deref().and_then(|name| files.get(&file_key(name)));
+        let embedded = snapshot.embedded.get(id);
+        let actual = match (file, embedded) {
+            (Some(file), _) => &file.hash,
+            (None, Some(embedded)) => embedded,
+            (None, None) => {
+                discrepancies.push(Discrepancy::OrphanEntry { id: id.to_string(), filename });
+                continue;
+            }
+        };
+        valid.insert(id);
+
+        if !registry.contains_key(id) {
+            discrepancies.push(Discrepancy::NotRegistered { id: id.to_string() });
+        }
+        if !config.contains_key(id) {
+            discrepancies.push(Discrepancy::NotLoaded { id: id.to_string() });
+        }
+        if let Some(stale) = entries.iter().find(|e| !e.hash.eq_ignore_ascii_case(actual)) {
+            discrepancies.push(Discrepancy::HashMismatch {
+                id: id.to_string(),
+                stored: stale.hash.clone(),
+                actual: actual.clone(),
+            });
+        }
+        if embedded.is_some_and(|embedded| !embedded.eq_ignore_ascii_case(actual)) {
+            discrepancies.push(Discrepancy::StaleEmbedded { id: id.to_string() });
+        }
+    }
+
+    for file in &snapshot.files {
+        if !referenced.contains(&file_key(&file.filename)) {
+            discrepancies.push(Discrepancy::UnregisteredFile { filename: file.filename.clone() });
+        }
+    }
+
+    let actives: BTreeSet<&str> = [&snapshot.registry.active, &snapshot.config.active]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    for id in actives {
+        if !valid.contains(id) {
+            discrepancies.push(Discrepancy::DanglingActive { id: id.to_string() });
+        }
+    }
+
+    discrepancies
+}
+
+/// Decides which discrepancies can be fixed automatically
+pub fn plan_repairs(discrepancies: &[Discrepancy], policy: RepairPolicy) -> RepairPlan {
+    let mut plan = RepairPlan::default();
+
+    for discrepancy in discrepancies {
+        let action = match discrepancy {
+            Discrepancy::OrphanEntry { id, .. } => RepairAction::Remove { id: id.clone() },
+            Discrepancy::HashMismatch { id, actual, .. } => RepairAction::UpdateHash { id: id.clone(), hash: actual.clone() },
+            Discrepancy::StaleEmbedded { id } => RepairAction::RefreshEmbedded { id: id.clone() },
+            Discrepancy::DanglingActive { id } => RepairAction::ClearActive { id: id.clone() },
+            Discrepancy::NotLoaded { id } => RepairAction::Load { id: id.clone() },
+            Discrepancy::NotRegistered { id } => RepairAction::Register { id: id.clone() },
+            Discrepancy::UnregisteredFile { filename } if policy.import_unregistered => {
+                RepairAction::Import { filename: filename.clone() }
+            }
+            Discrepancy::UnregisteredFile { .. } => {
+                plan.needs_confirmation.push(discrepancy.clone());
+                continue;
+            }
+        };
+        plan.actions.push(action);
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, filename: &str, hash: &str) -> StoreEntry {
+        StoreEntry {
+            id: id.to_string(),
+            filename: Some(filename.to_string()),
+            hash: hash.to_string(),
+        }
+    }
+
+    fn file(filename: &str, hash: &str) -> StoreFile {
+        StoreFile {
+            filename: filename.to_string(),
+            hash: hash.to_string(),
+        }
+    }
+
+    fn registrations(entries: Vec<StoreEntry>, active: Option<&str>) -> KeyboardRegistrations {
+        KeyboardRegistrations {
+            entries,
+            active: active.map(str::to_string),
+        }
+    }
+
+    /// Registry and GUI agree on the given entries
+    fn consistent(entries: Vec<StoreEntry>, active: Option<&str>, files: Vec<StoreFile>) -> StoreSnapshot {
+        StoreSnapshot {
+            registry: registrations(entries.clone(), active),
+            config: registrations(entries, active),
+            files,
+            embedded: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_consistent_store_is_clean() {
+        let snapshot = consistent(
+            vec![entry("zawcode", "ZawCode.km2", "AA11")],
+            Some("zawcode"),
+            vec![file("zawcode.km2", "aa11")],
+        );
+        assert!(classify(&snapshot).is_empty());
+    }
+
+    #[test]
+    fn test_missing_file_is_orphan_and_leaves_active_dangling() {
+        let mut snapshot = consistent(
+            vec![entry("a", "a.km2", "1"), entry("b", "b.km2", "2")],
+            Some("b"),
+            vec![file("a.km2", "1")],
+        );
+        snapshot.registry.entries.push(StoreEntry {
+            id: "broken".to_string(),
+            filename: None,
+            hash: String::new(),
+        });
+
+        assert_eq!(
+            classify(&snapshot),
+            vec![
+                Discrepancy::OrphanEntry { id: "b".to_string(), filename: Some("b.km2".to_string()) },
+                Discrepancy::OrphanEntry { id: "broken".to_string(), filename: None },
+                Discrepancy::DanglingActive { id: "b".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash_mismatch_and_unregistered_file() {
+        let snapshot = consistent(
+            vec![entry("a", "a.km2", "old")],
+            None,
+            vec![file("a.km2", "new"), file("extra.km2", "3")],
+        );
+        assert_eq!(
+            classify(&snapshot),
+            vec![
+                Discrepancy::HashMismatch { id: "a".to_string(), stored: "old".to_string(), actual: "new".to_string() },
+                Discrepancy::UnregisteredFile { filename: "extra.km2".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_registry_and_gui_divergence() {
+        let snapshot = StoreSnapshot {
+            registry: registrations(vec![entry("a", "a.km2", "1")], Some("a")),
+            config: registrations(vec![entry("b", "b.km2", "2")], Some("b")),
+            files: vec![file("a.km2", "1"), file("b.km2", "2")],
+            embedded: BTreeMap::new(),
+        };
+        assert_eq!(
+            classify(&snapshot),
+            vec![
+                Discrepancy::NotLoaded { id: "a".to_string() },
+                Discrepancy::NotRegistered { id: "b".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_embedded_copy_stands_in_for_a_missing_file() {
+        let mut snapshot = consistent(
+            vec![entry("a", "a.km2", "1"), entry("b", "b.km2", "2"), entry("c", "c.km2", "3")],
+            Some("a"),
+            vec![file("b.km2", "2")],
+        );
+        snapshot.embedded.insert("a".to_string(), "1".to_string());
+        snapshot.embedded.insert("b".to_string(), "old".to_string());
+        snapshot.embedded.insert("c".to_string(), "changed".to_string());
+        assert_eq!(
+            classify(&snapshot),
+            vec![
+                // The file wins while it is there
+                Discrepancy::StaleEmbedded { id: "b".to_string() },
+                Discrepancy::HashMismatch { id: "c".to_string(), stored: "3".to_string(), actual: "changed".to_string() },
+            ]
+        );
+
+        let plan = plan_repairs(&classify(&snapshot), RepairPolicy::default());
+        assert_eq!(
+            plan.actions,
+            vec![
+                RepairAction::RefreshEmbedded { id: "b".to_string() },
+                RepairAction::UpdateHash { id: "c".to_string(), hash: "changed".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unregistered_files_need_confirmation_unless_allowed() {
+        let discrepancies = vec![
+            Discrepancy::OrphanEntry { id: "gone".to_string(), filename: None },
+            Discrepancy::UnregisteredFile { filename: "new.km2".to_string() },
+            Discrepancy::DanglingActive { id: "gone".to_string() },
+        ];
+
+        let plan = plan_repairs(&discrepancies, RepairPolicy::default());
+        assert_eq!(
+            plan.actions,
+            vec![
+                RepairAction::Remove { id: "gone".to_string() },
+                RepairAction::ClearActive { id: "gone".to_string() },
+            ]
+        );
+        assert_eq!(plan.needs_confirmation, vec![discrepancies[1].clone()]);
+
+        let plan = plan_repairs(&discrepancies, RepairPolicy { import_unregistered: true });
+        assert!(plan.needs_confirmation.is_empty());
+        assert!(plan.actions.contains(&RepairAction::Import { filename: "new.km2".to_string() }));
+    }
+
+    #[test]
+    fn test_report_serialization() {
+        let report = RepairReport {
+            discrepancies: vec![Discrepancy::DanglingActive { id: "x".to_string() }],
+            fixed: vec![RepairAction::ClearActive { id: "x".to_string() }],
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["discrepancies"][0]["kind"], "dangling_active");
+        assert_eq!(json["fixed"][0]["action"], "clear_active");
+    }
+}
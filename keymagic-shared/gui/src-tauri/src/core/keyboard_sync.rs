@@ -0,0 +1,388 @@
+//! Merging keyboard settings changed by other processes
+//!
+//! The IME, the installer or a second settings window write the platform
+//! store while the settings window is open. Reloading the whole keyboard list
+//! would throw away what the user is editing, so a reload only takes the
+//! fields that differ from memory and leaves fields with pending local edits
+//! alone. The merge is a pure function; `KeyboardManager` gathers the stored
+//! keyboards and tracks the pending edits.
+
+use super::keyboard_manager::KeyboardInfo;
+use crate::platform::InstalledKeyboard;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// A setting of an installed keyboard that can change on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyboardField {
+    Name,
+    /// The km2 file; its metadata (description, icon, default hotkey) comes along
+    Filename,
+    Hotkey,
+    Enabled,
+    /// The file content changed; its metadata comes along
+    Hash,
+    OutputEncoding,
+    DisabledGroups,
+    /// The passthrough keys and whether to commit before them
+    PassthroughKeys,
+    OptionsOverrides,
+}
+
+const FIELDS: [KeyboardField; 9] = [
+    KeyboardField::Name,
+    KeyboardField::Filename,
+    KeyboardField::Hotkey,
+    KeyboardField::Enabled,
+    KeyboardField::Hash,
+    KeyboardField::OutputEncoding,
+    KeyboardField::DisabledGroups,
+    KeyboardField::PassthroughKeys,
+    KeyboardField::OptionsOverrides,
+];
+
+impl KeyboardField {
+    fn differs(self, current: &KeyboardInfo, stored: &InstalledKeyboard) -> bool {
+        match self {
+            KeyboardField::Name => current.name != stored.name,
+            KeyboardField::Filename => current.filename != stored.filename,
+            KeyboardField::Hotkey => current.hotkey != stored.hotkey,
+            KeyboardField::Enabled => current.enabled != stored.enabled,
+            KeyboardField::Hash => current.hash != stored.hash,
+            KeyboardField::OutputEncoding => current.output_encoding != stored.output_encoding,
+            KeyboardField::DisabledGroups => current.disabled_groups != stored.disabled_groups,
+            KeyboardField::PassthroughKeys => {
+                current.passthrough_keys != stored.passthrough_keys
+                    || current.commit_before_passthrough != stored.commit_before_passthrough
+            }
+            KeyboardField::OptionsOverrides => current.options_overrides != stored.options_overrides,
+        }
+    }
+
+    fn copy(self, current: &mut KeyboardInfo, stored: &KeyboardInfo) {
+        match self {
+            KeyboardField::Name => current.name = stored.name.clone(),
+            KeyboardField::Filename => {
+                current.filename = stored.filename.clone();
+                current.path = stored.path.clone();
+                copy_file_metadata(current, stored);
+            }
+            KeyboardField::Hash => {
+                current.hash = stored.hash.clone();
+                current.original_hash = stored.original_hash.clone();
+                copy_file_metadata(current, stored);
+            }
+            KeyboardField::Hotkey => {
+                current.hotkey = stored.hotkey.clone();
+                current.display_hotkey = stored.display_hotkey.clone();
+            }
+            KeyboardField::Enabled => current.enabled = stored.enabled,
+            KeyboardField::OutputEncoding => current.output_encoding = stored.output_encoding,
+            KeyboardField::DisabledGroups => current.disabled_groups = stored.disabled_groups.clone(),
+            KeyboardField::PassthroughKeys => {
+                current.passthrough_keys = stored.passthrough_keys.clone();
+                current.commit_before_passthrough = stored.commit_before_passthrough;
+            }
+            KeyboardField::OptionsOverrides => current.options_overrides = stored.options_overrides.clone(),
+        }
+    }
+}
+
+fn copy_file_metadata(current: &mut KeyboardInfo, stored: &KeyboardInfo) {
+    current.description = stored.description.clone();
+    current.icon_data = stored.icon_data.clone();
+    current.default_hotkey = stored.default_hotkey.clone();
+    current.default_display_hotkey = stored.default_display_hotkey.clone();
+    current.languages = stored.languages.clone();
+}
+
+/// Fields with local modifications that are not saved yet, per keyboard
+///
+/// Edits are counted so that the settings dialog and a save in flight can
+/// both hold the same field.
+#[derive(Debug, Clone, Default)]
+pub struct PendingEdits {
+    edits: HashMap<String, BTreeMap<KeyboardField, usize>>,
+}
+
+impl PendingEdits {
+    pub fn begin(&mut self, keyboard_id: &str, field: KeyboardField) {
+        *self.edits.entry(keyboard_id.to_string()).or_default().entry(field).or_default() += 1;
+    }
+
+    /// Ends an edit started with `begin`; unmatched calls are ignored
+    pub fn end(&mut self, keyboard_id: &str, field: KeyboardField) {
+        let Some(fields) = self.edits.get_mut(keyboard_id) else {
+            return;
+        };
+        if let Some(count) = fields.get_mut(&field) {
+            *count -= 1;
+            if *count == 0 {
+                fields.remove(&field);
+            }
+        }
+        if fields.is_empty() {
+            self.edits.remove(keyboard_id);
+        }
+    }
+
+    pub fn is_pending(&self, keyboard_id: &str, field: KeyboardField) -> bool {
+        self.edits.get(keyboard_id).is_some_and(|fields| fields.contains_key(&field))
+    }
+
+    /// Drops the edits of a keyboard that no longer exists
+    pub fn forget(&mut self, keyboard_id: &str) {
+        self.edits.remove(keyboard_id);
+    }
+}
+
+/// What a reload changed; sent to the UI as the `keyboards_changed` event so
+/// it can merge instead of rebuilding the list
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct KeyboardsChanged {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Fields taken from the store, per keyboard
+    pub changed: BTreeMap<String, Vec<KeyboardField>>,
+}
+
+impl KeyboardsChanged {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Keyboards whose file or file content changed
+    pub fn files_changed(&self) -> impl Iterator<Item = &str> {
+        self.changed
+            .iter()
+            .filter(|(_, fields)| fields.contains(&KeyboardField::Filename) || fields.contains(&KeyboardField::Hash))
+            .map(|(id, _)| id.as_str())
+    }
+}
+
+/// Brings `current` up to date with the `stored` keyboards, skipping fields
+/// with pending edits
+///
+/// `build` turns a stored keyboard into its full info (reading the km2 file
+/// for the metadata); it is only called for keyboards that are new or
+/// changed, and keyboards it returns `None` for are left as they are. A
+/// keyboard removed from the store is removed even with pending edits, since
+/// there is nothing left to save them to.
+pub fn merge_external(
+    current: &mut HashMap<String, KeyboardInfo>,
+    stored: &[InstalledKeyboard],
+    pending: &PendingEdits,
+    build: impl Fn(&InstalledKeyboard) -> Option<KeyboardInfo>,
+) -> KeyboardsChanged {
+    let mut changes = KeyboardsChanged::default();
+
+    for installed in stored {
+        let Some(keyboard) = current.get_mut(&installed.id) else {
+            if let Some(keyboard) = build(installed) {
+                current.insert(installed.id.clone(), keyboard);
+                changes.added.push(installed.id.clone());
+            }
+            continue;
+        };
+
+        let fields: Vec<KeyboardField> = FIELDS
+            .into_iter()
+            .filter(|&field| field.differs(keyboard, installed) && !pending.is_pending(&installed.id, field))
+            .collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let Some(built) = build(installed) else {
+            continue;
+        };
+        for &field in &fields {
+            field.copy(keyboard, &built);
+        }
+        changes.changed.insert(installed.id.clone(), fields);
+    }
+
+    let mut removed: Vec<String> = current
+        .keys()
+        .filter(|id| !stored.iter().any(|installed| &installed.id == *id))
+        .cloned()
+        .collect();
+    removed.sort();
+    for id in &removed {
+        current.remove(id);
+    }
+    changes.removed = removed;
+    changes.added.sort();
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::OutputEncoding;
+    use std::path::PathBuf;
+
+    fn installed(id: &str) -> InstalledKeyboard {
+        InstalledKeyboard {
+            id: id.to_string(),
+            name: id.to_string(),
+            filename: format!("{}.km2", id),
+            hotkey: None,
+            hash: "aa".to_string(),
+            enabled: true,
+            output_encoding: OutputEncoding::Unicode,
+            disabled_groups: Vec::new(),
+            passthrough_keys: Vec::new(),
+            commit_before_passthrough: false,
+            options_overrides: HashMap::new(),
+            original_hash: None,
+            sample_text: None,
+        }
+    }
+
+    /// Keyboard info as `KeyboardManager` builds it, with the hash as description
+    fn build(installed: &InstalledKeyboard) -> Option<KeyboardInfo> {
+        Some(KeyboardInfo {
+            id: installed.id.clone(),
+            name: installed.name.clone(),
+            filename: installed.filename.clone(),
+            path: PathBuf::from(&installed.filename),
+            hotkey: installed.hotkey.clone(),
+            default_hotkey: None,
+            hash: installed.hash.clone(),
+            original_hash: installed.original_hash.clone(),
+            is_active: false,
+            enabled: installed.enabled,
+            status: Default::default(),
+            storage: Default::default(),
+            languages: Vec::new(),
+            description: Some(format!("file {}", installed.hash)),
+            sample_text: None,
+            font_family: None,
+            icon_data: None,
+            display_hotkey: installed.hotkey.as_ref().map(|h| h.replace('+', " + ")),
+            default_display_hotkey: None,
+            output_encoding: installed.output_encoding,
+            disabled_groups: installed.disabled_groups.clone(),
+            passthrough_keys: installed.passthrough_keys.clone(),
+            commit_before_passthrough: installed.commit_before_passthrough,
+            options_overrides: installed.options_overrides.clone(),
+        })
+    }
+
+    fn memory(stored: &[InstalledKeyboard]) -> HashMap<String, KeyboardInfo> {
+        stored.iter().map(|kb| (kb.id.clone(), build(kb).unwrap())).collect()
+    }
+
+    #[test]
+    fn test_unchanged_store_changes_nothing() {
+        let stored = vec![installed("zawcode"), installed("myansan")];
+        let mut current = memory(&stored);
+        let changes = merge_external(&mut current, &stored, &PendingEdits::default(), |_| panic!("nothing to build"));
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_external_change_is_taken() {
+        let mut stored = vec![installed("zawcode")];
+        let mut current = memory(&stored);
+        current.get_mut("zawcode").unwrap().is_active = true;
+        stored[0].hotkey = Some("Ctrl+Shift+Z".to_string());
+
+        let changes = merge_external(&mut current, &stored, &PendingEdits::default(), build);
+        assert_eq!(changes.changed["zawcode"], vec![KeyboardField::Hotkey]);
+        let keyboard = &current["zawcode"];
+        assert_eq!(keyboard.hotkey.as_deref(), Some("Ctrl+Shift+Z"));
+        assert_eq!(keyboard.display_hotkey.as_deref(), Some("Ctrl + Shift + Z"));
+        // State that is not stored is kept
+        assert!(keyboard.is_active);
+    }
+
+    #[test]
+    fn test_pending_edit_survives_external_change_to_same_field() {
+        let mut stored = vec![installed("zawcode")];
+        let mut current = memory(&stored);
+        let mut pending = PendingEdits::default();
+        pending.begin("zawcode", KeyboardField::Hotkey);
+        current.get_mut("zawcode").unwrap().hotkey = Some("Ctrl+K".to_string());
+        stored[0].hotkey = Some("Ctrl+Shift+Z".to_string());
+
+        let changes = merge_external(&mut current, &stored, &pending, build);
+        assert!(changes.is_empty());
+        assert_eq!(current["zawcode"].hotkey.as_deref(), Some("Ctrl+K"));
+    }
+
+    #[test]
+    fn test_pending_edit_survives_external_change_to_other_field() {
+        let mut stored = vec![installed("zawcode")];
+        let mut current = memory(&stored);
+        let mut pending = PendingEdits::default();
+        pending.begin("zawcode", KeyboardField::Hotkey);
+        current.get_mut("zawcode").unwrap().hotkey = Some("Ctrl+K".to_string());
+        stored[0].enabled = false;
+        stored[0].passthrough_keys = vec!["VK_ESCAPE".to_string()];
+
+        let changes = merge_external(&mut current, &stored, &pending, build);
+        assert_eq!(changes.changed["zawcode"], vec![KeyboardField::Enabled, KeyboardField::PassthroughKeys]);
+        let keyboard = &current["zawcode"];
+        assert_eq!(keyboard.hotkey.as_deref(), Some("Ctrl+K"));
+        assert!(!keyboard.enabled);
+        assert_eq!(keyboard.passthrough_keys, vec!["VK_ESCAPE".to_string()]);
+    }
+
+    #[test]
+    fn test_field_is_taken_once_edit_ends() {
+        let mut stored = vec![installed("zawcode")];
+        let mut current = memory(&stored);
+        let mut pending = PendingEdits::default();
+        pending.begin("zawcode", KeyboardField::Hotkey);
+        pending.begin("zawcode", KeyboardField::Hotkey);
+        stored[0].hotkey = Some("Ctrl+Shift+Z".to_string());
+
+        pending.end("zawcode", KeyboardField::Hotkey);
+        assert!(merge_external(&mut current, &stored, &pending, build).is_empty());
+        pending.end("zawcode", KeyboardField::Hotkey);
+        pending.end("zawcode", KeyboardField::Hotkey);
+        let changes = merge_external(&mut current, &stored, &pending, build);
+        assert_eq!(changes.changed["zawcode"], vec![KeyboardField::Hotkey]);
+    }
+
+    #[test]
+    fn test_file_change_reloads_metadata() {
+        let mut stored = vec![installed("zawcode")];
+        let mut current = memory(&stored);
+        stored[0].hash = "bb".to_string();
+
+        let changes = merge_external(&mut current, &stored, &PendingEdits::default(), build);
+        assert_eq!(changes.files_changed().collect::<Vec<_>>(), vec!["zawcode"]);
+        assert_eq!(current["zawcode"].description.as_deref(), Some("file bb"));
+    }
+
+    #[test]
+    fn test_added_and_removed_keyboards() {
+        let mut current = memory(&[installed("zawcode"), installed("myansan")]);
+        let mut pending = PendingEdits::default();
+        pending.begin("myansan", KeyboardField::Hotkey);
+        let stored = vec![installed("zawcode"), installed("pyidaungsu")];
+
+        let changes = merge_external(&mut current, &stored, &pending, build);
+        assert_eq!(changes.added, vec!["pyidaungsu".to_string()]);
+        assert_eq!(changes.removed, vec!["myansan".to_string()]);
+        assert!(changes.changed.is_empty());
+        let mut ids: Vec<&String> = current.keys().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["pyidaungsu", "zawcode"]);
+    }
+
+    #[test]
+    fn test_unreadable_keyboard_is_left_alone() {
+        let mut stored = vec![installed("zawcode"), installed("broken")];
+        let mut current = memory(&stored[..1]);
+        stored[0].name = "ZawCode".to_string();
+
+        let changes = merge_external(&mut current, &stored, &PendingEdits::default(), |_| None);
+        assert!(changes.is_empty());
+        assert_eq!(current["zawcode"].name, "zawcode");
+        assert!(!current.contains_key("broken"));
+    }
+}
@@ -0,0 +1,302 @@
+//! Rules playground: compiling a KMS snippet in memory and typing keys into it
+//!
+//! Lets keyboard authors try a rule without saving, converting and importing
+//! a file. The snippet is compiled like `kms2km2::compile_kms` but never
+//! touches the file system: `include` is reported as an error and `@ICON` is
+//! ignored. Compile errors and warnings come back as diagnostics rather than
+//! failing the call, so the editor can show them next to the source. Size
+//! limits keep a pasted keyboard from tying up the backend, and typing stops
+//! once [`TIME_LIMIT`] has passed.
+
+use anyhow::Result;
+use keymagic_core::km2::RuleFormatter;
+use keymagic_core::{ActionType, KeyMagicEngine, KmsError};
+use kms2km2::binary::Compiler;
+use kms2km2::parser::Parser;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::core::key_event::KeyEventDto;
+
+/// Largest snippet accepted, in bytes
+pub const MAX_SOURCE_BYTES: usize = 64 * 1024;
+/// Most rules a snippet may have
+pub const MAX_RULES: usize = 2000;
+/// Most keys typed per call
+pub const MAX_KEY_EVENTS: usize = 500;
+/// Time after which the remaining keys are not typed
+pub const TIME_LIMIT: Duration = Duration::from_secs(2);
+
+/// Error for a snippet or key list over one of the playground limits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnippetLimitExceeded {
+    /// "source bytes", "rules" or "key events"
+    pub what: &'static str,
+    pub count: usize,
+    pub limit: usize,
+}
+
+impl std::fmt::Display for SnippetLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Snippet has {} {}, more than the playground allows ({})", self.count, self.what, self.limit)
+    }
+}
+
+impl std::error::Error for SnippetLimitExceeded {}
+
+/// A compile error or warning
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnippetDiagnostic {
+    /// "error" or "warning"
+    pub severity: String,
+    /// Source line, when known
+    pub line: Option<usize>,
+    /// Rule index, in source order, when known
+    pub rule: Option<usize>,
+    pub message: String,
+}
+
+impl SnippetDiagnostic {
+    fn error(line: Option<usize>, message: String) -> Self {
+        Self { severity: "error".to_string(), line, rule: None, message }
+    }
+}
+
+/// What one key did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnippetKeyOutput {
+    /// The key in compact text form ("k", "{BACK}", "<Shift+{KEY_Z}>")
+    pub key: String,
+    /// Whether a rule matched
+    pub processed: bool,
+    /// Characters deleted before the cursor
+    pub deleted: usize,
+    /// Text inserted after the delete
+    pub inserted: String,
+    pub composing_text: String,
+    /// Rules applied, as `#index: rule` (`#index (post): rule` for
+    /// post-rules), in application order
+    pub trace: Vec<String>,
+    /// States left on for the next key, in index order
+    pub states: Vec<usize>,
+    /// Names of those states as written in the snippet
+    pub state_names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnippetResult {
+    /// False when the snippet did not compile; no keys were typed then
+    pub compiled: bool,
+    pub diagnostics: Vec<SnippetDiagnostic>,
+    pub outputs: Vec<SnippetKeyOutput>,
+    /// Composing text after the last key typed
+    pub composing_text: String,
+    /// Keys left untyped because the time limit was reached
+    pub timed_out: bool,
+}
+
+/// Compiles `source` and types `key_events` into a fresh engine
+pub fn run_snippet(source: &str, key_events: &[KeyEventDto]) -> Result<SnippetResult> {
+    let started = Instant::now();
+    if source.len() > MAX_SOURCE_BYTES {
+        return Err(SnippetLimitExceeded { what: "source bytes", count: source.len(), limit: MAX_SOURCE_BYTES }.into());
+    }
+    if key_events.len() > MAX_KEY_EVENTS {
+        return Err(SnippetLimitExceeded { what: "key events", count: key_events.len(), limit: MAX_KEY_EVENTS }.into());
+    }
+    let inputs = key_events
+        .iter()
+        .map(|key| Ok((key, key.to_key_input()?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut result = SnippetResult::default();
+    let layout = match compile(source, &mut result.diagnostics)? {
+        Some(layout) => layout,
+        None => return Ok(result),
+    };
+    result.compiled = true;
+
+    let mut engine = KeyMagicEngine::new(layout)?;
+    for (key, input) in inputs {
+        if started.elapsed() > TIME_LIMIT {
+            result.timed_out = true;
+            break;
+        }
+        let output = engine.process_key(input)?;
+        let inserted = match output.action {
+            ActionType::Insert(text) | ActionType::BackspaceDeleteAndInsert(_, text) => text,
+            _ => String::new(),
+        };
+        result.outputs.push(SnippetKeyOutput {
+            key: key.to_string(),
+            processed: output.is_processed,
+            deleted: output.delete_chars,
+            inserted,
+            composing_text: output.composing_text,
+            trace: trace(&engine),
+            states: engine.active_states(),
+            state_names: engine.active_state_names(),
+        });
+    }
+    result.composing_text = engine.composing_text().to_string();
+    Ok(result)
+}
+
+/// Compiles without include processing, recording errors and warnings in
+/// `diagnostics`; `None` when the snippet does not compile
+fn compile(source: &str, diagnostics: &mut Vec<SnippetDiagnostic>) -> Result<Option<keymagic_core::Km2File>> {
+    let mut ast = match Parser::new(source).parse() {
+        Ok(ast) => ast,
+        Err(e) => {
+            diagnostics.push(kms_diagnostic(e));
+            return Ok(None);
+        }
+    };
+    if let Some(path) = ast.includes.first() {
+        let message = format!(
+            "include(\"{}\") is not available in the playground; paste the rules of {} into the snippet",
+            path, path
+        );
+        diagnostics.push(SnippetDiagnostic::error(include_line(source), message));
+        return Ok(None);
+    }
+    if ast.rules.len() > MAX_RULES {
+        return Err(SnippetLimitExceeded { what: "rules", count: ast.rules.len(), limit: MAX_RULES }.into());
+    }
+    ast.options.remove("ICON");
+
+    match Compiler::new().compile_with_warnings(ast) {
+        Ok((layout, warnings)) => {
+            diagnostics.extend(warnings.into_iter().map(|warning| SnippetDiagnostic {
+                severity: "warning".to_string(),
+                line: None,
+                rule: warning.rule,
+                message: warning.message,
+            }));
+            Ok(Some(layout))
+        }
+        Err(e) => {
+            diagnostics.push(kms_diagnostic(e));
+            Ok(None)
+        }
+    }
+}
+
+fn kms_diagnostic(err: KmsError) -> SnippetDiagnostic {
+    match err {
+        KmsError::Parse { line, message } => SnippetDiagnostic::error(Some(line).filter(|&line| line > 0), message),
+        err => SnippetDiagnostic::error(None, err.to_string()),
+    }
+}
+
+/// Line of the first `include` statement; the parser does not keep it
+fn include_line(source: &str) -> Option<usize> {
+    source.lines().position(|line| line.trim_start().starts_with("include")).map(|index| index + 1)
+}
+
+/// The rules the engine applied for the last key
+fn trace(engine: &KeyMagicEngine) -> Vec<String> {
+    let keyboard = engine.keyboard();
+    let formatter = RuleFormatter::for_keyboard(keyboard);
+    engine
+        .last_matched_rules()
+        .iter()
+        .map(|&rule| {
+            let post = if engine.is_post_rule(rule) { " (post)" } else { "" };
+            format!("#{}{}: {}", rule, post, formatter.format_rule(&keyboard.rules[rule]))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::key_event::{KeyEventError, VkRef};
+
+    fn chars(text: &str) -> Vec<KeyEventDto> {
+        text.chars().map(KeyEventDto::typed).collect()
+    }
+
+    #[test]
+    fn test_keys_are_typed_with_trace() {
+        let source = r#"
+            $cons = "ကခ"
+            "k" => "က"
+            $cons[*] + "a" => $1 + "ာ"
+        "#;
+        let result = run_snippet(source, &chars("ka")).unwrap();
+
+        assert!(result.compiled);
+        assert!(result.diagnostics.is_empty());
+        assert_eq!(result.composing_text, "ကာ");
+        assert_eq!(result.outputs.len(), 2);
+        assert_eq!(result.outputs[0].composing_text, "က");
+        assert!(result.outputs[0].processed);
+        assert_eq!(result.outputs[1].deleted, 0);
+        assert_eq!(result.outputs[1].inserted, "ာ");
+        assert_eq!(result.outputs[1].trace.len(), 1);
+        assert!(result.outputs[1].trace[0].starts_with("#1: "), "{:?}", result.outputs[1].trace);
+        assert!(!result.timed_out);
+    }
+
+    #[test]
+    fn test_combo_keys_and_states() {
+        let source = r#"
+            <VK_SHIFT & VK_KEY_Z> => ('zg')
+            ('zg') + "k" => "ၵ"
+        "#;
+        let keys = crate::core::key_event::parse_key_sequence("<Shift+{KEY_Z}>k").unwrap();
+        let result = run_snippet(source, &keys).unwrap();
+
+        assert_eq!(result.outputs[0].key, "<Shift+{KEY_Z}>");
+        assert_eq!(result.outputs[0].states, vec![0]);
+        assert_eq!(result.outputs[0].state_names, vec!["zg".to_string()]);
+        assert_eq!(result.outputs[1].trace, vec!["#1: ('zg') + \"k\" => \"ၵ\"".to_string()]);
+        assert_eq!(result.outputs[1].composing_text, "ၵ");
+        assert!(result.outputs[1].states.is_empty());
+
+        let unknown = vec![KeyEventDto { vk: Some(VkRef::Name("VK_NOPE".into())), ..KeyEventDto::default() }];
+        let err = run_snippet(source, &unknown).unwrap_err();
+        assert_eq!(err.downcast_ref::<KeyEventError>(), Some(&KeyEventError::UnknownKeyName("VK_NOPE".into())));
+    }
+
+    #[test]
+    fn test_compile_errors_are_diagnostics() {
+        let result = run_snippet("\"k\" => $missing\n", &chars("k")).unwrap();
+        assert!(!result.compiled);
+        assert!(result.outputs.is_empty());
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].severity, "error");
+        assert!(result.diagnostics[0].message.contains("missing"), "{:?}", result.diagnostics);
+
+        let result = run_snippet("\"k\" =>\n\"a\" => \"b\"", &[]).unwrap();
+        assert!(!result.compiled);
+        assert!(result.diagnostics[0].line.is_some(), "{:?}", result.diagnostics);
+    }
+
+    #[test]
+    fn test_includes_are_rejected() {
+        let source = "\"k\" => \"က\"\ninclude(\"common.kms\")\n";
+        let result = run_snippet(source, &chars("k")).unwrap();
+        assert!(!result.compiled);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].line, Some(2));
+        assert!(result.diagnostics[0].message.contains("common.kms"));
+        assert!(result.diagnostics[0].message.contains("not available in the playground"));
+    }
+
+    #[test]
+    fn test_limits() {
+        let big = "/* */".repeat(MAX_SOURCE_BYTES);
+        let err = run_snippet(&big, &[]).unwrap_err();
+        assert_eq!(err.downcast_ref::<SnippetLimitExceeded>().unwrap().what, "source bytes");
+
+        let keys = chars(&"k".repeat(MAX_KEY_EVENTS + 1));
+        let err = run_snippet("\"k\" => \"က\"", &keys).unwrap_err();
+        assert_eq!(err.downcast_ref::<SnippetLimitExceeded>().unwrap().count, MAX_KEY_EVENTS + 1);
+
+        let rules = "\"k\" => \"က\"\n".repeat(MAX_RULES + 1);
+        let err = run_snippet(&rules, &[]).unwrap_err();
+        assert_eq!(err.downcast_ref::<SnippetLimitExceeded>().unwrap().what, "rules");
+    }
+}
@@ -0,0 +1,315 @@
+//! Key processing that follows the input language of the focused window
+//!
+//! Users who switch the system layout to English for a spreadsheet do not
+//! want KeyMagic composing over it. With language activation on, the input
+//! language of the foreground window is looked up in the configured rules
+//! and key processing is turned on or off, or switched to a keyboard, to
+//! match. Languages change every time the user moves between applications,
+//! so a language only takes effect once it has been stable for a moment.
+
+use crate::platform::{LanguageAction, LanguageActivationConfig};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// How long a language must stay in the foreground before it is acted on
+pub const SETTLE_TIME: Duration = Duration::from_millis(600);
+
+/// Input language of a window
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InputLanguage {
+    /// Low word of the keyboard layout handle
+    pub langid: u16,
+    /// Locale name ("en-US") when known
+    pub locale: Option<String>,
+}
+
+impl InputLanguage {
+    pub fn new(langid: u16, locale: Option<&str>) -> Self {
+        Self { langid, locale: locale.map(str::to_string) }
+    }
+}
+
+impl std::fmt::Display for InputLanguage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.locale {
+            Some(locale) => write!(f, "{}", locale),
+            None => write!(f, "{:04X}", self.langid),
+        }
+    }
+}
+
+/// Rule key that is not a LANGID, locale name or language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidLanguageKey(pub String);
+
+impl std::fmt::Display for InvalidLanguageKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' is not a LANGID (e.g. 0409), a locale name (e.g. en-US) or a language (e.g. en)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidLanguageKey {}
+
+/// What a rule key names
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LanguageKey {
+    LangId(u16),
+    Locale(String),
+    Language(String),
+}
+
+impl LanguageKey {
+    fn parse(key: &str) -> Result<Self, InvalidLanguageKey> {
+        let invalid = || InvalidLanguageKey(key.to_string());
+        let key = key.trim();
+        let hex = key.strip_prefix("0x").or_else(|| key.strip_prefix("0X")).unwrap_or(key);
+        if hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return u16::from_str_radix(hex, 16).map(LanguageKey::LangId).map_err(|_| invalid());
+        }
+
+        let mut parts = key.split('-');
+        let language = parts.next().unwrap_or_default();
+        if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(invalid());
+        }
+        let mut subtags = 0;
+        for part in parts {
+            if part.is_empty() || !part.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(invalid());
+            }
+            subtags += 1;
+        }
+        if subtags == 0 {
+            Ok(LanguageKey::Language(language.to_ascii_lowercase()))
+        } else {
+            Ok(LanguageKey::Locale(key.to_ascii_lowercase()))
+        }
+    }
+
+    /// Whether the key names the language exactly (LANGID or locale) or only
+    /// by its language; `None` if it does not apply
+    fn matches(&self, language: &InputLanguage) -> Option<bool> {
+        let locale = language.locale.as_deref().map(str::to_ascii_lowercase);
+        match self {
+            LanguageKey::LangId(langid) => (*langid == language.langid).then_some(true),
+            LanguageKey::Locale(key) => (locale.as_deref() == Some(key.as_str())).then_some(true),
+            LanguageKey::Language(key) => {
+                let primary = locale.as_deref().and_then(|locale| locale.split('-').next());
+                (primary == Some(key.as_str())).then_some(false)
+            }
+        }
+    }
+}
+
+/// Checks the keys of a rule set before it is saved
+pub fn validate_rules<'a>(keys: impl IntoIterator<Item = &'a String>) -> Result<(), InvalidLanguageKey> {
+    keys.into_iter().try_for_each(|key| LanguageKey::parse(key).map(|_| ()))
+}
+
+/// Rule for a language: a LANGID or locale name beats a bare language.
+/// Returns the key that matched with its action.
+pub fn resolve<'a>(
+    config: &'a LanguageActivationConfig,
+    language: &InputLanguage,
+) -> Option<(&'a str, &'a LanguageAction)> {
+    let mut fallback = None;
+    for (key, action) in &config.rules {
+        let Ok(parsed) = LanguageKey::parse(key) else {
+            continue;
+        };
+        match parsed.matches(language) {
+            Some(true) => return Some((key.as_str(), action)),
+            Some(false) if fallback.is_none() => fallback = Some((key.as_str(), action)),
+            _ => {}
+        }
+    }
+    fallback
+}
+
+/// Holds a language back until it has been in the foreground for
+/// `SETTLE_TIME`, so flipping quickly through windows applies nothing
+#[derive(Debug)]
+pub struct LanguageDebouncer {
+    settle: Duration,
+    candidate: Option<(InputLanguage, Instant)>,
+    applied: Option<InputLanguage>,
+}
+
+impl Default for LanguageDebouncer {
+    fn default() -> Self {
+        Self::new(SETTLE_TIME)
+    }
+}
+
+impl LanguageDebouncer {
+    pub fn new(settle: Duration) -> Self {
+        Self { settle, candidate: None, applied: None }
+    }
+
+    /// Records the language seen at `now`; returns it once it has settled
+    /// and differs from the language acted on last
+    pub fn observe(&mut self, language: InputLanguage, now: Instant) -> Option<InputLanguage> {
+        if self.applied.as_ref() == Some(&language) {
+            self.candidate = None;
+            return None;
+        }
+        match &self.candidate {
+            Some((candidate, since)) if *candidate == language => {
+                if now.duration_since(*since) < self.settle {
+                    return None;
+                }
+            }
+            _ => {
+                self.candidate = Some((language, now));
+                return None;
+            }
+        }
+        self.candidate = None;
+        self.applied = Some(language.clone());
+        Some(language)
+    }
+
+    /// Forgets the language acted on last, so the current one is applied
+    /// again (e.g. after the rules changed)
+    pub fn reset(&mut self) {
+        self.candidate = None;
+        self.applied = None;
+    }
+}
+
+/// HUD text for a language rule that changed something
+pub fn activation_message(language: &InputLanguage, action: &LanguageAction, keyboard_name: Option<&str>) -> String {
+    match action {
+        LanguageAction::Enable => format!("KeyMagic on for {}", language),
+        LanguageAction::Disable => format!("KeyMagic off for {}", language),
+        LanguageAction::Keyboard { keyboard_id } => {
+            format!("{} for {}", keyboard_name.unwrap_or(keyboard_id), language)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(rules: &[(&str, LanguageAction)]) -> LanguageActivationConfig {
+        LanguageActivationConfig {
+            enabled: true,
+            rules: rules.iter().map(|(key, action)| (key.to_string(), action.clone())).collect(),
+        }
+    }
+
+    fn keyboard(id: &str) -> LanguageAction {
+        LanguageAction::Keyboard { keyboard_id: id.to_string() }
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(LanguageKey::parse("0409"), Ok(LanguageKey::LangId(0x0409)));
+        assert_eq!(LanguageKey::parse("0x0455"), Ok(LanguageKey::LangId(0x0455)));
+        assert_eq!(LanguageKey::parse("en-US"), Ok(LanguageKey::Locale("en-us".into())));
+        assert_eq!(LanguageKey::parse("tg-Cyrl-TJ"), Ok(LanguageKey::Locale("tg-cyrl-tj".into())));
+        assert_eq!(LanguageKey::parse("MY"), Ok(LanguageKey::Language("my".into())));
+
+        for key in ["", "e", "english", "en-", "en_US", "04090", "12-34"] {
+            assert!(LanguageKey::parse(key).is_err(), "{:?}", key);
+        }
+        let keys = ["0409".to_string(), "en_US".to_string()];
+        assert_eq!(validate_rules(&keys), Err(InvalidLanguageKey("en_US".into())));
+        assert_eq!(validate_rules(&keys[..1]), Ok(()));
+    }
+
+    #[test]
+    fn test_resolve_prefers_exact_matches() {
+        let config = config(&[
+            ("en", LanguageAction::Disable),
+            ("en-GB", LanguageAction::Enable),
+            ("0455", keyboard("myanmar3")),
+        ]);
+
+        let gb = InputLanguage::new(0x0809, Some("en-GB"));
+        assert_eq!(resolve(&config, &gb), Some(("en-GB", &LanguageAction::Enable)));
+        let us = InputLanguage::new(0x0409, Some("en-US"));
+        assert_eq!(resolve(&config, &us), Some(("en", &LanguageAction::Disable)));
+        let myanmar = InputLanguage::new(0x0455, None);
+        assert_eq!(resolve(&config, &myanmar), Some(("0455", &keyboard("myanmar3"))));
+
+        // Without a locale name only LANGIDs can match
+        assert_eq!(resolve(&config, &InputLanguage::new(0x0809, None)), None);
+        assert_eq!(resolve(&config, &InputLanguage::new(0x0411, Some("ja-JP"))), None);
+    }
+
+    #[test]
+    fn test_resolve_ignores_invalid_keys() {
+        let config = config(&[("en_US", LanguageAction::Disable), ("EN-us", LanguageAction::Enable)]);
+        let us = InputLanguage::new(0x0409, Some("en-US"));
+        assert_eq!(resolve(&config, &us), Some(("EN-us", &LanguageAction::Enable)));
+    }
+
+    #[test]
+    fn test_debounce_waits_for_language_to_settle() {
+        let mut debouncer = LanguageDebouncer::new(Duration::from_millis(500));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let english = InputLanguage::new(0x0409, Some("en-US"));
+
+        assert_eq!(debouncer.observe(english.clone(), at(0)), None);
+        assert_eq!(debouncer.observe(english.clone(), at(300)), None);
+        assert_eq!(debouncer.observe(english.clone(), at(500)), Some(english.clone()));
+        // Applied once only
+        assert_eq!(debouncer.observe(english.clone(), at(1500)), None);
+    }
+
+    #[test]
+    fn test_debounce_ignores_flapping() {
+        let mut debouncer = LanguageDebouncer::new(Duration::from_millis(500));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let english = InputLanguage::new(0x0409, Some("en-US"));
+        let myanmar = InputLanguage::new(0x0455, Some("my-MM"));
+
+        assert_eq!(debouncer.observe(myanmar.clone(), at(0)), None);
+        assert_eq!(debouncer.observe(myanmar.clone(), at(600)), Some(myanmar.clone()));
+
+        // Alt+Tab through an English window and back
+        assert_eq!(debouncer.observe(english.clone(), at(700)), None);
+        assert_eq!(debouncer.observe(english.clone(), at(900)), None);
+        assert_eq!(debouncer.observe(myanmar.clone(), at(1000)), None);
+        assert_eq!(debouncer.observe(english.clone(), at(1100)), None);
+        assert_eq!(debouncer.observe(english.clone(), at(1400)), None);
+        assert_eq!(debouncer.observe(myanmar.clone(), at(1500)), None);
+        assert_eq!(debouncer.observe(myanmar.clone(), at(2500)), None);
+
+        // Staying in English long enough applies it
+        assert_eq!(debouncer.observe(english.clone(), at(3000)), None);
+        assert_eq!(debouncer.observe(english.clone(), at(3600)), Some(english));
+    }
+
+    #[test]
+    fn test_debounce_reset_applies_again() {
+        let mut debouncer = LanguageDebouncer::new(Duration::ZERO);
+        let now = Instant::now();
+        let english = InputLanguage::new(0x0409, None);
+        assert_eq!(debouncer.observe(english.clone(), now), None);
+        assert_eq!(debouncer.observe(english.clone(), now), Some(english.clone()));
+        debouncer.reset();
+        assert_eq!(debouncer.observe(english.clone(), now), None);
+        assert_eq!(debouncer.observe(english.clone(), now), Some(english));
+    }
+
+    #[test]
+    fn test_activation_message() {
+        let english = InputLanguage::new(0x0409, Some("en-US"));
+        assert_eq!(activation_message(&english, &LanguageAction::Disable, None), "KeyMagic off for en-US");
+        let unknown = InputLanguage::new(0x0455, None);
+        assert_eq!(activation_message(&unknown, &LanguageAction::Enable, None), "KeyMagic on for 0455");
+        assert_eq!(
+            activation_message(&unknown, &keyboard("myanmar3"), Some("Myanmar3")),
+            "Myanmar3 for 0455"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use keymagic_core::{ModifierState, KeyInput, KeyMagicEngine, Km2File, VirtualKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyMapping {
+    pub shifted: Option<String>,
+    pub unshifted: Option<String>,
+}
+
+/// Key mappings from VirtualKey to DOM key names with their default characters
+/// (unshifted, shifted)
+pub const KEY_MAPPINGS: &[(VirtualKey, &str, char, char)] = &[
+    // Number row
+    (VirtualKey::Oem3, "Backquote", '`', '~'),
+    (VirtualKey::Key1, "Digit1", '1', '!'),
+    (VirtualKey::Key2, "Digit2", '2', '@'),
+    (VirtualKey::Key3, "Digit3", '3', '#'),
+    (VirtualKey::Key4, "Digit4", '4', '$'),
+    (VirtualKey::Key5, "Digit5", '5', '%'),
+    (VirtualKey::Key6, "Digit6", '6', '^'),
+    (VirtualKey::Key7, "Digit7", '7', '&'),
+    (VirtualKey::Key8, "Digit8", '8', '*'),
+    (VirtualKey::Key9, "Digit9", '9', '('),
+    (VirtualKey::Key0, "Digit0", '0', ')'),
+    (VirtualKey::OemMinus, "Minus", '-', '_'),
+    (VirtualKey::OemPlus, "Equal", '=', '+'),
+
+    // Top row (QWERTY)
+    (VirtualKey::KeyQ, "KeyQ", 'q', 'Q'),
+    (VirtualKey::KeyW, "KeyW", 'w', 'W'),
+    (VirtualKey::KeyE, "KeyE", 'e', 'E'),
+    (VirtualKey::KeyR, "KeyR", 'r', 'R'),
+    (VirtualKey::KeyT, "KeyT", 't', 'T'),
+    (VirtualKey::KeyY, "KeyY", 'y', 'Y'),
+    (VirtualKey::KeyU, "KeyU", 'u', 'U'),
+    (VirtualKey::KeyI, "KeyI", 'i', 'I'),
+    (VirtualKey::KeyO, "KeyO", 'o', 'O'),
+    (VirtualKey::KeyP, "KeyP", 'p', 'P'),
+    (VirtualKey::Oem4, "BracketLeft", '[', '{'),
+    (VirtualKey::Oem6, "BracketRight", ']', '}'),
+    (VirtualKey::Oem5, "Backslash", '\\', '|'),
+
+    // Home row (ASDF)
+    (VirtualKey::KeyA, "KeyA", 'a', 'A'),
+    (VirtualKey::KeyS, "KeyS", 's', 'S'),
+    (VirtualKey::KeyD, "KeyD", 'd', 'D'),
+    (VirtualKey::KeyF, "KeyF", 'f', 'F'),
+    (VirtualKey::KeyG, "KeyG", 'g', 'G'),
+    (VirtualKey::KeyH, "KeyH", 'h', 'H'),
+    (VirtualKey::KeyJ, "KeyJ", 'j', 'J'),
+    (VirtualKey::KeyK, "KeyK", 'k', 'K'),
+    (VirtualKey::KeyL, "KeyL", 'l', 'L'),
+    (VirtualKey::Oem1, "Semicolon", ';', ':'),
+    (VirtualKey::Oem7, "Quote", '\'', '"'),
+
+    // Bottom row (ZXCV)
+    (VirtualKey::KeyZ, "KeyZ", 'z', 'Z'),
+    (VirtualKey::KeyX, "KeyX", 'x', 'X'),
+    (VirtualKey::KeyC, "KeyC", 'c', 'C'),
+    (VirtualKey::KeyV, "KeyV", 'v', 'V'),
+    (VirtualKey::KeyB, "KeyB", 'b', 'B'),
+    (VirtualKey::KeyN, "KeyN", 'n', 'N'),
+    (VirtualKey::KeyM, "KeyM", 'm', 'M'),
+    (VirtualKey::OemComma, "Comma", ',', '<'),
+    (VirtualKey::OemPeriod, "Period", '.', '>'),
+    (VirtualKey::Oem2, "Slash", '/', '?'),
+
+    // Space
+    (VirtualKey::Space, "Space", ' ', ' '),
+];
+
+/// Runs every mapped key through the engine (from an empty state) and
+/// collects the resulting composing text keyed by DOM key name
+pub fn compute_key_map(engine: &mut KeyMagicEngine) -> HashMap<String, KeyMapping> {
+    let mut keys = HashMap::new();
+
+    let mut get_key_output = |vk: VirtualKey, modifiers: ModifierState, character: Option<char>| -> Option<String> {
+        // Reset engine state before each test
+        engine.reset();
+
+        let input = KeyInput::new(vk as u16, modifiers, character);
+        match engine.process_key_test(input) {
+            Ok(output) => {
+                if output.composing_text.is_empty() {
+                    None
+                } else {
+                    Some(output.composing_text)
+                }
+            }
+            Err(_) => None,
+        }
+    };
+
+    for (vk, dom_key, unshifted_char, shifted_char) in KEY_MAPPINGS.iter() {
+        let unshifted = get_key_output(*vk, ModifierState::new(false, false, false, false), Some(*unshifted_char));
+        let shifted = get_key_output(*vk, ModifierState::new(true, false, false, false), Some(*shifted_char));
+
+        keys.insert(dom_key.to_string(), KeyMapping {
+            shifted,
+            unshifted,
+        });
+    }
+
+    keys
+}
+
+/// Letter keys typed to make up a sample for keyboards that don't declare one
+const SAMPLE_KEYS: usize = 5;
+
+/// Preview text of a keyboard: its declared `@SAMPLE`, otherwise what the
+/// first few letter keys produce. `None` when neither gives anything.
+pub fn sample_text(layout: &Km2File) -> Option<String> {
+    if let Some(sample) = layout.metadata().sample_text() {
+        return Some(sample);
+    }
+    let mut engine = KeyMagicEngine::new(layout.clone()).ok()?;
+    generate_sample_text(&mut engine)
+}
+
+/// Types the first few letter keys, each from an empty state, and joins
+/// what they produce; keys that just type their own letter are left out
+pub fn generate_sample_text(engine: &mut KeyMagicEngine) -> Option<String> {
+    let mut sample = String::new();
+
+    for (vk, _, unshifted_char, _) in KEY_MAPPINGS
+        .iter()
+        .filter(|(_, _, unshifted_char, _)| unshifted_char.is_ascii_alphabetic())
+        .take(SAMPLE_KEYS)
+    {
+        engine.reset();
+        let input = KeyInput::new(*vk as u16, ModifierState::new(false, false, false, false), Some(*unshifted_char));
+        if let Ok(output) = engine.process_key_test(input) {
+            if output.composing_text != unshifted_char.to_string() {
+                sample.push_str(&output.composing_text);
+            }
+        }
+    }
+    engine.reset();
+
+    let sample = sample.trim();
+    (!sample.is_empty()).then(|| sample.to_string())
+}
+
+/// Samples by keyboard id, kept while the keyboard's file hash is unchanged
+/// so a keyboard is only typed through again after its file changes
+#[derive(Default)]
+pub struct SampleTextCache {
+    entries: HashMap<String, (String, Option<String>)>,
+}
+
+impl SampleTextCache {
+    pub fn get(&mut self, keyboard_id: &str, hash: &str, layout: &Km2File) -> Option<String> {
+        match self.entries.get(keyboard_id) {
+            Some((cached_hash, sample)) if cached_hash == hash => sample.clone(),
+            _ => {
+                let sample = sample_text(layout);
+                self.entries.insert(keyboard_id.to_string(), (hash.to_string(), sample.clone()));
+                sample
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_declared_sample_wins() {
+        let layout = kms2km2::compile_kms("// @SAMPLE = \"မင်္ဂလာပါ\"\n\"q\" => \"ဆ\"").unwrap();
+        assert_eq!(sample_text(&layout).as_deref(), Some("မင်္ဂလာပါ"));
+    }
+
+    #[test]
+    fn test_sample_generated_from_letter_keys() {
+        // q w e r t are the first letter keys; t types itself and is left out
+        let layout = kms2km2::compile_kms(
+            "\"q\" => \"ဆ\"\n\"w\" => \"တ\"\n\"e\" => \"န\"\n\"r\" => \"မ\"\n\"y\" => \"ပ\"",
+        )
+        .unwrap();
+        assert_eq!(sample_text(&layout).as_deref(), Some("ဆတနမ"));
+    }
+
+    #[test]
+    fn test_no_sample_when_letters_are_unmapped() {
+        let layout = kms2km2::compile_kms("\"1\" => \"၁\"").unwrap();
+        assert_eq!(sample_text(&layout), None);
+    }
+
+    #[test]
+    fn test_cache_follows_file_hash() {
+        let first = kms2km2::compile_kms("\"q\" => \"ဆ\"").unwrap();
+        let second = kms2km2::compile_kms("\"q\" => \"တ\"").unwrap();
+        let mut cache = SampleTextCache::default();
+
+        assert_eq!(cache.get("shan", "aa", &first).as_deref(), Some("ဆ"));
+        // Same hash, same file: the first result is reused
+        assert_eq!(cache.get("shan", "aa", &second).as_deref(), Some("ဆ"));
+        assert_eq!(cache.get("shan", "bb", &second).as_deref(), Some("တ"));
+        assert_eq!(cache.get("mon", "aa", &second).as_deref(), Some("တ"));
+    }
+}
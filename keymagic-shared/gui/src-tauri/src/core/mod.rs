@@ -1,3 +1,51 @@
+pub mod accessibility;
+pub mod bundled_keyboards;
+pub mod embedded_keyboards;
+pub mod fallback_controls;
+pub mod file_hashes;
+pub mod hotkey_conflicts;
 pub mod keyboard_manager;
+pub mod keyboard_metadata;
+pub mod keyboard_activation;
+pub mod layout_preview;
+pub mod keyboard_diff;
+pub mod keyboard_ids;
+pub mod keyboard_options;
+pub mod keyboard_query;
+pub mod keyboard_status;
+pub mod keyboard_store;
+pub mod keyboard_sync;
+pub mod key_processing;
+pub mod kms_playground;
+pub mod language_activation;
+pub mod notification;
+pub mod permissions;
+pub mod preview_font;
+pub mod preview_session;
+pub mod temporary_keyboard;
 
-pub use keyboard_manager::{KeyboardInfo, KeyboardManager};
\ No newline at end of file
+pub use bundled_keyboards::BundledKeyboard;
+pub use embedded_keyboards::{KeyboardStorage, KeyboardTooLarge};
+pub use fallback_controls::{FallbackAction, FallbackOutcome, FallbackView, FrontendWatchdog};
+pub use hotkey_conflicts::{HotkeyConflict, HotkeyConflictInfo, HotkeysInUse};
+pub use keyboard_activation::{ActivationFailure, KeyboardActivationError};
+pub use keyboard_manager::{
+    ImportedKeyboard, KeyboardInfo, KeyboardManager, KeyboardNotFound, LanguageActivated, PassthroughKeysInfo, ProfileApplied,
+    ProfileInfo, ProfileNotFound, RuleGroupInfo, SwitchAnnouncementInfo,
+};
+pub use layout_preview::KeyMapping;
+pub use keyboard_diff::KeyboardDiff;
+pub use keyboard_metadata::{KeyboardFileReadOnly, MetadataChanges};
+pub use keyboard_options::{InvalidOptionValue, KeyboardOptions};
+pub use keyboard_query::{KeyboardFilter, KeyboardIcon, KeyboardPage, KeyboardSort};
+pub use keyboard_status::{KeyboardStatus, KeyboardStatusDetail};
+pub use keyboard_store::{RepairPolicy, RepairReport};
+pub use keyboard_sync::{KeyboardField, KeyboardsChanged};
+pub use keymagic_core::key_event::{self, KeyEventDto, KeyEventError};
+pub use key_processing::HotkeyActivation;
+pub use kms_playground::{SnippetLimitExceeded, SnippetResult};
+pub use language_activation::{InputLanguage, InvalidLanguageKey};
+pub use notification::NotificationManager;
+pub use preview_font::PreviewFont;
+pub use preview_session::{PreviewInput, PreviewOutput, PreviewSessionNotFound, PreviewSessions, TooManyPreviewSessions};
+pub use temporary_keyboard::TemporaryKeyboardInfo;
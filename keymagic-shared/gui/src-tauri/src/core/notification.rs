@@ -0,0 +1,237 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::accessibility::keyboard_switch_announcement;
+
+type HudSink = Box<dyn Fn(&str) + Send + Sync>;
+
+/// A switch notification stays up at least this long before a newer one
+/// replaces it
+pub const SWITCH_MIN_DISPLAY: Duration = Duration::from_millis(300);
+
+/// Coalesces keyboard switch notifications
+///
+/// Switching quickly, e.g. holding a cycle hotkey, would otherwise pile up
+/// one notification per switch. The first is shown at once; one arriving
+/// while the shown one is younger than the floor waits, replacing any that
+/// was already waiting, and is shown once the floor is over. The last
+/// switch is always shown.
+#[derive(Debug)]
+pub struct SwitchCoalescer {
+    min_display: Duration,
+    shown_at: Option<Instant>,
+    pending: Option<String>,
+}
+
+impl Default for SwitchCoalescer {
+    fn default() -> Self {
+        Self::new(SWITCH_MIN_DISPLAY)
+    }
+}
+
+impl SwitchCoalescer {
+    pub fn new(min_display: Duration) -> Self {
+        Self { min_display, shown_at: None, pending: None }
+    }
+
+    /// Takes a notification at `now`; returns it if it is to be shown now,
+    /// otherwise it waits for [`take_due`](Self::take_due)
+    pub fn submit(&mut self, message: String, now: Instant) -> Option<String> {
+        if self.pending.is_none() && self.floor_over(now) {
+            self.shown_at = Some(now);
+            return Some(message);
+        }
+        self.pending = Some(message);
+        None
+    }
+
+    /// The waiting notification, once the floor of the shown one is over
+    pub fn take_due(&mut self, now: Instant) -> Option<String> {
+        if !self.floor_over(now) {
+            return None;
+        }
+        let message = self.pending.take()?;
+        self.shown_at = Some(now);
+        Some(message)
+    }
+
+    /// When the waiting notification is due; `None` if none waits
+    pub fn due_at(&self) -> Option<Instant> {
+        self.pending.as_ref()?;
+        Some(self.shown_at.map_or_else(Instant::now, |at| at + self.min_display))
+    }
+
+    fn floor_over(&self, now: Instant) -> bool {
+        self.shown_at.is_none_or(|at| now.saturating_duration_since(at) >= self.min_display)
+    }
+}
+
+#[derive(Default)]
+struct Sinks {
+    hud: Mutex<Option<HudSink>>,
+    /// Speaks messages through the platform screen reader
+    announcer: Mutex<Option<HudSink>>,
+    switches: Mutex<SwitchCoalescer>,
+}
+
+/// Routes short status messages to the HUD of the host
+///
+/// The sinks are installed once the UI is up; messages sent before that are
+/// only logged. Keyboard switches go through a [`SwitchCoalescer`]; a
+/// switch held back is delivered from a short-lived thread.
+#[derive(Default)]
+pub struct NotificationManager {
+    sinks: Arc<Sinks>,
+}
+
+impl NotificationManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A manager whose switch notifications stay up at least `min_display`
+    pub fn with_switch_floor(min_display: Duration) -> Self {
+        let manager = Self::default();
+        *manager.sinks.switches.lock().unwrap() = SwitchCoalescer::new(min_display);
+        manager
+    }
+
+    pub fn set_hud_sink(&self, sink: impl Fn(&str) + Send + Sync + 'static) {
+        *self.sinks.hud.lock().unwrap() = Some(Box::new(sink));
+    }
+
+    pub fn show_hud(&self, message: &str) {
+        log::info!("HUD: {}", message);
+        if let Some(sink) = self.sinks.hud.lock().unwrap().as_ref() {
+            sink(message);
+        }
+    }
+
+    pub fn set_announcer(&self, announcer: impl Fn(&str) + Send + Sync + 'static) {
+        *self.sinks.announcer.lock().unwrap() = Some(Box::new(announcer));
+    }
+
+    /// Announces a keyboard switch to the screen reader
+    pub fn show_keyboard_switch(&self, keyboard_name: &str) {
+        let message = keyboard_switch_announcement(keyboard_name);
+        let mut switches = self.sinks.switches.lock().unwrap();
+        let waiting = switches.due_at().is_some();
+        match switches.submit(message, Instant::now()) {
+            Some(message) => {
+                drop(switches);
+                announce(&self.sinks, &message);
+            }
+            // The first one held back starts the thread that delivers it;
+            // later ones replace it in place
+            None if !waiting => {
+                let sinks = self.sinks.clone();
+                std::thread::spawn(move || deliver_pending(&sinks));
+            }
+            None => log::debug!("Keyboard switch notification coalesced"),
+        }
+    }
+}
+
+fn announce(sinks: &Sinks, message: &str) {
+    log::debug!("Announcing: {}", message);
+    if let Some(announcer) = sinks.announcer.lock().unwrap().as_ref() {
+        announcer(message);
+    }
+}
+
+/// Waits for the held back switch notification to be due and delivers it
+fn deliver_pending(sinks: &Sinks) {
+    loop {
+        let mut switches = sinks.switches.lock().unwrap();
+        let Some(due_at) = switches.due_at() else {
+            return;
+        };
+        let now = Instant::now();
+        if let Some(message) = switches.take_due(now) {
+            drop(switches);
+            announce(sinks, &message);
+            return;
+        }
+        drop(switches);
+        std::thread::sleep(due_at.saturating_duration_since(now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLOOR: Duration = Duration::from_millis(300);
+
+    #[test]
+    fn test_first_switch_is_shown_at_once() {
+        let start = Instant::now();
+        let mut coalescer = SwitchCoalescer::new(FLOOR);
+        assert_eq!(coalescer.submit("a".to_string(), start), Some("a".to_string()));
+        assert_eq!(coalescer.due_at(), None);
+
+        // After the floor the next one is shown at once too
+        let later = start + FLOOR;
+        assert_eq!(coalescer.submit("b".to_string(), later), Some("b".to_string()));
+    }
+
+    #[test]
+    fn test_burst_shows_first_and_last() {
+        let start = Instant::now();
+        let mut coalescer = SwitchCoalescer::new(FLOOR);
+        let mut shown = Vec::new();
+        // 20 switches in two seconds, with a timer checking every 10ms
+        for tick in 0..=250u64 {
+            let now = start + Duration::from_millis(tick * 10);
+            shown.extend(coalescer.take_due(now).map(|message| (now, message)));
+            if tick % 10 == 0 && tick < 200 {
+                shown.extend(coalescer.submit(format!("k{}", tick / 10), now).map(|message| (now, message)));
+            }
+        }
+
+        assert_eq!(shown.first().map(|(_, message)| message.as_str()), Some("k0"));
+        assert_eq!(shown.last().map(|(_, message)| message.as_str()), Some("k19"));
+        assert!(shown.windows(2).all(|pair| pair[1].0 - pair[0].0 >= FLOOR), "{:?}", shown);
+        assert!(shown.len() < 20);
+        assert_eq!(coalescer.due_at(), None);
+    }
+
+    #[test]
+    fn test_pending_switch_is_replaced_and_waits_for_the_floor() {
+        let start = Instant::now();
+        let mut coalescer = SwitchCoalescer::new(FLOOR);
+        coalescer.submit("a".to_string(), start);
+
+        assert_eq!(coalescer.submit("b".to_string(), start + Duration::from_millis(50)), None);
+        assert_eq!(coalescer.submit("c".to_string(), start + Duration::from_millis(100)), None);
+        assert_eq!(coalescer.due_at(), Some(start + FLOOR));
+        assert_eq!(coalescer.take_due(start + Duration::from_millis(299)), None);
+        assert_eq!(coalescer.take_due(start + FLOOR), Some("c".to_string()));
+
+        // The floor starts again with the one just shown
+        let shown_at = start + FLOOR;
+        assert_eq!(coalescer.submit("d".to_string(), shown_at + Duration::from_millis(100)), None);
+        // Once one waits, the next waits too even past the floor
+        assert_eq!(coalescer.submit("e".to_string(), shown_at + FLOOR), None);
+        assert_eq!(coalescer.take_due(shown_at + FLOOR), Some("e".to_string()));
+    }
+
+    #[test]
+    fn test_held_back_switch_is_delivered_later() {
+        let manager = NotificationManager::with_switch_floor(Duration::from_millis(20));
+        let spoken = Arc::new(Mutex::new(Vec::new()));
+        let sink = spoken.clone();
+        manager.set_announcer(move |message| sink.lock().unwrap().push(message.to_string()));
+
+        for name in ["one", "two", "three"] {
+            manager.show_keyboard_switch(name);
+        }
+        assert_eq!(*spoken.lock().unwrap(), vec!["Keyboard: one".to_string()]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while spoken.lock().unwrap().len() < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(*spoken.lock().unwrap(), vec!["Keyboard: one".to_string(), "Keyboard: three".to_string()]);
+    }
+}
@@ -0,0 +1,203 @@
+//! macOS privacy permissions and which features need them
+//!
+//! macOS asks the user before an app may control other apps (Accessibility),
+//! read keys typed elsewhere (Input Monitoring) or read other windows
+//! (Screen Recording). None of KeyMagic's features need them today: typing
+//! goes through the input method, which is handed its keys, app lists come
+//! from NSWorkspace, and hotkeys are registered with the system. So nothing
+//! is asked up front. A feature that needs a permission lists it in
+//! [`Feature::required_permissions`] and asks through [`ensure`] when it is
+//! first used; the settings page shows each permission's state.
+
+// Only the macOS commands use this; it builds everywhere for its tests
+#![cfg_attr(not(target_os = "macos"), allow(dead_code))]
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    Accessibility,
+    InputMonitoring,
+    ScreenRecording,
+}
+
+pub const ALL_PERMISSIONS: [Permission; 3] = [Permission::Accessibility, Permission::InputMonitoring, Permission::ScreenRecording];
+
+impl Permission {
+    /// The permission's pane in System Settings
+    pub fn settings_url(self) -> &'static str {
+        match self {
+            Permission::Accessibility => "x-apple.systempreferences:com.apple.preference.security?Privacy_Accessibility",
+            Permission::InputMonitoring => "x-apple.systempreferences:com.apple.preference.security?Privacy_ListenEvent",
+            Permission::ScreenRecording => "x-apple.systempreferences:com.apple.preference.security?Privacy_ScreenCapture",
+        }
+    }
+
+    /// What the permission would let KeyMagic do, shown next to its state
+    pub fn rationale(self) -> &'static str {
+        match self {
+            Permission::Accessibility => "Lets an app control other apps. KeyMagic types through its input method and does not need it.",
+            Permission::InputMonitoring => "Lets an app see keys typed in other apps. The input method is given its keys, so KeyMagic does not need it.",
+            Permission::ScreenRecording => "Lets an app read other windows. KeyMagic lists apps without reading their windows and does not need it.",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PermissionState {
+    Granted,
+    Denied,
+    /// Not asked yet, or macOS cannot tell it apart from denied
+    NotDetermined,
+    /// No feature needs it and it is not granted
+    NotNeeded,
+}
+
+/// Features of the GUI on macOS, for deciding which permissions to ask for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Typing through the input method
+    Typing,
+    /// Keyboard switching hotkeys, registered with the system
+    HotkeySwitching,
+    /// Listing running apps for per-app settings, through NSWorkspace
+    AppPicker,
+    /// Announcing keyboard switches to VoiceOver
+    SwitchAnnouncements,
+}
+
+pub const FEATURES: [Feature; 4] = [Feature::Typing, Feature::HotkeySwitching, Feature::AppPicker, Feature::SwitchAnnouncements];
+
+impl Feature {
+    pub fn required_permissions(self) -> &'static [Permission] {
+        match self {
+            Feature::Typing | Feature::HotkeySwitching | Feature::AppPicker | Feature::SwitchAnnouncements => &[],
+        }
+    }
+}
+
+/// Permissions at least one of `features` needs
+pub fn required_permissions(features: &[Feature]) -> Vec<Permission> {
+    let mut required: Vec<Permission> = Vec::new();
+    for permission in features.iter().flat_map(|feature| feature.required_permissions()) {
+        if !required.contains(permission) {
+            required.push(*permission);
+        }
+    }
+    required
+}
+
+/// A row of the permissions panel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PermissionStatus {
+    pub permission: Permission,
+    pub state: PermissionState,
+    pub needed: bool,
+    pub rationale: &'static str,
+    pub settings_url: &'static str,
+}
+
+/// State of `permission` given the permissions features need and what macOS
+/// reports. A grant nothing needs is still shown, so it can be revoked.
+pub fn status(permission: Permission, required: &[Permission], query: impl FnOnce(Permission) -> PermissionState) -> PermissionStatus {
+    let needed = required.contains(&permission);
+    let state = match query(permission) {
+        PermissionState::Granted => PermissionState::Granted,
+        _ if !needed => PermissionState::NotNeeded,
+        state => state,
+    };
+    PermissionStatus {
+        permission,
+        state,
+        needed,
+        rationale: permission.rationale(),
+        settings_url: permission.settings_url(),
+    }
+}
+
+/// Asks for `permission` just before a feature uses it. The system prompt
+/// only appears for a permission a feature needs and the user was not asked
+/// about; macOS does not prompt again after a denial, so that is left to the
+/// settings link.
+pub fn ensure(
+    permission: Permission,
+    required: &[Permission],
+    query: impl Fn(Permission) -> PermissionState,
+    request: impl FnOnce(Permission),
+) -> PermissionState {
+    if !required.contains(&permission) {
+        return PermissionState::NotNeeded;
+    }
+    match query(permission) {
+        PermissionState::NotDetermined => {
+            request(permission);
+            query(permission)
+        }
+        state => state,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_no_feature_needs_a_permission() {
+        assert_eq!(required_permissions(&FEATURES), Vec::<Permission>::new());
+        for permission in ALL_PERMISSIONS {
+            let status = status(permission, &required_permissions(&FEATURES), |_| PermissionState::NotDetermined);
+            assert_eq!(status.state, PermissionState::NotNeeded);
+            assert!(!status.needed);
+        }
+    }
+
+    #[test]
+    fn test_status_of_needed_and_leftover_permissions() {
+        let required = [Permission::Accessibility];
+        assert_eq!(status(Permission::Accessibility, &required, |_| PermissionState::Denied).state, PermissionState::Denied);
+        assert_eq!(
+            status(Permission::Accessibility, &required, |_| PermissionState::NotDetermined).state,
+            PermissionState::NotDetermined
+        );
+        // Granted to an older version, so the panel offers to revoke it
+        let leftover = status(Permission::ScreenRecording, &required, |_| PermissionState::Granted);
+        assert_eq!(leftover.state, PermissionState::Granted);
+        assert!(!leftover.needed);
+        assert_eq!(status(Permission::ScreenRecording, &required, |_| PermissionState::Denied).state, PermissionState::NotNeeded);
+    }
+
+    #[test]
+    fn test_ensure_prompts_only_when_needed_and_undetermined() {
+        let prompts = Cell::new(0);
+        let request = |_| prompts.set(prompts.get() + 1);
+
+        assert_eq!(ensure(Permission::InputMonitoring, &[], |_| PermissionState::NotDetermined, request), PermissionState::NotNeeded);
+        assert_eq!(prompts.get(), 0);
+
+        let required = [Permission::InputMonitoring];
+        assert_eq!(ensure(Permission::InputMonitoring, &required, |_| PermissionState::Denied, request), PermissionState::Denied);
+        assert_eq!(ensure(Permission::InputMonitoring, &required, |_| PermissionState::Granted, request), PermissionState::Granted);
+        assert_eq!(prompts.get(), 0);
+
+        let granted = Cell::new(false);
+        let query = |_| if granted.get() { PermissionState::Granted } else { PermissionState::NotDetermined };
+        let state = ensure(Permission::InputMonitoring, &required, query, |_| {
+            prompts.set(prompts.get() + 1);
+            granted.set(true);
+        });
+        assert_eq!(state, PermissionState::Granted);
+        assert_eq!(prompts.get(), 1);
+    }
+
+    #[test]
+    fn test_serialized_names() {
+        assert_eq!(serde_json::to_value(PermissionState::NotDetermined).unwrap(), "not-determined");
+        assert_eq!(serde_json::to_value(PermissionState::NotNeeded).unwrap(), "not-needed");
+        assert_eq!(serde_json::from_value::<Permission>(serde_json::json!("input-monitoring")).unwrap(), Permission::InputMonitoring);
+        let status = status(Permission::Accessibility, &[], |_| PermissionState::Granted);
+        assert!(serde_json::to_value(&status).unwrap()["settings_url"].as_str().unwrap().ends_with("Privacy_Accessibility"));
+    }
+}
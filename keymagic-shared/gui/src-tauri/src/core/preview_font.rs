@@ -0,0 +1,133 @@
+//! Font used to preview what a keyboard types
+//!
+//! A keyboard may declare the font its output is meant for (`@FONTFAMILY`).
+//! Myanmar text drawn in the system default font often comes out as boxes,
+//! so previews ask for the declared font, then Myanmar Text, then Segoe UI.
+//! When the declared font is not installed the preview reports which font it
+//! fell back to, so the UI can suggest installing the declared one. KM2 files
+//! cannot embed a font, so there is nothing to register from the file itself.
+
+use serde::Serialize;
+
+/// Fonts tried after the declared one, in order
+pub const FALLBACK_FONTS: [&str; 2] = ["Myanmar Text", "Segoe UI"];
+
+/// Font a preview renders with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PreviewFont {
+    /// Family the keyboard declares
+    pub declared: Option<String>,
+    /// First family of the chain that is installed; `None` when none is, or
+    /// when the platform cannot list its fonts
+    pub family: Option<String>,
+    /// Declared family that is not installed, for an "install font X" hint
+    pub missing: Option<String>,
+    /// The whole chain as a CSS `font-family` value
+    pub css_font_family: String,
+}
+
+/// Family names compared without case or spacing, as font lists differ in both
+fn normalize(family: &str) -> String {
+    family.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect()
+}
+
+/// Declared family followed by the fallbacks, without blanks or repeats
+pub fn font_chain(declared: Option<&str>) -> Vec<String> {
+    let mut chain: Vec<String> = Vec::new();
+    for family in declared.into_iter().chain(FALLBACK_FONTS) {
+        let family = family.trim();
+        if !family.is_empty() && !chain.iter().any(|known| normalize(known) == normalize(family)) {
+            chain.push(family.to_string());
+        }
+    }
+    chain
+}
+
+/// Quotes each family for CSS and ends with the generic family
+pub fn css_font_family(chain: &[String]) -> String {
+    chain
+        .iter()
+        .map(|family| format!("\"{}\"", family.replace('\\', "\\\\").replace('"', "\\\"")))
+        .chain(std::iter::once("sans-serif".to_string()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Resolves the chain against the installed families
+///
+/// With `installed` unknown the declared font is assumed present: the
+/// webview still falls back on its own, there is just nothing to report.
+pub fn resolve_preview_font(declared: Option<&str>, installed: Option<&[String]>) -> PreviewFont {
+    let chain = font_chain(declared);
+    let declared = declared.map(str::trim).filter(|family| !family.is_empty()).map(str::to_string);
+    let css_font_family = css_font_family(&chain);
+
+    let Some(installed) = installed else {
+        return PreviewFont { family: declared.clone(), declared, missing: None, css_font_family };
+    };
+    let is_installed = |family: &str| installed.iter().any(|known| normalize(known) == normalize(family));
+    let family = chain.iter().find(|family| is_installed(family)).cloned();
+    let missing = declared.clone().filter(|declared| !is_installed(declared));
+    PreviewFont { declared, family, missing, css_font_family }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn installed(families: &[&str]) -> Vec<String> {
+        families.iter().map(|family| family.to_string()).collect()
+    }
+
+    #[test]
+    fn test_chain_starts_with_the_declared_font() {
+        assert_eq!(font_chain(Some("Pyidaungsu")), vec!["Pyidaungsu", "Myanmar Text", "Segoe UI"]);
+        assert_eq!(font_chain(Some(" myanmar  text ")), vec!["myanmar  text", "Segoe UI"]);
+        assert_eq!(font_chain(Some("")), vec!["Myanmar Text", "Segoe UI"]);
+        assert_eq!(font_chain(None), vec!["Myanmar Text", "Segoe UI"]);
+    }
+
+    #[test]
+    fn test_css_quotes_every_family() {
+        let chain = font_chain(Some("Zawgyi \"One\""));
+        assert_eq!(
+            css_font_family(&chain),
+            r#""Zawgyi \"One\"", "Myanmar Text", "Segoe UI", sans-serif"#
+        );
+    }
+
+    #[test]
+    fn test_installed_declared_font_is_used() {
+        let font = resolve_preview_font(Some("Pyidaungsu"), Some(&installed(&["Segoe UI", "pyidaungsu"])));
+        assert_eq!(font.family.as_deref(), Some("Pyidaungsu"));
+        assert_eq!(font.missing, None);
+    }
+
+    #[test]
+    fn test_missing_declared_font_falls_back_in_order() {
+        let font = resolve_preview_font(Some("Pyidaungsu"), Some(&installed(&["Segoe UI", "Myanmar Text"])));
+        assert_eq!(font.family.as_deref(), Some("Myanmar Text"));
+        assert_eq!(font.missing.as_deref(), Some("Pyidaungsu"));
+
+        let font = resolve_preview_font(Some("Pyidaungsu"), Some(&installed(&["Segoe UI"])));
+        assert_eq!(font.family.as_deref(), Some("Segoe UI"));
+
+        let font = resolve_preview_font(Some("Pyidaungsu"), Some(&installed(&["Noto Sans Myanmar"])));
+        assert_eq!(font.family, None);
+        assert_eq!(font.missing.as_deref(), Some("Pyidaungsu"));
+    }
+
+    #[test]
+    fn test_nothing_missing_without_a_declared_font() {
+        let font = resolve_preview_font(None, Some(&installed(&["Segoe UI"])));
+        assert_eq!((font.declared, font.family.as_deref(), font.missing), (None, Some("Segoe UI"), None));
+    }
+
+    #[test]
+    fn test_unknown_installed_fonts_trust_the_declared_font() {
+        let font = resolve_preview_font(Some("Pyidaungsu"), None);
+        assert_eq!(font.family.as_deref(), Some("Pyidaungsu"));
+        assert_eq!(font.missing, None);
+        assert_eq!(font.css_font_family, r#""Pyidaungsu", "Myanmar Text", "Segoe UI", sans-serif"#);
+    }
+}
@@ -0,0 +1,341 @@
+//! Streaming preview sessions for the keyboard test area
+//!
+//! A command per keystroke costs a round trip each, which makes fast typing
+//! in the preview lag and lets results arrive out of order. A session
+//! instead owns a dedicated engine and a worker thread: keys are queued to
+//! it in batches (with [`PREVIEW_KEYS_EVENT`] or a command) and processed
+//! strictly in the order queued, and every output is streamed back with a
+//! sequence number that grows by one per key, so the UI can drop anything
+//! older than what it already shows.
+//!
+//! A session ends when `end` is called or nothing was queued for
+//! [`IDLE_TIMEOUT`]; keys queued before it ended are still processed.
+
+use keymagic_core::{ActionType, SharedEngine};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::core::key_event::KeyEventDto;
+
+/// Event the UI sends [`PreviewInput`] with
+pub const PREVIEW_KEYS_EVENT: &str = "preview_keys";
+/// Sessions end after this long without keys
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Sessions open at once; the test area needs one per window
+pub const MAX_SESSIONS: usize = 8;
+
+/// Error for a session that ended or never existed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewSessionNotFound(pub String);
+
+impl std::fmt::Display for PreviewSessionNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Preview session {} has ended", self.0)
+    }
+}
+
+impl std::error::Error for PreviewSessionNotFound {}
+
+/// Error for a session started while [`MAX_SESSIONS`] are open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TooManyPreviewSessions;
+
+impl std::fmt::Display for TooManyPreviewSessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Too many preview sessions are open (at most {})", MAX_SESSIONS)
+    }
+}
+
+impl std::error::Error for TooManyPreviewSessions {}
+
+/// Keys for a session, as sent with [`PREVIEW_KEYS_EVENT`]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewInput {
+    pub session_id: String,
+    pub keys: Vec<KeyEventDto>,
+}
+
+/// What one key did in a session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewOutput {
+    pub session_id: String,
+    /// 1 for the first key of the session, one more for each key after
+    pub seq: u64,
+    /// The key in compact text form
+    pub key: String,
+    pub processed: bool,
+    pub deleted: usize,
+    pub inserted: String,
+    pub composing_text: String,
+    /// Why the key could not be processed; the engine is unchanged then
+    pub error: Option<String>,
+}
+
+/// Receives a session's outputs on its worker thread
+pub type PreviewSink = Box<dyn Fn(PreviewOutput) + Send>;
+
+#[derive(Default)]
+struct Registry {
+    sessions: HashMap<String, mpsc::Sender<Vec<KeyEventDto>>>,
+    started: u64,
+}
+
+/// The open preview sessions
+pub struct PreviewSessions {
+    registry: Arc<Mutex<Registry>>,
+    idle_timeout: Duration,
+}
+
+impl Default for PreviewSessions {
+    fn default() -> Self {
+        Self::with_idle_timeout(IDLE_TIMEOUT)
+    }
+}
+
+impl PreviewSessions {
+    pub fn with_idle_timeout(idle_timeout: Duration) -> Self {
+        Self { registry: Arc::default(), idle_timeout }
+    }
+
+    /// Starts a session typing into `engine` and returns its id
+    pub fn start(&self, engine: SharedEngine, sink: PreviewSink) -> Result<String, TooManyPreviewSessions> {
+        let mut registry = self.registry.lock().unwrap();
+        if registry.sessions.len() >= MAX_SESSIONS {
+            return Err(TooManyPreviewSessions);
+        }
+        registry.started += 1;
+        let session_id = format!("preview-{}", registry.started);
+        let (sender, receiver) = mpsc::channel();
+        registry.sessions.insert(session_id.clone(), sender);
+
+        let worker = Worker {
+            session_id: session_id.clone(),
+            engine,
+            sink,
+            seq: 0,
+        };
+        let registry = Arc::downgrade(&self.registry);
+        let idle_timeout = self.idle_timeout;
+        std::thread::spawn(move || worker.run(receiver, registry, idle_timeout));
+        Ok(session_id)
+    }
+
+    /// Queues keys behind those queued before
+    pub fn send(&self, session_id: &str, keys: Vec<KeyEventDto>) -> Result<(), PreviewSessionNotFound> {
+        let registry = self.registry.lock().unwrap();
+        registry
+            .sessions
+            .get(session_id)
+            .and_then(|sender| sender.send(keys).ok())
+            .ok_or_else(|| PreviewSessionNotFound(session_id.to_string()))
+    }
+
+    /// Ends a session once its queued keys are processed; false if it had
+    /// ended already
+    pub fn end(&self, session_id: &str) -> bool {
+        self.registry.lock().unwrap().sessions.remove(session_id).is_some()
+    }
+
+    pub fn is_open(&self, session_id: &str) -> bool {
+        self.registry.lock().unwrap().sessions.contains_key(session_id)
+    }
+
+    pub fn open_count(&self) -> usize {
+        self.registry.lock().unwrap().sessions.len()
+    }
+}
+
+struct Worker {
+    session_id: String,
+    engine: SharedEngine,
+    sink: PreviewSink,
+    seq: u64,
+}
+
+impl Worker {
+    fn run(mut self, receiver: mpsc::Receiver<Vec<KeyEventDto>>, registry: std::sync::Weak<Mutex<Registry>>, idle_timeout: Duration) {
+        loop {
+            match receiver.recv_timeout(idle_timeout) {
+                Ok(keys) => keys.iter().for_each(|key| self.process(key)),
+                Err(RecvTimeoutError::Timeout) => {
+                    // Once out of the registry nothing more can be queued;
+                    // keys that made it in before are still typed
+                    if let Some(registry) = registry.upgrade() {
+                        registry.lock().unwrap().sessions.remove(&self.session_id);
+                    }
+                    receiver.try_iter().for_each(|keys| keys.iter().for_each(|key| self.process(key)));
+                    log::debug!("Preview session {} expired", self.session_id);
+                    break;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    fn process(&mut self, key: &KeyEventDto) {
+        self.seq += 1;
+        let mut output = PreviewOutput {
+            session_id: self.session_id.clone(),
+            seq: self.seq,
+            key: key.to_string(),
+            processed: false,
+            deleted: 0,
+            inserted: String::new(),
+            composing_text: String::new(),
+            error: None,
+        };
+        match key.to_key_input().map_err(anyhow::Error::from).and_then(|input| self.engine.process_key(input).map_err(Into::into)) {
+            Ok(result) => {
+                output.processed = result.is_processed;
+                output.deleted = result.delete_chars;
+                output.inserted = match result.action {
+                    ActionType::Insert(text) | ActionType::BackspaceDeleteAndInsert(_, text) => text,
+                    _ => String::new(),
+                };
+                output.composing_text = result.composing_text;
+            }
+            Err(e) => {
+                output.composing_text = self.engine.composing_text();
+                output.error = Some(format!("{:#}", e));
+            }
+        }
+        (self.sink)(output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn engine() -> SharedEngine {
+        let layout = kms2km2::compile_kms("\"k\" => \"က\"\n\"ကa\" => \"ကာ\"\n").unwrap();
+        SharedEngine::from_keyboard(layout).unwrap()
+    }
+
+    fn collector() -> (PreviewSink, Arc<Mutex<Vec<PreviewOutput>>>) {
+        let outputs = Arc::new(Mutex::new(Vec::new()));
+        let sink_outputs = outputs.clone();
+        (Box::new(move |output| sink_outputs.lock().unwrap().push(output)), outputs)
+    }
+
+    fn wait_for(mut done: impl FnMut() -> bool) {
+        let started = Instant::now();
+        while !done() {
+            assert!(started.elapsed() < Duration::from_secs(5), "timed out");
+            std::thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    fn keys(text: &str) -> Vec<KeyEventDto> {
+        text.chars().map(KeyEventDto::typed).collect()
+    }
+
+    #[test]
+    fn test_bursty_input_is_processed_in_order() {
+        let sessions = Arc::new(PreviewSessions::default());
+        let (sink, outputs) = collector();
+        let id = sessions.start(engine(), sink).unwrap();
+
+        // Batches of every size, queued as fast as possible
+        let mut expected = String::new();
+        for round in 0..200 {
+            let batch: String = "kaxk".chars().cycle().skip(round % 4).take(round % 7).collect();
+            expected.push_str(&batch);
+            sessions.send(&id, keys(&batch)).unwrap();
+        }
+        wait_for(|| outputs.lock().unwrap().len() == expected.chars().count());
+
+        let outputs = outputs.lock().unwrap();
+        let seqs: Vec<u64> = outputs.iter().map(|output| output.seq).collect();
+        assert_eq!(seqs, (1..=expected.chars().count() as u64).collect::<Vec<_>>());
+        let typed: String = outputs.iter().map(|output| output.key.as_str()).collect();
+        assert_eq!(typed, expected);
+        assert!(outputs.iter().all(|output| output.session_id == id && output.error.is_none()));
+
+        // The same keys typed into a fresh engine end the same way
+        let reference = engine();
+        for key in keys(&expected) {
+            reference.process_key(key.to_key_input().unwrap()).unwrap();
+        }
+        assert_eq!(outputs.last().unwrap().composing_text, reference.composing_text());
+    }
+
+    #[test]
+    fn test_sessions_are_separate() {
+        let sessions = PreviewSessions::default();
+        let (first_sink, first) = collector();
+        let (second_sink, second) = collector();
+        let a = sessions.start(engine(), first_sink).unwrap();
+        let b = sessions.start(engine(), second_sink).unwrap();
+        assert_ne!(a, b);
+
+        sessions.send(&a, keys("ka")).unwrap();
+        sessions.send(&b, keys("k")).unwrap();
+        wait_for(|| first.lock().unwrap().len() == 2 && second.lock().unwrap().len() == 1);
+        assert_eq!(first.lock().unwrap()[1].composing_text, "ကာ");
+        assert_eq!(second.lock().unwrap()[0].composing_text, "က");
+        assert_eq!(second.lock().unwrap()[0].seq, 1);
+    }
+
+    #[test]
+    fn test_end_processes_queued_keys_then_refuses_more() {
+        let sessions = PreviewSessions::default();
+        let (sink, outputs) = collector();
+        let id = sessions.start(engine(), sink).unwrap();
+        sessions.send(&id, keys("kakaka")).unwrap();
+        assert!(sessions.end(&id));
+        assert!(!sessions.end(&id));
+        assert_eq!(sessions.send(&id, keys("k")), Err(PreviewSessionNotFound(id.clone())));
+        wait_for(|| outputs.lock().unwrap().len() == 6);
+    }
+
+    #[test]
+    fn test_idle_sessions_expire() {
+        let sessions = PreviewSessions::with_idle_timeout(Duration::from_millis(200));
+        let (sink, outputs) = collector();
+        let id = sessions.start(engine(), sink).unwrap();
+
+        // Activity keeps it open
+        for _ in 0..4 {
+            std::thread::sleep(Duration::from_millis(50));
+            sessions.send(&id, keys("k")).unwrap();
+        }
+        assert!(sessions.is_open(&id));
+
+        wait_for(|| !sessions.is_open(&id));
+        assert_eq!(outputs.lock().unwrap().len(), 4);
+        assert!(sessions.send(&id, keys("k")).is_err());
+        assert_eq!(sessions.open_count(), 0);
+    }
+
+    #[test]
+    fn test_session_limit() {
+        let sessions = PreviewSessions::default();
+        let ids: Vec<String> = (0..MAX_SESSIONS).map(|_| sessions.start(engine(), collector().0).unwrap()).collect();
+        assert_eq!(sessions.start(engine(), collector().0), Err(TooManyPreviewSessions));
+        sessions.end(&ids[0]);
+        assert!(sessions.start(engine(), collector().0).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_keys_are_reported_in_sequence() {
+        let sessions = PreviewSessions::default();
+        let (sink, outputs) = collector();
+        let id = sessions.start(engine(), sink).unwrap();
+        let invalid: KeyEventDto = serde_json::from_str(r#"{"vk": "VK_NOPE"}"#).unwrap();
+        sessions.send(&id, vec![KeyEventDto::typed('k'), invalid, KeyEventDto::typed('a')]).unwrap();
+        wait_for(|| outputs.lock().unwrap().len() == 3);
+
+        let outputs = outputs.lock().unwrap();
+        assert!(outputs[1].error.is_some());
+        assert_eq!(outputs[1].seq, 2);
+        assert_eq!(outputs[1].composing_text, "က");
+        assert_eq!(outputs[2].composing_text, "ကာ");
+    }
+}
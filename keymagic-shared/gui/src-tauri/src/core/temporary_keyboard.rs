@@ -0,0 +1,137 @@
+//! Trying out a keyboard file for the session without installing it
+//!
+//! A trial keyboard is loaded next to the saved active keyboard and wins
+//! over it until the trial ends; the config is never written. Precedence:
+//!
+//! - a normal switch (settings, hotkey, profile, language rule) ends the
+//!   trial and the switched-to keyboard is saved as usual
+//! - a new trial replaces the current one and the old token stops working
+//! - ending with a token that is no longer current (expired, replaced or
+//!   ended by a switch) does nothing, so a late expiry timer cannot end a
+//!   newer trial
+
+use keymagic_core::SharedEngine;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How long a trial lasts when the caller does not say
+pub const DEFAULT_TRIAL_DURATION: Duration = Duration::from_secs(60);
+
+/// HUD text while a keyboard is being tried out
+pub fn temporary_keyboard_message(name: &str) -> String {
+    format!("{} (temporary)", name)
+}
+
+/// A trial as reported to the UI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemporaryKeyboardInfo {
+    /// Ends this trial with `end_temporary_activation`
+    pub token: String,
+    pub name: String,
+    pub path: PathBuf,
+    pub duration_secs: u64,
+}
+
+struct Trial {
+    info: TemporaryKeyboardInfo,
+    engine: SharedEngine,
+}
+
+/// The current trial, if any
+#[derive(Default)]
+pub struct TemporaryKeyboard {
+    current: Option<Trial>,
+    started: u64,
+}
+
+impl TemporaryKeyboard {
+    /// Starts a trial, replacing the current one, and returns its info
+    pub fn begin(&mut self, name: String, path: PathBuf, engine: SharedEngine, duration: Duration) -> TemporaryKeyboardInfo {
+        self.started += 1;
+        let info = TemporaryKeyboardInfo {
+            token: format!("trial-{}", self.started),
+            name,
+            path,
+            duration_secs: duration.as_secs(),
+        };
+        self.current = Some(Trial { info: info.clone(), engine });
+        info
+    }
+
+    /// Ends the trial `token` started; false when it is no longer current
+    pub fn end(&mut self, token: &str) -> bool {
+        if self.current.as_ref().is_some_and(|trial| trial.info.token == token) {
+            self.current = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Ends whatever trial is running; false if there was none
+    pub fn clear(&mut self) -> bool {
+        self.current.take().is_some()
+    }
+
+    pub fn info(&self) -> Option<&TemporaryKeyboardInfo> {
+        self.current.as_ref().map(|trial| &trial.info)
+    }
+
+    /// Engine of the trial keyboard, which wins over the saved one
+    pub fn engine(&self) -> Option<SharedEngine> {
+        self.current.as_ref().map(|trial| trial.engine.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine() -> SharedEngine {
+        SharedEngine::from_keyboard(kms2km2::compile_kms(r#""k" => "က""#).unwrap()).unwrap()
+    }
+
+    fn begin(trials: &mut TemporaryKeyboard, name: &str) -> TemporaryKeyboardInfo {
+        trials.begin(name.to_string(), PathBuf::from(format!("{}.km2", name)), engine(), DEFAULT_TRIAL_DURATION)
+    }
+
+    #[test]
+    fn test_end_with_current_token() {
+        let mut trials = TemporaryKeyboard::default();
+        let info = begin(&mut trials, "Pyidaungsu");
+        assert_eq!(trials.info(), Some(&info));
+        assert!(trials.engine().is_some());
+        assert!(trials.end(&info.token));
+        assert!(trials.info().is_none());
+        // Ending twice is harmless
+        assert!(!trials.end(&info.token));
+    }
+
+    #[test]
+    fn test_new_trial_replaces_old_token() {
+        let mut trials = TemporaryKeyboard::default();
+        let first = begin(&mut trials, "first");
+        let second = begin(&mut trials, "second");
+        assert_eq!(second.duration_secs, 60);
+        assert_ne!(first.token, second.token);
+        // The first trial's timer must not end the second one
+        assert!(!trials.end(&first.token));
+        assert_eq!(trials.info().map(|info| info.name.as_str()), Some("second"));
+        assert!(trials.end(&second.token));
+    }
+
+    #[test]
+    fn test_switch_ends_trial() {
+        let mut trials = TemporaryKeyboard::default();
+        let info = begin(&mut trials, "trial");
+        assert!(trials.clear());
+        assert!(!trials.clear());
+        assert!(!trials.end(&info.token));
+    }
+
+    #[test]
+    fn test_message() {
+        assert_eq!(temporary_keyboard_message("Zawgyi"), "Zawgyi (temporary)");
+    }
+}
@@ -0,0 +1,503 @@
+//! Diagnostic bundles for problem reports
+//!
+//! Gathers what issue reports usually lack (versions, the install check, the
+//! active keyboard, settings, logs, the last input recording, the heartbeat
+//! input methods watch, the stray characters they dropped) into one zip with a `manifest.json`. Collectors run
+//! independently: one that fails is listed under `errors` in the manifest
+//! and the rest of the bundle is still written.
+//!
+//! Redaction before anything is added:
+//! - the home directory in paths and log lines becomes `~`
+//! - profile names, which the user typed, become `profile-1`, `profile-2`, ...
+//! - process names become the hash input recordings use (`process-1a2b3c4d`)
+//!   unless the user agrees to include them; this covers the hosts listed
+//!   with the keyboard file they loaded
+//!
+//! Hotkeys, keyboard ids and option values are kept; they are needed to
+//! reproduce most problems.
+
+use anyhow::{anyhow, Context, Result};
+use keymagic_core::load_log::KeyboardLoad;
+use keymagic_core::recorder::hash_process_name;
+use keymagic_core::Km2File;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::core::KeyboardInfo;
+use crate::install::InstallReport;
+use crate::platform::{Config, PlatformFeatures};
+
+/// Identifies bundles
+const BUNDLE_FORMAT: &str = "keymagic-diagnostics";
+const BUNDLE_VERSION: u32 = 1;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Log files added to a bundle, newest first
+const MAX_LOG_FILES: usize = 3;
+/// Only the end of a longer log file is added
+const MAX_LOG_BYTES: usize = 1024 * 1024;
+
+/// What the user agreed to include
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleOptions {
+    /// The active keyboard's km2 file, not just its metadata
+    pub include_keyboard: bool,
+    pub include_logs: bool,
+    /// Process names in plain text instead of hashed
+    pub reveal_process_names: bool,
+}
+
+/// Versions and platform, recorded in the manifest
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemInfo {
+    pub app_version: String,
+    pub core_version: String,
+    pub os: String,
+    pub arch: String,
+    /// `None` when the platform cannot tell
+    pub os_version: Option<String>,
+    pub features: PlatformFeatures,
+}
+
+/// A collector that failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectorError {
+    pub collector: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    format: &'static str,
+    version: u32,
+    created_at_ms: u64,
+    system: &'a SystemInfo,
+    options: BundleOptions,
+    files: Vec<&'a str>,
+    errors: &'a [CollectorError],
+}
+
+/// A file in the bundle: path inside the zip and contents
+pub type BundleFile = (String, Vec<u8>);
+
+/// Bundle contents gathered so far
+pub struct DiagnosticBundle {
+    system: SystemInfo,
+    options: BundleOptions,
+    files: Vec<BundleFile>,
+    errors: Vec<CollectorError>,
+}
+
+impl DiagnosticBundle {
+    pub fn new(system: SystemInfo, options: BundleOptions) -> Self {
+        Self { system, options, files: Vec::new(), errors: Vec::new() }
+    }
+
+    /// Adds the files `f` returns, or records its error under `collector`
+    pub fn collect(&mut self, collector: &str, f: impl FnOnce() -> Result<Vec<BundleFile>>) {
+        match f() {
+            Ok(files) => self.files.extend(files),
+            Err(e) => {
+                log::warn!("Diagnostic collector {} failed: {:#}", collector, e);
+                self.errors.push(CollectorError { collector: collector.to_string(), error: format!("{:#}", e) });
+            }
+        }
+    }
+
+    pub fn errors(&self) -> &[CollectorError] {
+        &self.errors
+    }
+
+    /// Writes the zip with the manifest first
+    pub fn write_zip(&self, path: &Path, created_at_ms: u64) -> Result<()> {
+        let manifest = Manifest {
+            format: BUNDLE_FORMAT,
+            version: BUNDLE_VERSION,
+            created_at_ms,
+            system: &self.system,
+            options: self.options,
+            files: self.files.iter().map(|(name, _)| name.as_str()).collect(),
+            errors: &self.errors,
+        };
+
+        let file = fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut zip = ZipWriter::new(file);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file(MANIFEST_FILE, options)?;
+        zip.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+        for (name, data) in &self.files {
+            zip.start_file(name.as_str(), options)?;
+            zip.write_all(data)?;
+        }
+        zip.finish().with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Where a new bundle goes: the Downloads folder, or `fallback_dir` when
+/// there is none. An existing file is never replaced.
+pub fn bundle_path(downloads: Option<PathBuf>, fallback_dir: &Path, created_at_ms: u64) -> PathBuf {
+    let dir = downloads.unwrap_or_else(|| fallback_dir.to_path_buf());
+    let stem = format!("keymagic-diagnostics-{}", created_at_ms / 1000);
+    let mut path = dir.join(format!("{}.zip", stem));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("{}-{}.zip", stem, n));
+    }
+    path
+}
+
+/// Replaces the home directory in `text` with "~"
+pub fn redact_home(text: &str, home: Option<&Path>) -> String {
+    let Some(home) = home.and_then(Path::to_str).filter(|home| home.len() > 1) else {
+        return text.to_string();
+    };
+    let home = home.trim_end_matches(['/', '\\']);
+    let mut text = text.replace(home, "~");
+    // Paths written with the other separator, as in logs and config on Windows
+    if home.contains('\\') {
+        text = text.replace(&home.replace('\\', "/"), "~");
+    } else if home.contains('/') {
+        text = text.replace(&home.replace('/', "\\"), "~");
+    }
+    text
+}
+
+/// Stands in for a process name: the hash input recordings use for it, so
+/// records and settings can still be matched up
+pub fn process_placeholder(name: &str) -> String {
+    format!("process-{:08x}", hash_process_name(name))
+}
+
+/// The config as JSON with the redactions described in the module docs
+pub fn redact_config(config: &Config, home: Option<&Path>, reveal_process_names: bool) -> Result<serde_json::Value> {
+    let mut config = config.clone();
+
+    if !reveal_process_names {
+        let hash = |hosts: &mut Vec<String>| hosts.iter_mut().for_each(|host| *host = process_placeholder(host));
+        hash(&mut config.composition_mode.enabled_hosts);
+        hash(&mut config.direct_mode.enabled_hosts);
+        hash(&mut config.char_suppression.enabled_hosts);
+        config.shortcut_passthrough.hosts = std::mem::take(&mut config.shortcut_passthrough.hosts)
+            .into_iter()
+            .map(|(host, keys)| (process_placeholder(&host), keys))
+            .collect();
+        for profile in config.profiles.values_mut() {
+            profile.composition_mode_hosts.iter_mut().for_each(hash);
+            profile.direct_mode_hosts.iter_mut().for_each(hash);
+        }
+    }
+
+    // Sorted, so a profile keeps its placeholder between bundles
+    let mut sorted: Vec<String> = config.profiles.keys().cloned().collect();
+    sorted.sort();
+    let names: HashMap<String, String> = sorted.into_iter()
+        .enumerate()
+        .map(|(i, name)| (name, format!("profile-{}", i + 1)))
+        .collect();
+    config.profiles = config.profiles.into_iter().map(|(name, profile)| (names[&name].clone(), profile)).collect();
+    config.active_profile = config.active_profile.map(|name| {
+        names.get(&name).cloned().unwrap_or_else(|| "profile-unknown".to_string())
+    });
+
+    let mut value = serde_json::to_value(&config)?;
+    redact_strings(&mut value, home);
+    Ok(value)
+}
+
+fn redact_strings(value: &mut serde_json::Value, home: Option<&Path>) {
+    match value {
+        serde_json::Value::String(s) => *s = redact_home(s, home),
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|item| redact_strings(item, home)),
+        serde_json::Value::Object(map) => map.values_mut().for_each(|item| redact_strings(item, home)),
+        _ => {}
+    }
+}
+
+/// The install check report with the home directory redacted from its paths
+pub fn install_report(report: &InstallReport, home: Option<&Path>) -> Result<serde_json::Value> {
+    let mut value = serde_json::to_value(report)?;
+    redact_strings(&mut value, home);
+    Ok(value)
+}
+
+/// What is known about the active keyboard, without its file path
+pub fn keyboard_summary(keyboard: &KeyboardInfo, km2: &Km2File) -> serde_json::Value {
+    let metadata = km2.metadata();
+    serde_json::json!({
+        "id": keyboard.id,
+        "name": keyboard.name,
+        "filename": keyboard.filename,
+        "hash": keyboard.hash,
+        "storage": keyboard.storage,
+        "format_version": format!("{}.{}", km2.header.major_version, km2.header.minor_version),
+        "description": metadata.description(),
+        "default_hotkey": metadata.hotkey(),
+        "locales": metadata.locales(),
+        "layout_options": km2.header.layout_options,
+        "rule_count": km2.rules.len(),
+        "string_count": km2.strings.len(),
+        "output_encoding": keyboard.output_encoding,
+        "disabled_groups": keyboard.disabled_groups,
+        "options_overrides": keyboard.options_overrides,
+    })
+}
+
+/// The keyboard each running host last loaded, from the load log
+///
+/// A host is `stale` when the file it loaded hashes to no installed keyboard,
+/// which happens when a keyboard was updated while the host kept its engine.
+pub fn host_loads(loads: &[KeyboardLoad], keyboards: &[KeyboardInfo], reveal_process_names: bool) -> serde_json::Value {
+    let hosts: Vec<serde_json::Value> = loads
+        .iter()
+        .map(|load| {
+            let keyboard = keyboards.iter().find(|keyboard| keyboard.hash.eq_ignore_ascii_case(&load.hash));
+            let process = if reveal_process_names { load.process.clone() } else { process_placeholder(&load.process) };
+            serde_json::json!({
+                "pid": load.pid,
+                "process": process,
+                "hash": load.hash,
+                "keyboard": keyboard.map(|keyboard| keyboard.id.clone()),
+                "stale": keyboard.is_none(),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "hosts": hosts,
+        "stale_hosts": hosts.iter().filter(|host| host["stale"] == true).count(),
+    })
+}
+
+/// The newest log files in `dir`, redacted, under `logs/`
+pub fn collect_logs(dir: &Path, home: Option<&Path>) -> Result<Vec<BundleFile>> {
+    let entries = fs::read_dir(dir).with_context(|| format!("No log directory at {}", redact_home(&dir.to_string_lossy(), home)))?;
+    let mut logs: Vec<(SystemTime, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "log"))
+        .map(|path| (fs::metadata(&path).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH), path))
+        .collect();
+    if logs.is_empty() {
+        return Err(anyhow!("No log files in {}", redact_home(&dir.to_string_lossy(), home)));
+    }
+    logs.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+    logs.into_iter()
+        .take(MAX_LOG_FILES)
+        .map(|(_, path)| {
+            let data = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            let tail = &data[data.len().saturating_sub(MAX_LOG_BYTES)..];
+            let text = redact_home(&String::from_utf8_lossy(tail), home);
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            Ok((format!("logs/{}", name), text.into_bytes()))
+        })
+        .collect()
+}
+
+/// JSON file for the bundle
+pub fn json_file(name: &str, value: &impl Serialize) -> Result<Vec<BundleFile>> {
+    Ok(vec![(name.to_string(), serde_json::to_vec_pretty(value)?)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::{
+        CharSuppressionConfig, CompositionModeConfig, DirectModeConfig, GeneralConfig, KeyboardsConfig, LanguageActivationConfig,
+        ProfileOverrides, ShortcutPassthroughConfig,
+    };
+    use std::collections::BTreeMap;
+    use std::io::Read;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic-diagnostics-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn system() -> SystemInfo {
+        SystemInfo {
+            app_version: "0.0.9".to_string(),
+            core_version: "0.0.9".to_string(),
+            os: "test".to_string(),
+            arch: "x86_64".to_string(),
+            os_version: None,
+            features: PlatformFeatures::default(),
+        }
+    }
+
+    fn config() -> Config {
+        let mut profiles = HashMap::new();
+        profiles.insert("Work at Acme".to_string(), ProfileOverrides {
+            direct_mode_hosts: Some(vec!["slack.exe".to_string()]),
+            ..Default::default()
+        });
+        profiles.insert("Home".to_string(), ProfileOverrides::default());
+        Config {
+            general: GeneralConfig {
+                start_with_system: true,
+                check_for_updates: true,
+                last_update_check: None,
+                last_scanned_version: None,
+                update_remind_after: None,
+                key_processing_enabled: None,
+            },
+            keyboards: KeyboardsConfig { active: Some("zawcode".to_string()), last_used: Vec::new(), installed: Vec::new() },
+            composition_mode: CompositionModeConfig { enabled_hosts: vec!["chrome.exe".to_string()] },
+            direct_mode: DirectModeConfig { enabled_hosts: vec!["Code.exe".to_string()] },
+            shortcut_passthrough: ShortcutPassthroughConfig {
+                hosts: BTreeMap::from([("gmail.exe".to_string(), vec!["VK_KEY_C".to_string()])]),
+            },
+            char_suppression: CharSuppressionConfig { enabled_hosts: vec!["teams.exe".to_string()] },
+            profiles,
+            active_profile: Some("Work at Acme".to_string()),
+            language_activation: LanguageActivationConfig::default(),
+        }
+    }
+
+    #[test]
+    fn test_redact_home() {
+        let home = Path::new("/home/aung");
+        assert_eq!(redact_home("opened /home/aung/keyboards/a.km2", Some(home)), "opened ~/keyboards/a.km2");
+        assert_eq!(redact_home("/home/aungmyint", None), "/home/aungmyint");
+        let windows = Path::new("C:\\Users\\Aung\\");
+        assert_eq!(
+            redact_home("C:\\Users\\Aung\\AppData and C:/Users/Aung/x", Some(windows)),
+            "~\\AppData and ~/x"
+        );
+        // A root home would replace every separator
+        assert_eq!(redact_home("/usr/bin", Some(Path::new("/"))), "/usr/bin");
+    }
+
+    #[test]
+    fn test_process_names_are_hashed() {
+        let json = redact_config(&config(), None, false).unwrap();
+        let chrome = process_placeholder("chrome.exe");
+        assert_eq!(chrome, format!("process-{:08x}", hash_process_name("chrome.exe")));
+        assert_eq!(json["composition_mode"]["enabled_hosts"][0], chrome);
+        assert_eq!(json["direct_mode"]["enabled_hosts"][0], process_placeholder("Code.exe"));
+        assert_eq!(json["profiles"]["profile-2"]["direct_mode_hosts"][0], process_placeholder("slack.exe"));
+        assert_eq!(json["shortcut_passthrough"]["hosts"][process_placeholder("gmail.exe")][0], "VK_KEY_C");
+        assert_eq!(json["char_suppression"]["enabled_hosts"][0], process_placeholder("teams.exe"));
+        assert!(!json.to_string().contains(".exe"));
+    }
+
+    #[test]
+    fn test_process_names_kept_when_revealed() {
+        let json = redact_config(&config(), None, true).unwrap();
+        assert_eq!(json["composition_mode"]["enabled_hosts"][0], "chrome.exe");
+        assert_eq!(json["profiles"]["profile-2"]["direct_mode_hosts"][0], "slack.exe");
+    }
+
+    #[test]
+    fn test_profile_names_are_replaced() {
+        let json = redact_config(&config(), None, true).unwrap();
+        let text = json.to_string();
+        assert!(!text.contains("Acme"));
+        assert!(!text.contains("Home"));
+        // Sorted: "Home" < "Work at Acme"
+        assert_eq!(json["active_profile"], "profile-2");
+        assert!(json["profiles"]["profile-1"].is_object());
+        // Keyboard ids stay
+        assert_eq!(json["keyboards"]["active"], "zawcode");
+    }
+
+    #[test]
+    fn test_logs_newest_first_and_redacted() {
+        let dir = temp_dir("logs");
+        for (i, name) in ["old.log", "mid.log", "new.log", "newest.log"].iter().enumerate() {
+            let path = dir.join(name);
+            fs::write(&path, format!("{} loaded /home/aung/k.km2", name)).unwrap();
+            let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000 + i as u64);
+            fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "not a log").unwrap();
+
+        let logs = collect_logs(&dir, Some(Path::new("/home/aung"))).unwrap();
+        let names: Vec<&str> = logs.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["logs/newest.log", "logs/new.log", "logs/mid.log"]);
+        assert_eq!(String::from_utf8(logs[0].1.clone()).unwrap(), "newest.log loaded ~/k.km2");
+
+        assert!(collect_logs(&dir.join("missing"), None).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_failing_collector_leaves_partial_bundle() {
+        let dir = temp_dir("bundle");
+        let options = BundleOptions { include_logs: true, ..Default::default() };
+        let mut bundle = DiagnosticBundle::new(system(), options);
+        bundle.collect("config", || json_file("config.json", &serde_json::json!({ "a": 1 })));
+        bundle.collect("logs", || Err(anyhow!("No log files")));
+        assert_eq!(bundle.errors(), &[CollectorError { collector: "logs".to_string(), error: "No log files".to_string() }]);
+
+        let path = bundle_path(None, &dir, 1_700_000_000_000);
+        assert_eq!(path, dir.join("keymagic-diagnostics-1700000000.zip"));
+        bundle.write_zip(&path, 1_700_000_000_000).unwrap();
+        // A second bundle in the same second gets its own name
+        assert_eq!(bundle_path(None, &dir, 1_700_000_000_000), dir.join("keymagic-diagnostics-1700000000-2.zip"));
+
+        let mut zip = zip::ZipArchive::new(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(zip.by_index(0).unwrap().name(), MANIFEST_FILE);
+        let mut manifest = String::new();
+        zip.by_name(MANIFEST_FILE).unwrap().read_to_string(&mut manifest).unwrap();
+        let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(manifest["format"], BUNDLE_FORMAT);
+        assert_eq!(manifest["system"]["app_version"], "0.0.9");
+        assert_eq!(manifest["options"]["include_logs"], true);
+        assert_eq!(manifest["files"], serde_json::json!(["config.json"]));
+        assert_eq!(manifest["errors"][0]["collector"], "logs");
+        assert!(zip.by_name("config.json").is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_keyboard_summary() {
+        let km2 = kms2km2::compile_kms("/*\n@NAME = \"Zaw\"\n@LOCALE = \"my-MM\"\n*/\n\"k\" => \"က\"").unwrap();
+        let keyboard: KeyboardInfo = serde_json::from_value(serde_json::json!({
+            "id": "zaw", "name": "Zaw", "filename": "zaw.km2", "path": "/home/aung/zaw.km2",
+            "hotkey": null, "hash": "abc", "is_active": true, "icon_data": null,
+        }))
+        .unwrap();
+        let summary = keyboard_summary(&keyboard, &km2);
+        assert_eq!(summary["id"], "zaw");
+        assert_eq!(summary["locales"], serde_json::json!(["my-MM"]));
+        assert_eq!(summary["rule_count"], 1);
+        assert_eq!(summary["storage"], "file");
+        assert!(!summary.to_string().contains("/home/aung"));
+    }
+
+    #[test]
+    fn test_host_loads_flag_stale_hosts() {
+        let current = keymagic_core::km2::content_hash(b"zaw v2");
+        let keyboard: KeyboardInfo = serde_json::from_value(serde_json::json!({
+            "id": "zaw", "name": "Zaw", "filename": "zaw.km2", "path": "/home/aung/zaw.km2",
+            "hotkey": null, "hash": current, "is_active": true, "icon_data": null,
+        }))
+        .unwrap();
+        let log = keymagic_core::load_log::LoadLog::in_memory();
+        log.report(10, "notepad.exe", &current).unwrap();
+        log.report(20, "winword.exe", &keymagic_core::km2::content_hash(b"zaw v1")).unwrap();
+
+        let report = host_loads(&log.latest_by_host(), std::slice::from_ref(&keyboard), false);
+        assert_eq!(report["stale_hosts"], 1);
+        assert_eq!(report["hosts"][0]["keyboard"], "zaw");
+        assert_eq!(report["hosts"][0]["stale"], false);
+        assert_eq!(report["hosts"][1]["keyboard"], serde_json::Value::Null);
+        assert_eq!(report["hosts"][1]["stale"], true);
+        assert_eq!(report["hosts"][1]["process"], process_placeholder("winword.exe"));
+
+        let report = host_loads(&log.latest_by_host(), &[keyboard], true);
+        assert_eq!(report["hosts"][1]["process"], "winword.exe");
+    }
+}
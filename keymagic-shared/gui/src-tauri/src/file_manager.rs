@@ -0,0 +1,294 @@
+//! Opening folders and revealing files in the system file manager
+//!
+//! Command lines are built by pure functions per OS so they can be tested on
+//! any host; `open_folder` and `reveal_file` run them for the current OS.
+//! `sanitize_file_stem` likewise makes imported file names valid per OS.
+
+use serde::Serialize;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use unicode_normalization::UnicodeNormalization;
+
+/// Error returned to the UI when a file cannot be shown
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RevealError {
+    /// No installed keyboard has this id
+    KeyboardNotFound { keyboard_id: String },
+    /// The keyboard is installed but its file is gone
+    FileMissing { path: PathBuf },
+    /// The file manager could not be started
+    LaunchFailed { message: String },
+}
+
+impl fmt::Display for RevealError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevealError::KeyboardNotFound { keyboard_id } => write!(f, "Keyboard not found: {}", keyboard_id),
+            RevealError::FileMissing { path } => write!(f, "Keyboard file is missing: {}", path.display()),
+            RevealError::LaunchFailed { message } => write!(f, "Failed to open the file manager: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RevealError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetOs {
+    Windows,
+    MacOs,
+    Linux,
+}
+
+impl TargetOs {
+    pub fn current() -> Self {
+        if cfg!(target_os = "windows") {
+            TargetOs::Windows
+        } else if cfg!(target_os = "macos") {
+            TargetOs::MacOs
+        } else {
+            TargetOs::Linux
+        }
+    }
+}
+
+/// A program invocation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchCommand {
+    pub program: &'static str,
+    pub args: Vec<OsString>,
+    /// Pass `args` to the program verbatim instead of quoting them (Windows only)
+    pub raw_args: bool,
+    /// Wait for the program and treat a non-zero exit as failure
+    pub wait: bool,
+}
+
+impl LaunchCommand {
+    fn new(program: &'static str, args: Vec<OsString>) -> Self {
+        Self { program, args, raw_args: false, wait: false }
+    }
+
+    fn run(&self) -> std::io::Result<()> {
+        let mut command = Command::new(self.program);
+        if self.raw_args {
+            #[cfg(windows)]
+            {
+                use std::os::windows::process::CommandExt;
+                for arg in &self.args {
+                    command.raw_arg(arg);
+                }
+            }
+            #[cfg(not(windows))]
+            command.args(&self.args);
+        } else {
+            command.args(&self.args);
+        }
+
+        if self.wait {
+            let status = command.stdout(Stdio::null()).stderr(Stdio::null()).status()?;
+            if !status.success() {
+                return Err(std::io::Error::other(format!("{} exited with {}", self.program, status)));
+            }
+        } else {
+            command.spawn()?;
+        }
+        Ok(())
+    }
+}
+
+/// Command that opens a folder
+pub fn open_folder_command(os: TargetOs, dir: &Path) -> LaunchCommand {
+    let program = match os {
+        TargetOs::Windows => "explorer",
+        TargetOs::MacOs => "open",
+        TargetOs::Linux => "xdg-open",
+    };
+    LaunchCommand::new(program, vec![dir.as_os_str().to_owned()])
+}
+
+/// Commands that show a file selected in its folder, in order of preference
+pub fn reveal_file_commands(os: TargetOs, file: &Path) -> Vec<LaunchCommand> {
+    match os {
+        TargetOs::Windows => {
+            // Explorer parses "/select," itself and needs the path quoted after the comma
+            let mut arg = OsString::from("/select,\"");
+            arg.push(file.as_os_str());
+            arg.push("\"");
+            vec![LaunchCommand { raw_args: true, ..LaunchCommand::new("explorer", vec![arg]) }]
+        }
+        TargetOs::MacOs => vec![LaunchCommand::new("open", vec!["-R".into(), file.as_os_str().to_owned()])],
+        TargetOs::Linux => {
+            let show_items = LaunchCommand {
+                wait: true,
+                ..LaunchCommand::new(
+                    "dbus-send",
+                    vec![
+                        "--session".into(),
+                        "--print-reply".into(),
+                        "--dest=org.freedesktop.FileManager1".into(),
+                        "--type=method_call".into(),
+                        "/org/freedesktop/FileManager1".into(),
+                        "org.freedesktop.FileManager1.ShowItems".into(),
+                        format!("array:string:{}", file_uri(file)).into(),
+                        "string:".into(),
+                    ],
+                )
+            };
+            let mut commands = vec![show_items];
+            if let Some(dir) = file.parent() {
+                commands.push(open_folder_command(os, dir));
+            }
+            commands
+        }
+    }
+}
+
+/// `file://` URI of an absolute path, percent-encoding everything but unreserved characters
+pub fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_encoded_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+/// Device names Windows reserves in every folder, with or without extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes a file name stem safe to create on `os`
+///
+/// The stem is normalized to NFC, so the same Burmese name typed on different
+/// systems maps to one file, and characters the file system rejects are
+/// dropped. Returns "keyboard" if nothing is left.
+pub fn sanitize_file_stem(os: TargetOs, stem: &str) -> String {
+    let invalid = |ch: char| match os {
+        TargetOs::Windows => ch.is_control() || r#"<>:"/\|?*"#.contains(ch),
+        TargetOs::MacOs => ch.is_control() || ch == '/' || ch == ':',
+        TargetOs::Linux => ch.is_control() || ch == '/',
+    };
+    let mut name: String = stem.nfc().filter(|&ch| !invalid(ch)).collect();
+
+    if os == TargetOs::Windows {
+        // Trailing dots and spaces are stripped by Win32, making the name differ
+        name.truncate(name.trim_end_matches(['.', ' ']).len());
+        if WINDOWS_RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(&name)) {
+            name.push('_');
+        }
+    }
+    if name.trim_start_matches('.').is_empty() {
+        return "keyboard".to_string();
+    }
+    name
+}
+
+fn run_first(commands: &[LaunchCommand]) -> Result<(), RevealError> {
+    let mut last_error = String::from("no file manager command available");
+    for command in commands {
+        match command.run() {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                log::debug!("{} failed: {}", command.program, e);
+                last_error = format!("{}: {}", command.program, e);
+            }
+        }
+    }
+    Err(RevealError::LaunchFailed { message: last_error })
+}
+
+/// Opens `dir` in the file manager, creating it first if needed
+pub fn open_folder(dir: &Path) -> Result<(), RevealError> {
+    std::fs::create_dir_all(dir).map_err(|e| RevealError::LaunchFailed {
+        message: format!("cannot create {}: {}", dir.display(), e),
+    })?;
+    run_first(&[open_folder_command(TargetOs::current(), dir)])
+}
+
+/// Opens the file manager with `file` selected
+pub fn reveal_file(file: &Path) -> Result<(), RevealError> {
+    if !file.is_file() {
+        return Err(RevealError::FileMissing { path: file.to_path_buf() });
+    }
+    run_first(&reveal_file_commands(TargetOs::current(), file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_file_stem() {
+        // "ဦ" written decomposed (U+1025 U+102E) becomes U+1026
+        assert_eq!(sanitize_file_stem(TargetOs::Linux, "\u{1025}\u{102E}းကီးဘုတ်"), "\u{1026}းကီးဘုတ်");
+        assert_eq!(sanitize_file_stem(TargetOs::Windows, "my:keyboard?v2"), "mykeyboardv2");
+        assert_eq!(sanitize_file_stem(TargetOs::MacOs, "my:keyboard?v2"), "mykeyboard?v2");
+        assert_eq!(sanitize_file_stem(TargetOs::Linux, "a/b\tc"), "abc");
+        assert_eq!(sanitize_file_stem(TargetOs::Windows, "zawgyi. . "), "zawgyi");
+        assert_eq!(sanitize_file_stem(TargetOs::Windows, "con"), "con_");
+        assert_eq!(sanitize_file_stem(TargetOs::Linux, "con"), "con");
+        assert_eq!(sanitize_file_stem(TargetOs::Windows, "<>"), "keyboard");
+        assert_eq!(sanitize_file_stem(TargetOs::Linux, ".."), "keyboard");
+    }
+
+    #[test]
+    fn test_open_folder_commands() {
+        let dir = Path::new("/home/user/My Keyboards");
+        assert_eq!(open_folder_command(TargetOs::Windows, dir).program, "explorer");
+        assert_eq!(open_folder_command(TargetOs::MacOs, dir).program, "open");
+        let command = open_folder_command(TargetOs::Linux, dir);
+        assert_eq!(command.program, "xdg-open");
+        assert_eq!(command.args, vec![OsString::from("/home/user/My Keyboards")]);
+    }
+
+    #[test]
+    fn test_reveal_on_windows_quotes_path_after_select() {
+        let commands = reveal_file_commands(TargetOs::Windows, Path::new(r"C:\Users\Mg Mg\Keyboards\ဇော်ဂျီ.km2"));
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0].raw_args);
+        assert_eq!(
+            commands[0].args,
+            vec![OsString::from(r#"/select,"C:\Users\Mg Mg\Keyboards\ဇော်ဂျီ.km2""#)]
+        );
+    }
+
+    #[test]
+    fn test_reveal_on_macos() {
+        let commands = reveal_file_commands(TargetOs::MacOs, Path::new("/Users/a b/Keyboards/x.km2"));
+        assert_eq!(commands[0].program, "open");
+        assert_eq!(commands[0].args, vec![OsString::from("-R"), OsString::from("/Users/a b/Keyboards/x.km2")]);
+        assert!(!commands[0].raw_args);
+    }
+
+    #[test]
+    fn test_reveal_on_linux_falls_back_to_folder() {
+        let commands = reveal_file_commands(TargetOs::Linux, Path::new("/home/u/My Keyboards/မြန်မာ.km2"));
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].program, "dbus-send");
+        assert!(commands[0].wait);
+        assert!(commands[0].args.contains(&OsString::from(
+            "array:string:file:///home/u/My%20Keyboards/%E1%80%99%E1%80%BC%E1%80%94%E1%80%BA%E1%80%99%E1%80%AC.km2"
+        )));
+        assert_eq!(commands[1], open_folder_command(TargetOs::Linux, Path::new("/home/u/My Keyboards")));
+    }
+
+    #[test]
+    fn test_reveal_missing_file() {
+        let path = std::env::temp_dir().join("keymagic-missing-keyboard.km2");
+        assert_eq!(reveal_file(&path), Err(RevealError::FileMissing { path }));
+    }
+
+    #[test]
+    fn test_error_serialization() {
+        let json = serde_json::to_value(RevealError::KeyboardNotFound { keyboard_id: "x".to_string() }).unwrap();
+        assert_eq!(json["kind"], "keyboard_not_found");
+        assert_eq!(json["keyboard_id"], "x");
+    }
+}
@@ -0,0 +1,283 @@
+//! Shortcuts caught by the text services instead of a system-wide hotkey
+//!
+//! `RegisterHotKey` steals its combination from every application, and some
+//! refuse to work without theirs. Keys already pass through the text service
+//! of the focused application, so the GUI publishes the combinations it wants
+//! to `keymagic_core::shortcut_watch` and polls for the ones that fired.
+//! Only keys the active keyboard leaves alone fire a shortcut.
+
+use anyhow::{anyhow, Result};
+use keymagic_core::hotkey::{HotkeyBinding, KeyCombo};
+use keymagic_core::shortcut_watch::{ShortcutWatch, WatchedShortcut, MAX_SHORTCUTS};
+use keymagic_core::VirtualKey;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Setting holding the registered shortcuts as JSON
+pub const SETTING: &str = "hook_shortcuts";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a shortcut does in the GUI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShortcutAction {
+    /// Bring up the main window on the keyboard list
+    OpenKeyboardList,
+    /// Turn key processing on or off
+    ToggleProcessing,
+}
+
+const ACTIONS: [ShortcutAction; 2] = [ShortcutAction::OpenKeyboardList, ShortcutAction::ToggleProcessing];
+
+impl ShortcutAction {
+    /// Number the text services report; 0 means no action
+    fn id(self) -> u32 {
+        ACTIONS.iter().position(|action| *action == self).unwrap() as u32 + 1
+    }
+
+    fn from_id(id: u32) -> Option<Self> {
+        ACTIONS.get((id as usize).checked_sub(1)?).copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HookShortcut {
+    /// As entered, e.g. "Ctrl+Shift+Space"
+    pub combo: String,
+    pub action: ShortcutAction,
+    /// Keep the key from the application; the composing text is committed
+    /// first
+    pub swallow: bool,
+}
+
+/// A shortcut that fired, emitted as `hook_shortcut_triggered`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ShortcutTriggered {
+    pub action: ShortcutAction,
+    /// Process the key was pressed in
+    pub process_id: u32,
+}
+
+/// A key combination a text service can report
+fn parse_combo(combo: &str) -> Result<KeyCombo> {
+    let binding = HotkeyBinding::parse(combo).map_err(|e| anyhow!("Invalid shortcut: {}", e))?;
+    let combo = *binding.combo().ok_or_else(|| anyhow!("A double-tap cannot be caught by the input method"))?;
+    if combo.meta {
+        return Err(anyhow!("The input method does not see the Win or Command key"));
+    }
+    if !combo.ctrl && !combo.alt {
+        return Err(anyhow!("The shortcut needs Ctrl or Alt, or it would get in the way of typing"));
+    }
+    if matches!(
+        combo.key,
+        VirtualKey::Shift | VirtualKey::Control | VirtualKey::Menu | VirtualKey::LShift | VirtualKey::RShift
+            | VirtualKey::LControl | VirtualKey::RControl | VirtualKey::LMenu | VirtualKey::RMenu
+    ) {
+        return Err(anyhow!("The shortcut needs a key besides the modifiers"));
+    }
+    Ok(combo)
+}
+
+#[cfg(target_os = "windows")]
+fn open_watch() -> std::io::Result<ShortcutWatch> {
+    ShortcutWatch::create_shared()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn open_watch() -> std::io::Result<ShortcutWatch> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Shortcuts are only caught by the Windows text service",
+    ))
+}
+
+struct MonitorState {
+    shortcuts: Vec<HookShortcut>,
+    /// Last event taken from the ring
+    last_seq: u64,
+}
+
+/// The registered shortcuts and the events reported for them
+pub struct HookShortcutMonitor {
+    watch: Option<ShortcutWatch>,
+    state: Mutex<MonitorState>,
+}
+
+impl HookShortcutMonitor {
+    /// Attaches to the shared list and publishes the saved shortcuts
+    pub fn new(saved: Option<&str>) -> Self {
+        let watch = open_watch()
+            .map_err(|e| log::debug!("Hook shortcuts unavailable: {}", e))
+            .ok();
+        let shortcuts = saved
+            .and_then(|saved| {
+                serde_json::from_str::<Vec<HookShortcut>>(saved)
+                    .map_err(|e| log::warn!("Ignoring saved hook shortcuts: {}", e))
+                    .ok()
+            })
+            .unwrap_or_default();
+        Self::with_watch(watch, shortcuts)
+    }
+
+    fn with_watch(watch: Option<ShortcutWatch>, shortcuts: Vec<HookShortcut>) -> Self {
+        let monitor = Self {
+            // Shortcuts fired before the GUI started are not picked up
+            state: Mutex::new(MonitorState { shortcuts, last_seq: watch.as_ref().map_or(0, ShortcutWatch::last_seq) }),
+            watch,
+        };
+        monitor.publish(&monitor.state.lock().unwrap().shortcuts);
+        monitor
+    }
+
+    fn publish(&self, shortcuts: &[HookShortcut]) {
+        let Some(watch) = &self.watch else {
+            return;
+        };
+        let watched: Vec<WatchedShortcut> = shortcuts
+            .iter()
+            .filter_map(|shortcut| {
+                let combo = parse_combo(&shortcut.combo).ok()?;
+                Some(WatchedShortcut { combo, action: shortcut.action.id(), swallow: shortcut.swallow })
+            })
+            .collect();
+        watch.set_shortcuts(&watched);
+    }
+
+    pub fn shortcuts(&self) -> Vec<HookShortcut> {
+        self.state.lock().unwrap().shortcuts.clone()
+    }
+
+    /// Registers a shortcut, replacing one with the same combination, and
+    /// returns the new list for saving
+    pub fn register(&self, combo: &str, action: ShortcutAction, swallow: bool) -> Result<Vec<HookShortcut>> {
+        if self.watch.is_none() {
+            return Err(anyhow!("Shortcuts through the input method are only available on Windows"));
+        }
+        let parsed = parse_combo(combo)?;
+        let mut state = self.state.lock().unwrap();
+        state.shortcuts.retain(|shortcut| parse_combo(&shortcut.combo).ok() != Some(parsed));
+        if state.shortcuts.len() >= MAX_SHORTCUTS {
+            return Err(anyhow!("At most {} shortcuts can be registered", MAX_SHORTCUTS));
+        }
+        state.shortcuts.push(HookShortcut { combo: combo.trim().to_string(), action, swallow });
+        self.publish(&state.shortcuts);
+        Ok(state.shortcuts.clone())
+    }
+
+    /// Removes the shortcut for a combination and returns the new list
+    pub fn unregister(&self, combo: &str) -> Result<Vec<HookShortcut>> {
+        let parsed = parse_combo(combo)?;
+        let mut state = self.state.lock().unwrap();
+        state.shortcuts.retain(|shortcut| parse_combo(&shortcut.combo).ok() != Some(parsed));
+        self.publish(&state.shortcuts);
+        Ok(state.shortcuts.clone())
+    }
+
+    /// Takes the shortcuts fired since the last poll
+    fn poll(&self) -> Vec<ShortcutTriggered> {
+        let Some(watch) = &self.watch else {
+            return Vec::new();
+        };
+        let mut state = self.state.lock().unwrap();
+        let events = watch.read_since(state.last_seq);
+        state.last_seq = state.last_seq.max(watch.last_seq());
+        events
+            .into_iter()
+            .filter_map(|event| {
+                Some(ShortcutTriggered { action: ShortcutAction::from_id(event.action)?, process_id: event.process_id })
+            })
+            .collect()
+    }
+
+    /// Calls `on_fire` from a background thread for every shortcut that fires
+    pub fn watch(self: &Arc<Self>, on_fire: impl Fn(ShortcutTriggered) + Send + 'static) {
+        if self.watch.is_none() {
+            return;
+        }
+        let monitor = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(POLL_INTERVAL);
+            for triggered in monitor.poll() {
+                on_fire(triggered);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(shortcuts: Vec<HookShortcut>) -> HookShortcutMonitor {
+        HookShortcutMonitor::with_watch(Some(ShortcutWatch::in_memory()), shortcuts)
+    }
+
+    fn watch(monitor: &HookShortcutMonitor) -> &ShortcutWatch {
+        monitor.watch.as_ref().unwrap()
+    }
+
+    #[test]
+    fn test_saved_shortcuts_are_published() {
+        let saved = r#"[{"combo":"Ctrl+Shift+Space","action":"open_keyboard_list","swallow":true}]"#;
+        let monitor = HookShortcutMonitor::with_watch(
+            Some(ShortcutWatch::in_memory()),
+            serde_json::from_str(saved).unwrap(),
+        );
+        let found = watch(&monitor).find(VirtualKey::Space, true, false, true).unwrap();
+        assert_eq!(ShortcutAction::from_id(found.action), Some(ShortcutAction::OpenKeyboardList));
+        assert!(found.swallow);
+    }
+
+    #[test]
+    fn test_register_replaces_the_same_combination() {
+        let monitor = monitor(Vec::new());
+        monitor.register("Ctrl+Shift+Space", ShortcutAction::OpenKeyboardList, true).unwrap();
+        let shortcuts = monitor.register("ctrl shift space", ShortcutAction::ToggleProcessing, false).unwrap();
+        assert_eq!(shortcuts.len(), 1);
+        assert_eq!(shortcuts[0].action, ShortcutAction::ToggleProcessing);
+        assert!(!watch(&monitor).find(VirtualKey::Space, true, false, true).unwrap().swallow);
+
+        assert!(monitor.unregister("CTRL+SHIFT+SPACE").unwrap().is_empty());
+        assert_eq!(watch(&monitor).find(VirtualKey::Space, true, false, true), None);
+    }
+
+    #[test]
+    fn test_shortcuts_a_host_cannot_catch_are_refused() {
+        let monitor = monitor(Vec::new());
+        for combo in ["Shift Shift", "Win+Space", "Shift+K", "K", "Ctrl+Shift", "Ctrl+Nope"] {
+            assert!(monitor.register(combo, ShortcutAction::OpenKeyboardList, true).is_err(), "{}", combo);
+        }
+        assert!(monitor.shortcuts().is_empty());
+    }
+
+    #[test]
+    fn test_fired_shortcuts_are_polled_once() {
+        let monitor = monitor(Vec::new());
+        monitor.register("Ctrl+Shift+Space", ShortcutAction::OpenKeyboardList, true).unwrap();
+        let action = watch(&monitor).find(VirtualKey::Space, true, false, true).unwrap().action;
+        watch(&monitor).report(action, 42);
+        // Unknown actions, e.g. from a newer text service, are skipped
+        watch(&monitor).report(99, 42);
+
+        assert_eq!(
+            monitor.poll(),
+            vec![ShortcutTriggered { action: ShortcutAction::OpenKeyboardList, process_id: 42 }]
+        );
+        assert!(monitor.poll().is_empty());
+    }
+
+    #[test]
+    fn test_unavailable_without_shared_list() {
+        let monitor = HookShortcutMonitor::with_watch(None, Vec::new());
+        assert!(monitor.register("Ctrl+Shift+Space", ShortcutAction::OpenKeyboardList, true).is_err());
+        assert!(monitor.poll().is_empty());
+    }
+
+    #[test]
+    fn test_serialized_names() {
+        let triggered = ShortcutTriggered { action: ShortcutAction::ToggleProcessing, process_id: 7 };
+        assert_eq!(serde_json::to_value(triggered).unwrap()["action"], "toggle_processing");
+    }
+}
@@ -1,36 +1,371 @@
 use anyhow::Result;
-use keymagic_core::hotkey::HotkeyBinding;
+use keymagic_core::hotkey::{is_injected, modifier_share, HotkeyBinding, HEAVY_MODIFIER_SHARE};
+use keymagic_core::{Km2File, VirtualKey};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
 
-pub struct HotkeyManager;
+use crate::core::{HotkeyActivation, KeyboardManager};
+
+/// A keyboard hotkey that could not be registered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HotkeyFailure {
+    pub keyboard_id: String,
+    pub hotkey: String,
+    pub reason: String,
+}
+
+/// Outcome of `HotkeyManager::register_all_hotkeys`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HotkeyRegistration {
+    /// Keyboards whose hotkey was (re-)registered
+    pub registered: Vec<String>,
+    /// Keyboards whose previous registration was dropped first
+    pub unregistered: Vec<String>,
+    pub failed: Vec<HotkeyFailure>,
+}
+
+/// What a hotkey does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotkeyAction {
+    /// Turn key processing on or off, bound by the `on_off_hotkey` setting
+    ToggleProcessing,
+    /// Activate a keyboard, bound by its hotkey
+    SwitchKeyboard(String),
+}
+
+/// Outcome of `HotkeyManager::dispatch`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum HotkeyOutcome {
+    Processing {
+        enabled: bool,
+        /// Keyboard activated by turning processing on
+        activated: Option<String>,
+    },
+    Keyboard {
+        keyboard_id: String,
+        activation: HotkeyActivation,
+    },
+}
+
+/// Keeps track of the keyboard hotkeys currently registered, so that
+/// registering them again only touches what changed
+pub struct HotkeyManager {
+    registered: Mutex<BTreeMap<String, HotkeyBinding>>,
+    on_off: Mutex<Option<HotkeyBinding>>,
+}
 
 impl HotkeyManager {
     pub fn new() -> Self {
-        Self
+        Self { registered: Mutex::new(BTreeMap::new()), on_off: Mutex::new(None) }
     }
 
-    /// Validate a hotkey string without registering it
-    pub fn validate_hotkey(&self, hotkey_str: &str) -> Result<()> {
+    /// Validate a hotkey string without registering it. A double-tap of a
+    /// modifier that `layout`, the active keyboard, uses heavily is refused:
+    /// typing would keep triggering it.
+    pub fn validate_hotkey(&self, hotkey_str: &str, layout: Option<&Km2File>) -> Result<()> {
         // Empty hotkey is valid (removes hotkey)
         if hotkey_str.is_empty() {
             return Ok(());
         }
-        
+
+        let hotkey = self.parse_hotkey(hotkey_str)?;
+        if let (Some(key), Some(layout)) = (hotkey.double_tap_key(), layout) {
+            let share = modifier_share(layout, key);
+            if share >= HEAVY_MODIFIER_SHARE {
+                return Err(anyhow::anyhow!(
+                    "The active keyboard uses {} in {:.0}% of its rules; double-tapping it would get in the way of typing",
+                    key.to_display_string(),
+                    share * 100.0
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_hotkey(&self, hotkey_str: &str) -> Result<HotkeyBinding> {
         // Try to parse the hotkey using keymagic-core's parser
         let hotkey = HotkeyBinding::parse(hotkey_str)
             .map_err(|e| anyhow::anyhow!("Invalid hotkey: {}", e))?;
-        
+
         // On Windows, disallow Win/Meta modifier
         #[cfg(target_os = "windows")]
-        if hotkey.meta {
+        if hotkey.combo().is_some_and(|combo| combo.meta) {
             return Err(anyhow::anyhow!("The hotkey cannot contain the Win key"));
         }
-        
+
+        Ok(hotkey)
+    }
+
+    /// Keyboard ids with a registered hotkey
+    pub fn registered_ids(&self) -> Vec<String> {
+        self.registered.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Sets the hotkey that turns key processing on and off; None or an
+    /// empty string removes it
+    pub fn set_on_off_hotkey(&self, hotkey: Option<&str>) -> Result<()> {
+        let binding = match hotkey.filter(|hotkey| !hotkey.is_empty()) {
+            Some(hotkey) => Some(self.parse_hotkey(hotkey)?),
+            None => None,
+        };
+        *self.on_off.lock().unwrap() = binding;
         Ok(())
     }
+
+    /// The action bound to a double-tap of `key`. The on/off hotkey wins
+    /// over a keyboard hotkey.
+    pub fn double_tap_action(&self, key: VirtualKey) -> Option<HotkeyAction> {
+        let binding = HotkeyBinding::DoubleTap(key);
+        if *self.on_off.lock().unwrap() == Some(binding) {
+            return Some(HotkeyAction::ToggleProcessing);
+        }
+        self.registered.lock().unwrap()
+            .iter()
+            .find(|(_, registered)| **registered == binding)
+            .map(|(keyboard_id, _)| HotkeyAction::SwitchKeyboard(keyboard_id.clone()))
+    }
+
+    /// `double_tap_action` for a double-tap a backend's key hook detected;
+    /// `extra_info` is the `dwExtraInfo` of the completing key event. Taps
+    /// KeyMagic injected itself trigger nothing.
+    pub fn hook_double_tap(&self, key: VirtualKey, extra_info: usize) -> Option<HotkeyAction> {
+        if is_injected(extra_info) {
+            log::debug!("Ignoring double-tap of {} from injected input", key.to_display_string());
+            return None;
+        }
+        self.double_tap_action(key)
+    }
+
+    /// Carries out a hotkey the way a pressed key combination would
+    pub fn dispatch(&self, keyboard_manager: &KeyboardManager, action: &HotkeyAction) -> Result<HotkeyOutcome> {
+        match action {
+            HotkeyAction::ToggleProcessing => {
+                let enabled = !keyboard_manager.is_key_processing_enabled();
+                let activated = keyboard_manager.set_key_processing_enabled(enabled)?;
+                Ok(HotkeyOutcome::Processing { enabled, activated })
+            }
+            HotkeyAction::SwitchKeyboard(keyboard_id) => Ok(HotkeyOutcome::Keyboard {
+                keyboard_id: keyboard_id.clone(),
+                activation: keyboard_manager.activate_keyboard_by_hotkey(keyboard_id)?,
+            }),
+        }
+    }
+
+    /// Brings the registrations in line with `hotkeys` (keyboard id, hotkey).
+    /// Registrations that are gone or changed are dropped first. With `force`
+    /// every hotkey is registered again, for when the system may have lost
+    /// them; otherwise unchanged ones are left alone.
+    pub fn sync_hotkeys(&self, hotkeys: &[(String, String)], force: bool) -> HotkeyRegistration {
+        let mut registered = self.registered.lock().unwrap();
+        let mut result = HotkeyRegistration::default();
+
+        let mut wanted: BTreeMap<String, HotkeyBinding> = BTreeMap::new();
+        for (keyboard_id, hotkey) in hotkeys {
+            let fail = |reason: String| HotkeyFailure {
+                keyboard_id: keyboard_id.clone(),
+                hotkey: hotkey.clone(),
+                reason,
+            };
+            let binding = match self.parse_hotkey(hotkey) {
+                Ok(binding) => binding,
+                Err(e) => {
+                    result.failed.push(fail(e.to_string()));
+                    continue;
+                }
+            };
+            if let Some((other, _)) = wanted.iter().find(|(_, taken)| **taken == binding) {
+                result.failed.push(fail(format!("Already used by keyboard {}", other)));
+                continue;
+            }
+            wanted.insert(keyboard_id.clone(), binding);
+        }
+
+        registered.retain(|keyboard_id, binding| {
+            let keep = !force && wanted.get(keyboard_id) == Some(binding);
+            if !keep {
+                result.unregistered.push(keyboard_id.clone());
+            }
+            keep
+        });
+        for (keyboard_id, binding) in wanted {
+            if !registered.contains_key(&keyboard_id) {
+                registered.insert(keyboard_id.clone(), binding);
+                result.registered.push(keyboard_id);
+            }
+        }
+        result
+    }
+
+    /// Registers the hotkeys of all installed keyboards and has the input
+    /// method pick them up. On Windows the text service in each application
+    /// preserves the keys itself when signalled.
+    pub fn register_all_hotkeys(&self, keyboard_manager: &KeyboardManager, force: bool) -> Result<HotkeyRegistration> {
+        let hotkeys: Vec<(String, String)> = keyboard_manager.get_keyboards()
+            .into_iter()
+            .filter_map(|keyboard| {
+                // Same precedence as the text service: the user's hotkey, else the keyboard's own
+                let hotkey = keyboard.hotkey_in_effect()?.to_string();
+                Some((keyboard.id, hotkey))
+            })
+            .collect();
+
+        let on_off = keyboard_manager.get_platform().get_setting("on_off_hotkey")?;
+        if let Err(e) = self.set_on_off_hotkey(on_off.as_deref()) {
+            log::warn!("On/off hotkey was not registered: {}", e);
+        }
+
+        let registration = self.sync_hotkeys(&hotkeys, force);
+        for failure in &registration.failed {
+            log::warn!(
+                "Hotkey {} of keyboard {} was not registered: {}",
+                failure.hotkey, failure.keyboard_id, failure.reason
+            );
+        }
+        if !registration.registered.is_empty() || !registration.unregistered.is_empty() {
+            keyboard_manager.get_platform().notify_keyboards_changed()?;
+        }
+        Ok(registration)
+    }
+
+    /// Drops and registers every hotkey again and re-asserts the active
+    /// keyboard, for after the session changed (resume, unlock, reconnect)
+    pub fn force_reregister(&self, keyboard_manager: &KeyboardManager) -> Result<HotkeyRegistration> {
+        let registration = self.register_all_hotkeys(keyboard_manager, true)?;
+        keyboard_manager.reassert_shared_state()?;
+        log::info!(
+            "Re-registered hotkeys of {} keyboards ({} failed)",
+            registration.registered.len(),
+            registration.failed.len()
+        );
+        Ok(registration)
+    }
 }
 
 impl Default for HotkeyManager {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hotkeys(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries.iter().map(|(id, hotkey)| (id.to_string(), hotkey.to_string())).collect()
+    }
+
+    #[test]
+    fn test_registering_again_is_a_no_op() {
+        let manager = HotkeyManager::new();
+        let wanted = hotkeys(&[("myanmar3", "CTRL+SHIFT+M"), ("zawgyi", "CTRL+SHIFT+Z")]);
+
+        let first = manager.sync_hotkeys(&wanted, false);
+        assert_eq!(first.registered, vec!["myanmar3", "zawgyi"]);
+        assert!(first.unregistered.is_empty());
+
+        assert_eq!(manager.sync_hotkeys(&wanted, false), HotkeyRegistration::default());
+        assert_eq!(manager.registered_ids(), vec!["myanmar3", "zawgyi"]);
+    }
+
+    #[test]
+    fn test_stale_and_changed_hotkeys_are_unregistered_first() {
+        let manager = HotkeyManager::new();
+        manager.sync_hotkeys(&hotkeys(&[("myanmar3", "CTRL+SHIFT+M"), ("zawgyi", "CTRL+SHIFT+Z")]), false);
+
+        let result = manager.sync_hotkeys(&hotkeys(&[("myanmar3", "CTRL+ALT+M")]), false);
+        assert_eq!(result.unregistered, vec!["myanmar3", "zawgyi"]);
+        assert_eq!(result.registered, vec!["myanmar3"]);
+        assert_eq!(manager.registered_ids(), vec!["myanmar3"]);
+    }
+
+    #[test]
+    fn test_forced_registration_redoes_everything() {
+        let manager = HotkeyManager::new();
+        let wanted = hotkeys(&[("myanmar3", "CTRL+SHIFT+M"), ("zawgyi", "CTRL+SHIFT+Z")]);
+        manager.sync_hotkeys(&wanted, false);
+
+        let result = manager.sync_hotkeys(&wanted, true);
+        assert_eq!(result.unregistered, vec!["myanmar3", "zawgyi"]);
+        assert_eq!(result.registered, vec!["myanmar3", "zawgyi"]);
+    }
+
+    #[test]
+    fn test_invalid_and_duplicate_hotkeys_fail() {
+        let manager = HotkeyManager::new();
+        let result = manager.sync_hotkeys(
+            &hotkeys(&[("myanmar3", "CTRL+SHIFT+M"), ("pali", "CTRL+NOPE"), ("zawgyi", "ctrl+shift+m")]),
+            false,
+        );
+        assert_eq!(result.registered, vec!["myanmar3"]);
+        let failed: Vec<&str> = result.failed.iter().map(|f| f.keyboard_id.as_str()).collect();
+        assert_eq!(failed, vec!["pali", "zawgyi"]);
+        assert_eq!(result.failed[1].reason, "Already used by keyboard myanmar3");
+        assert_eq!(manager.registered_ids(), vec!["myanmar3"]);
+    }
+
+    #[test]
+    fn test_double_tap_actions() {
+        let manager = HotkeyManager::new();
+        let result = manager.sync_hotkeys(&hotkeys(&[("myanmar3", "Shift Shift"), ("zawgyi", "double:RCtrl")]), false);
+        assert_eq!(result.registered, vec!["myanmar3", "zawgyi"]);
+
+        assert_eq!(
+            manager.double_tap_action(VirtualKey::RControl),
+            Some(HotkeyAction::SwitchKeyboard("zawgyi".to_string()))
+        );
+        assert_eq!(manager.double_tap_action(VirtualKey::Control), None);
+
+        manager.set_on_off_hotkey(Some("shift+shift")).unwrap();
+        assert_eq!(manager.double_tap_action(VirtualKey::Shift), Some(HotkeyAction::ToggleProcessing));
+        manager.set_on_off_hotkey(None).unwrap();
+        assert_eq!(
+            manager.double_tap_action(VirtualKey::Shift),
+            Some(HotkeyAction::SwitchKeyboard("myanmar3".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_injected_double_taps_are_ignored() {
+        use keymagic_core::hotkey::INJECTED_INPUT_SIGNATURE;
+
+        let manager = HotkeyManager::new();
+        manager.sync_hotkeys(&hotkeys(&[("zawgyi", "double:RCtrl")]), false);
+        let zawgyi = Some(HotkeyAction::SwitchKeyboard("zawgyi".to_string()));
+        // A stream of taps as backends report them, (key, extra info)
+        let taps = [
+            (VirtualKey::RControl, 0),
+            (VirtualKey::RControl, INJECTED_INPUT_SIGNATURE),
+            (VirtualKey::RControl, 0x1234),
+            (VirtualKey::Shift, 0),
+        ];
+        let actions: Vec<_> = taps.iter().map(|&(key, extra)| manager.hook_double_tap(key, extra)).collect();
+        assert_eq!(actions, vec![zawgyi.clone(), None, zawgyi, None]);
+    }
+
+    #[test]
+    fn test_double_tap_of_busy_modifier_is_refused() {
+        let manager = HotkeyManager::new();
+        // 2 of 7 rules are typed with Shift, 1 with Ctrl
+        let layout = kms2km2::compile_kms(
+            r#"
+            "k" => "က"
+            "K" => "ဃ"
+            "L" => "ဠ"
+            "a" => "ာ"
+            "b" => "ဘ"
+            "c" => "ခ"
+            <VK_CTRL & VK_KEY_M> => "မ"
+            "#,
+        )
+        .unwrap();
+
+        let error = manager.validate_hotkey("Shift Shift", Some(&layout)).unwrap_err();
+        assert!(error.to_string().contains("29%"), "{}", error);
+        manager.validate_hotkey("double:LShift", None).unwrap();
+        manager.validate_hotkey("Ctrl Ctrl", Some(&layout)).unwrap();
+        manager.validate_hotkey("Ctrl+Shift+K", Some(&layout)).unwrap();
+    }
+}
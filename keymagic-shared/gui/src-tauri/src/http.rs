@@ -0,0 +1,367 @@
+//! HTTP client shared by every network call
+//!
+//! Update checks and keyboard downloads go through one configured client:
+//! connect and read timeouts so a captive portal cannot hang a request, the
+//! system proxy (or the one the user set, with its credentials) and a
+//! User-Agent naming the app version and platform. Idempotent GETs are
+//! retried with exponential backoff and jitter.
+
+use anyhow::{anyhow, Result};
+use reqwest::{Client, ClientBuilder, Proxy, Response, StatusCode, Url};
+use serde::Serialize;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::platform::Platform;
+
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest wait for the next bytes of a response
+pub const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const OFFLINE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Probed by [`is_offline`]: the update server, which every install talks to
+const PROBE_ADDRESS: &str = "thantthet.github.io:443";
+
+/// Proxy for users whose network needs one the system does not announce
+pub const PROXY_URL_SETTING: &str = "http_proxy_url";
+pub const PROXY_USERNAME_SETTING: &str = "http_proxy_username";
+pub const PROXY_PASSWORD_SETTING: &str = "http_proxy_password";
+
+/// Proxy the user configured; without one the system proxy is used
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxySettings {
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Proxy settings for the UI; the password is never sent back
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProxySettingsInfo {
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub has_password: bool,
+}
+
+impl ProxySettings {
+    /// Reads the settings; empty values count as unset
+    pub fn load(platform: &dyn Platform) -> Self {
+        let get = |key: &str| platform.get_setting(key).ok().flatten().filter(|value| !value.is_empty());
+        Self {
+            url: get(PROXY_URL_SETTING),
+            username: get(PROXY_USERNAME_SETTING),
+            password: get(PROXY_PASSWORD_SETTING),
+        }
+    }
+
+    pub fn save(&self, platform: &dyn Platform) -> Result<()> {
+        platform.set_setting(PROXY_URL_SETTING, self.url.as_deref().unwrap_or_default())?;
+        platform.set_setting(PROXY_USERNAME_SETTING, self.username.as_deref().unwrap_or_default())?;
+        platform.set_setting(PROXY_PASSWORD_SETTING, self.password.as_deref().unwrap_or_default())
+    }
+
+    pub fn info(&self) -> ProxySettingsInfo {
+        ProxySettingsInfo { url: self.url.clone(), username: self.username.clone(), has_password: self.password.is_some() }
+    }
+
+    /// The proxy to route through, `None` to use the system's
+    pub fn proxy(&self) -> Result<Option<Proxy>> {
+        let Some(url) = &self.url else {
+            return Ok(None);
+        };
+        let parsed = Url::parse(url).map_err(|_| anyhow!("Invalid proxy URL: {}", url))?;
+        if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none() {
+            return Err(anyhow!("The proxy URL must start with http:// or https:// and name a host: {}", url));
+        }
+        let mut proxy = Proxy::all(parsed)?;
+        if let Some(username) = &self.username {
+            proxy = proxy.basic_auth(username, self.password.as_deref().unwrap_or_default());
+        }
+        Ok(Some(proxy))
+    }
+
+    /// Host and port of the proxy, for [`is_offline`]
+    fn address(&self) -> Option<String> {
+        let url = Url::parse(self.url.as_deref()?).ok()?;
+        Some(format!("{}:{}", url.host_str()?, url.port_or_known_default()?))
+    }
+}
+
+/// Proxy of the clients built from now on
+static PROXY: Mutex<Option<ProxySettings>> = Mutex::new(None);
+
+/// Routes later requests through the user's proxy; an invalid proxy is
+/// refused and the previous one kept
+pub fn set_proxy(settings: ProxySettings) -> Result<()> {
+    settings.proxy()?;
+    *PROXY.lock().unwrap() = Some(settings);
+    Ok(())
+}
+
+pub fn user_agent() -> String {
+    let (os, arch) = crate::updater::get_platform_info();
+    format!("KeyMagic/{} ({}/{})", env!("CARGO_PKG_VERSION"), os, arch)
+}
+
+/// Timeouts of a client; the defaults suit every call but tests
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub connect: Duration,
+    pub read: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self { connect: CONNECT_TIMEOUT, read: READ_TIMEOUT }
+    }
+}
+
+/// Client settings shared by all network calls; callers add their own
+/// redirect policy or total timeout
+pub fn client_builder() -> ClientBuilder {
+    client_builder_with(Timeouts::default())
+}
+
+pub fn client_builder_with(timeouts: Timeouts) -> ClientBuilder {
+    let builder = Client::builder()
+        .user_agent(user_agent())
+        .connect_timeout(timeouts.connect)
+        .read_timeout(timeouts.read);
+    let proxy = PROXY.lock().unwrap().as_ref().map(ProxySettings::proxy);
+    match proxy {
+        Some(Ok(Some(proxy))) => builder.proxy(proxy),
+        Some(Err(e)) => {
+            log::warn!("Ignoring the proxy setting: {}", e);
+            builder
+        }
+        _ => builder,
+    }
+}
+
+/// How often and how patiently an idempotent request is retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Tries in total, the first one included
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each later one
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { attempts: 3, base_delay: Duration::from_millis(500), max_delay: Duration::from_secs(5) }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before retry `retry` (counted from 0): the backoff plus up to
+    /// half of it again as jitter, so clients do not retry in step
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.base_delay.saturating_mul(1 << retry.min(16)).min(self.max_delay);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        backoff + backoff.mul_f64(f64::from(nanos % 1000) / 2000.0)
+    }
+}
+
+/// Statuses worth asking again for
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::REQUEST_TIMEOUT
+}
+
+fn is_transient_error(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect()
+}
+
+/// Sends a GET, retrying timeouts, failed connections and transient statuses
+/// as `policy` allows. The last response is returned whatever its status.
+pub async fn get_with_retry(client: &Client, url: Url, policy: RetryPolicy) -> reqwest::Result<Response> {
+    let mut retry = 0;
+    loop {
+        let last = retry + 1 >= policy.attempts;
+        match client.get(url.clone()).send().await {
+            Ok(response) if last || !is_transient_status(response.status()) => return Ok(response),
+            Ok(response) => log::info!("GET {} returned {}, retrying", url, response.status()),
+            Err(e) if last || !is_transient_error(&e) => return Err(e),
+            Err(e) => log::info!("GET {} failed, retrying: {}", url, e),
+        }
+        tokio::time::sleep(policy.delay(retry)).await;
+        retry += 1;
+    }
+}
+
+/// Whether the network looks unreachable: the update server (or the user's
+/// proxy) does not accept a connection within two seconds. Meant for
+/// skipping background work quickly, not as a reason to refuse a request
+/// the user asked for.
+pub fn is_offline() -> bool {
+    let proxy = PROXY.lock().unwrap().as_ref().and_then(ProxySettings::address);
+    !is_reachable(proxy.as_deref().unwrap_or(PROBE_ADDRESS), OFFLINE_CHECK_TIMEOUT)
+}
+
+/// Resolves and connects on another thread, so a hanging DNS lookup counts
+/// against the timeout too
+fn is_reachable(address: &str, timeout: Duration) -> bool {
+    let (sender, receiver) = mpsc::channel();
+    let address = address.to_string();
+    std::thread::spawn(move || {
+        let reachable = address
+            .to_socket_addrs()
+            .into_iter()
+            .flatten()
+            .any(|addr| TcpStream::connect_timeout(&addr, timeout).is_ok());
+        let _ = sender.send(reachable);
+    });
+    receiver.recv_timeout(timeout).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Serves one canned response per connection, each after its delay;
+    /// returns the address and the number of connections taken
+    fn serve(responses: Vec<(Duration, String)>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        std::thread::spawn(move || {
+            for (delay, response) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut request = [0u8; 4096];
+                let _ = stream.read(&mut request);
+                std::thread::spawn(move || {
+                    std::thread::sleep(delay);
+                    let _ = stream.write_all(response.as_bytes());
+                });
+            }
+        });
+        (address, connections)
+    }
+
+    fn status(code: u16, body: &str) -> String {
+        format!("HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", code, body.len(), body)
+    }
+
+    fn quick() -> (Client, RetryPolicy) {
+        let client = client_builder_with(Timeouts { connect: Duration::from_secs(1), read: Duration::from_millis(300) })
+            .build()
+            .unwrap();
+        let policy = RetryPolicy { attempts: 3, base_delay: Duration::from_millis(10), max_delay: Duration::from_millis(20) };
+        (client, policy)
+    }
+
+    fn url(address: &str) -> Url {
+        Url::parse(&format!("{}/updates.json", address)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_retried() {
+        let (address, connections) = serve(vec![
+            (Duration::ZERO, status(503, "")),
+            (Duration::ZERO, status(500, "")),
+            (Duration::ZERO, status(200, "ok")),
+        ]);
+        let (client, policy) = quick();
+        let response = get_with_retry(&client, url(&address), policy).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retries_are_bounded() {
+        let (address, connections) = serve(vec![(Duration::ZERO, status(502, "")); 4]);
+        let (client, policy) = quick();
+        let response = get_with_retry(&client, url(&address), policy).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let (address, connections) = serve(vec![(Duration::ZERO, status(404, "")); 2]);
+        let (client, policy) = quick();
+        let response = get_with_retry(&client, url(&address), policy).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hung_server_times_out_then_recovers() {
+        let (address, connections) = serve(vec![
+            (Duration::from_secs(2), status(200, "late")),
+            (Duration::ZERO, status(200, "ok")),
+        ]);
+        let (client, policy) = quick();
+        let response = get_with_retry(&client, url(&address), policy).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+        let (address, _) = serve(vec![(Duration::from_secs(2), status(200, "late")); 3]);
+        let err = get_with_retry(&client, url(&address), policy).await.unwrap_err();
+        assert!(err.is_timeout(), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_slow_response_within_the_timeout() {
+        let (address, connections) = serve(vec![(Duration::from_millis(100), status(200, "slow"))]);
+        let (client, policy) = quick();
+        let response = get_with_retry(&client, url(&address), policy).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "slow");
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy { attempts: 5, base_delay: Duration::from_millis(100), max_delay: Duration::from_millis(300) };
+        let within = |retry: u32, backoff: u64| {
+            let delay = policy.delay(retry);
+            delay >= Duration::from_millis(backoff) && delay <= Duration::from_millis(backoff * 3 / 2)
+        };
+        assert!(within(0, 100));
+        assert!(within(1, 200));
+        assert!(within(2, 300));
+        assert!(within(30, 300));
+    }
+
+    #[test]
+    fn test_reachability() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().to_string();
+        assert!(is_reachable(&open, Duration::from_secs(1)));
+
+        drop(listener);
+        assert!(!is_reachable(&open, Duration::from_secs(1)));
+        assert!(!is_reachable("not a host", Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_proxy_settings() {
+        assert!(ProxySettings::default().proxy().unwrap().is_none());
+
+        let settings = ProxySettings {
+            url: Some("http://proxy.example.com:3128".to_string()),
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+        };
+        assert!(settings.proxy().unwrap().is_some());
+        assert_eq!(settings.address().as_deref(), Some("proxy.example.com:3128"));
+        assert_eq!(
+            settings.info(),
+            ProxySettingsInfo { url: settings.url.clone(), username: Some("user".to_string()), has_password: true }
+        );
+
+        for url in ["proxy.example.com:3128", "ftp://proxy.example.com", "http://"] {
+            let settings = ProxySettings { url: Some(url.to_string()), ..Default::default() };
+            assert!(settings.proxy().is_err(), "{}", url);
+        }
+    }
+}
@@ -9,7 +9,7 @@ pub struct IBusConfig {
     pub config_path: String,
 }
 
-const IBUS_CONFIG_PATH: &str = "/usr/share/ibus/component/keymagic3.xml";
+pub(crate) const IBUS_CONFIG_PATH: &str = "/usr/share/ibus/component/keymagic3.xml";
 
 /// Get IBus configuration information
 #[tauri::command]
@@ -4,6 +4,8 @@ use std::fs;
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Manager};
 
+use crate::version::Version;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InstallResult {
     pub success: bool,
@@ -22,7 +24,7 @@ pub struct IMKInfo {
 }
 
 /// Get the path to the IMK bundle in the user's Input Methods directory
-fn get_user_imk_path() -> PathBuf {
+pub(crate) fn get_user_imk_path() -> PathBuf {
     let home = std::env::var("HOME").unwrap_or_else(|_| "/".to_string());
     PathBuf::from(home)
         .join("Library")
@@ -85,7 +87,7 @@ pub fn check_imk_status(app: AppHandle) -> Result<IMKInfo, String> {
         if embedded_imk_path.exists() {
             if let Some(embedded_version) = read_bundle_version(&embedded_imk_path) {
                 match &installed_version {
-                    Some(installed) => compare_versions(installed, &embedded_version) < 0,
+                    Some(installed) => is_older(installed, &embedded_version),
                     None => true, // If we can't read installed version, assume update is needed
                 }
             } else {
@@ -109,29 +111,13 @@ pub fn check_imk_status(app: AppHandle) -> Result<IMKInfo, String> {
     })
 }
 
-/// Compare semantic versions (returns -1 if v1 < v2, 0 if equal, 1 if v1 > v2)
-fn compare_versions(v1: &str, v2: &str) -> i32 {
-    let v1_parts: Vec<u32> = v1
-        .split('.')
-        .filter_map(|s| s.parse().ok())
-        .collect();
-    let v2_parts: Vec<u32> = v2
-        .split('.')
-        .filter_map(|s| s.parse().ok())
-        .collect();
-    
-    for i in 0..std::cmp::max(v1_parts.len(), v2_parts.len()) {
-        let p1 = v1_parts.get(i).unwrap_or(&0);
-        let p2 = v2_parts.get(i).unwrap_or(&0);
-        
-        if p1 < p2 {
-            return -1;
-        } else if p1 > p2 {
-            return 1;
-        }
+/// Whether the installed bundle is older than the embedded one; when either
+/// version can't be parsed, any difference counts as needing an update
+fn is_older(installed: &str, embedded: &str) -> bool {
+    match (Version::parse(installed), Version::parse(embedded)) {
+        (Ok(installed), Ok(embedded)) => installed < embedded,
+        _ => installed.trim() != embedded.trim(),
     }
-    
-    0
 }
 
 /// Check if KeyMagic is enabled in system input sources
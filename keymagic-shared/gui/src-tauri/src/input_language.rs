@@ -0,0 +1,61 @@
+//! Input language of the foreground window on Windows
+//!
+//! Every thread has its own keyboard layout, so the language the user
+//! typed in last is the layout of the foreground window's thread. It is
+//! polled rather than hooked: `WM_INPUTLANGCHANGE` only reaches the window
+//! whose language changed, not ours.
+
+use crate::core::language_activation::LanguageDebouncer;
+use crate::core::InputLanguage;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use windows::Win32::UI::Input::KeyboardAndMouse::GetKeyboardLayout;
+use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+static REAPPLY: AtomicBool = AtomicBool::new(false);
+
+/// Input language of the foreground window, `None` when no window has
+/// focus or it belongs to this process (the settings window)
+fn foreground_language() -> Option<InputLanguage> {
+    unsafe {
+        let hwnd = GetForegroundWindow();
+        if hwnd.0.is_null() {
+            return None;
+        }
+        let mut process_id = 0u32;
+        let thread_id = GetWindowThreadProcessId(hwnd, Some(&mut process_id));
+        if thread_id == 0 || process_id == std::process::id() {
+            return None;
+        }
+        let langid = (GetKeyboardLayout(thread_id).0 as usize & 0xFFFF) as u16;
+        Some(InputLanguage::new(langid, crate::windows_languages::lcid_to_language_code(langid)))
+    }
+}
+
+/// Hands the current language to the watcher again, for when the rules or
+/// the on/off switch changed
+pub fn reapply() {
+    REAPPLY.store(true, Ordering::Relaxed);
+}
+
+/// Calls `on_language` from a background thread with every foreground
+/// input language that stayed put long enough to act on
+pub fn watch(on_language: impl Fn(&InputLanguage) + Send + 'static) {
+    std::thread::spawn(move || {
+        let mut debouncer = LanguageDebouncer::default();
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if REAPPLY.swap(false, Ordering::Relaxed) {
+                debouncer.reset();
+            }
+            let Some(language) = foreground_language() else {
+                continue;
+            };
+            if let Some(settled) = debouncer.observe(language, Instant::now()) {
+                on_language(&settled);
+            }
+        }
+    });
+}
@@ -0,0 +1,295 @@
+//! Input latency seen by the text services, and a warning when it stays high
+//!
+//! Text services time a sample of keys into histogram counters shared with
+//! the GUI, see `keymagic_core::input_latency`. The GUI reports percentiles
+//! since it started, for the settings page and diagnostic bundles, and
+//! watches the 95th percentile of each poll interval: when it stays above
+//! the warning threshold for [`SUSTAINED_FOR`], the HUD suggests running
+//! diagnostics, once per run of the GUI. Nothing is written to disk.
+
+use keymagic_core::input_latency::{InputLatency, LatencyHistogram, LatencySummary, SAMPLE_EVERY};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Setting holding the warning threshold in milliseconds
+pub const THRESHOLD_SETTING: &str = "latency_warning_ms";
+pub const DEFAULT_THRESHOLD_MS: u64 = 50;
+
+/// How long the 95th percentile must stay above the threshold to warn
+pub const SUSTAINED_FOR: Duration = Duration::from_secs(5 * 60);
+
+/// Intervals with fewer timed keys say nothing about latency
+const MIN_INTERVAL_SAMPLES: u64 = 5;
+
+/// Percent of the threshold an interval must drop below to end a slow
+/// stretch, so latency hovering at the threshold does not restart it
+const CLEAR_PERCENT: u64 = 75;
+
+/// Message shown when latency stays high
+pub const WARNING_MESSAGE: &str = "Typing seems slow lately. Run diagnostics from KeyMagic's settings to find out why.";
+
+/// Decides when sustained high latency is worth a warning
+#[derive(Debug, Clone)]
+pub struct LatencyWatchdog {
+    threshold_us: u64,
+    sustained_for: Duration,
+    /// Start of the current slow stretch
+    slow_since: Option<Instant>,
+    warned: bool,
+}
+
+impl LatencyWatchdog {
+    pub fn new(threshold_ms: u64, sustained_for: Duration) -> Self {
+        Self { threshold_us: threshold_ms.saturating_mul(1000), sustained_for, slow_since: None, warned: false }
+    }
+
+    pub fn threshold_ms(&self) -> u64 {
+        self.threshold_us / 1000
+    }
+
+    /// Changes the threshold; a slow stretch under way starts over
+    pub fn set_threshold_ms(&mut self, threshold_ms: u64) {
+        self.threshold_us = threshold_ms.saturating_mul(1000);
+        self.slow_since = None;
+    }
+
+    pub fn has_warned(&self) -> bool {
+        self.warned
+    }
+
+    /// Takes the 95th percentile of an interval ending at `now`, `None` if
+    /// too few keys were timed; returns true the one time it should warn
+    pub fn observe(&mut self, p95_us: Option<u64>, now: Instant) -> bool {
+        let Some(p95_us) = p95_us else {
+            return false;
+        };
+        if self.warned {
+            return false;
+        }
+        if p95_us > self.threshold_us {
+            let since = *self.slow_since.get_or_insert(now);
+            if now.saturating_duration_since(since) >= self.sustained_for {
+                self.warned = true;
+                return true;
+            }
+        } else if p95_us < self.threshold_us / 100 * CLEAR_PERCENT {
+            self.slow_since = None;
+        }
+        false
+    }
+}
+
+/// Latency since the GUI started, for the UI and diagnostics
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InputLatencyStats {
+    /// Whether the text services' counters could be reached
+    pub available: bool,
+    /// One key in this many is timed
+    pub sample_every: u64,
+    pub latency: LatencySummary,
+    pub warning_threshold_ms: u64,
+    /// Whether the slow typing warning was shown
+    pub warned: bool,
+}
+
+#[cfg(target_os = "windows")]
+fn open_latency() -> std::io::Result<InputLatency> {
+    InputLatency::create_shared()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn open_latency() -> std::io::Result<InputLatency> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Input latency is only measured on Windows",
+    ))
+}
+
+struct MonitorState {
+    /// Counts when the GUI started; earlier runs are not reported
+    baseline: LatencyHistogram,
+    /// Counts at the last poll
+    last: LatencyHistogram,
+    watchdog: LatencyWatchdog,
+}
+
+/// The GUI's view of the shared latency counters
+pub struct InputLatencyMonitor {
+    latency: Option<InputLatency>,
+    state: Mutex<MonitorState>,
+}
+
+impl InputLatencyMonitor {
+    /// Attaches to the shared counters; without them nothing is reported
+    pub fn new(threshold_ms: u64) -> Self {
+        let latency = open_latency()
+            .map_err(|e| log::debug!("Input latency unavailable: {}", e))
+            .ok();
+        Self::with_latency(latency, threshold_ms, SUSTAINED_FOR)
+    }
+
+    fn with_latency(latency: Option<InputLatency>, threshold_ms: u64, sustained_for: Duration) -> Self {
+        let baseline = latency.as_ref().map(InputLatency::snapshot).unwrap_or_default();
+        Self {
+            latency,
+            state: Mutex::new(MonitorState {
+                last: baseline.clone(),
+                baseline,
+                watchdog: LatencyWatchdog::new(threshold_ms, sustained_for),
+            }),
+        }
+    }
+
+    pub fn configure(&self, threshold_ms: u64) {
+        self.state.lock().unwrap().watchdog.set_threshold_ms(threshold_ms);
+    }
+
+    pub fn stats(&self) -> InputLatencyStats {
+        let state = self.state.lock().unwrap();
+        let latency = self
+            .latency
+            .as_ref()
+            .map(|latency| latency.snapshot().since(&state.baseline).summary())
+            .unwrap_or_default();
+        InputLatencyStats {
+            available: self.latency.is_some(),
+            sample_every: SAMPLE_EVERY,
+            latency,
+            warning_threshold_ms: state.watchdog.threshold_ms(),
+            warned: state.watchdog.has_warned(),
+        }
+    }
+
+    /// Feeds the interval since the last poll to the watchdog; returns true
+    /// when the warning should be shown
+    fn poll_at(&self, now: Instant) -> bool {
+        let Some(latency) = &self.latency else {
+            return false;
+        };
+        let snapshot = latency.snapshot();
+        let mut state = self.state.lock().unwrap();
+        let interval = snapshot.since(&state.last);
+        state.last = snapshot;
+        let p95 = if interval.count() >= MIN_INTERVAL_SAMPLES { interval.percentile(95.0) } else { None };
+        state.watchdog.observe(p95, now)
+    }
+
+    /// Polls from a background thread until the warning was shown
+    pub fn watch(self: &Arc<Self>, on_slow: impl Fn() + Send + 'static) {
+        if self.latency.is_none() {
+            return;
+        }
+        let monitor = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if monitor.poll_at(Instant::now()) {
+                let stats = monitor.stats();
+                log::warn!("Input latency stayed high: p95 {:?}µs over {} samples", stats.latency.p95_us, stats.latency.samples);
+                on_slow();
+                return;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn watchdog() -> LatencyWatchdog {
+        LatencyWatchdog::new(50, 5 * MINUTE)
+    }
+
+    #[test]
+    fn test_warns_once_after_sustained_latency() {
+        let mut watchdog = watchdog();
+        let start = Instant::now();
+        for minute in 0..5 {
+            assert!(!watchdog.observe(Some(80_000), start + MINUTE * minute));
+        }
+        assert!(watchdog.observe(Some(80_000), start + MINUTE * 5));
+        assert!(watchdog.has_warned());
+        assert!(!watchdog.observe(Some(80_000), start + MINUTE * 20));
+    }
+
+    #[test]
+    fn test_a_fast_interval_starts_over() {
+        let mut watchdog = watchdog();
+        let start = Instant::now();
+        watchdog.observe(Some(80_000), start);
+        watchdog.observe(Some(80_000), start + MINUTE * 4);
+        // Well below the threshold
+        watchdog.observe(Some(10_000), start + MINUTE * 4 + MINUTE / 2);
+        assert!(!watchdog.observe(Some(80_000), start + MINUTE * 5));
+        assert!(!watchdog.observe(Some(80_000), start + MINUTE * 9));
+        assert!(watchdog.observe(Some(80_000), start + MINUTE * 10));
+    }
+
+    #[test]
+    fn test_hovering_near_the_threshold_keeps_the_stretch() {
+        let mut watchdog = watchdog();
+        let start = Instant::now();
+        watchdog.observe(Some(60_000), start);
+        // Under the threshold but above the clear level, or too few keys
+        watchdog.observe(Some(45_000), start + MINUTE * 2);
+        watchdog.observe(None, start + MINUTE * 3);
+        assert!(watchdog.observe(Some(51_000), start + MINUTE * 5));
+    }
+
+    #[test]
+    fn test_changing_the_threshold_starts_over() {
+        let mut watchdog = watchdog();
+        let start = Instant::now();
+        watchdog.observe(Some(80_000), start);
+        watchdog.set_threshold_ms(70);
+        assert_eq!(watchdog.threshold_ms(), 70);
+        assert!(!watchdog.observe(Some(80_000), start + MINUTE * 5));
+        assert!(watchdog.observe(Some(80_000), start + MINUTE * 10));
+    }
+
+    #[test]
+    fn test_monitor_reports_this_run_and_watches_intervals() {
+        let latency = InputLatency::in_memory();
+        // Before the GUI started
+        latency.record(900_000);
+        let monitor = InputLatencyMonitor::with_latency(Some(latency), 50, 5 * MINUTE);
+        let shared = monitor.latency.as_ref().unwrap();
+        for _ in 0..10 {
+            shared.record(2_000);
+        }
+
+        let stats = monitor.stats();
+        assert!(stats.available);
+        assert_eq!(stats.latency.samples, 10);
+        assert_eq!(stats.latency.max_us, Some(2_047));
+        assert_eq!(stats.warning_threshold_ms, 50);
+
+        let start = Instant::now();
+        assert!(!monitor.poll_at(start));
+        for minute in 1..=6 {
+            for _ in 0..10 {
+                shared.record(120_000);
+            }
+            // Too few keys in between do not end the stretch
+            shared.record(1_000);
+            assert_eq!(monitor.poll_at(start + MINUTE * minute), minute == 6, "minute {}", minute);
+            assert!(!monitor.poll_at(start + MINUTE * minute + MINUTE / 2));
+        }
+        assert!(monitor.stats().warned);
+    }
+
+    #[test]
+    fn test_monitor_without_counters() {
+        let monitor = InputLatencyMonitor::with_latency(None, 50, SUSTAINED_FOR);
+        assert!(!monitor.poll_at(Instant::now()));
+        let stats = monitor.stats();
+        assert!(!stats.available);
+        assert_eq!(stats.latency, LatencySummary::default());
+    }
+}
@@ -0,0 +1,213 @@
+//! Effective input mode of the foreground application
+//!
+//! The text service of the focused application decides between composition
+//! and direct mode with `keymagic_core::input_mode::resolve_input_mode` and
+//! publishes the decision to shared memory. The GUI watches it so the
+//! settings page can show which mode an application gets and which host
+//! list entry caused it. Only Windows publishes a mode.
+
+use keymagic_core::input_mode::{EffectiveInputMode, InputMode, InputModeState};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Effective mode with a line for the UI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InputModeInfo {
+    #[serde(flatten)]
+    pub effective: EffectiveInputMode,
+    /// e.g. "chrome.exe → Direct mode via rule '*chrome*'"
+    pub summary: String,
+}
+
+impl From<EffectiveInputMode> for InputModeInfo {
+    fn from(effective: EffectiveInputMode) -> Self {
+        let summary = describe(&effective);
+        Self { effective, summary }
+    }
+}
+
+/// One line explaining the mode of an application
+pub fn describe(effective: &EffectiveInputMode) -> String {
+    let mode = match effective.resolution.mode {
+        InputMode::Composition => "Composition mode",
+        InputMode::Direct => "Direct mode",
+    };
+    let reason = match &effective.resolution.matched {
+        Some(matched) => format!("via rule '{}'", matched.pattern),
+        None => "by default".to_string(),
+    };
+    format!("{} → {} {}", effective.process, mode, reason)
+}
+
+#[cfg(target_os = "windows")]
+fn open_state() -> std::io::Result<InputModeState> {
+    InputModeState::create_shared()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn open_state() -> std::io::Result<InputModeState> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "The effective input mode is only published on Windows",
+    ))
+}
+
+/// The GUI's view of the published mode
+///
+/// Modes published by the GUI's own process are ignored: while the settings
+/// page has focus it should keep showing the application used before.
+pub struct InputModeMonitor {
+    state: Option<InputModeState>,
+    own_process_id: u32,
+    last: Mutex<Option<EffectiveInputMode>>,
+}
+
+impl InputModeMonitor {
+    /// Attaches to the shared block; without one the mode is never known
+    pub fn new() -> Self {
+        let state = open_state()
+            .map_err(|e| log::debug!("Input mode monitoring unavailable: {}", e))
+            .ok();
+        Self::with_state(state, std::process::id())
+    }
+
+    fn with_state(state: Option<InputModeState>, own_process_id: u32) -> Self {
+        Self {
+            state,
+            own_process_id,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Mode of the last application other than the GUI that had focus
+    pub fn current(&self) -> Option<InputModeInfo> {
+        self.refresh();
+        self.last.lock().unwrap().clone().map(InputModeInfo::from)
+    }
+
+    /// Reads the block; returns the mode if it changed since the last read
+    fn refresh(&self) -> Option<EffectiveInputMode> {
+        let published = self.state.as_ref()?.read()?;
+        if published.process_id == self.own_process_id {
+            return None;
+        }
+        let mut last = self.last.lock().unwrap();
+        let changed = last.as_ref().is_none_or(|last| {
+            last.process_id != published.process_id
+                || last.process != published.process
+                || last.resolution != published.resolution
+        });
+        *last = Some(published.clone());
+        changed.then_some(published)
+    }
+
+    /// Calls `on_change` from a background thread whenever another
+    /// application publishes a different mode
+    pub fn watch(self: &Arc<Self>, on_change: impl Fn(InputModeInfo) + Send + 'static) {
+        let Some(mut sequence) = self.state.as_ref().map(InputModeState::sequence) else {
+            return;
+        };
+        let monitor = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let current = monitor.state.as_ref().map_or(sequence, InputModeState::sequence);
+            if current == sequence {
+                continue;
+            }
+            sequence = current;
+            if let Some(changed) = monitor.refresh() {
+                on_change(changed.into());
+            }
+        });
+    }
+}
+
+impl Default for InputModeMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keymagic_core::input_mode::{resolve_input_mode, HostList, HostMatch, InputModeResolution};
+
+    fn effective(process_id: u32, process: &str, resolution: InputModeResolution) -> EffectiveInputMode {
+        EffectiveInputMode {
+            process_id,
+            process: process.to_string(),
+            resolution,
+            updated_at_ms: 0,
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        let resolution = resolve_input_mode("chrome.exe", &["excel.exe"], &["*chrome*"], InputMode::Composition);
+        assert_eq!(
+            describe(&effective(1, "chrome.exe", resolution)),
+            "chrome.exe → Direct mode via rule '*chrome*'"
+        );
+
+        let resolution = InputModeResolution {
+            mode: InputMode::Composition,
+            matched: Some(HostMatch { list: HostList::Composition, pattern: "excel.exe".into() }),
+        };
+        assert_eq!(
+            describe(&effective(1, "excel.exe", resolution)),
+            "excel.exe → Composition mode via rule 'excel.exe'"
+        );
+
+        let resolution = InputModeResolution { mode: InputMode::Direct, matched: None };
+        assert_eq!(describe(&effective(1, "notepad.exe", resolution)), "notepad.exe → Direct mode by default");
+    }
+
+    #[test]
+    fn test_info_serialization() {
+        let resolution = resolve_input_mode("chrome.exe", &[] as &[&str], &["*chrome*"], InputMode::Composition);
+        let info = InputModeInfo::from(effective(7, "chrome.exe", resolution));
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["process_id"], 7);
+        assert_eq!(json["process"], "chrome.exe");
+        assert_eq!(json["mode"], "direct");
+        assert_eq!(json["matched"]["list"], "direct");
+        assert_eq!(json["matched"]["pattern"], "*chrome*");
+        assert_eq!(json["summary"], "chrome.exe → Direct mode via rule '*chrome*'");
+    }
+
+    #[test]
+    fn test_monitor_ignores_own_process() {
+        let state = InputModeState::in_memory();
+        let direct = InputModeResolution { mode: InputMode::Direct, matched: None };
+        state.publish(&effective(2, "chrome.exe", direct.clone()));
+        let monitor = InputModeMonitor::with_state(Some(state), 1);
+        assert_eq!(monitor.current().unwrap().effective.process, "chrome.exe");
+
+        // The GUI gets focus; the page keeps showing the previous application
+        monitor.state.as_ref().unwrap().publish(&effective(1, "keymagic.exe", direct));
+        assert_eq!(monitor.current().unwrap().effective.process, "chrome.exe");
+    }
+
+    #[test]
+    fn test_monitor_reports_changes_once() {
+        let state = InputModeState::in_memory();
+        let monitor = InputModeMonitor::with_state(Some(state), 1);
+        assert_eq!(monitor.refresh(), None);
+
+        let direct = InputModeResolution { mode: InputMode::Direct, matched: None };
+        let published = effective(2, "chrome.exe", direct);
+        monitor.state.as_ref().unwrap().publish(&published);
+        assert_eq!(monitor.refresh(), Some(published.clone()));
+        assert_eq!(monitor.refresh(), None);
+    }
+
+    #[test]
+    fn test_monitor_without_state() {
+        let monitor = InputModeMonitor::with_state(None, 1);
+        assert_eq!(monitor.current(), None);
+    }
+}
@@ -0,0 +1,273 @@
+//! Input event recording for bug reports
+//!
+//! The GUI owns the shared ring the text services append to (see
+//! `keymagic_core::recorder`), turns recording on and off, and exports the
+//! ring as a JSON file users can attach to an issue.
+//!
+//! Exports list the processes of the recording with their display names, so
+//! users can check the application they report was captured. Diagnostic
+//! bundles leave the names out unless the user agrees to include them.
+
+use crate::temp_file;
+use anyhow::{Context, Result};
+use keymagic_core::recorder::{InputRecord, InputRecorder, ProcessEntry, ProcessSummary, RecordingSnapshot};
+use keymagic_core::VirtualKey;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Identifies exported files
+const EXPORT_FORMAT: &str = "keymagic-input-recording";
+const EXPORT_VERSION: u32 = 1;
+
+/// Recording state shown in the UI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RecordingStatus {
+    pub recording: bool,
+    pub verbose: bool,
+    pub capacity: usize,
+    pub total_recorded: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedRecord<'a> {
+    #[serde(flatten)]
+    record: &'a InputRecord,
+    /// KMS name of the key, when KeyMagic knows it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedProcess<'a> {
+    process_hash: u32,
+    first_seen_ms: u64,
+    /// Display name, left out when redacted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    is_uwp: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct RecordingExport<'a> {
+    format: &'static str,
+    version: u32,
+    app_version: &'a str,
+    os: &'static str,
+    exported_at_ms: u64,
+    capacity: usize,
+    verbose: bool,
+    started_at_ms: u64,
+    total_recorded: u64,
+    dropped: u64,
+    records: Vec<ExportedRecord<'a>>,
+    processes: Vec<ExportedProcess<'a>>,
+}
+
+/// JSON document for a snapshot; without `reveal_process_names` the
+/// processes are listed by hash only
+pub fn export_json(snapshot: &RecordingSnapshot, app_version: &str, exported_at_ms: u64, reveal_process_names: bool) -> Result<String> {
+    let export = RecordingExport {
+        format: EXPORT_FORMAT,
+        version: EXPORT_VERSION,
+        app_version,
+        os: std::env::consts::OS,
+        exported_at_ms,
+        capacity: snapshot.capacity,
+        verbose: snapshot.verbose,
+        started_at_ms: snapshot.started_at_ms,
+        total_recorded: snapshot.total_recorded,
+        dropped: snapshot.dropped,
+        records: snapshot
+            .records
+            .iter()
+            .map(|record| ExportedRecord {
+                record,
+                key: VirtualKey::from_win_vk(record.vk).map(|key| key.to_kms_name()),
+            })
+            .collect(),
+        processes: snapshot
+            .processes
+            .iter()
+            .map(|process: &ProcessEntry| ExportedProcess {
+                process_hash: process.process_hash,
+                first_seen_ms: process.first_seen_ms,
+                name: reveal_process_names.then_some(process.name.as_str()),
+                is_uwp: process.is_uwp,
+            })
+            .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&export)?)
+}
+
+#[cfg(target_os = "windows")]
+fn open_recorder() -> std::io::Result<InputRecorder> {
+    InputRecorder::create_shared(keymagic_core::recorder::DEFAULT_CAPACITY)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn open_recorder() -> std::io::Result<InputRecorder> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Input recording is only available on Windows",
+    ))
+}
+
+/// The GUI's handle to the shared ring, created on first use and kept for
+/// the lifetime of the app so the ring survives between recordings
+#[derive(Default)]
+pub struct InputRecording {
+    recorder: Mutex<Option<InputRecorder>>,
+}
+
+impl InputRecording {
+    fn with_recorder<T>(&self, f: impl FnOnce(&InputRecorder) -> T) -> Result<T> {
+        let mut recorder = self.recorder.lock().unwrap();
+        if recorder.is_none() {
+            *recorder = Some(open_recorder().context("Failed to set up input recording")?);
+        }
+        Ok(f(recorder.as_ref().unwrap()))
+    }
+
+    /// Starts a new recording; earlier records are discarded
+    pub fn start(&self, verbose: bool) -> Result<RecordingStatus> {
+        self.with_recorder(|recorder| {
+            recorder.clear();
+            recorder.start(verbose);
+            status(recorder)
+        })
+    }
+
+    pub fn stop(&self) -> Result<RecordingStatus> {
+        self.with_recorder(|recorder| {
+            recorder.stop();
+            status(recorder)
+        })
+    }
+
+    pub fn status(&self) -> Option<RecordingStatus> {
+        self.recorder.lock().unwrap().as_ref().map(status)
+    }
+
+    /// Writes the records in the ring to `path`; recording continues. The
+    /// user exports for themselves, so process names are kept.
+    pub fn export(&self, path: &Path, app_version: &str) -> Result<usize> {
+        let snapshot = self.with_recorder(|recorder| recorder.snapshot())?;
+        let json = export_json(&snapshot, app_version, now_ms(), true)?;
+        temp_file::write_atomic(path, json)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(snapshot.records.len())
+    }
+
+    /// The records in the ring as an export, `None` when nothing was recorded
+    /// this session. Unlike `export` this never sets up the ring.
+    pub fn last_recording_json(&self, app_version: &str, reveal_process_names: bool) -> Result<Option<String>> {
+        let snapshot = match self.recorder.lock().unwrap().as_ref() {
+            Some(recorder) => recorder.snapshot(),
+            None => return Ok(None),
+        };
+        if snapshot.records.is_empty() {
+            return Ok(None);
+        }
+        export_json(&snapshot, app_version, now_ms(), reveal_process_names).map(Some)
+    }
+
+    /// Events per process in the ring, most first; empty when nothing was
+    /// recorded this session. Never sets up the ring.
+    pub fn process_summary(&self) -> Vec<ProcessSummary> {
+        self.recorder
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|recorder| recorder.snapshot().process_summary())
+            .unwrap_or_default()
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn status(recorder: &InputRecorder) -> RecordingStatus {
+    let snapshot = recorder.snapshot();
+    RecordingStatus {
+        recording: snapshot.recording,
+        verbose: snapshot.verbose,
+        capacity: snapshot.capacity,
+        total_recorded: snapshot.total_recorded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keymagic_core::recorder::{hash_process_name, KeyEventRecord, MOD_CTRL};
+
+    fn recorded(verbose: bool) -> RecordingSnapshot {
+        let recorder = InputRecorder::in_memory(64);
+        recorder.start(verbose);
+        recorder.record(&KeyEventRecord {
+            process_hash: recorder.note_process("C:\\Program Files\\Google\\chrome.exe", false),
+            vk: 0x4B,
+            modifiers: MOD_CTRL,
+            action_type: 1,
+            is_processed: true,
+            delete_count: 0,
+            composing_text: "က",
+        });
+        recorder.snapshot()
+    }
+
+    #[test]
+    fn test_export_without_text() {
+        let json: serde_json::Value = serde_json::from_str(&export_json(&recorded(false), "0.0.9", 42, true).unwrap()).unwrap();
+        assert_eq!(json["format"], EXPORT_FORMAT);
+        assert_eq!(json["exported_at_ms"], 42);
+        assert_eq!(json["verbose"], false);
+        let record = &json["records"][0];
+        assert_eq!(record["seq"], 1);
+        assert_eq!(record["vk"], 0x4B);
+        assert_eq!(record["key"], "VK_KEY_K");
+        assert_eq!(record["modifiers"], MOD_CTRL);
+        assert_eq!(record["composing_len"], 1);
+        assert!(record.get("text").is_none());
+    }
+
+    #[test]
+    fn test_export_verbose_text() {
+        let json: serde_json::Value = serde_json::from_str(&export_json(&recorded(true), "0.0.9", 0, true).unwrap()).unwrap();
+        assert_eq!(json["verbose"], true);
+        assert_eq!(json["records"][0]["text"], "က");
+    }
+
+    #[test]
+    fn test_export_lists_processes() {
+        let json: serde_json::Value = serde_json::from_str(&export_json(&recorded(false), "0.0.9", 0, true).unwrap()).unwrap();
+        let process = &json["processes"][0];
+        assert_eq!(process["process_hash"], hash_process_name("C:\\Program Files\\Google\\chrome.exe"));
+        assert_eq!(process["name"], "chrome");
+        assert_eq!(process["is_uwp"], false);
+        assert_eq!(json["records"][0]["process_hash"], process["process_hash"]);
+    }
+
+    #[test]
+    fn test_redacted_export_leaves_names_out() {
+        let json: serde_json::Value = serde_json::from_str(&export_json(&recorded(false), "0.0.9", 0, false).unwrap()).unwrap();
+        let process = &json["processes"][0];
+        assert!(process.get("name").is_none());
+        assert!(process["process_hash"].is_u64());
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_unsupported_without_shared_ring() {
+        let recording = InputRecording::default();
+        assert!(recording.start(false).is_err());
+        assert_eq!(recording.status(), None);
+        assert_eq!(recording.last_recording_json("0.0.9", false).unwrap(), None);
+        assert!(recording.process_summary().is_empty());
+    }
+}
@@ -0,0 +1,678 @@
+//! Install verification and provisioning for package managers
+//!
+//! `keymagic --verify-install [--json]` checks what an installer should have
+//! left in place: the input method registered with the system (TSF, IMK or
+//! IBus), binaries built for this machine, the bundled keyboards, a writable
+//! config folder and an input method from the same release as the app. Each
+//! check reports pass, fail or skipped; the exit code is 0 when none failed
+//! and 1 otherwise. `--json` prints the report as one JSON object for CI.
+//! Diagnostic bundles include the same report.
+//!
+//! `keymagic --silent-first-run` does what the first start does without a
+//! window: saves the default settings and imports the new and updated
+//! bundled keyboards, so the import wizard is not offered again. It exits
+//! with 1 when a keyboard failed to import.
+//!
+//! Neither needs an interactive session, only the user whose settings they
+//! check or write.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::core::KeyboardManager;
+use crate::platform::Platform;
+use crate::version::Version;
+
+/// The text service's COM class, as `language_profiles` registers it
+#[cfg(target_os = "windows")]
+const TEXT_SERVICE_CLSID: &str = "{094A562B-D08B-4CAF-8E95-8F8031CFD24C}";
+
+/// Bytes read from a binary to find its architectures
+const HEADER_BYTES: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    /// Nothing to check on this platform or install
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    pub check: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(check: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self { check, status, detail: detail.into() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallReport {
+    pub app_version: String,
+    pub os: &'static str,
+    /// Architecture of the machine, which may differ from this build's
+    pub arch: &'static str,
+    pub passed: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl InstallReport {
+    fn new(checks: Vec<CheckResult>) -> Self {
+        Self {
+            app_version: Version::current().to_string(),
+            os: std::env::consts::OS,
+            arch: host_architecture(),
+            passed: checks.iter().all(|check| check.status != CheckStatus::Fail),
+            checks,
+        }
+    }
+
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|check| check.status == CheckStatus::Fail).count()
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self.passed { 0 } else { 1 }
+    }
+}
+
+impl fmt::Display for InstallReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Pass => "pass",
+                CheckStatus::Fail => "FAIL",
+                CheckStatus::Skipped => "skipped",
+            };
+            writeln!(f, "{}: {}: {}", check.check, status, check.detail)?;
+        }
+        write!(f, "{} of {} checks failed", self.failures(), self.checks.len())
+    }
+}
+
+/// Where the system finds the input method
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputMethodInstall {
+    /// Where it is registered, for the report
+    pub registration: String,
+    pub binaries: Vec<PathBuf>,
+    /// Release it came from, `None` where it does not say
+    pub version: Option<String>,
+}
+
+/// Runs every check; `platform` is only missing when the CLI could not
+/// create it, which fails the checks that need it
+pub fn verify_install(platform: Result<&dyn Platform>) -> InstallReport {
+    let input_method = find_input_method();
+
+    let mut binaries = app_binaries();
+    if let Ok(input_method) = &input_method {
+        binaries.extend(input_method.binaries.iter().cloned());
+    }
+
+    let mut checks = vec![
+        check_input_method(&input_method),
+        check_architecture(host_architecture(), &binaries),
+    ];
+    match platform {
+        Ok(platform) => {
+            checks.push(check_bundled_keyboards(platform.get_bundled_keyboards_path().as_deref()));
+            checks.push(check_writable(&[platform.get_config_dir(), platform.get_keyboards_dir()]));
+        }
+        Err(e) => {
+            let detail = format!("Platform backend is not available: {:#}", e);
+            checks.push(CheckResult::new("bundled_keyboards", CheckStatus::Fail, detail.clone()));
+            checks.push(CheckResult::new("config_writable", CheckStatus::Fail, detail));
+        }
+    }
+    let installed_version = input_method.as_ref().ok().and_then(|input_method| input_method.version.as_deref());
+    checks.push(check_version(&Version::current(), installed_version));
+
+    InstallReport::new(checks)
+}
+
+fn check_input_method(found: &Result<InputMethodInstall>) -> CheckResult {
+    match found {
+        Ok(input_method) => CheckResult::new("input_method", CheckStatus::Pass, input_method.registration.clone()),
+        Err(e) => CheckResult::new("input_method", CheckStatus::Fail, format!("{:#}", e)),
+    }
+}
+
+/// Every binary must run natively on a `host` machine; a universal binary
+/// passes when one of its slices does
+fn check_architecture(host: &str, binaries: &[PathBuf]) -> CheckResult {
+    let mut problems = Vec::new();
+    for binary in binaries {
+        match read_header(binary) {
+            Ok(header) => {
+                let architectures = binary_architectures(&header);
+                if architectures.is_empty() {
+                    problems.push(format!("{} is not a known executable format", binary.display()));
+                } else if !architectures.contains(&host) {
+                    problems.push(format!("{} is built for {}", binary.display(), architectures.join(", ")));
+                }
+            }
+            Err(e) => problems.push(format!("{}: {}", binary.display(), e)),
+        }
+    }
+
+    if problems.is_empty() {
+        let names: Vec<String> = binaries
+            .iter()
+            .map(|binary| binary.file_name().unwrap_or(binary.as_os_str()).to_string_lossy().into_owned())
+            .collect();
+        CheckResult::new("architecture", CheckStatus::Pass, format!("{}: {}", host, names.join(", ")))
+    } else {
+        CheckResult::new("architecture", CheckStatus::Fail, format!("{} machine: {}", host, problems.join("; ")))
+    }
+}
+
+fn read_header(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut header = Vec::new();
+    fs::File::open(path)?.take(HEADER_BYTES).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Architectures a PE, ELF or Mach-O binary is built for, named as
+/// `std::env::consts::ARCH` names them; empty for anything else
+pub fn binary_architectures(header: &[u8]) -> Vec<&'static str> {
+    let u16_le = |at: usize| header.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_le = |at: usize| header.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let u32_be = |at: usize| header.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+
+    match header {
+        [b'M', b'Z', ..] => {
+            // The PE header is found through the DOS header
+            let machine = u32_le(0x3C)
+                .map(|offset| offset as usize)
+                .filter(|&offset| header.get(offset..offset + 4) == Some(b"PE\0\0"))
+                .and_then(|offset| u16_le(offset + 4));
+            machine.and_then(pe_machine_architecture).into_iter().collect()
+        }
+        [0x7F, b'E', b'L', b'F', ..] => {
+            let machine = match header.get(5) {
+                Some(1) => u16_le(18),
+                Some(2) => header.get(18..20).map(|b| u16::from_be_bytes([b[0], b[1]])),
+                _ => None,
+            };
+            let architecture = match machine {
+                Some(0x03) => Some("x86"),
+                Some(0x3E) => Some("x86_64"),
+                Some(0xB7) => Some("aarch64"),
+                _ => None,
+            };
+            architecture.into_iter().collect()
+        }
+        // Thin 64-bit Mach-O, little endian
+        [0xCF, 0xFA, 0xED, 0xFE, ..] => u32_le(4).and_then(mach_cpu_architecture).into_iter().collect(),
+        // Universal binary: a big endian table of slices
+        [0xCA, 0xFE, 0xBA, 0xBE, ..] | [0xCA, 0xFE, 0xBA, 0xBF, ..] => {
+            let entry_size = if header[3] == 0xBE { 20 } else { 32 };
+            let count = u32_be(4).unwrap_or(0) as usize;
+            (0..count)
+                .map_while(|slice| u32_be(8 + slice * entry_size))
+                .filter_map(mach_cpu_architecture)
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn pe_machine_architecture(machine: u16) -> Option<&'static str> {
+    match machine {
+        0x014C => Some("x86"),
+        0x8664 => Some("x86_64"),
+        0xAA64 => Some("aarch64"),
+        _ => None,
+    }
+}
+
+fn mach_cpu_architecture(cpu_type: u32) -> Option<&'static str> {
+    match cpu_type {
+        0x0000_0007 => Some("x86"),
+        0x0100_0007 => Some("x86_64"),
+        0x0100_000C => Some("aarch64"),
+        _ => None,
+    }
+}
+
+/// The bundled keyboards folder must hold at least one keyboard
+fn check_bundled_keyboards(path: Option<&Path>) -> CheckResult {
+    let Some(path) = path else {
+        return CheckResult::new("bundled_keyboards", CheckStatus::Fail, "No bundled keyboards folder found");
+    };
+    let count = fs::read_dir(path).map(|entries| {
+        entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().and_then(|e| e.to_str()) == Some("km2"))
+            .count()
+    });
+    match count {
+        Ok(0) => CheckResult::new("bundled_keyboards", CheckStatus::Fail, format!("No keyboards in {}", path.display())),
+        Ok(count) => CheckResult::new("bundled_keyboards", CheckStatus::Pass, format!("{} keyboards in {}", count, path.display())),
+        Err(e) => CheckResult::new("bundled_keyboards", CheckStatus::Fail, format!("{}: {}", path.display(), e)),
+    }
+}
+
+/// Creates the folders if needed and writes and removes a file in each
+fn check_writable(dirs: &[PathBuf]) -> CheckResult {
+    let problems: Vec<String> = dirs
+        .iter()
+        .filter_map(|dir| probe_writable(dir).err().map(|e| format!("{}: {:#}", dir.display(), e)))
+        .collect();
+    if problems.is_empty() {
+        let dirs: Vec<String> = dirs.iter().map(|dir| dir.display().to_string()).collect();
+        CheckResult::new("config_writable", CheckStatus::Pass, dirs.join(", "))
+    } else {
+        CheckResult::new("config_writable", CheckStatus::Fail, problems.join("; "))
+    }
+}
+
+fn probe_writable(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).context("Failed to create the folder")?;
+    let probe = dir.join(format!(".keymagic-write-test-{}", std::process::id()));
+    fs::write(&probe, b"").context("Failed to write a file")?;
+    fs::remove_file(&probe).context("Failed to remove the test file")
+}
+
+/// The input method must come from the same release as the app; a mix
+/// means an upgrade replaced only part of the install
+fn check_version(app: &Version, installed: Option<&str>) -> CheckResult {
+    let Some(installed) = installed else {
+        return CheckResult::new("version", CheckStatus::Skipped, format!("{}; the input method does not report its version", app));
+    };
+    match Version::parse(installed) {
+        Ok(version) if version == *app => CheckResult::new("version", CheckStatus::Pass, app.to_string()),
+        Ok(version) => CheckResult::new("version", CheckStatus::Fail, format!("App is {} but the input method is {}", app, version)),
+        Err(e) => CheckResult::new("version", CheckStatus::Fail, format!("Input method version: {}", e)),
+    }
+}
+
+/// The app's own binaries
+fn app_binaries() -> Vec<PathBuf> {
+    let Ok(exe) = std::env::current_exe() else {
+        return Vec::new();
+    };
+    // The installer puts the tray manager beside the app
+    #[cfg(target_os = "windows")]
+    if let Some(tray) = exe.parent().map(|dir| dir.join("keymagic-tray.exe")).filter(|tray| tray.exists()) {
+        return vec![exe, tray];
+    }
+    vec![exe]
+}
+
+/// Architecture of the machine; a build running under emulation reports
+/// the one it emulates
+#[cfg(target_os = "windows")]
+fn host_architecture() -> &'static str {
+    use windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE;
+    use windows::Win32::System::Threading::{GetCurrentProcess, IsWow64Process2};
+
+    let mut process = IMAGE_FILE_MACHINE::default();
+    let mut native = IMAGE_FILE_MACHINE::default();
+    match unsafe { IsWow64Process2(GetCurrentProcess(), &mut process, Some(&mut native)) } {
+        Ok(()) => pe_machine_architecture(native.0).unwrap_or(std::env::consts::ARCH),
+        Err(_) => std::env::consts::ARCH,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn host_architecture() -> &'static str {
+    // Set on Apple silicon, also for processes under Rosetta
+    let arm64 = std::process::Command::new("/usr/sbin/sysctl")
+        .args(["-n", "hw.optional.arm64"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim() == "1")
+        .unwrap_or(false);
+    if arm64 { "aarch64" } else { std::env::consts::ARCH }
+}
+
+#[cfg(target_os = "linux")]
+fn host_architecture() -> &'static str {
+    std::env::consts::ARCH
+}
+
+/// The text service DLL registered for the COM class; the installer puts
+/// it in a folder named after the release, `TSF\0_0_9`
+#[cfg(target_os = "windows")]
+fn find_input_method() -> Result<InputMethodInstall> {
+    use winreg::{enums::HKEY_CLASSES_ROOT, RegKey};
+
+    let key_path = format!(r"CLSID\{}\InprocServer32", TEXT_SERVICE_CLSID);
+    let key = RegKey::predef(HKEY_CLASSES_ROOT)
+        .open_subkey(&key_path)
+        .map_err(|_| anyhow!("The text service is not registered (HKCR\\{} is missing)", key_path))?;
+    let dll: String = key.get_value("").context("The text service registration has no DLL")?;
+    let dll = PathBuf::from(dll);
+    if !dll.exists() {
+        return Err(anyhow!("The registered text service {} does not exist", dll.display()));
+    }
+    Ok(InputMethodInstall {
+        registration: format!("Text service registered as {}", dll.display()),
+        version: tsf_folder_version(&dll),
+        binaries: vec![dll],
+    })
+}
+
+/// `0.0.9` from `...\TSF\0_0_9\KeyMagicTSF_x64.dll`
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn tsf_folder_version(dll: &Path) -> Option<String> {
+    let folder = dll.parent()?.file_name()?.to_str()?;
+    let version = folder.replace('_', ".");
+    Version::parse(&version).is_ok().then_some(version)
+}
+
+/// The input method bundle the app installs for the user
+#[cfg(target_os = "macos")]
+fn find_input_method() -> Result<InputMethodInstall> {
+    let bundle = crate::imk_installer::get_user_imk_path();
+    if !bundle.exists() {
+        return Err(anyhow!("The input method is not installed at {}", bundle.display()));
+    }
+    let info: plist::Dictionary = plist::from_file(bundle.join("Contents").join("Info.plist"))
+        .context("Failed to read the input method's Info.plist")?;
+    let executable = info
+        .get("CFBundleExecutable")
+        .and_then(|value| value.as_string())
+        .ok_or_else(|| anyhow!("The input method's Info.plist names no executable"))?;
+    Ok(InputMethodInstall {
+        registration: format!("Input method installed at {}", bundle.display()),
+        binaries: vec![bundle.join("Contents").join("MacOS").join(executable)],
+        version: info
+            .get("CFBundleShortVersionString")
+            .and_then(|value| value.as_string())
+            .map(str::to_string),
+    })
+}
+
+/// The IBus component and the engine it starts. The component's version is
+/// not updated with releases, so it is not reported.
+#[cfg(target_os = "linux")]
+fn find_input_method() -> Result<InputMethodInstall> {
+    let component = Path::new(crate::ibus_config::IBUS_CONFIG_PATH);
+    let xml = fs::read_to_string(component)
+        .map_err(|e| anyhow!("The IBus component {} is not installed: {}", component.display(), e))?;
+    let engine = component_exec(&xml).ok_or_else(|| anyhow!("{} names no engine", component.display()))?;
+    if !engine.exists() {
+        return Err(anyhow!("The IBus engine {} does not exist", engine.display()));
+    }
+    Ok(InputMethodInstall {
+        registration: format!("IBus component {} starts {}", component.display(), engine.display()),
+        binaries: vec![engine],
+        version: None,
+    })
+}
+
+/// The program in an IBus component's `<exec>`, without its arguments
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn component_exec(xml: &str) -> Option<PathBuf> {
+    let start = xml.find("<exec>")? + "<exec>".len();
+    let end = start + xml[start..].find("</exec>")?;
+    xml[start..end].split_whitespace().next().map(PathBuf::from)
+}
+
+/// What `--silent-first-run` did
+#[derive(Debug, Default, Serialize)]
+pub struct FirstRunReport {
+    /// Bundled keyboards were offered to this version already
+    pub already_scanned: bool,
+    pub imported: Vec<String>,
+    /// Keyboard names and why they failed
+    pub failed: Vec<(String, String)>,
+}
+
+impl FirstRunReport {
+    pub fn exit_code(&self) -> i32 {
+        if self.failed.is_empty() { 0 } else { 1 }
+    }
+}
+
+impl fmt::Display for FirstRunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.already_scanned {
+            return write!(f, "Bundled keyboards were already imported for this version");
+        }
+        for name in &self.imported {
+            writeln!(f, "Imported {}", name)?;
+        }
+        for (name, error) in &self.failed {
+            writeln!(f, "Failed to import {}: {}", name, error)?;
+        }
+        write!(f, "{} keyboards imported, {} failed", self.imported.len(), self.failed.len())
+    }
+}
+
+/// Saves the default settings and imports the bundled keyboards the import
+/// wizard would preselect: new ones and updates. Keyboards that fail are
+/// reported and the rest still imported; the wizard is not offered after.
+pub fn first_run(manager: &KeyboardManager) -> Result<FirstRunReport> {
+    let platform = manager.get_platform();
+    // Settings never saved load as defaults; saving them writes them out
+    platform.save_config(&platform.load_config()?).context("Failed to save the default settings")?;
+
+    if !manager.should_scan_bundled_keyboards()? {
+        return Ok(FirstRunReport { already_scanned: true, ..Default::default() });
+    }
+    let mut report = FirstRunReport::default();
+    for keyboard in manager.get_bundled_keyboards()? {
+        let update = match keyboard.status.as_str() {
+            "New" => false,
+            "Updated" => true,
+            _ => continue,
+        };
+        match manager.import_bundled_keyboard(Path::new(&keyboard.bundled_path), update) {
+            Ok(_) => report.imported.push(keyboard.name),
+            Err(e) => report.failed.push((keyboard.name, format!("{:#}", e))),
+        }
+    }
+    manager.mark_bundled_keyboards_scanned()?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::MockPlatform;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic_install_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn named(name: &str) -> String {
+        format!("/*\n@NAME = \"{}\"\n*/\n\"k\" => \"က\"", name)
+    }
+
+    /// A PE header with the machine field set
+    fn pe(machine: u16) -> Vec<u8> {
+        let mut header = vec![0; 0x100];
+        header[..2].copy_from_slice(b"MZ");
+        header[0x3C..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        header[0x80..0x84].copy_from_slice(b"PE\0\0");
+        header[0x84..0x86].copy_from_slice(&machine.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn test_binary_architectures() {
+        assert_eq!(binary_architectures(&pe(0x8664)), vec!["x86_64"]);
+        assert_eq!(binary_architectures(&pe(0xAA64)), vec!["aarch64"]);
+        assert_eq!(binary_architectures(&pe(0x01C4)), Vec::<&str>::new());
+
+        let mut elf = vec![0x7F, b'E', b'L', b'F', 2, 1, 1, 0];
+        elf.resize(20, 0);
+        elf[18..20].copy_from_slice(&0xB7u16.to_le_bytes());
+        assert_eq!(binary_architectures(&elf), vec!["aarch64"]);
+
+        let mut thin = vec![0xCF, 0xFA, 0xED, 0xFE];
+        thin.extend(0x0100_0007u32.to_le_bytes());
+        assert_eq!(binary_architectures(&thin), vec!["x86_64"]);
+
+        // Universal binaries list every slice
+        let mut universal = vec![0xCA, 0xFE, 0xBA, 0xBE, 0, 0, 0, 2];
+        for cpu_type in [0x0100_0007u32, 0x0100_000C] {
+            universal.extend(cpu_type.to_be_bytes());
+            universal.extend([0; 16]);
+        }
+        assert_eq!(binary_architectures(&universal), vec!["x86_64", "aarch64"]);
+
+        assert!(binary_architectures(b"#!/bin/sh\n").is_empty());
+        assert!(binary_architectures(b"MZ").is_empty());
+    }
+
+    #[test]
+    fn test_architecture_check() {
+        let dir = test_dir("arch");
+        let app = dir.join("keymagic.exe");
+        let tsf = dir.join("KeyMagicTSF_x64.dll");
+        fs::write(&app, pe(0xAA64)).unwrap();
+        fs::write(&tsf, pe(0x8664)).unwrap();
+
+        let check = check_architecture("aarch64", std::slice::from_ref(&app));
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert_eq!(check.detail, "aarch64: keymagic.exe");
+
+        let check = check_architecture("aarch64", &[app, tsf, dir.join("missing.dll")]);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.detail.contains("KeyMagicTSF_x64.dll is built for x86_64"), "{}", check.detail);
+        assert!(check.detail.contains("missing.dll"), "{}", check.detail);
+        assert!(!check.detail.contains("keymagic.exe"), "{}", check.detail);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bundled_keyboards_check() {
+        assert_eq!(check_bundled_keyboards(None).status, CheckStatus::Fail);
+
+        let dir = test_dir("bundled");
+        assert_eq!(check_bundled_keyboards(Some(&dir)).status, CheckStatus::Fail);
+        fs::write(dir.join("readme.txt"), "").unwrap();
+        fs::write(dir.join("myanmar3.km2"), "").unwrap();
+        let check = check_bundled_keyboards(Some(&dir));
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert!(check.detail.starts_with("1 keyboards in"), "{}", check.detail);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_writable_check_creates_folders_and_cleans_up() {
+        let dir = test_dir("writable");
+        let config = dir.join("config").join("KeyMagic");
+        assert_eq!(check_writable(std::slice::from_ref(&config)).status, CheckStatus::Pass);
+        assert!(config.is_dir());
+        assert_eq!(fs::read_dir(&config).unwrap().count(), 0);
+
+        // A file where the folder should be
+        let blocked = dir.join("blocked");
+        fs::write(&blocked, "").unwrap();
+        let check = check_writable(&[config, blocked.join("KeyMagic")]);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.detail.contains("blocked"), "{}", check.detail);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_version_check() {
+        let app = Version::parse("0.0.9").unwrap();
+        assert_eq!(check_version(&app, Some("0.0.9")).status, CheckStatus::Pass);
+        assert_eq!(check_version(&app, Some("0.0.8")).status, CheckStatus::Fail);
+        assert_eq!(check_version(&app, Some("broken")).status, CheckStatus::Fail);
+        assert_eq!(check_version(&app, None).status, CheckStatus::Skipped);
+
+        assert_eq!(tsf_folder_version(Path::new("KeyMagic 3/TSF/0_0_9/KeyMagicTSF.dll")).as_deref(), Some("0.0.9"));
+        assert_eq!(tsf_folder_version(Path::new("KeyMagic 3/TSF/KeyMagicTSF.dll")), None);
+    }
+
+    #[test]
+    fn test_component_exec() {
+        let xml = "<component><exec>/usr/lib/ibus-keymagic3/ibus-engine-keymagic3 --ibus</exec></component>";
+        assert_eq!(component_exec(xml), Some(PathBuf::from("/usr/lib/ibus-keymagic3/ibus-engine-keymagic3")));
+        assert_eq!(component_exec("<component></component>"), None);
+    }
+
+    #[test]
+    fn test_report_fails_on_any_failed_check() {
+        let report = InstallReport::new(vec![
+            CheckResult::new("input_method", CheckStatus::Pass, "registered"),
+            CheckResult::new("version", CheckStatus::Skipped, "unknown"),
+        ]);
+        assert!(report.passed);
+        assert_eq!(report.exit_code(), 0);
+
+        let report = InstallReport::new(vec![
+            CheckResult::new("input_method", CheckStatus::Fail, "not registered"),
+            CheckResult::new("version", CheckStatus::Pass, "0.0.9"),
+        ]);
+        assert_eq!(report.exit_code(), 1);
+        assert_eq!(
+            report.to_string(),
+            "input_method: FAIL: not registered\nversion: pass: 0.0.9\n1 of 2 checks failed"
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["passed"], false);
+        assert_eq!(json["checks"][0]["status"], "fail");
+        assert_eq!(json["checks"][1]["check"], "version");
+    }
+
+    #[test]
+    fn test_verify_install_reports_every_check() {
+        let platform = MockPlatform::builder("install-verify").bundled_keyboard("myanmar3", &named("Myanmar3")).build();
+        let report = verify_install(Ok(&platform));
+        let checks: Vec<_> = report.checks.iter().map(|check| check.check).collect();
+        assert_eq!(checks, ["input_method", "architecture", "bundled_keyboards", "config_writable", "version"]);
+        assert_eq!(status_of(&report, "bundled_keyboards"), CheckStatus::Pass);
+        assert_eq!(status_of(&report, "config_writable"), CheckStatus::Pass);
+
+        let report = verify_install(Err(anyhow!("no registry")));
+        assert_eq!(report.checks.len(), 5);
+        assert_eq!(status_of(&report, "config_writable"), CheckStatus::Fail);
+        assert_eq!(report.exit_code(), 1);
+    }
+
+    fn status_of(report: &InstallReport, name: &str) -> CheckStatus {
+        report.checks.iter().find(|check| check.check == name).unwrap().status
+    }
+
+    #[test]
+    fn test_first_run_imports_new_and_updated_keyboards_once() {
+        let platform = MockPlatform::builder("install-first-run")
+            .keyboard("myanmar3")
+            .bundled_keyboard("myanmar3", &named("myanmar3"))
+            .bundled_keyboard("zawgyi", &named("Zawgyi"))
+            .build();
+        let manager = KeyboardManager::new(Box::new(platform));
+        manager.initialize().unwrap();
+
+        let report = first_run(&manager).unwrap();
+        let mut imported = report.imported.clone();
+        imported.sort();
+        assert_eq!(imported, ["Zawgyi", "myanmar3"]);
+        assert!(report.failed.is_empty(), "{:?}", report.failed);
+        assert_eq!(report.exit_code(), 0);
+        let names: Vec<_> = manager.get_keyboards().into_iter().map(|kb| kb.name).collect();
+        assert_eq!(names.iter().filter(|name| *name == "myanmar3").count(), 1);
+        assert!(names.contains(&"Zawgyi".to_string()));
+        assert!(!manager.should_scan_bundled_keyboards().unwrap());
+
+        // The second run finds nothing to do
+        let report = first_run(&manager).unwrap();
+        assert!(report.already_scanned);
+        assert_eq!(report.to_string(), "Bundled keyboards were already imported for this version");
+        assert_eq!(manager.get_keyboards().len(), 2);
+    }
+}
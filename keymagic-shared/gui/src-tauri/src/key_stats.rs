@@ -0,0 +1,325 @@
+//! Key press heatmaps for layout research
+//!
+//! When the user opts in, text services count the virtual keys they process
+//! per keyboard and day in `keymagic_core::key_stats`; the GUI drains the
+//! counters now and then into a store in the data directory, merging days
+//! older than a month into monthly buckets. Nothing is counted while the
+//! setting is off or a password field has focus, and only key codes are
+//! kept, never text.
+
+use anyhow::{Context, Result};
+use keymagic_core::key_stats::{self, Bucket, KeyHeatmap, KeyStats, KeyStatsStore, KEY_COUNT};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Setting that turns counting on when "true"
+pub const ENABLED_SETTING: &str = "key_stats_enabled";
+
+/// File in the data directory holding the counts
+pub const STORE_FILE: &str = "key_stats.json";
+
+/// Days a heatmap covers, inclusive, as `YYYY-MM-DD`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DateRange {
+    pub from: String,
+    pub to: String,
+}
+
+impl DateRange {
+    /// The first and last day, or an error naming the unreadable date
+    pub fn days(&self) -> Result<(u32, u32), String> {
+        let parse = |date: &str| key_stats::parse_day(date).ok_or_else(|| format!("Invalid date '{}'", date));
+        let (from, to) = (parse(&self.from)?, parse(&self.to)?);
+        if from > to {
+            return Err(format!("The range starts after it ends ({} to {})", self.from, self.to));
+        }
+        Ok((from, to))
+    }
+}
+
+/// Counts of one key in a heatmap
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyCount {
+    pub vk: u8,
+    /// KMS name, e.g. `VK_KEY_A`
+    pub name: String,
+    pub count: u64,
+}
+
+/// A keyboard's key presses over a range of days, for the heatmap overlay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyHeatmapInfo {
+    pub keyboard_id: String,
+    /// Range covered; wider than asked when it takes in monthly buckets
+    pub from: String,
+    pub to: String,
+    pub total: u64,
+    /// Keys pressed at least once, most pressed first
+    pub keys: Vec<KeyCount>,
+}
+
+impl KeyHeatmapInfo {
+    fn new(keyboard_id: &str, heatmap: &KeyHeatmap) -> Self {
+        Self {
+            keyboard_id: keyboard_id.to_string(),
+            from: key_stats::format_day(heatmap.from_day),
+            to: key_stats::format_day(heatmap.to_day),
+            total: heatmap.total,
+            keys: heatmap
+                .keys()
+                .into_iter()
+                .map(|(vk, count)| KeyCount { vk, name: key_stats::key_name(vk), count })
+                .collect(),
+        }
+    }
+}
+
+fn today() -> u32 {
+    key_stats::day_of(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_millis() as u64))
+}
+
+fn load(path: &Path) -> Result<KeyStatsStore> {
+    let contents = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let buckets: Vec<Bucket> = serde_json::from_str(&contents).context("Failed to parse the key statistics")?;
+    Ok(KeyStatsStore::from_buckets(buckets.into_iter().filter(|bucket| bucket.counts.len() <= KEY_COUNT)))
+}
+
+fn save(path: &Path, store: &KeyStatsStore) -> Result<()> {
+    let contents = serde_json::to_string(&store.buckets())?;
+    std::fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(target_os = "windows")]
+fn open_stats() -> std::io::Result<KeyStats> {
+    KeyStats::create_shared()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn open_stats() -> std::io::Result<KeyStats> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Key presses are only counted on Windows",
+    ))
+}
+
+struct MonitorState {
+    store: KeyStatsStore,
+    /// Whether the store file was read, which happens on first use
+    loaded: bool,
+}
+
+/// The GUI's key statistics, fed from the shared counters
+pub struct KeyStatsMonitor {
+    stats: Option<KeyStats>,
+    store_file: PathBuf,
+    state: Mutex<MonitorState>,
+}
+
+impl KeyStatsMonitor {
+    /// Attaches to the shared counters; without them no keys are counted
+    pub fn new(enabled: bool, data_dir: &Path) -> Self {
+        let stats = open_stats()
+            .map_err(|e| log::debug!("Key statistics unavailable: {}", e))
+            .ok();
+        Self::with_stats(stats, enabled, data_dir.join(STORE_FILE))
+    }
+
+    fn with_stats(stats: Option<KeyStats>, enabled: bool, store_file: PathBuf) -> Self {
+        let monitor = Self {
+            stats,
+            store_file,
+            state: Mutex::new(MonitorState { store: KeyStatsStore::new(), loaded: false }),
+        };
+        monitor.configure(enabled);
+        monitor
+    }
+
+    /// Turns counting on or off in every host; counts already kept stay
+    /// until cleared
+    pub fn configure(&self, enabled: bool) {
+        if let Some(stats) = &self.stats {
+            if !enabled && stats.is_enabled() {
+                // Keep what was counted up to now
+                self.poll_at(today());
+            }
+            stats.set_enabled(enabled);
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.stats.as_ref().is_some_and(KeyStats::is_enabled)
+    }
+
+    fn lock_loaded(&self) -> std::sync::MutexGuard<'_, MonitorState> {
+        let mut state = self.state.lock().unwrap();
+        if !state.loaded {
+            state.loaded = true;
+            if self.store_file.exists() {
+                match load(&self.store_file) {
+                    Ok(store) => state.store = store,
+                    Err(e) => log::warn!("{:#}", e),
+                }
+            }
+        }
+        state
+    }
+
+    /// Takes new counts, compacts old days and saves the store if anything
+    /// changed; returns whether it did
+    fn poll_at(&self, today: u32) -> bool {
+        let Some(stats) = &self.stats else {
+            return false;
+        };
+        let drained = stats.drain(today);
+        let mut state = self.lock_loaded();
+        state.store.merge(&drained);
+        let compacted = state.store.compact(today);
+        let changed = !drained.is_empty() || compacted > 0;
+        if changed {
+            if let Err(e) = save(&self.store_file, &state.store) {
+                log::warn!("Failed to keep the key statistics: {:#}", e);
+            }
+        }
+        changed
+    }
+
+    /// Presses of a keyboard's keys over `range`
+    pub fn heatmap(&self, keyboard_id: &str, range: &DateRange) -> Result<KeyHeatmapInfo, String> {
+        let (from, to) = range.days()?;
+        self.poll_at(today());
+        let heatmap = self.lock_loaded().store.heatmap(key_stats::hash_keyboard_id(keyboard_id), from, to);
+        Ok(KeyHeatmapInfo::new(keyboard_id, &heatmap))
+    }
+
+    /// A keyboard's counts over `range` as CSV, see `KeyStatsStore::to_csv`
+    pub fn csv(&self, keyboard_id: &str, range: &DateRange) -> Result<String, String> {
+        let (from, to) = range.days()?;
+        self.poll_at(today());
+        Ok(self.lock_loaded().store.to_csv(key_stats::hash_keyboard_id(keyboard_id), from, to))
+    }
+
+    /// Forgets every count, including ones not drained yet
+    pub fn clear(&self) -> Result<()> {
+        if let Some(stats) = &self.stats {
+            stats.drain(u32::MAX);
+        }
+        let mut state = self.lock_loaded();
+        state.store.clear();
+        if self.store_file.exists() {
+            std::fs::remove_file(&self.store_file)
+                .with_context(|| format!("Failed to remove {}", self.store_file.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Drains the counters from a background thread while counting is on
+    pub fn watch(self: &Arc<Self>) {
+        if self.stats.is_none() {
+            return;
+        }
+        let monitor = self.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if monitor.is_enabled() {
+                monitor.poll_at(today());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic-key-stats-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn monitor(enabled: bool, dir: &Path) -> KeyStatsMonitor {
+        KeyStatsMonitor::with_stats(Some(KeyStats::in_memory()), enabled, dir.join(STORE_FILE))
+    }
+
+    fn range(from: &str, to: &str) -> DateRange {
+        DateRange { from: from.to_string(), to: to.to_string() }
+    }
+
+    fn day(date: &str) -> u32 {
+        key_stats::parse_day(date).unwrap()
+    }
+
+    #[test]
+    fn test_date_range() {
+        assert_eq!(range("2026-10-01", "2026-10-16").days(), Ok((day("2026-10-01"), day("2026-10-16"))));
+        assert!(range("2026-10-16", "2026-10-01").days().is_err());
+        assert!(range("yesterday", "2026-10-01").days().unwrap_err().contains("yesterday"));
+    }
+
+    #[test]
+    fn test_nothing_is_counted_or_written_while_off() {
+        let dir = test_dir("off");
+        let monitor = monitor(false, &dir);
+        let keyboard = key_stats::hash_keyboard_id("myanmar3");
+        assert!(!monitor.stats.as_ref().unwrap().record(keyboard, day("2026-10-16"), 0x41));
+        assert!(!monitor.poll_at(day("2026-10-16")));
+        assert!(!dir.join(STORE_FILE).exists());
+
+        let heatmap = monitor.heatmap("myanmar3", &range("2026-10-16", "2026-10-16")).unwrap();
+        assert_eq!(heatmap.total, 0);
+        assert!(heatmap.keys.is_empty());
+    }
+
+    #[test]
+    fn test_heatmap_and_csv_from_counted_keys() {
+        let dir = test_dir("counted");
+        let monitor = monitor(true, &dir);
+        let stats = monitor.stats.as_ref().unwrap();
+        let (keyboard, today) = (key_stats::hash_keyboard_id("Myanmar3"), day("2026-10-16"));
+        for vk in [0x41, 0x41, 0x20] {
+            assert!(stats.record(keyboard, today, vk));
+        }
+        assert!(monitor.poll_at(today));
+
+        let heatmap = monitor.heatmap("myanmar3", &range("2026-10-16", "2026-10-16")).unwrap();
+        assert_eq!(heatmap.total, 3);
+        assert_eq!(heatmap.keys[0], KeyCount { vk: 0x41, name: "VK_KEY_A".to_string(), count: 2 });
+        assert_eq!(heatmap.from, "2026-10-16");
+        assert_eq!(
+            monitor.csv("myanmar3", &range("2026-10-16", "2026-10-16")).unwrap(),
+            "period,vk,key,count\n2026-10-16,32,VK_SPACE,1\n2026-10-16,65,VK_KEY_A,2\n"
+        );
+
+        // Kept across restarts, and compacted once old
+        let restarted = self::monitor(true, &dir);
+        assert!(restarted.poll_at(today + 40));
+        let heatmap = restarted.heatmap("myanmar3", &range("2026-10-16", "2026-10-16")).unwrap();
+        assert_eq!((heatmap.total, heatmap.from.as_str(), heatmap.to.as_str()), (3, "2026-10-01", "2026-10-31"));
+
+        restarted.clear().unwrap();
+        assert!(!dir.join(STORE_FILE).exists());
+        assert_eq!(restarted.heatmap("myanmar3", &range("2026-10-01", "2026-10-31")).unwrap().total, 0);
+    }
+
+    #[test]
+    fn test_turning_off_keeps_counts_so_far() {
+        let dir = test_dir("turn-off");
+        let monitor = monitor(true, &dir);
+        let stats = monitor.stats.as_ref().unwrap();
+        assert!(stats.record(key_stats::hash_keyboard_id("zawgyi"), today(), 0x41));
+
+        monitor.configure(false);
+        assert!(!monitor.is_enabled());
+        assert!(!stats.record(key_stats::hash_keyboard_id("zawgyi"), today(), 0x41));
+        let today = key_stats::format_day(today());
+        assert_eq!(monitor.heatmap("zawgyi", &range(&today, &today)).unwrap().total, 1);
+    }
+}
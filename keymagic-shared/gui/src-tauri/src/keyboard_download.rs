@@ -0,0 +1,339 @@
+//! Downloading keyboards from a URL before importing them
+//!
+//! Keyboard authors publish .km2 files on websites and GitHub releases. The
+//! file is downloaded into memory with a size cap, checked against the
+//! checksum the user was given (if any) and loaded once before it is written
+//! to a temporary file for the regular import.
+
+use crate::http::{self, RetryPolicy};
+use crate::temp_file;
+use keymagic_core::km2::Km2Loader;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Largest keyboard accepted; real keyboards are well under 1 MB
+pub const MAX_KEYBOARD_SIZE: u64 = 10 * 1024 * 1024;
+/// Time allowed for the whole download, including redirects
+pub const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_REDIRECTS: usize = 10;
+
+/// Progress of a keyboard download, sent to the UI as the
+/// `keyboard_download_progress` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DownloadProgress {
+    pub url: String,
+    pub downloaded: u64,
+    /// From Content-Length, when the server sent one
+    pub total: Option<u64>,
+}
+
+/// Why a keyboard could not be downloaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyboardDownloadError {
+    InvalidUrl(String),
+    InvalidChecksum(String),
+    /// Plain HTTP (directly or through a redirect) without the developer setting
+    InsecureUrl(String),
+    /// The server failed or returned an error status
+    Http(String),
+    TooLarge { limit: u64 },
+    ChecksumMismatch { expected: String, actual: String },
+    /// The download is not a keyboard the engine can load
+    InvalidKeyboard(String),
+}
+
+impl std::fmt::Display for KeyboardDownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl(url) => write!(f, "Invalid keyboard URL: {}", url),
+            Self::InvalidChecksum(checksum) => write!(f, "Invalid SHA-256 checksum: {}", checksum),
+            Self::InsecureUrl(url) => write!(f, "Keyboards can only be downloaded over HTTPS: {}", url),
+            Self::Http(message) => write!(f, "Failed to download keyboard: {}", message),
+            Self::TooLarge { limit } => write!(f, "The download is larger than {} bytes", limit),
+            Self::ChecksumMismatch { expected, actual } => {
+                write!(f, "Checksum mismatch: expected SHA-256 {}, got {}", expected, actual)
+            }
+            Self::InvalidKeyboard(message) => write!(f, "The download is not a valid keyboard: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for KeyboardDownloadError {}
+
+/// Limits applied to one download
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadOptions {
+    /// Accept plain HTTP, for developers testing against a local server
+    pub allow_insecure: bool,
+    pub max_size: u64,
+    pub timeout: Duration,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self { allow_insecure: false, max_size: MAX_KEYBOARD_SIZE, timeout: DOWNLOAD_TIMEOUT }
+    }
+}
+
+/// A downloaded keyboard in a temporary folder, removed on drop
+#[derive(Debug)]
+pub struct DownloadedKeyboard {
+    dir: PathBuf,
+    path: PathBuf,
+    pub sha256: String,
+}
+
+impl DownloadedKeyboard {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for DownloadedKeyboard {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            log::warn!("Failed to remove downloaded keyboard {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+fn check_scheme(url: &Url, allow_insecure: bool) -> Result<(), KeyboardDownloadError> {
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if allow_insecure => Ok(()),
+        _ => Err(KeyboardDownloadError::InsecureUrl(url.to_string())),
+    }
+}
+
+/// Normalizes a user-supplied SHA-256: hex digits only, any case, optional
+/// `sha256:` prefix
+fn normalize_sha256(checksum: &str) -> Result<String, KeyboardDownloadError> {
+    let checksum = checksum.trim();
+    let checksum = checksum.strip_prefix("sha256:").unwrap_or(checksum).to_ascii_lowercase();
+    if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(KeyboardDownloadError::InvalidChecksum(checksum));
+    }
+    Ok(checksum)
+}
+
+/// File name for the download, taken from the last segment of the final URL
+fn file_name(url: &Url) -> String {
+    let stem = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.strip_suffix(".km2").unwrap_or(name).to_string())
+        .unwrap_or_else(|| "keyboard".to_string());
+    format!("{}.km2", stem)
+}
+
+fn http_error(err: reqwest::Error) -> KeyboardDownloadError {
+    // Redirect policy errors carry our own error as the source
+    let mut source = std::error::Error::source(&err);
+    while let Some(inner) = source {
+        if let Some(e) = inner.downcast_ref::<KeyboardDownloadError>() {
+            return e.clone();
+        }
+        source = inner.source();
+    }
+    if err.is_timeout() {
+        return KeyboardDownloadError::Http("the server did not respond in time".to_string());
+    }
+    KeyboardDownloadError::Http(err.to_string())
+}
+
+/// Downloads the keyboard at `url`, verifies it and writes it to a temporary
+/// file. `on_progress` is called as data arrives.
+pub async fn download_keyboard(
+    url: &str,
+    expected_sha256: Option<&str>,
+    options: DownloadOptions,
+    mut on_progress: impl FnMut(DownloadProgress),
+) -> Result<DownloadedKeyboard, KeyboardDownloadError> {
+    let parsed = Url::parse(url).map_err(|_| KeyboardDownloadError::InvalidUrl(url.to_string()))?;
+    check_scheme(&parsed, options.allow_insecure)?;
+    let expected_sha256 = expected_sha256.map(normalize_sha256).transpose()?;
+
+    let allow_insecure = options.allow_insecure;
+    let client = http::client_builder()
+        .redirect(Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error(KeyboardDownloadError::Http("too many redirects".to_string()));
+            }
+            match check_scheme(attempt.url(), allow_insecure) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        }))
+        .timeout(options.timeout)
+        .build()
+        .map_err(http_error)?;
+
+    let mut response = http::get_with_retry(&client, parsed, RetryPolicy::default()).await.map_err(http_error)?;
+    if !response.status().is_success() {
+        return Err(KeyboardDownloadError::Http(format!("server returned {}", response.status())));
+    }
+
+    let total = response.content_length();
+    if total.is_some_and(|total| total > options.max_size) {
+        return Err(KeyboardDownloadError::TooLarge { limit: options.max_size });
+    }
+    let final_url = response.url().clone();
+
+    let mut data = Vec::new();
+    on_progress(DownloadProgress { url: url.to_string(), downloaded: 0, total });
+    while let Some(chunk) = response.chunk().await.map_err(http_error)? {
+        // Content-Length may be missing or wrong
+        if (data.len() + chunk.len()) as u64 > options.max_size {
+            return Err(KeyboardDownloadError::TooLarge { limit: options.max_size });
+        }
+        data.extend_from_slice(&chunk);
+        on_progress(DownloadProgress { url: url.to_string(), downloaded: data.len() as u64, total });
+    }
+
+    let sha256 = format!("{:x}", Sha256::digest(&data));
+    if let Some(expected) = expected_sha256 {
+        if expected != sha256 {
+            return Err(KeyboardDownloadError::ChecksumMismatch { expected, actual: sha256 });
+        }
+    }
+
+    Km2Loader::load(&data).map_err(|e| KeyboardDownloadError::InvalidKeyboard(e.to_string()))?;
+
+    static NEXT_DOWNLOAD: AtomicU64 = AtomicU64::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "keymagic-download-{}-{}",
+        std::process::id(),
+        NEXT_DOWNLOAD.fetch_add(1, Ordering::Relaxed)
+    ));
+    let io_error = |e: std::io::Error| KeyboardDownloadError::Http(format!("failed to save download: {}", e));
+    fs::create_dir_all(&dir).map_err(io_error)?;
+    let keyboard = DownloadedKeyboard { path: dir.join(file_name(&final_url)), dir, sha256 };
+    temp_file::write_atomic(&keyboard.path, &data).map_err(io_error)?;
+    Ok(keyboard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Serves one canned HTTP response per connection, in order
+    fn serve(responses: Vec<Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 4096];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(&response);
+            }
+        });
+        address
+    }
+
+    fn ok(body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len())
+            .into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    fn keyboard_bytes() -> Vec<u8> {
+        let mut data = Vec::new();
+        kms2km2::binary::Km2Writer::new(&mut data)
+            .write_km2_file(&keymagic_core::Km2File::default())
+            .unwrap();
+        data
+    }
+
+    fn insecure() -> DownloadOptions {
+        DownloadOptions { allow_insecure: true, ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn test_downloads_and_verifies_keyboard() {
+        let data = keyboard_bytes();
+        let sha256 = format!("{:x}", Sha256::digest(&data));
+        let url = format!("{}/releases/MyanSan.km2", serve(vec![ok(&data)]));
+
+        let mut progress = Vec::new();
+        let keyboard = download_keyboard(&url, Some(&sha256.to_uppercase()), insecure(), |p| progress.push(p))
+            .await
+            .unwrap();
+
+        assert_eq!(keyboard.path().file_name().unwrap(), "MyanSan.km2");
+        assert_eq!(fs::read(keyboard.path()).unwrap(), data);
+        assert!(crate::temp_file::leftovers(&keyboard.dir).is_empty());
+        assert_eq!(progress.last().unwrap().downloaded, data.len() as u64);
+        assert_eq!(progress.last().unwrap().total, Some(data.len() as u64));
+
+        let dir = keyboard.dir.clone();
+        drop(keyboard);
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_checksum_mismatch_is_rejected() {
+        let url = format!("{}/k.km2", serve(vec![ok(&keyboard_bytes())]));
+        let err = download_keyboard(&url, Some(&"0".repeat(64)), insecure(), |_| {}).await.unwrap_err();
+        assert!(matches!(err, KeyboardDownloadError::ChecksumMismatch { .. }), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_oversize_response_is_rejected() {
+        let options = DownloadOptions { max_size: 16, ..insecure() };
+
+        // Declared too large up front
+        let url = format!("{}/k.km2", serve(vec![ok(&[0u8; 64])]));
+        let err = download_keyboard(&url, None, options, |_| {}).await.unwrap_err();
+        assert_eq!(err, KeyboardDownloadError::TooLarge { limit: 16 });
+
+        // No Content-Length; cut off while streaming
+        let mut response = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n".to_vec();
+        response.extend_from_slice(&[0u8; 64]);
+        let url = format!("{}/k.km2", serve(vec![response]));
+        let err = download_keyboard(&url, None, options, |_| {}).await.unwrap_err();
+        assert_eq!(err, KeyboardDownloadError::TooLarge { limit: 16 });
+    }
+
+    #[tokio::test]
+    async fn test_invalid_keyboard_is_rejected() {
+        let url = format!("{}/k.km2", serve(vec![ok(b"not a keyboard")]));
+        let err = download_keyboard(&url, None, insecure(), |_| {}).await.unwrap_err();
+        assert!(matches!(err, KeyboardDownloadError::InvalidKeyboard(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_plain_http_needs_developer_setting() {
+        let err = download_keyboard("http://example.com/k.km2", None, DownloadOptions::default(), |_| {})
+            .await
+            .unwrap_err();
+        assert!(matches!(err, KeyboardDownloadError::InsecureUrl(_)), "{:?}", err);
+
+        let err = download_keyboard("file:///etc/passwd", None, insecure(), |_| {}).await.unwrap_err();
+        assert!(matches!(err, KeyboardDownloadError::InsecureUrl(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_redirects_are_followed() {
+        let data = keyboard_bytes();
+        let target = serve(vec![ok(&data)]);
+        let redirect = format!(
+            "HTTP/1.1 302 Found\r\nLocation: {}/files/Zawgyi.km2\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            target
+        );
+        let url = format!("{}/download?id=1", serve(vec![redirect.into_bytes()]));
+
+        let keyboard = download_keyboard(&url, None, insecure(), |_| {}).await.unwrap();
+        assert_eq!(keyboard.path().file_name().unwrap(), "Zawgyi.km2");
+    }
+}
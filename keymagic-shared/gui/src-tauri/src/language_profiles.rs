@@ -83,6 +83,37 @@ pub fn update_language_profiles(enabled_languages: &[String]) -> Result<()> {
 }
 
 
+/// Removes every language profile of the KeyMagic text service, for
+/// uninstalling; returns how many there were
+#[cfg(target_os = "windows")]
+pub fn remove_all_language_profiles() -> Result<usize> {
+    unsafe {
+        let hr = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+        if hr.is_err() {
+            return Err(anyhow!("Failed to initialize COM: {:?}", hr));
+        }
+        
+        let profiles: ITfInputProcessorProfiles = CoCreateInstance(
+            &CLSID_TF_InputProcessorProfiles,
+            None,
+            CLSCTX_INPROC_SERVER,
+        ).map_err(|e| anyhow!("Failed to create ITfInputProcessorProfiles: {:?}", e))?;
+        
+        let langids = get_registered_language_profiles(&profiles)?;
+        for langid in &langids {
+            remove_language_profile(&profiles, *langid)?;
+        }
+        
+        if !langids.is_empty() {
+            notify_language_profile_change()?;
+        }
+        
+        CoUninitialize();
+        
+        Ok(langids.len())
+    }
+}
+
 #[cfg(target_os = "windows")]
 fn get_registered_language_profiles(profiles: &ITfInputProcessorProfiles) -> Result<Vec<u16>> {
     use windows::Win32::UI::TextServices::IEnumTfLanguageProfiles;
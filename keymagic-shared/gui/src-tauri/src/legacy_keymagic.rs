@@ -0,0 +1,431 @@
+//! Migration from KeyMagic 2
+//!
+//! Users upgrading from KeyMagic 2 have layouts in its install and data
+//! folders, and registered in its registry key, which the bundled keyboard
+//! scan never looks at. `detect_legacy_keymagic` lists what it finds there;
+//! `migrate_legacy_keyboards` imports the user's choice through the normal
+//! import, which validates the files and reads the older KM2 versions, and
+//! can make KeyMagic 2's active layout the active keyboard again.
+//!
+//! Migration is offered once: any call to migrate, also with nothing
+//! selected, records that it was offered. Locations that cannot be read are
+//! reported with the rest rather than failing the scan.
+
+use anyhow::Result;
+use keymagic_core::Km2Loader;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::core::KeyboardManager;
+
+/// Setting that is "true" once migration was offered
+pub const OFFERED_SETTING: &str = "legacy_migration_offered";
+
+/// Folder of layouts under the KeyMagic 2 install and data folders
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+const LAYOUTS_FOLDER: &str = "KeyboardLayouts";
+
+/// Uninstall entries of the KeyMagic 2 installer, for its install folder
+#[cfg(target_os = "windows")]
+const UNINSTALL_KEYS: [&str; 2] = [
+    r"SOFTWARE\Microsoft\Windows\CurrentVersion\Uninstall\KeyMagic",
+    r"SOFTWARE\WOW6432Node\Microsoft\Windows\CurrentVersion\Uninstall\KeyMagic",
+];
+
+/// KeyMagic 2's settings, with its active layout
+#[cfg(target_os = "windows")]
+const LEGACY_SETTINGS_KEY: &str = r"Software\KeyMagic2";
+/// Layouts registered with KeyMagic 2, as name = path values
+#[cfg(target_os = "windows")]
+const LEGACY_LAYOUTS_KEY: &str = r"Software\KeyMagic2\Layouts";
+
+/// Where KeyMagic 2 may have left layouts
+#[derive(Debug, Clone, Default)]
+pub struct LegacyLocations {
+    /// Folders searched for `.km2` files
+    pub folders: Vec<PathBuf>,
+    /// Layouts registered by name
+    pub registered: Vec<(String, PathBuf)>,
+    /// Name or file stem of the layout KeyMagic 2 had active
+    pub active: Option<String>,
+    /// Locations that exist but could not be read
+    pub problems: Vec<LocationProblem>,
+}
+
+/// A location that could not be read, e.g. for lack of permission
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocationProblem {
+    pub location: String,
+    pub error: String,
+}
+
+/// A layout found in the KeyMagic 2 locations
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyKeyboard {
+    /// Name from the file, or its file stem
+    pub name: String,
+    /// Identifies the layout in a migration selection
+    pub path: String,
+    /// KeyMagic 2 had it active
+    pub is_active: bool,
+    /// The same file is installed already
+    pub installed: bool,
+    /// Why the file cannot be imported; such layouts are listed but not
+    /// selectable
+    pub error: Option<String>,
+}
+
+/// What was found of KeyMagic 2
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyDetection {
+    /// Migration was offered before; the UI should not prompt again
+    pub offered: bool,
+    pub keyboards: Vec<LegacyKeyboard>,
+    pub problems: Vec<LocationProblem>,
+}
+
+impl LegacyDetection {
+    /// Whether the first run should offer migration
+    pub fn should_offer(&self) -> bool {
+        !self.offered && self.keyboards.iter().any(|keyboard| keyboard.error.is_none() && !keyboard.installed)
+    }
+}
+
+/// Layouts to migrate, by `LegacyKeyboard::path`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationSelection {
+    pub paths: Vec<String>,
+    /// Make KeyMagic 2's active layout active, if it was migrated
+    #[serde(default)]
+    pub import_active: bool,
+}
+
+/// What a migration did
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrationReport {
+    /// Ids of the imported keyboards, in selection order
+    pub imported: Vec<String>,
+    /// Paths and why they were not imported
+    pub failed: Vec<(String, String)>,
+    /// The keyboard made active
+    pub activated: Option<String>,
+}
+
+/// The KeyMagic 2 locations of this machine and user
+#[cfg(target_os = "windows")]
+pub fn system_locations() -> LegacyLocations {
+    use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+    use winreg::RegKey;
+
+    let mut locations = LegacyLocations::default();
+    let env_dir = |name: &str| std::env::var_os(name).map(PathBuf::from);
+    for name in ["ProgramFiles", "ProgramFiles(x86)", "APPDATA"] {
+        if let Some(dir) = env_dir(name) {
+            locations.folders.push(dir.join("KeyMagic").join(LAYOUTS_FOLDER));
+        }
+    }
+
+    let mut open = |root, path: &str| match RegKey::predef(root).open_subkey(path) {
+        Ok(key) => Some(key),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => {
+            locations.problems.push(LocationProblem { location: path.to_string(), error: e.to_string() });
+            None
+        }
+    };
+    // The installer records where KeyMagic 2 went; KeyMagic 3 uses another entry
+    let mut install_dirs = Vec::new();
+    for (root, path) in [(HKEY_LOCAL_MACHINE, UNINSTALL_KEYS[0]), (HKEY_LOCAL_MACHINE, UNINSTALL_KEYS[1]), (HKEY_CURRENT_USER, UNINSTALL_KEYS[0])] {
+        if let Some(key) = open(root, path) {
+            let version: String = key.get_value("DisplayVersion").unwrap_or_default();
+            let location: String = key.get_value("InstallLocation").unwrap_or_default();
+            if version.starts_with("2.") && !location.trim().is_empty() {
+                install_dirs.push(PathBuf::from(location.trim()).join(LAYOUTS_FOLDER));
+            }
+        }
+    }
+    let registered = open(HKEY_CURRENT_USER, LEGACY_LAYOUTS_KEY).map(|key| {
+        key.enum_values()
+            .filter_map(Result::ok)
+            .map(|(name, value)| (name, PathBuf::from(value.to_string().trim_matches('"'))))
+            .collect::<Vec<_>>()
+    });
+    let active = open(HKEY_CURRENT_USER, LEGACY_SETTINGS_KEY).and_then(|key| key.get_value::<String, _>("ActiveLayout").ok());
+
+    locations.folders.extend(install_dirs);
+    locations.registered = registered.unwrap_or_default();
+    locations.active = active.filter(|active| !active.is_empty());
+    locations
+}
+
+/// KeyMagic 2 only ran on Windows
+#[cfg(not(target_os = "windows"))]
+pub fn system_locations() -> LegacyLocations {
+    LegacyLocations::default()
+}
+
+fn problem(location: &Path, error: impl ToString) -> LocationProblem {
+    LocationProblem { location: location.display().to_string(), error: error.to_string() }
+}
+
+/// The `.km2` files of a folder; a missing folder has none
+fn folder_layouts(folder: &Path, problems: &mut Vec<LocationProblem>) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            problems.push(problem(folder, e));
+            return Vec::new();
+        }
+    };
+    let mut layouts: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("km2")) && path.is_file())
+        .collect();
+    layouts.sort();
+    layouts
+}
+
+/// Compares paths the way Windows does, ignoring case
+fn path_key(path: &Path) -> String {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().to_lowercase()
+}
+
+/// Lists the layouts in `locations`, each once, and whether they are
+/// installed already
+pub fn detect(manager: &KeyboardManager, locations: &LegacyLocations) -> Result<LegacyDetection> {
+    let mut problems = locations.problems.clone();
+    let mut paths: Vec<(Option<String>, PathBuf)> = Vec::new();
+    for folder in &locations.folders {
+        paths.extend(folder_layouts(folder, &mut problems).into_iter().map(|path| (None, path)));
+    }
+    paths.extend(locations.registered.iter().map(|(name, path)| (Some(name.clone()), path.clone())));
+
+    let installed_hashes: BTreeSet<String> =
+        manager.get_keyboards().into_iter().map(|keyboard| keyboard.hash.to_lowercase()).collect();
+    let active = locations.active.as_deref().map(str::to_lowercase);
+    let mut seen = BTreeSet::new();
+    let mut keyboards = Vec::new();
+    for (registered_name, path) in paths {
+        if !seen.insert(path_key(&path)) {
+            continue;
+        }
+        let file_stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default().to_string();
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            // A registered layout that was deleted since
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => {
+                problems.push(problem(&path, e));
+                continue;
+            }
+        };
+        let (name, error) = match Km2Loader::load(&data) {
+            Ok(layout) => (layout.metadata().name().or(registered_name).unwrap_or_else(|| file_stem.clone()), None),
+            Err(e) => (registered_name.unwrap_or_else(|| file_stem.clone()), Some(e.to_string())),
+        };
+        let installed = error.is_none()
+            && manager.calculate_file_hash(&path).is_ok_and(|hash| installed_hashes.contains(&hash.to_lowercase()));
+        let is_active = active.as_deref().is_some_and(|active| active == name.to_lowercase() || active == file_stem.to_lowercase());
+        keyboards.push(LegacyKeyboard { name, path: path.display().to_string(), is_active, installed, error });
+    }
+
+    let offered = manager.get_platform().get_setting(OFFERED_SETTING)?.as_deref() == Some("true");
+    Ok(LegacyDetection { offered, keyboards, problems })
+}
+
+/// Imports the selected layouts of `locations` and records that migration
+/// was offered; paths that are not layouts found there are refused
+pub fn migrate(manager: &KeyboardManager, locations: &LegacyLocations, selection: &MigrationSelection) -> Result<MigrationReport> {
+    let detection = detect(manager, locations)?;
+    let mut report = MigrationReport::default();
+    let mut active_id = None;
+    for path in &selection.paths {
+        let Some(keyboard) = detection.keyboards.iter().find(|keyboard| &keyboard.path == path) else {
+            report.failed.push((path.clone(), "Not a KeyMagic 2 layout".to_string()));
+            continue;
+        };
+        if let Some(error) = &keyboard.error {
+            report.failed.push((path.clone(), error.clone()));
+            continue;
+        }
+        match manager.import_keyboard(Path::new(path)) {
+            Ok(imported) => {
+                if keyboard.is_active {
+                    active_id = Some(imported.id.clone());
+                }
+                report.imported.push(imported.id);
+            }
+            Err(e) => report.failed.push((path.clone(), format!("{:#}", e))),
+        }
+    }
+
+    if selection.import_active {
+        if let Some(id) = active_id {
+            match manager.set_active_keyboard(&id) {
+                Ok(()) => report.activated = Some(id),
+                Err(e) => log::warn!("Failed to activate migrated keyboard {}: {:#}", id, e),
+            }
+        }
+    }
+    manager.get_platform().set_setting(OFFERED_SETTING, "true")?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::{compile_keyboard, MockPlatform};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic_legacy_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn named(name: &str) -> Vec<u8> {
+        compile_keyboard(&format!("/*\n@NAME = \"{}\"\n*/\n\"k\" => \"က\"", name)).unwrap()
+    }
+
+    /// A KeyMagic 2 install: layouts in the program folder and the user's
+    /// data folder, one registered from elsewhere, and a broken file
+    fn legacy_layout(dir: &Path) -> LegacyLocations {
+        let program = dir.join("Program Files").join("KeyMagic").join(LAYOUTS_FOLDER);
+        let appdata = dir.join("AppData").join("KeyMagic").join(LAYOUTS_FOLDER);
+        let elsewhere = dir.join("Documents");
+        for folder in [&program, &appdata, &elsewhere] {
+            fs::create_dir_all(folder).unwrap();
+        }
+        fs::write(program.join("Myanmar3.km2"), named("Myanmar3")).unwrap();
+        fs::write(program.join("Zawgyi.KM2"), named("Zawgyi")).unwrap();
+        fs::write(program.join("readme.txt"), "").unwrap();
+        fs::write(appdata.join("broken.km2"), b"KMKL").unwrap();
+        fs::write(elsewhere.join("pali.km2"), named("Pali")).unwrap();
+
+        LegacyLocations {
+            folders: vec![program.clone(), appdata, dir.join("missing")],
+            registered: vec![
+                ("Pali".to_string(), elsewhere.join("pali.km2")),
+                // Listed in both places
+                ("Myanmar3".to_string(), program.join("Myanmar3.km2")),
+                ("Gone".to_string(), elsewhere.join("gone.km2")),
+            ],
+            active: Some("zawgyi".to_string()),
+            problems: Vec::new(),
+        }
+    }
+
+    fn manager(name: &str) -> KeyboardManager {
+        let manager = KeyboardManager::new(Box::new(MockPlatform::builder(name).build()));
+        manager.initialize().unwrap();
+        manager
+    }
+
+    fn path_of(detection: &LegacyDetection, name: &str) -> String {
+        detection.keyboards.iter().find(|keyboard| keyboard.name == name).unwrap().path.clone()
+    }
+
+    #[test]
+    fn test_detection_lists_layouts_once() {
+        let dir = test_dir("detect");
+        let locations = legacy_layout(&dir);
+        let manager = manager("legacy-detect");
+
+        let detection = detect(&manager, &locations).unwrap();
+        let names: Vec<_> = detection.keyboards.iter().map(|keyboard| keyboard.name.as_str()).collect();
+        assert_eq!(names, ["Myanmar3", "Zawgyi", "broken", "Pali"]);
+        assert!(detection.problems.is_empty(), "{:?}", detection.problems);
+        assert!(detection.keyboards[1].is_active);
+        assert!(detection.keyboards[2].error.is_some());
+        assert!(detection.keyboards.iter().all(|keyboard| !keyboard.installed));
+        assert!(detection.should_offer());
+
+        // Nothing found, nothing to offer
+        let detection = detect(&manager, &LegacyLocations::default()).unwrap();
+        assert!(detection.keyboards.is_empty());
+        assert!(!detection.should_offer());
+    }
+
+    #[test]
+    fn test_migration_imports_selection_and_active_keyboard() {
+        let dir = test_dir("migrate");
+        let locations = legacy_layout(&dir);
+        let manager = manager("legacy-migrate");
+        let detection = detect(&manager, &locations).unwrap();
+
+        let selection = MigrationSelection {
+            paths: vec![
+                path_of(&detection, "Zawgyi"),
+                path_of(&detection, "Pali"),
+                path_of(&detection, "broken"),
+                dir.join("Documents").join("other.km2").display().to_string(),
+            ],
+            import_active: true,
+        };
+        let report = migrate(&manager, &locations, &selection).unwrap();
+        assert_eq!(report.imported.len(), 2);
+        assert_eq!(report.failed.len(), 2);
+        assert_eq!(report.failed[1].1, "Not a KeyMagic 2 layout");
+        let zawgyi = manager.get_keyboard_by_name("Zawgyi").unwrap();
+        assert_eq!(report.activated.as_deref(), Some(zawgyi.id.as_str()));
+        assert_eq!(manager.get_active_keyboard().as_deref(), Some(zawgyi.id.as_str()));
+
+        // Migrated layouts show as installed, and the prompt is not repeated
+        let detection = detect(&manager, &locations).unwrap();
+        assert!(detection.offered);
+        assert!(!detection.should_offer());
+        let installed: Vec<_> = detection.keyboards.iter().filter(|keyboard| keyboard.installed).map(|keyboard| keyboard.name.as_str()).collect();
+        assert_eq!(installed, ["Zawgyi", "Pali"]);
+
+        // Migrating again does not install twice
+        let report = migrate(&manager, &locations, &selection).unwrap();
+        assert_eq!(report.imported.len(), 2);
+        assert_eq!(manager.get_keyboards().len(), 2);
+    }
+
+    #[test]
+    fn test_declining_records_the_offer() {
+        let dir = test_dir("decline");
+        let locations = legacy_layout(&dir);
+        let manager = manager("legacy-decline");
+        let report = migrate(&manager, &locations, &MigrationSelection::default()).unwrap();
+        assert_eq!(report, MigrationReport::default());
+        assert!(manager.get_keyboards().is_empty());
+        assert!(detect(&manager, &locations).unwrap().offered);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable_locations_are_reported() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("unreadable");
+        let mut locations = legacy_layout(&dir);
+        let locked = dir.join("locked");
+        fs::create_dir_all(&locked).unwrap();
+        fs::write(locked.join("hidden.km2"), named("Hidden")).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads anything, so there is nothing to check
+        if fs::read_dir(&locked).is_ok() {
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+        locations.folders.push(locked.clone());
+
+        let detection = detect(&manager("legacy-unreadable"), &locations).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(detection.keyboards.len(), 4);
+        assert_eq!(detection.problems.len(), 1);
+        assert_eq!(detection.problems[0].location, locked.display().to_string());
+    }
+}
@@ -1,13 +1,38 @@
+mod command_error;
+mod command_logic;
 mod commands;
 mod core;
+mod file_manager;
 mod hotkey;
+mod http;
 mod platform;
 mod updater;
+mod version;
 mod app_enumerator;
+mod soft_keyboard;
+mod input_mode;
+mod commit_history;
+mod key_stats;
+mod input_latency;
+mod composition_journal;
+mod legacy_keymagic;
+mod hook_shortcuts;
+mod input_recording;
+mod install;
+mod diagnostics;
+mod keyboard_download;
+mod native_ui;
+mod privileged;
+mod screen_reader;
+mod temp_file;
+mod uninstall;
 
 #[cfg(target_os = "macos")]
 mod imk_installer;
 
+#[cfg(target_os = "macos")]
+mod permissions;
+
 #[cfg(target_os = "linux")]
 mod ibus_config;
 
@@ -23,24 +48,66 @@ mod windows_languages;
 #[cfg(target_os = "windows")]
 mod windows_event;
 
+#[cfg(target_os = "windows")]
+mod session_events;
+
+#[cfg(target_os = "windows")]
+mod input_language;
+
+#[cfg(target_os = "windows")]
+mod registry_watcher;
+
 use commands::AppState;
 use core::KeyboardManager;
 use hotkey::HotkeyManager;
-use platform::create_platform;
+use platform::{create_platform, Platform};
 use std::sync::Arc;
-use tauri::{Emitter, Manager};
+use tauri::{Emitter, Listener, Manager};
+
+/// The keyboard manager and platform types, for driving the backend from
+/// integration tests with a `MockPlatform`
+#[cfg(feature = "test-util")]
+pub mod testing {
+    pub use crate::command_error::{CommandError, ErrorCode};
+    pub use crate::command_logic::{self, EventSink, RecordedEvents};
+    pub use crate::core::*;
+    pub use crate::hotkey::HotkeyManager;
+    pub use crate::platform::{
+        compile_keyboard, Config, HostMode, InstalledKeyboard, MockPlatform, MockPlatformBuilder, Platform,
+        DEFAULT_KEYBOARD_KMS,
+    };
+    pub use crate::version::Version;
+}
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    run_with_platform(create_platform)
+}
+
+/// Runs the app on the platform backend `create_platform` makes; it is
+/// called once logging is set up
+pub fn run_with_platform<F>(create_platform: F)
+where
+    F: FnOnce() -> anyhow::Result<Box<dyn Platform>> + Send + 'static,
+{
     tauri::Builder::default()
-        .setup(|app| {
-            // Setup logging
+        .setup(move |app| {
+            // Setup logging; release builds keep a few small files in the
+            // log directory for diagnostic bundles
             if cfg!(debug_assertions) {
                 app.handle().plugin(
                     tauri_plugin_log::Builder::default()
                         .level(log::LevelFilter::Debug)
                         .build(),
                 )?;
+            } else {
+                app.handle().plugin(
+                    tauri_plugin_log::Builder::default()
+                        .level(log::LevelFilter::Info)
+                        .max_file_size(512 * 1024)
+                        .rotation_strategy(tauri_plugin_log::RotationStrategy::KeepSome(3))
+                        .build(),
+                )?;
             }
             
             // Initialize platform
@@ -52,12 +119,227 @@ pub fn run() {
             keyboard_manager.initialize()
                 .expect("Failed to initialize keyboard manager");
             
+            // Registry entries, keyboard files and the loaded list can drift
+            // apart (e.g. a file deleted by hand); fix what is safe to fix
+            #[cfg(target_os = "windows")]
+            match keyboard_manager.repair_keyboard_store(keyboard_manager.repair_policy()) {
+                Ok(report) if !report.is_clean() => log::info!("Keyboard store repaired: {:?}", report),
+                Ok(_) => {}
+                Err(e) => log::error!("Keyboard store integrity check failed: {}", e),
+            }
+            
+            // Input methods stop processing keys once the heartbeat goes stale,
+            // so a crashed GUI does not leave them converting text
+            {
+                let keyboard_manager = keyboard_manager.clone();
+                std::thread::spawn(move || loop {
+                    std::thread::sleep(keymagic_core::processing_state::HEARTBEAT_INTERVAL);
+                    keyboard_manager.beat_input_methods();
+                });
+            }
+            
+            // Network calls go through the user's proxy, if one is set
+            if let Err(e) = http::set_proxy(http::ProxySettings::load(keyboard_manager.get_platform())) {
+                log::warn!("Ignoring the proxy setting: {}", e);
+            }
+            
+            // Show HUD messages from the backend in the UI
+            let app_handle = app.handle().clone();
+            keyboard_manager.notifications().set_hud_sink(move |message| {
+                let _ = app_handle.emit("hud_message", message);
+            });
+            keyboard_manager.notifications().set_announcer(screen_reader::announcer(app));
+            
             // Create hotkey manager
             let hotkey_manager = Arc::new(HotkeyManager::new());
+            if let Err(e) = hotkey_manager.register_all_hotkeys(&keyboard_manager, false) {
+                log::error!("Failed to register hotkeys: {}", e);
+            }
+            
+            // Sleep and session changes can lose hotkey registrations
+            #[cfg(target_os = "windows")]
+            {
+                let keyboard_manager = keyboard_manager.clone();
+                let hotkey_manager = hotkey_manager.clone();
+                session_events::start(move |event| {
+                    log::info!("Session event {:?}, registering hotkeys again", event);
+                    if let Err(e) = hotkey_manager.force_reregister(&keyboard_manager) {
+                        log::error!("Failed to re-register hotkeys: {}", e);
+                    }
+                });
+            }
+            
+            // Take keyboard changes other processes write to the registry
+            // without losing what the user is editing
+            #[cfg(target_os = "windows")]
+            {
+                let keyboard_manager = keyboard_manager.clone();
+                let hotkey_manager = hotkey_manager.clone();
+                let app_handle = app.handle().clone();
+                registry_watcher::watch(move || {
+                    match keyboard_manager.reload_external_changes() {
+                        Ok(changes) if !changes.is_empty() => {
+                            log::info!("Keyboards changed in the registry: {:?}", changes);
+                            if let Err(e) = hotkey_manager.register_all_hotkeys(&keyboard_manager, false) {
+                                log::error!("Failed to register hotkeys: {}", e);
+                            }
+                            let _ = app_handle.emit("keyboards_changed", changes);
+                        }
+                        Ok(_) => {}
+                        Err(e) => log::warn!("Failed to reload keyboards: {}", e),
+                    }
+                });
+            }
+            
+            // Key processing follows the input language of the focused window
+            #[cfg(target_os = "windows")]
+            {
+                let keyboard_manager = keyboard_manager.clone();
+                let app_handle = app.handle().clone();
+                input_language::watch(move |language| {
+                    match keyboard_manager.apply_input_language(language) {
+                        Ok(Some(applied)) => {
+                            let _ = app_handle.emit("key_processing_changed", applied.key_processing_enabled);
+                            if let Some(keyboard_id) = &applied.active_keyboard {
+                                let _ = app_handle.emit("active_keyboard_changed", keyboard_id);
+                            }
+                            let _ = app_handle.emit("language_activation_applied", applied);
+                        }
+                        Ok(None) => {}
+                        Err(e) => log::warn!("Failed to apply the rule for input language {}: {}", language, e),
+                    }
+                });
+            }
+            
+            // Show the input mode of the foreground application in settings
+            let input_mode_monitor = Arc::new(input_mode::InputModeMonitor::new());
+            let app_handle = app.handle().clone();
+            input_mode_monitor.watch(move |info| {
+                log::debug!("Input mode changed: {}", info.summary);
+                let _ = app_handle.emit("input_mode_changed", info);
+            });
+            
+            // Keep recently committed text for inserting it again
+            let commit_history_monitor = {
+                let platform = keyboard_manager.get_platform();
+                let options = commit_history::HistoryOptions::from_settings(
+                    platform.get_setting(commit_history::SIZE_SETTING).ok().flatten().as_deref(),
+                    platform.get_setting(commit_history::PERSIST_SETTING).ok().flatten().as_deref(),
+                );
+                Arc::new(commit_history::CommitHistoryMonitor::new(options, &platform.get_data_dir()))
+            };
+            let app_handle = app.handle().clone();
+            commit_history_monitor.watch(move |entries| {
+                let _ = app_handle.emit("commit_history_changed", entries);
+            });
+            
+            // Count key presses per keyboard for layout research, if opted in
+            let key_stats_monitor = {
+                let platform = keyboard_manager.get_platform();
+                let enabled = platform.get_setting(key_stats::ENABLED_SETTING).ok().flatten().as_deref() == Some("true");
+                Arc::new(key_stats::KeyStatsMonitor::new(enabled, &platform.get_data_dir()))
+            };
+            key_stats_monitor.watch();
+            
+            // Time a sample of keys in the text services; latency that stays
+            // high gets a suggestion to run diagnostics
+            let input_latency_monitor = {
+                let threshold_ms = keyboard_manager.get_platform()
+                    .get_setting(input_latency::THRESHOLD_SETTING).ok().flatten()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(input_latency::DEFAULT_THRESHOLD_MS);
+                Arc::new(input_latency::InputLatencyMonitor::new(threshold_ms))
+            };
+            {
+                let keyboard_manager = keyboard_manager.clone();
+                input_latency_monitor.watch(move || keyboard_manager.notifications().show_hud(input_latency::WARNING_MESSAGE));
+            }
+            
+            // Keep text being composed, if opted in, to offer it back after
+            // an application crashes
+            let composition_journal_monitor = {
+                let platform = keyboard_manager.get_platform();
+                let enabled = platform.get_setting(composition_journal::ENABLED_SETTING).ok().flatten().as_deref() == Some("true");
+                Arc::new(composition_journal::CompositionJournalMonitor::new(enabled, &platform.get_data_dir()))
+            };
+            composition_journal_monitor.watch();
+            
+            // Shortcuts the text services catch instead of a system-wide hotkey
+            let hook_shortcut_monitor = Arc::new(hook_shortcuts::HookShortcutMonitor::new(
+                keyboard_manager.get_platform().get_setting(hook_shortcuts::SETTING).ok().flatten().as_deref(),
+            ));
+            {
+                let keyboard_manager = keyboard_manager.clone();
+                let app_handle = app.handle().clone();
+                hook_shortcut_monitor.watch(move |triggered| {
+                    match triggered.action {
+                        hook_shortcuts::ShortcutAction::OpenKeyboardList => {
+                            if let Some(window) = app_handle.get_webview_window("main") {
+                                let _ = window.show();
+                                let _ = window.set_focus();
+                            }
+                            let _ = app_handle.emit("navigate", "keyboards");
+                        }
+                        hook_shortcuts::ShortcutAction::ToggleProcessing => {
+                            let enabled = !keyboard_manager.is_key_processing_enabled();
+                            match keyboard_manager.set_key_processing_enabled(enabled) {
+                                Ok(activated) => {
+                                    let _ = app_handle.emit("key_processing_changed", enabled);
+                                    if let Some(keyboard_id) = activated {
+                                        let _ = app_handle.emit("active_keyboard_changed", keyboard_id);
+                                    }
+                                }
+                                Err(e) => log::error!("Failed to toggle key processing: {}", e),
+                            }
+                        }
+                    }
+                    let _ = app_handle.emit("hook_shortcut_triggered", triggered);
+                });
+            }
+            
+            // Track focused windows so the on-screen keyboard can target them
+            let focus_history = soft_keyboard::SharedFocusHistory::default();
+            soft_keyboard::start_focus_tracking(focus_history.clone());
+            
+            // Load app picker icons off the command thread
+            let app_icons = {
+                let app_handle = app.handle().clone();
+                let cache_dir = keyboard_manager.get_platform().get_data_dir().join(app_enumerator::ICON_CACHE_DIR);
+                Arc::new(app_enumerator::AppIconService::new(cache_dir, app_enumerator::extract_icon, move |ready| {
+                    let _ = app_handle.emit("app_icon_ready", ready);
+                }))
+            };
             
             // Store in app state
             app.manage(keyboard_manager.clone() as AppState);
             app.manage(hotkey_manager.clone());
+            app.manage(focus_history);
+            app.manage(input_recording::InputRecording::default());
+            app.manage(input_mode_monitor);
+            app.manage(commit_history_monitor);
+            app.manage(key_stats_monitor);
+            app.manage(input_latency_monitor);
+            app.manage(composition_journal_monitor);
+            app.manage(hook_shortcut_monitor);
+            app.manage(app_icons);
+
+            // Keys for preview sessions can come as events, which skip the
+            // reply a command waits for
+            let preview_sessions = Arc::new(core::PreviewSessions::default());
+            {
+                let preview_sessions = preview_sessions.clone();
+                app.listen_any(core::preview_session::PREVIEW_KEYS_EVENT, move |event| {
+                    match serde_json::from_str::<core::PreviewInput>(event.payload()) {
+                        Ok(input) => {
+                            if let Err(e) = preview_sessions.send(&input.session_id, input.keys) {
+                                log::debug!("{}", e);
+                            }
+                        }
+                        Err(e) => log::warn!("Invalid preview keys: {}", e),
+                    }
+                });
+            }
+            app.manage(preview_sessions);
             
             // Setup plugins
             app.handle().plugin(tauri_plugin_opener::init())?;
@@ -101,13 +383,37 @@ pub fn run() {
             }
             
             app.handle().plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
-                // If another instance tries to start, focus our window
+                // If another instance tries to start, focus our window; not
+                // the webview the native controls took over from
+                if native_ui::is_open() {
+                    return;
+                }
                 if let Some(window) = app.get_webview_window("main") {
                     let _ = window.show();
                     let _ = window.set_focus();
                 }
             }))?;
             
+            // A webview that fails to load leaves a blank window; without the
+            // frontend reporting in, native controls take over
+            {
+                use core::fallback_controls::{native_ui_requested, FRONTEND_READY_EVENT, FRONTEND_READY_TIMEOUT};
+                let forced = native_ui_requested(&std::env::args().collect::<Vec<_>>());
+                let watchdog = Arc::new(core::FrontendWatchdog::new());
+                let ready = watchdog.clone();
+                app.listen_any(FRONTEND_READY_EVENT, move |_| ready.mark_ready());
+                let app_handle = app.handle().clone();
+                let keyboard_manager = keyboard_manager.clone();
+                std::thread::spawn(move || {
+                    if !forced {
+                        if watchdog.wait(FRONTEND_READY_TIMEOUT) {
+                            return;
+                        }
+                        log::error!("The UI did not load within {:?}, opening native controls", FRONTEND_READY_TIMEOUT);
+                    }
+                    native_ui::open(&app_handle, keyboard_manager);
+                });
+            }
             
             // Check for updates on startup (async)
             let app_handle = app.handle().clone();
@@ -115,6 +421,12 @@ pub fn run() {
                 // Wait a bit for the app to fully initialize
                 tokio::time::sleep(std::time::Duration::from_secs(5)).await;
                 
+                // Without a network the check would only wait for timeouts
+                if tauri::async_runtime::spawn_blocking(http::is_offline).await.unwrap_or(true) {
+                    log::info!("Offline, skipping the update check");
+                    return;
+                }
+                
                 // Check for updates silently
                 match crate::updater::check_for_updates_async().await {
                     Ok(update_info) => {
@@ -136,45 +448,134 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             commands::get_platform_info,
             commands::get_keyboards,
+            commands::query_keyboards,
+            commands::get_keyboard_icon,
+            commands::get_keyboard_status_detail,
+            commands::set_keyboard_storage,
             commands::get_active_keyboard,
             commands::set_active_keyboard,
+            commands::activate_temporary_keyboard,
+            commands::end_temporary_activation,
+            commands::get_temporary_keyboard,
+            commands::activate_keyboard_by_hotkey,
+            commands::trigger_double_tap,
+            commands::get_key_processing_enabled,
+            commands::set_key_processing_enabled,
+            commands::set_auto_enable_on_keyboard_hotkey,
             commands::get_keyboard_layout,
+            commands::explain_key,
+            commands::send_virtual_key,
+            commands::get_commit_history,
+            commands::reinsert_commit,
+            commands::clear_commit_history,
+            commands::set_commit_history_options,
+            commands::get_key_stats_enabled,
+            commands::set_key_stats_enabled,
+            commands::get_key_heatmap,
+            commands::export_key_heatmap_csv,
+            commands::clear_key_stats,
+            commands::get_input_latency_stats,
+            commands::set_input_latency_threshold,
+            commands::get_composition_journal_enabled,
+            commands::set_composition_journal_enabled,
+            commands::get_unrecovered_compositions,
+            commands::dismiss_unrecovered_composition,
+            commands::get_hook_shortcuts,
+            commands::register_global_shortcut_via_hook,
+            commands::unregister_global_shortcut_via_hook,
+            commands::diff_keyboards,
+            commands::analyze_keyboard_rules,
+            commands::analyze_keyboard_performance,
+            commands::enumerate_keyboard_outputs,
+            commands::start_input_recording,
+            commands::stop_input_recording,
+            commands::get_input_recording_status,
+            commands::export_input_recording,
+            commands::get_recording_process_summary,
+            commands::create_diagnostic_bundle,
+            commands::diff_installed_keyboard,
             commands::scan_keyboards,
             commands::import_keyboard,
+            commands::import_keyboard_from_url,
             commands::remove_keyboard,
+            commands::repair_keyboard_store,
+            commands::force_rehash_keyboards,
             commands::update_hotkey,
+            commands::rewrite_keyboard_metadata,
+            commands::begin_keyboard_edit,
+            commands::end_keyboard_edit,
+            commands::set_output_encoding,
+            commands::get_rule_groups,
+            commands::set_rule_group_enabled,
+            commands::get_passthrough_keys,
+            commands::set_passthrough_keys,
+            commands::get_keyboard_options,
+            commands::set_keyboard_option,
+            commands::get_switch_announcement,
+            commands::set_switch_announcement,
             commands::validate_hotkey,
+            commands::force_reregister_hotkeys,
             commands::check_for_updates,
+            commands::get_proxy_settings,
+            commands::set_proxy_settings,
             commands::restart_app,
             commands::quit_app,
             commands::open_keyboards_folder,
+            commands::reveal_keyboard_file,
             commands::get_composition_mode_hosts,
             commands::set_composition_mode_hosts,
+            commands::list_profiles,
+            commands::save_current_as_profile,
+            commands::apply_profile,
+            commands::delete_profile,
             commands::get_app_version,
             commands::should_scan_bundled_keyboards,
             commands::get_bundled_keyboards,
             commands::import_bundled_keyboard,
             commands::mark_bundled_keyboards_scanned,
+            commands::detect_legacy_keymagic,
+            commands::migrate_legacy_keyboards,
             commands::get_setting,
             commands::set_setting,
             commands::get_update_remind_after,
             commands::set_update_remind_after,
-            commands::run_command,
+            commands::open_url,
+            commands::open_input_settings,
+            commands::download_update,
+            commands::launch_installer,
             commands::add_composition_mode_host,
             commands::remove_composition_mode_host,
+            commands::get_effective_input_mode,
+            commands::get_language_activation,
+            commands::set_language_activation_enabled,
+            commands::set_language_activation_rules,
             commands::get_direct_mode_hosts,
             commands::add_direct_mode_host,
             commands::remove_direct_mode_host,
+            commands::get_shortcut_passthrough_keys,
+            commands::set_shortcut_passthrough_keys,
+            commands::remove_shortcut_passthrough_host,
+            commands::hud_test_burst,
+            commands::get_char_suppression_hosts,
+            commands::set_char_suppression_hosts,
             commands::get_supported_languages,
             commands::get_enabled_languages,
             commands::search_languages,
             commands::set_enabled_languages,
-            commands::apply_language_changes_elevated,
+            commands::restart_elevated,
             commands::check_for_update,
             commands::convert_kms_to_km2,
+            commands::export_km2_json,
+            commands::import_km2_json,
             commands::validate_kms_file,
+            commands::test_kms_snippet,
+            commands::start_preview_session,
+            commands::send_preview_keys,
+            commands::end_preview_session,
             commands::convert_kms_file,
+            commands::convert_kmn_file,
             commands::get_running_apps,
+            commands::get_app_icon,
             #[cfg(target_os = "macos")]
             imk_installer::check_imk_status,
             #[cfg(target_os = "macos")]
@@ -183,13 +584,36 @@ pub fn run() {
             imk_installer::uninstall_imk_bundle,
             #[cfg(target_os = "macos")]
             imk_installer::open_input_sources_settings,
+            #[cfg(target_os = "macos")]
+            permissions::get_permission_status,
+            #[cfg(target_os = "macos")]
+            permissions::request_permission,
+            #[cfg(target_os = "macos")]
+            permissions::open_permission_settings,
             #[cfg(target_os = "linux")]
             ibus_config::get_ibus_config,
             #[cfg(target_os = "linux")]
             ibus_config::check_ibus_installed,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // A keyboard being tried out must not outlive the app
+            if let tauri::RunEvent::Exit = event {
+                if let Some(manager) = app.try_state::<AppState>() {
+                    if let Err(e) = manager.end_any_temporary_activation() {
+                        log::warn!("Failed to end temporary keyboard: {}", e);
+                    }
+                }
+            }
+        });
+}
+
+/// Runs only the native control window, for `--native-ui` on macOS where it
+/// cannot share the process with the webview
+#[cfg(target_os = "macos")]
+pub fn run_native_ui() -> i32 {
+    native_ui::run_standalone()
 }
 
 /// Update language profiles when running with elevated privileges
@@ -218,3 +642,58 @@ pub fn update_languages_elevated(_languages_str: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Removes settings, keyboards and system registrations for the
+/// uninstallers; prints what each step did and returns the exit code
+pub fn uninstall_cleanup(args: &[String]) -> i32 {
+    let options = uninstall::CleanupOptions::from_args(args);
+    let report = uninstall::uninstall_cleanup(&options);
+    println!("{}", report);
+    report.exit_code()
+}
+
+
+/// Checks the install for package manager CI; prints the report, as JSON
+/// with `--json`, and returns the exit code
+pub fn verify_install(args: &[String]) -> i32 {
+    let platform = create_platform();
+    let report = install::verify_install(platform.as_deref().map_err(|e| anyhow::anyhow!("{:#}", e)));
+    if args.iter().any(|arg| arg == "--json") {
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to write the report: {}", e);
+                return 1;
+            }
+        }
+    } else {
+        println!("{}", report);
+    }
+    report.exit_code()
+}
+
+/// Saves the default settings and imports the bundled keyboards without a
+/// window, for post-install scripts; prints what it did and returns the
+/// exit code
+pub fn silent_first_run() -> i32 {
+    let manager = match create_platform().map(KeyboardManager::new) {
+        Ok(manager) => manager,
+        Err(e) => {
+            eprintln!("Failed to create platform backend: {}", e);
+            return 1;
+        }
+    };
+    if let Err(e) = manager.initialize() {
+        eprintln!("Failed to initialize keyboard manager: {}", e);
+        return 1;
+    }
+    match install::first_run(&manager) {
+        Ok(report) => {
+            println!("{}", report);
+            report.exit_code()
+        }
+        Err(e) => {
+            eprintln!("First run failed: {:#}", e);
+            1
+        }
+    }
+}
@@ -20,6 +20,25 @@ fn main() {
         }
     }
     
+    // Run by the uninstallers: --uninstall-cleanup [--keep-user-data] [--remove-input-method]
+    if args.iter().any(|arg| arg == "--uninstall-cleanup") {
+        std::process::exit(keymagic_gui_lib::uninstall_cleanup(&args[1..]));
+    }
+    
+    // Run by package managers and post-install scripts, without a window
+    if args.iter().any(|arg| arg == "--verify-install") {
+        std::process::exit(keymagic_gui_lib::verify_install(&args[1..]));
+    }
+    if args.iter().any(|arg| arg == "--silent-first-run") {
+        std::process::exit(keymagic_gui_lib::silent_first_run());
+    }
+    
+    // macOS has room for one event loop, so --native-ui runs without the webview
+    #[cfg(target_os = "macos")]
+    if args.iter().skip(1).any(|arg| arg == "--native-ui") {
+        std::process::exit(keymagic_gui_lib::run_native_ui());
+    }
+    
     // Normal GUI execution
     keymagic_gui_lib::run();
 }
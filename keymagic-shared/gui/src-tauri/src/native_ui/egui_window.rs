@@ -0,0 +1,114 @@
+//! The native control window drawn with egui
+
+use super::{Controls, TITLE};
+use crate::core::fallback_controls::{FallbackAction, FallbackOutcome, FallbackView};
+use eframe::egui;
+use std::time::{Duration, Instant};
+
+/// How often the window picks up changes made by hotkeys and the text services
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+struct ControlsApp {
+    controls: Controls,
+    view: FallbackView,
+    refreshed: Instant,
+    status: String,
+}
+
+impl ControlsApp {
+    fn new(controls: Controls) -> Self {
+        let view = controls.view();
+        Self {
+            controls,
+            view,
+            refreshed: Instant::now(),
+            status: "The settings window could not be loaded.".to_string(),
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.view = self.controls.view();
+        self.refreshed = Instant::now();
+    }
+
+    fn perform(&mut self, ctx: &egui::Context, action: FallbackAction) {
+        match self.controls.perform(action) {
+            Ok(FallbackOutcome::Quit) => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+            Ok(_) => {
+                self.status.clear();
+                self.refresh();
+            }
+            Err(e) => self.status = e,
+        }
+    }
+}
+
+impl eframe::App for ControlsApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Closing the window quits, as closing the main window does
+        if ctx.input(|input| input.viewport().close_requested()) {
+            let _ = self.controls.perform(FallbackAction::Quit);
+            return;
+        }
+        if self.refreshed.elapsed() >= REFRESH_INTERVAL {
+            self.refresh();
+        }
+
+        let mut action = None;
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
+            ui.label(&self.status);
+        });
+        egui::SidePanel::right("actions").resizable(false).show(ctx, |ui| {
+            ui.add_space(4.0);
+            if ui.button(self.view.toggle_label()).clicked() {
+                action = Some(FallbackAction::ToggleKeyProcessing);
+            }
+            if ui.button("Open Keyboards Folder").clicked() {
+                action = Some(FallbackAction::OpenKeyboardsFolder);
+            }
+            if ui.button("Quit").clicked() {
+                action = Some(FallbackAction::Quit);
+            }
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Keyboards");
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for keyboard in &self.view.keyboards {
+                    if ui.selectable_label(keyboard.is_active, keyboard.label()).clicked() && !keyboard.is_active {
+                        action = Some(FallbackAction::Activate(keyboard.id.clone()));
+                    }
+                }
+            });
+        });
+
+        if let Some(action) = action {
+            self.perform(ctx, action);
+        }
+        ctx.request_repaint_after(REFRESH_INTERVAL);
+    }
+}
+
+/// Runs the window on the calling thread until it closes
+pub fn run(controls: Controls) -> eframe::Result<()> {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_title(TITLE).with_inner_size([420.0, 360.0]),
+        // The main thread runs the webview's event loop
+        #[cfg(target_os = "linux")]
+        event_loop_builder: Some(Box::new(|builder| {
+            use winit::platform::x11::EventLoopBuilderExtX11;
+            builder.with_any_thread(true);
+        })),
+        ..Default::default()
+    };
+    eframe::run_native(TITLE, options, Box::new(|_| Ok(Box::new(ControlsApp::new(controls)))))
+}
+
+/// Opens the window on a new thread
+#[cfg(target_os = "linux")]
+pub fn spawn(controls: Controls) {
+    std::thread::spawn(move || {
+        if let Err(e) = run(controls) {
+            log::error!("Failed to open the native controls: {}", e);
+        }
+    });
+}
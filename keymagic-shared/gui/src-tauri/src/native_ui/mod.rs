@@ -0,0 +1,126 @@
+//! Native control window for when the webview UI does not load
+//!
+//! [`crate::core::fallback_controls`] decides what the window shows and what
+//! its buttons do; this module only draws it. On Windows it is a plain Win32
+//! window with a thread and message loop of its own, beside the Tauri event
+//! loop. Elsewhere it is an egui window. Linux lets winit run off the main
+//! thread, so it opens beside the webview too. macOS allows one event loop
+//! per process, on the main thread tao already owns, so there the window
+//! runs in a new process started with `--native-ui` and the broken one exits.
+
+#[cfg(not(target_os = "windows"))]
+mod egui_window;
+#[cfg(target_os = "windows")]
+mod win32;
+
+use crate::core::fallback_controls::{FallbackAction, FallbackOutcome, FallbackView};
+use crate::core::KeyboardManager;
+use crate::file_manager;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Title of the native window
+const TITLE: &str = "KeyMagic";
+
+static OPEN: AtomicBool = AtomicBool::new(false);
+
+/// What the window works on: the app's keyboard manager, and the app to
+/// tell about changes when the window runs beside it
+pub struct Controls {
+    manager: Arc<KeyboardManager>,
+    app: Option<AppHandle>,
+}
+
+impl Controls {
+    pub fn view(&self) -> FallbackView {
+        FallbackView::load(&self.manager)
+    }
+
+    /// Applies `action` and does what it leaves to the window: the events
+    /// the matching command emits, opening the folder and quitting. Errors
+    /// come back as text for the window to show.
+    pub fn perform(&self, action: FallbackAction) -> Result<FallbackOutcome, String> {
+        let outcome = action.apply(&self.manager).map_err(|e| {
+            log::error!("Native controls: {}", e);
+            e.to_string()
+        })?;
+        if let Some(app) = &self.app {
+            for (event, payload) in outcome.events() {
+                let _ = app.emit(event, payload);
+            }
+        }
+        match &outcome {
+            FallbackOutcome::OpenFolder(path) => file_manager::open_folder(path).map_err(|e| e.to_string())?,
+            FallbackOutcome::Quit => {
+                if let Some(app) = &self.app {
+                    app.exit(0);
+                }
+            }
+            _ => {}
+        }
+        Ok(outcome)
+    }
+}
+
+/// Whether the native window has taken over from the webview
+pub fn is_open() -> bool {
+    OPEN.load(Ordering::SeqCst)
+}
+
+/// Hides the webview window and opens the native one; only the first call
+/// takes effect
+pub fn open(app: &AppHandle, manager: Arc<KeyboardManager>) {
+    if OPEN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.hide();
+    }
+    let controls = Controls { manager, app: Some(app.clone()) };
+
+    #[cfg(target_os = "windows")]
+    win32::open(controls);
+
+    #[cfg(target_os = "linux")]
+    egui_window::spawn(controls);
+
+    #[cfg(target_os = "macos")]
+    {
+        drop(controls);
+        let started = std::env::current_exe().and_then(|exe| {
+            std::process::Command::new(exe)
+                .arg(crate::core::fallback_controls::NATIVE_UI_FLAG)
+                .spawn()
+        });
+        match started {
+            Ok(_) => app.exit(0),
+            Err(e) => log::error!("Failed to start the native controls: {}", e),
+        }
+    }
+}
+
+/// Runs the native window as the whole app, on the main thread; for macOS,
+/// where it cannot share the process with the webview
+#[cfg(target_os = "macos")]
+pub fn run_standalone() -> i32 {
+    let manager = match crate::platform::create_platform().map(KeyboardManager::new) {
+        Ok(manager) => manager,
+        Err(e) => {
+            eprintln!("Failed to create platform backend: {}", e);
+            return 1;
+        }
+    };
+    if let Err(e) = manager.initialize() {
+        eprintln!("Failed to initialize keyboard manager: {}", e);
+        return 1;
+    }
+    OPEN.store(true, Ordering::SeqCst);
+    match egui_window::run(Controls { manager: Arc::new(manager), app: None }) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Failed to open the native controls: {}", e);
+            1
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! The native control window as a Win32 window
+//!
+//! The window lives on a thread of its own: its state stays in a thread
+//! local, and its message loop runs next to the Tauri one.
+
+use super::{Controls, TITLE};
+use crate::core::fallback_controls::{FallbackAction, FallbackOutcome, FallbackView};
+use std::cell::RefCell;
+use windows::core::{w, Result, HSTRING, PCWSTR};
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::Graphics::Gdi::{GetStockObject, COLOR_BTNFACE, DEFAULT_GUI_FONT, HBRUSH};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, IsDialogMessageW, LoadCursorW, PostQuitMessage,
+    RegisterClassW, SendMessageW, SetForegroundWindow, SetWindowTextW, ShowWindow, TranslateMessage, BN_CLICKED,
+    BS_PUSHBUTTON, CW_USEDEFAULT, HMENU, IDC_ARROW, LBN_DBLCLK, LBS_NOTIFY, LB_ADDSTRING, LB_ERR, LB_GETCURSEL,
+    LB_RESETCONTENT, LB_SETCURSEL, MSG, SW_SHOW, WA_INACTIVE, WINDOW_EX_STYLE, WINDOW_STYLE, WM_ACTIVATE, WM_CLOSE,
+    WM_COMMAND, WM_DESTROY, WM_SETFONT, WNDCLASSW, WS_BORDER, WS_CAPTION, WS_CHILD, WS_MINIMIZEBOX, WS_OVERLAPPED,
+    WS_SYSMENU, WS_TABSTOP, WS_VISIBLE, WS_VSCROLL,
+};
+
+const ID_LIST: usize = 100;
+const ID_ACTIVATE: usize = 101;
+const ID_TOGGLE: usize = 102;
+const ID_FOLDER: usize = 103;
+const ID_QUIT: usize = 104;
+
+const WIDTH: i32 = 420;
+const HEIGHT: i32 = 360;
+const MARGIN: i32 = 12;
+const BUTTON_WIDTH: i32 = 180;
+const BUTTON_HEIGHT: i32 = 28;
+
+/// The window's controls and what it last showed
+struct Window {
+    controls: Controls,
+    view: FallbackView,
+    list: HWND,
+    toggle: HWND,
+    status: HWND,
+}
+
+thread_local! {
+    static WINDOW: RefCell<Option<Window>> = const { RefCell::new(None) };
+}
+
+impl Window {
+    /// Shows the manager's current state
+    fn refresh(&mut self) {
+        self.view = self.controls.view();
+        unsafe {
+            SendMessageW(self.list, LB_RESETCONTENT, WPARAM(0), LPARAM(0));
+            for keyboard in &self.view.keyboards {
+                let marker = if keyboard.is_active { "● " } else { "   " };
+                let label = HSTRING::from(format!("{}{}", marker, keyboard.label()));
+                SendMessageW(self.list, LB_ADDSTRING, WPARAM(0), LPARAM(label.as_ptr() as isize));
+            }
+            if let Some(index) = self.view.active_index() {
+                SendMessageW(self.list, LB_SETCURSEL, WPARAM(index), LPARAM(0));
+            }
+            let _ = SetWindowTextW(self.toggle, &HSTRING::from(self.view.toggle_label()));
+        }
+    }
+
+    fn set_status(&self, text: &str) {
+        unsafe {
+            let _ = SetWindowTextW(self.status, &HSTRING::from(text));
+        }
+    }
+
+    fn selected_keyboard(&self) -> Option<String> {
+        let index = unsafe { SendMessageW(self.list, LB_GETCURSEL, WPARAM(0), LPARAM(0)).0 };
+        if index == LB_ERR as isize {
+            return None;
+        }
+        self.view.keyboards.get(index as usize).map(|keyboard| keyboard.id.clone())
+    }
+
+    fn perform(&mut self, action: FallbackAction) {
+        match self.controls.perform(action) {
+            Ok(FallbackOutcome::Quit) => {}
+            Ok(_) => {
+                self.set_status("");
+                self.refresh();
+            }
+            Err(e) => self.set_status(&e),
+        }
+    }
+
+    fn command(&mut self, id: usize, notification: u32) {
+        match (id, notification) {
+            (ID_LIST, LBN_DBLCLK) | (ID_ACTIVATE, BN_CLICKED) => match self.selected_keyboard() {
+                Some(keyboard_id) => self.perform(FallbackAction::Activate(keyboard_id)),
+                None => self.set_status("Select a keyboard first"),
+            },
+            (ID_TOGGLE, BN_CLICKED) => self.perform(FallbackAction::ToggleKeyProcessing),
+            (ID_FOLDER, BN_CLICKED) => self.perform(FallbackAction::OpenKeyboardsFolder),
+            (ID_QUIT, BN_CLICKED) => self.perform(FallbackAction::Quit),
+            _ => {}
+        }
+    }
+}
+
+fn with_window(f: impl FnOnce(&mut Window)) {
+    // Messages sent while a handler runs find the window borrowed; they
+    // need nothing from it
+    WINDOW.with(|window| {
+        if let Ok(mut window) = window.try_borrow_mut() {
+            if let Some(window) = window.as_mut() {
+                f(window);
+            }
+        }
+    });
+}
+
+extern "system" fn window_proc(window: HWND, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    match message {
+        WM_COMMAND => {
+            let id = wparam.0 & 0xFFFF;
+            let notification = ((wparam.0 >> 16) & 0xFFFF) as u32;
+            with_window(|window| window.command(id, notification));
+            LRESULT(0)
+        }
+        // Hotkeys and the text services change the state behind the window
+        WM_ACTIVATE if (wparam.0 & 0xFFFF) as u32 != WA_INACTIVE => {
+            with_window(Window::refresh);
+            unsafe { DefWindowProcW(window, message, wparam, lparam) }
+        }
+        // Closing the window quits, as closing the main window does
+        WM_CLOSE => {
+            with_window(|window| window.perform(FallbackAction::Quit));
+            unsafe { DefWindowProcW(window, message, wparam, lparam) }
+        }
+        WM_DESTROY => {
+            unsafe { PostQuitMessage(0) };
+            LRESULT(0)
+        }
+        _ => unsafe { DefWindowProcW(window, message, wparam, lparam) },
+    }
+}
+
+unsafe fn create_child(
+    parent: HWND,
+    class: PCWSTR,
+    text: &str,
+    style: WINDOW_STYLE,
+    id: usize,
+    rect: (i32, i32, i32, i32),
+) -> Result<HWND> {
+    let (x, y, width, height) = rect;
+    let child = CreateWindowExW(
+        WINDOW_EX_STYLE::default(),
+        class,
+        &HSTRING::from(text),
+        WS_CHILD | WS_VISIBLE | style,
+        x,
+        y,
+        width,
+        height,
+        parent,
+        HMENU(id as _),
+        None,
+        None,
+    )?;
+    let font = GetStockObject(DEFAULT_GUI_FONT);
+    SendMessageW(child, WM_SETFONT, WPARAM(font.0 as usize), LPARAM(1));
+    Ok(child)
+}
+
+fn run_window(controls: Controls) -> Result<()> {
+    unsafe {
+        let instance = GetModuleHandleW(None)?;
+        let class_name = w!("KeyMagicNativeControls");
+        let class = WNDCLASSW {
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            lpfnWndProc: Some(window_proc),
+            hCursor: LoadCursorW(None, IDC_ARROW)?,
+            hbrBackground: HBRUSH((COLOR_BTNFACE.0 + 1) as _),
+            ..Default::default()
+        };
+        RegisterClassW(&class);
+
+        let window = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            class_name,
+            &HSTRING::from(TITLE),
+            WS_OVERLAPPED | WS_CAPTION | WS_SYSMENU | WS_MINIMIZEBOX,
+            CW_USEDEFAULT,
+            CW_USEDEFAULT,
+            WIDTH,
+            HEIGHT,
+            None,
+            None,
+            instance,
+            None,
+        )?;
+
+        // Keyboard list on the left, buttons down the right, errors below
+        let list_width = WIDTH - BUTTON_WIDTH - 4 * MARGIN;
+        let list_height = HEIGHT - 6 * MARGIN - BUTTON_HEIGHT;
+        let list = create_child(
+            window,
+            w!("LISTBOX"),
+            "",
+            WS_BORDER | WS_VSCROLL | WS_TABSTOP | WINDOW_STYLE(LBS_NOTIFY as u32),
+            ID_LIST,
+            (MARGIN, MARGIN, list_width, list_height),
+        )?;
+        let button_x = MARGIN * 2 + list_width;
+        let button = |id: usize, text: &str, row: i32| {
+            create_child(
+                window,
+                w!("BUTTON"),
+                text,
+                WS_TABSTOP | WINDOW_STYLE(BS_PUSHBUTTON as u32),
+                id,
+                (button_x, MARGIN + row * (BUTTON_HEIGHT + MARGIN / 2), BUTTON_WIDTH, BUTTON_HEIGHT),
+            )
+        };
+        button(ID_ACTIVATE, "Activate", 0)?;
+        let toggle = button(ID_TOGGLE, "", 1)?;
+        button(ID_FOLDER, "Open Keyboards Folder", 2)?;
+        button(ID_QUIT, "Quit", 3)?;
+        let status = create_child(
+            window,
+            w!("STATIC"),
+            "The settings window could not be loaded.",
+            WINDOW_STYLE::default(),
+            0,
+            (MARGIN, MARGIN * 2 + list_height, WIDTH - 3 * MARGIN, BUTTON_HEIGHT),
+        )?;
+
+        WINDOW.with(|slot| {
+            let view = FallbackView { keyboards: Vec::new(), key_processing_enabled: false };
+            let mut window = Window { controls, view, list, toggle, status };
+            window.refresh();
+            *slot.borrow_mut() = Some(window);
+        });
+        let _ = ShowWindow(window, SW_SHOW);
+        let _ = SetForegroundWindow(window);
+
+        let mut message = MSG::default();
+        while GetMessageW(&mut message, None, 0, 0).as_bool() {
+            // Tab and Enter move between and press the controls
+            if !IsDialogMessageW(window, &message).as_bool() {
+                let _ = TranslateMessage(&message);
+                DispatchMessageW(&message);
+            }
+        }
+        WINDOW.with(|slot| slot.borrow_mut().take());
+    }
+    Ok(())
+}
+
+/// Opens the window on a new thread
+pub fn open(controls: Controls) {
+    std::thread::spawn(move || {
+        if let Err(e) = run_window(controls) {
+            log::error!("Failed to open the native controls: {}", e);
+        }
+    });
+}
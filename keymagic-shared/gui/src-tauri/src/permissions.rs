@@ -0,0 +1,99 @@
+//! Privacy permission checks and prompts through the macOS APIs
+//!
+//! Which permissions are needed is decided in `core::permissions`; this only
+//! asks macOS. Accessibility and Screen Recording only report whether they
+//! are granted, so "not granted" is reported as not determined.
+
+use cocoa::base::{id, YES};
+use objc::{class, msg_send, sel, sel_impl};
+use std::process::Command;
+
+use crate::core::permissions::{self, Permission, PermissionState, PermissionStatus, ALL_PERMISSIONS, FEATURES};
+
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    static kAXTrustedCheckOptionPrompt: id;
+    fn AXIsProcessTrusted() -> u8;
+    fn AXIsProcessTrustedWithOptions(options: id) -> u8;
+}
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IOHIDCheckAccess(request_type: u32) -> u32;
+    fn IOHIDRequestAccess(request_type: u32) -> bool;
+}
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGPreflightScreenCaptureAccess() -> bool;
+    fn CGRequestScreenCaptureAccess() -> bool;
+}
+
+/// kIOHIDRequestTypeListenEvent
+const IOHID_REQUEST_LISTEN_EVENT: u32 = 1;
+/// IOHIDAccessType values
+const IOHID_ACCESS_GRANTED: u32 = 0;
+const IOHID_ACCESS_DENIED: u32 = 1;
+
+fn query(permission: Permission) -> PermissionState {
+    let granted = |granted: bool| if granted { PermissionState::Granted } else { PermissionState::NotDetermined };
+    unsafe {
+        match permission {
+            Permission::Accessibility => granted(AXIsProcessTrusted() != 0),
+            Permission::InputMonitoring => match IOHIDCheckAccess(IOHID_REQUEST_LISTEN_EVENT) {
+                IOHID_ACCESS_GRANTED => PermissionState::Granted,
+                IOHID_ACCESS_DENIED => PermissionState::Denied,
+                _ => PermissionState::NotDetermined,
+            },
+            Permission::ScreenRecording => granted(CGPreflightScreenCaptureAccess()),
+        }
+    }
+}
+
+/// Shows the system prompt; the answer arrives later, through System Settings
+fn request(permission: Permission) {
+    unsafe {
+        match permission {
+            Permission::Accessibility => {
+                let prompt: id = msg_send![class!(NSNumber), numberWithBool: YES];
+                let options: id = msg_send![class!(NSDictionary), dictionaryWithObject: prompt forKey: kAXTrustedCheckOptionPrompt];
+                AXIsProcessTrustedWithOptions(options);
+            }
+            Permission::InputMonitoring => {
+                IOHIDRequestAccess(IOHID_REQUEST_LISTEN_EVENT);
+            }
+            Permission::ScreenRecording => {
+                CGRequestScreenCaptureAccess();
+            }
+        }
+    }
+}
+
+/// Each permission's state, for the permissions panel
+#[tauri::command]
+pub fn get_permission_status() -> Vec<PermissionStatus> {
+    let required = permissions::required_permissions(&FEATURES);
+    ALL_PERMISSIONS.iter().map(|permission| permissions::status(*permission, &required, query)).collect()
+}
+
+/// Asks for a permission a feature is about to use; permissions no feature
+/// needs are never asked for
+#[tauri::command]
+pub fn request_permission(permission: Permission) -> PermissionState {
+    let state = permissions::ensure(permission, &permissions::required_permissions(&FEATURES), query, request);
+    log::info!("Permission {:?}: {:?}", permission, state);
+    state
+}
+
+/// Opens the permission's pane in System Settings
+#[tauri::command]
+pub fn open_permission_settings(permission: Permission) -> Result<(), String> {
+    let output = Command::new("open")
+        .arg(permission.settings_url())
+        .output()
+        .map_err(|e| format!("Failed to open System Settings: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("Failed to open System Settings: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
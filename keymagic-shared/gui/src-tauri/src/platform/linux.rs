@@ -5,6 +5,7 @@ use super::{
 use anyhow::{Context, Result};
 use std::fs;
 use std::path::PathBuf;
+use crate::temp_file;
 use keymagic_core::hotkey::HotkeyBinding;
 
 pub struct LinuxBackend {
@@ -50,6 +51,7 @@ impl LinuxBackend {
                 last_update_check: None,
                 last_scanned_version: None,
                 update_remind_after: None,
+                key_processing_enabled: None,
             },
             keyboards: KeyboardsConfig {
                 active: None,
@@ -62,6 +64,11 @@ impl LinuxBackend {
             direct_mode: DirectModeConfig {
                 enabled_hosts: vec![],
             },
+            shortcut_passthrough: Default::default(),
+            char_suppression: Default::default(),
+            profiles: Default::default(),
+            active_profile: None,
+            language_activation: Default::default(),
         }
     }
 }
@@ -87,7 +94,7 @@ impl Platform for LinuxBackend {
         let contents = toml::to_string_pretty(config)
             .context("Failed to serialize config")?;
         
-        fs::write(&config_path, contents)
+        temp_file::write_durable(&config_path, contents)
             .context("Failed to write config file")?;
         
         Ok(())
@@ -153,6 +160,52 @@ impl Platform for LinuxBackend {
         }
     }
     
+    fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        match key {
+            // Kept in config.toml where the IBus engine watches it
+            "key_processing_enabled" => Ok(self
+                .load_config()?
+                .general
+                .key_processing_enabled
+                .map(|enabled| enabled.to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "key_processing_enabled" => {
+                let mut config = self.load_config()?;
+                config.general.key_processing_enabled = Some(value != "false");
+                self.save_config(&config)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn os_version(&self) -> Option<String> {
+        let release = fs::read_to_string("/etc/os-release").ok()?;
+        release.lines()
+            .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+            .map(|name| name.trim_matches('"').to_string())
+    }
+    
+    fn installed_font_families(&self) -> Option<Vec<String>> {
+        // One font per line, with its families separated by commas
+        let output = std::process::Command::new("fc-list").args([":", "family"]).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .flat_map(|line| line.split(','))
+                .map(|family| family.trim().to_string())
+                .filter(|family| !family.is_empty())
+                .collect(),
+        )
+    }
+    
     fn get_bundled_keyboards_path(&self) -> Option<PathBuf> {
         // Check system-wide bundled keyboards location
         let system_keyboards_path = PathBuf::from("/usr/share/keymagic3/keyboards");
@@ -180,7 +233,12 @@ impl Platform for LinuxBackend {
         
         // Try to parse the hotkey
         match HotkeyBinding::parse(hotkey) {
-            Ok(binding) => {
+            Ok(HotkeyBinding::DoubleTap(key)) => {
+                // Named twice, as typed
+                let name = key.to_display_string();
+                format!("{} {}", name, name)
+            }
+            Ok(HotkeyBinding::Combo(binding)) => {
                 let mut parts = Vec::new();
                 
                 // Add modifiers in Linux order: Super, Ctrl, Alt, Shift
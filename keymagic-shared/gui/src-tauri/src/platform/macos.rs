@@ -3,27 +3,49 @@ use super::{
     Platform, PlatformFeatures, PlatformInfo,
 };
 use anyhow::{Context, Result};
+use cocoa::base::{id, nil, BOOL, YES};
+use cocoa::foundation::{NSAutoreleasePool, NSString};
+use objc::{class, msg_send, sel, sel_impl};
 use plist;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use crate::temp_file;
 use keymagic_core::hotkey::HotkeyBinding;
 
+/// Bundle identifier of the KeyMagic input method (IMK) process
+pub const IMK_BUNDLE_ID: &str = "org.keymagic.inputmethod.KeyMagic3";
+
+/// Distributed notification posted when the active keyboard changes.
+/// The notification object is the keyboard id; the IMK switches to it
+/// without waiting for the config file to be rewritten.
+pub const KEYBOARD_CHANGED_NOTIFICATION: &str = "org.keymagic.inputmethod.KeyboardChanged";
+
+/// Config file name inside the data directory
+pub const CONFIG_FILE_NAME: &str = "config.plist";
+
 pub struct MacOSBackend {
     config_dir: PathBuf,
     data_dir: PathBuf,
     keyboards_dir: PathBuf,
+    /// Config location used by earlier versions, migrated on first load
+    legacy_config_path: Option<PathBuf>,
 }
 
 impl MacOSBackend {
     pub fn new() -> Result<Self> {
-        let config_dir = dirs::preference_dir()
-            .context("Failed to get preferences directory")?
-            .join("net.keymagic");
-        
+        // ~/Library/Application Support/KeyMagic holds both config and keyboards
         let data_dir = dirs::data_dir()
             .context("Failed to get data directory")?
             .join("KeyMagic");
         
+        let legacy_config_path = dirs::preference_dir()
+            .map(|dir| dir.join("net.keymagic").join(CONFIG_FILE_NAME));
+        
+        Self::with_dirs(data_dir.clone(), data_dir, legacy_config_path)
+    }
+    
+    /// Creates a backend rooted at the given directories, creating them if needed
+    pub fn with_dirs(config_dir: PathBuf, data_dir: PathBuf, legacy_config_path: Option<PathBuf>) -> Result<Self> {
         // User-specific keyboards directory (primary location)
         let keyboards_dir = data_dir.join("Keyboards");
         
@@ -36,11 +58,24 @@ impl MacOSBackend {
             config_dir,
             data_dir,
             keyboards_dir,
+            legacy_config_path,
         })
     }
     
     fn get_config_path(&self) -> PathBuf {
-        self.config_dir.join("config.plist")
+        self.config_dir.join(CONFIG_FILE_NAME)
+    }
+    
+    /// Reads a config written by an earlier version from its old location
+    fn load_legacy_config(&self) -> Option<Config> {
+        let legacy_path = self.legacy_config_path.as_ref().filter(|p| p.exists())?;
+        match plist::from_file(legacy_path) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                log::warn!("Ignoring unreadable legacy config {}: {}", legacy_path.display(), e);
+                None
+            }
+        }
     }
     
     fn default_config() -> Config {
@@ -51,6 +86,7 @@ impl MacOSBackend {
                 last_update_check: None,
                 last_scanned_version: None,
                 update_remind_after: None,
+                key_processing_enabled: None,
             },
             keyboards: KeyboardsConfig {
                 active: None,
@@ -76,8 +112,34 @@ impl MacOSBackend {
                     "com.apple.AppStore".to_string(),
                 ],
             },
+            shortcut_passthrough: Default::default(),
+            char_suppression: Default::default(),
+            profiles: Default::default(),
+            active_profile: None,
+            language_activation: Default::default(),
+        }
+    }
+}
+
+/// Resolves `Contents/Resources/keyboards` from the executable inside an app
+/// bundle, falling back to a `keyboards` directory next to the executable
+/// for development builds
+fn bundled_keyboards_path_for_exe(exe_path: &Path) -> Option<PathBuf> {
+    // Typical structure: MyApp.app/Contents/MacOS/executable
+    let macos_dir = exe_path.parent()?;
+    if let Some(contents_dir) = macos_dir.parent() {
+        let resources_keyboards_path = contents_dir.join("Resources").join("keyboards");
+        if resources_keyboards_path.is_dir() {
+            return Some(resources_keyboards_path);
         }
     }
+    
+    let bundled_path = macos_dir.join("keyboards");
+    if bundled_path.is_dir() {
+        return Some(bundled_path);
+    }
+    
+    None
 }
 
 impl Platform for MacOSBackend {
@@ -85,30 +147,23 @@ impl Platform for MacOSBackend {
         let config_path = self.get_config_path();
         
         if !config_path.exists() {
-            let config = Self::default_config();
+            let config = self.load_legacy_config().unwrap_or_else(Self::default_config);
             self.save_config(&config)?;
             return Ok(config);
         }
         
-        // Load from plist
-        let value = plist::from_file(&config_path)
-            .context("Failed to read plist config file")?;
-        
-        plist::from_value(&value).context("Failed to parse plist config")
+        plist::from_file(&config_path).context("Failed to parse plist config")
     }
     
     fn save_config(&self, config: &Config) -> Result<()> {
-        let config_path = self.get_config_path();
-        
-        // Convert to plist Value
-        let value = plist::to_value(config)
-            .context("Failed to serialize config to plist value")?;
-        
         // Write as binary plist for better performance and smaller size
-        plist::to_file_binary(&config_path, &value)
-            .context("Failed to write plist config file")?;
+        let mut contents = Vec::new();
+        plist::to_writer_binary(&mut contents, config)
+            .context("Failed to serialize config to plist")?;
         
-        Ok(())
+        // Replaced in one step, so the IMK never reads half a config
+        temp_file::write_durable(&self.get_config_path(), &contents)
+            .context("Failed to write plist config file")
     }
     
     fn get_keyboards_dir(&self) -> PathBuf {
@@ -123,24 +178,52 @@ impl Platform for MacOSBackend {
             for entry in fs::read_dir(&self.keyboards_dir)? {
                 let entry = entry?;
                 let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()) == Some("km2") {
+                let is_km2 = path.extension()
+                    .and_then(|s| s.to_str())
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("km2"));
+                if is_km2 && path.is_file() {
                     keyboards.push(path);
                 }
             }
         }
         
+        keyboards.sort();
         Ok(keyboards)
     }
     
-    fn notify_ime_update(&self, _keyboard_id: &str) -> Result<()> {
-        // TODO: Notify macOS IMK engine
-        // This would involve sending a notification to the Input Method Kit
+    fn notify_ime_update(&self, keyboard_id: &str) -> Result<()> {
+        // The IMK observes this in every process it is loaded into
+        unsafe {
+            let pool = NSAutoreleasePool::new(nil);
+            
+            let center: id = msg_send![class!(NSDistributedNotificationCenter), defaultCenter];
+            let name = NSString::alloc(nil).init_str(KEYBOARD_CHANGED_NOTIFICATION).autorelease();
+            let object = NSString::alloc(nil).init_str(keyboard_id).autorelease();
+            let _: () = msg_send![center,
+                postNotificationName: name
+                object: object
+                userInfo: nil
+                deliverImmediately: YES];
+            
+            pool.drain();
+        }
+        
+        log::info!("Posted keyboard change notification for: {}", keyboard_id);
         Ok(())
     }
     
     fn is_ime_running(&self) -> bool {
-        // TODO: Check if KeyMagic IMK is running
-        false
+        unsafe {
+            let pool = NSAutoreleasePool::new(nil);
+            
+            let bundle_id = NSString::alloc(nil).init_str(IMK_BUNDLE_ID).autorelease();
+            let apps: id = msg_send![class!(NSRunningApplication),
+                runningApplicationsWithBundleIdentifier: bundle_id];
+            let count: usize = if apps != nil { msg_send![apps, count] } else { 0 };
+            
+            pool.drain();
+            count > 0
+        }
     }
     
     fn switch_keyboard(&self, keyboard_id: &str) -> Result<()> {
@@ -170,29 +253,51 @@ impl Platform for MacOSBackend {
     
     fn get_bundled_keyboards_path(&self) -> Option<PathBuf> {
         // For macOS, bundled keyboards are inside the app bundle
-        if let Ok(exe_path) = std::env::current_exe() {
-            // Navigate from executable to Resources directory in app bundle
-            // Typical structure: MyApp.app/Contents/MacOS/executable
-            // We need: MyApp.app/Contents/Resources/keyboards
-            if let Some(macos_dir) = exe_path.parent() {
-                if let Some(contents_dir) = macos_dir.parent() {
-                    let resources_keyboards_path = contents_dir.join("Resources").join("keyboards");
-                    if resources_keyboards_path.exists() {
-                        return Some(resources_keyboards_path);
-                    }
-                }
+        let exe_path = std::env::current_exe().ok()?;
+        bundled_keyboards_path_for_exe(&exe_path)
+    }
+    
+    fn os_version(&self) -> Option<String> {
+        let version: plist::Dictionary =
+            plist::from_file("/System/Library/CoreServices/SystemVersion.plist").ok()?;
+        let name = version.get("ProductName")?.as_string()?;
+        let number = version.get("ProductVersion")?.as_string()?;
+        let build = version.get("ProductBuildVersion").and_then(|b| b.as_string()).unwrap_or("?");
+        Some(format!("{} {} ({})", name, number, build))
+    }
+    
+    fn screen_reader_active(&self) -> Option<bool> {
+        unsafe {
+            let workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+            // Available from macOS 10.13
+            let supported: BOOL = msg_send![workspace, respondsToSelector: sel!(isVoiceOverEnabled)];
+            if supported != YES {
+                return None;
             }
+            let enabled: BOOL = msg_send![workspace, isVoiceOverEnabled];
+            Some(enabled == YES)
+        }
+    }
+    
+    fn installed_font_families(&self) -> Option<Vec<String>> {
+        unsafe {
+            let pool = NSAutoreleasePool::new(nil);
             
-            // Fallback for development: check relative to executable
-            if let Some(parent) = exe_path.parent() {
-                let bundled_path = parent.join("keyboards");
-                if bundled_path.exists() {
-                    return Some(bundled_path);
+            let manager: id = msg_send![class!(NSFontManager), sharedFontManager];
+            let families: id = msg_send![manager, availableFontFamilies];
+            let count: usize = if families != nil { msg_send![families, count] } else { 0 };
+            let mut names = Vec::with_capacity(count);
+            for index in 0..count {
+                let family: id = msg_send![families, objectAtIndex: index];
+                let utf8 = family.UTF8String();
+                if !utf8.is_null() {
+                    names.push(std::ffi::CStr::from_ptr(utf8).to_string_lossy().into_owned());
                 }
             }
+            
+            pool.drain();
+            (families != nil).then_some(names)
         }
-        
-        None
     }
     
     fn normalize_hotkey_for_display(&self, hotkey: &str) -> String {
@@ -202,7 +307,12 @@ impl Platform for MacOSBackend {
         
         // Try to parse the hotkey
         match HotkeyBinding::parse(hotkey) {
-            Ok(binding) => {
+            Ok(HotkeyBinding::DoubleTap(key)) => {
+                // Named twice, as typed
+                let name = key.to_display_string();
+                format!("{} {}", name, name)
+            }
+            Ok(HotkeyBinding::Combo(binding)) => {
                 let mut parts = Vec::new();
                 
                 // Add modifiers in macOS order: Cmd, Ctrl, Opt, Shift
@@ -231,4 +341,137 @@ impl Platform for MacOSBackend {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("keymagic-macos-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn backend(root: &Path, legacy: Option<PathBuf>) -> MacOSBackend {
+        let data_dir = root.join("Application Support").join("KeyMagic");
+        MacOSBackend::with_dirs(data_dir.clone(), data_dir, legacy).unwrap()
+    }
+
+    #[test]
+    fn test_directories_are_created() {
+        let root = temp_root("dirs");
+        let backend = backend(&root, None);
+
+        assert!(backend.get_config_dir().is_dir());
+        assert!(backend.get_keyboards_dir().is_dir());
+        assert_eq!(backend.get_keyboards_dir(), backend.get_data_dir().join("Keyboards"));
+        assert_eq!(backend.get_config_path(), backend.get_data_dir().join(CONFIG_FILE_NAME));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let root = temp_root("roundtrip");
+        let backend = backend(&root, None);
+
+        // First load writes the default config
+        let mut config = backend.load_config().unwrap();
+        assert!(backend.get_config_path().exists());
+        assert!(!config.direct_mode.enabled_hosts.is_empty());
+
+        config.keyboards.active = Some("myanmar3".to_string());
+        config.keyboards.last_used = vec!["myanmar3".to_string()];
+        config.composition_mode.enabled_hosts = vec!["com.apple.Terminal".to_string()];
+        backend.save_config(&config).unwrap();
+
+        let loaded = backend.load_config().unwrap();
+        assert_eq!(loaded.keyboards.active.as_deref(), Some("myanmar3"));
+        assert_eq!(loaded.keyboards.last_used, vec!["myanmar3".to_string()]);
+        assert_eq!(loaded.composition_mode.enabled_hosts, vec!["com.apple.Terminal".to_string()]);
+
+        // The temporary file is renamed away
+        assert!(!backend.get_config_path().with_extension("plist.tmp").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_config_is_migrated() {
+        let root = temp_root("legacy");
+        let legacy_dir = root.join("Preferences").join("net.keymagic");
+        fs::create_dir_all(&legacy_dir).unwrap();
+        let legacy_path = legacy_dir.join(CONFIG_FILE_NAME);
+
+        let mut legacy = MacOSBackend::default_config();
+        legacy.keyboards.active = Some("zawgyi".to_string());
+        plist::to_file_binary(&legacy_path, &legacy).unwrap();
+
+        let backend = backend(&root, Some(legacy_path.clone()));
+        let config = backend.load_config().unwrap();
+        assert_eq!(config.keyboards.active.as_deref(), Some("zawgyi"));
+        assert!(backend.get_config_path().exists());
+
+        // Once migrated, the new location wins
+        let mut updated = config;
+        updated.keyboards.active = Some("myanmar3".to_string());
+        backend.save_config(&updated).unwrap();
+        assert_eq!(backend.load_config().unwrap().keyboards.active.as_deref(), Some("myanmar3"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_keyboard_files_are_enumerated() {
+        let root = temp_root("keyboards");
+        let backend = backend(&root, None);
+        let dir = backend.get_keyboards_dir();
+
+        fs::write(dir.join("b.km2"), b"").unwrap();
+        fs::write(dir.join("a.KM2"), b"").unwrap();
+        fs::write(dir.join("notes.txt"), b"").unwrap();
+        fs::create_dir_all(dir.join("folder.km2")).unwrap();
+
+        let files = backend.get_keyboard_files().unwrap();
+        assert_eq!(files, vec![dir.join("a.KM2"), dir.join("b.km2")]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_bundled_keyboards_path_resolution() {
+        let root = temp_root("bundle");
+        let contents = root.join("KeyMagic.app").join("Contents");
+        let exe = contents.join("MacOS").join("keymagic");
+        fs::create_dir_all(exe.parent().unwrap()).unwrap();
+
+        // Nothing bundled yet
+        assert_eq!(bundled_keyboards_path_for_exe(&exe), None);
+
+        // Development layout: keyboards next to the executable
+        let dev_path = contents.join("MacOS").join("keyboards");
+        fs::create_dir_all(&dev_path).unwrap();
+        assert_eq!(bundled_keyboards_path_for_exe(&exe), Some(dev_path));
+
+        // App bundle resources take precedence
+        let resources_path = contents.join("Resources").join("keyboards");
+        fs::create_dir_all(&resources_path).unwrap();
+        assert_eq!(bundled_keyboards_path_for_exe(&exe), Some(resources_path));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_platform_features() {
+        let root = temp_root("features");
+        let info = backend(&root, None).get_platform_info();
+
+        assert_eq!(info.os, "macos");
+        assert!(!info.features.language_profiles);
+        assert!(info.features.composition_mode);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+//! Platform backend for tests, built with the `test-util` feature
+//!
+//! Config and settings live in memory; keyboard files go to a fresh folder
+//! under the system temp dir, so the `KeyboardManager` flows run the same on
+//! every OS without touching the registry or an installed input method.
+
+use super::{
+    Config, GeneralConfig, InstalledKeyboard, KeyboardsConfig, OutputEncoding, Platform, PlatformFeatures,
+    PlatformInfo,
+};
+use crate::core::key_processing::switch_input_methods;
+use crate::core::keyboard_ids::{ID_SCHEME, ID_SCHEME_SETTING};
+use anyhow::Result;
+use keymagic_core::processing_state::ProcessingState;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Layout of the keyboards seeded without a source
+pub const DEFAULT_KEYBOARD_KMS: &str = r#""k" => "က""#;
+
+/// Compiles KMS source into km2 file contents
+pub fn compile_keyboard(kms: &str) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    kms2km2::binary::Km2Writer::new(&mut buffer).write_km2_file(&kms2km2::compile_kms(kms)?)?;
+    Ok(buffer)
+}
+
+pub struct MockPlatform {
+    root: PathBuf,
+    config: Mutex<Config>,
+    settings: Mutex<HashMap<String, String>>,
+    /// Embedded keyboard copies by keyboard id, dropped with the registration
+    embedded: Mutex<HashMap<String, Vec<u8>>>,
+    temporary: Mutex<Option<PathBuf>>,
+    bundled: Option<PathBuf>,
+    /// Switch read by a simulated input method, if any
+    processing: Option<Arc<ProcessingState>>,
+}
+
+impl MockPlatform {
+    /// Starts a platform whose files go to a temp folder named after `name`;
+    /// tests running at the same time need different names
+    pub fn builder(name: &str) -> MockPlatformBuilder {
+        MockPlatformBuilder {
+            root: std::env::temp_dir().join(format!("keymagic-mock-{}-{}", name, std::process::id())),
+            keyboards: Vec::new(),
+            bundled: Vec::new(),
+            active: None,
+            // Seeded ids are taken as current unless `legacy_keyboard_ids`
+            settings: HashMap::from([(ID_SCHEME_SETTING.to_string(), ID_SCHEME.to_string())]),
+            processing: None,
+        }
+    }
+}
+
+impl Platform for MockPlatform {
+    fn load_config(&self) -> Result<Config> {
+        Ok(self.config.lock().unwrap().clone())
+    }
+    fn save_config(&self, config: &Config) -> Result<()> {
+        // Like the registry, where a removed keyboard's key goes with its copy
+        self.embedded.lock().unwrap().retain(|id, _| config.keyboards.installed.iter().any(|kb| &kb.id == id));
+        *self.config.lock().unwrap() = config.clone();
+        Ok(())
+    }
+    fn get_keyboards_dir(&self) -> PathBuf {
+        self.root.join("keyboards")
+    }
+    fn get_keyboard_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = fs::read_dir(self.get_keyboards_dir())?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("km2"))
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+    fn embedded_keyboard(&self, keyboard_id: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.embedded.lock().unwrap().get(keyboard_id).cloned())
+    }
+    fn set_embedded_keyboard(&self, keyboard_id: &str, data: Option<&[u8]>) -> Result<()> {
+        if !self.config.lock().unwrap().keyboards.installed.iter().any(|kb| kb.id == keyboard_id) {
+            return Err(anyhow::anyhow!("Keyboard {} is not registered", keyboard_id));
+        }
+        let mut embedded = self.embedded.lock().unwrap();
+        match data {
+            Some(data) => embedded.insert(keyboard_id.to_string(), data.to_vec()),
+            None => embedded.remove(keyboard_id),
+        };
+        Ok(())
+    }
+    fn notify_ime_update(&self, _keyboard_id: &str) -> Result<()> {
+        Ok(())
+    }
+    fn is_ime_running(&self) -> bool {
+        true
+    }
+    fn switch_keyboard(&self, _keyboard_id: &str) -> Result<()> {
+        Ok(())
+    }
+    fn set_temporary_keyboard(&self, path: Option<&Path>) -> Result<()> {
+        *self.temporary.lock().unwrap() = path.map(Path::to_path_buf);
+        Ok(())
+    }
+    fn set_input_method_processing(&self, enabled: bool, timeout: Duration) -> Result<bool> {
+        match &self.processing {
+            Some(state) => switch_input_methods(state, enabled, timeout, || Ok(())),
+            None => Ok(true),
+        }
+    }
+    fn beat_input_methods(&self, now_ms: u64) -> Result<Option<u64>> {
+        Ok(self.processing.as_ref().and_then(|state| state.beat(now_ms)))
+    }
+    fn input_method_heartbeat(&self) -> Result<Option<Option<u64>>> {
+        Ok(self.processing.as_ref().map(|state| state.last_heartbeat()))
+    }
+    fn get_config_dir(&self) -> PathBuf {
+        self.root.clone()
+    }
+    fn get_data_dir(&self) -> PathBuf {
+        self.root.clone()
+    }
+    fn get_platform_info(&self) -> PlatformInfo {
+        PlatformInfo { os: "test".to_string(), features: PlatformFeatures::default() }
+    }
+    fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.settings.lock().unwrap().get(key).cloned())
+    }
+    fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        self.settings.lock().unwrap().insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+    fn get_bundled_keyboards_path(&self) -> Option<PathBuf> {
+        self.bundled.clone()
+    }
+    fn normalize_hotkey_for_display(&self, hotkey: &str) -> String {
+        hotkey.replace("CTRL", "Ctrl").replace("SPACE", "Space")
+    }
+}
+
+/// Seeds a `MockPlatform` with keyboards, bundled keyboards and settings
+pub struct MockPlatformBuilder {
+    root: PathBuf,
+    keyboards: Vec<(String, String)>,
+    bundled: Vec<(String, String)>,
+    active: Option<String>,
+    settings: HashMap<String, String>,
+    processing: Option<Arc<ProcessingState>>,
+}
+
+impl MockPlatformBuilder {
+    /// Installs a keyboard with the default layout
+    pub fn keyboard(self, id: &str) -> Self {
+        self.keyboard_from_kms(id, DEFAULT_KEYBOARD_KMS)
+    }
+
+    /// Installs a keyboard compiled from `kms`; its name is the id
+    pub fn keyboard_from_kms(mut self, id: &str, kms: &str) -> Self {
+        self.keyboards.push((id.to_string(), kms.to_string()));
+        self
+    }
+
+    /// Ships a keyboard compiled from `kms` as `<file_stem>.km2` in the
+    /// bundled keyboards folder
+    pub fn bundled_keyboard(mut self, file_stem: &str, kms: &str) -> Self {
+        self.bundled.push((file_stem.to_string(), kms.to_string()));
+        self
+    }
+
+    pub fn active(mut self, id: &str) -> Self {
+        self.active = Some(id.to_string());
+        self
+    }
+
+    pub fn setting(mut self, key: &str, value: &str) -> Self {
+        self.settings.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Starts as an install from before ids were unique, whose seeded ids
+    /// are migrated by `KeyboardManager::initialize`
+    pub fn legacy_keyboard_ids(mut self) -> Self {
+        self.settings.remove(ID_SCHEME_SETTING);
+        self
+    }
+
+    /// Has `set_input_method_processing` drive `state` like an input method
+    /// would read it
+    pub fn processing(mut self, state: Arc<ProcessingState>) -> Self {
+        self.processing = Some(state);
+        self
+    }
+
+    /// Writes the keyboard files, starting from an empty folder; panics if
+    /// the folder cannot be written or a layout does not compile
+    pub fn build(self) -> MockPlatform {
+        let _ = fs::remove_dir_all(&self.root);
+        let keyboards_dir = self.root.join("keyboards");
+        fs::create_dir_all(&keyboards_dir).expect("Failed to create the keyboards folder");
+
+        let installed = self.keyboards.iter()
+            .map(|(id, kms)| {
+                let filename = format!("{}.km2", id);
+                fs::write(keyboards_dir.join(&filename), compile_keyboard(kms).expect("Invalid keyboard layout"))
+                    .expect("Failed to write keyboard file");
+                InstalledKeyboard {
+                    id: id.clone(),
+                    name: id.clone(),
+                    filename,
+                    hotkey: None,
+                    hash: String::new(),
+                    enabled: true,
+                    output_encoding: OutputEncoding::default(),
+                    disabled_groups: Vec::new(),
+                    passthrough_keys: Vec::new(),
+                    commit_before_passthrough: false,
+                    options_overrides: HashMap::new(),
+                    original_hash: None,
+                    sample_text: None,
+                }
+            })
+            .collect();
+
+        let bundled_dir = self.root.join("bundled");
+        if !self.bundled.is_empty() {
+            fs::create_dir_all(&bundled_dir).expect("Failed to create the bundled keyboards folder");
+        }
+        for (file_stem, kms) in &self.bundled {
+            fs::write(bundled_dir.join(format!("{}.km2", file_stem)), compile_keyboard(kms).expect("Invalid keyboard layout"))
+                .expect("Failed to write bundled keyboard file");
+        }
+
+        let config = Config {
+            general: GeneralConfig {
+                start_with_system: false,
+                check_for_updates: false,
+                last_update_check: None,
+                last_scanned_version: None,
+                update_remind_after: None,
+                key_processing_enabled: None,
+            },
+            keyboards: KeyboardsConfig { active: self.active, last_used: vec![], installed },
+            composition_mode: Default::default(),
+            direct_mode: Default::default(),
+            shortcut_passthrough: Default::default(),
+            char_suppression: Default::default(),
+            profiles: Default::default(),
+            active_profile: None,
+            language_activation: Default::default(),
+        };
+
+        MockPlatform {
+            root: self.root,
+            config: Mutex::new(config),
+            settings: Mutex::new(self.settings),
+            embedded: Mutex::new(HashMap::new()),
+            temporary: Mutex::new(None),
+            bundled: (!self.bundled.is_empty()).then_some(bundled_dir),
+            processing: self.processing,
+        }
+    }
+}
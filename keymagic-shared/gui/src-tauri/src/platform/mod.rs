@@ -1,6 +1,12 @@
 use anyhow::Result;
+use keymagic_core::char_suppression::CharSuppressionSnapshot;
+use keymagic_core::transform::TransformId;
+use crate::core::keyboard_store::{KeyboardRegistrations, StoreEntry};
+use crate::version::Version;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[cfg(target_os = "windows")]
 mod windows;
@@ -17,6 +23,11 @@ mod macos;
 #[cfg(target_os = "macos")]
 pub use macos::MacOSBackend as PlatformBackend;
 
+#[cfg(any(test, feature = "test-util"))]
+mod mock;
+#[cfg(any(test, feature = "test-util"))]
+pub use mock::{compile_keyboard, MockPlatform, MockPlatformBuilder, DEFAULT_KEYBOARD_KMS};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub general: GeneralConfig,
@@ -25,6 +36,21 @@ pub struct Config {
     pub composition_mode: CompositionModeConfig,
     #[serde(default)]
     pub direct_mode: DirectModeConfig,
+    /// Keys some applications use as single-key shortcuts
+    #[serde(default)]
+    pub shortcut_passthrough: ShortcutPassthroughConfig,
+    /// Applications whose stray characters of eaten keys are dropped
+    #[serde(default)]
+    pub char_suppression: CharSuppressionConfig,
+    /// Named sets of overrides the user can switch between
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileOverrides>,
+    /// Profile applied last
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    /// Key processing follows the input language of the focused window
+    #[serde(default)]
+    pub language_activation: LanguageActivationConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +60,10 @@ pub struct GeneralConfig {
     pub last_update_check: Option<String>,
     pub last_scanned_version: Option<String>,
     pub update_remind_after: Option<String>,
+    /// Key processing switch shared with the IBus engine, `None` on
+    /// platforms that keep it elsewhere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_processing_enabled: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +80,67 @@ pub struct InstalledKeyboard {
     pub filename: String,
     pub hotkey: Option<String>,
     pub hash: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub output_encoding: OutputEncoding,
+    /// Rule groups the user switched off
+    #[serde(default)]
+    pub disabled_groups: Vec<String>,
+    /// Keys (e.g. "VK_ESCAPE") that always go to the application
+    #[serde(default)]
+    pub passthrough_keys: Vec<String>,
+    /// Commit the composing text before a passthrough key
+    #[serde(default)]
+    pub commit_before_passthrough: bool,
+    /// Layout options set by the user, by option name
+    #[serde(default)]
+    pub options_overrides: HashMap<String, serde_json::Value>,
+    /// Hash of the file before its metadata was edited in the app
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_hash: Option<String>,
+    /// Preview text for the text services' switch notification
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_text: Option<String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Encoding of the text a keyboard emits to applications
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputEncoding {
+    #[default]
+    Unicode,
+    Zawgyi,
+}
+
+impl OutputEncoding {
+    /// Name stored in the registry / config files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputEncoding::Unicode => "unicode",
+            OutputEncoding::Zawgyi => "zawgyi",
+        }
+    }
+
+    /// Parses a stored name, falling back to Unicode for unknown values
+    pub fn from_name(name: &str) -> Self {
+        match TransformId::from_name(name) {
+            Some(TransformId::Zawgyi) => OutputEncoding::Zawgyi,
+            None => OutputEncoding::Unicode,
+        }
+    }
+
+    /// Engine output transform implementing this encoding
+    pub fn transform_id(&self) -> Option<TransformId> {
+        match self {
+            OutputEncoding::Unicode => None,
+            OutputEncoding::Zawgyi => Some(TransformId::Zawgyi),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -62,6 +153,88 @@ pub struct DirectModeConfig {
     pub enabled_hosts: Vec<String>,
 }
 
+/// Per-application input mode kept as a host list in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostMode {
+    Composition,
+    Direct,
+}
+
+impl Config {
+    /// Hosts the given mode is turned on for
+    pub fn mode_hosts(&self, mode: HostMode) -> &Vec<String> {
+        match mode {
+            HostMode::Composition => &self.composition_mode.enabled_hosts,
+            HostMode::Direct => &self.direct_mode.enabled_hosts,
+        }
+    }
+
+    pub fn mode_hosts_mut(&mut self, mode: HostMode) -> &mut Vec<String> {
+        match mode {
+            HostMode::Composition => &mut self.composition_mode.enabled_hosts,
+            HostMode::Direct => &mut self.direct_mode.enabled_hosts,
+        }
+    }
+}
+
+/// Keys (e.g. "VK_KEY_C") that reach an application unprocessed while
+/// nothing is composed, by host pattern
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ShortcutPassthroughConfig {
+    pub hosts: BTreeMap<String, Vec<String>>,
+}
+
+/// Host patterns that get the text service's character filter, see
+/// `keymagic_core::char_suppression`
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CharSuppressionConfig {
+    pub enabled_hosts: Vec<String>,
+}
+
+/// What a profile changes when applied; `None` keeps the current value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct ProfileOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_keyboard: Option<String>,
+    /// Keyboards to enable; other installed keyboards are disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled_keyboards: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub composition_mode_hosts: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direct_mode_hosts: Option<Vec<String>>,
+    /// Values for the `PROFILE_SETTINGS` keys
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub settings: BTreeMap<String, String>,
+}
+
+/// Switching key processing with the input language of the focused window
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct LanguageActivationConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// What to do per language: a LANGID ("0409"), a locale name ("en-US")
+    /// or a bare language ("en") that covers all its locales
+    #[serde(default)]
+    pub rules: BTreeMap<String, LanguageAction>,
+}
+
+/// What switching to a language does
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum LanguageAction {
+    /// Turn key processing on
+    Enable,
+    /// Turn key processing off
+    Disable,
+    /// Turn key processing on with this keyboard
+    Keyboard { keyboard_id: String },
+}
+
+/// Settings captured in a profile: whether the HUD and the tray preview are shown
+pub const PROFILE_SETTINGS: &[&str] = &["hud_enabled", "preview_window_enabled"];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlatformInfo {
     pub os: String,
@@ -85,10 +258,78 @@ pub trait Platform: Send + Sync {
     fn get_keyboards_dir(&self) -> PathBuf;
     fn get_keyboard_files(&self) -> Result<Vec<PathBuf>>;
     
+    // Raw keyboard registrations for the integrity check, including ones
+    // `load_config` would skip
+    fn keyboard_registrations(&self) -> Result<KeyboardRegistrations> {
+        let config = self.load_config()?;
+        Ok(KeyboardRegistrations {
+            entries: config.keyboards.installed
+                .into_iter()
+                .map(|kb| StoreEntry {
+                    id: kb.id,
+                    filename: Some(kb.filename),
+                    hash: kb.hash,
+                })
+                .collect(),
+            active: config.keyboards.active,
+        })
+    }
+    
+    /// Copy of a keyboard's data stored with its registration, read when its
+    /// file is missing (see `embedded_keyboards`)
+    fn embedded_keyboard(&self, _keyboard_id: &str) -> Result<Option<Vec<u8>>> {
+        Ok(None) // Default: keyboards are only kept as files
+    }
+    /// Stores a copy of a keyboard's data with its registration, or removes
+    /// it with `None`
+    fn set_embedded_keyboard(&self, _keyboard_id: &str, data: Option<&[u8]>) -> Result<()> {
+        match data {
+            Some(_) => Err(anyhow::anyhow!("Storing keyboards in the registry is not supported on this platform")),
+            None => Ok(()), // Nothing can have been stored
+        }
+    }
+
     // IME integration
     fn notify_ime_update(&self, keyboard_id: &str) -> Result<()>;
     fn is_ime_running(&self) -> bool;
     fn switch_keyboard(&self, keyboard_id: &str) -> Result<()>;
+    fn notify_keyboards_changed(&self) -> Result<()> {
+        Ok(()) // Default: the IME watches the config itself
+    }
+    /// Points the input method at a keyboard file for this session only, or
+    /// back to the saved keyboard with `None`; the config is left untouched
+    fn set_temporary_keyboard(&self, path: Option<&Path>) -> Result<()> {
+        match path {
+            Some(_) => Err(anyhow::anyhow!("Trying out keyboards is not supported on this platform")),
+            None => Ok(()), // Nothing can have been set
+        }
+    }
+    
+    /// Switches key processing in running input methods. Turning it off
+    /// waits up to `timeout` for them to commit the composition; returns
+    /// false if they did not confirm in time
+    fn set_input_method_processing(&self, _enabled: bool, _timeout: Duration) -> Result<bool> {
+        Ok(true) // Default: the IME reads the setting itself
+    }
+    
+    /// Tells running input methods the GUI is alive at `now_ms` (milliseconds
+    /// since the Unix epoch), so they keep processing keys; returns the
+    /// previous heartbeat. `Ok(None)` where input methods do not watch it.
+    fn beat_input_methods(&self, _now_ms: u64) -> Result<Option<u64>> {
+        Ok(None)
+    }
+    
+    /// Last heartbeat input methods saw: `None` where they do not watch it,
+    /// `Some(None)` before the first one
+    fn input_method_heartbeat(&self) -> Result<Option<Option<u64>>> {
+        Ok(None)
+    }
+    
+    /// Stray characters the input methods dropped, see
+    /// `keymagic_core::char_suppression`; `None` where they drop none
+    fn char_suppression_stats(&self) -> Result<Option<CharSuppressionSnapshot>> {
+        Ok(None)
+    }
     
     // System integration
     fn get_config_dir(&self) -> PathBuf;
@@ -117,6 +358,23 @@ pub trait Platform: Send + Sync {
         Ok(())
     }
     
+    /// Name and version of the operating system for problem reports, `None`
+    /// when the platform cannot tell
+    fn os_version(&self) -> Option<String> {
+        None
+    }
+    
+    // Accessibility
+    /// Whether a screen reader is running, `None` when the platform cannot tell
+    fn screen_reader_active(&self) -> Option<bool> {
+        None
+    }
+    
+    // Fonts
+    /// Families of the installed fonts, `None` when the platform cannot list them
+    fn installed_font_families(&self) -> Option<Vec<String>> {
+        None
+    }
     
     // Bundled keyboards
     fn get_bundled_keyboards_path(&self) -> Option<PathBuf> {
@@ -152,28 +410,14 @@ pub fn create_platform() -> Result<Box<dyn Platform>> {
     }
 }
 
-// Helper function to compare version strings
+/// Whether `current` is a newer version than `last`; a string that is not a
+/// version counts as not newer
+#[deprecated(note = "parse both sides with `crate::version::Version` and compare")]
 pub fn compare_versions(current: &str, last: &str) -> bool {
-    // Simple version comparison - split by dots and compare numerically
-    let current_parts: Vec<u32> = current.split('.')
-        .filter_map(|s| s.parse().ok())
-        .collect();
-    let last_parts: Vec<u32> = last.split('.')
-        .filter_map(|s| s.parse().ok())
-        .collect();
-    
-    for i in 0..current_parts.len().max(last_parts.len()) {
-        let current_part = current_parts.get(i).unwrap_or(&0);
-        let last_part = last_parts.get(i).unwrap_or(&0);
-        
-        if current_part > last_part {
-            return true;
-        } else if current_part < last_part {
-            return false;
-        }
+    match (Version::parse(current), Version::parse(last)) {
+        (Ok(current), Ok(last)) => current > last,
+        _ => false,
     }
-    
-    false // Versions are equal
 }
 
 #[cfg(test)]
@@ -181,6 +425,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(deprecated)]
     fn test_version_comparison() {
         // Test basic version comparisons
         assert!(compare_versions("1.0.0", "0.9.9"));
@@ -1,12 +1,20 @@
 use super::{
     CompositionModeConfig, DirectModeConfig, Config, GeneralConfig, InstalledKeyboard, KeyboardsConfig,
-    Platform, PlatformFeatures, PlatformInfo,
+    LanguageActivationConfig, OutputEncoding, Platform, PlatformFeatures, PlatformInfo,
 };
+use crate::core::keyboard_store::{KeyboardRegistrations, StoreEntry};
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use winreg::enums::*;
-use winreg::RegKey;
+use winreg::{RegKey, RegValue};
+use keymagic_core::char_suppression::{CharSuppressionSnapshot, CharSuppressionStats};
 use keymagic_core::hotkey::HotkeyBinding;
+use keymagic_core::input_mode::{HostShortcuts, SHORTCUT_ENTRY_SEPARATOR};
+use keymagic_core::processing_state::{GuiPresence, ProcessingState};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use windows::Win32::System::Registry::{
     RegQueryValueExW, RegSetValueExW, REG_MULTI_SZ, REG_VALUE_TYPE,
@@ -20,9 +28,22 @@ const KEYMAGIC_ROOT: &str = r"Software\KeyMagic";
 const KEYBOARDS_KEY: &str = r"Software\KeyMagic\Keyboards";
 const SETTINGS_KEY: &str = r"Software\KeyMagic\Settings";
 
+/// Form of a path stored for TSF: canonical and extended-length (`\\?\`)
+/// so deep folders open without the MAX_PATH limit
+fn persisted_path(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| keymagic_core::paths::to_extended_length(path))
+}
+
 // Registry value names
 const DEFAULT_KEYBOARD_VALUE: &str = "DefaultKeyboard";
+const TEMPORARY_KEYBOARD_VALUE: &str = "TemporaryKeyboard";
 const KEY_PROCESSING_ENABLED_VALUE: &str = "KeyProcessingEnabled";
+const PROFILES_VALUE: &str = "Profiles";
+const ACTIVE_PROFILE_VALUE: &str = "ActiveProfile";
+const LANGUAGE_ACTIVATION_VALUE: &str = "LanguageActivation";
+const SHORTCUT_PASSTHROUGH_VALUE: &str = "ShortcutPassthroughKeys";
+/// Hosts the text service installs its character filter in
+const CHAR_SUPPRESSION_HOSTS_VALUE: &str = "CharSuppressionHosts";
 
 // Keyboard entry value names
 const KEYBOARD_PATH_VALUE: &str = "Path";  // Legacy name for backward compatibility
@@ -32,6 +53,53 @@ const KEYBOARD_DESCRIPTION_VALUE: &str = "Description";
 const KEYBOARD_HOTKEY_VALUE: &str = "Hotkey";
 const KEYBOARD_ENABLED_VALUE: &str = "Enabled";
 const KEYBOARD_HASH_VALUE: &str = "Hash";
+const KEYBOARD_ORIGINAL_HASH_VALUE: &str = "OriginalHash";
+const KEYBOARD_OUTPUT_ENCODING_VALUE: &str = "OutputEncoding";
+const KEYBOARD_DISABLED_GROUPS_VALUE: &str = "DisabledGroups";
+const KEYBOARD_PASSTHROUGH_KEYS_VALUE: &str = "PassthroughKeys";
+const KEYBOARD_COMMIT_BEFORE_PASSTHROUGH_VALUE: &str = "CommitBeforePassthrough";
+const KEYBOARD_OPTION_OVERRIDES_VALUE: &str = "OptionOverrides";
+const KEYBOARD_SAMPLE_TEXT_VALUE: &str = "SampleText";
+/// Embedded copy of the keyboard file, read by TSF when the file is missing
+const KEYBOARD_DATA_VALUE: &str = "Data";
+
+/// Option overrides as "name=0" / "name=1" entries, the form the IME reads.
+/// Only boolean values have a registry form.
+fn encode_option_overrides(overrides: &HashMap<String, serde_json::Value>) -> Vec<String> {
+    let mut entries: Vec<String> = overrides
+        .iter()
+        .filter_map(|(name, value)| value.as_bool().map(|on| format!("{}={}", name, on as u8)))
+        .collect();
+    entries.sort();
+    entries
+}
+
+fn decode_option_overrides(entries: &[String]) -> HashMap<String, serde_json::Value> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let (name, value) = entry.split_once('=')?;
+            Some((name.to_string(), serde_json::Value::Bool(value == "1")))
+        })
+        .collect()
+}
+
+/// Shortcut keys as `host=VK_KEY_C,VK_KEY_J` entries, read by the text service
+fn encode_shortcut_keys(hosts: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+    hosts
+        .iter()
+        .filter(|(_, keys)| !keys.is_empty())
+        .map(|(host, keys)| format!("{}{}{}", host, SHORTCUT_ENTRY_SEPARATOR, keys.join(",")))
+        .collect()
+}
+
+fn decode_shortcut_keys(entries: &[String]) -> BTreeMap<String, Vec<String>> {
+    entries
+        .iter()
+        .filter_map(|entry| HostShortcuts::parse(entry).ok())
+        .map(|entry| (entry.pattern, entry.keys.iter().map(|key| key.to_kms_name().to_string()).collect()))
+        .collect()
+}
 
 /// Helper function to convert snake_case to PascalCase
 fn snake_case_to_pascal_case(snake_case: &str) -> String {
@@ -178,9 +246,29 @@ fn write_multi_string_value(key: &RegKey, value_name: &str, values: &[String]) -
     Ok(())
 }
 
+/// Reads a REG_BINARY value; `None` if it is missing or of another type
+fn read_binary_value(key: &RegKey, value_name: &str) -> Result<Option<Vec<u8>>> {
+    match key.get_raw_value(value_name) {
+        Ok(RegValue { bytes, vtype: REG_BINARY }) => Ok(Some(bytes)),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read registry value {}", value_name)),
+    }
+}
+
+/// Writes `data` as a REG_BINARY value
+fn write_binary_value(key: &RegKey, value_name: &str, data: &[u8]) -> Result<()> {
+    key.set_raw_value(value_name, &RegValue { bytes: data.to_vec(), vtype: REG_BINARY })
+        .with_context(|| format!("Failed to write registry value {}", value_name))
+}
+
 
 pub struct WindowsBackend {
     registry_key: RegKey,
+    /// Key processing switch shared with TSF, opened on first use
+    processing: Mutex<Option<ProcessingState>>,
+    /// Tells TSF the GUI is running, taken with the first heartbeat
+    presence: Mutex<Option<GuiPresence>>,
 }
 
 impl WindowsBackend {
@@ -215,12 +303,21 @@ impl WindowsBackend {
         }
         
         // Save the path to registry
-        settings_key.set_value("KeyboardsPath", &keyboards_dir.to_string_lossy().to_string())
+        settings_key.set_value("KeyboardsPath", &persisted_path(&keyboards_dir).into_os_string())
             .context("Failed to save keyboards path to registry")?;
         
         log::info!("Keyboards directory path saved to registry: {}", keyboards_dir.display());
         
-        Ok(Self { registry_key })
+        Ok(Self { registry_key, processing: Mutex::new(None), presence: Mutex::new(None) })
+    }
+    
+    /// Runs `f` on the key processing switch, opening it first if needed
+    fn with_processing<T>(&self, f: impl FnOnce(&ProcessingState) -> Result<T>) -> Result<T> {
+        let mut processing = self.processing.lock().unwrap();
+        if processing.is_none() {
+            *processing = Some(ProcessingState::create_shared().context("Failed to open the key processing switch")?);
+        }
+        f(processing.as_ref().expect("opened above"))
     }
     
     fn default_config() -> Config {
@@ -231,6 +328,7 @@ impl WindowsBackend {
                 last_update_check: None,
                 last_scanned_version: None,
                 update_remind_after: None,
+                key_processing_enabled: None,
             },
             keyboards: KeyboardsConfig {
                 active: None,
@@ -246,6 +344,11 @@ impl WindowsBackend {
             direct_mode: DirectModeConfig {
                 enabled_hosts: vec![],
             },
+            shortcut_passthrough: Default::default(),
+            char_suppression: Default::default(),
+            profiles: Default::default(),
+            active_profile: None,
+            language_activation: Default::default(),
         }
     }
 }
@@ -304,6 +407,26 @@ impl Platform for WindowsBackend {
             if let Ok(active) = settings_key.get_value::<String, _>(DEFAULT_KEYBOARD_VALUE) {
                 config.keyboards.active = Some(active);
             }
+            
+            // Profiles are kept as one JSON value; only the GUI reads them
+            if let Ok(profiles) = settings_key.get_value::<String, _>(PROFILES_VALUE) {
+                match serde_json::from_str(&profiles) {
+                    Ok(profiles) => config.profiles = profiles,
+                    Err(e) => log::warn!("Ignoring unreadable profiles: {}", e),
+                }
+            }
+            
+            // Shown by the tray manager in its tooltip
+            if let Ok(active_profile) = settings_key.get_value::<String, _>(ACTIVE_PROFILE_VALUE) {
+                config.active_profile = Some(active_profile);
+            }
+            
+            if let Ok(language_activation) = settings_key.get_value::<String, _>(LANGUAGE_ACTIVATION_VALUE) {
+                match serde_json::from_str(&language_activation) {
+                    Ok(language_activation) => config.language_activation = language_activation,
+                    Err(e) => log::warn!("Ignoring unreadable language activation settings: {}", e),
+                }
+            }
         }
         
         // Load installed keyboards from registry
@@ -339,6 +462,22 @@ impl Platform for WindowsBackend {
                         filename,
                         hotkey: kb_key.get_value(KEYBOARD_HOTKEY_VALUE).ok(),
                         hash: kb_key.get_value(KEYBOARD_HASH_VALUE).unwrap_or_default(),
+                        enabled: kb_key.get_value::<u32, _>(KEYBOARD_ENABLED_VALUE).map(|v| v != 0).unwrap_or(true),
+                        output_encoding: kb_key.get_value::<String, _>(KEYBOARD_OUTPUT_ENCODING_VALUE)
+                            .map(|name| OutputEncoding::from_name(&name))
+                            .unwrap_or_default(),
+                        disabled_groups: read_multi_string_value(&kb_key, KEYBOARD_DISABLED_GROUPS_VALUE)
+                            .unwrap_or_default(),
+                        passthrough_keys: read_multi_string_value(&kb_key, KEYBOARD_PASSTHROUGH_KEYS_VALUE)
+                            .unwrap_or_default(),
+                        commit_before_passthrough: kb_key.get_value::<u32, _>(KEYBOARD_COMMIT_BEFORE_PASSTHROUGH_VALUE)
+                            .map(|v| v != 0)
+                            .unwrap_or(false),
+                        options_overrides: read_multi_string_value(&kb_key, KEYBOARD_OPTION_OVERRIDES_VALUE)
+                            .map(|entries| decode_option_overrides(&entries))
+                            .unwrap_or_default(),
+                        original_hash: kb_key.get_value(KEYBOARD_ORIGINAL_HASH_VALUE).ok(),
+                        sample_text: kb_key.get_value(KEYBOARD_SAMPLE_TEXT_VALUE).ok(),
                     };
                     config.keyboards.installed.push(keyboard);
                 }
@@ -358,6 +497,18 @@ impl Platform for WindowsBackend {
                     .map(|s| s.trim().to_string())
                     .collect();
             }
+            
+            if let Ok(hosts) = read_multi_string_value(&settings_key, "DirectModeHosts") {
+                config.direct_mode.enabled_hosts = hosts;
+            }
+            
+            if let Ok(entries) = read_multi_string_value(&settings_key, SHORTCUT_PASSTHROUGH_VALUE) {
+                config.shortcut_passthrough.hosts = decode_shortcut_keys(&entries);
+            }
+            
+            if let Ok(hosts) = read_multi_string_value(&settings_key, CHAR_SUPPRESSION_HOSTS_VALUE) {
+                config.char_suppression.enabled_hosts = hosts;
+            }
         }
         
         Ok(config)
@@ -393,15 +544,38 @@ impl Platform for WindowsBackend {
             settings_key.set_value("LastScannedVersion", last_scanned)?;
         }
         
-        // Active keyboard uses DefaultKeyboard name
+        // Active keyboard uses DefaultKeyboard name; remove it when cleared so
+        // TSF does not keep loading a keyboard that no longer exists
         if let Some(ref active) = config.keyboards.active {
             settings_key.set_value(DEFAULT_KEYBOARD_VALUE, active)?;
+        } else {
+            let _ = settings_key.delete_value(DEFAULT_KEYBOARD_VALUE);
+        }
+        
+        if config.profiles.is_empty() {
+            let _ = settings_key.delete_value(PROFILES_VALUE);
+        } else {
+            settings_key.set_value(PROFILES_VALUE, &serde_json::to_string(&config.profiles)?)?;
+        }
+        if let Some(ref active_profile) = config.active_profile {
+            settings_key.set_value(ACTIVE_PROFILE_VALUE, active_profile)?;
+        } else {
+            let _ = settings_key.delete_value(ACTIVE_PROFILE_VALUE);
+        }
+        
+        if config.language_activation == LanguageActivationConfig::default() {
+            let _ = settings_key.delete_value(LANGUAGE_ACTIVATION_VALUE);
+        } else {
+            settings_key.set_value(
+                LANGUAGE_ACTIVATION_VALUE,
+                &serde_json::to_string(&config.language_activation)?,
+            )?;
         }
         
         // Update keyboards directory path for TSF to use
         // This ensures TSF always has the correct path even if it changes
         let keyboards_dir = self.get_keyboards_dir();
-        settings_key.set_value("KeyboardsPath", &keyboards_dir.to_string_lossy().to_string())
+        settings_key.set_value("KeyboardsPath", &persisted_path(&keyboards_dir).into_os_string())
             .context("Failed to update keyboards path in registry")?;
         
         // Open or create the Keyboards key
@@ -446,7 +620,38 @@ impl Platform for WindowsBackend {
             let _ = kb_key.delete_value(KEYBOARD_PATH_VALUE);
             
             kb_key.set_value(KEYBOARD_HASH_VALUE, &keyboard.hash)?;
-            kb_key.set_value(KEYBOARD_ENABLED_VALUE, &1u32)?; // Always enabled for now
+            match &keyboard.original_hash {
+                Some(original_hash) => kb_key.set_value(KEYBOARD_ORIGINAL_HASH_VALUE, original_hash)?,
+                None => {
+                    let _ = kb_key.delete_value(KEYBOARD_ORIGINAL_HASH_VALUE);
+                }
+            }
+            kb_key.set_value(KEYBOARD_ENABLED_VALUE, &(keyboard.enabled as u32))?;
+            kb_key.set_value(KEYBOARD_OUTPUT_ENCODING_VALUE, &keyboard.output_encoding.as_str())?;
+            if keyboard.disabled_groups.is_empty() {
+                let _ = kb_key.delete_value(KEYBOARD_DISABLED_GROUPS_VALUE);
+            } else {
+                write_multi_string_value(&kb_key, KEYBOARD_DISABLED_GROUPS_VALUE, &keyboard.disabled_groups)?;
+            }
+            if keyboard.passthrough_keys.is_empty() {
+                let _ = kb_key.delete_value(KEYBOARD_PASSTHROUGH_KEYS_VALUE);
+            } else {
+                write_multi_string_value(&kb_key, KEYBOARD_PASSTHROUGH_KEYS_VALUE, &keyboard.passthrough_keys)?;
+            }
+            kb_key.set_value(KEYBOARD_COMMIT_BEFORE_PASSTHROUGH_VALUE, &(keyboard.commit_before_passthrough as u32))?;
+            let overrides = encode_option_overrides(&keyboard.options_overrides);
+            if overrides.is_empty() {
+                let _ = kb_key.delete_value(KEYBOARD_OPTION_OVERRIDES_VALUE);
+            } else {
+                write_multi_string_value(&kb_key, KEYBOARD_OPTION_OVERRIDES_VALUE, &overrides)?;
+            }
+            // Shown by the text service under the name when switching to the keyboard
+            match &keyboard.sample_text {
+                Some(sample_text) => kb_key.set_value(KEYBOARD_SAMPLE_TEXT_VALUE, sample_text)?,
+                None => {
+                    let _ = kb_key.delete_value(KEYBOARD_SAMPLE_TEXT_VALUE);
+                }
+            }
             
             if let Some(ref hotkey) = keyboard.hotkey {
                 kb_key.set_value(KEYBOARD_HOTKEY_VALUE, hotkey)?;
@@ -458,14 +663,35 @@ impl Platform for WindowsBackend {
             write_multi_string_value(&settings_key, "CompositionModeHosts", &config.composition_mode.enabled_hosts)?;
         }
         
+        // Direct mode hosts; removed when empty so the text service sees no list
+        if config.direct_mode.enabled_hosts.is_empty() {
+            let _ = settings_key.delete_value("DirectModeHosts");
+        } else {
+            write_multi_string_value(&settings_key, "DirectModeHosts", &config.direct_mode.enabled_hosts)?;
+        }
+        
+        let shortcut_keys = encode_shortcut_keys(&config.shortcut_passthrough.hosts);
+        if shortcut_keys.is_empty() {
+            let _ = settings_key.delete_value(SHORTCUT_PASSTHROUGH_VALUE);
+        } else {
+            write_multi_string_value(&settings_key, SHORTCUT_PASSTHROUGH_VALUE, &shortcut_keys)?;
+        }
+        
+        if config.char_suppression.enabled_hosts.is_empty() {
+            let _ = settings_key.delete_value(CHAR_SUPPRESSION_HOSTS_VALUE);
+        } else {
+            write_multi_string_value(&settings_key, CHAR_SUPPRESSION_HOSTS_VALUE, &config.char_suppression.enabled_hosts)?;
+        }
+        
         Ok(())
     }
     
     fn get_keyboards_dir(&self) -> PathBuf {
         // Try to get from Settings registry first
         if let Ok(settings_key) = RegKey::predef(HKEY_CURRENT_USER).open_subkey(SETTINGS_KEY) {
-            if let Ok(path) = settings_key.get_value::<String, _>("KeyboardsPath") {
-                return PathBuf::from(path);
+            if let Ok(path) = settings_key.get_value::<OsString, _>("KeyboardsPath") {
+                // Drop the \\?\ prefix where the path works without it, for display
+                return dunce::simplified(Path::new(&path)).to_path_buf();
             }
         }
         
@@ -500,6 +726,63 @@ impl Platform for WindowsBackend {
         Ok(keyboards)
     }
     
+    fn keyboard_registrations(&self) -> Result<KeyboardRegistrations> {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        let mut registrations = KeyboardRegistrations::default();
+        
+        if let Ok(settings_key) = hkcu.open_subkey(SETTINGS_KEY) {
+            registrations.active = settings_key.get_value::<String, _>(DEFAULT_KEYBOARD_VALUE).ok();
+        }
+        
+        // Unlike load_config, keep subkeys without a file name so they can be cleaned up
+        let keyboards_key = hkcu.open_subkey(KEYBOARDS_KEY)
+            .context("Failed to open Keyboards key")?;
+        for id in keyboards_key.enum_keys().filter_map(Result::ok) {
+            let Ok(kb_key) = keyboards_key.open_subkey(&id) else {
+                continue;
+            };
+            let filename = kb_key.get_value::<String, _>(KEYBOARD_FILENAME_VALUE)
+                .or_else(|_| kb_key.get_value::<String, _>(KEYBOARD_PATH_VALUE))
+                .ok()
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    PathBuf::from(&name)
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .map(str::to_string)
+                        .unwrap_or(name)
+                });
+            registrations.entries.push(StoreEntry {
+                id,
+                filename,
+                hash: kb_key.get_value(KEYBOARD_HASH_VALUE).unwrap_or_default(),
+            });
+        }
+        
+        Ok(registrations)
+    }
+    
+    fn embedded_keyboard(&self, keyboard_id: &str) -> Result<Option<Vec<u8>>> {
+        let Ok(kb_key) = RegKey::predef(HKEY_CURRENT_USER).open_subkey(format!(r"{}\{}", KEYBOARDS_KEY, keyboard_id)) else {
+            return Ok(None);
+        };
+        read_binary_value(&kb_key, KEYBOARD_DATA_VALUE)
+    }
+    
+    fn set_embedded_keyboard(&self, keyboard_id: &str, data: Option<&[u8]>) -> Result<()> {
+        // Only registered keyboards; save_config would drop a new key again
+        let kb_key = RegKey::predef(HKEY_CURRENT_USER)
+            .open_subkey_with_flags(format!(r"{}\{}", KEYBOARDS_KEY, keyboard_id), KEY_READ | KEY_WRITE)
+            .with_context(|| format!("Keyboard {} is not registered", keyboard_id))?;
+        match data {
+            Some(data) => write_binary_value(&kb_key, KEYBOARD_DATA_VALUE, data)?,
+            None => {
+                let _ = kb_key.delete_value(KEYBOARD_DATA_VALUE);
+            }
+        }
+        notify_registry_change()
+    }
+    
     fn notify_ime_update(&self, keyboard_id: &str) -> Result<()> {
         // Update the active keyboard in Settings
         let hkcu = RegKey::predef(HKEY_CURRENT_USER);
@@ -525,6 +808,51 @@ impl Platform for WindowsBackend {
         self.notify_ime_update(keyboard_id)
     }
     
+    fn notify_keyboards_changed(&self) -> Result<()> {
+        notify_registry_change()
+    }
+    
+    fn set_input_method_processing(&self, enabled: bool, timeout: Duration) -> Result<bool> {
+        self.with_processing(|state| crate::core::key_processing::switch_input_methods(state, enabled, timeout, notify_registry_change))
+    }
+    
+    fn beat_input_methods(&self, now_ms: u64) -> Result<Option<u64>> {
+        let mut presence = self.presence.lock().unwrap();
+        if presence.is_none() {
+            *presence = Some(GuiPresence::hold().context("Failed to create the KeyMagic presence mutex")?);
+        }
+        self.with_processing(|state| Ok(state.beat(now_ms)))
+    }
+    
+    fn input_method_heartbeat(&self) -> Result<Option<Option<u64>>> {
+        self.with_processing(|state| Ok(Some(state.last_heartbeat())))
+    }
+    
+    fn char_suppression_stats(&self) -> Result<Option<CharSuppressionSnapshot>> {
+        let stats = CharSuppressionStats::create_shared().context("Failed to open the character filter counters")?;
+        Ok(Some(stats.snapshot()))
+    }
+    
+    fn set_temporary_keyboard(&self, path: Option<&Path>) -> Result<()> {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        let (settings_key, _) = hkcu
+            .create_subkey(SETTINGS_KEY)
+            .context("Failed to open Settings key")?;
+        
+        // TSF prefers this over DefaultKeyboard while it is set
+        match path {
+            Some(path) => settings_key.set_value(
+                TEMPORARY_KEYBOARD_VALUE,
+                &persisted_path(path).to_string_lossy().to_string(),
+            )?,
+            None => {
+                let _ = settings_key.delete_value(TEMPORARY_KEYBOARD_VALUE);
+            }
+        }
+        
+        notify_registry_change()
+    }
+    
     fn get_config_dir(&self) -> PathBuf {
         // Use %LOCALAPPDATA% for config as well, matching the original implementation
         dirs::data_local_dir()
@@ -653,6 +981,57 @@ impl Platform for WindowsBackend {
         Ok(())
     }
     
+    fn os_version(&self) -> Option<String> {
+        let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+        let key = hklm.open_subkey(r"SOFTWARE\Microsoft\Windows NT\CurrentVersion").ok()?;
+        let product: String = key.get_value("ProductName").ok()?;
+        let build: String = key.get_value("CurrentBuild").unwrap_or_default();
+        let revision: u32 = key.get_value("UBR").unwrap_or(0);
+        // Windows 11 still reports "Windows 10" as the product name; the build tells them apart
+        match key.get_value::<String, _>("DisplayVersion") {
+            Ok(display) => Some(format!("{} {} (build {}.{})", product, display, build, revision)),
+            Err(_) => Some(format!("{} (build {}.{})", product, build, revision)),
+        }
+    }
+    
+    fn screen_reader_active(&self) -> Option<bool> {
+        use windows::Win32::Foundation::BOOL;
+        use windows::Win32::UI::WindowsAndMessaging::{
+            SystemParametersInfoW, SPI_GETSCREENREADER, SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS,
+        };
+        
+        // Set by Narrator, NVDA and JAWS while they run
+        let mut active = BOOL(0);
+        unsafe {
+            SystemParametersInfoW(
+                SPI_GETSCREENREADER,
+                0,
+                Some(&mut active as *mut BOOL as *mut _),
+                SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+            )
+        }
+        .ok()?;
+        Some(active.as_bool())
+    }
+    
+    fn installed_font_families(&self) -> Option<Vec<String>> {
+        const FONTS_KEY: &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\Fonts";
+        
+        // Values are named like "Myanmar Text (TrueType)" or "Cambria & Cambria Math (TrueType)";
+        // fonts installed for the current user only are listed under HKCU
+        let mut families = Vec::new();
+        for root in [HKEY_LOCAL_MACHINE, HKEY_CURRENT_USER] {
+            let Ok(key) = RegKey::predef(root).open_subkey(FONTS_KEY) else {
+                continue;
+            };
+            for (name, _) in key.enum_values().flatten() {
+                let name = name.rsplit_once(" (").map_or(name.as_str(), |(name, _)| name);
+                families.extend(name.split(" & ").map(|family| family.trim().to_string()));
+            }
+        }
+        (!families.is_empty()).then_some(families)
+    }
+    
     fn normalize_hotkey_for_display(&self, hotkey: &str) -> String {
         if hotkey.is_empty() {
             return String::new();
@@ -660,7 +1039,12 @@ impl Platform for WindowsBackend {
         
         // Try to parse the hotkey
         match HotkeyBinding::parse(hotkey) {
-            Ok(binding) => {
+            Ok(HotkeyBinding::DoubleTap(key)) => {
+                // Named twice, as typed
+                let name = key.to_display_string();
+                format!("{} {}", name, name)
+            }
+            Ok(HotkeyBinding::Combo(binding)) => {
                 let mut parts = Vec::new();
                 
                 // Add modifiers in Windows order: Ctrl, Alt, Shift
@@ -685,4 +1069,43 @@ impl Platform for WindowsBackend {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A key of its own under the KeyMagic root, removed by `drop_test_key`
+    fn test_key(name: &str) -> (RegKey, String) {
+        let path = format!(r"{}\Tests\{}-{}", KEYMAGIC_ROOT, name, std::process::id());
+        let (key, _) = RegKey::predef(HKEY_CURRENT_USER).create_subkey(&path).unwrap();
+        (key, path)
+    }
+
+    fn drop_test_key(path: &str) {
+        let _ = RegKey::predef(HKEY_CURRENT_USER).delete_subkey_all(path);
+    }
+
+    #[test]
+    fn test_binary_value_round_trip() {
+        let (key, path) = test_key("binary");
+        assert_eq!(read_binary_value(&key, KEYBOARD_DATA_VALUE).unwrap(), None);
+
+        let data: Vec<u8> = (0..=255).cycle().take(200 * 1024).collect();
+        write_binary_value(&key, KEYBOARD_DATA_VALUE, &data).unwrap();
+        assert_eq!(read_binary_value(&key, KEYBOARD_DATA_VALUE).unwrap(), Some(data));
+
+        // Empty data is still data
+        write_binary_value(&key, KEYBOARD_DATA_VALUE, &[]).unwrap();
+        assert_eq!(read_binary_value(&key, KEYBOARD_DATA_VALUE).unwrap(), Some(Vec::new()));
+        drop_test_key(&path);
+    }
+
+    #[test]
+    fn test_binary_value_of_another_type_is_ignored() {
+        let (key, path) = test_key("binary-type");
+        key.set_value(KEYBOARD_DATA_VALUE, &"not binary").unwrap();
+        assert_eq!(read_binary_value(&key, KEYBOARD_DATA_VALUE).unwrap(), None);
+        drop_test_key(&path);
+    }
+}
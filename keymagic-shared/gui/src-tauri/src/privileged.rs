@@ -0,0 +1,283 @@
+//! Actions that open URLs, run installers or relaunch the app elevated
+//!
+//! The webview never names a program or arguments. Each action the settings
+//! pages need has its own command, and its parameters are checked here
+//! before anything is started.
+
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Schemes `open_url` hands to the system
+pub const ALLOWED_URL_SCHEMES: &[&str] = &["https", "http", "mailto"];
+
+/// Why a privileged action was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrivilegedActionError {
+    InvalidUrl(String),
+    UnsupportedScheme(String),
+    /// Network shares, including `\\?\UNC\` paths
+    UncPath(PathBuf),
+    /// Relative paths and paths with `..` components
+    InvalidPath(PathBuf),
+    OutsideDownloadDir(PathBuf),
+    /// The updater has no verified checksum for the file
+    NotVerified(PathBuf),
+    ChecksumMismatch { expected: String, actual: String },
+    InvalidLanguage(String),
+}
+
+impl std::fmt::Display for PrivilegedActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl(url) => write!(f, "Invalid URL: {}", url),
+            Self::UnsupportedScheme(scheme) => write!(f, "URLs with the '{}' scheme cannot be opened", scheme),
+            Self::UncPath(path) => write!(f, "Installers on network paths cannot be launched: {}", path.display()),
+            Self::InvalidPath(path) => write!(f, "Invalid installer path: {}", path.display()),
+            Self::OutsideDownloadDir(path) => {
+                write!(f, "Only installers downloaded by KeyMagic can be launched: {}", path.display())
+            }
+            Self::NotVerified(path) => write!(f, "The installer was not verified: {}", path.display()),
+            Self::ChecksumMismatch { expected, actual } => {
+                write!(f, "Installer checksum mismatch: expected SHA-256 {}, got {}", expected, actual)
+            }
+            Self::InvalidLanguage(code) => write!(f, "Invalid language code: {}", code),
+        }
+    }
+}
+
+impl std::error::Error for PrivilegedActionError {}
+
+/// Parses `url` and checks its scheme against [`ALLOWED_URL_SCHEMES`]
+pub fn validate_url(url: &str) -> Result<Url, PrivilegedActionError> {
+    let parsed = Url::parse(url.trim()).map_err(|_| PrivilegedActionError::InvalidUrl(url.to_string()))?;
+    if !ALLOWED_URL_SCHEMES.contains(&parsed.scheme()) {
+        return Err(PrivilegedActionError::UnsupportedScheme(parsed.scheme().to_string()));
+    }
+    // Web URLs need a host; `https:foo` would be handed to the shell as is
+    if parsed.scheme() != "mailto" && parsed.host_str().is_none_or(str::is_empty) {
+        return Err(PrivilegedActionError::InvalidUrl(url.to_string()));
+    }
+    Ok(parsed)
+}
+
+/// `\\server\share`, `//server/share` and `\\?\UNC\server\share`. Verbatim
+/// paths to local drives (`\\?\C:\`) are allowed.
+fn is_unc_path(path: &Path) -> bool {
+    let text = path.to_string_lossy().replace('/', "\\");
+    if let Some(verbatim) = text.strip_prefix("\\\\?\\").or_else(|| text.strip_prefix("\\\\.\\")) {
+        let mut chars = verbatim.chars();
+        return !matches!((chars.next(), chars.next()), (Some(drive), Some(':')) if drive.is_ascii_alphabetic());
+    }
+    text.starts_with("\\\\")
+}
+
+/// Checks that `path` names an existing file inside `download_dir`
+///
+/// Returns the canonical path so the file that was checked is the one that
+/// gets launched even if `path` went through a link.
+pub fn validate_installer_path(path: &Path, download_dir: &Path) -> Result<PathBuf, PrivilegedActionError> {
+    if is_unc_path(path) {
+        return Err(PrivilegedActionError::UncPath(path.to_path_buf()));
+    }
+    let has_parent = path.components().any(|c| c == Component::ParentDir);
+    if !path.is_absolute() || has_parent {
+        return Err(PrivilegedActionError::InvalidPath(path.to_path_buf()));
+    }
+
+    let outside = || PrivilegedActionError::OutsideDownloadDir(path.to_path_buf());
+    let canonical = fs::canonicalize(path).map_err(|_| outside())?;
+    let dir = fs::canonicalize(download_dir).map_err(|_| outside())?;
+    if !canonical.starts_with(&dir) || !canonical.is_file() {
+        return Err(outside());
+    }
+    Ok(canonical)
+}
+
+/// Validates the path and compares the file with the checksum the updater
+/// recorded when it downloaded it
+pub fn verify_installer(
+    path: &Path,
+    download_dir: &Path,
+    recorded_sha256: impl FnOnce(&Path) -> Option<String>,
+) -> Result<PathBuf, PrivilegedActionError> {
+    let canonical = validate_installer_path(path, download_dir)?;
+    let expected = recorded_sha256(&canonical).ok_or_else(|| PrivilegedActionError::NotVerified(path.to_path_buf()))?;
+    let data = fs::read(&canonical).map_err(|_| PrivilegedActionError::NotVerified(path.to_path_buf()))?;
+    let actual = format!("{:x}", Sha256::digest(&data));
+    if !actual.eq_ignore_ascii_case(&expected) {
+        return Err(PrivilegedActionError::ChecksumMismatch { expected, actual });
+    }
+    Ok(canonical)
+}
+
+/// Work the app relaunches itself elevated to do
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ElevatedAction {
+    /// Register the TSF language profiles for these languages
+    UpdateLanguages { languages: Vec<String> },
+}
+
+/// BCP 47 style tags such as `my-MM` or `shn`
+fn is_language_code(code: &str) -> bool {
+    let mut parts = code.split('-');
+    let primary = parts.next().unwrap_or_default();
+    (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| (1..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+impl ElevatedAction {
+    pub fn validate(&self) -> Result<(), PrivilegedActionError> {
+        match self {
+            Self::UpdateLanguages { languages } => {
+                if let Some(code) = languages.iter().find(|code| !is_language_code(code)) {
+                    return Err(PrivilegedActionError::InvalidLanguage(code.clone()));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Command line for the elevated instance, see `main.rs`
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            Self::UpdateLanguages { languages } => vec!["--update-languages".to_string(), languages.join(",")],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheme_error(url: &str) -> Option<PrivilegedActionError> {
+        validate_url(url).err()
+    }
+
+    #[test]
+    fn test_allowed_urls() {
+        assert!(validate_url("https://github.com/thantthet/keymagic-3/releases").is_ok());
+        assert!(validate_url("http://localhost:8080/keyboard").is_ok());
+        assert!(validate_url(" mailto:someone@example.com ").is_ok());
+    }
+
+    #[test]
+    fn test_unexpected_schemes() {
+        for (url, scheme) in [
+            ("file:///C:/Windows/System32/cmd.exe", "file"),
+            ("javascript:alert(1)", "javascript"),
+            ("ms-settings:regionlanguage", "ms-settings"),
+            ("smb://server/share/setup.exe", "smb"),
+            ("C:\\Windows\\System32\\calc.exe", "c"),
+        ] {
+            assert_eq!(scheme_error(url), Some(PrivilegedActionError::UnsupportedScheme(scheme.to_string())), "{}", url);
+        }
+        assert!(matches!(scheme_error("not a url"), Some(PrivilegedActionError::InvalidUrl(_))));
+        assert!(matches!(scheme_error("https:"), Some(PrivilegedActionError::InvalidUrl(_))));
+    }
+
+    fn download_dir() -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("keymagic-privileged-{}-{:?}", std::process::id(), std::thread::current().id()));
+        let dir = root.join("downloads");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("setup.exe"), b"installer").unwrap();
+        fs::write(root.join("outside.exe"), b"outside").unwrap();
+        (root, dir)
+    }
+
+    #[test]
+    fn test_installer_inside_download_dir() {
+        let (root, dir) = download_dir();
+        let path = validate_installer_path(&dir.join("setup.exe"), &dir).unwrap();
+        assert_eq!(path, fs::canonicalize(dir.join("setup.exe")).unwrap());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_installer_path_traversal() {
+        let (root, dir) = download_dir();
+        let traversal = dir.join("..").join("outside.exe");
+        assert_eq!(validate_installer_path(&traversal, &dir), Err(PrivilegedActionError::InvalidPath(traversal)));
+        assert!(matches!(
+            validate_installer_path(Path::new("downloads/setup.exe"), &dir),
+            Err(PrivilegedActionError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            validate_installer_path(&root.join("outside.exe"), &dir),
+            Err(PrivilegedActionError::OutsideDownloadDir(_))
+        ));
+        assert!(matches!(
+            validate_installer_path(&dir.join("missing.exe"), &dir),
+            Err(PrivilegedActionError::OutsideDownloadDir(_))
+        ));
+        // The directory itself is not an installer
+        assert!(matches!(validate_installer_path(&dir, &dir), Err(PrivilegedActionError::OutsideDownloadDir(_))));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_installer_unc_paths() {
+        let dir = std::env::temp_dir();
+        for path in [
+            "\\\\server\\share\\setup.exe",
+            "//server/share/setup.exe",
+            "\\\\?\\UNC\\server\\share\\setup.exe",
+            "\\\\.\\pipe\\setup",
+        ] {
+            assert_eq!(
+                validate_installer_path(Path::new(path), &dir),
+                Err(PrivilegedActionError::UncPath(PathBuf::from(path))),
+                "{}",
+                path
+            );
+        }
+        assert!(!is_unc_path(Path::new("\\\\?\\C:\\Users\\setup.exe")));
+        assert!(!is_unc_path(Path::new("C:\\Users\\setup.exe")));
+    }
+
+    #[test]
+    fn test_installer_checksum() {
+        let (root, dir) = download_dir();
+        let path = dir.join("setup.exe");
+        let sha256 = format!("{:x}", Sha256::digest(b"installer"));
+
+        assert_eq!(verify_installer(&path, &dir, |_| Some(sha256.to_uppercase())).unwrap(), fs::canonicalize(&path).unwrap());
+        assert_eq!(verify_installer(&path, &dir, |_| None), Err(PrivilegedActionError::NotVerified(path.clone())));
+
+        // Replaced after it was verified
+        fs::write(&path, b"tampered").unwrap();
+        assert!(matches!(
+            verify_installer(&path, &dir, |_| Some(sha256.clone())),
+            Err(PrivilegedActionError::ChecksumMismatch { .. })
+        ));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_elevated_action() {
+        let action: ElevatedAction =
+            serde_json::from_value(serde_json::json!({ "action": "update_languages", "languages": ["my-MM", "shn"] })).unwrap();
+        assert_eq!(action.validate(), Ok(()));
+        assert_eq!(action.args(), vec!["--update-languages", "my-MM,shn"]);
+
+        for code in ["my-MM','x", "en US", "", "m", "my-", "my;calc", "myanmar"] {
+            let action = ElevatedAction::UpdateLanguages { languages: vec!["en-US".to_string(), code.to_string()] };
+            assert_eq!(action.validate(), Err(PrivilegedActionError::InvalidLanguage(code.to_string())), "{}", code);
+        }
+        assert!(serde_json::from_value::<ElevatedAction>(serde_json::json!({ "action": "run", "command": "cmd" })).is_err());
+    }
+
+    #[test]
+    fn test_generic_command_runner_is_gone() {
+        let handlers = include_str!("lib.rs");
+        assert!(!handlers.contains("commands::run_command"));
+        assert!(handlers.contains("commands::open_url"));
+        assert!(handlers.contains("commands::launch_installer"));
+        assert!(handlers.contains("commands::restart_elevated"));
+        assert!(!include_str!("commands.rs").contains("fn run_command"));
+    }
+}
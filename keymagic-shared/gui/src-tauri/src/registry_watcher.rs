@@ -0,0 +1,56 @@
+//! Changes to the KeyMagic registry key on Windows
+//!
+//! The IME writes the registry when it confirms a switch, and installers or
+//! another settings window can change keyboards too. Our own writes are
+//! reported as well; the reload that follows finds nothing to merge.
+
+use std::time::Duration;
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::System::Registry::{RegNotifyChangeKeyValue, HKEY, REG_NOTIFY_CHANGE_LAST_SET, REG_NOTIFY_CHANGE_NAME};
+use winreg::enums::{HKEY_CURRENT_USER, KEY_NOTIFY};
+use winreg::RegKey;
+
+const KEYMAGIC_ROOT: &str = r"Software\KeyMagic";
+
+/// Values are written one by one; wait for the rest before reloading
+const SETTLE_TIME: Duration = Duration::from_millis(150);
+
+/// Delay before watching again after the key could not be opened
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Calls `on_change` from a background thread after every change below
+/// `HKCU\Software\KeyMagic`
+pub fn watch(on_change: impl Fn() + Send + 'static) {
+    std::thread::spawn(move || loop {
+        let key = match RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags(KEYMAGIC_ROOT, KEY_NOTIFY) {
+            Ok(key) => key,
+            Err(e) => {
+                log::debug!("Cannot watch the KeyMagic registry key: {}", e);
+                std::thread::sleep(RETRY_INTERVAL);
+                continue;
+            }
+        };
+        let hkey = unsafe { std::mem::transmute::<isize, HKEY>(key.raw_handle()) };
+
+        loop {
+            // Blocks until something below the key changes
+            let result = unsafe {
+                RegNotifyChangeKeyValue(
+                    hkey,
+                    true,
+                    REG_NOTIFY_CHANGE_NAME | REG_NOTIFY_CHANGE_LAST_SET,
+                    HANDLE::default(),
+                    false,
+                )
+            };
+            if result.is_err() {
+                // The key was deleted (uninstall); open it again once it is back
+                log::debug!("Registry change notification failed: {:?}", result);
+                break;
+            }
+            std::thread::sleep(SETTLE_TIME);
+            on_change();
+        }
+        std::thread::sleep(RETRY_INTERVAL);
+    });
+}
@@ -0,0 +1,102 @@
+//! Speaking messages through the platform screen reader
+//!
+//! Windows raises a UI Automation notification on the main window, which
+//! Narrator, NVDA and JAWS read out. macOS posts an announcement request to
+//! VoiceOver. Elsewhere messages are only logged.
+
+use tauri::App;
+
+/// Announcer for `NotificationManager::set_announcer`
+#[cfg(target_os = "windows")]
+pub fn announcer(app: &App) -> impl Fn(&str) + Send + Sync + 'static {
+    use tauri::Manager;
+
+    // Kept as a number: window handles are not Send
+    let hwnd = app.get_webview_window("main")
+        .and_then(|window| window.hwnd().ok())
+        .map(|hwnd| hwnd.0 as isize);
+    move |message| match hwnd {
+        Some(hwnd) => {
+            if let Err(e) = windows_announce(hwnd, message) {
+                log::warn!("Screen reader announcement failed: {}", e);
+            }
+        }
+        None => log::debug!("No window to announce from: {}", message),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn windows_announce(hwnd: isize, message: &str) -> windows::core::Result<()> {
+    use windows::core::BSTR;
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::System::Com::{CoInitializeEx, CoUninitialize, COINIT_MULTITHREADED};
+    use windows::Win32::UI::Accessibility::{
+        NotificationKind_ActionCompleted, NotificationProcessing_ImportantMostRecent, UiaClientsAreListening,
+        UiaHostProviderFromHwnd, UiaRaiseNotificationEvent,
+    };
+
+    unsafe {
+        if !UiaClientsAreListening().as_bool() {
+            return Ok(());
+        }
+        // Switches come from command threads; the thread may already be
+        // initialised in another mode, which is fine
+        let initialized = CoInitializeEx(None, COINIT_MULTITHREADED).is_ok();
+        let result = UiaHostProviderFromHwnd(HWND(hwnd as *mut _)).and_then(|provider| {
+            UiaRaiseNotificationEvent(
+                &provider,
+                NotificationKind_ActionCompleted,
+                // A newer switch replaces one not read out yet
+                NotificationProcessing_ImportantMostRecent,
+                &BSTR::from(message),
+                &BSTR::from("KeyMagic.KeyboardSwitch"),
+            )
+        });
+        if initialized {
+            CoUninitialize();
+        }
+        result
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn announcer(_app: &App) -> impl Fn(&str) + Send + Sync + 'static {
+    macos_announce
+}
+
+#[cfg(target_os = "macos")]
+fn macos_announce(message: &str) {
+    use cocoa::base::{id, nil};
+    use cocoa::foundation::{NSAutoreleasePool, NSString};
+    use objc::{class, msg_send, sel, sel_impl};
+
+    #[link(name = "AppKit", kind = "framework")]
+    extern "C" {
+        static NSAccessibilityAnnouncementRequestedNotification: id;
+        static NSAccessibilityAnnouncementKey: id;
+        static NSAccessibilityPriorityKey: id;
+        fn NSAccessibilityPostNotificationWithUserInfo(element: id, notification: id, user_info: id);
+    }
+    // NSAccessibilityPriorityHigh
+    const PRIORITY_HIGH: isize = 90;
+
+    unsafe {
+        let pool = NSAutoreleasePool::new(nil);
+        let app: id = msg_send![class!(NSApplication), sharedApplication];
+        let text = NSString::alloc(nil).init_str(message).autorelease();
+        let priority: id = msg_send![class!(NSNumber), numberWithInteger: PRIORITY_HIGH];
+        let keys = [NSAccessibilityAnnouncementKey, NSAccessibilityPriorityKey];
+        let values = [text, priority];
+        let user_info: id = msg_send![class!(NSDictionary),
+            dictionaryWithObjects: values.as_ptr()
+            forKeys: keys.as_ptr()
+            count: keys.len()];
+        NSAccessibilityPostNotificationWithUserInfo(app, NSAccessibilityAnnouncementRequestedNotification, user_info);
+        pool.drain();
+    }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub fn announcer(_app: &App) -> impl Fn(&str) + Send + Sync + 'static {
+    |message: &str| log::debug!("No screen reader support, not announced: {}", message)
+}
@@ -0,0 +1,116 @@
+//! Resume and session change notifications on Windows
+//!
+//! Hotkey registrations and the state the text services read can be lost
+//! when Windows resumes from sleep or a session is unlocked or reconnected
+//! (for example over Remote Desktop). A hidden window receives those
+//! notifications and runs a handler so the app can set everything up again.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use windows::core::{w, Result};
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::RemoteDesktop::{WTSRegisterSessionNotification, NOTIFY_FOR_THIS_SESSION};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, RegisterClassW, MSG, WINDOW_EX_STYLE,
+    WINDOW_STYLE, WNDCLASSW,
+};
+
+const WM_POWERBROADCAST: u32 = 0x0218;
+const PBT_APMRESUMESUSPEND: usize = 0x0007;
+const PBT_APMRESUMEAUTOMATIC: usize = 0x0012;
+const WM_WTSSESSION_CHANGE: u32 = 0x02B1;
+const WTS_CONSOLE_CONNECT: usize = 0x1;
+const WTS_REMOTE_CONNECT: usize = 0x3;
+const WTS_SESSION_LOGON: usize = 0x5;
+const WTS_SESSION_UNLOCK: usize = 0x8;
+
+/// Resume and unlock usually arrive together; handle them once
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// What happened to the session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    Resumed,
+    Unlocked,
+    Connected,
+}
+
+type Handler = Box<dyn Fn(SessionEvent) + Send + Sync>;
+
+static HANDLER: OnceLock<Handler> = OnceLock::new();
+static LAST_HANDLED: Mutex<Option<Instant>> = Mutex::new(None);
+
+fn session_event(message: u32, wparam: WPARAM) -> Option<SessionEvent> {
+    match (message, wparam.0) {
+        (WM_POWERBROADCAST, PBT_APMRESUMEAUTOMATIC | PBT_APMRESUMESUSPEND) => Some(SessionEvent::Resumed),
+        (WM_WTSSESSION_CHANGE, WTS_SESSION_UNLOCK | WTS_SESSION_LOGON) => Some(SessionEvent::Unlocked),
+        (WM_WTSSESSION_CHANGE, WTS_CONSOLE_CONNECT | WTS_REMOTE_CONNECT) => Some(SessionEvent::Connected),
+        _ => None,
+    }
+}
+
+extern "system" fn window_proc(window: HWND, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if let Some(event) = session_event(message, wparam) {
+        let mut last = LAST_HANDLED.lock().unwrap();
+        let due = last.is_none_or(|at| at.elapsed() >= DEBOUNCE);
+        if due {
+            *last = Some(Instant::now());
+            drop(last);
+            if let Some(handler) = HANDLER.get() {
+                handler(event);
+            }
+        }
+    }
+    unsafe { DefWindowProcW(window, message, wparam, lparam) }
+}
+
+fn run_message_window() -> Result<()> {
+    unsafe {
+        let instance = GetModuleHandleW(None)?;
+        let class_name = w!("KeyMagicSessionEvents");
+        let class = WNDCLASSW {
+            hInstance: instance.into(),
+            lpszClassName: class_name,
+            lpfnWndProc: Some(window_proc),
+            ..Default::default()
+        };
+        RegisterClassW(&class);
+
+        // A hidden top-level window; message-only windows miss power broadcasts
+        let window = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            class_name,
+            w!("KeyMagic session events"),
+            WINDOW_STYLE::default(),
+            0,
+            0,
+            0,
+            0,
+            None,
+            None,
+            instance,
+            None,
+        )?;
+        WTSRegisterSessionNotification(window, NOTIFY_FOR_THIS_SESSION)?;
+
+        let mut message = MSG::default();
+        while GetMessageW(&mut message, None, 0, 0).as_bool() {
+            DispatchMessageW(&message);
+        }
+    }
+    Ok(())
+}
+
+/// Calls `handler` on a background thread whenever the machine resumes or
+/// the session is unlocked or reconnected. Only the first call takes effect.
+pub fn start(handler: impl Fn(SessionEvent) + Send + Sync + 'static) {
+    if HANDLER.set(Box::new(handler)).is_err() {
+        return;
+    }
+    std::thread::spawn(|| {
+        if let Err(e) = run_message_window() {
+            log::error!("Failed to listen for session changes: {}", e);
+        }
+    });
+}
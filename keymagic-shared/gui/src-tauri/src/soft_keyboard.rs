@@ -0,0 +1,474 @@
+//! On-screen keyboard input: sends keys to the application that had focus
+//! before the KeyMagic window, so they go through the normal IME pipeline.
+
+use anyhow::{anyhow, Result};
+use keymagic_core::{ActionType, KeyMagicEngine, VirtualKey};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::core::KeyEventDto;
+
+/// A top-level window that received focus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FocusEntry {
+    /// Native window handle (HWND on Windows)
+    pub window: isize,
+    pub process_id: u32,
+}
+
+/// Most-recently-focused windows, newest last
+#[derive(Debug)]
+pub struct FocusHistory {
+    entries: VecDeque<FocusEntry>,
+    capacity: usize,
+}
+
+impl Default for FocusHistory {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+impl FocusHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Records a focus change; a window already in the history moves to the front
+    pub fn record(&mut self, entry: FocusEntry) {
+        if entry.window == 0 {
+            return;
+        }
+        self.entries.retain(|e| e.window != entry.window);
+        self.entries.push_back(entry);
+        if self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Picks the window that should receive soft keyboard input: the most recent
+    /// window that does not belong to `own_process_id` and is still alive.
+    /// Dead windows encountered on the way are dropped from the history.
+    pub fn target(&mut self, own_process_id: u32, is_alive: impl Fn(isize) -> bool) -> Option<FocusEntry> {
+        self.entries.retain(|e| is_alive(e.window));
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.process_id != own_process_id)
+            .copied()
+    }
+}
+
+/// Result of a soft keyboard key press, mirrored by the on-screen keyboard UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SoftKeyResult {
+    /// "none", "insert", "delete" or "delete_and_insert"
+    pub action: String,
+    pub text: Option<String>,
+    /// Characters (Unicode scalar values) deleted before the cursor
+    pub delete_count: usize,
+    /// UTF-16 code units deleted before the cursor
+    pub delete_utf16_units: usize,
+    pub composing_text: String,
+    pub is_processed: bool,
+    /// Whether the key was delivered to an external application
+    pub injected: bool,
+}
+
+/// The key of a soft keyboard event; character-only events have nothing to press
+fn pressed_key(key: &KeyEventDto) -> Result<VirtualKey> {
+    key.virtual_key()?.ok_or_else(|| anyhow!("Soft keyboard events need a key"))
+}
+
+/// Runs a key through the given engine so the UI can mirror the result. The
+/// key types its US layout character unless the event gives one.
+pub fn mirror_key(engine: &mut KeyMagicEngine, key: &KeyEventDto) -> Result<SoftKeyResult> {
+    pressed_key(key)?;
+    let input = key.clone().with_us_character()?.to_key_input()?;
+    let output = engine.process_key(input)?;
+
+    let (action, text, delete_count) = match output.action {
+        ActionType::None => ("none", None, 0),
+        ActionType::Insert(text) => ("insert", Some(text), 0),
+        ActionType::BackspaceDelete(count) => ("delete", None, count),
+        ActionType::BackspaceDeleteAndInsert(count, text) => ("delete_and_insert", Some(text), count),
+        // Edits added to the engine later are left to the composing text
+        _ => ("none", None, 0),
+    };
+
+    Ok(SoftKeyResult {
+        action: action.to_string(),
+        text,
+        delete_count,
+        delete_utf16_units: output.delete_utf16_units,
+        composing_text: output.composing_text,
+        is_processed: output.is_processed,
+        injected: false,
+    })
+}
+
+/// Largest number of UTF-16 code units sent with one `SendInput` call
+pub const INJECT_CHUNK_UNITS: usize = 64;
+
+/// Whether `ch` attaches to the character before it
+fn is_combining(ch: char) -> bool {
+    matches!(ch,
+        '\u{0300}'..='\u{036F}'
+        | '\u{102B}'..='\u{103E}'
+        | '\u{1056}'..='\u{1059}'
+        | '\u{105E}'..='\u{1060}'
+        | '\u{1062}'..='\u{1064}'
+        | '\u{1067}'..='\u{106D}'
+        | '\u{1071}'..='\u{1074}'
+        | '\u{1082}'..='\u{108D}'
+        | '\u{108F}'
+        | '\u{109A}'..='\u{109D}'
+        | '\u{200C}' | '\u{200D}'
+        | '\u{FE00}'..='\u{FE0F}')
+}
+
+/// Whether a piece may start with `ch` when it follows `prev`
+fn starts_cluster(prev: char, ch: char) -> bool {
+    !is_combining(ch) && !matches!(prev, '\u{1039}' | '\u{200D}')
+}
+
+/// Splits text into pieces of at most `max_units` UTF-16 code units, for
+/// injecting long text a bit at a time. Surrogate pairs are never split, and
+/// a piece only starts inside a cluster (a mark, or a consonant stacked after
+/// the virama) when the cluster is too long for one piece.
+pub fn chunk_text(text: &str, max_units: usize) -> Vec<String> {
+    let max_units = max_units.max(2);
+    let mut chunks = Vec::new();
+    let mut current: Vec<char> = Vec::new();
+    let mut units = 0;
+    for ch in text.chars() {
+        if units + ch.len_utf16() > max_units {
+            // Start the next piece at the last cluster boundary
+            let boundary = match current.last() {
+                Some(&prev) if !starts_cluster(prev, ch) => (1..current.len())
+                    .rev()
+                    .find(|&i| starts_cluster(current[i - 1], current[i]))
+                    .unwrap_or(current.len()),
+                _ => current.len(),
+            };
+            let tail = current.split_off(boundary);
+            chunks.push(current.iter().collect::<String>());
+            units = tail.iter().map(|c| c.len_utf16()).sum();
+            current = tail;
+        }
+        units += ch.len_utf16();
+        current.push(ch);
+    }
+    if !current.is_empty() {
+        chunks.push(current.iter().collect());
+    }
+    chunks
+}
+
+/// Shared focus history, fed by the platform focus tracker
+pub type SharedFocusHistory = Arc<Mutex<FocusHistory>>;
+
+#[cfg(target_os = "windows")]
+mod windows_impl {
+    use super::{chunk_text, FocusEntry, SharedFocusHistory, INJECT_CHUNK_UNITS};
+    use anyhow::{anyhow, Result};
+    use std::time::Duration;
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYBD_EVENT_FLAGS, KEYEVENTF_KEYUP, KEYEVENTF_UNICODE,
+        VIRTUAL_KEY, VK_CONTROL, VK_MENU, VK_SHIFT,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        GetForegroundWindow, GetWindowThreadProcessId, IsWindow, SetForegroundWindow,
+    };
+
+    fn foreground_entry() -> Option<FocusEntry> {
+        unsafe {
+            let hwnd = GetForegroundWindow();
+            if hwnd.0.is_null() {
+                return None;
+            }
+            let mut process_id = 0u32;
+            GetWindowThreadProcessId(hwnd, Some(&mut process_id));
+            Some(FocusEntry {
+                window: hwnd.0 as isize,
+                process_id,
+            })
+        }
+    }
+
+    pub fn is_window_alive(window: isize) -> bool {
+        unsafe { IsWindow(HWND(window as *mut _)).as_bool() }
+    }
+
+    /// Polls the foreground window and records changes into the history
+    pub fn start_focus_tracker(history: SharedFocusHistory) {
+        std::thread::spawn(move || {
+            let mut last = None;
+            loop {
+                let current = foreground_entry();
+                if current != last {
+                    if let Some(entry) = current {
+                        history.lock().unwrap().record(entry);
+                    }
+                    last = current;
+                }
+                std::thread::sleep(Duration::from_millis(250));
+            }
+        });
+    }
+
+    fn key_input(vk: VIRTUAL_KEY, up: bool) -> INPUT {
+        INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: vk,
+                    wScan: 0,
+                    dwFlags: if up { KEYEVENTF_KEYUP } else { KEYBD_EVENT_FLAGS(0) },
+                    time: 0,
+                    dwExtraInfo: 0,
+                },
+            },
+        }
+    }
+
+    /// Focuses the target window and sends the key as a normal key event, so the
+    /// KeyMagic text service in that application processes it with its engine
+    pub fn inject_key(target: FocusEntry, vk: u16, shift: bool, ctrl: bool, alt: bool) -> Result<()> {
+        let modifiers: Vec<VIRTUAL_KEY> = [(shift, VK_SHIFT), (ctrl, VK_CONTROL), (alt, VK_MENU)]
+            .iter()
+            .filter(|(pressed, _)| *pressed)
+            .map(|(_, key)| *key)
+            .collect();
+
+        let mut inputs = Vec::new();
+        inputs.extend(modifiers.iter().map(|m| key_input(*m, false)));
+        inputs.push(key_input(VIRTUAL_KEY(vk), false));
+        inputs.push(key_input(VIRTUAL_KEY(vk), true));
+        inputs.extend(modifiers.iter().rev().map(|m| key_input(*m, true)));
+
+        unsafe {
+            if !SetForegroundWindow(HWND(target.window as *mut _)).as_bool() {
+                return Err(anyhow!("Failed to focus target window"));
+            }
+            let sent = SendInput(&inputs, std::mem::size_of::<INPUT>() as i32);
+            if sent as usize != inputs.len() {
+                return Err(anyhow!("SendInput delivered {} of {} events", sent, inputs.len()));
+            }
+        }
+        Ok(())
+    }
+
+    fn unicode_input(unit: u16, up: bool) -> INPUT {
+        INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: VIRTUAL_KEY(0),
+                    wScan: unit,
+                    dwFlags: if up { KEYEVENTF_UNICODE | KEYEVENTF_KEYUP } else { KEYEVENTF_UNICODE },
+                    time: 0,
+                    dwExtraInfo: 0,
+                },
+            },
+        }
+    }
+
+    /// Focuses the target window and types the text as Unicode characters.
+    /// They arrive as VK_PACKET, which the text service passes through, so
+    /// the text is inserted as is. Long text goes in pieces so the input
+    /// queue of the application keeps up.
+    pub fn inject_text(target: FocusEntry, text: &str) -> Result<()> {
+        unsafe {
+            if !SetForegroundWindow(HWND(target.window as *mut _)).as_bool() {
+                return Err(anyhow!("Failed to focus target window"));
+            }
+        }
+        for (i, chunk) in chunk_text(text, INJECT_CHUNK_UNITS).iter().enumerate() {
+            if i > 0 {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            let inputs: Vec<INPUT> = chunk
+                .encode_utf16()
+                .flat_map(|unit| [unicode_input(unit, false), unicode_input(unit, true)])
+                .collect();
+            let sent = unsafe { SendInput(&inputs, std::mem::size_of::<INPUT>() as i32) };
+            if sent as usize != inputs.len() {
+                return Err(anyhow!("SendInput delivered {} of {} events", sent, inputs.len()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Starts tracking focused windows (no-op where injection is unsupported)
+pub fn start_focus_tracking(history: SharedFocusHistory) {
+    #[cfg(target_os = "windows")]
+    windows_impl::start_focus_tracker(history);
+
+    #[cfg(not(target_os = "windows"))]
+    let _ = history;
+}
+
+/// Delivers a key to the previously focused external application.
+/// Returns Ok(false) when the platform has no injection bridge.
+pub fn deliver_key(history: &SharedFocusHistory, key: &KeyEventDto) -> Result<bool> {
+    let vk = pressed_key(key)?.to_win_vk();
+    #[cfg(target_os = "windows")]
+    {
+        let target = history
+            .lock()
+            .unwrap()
+            .target(std::process::id(), windows_impl::is_window_alive)
+            .ok_or_else(|| anyhow!("No target window to send input to"))?;
+        windows_impl::inject_key(target, vk, key.shift, key.ctrl, key.alt)?;
+        Ok(true)
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        let _ = (history, vk);
+        Ok(false)
+    }
+}
+
+/// Types text into the previously focused external application.
+/// Returns Ok(false) when the platform has no injection bridge.
+pub fn deliver_text(history: &SharedFocusHistory, text: &str) -> Result<bool> {
+    #[cfg(target_os = "windows")]
+    {
+        let target = history
+            .lock()
+            .unwrap()
+            .target(std::process::id(), windows_impl::is_window_alive)
+            .ok_or_else(|| anyhow!("No target window to send input to"))?;
+        windows_impl::inject_text(target, text)?;
+        Ok(true)
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        let _ = (history, text);
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::key_event::VkRef;
+
+    const OWN_PID: u32 = 100;
+
+    fn entry(window: isize, process_id: u32) -> FocusEntry {
+        FocusEntry { window, process_id }
+    }
+
+    #[test]
+    fn test_target_skips_own_windows() {
+        let mut history = FocusHistory::default();
+        history.record(entry(1, 200));
+        history.record(entry(2, OWN_PID));
+
+        assert_eq!(history.target(OWN_PID, |_| true), Some(entry(1, 200)));
+    }
+
+    #[test]
+    fn test_target_prefers_most_recent_and_refocus_moves_to_front() {
+        let mut history = FocusHistory::default();
+        history.record(entry(1, 200));
+        history.record(entry(2, 300));
+        assert_eq!(history.target(OWN_PID, |_| true), Some(entry(2, 300)));
+
+        // Window 1 focused again becomes the most recent one
+        history.record(entry(1, 200));
+        history.record(entry(3, OWN_PID));
+        assert_eq!(history.target(OWN_PID, |_| true), Some(entry(1, 200)));
+    }
+
+    #[test]
+    fn test_target_drops_closed_windows() {
+        let mut history = FocusHistory::default();
+        history.record(entry(1, 200));
+        history.record(entry(2, 300));
+
+        assert_eq!(history.target(OWN_PID, |w| w != 2), Some(entry(1, 200)));
+        // Once dropped, the window does not come back even if the check changes
+        assert_eq!(history.target(OWN_PID, |_| true), Some(entry(1, 200)));
+        assert_eq!(history.target(OWN_PID, |_| false), None);
+    }
+
+    #[test]
+    fn test_history_is_bounded_and_ignores_null_window() {
+        let mut history = FocusHistory::new(2);
+        history.record(entry(0, 200));
+        assert_eq!(history.target(OWN_PID, |_| true), None);
+
+        history.record(entry(1, 200));
+        history.record(entry(2, 200));
+        history.record(entry(3, 200));
+        assert_eq!(history.entries.len(), 2);
+        assert_eq!(history.target(OWN_PID, |w| w == 1), None);
+    }
+
+    #[test]
+    fn test_mirror_key_reports_engine_action() {
+        let km2 = kms2km2::compile_kms(r#""k" => "က""#).unwrap();
+        let mut engine = KeyMagicEngine::new(km2).unwrap();
+
+        let result = mirror_key(&mut engine, &KeyEventDto { vk: Some(VkRef::Code(VirtualKey::KeyK.to_win_vk())), ..KeyEventDto::default() }).unwrap();
+        assert_eq!(result.action, "insert");
+        assert_eq!(result.text.as_deref(), Some("က"));
+        assert_eq!(result.composing_text, "က");
+        assert!(!result.injected);
+
+        let result = mirror_key(&mut engine, &KeyEventDto::key(VirtualKey::Back)).unwrap();
+        assert_eq!(result.action, "delete");
+        assert_eq!(result.delete_count, 1);
+        assert_eq!(result.composing_text, "");
+
+        let unknown = KeyEventDto { vk: Some(VkRef::Code(0xFFFF)), ..KeyEventDto::default() };
+        assert!(mirror_key(&mut engine, &unknown).is_err());
+        assert!(mirror_key(&mut engine, &KeyEventDto::typed('k')).is_err());
+    }
+
+    fn units(chunks: &[String]) -> Vec<usize> {
+        chunks.iter().map(|chunk| chunk.encode_utf16().count()).collect()
+    }
+
+    #[test]
+    fn test_chunk_text_fits_limit() {
+        assert!(chunk_text("", 8).is_empty());
+        assert_eq!(chunk_text("abc", 8), vec!["abc"]);
+        let chunks = chunk_text(&"a".repeat(20), 8);
+        assert_eq!(units(&chunks), vec![8, 8, 4]);
+        assert_eq!(chunks.concat(), "a".repeat(20));
+    }
+
+    #[test]
+    fn test_chunk_text_keeps_surrogate_pairs() {
+        let chunks = chunk_text("ab😀😀", 3);
+        assert_eq!(chunks, vec!["ab", "😀", "😀"]);
+        // Limits below a pair are raised to fit one
+        assert_eq!(chunk_text("😀😀", 1), vec!["😀", "😀"]);
+    }
+
+    #[test]
+    fn test_chunk_text_keeps_myanmar_clusters() {
+        // "မင်္ဂလာ": the stacked ဂ after the virama stays with its cluster
+        let text = "မင်္ဂလာ";
+        let chunks = chunk_text(text, 4);
+        assert_eq!(chunks, vec!["မ", "င်္ဂ", "လာ"]);
+        assert_eq!(chunks.concat(), text);
+
+        // Marks start a piece only when a cluster does not fit
+        let chunks = chunk_text("ကိုု", 2);
+        assert_eq!(chunks.concat(), "ကိုု");
+        assert!(units(&chunks).iter().all(|&n| n <= 2));
+    }
+}
@@ -0,0 +1,311 @@
+//! Files written in full before they replace their destination
+//!
+//! Keyboards, configs, downloads and exports are written to a temporary
+//! file in the destination's folder and renamed over the destination once
+//! complete. The rename stays on one volume, so readers see the old file or
+//! the new one, never part of one. A temporary file that is dropped without
+//! being committed is removed, whatever error or early return dropped it.
+//!
+//! On Windows antivirus scanners and indexers open new files for a moment,
+//! and a rename meanwhile fails with a sharing violation; the rename is
+//! retried a few times before the error is returned.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+/// Waits before each retry of the final rename
+const RENAME_BACKOFF: [Duration; 5] = [
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(200),
+];
+
+/// Suffix of temporary files, for telling leftovers apart
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+/// A file that replaces `dest` on [`commit`](Self::commit), and is removed
+/// if dropped before
+pub struct AtomicFile {
+    file: NamedTempFile,
+    dest: PathBuf,
+    durable: bool,
+}
+
+impl AtomicFile {
+    /// Starts the file next to `dest`, creating the folder if needed
+    pub fn new(dest: &Path) -> io::Result<Self> {
+        let name = dest.file_name().ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidInput, format!("{} does not name a file", dest.display()))
+        })?;
+        let dir = match dest.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(dir)?;
+
+        let mut prefix = std::ffi::OsString::from(".");
+        prefix.push(name);
+        prefix.push(".");
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(&prefix).suffix(TEMP_SUFFIX);
+        // Like fs::write: readable by others unless the umask says otherwise
+        #[cfg(unix)]
+        builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+        let file = builder.tempfile_in(dir)?;
+        Ok(Self { file, dest: dest.to_path_buf(), durable: false })
+    }
+
+    /// Flushes the data to disk before the rename, and the rename after it,
+    /// for files that must survive a crash or power cut: configs and
+    /// rewritten keyboards
+    pub fn durable(mut self) -> Self {
+        self.durable = true;
+        self
+    }
+
+    /// Replaces the destination with what was written
+    pub fn commit(self) -> io::Result<()> {
+        self.commit_with(&mut |from, to| fs::rename(from, to))
+    }
+
+    fn commit_with(mut self, rename: &mut dyn FnMut(&Path, &Path) -> io::Result<()>) -> io::Result<()> {
+        self.file.flush()?;
+        if self.durable {
+            self.file.as_file().sync_all()?;
+        }
+        // Closes the file, which Windows needs for the rename; the path
+        // still removes the file when dropped
+        let temp = self.file.into_temp_path();
+        rename_with_retry(&temp, &self.dest, rename)?;
+        // Renamed away, nothing is left to remove
+        let _ = temp.keep();
+        if self.durable {
+            sync_dir(&self.dest);
+        }
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Replaces `dest` with `contents`
+pub fn write_atomic(dest: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut file = AtomicFile::new(dest)?;
+    file.write_all(contents.as_ref())?;
+    file.commit()
+}
+
+/// Replaces `dest` with `contents`, on disk before this returns
+pub fn write_durable(dest: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut file = AtomicFile::new(dest)?.durable();
+    file.write_all(contents.as_ref())?;
+    file.commit()
+}
+
+/// Replaces `dest` with a copy of `src`; returns the bytes copied
+pub fn copy_atomic(src: &Path, dest: &Path) -> io::Result<u64> {
+    let mut source = File::open(src)?;
+    let mut file = AtomicFile::new(dest)?;
+    let copied = io::copy(&mut source, &mut file)?;
+    file.commit()?;
+    Ok(copied)
+}
+
+fn rename_with_retry(from: &Path, to: &Path, rename: &mut dyn FnMut(&Path, &Path) -> io::Result<()>) -> io::Result<()> {
+    let mut backoff = RENAME_BACKOFF.iter();
+    loop {
+        match rename(from, to) {
+            Ok(()) => return Ok(()),
+            Err(e) if is_transient(&e) => match backoff.next() {
+                Some(delay) => {
+                    log::debug!("Retrying rename to {} in {:?}: {}", to.display(), delay, e);
+                    thread::sleep(*delay);
+                }
+                None => return Err(e),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether a failed rename may work a moment later
+fn is_transient(err: &io::Error) -> bool {
+    /// ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    const WINDOWS_LOCKED: [i32; 2] = [32, 33];
+    match err.kind() {
+        ErrorKind::ResourceBusy => true,
+        // Scanners holding the destination open get the rename denied
+        ErrorKind::PermissionDenied => cfg!(windows),
+        _ => cfg!(windows) && err.raw_os_error().is_some_and(|code| WINDOWS_LOCKED.contains(&code)),
+    }
+}
+
+/// Flushes the folder entry of a renamed file; Windows has no such thing
+/// and flushes it with the file
+fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        if let Err(e) = File::open(dir).and_then(|dir| dir.sync_all()) {
+            log::debug!("Failed to sync {}: {}", dir.display(), e);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// Files in `dir` left by `AtomicFile`s, for tests checking nothing leaked
+#[cfg(test)]
+pub fn leftovers(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    files.retain(|path| {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        name.starts_with('.') && name.ends_with(TEMP_SUFFIX)
+    });
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic-temp-file-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn busy() -> io::Error {
+        io::Error::new(ErrorKind::ResourceBusy, "locked")
+    }
+
+    #[test]
+    fn test_write_replaces_the_destination() {
+        let dir = test_dir("write");
+        let dest = dir.join("config.toml");
+        write_atomic(&dest, "first").unwrap();
+        write_durable(&dest, "second").unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "second");
+        assert!(leftovers(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_temporary_file_is_next_to_the_destination() {
+        let dir = test_dir("next-to");
+        let dest = dir.join("nested").join("keyboard.km2");
+        let file = AtomicFile::new(&dest).unwrap();
+        assert_eq!(file.file.path().parent(), dest.parent());
+        let name = file.file.path().file_name().unwrap().to_str().unwrap().to_string();
+        assert!(name.starts_with(".keyboard.km2.") && name.ends_with(TEMP_SUFFIX), "{}", name);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dropped_file_is_removed() {
+        let dir = test_dir("dropped");
+        let dest = dir.join("keyboard.km2");
+        fs::write(&dest, "old").unwrap();
+
+        let write_half = || -> io::Result<()> {
+            let mut file = AtomicFile::new(&dest)?;
+            file.write_all(b"half")?;
+            Err(io::Error::other("stopped"))
+        };
+        assert!(write_half().is_err());
+        assert!(leftovers(&dir).is_empty());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_locked_destination_is_retried() {
+        let dir = test_dir("retried");
+        let dest = dir.join("installer.exe");
+        let mut file = AtomicFile::new(&dest).unwrap();
+        file.write_all(b"new").unwrap();
+
+        let mut attempts = 0;
+        file.commit_with(&mut |from, to| {
+            attempts += 1;
+            if attempts < 3 { Err(busy()) } else { fs::rename(from, to) }
+        })
+        .unwrap();
+        assert_eq!(attempts, 3);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        assert!(leftovers(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_destination_locked_for_good() {
+        let dir = test_dir("locked");
+        let dest = dir.join("config.toml");
+        fs::write(&dest, "old").unwrap();
+        let mut file = AtomicFile::new(&dest).unwrap().durable();
+        file.write_all(b"new").unwrap();
+
+        let mut attempts = 0;
+        let err = file
+            .commit_with(&mut |_, _| {
+                attempts += 1;
+                Err(busy())
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResourceBusy);
+        assert_eq!(attempts, RENAME_BACKOFF.len() + 1);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+        assert!(leftovers(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_other_errors_are_not_retried() {
+        let dir = test_dir("not-retried");
+        let file = AtomicFile::new(&dir.join("a.km2")).unwrap();
+        let mut attempts = 0;
+        let err = file
+            .commit_with(&mut |_, _| {
+                attempts += 1;
+                Err(io::Error::new(ErrorKind::NotFound, "gone"))
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+        assert!(leftovers(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy() {
+        let dir = test_dir("copy");
+        let src = dir.join("source.km2");
+        fs::write(&src, b"KMKL data").unwrap();
+        assert_eq!(copy_atomic(&src, &dir.join("keyboards").join("copy.km2")).unwrap(), 9);
+        assert_eq!(fs::read(dir.join("keyboards").join("copy.km2")).unwrap(), b"KMKL data");
+
+        // A folder in the way fails the rename; the copy does not linger
+        fs::create_dir_all(dir.join("blocked.km2").join("inside")).unwrap();
+        assert!(copy_atomic(&src, &dir.join("blocked.km2")).is_err());
+        assert!(leftovers(&dir).is_empty());
+        assert!(copy_atomic(&dir.join("missing.km2"), &dir.join("other.km2")).is_err());
+        assert!(!dir.join("other.km2").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
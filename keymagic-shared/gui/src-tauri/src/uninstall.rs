@@ -0,0 +1,414 @@
+//! Cleanup run by the installers when KeyMagic is removed
+//!
+//! `keymagic --uninstall-cleanup [--keep-user-data] [--remove-input-method]`
+//! turns key processing off and removes what the app set up outside its
+//! install folder: keyboard hotkeys, settings, keyboards, language profiles
+//! and autostart entries. Left behind, a reinstall would start from a stale
+//! active keyboard that points at a deleted file.
+//!
+//! Every step can run again after a partial uninstall, and a failing step
+//! does not stop the ones after it. The exit code is 0 when every step
+//! succeeded or found nothing to do, and 1 when any step failed.
+
+use anyhow::{anyhow, Context, Result};
+use keymagic_core::processing_state::DEFAULT_ACK_TIMEOUT;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::platform::{create_platform, Platform};
+
+/// Name the autostart plugin registers the app under
+const AUTOSTART_NAME: &str = "KeyMagic";
+
+#[cfg(target_os = "windows")]
+const KEYMAGIC_REGISTRY_KEY: &str = r"Software\KeyMagic";
+#[cfg(target_os = "windows")]
+const RUN_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+/// Run value the installer adds for the tray manager
+#[cfg(target_os = "windows")]
+const TRAY_RUN_VALUE: &str = "KeyMagicTray";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanupOptions {
+    /// Keep keyboards, settings and hotkeys for a later reinstall
+    pub keep_user_data: bool,
+    /// Also remove the input method bundle
+    #[cfg(target_os = "macos")]
+    pub remove_input_method: bool,
+}
+
+impl CleanupOptions {
+    pub fn from_args(args: &[String]) -> Self {
+        Self {
+            keep_user_data: args.iter().any(|arg| arg == "--keep-user-data"),
+            #[cfg(target_os = "macos")]
+            remove_input_method: args.iter().any(|arg| arg == "--remove-input-method"),
+        }
+    }
+}
+
+/// What a step that did not fail found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Done,
+    /// Already removed, e.g. by an earlier run
+    NothingToDo,
+    /// Left alone because of the options
+    Skipped,
+}
+
+#[derive(Debug)]
+pub struct StepReport {
+    pub step: &'static str,
+    pub result: Result<StepOutcome, String>,
+}
+
+#[derive(Debug, Default)]
+pub struct CleanupReport {
+    pub steps: Vec<StepReport>,
+}
+
+impl CleanupReport {
+    pub fn failures(&self) -> usize {
+        self.steps.iter().filter(|step| step.result.is_err()).count()
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self.failures() == 0 { 0 } else { 1 }
+    }
+}
+
+impl fmt::Display for CleanupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            match &step.result {
+                Ok(StepOutcome::Done) => writeln!(f, "{}: done", step.step)?,
+                Ok(StepOutcome::NothingToDo) => writeln!(f, "{}: nothing to do", step.step)?,
+                Ok(StepOutcome::Skipped) => writeln!(f, "{}: skipped", step.step)?,
+                Err(e) => writeln!(f, "{}: failed: {}", step.step, e)?,
+            }
+        }
+        write!(f, "{} of {} steps failed", self.failures(), self.steps.len())
+    }
+}
+
+type StepFn<'a> = Box<dyn FnOnce() -> Result<StepOutcome> + 'a>;
+
+/// Runs every step in order, whatever the earlier ones returned
+fn run_steps(steps: Vec<(&'static str, StepFn<'_>)>) -> CleanupReport {
+    let steps = steps
+        .into_iter()
+        .map(|(step, run)| StepReport {
+            step,
+            result: run().map_err(|e| format!("{:#}", e)),
+        })
+        .collect();
+    CleanupReport { steps }
+}
+
+/// Runs the cleanup for the installers
+pub fn uninstall_cleanup(options: &CleanupOptions) -> CleanupReport {
+    let platform = create_platform().map_err(|e| format!("{:#}", e));
+    let platform = || -> Result<&dyn Platform> {
+        platform
+            .as_deref()
+            .map_err(|e| anyhow!("Platform backend is not available: {}", e))
+    };
+    let keep_user_data = options.keep_user_data;
+
+    let mut steps: Vec<(&'static str, StepFn<'_>)> = vec![
+        ("disable key processing", Box::new(|| disable_key_processing(platform()?))),
+        ("unregister keyboard hotkeys", Box::new(move || {
+            if keep_user_data {
+                return Ok(StepOutcome::Skipped);
+            }
+            clear_keyboard_hotkeys(platform()?)
+        })),
+    ];
+    #[cfg(target_os = "windows")]
+    steps.push(("unregister language profiles", Box::new(|| {
+        let removed = crate::language_profiles::remove_all_language_profiles()?;
+        Ok(if removed > 0 { StepOutcome::Done } else { StepOutcome::NothingToDo })
+    })));
+    steps.push(("remove autostart entries", Box::new(remove_autostart_entries)));
+    // Reads the keyboards from the settings, so it goes before those
+    steps.push(("remove keyboards and config", Box::new(move || {
+        if keep_user_data {
+            return Ok(StepOutcome::Skipped);
+        }
+        remove_user_data(platform()?)
+    })));
+    #[cfg(target_os = "windows")]
+    steps.push(("remove registry settings", Box::new(move || {
+        if keep_user_data {
+            return Ok(StepOutcome::Skipped);
+        }
+        remove_registry_settings()
+    })));
+    #[cfg(target_os = "macos")]
+    {
+        let remove_input_method = options.remove_input_method;
+        steps.push(("remove input method", Box::new(move || {
+            if !remove_input_method {
+                return Ok(StepOutcome::Skipped);
+            }
+            remove_input_method_bundle()
+        })));
+    }
+
+    run_steps(steps)
+}
+
+/// Has running input methods stop handling keys and commit what they compose
+fn disable_key_processing(platform: &dyn Platform) -> Result<StepOutcome> {
+    if !platform.set_input_method_processing(false, DEFAULT_ACK_TIMEOUT)? {
+        log::warn!("Input methods did not confirm that key processing is off");
+    }
+    Ok(StepOutcome::Done)
+}
+
+/// Turns every keyboard's hotkey off so input methods stop switching on them
+fn clear_keyboard_hotkeys(platform: &dyn Platform) -> Result<StepOutcome> {
+    let mut config = platform.load_config()?;
+    let mut changed = false;
+    for keyboard in &mut config.keyboards.installed {
+        // An empty custom hotkey also overrides the keyboard's own
+        if keyboard.hotkey.as_deref() != Some("") {
+            keyboard.hotkey = Some(String::new());
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(StepOutcome::NothingToDo);
+    }
+    platform.save_config(&config)?;
+    platform.notify_keyboards_changed()?;
+    Ok(StepOutcome::Done)
+}
+
+/// Removes the config and data folders. A keyboards folder the user moved
+/// elsewhere only loses the keyboards KeyMagic installed there.
+fn remove_user_data(platform: &dyn Platform) -> Result<StepOutcome> {
+    let mut paths = Vec::new();
+    let mut own_dirs = vec![platform.get_config_dir(), platform.get_data_dir()];
+    own_dirs.dedup();
+
+    let keyboards_dir = platform.get_keyboards_dir();
+    let moved_keyboards = !own_dirs.iter().any(|dir| keyboards_dir.starts_with(dir));
+    if moved_keyboards {
+        let config = platform.load_config()?;
+        paths.extend(config.keyboards.installed.iter().map(|kb| keyboards_dir.join(&kb.filename)));
+    }
+    paths.extend(own_dirs);
+
+    let outcome = remove_paths(&paths);
+    if moved_keyboards {
+        // Only goes if nothing else is in it
+        let _ = fs::remove_dir(&keyboards_dir);
+    }
+    outcome
+}
+
+/// Removes files and folders, going on past the ones that fail
+fn remove_paths(paths: &[PathBuf]) -> Result<StepOutcome> {
+    let mut removed = false;
+    let mut failures = Vec::new();
+    for path in paths {
+        match remove_path(path) {
+            Ok(true) => removed = true,
+            Ok(false) => {}
+            Err(e) => failures.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    if !failures.is_empty() {
+        Err(anyhow!("Failed to remove {}", failures.join("; ")))
+    } else if removed {
+        Ok(StepOutcome::Done)
+    } else {
+        Ok(StepOutcome::NothingToDo)
+    }
+}
+
+/// Removes a file or folder; false when it was already gone
+fn remove_path(path: &Path) -> std::io::Result<bool> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn remove_autostart_entries() -> Result<StepOutcome> {
+    use winreg::{enums::*, RegKey};
+
+    let run_key = match RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags(RUN_KEY, KEY_READ | KEY_WRITE) {
+        Ok(key) => key,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(StepOutcome::NothingToDo),
+        Err(e) => return Err(e).context("Failed to open the Run key"),
+    };
+
+    let mut removed = false;
+    for value in [TRAY_RUN_VALUE, AUTOSTART_NAME] {
+        match run_key.delete_value(value) {
+            Ok(()) => removed = true,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to remove the {} Run value", value)),
+        }
+    }
+    Ok(if removed { StepOutcome::Done } else { StepOutcome::NothingToDo })
+}
+
+#[cfg(target_os = "macos")]
+fn remove_autostart_entries() -> Result<StepOutcome> {
+    let home = dirs::home_dir().context("Failed to find the home folder")?;
+    remove_paths(&[home.join("Library").join("LaunchAgents").join(format!("{}.plist", AUTOSTART_NAME))])
+}
+
+#[cfg(target_os = "linux")]
+fn remove_autostart_entries() -> Result<StepOutcome> {
+    let config = dirs::config_dir().context("Failed to find the config folder")?;
+    remove_paths(&[config.join("autostart").join(format!("{}.desktop", AUTOSTART_NAME))])
+}
+
+#[cfg(target_os = "windows")]
+fn remove_registry_settings() -> Result<StepOutcome> {
+    use winreg::{enums::HKEY_CURRENT_USER, RegKey};
+
+    match RegKey::predef(HKEY_CURRENT_USER).delete_subkey_all(KEYMAGIC_REGISTRY_KEY) {
+        Ok(()) => Ok(StepOutcome::Done),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(StepOutcome::NothingToDo),
+        Err(e) => Err(e).context("Failed to delete the KeyMagic registry key"),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn remove_input_method_bundle() -> Result<StepOutcome> {
+    if !crate::imk_installer::get_user_imk_path().exists() {
+        return Ok(StepOutcome::NothingToDo);
+    }
+    tauri::async_runtime::block_on(crate::imk_installer::uninstall_imk_bundle()).map_err(|e| anyhow!(e))?;
+    Ok(StepOutcome::Done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::MockPlatform;
+    use keymagic_core::processing_state::ProcessingState;
+    use std::sync::Arc;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("keymagic_uninstall_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_options_from_args() {
+        let args: Vec<String> = ["keymagic", "--uninstall-cleanup", "--keep-user-data"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let options = CleanupOptions::from_args(&args);
+        assert!(options.keep_user_data);
+        assert!(!CleanupOptions::from_args(&args[..2]).keep_user_data);
+    }
+
+    #[test]
+    fn test_failed_step_does_not_stop_the_rest() {
+        let report = run_steps(vec![
+            ("first", Box::new(|| Ok(StepOutcome::Done))),
+            ("second", Box::new(|| Err(anyhow!("access denied")))),
+            ("third", Box::new(|| Ok(StepOutcome::NothingToDo))),
+        ]);
+        assert_eq!(report.steps.len(), 3);
+        assert_eq!(report.steps[2].result, Ok(StepOutcome::NothingToDo));
+        assert_eq!(report.failures(), 1);
+        assert_eq!(report.exit_code(), 1);
+        assert_eq!(
+            report.to_string(),
+            "first: done\nsecond: failed: access denied\nthird: nothing to do\n1 of 3 steps failed"
+        );
+
+        let report = run_steps(vec![("only", Box::new(|| Ok(StepOutcome::Skipped)))]);
+        assert_eq!(report.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_key_processing_is_turned_off() {
+        let state = Arc::new(ProcessingState::in_memory());
+        let platform = MockPlatform::builder("uninstall-processing")
+            .keyboard("myanmar3")
+            .processing(state.clone())
+            .build();
+        assert!(state.is_enabled());
+
+        // Nothing answers, which still counts as done
+        assert_eq!(disable_key_processing(&platform).unwrap(), StepOutcome::Done);
+        assert!(!state.is_enabled());
+        assert_eq!(disable_key_processing(&platform).unwrap(), StepOutcome::Done);
+        assert!(!state.is_enabled());
+    }
+
+    #[test]
+    fn test_hotkeys_are_cleared_once() {
+        let platform = MockPlatform::builder("uninstall-hotkeys").keyboard("myanmar3").keyboard("zawgyi").build();
+        let mut config = platform.load_config().unwrap();
+        config.keyboards.installed[0].hotkey = Some("CTRL+SHIFT+M".to_string());
+        platform.save_config(&config).unwrap();
+
+        assert_eq!(clear_keyboard_hotkeys(&platform).unwrap(), StepOutcome::Done);
+        let config = platform.load_config().unwrap();
+        assert!(config.keyboards.installed.iter().all(|kb| kb.hotkey.as_deref() == Some("")));
+
+        assert_eq!(clear_keyboard_hotkeys(&platform).unwrap(), StepOutcome::NothingToDo);
+    }
+
+    #[test]
+    fn test_user_data_is_removed_once() {
+        let platform = MockPlatform::builder("uninstall-data").keyboard("myanmar3").build();
+        let data_dir = platform.get_data_dir();
+        assert!(platform.get_keyboards_dir().join("myanmar3.km2").exists());
+
+        assert_eq!(remove_user_data(&platform).unwrap(), StepOutcome::Done);
+        assert!(!data_dir.exists());
+        assert_eq!(remove_user_data(&platform).unwrap(), StepOutcome::NothingToDo);
+    }
+
+    #[test]
+    fn test_remove_paths_goes_on_past_failures() {
+        let dir = test_dir("paths");
+        let file = dir.join("KeyMagic.desktop");
+        let folder = dir.join("keyboards");
+        fs::write(&file, "[Desktop Entry]").unwrap();
+        fs::create_dir_all(folder.join("nested")).unwrap();
+        fs::write(folder.join("nested").join("a.km2"), b"KMKL").unwrap();
+
+        assert_eq!(remove_paths(&[file.clone(), folder.clone()]).unwrap(), StepOutcome::Done);
+        assert!(!file.exists() && !folder.exists());
+        assert_eq!(remove_paths(&[file.clone(), folder.clone()]).unwrap(), StepOutcome::NothingToDo);
+
+        // A path that cannot go is reported and the others still go
+        #[cfg(unix)]
+        {
+            let blocked = dir.join("blocked").join("child");
+            fs::write(dir.join("blocked"), b"not a folder").unwrap();
+            fs::write(&file, "[Desktop Entry]").unwrap();
+            let err = remove_paths(&[blocked, file.clone()]).unwrap_err();
+            assert!(err.to_string().contains("blocked"), "{}", err);
+            assert!(!file.exists());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,13 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use semver::Version;
+use crate::http::{self, RetryPolicy};
+use crate::temp_file;
+use crate::version::Version;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
 const UPDATE_JSON_URL: &str = "https://thantthet.github.io/keymagic-3/updates.json";
 
@@ -11,6 +17,9 @@ pub struct UpdateInfo {
     pub latest_version: String,
     pub update_available: bool,
     pub download_url: Option<String>,
+    /// Checksum of the file at `download_url`, from the update manifest
+    #[serde(default)]
+    pub sha256: Option<String>,
     pub release_notes: Option<String>,
     pub published_at: Option<String>,
 }
@@ -40,7 +49,6 @@ enum PlatformRelease {
         signature: Option<String>,
         #[allow(dead_code)]
         size: Option<u64>,
-        #[allow(dead_code)]
         sha256: Option<String>,
     },
     // Linux format with packages
@@ -60,11 +68,10 @@ struct PackageInfo {
     url: String,
     #[allow(dead_code)]
     size: Option<u64>,
-    #[allow(dead_code)]
     sha256: Option<String>,
 }
 
-fn get_platform_info() -> (&'static str, &'static str) {
+pub(crate) fn get_platform_info() -> (&'static str, &'static str) {
     #[cfg(target_os = "windows")]
     let os = "windows";
     #[cfg(target_os = "macos")]
@@ -128,23 +135,9 @@ fn determine_linux_package_type() -> &'static str {
 }
 
 async fn fetch_update_manifest() -> Result<UpdateManifest> {
-    // Build user agent with current version and platform info
-    let (os, arch) = get_platform_info();
-    let user_agent = format!(
-        "KeyMagic/{} ({}/{})",
-        env!("CARGO_PKG_VERSION"),
-        os,
-        arch
-    );
-    
-    let client = reqwest::Client::builder()
-        .user_agent(user_agent)
-        .build()?;
-    
-    let response = client
-        .get(UPDATE_JSON_URL)
-        .send()
-        .await?;
+    let client = http::client_builder().build()?;
+    
+    let response = http::get_with_retry(&client, reqwest::Url::parse(UPDATE_JSON_URL)?, RetryPolicy::default()).await?;
     
     if !response.status().is_success() {
         return Err(anyhow!("Failed to fetch update manifest: {}", response.status()));
@@ -155,9 +148,7 @@ async fn fetch_update_manifest() -> Result<UpdateManifest> {
 }
 
 pub async fn check_for_updates_async() -> Result<UpdateInfo> {
-    // Get current version from cargo
-    let current_version_str = env!("CARGO_PKG_VERSION");
-    let current_version = Version::parse(current_version_str)?;
+    let current_version = Version::current();
     
     let update_manifest = fetch_update_manifest().await?;
     
@@ -173,9 +164,9 @@ pub async fn check_for_updates_async() -> Result<UpdateInfo> {
         .ok_or_else(|| anyhow!("No releases found for {} {}", os, arch))?;
     
     // Extract version, URL, and release date based on platform format
-    let (version, download_url, release_date) = match release_info {
-        PlatformRelease::Direct { version, url, release_date, .. } => {
-            (version.clone(), url.clone(), release_date.clone())
+    let (version, download_url, sha256, release_date) = match release_info {
+        PlatformRelease::Direct { version, url, sha256, release_date, .. } => {
+            (version.clone(), url.clone(), sha256.clone(), release_date.clone())
         },
         PlatformRelease::Linux { version, packages, release_date, .. } => {
             // For Linux, determine which package format to use
@@ -183,7 +174,7 @@ pub async fn check_for_updates_async() -> Result<UpdateInfo> {
             let package_info = packages
                 .get(package_type)
                 .ok_or_else(|| anyhow!("No {} package found for Linux", package_type))?;
-            (version.clone(), package_info.url.clone(), release_date.clone())
+            (version.clone(), package_info.url.clone(), package_info.sha256.clone(), release_date.clone())
         }
     };
     
@@ -197,15 +188,90 @@ pub async fn check_for_updates_async() -> Result<UpdateInfo> {
         .cloned();
     
     Ok(UpdateInfo {
-        current_version: current_version_str.to_string(),
+        current_version: current_version.to_string(),
         latest_version: version,
         update_available,
         download_url: Some(download_url),
+        sha256,
         release_notes,
         published_at: release_date,
     })
 }
 
+/// Largest installer the updater downloads
+const MAX_INSTALLER_SIZE: u64 = 500 * 1024 * 1024;
+const INSTALLER_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Installers downloaded by the updater whose checksum matched the manifest,
+/// by canonical path
+static VERIFIED_INSTALLERS: Mutex<Option<HashMap<PathBuf, String>>> = Mutex::new(None);
+
+/// Folder the updater downloads installers into
+pub fn installer_dir() -> PathBuf {
+    std::env::temp_dir().join("keymagic-updates")
+}
+
+/// SHA-256 recorded when the updater downloaded and verified `path`
+pub fn verified_installer_sha256(path: &Path) -> Option<String> {
+    VERIFIED_INSTALLERS.lock().unwrap().as_ref()?.get(path).cloned()
+}
+
+fn record_verified_installer(path: &Path, sha256: &str) {
+    VERIFIED_INSTALLERS
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(path.to_path_buf(), sha256.to_string());
+}
+
+/// File name for an installer URL, without any directory parts
+fn installer_file_name(url: &reqwest::Url) -> Result<String> {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .map(|name| name.replace(['/', '\\', ':'], "_"))
+        .filter(|name| !name.is_empty() && name != "." && name != "..")
+        .ok_or_else(|| anyhow!("The update URL does not name a file: {}", url))
+}
+
+/// Downloads the installer of `info` into [`installer_dir`] and records it
+/// as verified. Releases without a checksum in the manifest are refused.
+pub async fn download_installer(info: &UpdateInfo) -> Result<PathBuf> {
+    let url = info.download_url.as_deref().ok_or_else(|| anyhow!("The update has no download URL"))?;
+    let expected = info.sha256.as_deref()
+        .map(|sha256| sha256.trim().to_ascii_lowercase())
+        .ok_or_else(|| anyhow!("The update manifest has no checksum for {}", url))?;
+    let url = reqwest::Url::parse(url)?;
+    if url.scheme() != "https" {
+        return Err(anyhow!("Updates can only be downloaded over HTTPS: {}", url));
+    }
+
+    let client = http::client_builder().timeout(INSTALLER_DOWNLOAD_TIMEOUT).build()?;
+    let mut response = http::get_with_retry(&client, url.clone(), RetryPolicy::default()).await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Failed to download update: {}", response.status()));
+    }
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (data.len() + chunk.len()) as u64 > MAX_INSTALLER_SIZE {
+            return Err(anyhow!("The update is larger than {} bytes", MAX_INSTALLER_SIZE));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    let actual = format!("{:x}", Sha256::digest(&data));
+    if actual != expected {
+        return Err(anyhow!("Update checksum mismatch: expected SHA-256 {}, got {}", expected, actual));
+    }
+
+    let dir = installer_dir();
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(installer_file_name(&url)?);
+    temp_file::write_atomic(&path, &data)?;
+    let path = std::fs::canonicalize(&path)?;
+    record_verified_installer(&path, &actual);
+    Ok(path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -220,4 +286,20 @@ mod tests {
         let v4 = Version::parse("1.0.0-beta").unwrap();
         assert!(v3 > v4);
     }
+
+    #[test]
+    fn test_installer_file_name() {
+        let name = |url: &str| installer_file_name(&reqwest::Url::parse(url).unwrap()).ok();
+        assert_eq!(name("https://example.com/releases/KeyMagic-Setup.exe").as_deref(), Some("KeyMagic-Setup.exe"));
+        assert_eq!(name("https://example.com/a/..%5Csetup.exe").as_deref(), Some("..%5Csetup.exe"));
+        assert_eq!(name("https://example.com/"), None);
+    }
+
+    #[test]
+    fn test_verified_installers() {
+        let path = installer_dir().join("test-verified.exe");
+        assert_eq!(verified_installer_sha256(&path), None);
+        record_verified_installer(&path, "abc");
+        assert_eq!(verified_installer_sha256(&path).as_deref(), Some("abc"));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,263 @@
+//! Application and bundle versions
+//!
+//! Versions follow semver: `major.minor.patch`, an optional `-prerelease`
+//! and optional `+build` metadata. Missing minor or patch numbers count as 0
+//! so that "1.0" reads as "1.0.0", and a leading "v" is accepted. Anything
+//! else is an error rather than a best guess.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Error for a string that is not a version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidVersion(pub String);
+
+impl fmt::Display for InvalidVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid version: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidVersion {}
+
+/// A dot-separated part of a prerelease
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Identifier {
+    /// Compared numerically, and lower than any alphanumeric identifier
+    Numeric(u64),
+    /// Compared in ASCII order
+    Alphanumeric(String),
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Identifier::Numeric(n) => write!(f, "{}", n),
+            Identifier::Alphanumeric(s) => f.write_str(s),
+        }
+    }
+}
+
+/// A version; build metadata is kept but does not take part in comparisons
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// Empty for a release
+    pub pre: Vec<Identifier>,
+    pub build: Vec<String>,
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self { major, minor, patch, pre: Vec::new(), build: Vec::new() }
+    }
+
+    /// Version of this build of KeyMagic
+    pub fn current() -> Self {
+        Self::parse(env!("CARGO_PKG_VERSION")).expect("package version is valid")
+    }
+
+    pub fn parse(text: &str) -> Result<Self, InvalidVersion> {
+        let invalid = || InvalidVersion(text.to_string());
+        let trimmed = text.trim();
+        let trimmed = trimmed.strip_prefix('v').unwrap_or(trimmed);
+
+        let (rest, build) = match trimmed.split_once('+') {
+            Some((rest, build)) => (rest, Some(build)),
+            None => (trimmed, None),
+        };
+        let (core, pre) = match rest.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (rest, None),
+        };
+
+        let numbers = core
+            .split('.')
+            .map(parse_number)
+            .collect::<Option<Vec<u64>>>()
+            .filter(|numbers| numbers.len() <= 3)
+            .ok_or_else(invalid)?;
+
+        let pre = match pre {
+            Some(pre) => pre
+                .split('.')
+                .map(|part| {
+                    if !is_identifier(part) {
+                        None
+                    } else if part.bytes().all(|b| b.is_ascii_digit()) {
+                        parse_number(part).map(Identifier::Numeric)
+                    } else {
+                        Some(Identifier::Alphanumeric(part.to_string()))
+                    }
+                })
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(invalid)?,
+            None => Vec::new(),
+        };
+
+        let build = match build {
+            Some(build) => build
+                .split('.')
+                .map(|part| is_identifier(part).then(|| part.to_string()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(invalid)?,
+            None => Vec::new(),
+        };
+
+        let number = |i: usize| numbers.get(i).copied().unwrap_or(0);
+        Ok(Self { major: number(0), minor: number(1), patch: number(2), pre, build })
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+}
+
+fn parse_number(part: &str) -> Option<u64> {
+    if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    part.parse().ok()
+}
+
+fn is_identifier(part: &str) -> bool {
+    !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                // A prerelease comes before its release
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                // Identifier by identifier; a shorter list that matches so far is lower
+                (false, false) => self.pre.cmp(&other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl FromStr for Version {
+    type Err = InvalidVersion;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if !self.pre.is_empty() {
+            let pre: Vec<String> = self.pre.iter().map(ToString::to_string).collect();
+            write!(f, "-{}", pre.join("."))?;
+        }
+        if !self.build.is_empty() {
+            write!(f, "+{}", self.build.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(text: &str) -> Version {
+        Version::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_release_ordering() {
+        assert!(v("1.0.0") > v("0.9.9"));
+        assert!(v("2.0.0") > v("1.9.9"));
+        assert!(v("0.2.0") > v("0.1.9"));
+        assert!(v("0.0.2") > v("0.0.1"));
+        assert!(v("0.10.0") > v("0.9.0"));
+        assert_eq!(v("1.2.3"), v("1.2.3"));
+    }
+
+    #[test]
+    fn test_unequal_segment_counts() {
+        assert_eq!(v("1.0"), v("1.0.0"));
+        assert_eq!(v("1"), v("1.0.0"));
+        assert!(v("1.0.1") > v("1.0"));
+        assert!(v("1.1") > v("1.0.0"));
+        assert!(v("2") > v("1.9.9"));
+    }
+
+    #[test]
+    fn test_prerelease_precedence() {
+        // The example chain from the semver specification
+        let chain = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+        ];
+        for pair in chain.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+        assert!(v("1.0.0-beta") < v("1.0.0"));
+        assert_ne!(v("1.0.0-beta"), v("1.0.0"));
+        assert!(v("1.0.1-beta") > v("1.0.0"));
+        assert!(v("1.0.0-beta").is_prerelease());
+    }
+
+    #[test]
+    fn test_build_metadata_is_ignored_in_comparisons() {
+        assert_eq!(v("1.0.0+20240101"), v("1.0.0"));
+        assert_eq!(v("1.0.0-rc.1+build.5"), v("1.0.0-rc.1"));
+        assert_eq!(v("1.0.0+exp.sha.5114f85").build, vec!["exp", "sha", "5114f85"]);
+    }
+
+    #[test]
+    fn test_garbage_is_an_error() {
+        for text in ["", "abc", "1.x.0", "1.0.0.0", "1..0", "1.0.", "-beta", "1.0.0-", "1.0.0-a..b", "1.0.0+", "1.0.0-be ta", "+1"] {
+            assert_eq!(Version::parse(text), Err(InvalidVersion(text.to_string())), "{:?}", text);
+        }
+        assert!("99999999999999999999.0.0".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn test_lenient_forms() {
+        assert_eq!(v("v1.2.3"), v("1.2.3"));
+        assert_eq!(v(" 1.2.3\n"), v("1.2.3"));
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for text in ["1.2.3", "1.0.0-beta.11", "1.0.0-rc.1+build.5", "0.0.9+1"] {
+            assert_eq!(v(text).to_string(), text);
+        }
+        assert_eq!(v("1.0").to_string(), "1.0.0");
+    }
+
+    #[test]
+    fn test_current_version_parses() {
+        assert_eq!(Version::current().to_string(), Version::parse(env!("CARGO_PKG_VERSION")).unwrap().to_string());
+    }
+}
@@ -265,6 +265,11 @@ pub fn language_code_to_lcid(code: &str) -> Option<u16> {
     WINDOWS_LANGUAGES.get(code).map(|lang| lang.lcid)
 }
 
+// Convert LCID to language code
+pub fn lcid_to_language_code(lcid: u16) -> Option<&'static str> {
+    WINDOWS_LANGUAGES.values().find(|lang| lang.lcid == lcid).map(|lang| lang.code)
+}
+
 // Search languages by name (case-insensitive)
 pub fn search_languages(query: &str) -> Vec<(String, String)> {
     let query = query.to_lowercase();
@@ -0,0 +1,195 @@
+//! Keyboard commands run end to end against the mock platform backend, with
+//! the keyboards bundled with KeyMagic
+//!
+//! Run with `cargo test --features test-util`.
+
+use keymagic_core::{KeyOutcome, VirtualKey};
+use keymagic_gui_lib::testing::{
+    command_logic, ErrorCode, HotkeyManager, KeyEventDto, KeyboardManager, KeyboardStatus, KeyboardStorage, MockPlatform,
+    RecordedEvents,
+};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+
+fn start(name: &str) -> KeyboardManager {
+    let manager = KeyboardManager::new(Box::new(MockPlatform::builder(name).build()));
+    manager.initialize().unwrap();
+    manager
+}
+
+fn bundled(file_name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../../keyboards/bundled").join(file_name)
+}
+
+fn event_names(events: &RecordedEvents) -> Vec<String> {
+    events.take().into_iter().map(|(name, _)| name).collect()
+}
+
+#[test]
+fn test_import_activate_layout_hotkey_remove() {
+    let manager = start("commands-lifecycle");
+    let events = RecordedEvents::default();
+    let hotkeys = HotkeyManager::new();
+    let keyboards_dir = manager.get_platform().get_keyboards_dir();
+
+    // Import
+    let imported = command_logic::import_keyboard(&manager, &hotkeys, &bundled("ZawCode.km2")).unwrap();
+    let zawcode = imported.keyboard;
+    assert!(zawcode.id.starts_with("zawcode-"), "{}", zawcode.id);
+    assert_eq!(zawcode.path, keyboards_dir.join(format!("{}.km2", zawcode.id)));
+    assert_eq!(fs::read(&zawcode.path).unwrap(), fs::read(bundled("ZawCode.km2")).unwrap());
+    assert!(imported.hotkey_conflict.is_none());
+    let other = command_logic::import_keyboard(&manager, &hotkeys, &bundled("Pyidaungsu MM.km2")).unwrap().keyboard;
+
+    let listed: Vec<String> = command_logic::get_keyboards(&manager).into_iter().map(|k| k.id).collect();
+    assert_eq!(listed.len(), 2);
+    assert!(listed.contains(&zawcode.id) && listed.contains(&other.id));
+    let config = manager.get_config();
+    let filenames: Vec<&str> = config.keyboards.installed.iter().map(|k| k.filename.as_str()).collect();
+    assert!(filenames.contains(&format!("{}.km2", zawcode.id).as_str()));
+    assert!(events.take().is_empty());
+
+    // Activate
+    command_logic::set_active_keyboard(&manager, &events, &zawcode.id).unwrap();
+    assert_eq!(events.take(), vec![("active_keyboard_changed".to_string(), json!(zawcode.id))]);
+    assert_eq!(manager.get_active_keyboard(), Some(zawcode.id.clone()));
+    assert_eq!(manager.get_config().keyboards.active.as_deref(), Some(zawcode.id.as_str()));
+
+    // Layout
+    let layout = command_logic::get_keyboard_layout(&manager, &zawcode.id).unwrap();
+    assert_eq!(layout.keyboard_id, zawcode.id);
+    assert_eq!(layout.keyboard_name, zawcode.name);
+    assert!(!layout.keys.is_empty());
+    // A Myanmar keyboard puts Myanmar letters on the letter keys
+    let letter = layout.keys.get("KeyU").and_then(|key| key.unshifted.clone()).unwrap_or_default();
+    assert!(letter.chars().any(|c| ('\u{1000}'..='\u{109F}').contains(&c)), "KeyU gives {:?}", letter);
+
+    // Hotkey
+    command_logic::update_hotkey(&manager, &hotkeys, &zawcode.id, Some("Ctrl+Shift+Z".to_string())).unwrap();
+    let saved = manager.get_config();
+    let entry = saved.keyboards.installed.iter().find(|k| k.filename == format!("{}.km2", zawcode.id)).unwrap();
+    assert_eq!(entry.hotkey.as_deref(), Some("Ctrl+Shift+Z"));
+    assert!(hotkeys.registered_ids().contains(&zawcode.id));
+    // An empty hotkey turns it off; none at all goes back to the keyboard's own
+    command_logic::update_hotkey(&manager, &hotkeys, &zawcode.id, Some(String::new())).unwrap();
+    assert!(!hotkeys.registered_ids().contains(&zawcode.id));
+    command_logic::update_hotkey(&manager, &hotkeys, &zawcode.id, None).unwrap();
+    assert_eq!(hotkeys.registered_ids().contains(&zawcode.id), zawcode.default_hotkey.is_some());
+
+    // Remove the active keyboard
+    command_logic::remove_keyboard(&manager, &events, &zawcode.id).unwrap();
+    assert_eq!(events.take(), vec![("active_keyboard_changed".to_string(), Value::Null)]);
+    assert_eq!(manager.get_active_keyboard(), None);
+    let config = manager.get_config();
+    assert_eq!(config.keyboards.installed.len(), 1);
+    assert_eq!(config.keyboards.active, None);
+    assert!(command_logic::get_keyboards(&manager).iter().all(|k| k.id != zawcode.id));
+    // Only the registration goes; the file stays in the keyboards folder
+    assert!(zawcode.path.exists());
+
+    // Removing another keyboard leaves the active one alone
+    command_logic::remove_keyboard(&manager, &events, &other.id).unwrap();
+    assert!(events.take().is_empty());
+    assert!(manager.get_config().keyboards.installed.is_empty());
+
+    let err = command_logic::get_keyboard_layout(&manager, &zawcode.id).unwrap_err();
+    assert_eq!(err.code, ErrorCode::NotFound);
+}
+
+#[test]
+fn test_every_bundled_keyboard_imports_and_lays_out() {
+    let manager = start("commands-bundled");
+    let events = RecordedEvents::default();
+    let hotkeys = HotkeyManager::new();
+
+    let mut files: Vec<PathBuf> = fs::read_dir(bundled(""))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "km2"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty());
+
+    for file in &files {
+        let keyboard = command_logic::import_keyboard(&manager, &hotkeys, file).unwrap().keyboard;
+        command_logic::set_active_keyboard(&manager, &events, &keyboard.id).unwrap();
+        let layout = command_logic::get_keyboard_layout(&manager, &keyboard.id).unwrap();
+        assert!(!layout.keys.is_empty(), "{} has no keys", file.display());
+    }
+    assert_eq!(command_logic::get_keyboards(&manager).len(), files.len());
+    assert_eq!(event_names(&events), vec!["active_keyboard_changed"; files.len()]);
+}
+
+#[test]
+fn test_failed_activation_is_reported() {
+    let manager = start("commands-activation");
+    let events = RecordedEvents::default();
+    let hotkeys = HotkeyManager::new();
+    let keyboard = command_logic::import_keyboard(&manager, &hotkeys, &bundled("MyanSan.km2")).unwrap().keyboard;
+
+    // The file went bad after it was imported
+    fs::write(&keyboard.path, b"not a keyboard").unwrap();
+    let err = command_logic::set_active_keyboard(&manager, &events, &keyboard.id).unwrap_err();
+    assert_eq!(err.code, ErrorCode::InvalidInput);
+    let sent = events.take();
+    assert_eq!(sent.len(), 1);
+    let (name, payload) = &sent[0];
+    assert_eq!(name, "keyboard_activation_failed");
+    assert_eq!(payload["keyboard_id"], json!(keyboard.id));
+    assert_eq!(manager.get_active_keyboard(), None);
+
+    // Unknown keyboards are not found, and nothing is sent
+    let err = command_logic::set_active_keyboard(&manager, &events, "missing").unwrap_err();
+    assert_eq!(err.code, ErrorCode::NotFound);
+    assert!(events.take().is_empty());
+}
+
+#[test]
+fn test_explain_key() {
+    let manager = start("commands-explain");
+    let hotkeys = HotkeyManager::new();
+    let keyboard = command_logic::import_keyboard(&manager, &hotkeys, &bundled("ZawCode.km2")).unwrap().keyboard;
+
+    let letter = KeyEventDto::key(VirtualKey::KeyU).with_us_character().unwrap();
+    let explanation = command_logic::explain_key(&manager, &keyboard.id, &letter, None).unwrap();
+    assert!(matches!(explanation.outcome, KeyOutcome::RuleMatched { .. }), "{:?}", explanation.outcome);
+    assert!(explanation.rules_for_key > 0);
+    assert!(explanation.candidates.is_empty());
+
+    // A key no rule is for lists the rules that came closest
+    let explanation = command_logic::explain_key(&manager, &keyboard.id, &KeyEventDto::key(VirtualKey::F9), None).unwrap();
+    assert_eq!(explanation.outcome, KeyOutcome::Unprocessed);
+    assert_eq!(explanation.rules_for_key, 0);
+    assert!(!explanation.candidates.is_empty());
+
+    let err = command_logic::explain_key(&manager, "missing", &letter, None).unwrap_err();
+    assert_eq!(err.code, ErrorCode::NotFound);
+}
+
+#[test]
+fn test_keyboard_kept_in_the_registry() {
+    let manager = start("commands-storage");
+    let events = RecordedEvents::default();
+    let hotkeys = HotkeyManager::new();
+    let keyboard = command_logic::import_keyboard(&manager, &hotkeys, &bundled("MyanSan.km2")).unwrap().keyboard;
+    assert_eq!(keyboard.storage, KeyboardStorage::File);
+
+    let embedded = command_logic::set_keyboard_storage(&manager, &keyboard.id, KeyboardStorage::Embedded).unwrap();
+    assert_eq!(embedded.storage, KeyboardStorage::Embedded);
+
+    // The file is stripped between sessions; the keyboard still works
+    fs::remove_file(&keyboard.path).unwrap();
+    command_logic::set_active_keyboard(&manager, &events, &keyboard.id).unwrap();
+    assert!(!command_logic::get_keyboard_layout(&manager, &keyboard.id).unwrap().keys.is_empty());
+    let listed = command_logic::get_keyboards(&manager).into_iter().find(|k| k.id == keyboard.id).unwrap();
+    assert_eq!(listed.status, KeyboardStatus::Active);
+
+    // Back to file storage restores the file
+    let restored = command_logic::set_keyboard_storage(&manager, &keyboard.id, KeyboardStorage::File).unwrap();
+    assert_eq!(restored.storage, KeyboardStorage::File);
+    assert_eq!(fs::read(&keyboard.path).unwrap(), fs::read(bundled("MyanSan.km2")).unwrap());
+
+    let err = command_logic::set_keyboard_storage(&manager, "missing", KeyboardStorage::Embedded).unwrap_err();
+    assert_eq!(err.code, ErrorCode::NotFound);
+}
@@ -0,0 +1,299 @@
+//! Keyboard manager flows run against the mock platform backend
+//!
+//! Run with `cargo test --features test-util`.
+
+use keymagic_gui_lib::testing::{
+    compile_keyboard, HostMode, HotkeyConflict, KeyboardManager, MetadataChanges, MockPlatform, MockPlatformBuilder, Platform,
+};
+use std::fs;
+use std::path::PathBuf;
+
+fn start(builder: MockPlatformBuilder) -> KeyboardManager {
+    let manager = KeyboardManager::new(Box::new(builder.build()));
+    manager.initialize().unwrap();
+    manager
+}
+
+/// Writes a keyboard compiled from `kms` outside the keyboards folder, as a
+/// file picked for import would be
+fn keyboard_file(test: &str, file_name: &str, kms: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("keymagic-flows-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(file_name);
+    fs::write(&path, compile_keyboard(kms).unwrap()).unwrap();
+    path
+}
+
+fn named(name: &str) -> String {
+    format!("/*\n@NAME = \"{}\"\n*/\n\"k\" => \"က\"", name)
+}
+
+#[test]
+fn test_import_keyboard() {
+    let manager = start(MockPlatform::builder("flows-import"));
+    let path = keyboard_file("import", "myanmar3.km2", &named("Myanmar3"));
+
+    let keyboard = manager.import_keyboard(&path).unwrap();
+    assert_eq!(keyboard.id, format!("myanmar3-{}", &keyboard.hash[..8]));
+    assert_eq!(keyboard.name, "Myanmar3");
+    assert!(!keyboard.hash.is_empty());
+    assert_eq!(keyboard.path, manager.get_platform().get_keyboards_dir().join(format!("{}.km2", keyboard.id)));
+    assert!(keyboard.path.exists());
+    assert_eq!(manager.get_keyboards().len(), 1);
+    let config = manager.get_config();
+    assert_eq!(config.keyboards.installed.len(), 1);
+    assert_eq!(config.keyboards.installed[0].filename, format!("{}.km2", keyboard.id));
+
+    // The same file again is the keyboard already installed
+    let again = manager.import_keyboard(&path).unwrap();
+    assert_eq!(again.id, keyboard.id);
+    assert_eq!(manager.get_keyboards().len(), 1);
+
+    // Files that are not keyboards are rejected and change nothing
+    let broken = path.with_file_name("broken.km2");
+    fs::write(&broken, b"not a keyboard").unwrap();
+    assert!(manager.import_keyboard(&broken).is_err());
+    assert_eq!(manager.get_keyboards().len(), 1);
+}
+
+#[test]
+fn test_import_keyboards_sharing_a_file_name() {
+    let manager = start(MockPlatform::builder("flows-import-collision"));
+
+    // myanmar.km2 from two authors: same file name, different content
+    let first = manager.import_keyboard(&keyboard_file("collision-a", "myanmar.km2", &named("Myanmar"))).unwrap();
+    let other_author = format!("{}\n\"a\" => \"အ\"", named("Myanmar"));
+    let second = manager.import_keyboard(&keyboard_file("collision-b", "myanmar.km2", &other_author)).unwrap();
+    assert_ne!(first.id, second.id);
+    assert_ne!(first.path, second.path);
+    assert!(first.id.starts_with("myanmar-") && second.id.starts_with("myanmar-"));
+    assert_eq!(manager.get_keyboards().len(), 2);
+
+    // Hotkeys and activation follow the id, not the file name
+    manager.update_hotkey(&first.id, Some("CTRL+SHIFT+1".to_string())).unwrap();
+    manager.update_hotkey(&second.id, Some("CTRL+SHIFT+2".to_string())).unwrap();
+    manager.set_active_keyboard(&second.id).unwrap();
+    assert_eq!(manager.get_keyboard(&first.id).unwrap().hotkey.as_deref(), Some("CTRL+SHIFT+1"));
+    assert_eq!(manager.get_keyboard(&second.id).unwrap().hotkey.as_deref(), Some("CTRL+SHIFT+2"));
+    assert_eq!(manager.get_config().keyboards.active.as_deref(), Some(second.id.as_str()));
+
+    // Ties on the name resolve to the same keyboard every time
+    let smallest = first.id.clone().min(second.id.clone());
+    assert_eq!(manager.get_keyboard_by_name("Myanmar").unwrap().id, smallest);
+
+    // The same content under another file name is already installed
+    let copy = manager.import_keyboard(&keyboard_file("collision-c", "myanmar (1).km2", &named("Myanmar"))).unwrap();
+    assert_eq!(copy.id, first.id);
+    assert_eq!(manager.get_keyboards().len(), 2);
+}
+
+#[test]
+fn test_legacy_keyboard_ids_are_migrated() {
+    let builder = MockPlatform::builder("flows-legacy-ids")
+        .legacy_keyboard_ids()
+        .keyboard("myanmar3")
+        .keyboard("zawgyi")
+        .active("zawgyi");
+    let platform = builder.build();
+    let mut config = platform.load_config().unwrap();
+    config.keyboards.installed[1].hotkey = Some("CTRL+SHIFT+Z".to_string());
+    platform.save_config(&config).unwrap();
+    let manager = KeyboardManager::new(Box::new(platform));
+    manager.initialize().unwrap();
+
+    let zawgyi = manager.get_keyboard_by_name("zawgyi").unwrap();
+    assert!(zawgyi.id.starts_with("zawgyi-"), "{}", zawgyi.id);
+    assert_eq!(zawgyi.filename, "zawgyi.km2");
+    assert_eq!(zawgyi.hotkey.as_deref(), Some("CTRL+SHIFT+Z"));
+    assert!(manager.get_keyboard("zawgyi").is_none());
+    assert_eq!(manager.get_active_keyboard(), Some(zawgyi.id.clone()));
+    assert!(manager.get_engine().is_some());
+    let myanmar3 = manager.get_keyboard_by_name("myanmar3").unwrap();
+    assert!(myanmar3.id.starts_with("myanmar3-"), "{}", myanmar3.id);
+
+    // Saved, and not run again on the next start
+    let config = manager.get_config();
+    assert_eq!(config.keyboards.active.as_deref(), Some(zawgyi.id.as_str()));
+    assert!(config.keyboards.installed.iter().all(|kb| kb.id != "zawgyi" && kb.id != "myanmar3"));
+    manager.initialize().unwrap();
+    assert_eq!(manager.get_active_keyboard(), Some(zawgyi.id));
+}
+
+#[test]
+fn test_remove_keyboard() {
+    let manager = start(MockPlatform::builder("flows-remove").keyboard("myanmar3").keyboard("zawgyi").active("myanmar3"));
+    manager.set_active_keyboard("myanmar3").unwrap();
+
+    manager.remove_keyboard("zawgyi").unwrap();
+    assert!(manager.get_keyboard("zawgyi").is_none());
+    assert_eq!(manager.get_active_keyboard().as_deref(), Some("myanmar3"));
+
+    // Removing the active keyboard leaves none active
+    manager.remove_keyboard("myanmar3").unwrap();
+    assert!(manager.get_keyboards().is_empty());
+    assert_eq!(manager.get_active_keyboard(), None);
+    assert!(manager.get_engine().is_none());
+    let config = manager.get_config();
+    assert!(config.keyboards.installed.is_empty());
+    assert_eq!(config.keyboards.active, None);
+}
+
+#[test]
+fn test_activate_keyboard() {
+    let manager = start(MockPlatform::builder("flows-activate").keyboard("myanmar3").keyboard("zawgyi"));
+    assert_eq!(manager.get_active_keyboard(), None);
+
+    manager.set_active_keyboard("zawgyi").unwrap();
+    assert_eq!(manager.get_active_keyboard().as_deref(), Some("zawgyi"));
+    assert!(manager.get_keyboard("zawgyi").unwrap().is_active);
+    assert!(manager.get_engine().is_some());
+    assert_eq!(manager.get_config().keyboards.active.as_deref(), Some("zawgyi"));
+
+    // An unknown keyboard leaves the active one alone
+    assert!(manager.set_active_keyboard("missing").is_err());
+    assert_eq!(manager.get_active_keyboard().as_deref(), Some("zawgyi"));
+}
+
+#[test]
+fn test_bundled_keyboard_scan_and_update() {
+    let manager = start(
+        MockPlatform::builder("flows-bundled")
+            .keyboard("myanmar3")
+            .bundled_keyboard("myanmar3", &named("myanmar3"))
+            .bundled_keyboard("zawgyi", &named("Zawgyi")),
+    );
+    assert!(manager.should_scan_bundled_keyboards().unwrap());
+    manager.mark_bundled_keyboards_scanned().unwrap();
+    assert!(!manager.should_scan_bundled_keyboards().unwrap());
+
+    let status = |name: &str| {
+        manager.get_bundled_keyboards().unwrap()
+            .into_iter()
+            .find(|kb| kb.name == name)
+            .map(|kb| (kb.status, PathBuf::from(kb.bundled_path)))
+            .unwrap()
+    };
+    let (zawgyi_status, zawgyi_path) = status("Zawgyi");
+    assert_eq!(zawgyi_status, "New");
+    let (myanmar3_status, myanmar3_path) = status("myanmar3");
+    assert_eq!(myanmar3_status, "Updated");
+
+    manager.import_bundled_keyboard(&zawgyi_path, false).unwrap();
+    assert_eq!(status("Zawgyi").0, "Unchanged");
+
+    // An update replaces the installed keyboard of the same name
+    let updated = manager.import_bundled_keyboard(&myanmar3_path, true).unwrap();
+    assert_eq!(status("myanmar3").0, "Unchanged");
+    let myanmar3: Vec<_> = manager.get_keyboards().into_iter().filter(|kb| kb.name == "myanmar3").collect();
+    assert_eq!(myanmar3.len(), 1);
+    assert_eq!(myanmar3[0].id, updated.id);
+}
+
+#[test]
+fn test_bundled_keyboard_with_edited_metadata_is_modified() {
+    let manager = start(MockPlatform::builder("flows-bundled-edited").bundled_keyboard("zawgyi", &named("Zawgyi")));
+    let bundled = manager.get_bundled_keyboards().unwrap().remove(0);
+    let installed = manager.import_bundled_keyboard(&PathBuf::from(&bundled.bundled_path), false).unwrap();
+
+    let changes = MetadataChanges { name: Some("My Zawgyi".into()), ..Default::default() };
+    manager.rewrite_keyboard_metadata(&installed.id, &changes).unwrap();
+    let bundled = manager.get_bundled_keyboards().unwrap().remove(0);
+    assert_eq!((bundled.status.as_str(), bundled.id.as_str()), ("Modified", installed.id.as_str()));
+
+    // Restoring the bundled copy replaces the edited keyboard
+    manager.import_bundled_keyboard(&PathBuf::from(&bundled.bundled_path), true).unwrap();
+    let names: Vec<_> = manager.get_keyboards().into_iter().map(|kb| kb.name).collect();
+    assert_eq!(names, vec!["Zawgyi".to_string()]);
+    assert_eq!(manager.get_bundled_keyboards().unwrap()[0].status, "Unchanged");
+}
+
+#[test]
+fn test_bundled_keyboards_rescanned_for_damaged_version() {
+    let manager = start(MockPlatform::builder("flows-bundled-version"));
+    assert!(manager.get_bundled_keyboards().unwrap().is_empty());
+
+    let mut config = manager.get_config();
+    config.general.last_scanned_version = Some("not a version".to_string());
+    manager.save_config(&config).unwrap();
+    assert!(manager.should_scan_bundled_keyboards().unwrap());
+}
+
+#[test]
+fn test_update_hotkey() {
+    let manager = start(MockPlatform::builder("flows-hotkey").keyboard("myanmar3"));
+
+    manager.update_hotkey("myanmar3", Some("CTRL+SPACE".to_string())).unwrap();
+    let keyboard = manager.get_keyboard("myanmar3").unwrap();
+    assert_eq!(keyboard.hotkey.as_deref(), Some("CTRL+SPACE"));
+    assert_eq!(keyboard.display_hotkey.as_deref(), Some("Ctrl+Space"));
+    assert_eq!(manager.get_config().keyboards.installed[0].hotkey.as_deref(), Some("CTRL+SPACE"));
+
+    manager.update_hotkey("myanmar3", None).unwrap();
+    let keyboard = manager.get_keyboard("myanmar3").unwrap();
+    assert_eq!(keyboard.hotkey, None);
+    assert_eq!(keyboard.display_hotkey, None);
+    assert_eq!(manager.get_config().keyboards.installed[0].hotkey, None);
+}
+
+#[test]
+fn test_import_keyboard_with_taken_hotkey() {
+    let manager = start(MockPlatform::builder("flows-hotkey-clash").keyboard("zawgyi").setting("on_off_hotkey", "CTRL+SPACE"));
+    manager.update_hotkey("zawgyi", Some("CTRL+SHIFT+M".to_string())).unwrap();
+    let with_hotkey = |name: &str, hotkey: &str| format!("/*\n@NAME = \"{}\"\n@HOTKEY = \"{}\"\n*/\n\"k\" => \"က\"", name, hotkey);
+
+    // The hotkey of another keyboard is left off and a free variant offered
+    let path = keyboard_file("hotkey-clash", "myanmar3.km2", &with_hotkey("Myanmar3", "CTRL+SHIFT+M"));
+    let (keyboard, conflict) = manager.import_keyboard_reporting(&path, |_| true).unwrap();
+    let conflict = conflict.unwrap();
+    assert_eq!(conflict.hotkey, "CTRL+SHIFT+M");
+    assert_eq!(conflict.conflict, HotkeyConflict::Keyboard { keyboard_id: "zawgyi".to_string(), name: "zawgyi".to_string() });
+    assert_eq!(conflict.proposed.as_deref(), Some("CTRL+ALT+SHIFT+M"));
+    assert_eq!(keyboard.hotkey.as_deref(), Some(""));
+    assert_eq!(keyboard.default_hotkey.as_deref(), Some("CTRL+SHIFT+M"));
+    assert_eq!(manager.get_keyboard(&keyboard.id).unwrap().hotkey_in_effect(), None);
+
+    // So are the on/off hotkey and system shortcuts, whatever the spelling
+    let path = keyboard_file("hotkey-clash", "pali.km2", &with_hotkey("Pali", "ctrl space"));
+    let (_, conflict) = manager.import_keyboard_reporting(&path, |hotkey| hotkey != "CTRL+ALT+SPACE").unwrap();
+    let conflict = conflict.unwrap();
+    assert_eq!(conflict.conflict, HotkeyConflict::OnOff);
+    assert_eq!(conflict.proposed.as_deref(), Some("CTRL+SHIFT+SPACE"));
+    let path = keyboard_file("hotkey-clash", "shan.km2", &with_hotkey("Shan", "CTRL+SHIFT+ESC"));
+    let (_, conflict) = manager.import_keyboard_reporting(&path, |_| true).unwrap();
+    assert!(matches!(conflict.unwrap().conflict, HotkeyConflict::Reserved { .. }));
+
+    // A free hotkey is kept as the keyboard's own
+    let path = keyboard_file("hotkey-clash", "mon.km2", &with_hotkey("Mon", "CTRL+SHIFT+N"));
+    let (keyboard, conflict) = manager.import_keyboard_reporting(&path, |_| true).unwrap();
+    assert!(conflict.is_none());
+    assert_eq!(keyboard.hotkey, None);
+    assert_eq!(keyboard.hotkey_in_effect(), Some("CTRL+SHIFT+N"));
+    let in_use = manager.hotkeys_in_use(Some(&keyboard.id)).unwrap();
+    assert!(in_use.conflict("CTRL+SHIFT+N").is_none());
+    assert!(manager.hotkeys_in_use(None).unwrap().conflict("CTRL+SHIFT+N").is_some());
+}
+
+#[test]
+fn test_host_list_edits() {
+    let manager = start(MockPlatform::builder("flows-hosts"));
+
+    manager.add_mode_host(HostMode::Composition, "notepad.exe").unwrap();
+    manager.add_mode_host(HostMode::Composition, "notepad.exe").unwrap();
+    manager.add_mode_host(HostMode::Direct, "code.exe").unwrap();
+    assert_eq!(manager.mode_hosts(HostMode::Composition), vec!["notepad.exe"]);
+    assert_eq!(manager.mode_hosts(HostMode::Direct), vec!["code.exe"]);
+    assert_eq!(manager.get_config().composition_mode.enabled_hosts, vec!["notepad.exe"]);
+
+    manager.remove_mode_host(HostMode::Composition, "notepad.exe").unwrap();
+    assert!(manager.mode_hosts(HostMode::Composition).is_empty());
+    assert_eq!(manager.mode_hosts(HostMode::Direct), vec!["code.exe"]);
+
+    manager.set_mode_hosts(HostMode::Direct, vec!["a.exe".to_string(), "b.exe".to_string()]).unwrap();
+    assert_eq!(manager.get_config().direct_mode.enabled_hosts, vec!["a.exe", "b.exe"]);
+
+    // Shortcut keys are kept per host too
+    manager.set_shortcut_passthrough_keys("photoshop.exe", &["KEY_B".to_string()]).unwrap();
+    assert_eq!(manager.get_shortcut_passthrough_keys()["photoshop.exe"], vec!["VK_KEY_B"]);
+    manager.set_shortcut_passthrough_keys("photoshop.exe", &[]).unwrap();
+    assert!(manager.get_shortcut_passthrough_keys().is_empty());
+}
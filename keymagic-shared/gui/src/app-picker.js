@@ -1,6 +1,6 @@
-const { invoke } = window.__TAURI__.core;
+import { invoke } from './js/invoke.js';
 const { getCurrentWebviewWindow } = window.__TAURI__.webviewWindow;
-const { emit } = window.__TAURI__.event;
+const { emit, listen } = window.__TAURI__.event;
 
 // Add error handling for Tauri API
 if (!window.__TAURI__) {
@@ -38,6 +38,11 @@ async function init() {
     console.error('Failed to get platform info:', error);
   }
   
+  // Icons that were not cached arrive after the list
+  await listen('app_icon_ready', (event) => {
+    showIcon(event.payload.identifier, event.payload.icon_base64);
+  });
+  
   // Load running apps
   await loadApps();
   
@@ -118,18 +123,8 @@ function displayApps(apps) {
     item.dataset.appId = app.identifier;
     item.dataset.appName = app.display_name.toLowerCase();
     
-    // Create icon element
-    let iconElement;
-    if (app.icon_base64) {
-      iconElement = `<img src="data:image/png;base64,${app.icon_base64}" class="app-icon" alt="${app.display_name}">`;
-    } else {
-      // Create placeholder icon with first letter
-      const firstLetter = app.display_name.charAt(0).toUpperCase();
-      iconElement = `<div class="app-icon placeholder">${firstLetter}</div>`;
-    }
-    
     item.innerHTML = `
-      ${iconElement}
+      ${iconMarkup(app)}
       <div class="app-info">
         <div class="app-name">${app.display_name}</div>
         <div class="app-id">${app.identifier}</div>
@@ -142,6 +137,30 @@ function displayApps(apps) {
   });
 }
 
+function iconMarkup(app) {
+  if (app.icon_base64) {
+    return `<img src="data:image/png;base64,${app.icon_base64}" class="app-icon" alt="${app.display_name}">`;
+  }
+  // Placeholder icon with first letter until the icon is loaded
+  const firstLetter = app.display_name.charAt(0).toUpperCase();
+  return `<div class="app-icon placeholder">${firstLetter}</div>`;
+}
+
+function showIcon(identifier, iconBase64) {
+  const app = allApps.find(app => app.identifier === identifier);
+  if (!app) {
+    return;
+  }
+  app.icon_base64 = iconBase64;
+  
+  const item = Array.from(document.querySelectorAll('.app-item'))
+    .find(item => item.dataset.appId === identifier);
+  const placeholder = item?.querySelector('.app-icon');
+  if (placeholder) {
+    placeholder.outerHTML = iconMarkup(app);
+  }
+}
+
 function displayError() {
   const appList = document.getElementById('app-list');
   appList.innerHTML = `
@@ -1,4 +1,4 @@
-const { invoke } = window.__TAURI__.core;
+import { invoke } from './js/invoke.js';
 const { getCurrentWebviewWindow } = window.__TAURI__.webviewWindow;
 
 // Add error handling for Tauri API
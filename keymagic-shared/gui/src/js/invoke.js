@@ -0,0 +1,15 @@
+// Wrapper around Tauri's invoke for backend commands.
+// Commands reject with { code, message, details }; the object gets a
+// toString() returning the message so `'Failed: ' + error` keeps working.
+const { invoke: tauriInvoke } = window.__TAURI__.core;
+
+export async function invoke(command, args) {
+  try {
+    return await tauriInvoke(command, args);
+  } catch (error) {
+    if (error && typeof error === 'object' && typeof error.code === 'string' && 'message' in error) {
+      Object.defineProperty(error, 'toString', { value: () => error.message });
+    }
+    throw error;
+  }
+}
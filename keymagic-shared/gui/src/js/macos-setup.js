@@ -1,5 +1,5 @@
 // macOS IMK setup handler
-const { invoke } = window.__TAURI__.core;
+import { invoke } from './invoke.js';
 
 let setupDialog = null;
 
@@ -1,4 +1,4 @@
-const { invoke } = window.__TAURI__.core;
+import { invoke } from './js/invoke.js';
 const { listen } = window.__TAURI__.event;
 import { checkMacOSSetup } from './js/macos-setup.js';
 
@@ -73,6 +73,7 @@ window.switchPage = function(pageName) {
       if (activePanel === 'input-method' && platformInfo) {
         if (platformInfo.os === 'macos') {
           loadIMKStatus();
+          loadPermissionStatus();
         } else if (platformInfo.os === 'linux') {
           loadIBusConfig();
         }
@@ -137,8 +138,19 @@ function renderKeyboardList() {
   }
 }
 
+// Labels for KeyboardInfo.status, by kind
+const KEYBOARD_STATUS_LABELS = {
+  active: 'Active',
+  enabled: 'Inactive',
+  disabled_by_user: 'Turned off',
+  blocked_validation: 'Does not load',
+  unavailable: 'File missing',
+};
+
 function createKeyboardCard(keyboard) {
   const isActive = keyboard.id === activeKeyboardId;
+  const statusKind = isActive ? 'active' : (keyboard.status?.kind || 'enabled');
+  const isBlocked = statusKind === 'blocked_validation' || statusKind === 'unavailable';
   const isSelected = keyboard.id === selectedKeyboardId;
   const isRecentlyAdded = recentlyAddedKeyboardIds.has(keyboard.id);
   
@@ -158,12 +170,14 @@ function createKeyboardCard(keyboard) {
           ${keyboard.name}
           ${isRecentlyAdded ? '<span class="keyboard-badge-new">Just Added</span>' : ''}
         </div>
+        ${keyboard.sample_text ? `<div class="keyboard-sample">${keyboard.sample_text}</div>` : ''}
         <div class="keyboard-description">${keyboard.description || 'No description'}</div>
       </div>
     </div>
     <div class="keyboard-meta">
-      <span class="keyboard-status ${isActive ? 'active' : ''}">
-        ${isActive ? 'Active' : 'Inactive'}
+      <span class="keyboard-status ${statusKind.replace(/_/g, '-')} ${isBlocked ? 'blocked' : ''}"
+        ${isBlocked ? `onclick="showKeyboardStatusDetail('${keyboard.id}')" title="Why?"` : ''}>
+        ${KEYBOARD_STATUS_LABELS[statusKind] || 'Inactive'}
       </span>
       ${(() => {
         // Determine what hotkey to display
@@ -193,9 +207,15 @@ function createKeyboardCard(keyboard) {
         
         return `<span class="${displayClass}" onclick="configureHotkey('${keyboard.id}')" title="${displayTitle}">${displayHotkey}</span>`;
       })()}
+      <select class="keyboard-encoding" title="Output encoding" onchange="setOutputEncoding('${keyboard.id}', this.value)">
+        <option value="unicode" ${keyboard.output_encoding !== 'zawgyi' ? 'selected' : ''}>Unicode</option>
+        <option value="zawgyi" ${keyboard.output_encoding === 'zawgyi' ? 'selected' : ''}>Zawgyi</option>
+      </select>
     </div>
     <div class="keyboard-actions">
-      ${!isActive ? 
+      ${isBlocked ?
+        `<button class="btn btn-disabled" disabled>Activate</button>` :
+        !isActive ? 
         `<button class="btn btn-primary" onclick="activateKeyboard('${keyboard.id}')">Activate</button>` :
         `<button class="btn btn-disabled" disabled>Active</button>`
       }
@@ -205,7 +225,7 @@ function createKeyboardCard(keyboard) {
   `;
   
   card.addEventListener('click', (e) => {
-    if (!e.target.closest('button')) {
+    if (!e.target.closest('button, select')) {
       selectKeyboard(keyboard.id);
     }
   });
@@ -271,7 +291,35 @@ window.activateKeyboard = async function(keyboardId) {
     showSuccess('Keyboard activated');
   } catch (error) {
     console.error('Failed to activate keyboard:', error);
-    showError('Failed to activate keyboard');
+    // Load failures are reported by the keyboard_activation_failed listener
+    if (!error?.details?.issues) {
+      showError('Failed to activate keyboard');
+    }
+  }
+}
+
+window.showKeyboardStatusDetail = async function(keyboardId) {
+  try {
+    const detail = await invoke('get_keyboard_status_detail', { keyboardId });
+    showError(detail.message);
+    await loadKeyboards();
+  } catch (error) {
+    console.error('Failed to get keyboard status:', error);
+  }
+};
+
+window.setOutputEncoding = async function(keyboardId, encoding) {
+  try {
+    await invoke('set_output_encoding', { keyboardId, encoding });
+    const keyboard = keyboards.find(k => k.id === keyboardId);
+    if (keyboard) {
+      keyboard.output_encoding = encoding;
+    }
+    showSuccess(encoding === 'zawgyi' ? 'Keyboard will output Zawgyi text' : 'Keyboard will output Unicode text');
+  } catch (error) {
+    console.error('Failed to set output encoding:', error);
+    showError('Failed to set output encoding');
+    renderKeyboardList();
   }
 }
 
@@ -357,6 +405,12 @@ window.viewKeyboardLayout = async function(keyboardId) {
 
 // Event listener setup function
 function setupEventListeners() {
+  document.getElementById('add-keyboard-url-btn')?.addEventListener('click', importKeyboardFromUrl);
+  document.getElementById('report-problem-link')?.addEventListener('click', (e) => {
+    e.preventDefault();
+    showReportProblemDialog();
+  });
+  
   // Add keyboard
   addKeyboardBtn.addEventListener('click', async () => {
     try {
@@ -393,6 +447,8 @@ function setupEventListeners() {
           await loadKeyboards();
           await updateTrayMenu();
           showSuccess('Keyboard added successfully');
+          await offerLanguageProfiles(keyboard);
+          await offerFreeHotkey(keyboard);
           // Remove "just added" label after 60 seconds (1 minute)
           setTimeout(() => {
             recentlyAddedKeyboardIds.delete(keyboard.id);
@@ -435,9 +491,13 @@ async function loadSettings() {
       await loadCompositionModeHosts();
     }
     
+    await loadSwitchAnnouncementSetting();
+    await loadProxySettings();
+    
     // Load preview window setting on Windows
     if (platformInfo.os === 'windows') {
       await loadPreviewWindowSetting();
+      await loadEffectiveInputMode();
     }
   } catch (error) {
     console.error('Failed to load settings:', error);
@@ -482,6 +542,80 @@ window.togglePreviewWindow = async function() {
   }
 }
 
+// Screen reader announcement of keyboard switches
+async function loadSwitchAnnouncementSetting() {
+  try {
+    const info = await invoke('get_switch_announcement');
+    updateSwitchAnnouncementCheckbox(info);
+  } catch (error) {
+    console.error('Failed to load announcement setting:', error);
+  }
+}
+
+function updateSwitchAnnouncementCheckbox(info) {
+  const checkbox = document.getElementById('announce-keyboard-switch');
+  if (checkbox) {
+    checkbox.checked = info.enabled;
+  }
+  const hint = document.getElementById('announce-keyboard-switch-hint');
+  if (hint) {
+    hint.textContent = info.setting === null
+      ? (info.screen_reader_active ? 'On because a screen reader is running.' : 'Turns on by itself when a screen reader is running.')
+      : '';
+  }
+}
+
+window.toggleSwitchAnnouncement = async function() {
+  const checkbox = document.getElementById('announce-keyboard-switch');
+  const enabled = checkbox.checked;
+  
+  try {
+    const info = await invoke('set_switch_announcement', { enabled });
+    updateSwitchAnnouncementCheckbox(info);
+  } catch (error) {
+    console.error('Failed to save announcement setting:', error);
+    showError('Failed to save announcement setting');
+    checkbox.checked = !enabled;
+  }
+}
+
+// Proxy for update checks and keyboard downloads
+async function loadProxySettings() {
+  try {
+    updateProxyFields(await invoke('get_proxy_settings'));
+  } catch (error) {
+    console.error('Failed to load proxy settings:', error);
+  }
+}
+
+function updateProxyFields(info) {
+  document.getElementById('proxy-url').value = info.url || '';
+  document.getElementById('proxy-username').value = info.username || '';
+  const password = document.getElementById('proxy-password');
+  password.value = '';
+  password.placeholder = info.has_password ? 'Saved; type to replace' : '';
+  document.getElementById('proxy-password-hint').textContent = info.has_password
+    ? 'Leave the password empty to keep the saved one.'
+    : '';
+}
+
+window.saveProxySettings = async function() {
+  const password = document.getElementById('proxy-password').value;
+  try {
+    const info = await invoke('set_proxy_settings', {
+      url: document.getElementById('proxy-url').value,
+      username: document.getElementById('proxy-username').value,
+      // An empty field keeps the saved password
+      password: password === '' ? null : password,
+    });
+    updateProxyFields(info);
+    showSuccess(info.url ? 'Proxy saved' : 'Using the system proxy');
+  } catch (error) {
+    console.error('Failed to save proxy settings:', error);
+    showError('Failed to save proxy settings: ' + (error.message || error));
+  }
+}
+
 // Composition Mode Host Management
 async function loadCompositionModeHosts() {
   try {
@@ -493,6 +627,27 @@ async function loadCompositionModeHosts() {
   }
 }
 
+// Mode of the last application used, e.g. "chrome.exe → Direct mode via rule '*chrome*'"
+async function loadEffectiveInputMode() {
+  try {
+    renderEffectiveInputMode(await invoke('get_effective_input_mode'));
+  } catch (error) {
+    console.error('Failed to load effective input mode:', error);
+  }
+}
+
+function renderEffectiveInputMode(info) {
+  const element = document.getElementById('effective-input-mode');
+  if (!element) return;
+  
+  if (!info) {
+    element.style.display = 'none';
+    return;
+  }
+  element.textContent = `Last application used: ${info.summary}`;
+  element.style.display = 'block';
+}
+
 function renderHostList(hosts) {
   const hostList = document.getElementById('composition-mode-process-list');
   if (!hostList) return;
@@ -536,6 +691,129 @@ async function removeHostFromCompositionMode(hostName) {
   }
 }
 
+function showKeyboardUrlDialog() {
+  return new Promise((resolve) => {
+    window._keyboardUrlResolve = resolve;
+    
+    showModal(
+      'Add Keyboard from URL',
+      `
+        <p>Enter the address of a .km2 keyboard file:</p>
+        <input type="text" id="keyboard-url-input" class="modal-input" placeholder="https://example.com/keyboard.km2"
+               autocomplete="off" autocorrect="off" autocapitalize="off" spellcheck="false" />
+        <p>SHA-256 checksum (optional):</p>
+        <input type="text" id="keyboard-sha256-input" class="modal-input" placeholder="checksum published by the author"
+               autocomplete="off" autocorrect="off" autocapitalize="off" spellcheck="false" />
+        <p class="modal-hint">The keyboard is checked before it is added.</p>
+      `,
+      `
+        <button class="btn btn-secondary" onclick="hideModal(); window.resolveKeyboardUrl(null);">Cancel</button>
+        <button class="btn btn-primary" onclick="window.confirmKeyboardUrl();">Download</button>
+      `
+    );
+    
+    setTimeout(() => {
+      const input = document.getElementById('keyboard-url-input');
+      if (input) {
+        input.focus();
+      }
+    }, 100);
+  });
+}
+
+window.confirmKeyboardUrl = function() {
+  const url = document.getElementById('keyboard-url-input')?.value.trim() || '';
+  const sha256 = document.getElementById('keyboard-sha256-input')?.value.trim() || '';
+  if (url) {
+    hideModal();
+    window.resolveKeyboardUrl({ url, expectedSha256: sha256 || null });
+  }
+};
+
+window.resolveKeyboardUrl = function(value) {
+  if (window._keyboardUrlResolve) {
+    window._keyboardUrlResolve(value);
+    delete window._keyboardUrlResolve;
+  }
+};
+
+async function importKeyboardFromUrl() {
+  const request = await showKeyboardUrlDialog();
+  if (!request) {
+    return;
+  }
+  
+  try {
+    showToast('Downloading keyboard...', 'info');
+    const keyboard = await invoke('import_keyboard_from_url', request);
+    recentlyAddedKeyboardIds.add(keyboard.id);
+    await loadKeyboards();
+    await updateTrayMenu();
+    showSuccess(`Keyboard added: ${keyboard.name}`);
+    await offerLanguageProfiles(keyboard);
+    await offerFreeHotkey(keyboard);
+    setTimeout(() => {
+      recentlyAddedKeyboardIds.delete(keyboard.id);
+      renderKeyboardList();
+    }, 60000);
+  } catch (error) {
+    console.error('Failed to add keyboard from URL:', error);
+    showError('Failed to add keyboard: ' + error);
+  }
+}
+
+function showReportProblemDialog() {
+  showModal(
+    'Report a Problem',
+    `
+      <p>KeyMagic will save a diagnostic file to your Downloads folder that you can attach to a bug report.</p>
+      <div class="toggle-setting">
+        <label class="toggle-switch">
+          <input type="checkbox" id="report-include-keyboard" checked>
+          <span class="toggle-slider"></span>
+        </label>
+        <label for="report-include-keyboard" class="toggle-label">Include the active keyboard file</label>
+      </div>
+      <div class="toggle-setting">
+        <label class="toggle-switch">
+          <input type="checkbox" id="report-include-logs" checked>
+          <span class="toggle-slider"></span>
+        </label>
+        <label for="report-include-logs" class="toggle-label">Include recent logs</label>
+      </div>
+      <div class="toggle-setting">
+        <label class="toggle-switch">
+          <input type="checkbox" id="report-reveal-process-names">
+          <span class="toggle-slider"></span>
+        </label>
+        <label for="report-reveal-process-names" class="toggle-label">Show application names</label>
+      </div>
+      <p class="modal-hint">Your home folder is removed from paths and application names are replaced unless you choose to show them.</p>
+    `,
+    `
+      <button class="btn btn-secondary" onclick="hideModal()">Cancel</button>
+      <button class="btn btn-primary" onclick="window.createDiagnosticBundle()">Save</button>
+    `
+  );
+}
+
+window.createDiagnosticBundle = async function() {
+  const request = {
+    includeKeyboard: document.getElementById('report-include-keyboard')?.checked ?? false,
+    includeLogs: document.getElementById('report-include-logs')?.checked ?? false,
+    revealProcessNames: document.getElementById('report-reveal-process-names')?.checked ?? false,
+  };
+  hideModal();
+  
+  try {
+    const path = await invoke('create_diagnostic_bundle', request);
+    showSuccess(`Diagnostic file saved to ${path}`);
+  } catch (error) {
+    console.error('Failed to create diagnostic bundle:', error);
+    showError('Failed to create diagnostic file: ' + (error.message || error));
+  }
+};
+
 function showHostInputDialog() {
   return new Promise((resolve) => {
     // Store resolve function for later use
@@ -708,6 +986,7 @@ window.hideModal = function() {
   modal.classList.remove('show');
   // Remove any special modal classes
   modal.classList.remove('modal-compact');
+  endHotkeyEdit();
 }
 
 
@@ -790,6 +1069,9 @@ window.configureHotkey = function(keyboardId) {
   
   currentHotkeyKeyboard = keyboard;
   recordedKeys = [];
+  // Keep registry reloads from resetting the hotkey while it is edited
+  invoke('begin_keyboard_edit', { keyboardId, field: 'hotkey' })
+    .catch(error => console.error('Failed to mark hotkey edit:', error));
   
   // Determine initial display value and state
   let initialValue = '';
@@ -857,6 +1139,11 @@ window.configureHotkey = function(keyboardId) {
   }, 100);
 }
 
+// A lone modifier pressed twice within this time is recorded as a
+// double-tap, e.g. "Shift Shift"
+const DOUBLE_TAP_WINDOW_MS = 300;
+let lastModifierTap = null;
+
 function recordHotkey(e) {
   e.preventDefault();
   e.stopPropagation();
@@ -867,6 +1154,24 @@ function recordHotkey(e) {
     errorDiv.style.display = 'none';
   }
   
+  const modifierName = {
+    ShiftLeft: 'Shift', ShiftRight: 'Shift',
+    ControlLeft: 'Ctrl', ControlRight: 'Ctrl',
+    AltLeft: 'Alt', AltRight: 'Alt'
+  }[e.code];
+  if (!modifierName) {
+    lastModifierTap = null;
+  } else if (!e.repeat) {
+    const now = Date.now();
+    if (lastModifierTap && lastModifierTap.code === e.code && now - lastModifierTap.time <= DOUBLE_TAP_WINDOW_MS) {
+      lastModifierTap = null;
+      recordedKeys = [modifierName, modifierName];
+      document.getElementById('hotkey-input').value = recordedKeys.join(' ');
+      return;
+    }
+    lastModifierTap = { code: e.code, time: now };
+  }
+  
   recordedKeys = [];
   
   // Record modifiers
@@ -956,11 +1261,18 @@ window.cancelHotkeyConfig = function() {
   if (input) {
     input.removeEventListener('keydown', recordHotkey);
   }
-  currentHotkeyKeyboard = null;
   recordedKeys = [];
   hideModal();
 }
 
+function endHotkeyEdit() {
+  if (currentHotkeyKeyboard) {
+    invoke('end_keyboard_edit', { keyboardId: currentHotkeyKeyboard.id, field: 'hotkey' })
+      .catch(error => console.error('Failed to end hotkey edit:', error));
+    currentHotkeyKeyboard = null;
+  }
+}
+
 // Validate hotkey using backend logic
 async function validateHotkey(hotkeyString, recordedKeys) {
   if (!hotkeyString) {
@@ -1050,7 +1362,6 @@ window.saveHotkey = async function() {
       showSuccess(successMessage);
       
       // Only close modal on success
-      currentHotkeyKeyboard = null;
       recordedKeys = [];
       hideModal();
     } catch (error) {
@@ -1069,11 +1380,7 @@ window.openWindowsInputSettings = async function() {
   }
   
   try {
-    // Use rundll32 to open the language settings
-    await invoke('run_command', {
-      command: 'rundll32.exe',
-      args: ['Shell32.dll,Control_RunDLL', 'input.dll,,{C07337D3-DB2C-4D0B-9A93-B722A6C106E2}{HOTKEYS}']
-    });
+    await invoke('open_input_settings');
   } catch (error) {
     console.error('Failed to open Windows input settings:', error);
     showError('Failed to open Windows input settings');
@@ -1451,10 +1758,10 @@ window.applyLanguageChanges = async function() {
   } catch (error) {
     console.error('Failed to apply language changes:', error);
     
-    if (error.includes && error.includes('ELEVATION_REQUIRED')) {
+    if (error.code === 'ELEVATION_REQUIRED') {
       // Need elevation, launch elevated process
       try {
-        await invoke('apply_language_changes_elevated', { languages });
+        await invoke('restart_elevated', { action: { action: 'update_languages', languages } });
         
         // If successful, update the original set
         originalEnabledLanguageCodes = new Set(languages);
@@ -1462,7 +1769,7 @@ window.applyLanguageChanges = async function() {
         updateLanguageChangesUI();
         showSuccess('Language changes applied successfully');
       } catch (elevatedError) {
-        if (elevatedError === 'ELEVATION_CANCELLED') {
+        if (elevatedError.code === 'ELEVATION_CANCELLED') {
           // User cancelled UAC prompt, keep changes pending
           showError('Administrator privileges required. Changes not applied.');
         } else {
@@ -1487,6 +1794,69 @@ window.cancelLanguageChanges = function() {
   showSuccess('Changes cancelled');
 }
 
+// Offer to enable the language profiles an imported keyboard declares
+async function offerLanguageProfiles(keyboard) {
+  const suggested = keyboard.suggested_languages || [];
+  // Only profiles Windows knows can be enabled; tags are matched ignoring case
+  const languages = suggested
+    .map(tag => allLanguages.find(([code]) => code.toLowerCase() === tag.toLowerCase()))
+    .filter(language => language && !enabledLanguageCodes.has(language[0]));
+  if (languages.length === 0) {
+    return;
+  }
+  
+  const names = languages.map(([, name]) => name).join(', ');
+  const profiles = languages.length === 1 ? 'language profile' : 'language profiles';
+  const confirmed = await showConfirmDialog(
+    'Enable Language',
+    `${keyboard.name} is made for ${names}. Enable the ${names} ${profiles}?`
+  );
+  if (!confirmed) {
+    return;
+  }
+  
+  languages.forEach(([code]) => enabledLanguageCodes.add(code));
+  renderEnabledLanguages();
+  await window.applyLanguageChanges();
+}
+
+// Offer another hotkey when an imported keyboard's own one was taken
+async function offerFreeHotkey(keyboard) {
+  const clash = keyboard.hotkey_conflict;
+  if (!clash) {
+    return;
+  }
+  
+  let usedBy;
+  if (clash.conflict.kind === 'keyboard') {
+    usedBy = `the ${clash.conflict.name} keyboard`;
+  } else if (clash.conflict.kind === 'on_off') {
+    usedBy = 'the on/off hotkey';
+  } else {
+    usedBy = clash.conflict.description;
+  }
+  if (!clash.proposed) {
+    showToast(`${keyboard.name}'s hotkey ${clash.hotkey} is used by ${usedBy}, so it was left off`, 'info');
+    return;
+  }
+  
+  const confirmed = await showConfirmDialog(
+    'Hotkey In Use',
+    `${keyboard.name}'s hotkey ${clash.hotkey} is used by ${usedBy}, so it was left off. Use ${clash.proposed} instead?`
+  );
+  if (!confirmed) {
+    return;
+  }
+  
+  try {
+    await invoke('update_hotkey', { keyboardId: keyboard.id, hotkey: clash.proposed });
+    await loadKeyboards();
+  } catch (error) {
+    console.error('Failed to set proposed hotkey:', error);
+    showError('Failed to set hotkey: ' + error);
+  }
+}
+
 // Platform detection
 async function loadPlatformInfo() {
   try {
@@ -1623,6 +1993,19 @@ function updatePlatformSpecificUI() {
     }
   }
   
+  // Show/hide the permissions panel for macOS
+  const permissionsSection = document.getElementById('permissions-section');
+  if (permissionsSection) {
+    if (platformInfo.os === 'macos') {
+      permissionsSection.style.display = 'block';
+      if (document.getElementById('settings-page').classList.contains('active')) {
+        loadPermissionStatus();
+      }
+    } else {
+      permissionsSection.style.display = 'none';
+    }
+  }
+  
   // Show/hide IBus settings section for Linux
   const ibusSection = document.getElementById('ibus-settings-section');
   if (ibusSection) {
@@ -1776,6 +2159,80 @@ window.openInputSourcesSettings = async function() {
   }
 }
 
+// Privacy permissions (macOS)
+const PERMISSION_NAMES = {
+  'accessibility': 'Accessibility',
+  'input-monitoring': 'Input Monitoring',
+  'screen-recording': 'Screen Recording',
+};
+
+const PERMISSION_STATES = {
+  'granted': { text: 'Granted', color: 'var(--success-color)' },
+  'denied': { text: 'Denied', color: 'var(--error-color)' },
+  'not-determined': { text: 'Not granted', color: 'var(--warning-color)' },
+  'not-needed': { text: 'Not needed', color: 'var(--text-secondary)' },
+};
+
+async function loadPermissionStatus() {
+  if (!platformInfo || platformInfo.os !== 'macos') return;
+  
+  const list = document.getElementById('permissions-list');
+  if (!list) return;
+  
+  try {
+    const statuses = await invoke('get_permission_status');
+    list.innerHTML = '';
+    for (const status of statuses) {
+      list.appendChild(renderPermissionStatus(status));
+    }
+  } catch (error) {
+    console.error('Failed to check permissions:', error);
+    list.innerHTML = '<p class="setting-hint" style="color: var(--error-color);">Failed to check permissions</p>';
+  }
+}
+
+function renderPermissionStatus(status) {
+  const state = PERMISSION_STATES[status.state] || { text: status.state, color: 'var(--text-secondary)' };
+  
+  const row = document.createElement('div');
+  row.className = 'permission-row';
+  
+  const info = document.createElement('div');
+  info.className = 'permission-info';
+  const name = document.createElement('h3');
+  name.textContent = PERMISSION_NAMES[status.permission] || status.permission;
+  const stateText = document.createElement('span');
+  stateText.className = 'permission-state';
+  stateText.style.color = state.color;
+  stateText.textContent = state.text;
+  name.appendChild(stateText);
+  const rationale = document.createElement('p');
+  rationale.className = 'setting-hint';
+  rationale.textContent = status.rationale;
+  info.append(name, rationale);
+  row.appendChild(info);
+  
+  // A denial can only be undone in System Settings, and a grant nothing
+  // needs can be revoked there
+  if (status.state === 'denied' || (status.state === 'granted' && !status.needed)) {
+    const button = document.createElement('button');
+    button.className = 'btn btn-secondary';
+    button.textContent = 'Open System Settings';
+    button.addEventListener('click', () => openPermissionSettings(status.permission));
+    row.appendChild(button);
+  }
+  return row;
+}
+
+async function openPermissionSettings(permission) {
+  try {
+    await invoke('open_permission_settings', { permission });
+  } catch (error) {
+    console.error('Failed to open permission settings:', error);
+    showError('Failed to open System Settings');
+  }
+}
+
 // IBus Configuration Functions (Linux)
 async function loadIBusConfig() {
   if (!platformInfo || platformInfo.os !== 'linux') return;
@@ -1833,6 +2290,7 @@ function initSettingsTabs() {
       if (targetPanel === 'input-method' && platformInfo) {
         if (platformInfo.os === 'macos') {
           loadIMKStatus();
+          loadPermissionStatus();
         } else if (platformInfo.os === 'linux') {
           loadIBusConfig();
         }
@@ -1843,6 +2301,9 @@ function initSettingsTabs() {
 
 // Initialize
 async function init() {
+  // Tell the backend the UI loaded, before it gives up and opens native controls
+  window.__TAURI__.event.emit('frontend_ready');
+  
   // Initialize DOM elements
   keyboardList = document.getElementById('keyboard-list');
   addKeyboardBtn = document.getElementById('add-keyboard-btn');
@@ -1904,6 +2365,8 @@ async function init() {
       await mainWindow.show();
       await mainWindow.unminimize();
       await mainWindow.setFocus();
+      await offerLanguageProfiles(keyboard);
+      await offerFreeHotkey(keyboard);
       
       // Remove "just added" label after 60 seconds (1 minute)
       setTimeout(() => {
@@ -1956,6 +2419,59 @@ async function setupTrayEventListeners() {
     renderKeyboardList();
   });
   
+  // Another process changed keyboards in the registry. Only the keyboards
+  // it touched are replaced; the backend kept fields being edited here.
+  await listen('keyboards_changed', async (event) => {
+    const changes = event.payload;
+    const latest = await invoke('get_keyboards');
+    const touched = new Set([...changes.added, ...Object.keys(changes.changed)]);
+    keyboards = keyboards.filter(k => !changes.removed.includes(k.id));
+    for (const keyboard of latest.filter(k => touched.has(k.id))) {
+      const index = keyboards.findIndex(k => k.id === keyboard.id);
+      if (index >= 0) {
+        keyboards[index] = keyboard;
+      } else {
+        keyboards.push(keyboard);
+      }
+    }
+    if (currentHotkeyKeyboard && changes.removed.includes(currentHotkeyKeyboard.id)) {
+      currentHotkeyKeyboard = null;
+      hideModal();
+    }
+    renderKeyboardList();
+    await updateTrayMenu();
+  });
+  
+  // The text service of the foreground application decided its input mode
+  await listen('input_mode_changed', (event) => {
+    renderEffectiveInputMode(event.payload);
+  });
+  
+  // A profile changes keyboards and settings at once
+  await listen('profile_applied', async (event) => {
+    const applied = event.payload;
+    await loadKeyboards();
+    await loadSettings();
+    if (applied.skipped_keyboards.length > 0) {
+      const message = document.createElement('span');
+      message.textContent = `Profile ${applied.name} applied; skipped ${applied.skipped_keyboards.join(', ')}`;
+      showToast(message.innerHTML, 'info', 6000);
+    }
+  });
+  
+  // Keyboards whose file failed to load stay inactive; say why
+  await listen('keyboard_activation_failed', (event) => {
+    const failure = event.payload;
+    const keyboard = keyboards.find(k => k.id === failure.keyboard_id);
+    const name = keyboard ? keyboard.name : failure.keyboard_id;
+    const reason = failure.reason === 'file_missing'
+      ? 'its file is missing'
+      : failure.issues[failure.issues.length - 1] || 'its file could not be loaded';
+    const message = document.createElement('span');
+    message.textContent = `Could not activate ${name}: ${reason}`;
+    showToast(message.innerHTML, 'error', 6000);
+  });
+  
   // Listen for check for updates event from tray
   await listen('check_for_updates', async () => {
     // Delay slightly to ensure settings page is loaded
@@ -2123,6 +2639,8 @@ function initializeConverter() {
     }
   });
   
+  document.getElementById('convert-kmn-btn').addEventListener('click', convertKmnFile);
+  
   convertBtn.addEventListener('click', async () => {
     await convertKmsFile(false);
   });
@@ -2228,6 +2746,8 @@ async function convertKmsFile(shouldImport) {
         await updateTrayMenu();
         
         showSuccess(`Keyboard "${keyboard.name}" has been imported successfully`);
+        await offerLanguageProfiles(keyboard);
+        await offerFreeHotkey(keyboard);
         
         // Remove "just added" label after 60 seconds (1 minute)
         setTimeout(() => {
@@ -2289,6 +2809,60 @@ async function convertKmsFile(shouldImport) {
   }
 }
 
+// Converts a Keyman source to KMS and lists what did not convert
+async function convertKmnFile() {
+  const { open, save } = window.__TAURI__.dialog;
+  const resultElement = document.getElementById('kmn-conversion-result');
+
+  const inputPath = await open({
+    multiple: false,
+    filters: [{ name: 'Keyman Source', extensions: ['kmn'] }],
+    title: 'Select KMN File'
+  });
+  if (!inputPath) return;
+
+  const fileName = inputPath.split(/[\\/]/).pop();
+  const outputPath = await save({
+    defaultPath: fileName.replace(/\.kmn$/i, '.kms'),
+    filters: [{ name: 'KeyMagic Script', extensions: ['kms'] }]
+  });
+  if (!outputPath) return;
+
+  resultElement.textContent = '';
+  const box = document.createElement('div');
+  const message = document.createElement('div');
+  const title = document.createElement('strong');
+  message.appendChild(title);
+  box.appendChild(message);
+
+  try {
+    const result = await invoke('convert_kmn_file', { inputPath, outputPath });
+    box.className = result.issues.length === 0 ? 'conversion-success' : 'conversion-warning';
+    title.textContent = result.rules_skipped === 0
+      ? `Converted ${result.rules_converted} rules`
+      : `Converted ${result.rules_converted} rules, ${result.rules_skipped} left as comments`;
+    message.appendChild(document.createTextNode(`Saved as ${outputPath.split(/[\\/]/).pop()}. Open it to review, then build it above.`));
+
+    if (result.issues.length > 0) {
+      const list = document.createElement('ul');
+      list.className = 'kmn-issue-list';
+      for (const issue of result.issues) {
+        const item = document.createElement('li');
+        item.textContent = `Line ${issue.line}: ${issue.kind} ${issue.construct}: ${issue.message}`;
+        list.appendChild(item);
+      }
+      message.appendChild(list);
+    }
+  } catch (error) {
+    box.className = 'conversion-error';
+    title.textContent = 'Conversion failed:';
+    message.appendChild(document.createTextNode(error.toString()));
+  }
+
+  resultElement.appendChild(box);
+  resultElement.style.display = 'block';
+}
+
 // Helper function for save file dialog
 async function saveFileDialog(defaultFileName) {
   const { save } = window.__TAURI__.dialog;
@@ -1,6 +1,5 @@
 const { invoke } = window.__TAURI__.core;
 const { WebviewWindow } = window.__TAURI__.webviewWindow;
-const { open } = window.__TAURI__.opener;
 
 let updateInfo = null;
 
@@ -100,8 +99,19 @@ async function loadUpdateInfo() {
 async function downloadUpdate() {
   if (updateInfo && updateInfo.download_url) {
     try {
-      // Use the opener plugin to open URL in default browser
-      await invoke('plugin:opener|open_url', { url: updateInfo.download_url });
+      if (updateInfo.sha256) {
+        // Verified installers are launched directly
+        try {
+          const installerPath = await invoke('download_update');
+          await invoke('launch_installer', { path: installerPath });
+        } catch (error) {
+          console.error('Failed to install update, opening download page instead:', error);
+          await invoke('open_url', { url: updateInfo.download_url });
+        }
+      } else {
+        // Open the download in the default browser
+        await invoke('open_url', { url: updateInfo.download_url });
+      }
       
       // Close the update window
       const currentWindow = WebviewWindow.getCurrent();
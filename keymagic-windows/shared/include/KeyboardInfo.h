@@ -1,6 +1,7 @@
 #pragma once
 
 #include <string>
+#include <vector>
 
 // Common structure for keyboard information
 struct KeyboardInfo {
@@ -8,5 +9,13 @@ struct KeyboardInfo {
     std::wstring name;
     std::wstring path;
     std::wstring hotkey;
+    bool hasHotkey = false;  // A Hotkey value exists; empty means the user turned the hotkey off
     bool enabled = true;  // Default to enabled if not specified
+    std::wstring outputEncoding;  // "unicode" (default) or "zawgyi"
+    std::vector<std::wstring> disabledGroups;  // Rule groups switched off by the user
+    std::vector<std::wstring> passthroughKeys;  // Key names (e.g. "VK_ESCAPE") the keyboard never handles
+    bool commitBeforePassthrough = false;  // Commit composing text when a passthrough key is pressed
+    std::vector<std::wstring> optionOverrides;  // Layout options set by the user, "name=0" or "name=1"
+    std::wstring sampleText;  // Preview text shown under the name when switching to the keyboard
+    std::wstring hash;  // Content hash of the keyboard file as last installed by the GUI
 };
\ No newline at end of file
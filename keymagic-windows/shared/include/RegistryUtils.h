@@ -74,6 +74,31 @@ inline bool ReadRegistryMultiString(HKEY hKey, const std::wstring& valueName, st
     return false;
 }
 
+// Helper function to read a binary value from registry
+inline bool ReadRegistryBinary(HKEY hKey, const std::wstring& valueName, std::vector<BYTE>& data) {
+    data.clear();
+    DWORD dataSize = 0;
+    DWORD type;
+    
+    // Get size first
+    if (RegQueryValueExW(hKey, valueName.c_str(), nullptr, &type, nullptr, &dataSize) != ERROR_SUCCESS ||
+        type != REG_BINARY) {
+        return false;
+    }
+    
+    data.resize(dataSize);
+    if (dataSize == 0) {
+        return true;
+    }
+    if (RegQueryValueExW(hKey, valueName.c_str(), nullptr, &type, data.data(), &dataSize) == ERROR_SUCCESS &&
+        type == REG_BINARY) {
+        data.resize(dataSize);
+        return true;
+    }
+    data.clear();
+    return false;
+}
+
 // Helper function to convert snake_case to PascalCase
 inline std::wstring SnakeCaseToPascalCase(const std::wstring& snakeCase) {
     std::wstring pascalCase;
@@ -177,7 +202,35 @@ inline bool ReadKeyboardInfo(HKEY hKeyboardsKey, const std::wstring& keyboardId,
     
     // Read hotkey - note: the special logic for reading from KM2 file
     // should be handled by the caller if needed
-    ReadRegistryString(hSubKey, L"Hotkey", info.hotkey);
+    info.hasHotkey = ReadRegistryString(hSubKey, L"Hotkey", info.hotkey);
+    
+    // Read output encoding (missing means plain Unicode)
+    ReadRegistryString(hSubKey, L"OutputEncoding", info.outputEncoding);
+    
+    // Read disabled rule groups (missing means all groups are on)
+    ReadRegistryMultiString(hSubKey, L"DisabledGroups", info.disabledGroups);
+    
+    // Read passthrough keys (missing means the keyboard sees every key)
+    ReadRegistryMultiString(hSubKey, L"PassthroughKeys", info.passthroughKeys);
+    
+    // Read layout option overrides (missing means the keyboard's own options)
+    ReadRegistryMultiString(hSubKey, L"OptionOverrides", info.optionOverrides);
+    
+    // Read the preview text for the switch notification (missing means name only)
+    ReadRegistryString(hSubKey, L"SampleText", info.sampleText);
+    
+    // Read the file hash the GUI recorded (missing means unknown)
+    ReadRegistryString(hSubKey, L"Hash", info.hash);
+    
+    DWORD commitBeforePassthrough = 0;
+    DWORD commitSize = sizeof(commitBeforePassthrough);
+    DWORD commitType;
+    if (RegQueryValueExW(hSubKey, L"CommitBeforePassthrough", nullptr, &commitType,
+                         reinterpret_cast<LPBYTE>(&commitBeforePassthrough), &commitSize) == ERROR_SUCCESS) {
+        if (commitType == REG_DWORD) {
+            info.commitBeforePassthrough = (commitBeforePassthrough != 0);
+        }
+    }
     
     // Read enabled state (default to true if not present)
     DWORD enabled = 1;
@@ -206,6 +259,20 @@ inline bool GetKeyboardInfoById(const std::wstring& keyboardId, KeyboardInfo& in
     return result;
 }
 
+// Read the km2 data a keyboard keeps in the registry for when its file is
+// missing (the Data value the GUI writes for embedded keyboards)
+inline bool ReadKeyboardData(const std::wstring& keyboardId, std::vector<BYTE>& data) {
+    std::wstring subKeyPath = std::wstring(KEYMAGIC_KEYBOARDS_PATH) + L"\\" + keyboardId;
+    HKEY hSubKey;
+    if (RegOpenKeyExW(HKEY_CURRENT_USER, subKeyPath.c_str(), 0, KEY_READ, &hSubKey) != ERROR_SUCCESS) {
+        return false;
+    }
+    
+    bool result = ReadRegistryBinary(hSubKey, L"Data", data) && !data.empty();
+    RegCloseKey(hSubKey);
+    return result;
+}
+
 // Get list of all installed keyboards
 inline std::vector<KeyboardInfo> GetInstalledKeyboards() {
     std::vector<KeyboardInfo> keyboards;
@@ -245,6 +312,14 @@ inline std::wstring GetDefaultKeyboardId() {
     return defaultKeyboard;
 }
 
+// Path of a keyboard the GUI is trying out for this session; it takes
+// precedence over DefaultKeyboard while set
+inline std::wstring GetTemporaryKeyboardPath() {
+    std::wstring path;
+    ReadKeyMagicSetting(L"TemporaryKeyboard", path);
+    return path;
+}
+
 // Set default keyboard ID
 inline bool SetDefaultKeyboardId(const std::wstring& keyboardId) {
     HKEY hSettingsKey;
@@ -8,7 +8,7 @@ extern "C" {
 #include <stdint.h>
 #include <stddef.h>
 
-// Opaque handle to the engine
+// Opaque handle to the engine (may be shared between threads)
 typedef struct EngineHandle EngineHandle;
 
 // Result codes
@@ -25,22 +25,57 @@ typedef enum {
 typedef struct {
     int action_type;      // 0=None, 1=Insert, 2=BackspaceDelete, 3=BackspaceDeleteAndInsert
     char* text;           // UTF-8 encoded, null-terminated (needs to be freed)
-    int delete_count;     // Number of characters to delete
+    int delete_count;     // Number of characters (Unicode scalar values) to delete
     char* composing_text; // UTF-8 encoded, null-terminated (needs to be freed)
     int is_processed;     // 0=false, 1=true
+    int delete_utf16_count; // Number of UTF-16 code units to delete (surrogate pairs count 2)
+    int composing_caret_utf16; // Caret position within composing_text, in UTF-16 code units
+    char* notification;   // Message a rule asked to show, or NULL; never set for dry runs (needs to be freed)
+    int delete_clamped;   // 0 = false, 1 = true; the engine cut a delete reaching past what the keyboard typed
 } ProcessKeyOutput;
 
 // Engine management
 EngineHandle* keymagic_engine_new(void);
+// Shared handles are only freed once every owner has freed them
 void keymagic_engine_free(EngineHandle* handle);
 
-// Keyboard loading
+// Engine sharing: while enabled, acquiring a keyboard that an engine in this
+// process already has loaded returns that engine with its refcount increased,
+// unless the file changed since that engine loaded it
+void keymagic_engine_set_sharing(int enabled);
+// Returns an engine with the keyboard loaded, or NULL; free with keymagic_engine_free
+EngineHandle* keymagic_engine_acquire(const char* km2_path);
+EngineHandle* keymagic_engine_acquire_w(const uint16_t* km2_path);
+
+// Diagnostics: live engine handles in this process
+int keymagic_debug_instance_count(void);
+// JSON: {"sharing":bool,"count":n,"instances":[{"id","thread_id","keyboard","hash","refs"}]}
+// Free with keymagic_free_string
+char* keymagic_debug_dump_instances(void);
+
+// Keyboard loading; each keyboard file loaded is reported to the shared
+// load log the GUI's diagnostics read
 KeyMagicResult keymagic_engine_load_keyboard(EngineHandle* handle, const char* km2_path);
+// Null-terminated UTF-16 path; handles long (\\?\) and non-UTF-8 paths
+KeyMagicResult keymagic_engine_load_keyboard_w(EngineHandle* handle, const uint16_t* km2_path);
 KeyMagicResult keymagic_engine_load_keyboard_from_memory(
     EngineHandle* handle, 
     const uint8_t* km2_data, 
     size_t data_len
 );
+// Built-in keyboard that passes every key through (is_processed 0); load it
+// when the real keyboard fails so the handle always has an engine
+KeyMagicResult keymagic_engine_load_fallback(EngineHandle* handle);
+// "keyboard: reason" of the last failed load in this process, or NULL; kept
+// across later successful loads. Free with keymagic_free_string
+char* keymagic_engine_last_error(void);
+
+// Content hash (lowercase hex SHA-256, as the GUI records in the keyboard's
+// Hash value) of the file the engine loaded, or NULL; free with keymagic_free_string
+char* keymagic_engine_get_loaded_hash(EngineHandle* handle);
+// 1 if the keyboard file now hashes to something else and should be loaded
+// again; an empty expected hash is unknown and returns 0
+int keymagic_engine_needs_reload(EngineHandle* handle, const char* expected_hash);
 
 // Key processing
 KeyMagicResult keymagic_engine_process_key(
@@ -59,9 +94,242 @@ void keymagic_free_string(char* s);
 
 // Engine control
 KeyMagicResult keymagic_engine_reset(EngineHandle* handle);
+
+// How much keymagic_engine_reset_level clears; each level includes the ones before
+typedef enum {
+    // Composing text, undo history and emitted count; states for the next key
+    // survive (caret moved within the same field)
+    KeyMagicResetLevel_Composing = 0,
+    // Also the states (focus moved to another field)
+    KeyMagicResetLevel_Context = 1,
+    // Also the last matched rules; same as keymagic_engine_reset (keyboard switch)
+    KeyMagicResetLevel_Full = 2,
+} KeyMagicResetLevel;
+KeyMagicResult keymagic_engine_reset_level(EngineHandle* handle, int level);
 char* keymagic_engine_get_composition(EngineHandle* handle);
+// Returns the composing text (as emitted) and resets the engine in one step,
+// for committing before the text service stands down; free with keymagic_free_string
+char* keymagic_engine_flush(EngineHandle* handle);
+// UTF-16 composition and caret (in UTF-16 units). Returns the required buffer
+// length including the NUL terminator, or 0 on error. A buffer that is too
+// small receives as many whole characters as fit, NUL-terminated.
+size_t keymagic_engine_get_composition_ex(
+    EngineHandle* handle,
+    uint16_t* out_buf,
+    size_t buf_len,
+    int* out_caret_index
+);
 KeyMagicResult keymagic_engine_set_composition(EngineHandle* handle, const char* text);
 
+// Output transform (0=None/Unicode, 1=Zawgyi); must be set again after loading a keyboard
+KeyMagicResult keymagic_engine_set_output_transform(EngineHandle* handle, int transform_id);
+
+// Named rule groups; toggles must be set again after loading a keyboard.
+// set_group_enabled returns ErrorInvalidParameter for an unknown group.
+KeyMagicResult keymagic_engine_set_group_enabled(EngineHandle* handle, const char* name, int enabled);
+int keymagic_engine_get_rule_group_count(EngineHandle* handle);  // -1 on error
+char* keymagic_engine_get_rule_group_name(EngineHandle* handle, int index);  // free with keymagic_free_string
+
+// Rule match statistics for diagnosing slow keyboards; off by default.
+// Enabling clears the counts. get_rule_histogram writes the most matched
+// rules, hottest first, and returns how many, or -1 while disabled.
+typedef struct {
+    int rule;       // rule index in source order
+    uint32_t hits;
+} RuleHistogramEntry;
+KeyMagicResult keymagic_engine_set_rule_histogram(EngineHandle* handle, int enabled);
+int keymagic_engine_get_rule_histogram(EngineHandle* handle, RuleHistogramEntry* out_entries, int max_entries, uint64_t* out_keys, uint64_t* out_scanned);
+
+// Suggestions for the composing text from the engine's suggestion provider,
+// refreshed by every key. get_suggestions returns out_count UTF-8 strings,
+// best first, or NULL when there are none; free with keymagic_free_string_array.
+// apply_suggestion fills output like a key and returns ErrorInvalidParameter
+// for an index with no suggestion. set_recent_suggestions (engines built with
+// the recent-suggestions feature) suggests words committed earlier.
+char** keymagic_engine_get_suggestions(EngineHandle* handle, int* out_count);
+void keymagic_free_string_array(char** strings, int count);
+KeyMagicResult keymagic_engine_apply_suggestion(EngineHandle* handle, int index, ProcessKeyOutput* output);
+KeyMagicResult keymagic_engine_set_recent_suggestions(EngineHandle* handle, int enabled);
+
+// Layout option overrides ("track_caps", "auto_bksp", "eat", "pos_based",
+// "right_alt", "case_insensitive_ascii"); must be set again after loading a keyboard. Returns
+// ErrorInvalidParameter for an unknown option name.
+KeyMagicResult keymagic_engine_set_layout_option(EngineHandle* handle, const char* name, int value);
+
+// Passthrough keys (Windows VK codes) always reach the application without
+// matching rules; must be set again after loading a keyboard. With
+// commit_composing set, a passthrough key first commits the composing text.
+#define KEYMAGIC_PASSTHROUGH_NONE   0
+#define KEYMAGIC_PASSTHROUGH        1
+#define KEYMAGIC_PASSTHROUGH_COMMIT 2  // process the key to end the composition, then pass it on
+KeyMagicResult keymagic_engine_set_passthrough_keys(EngineHandle* handle, const int* vk_codes, int count, int commit_composing);
+int keymagic_engine_check_passthrough(EngineHandle* handle, int vk_code);
+
+// Whether the composition must go on after a key, checked without processing
+// it. Unless the key is a prefix of a longer rule, its result can be committed
+// at once. Invalid handles and missing keyboards report a prefix.
+#define KEYMAGIC_PREFIX_NO                  0
+#define KEYMAGIC_PREFIX_COMPLETE_MATCH_ONLY 1  // a rule matches, nothing can follow it
+#define KEYMAGIC_PREFIX_OF_LONGER           2
+int keymagic_engine_is_prefix(EngineHandle* handle, int key_code, char character,
+                              int shift, int ctrl, int alt, int caps_lock);
+int keymagic_engine_is_prefix_win(EngineHandle* handle, int vk_code, char character,
+                                  int shift, int ctrl, int alt, int caps_lock);
+// Why a key does what it does, checked without processing it. JSON:
+// {"outcome":{"kind",...},"rules_for_key":n,"candidates":[{"rule","text",
+// "for_key","element","failure":{"kind",...}}]}; NULL for an invalid handle
+// or when no keyboard is loaded. Free with keymagic_free_string
+char* keymagic_engine_explain_key(EngineHandle* handle, int key_code, char character,
+                                  int shift, int ctrl, int alt, int caps_lock);
+char* keymagic_engine_explain_key_win(EngineHandle* handle, int vk_code, char character,
+                                      int shift, int ctrl, int alt, int caps_lock);
+// Windows VK code for a key name such as "VK_ESCAPE" or "F1", or 0 if unknown
+int keymagic_vk_from_name(const char* name);
+
+// Input event recorder for bug reports. The GUI owns the shared ring and
+// turns recording on; hosts append one record per processed key. Records hold
+// no text unless the GUI enabled verbose mode. Returns the record's sequence
+// number (log it to correlate traces), or 0 when not recording.
+typedef struct RecorderHandle RecorderHandle;
+RecorderHandle* keymagic_recorder_open(void);
+void keymagic_recorder_free(RecorderHandle* handle);
+uint64_t keymagic_recorder_record(
+    RecorderHandle* handle,
+    const uint16_t* process_name,
+    int vk_code,
+    int shift,
+    int ctrl,
+    int alt,
+    int caps_lock,
+    const ProcessKeyOutput* output
+);
+
+// Input mode of a host (1=composition, 0=direct). Host lists are arrays of
+// null-terminated UTF-16 patterns, matched ignoring case, with * and ?
+// wildcards; the most specific entry wins. Hosts nothing matches get
+// default_composition. The result is published for the GUI.
+int keymagic_resolve_input_mode_w(
+    const uint16_t* process_name,
+    const uint16_t* const* composition_hosts,
+    int composition_count,
+    const uint16_t* const* direct_hosts,
+    int direct_count,
+    int default_composition
+);
+
+// Single-key shortcuts of the host application, from entries such as
+// "chrome.exe=VK_KEY_C,VK_KEY_J" (host patterns as above). They pass through
+// while nothing is composed, reported by keymagic_engine_check_passthrough;
+// must be set again after loading a keyboard.
+KeyMagicResult keymagic_engine_set_shortcut_keys_w(
+    EngineHandle* handle,
+    const uint16_t* process_name,
+    const uint16_t* const* entries,
+    int count
+);
+
+// Key processing switch set by the GUI. After the registry update event,
+// call observe: on KEYMAGIC_PROCESSING_FLUSH, commit the composition first,
+// then pass the request to finish_disable, which stands down and
+// acknowledges it so the GUI stops waiting. is_enabled stays 1 until then.
+// is_enabled is also 0 while the GUI is gone (its heartbeat went stale and
+// its mutex is released, as after a crash); it checks every few seconds.
+#define KEYMAGIC_PROCESSING_NONE   0
+#define KEYMAGIC_PROCESSING_ENABLE 1
+#define KEYMAGIC_PROCESSING_FLUSH  2
+typedef struct ProcessingHandle ProcessingHandle;
+ProcessingHandle* keymagic_processing_open(void);
+void keymagic_processing_free(ProcessingHandle* handle);
+int keymagic_processing_observe(ProcessingHandle* handle, uint64_t* request);
+int keymagic_processing_finish_disable(ProcessingHandle* handle, uint64_t request);
+int keymagic_processing_is_enabled(ProcessingHandle* handle);
+
+// Committed text for the GUI's re-insert history. Hosts report each commit
+// (UTF-8); nothing is kept unless the GUI turned the history on. Returns the
+// commit's sequence number, or 0 when not reported. clear asks the GUI to
+// forget its history, e.g. from the tray.
+typedef struct CommitLogHandle CommitLogHandle;
+CommitLogHandle* keymagic_commit_log_open(void);
+void keymagic_commit_log_free(CommitLogHandle* handle);
+uint64_t keymagic_commit_log_report(CommitLogHandle* handle, const char* text);
+void keymagic_commit_log_clear(CommitLogHandle* handle);
+
+// Composing text kept for crash recovery. Hosts report their composing text
+// (UTF-8) with the process name after each key and clear it on commit;
+// nothing is kept unless the user opted in, and text under 8 characters is
+// ignored. report_w returns 1 when the text was kept.
+typedef struct CompositionJournalHandle CompositionJournalHandle;
+CompositionJournalHandle* keymagic_composition_journal_open(void);
+void keymagic_composition_journal_free(CompositionJournalHandle* handle);
+int keymagic_composition_journal_report_w(CompositionJournalHandle* handle, const uint16_t* process_name, const char* text);
+void keymagic_composition_journal_clear(CompositionJournalHandle* handle);
+
+// Key press counts for the GUI's layout heatmap. Hosts count the virtual key
+// of each key they process with the active keyboard's id; nothing is counted
+// unless the user opted in, or while set_suppressed(1) is in effect (a
+// password field has focus). record_w returns 1 when the key was counted.
+typedef struct KeyStatsHandle KeyStatsHandle;
+KeyStatsHandle* keymagic_key_stats_open(void);
+void keymagic_key_stats_free(KeyStatsHandle* handle);
+int keymagic_key_stats_is_enabled(KeyStatsHandle* handle);
+void keymagic_key_stats_set_suppressed(KeyStatsHandle* handle, int suppressed);
+int keymagic_key_stats_record_w(KeyStatsHandle* handle, const uint16_t* keyboard_id, int vk_code);
+
+// Input latency of the host, for the GUI's diagnostics. should_sample counts
+// a key and returns 1 for the one in 50 to time; record adds the
+// microseconds from the key reaching the host to its output being in the
+// document. Only histogram counts are kept.
+typedef struct InputLatencyHandle InputLatencyHandle;
+InputLatencyHandle* keymagic_input_latency_open(void);
+void keymagic_input_latency_free(InputLatencyHandle* handle);
+int keymagic_input_latency_should_sample(InputLatencyHandle* handle);
+void keymagic_input_latency_record(InputLatencyHandle* handle, uint64_t micros);
+
+// Shortcuts the GUI watches for in place of a system-wide hotkey. find_win
+// returns the action of the shortcut a key the keyboard left alone fires, or
+// 0; with *swallow set the host keeps the key from the application and
+// commits its composing text first. report tells the GUI the shortcut fired.
+typedef struct ShortcutWatchHandle ShortcutWatchHandle;
+ShortcutWatchHandle* keymagic_shortcut_watch_open(void);
+void keymagic_shortcut_watch_free(ShortcutWatchHandle* handle);
+unsigned int keymagic_shortcut_watch_find_win(ShortcutWatchHandle* handle, int vk_code, int shift, int ctrl, int alt, int* swallow);
+uint64_t keymagic_shortcut_watch_report(ShortcutWatchHandle* handle, unsigned int action);
+
+// Compositions per input context. Pass any id unique among the process's
+// live contexts (e.g. the ITfContext pointer). focused saves the engine's
+// composition for the context that had the focus and restores the one of
+// context_id (returns 1) or resets the engine (0); calling it again for the
+// focused context does nothing. destroyed keeps the composition of the
+// focused context and resets the engine. A context created within 500ms of
+// another one of the process being destroyed, in either order, takes over
+// its composition (created/destroyed return 1), so an Electron renderer
+// reload keeps what was typed. handle may be null for destroyed.
+int keymagic_context_created(uint64_t context_id);
+int keymagic_context_destroyed(EngineHandle* handle, uint64_t context_id);
+int keymagic_context_focused(EngineHandle* handle, uint64_t context_id);
+
+// Stray characters of eaten keys, for hosts whose framework translates a key
+// before the text service eats it. record_eaten takes each key the host ate;
+// should_drop returns 1 for a WM_CHAR/WM_UNICHAR of such a key within 100ms,
+// once per key. scan_code is bits 16-24 of lParam, time_ms a tick count.
+// host_listed checks the CharSuppressionHosts opt-in list; hook_installed
+// counts a message hook for the GUI's diagnostics (detected: 1 when the host
+// found the doubling itself).
+void keymagic_char_filter_record_eaten(unsigned int thread_id, int vk_code, int scan_code, uint64_t time_ms);
+int keymagic_char_filter_should_drop(unsigned int thread_id, int scan_code, uint64_t time_ms);
+void keymagic_char_filter_forget_thread(unsigned int thread_id);
+int keymagic_char_filter_host_listed_w(const uint16_t* process_name, const uint16_t** hosts, int count);
+void keymagic_char_filter_hook_installed(int detected);
+
+// Monochrome tray icons. Pixels are BGRA with straight alpha, rows top to
+// bottom, size * size * 4 bytes; sizes up to 256. render_icon draws the
+// keyboard glyph for a light or dark taskbar, with the disabled badge when
+// processing_enabled is 0. draw_badge marks any icon in place.
+#define KEYMAGIC_BADGE_DOT   1
+#define KEYMAGIC_BADGE_SLASH 2
+KeyMagicResult keymagic_tray_render_icon(int size, int light_theme, int processing_enabled, uint8_t* out_bgra, size_t buf_len);
+KeyMagicResult keymagic_tray_draw_badge(uint8_t* bgra, int size, int light_theme, int badge);
+
 // Version info
 const char* keymagic_get_version(void);
 
@@ -98,7 +366,38 @@ typedef struct {
     int meta;           // 0 or 1
 } HotkeyInfo;
 
+// Returns 0 for double-tap hotkeys, which are not key combinations
 int keymagic_parse_hotkey(const char* hotkey_str, HotkeyInfo* info);
+// Double-tap hotkeys ("Shift Shift", "double:LShift"): returns 1 and the
+// Windows VK code of the modifier, 0 for other hotkeys
+int keymagic_parse_double_tap(const char* hotkey_str, int* vk_code);
+
+// Double-tap detector fed with every key press and release. key_down returns
+// the watched VK code when the press completes a double-tap, else 0. Pass
+// side-specific codes (VK_LSHIFT, ...) where known; window_ms 0 is 300ms.
+typedef struct DoubleTapHandle DoubleTapHandle;
+DoubleTapHandle* keymagic_double_tap_new(unsigned int window_ms);
+void keymagic_double_tap_free(DoubleTapHandle* handle);
+void keymagic_double_tap_set_keys(DoubleTapHandle* handle, const int* vk_codes, size_t count);
+int keymagic_double_tap_key_down(DoubleTapHandle* handle, int vk_code, uint64_t time_ms);
+void keymagic_double_tap_key_up(DoubleTapHandle* handle, int vk_code, uint64_t time_ms);
+void keymagic_double_tap_reset(DoubleTapHandle* handle);
+
+// dwExtraInfo of every key event KeyMagic injects with SendInput ("KMAG").
+// Key sinks and hooks skip events carrying it, for key processing as well
+// as hotkeys and double-taps.
+#define KEYMAGIC_INJECTED_INPUT_SIGNATURE 0x4B4D4147
+// Returns 1 if an event with this dwExtraInfo was injected by KeyMagic
+int keymagic_is_injected_input(uintptr_t extra_info);
+
+// Breaks keyboard switch loops: check returns 0 to switch, 1 when this
+// switch starts a cooldown (warn the user), 2 while switches are
+// suppressed. 0 arguments to new use the defaults (5 switches within
+// 1000ms, 2000ms cooldown).
+typedef struct SwitchGuardHandle SwitchGuardHandle;
+SwitchGuardHandle* keymagic_switch_guard_new(unsigned int max_switches, unsigned int window_ms, unsigned int cooldown_ms);
+void keymagic_switch_guard_free(SwitchGuardHandle* handle);
+int keymagic_switch_guard_check(SwitchGuardHandle* handle, uint64_t time_ms);
 
 // KM2 file loading and metadata access
 typedef struct Km2FileHandle Km2FileHandle;
@@ -106,6 +405,9 @@ typedef struct Km2FileHandle Km2FileHandle;
 // Load a KM2 file
 Km2FileHandle* keymagic_km2_load(const char* path);
 
+// Load a KM2 file from a memory buffer
+Km2FileHandle* keymagic_km2_load_from_memory(const uint8_t* km2_data, size_t data_len);
+
 // Free a loaded KM2 file
 void keymagic_km2_free(Km2FileHandle* handle);
 
@@ -115,9 +417,40 @@ char* keymagic_km2_get_name(Km2FileHandle* handle);
 // Get keyboard description (returns NULL if not defined)
 char* keymagic_km2_get_description(Km2FileHandle* handle);
 
+// Get the declared font family (returns NULL if not declared)
+char* keymagic_km2_get_font_family(Km2FileHandle* handle);
+
 // Get hotkey string (returns NULL if not defined)
 char* keymagic_km2_get_hotkey(Km2FileHandle* handle);
 
+// Get the declared language tags, comma separated (returns NULL if none)
+char* keymagic_km2_get_locales(Km2FileHandle* handle);
+
+// Get the declared preview text (returns NULL if not declared)
+char* keymagic_km2_get_sample_text(Km2FileHandle* handle);
+
+// Load a KM2 file into an engine; the engine keeps its own copy
+KeyMagicResult keymagic_engine_load_km2(EngineHandle* handle, const Km2FileHandle* km2);
+
+// Format version, table sizes and layout options of a KM2 file
+typedef struct {
+    int major_version;
+    int minor_version;
+    int string_count;
+    int info_count;
+    int rule_count;
+    int track_caps;
+    int auto_bksp;
+    int eat;
+    int pos_based;
+    int right_alt;
+} Km2Info;
+
+KeyMagicResult keymagic_km2_get_info(Km2FileHandle* handle, Km2Info* info);
+// Layout option by name, including those not in Km2Info
+// ("case_insensitive_ascii"): 1 if on, 0 if off, -1 for an unknown name
+int keymagic_km2_get_layout_option(Km2FileHandle* handle, const char* name);
+
 // Get icon data from KM2 file
 // If buffer is NULL, returns the required buffer size
 // If buffer is not NULL, copies icon data to buffer and returns actual size copied
@@ -24,6 +24,7 @@ constexpr UINT WM_TRAYICON = WM_USER + 1;
 constexpr UINT WM_PIPE_MESSAGE = WM_USER + 2;
 constexpr UINT WM_MENU_SHOWN = WM_USER + 3;
 constexpr UINT WM_MENU_DISMISSED = WM_USER + 4;
+constexpr UINT WM_APPEARANCE_CHANGED = WM_USER + 5;
 
 // Timer IDs
 constexpr UINT TIMER_HIDE_DELAY = 1;
@@ -37,5 +38,8 @@ constexpr UINT TRAY_ICON_ID = 1;
 // Default icon size
 constexpr int DEFAULT_ICON_SIZE = 16;
 
+// Taskbar theme (SystemUsesLightTheme)
+constexpr const wchar_t* PERSONALIZE_PATH = L"Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize";
+
 // Using namespace for shared utilities
 using namespace KeyMagicUtils;
\ No newline at end of file
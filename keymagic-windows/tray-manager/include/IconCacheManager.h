@@ -7,6 +7,12 @@ namespace Gdiplus {
     class GdiplusStartupInput;
 }
 
+// Taskbar theme and key processing state an icon is drawn for
+struct IconStyle {
+    bool lightTheme;
+    bool processingEnabled;
+};
+
 class IconCacheManager {
 public:
     IconCacheManager();
@@ -15,8 +21,12 @@ public:
     // Initialize the cache directory
     bool Initialize();
     
-    // Get icon for keyboard (loads from cache or extracts)
-    HICON GetIcon(const std::wstring& keyboardId, const std::wstring& km2Path, int size);
+    // Get icon for keyboard (loads from cache or extracts), badged while
+    // key processing is disabled; nullptr if the keyboard has no icon
+    HICON GetIcon(const std::wstring& keyboardId, const std::wstring& km2Path, int size, const IconStyle& style);
+    
+    // Get the monochrome keyboard glyph for keyboards without an icon
+    HICON GetGlyphIcon(int size, const IconStyle& style);
     
     // Clear cache for a specific keyboard
     void ClearCache(const std::wstring& keyboardId);
@@ -29,13 +39,19 @@ private:
     bool ExtractIcon(const std::wstring& km2Path, std::vector<BYTE>& iconData);
     
     // Convert image data (PNG, BMP, etc.) to HICON using GDI+
-    HICON ImageDataToIcon(const std::vector<BYTE>& imageData, int size);
+    HICON ImageDataToIcon(const std::vector<BYTE>& imageData, int size, const IconStyle& style);
+    
+    // Create an HICON from size * size BGRA pixels with straight alpha
+    static HICON CreateIconFromBgra(const std::vector<BYTE>& pixels, int size);
+    
+    // In-memory cache key for an icon variant
+    static std::wstring GetCacheKey(const std::wstring& keyboardId, int size, const IconStyle& style);
     
     // Save icon data to cache
     bool SaveToCache(const std::wstring& keyboardId, int size, const std::vector<BYTE>& iconData);
     
     // Load icon from cache
-    HICON LoadFromCache(const std::wstring& keyboardId, int size);
+    HICON LoadFromCache(const std::wstring& keyboardId, int size, const IconStyle& style);
     
     // Get cache file path
     std::wstring GetCachePath(const std::wstring& keyboardId, int size);
@@ -54,6 +54,11 @@ private:
     void SimulateKeyboardLayout();
     void InitializeDefaultLayout();
     
+    // Fonts
+    void CreateFonts();
+    static bool IsFontInstalled(const std::wstring& family);
+    static std::wstring ResolvePreviewFont(const std::wstring& declaredFont);
+    
     // Rendering
     void OnPaint(HDC hdc);
     void DrawKeyboard(Gdiplus::Graphics& graphics);
@@ -87,6 +92,10 @@ private:
     std::unique_ptr<Gdiplus::Font> m_fontSmall;
     std::unique_ptr<Gdiplus::Font> m_fontLabel;
     
+    // Font the keyboard declares, and the family its keys are drawn with
+    std::wstring m_declaredFont;
+    std::wstring m_fontFamily;
+    
     // Keyboard data
     std::wstring m_keyboardName;
     std::wstring m_keyboardPath;
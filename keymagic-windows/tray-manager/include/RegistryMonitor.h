@@ -11,8 +11,9 @@ public:
     RegistryMonitor();
     ~RegistryMonitor();
     
-    // Start monitoring registry changes
-    bool Start(ChangeCallback callback);
+    // Start monitoring registry changes. appearanceCallback runs when the
+    // taskbar theme or the key processing setting changes.
+    bool Start(ChangeCallback callback, ChangeCallback appearanceCallback = nullptr);
     
     // Stop monitoring
     void Stop();
@@ -31,6 +32,12 @@ public:
     
     // Notify all TIPs of registry change
     void NotifyTipsOfChange();
+    
+    // Whether the taskbar uses the light theme
+    static bool IsLightTaskbar();
+    
+    // Whether key processing is enabled in the settings
+    static bool IsKeyProcessingEnabled();
 
 private:
     // Monitor thread
@@ -38,14 +45,20 @@ private:
     
     // Ensure registry structure exists
     void EnsureRegistryStructure();
+    
+    // Register for the next change of the appearance keys
+    void WatchAppearanceKeys();
 
 private:
     HKEY m_hKeyboardsKey;
     HKEY m_hSettingsKey;
+    HKEY m_hThemeKey;
     HANDLE m_hRegChangeEvent;
+    HANDLE m_hAppearanceChangeEvent;
     HANDLE m_hGlobalUpdateEvent;
     HANDLE m_hStopEvent;
     std::thread m_monitorThread;
     std::atomic<bool> m_running;
     ChangeCallback m_callback;
+    ChangeCallback m_appearanceCallback;
 };
\ No newline at end of file
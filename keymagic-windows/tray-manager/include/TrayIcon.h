@@ -73,4 +73,5 @@ private:
     static constexpr UINT IDM_EXIT = 999;
     static constexpr UINT IDM_ABOUT = 998;
     static constexpr UINT IDM_SETTINGS = 997;
+    static constexpr UINT IDM_CLEAR_HISTORY = 996;
 };
\ No newline at end of file
@@ -43,18 +43,18 @@ bool IconCacheManager::Initialize() {
     return EnsureGdiPlusInitialized();
 }
 
-HICON IconCacheManager::GetIcon(const std::wstring& keyboardId, const std::wstring& km2Path, int size) {
+HICON IconCacheManager::GetIcon(const std::wstring& keyboardId, const std::wstring& km2Path, int size, const IconStyle& style) {
     std::lock_guard<std::mutex> lock(m_cacheMutex);
     
     // Check in-memory cache first
-    std::wstring cacheKey = keyboardId + L"_" + std::to_wstring(size);
+    std::wstring cacheKey = GetCacheKey(keyboardId, size, style);
     auto it = m_iconCache.find(cacheKey);
     if (it != m_iconCache.end()) {
         return it->second;
     }
     
     // Try to load from disk cache
-    HICON hIcon = LoadFromCache(keyboardId, size);
+    HICON hIcon = LoadFromCache(keyboardId, size, style);
     if (hIcon) {
         m_iconCache[cacheKey] = hIcon;
         return hIcon;
@@ -64,7 +64,7 @@ HICON IconCacheManager::GetIcon(const std::wstring& keyboardId, const std::wstri
     std::vector<BYTE> iconData;
     if (ExtractIcon(km2Path, iconData) && !iconData.empty()) {
         // Convert to requested size and create HICON
-        hIcon = ImageDataToIcon(iconData, size);
+        hIcon = ImageDataToIcon(iconData, size, style);
         if (hIcon) {
             // Save to cache for future use
             SaveToCache(keyboardId, size, iconData);
@@ -76,6 +76,29 @@ HICON IconCacheManager::GetIcon(const std::wstring& keyboardId, const std::wstri
     return nullptr;
 }
 
+HICON IconCacheManager::GetGlyphIcon(int size, const IconStyle& style) {
+    std::lock_guard<std::mutex> lock(m_cacheMutex);
+    
+    // Keyboard IDs never start with '*'
+    std::wstring cacheKey = GetCacheKey(L"*glyph", size, style);
+    auto it = m_iconCache.find(cacheKey);
+    if (it != m_iconCache.end()) {
+        return it->second;
+    }
+    
+    std::vector<BYTE> pixels(static_cast<size_t>(size) * size * 4);
+    if (keymagic_tray_render_icon(size, style.lightTheme, style.processingEnabled,
+                                  pixels.data(), pixels.size()) != KeyMagicResult_Success) {
+        return nullptr;
+    }
+    
+    HICON hIcon = CreateIconFromBgra(pixels, size);
+    if (hIcon) {
+        m_iconCache[cacheKey] = hIcon;
+    }
+    return hIcon;
+}
+
 void IconCacheManager::ClearCache(const std::wstring& keyboardId) {
     std::lock_guard<std::mutex> lock(m_cacheMutex);
     
@@ -158,7 +181,7 @@ bool IconCacheManager::ExtractIcon(const std::wstring& km2Path, std::vector<BYTE
     return true;
 }
 
-HICON IconCacheManager::ImageDataToIcon(const std::vector<BYTE>& imageData, int size) {
+HICON IconCacheManager::ImageDataToIcon(const std::vector<BYTE>& imageData, int size, const IconStyle& style) {
     if (!EnsureGdiPlusInitialized()) {
         return nullptr;
     }
@@ -193,24 +216,84 @@ HICON IconCacheManager::ImageDataToIcon(const std::vector<BYTE>& imageData, int
         return nullptr;
     }
     
-    // Scale to requested size if needed
-    HICON hIcon = nullptr;
-    if (pBitmap->GetWidth() != size || pBitmap->GetHeight() != size) {
-        Bitmap* pResized = new Bitmap(size, size, PixelFormat32bppARGB);
-        Graphics graphics(pResized);
+    // Draw at the requested size into a bitmap whose pixels we can badge
+    Bitmap canvas(size, size, PixelFormat32bppARGB);
+    {
+        Graphics graphics(&canvas);
+        graphics.Clear(Color(0, 0, 0, 0));
         graphics.SetInterpolationMode(InterpolationModeHighQualityBicubic);
         graphics.DrawImage(pBitmap, 0, 0, size, size);
-        
-        pResized->GetHICON(&hIcon);
-        delete pResized;
-    } else {
-        pBitmap->GetHICON(&hIcon);
     }
-    
     delete pBitmap;
+    
+    Rect rect(0, 0, size, size);
+    BitmapData data;
+    if (canvas.LockBits(&rect, ImageLockModeRead, PixelFormat32bppARGB, &data) != Ok) {
+        return nullptr;
+    }
+    
+    std::vector<BYTE> pixels(static_cast<size_t>(size) * size * 4);
+    for (int y = 0; y < size; y++) {
+        memcpy(&pixels[static_cast<size_t>(y) * size * 4],
+               static_cast<const BYTE*>(data.Scan0) + static_cast<ptrdiff_t>(y) * data.Stride,
+               static_cast<size_t>(size) * 4);
+    }
+    canvas.UnlockBits(&data);
+    
+    if (!style.processingEnabled) {
+        keymagic_tray_draw_badge(pixels.data(), size, style.lightTheme, KEYMAGIC_BADGE_SLASH);
+    }
+    
+    return CreateIconFromBgra(pixels, size);
+}
+
+HICON IconCacheManager::CreateIconFromBgra(const std::vector<BYTE>& pixels, int size) {
+    // Top-down 32bpp DIB; the alpha channel is the icon's transparency
+    BITMAPV5HEADER header = {};
+    header.bV5Size = sizeof(BITMAPV5HEADER);
+    header.bV5Width = size;
+    header.bV5Height = -size;
+    header.bV5Planes = 1;
+    header.bV5BitCount = 32;
+    header.bV5Compression = BI_BITFIELDS;
+    header.bV5RedMask = 0x00FF0000;
+    header.bV5GreenMask = 0x0000FF00;
+    header.bV5BlueMask = 0x000000FF;
+    header.bV5AlphaMask = 0xFF000000;
+    
+    void* bits = nullptr;
+    HDC hDC = GetDC(nullptr);
+    HBITMAP hColor = CreateDIBSection(hDC, reinterpret_cast<BITMAPINFO*>(&header), DIB_RGB_COLORS, &bits, nullptr, 0);
+    ReleaseDC(nullptr, hDC);
+    if (!hColor) {
+        return nullptr;
+    }
+    memcpy(bits, pixels.data(), pixels.size());
+    
+    // The mask is ignored for 32bpp icons with alpha but must exist
+    std::vector<BYTE> maskBits(static_cast<size_t>((size + 15) / 16) * 2 * size, 0);
+    HBITMAP hMask = CreateBitmap(size, size, 1, 1, maskBits.data());
+    
+    ICONINFO iconInfo = {};
+    iconInfo.fIcon = TRUE;
+    iconInfo.hbmColor = hColor;
+    iconInfo.hbmMask = hMask;
+    HICON hIcon = hMask ? CreateIconIndirect(&iconInfo) : nullptr;
+    
+    // CreateIconIndirect copies the bitmaps
+    DeleteObject(hColor);
+    if (hMask) {
+        DeleteObject(hMask);
+    }
     return hIcon;
 }
 
+std::wstring IconCacheManager::GetCacheKey(const std::wstring& keyboardId, int size, const IconStyle& style) {
+    return keyboardId + L"_" + std::to_wstring(size) +
+        (style.lightTheme ? L"_light" : L"_dark") +
+        (style.processingEnabled ? L"" : L"_disabled");
+}
+
 bool IconCacheManager::SaveToCache(const std::wstring& keyboardId, int size, const std::vector<BYTE>& iconData) {
     std::wstring cachePath = GetCachePath(keyboardId, size);
     
@@ -223,7 +306,7 @@ bool IconCacheManager::SaveToCache(const std::wstring& keyboardId, int size, con
     return file.good();
 }
 
-HICON IconCacheManager::LoadFromCache(const std::wstring& keyboardId, int size) {
+HICON IconCacheManager::LoadFromCache(const std::wstring& keyboardId, int size, const IconStyle& style) {
     std::wstring cachePath = GetCachePath(keyboardId, size);
     
     // Check if file exists
@@ -245,7 +328,7 @@ HICON IconCacheManager::LoadFromCache(const std::wstring& keyboardId, int size)
         return nullptr;
     }
     
-    return ImageDataToIcon(iconData, size);
+    return ImageDataToIcon(iconData, size, style);
 }
 
 std::wstring IconCacheManager::GetCachePath(const std::wstring& keyboardId, int size) {
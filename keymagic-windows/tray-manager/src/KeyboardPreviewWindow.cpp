@@ -16,7 +16,9 @@ extern "C" {
     void* keymagic_engine_new();
     void keymagic_engine_free(void* handle);
     int keymagic_engine_load_keyboard(void* handle, const char* km2_path);
+    int keymagic_engine_load_keyboard_w(void* handle, const uint16_t* km2_path);
     void keymagic_engine_reset(void* handle);
+    int keymagic_engine_reset_level(void* handle, int level);
     
     // Process key output structure
     struct ProcessKeyOutput {
@@ -25,6 +27,10 @@ extern "C" {
         int delete_count;
         char* composing_text;
         int is_processed;
+        int delete_utf16_count;
+        int composing_caret_utf16;
+        char* notification;
+        int delete_clamped;
     };
     
     // Key processing
@@ -46,9 +52,17 @@ extern "C" {
     void* keymagic_km2_load(const char* path);
     void keymagic_km2_free(void* handle);
     char* keymagic_km2_get_name(void* handle);
+    char* keymagic_km2_get_font_family(void* handle);
     void keymagic_free_string(char* str);
 }
 
+// Fonts tried after the one the keyboard declares; matches FALLBACK_FONTS in
+// the GUI's preview_font.rs
+static const wchar_t* FALLBACK_FONTS[] = {
+    L"Myanmar Text",
+    L"Segoe UI"
+};
+
 KeyboardPreviewWindow* KeyboardPreviewWindow::s_instance = nullptr;
 const wchar_t* KeyboardPreviewWindow::WINDOW_CLASS_NAME = L"KeyMagicPreviewWindow";
 
@@ -105,17 +119,9 @@ bool KeyboardPreviewWindow::Initialize(HINSTANCE hInstance) {
         return false;
     }
     
-    // Create fonts for rendering with scaling
-    // Use a font that supports complex scripts (e.g., Segoe UI, Arial Unicode MS, or Noto Sans)
-    m_fontNormal = std::make_unique<Gdiplus::Font>(L"Segoe UI", 14.0f * m_scale, Gdiplus::FontStyleRegular);
-    m_fontSmall = std::make_unique<Gdiplus::Font>(L"Segoe UI", 10.0f * m_scale, Gdiplus::FontStyleRegular);
-    m_fontLabel = std::make_unique<Gdiplus::Font>(L"Segoe UI", 9.0f * m_scale, Gdiplus::FontStyleBold);
-    
-    // If Segoe UI doesn't support the script, try fallback fonts
-    if (m_fontNormal->GetLastStatus() != Gdiplus::Ok) {
-        m_fontNormal = std::make_unique<Gdiplus::Font>(L"Arial Unicode MS", 14.0f * m_scale, Gdiplus::FontStyleRegular);
-        m_fontSmall = std::make_unique<Gdiplus::Font>(L"Arial Unicode MS", 10.0f * m_scale, Gdiplus::FontStyleRegular);
-    }
+    // Create fonts for rendering with scaling; no keyboard is loaded yet
+    m_fontFamily = ResolvePreviewFont(L"");
+    CreateFonts();
     
     // Create KeyMagic engine for simulation
     m_engineHandle = keymagic_engine_new();
@@ -131,6 +137,7 @@ void KeyboardPreviewWindow::Show(const POINT& anchorPoint, const std::wstring& k
     
     m_keyboardPath = keyboardPath;
     m_lastTrayIconPos = anchorPoint;
+    m_declaredFont.clear();
     
     // Load keyboard layout
     if (!LoadKeyboardLayout(keyboardPath)) {
@@ -141,6 +148,13 @@ void KeyboardPreviewWindow::Show(const POINT& anchorPoint, const std::wstring& k
         SimulateKeyboardLayout();
     }
     
+    // Draw the keys in the font this keyboard asks for
+    std::wstring fontFamily = ResolvePreviewFont(m_declaredFont);
+    if (fontFamily != m_fontFamily) {
+        m_fontFamily = fontFamily;
+        CreateFonts();
+    }
+    
     // Generate visual layout
     GenerateKeyboardLayout();
     
@@ -181,14 +195,40 @@ void KeyboardPreviewWindow::SetScale(float scale) {
                      SWP_NOMOVE | SWP_NOZORDER | SWP_NOACTIVATE);
         
         // Recreate fonts with scaled sizes
-        m_fontNormal = std::make_unique<Gdiplus::Font>(L"Segoe UI", 14.0f * m_scale, Gdiplus::FontStyleRegular);
-        m_fontSmall = std::make_unique<Gdiplus::Font>(L"Segoe UI", 10.0f * m_scale, Gdiplus::FontStyleRegular);
-        m_fontLabel = std::make_unique<Gdiplus::Font>(L"Segoe UI", 9.0f * m_scale, Gdiplus::FontStyleBold);
+        CreateFonts();
         
         InvalidateRect(m_hWnd, nullptr, TRUE);
     }
 }
 
+void KeyboardPreviewWindow::CreateFonts() {
+    m_fontNormal = std::make_unique<Gdiplus::Font>(m_fontFamily.c_str(), 14.0f * m_scale, Gdiplus::FontStyleRegular);
+    m_fontSmall = std::make_unique<Gdiplus::Font>(m_fontFamily.c_str(), 10.0f * m_scale, Gdiplus::FontStyleRegular);
+    // Modifier labels are Latin, so they keep the UI font
+    m_fontLabel = std::make_unique<Gdiplus::Font>(L"Segoe UI", 9.0f * m_scale, Gdiplus::FontStyleBold);
+}
+
+bool KeyboardPreviewWindow::IsFontInstalled(const std::wstring& family) {
+    if (family.empty()) {
+        return false;
+    }
+    // GDI+ reports FontFamilyNotFound for families that are not installed
+    Gdiplus::FontFamily fontFamily(family.c_str());
+    return fontFamily.GetLastStatus() == Gdiplus::Ok;
+}
+
+std::wstring KeyboardPreviewWindow::ResolvePreviewFont(const std::wstring& declaredFont) {
+    if (IsFontInstalled(declaredFont)) {
+        return declaredFont;
+    }
+    for (const auto& family : FALLBACK_FONTS) {
+        if (IsFontInstalled(family)) {
+            return family;
+        }
+    }
+    return L"Segoe UI";
+}
+
 bool KeyboardPreviewWindow::RegisterWindowClass() {
     WNDCLASSEXW wcex = {};
     wcex.cbSize = sizeof(WNDCLASSEXW);
@@ -258,8 +298,9 @@ bool KeyboardPreviewWindow::LoadKeyboardLayout(const std::wstring& keyboardPath)
         WideCharToMultiByte(CP_UTF8, 0, keyboardPath.c_str(), -1, &narrowPath[0], size_needed, nullptr, nullptr);
     }
     
-    // Load keyboard into engine
-    if (keymagic_engine_load_keyboard(m_engineHandle, narrowPath.c_str()) != 0) {
+    // Load keyboard into engine; the wide variant copes with long paths
+    if (keymagic_engine_load_keyboard_w(m_engineHandle,
+            reinterpret_cast<const uint16_t*>(keyboardPath.c_str())) != 0) {
         return false;
     }
     
@@ -278,6 +319,16 @@ bool KeyboardPreviewWindow::LoadKeyboardLayout(const std::wstring& keyboardPath)
         } else {
             m_keyboardName = L"KeyMagic Keyboard";
         }
+        
+        char* fontFamily = keymagic_km2_get_font_family(km2Handle);
+        if (fontFamily) {
+            int wsize = MultiByteToWideChar(CP_UTF8, 0, fontFamily, -1, nullptr, 0);
+            if (wsize > 0) {
+                m_declaredFont.resize(wsize - 1);
+                MultiByteToWideChar(CP_UTF8, 0, fontFamily, -1, &m_declaredFont[0], wsize);
+            }
+            keymagic_free_string(fontFamily);
+        }
         keymagic_km2_free(km2Handle);
     }
     
@@ -361,8 +412,8 @@ void KeyboardPreviewWindow::SimulateKeyboardLayout() {
         ProcessKeyOutput output;
         KeyInfo keyInfo;
         
-        // Test unshifted key
-        keymagic_engine_reset(m_engineHandle);
+        // Test unshifted key, each from an empty composition without states
+        keymagic_engine_reset_level(m_engineHandle, 1);  // Context
         int result = keymagic_engine_process_key_test_win(
             m_engineHandle,
             mapping.vkCode,
@@ -400,7 +451,7 @@ void KeyboardPreviewWindow::SimulateKeyboardLayout() {
         if (output.composing_text) keymagic_free_string(output.composing_text);
         
         // Test shifted key
-        keymagic_engine_reset(m_engineHandle);
+        keymagic_engine_reset_level(m_engineHandle, 1);  // Context
         result = keymagic_engine_process_key_test_win(
             m_engineHandle,
             mapping.vkCode,
@@ -654,6 +705,18 @@ void KeyboardPreviewWindow::OnPaint(HDC hdc) {
     Gdiplus::RectF titleRect(0, 0, Scale(BASE_WINDOW_WIDTH), Scale(BASE_TITLE_HEIGHT));
     graphics.DrawString(m_keyboardName.c_str(), -1, &titleFont, titleRect, &titleFormat, &titleBrush);
     
+    // The declared font is missing: say which one the keys are drawn with instead
+    if (!m_declaredFont.empty() && m_fontFamily != m_declaredFont) {
+        std::wstring hint = L"Install " + m_declaredFont + L" for best results (showing " + m_fontFamily + L")";
+        Gdiplus::Font hintFont(L"Segoe UI", 9.0f * m_scale, Gdiplus::FontStyleRegular);
+        Gdiplus::SolidBrush hintBrush(Gdiplus::Color(138, 109, 59));
+        Gdiplus::StringFormat hintFormat;
+        hintFormat.SetAlignment(Gdiplus::StringAlignmentFar);
+        hintFormat.SetLineAlignment(Gdiplus::StringAlignmentCenter);
+        Gdiplus::RectF hintRect(0, 0, Scale(BASE_WINDOW_WIDTH) - Scale(BASE_MARGIN), Scale(BASE_TITLE_HEIGHT));
+        graphics.DrawString(hint.c_str(), -1, &hintFont, hintRect, &hintFormat, &hintBrush);
+    }
+    
     // Draw keyboard
     DrawKeyboard(graphics);
 }
@@ -723,30 +786,11 @@ void KeyboardPreviewWindow::DrawKey(Gdiplus::Graphics& graphics, const VisualKey
 void KeyboardPreviewWindow::DrawTextWithComplexScript(Gdiplus::Graphics& graphics, const std::wstring& text,
                                                      const Gdiplus::RectF& rect, const Gdiplus::StringFormat& format,
                                                      const Gdiplus::Brush& brush, float fontSize) {
-    // Try multiple fonts to ensure complex script support
-    const wchar_t* fontFamilies[] = {
-        L"Segoe UI",           // Default Windows font
-        L"Arial Unicode MS",   // Broad Unicode support
-        L"Microsoft Sans Serif",
-        L"Nirmala UI",        // Good for Indic scripts
-        L"Microsoft YaHei",    // Chinese
-        L"Meiryo",            // Japanese
-        L"Malgun Gothic",     // Korean
-        L"Leelawadee UI",     // Thai
-        L"Myanmar Text"        // Myanmar
-    };
-    
-    // Try each font until we find one that can render the text
-    for (const auto& fontFamily : fontFamilies) {
-        Gdiplus::Font font(fontFamily, fontSize, Gdiplus::FontStyleRegular);
-        if (font.GetLastStatus() == Gdiplus::Ok) {
-            // Use DirectWrite/Uniscribe for complex script shaping
-            graphics.DrawString(text.c_str(), -1, &font, rect, &format, &brush);
-            
-            // Check if the font actually rendered the characters
-            // (In a real implementation, you might want to check if glyphs are missing)
-            break;
-        }
+    // Drawn in the keyboard's font, resolved when the keyboard was shown;
+    // GDI+ shapes complex scripts itself
+    Gdiplus::Font font(m_fontFamily.c_str(), fontSize, Gdiplus::FontStyleRegular);
+    if (font.GetLastStatus() == Gdiplus::Ok) {
+        graphics.DrawString(text.c_str(), -1, &font, rect, &format, &brush);
     }
 }
 
@@ -6,7 +6,9 @@
 RegistryMonitor::RegistryMonitor()
     : m_hKeyboardsKey(nullptr)
     , m_hSettingsKey(nullptr)
+    , m_hThemeKey(nullptr)
     , m_hRegChangeEvent(nullptr)
+    , m_hAppearanceChangeEvent(nullptr)
     , m_hGlobalUpdateEvent(nullptr)
     , m_hStopEvent(nullptr)
     , m_running(false) {
@@ -16,12 +18,13 @@ RegistryMonitor::~RegistryMonitor() {
     Stop();
 }
 
-bool RegistryMonitor::Start(ChangeCallback callback) {
+bool RegistryMonitor::Start(ChangeCallback callback, ChangeCallback appearanceCallback) {
     if (m_running) {
         return false;
     }
     
     m_callback = callback;
+    m_appearanceCallback = appearanceCallback;
     
     // Ensure registry structure exists
     EnsureRegistryStructure();
@@ -33,7 +36,7 @@ bool RegistryMonitor::Start(ChangeCallback callback) {
     }
     
     if (RegOpenKeyExW(HKEY_CURRENT_USER, KEYMAGIC_SETTINGS_PATH, 0,
-                      KEY_READ | KEY_WRITE, &m_hSettingsKey) != ERROR_SUCCESS) {
+                      KEY_READ | KEY_WRITE | KEY_NOTIFY, &m_hSettingsKey) != ERROR_SUCCESS) {
         RegCloseKey(m_hKeyboardsKey);
         m_hKeyboardsKey = nullptr;
         return false;
@@ -49,6 +52,10 @@ bool RegistryMonitor::Start(ChangeCallback callback) {
         return false;
     }
     
+    // The theme key is missing before Windows 10 1903; the theme is then fixed
+    RegOpenKeyExW(HKEY_CURRENT_USER, PERSONALIZE_PATH, 0, KEY_READ | KEY_NOTIFY, &m_hThemeKey);
+    m_hAppearanceChangeEvent = CreateEventW(nullptr, FALSE, FALSE, nullptr);
+    
     // Create or open global update event
     m_hGlobalUpdateEvent = SecurityUtils::CreateGlobalEvent(KEYMAGIC_REGISTRY_UPDATE_EVENT);
     if (!m_hGlobalUpdateEvent) {
@@ -59,6 +66,8 @@ bool RegistryMonitor::Start(ChangeCallback callback) {
     m_hStopEvent = CreateEventW(nullptr, TRUE, FALSE, nullptr);
     if (!m_hStopEvent) {
         CloseHandle(m_hRegChangeEvent);
+        if (m_hAppearanceChangeEvent) CloseHandle(m_hAppearanceChangeEvent);
+        if (m_hThemeKey) RegCloseKey(m_hThemeKey);
         if (m_hGlobalUpdateEvent) CloseHandle(m_hGlobalUpdateEvent);
         RegCloseKey(m_hKeyboardsKey);
         RegCloseKey(m_hSettingsKey);
@@ -91,6 +100,11 @@ void RegistryMonitor::Stop() {
         m_hRegChangeEvent = nullptr;
     }
     
+    if (m_hAppearanceChangeEvent) {
+        CloseHandle(m_hAppearanceChangeEvent);
+        m_hAppearanceChangeEvent = nullptr;
+    }
+    
     if (m_hGlobalUpdateEvent) {
         CloseHandle(m_hGlobalUpdateEvent);
         m_hGlobalUpdateEvent = nullptr;
@@ -110,15 +124,20 @@ void RegistryMonitor::Stop() {
         RegCloseKey(m_hSettingsKey);
         m_hSettingsKey = nullptr;
     }
+    
+    if (m_hThemeKey) {
+        RegCloseKey(m_hThemeKey);
+        m_hThemeKey = nullptr;
+    }
 }
 
 std::vector<KeyboardInfo> RegistryMonitor::GetKeyboards() {
     // Use shared utility function
     std::vector<KeyboardInfo> keyboards = RegistryUtils::GetInstalledKeyboards();
     
-    // For each keyboard, if hotkey is empty, try to load from KM2 file
+    // For each keyboard without a hotkey of its own, try to load from KM2 file
     for (auto& keyboard : keyboards) {
-        if (keyboard.hotkey.empty() && !keyboard.path.empty()) {
+        if (!keyboard.hasHotkey && !keyboard.path.empty()) {
             std::wstring km2Hotkey = KeyMagicUtils::LoadHotkeyFromKm2(keyboard.path);
             if (!km2Hotkey.empty()) {
                 keyboard.hotkey = km2Hotkey;
@@ -156,8 +175,9 @@ bool RegistryMonitor::GetKeyboardInfo(const std::wstring& keyboardId, KeyboardIn
         return false;
     }
     
-    // If hotkey is empty, try to load from KM2 file
-    if (info.hotkey.empty() && !info.path.empty()) {
+    // Without a hotkey of its own, try to load from KM2 file; an empty one
+    // means the user turned it off
+    if (!info.hasHotkey && !info.path.empty()) {
         std::wstring km2Hotkey = KeyMagicUtils::LoadHotkeyFromKm2(info.path);
         if (!km2Hotkey.empty()) {
             info.hotkey = km2Hotkey;
@@ -179,11 +199,14 @@ void RegistryMonitor::MonitorThread() {
     RegNotifyChangeKeyValue(m_hKeyboardsKey, TRUE, 
                            REG_NOTIFY_CHANGE_NAME | REG_NOTIFY_CHANGE_LAST_SET,
                            m_hRegChangeEvent, TRUE);
+    WatchAppearanceKeys();
     
-    HANDLE waitHandles[] = { m_hStopEvent, m_hRegChangeEvent };
+    // Without an appearance event, wait on the stop event twice
+    HANDLE waitHandles[] = { m_hStopEvent, m_hRegChangeEvent,
+                             m_hAppearanceChangeEvent ? m_hAppearanceChangeEvent : m_hStopEvent };
     
     while (m_running) {
-        DWORD waitResult = WaitForMultipleObjects(2, waitHandles, FALSE, INFINITE);
+        DWORD waitResult = WaitForMultipleObjects(3, waitHandles, FALSE, INFINITE);
         
         if (waitResult == WAIT_OBJECT_0) {
             // Stop event signaled
@@ -198,10 +221,52 @@ void RegistryMonitor::MonitorThread() {
             RegNotifyChangeKeyValue(m_hKeyboardsKey, TRUE,
                                    REG_NOTIFY_CHANGE_NAME | REG_NOTIFY_CHANGE_LAST_SET,
                                    m_hRegChangeEvent, TRUE);
+        } else if (waitResult == WAIT_OBJECT_0 + 2) {
+            // Taskbar theme or settings changed
+            if (m_appearanceCallback) {
+                m_appearanceCallback();
+            }
+            
+            WatchAppearanceKeys();
         }
     }
 }
 
+void RegistryMonitor::WatchAppearanceKeys() {
+    if (!m_hAppearanceChangeEvent) {
+        return;
+    }
+    
+    // Both keys signal the same event; a change to either re-registers both
+    RegNotifyChangeKeyValue(m_hSettingsKey, FALSE, REG_NOTIFY_CHANGE_LAST_SET,
+                           m_hAppearanceChangeEvent, TRUE);
+    if (m_hThemeKey) {
+        RegNotifyChangeKeyValue(m_hThemeKey, FALSE, REG_NOTIFY_CHANGE_LAST_SET,
+                               m_hAppearanceChangeEvent, TRUE);
+    }
+}
+
+bool RegistryMonitor::IsLightTaskbar() {
+    DWORD value = 0;
+    DWORD size = sizeof(value);
+    // Taskbars were always dark before the setting existed
+    if (RegGetValueW(HKEY_CURRENT_USER, PERSONALIZE_PATH, L"SystemUsesLightTheme",
+                     RRF_RT_REG_DWORD, nullptr, &value, &size) != ERROR_SUCCESS) {
+        return false;
+    }
+    return value != 0;
+}
+
+bool RegistryMonitor::IsKeyProcessingEnabled() {
+    std::wstring value;
+    if (RegistryUtils::ReadKeyMagicSetting(L"KeyProcessingEnabled", value)) {
+        return _wcsicmp(value.c_str(), L"false") != 0;
+    }
+    
+    // Enabled unless turned off
+    return true;
+}
+
 void RegistryMonitor::EnsureRegistryStructure() {
     // Use shared utility function
     RegistryUtils::EnsureRegistryStructure();
@@ -164,6 +164,7 @@ void TrayIcon::ShowContextMenu(HWND hWnd, const std::vector<KeyboardInfo>& keybo
     }
     
     // Add standard items
+    AppendMenuW(hMenu, MF_STRING, IDM_CLEAR_HISTORY, L"Clear Typing History");
     AppendMenuW(hMenu, MF_STRING, IDM_SETTINGS, L"Settings...");
     
     // Get cursor position
@@ -1,4 +1,5 @@
 #include "TrayManager.h"
+#include "../../shared/include/keymagic_ffi.h"
 #include <shlobj.h>
 #include <shellapi.h>
 
@@ -137,6 +138,13 @@ LRESULT TrayManager::HandleMessage(HWND hWnd, UINT message, WPARAM wParam, LPARA
             UpdateTrayIcon();
             return 0;
             
+        case WM_APPEARANCE_CHANGED:
+            {
+                std::lock_guard<std::mutex> lock(m_stateMutex);
+                UpdateTrayIcon();
+            }
+            return 0;
+            
         case WM_TIMER:
             if (wParam == TIMER_HIDE_DELAY) {
                 OutputDebugStringW(L"TrayManager: Hide timer triggered\n");
@@ -177,7 +185,10 @@ bool TrayManager::Initialize() {
     
     // Initialize registry monitor
     m_registryMonitor = std::make_unique<RegistryMonitor>();
-    if (!m_registryMonitor->Start([this]() { OnRegistryChange(); })) {
+    if (!m_registryMonitor->Start(
+            [this]() { OnRegistryChange(); },
+            // Redraw on the UI thread when the taskbar theme or processing state changes
+            [this]() { PostMessage(m_hWnd, WM_APPEARANCE_CHANGED, 0, 0); })) {
         return false;
     }
     
@@ -387,10 +398,15 @@ void TrayManager::OnMenuCommand(UINT cmdId) {
     const UINT IDM_KEYBOARD_BASE = 1000;
     const UINT IDM_ABOUT = 998;
     const UINT IDM_SETTINGS = 997;
+    const UINT IDM_CLEAR_HISTORY = 996;
     
     if (cmdId == IDM_SETTINGS) {
         // Launch KeyMagic GUI application
         LaunchKeyMagicApp();
+    } else if (cmdId == IDM_CLEAR_HISTORY) {
+        // The GUI owns the history; the shared log tells it to forget
+        static CommitLogHandle* commitLog = keymagic_commit_log_open();
+        keymagic_commit_log_clear(commitLog);
     } else if (cmdId >= IDM_KEYBOARD_BASE) {
         // Keyboard selection
         UINT index = cmdId - IDM_KEYBOARD_BASE;
@@ -461,14 +477,25 @@ void TrayManager::UpdateTrayIcon() {
         // Get keyboard info
         KeyboardInfo info;
         if (m_registryMonitor->GetKeyboardInfo(m_currentKeyboardId, info)) {
-            // Update icon
-            HICON hIcon = m_iconCache->GetIcon(m_currentKeyboardId, info.path, DEFAULT_ICON_SIZE);
+            // Update icon; keyboards without one get the glyph for the taskbar theme
+            IconStyle style = { RegistryMonitor::IsLightTaskbar(), RegistryMonitor::IsKeyProcessingEnabled() };
+            HICON hIcon = m_iconCache->GetIcon(m_currentKeyboardId, info.path, DEFAULT_ICON_SIZE, style);
+            if (!hIcon) {
+                hIcon = m_iconCache->GetGlyphIcon(DEFAULT_ICON_SIZE, style);
+            }
             if (hIcon) {
                 m_trayIcon->SetIcon(hIcon);
             }
             
             // Update tooltip
             std::wstring tooltip = L"KeyMagic - " + info.name;
+            std::wstring profile;
+            if (RegistryUtils::ReadKeyMagicSetting(L"ActiveProfile", profile) && !profile.empty()) {
+                tooltip += L" (" + profile + L")";
+            }
+            if (!style.processingEnabled) {
+                tooltip += L" - Disabled";
+            }
             m_trayIcon->SetTooltip(tooltip);
             
             // Update keyboard info for preview
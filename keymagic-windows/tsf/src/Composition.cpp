@@ -144,7 +144,7 @@ HRESULT CCompositionManager::StartComposition(ITfContext *pContext, TfEditCookie
     return hr;
 }
 
-HRESULT CCompositionManager::UpdateComposition(ITfContext *pContext, TfEditCookie ec, const std::wstring &text)
+HRESULT CCompositionManager::UpdateComposition(ITfContext *pContext, TfEditCookie ec, const std::wstring &text, LONG caret)
 {
     DEBUG_LOG_FUNC();
     DEBUG_LOG_TEXT(L"Updating composition with", text);
@@ -188,12 +188,22 @@ HRESULT CCompositionManager::UpdateComposition(ITfContext *pContext, TfEditCooki
         // Apply display attributes (underline)
         ApplyDisplayAttributes(pContext, ec, pRange);
         
-        // Move cursor to the end of composition
+        // Move cursor to the engine's caret (end of composition by default)
         ITfRange *pSelection;
         if (SUCCEEDED(pRange->Clone(&pSelection)))
         {
-            // Collapse to end of range
-            pSelection->Collapse(ec, TF_ANCHOR_END);
+            if (caret >= 0 && caret < static_cast<LONG>(text.length()))
+            {
+                LONG shifted;
+                pSelection->Collapse(ec, TF_ANCHOR_START);
+                pSelection->ShiftEnd(ec, caret, &shifted, nullptr);
+                pSelection->ShiftStart(ec, caret, &shifted, nullptr);
+            }
+            else
+            {
+                // Collapse to end of range
+                pSelection->Collapse(ec, TF_ANCHOR_END);
+            }
             
             // Set selection
             TF_SELECTION tfSelection;
@@ -204,7 +214,7 @@ HRESULT CCompositionManager::UpdateComposition(ITfContext *pContext, TfEditCooki
             pContext->SetSelection(ec, 1, &tfSelection);
             pSelection->Release();
             
-            DEBUG_LOG(L"Cursor moved to end of composition");
+            DEBUG_LOG(L"Cursor moved to caret position");
         }
     }
     
@@ -426,7 +436,7 @@ STDAPI CCompositionManager::OnCompositionTerminated(TfEditCookie ecWrite, ITfCom
         if (m_pTextService)
         {
             DEBUG_LOG(L"Resetting engine on composition termination");
-            m_pTextService->ResetEngine();
+            m_pTextService->ResetEngine(KeyMagicResetLevel_Composing);
         }
     }
     
@@ -22,7 +22,8 @@ public:
     
     // Composition management
     HRESULT StartComposition(ITfContext *pContext, TfEditCookie ec);
-    HRESULT UpdateComposition(ITfContext *pContext, TfEditCookie ec, const std::wstring &text);
+    // caret is a UTF-16 offset into text; -1 places it at the end
+    HRESULT UpdateComposition(ITfContext *pContext, TfEditCookie ec, const std::wstring &text, LONG caret = -1);
     HRESULT EndComposition(TfEditCookie ec);
     HRESULT CommitComposition(ITfContext *pContext, TfEditCookie ec, const std::wstring &text);
     HRESULT CancelComposition(TfEditCookie ec);
@@ -24,6 +24,7 @@ CCompositionEditSession::CCompositionEditSession(CKeyMagicTextService *pTextServ
     m_wParam = 0;
     m_lParam = 0;
     m_pfEaten = nullptr;
+    m_disableRequest = 0;
 }
 
 CCompositionEditSession::~CCompositionEditSession()
@@ -100,6 +101,11 @@ void CCompositionEditSession::SetKeyData(WPARAM wParam, LPARAM lParam, BOOL *pfE
     m_pfEaten = pfEaten;
 }
 
+void CCompositionEditSession::SetDisableRequest(uint64_t request)
+{
+    m_disableRequest = request;
+}
+
 // Process key implementation using composition
 HRESULT CCompositionEditSession::ProcessKey(TfEditCookie ec)
 {
@@ -131,6 +137,16 @@ HRESULT CCompositionEditSession::ProcessKey(TfEditCookie ec)
         return S_OK;
     }
     
+    // With eager commit, a key no later key can build on is committed right
+    // away rather than shown as a composition first
+    bool terminalKey = m_pTextService->GetEagerCommit() &&
+        keymagic_engine_is_prefix_win(
+            m_pEngine,
+            static_cast<int>(m_wParam),
+            keyInput.character,
+            keyInput.shift, keyInput.ctrl, keyInput.alt, keyInput.capsLock
+        ) != KEYMAGIC_PREFIX_OF_LONGER;
+    
     // Process with engine
     ProcessKeyOutput output = {0};
     
@@ -152,6 +168,17 @@ HRESULT CCompositionEditSession::ProcessKey(TfEditCookie ec)
     
     // Process the output using secure debug macro
     DEBUG_LOG_ENGINE(output);
+
+    // Numbered so the trace can be matched against an exported recording
+    uint64_t recordSeq = KeyProcessingUtils::RecordKeyEvent(m_wParam, keyInput, output);
+    if (recordSeq != 0)
+    {
+        DEBUG_LOG(L"Input record #" + std::to_wstring(recordSeq));
+    }
+    KeyProcessingUtils::CountKey(ec, m_pContext, m_pTextService->GetCurrentKeyboardId(), m_wParam);
+
+    KeyProcessingUtils::ShowNotification(output);
+    KeyProcessingUtils::JournalComposition(ec, m_pContext, output.composing_text);
     
     // Handle composition based on engine's composing text
     if (output.composing_text && strlen(output.composing_text) > 0)
@@ -162,7 +189,7 @@ HRESULT CCompositionEditSession::ProcessKey(TfEditCookie ec)
         DEBUG_LOG_TEXT(L"Engine composing text", composingText);
         
         // Check if we should commit the composition
-        if (ShouldCommitComposition(m_wParam, composingUtf8, output.is_processed))
+        if (terminalKey || ShouldCommitComposition(m_wParam, composingUtf8, output.is_processed))
         {
             DEBUG_LOG(L"Committing composition");
             
@@ -177,6 +204,7 @@ HRESULT CCompositionEditSession::ProcessKey(TfEditCookie ec)
             
             // Commit the text
             m_pCompositionManager->CommitComposition(m_pContext, ec, textToCommit);
+            KeyProcessingUtils::ReportCommit(composingUtf8.c_str());
             
             // Reset engine after commit
             keymagic_engine_reset(m_pEngine);
@@ -191,7 +219,7 @@ HRESULT CCompositionEditSession::ProcessKey(TfEditCookie ec)
                 m_pCompositionManager->StartComposition(m_pContext, ec);
             }
             
-            m_pCompositionManager->UpdateComposition(m_pContext, ec, composingText);
+            m_pCompositionManager->UpdateComposition(m_pContext, ec, composingText, output.composing_caret_utf16);
         }
     }
     else
@@ -213,6 +241,9 @@ HRESULT CCompositionEditSession::ProcessKey(TfEditCookie ec)
         switch (m_wParam)
         {
             case VK_ESCAPE:
+                // Cancel composition; states stay on
+                keymagic_engine_reset_level(m_pEngine, KeyMagicResetLevel_Composing);
+                break;
             case VK_RETURN:
             case VK_TAB:
                 keymagic_engine_reset(m_pEngine);
@@ -225,6 +256,7 @@ HRESULT CCompositionEditSession::ProcessKey(TfEditCookie ec)
     // Cleanup
     if (output.text) keymagic_free_string(output.text);
     if (output.composing_text) keymagic_free_string(output.composing_text);
+    if (output.notification) keymagic_free_string(output.notification);
     
     return S_OK;
 }
@@ -265,7 +297,7 @@ HRESULT CCompositionEditSession::SyncEngineWithDocument(TfEditCookie ec)
                     DEBUG_LOG_SYNC_MISMATCH(engineText, documentCompositionText);
                     // Mismatch detected - reset engine and end composition
                     DEBUG_LOG(L"Engine and document composition mismatch, resetting engine");
-                    keymagic_engine_reset(m_pEngine);
+                    keymagic_engine_reset_level(m_pEngine, KeyMagicResetLevel_Composing);
                     m_pCompositionManager->EndComposition(ec);
                     return S_OK;
                 }
@@ -275,7 +307,7 @@ HRESULT CCompositionEditSession::SyncEngineWithDocument(TfEditCookie ec)
 
     // If not composing, just reset the engine
     DEBUG_LOG(L"Not composing, resetting engine");
-    keymagic_engine_reset(m_pEngine);
+    keymagic_engine_reset_level(m_pEngine, KeyMagicResetLevel_Composing);
     
     return S_OK;
 }
@@ -293,7 +325,7 @@ HRESULT CCompositionEditSession::CommitAndRecomposeAtCursor(TfEditCookie ec)
     }
     
     // Reset engine and sync with new cursor position
-    keymagic_engine_reset(m_pEngine);
+    keymagic_engine_reset_level(m_pEngine, KeyMagicResetLevel_Composing);
     
     // Now sync engine with text at new cursor position
     return SyncEngineWithDocument(ec);
@@ -380,11 +412,21 @@ HRESULT CCompositionEditSession::TerminateComposition(TfEditCookie ec)
         m_pCompositionManager->EndComposition(ec);
     }
     
-    // Reset the engine
+    // Reset the engine; the committed text stays in the document
     if (m_pEngine)
     {
-        DEBUG_LOG(L"Resetting engine");
-        keymagic_engine_reset(m_pEngine);
+        DEBUG_LOG(L"Flushing engine");
+        char* committed = keymagic_engine_flush(m_pEngine);
+        if (committed)
+        {
+            KeyProcessingUtils::ReportCommit(committed);
+            keymagic_free_string(committed);
+        }
+    }
+    
+    if (m_disableRequest)
+    {
+        m_pTextService->FinishDisable(m_disableRequest);
     }
     
     return S_OK;
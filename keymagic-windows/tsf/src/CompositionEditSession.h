@@ -37,6 +37,8 @@ public:
     
     // Set parameters for different actions
     void SetKeyData(WPARAM wParam, LPARAM lParam, BOOL *pfEaten);
+    // TerminateComposition: key processing request to finish once committed
+    void SetDisableRequest(uint64_t request);
     
 private:
     LONG m_cRef;
@@ -50,6 +52,7 @@ private:
     WPARAM m_wParam;
     LPARAM m_lParam;
     BOOL *m_pfEaten;
+    uint64_t m_disableRequest;
     
     // Action implementations
     HRESULT ProcessKey(TfEditCookie ec);
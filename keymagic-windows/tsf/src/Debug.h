@@ -177,8 +177,8 @@ inline void DebugLogEngineOutput(const ProcessKeyOutput& output)
     switch (output.action_type) {
         case 0: oss << L"None"; break;
         case 1: oss << L"Insert"; break;
-        case 2: oss << L"Delete(" << output.delete_count << L")"; break;
-        case 3: oss << L"DeleteAndInsert(" << output.delete_count << L")"; break;
+        case 2: oss << L"Delete(" << output.delete_count << L", utf16=" << output.delete_utf16_count << L")"; break;
+        case 3: oss << L"DeleteAndInsert(" << output.delete_count << L", utf16=" << output.delete_utf16_count << L")"; break;
         default: oss << L"Unknown(" << output.action_type << L")"; break;
     }
     
@@ -241,8 +241,8 @@ inline void DebugLogEngineCensored(const ProcessKeyOutput& output)
     switch (output.action_type) {
         case 0: oss << L"None"; break;
         case 1: oss << L"Insert"; break;
-        case 2: oss << L"Delete(" << output.delete_count << L")"; break;
-        case 3: oss << L"DeleteAndInsert(" << output.delete_count << L")"; break;
+        case 2: oss << L"Delete(" << output.delete_count << L", utf16=" << output.delete_utf16_count << L")"; break;
+        case 3: oss << L"DeleteAndInsert(" << output.delete_count << L", utf16=" << output.delete_utf16_count << L")"; break;
         default: oss << L"Unknown(" << output.action_type << L")"; break;
     }
     
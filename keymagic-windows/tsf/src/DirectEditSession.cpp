@@ -140,14 +140,26 @@ HRESULT CDirectEditSession::ProcessKey(TfEditCookie ec)
     
     // Process the output using secure debug macro
     DEBUG_LOG_ENGINE(output);
+
+    // Numbered so the trace can be matched against an exported recording
+    uint64_t recordSeq = KeyProcessingUtils::RecordKeyEvent(m_wParam, keyInput, output);
+    if (recordSeq != 0)
+    {
+        DEBUG_LOG(L"Input record #" + std::to_wstring(recordSeq));
+    }
+    KeyProcessingUtils::CountKey(ec, m_pContext, m_pTextService->GetCurrentKeyboardId(), m_wParam);
+
+    KeyProcessingUtils::ShowNotification(output);
+    KeyProcessingUtils::JournalComposition(ec, m_pContext, output.composing_text);
     
     if (output.action_type != 0) // Not None
     {
-        // Handle backspace count
-        if (output.delete_count > 0)
+        // Handle backspace count; the document holds UTF-16, so characters
+        // outside the BMP take two backspaces (one per surrogate)
+        if (output.delete_utf16_count > 0)
         {
-            DEBUG_LOG(L"Sending " + std::to_wstring(output.delete_count) + L" backspaces");
-            SendBackspaces(output.delete_count, KEYMAGIC_EXTRAINFO_SIGNATURE, nullptr);
+            DEBUG_LOG(L"Sending " + std::to_wstring(output.delete_utf16_count) + L" backspaces");
+            SendBackspaces(output.delete_utf16_count, KEYMAGIC_INJECTED_INPUT_SIGNATURE, nullptr);
         }
         
         // Handle text insertion
@@ -155,7 +167,7 @@ HRESULT CDirectEditSession::ProcessKey(TfEditCookie ec)
         {
             std::wstring textToInsert = KeyMagicUtils::ConvertUtf8ToUtf16(output.text);
             DEBUG_LOG_TEXT(L"Sending text", textToInsert);
-            SendUnicodeText(textToInsert, KEYMAGIC_EXTRAINFO_SIGNATURE, nullptr);
+            SendUnicodeText(textToInsert, KEYMAGIC_INJECTED_INPUT_SIGNATURE, nullptr);
         }
     }
     
@@ -174,6 +186,7 @@ HRESULT CDirectEditSession::ProcessKey(TfEditCookie ec)
                     if (!composingText.empty() && composingText.back() == L' ')
                     {
                         // Reset engine after space commit
+                        KeyProcessingUtils::ReportCommit(composingUtf8.c_str());
                         keymagic_engine_reset(m_pEngine);
                     }
                 }
@@ -182,7 +195,8 @@ HRESULT CDirectEditSession::ProcessKey(TfEditCookie ec)
                     // Engine didn't process space - append space and reset
                     if (!composingText.empty())
                     {
-                        SendUnicodeText(L" ", KEYMAGIC_EXTRAINFO_SIGNATURE, nullptr);
+                        SendUnicodeText(L" ", KEYMAGIC_INJECTED_INPUT_SIGNATURE, nullptr);
+                        KeyProcessingUtils::ReportCommit(composingUtf8.c_str());
                     }
                     keymagic_engine_reset(m_pEngine);
                 }
@@ -191,12 +205,13 @@ HRESULT CDirectEditSession::ProcessKey(TfEditCookie ec)
             case VK_RETURN:
             case VK_TAB:
                 // Reset engine after these keys
+                KeyProcessingUtils::ReportCommit(composingUtf8.c_str());
                 keymagic_engine_reset(m_pEngine);
                 break;
                 
             case VK_ESCAPE:
-                // Cancel composition
-                keymagic_engine_reset(m_pEngine);
+                // Cancel composition; states stay on
+                keymagic_engine_reset_level(m_pEngine, KeyMagicResetLevel_Composing);
                 break;
         }
     }
@@ -206,6 +221,7 @@ HRESULT CDirectEditSession::ProcessKey(TfEditCookie ec)
     // Cleanup
     if (output.text) keymagic_free_string(output.text);
     if (output.composing_text) keymagic_free_string(output.composing_text);
+    if (output.notification) keymagic_free_string(output.notification);
     
     return S_OK;
 }
@@ -228,7 +244,7 @@ HRESULT CDirectEditSession::SyncEngineWithDocument(TfEditCookie ec)
             if (documentText.back() == L' ')
             {
                 DEBUG_LOG(L"Text before cursor ends with space, resetting engine instead of syncing");
-                keymagic_engine_reset(m_pEngine);
+                keymagic_engine_reset_level(m_pEngine, KeyMagicResetLevel_Composing);
                 return S_OK;
             }
             
@@ -283,20 +299,20 @@ HRESULT CDirectEditSession::SyncEngineWithDocument(TfEditCookie ec)
             else
             {
                 DEBUG_LOG(L"Failed to set engine composition, error: " + std::to_wstring(result));
-                keymagic_engine_reset(m_pEngine);
+                keymagic_engine_reset_level(m_pEngine, KeyMagicResetLevel_Composing);
             }
         }
         else
         {
             // Empty document, reset engine
             DEBUG_LOG(L"Document is empty, resetting engine");
-            keymagic_engine_reset(m_pEngine);
+            keymagic_engine_reset_level(m_pEngine, KeyMagicResetLevel_Composing);
         }
     }
     else
     {
         DEBUG_LOG(L"Failed to read document text for sync, resetting engine");
-        keymagic_engine_reset(m_pEngine);
+        keymagic_engine_reset_level(m_pEngine, KeyMagicResetLevel_Composing);
     }
     
     return S_OK;
@@ -5,6 +5,7 @@
 #include "ClassFactory.h"
 #include "KeyMagicGuids.h"
 #include "Registry.h"
+#include "Debug.h"
 
 // DLL entry point
 BOOL WINAPI DllMain(HINSTANCE hInstance, DWORD dwReason, LPVOID pvReserved)
@@ -17,6 +18,16 @@ BOOL WINAPI DllMain(HINSTANCE hInstance, DWORD dwReason, LPVOID pvReserved)
         break;
 
     case DLL_PROCESS_DETACH:
+        // On FreeLibrary (not process exit) every engine should be gone by now
+        if (pvReserved == nullptr && keymagic_debug_instance_count() > 0)
+        {
+            char* instances = keymagic_debug_dump_instances();
+            if (instances)
+            {
+                DEBUG_LOG(L"Engine handles never freed: " + KeyMagicUtils::ConvertUtf8ToUtf16(instances));
+                keymagic_free_string(instances);
+            }
+        }
         break;
     }
 
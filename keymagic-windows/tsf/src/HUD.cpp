@@ -1,10 +1,22 @@
 #include "HUD.h"
+#include "../../shared/include/RegistryUtils.h"
 #include <algorithm>
 #include <vector>
 
 // RGB macro
 #define RGB_MACRO(r,g,b) ((COLORREF)(((BYTE)(r)|((WORD)((BYTE)(g))<<8))|(((DWORD)(BYTE)(b))<<16)))
 
+// The HUD is shown unless the hud_enabled setting is "false"
+static bool IsHudEnabled()
+{
+    std::wstring value;
+    if (RegistryUtils::ReadKeyMagicSetting(L"HudEnabled", value))
+    {
+        return _wcsicmp(value.c_str(), L"false") != 0;
+    }
+    return true;
+}
+
 KeyMagicHUD& KeyMagicHUD::GetInstance()
 {
     static KeyMagicHUD instance;
@@ -65,16 +77,31 @@ HRESULT KeyMagicHUD::Initialize()
     return S_OK;
 }
 
-void KeyMagicHUD::ShowKeyboard(const std::wstring& keyboardName)
+void KeyMagicHUD::ShowKeyboard(const std::wstring& keyboardName, const std::wstring& sampleText)
 {
-    if (!m_hwnd)
+    if (!m_hwnd || !IsHudEnabled())
         return;
         
-    // Allocate and copy string
-    std::wstring* pText = new std::wstring(keyboardName);
-    
-    // Post message to show HUD
-    PostMessage(m_hwnd, WM_SHOW_HUD, 0, reinterpret_cast<LPARAM>(pText));
+    // Replace the text waiting to be shown; only the first of a burst posts
+    bool post = false;
+    {
+        std::lock_guard<std::mutex> lock(m_pendingLock);
+        m_pending = HudText{keyboardName, sampleText};
+        m_hasPending = true;
+        post = !m_showPosted;
+        m_showPosted = true;
+    }
+
+    if (post && !PostMessage(m_hwnd, WM_SHOW_HUD, 0, 0))
+    {
+        std::lock_guard<std::mutex> lock(m_pendingLock);
+        m_showPosted = false;
+    }
+}
+
+void KeyMagicHUD::ShowMessage(const std::wstring& message)
+{
+    ShowKeyboard(message);
 }
 
 void KeyMagicHUD::Cleanup()
@@ -115,15 +142,20 @@ LRESULT KeyMagicHUD::HandleMessage(HWND hwnd, UINT msg, WPARAM wParam, LPARAM lP
     {
         case WM_SHOW_HUD:
         {
-            std::wstring* pText = reinterpret_cast<std::wstring*>(lParam);
-            if (pText)
             {
-                ShowHudInternal(*pText);
-                delete pText;
-                
-                // Set timer to hide
-                SetTimer(hwnd, HUD_TIMER_ID, HUD_DISPLAY_TIME_MS, nullptr);
+                std::lock_guard<std::mutex> lock(m_pendingLock);
+                m_showPosted = false;
             }
+
+            // Text drawn moments ago stays up for the floor first; the
+            // floor timer then draws whatever is pending by then
+            ULONGLONG shownFor = GetTickCount64() - m_shownAt;
+            if (m_shownAt != 0 && shownFor < HUD_MIN_DISPLAY_MS)
+            {
+                SetTimer(hwnd, HUD_FLOOR_TIMER_ID, static_cast<UINT>(HUD_MIN_DISPLAY_MS - shownFor), nullptr);
+                return 0;
+            }
+            ShowPending();
             return 0;
         }
         
@@ -133,6 +165,12 @@ LRESULT KeyMagicHUD::HandleMessage(HWND hwnd, UINT msg, WPARAM wParam, LPARAM lP
             {
                 HideHud();
                 KillTimer(hwnd, HUD_TIMER_ID);
+                m_shownAt = 0;
+            }
+            else if (wParam == HUD_FLOOR_TIMER_ID)
+            {
+                KillTimer(hwnd, HUD_FLOOR_TIMER_ID);
+                ShowPending();
             }
             return 0;
         }
@@ -145,7 +183,26 @@ LRESULT KeyMagicHUD::HandleMessage(HWND hwnd, UINT msg, WPARAM wParam, LPARAM lP
     }
 }
 
-void KeyMagicHUD::ShowHudInternal(const std::wstring& text)
+// Draws the latest text in place and restarts the hide timer
+void KeyMagicHUD::ShowPending()
+{
+    HudText text;
+    {
+        std::lock_guard<std::mutex> lock(m_pendingLock);
+        if (!m_hasPending)
+            return;
+        text = std::move(m_pending);
+        m_hasPending = false;
+    }
+
+    ShowHudInternal(text);
+    m_shownAt = GetTickCount64();
+
+    // SetTimer on a running timer restarts it
+    SetTimer(m_hwnd, HUD_TIMER_ID, HUD_DISPLAY_TIME_MS, nullptr);
+}
+
+void KeyMagicHUD::ShowHudInternal(const HudText& text)
 {
     // Show window
     ShowWindow(m_hwnd, SW_SHOWNOACTIVATE);
@@ -164,15 +221,35 @@ void KeyMagicHUD::ShowHudInternal(const std::wstring& text)
         L"Segoe UI"
     );
     
+    // Smaller font for the sample line under the name
+    int sampleFontSize = -MulDiv(14, GetDeviceCaps(hdcScreen, LOGPIXELSY), 72);
+    HFONT sampleFont = CreateFontW(
+        sampleFontSize, 0, 0, 0,
+        FW_NORMAL, FALSE, FALSE, FALSE,
+        DEFAULT_CHARSET, OUT_DEFAULT_PRECIS,
+        CLIP_DEFAULT_PRECIS, DEFAULT_QUALITY,
+        DEFAULT_PITCH | FF_SWISS,
+        L"Segoe UI"
+    );
+    
     HFONT oldFont = (HFONT)SelectObject(memDC, font);
     
     // Measure text
     SIZE textSize;
-    GetTextExtentPoint32W(memDC, text.c_str(), (int)text.length(), &textSize);
+    GetTextExtentPoint32W(memDC, text.name.c_str(), (int)text.name.length(), &textSize);
+    
+    SIZE sampleSize = {0, 0};
+    if (!text.sample.empty())
+    {
+        SelectObject(memDC, sampleFont);
+        GetTextExtentPoint32W(memDC, text.sample.c_str(), (int)text.sample.length(), &sampleSize);
+        SelectObject(memDC, font);
+    }
     
     const int padding = 20;
-    int width = textSize.cx + (padding * 2);
-    int height = textSize.cy + (padding * 2);
+    const int lineGap = text.sample.empty() ? 0 : 4;
+    int width = (std::max)(textSize.cx, sampleSize.cx) + (padding * 2);
+    int height = textSize.cy + lineGap + sampleSize.cy + (padding * 2);
     
     // Create bitmap
     HBITMAP bitmap = CreateCompatibleBitmap(hdcScreen, width, height);
@@ -205,8 +282,15 @@ void KeyMagicHUD::ShowHudInternal(const std::wstring& text)
     // Draw text
     SetBkMode(memDC, TRANSPARENT);
     SetTextColor(memDC, textColor);
-    RECT textRect = {0, 0, width, height};
-    DrawTextW(memDC, text.c_str(), (int)text.length(), &textRect, DT_CENTER | DT_SINGLELINE | DT_VCENTER);
+    RECT textRect = {0, padding, width, padding + textSize.cy};
+    DrawTextW(memDC, text.name.c_str(), (int)text.name.length(), &textRect, DT_CENTER | DT_SINGLELINE | DT_VCENTER);
+    
+    if (!text.sample.empty())
+    {
+        SelectObject(memDC, sampleFont);
+        RECT sampleRect = {0, textRect.bottom + lineGap, width, height - padding};
+        DrawTextW(memDC, text.sample.c_str(), (int)text.sample.length(), &sampleRect, DT_CENTER | DT_SINGLELINE | DT_VCENTER);
+    }
     
     // Apply alpha channel
     SetBitmapAlpha(memDC, bitmap, transparentColor, textColor);
@@ -218,6 +302,7 @@ void KeyMagicHUD::ShowHudInternal(const std::wstring& text)
     SelectObject(memDC, oldFont);
     SelectObject(memDC, oldBitmap);
     DeleteObject(font);
+    DeleteObject(sampleFont);
     DeleteObject(bitmap);
     DeleteDC(memDC);
     ReleaseDC(nullptr, hdcScreen);
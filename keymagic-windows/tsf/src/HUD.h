@@ -2,6 +2,7 @@
 #define KEYMAGIC_HUD_H
 
 #include <windows.h>
+#include <mutex>
 #include <string>
 
 class KeyMagicHUD
@@ -12,8 +13,11 @@ public:
     // Initialize the HUD window
     HRESULT Initialize();
     
-    // Show keyboard name in HUD
-    void ShowKeyboard(const std::wstring& keyboardName);
+    // Show keyboard name in HUD, with its sample text (if any) underneath
+    void ShowKeyboard(const std::wstring& keyboardName, const std::wstring& sampleText = L"");
+
+    // Show a message a keyboard rule raised, e.g. "Numbers mode"
+    void ShowMessage(const std::wstring& message);
     
     // Cleanup
     void Cleanup();
@@ -30,16 +34,39 @@ private:
     static LRESULT CALLBACK WndProc(HWND hwnd, UINT msg, WPARAM wParam, LPARAM lParam);
     LRESULT HandleMessage(HWND hwnd, UINT msg, WPARAM wParam, LPARAM lParam);
     
+    // Text posted with WM_SHOW_HUD
+    struct HudText
+    {
+        std::wstring name;
+        std::wstring sample;
+    };
+    
     // Internal methods
-    void ShowHudInternal(const std::wstring& text);
+    void ShowPending();
+    void ShowHudInternal(const HudText& text);
     void HideHud();
     void UpdateLayeredWindow(HDC memDC, int width, int height);
     void SetBitmapAlpha(HDC hdc, HBITMAP bitmap, COLORREF transparentColor, COLORREF textColor);
     
     HWND m_hwnd;
+
+    // Text to show next; a newer one replaces it, so a burst of switches
+    // only draws the last. Guarded by m_pendingLock.
+    std::mutex m_pendingLock;
+    HudText m_pending;
+    bool m_hasPending = false;
+    // Whether a WM_SHOW_HUD is queued and not yet handled
+    bool m_showPosted = false;
+    // Tick count when the shown text was drawn, 0 when hidden
+    ULONGLONG m_shownAt = 0;
+
     static const UINT WM_SHOW_HUD = WM_USER + 1;
     static const UINT HUD_TIMER_ID = 1;
+    // Fires once the shown text has been up for HUD_MIN_DISPLAY_MS
+    static const UINT HUD_FLOOR_TIMER_ID = 2;
     static const UINT HUD_DISPLAY_TIME_MS = 1500;
+    // Text stays up at least this long before a newer one replaces it
+    static const UINT HUD_MIN_DISPLAY_MS = 300;
 };
 
 #endif // KEYMAGIC_HUD_H
\ No newline at end of file
@@ -43,6 +43,7 @@ CKeyMagicTextService::CKeyMagicTextService()
         DEBUG_LOG(L"Failed to create KeyMagic engine");
     }
     m_useCompositionEditSession = true;  // Default to using composition edit session
+    m_eagerCommit = false;
     
     // Create composition manager
     m_pCompositionMgr = new CCompositionManager(this);
@@ -65,6 +66,15 @@ CKeyMagicTextService::CKeyMagicTextService()
     
     // Initialize preserved key support
     m_pKeystrokeMgr = nullptr;
+    m_pDoubleTap = keymagic_double_tap_new(0);
+    m_pSwitchGuard = keymagic_switch_guard_new(0, 0, 0);
+    m_pProcessing = keymagic_processing_open();
+    m_pShortcutWatch = keymagic_shortcut_watch_open();
+    m_pendingShortcut = 0;
+    m_pendingShortcutKey = 0;
+    m_hCharFilterHook = nullptr;
+    m_charFilterDetected = false;
+    m_dwUiThreadId = 0;
     
     // Initialize HUD
     KeyMagicHUD::GetInstance().Initialize();
@@ -108,6 +118,26 @@ CKeyMagicTextService::~CKeyMagicTextService()
     }
     
     UninitializeEngine();
+    if (m_pDoubleTap)
+    {
+        keymagic_double_tap_free(m_pDoubleTap);
+        m_pDoubleTap = nullptr;
+    }
+    if (m_pSwitchGuard)
+    {
+        keymagic_switch_guard_free(m_pSwitchGuard);
+        m_pSwitchGuard = nullptr;
+    }
+    if (m_pProcessing)
+    {
+        keymagic_processing_free(m_pProcessing);
+        m_pProcessing = nullptr;
+    }
+    if (m_pShortcutWatch)
+    {
+        keymagic_shortcut_watch_free(m_pShortcutWatch);
+        m_pShortcutWatch = nullptr;
+    }
     DeleteCriticalSection(&m_cs);
     DllRelease();
 }
@@ -203,6 +233,7 @@ STDAPI CKeyMagicTextService::ActivateEx(ITfThreadMgr *ptim, TfClientId tid, DWOR
     m_pThreadMgr = ptim;
     m_pThreadMgr->AddRef();
     m_tfClientId = tid;
+    m_dwUiThreadId = GetCurrentThreadId();
 
     // Register thread manager event sink
     ITfSource *pSource;
@@ -259,6 +290,7 @@ STDAPI CKeyMagicTextService::Deactivate()
     // Clean up sinks
     UninitTextEditSink();
     UninitMouseSink();
+    RemoveCharFilter();
 
     // Unregister display attribute provider
 
@@ -323,6 +355,8 @@ STDAPI CKeyMagicTextService::OnSetFocus(ITfDocumentMgr *pdimFocus, ITfDocumentMg
             // Sync engine with document content instead of resetting
             if (m_pEngine && pContext)
             {
+                // Nothing typed in the previous field applies to this one
+                ResetEngine(KeyMagicResetLevel_Context);
                 DEBUG_LOG(L"Syncing engine with document on focus change");
                 
                 // Create edit session to read document and sync engine
@@ -345,13 +379,13 @@ STDAPI CKeyMagicTextService::OnSetFocus(ITfDocumentMgr *pdimFocus, ITfDocumentMg
                         else
                         {
                             DEBUG_LOG(L"Failed to sync engine with document, falling back to reset");
-                            ResetEngine();
+                            ResetEngine(KeyMagicResetLevel_Context);
                         }
                     }
                     else
                     {
                         DEBUG_LOG(L"Failed to create edit session, falling back to reset");
-                        ResetEngine();
+                        ResetEngine(KeyMagicResetLevel_Context);
                     }
                 }
                 else
@@ -372,26 +406,26 @@ STDAPI CKeyMagicTextService::OnSetFocus(ITfDocumentMgr *pdimFocus, ITfDocumentMg
                         else
                         {
                             DEBUG_LOG(L"Failed to sync engine with document, falling back to reset");
-                            ResetEngine();
+                            ResetEngine(KeyMagicResetLevel_Context);
                         }
                     }
                     else
                     {
                         DEBUG_LOG(L"Failed to create edit session, falling back to reset");
-                        ResetEngine();
+                        ResetEngine(KeyMagicResetLevel_Context);
                     }
                 }
             }
             else
             {
                 // No context or engine, just reset
-                ResetEngine();
+                ResetEngine(KeyMagicResetLevel_Context);
             }
         }
         else
         {
             // No valid context, reset engine
-            ResetEngine();
+            ResetEngine(KeyMagicResetLevel_Context);
         }
         
         // Start event monitoring when gaining focus
@@ -399,6 +433,7 @@ STDAPI CKeyMagicTextService::OnSetFocus(ITfDocumentMgr *pdimFocus, ITfDocumentMg
         
         // Also reload registry settings immediately
         ReloadRegistrySettings();
+        SyncKeyProcessing();
     }
     else
     {
@@ -441,6 +476,11 @@ STDAPI CKeyMagicTextService::OnSetFocus(BOOL fForeground)
     else
     {
         DEBUG_LOG(L"Text service no longer active input processor");
+        // Key releases are not seen while in the background
+        if (m_pDoubleTap)
+        {
+            keymagic_double_tap_reset(m_pDoubleTap);
+        }
         // We keep the monitoring thread running but it won't actively wait when document focus is lost
         // Notify tray manager that we lost focus
         NotifyTrayManagerFocusChange(FALSE);
@@ -465,24 +505,34 @@ STDAPI CKeyMagicTextService::OnTestKeyDown(ITfContext *pic, WPARAM wParam, LPARA
         return S_OK;
     }
 
-    char character = MapVirtualKeyToChar(wParam, lParam);
-    DEBUG_LOG_KEY(L"OnTestKeyDown", wParam, lParam, character);
+    // Our own SendInput output is neither processed again nor taken for a
+    // hotkey, double-tap or watched shortcut
+    if (IsInjectedKey())
+    {
+        DEBUG_LOG(L"Skipping key event from our SendInput");
+        return S_OK;
+    }
 
-    // Check if this is our own SendInput by examining the extra info
-    // Skip this check on Windows 10 due to unreliable GetMessageExtraInfo behavior
-    if (!m_isWindows10)
+    // Every press reaches OnTestKeyDown, modifiers included
+    if (FeedDoubleTap(wParam, lParam, true))
     {
-        ULONG_PTR extraInfo = GetMessageExtraInfo();
-        if (extraInfo == KEYMAGIC_EXTRAINFO_SIGNATURE)
-        {
-            DEBUG_LOG(L"Skipping key event from our SendInput");
-            return S_OK;
-        }
+        return S_OK;
     }
-    else
+
+    // Checked before the on/off switch so the GUI's shortcuts work either way
+    if (TestWatchedShortcut(wParam, lParam, pfEaten))
+    {
+        return S_OK;
+    }
+
+    // Switched off by the GUI: keys go straight to the application
+    if (!IsKeyProcessingEnabled())
     {
-        DEBUG_LOG(L"Windows 10 detected - skipping signature check");
+        return S_OK;
     }
+
+    char character = MapVirtualKeyToChar(wParam, lParam);
+    DEBUG_LOG_KEY(L"OnTestKeyDown", wParam, lParam, character);
     
     // Also check time-based filtering for VK_BACK as GetMessageExtraInfo is not reliable
     // Skip VK_BACK if we recently sent input (within 50ms)
@@ -502,9 +552,21 @@ STDAPI CKeyMagicTextService::OnTestKeyDown(ITfContext *pic, WPARAM wParam, LPARA
     // Use engine test mode to determine if we should consume this key
     if (m_pEngine)
     {
+        // Passthrough keys, and the host's shortcut keys while nothing is
+        // composed, go straight to the application. When composing
+        // text must be committed first, take the key so OnKeyDown ends the
+        // composition; the engine leaves the key itself unprocessed.
+        int passthrough = keymagic_engine_check_passthrough(m_pEngine, static_cast<int>(wParam));
+        if (passthrough != KEYMAGIC_PASSTHROUGH_NONE)
+        {
+            DEBUG_LOG(L"Passthrough key: " + std::to_wstring(passthrough));
+            *pfEaten = (passthrough == KEYMAGIC_PASSTHROUGH_COMMIT) ? TRUE : FALSE;
+            return S_OK;
+        }
+
         // Prepare key input using the utility
         KeyProcessingUtils::KeyInputData keyInput = KeyProcessingUtils::PrepareKeyInput(wParam, lParam);
-        
+
         // Skip modifier and function keys
         if (keyInput.shouldSkip)
         {
@@ -572,13 +634,27 @@ STDAPI CKeyMagicTextService::OnKeyDown(ITfContext *pic, WPARAM wParam, LPARAM lP
         DEBUG_LOG(L"VK_PACKET - skipping");
         return S_OK;
     }
+
+    // Timed from here, for keys that reach the engine
+    KeyProcessingUtils::LatencySample latency;
     
+    // A watched shortcut OnTestKeyDown kept from the application
+    if (m_pendingShortcut != 0 && wParam == m_pendingShortcutKey)
+    {
+        unsigned int action = m_pendingShortcut;
+        m_pendingShortcut = 0;
+        // Held down, the key repeats; the shortcut fires once
+        if ((lParam & (1 << 30)) == 0)
+        {
+            FireWatchedShortcut(pic, action);
+        }
+        *pfEaten = TRUE;
+        return S_OK;
+    }
+
     // Mark that we're processing a key to help OnEndEdit
     m_isProcessingKey = true;
 
-    // Check if this is our own SendInput by examining the extra info
-    ULONG_PTR extraInfo = GetMessageExtraInfo();
-    
     // Special handling for VK_SPACE sent for termination (used to terminate composition when disabling TSF)
     if (wParam == VK_SPACE && m_lastTerminationSpaceTime > 0)
     {
@@ -620,14 +696,10 @@ STDAPI CKeyMagicTextService::OnKeyDown(ITfContext *pic, WPARAM wParam, LPARAM lP
     }
     
     // Skip other keys with our signature
-    // Skip this check on Windows 10 due to unreliable GetMessageExtraInfo behavior
-    if (!m_isWindows10)
+    if (IsInjectedKey())
     {
-        if (extraInfo == KEYMAGIC_EXTRAINFO_SIGNATURE)
-        {
-            DEBUG_LOG(L"Skipping key event from our SendInput");
-            return S_OK;
-        }
+        DEBUG_LOG(L"Skipping key event from our SendInput");
+        return S_OK;
     }
     
     // Also check time-based filtering for VK_BACK as GetMessageExtraInfo is not reliable
@@ -679,6 +751,13 @@ STDAPI CKeyMagicTextService::OnKeyDown(ITfContext *pic, WPARAM wParam, LPARAM lP
         }
     }
     
+    latency.Finish();
+
+    if (*pfEaten)
+    {
+        NoteEatenKey(wParam, lParam);
+    }
+    
     // Clear the processing flag after key is processed
     m_isProcessingKey = false;
 
@@ -691,6 +770,10 @@ STDAPI CKeyMagicTextService::OnTestKeyUp(ITfContext *pic, WPARAM wParam, LPARAM
         return E_INVALIDARG;
 
     *pfEaten = FALSE;
+    if (!IsInjectedKey())
+    {
+        FeedDoubleTap(wParam, lParam, false);
+    }
     return S_OK;
 }
 
@@ -718,30 +801,7 @@ STDAPI CKeyMagicTextService::OnPreservedKey(ITfContext *pic, REFGUID rguid, BOOL
         if (IsEqualGUID(rguid, preservedKey.guid))
         {
             DEBUG_LOG(L"Preserved key triggered for keyboard: " + preservedKey.keyboardId);
-            
-            // NOTE: We don't update registry here because TIP might run in containerized hosts
-            // The tray manager will update the registry and signal the global event
-            
-            // Reload the keyboard
-            LoadKeyboardByID(preservedKey.keyboardId);
-            
-            // Show HUD notification
-            // Get keyboard display name from registry using shared utility
-            std::wstring displayName = preservedKey.keyboardId;
-            KeyboardInfo kbInfo;
-            if (RegistryUtils::GetKeyboardInfoById(preservedKey.keyboardId, kbInfo))
-            {
-                if (!kbInfo.name.empty())
-                {
-                    displayName = kbInfo.name;
-                }
-            }
-            
-            KeyMagicHUD::GetInstance().ShowKeyboard(displayName);
-            
-            // Notify tray manager about the keyboard change
-            // The tray manager will update the registry and signal the global event
-            NotifyTrayManagerKeyboardChange();
+            ActivateKeyboardByHotkey(preservedKey.keyboardId);
             
             *pfEaten = TRUE;
             break;
@@ -752,6 +812,209 @@ STDAPI CKeyMagicTextService::OnPreservedKey(ITfContext *pic, REFGUID rguid, BOOL
     return S_OK;
 }
 
+// Switches to a keyboard from its hotkey, shows the HUD and tells the tray
+// manager. Call with m_cs held.
+void CKeyMagicTextService::ActivateKeyboardByHotkey(const std::wstring& keyboardId)
+{
+    int decision = m_pSwitchGuard ? keymagic_switch_guard_check(m_pSwitchGuard, GetTickCount64()) : 0;
+    if (decision == 1)
+    {
+        DEBUG_LOG(L"Too many keyboard switches - suppressing switches for a while");
+        KeyMagicHUD::GetInstance().ShowMessage(L"Keyboard switching paused: too many switches in a second");
+    }
+    if (decision != 0)
+    {
+        return;
+    }
+
+    // NOTE: We don't update registry here because TIP might run in containerized hosts
+    // The tray manager will update the registry and signal the global event
+
+    // Reload the keyboard
+    LoadKeyboardByID(keyboardId);
+
+    // Show HUD notification
+    // Get keyboard display name from registry using shared utility
+    std::wstring displayName = keyboardId;
+    std::wstring sampleText;
+    KeyboardInfo kbInfo;
+    if (RegistryUtils::GetKeyboardInfoById(keyboardId, kbInfo))
+    {
+        if (!kbInfo.name.empty())
+        {
+            displayName = kbInfo.name;
+        }
+
+        // Hint that this keyboard emits legacy-encoded text
+        if (kbInfo.outputEncoding == L"zawgyi")
+        {
+            displayName += L" (Zawgyi)";
+        }
+
+        sampleText = kbInfo.sampleText;
+    }
+
+    KeyMagicHUD::GetInstance().ShowKeyboard(displayName, sampleText);
+
+    // Notify tray manager about the keyboard change
+    // The tray manager will update the registry and signal the global event
+    NotifyTrayManagerKeyboardChange();
+}
+
+// Whether the key being handled was injected by our own SendInput.
+// GetMessageExtraInfo is unreliable on Windows 10, so the check is skipped
+// there and VK_BACK is filtered by time instead.
+bool CKeyMagicTextService::IsInjectedKey()
+{
+    if (m_isWindows10)
+    {
+        return false;
+    }
+    return keymagic_is_injected_input(GetMessageExtraInfo()) != 0;
+}
+
+// Passes a key press or release to the double-tap detector and switches
+// keyboards when it completes a double-tap. Returns true if it did.
+bool CKeyMagicTextService::FeedDoubleTap(WPARAM wParam, LPARAM lParam, bool keyDown)
+{
+    if (!m_pDoubleTap || m_doubleTapKeys.empty())
+        return false;
+    
+    // wParam has VK_SHIFT etc.; the scan code and extended flag tell the side
+    int vkCode = static_cast<int>(wParam);
+    UINT scanCode = (lParam >> 16) & 0xFF;
+    bool extended = (lParam & (1 << 24)) != 0;
+    switch (wParam)
+    {
+        case VK_SHIFT:
+            vkCode = static_cast<int>(MapVirtualKey(scanCode, MAPVK_VSC_TO_VK_EX));
+            if (vkCode == 0)
+                vkCode = VK_SHIFT;
+            break;
+        case VK_CONTROL:
+            vkCode = extended ? VK_RCONTROL : VK_LCONTROL;
+            break;
+        case VK_MENU:
+            vkCode = extended ? VK_RMENU : VK_LMENU;
+            break;
+    }
+    
+    ULONGLONG now = GetTickCount64();
+    if (!keyDown)
+    {
+        keymagic_double_tap_key_up(m_pDoubleTap, vkCode, now);
+        return false;
+    }
+    
+    int tapped = keymagic_double_tap_key_down(m_pDoubleTap, vkCode, now);
+    if (tapped == 0)
+        return false;
+    
+    EnterCriticalSection(&m_cs);
+    bool activated = false;
+    for (const auto& doubleTap : m_doubleTapKeys)
+    {
+        if (doubleTap.vkCode == tapped)
+        {
+            DEBUG_LOG(L"Double-tap triggered for keyboard: " + doubleTap.keyboardId);
+            ActivateKeyboardByHotkey(doubleTap.keyboardId);
+            activated = true;
+            break;
+        }
+    }
+    LeaveCriticalSection(&m_cs);
+    return activated;
+}
+
+// Checks a key against the shortcuts the GUI watches for. Returns true
+// when it is one and *pfEaten is decided; a shortcut the keyboard itself
+// uses is left to the keyboard.
+bool CKeyMagicTextService::TestWatchedShortcut(WPARAM wParam, LPARAM lParam, BOOL *pfEaten)
+{
+    m_pendingShortcut = 0;
+    if (!m_pShortcutWatch)
+        return false;
+
+    int shift = (GetKeyState(VK_SHIFT) & 0x8000) ? 1 : 0;
+    int ctrl = (GetKeyState(VK_CONTROL) & 0x8000) ? 1 : 0;
+    int alt = (GetKeyState(VK_MENU) & 0x8000) ? 1 : 0;
+    int swallow = 0;
+    unsigned int action = keymagic_shortcut_watch_find_win(m_pShortcutWatch, static_cast<int>(wParam), shift, ctrl, alt, &swallow);
+    if (action == 0)
+        return false;
+
+    if (m_pEngine && IsKeyProcessingEnabled())
+    {
+        KeyProcessingUtils::KeyInputData keyInput = KeyProcessingUtils::PrepareKeyInput(wParam, lParam);
+        ProcessKeyOutput testOutput = {0};
+        bool keyboardUsesKey = !keyInput.shouldSkip &&
+            keymagic_engine_process_key_test_win(m_pEngine, static_cast<int>(wParam), keyInput.character,
+                                                 keyInput.shift, keyInput.ctrl, keyInput.alt, keyInput.capsLock,
+                                                 &testOutput) == KeyMagicResult_Success &&
+            testOutput.is_processed;
+        if (testOutput.text) keymagic_free_string(testOutput.text);
+        if (testOutput.composing_text) keymagic_free_string(testOutput.composing_text);
+        if (keyboardUsesKey)
+        {
+            DEBUG_LOG(L"Watched shortcut is used by the keyboard - leaving it to the keyboard");
+            return false;
+        }
+    }
+
+    if (swallow)
+    {
+        // OnKeyDown commits the composition and reports the shortcut
+        m_pendingShortcut = action;
+        m_pendingShortcutKey = wParam;
+        *pfEaten = TRUE;
+        return true;
+    }
+
+    // The key reaches the application, so the composition stays as it is
+    if ((lParam & (1 << 30)) == 0)
+    {
+        DEBUG_LOG(L"Watched shortcut fired: " + std::to_wstring(action));
+        keymagic_shortcut_watch_report(m_pShortcutWatch, action);
+    }
+    *pfEaten = FALSE;
+    return true;
+}
+
+// Commits the composing text, then tells the GUI a swallowed shortcut fired
+void CKeyMagicTextService::FireWatchedShortcut(ITfContext *pic, unsigned int action)
+{
+    DEBUG_LOG(L"Watched shortcut fired: " + std::to_wstring(action));
+    EnterCriticalSection(&m_cs);
+
+    if (m_useCompositionEditSession && m_pEngine && m_pCompositionMgr)
+    {
+        CCompositionEditSession *pEditSession = new CCompositionEditSession(this, pic,
+                                                                          m_pCompositionMgr,
+                                                                          CCompositionEditSession::EditAction::TerminateComposition,
+                                                                          m_pEngine);
+        if (pEditSession)
+        {
+            HRESULT hr;
+            pic->RequestEditSession(m_tfClientId, pEditSession, TF_ES_SYNC | TF_ES_READWRITE, &hr);
+            pEditSession->Release();
+        }
+    }
+    else if (m_pEngine)
+    {
+        // Direct mode already wrote the composing text into the document;
+        // flushing keeps the engine from building on it later
+        char* committed = keymagic_engine_flush(m_pEngine);
+        if (committed)
+        {
+            KeyProcessingUtils::ReportCommit(committed);
+            keymagic_free_string(committed);
+        }
+    }
+
+    LeaveCriticalSection(&m_cs);
+    keymagic_shortcut_watch_report(m_pShortcutWatch, action);
+}
+
 // ITfTextEditSink
 STDAPI CKeyMagicTextService::OnEndEdit(ITfContext *pic, TfEditCookie ecReadOnly, ITfEditRecord *pEditRecord)
 {
@@ -817,7 +1080,7 @@ STDAPI CKeyMagicTextService::OnEndEdit(ITfContext *pic, TfEditCookie ecReadOnly,
         else
         {
             // No context available, fall back to reset
-            ResetEngine();
+            ResetEngine(KeyMagicResetLevel_Composing);
         }
     }
 
@@ -855,12 +1118,12 @@ STDAPI CKeyMagicTextService::OnMouseEvent(ULONG uEdge, ULONG uQuadrant, DWORD dw
                     if (FAILED(hr))
                     {
                         DEBUG_LOG(L"Failed to sync on mouse click, falling back to reset");
-                        ResetEngine();
+                        ResetEngine(KeyMagicResetLevel_Composing);
                     }
                 }
                 else
                 {
-                    ResetEngine();
+                    ResetEngine(KeyMagicResetLevel_Composing);
                 }
             }
             else
@@ -877,18 +1140,18 @@ STDAPI CKeyMagicTextService::OnMouseEvent(ULONG uEdge, ULONG uQuadrant, DWORD dw
                     if (FAILED(hr))
                     {
                         DEBUG_LOG(L"Failed to sync on mouse click, falling back to reset");
-                        ResetEngine();
+                        ResetEngine(KeyMagicResetLevel_Composing);
                     }
                 }
                 else
                 {
-                    ResetEngine();
+                    ResetEngine(KeyMagicResetLevel_Composing);
                 }
             }
         }
         else
         {
-            ResetEngine();
+            ResetEngine(KeyMagicResetLevel_Composing);
         }
     }
 
@@ -927,6 +1190,11 @@ HRESULT CKeyMagicTextService::RegisterPreservedKeys()
             hotkeyToUse = keyboard.hotkey;
             DEBUG_LOG(L"Using hotkey from registry for keyboard " + keyboard.id + L": " + hotkeyToUse);
         }
+        else if (keyboard.hasHotkey)
+        {
+            // An empty hotkey turns off the one in the KM2 file too
+            DEBUG_LOG(L"Hotkey turned off for keyboard " + keyboard.id);
+        }
         else if (!keyboard.path.empty())
         {
             // No hotkey in registry - try to get from KM2 file
@@ -938,8 +1206,19 @@ HRESULT CKeyMagicTextService::RegisterPreservedKeys()
             }
         }
         
+        // Double-tap hotkeys are detected from key events
+        int doubleTapVk = 0;
+        if (!hotkeyToUse.empty() &&
+            keymagic_parse_double_tap(KeyMagicUtils::ConvertUtf16ToUtf8(hotkeyToUse).c_str(), &doubleTapVk) == 1)
+        {
+            DoubleTapKeyInfo info;
+            info.keyboardId = keyboard.id;
+            info.vkCode = doubleTapVk;
+            m_doubleTapKeys.push_back(info);
+            DEBUG_LOG(L"Registered double-tap for keyboard: " + keyboard.id + L" with hotkey: " + hotkeyToUse);
+        }
         // Register the preserved key if we have a valid hotkey
-        if (!hotkeyToUse.empty())
+        else if (!hotkeyToUse.empty())
         {
             TF_PRESERVEDKEY tfKey;
             if (SUCCEEDED(ParseHotkeyString(hotkeyToUse, tfKey)))
@@ -972,6 +1251,16 @@ HRESULT CKeyMagicTextService::RegisterPreservedKeys()
             DEBUG_LOG(L"No hotkey registered for keyboard: " + keyboard.id + L" (disabled or not configured)");
         }
     }
+    
+    if (m_pDoubleTap)
+    {
+        std::vector<int> doubleTapVks;
+        for (const auto& doubleTap : m_doubleTapKeys)
+        {
+            doubleTapVks.push_back(doubleTap.vkCode);
+        }
+        keymagic_double_tap_set_keys(m_pDoubleTap, doubleTapVks.data(), doubleTapVks.size());
+    }
     return S_OK;
 }
 
@@ -986,6 +1275,11 @@ HRESULT CKeyMagicTextService::UnregisterPreservedKeys()
     }
     
     m_preservedKeys.clear();
+    m_doubleTapKeys.clear();
+    if (m_pDoubleTap)
+    {
+        keymagic_double_tap_set_keys(m_pDoubleTap, nullptr, 0);
+    }
     return S_OK;
 }
 
@@ -1145,8 +1439,45 @@ BOOL CKeyMagicTextService::LoadKeyboard(const std::wstring& km2Path)
     if (!m_pEngine)
         return FALSE;
 
-    std::string utf8Path = KeyMagicUtils::ConvertUtf16ToUtf8(km2Path);
-    KeyMagicResult result = keymagic_engine_load_keyboard(m_pEngine, utf8Path.c_str());
+    // Text services activated on several threads of one application can
+    // share one engine per keyboard, so they do not fight over composition
+    DWORD shareEngines = 0;
+    RegistryUtils::ReadKeyMagicSetting(L"ShareEngines", shareEngines);
+    keymagic_engine_set_sharing(shareEngines != 0 ? 1 : 0);
+    if (shareEngines != 0)
+    {
+        EngineHandle* shared = keymagic_engine_acquire_w(reinterpret_cast<const uint16_t*>(km2Path.c_str()));
+        if (shared)
+        {
+            keymagic_engine_free(m_pEngine);
+            m_pEngine = shared;
+            m_currentKeyboardPath = km2Path;
+            DEBUG_LOG(L"Keyboard loaded into shared engine (" +
+                      std::to_wstring(keymagic_debug_instance_count()) + L" engines in process): " + km2Path);
+            return TRUE;
+        }
+
+        DEBUG_LOG(L"Failed to load keyboard: " + km2Path);
+        LoadFallbackKeyboard();
+        return FALSE;
+    }
+
+    // Pass the path as UTF-16 so long and non-UTF-8 paths survive
+    KeyMagicResult result = keymagic_engine_load_keyboard_w(
+        m_pEngine, reinterpret_cast<const uint16_t*>(km2Path.c_str()));
+    if (result == KeyMagicResult_ErrorInvalidParameter)
+    {
+        // Still holding an engine shared with other threads from before
+        // sharing was turned off; it keeps its keyboard, so use our own
+        EngineHandle* engine = keymagic_engine_new();
+        if (engine)
+        {
+            keymagic_engine_free(m_pEngine);
+            m_pEngine = engine;
+            result = keymagic_engine_load_keyboard_w(
+                m_pEngine, reinterpret_cast<const uint16_t*>(km2Path.c_str()));
+        }
+    }
     
     if (result == KeyMagicResult_Success)
     {
@@ -1156,9 +1487,61 @@ BOOL CKeyMagicTextService::LoadKeyboard(const std::wstring& km2Path)
     }
 
     DEBUG_LOG(L"Failed to load keyboard: " + km2Path);
+    LoadFallbackKeyboard();
     return FALSE;
 }
 
+// Loads keyboard data kept in the registry. Engines are shared by file
+// path, so the copy always gets an engine of its own.
+BOOL CKeyMagicTextService::LoadKeyboardFromMemory(const std::vector<BYTE>& data, const std::wstring& keyboardId)
+{
+    EngineHandle* engine = keymagic_engine_new();
+    if (!engine)
+        return FALSE;
+    
+    if (keymagic_engine_load_keyboard_from_memory(engine, data.data(), data.size()) != KeyMagicResult_Success)
+    {
+        keymagic_engine_free(engine);
+        DEBUG_LOG(L"Failed to load keyboard from the registry: " + keyboardId);
+        LoadFallbackKeyboard();
+        return FALSE;
+    }
+    
+    if (m_pEngine)
+        keymagic_engine_free(m_pEngine);
+    m_pEngine = engine;
+    m_currentKeyboardPath.clear();
+    DEBUG_LOG(L"Keyboard file missing, loaded the copy from the registry: " + keyboardId);
+    return TRUE;
+}
+
+// Leaves the text service with an engine that passes every key through
+// rather than a half-loaded one. A fresh handle is used so engines shared
+// with other threads keep their keyboard.
+void CKeyMagicTextService::LoadFallbackKeyboard()
+{
+    char* error = keymagic_engine_last_error();
+    if (error)
+    {
+        DEBUG_LOG(L"Keyboard load error: " + KeyMagicUtils::ConvertUtf8ToUtf16(error));
+        keymagic_free_string(error);
+    }
+
+    EngineHandle* engine = keymagic_engine_new();
+    if (!engine)
+        return;
+    if (keymagic_engine_load_fallback(engine) != KeyMagicResult_Success)
+    {
+        keymagic_engine_free(engine);
+        return;
+    }
+    if (m_pEngine)
+        keymagic_engine_free(m_pEngine);
+    m_pEngine = engine;
+    m_currentKeyboardPath.clear();
+    DEBUG_LOG(L"Using the fallback keyboard");
+}
+
 BOOL CKeyMagicTextService::LoadKeyboardByID(const std::wstring& keyboardId)
 {
     if (keyboardId.empty())
@@ -1183,13 +1566,6 @@ BOOL CKeyMagicTextService::LoadKeyboardByID(const std::wstring& keyboardId)
         return FALSE;
     }
     
-    // Check if keyboard has a valid path
-    if (kbInfo.path.empty())
-    {
-        DEBUG_LOG(L"Keyboard has no path configured: " + keyboardId);
-        return FALSE;
-    }
-    
     // Check if keyboard is enabled
     if (!kbInfo.enabled)
     {
@@ -1197,14 +1573,86 @@ BOOL CKeyMagicTextService::LoadKeyboardByID(const std::wstring& keyboardId)
         return FALSE;
     }
     
-    // Load the keyboard
-    BOOL result = LoadKeyboard(kbInfo.path.c_str());
+    // Load the keyboard file, or the copy kept in the registry when the
+    // file is gone (locked-down machines that strip the keyboards folder)
+    BOOL result = FALSE;
+    std::vector<BYTE> embeddedData;
+    bool fileMissing = kbInfo.path.empty() || GetFileAttributesW(kbInfo.path.c_str()) == INVALID_FILE_ATTRIBUTES;
+    if (fileMissing && RegistryUtils::ReadKeyboardData(keyboardId, embeddedData))
+    {
+        result = LoadKeyboardFromMemory(embeddedData, keyboardId);
+    }
+    else if (kbInfo.path.empty())
+    {
+        DEBUG_LOG(L"Keyboard has no path configured: " + keyboardId);
+        return FALSE;
+    }
+    else
+    {
+        result = LoadKeyboard(kbInfo.path.c_str());
+    }
     
     if (result)
     {
         // Store keyboard info
         m_currentKeyboardId = keyboardId;
         
+        // Apply the keyboard's output encoding (transform is reset by each load)
+        int transformId = (kbInfo.outputEncoding == L"zawgyi") ? 1 : 0;
+        if (keymagic_engine_set_output_transform(m_pEngine, transformId) != KeyMagicResult_Success)
+        {
+            DEBUG_LOG(L"Failed to set output transform for keyboard: " + keyboardId);
+        }
+        
+        // Apply the user's rule group toggles (also reset by each load)
+        for (const auto& group : kbInfo.disabledGroups)
+        {
+            std::string utf8Group = KeyMagicUtils::ConvertUtf16ToUtf8(group);
+            if (keymagic_engine_set_group_enabled(m_pEngine, utf8Group.c_str(), 0) != KeyMagicResult_Success)
+            {
+                DEBUG_LOG(L"Ignoring unknown rule group: " + group);
+            }
+        }
+        
+        // Apply the user's layout option overrides (also reset by each load)
+        for (const auto& entry : kbInfo.optionOverrides)
+        {
+            size_t separator = entry.find(L'=');
+            if (separator == std::wstring::npos)
+            {
+                DEBUG_LOG(L"Ignoring malformed option override: " + entry);
+                continue;
+            }
+            std::string utf8Name = KeyMagicUtils::ConvertUtf16ToUtf8(entry.substr(0, separator));
+            int value = (entry.substr(separator + 1) == L"1") ? 1 : 0;
+            if (keymagic_engine_set_layout_option(m_pEngine, utf8Name.c_str(), value) != KeyMagicResult_Success)
+            {
+                DEBUG_LOG(L"Ignoring unknown layout option: " + entry);
+            }
+        }
+        
+        // Apply the passthrough keys (also reset by each load)
+        std::vector<int> passthroughVks;
+        for (const auto& keyName : kbInfo.passthroughKeys)
+        {
+            std::string utf8Name = KeyMagicUtils::ConvertUtf16ToUtf8(keyName);
+            int vk = keymagic_vk_from_name(utf8Name.c_str());
+            if (vk == 0)
+            {
+                DEBUG_LOG(L"Ignoring unknown passthrough key: " + keyName);
+                continue;
+            }
+            passthroughVks.push_back(vk);
+        }
+        if (keymagic_engine_set_passthrough_keys(m_pEngine, passthroughVks.data(), static_cast<int>(passthroughVks.size()),
+                                                 kbInfo.commitBeforePassthrough ? 1 : 0) != KeyMagicResult_Success)
+        {
+            DEBUG_LOG(L"Failed to set passthrough keys for keyboard: " + keyboardId);
+        }
+        
+        // The host's shortcut keys are reset by each load too
+        ApplyShortcutKeys();
+        
         DEBUG_LOG(L"Loaded keyboard: " + kbInfo.name + L" (" + keyboardId + L")");
         
         // Notify tray manager of keyboard change
@@ -1214,14 +1662,83 @@ BOOL CKeyMagicTextService::LoadKeyboardByID(const std::wstring& keyboardId)
     return result;
 }
 
-void CKeyMagicTextService::ResetEngine()
+void CKeyMagicTextService::SyncKeyProcessing()
+{
+    if (!m_pProcessing)
+        return;
+
+    uint64_t request = 0;
+    int action = keymagic_processing_observe(m_pProcessing, &request);
+    if (action == KEYMAGIC_PROCESSING_ENABLE)
+    {
+        DEBUG_LOG(L"Key processing switched on");
+        return;
+    }
+    if (action != KEYMAGIC_PROCESSING_FLUSH)
+        return;
+
+    DEBUG_LOG(L"Key processing switched off - committing composition for request " + std::to_wstring(request));
+    EnterCriticalSection(&m_cs);
+
+    bool requested = false;
+    if (m_useCompositionEditSession && m_pTextEditContext && m_pCompositionMgr && m_pEngine)
+    {
+        // The composition can only be ended in an edit session, which calls
+        // FinishDisable once the text is committed
+        CCompositionEditSession *pEditSession = new CCompositionEditSession(this, m_pTextEditContext,
+                                                                          m_pCompositionMgr,
+                                                                          CCompositionEditSession::EditAction::TerminateComposition,
+                                                                          m_pEngine);
+        if (pEditSession)
+        {
+            pEditSession->SetDisableRequest(request);
+            HRESULT hr = E_FAIL;
+            requested = SUCCEEDED(m_pTextEditContext->RequestEditSession(m_tfClientId, pEditSession,
+                                                                         TF_ES_ASYNCDONTCARE | TF_ES_READWRITE, &hr));
+            pEditSession->Release();
+        }
+    }
+
+    if (!requested)
+    {
+        // Direct mode already wrote the composing text into the document;
+        // flushing keeps the engine from building on it later
+        if (m_pEngine)
+        {
+            char* committed = keymagic_engine_flush(m_pEngine);
+            if (committed)
+            {
+                KeyProcessingUtils::ReportCommit(committed);
+                keymagic_free_string(committed);
+            }
+        }
+        FinishDisable(request);
+    }
+
+    LeaveCriticalSection(&m_cs);
+}
+
+void CKeyMagicTextService::FinishDisable(uint64_t request)
+{
+    if (m_pProcessing && keymagic_processing_finish_disable(m_pProcessing, request))
+    {
+        DEBUG_LOG(L"Key processing off - acknowledged request " + std::to_wstring(request));
+    }
+}
+
+bool CKeyMagicTextService::IsKeyProcessingEnabled()
+{
+    return !m_pProcessing || keymagic_processing_is_enabled(m_pProcessing);
+}
+
+void CKeyMagicTextService::ResetEngine(int level)
 {
     DEBUG_LOG_FUNC();
     EnterCriticalSection(&m_cs);
     
     if (m_pEngine)
     {
-        keymagic_engine_reset(m_pEngine);
+        keymagic_engine_reset_level(m_pEngine, level);
         DEBUG_LOG(L"Engine reset completed");
     }
     
@@ -1437,22 +1954,61 @@ HRESULT CKeyMagicTextService::CreateDisplayAttributeInfo()
 
 
 // Settings update method
-void CKeyMagicTextService::UpdateSettings(const std::wstring& keyboardId)
+void CKeyMagicTextService::UpdateSettings(const std::wstring& keyboardId, const std::wstring& temporaryPath)
 {
-    DEBUG_LOG(L"UpdateSettings: keyboard=" + keyboardId);
+    DEBUG_LOG(L"UpdateSettings: keyboard=" + keyboardId + L", temporary=" + temporaryPath);
     
     EnterCriticalSection(&m_cs);
     
+    // A keyboard being tried out wins over the default one
+    if (!temporaryPath.empty())
+    {
+        if (temporaryPath != m_temporaryKeyboardPath && LoadKeyboard(temporaryPath))
+        {
+            DEBUG_LOG(L"Trying out keyboard: " + temporaryPath);
+            m_temporaryKeyboardPath = temporaryPath;
+            // Load the default keyboard again once the trial ends
+            m_currentKeyboardId.clear();
+        }
+        LeaveCriticalSection(&m_cs);
+        return;
+    }
+    m_temporaryKeyboardPath.clear();
+    
     // Update keyboard if changed
     if (!keyboardId.empty() && keyboardId != m_currentKeyboardId)
     {
         DEBUG_LOG(L"Default keyboard changed from \"" + m_currentKeyboardId + L"\" to \"" + keyboardId + L"\"");
         LoadKeyboardByID(keyboardId);
     }
+    else if (IsLoadedKeyboardStale(keyboardId))
+    {
+        // The keyboard file was replaced since the engine loaded it
+        DEBUG_LOG(L"Keyboard file changed, reloading: " + keyboardId);
+        LoadKeyboardByID(keyboardId);
+    }
+    else
+    {
+        ApplyShortcutKeys();
+    }
     
     LeaveCriticalSection(&m_cs);
 }
 
+bool CKeyMagicTextService::IsLoadedKeyboardStale(const std::wstring& keyboardId)
+{
+    if (keyboardId.empty() || !m_pEngine)
+        return false;
+    
+    KeyboardInfo kbInfo;
+    if (!RegistryUtils::GetKeyboardInfoById(keyboardId, kbInfo))
+        return false;
+    
+    // An empty hash (GUI versions that did not record one) never reloads
+    std::string utf8Hash = KeyMagicUtils::ConvertUtf16ToUtf8(kbInfo.hash);
+    return keymagic_engine_needs_reload(m_pEngine, utf8Hash.c_str()) != 0;
+}
+
 // Registry reload implementation
 void CKeyMagicTextService::ReloadRegistrySettings()
 {
@@ -1468,66 +2024,198 @@ void CKeyMagicTextService::ReloadRegistrySettings()
     // Determine UseCompositionEditSession based on current process
     m_useCompositionEditSession = ShouldUseCompositionEditSession();
     
+    // Off unless enabled: skips the composition for keys no rule can build on
+    DWORD eagerCommit = 0;
+    RegistryUtils::ReadKeyMagicSetting(L"EagerCommit", eagerCommit);
+    m_eagerCommit = eagerCommit != 0;
+    
+    UpdateCharFilter();
+    
+    std::wstring temporaryKeyboard = RegistryUtils::GetTemporaryKeyboardPath();
+    
     // Apply settings
-    UpdateSettings(defaultKeyboard);
+    UpdateSettings(defaultKeyboard, temporaryKeyboard);
 }
 
-// Composition edit session determination
-bool CKeyMagicTextService::ShouldUseCompositionEditSession()
+// Single-key shortcuts of this host, passed through while nothing is composed
+void CKeyMagicTextService::ApplyShortcutKeys()
 {
-    // Get the effective process name (handles parent process detection for WebView2)
-    std::wstring processToCheck = ProcessDetector::GetEffectiveProcessName();
+    if (!m_pEngine)
+        return;
     
-    DEBUG_LOG(L"Checking composition mode for process: " + processToCheck);
+    std::vector<std::wstring> entries;
+    RegistryUtils::ReadKeyMagicSetting(L"ShortcutPassthroughKeys", entries);
     
-    // Read the list of executables that should use composition mode from registry
-    std::vector<std::wstring> compositionModeHosts;
-    if (RegistryUtils::ReadKeyMagicSetting(L"CompositionModeHosts", compositionModeHosts))
+    std::vector<const uint16_t*> pointers;
+    for (const auto& entry : entries)
+    {
+        pointers.push_back(reinterpret_cast<const uint16_t*>(entry.c_str()));
+    }
+    
+    std::wstring processName = ProcessDetector::GetEffectiveProcessName();
+    if (keymagic_engine_set_shortcut_keys_w(m_pEngine,
+                                            reinterpret_cast<const uint16_t*>(processName.c_str()),
+                                            pointers.data(),
+                                            static_cast<int>(pointers.size())) != KeyMagicResult_Success)
     {
-        // Check if current process is in the list
-        for (const auto& processName : compositionModeHosts)
+        DEBUG_LOG(L"Failed to set shortcut keys for process: " + processName);
+    }
+}
+
+// Installs the character filter for hosts on the opt-in list. A filter
+// installed because doubling was detected stays until deactivation.
+void CKeyMagicTextService::UpdateCharFilter()
+{
+    std::vector<std::wstring> hosts;
+    RegistryUtils::ReadKeyMagicSetting(L"CharSuppressionHosts", hosts);
+    
+    std::vector<const uint16_t*> pointers;
+    for (const auto& host : hosts)
+    {
+        pointers.push_back(reinterpret_cast<const uint16_t*>(host.c_str()));
+    }
+    
+    std::wstring processName = ProcessDetector::GetEffectiveProcessName();
+    bool listed = keymagic_char_filter_host_listed_w(reinterpret_cast<const uint16_t*>(processName.c_str()),
+                                                     pointers.data(),
+                                                     static_cast<int>(pointers.size())) != 0;
+    
+    EnterCriticalSection(&m_cs);
+    if (listed && !m_hCharFilterHook)
+    {
+        InstallCharFilter(false);
+    }
+    else if (!listed && m_hCharFilterHook && !m_charFilterDetected)
+    {
+        RemoveCharFilter();
+    }
+    LeaveCriticalSection(&m_cs);
+}
+
+void CKeyMagicTextService::InstallCharFilter(bool detected)
+{
+    EnterCriticalSection(&m_cs);
+    if (!m_hCharFilterHook && m_dwUiThreadId != 0)
+    {
+        // The hook only sees the UI thread's queue, so it costs other
+        // threads nothing
+        m_hCharFilterHook = SetWindowsHookExW(WH_GETMESSAGE, CharFilterProc, nullptr, m_dwUiThreadId);
+        if (m_hCharFilterHook)
         {
-            // Convert to lowercase for comparison
-            std::wstring lowerProcessName = processName;
-            std::transform(lowerProcessName.begin(), lowerProcessName.end(), lowerProcessName.begin(), ::towlower);
-            
-            if (processToCheck == lowerProcessName)
+            m_charFilterDetected = detected;
+            keymagic_char_filter_hook_installed(detected ? 1 : 0);
+            DEBUG_LOG(detected ? L"Character filter installed: doubling detected"
+                               : L"Character filter installed: host is listed");
+        }
+        else
+        {
+            DEBUG_LOG(L"Failed to install character filter. Error: " + std::to_wstring(GetLastError()));
+        }
+    }
+    LeaveCriticalSection(&m_cs);
+}
+
+void CKeyMagicTextService::RemoveCharFilter()
+{
+    EnterCriticalSection(&m_cs);
+    if (m_hCharFilterHook)
+    {
+        UnhookWindowsHookEx(m_hCharFilterHook);
+        m_hCharFilterHook = nullptr;
+        m_charFilterDetected = false;
+        keymagic_char_filter_forget_thread(m_dwUiThreadId);
+    }
+    LeaveCriticalSection(&m_cs);
+}
+
+// Records a key OnKeyDown ate. Without a filter yet, a WM_CHAR of the key
+// already in the queue means the host translated it before we saw it.
+void CKeyMagicTextService::NoteEatenKey(WPARAM wParam, LPARAM lParam)
+{
+    int scanCode = static_cast<int>((lParam >> 16) & 0x1FF);
+    keymagic_char_filter_record_eaten(GetCurrentThreadId(), static_cast<int>(wParam), scanCode, GetTickCount64());
+    
+    if (m_hCharFilterHook)
+        return;
+    
+    MSG msg;
+    if (PeekMessageW(&msg, nullptr, WM_CHAR, WM_CHAR, PM_NOREMOVE | PM_NOYIELD) &&
+        static_cast<int>((msg.lParam >> 16) & 0x1FF) == scanCode)
+    {
+        // The queued character is dropped once the hook is in place
+        InstallCharFilter(true);
+    }
+}
+
+// WH_GETMESSAGE hook: turns the stray character of an eaten key into
+// WM_NULL as the UI thread takes it from its queue
+LRESULT CALLBACK CKeyMagicTextService::CharFilterProc(int nCode, WPARAM wParam, LPARAM lParam)
+{
+    if (nCode == HC_ACTION && wParam == PM_REMOVE)
+    {
+        MSG* msg = reinterpret_cast<MSG*>(lParam);
+        bool isChar = msg->message == WM_CHAR ||
+                      (msg->message == WM_UNICHAR && msg->wParam != UNICODE_NOCHAR);
+        if (isChar)
+        {
+            int scanCode = static_cast<int>((msg->lParam >> 16) & 0x1FF);
+            if (keymagic_char_filter_should_drop(GetCurrentThreadId(), scanCode, GetTickCount64()))
             {
-                DEBUG_LOG(L"Process found in composition mode list: " + processToCheck);
-                return true;
+                DEBUG_LOG(L"Dropped stray character of an eaten key");
+                msg->message = WM_NULL;
             }
         }
-        
-        DEBUG_LOG(L"Process not found in composition mode list: " + processToCheck);
-        return false;  // Not in the list, use direct mode
     }
-    else
+    return CallNextHookEx(nullptr, nCode, wParam, lParam);
+}
+
+// Composition edit session determination
+bool CKeyMagicTextService::ShouldUseCompositionEditSession()
+{
+    // Get the effective process name (handles parent process detection for WebView2)
+    std::wstring processToCheck = ProcessDetector::GetEffectiveProcessName();
+    
+    DEBUG_LOG(L"Checking composition mode for process: " + processToCheck);
+    
+    // Read the host lists from registry; patterns may use * and ? wildcards
+    std::vector<std::wstring> compositionModeHosts;
+    if (!RegistryUtils::ReadKeyMagicSetting(L"CompositionModeHosts", compositionModeHosts))
     {
         DEBUG_LOG(L"CompositionModeHosts value not found, using default list");
         
         // Use default list of processes that should use composition mode
-        std::vector<std::wstring> defaultProcesses = {
+        compositionModeHosts = {
             L"ms-teams.exe",
             L"excel.exe"
         };
-        
-        // Check if current process is in the default list
-        for (const auto& process : defaultProcesses)
-        {
-            std::wstring lowerProcess = process;
-            std::transform(lowerProcess.begin(), lowerProcess.end(), lowerProcess.begin(), ::towlower);
-            
-            if (processToCheck == lowerProcess)
-            {
-                DEBUG_LOG(L"Process found in default composition mode list: " + processToCheck);
-                return true;
-            }
-        }
-        
-        DEBUG_LOG(L"Process not found in default composition mode list: " + processToCheck);
     }
     
-    return false;  // Default to direct mode
+    std::vector<std::wstring> directModeHosts;
+    RegistryUtils::ReadKeyMagicSetting(L"DirectModeHosts", directModeHosts);
+    
+    auto toPointers = [](const std::vector<std::wstring>& hosts) {
+        std::vector<const uint16_t*> pointers;
+        for (const auto& host : hosts)
+        {
+            pointers.push_back(reinterpret_cast<const uint16_t*>(host.c_str()));
+        }
+        return pointers;
+    };
+    std::vector<const uint16_t*> compositionPointers = toPointers(compositionModeHosts);
+    std::vector<const uint16_t*> directPointers = toPointers(directModeHosts);
+    
+    // Resolution is shared with the GUI, which shows the mode published here
+    bool useComposition = keymagic_resolve_input_mode_w(
+        reinterpret_cast<const uint16_t*>(processToCheck.c_str()),
+        compositionPointers.data(),
+        static_cast<int>(compositionPointers.size()),
+        directPointers.data(),
+        static_cast<int>(directPointers.size()),
+        0  // Default to direct mode
+    ) != 0;
+    
+    DEBUG_LOG(std::wstring(L"Process ") + processToCheck + (useComposition ? L" uses composition mode" : L" uses direct mode"));
+    return useComposition;
 }
 
 // Event monitoring implementation
@@ -1675,6 +2363,9 @@ DWORD WINAPI CKeyMagicTextService::EventMonitorThreadProc(LPVOID lpParam)
                 // Reload registry settings
                 pThis->ReloadRegistrySettings();
                 
+                // Commit the composition if processing was switched off
+                pThis->SyncKeyProcessing();
+                
                 // Update preserved keys in case hotkeys changed
                 pThis->UpdatePreservedKeys();
             }
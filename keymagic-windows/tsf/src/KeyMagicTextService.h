@@ -69,8 +69,12 @@ private:
     void UninitializeEngine();
     HKEY OpenSettingsKey(REGSAM samDesired);
     BOOL LoadKeyboard(const std::wstring& km2Path);
+    BOOL LoadKeyboardFromMemory(const std::vector<BYTE>& data, const std::wstring& keyboardId);
+    void LoadFallbackKeyboard();
     BOOL LoadKeyboardByID(const std::wstring& keyboardId);
-    void ResetEngine();
+    bool IsLoadedKeyboardStale(const std::wstring& keyboardId);
+    // Clears the engine up to a KeyMagicResetLevel
+    void ResetEngine(int level = KeyMagicResetLevel_Full);
     bool IsWindows10();
     
     
@@ -89,11 +93,28 @@ private:
     HRESULT CreateDisplayAttributeInfo();
     
     // Settings update notification
-    void UpdateSettings(const std::wstring& keyboardId);
+    void UpdateSettings(const std::wstring& keyboardId, const std::wstring& temporaryPath);
     
     // Composition edit session determination
     bool ShouldUseCompositionEditSession();
     
+    // Host shortcut keys from the ShortcutPassthroughKeys setting
+    void ApplyShortcutKeys();
+    
+    // Some frameworks translate a key before the text service sees it, so
+    // the WM_CHAR of a key we eat still reaches the window. A message hook on
+    // the UI thread drops it; it is installed for hosts on the
+    // CharSuppressionHosts list, or once OnKeyDown finds the character of an
+    // eaten key already queued.
+    HHOOK m_hCharFilterHook;
+    bool m_charFilterDetected;
+    DWORD m_dwUiThreadId;
+    void UpdateCharFilter();
+    void InstallCharFilter(bool detected);
+    void RemoveCharFilter();
+    void NoteEatenKey(WPARAM wParam, LPARAM lParam);
+    static LRESULT CALLBACK CharFilterProc(int nCode, WPARAM wParam, LPARAM lParam);
+    
     // Member variables
     LONG m_cRef;
     ITfThreadMgr *m_pThreadMgr;
@@ -108,6 +129,7 @@ private:
     EngineHandle *m_pEngine;
     std::wstring m_currentKeyboardPath;
     std::wstring m_currentKeyboardId;
+    std::wstring m_temporaryKeyboardPath;
     
     // Critical section for thread safety
     CRITICAL_SECTION m_cs;
@@ -118,6 +140,8 @@ private:
     // Configuration methods
     void SetUseCompositionEditSession(bool useComposition) { m_useCompositionEditSession = useComposition; }
     bool GetUseCompositionEditSession() const { return m_useCompositionEditSession; }
+    bool GetEagerCommit() const { return m_eagerCommit; }
+    const std::wstring& GetCurrentKeyboardId() const { return m_currentKeyboardId; }
     
     // Composition manager
     CCompositionManager *m_pCompositionMgr;
@@ -125,20 +149,19 @@ private:
     
     // Edit session mode flag
     bool m_useCompositionEditSession;  // If true, use CCompositionEditSession; if false, use CDirectEditSession
+    bool m_eagerCommit;  // Commit keys no rule can build on instead of composing them
     
     // Display attributes
     ITfDisplayAttributeInfo **m_ppDisplayAttributeInfo;
     ULONG m_displayAttributeInfoCount;
     TfGuidAtom m_inputDisplayAttributeAtom;
     
-    // SendInput signatures
-    static const ULONG_PTR KEYMAGIC_EXTRAINFO_SIGNATURE = 0x4B4D5453; // "KMTS" in hex
-    
     // Processing state
     bool m_isProcessingKey;
     DWORD m_lastSendInputTime;
     DWORD m_lastTerminationSpaceTime;  // Timestamp when we send SPACE for composition termination
     bool m_isWindows10;  // Flag to detect Windows 10 for signature filtering workaround
+    bool IsInjectedKey();  // Whether the key being handled came from our SendInput
     
     // Event monitoring
     HANDLE m_hRegistryUpdateEvent;
@@ -163,6 +186,39 @@ private:
     HRESULT UpdatePreservedKeys();
     HRESULT ParseHotkeyString(const std::wstring& hotkeyStr, TF_PRESERVEDKEY& tfKey);
     GUID GenerateGuidForKeyboard(const std::wstring& keyboardId);
+    void ActivateKeyboardByHotkey(const std::wstring& keyboardId);
+    
+    // Stops switch loops, e.g. injected input re-triggering a hotkey: past
+    // a burst of switches further ones are dropped for a while
+    SwitchGuardHandle *m_pSwitchGuard;
+    
+    // Double-tap hotkeys ("Shift Shift") cannot be preserved keys; the key
+    // event sink feeds every press and release to a detector instead
+    struct DoubleTapKeyInfo {
+        std::wstring keyboardId;
+        int vkCode;
+    };
+    std::vector<DoubleTapKeyInfo> m_doubleTapKeys;
+    DoubleTapHandle *m_pDoubleTap;
+    bool FeedDoubleTap(WPARAM wParam, LPARAM lParam, bool keyDown);
+    
+    // Key processing switch set by the GUI. Turning it off commits the
+    // composition before keys stop reaching the engine; the GUI waits for
+    // the acknowledgement sent by FinishDisable.
+    ProcessingHandle *m_pProcessing;
+    void SyncKeyProcessing();
+    void FinishDisable(uint64_t request);
+    bool IsKeyProcessingEnabled();
+    
+    // Shortcuts the GUI watches for instead of registering a system-wide
+    // hotkey. OnTestKeyDown checks keys the keyboard leaves alone; one the
+    // GUI wants swallowed is eaten and handled in OnKeyDown, which commits
+    // the composition before reporting it.
+    ShortcutWatchHandle *m_pShortcutWatch;
+    unsigned int m_pendingShortcut;
+    WPARAM m_pendingShortcutKey;
+    bool TestWatchedShortcut(WPARAM wParam, LPARAM lParam, BOOL *pfEaten);
+    void FireWatchedShortcut(ITfContext *pic, unsigned int action);
     
     // Tray client for communicating with tray manager
     std::unique_ptr<TrayClient> m_pTrayClient;
@@ -1,4 +1,8 @@
 #include "KeyProcessingUtils.h"
+#include "HUD.h"
+#include "ProcessDetector.h"
+#include "../../shared/include/KeyMagicUtils.h"
+#include <InputScope.h>
 
 namespace KeyProcessingUtils
 {
@@ -60,4 +64,146 @@ namespace KeyProcessingUtils
                (wParam >= VK_F1 && wParam <= VK_F24);
     }
     
-}
\ No newline at end of file
+    uint64_t RecordKeyEvent(WPARAM wParam, const KeyInputData& keyInput, const ProcessKeyOutput& output)
+    {
+        // One recorder per process; it attaches to the GUI's ring on its own
+        static RecorderHandle* recorder = keymagic_recorder_open();
+        static const std::wstring processName = ProcessDetector::GetEffectiveProcessName();
+
+        return keymagic_recorder_record(
+            recorder,
+            reinterpret_cast<const uint16_t*>(processName.c_str()),
+            static_cast<int>(wParam),
+            keyInput.shift, keyInput.ctrl, keyInput.alt, keyInput.capsLock,
+            &output
+        );
+    }
+    
+    static CompositionJournalHandle* GetCompositionJournal()
+    {
+        // One handle per process; it attaches to the GUI's journal on its own
+        static CompositionJournalHandle* journal = keymagic_composition_journal_open();
+        return journal;
+    }
+
+    void ReportCommit(const char* text)
+    {
+        if (!text || !*text)
+            return;
+
+        // One log per process; it attaches to the GUI's ring on its own
+        static CommitLogHandle* commitLog = keymagic_commit_log_open();
+        keymagic_commit_log_report(commitLog, text);
+
+        keymagic_composition_journal_clear(GetCompositionJournal());
+    }
+
+    void JournalComposition(TfEditCookie ec, ITfContext* pContext, const char* composingText)
+    {
+        if (!composingText || IsPasswordField(ec, pContext))
+            return;
+
+        static const std::wstring processName = ProcessDetector::GetEffectiveProcessName();
+        keymagic_composition_journal_report_w(
+            GetCompositionJournal(),
+            reinterpret_cast<const uint16_t*>(processName.c_str()),
+            composingText);
+    }
+
+    void CountKey(TfEditCookie ec, ITfContext* pContext, const std::wstring& keyboardId, WPARAM wParam)
+    {
+        // One handle per process; it attaches to the GUI's counters on its own
+        static KeyStatsHandle* keyStats = keymagic_key_stats_open();
+
+        if (keyboardId.empty() || !keymagic_key_stats_is_enabled(keyStats))
+            return;
+
+        keymagic_key_stats_set_suppressed(keyStats, IsPasswordField(ec, pContext) ? 1 : 0);
+        keymagic_key_stats_record_w(keyStats, reinterpret_cast<const uint16_t*>(keyboardId.c_str()), static_cast<int>(wParam));
+    }
+
+    bool IsPasswordField(TfEditCookie ec, ITfContext* pContext)
+    {
+        if (!pContext)
+            return false;
+
+        TF_SELECTION selection = {};
+        ULONG fetched = 0;
+        if (FAILED(pContext->GetSelection(ec, TF_DEFAULT_SELECTION, 1, &selection, &fetched)) || fetched == 0)
+            return false;
+
+        bool password = false;
+        ITfProperty* pProperty = nullptr;
+        if (SUCCEEDED(pContext->GetProperty(GUID_PROP_INPUTSCOPE, &pProperty)))
+        {
+            VARIANT value;
+            VariantInit(&value);
+            if (SUCCEEDED(pProperty->GetValue(ec, selection.range, &value)) && value.vt == VT_UNKNOWN && value.punkVal)
+            {
+                ITfInputScope* pInputScope = nullptr;
+                if (SUCCEEDED(value.punkVal->QueryInterface(IID_ITfInputScope, (void**)&pInputScope)))
+                {
+                    InputScope* scopes = nullptr;
+                    UINT count = 0;
+                    if (SUCCEEDED(pInputScope->GetInputScopes(&scopes, &count)))
+                    {
+                        for (UINT i = 0; i < count; i++)
+                        {
+                            password |= scopes[i] == IS_PASSWORD || scopes[i] == IS_NUMERIC_PASSWORD;
+                        }
+                        CoTaskMemFree(scopes);
+                    }
+                    pInputScope->Release();
+                }
+            }
+            VariantClear(&value);
+            pProperty->Release();
+        }
+        selection.range->Release();
+        return password;
+    }
+
+    static InputLatencyHandle* GetInputLatency()
+    {
+        // One handle per process; it attaches to the GUI's counters on its own
+        static InputLatencyHandle* latency = keymagic_input_latency_open();
+        return latency;
+    }
+
+    LatencySample::LatencySample()
+        : m_sampled(keymagic_input_latency_should_sample(GetInputLatency()) != 0)
+    {
+        m_start.QuadPart = 0;
+        if (m_sampled)
+        {
+            QueryPerformanceCounter(&m_start);
+        }
+    }
+
+    void LatencySample::Finish()
+    {
+        if (!m_sampled)
+            return;
+        m_sampled = false;
+
+        LARGE_INTEGER end, frequency;
+        QueryPerformanceCounter(&end);
+        QueryPerformanceFrequency(&frequency);
+        uint64_t micros = static_cast<uint64_t>(end.QuadPart - m_start.QuadPart) * 1000000 / frequency.QuadPart;
+        keymagic_input_latency_record(GetInputLatency(), micros);
+    }
+
+    void ShowNotification(const ProcessKeyOutput& output)
+    {
+        if (output.delete_clamped)
+        {
+            KeyMagicHUD::GetInstance().ShowMessage(L"Keyboard misbehaving: it tried to delete text it did not type");
+            return;
+        }
+
+        if (!output.notification || !*output.notification)
+            return;
+
+        KeyMagicHUD::GetInstance().ShowMessage(KeyMagicUtils::ConvertUtf8ToUtf16(output.notification));
+    }
+}
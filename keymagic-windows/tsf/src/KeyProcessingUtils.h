@@ -1,6 +1,7 @@
 #pragma once
 
 #include <Windows.h>
+#include <msctf.h>
 #include <string>
 #include <sstream>
 #include "../../shared/include/keymagic_ffi.h"
@@ -31,4 +32,45 @@ namespace KeyProcessingUtils
 
     // Helper to check if we should skip this key (modifiers, function keys)
     bool ShouldSkipKey(WPARAM wParam);
+
+    // Appends the key to the input recorder if the GUI is recording;
+    // returns the record's sequence number, or 0
+    uint64_t RecordKeyEvent(WPARAM wParam, const KeyInputData& keyInput, const ProcessKeyOutput& output);
+
+    // Reports text committed to the document (UTF-8) for the GUI's
+    // re-insert history, if the GUI turned it on, and drops the composing
+    // text kept for crash recovery
+    void ReportCommit(const char* text);
+
+    // Keeps the engine's composing text (UTF-8) for crash recovery if the
+    // user opted in and the field is not a password; ReportCommit drops it
+    // again
+    void JournalComposition(TfEditCookie ec, ITfContext* pContext, const char* composingText);
+
+    // Counts the key for the GUI's layout heatmap if the user opted in;
+    // keys typed into a password field are never counted
+    void CountKey(TfEditCookie ec, ITfContext* pContext, const std::wstring& keyboardId, WPARAM wParam);
+
+    // Whether the selection of the context is in a field with the password
+    // input scope
+    bool IsPasswordField(TfEditCookie ec, ITfContext* pContext);
+
+    // Times a key for the GUI's latency statistics if it is one of the
+    // sampled keys; the time from construction to Finish() is recorded,
+    // nothing if Finish() is never called
+    class LatencySample
+    {
+    public:
+        LatencySample();
+        void Finish();
+
+    private:
+        bool m_sampled;
+        LARGE_INTEGER m_start;
+    };
+
+    // Shows the message a rule raised (if any) in the HUD; the engine has
+    // already rate limited it per keyboard. A clamped delete is reported
+    // instead, as the keyboard misbehaving.
+    void ShowNotification(const ProcessKeyOutput& output);
 }
\ No newline at end of file
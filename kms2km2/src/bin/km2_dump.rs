@@ -2,7 +2,7 @@ use std::fs::File;
 use std::io::Read;
 use byteorder::{LittleEndian, ReadBytesExt};
 use keymagic_core::km2::Km2Loader;
-use keymagic_core::types::opcodes::*;
+use keymagic_core::km2::format::*;
 use keymagic_core::types::virtual_keys::VirtualKey;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -30,12 +30,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let eat = km2.header.layout_options.eat;
             let pos_based = km2.header.layout_options.pos_based;
             let right_alt = km2.header.layout_options.right_alt;
+            let case_insensitive_ascii = km2.header.layout_options.case_insensitive_ascii;
             
             println!("Version: {}.{}", major_version, minor_version);
             println!("Counts: {} strings, {} info, {} rules", 
                      string_count, info_count, rule_count);
-            println!("Layout options: track_caps={}, auto_bksp={}, eat={}, pos_based={}, right_alt={}",
-                     track_caps, auto_bksp, eat, pos_based, right_alt);
+            println!("Layout options: track_caps={}, auto_bksp={}, eat={}, pos_based={}, right_alt={}, case_insensitive_ascii={}",
+                     track_caps, auto_bksp, eat, pos_based, right_alt, case_insensitive_ascii);
         }
         Err(e) => {
             eprintln!("Warning: keymagic-core failed to load: {:?}", e);
@@ -53,7 +54,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     file.read_exact(&mut magic)?;
     println!("Magic: {:?} ({})", magic, String::from_utf8_lossy(&magic));
     
-    if &magic != b"KMKL" {
+    if magic != MAGIC {
         eprintln!("Error: Invalid magic code, not a KM2 file");
         std::process::exit(1);
     }
@@ -184,7 +185,7 @@ fn dump_rule_elements(file: &mut File, len: u16) -> Result<(), Box<dyn std::erro
         remaining -= 1;
         
         match opcode {
-            OP_STRING => {
+            OP_STRING | OP_NOTIFY => {
                 let str_len = file.read_u16::<LittleEndian>()?;
                 remaining -= 1;
                 let mut utf16_data = vec![0u16; str_len as usize];
@@ -193,7 +194,8 @@ fn dump_rule_elements(file: &mut File, len: u16) -> Result<(), Box<dyn std::erro
                     remaining -= 1;
                 }
                 let s = String::from_utf16_lossy(&utf16_data);
-                print!("STRING(\"{}\") ", s);
+                let name = opcode_name(opcode).unwrap_or_default();
+                print!("{}(\"{}\") ", name, s);
                 last_was_and = false;
             }
             OP_VARIABLE => {
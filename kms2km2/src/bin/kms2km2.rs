@@ -1,12 +1,19 @@
-use clap::Parser;
-use std::path::PathBuf;
-use kms2km2::convert_kms_to_km2;
+use clap::{ArgAction, Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use kms2km2::{compile_kms_file, convert_kms_to_km2_with_options, write_km2_file, CompileOptions, Km2File, KeyMagicEngine};
+use kms2km2::analysis::analyze_keyboard;
+use kms2km2::km2::Km2Loader;
+use kms2km2::kmn::convert_kmn_file;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "KeyMagic Script to Binary Converter", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Input KMS file path
-    input: PathBuf,
+    input: Option<PathBuf>,
     
     /// Output KM2 file path (defaults to input with .km2 extension)
     output: Option<PathBuf>,
@@ -14,32 +21,177 @@ struct Args {
     /// Enable verbose output
     #[arg(short, long)]
     verbose: bool,
+
+    /// KM2 version to write, 1.5 or 1.6 (defaults to the oldest that fits)
+    #[arg(long, value_parser = parse_version)]
+    target_version: Option<(u8, u8)>,
+
+    /// Do not run the @test lines of the input
+    #[arg(long)]
+    skip_tests: bool,
+
+    /// Keep the state and variable names in the output for details and debug views
+    #[arg(long, action = ArgAction::Set, default_value_t = true)]
+    emit_names: bool,
+}
+
+fn parse_version(text: &str) -> Result<(u8, u8), String> {
+    let (major, minor) = text.split_once('.').ok_or_else(|| format!("expected MAJOR.MINOR, got '{}'", text))?;
+    let number = |part: &str| part.parse::<u8>().map_err(|_| format!("expected MAJOR.MINOR, got '{}'", text));
+    Ok((number(major)?, number(minor)?))
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report unreachable rules and, with a corpus, rule coverage as JSON
+    Analyze {
+        /// Keyboard to analyze (.kms or .km2)
+        keyboard: PathBuf,
+
+        /// Text file with one typed key sequence per line
+        #[arg(long)]
+        corpus: Option<PathBuf>,
+    },
+    /// Print a Markdown table of the key sequences the keyboard handles
+    Docs {
+        /// Keyboard to document (.kms or .km2)
+        keyboard: PathBuf,
+
+        /// Longest key sequence to try
+        #[arg(long, default_value_t = 2)]
+        depth: usize,
+    },
+    /// Write a keyboard as JSON for external layout editors
+    ExportJson {
+        /// Keyboard to export (.kms or .km2)
+        keyboard: PathBuf,
+
+        /// Output JSON file path (defaults to input with .json extension)
+        output: Option<PathBuf>,
+    },
+    /// Write a KM2 file from JSON written by export-json
+    ImportJson {
+        /// JSON file to import
+        json: PathBuf,
+
+        /// Output KM2 file path (defaults to input with .km2 extension)
+        output: Option<PathBuf>,
+    },
+    /// Convert a Keyman keyboard source (.kmn) to KMS, reporting what did not convert
+    ConvertKmn {
+        /// Keyman source file
+        kmn: PathBuf,
+
+        /// Output KMS file path (defaults to input with .kms extension)
+        output: Option<PathBuf>,
+    },
 }
 
 fn main() {
     let args = Args::parse();
-    
+
+    let result = match args.command {
+        Some(Command::Analyze { keyboard, corpus }) => analyze(&keyboard, corpus.as_deref()),
+        Some(Command::Docs { keyboard, depth }) => docs(&keyboard, depth),
+        Some(Command::ExportJson { keyboard, output }) => export_json(&keyboard, output),
+        Some(Command::ImportJson { json, output }) => import_json(&json, output),
+        Some(Command::ConvertKmn { kmn, output }) => convert_kmn(&kmn, output),
+        None => match args.input {
+            Some(input) => {
+                let options = CompileOptions {
+                    target_version: args.target_version,
+                    skip_tests: args.skip_tests,
+                    strip_names: !args.emit_names,
+                };
+                convert(&input, args.output, options, args.verbose)
+            }
+            None => Err("No input file given (see --help)".to_string()),
+        },
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn convert(input: &Path, output: Option<PathBuf>, options: CompileOptions, verbose: bool) -> Result<(), String> {
     // Determine output path
-    let output_path = args.output.unwrap_or_else(|| {
-        let mut path = args.input.clone();
-        path.set_extension("km2");
-        path
-    });
+    let output_path = output.unwrap_or_else(|| input.with_extension("km2"));
     
-    if args.verbose {
-        println!("Converting {} to {}", args.input.display(), output_path.display());
+    if verbose {
+        println!("Converting {} to {}", input.display(), output_path.display());
     }
     
     // Perform conversion
-    match convert_kms_to_km2(&args.input, &output_path) {
-        Ok(()) => {
-            if args.verbose {
-                println!("Conversion successful!");
-            }
-        }
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            std::process::exit(1);
-        }
+    let warnings = convert_kms_to_km2_with_options(input, &output_path, options).map_err(|e| e.to_string())?;
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    if verbose {
+        println!("Conversion successful!");
+    }
+    Ok(())
+}
+
+fn load_keyboard(path: &Path) -> Result<Km2File, String> {
+    let is_km2 = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("km2"));
+    if is_km2 {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        Km2Loader::load(&data).map_err(|e| e.to_string())
+    } else {
+        compile_kms_file(path).map_err(|e| e.to_string())
+    }
+}
+
+fn analyze(keyboard_path: &Path, corpus_path: Option<&Path>) -> Result<(), String> {
+    let keyboard = load_keyboard(keyboard_path)?;
+    let corpus = corpus_path
+        .map(std::fs::read_to_string)
+        .transpose()
+        .map_err(|e| format!("Failed to read corpus: {}", e))?;
+
+    let report = analyze_keyboard(&keyboard, corpus.as_deref().map(str::lines))
+        .map_err(|e| e.to_string())?;
+    let json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    println!("{}", json);
+    Ok(())
+}
+
+fn docs(keyboard_path: &Path, depth: usize) -> Result<(), String> {
+    let keyboard = load_keyboard(keyboard_path)?;
+    let engine = KeyMagicEngine::new(keyboard).map_err(|e| e.to_string())?;
+
+    // Pipes would end the table cell
+    let cell = |text: &str| text.replace('|', "\\|");
+    println!("| Keys | Output |");
+    println!("| --- | --- |");
+    for entry in engine.enumerate_outputs(depth) {
+        let keys: Vec<String> = entry.keys.iter().map(|key| format!("`{}`", cell(&key.to_string()))).collect();
+        println!("| {} | {} |", keys.join(" "), cell(&entry.output));
+    }
+    Ok(())
+}
+
+fn export_json(keyboard_path: &Path, output: Option<PathBuf>) -> Result<(), String> {
+    let keyboard = load_keyboard(keyboard_path)?;
+    let output_path = output.unwrap_or_else(|| keyboard_path.with_extension("json"));
+    std::fs::write(&output_path, keyboard.to_json()).map_err(|e| e.to_string())
+}
+
+fn import_json(json_path: &Path, output: Option<PathBuf>) -> Result<(), String> {
+    let json = std::fs::read_to_string(json_path).map_err(|e| e.to_string())?;
+    let keyboard = Km2File::from_json(&json).map_err(|e| e.to_string())?;
+    let output_path = output.unwrap_or_else(|| json_path.with_extension("km2"));
+    write_km2_file(&keyboard, &output_path).map_err(|e| e.to_string())
+}
+
+fn convert_kmn(kmn_path: &Path, output: Option<PathBuf>) -> Result<(), String> {
+    let output_path = output.unwrap_or_else(|| kmn_path.with_extension("kms"));
+    let report = convert_kmn_file(kmn_path, &output_path).map_err(|e| e.to_string())?;
+    for issue in &report.issues {
+        eprintln!("{}", issue);
     }
-}
\ No newline at end of file
+    eprintln!("{} rules converted, {} skipped", report.rules_converted, report.rules_skipped);
+    Ok(())
+}
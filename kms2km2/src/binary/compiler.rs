@@ -1,17 +1,65 @@
 use crate::parser::{KmsFile, ValueElement, PatternElement, OutputElement, VariableDecl};
-use keymagic_core::*;
+use keymagic_core::types::{km2::*, opcodes::*, virtual_keys::create_vk_map};
+use keymagic_core::km2::FeatureRequirements;
+use keymagic_core::{KmsError, VirtualKey};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::fs;
 
+/// Collects the `@group` ranges of the rules, groups in order of first use
+fn collect_rule_groups(rules: &[crate::parser::RuleDecl]) -> Vec<RuleGroup> {
+    let mut groups: Vec<RuleGroup> = Vec::new();
+
+    for (index, rule) in rules.iter().enumerate() {
+        let Some(name) = &rule.group else { continue };
+        let group = match groups.iter().position(|g| &g.name == name) {
+            Some(pos) => &mut groups[pos],
+            None => {
+                groups.push(RuleGroup { name: name.clone(), ranges: Vec::new() });
+                groups.last_mut().unwrap()
+            }
+        };
+        match group.ranges.last_mut() {
+            Some(range) if range.end == index => range.end = index + 1,
+            _ => group.ranges.push(index..index + 1),
+        }
+    }
+
+    groups
+}
+
+/// Something the compiler accepted but changed or that has no effect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileWarning {
+    /// Rule index, in file order; `None` for the header options
+    pub rule: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for CompileWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.rule {
+            Some(rule) => write!(f, "rule {}: {}", rule + 1, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
 pub struct Compiler {
     strings: Vec<StringEntry>,
     string_map: HashMap<String, usize>,
     variables: HashMap<String, usize>,
+    /// Variable names and string indices in declaration order
+    variable_order: Vec<(String, usize)>,
     states: HashMap<String, usize>,
     vk_map: HashMap<&'static str, VirtualKey>,
     next_state_index: usize,
     base_dir: Option<PathBuf>,
+    /// km2 version to write, `None` for the oldest one that fits
+    target_version: Option<(u8, u8)>,
+    /// Keep the state and variable names in the file
+    emit_names: bool,
+    warnings: Vec<CompileWarning>,
 }
 
 impl Compiler {
@@ -20,10 +68,14 @@ impl Compiler {
             strings: Vec::new(),
             string_map: HashMap::new(),
             variables: HashMap::new(),
+            variable_order: Vec::new(),
             states: HashMap::new(),
             vk_map: create_vk_map(),
             next_state_index: 0,
             base_dir: None,
+            target_version: None,
+            emit_names: true,
+            warnings: Vec::new(),
         }
     }
 
@@ -32,7 +84,32 @@ impl Compiler {
         self
     }
 
-    pub fn compile(mut self, ast: KmsFile) -> std::result::Result<Km2File, KmsError> {
+    /// Writes this km2 version (1.5 or 1.6) instead of the oldest one that
+    /// fits; a keyboard too large for it fails to compile
+    pub fn with_target_version(mut self, major: u8, minor: u8) -> Self {
+        self.target_version = Some((major, minor));
+        self
+    }
+
+    /// Whether to keep the state and variable names of the source in the
+    /// file (the default) for details and debug views
+    pub fn with_names(mut self, emit_names: bool) -> Self {
+        self.emit_names = emit_names;
+        self
+    }
+
+    pub fn compile(self, ast: KmsFile) -> std::result::Result<Km2File, KmsError> {
+        self.compile_with_warnings(ast).map(|(km2, _)| km2)
+    }
+
+    /// Compiles and also returns the warnings, in rule order
+    pub fn compile_with_warnings(mut self, ast: KmsFile) -> std::result::Result<(Km2File, Vec<CompileWarning>), KmsError> {
+        if let Some((major, minor)) = self.target_version {
+            if major != 1 || !(5..=KM2_LARGE_VERSION).contains(&minor) {
+                return Err(KmsError::UnsupportedTarget { major, minor });
+            }
+        }
+
         // First, compile all variables
         for var in &ast.variables {
             self.compile_variable(var)?;
@@ -46,28 +123,63 @@ impl Compiler {
 
         // Compile rules
         let mut rules = Vec::new();
-        for rule in &ast.rules {
-            rules.push(self.compile_rule(rule)?);
+        for (index, rule) in ast.rules.iter().enumerate() {
+            rules.push(self.compile_rule(index, rule)?);
         }
 
         // Create header
         let mut header = FileHeader::new();
-        header.string_count = self.strings.len() as u16;
-        header.rule_count = rules.len() as u16;
+        header.string_count = self.strings.len() as u32;
+        header.rule_count = rules.len() as u32;
 
         // Set layout options from AST options
         self.set_layout_options(&mut header.layout_options, &ast.options);
 
         // Create info entries
-        let info = self.create_info_entries(&ast.options)?;
-        header.info_count = info.len() as u16;
+        let mut info = self.create_info_entries(&ast.options)?;
+        let groups = collect_rule_groups(&ast.rules);
+        if !groups.is_empty() {
+            // Groups past the indices of INFO_GRPS go in the wide entry
+            info.push(if RuleGroup::fits_list(&groups) {
+                InfoEntry { id: *INFO_GRPS, data: RuleGroup::encode_list(&groups) }
+            } else {
+                InfoEntry { id: *INFO_GRPL, data: RuleGroup::encode_list_wide(&groups) }
+            });
+        }
+        let post_rules = PostRules::from_flags(ast.rules.iter().map(|rule| rule.post));
+        if !post_rules.is_empty() {
+            info.push(InfoEntry {
+                id: *INFO_POST,
+                data: post_rules.encode(),
+            });
+        }
+        if self.emit_names {
+            info.extend(self.name_entries());
+        }
+        // Options the header has no room for
+        info.extend(header.layout_options.extended_entry());
+        header.info_count = info.len() as u32;
 
-        Ok(Km2File {
+        let needs_wide = [self.strings.len(), info.len(), rules.len(), self.next_state_index]
+            .iter()
+            .any(|&count| count > KM2_LEGACY_LIMIT);
+        let (major, minor) = self.target_version.unwrap_or((1, if needs_wide { KM2_LARGE_VERSION } else { 5 }));
+        header.major_version = major;
+        header.minor_version = minor;
+
+        let mut km2 = Km2File {
             header,
-            strings: self.strings,
+            strings: self.strings.into(),
             info,
             rules,
-        })
+        };
+        // Lets engines that lack a feature refuse the keyboard by name
+        if let Some(entry) = FeatureRequirements::entry_for(&km2) {
+            km2.set_info(&entry.id, entry.data);
+        }
+        // Too large for an older target: fail instead of truncating
+        super::check_format_limits(&km2)?;
+        Ok((km2, self.warnings))
     }
 
     fn compile_variable(&mut self, var: &VariableDecl) -> std::result::Result<(), KmsError> {
@@ -79,6 +191,11 @@ impl Compiler {
         // Add to strings and map
         let index = self.add_string(value);
         self.variables.insert(var_name.to_string(), index);
+        // A variable declared again keeps its place with the new value
+        match self.variable_order.iter_mut().find(|(name, _)| name == var_name) {
+            Some(entry) => entry.1 = index,
+            None => self.variable_order.push((var_name.to_string(), index)),
+        }
         
         Ok(())
     }
@@ -171,13 +288,27 @@ impl Compiler {
         Ok(result)
     }
 
-    fn compile_rule(&mut self, rule: &crate::parser::RuleDecl) -> std::result::Result<Rule, KmsError> {
+    fn compile_rule(&mut self, index: usize, rule: &crate::parser::RuleDecl) -> std::result::Result<Rule, KmsError> {
         let lhs = self.compile_pattern(&rule.lhs)?;
-        let rhs = self.compile_output(&rule.rhs)?;
+        let mut rhs = self.compile_output(&rule.rhs)?;
+        
+        // An output of empty strings only deletes the match, which is what
+        // NULL says; write NULL so every such rule looks the same
+        let is_empty_string = |elem: &BinaryFormatElement| matches!(elem, BinaryFormatElement::String(s) if s.is_empty());
+        if !rhs.is_empty() && rhs.iter().all(is_empty_string) {
+            self.warn(index, "empty string output is compiled as NULL");
+            rhs = vec![BinaryFormatElement::Predefined(PREDEFINED_NULL)];
+        } else if rhs.len() > 1 && rule.rhs.iter().any(|elem| matches!(elem, OutputElement::Null)) {
+            self.warn(index, "NULL next to other output has no effect");
+        }
         
         Ok(Rule { lhs, rhs })
     }
 
+    fn warn(&mut self, rule: usize, message: &str) {
+        self.warnings.push(CompileWarning { rule: Some(rule), message: message.to_string() });
+    }
+
     fn compile_pattern(&mut self, pattern: &[PatternElement]) -> std::result::Result<Vec<BinaryFormatElement>, KmsError> {
         let mut elements = Vec::new();
         
@@ -287,7 +418,7 @@ impl Compiler {
                 }
                 OutputElement::Null => {
                     // NULL is represented as opPREDEFINED(1) = pdNULL
-                    elements.push(BinaryFormatElement::Predefined(1));
+                    elements.push(BinaryFormatElement::Predefined(PREDEFINED_NULL));
                 }
                 OutputElement::State(state) => {
                     if let Some(&idx) = self.states.get(state) {
@@ -296,12 +427,49 @@ impl Compiler {
                         return Err(KmsError::InvalidRule(format!("Unknown state: {}", state)));
                     }
                 }
+                OutputElement::Notify(message) => {
+                    let message = self.process_string_escapes(message)?;
+                    let length = message.chars().count();
+                    if message.trim().is_empty() || length > MAX_NOTIFY_LEN {
+                        return Err(KmsError::InvalidRule(format!(
+                            "@notify message must have 1 to {} characters, found {}",
+                            MAX_NOTIFY_LEN, length
+                        )));
+                    }
+                    elements.push(BinaryFormatElement::Notify(message));
+                }
             }
         }
         
         Ok(elements)
     }
 
+    /// Info entries naming the states and variables; a table too long for
+    /// an info entry is left out with a warning
+    fn name_entries(&mut self) -> Vec<InfoEntry> {
+        let mut states: Vec<(usize, String)> = self.states.iter().map(|(name, &index)| (index, name.clone())).collect();
+        states.sort_unstable();
+        // Variable indices are 1-based in rules
+        let variables = self.variable_order.iter().map(|(name, index)| (index + 1, name.clone())).collect();
+
+        let mut entries = Vec::new();
+        for (id, what, names) in [(INFO_STNM, "state", states), (INFO_VRNM, "variable", variables)] {
+            if names.is_empty() {
+                continue;
+            }
+            let data = IndexNames::new(names).encode();
+            if data.len() > u16::MAX as usize {
+                self.warnings.push(CompileWarning {
+                    rule: None,
+                    message: format!("{} names left out: {} bytes do not fit in an info entry", what, data.len()),
+                });
+                continue;
+            }
+            entries.push(InfoEntry { id: *id, data });
+        }
+        entries
+    }
+
     fn add_string(&mut self, s: String) -> usize {
         if let Some(&idx) = self.string_map.get(&s) {
             idx
@@ -329,9 +497,13 @@ impl Compiler {
         if let Some(v) = ast_options.get("TREAT_CTRL_ALT_AS_RALT") {
             options.right_alt = if v.to_uppercase() == "TRUE" { 1 } else { 0 };
         }
+        // Newer option, also accepted as `@case_insensitive_ascii`
+        if let Some(v) = ast_options.iter().find(|(name, _)| name.eq_ignore_ascii_case("CASE_INSENSITIVE_ASCII")).map(|(_, v)| v) {
+            options.case_insensitive_ascii = if v.to_uppercase() == "TRUE" { 1 } else { 0 };
+        }
     }
 
-    fn create_info_entries(&self, options: &HashMap<String, String>) -> std::result::Result<Vec<InfoEntry>, KmsError> {
+    fn create_info_entries(&mut self, options: &HashMap<String, String>) -> std::result::Result<Vec<InfoEntry>, KmsError> {
         let mut entries = Vec::new();
         
         if let Some(name) = options.get("NAME") {
@@ -363,6 +535,22 @@ impl Compiler {
             });
         }
         
+        // A blank sample is left out so the GUI generates one
+        if let Some(sample) = options.get("SAMPLE").map(|sample| sample.trim()).filter(|sample| !sample.is_empty()) {
+            entries.push(InfoEntry {
+                id: *INFO_SMPL,
+                data: self.string_to_utf8(sample),
+            });
+        }
+        
+        if let Some(locales) = options.get("LOCALE") {
+            let data = self.compile_locales(locales)?;
+            entries.push(InfoEntry {
+                id: *INFO_LOCL,
+                data: self.string_to_utf8(&data),
+            });
+        }
+        
         // Handle ICON
         if let Some(icon_path) = options.get("ICON") {
             let icon_data = self.load_icon_file(icon_path)?;
@@ -375,6 +563,25 @@ impl Compiler {
         Ok(entries)
     }
 
+    /// Checks the comma-separated `@LOCALE` tags; malformed tags are errors,
+    /// unusual ones warnings
+    fn compile_locales(&mut self, locales: &str) -> std::result::Result<String, KmsError> {
+        let mut tags: Vec<&str> = Vec::new();
+        for tag in locales.split(keymagic_core::locale::LOCALE_SEPARATOR).map(str::trim) {
+            let notes = keymagic_core::locale::check_locale_tag(tag).map_err(|reason| KmsError::Parse {
+                line: 0,
+                message: format!("Invalid @LOCALE \"{}\": {}", tag, reason),
+            })?;
+            for note in notes {
+                self.warnings.push(CompileWarning { rule: None, message: format!("@LOCALE \"{}\": {}", tag, note) });
+            }
+            if !tags.iter().any(|seen| seen.eq_ignore_ascii_case(tag)) {
+                tags.push(tag);
+            }
+        }
+        Ok(tags.join(&keymagic_core::locale::LOCALE_SEPARATOR.to_string()))
+    }
+
     fn string_to_utf8(&self, s: &str) -> Vec<u8> {
         s.as_bytes().to_vec()
     }
@@ -0,0 +1,78 @@
+//! Runs the `@test` lines of a KMS file against the compiled keyboard
+//!
+//! Each test types its keys into a fresh engine, starting from an empty
+//! composition, and compares the composing text after the last key with the
+//! expected output. The keys are written in the compact key text of
+//! `keymagic_core::key_event`, e.g. `"ka{BKSP}<Shift+k>"`.
+
+use crate::parser::EmbeddedTest;
+use keymagic_core::key_event::{parse_key_sequence, KeyEventDto, KeyEventError};
+use keymagic_core::{KeyMagicEngine, Km2File};
+use std::fmt;
+
+/// Why a test did not pass
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestFailureKind {
+    /// The keys typed other text
+    Mismatch { actual: String },
+    /// The key text does not parse
+    InvalidKeys(KeyEventError),
+    /// The engine rejected the keyboard or a key
+    Engine(String),
+}
+
+/// A `@test` line that did not pass
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestFailure {
+    pub test: EmbeddedTest,
+    pub kind: TestFailureKind,
+}
+
+impl fmt::Display for TestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@test on line {} ", self.test.line)?;
+        match &self.kind {
+            TestFailureKind::Mismatch { actual } => write!(
+                f,
+                "failed: \"{}\" typed \"{}\", expected \"{}\"",
+                self.test.input, actual, self.test.expected
+            ),
+            TestFailureKind::InvalidKeys(e) => write!(f, "has invalid keys: {}", e),
+            TestFailureKind::Engine(message) => write!(f, "could not run: {}", message),
+        }
+    }
+}
+
+/// Runs `tests` against `keyboard` and returns the ones that fail, in order
+pub fn run_embedded_tests(keyboard: &Km2File, tests: &[EmbeddedTest]) -> Vec<TestFailure> {
+    if tests.is_empty() {
+        return Vec::new();
+    }
+    let failure = |test: &EmbeddedTest, kind| TestFailure { test: test.clone(), kind };
+    let mut engine = match KeyMagicEngine::new(keyboard.clone()) {
+        Ok(engine) => engine,
+        Err(e) => return tests.iter().map(|test| failure(test, TestFailureKind::Engine(e.to_string()))).collect(),
+    };
+
+    tests
+        .iter()
+        .filter_map(|test| run_test(&mut engine, test).err().map(|kind| failure(test, kind)))
+        .collect()
+}
+
+fn run_test(engine: &mut KeyMagicEngine, test: &EmbeddedTest) -> Result<(), TestFailureKind> {
+    let inputs = parse_key_sequence(&test.input)
+        .and_then(|events| events.iter().map(KeyEventDto::to_key_input).collect::<Result<Vec<_>, _>>())
+        .map_err(TestFailureKind::InvalidKeys)?;
+
+    engine.reset();
+    for input in inputs {
+        engine.process_key(input).map_err(|e| TestFailureKind::Engine(e.to_string()))?;
+    }
+    let actual = engine.composing_text().to_string();
+    if actual == test.expected {
+        Ok(())
+    } else {
+        Err(TestFailureKind::Mismatch { actual })
+    }
+}
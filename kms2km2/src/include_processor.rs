@@ -109,6 +109,7 @@ impl IncludeProcessor {
         // Copy original content first
         result.variables = ast.variables;
         result.rules = ast.rules;
+        result.tests = ast.tests;
         
         // Process each include
         for include_path in ast.includes {
@@ -125,6 +126,7 @@ impl IncludeProcessor {
             // Append included content
             result.variables.extend(included_ast.variables);
             result.rules.extend(included_ast.rules);
+            result.tests.extend(included_ast.tests);
         }
         
         Ok(result)
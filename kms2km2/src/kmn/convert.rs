@@ -0,0 +1,807 @@
+//! Turns parsed Keyman statements into KMS source
+
+use std::collections::{HashMap, HashSet};
+
+use super::parser::{classify, logical_lines, tokenize, Statement, Token};
+use super::{ConversionIssue, ConversionReport, IssueKind, KmnConversion};
+
+/// Characters a key types on a US layout, unshifted and shifted
+type UsChars = Option<(char, char)>;
+
+/// Keyman key names with the virtual key KMS calls them and the characters
+/// they type
+const KEYS: &[(&str, &str, UsChars)] = &[
+    ("K_SPACE", "VK_SPACE", Some((' ', ' '))),
+    ("K_BKQUOTE", "VK_OEM_3", Some(('`', '~'))),
+    ("K_HYPHEN", "VK_OEM_MINUS", Some(('-', '_'))),
+    ("K_EQUAL", "VK_OEM_PLUS", Some(('=', '+'))),
+    ("K_LBRKT", "VK_OEM_4", Some(('[', '{'))),
+    ("K_RBRKT", "VK_OEM_6", Some((']', '}'))),
+    ("K_BKSLASH", "VK_OEM_5", Some(('\\', '|'))),
+    ("K_COLON", "VK_OEM_1", Some((';', ':'))),
+    ("K_QUOTE", "VK_OEM_7", Some(('\'', '"'))),
+    ("K_COMMA", "VK_OEM_COMMA", Some((',', '<'))),
+    ("K_PERIOD", "VK_OEM_PERIOD", Some(('.', '>'))),
+    ("K_SLASH", "VK_OEM_2", Some(('/', '?'))),
+    ("K_BKSP", "VK_BACK", None),
+    ("K_ENTER", "VK_RETURN", None),
+    ("K_TAB", "VK_TAB", None),
+    ("K_ESC", "VK_ESCAPE", None),
+    ("K_DEL", "VK_DELETE", None),
+    ("K_CAPS", "VK_CAPSLOCK", None),
+    ("K_HOME", "VK_HOME", None),
+    ("K_END", "VK_END", None),
+    ("K_PGUP", "VK_PRIOR", None),
+    ("K_PGDN", "VK_NEXT", None),
+    ("K_LEFT", "VK_LEFT", None),
+    ("K_UP", "VK_UP", None),
+    ("K_RIGHT", "VK_RIGHT", None),
+    ("K_DOWN", "VK_DOWN", None),
+    ("K_INS", "VK_INSERT", None),
+];
+
+const SHIFTED_DIGITS: &str = ")!@#$%^&*(";
+
+/// A key: its virtual key name and characters, from [`KEYS`] or the
+/// letter, digit, function and numpad keys
+fn lookup_key(name: &str) -> Option<(String, UsChars)> {
+    if let Some((_, vk, chars)) = KEYS.iter().find(|(key, _, _)| *key == name) {
+        return Some((vk.to_string(), *chars));
+    }
+    let rest = name.strip_prefix("K_")?;
+    let mut chars = rest.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter @ 'A'..='Z'), None) => Some((format!("VK_KEY_{}", letter), Some((letter.to_ascii_lowercase(), letter)))),
+        (Some(digit @ '0'..='9'), None) => {
+            let shifted = SHIFTED_DIGITS.chars().nth(digit as usize - '0' as usize)?;
+            Some((format!("VK_KEY_{}", digit), Some((digit, shifted))))
+        }
+        _ => {
+            if let Some(number) = rest.strip_prefix('F').and_then(|n| n.parse::<u8>().ok()).filter(|n| (1..=12).contains(n)) {
+                return Some((format!("VK_F{}", number), None));
+            }
+            let digit = rest.strip_prefix("NP").and_then(|n| n.parse::<u8>().ok()).filter(|n| *n <= 9)?;
+            Some((format!("VK_NUMPAD{}", digit), None))
+        }
+    }
+}
+
+/// A key pattern: the character it types, or the virtual keys to match
+enum KeyPattern {
+    Char(char),
+    Vk(Vec<String>),
+}
+
+/// Converts `[SHIFT K_A]`. Caps Lock conditions are dropped, which the
+/// second value reports.
+fn key_pattern(words: &[String]) -> Result<(KeyPattern, bool), String> {
+    let (name, modifiers) = words.split_last().ok_or("empty key '[]'")?;
+    let (vk, chars) = lookup_key(name).ok_or_else(|| format!("no KeyMagic key for {}", name))?;
+    let mut shift = false;
+    let mut caps = false;
+    let mut vks = Vec::new();
+    for modifier in modifiers {
+        match modifier.as_str() {
+            "SHIFT" => shift = true,
+            "CTRL" | "LCTRL" | "RCTRL" => vks.push("VK_CTRL"),
+            "ALT" | "LALT" => vks.push("VK_ALT"),
+            "RALT" => vks.push("VK_ALT_GR"),
+            "CAPS" | "NCAPS" => caps = true,
+            other => return Err(format!("no KeyMagic modifier for {}", other)),
+        }
+    }
+    if vks.is_empty() {
+        if let Some((unshifted, shifted)) = chars {
+            return Ok((KeyPattern::Char(if shift { shifted } else { unshifted }), caps));
+        }
+    }
+    let mut combo: Vec<String> = Vec::new();
+    if shift {
+        combo.push("VK_SHIFT".to_string());
+    }
+    combo.extend(vks.iter().map(|vk| vk.to_string()));
+    combo.push(vk);
+    Ok((KeyPattern::Vk(combo), caps))
+}
+
+/// Whether a character is written as `UXXXX` rather than inside a string
+fn needs_code(ch: char) -> bool {
+    (ch.is_control() || ch == '\u{00AD}' || ('\u{200B}'..='\u{200F}').contains(&ch) || ('\u{2060}'..='\u{206F}').contains(&ch) || ch == '\u{FEFF}')
+        && (ch as u32) <= 0xFFFF
+}
+
+/// Text as KMS string and `UXXXX` elements joined with `+`
+fn kms_text(text: &str) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for ch in text.chars() {
+        if needs_code(ch) {
+            if !current.is_empty() {
+                parts.push(format!("\"{}\"", std::mem::take(&mut current)));
+            }
+            parts.push(format!("U{:04X}", ch as u32));
+        } else {
+            if ch == '"' || ch == '\\' {
+                current.push('\\');
+            }
+            current.push(ch);
+        }
+    }
+    if !current.is_empty() || parts.is_empty() {
+        parts.push(format!("\"{}\"", current));
+    }
+    parts.join(" + ")
+}
+
+/// A KMS identifier for a Keyman name
+fn identifier(name: &str) -> String {
+    let mut id: String = name.chars().map(|ch| if ch.is_ascii_alphanumeric() || ch == '_' { ch } else { '_' }).collect();
+    if id.is_empty() || id.starts_with(|ch: char| ch.is_ascii_digit()) {
+        id.insert(0, '_');
+    }
+    id
+}
+
+fn state_name(deadkey: &str) -> String {
+    identifier(&deadkey.to_ascii_lowercase())
+}
+
+struct StoreDef {
+    line: usize,
+    items: Vec<Token>,
+    /// KMS variable name
+    var: String,
+}
+
+struct RuleDef {
+    line: usize,
+    text: String,
+    lhs: Vec<Token>,
+    rhs: Vec<Token>,
+}
+
+struct GroupDef {
+    name: String,
+    line: usize,
+    using_keys: bool,
+    rules: Vec<RuleDef>,
+}
+
+/// Position in a rule's context, as `index()` and `context()` count them
+#[derive(Clone)]
+enum Position {
+    Char(char),
+    /// `any()`: KMS capture number
+    AnyOf(usize),
+    /// `notany()`: KMS capture number
+    NotAnyOf(usize),
+    State(String),
+    /// A key matched as virtual keys
+    VirtualKey,
+}
+
+enum Segment {
+    Text(String),
+    AnyOf(String),
+    NotAnyOf(String),
+    State(String),
+    Vk(Vec<String>),
+}
+
+enum Output {
+    Text(String),
+    BackRef(usize),
+    Indexed(String, usize),
+    Var(String),
+    State(String),
+    Null,
+}
+
+/// A rule that could not be converted, as (construct, reason)
+type RuleError = (String, String);
+
+pub(super) struct Converter {
+    source: String,
+    issues: Vec<ConversionIssue>,
+    stores: HashMap<String, StoreDef>,
+    /// Lower case store names, in file order
+    store_order: Vec<String>,
+    resolved: HashMap<String, Result<Vec<char>, String>>,
+    groups: Vec<GroupDef>,
+    begin: Option<(usize, String, Option<String>)>,
+    options: Vec<(&'static str, String)>,
+    comments: Vec<String>,
+    /// Context groups called with `use()`, and whether `match` calls them
+    used_groups: HashMap<String, bool>,
+    rules_converted: usize,
+    rules_skipped: usize,
+}
+
+impl Converter {
+    pub(super) fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            issues: Vec::new(),
+            stores: HashMap::new(),
+            store_order: Vec::new(),
+            resolved: HashMap::new(),
+            groups: Vec::new(),
+            begin: None,
+            options: Vec::new(),
+            comments: Vec::new(),
+            used_groups: HashMap::new(),
+            rules_converted: 0,
+            rules_skipped: 0,
+        }
+    }
+
+    fn issue(&mut self, line: usize, kind: IssueKind, construct: &str, message: impl Into<String>) {
+        self.issues.push(ConversionIssue { line, kind, construct: construct.to_string(), message: message.into() });
+    }
+
+    pub(super) fn convert(mut self) -> KmnConversion {
+        let mut system_stores = Vec::new();
+        for line in logical_lines(&self.source.clone()) {
+            let tokens = match tokenize(&line.text) {
+                Ok(tokens) => tokens,
+                Err(message) => {
+                    self.issue(line.number, IssueKind::Skipped, "line", message);
+                    continue;
+                }
+            };
+            match classify(tokens) {
+                Statement::Store { name, items } if name.starts_with('&') => system_stores.push((line.number, name, items)),
+                Statement::Store { name, items } => self.add_store(line.number, name, items),
+                Statement::Begin { encoding, group } => {
+                    // A Unicode start wins over an ANSI one
+                    if self.begin.as_ref().is_none_or(|(_, current, _)| current != "unicode") {
+                        self.begin = Some((line.number, encoding, group));
+                    }
+                }
+                Statement::Group { name, using_keys } => {
+                    self.groups.push(GroupDef { name, line: line.number, using_keys, rules: Vec::new() })
+                }
+                Statement::Rule { lhs, rhs } => match self.groups.last_mut() {
+                    Some(group) => group.rules.push(RuleDef { line: line.number, text: line.text, lhs, rhs }),
+                    None => self.issue(line.number, IssueKind::Skipped, "rule", "rules must be inside a group"),
+                },
+                Statement::Other(tokens) => {
+                    let construct = match tokens.first() {
+                        Some(Token::Word(word)) => word.clone(),
+                        Some(Token::Call(name, _)) => format!("{}()", name),
+                        _ => "line".to_string(),
+                    };
+                    self.issue(line.number, IssueKind::Skipped, &construct, "not a store, group or rule KeyMagic can use");
+                }
+            }
+        }
+
+        let names = self.store_order.clone();
+        for name in &names {
+            if let Err(message) = self.resolve(name, &mut HashSet::new()) {
+                let line = self.stores[name].line;
+                self.issue(line, IssueKind::Skipped, &format!("store({})", name), format!("{}; rules using it are skipped", message));
+            }
+        }
+        for (line, name, items) in system_stores {
+            self.system_store(line, &name, &items);
+        }
+
+        let rules = self.convert_groups();
+        let kms = self.write(rules);
+        KmnConversion {
+            kms,
+            report: ConversionReport { rules_converted: self.rules_converted, rules_skipped: self.rules_skipped, issues: self.issues },
+        }
+    }
+
+    fn add_store(&mut self, line: usize, name: String, items: Vec<Token>) {
+        let key = name.to_ascii_lowercase();
+        let mut var = identifier(&name);
+        while self.stores.values().any(|store| store.var == var) {
+            var.push('_');
+        }
+        if self.stores.insert(key.clone(), StoreDef { line, items, var }).is_none() {
+            self.store_order.push(key);
+        }
+    }
+
+    /// A store's characters, with `outs()` and keys expanded
+    fn resolve(&mut self, name: &str, visiting: &mut HashSet<String>) -> Result<Vec<char>, String> {
+        if let Some(resolved) = self.resolved.get(name) {
+            return resolved.clone();
+        }
+        let Some(store) = self.stores.get(name) else {
+            return Err(format!("no store named '{}'", name));
+        };
+        if !visiting.insert(name.to_string()) {
+            return Err(format!("store '{}' includes itself", name));
+        }
+        let items = store.items.clone();
+        let mut chars = Vec::new();
+        let mut result = Ok(());
+        for item in &items {
+            match item {
+                Token::Str(text) => chars.extend(text.chars()),
+                Token::Char(ch) => chars.push(*ch),
+                Token::Call(call, other) if call == "outs" => match self.resolve(&other.to_ascii_lowercase(), visiting) {
+                    Ok(other) => chars.extend(other),
+                    Err(message) => result = Err(message),
+                },
+                Token::Key(words) => match key_pattern(words) {
+                    Ok((KeyPattern::Char(ch), _)) => chars.push(ch),
+                    Ok((KeyPattern::Vk(_), _)) => result = Err(format!("key [{}] does not type a character", words.join(" "))),
+                    Err(message) => result = Err(message),
+                },
+                Token::Call(call, _) if call == "dk" || call == "deadkey" => result = Err("deadkeys in stores are not supported".to_string()),
+                Token::Word(word) if word.eq_ignore_ascii_case("nul") => {}
+                other => result = Err(format!("{} is not supported in stores", describe(other))),
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+        let resolved = result.map(|_| chars);
+        self.resolved.insert(name.to_string(), resolved.clone());
+        resolved
+    }
+
+    /// Header stores (`&NAME`, `&HOTKEY`, ...) as KMS options
+    fn system_store(&mut self, line: usize, name: &str, items: &[Token]) {
+        let value: String = items
+            .iter()
+            .filter_map(|item| match item {
+                Token::Str(text) => Some(text.clone()),
+                Token::Char(ch) => Some(ch.to_string()),
+                _ => None,
+            })
+            .collect();
+        let construct = format!("store({})", name);
+        match name.trim_start_matches('&').to_ascii_lowercase().as_str() {
+            "name" => self.options.push(("NAME", value)),
+            "message" => self.options.push(("DESCRIPTION", value)),
+            "copyright" => self.comments.push(value),
+            "hotkey" => match hotkey(&value) {
+                Some(hotkey) => self.options.push(("HOTKEY", hotkey)),
+                None => self.issue(line, IssueKind::Skipped, &construct, format!("no KeyMagic hotkey for {}", value)),
+            },
+            "mnemoniclayout" => {
+                if value.trim() == "1" {
+                    self.options.push(("US_LAYOUT_BASED", "FALSE".to_string()));
+                }
+            }
+            // The Keyman compiler version the source was written for
+            "version" => {}
+            "targets" => self.issue(
+                line,
+                IssueKind::Skipped,
+                &construct,
+                "platform targets do not apply; KeyMagic keyboards run on every platform KeyMagic supports",
+            ),
+            _ => self.issue(line, IssueKind::Skipped, &construct, "no KeyMagic equivalent"),
+        }
+    }
+
+    /// The rules of the starting group and of the groups it uses, as KMS
+    /// lines; the second list goes in `@post`
+    fn convert_groups(&mut self) -> (Vec<String>, Vec<String>) {
+        let start = match self.begin.clone() {
+            Some((line, _, Some(group))) => match self.groups.iter().position(|g| g.name == group) {
+                Some(index) => Some(index),
+                None => {
+                    self.issue(line, IssueKind::Skipped, "begin", format!("no group named '{}'", group));
+                    None
+                }
+            },
+            _ => {
+                let first = self.groups.iter().position(|g| g.using_keys);
+                if first.is_some() {
+                    self.issue(0, IssueKind::Approximated, "begin", "no 'begin' statement; starting from the first 'using keys' group");
+                }
+                first
+            }
+        };
+        let Some(start) = start else {
+            return (Vec::new(), Vec::new());
+        };
+        let groups = std::mem::take(&mut self.groups);
+        if !groups[start].using_keys {
+            self.issue(groups[start].line, IssueKind::Approximated, "group", "the starting group does not use keys; its rules are converted as key rules");
+        }
+
+        let mut rules = Vec::new();
+        for rule in &groups[start].rules {
+            if let Some(line) = self.convert_rule(rule, &groups, true) {
+                rules.push(line);
+            }
+        }
+
+        let mut post = Vec::new();
+        for (index, group) in groups.iter().enumerate() {
+            if index == start {
+                continue;
+            }
+            match self.used_groups.get(&group.name).copied() {
+                Some(from_match) => {
+                    if !from_match {
+                        self.issue(
+                            group.line,
+                            IssueKind::Approximated,
+                            &format!("group({})", group.name),
+                            "converted to @post rules, which run after every key and not only after the rules that use the group",
+                        );
+                    }
+                    for rule in &group.rules {
+                        if let Some(line) = self.convert_rule(rule, &groups, false) {
+                            post.push(line);
+                        }
+                    }
+                }
+                None => self.issue(group.line, IssueKind::Skipped, &format!("group({})", group.name), "not used from the starting group"),
+            }
+        }
+        self.groups = groups;
+        (rules, post)
+    }
+
+    /// A rule as a KMS line; a rule that cannot be converted is reported
+    /// and written as a comment
+    fn convert_rule(&mut self, rule: &RuleDef, groups: &[GroupDef], keyed: bool) -> Option<String> {
+        let mut notes = Vec::new();
+        match self.rule_text(rule, groups, keyed, &mut notes) {
+            Ok(Some(text)) => {
+                self.rules_converted += 1;
+                for (construct, message) in notes {
+                    self.issue(rule.line, IssueKind::Approximated, &construct, message);
+                }
+                Some(text)
+            }
+            Ok(None) => None,
+            Err((construct, message)) => {
+                self.rules_skipped += 1;
+                self.issue(rule.line, IssueKind::Skipped, &construct, message);
+                Some(format!("// Not converted (line {}): {}", rule.line, rule.text))
+            }
+        }
+    }
+
+    /// `Ok(None)` for rules that only wire groups together
+    fn rule_text(&mut self, rule: &RuleDef, groups: &[GroupDef], keyed: bool, notes: &mut Vec<RuleError>) -> Result<Option<String>, RuleError> {
+        if let [Token::Word(word)] = &rule.lhs[..] {
+            let word = word.to_ascii_lowercase();
+            if word == "match" && keyed {
+                if let [Token::Call(call, group)] = &rule.rhs[..] {
+                    if call == "use" {
+                        self.use_group(group, groups, true)?;
+                        return Ok(None);
+                    }
+                }
+                return Err(("match".to_string(), "only 'match > use(group)' is supported".to_string()));
+            }
+            if word == "match" || word == "nomatch" {
+                return Err((word, "has no KeyMagic equivalent".to_string()));
+            }
+        }
+
+        let (context, key) = match rule.lhs.iter().position(|token| *token == Token::Plus) {
+            Some(plus) if keyed => {
+                let key = &rule.lhs[plus + 1..];
+                if key.len() != 1 {
+                    return Err(("+".to_string(), "expected one key after '+'".to_string()));
+                }
+                (&rule.lhs[..plus], Some(&key[0]))
+            }
+            Some(_) => return Err(("+".to_string(), "keys can only be matched in the starting group".to_string())),
+            None if keyed => return Err(("rule".to_string(), "rules in a 'using keys' group need a key after '+'".to_string())),
+            None => (&rule.lhs[..], None),
+        };
+
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut positions: Vec<Position> = Vec::new();
+        let mut captures = 0;
+        for token in context {
+            self.context_item(token, &mut segments, &mut positions, &mut captures)?;
+        }
+        let context_len = positions.len();
+        if let Some(key) = key {
+            self.key_item(key, &mut segments, &mut positions, &mut captures, notes)?;
+        }
+        if !keyed && !segments.iter().any(|segment| !matches!(segment, Segment::State(_))) {
+            return Err(("dk()".to_string(), "rules after the key must match some text besides deadkeys".to_string()));
+        }
+
+        let mut outputs: Vec<Output> = Vec::new();
+        for token in &rule.rhs {
+            self.output_item(token, &positions[..context_len], &positions, &mut outputs, groups, keyed, notes)?;
+        }
+        if outputs.is_empty() {
+            outputs.push(Output::Null);
+        }
+
+        let lhs: Vec<String> = segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => kms_text(text),
+                Segment::AnyOf(var) => format!("${}[*]", var),
+                Segment::NotAnyOf(var) => format!("${}[^]", var),
+                Segment::State(state) => format!("('{}')", state),
+                Segment::Vk(keys) => format!("<{}>", keys.join(" & ")),
+            })
+            .collect();
+        let rhs: Vec<String> = outputs
+            .iter()
+            .map(|output| match output {
+                Output::Text(text) => kms_text(text),
+                Output::BackRef(n) => format!("${}", n),
+                Output::Indexed(var, n) => format!("${}[${}]", var, n),
+                Output::Var(var) => format!("${}", var),
+                Output::State(state) => format!("('{}')", state),
+                Output::Null => "NULL".to_string(),
+            })
+            .collect();
+        Ok(Some(format!("{} => {}", lhs.join(" + "), rhs.join(" + "))))
+    }
+
+    /// A store usable in a rule, as its KMS variable name
+    fn store_var(&mut self, name: &str, construct: &str) -> Result<String, RuleError> {
+        let name = name.trim().to_ascii_lowercase();
+        match self.resolve(&name, &mut HashSet::new()) {
+            Ok(_) => Ok(self.stores[&name].var.clone()),
+            Err(message) => Err((construct.to_string(), message)),
+        }
+    }
+
+    fn push_text(segments: &mut Vec<Segment>, positions: &mut Vec<Position>, captures: &mut usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match segments.last_mut() {
+            Some(Segment::Text(current)) => current.push_str(text),
+            _ => {
+                *captures += 1;
+                segments.push(Segment::Text(text.to_string()));
+            }
+        }
+        positions.extend(text.chars().map(Position::Char));
+    }
+
+    fn context_item(&mut self, token: &Token, segments: &mut Vec<Segment>, positions: &mut Vec<Position>, captures: &mut usize) -> Result<(), RuleError> {
+        match token {
+            Token::Str(text) => Self::push_text(segments, positions, captures, text),
+            Token::Char(ch) => Self::push_text(segments, positions, captures, &ch.to_string()),
+            Token::Call(call, args) if call == "any" || call == "notany" => {
+                let var = self.store_var(args, &format!("{}()", call))?;
+                *captures += 1;
+                if call == "any" {
+                    segments.push(Segment::AnyOf(var));
+                    positions.push(Position::AnyOf(*captures));
+                } else {
+                    segments.push(Segment::NotAnyOf(var));
+                    positions.push(Position::NotAnyOf(*captures));
+                }
+            }
+            Token::Call(call, args) if call == "outs" => {
+                self.store_var(args, "outs()")?;
+                let text: String = self.resolve(&args.trim().to_ascii_lowercase(), &mut HashSet::new()).unwrap_or_default().into_iter().collect();
+                Self::push_text(segments, positions, captures, &text);
+            }
+            Token::Call(call, args) if call == "dk" || call == "deadkey" => {
+                let state = state_name(args);
+                segments.push(Segment::State(state.clone()));
+                positions.push(Position::State(state));
+            }
+            Token::Word(word) if word.eq_ignore_ascii_case("nul") => {}
+            Token::Call(call, _) if call == "if" || call == "platform" || call == "baselayout" || call == "layer" => {
+                return Err((format!("{}()", call), "conditions on platform, options or layers have no KeyMagic equivalent".to_string()));
+            }
+            other => return Err((describe(other), "is not supported in the context".to_string())),
+        }
+        Ok(())
+    }
+
+    fn key_item(
+        &mut self,
+        token: &Token,
+        segments: &mut Vec<Segment>,
+        positions: &mut Vec<Position>,
+        captures: &mut usize,
+        notes: &mut Vec<RuleError>,
+    ) -> Result<(), RuleError> {
+        let ch = match token {
+            Token::Str(text) if text.chars().count() == 1 => text.chars().next(),
+            Token::Char(ch) => Some(*ch),
+            Token::Key(words) => {
+                let (pattern, caps) = key_pattern(words).map_err(|message| (format!("[{}]", words.join(" ")), message))?;
+                if caps {
+                    notes.push((format!("[{}]", words.join(" ")), "Caps Lock conditions are ignored".to_string()));
+                }
+                match pattern {
+                    KeyPattern::Char(ch) => Some(ch),
+                    KeyPattern::Vk(keys) => {
+                        segments.push(Segment::Vk(keys));
+                        positions.push(Position::VirtualKey);
+                        None
+                    }
+                }
+            }
+            Token::Call(call, args) if call == "any" => {
+                let var = self.store_var(args, "any()")?;
+                *captures += 1;
+                segments.push(Segment::AnyOf(var));
+                positions.push(Position::AnyOf(*captures));
+                None
+            }
+            other => return Err((describe(other), "is not supported as a key".to_string())),
+        };
+        if let Some(ch) = ch {
+            // Its own segment, so the key reads as one in the KMS rule
+            *captures += 1;
+            segments.push(Segment::Text(ch.to_string()));
+            positions.push(Position::Char(ch));
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn output_item(
+        &mut self,
+        token: &Token,
+        context: &[Position],
+        positions: &[Position],
+        outputs: &mut Vec<Output>,
+        groups: &[GroupDef],
+        keyed: bool,
+        notes: &mut Vec<RuleError>,
+    ) -> Result<(), RuleError> {
+        let push_text = |outputs: &mut Vec<Output>, text: &str| match outputs.last_mut() {
+            Some(Output::Text(current)) => current.push_str(text),
+            _ => outputs.push(Output::Text(text.to_string())),
+        };
+        let push_position = |outputs: &mut Vec<Output>, position: &Position| match position {
+            Position::Char(ch) => push_text(outputs, &ch.to_string()),
+            Position::AnyOf(capture) | Position::NotAnyOf(capture) => outputs.push(Output::BackRef(*capture)),
+            Position::State(state) => outputs.push(Output::State(state.clone())),
+            Position::VirtualKey => {}
+        };
+        let position = |args: &str, within: &[Position], construct: &str| -> Result<Position, RuleError> {
+            args.trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|n| within.get(n))
+                .cloned()
+                .ok_or_else(|| (construct.to_string(), format!("position {} is outside the context", args.trim())))
+        };
+
+        match token {
+            Token::Str(text) => push_text(outputs, text),
+            Token::Char(ch) => push_text(outputs, &ch.to_string()),
+            Token::Call(call, args) if call == "outs" => outputs.push(Output::Var(self.store_var(args, "outs()")?)),
+            Token::Word(word) if word.eq_ignore_ascii_case("context") => context.iter().for_each(|p| push_position(outputs, p)),
+            Token::Call(call, args) if call == "context" => push_position(outputs, &position(args, context, "context()")?),
+            Token::Call(call, args) if call == "index" => {
+                let (store, offset) = args.split_once(',').ok_or(("index()".to_string(), "expected index(store, offset)".to_string()))?;
+                let var = self.store_var(store, "index()")?;
+                match position(offset, positions, "index()")? {
+                    Position::AnyOf(capture) => outputs.push(Output::Indexed(var, capture)),
+                    _ => return Err(("index()".to_string(), "the offset must point at an any() in the context or key".to_string())),
+                }
+            }
+            Token::Call(call, args) if call == "dk" || call == "deadkey" => outputs.push(Output::State(state_name(args))),
+            Token::Word(word) if word.eq_ignore_ascii_case("nul") => {}
+            Token::Word(word) if word.eq_ignore_ascii_case("beep") => notes.push(("beep".to_string(), "dropped; KeyMagic does not beep".to_string())),
+            Token::Word(word) if word.eq_ignore_ascii_case("return") => {
+                notes.push(("return".to_string(), "dropped; @post rules still run after this rule".to_string()))
+            }
+            Token::Call(call, group) if call == "use" => {
+                if keyed {
+                    self.use_group(group, groups, false)?;
+                } else {
+                    notes.push(("use()".to_string(), "dropped; @post rules are not chained".to_string()));
+                }
+            }
+            Token::Call(call, _) if call == "call" => return Err(("call()".to_string(), "IMX calls have no KeyMagic equivalent".to_string())),
+            Token::Call(call, _) if matches!(call.as_str(), "set" | "save" | "reset" | "layer") => {
+                return Err((format!("{}()", call), "options and layers have no KeyMagic equivalent".to_string()));
+            }
+            other => return Err((describe(other), "is not supported in the output".to_string())),
+        }
+        Ok(())
+    }
+
+    /// Records that the starting group uses a context-only group
+    fn use_group(&mut self, name: &str, groups: &[GroupDef], from_match: bool) -> Result<(), RuleError> {
+        let name = name.trim().to_ascii_lowercase();
+        match groups.iter().find(|group| group.name == name) {
+            Some(group) if !group.using_keys => {
+                let entry = self.used_groups.entry(name).or_insert(from_match);
+                *entry = *entry && from_match;
+                Ok(())
+            }
+            Some(_) => Err(("use()".to_string(), format!("group '{}' uses keys; only context groups can follow the starting group", name))),
+            None => Err(("use()".to_string(), format!("no group named '{}'", name))),
+        }
+    }
+
+    fn write(&self, (rules, post): (Vec<String>, Vec<String>)) -> String {
+        let mut kms = String::from("/*\n");
+        let mut options = self.options.clone();
+        if !options.iter().any(|(name, _)| *name == "US_LAYOUT_BASED") {
+            // Keyman rules name keys by their US position
+            options.push(("US_LAYOUT_BASED", "TRUE".to_string()));
+        }
+        for (name, value) in options {
+            let value = value.replace('"', "'").replace("*/", "* /");
+            kms.push_str(&format!("@{} = \"{}\"\n", name, value));
+        }
+        kms.push_str("*/\n\n// Converted from a Keyman source file by kms2km2 convert-kmn\n");
+        for comment in &self.comments {
+            kms.push_str(&format!("// {}\n", comment));
+        }
+
+        let stores: Vec<&String> = self.store_order.iter().filter(|name| matches!(self.resolved.get(*name), Some(Ok(_)))).collect();
+        if !stores.is_empty() {
+            kms.push('\n');
+        }
+        for name in stores {
+            let chars: String = self.resolved[name].as_ref().map(|chars| chars.iter().collect()).unwrap_or_default();
+            kms.push_str(&format!("${} = {}\n", self.stores[name].var, kms_text(&chars)));
+        }
+
+        if !rules.is_empty() {
+            kms.push('\n');
+        }
+        for rule in rules {
+            kms.push_str(&rule);
+            kms.push('\n');
+        }
+        if !post.is_empty() {
+            kms.push_str("\n@post\n");
+            for rule in post {
+                kms.push_str(&rule);
+                kms.push('\n');
+            }
+            kms.push_str("@endpost\n");
+        }
+        kms
+    }
+}
+
+/// How a token is named in the report
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Str(text) => format!("'{}'", text),
+        Token::Char(ch) => format!("U+{:04X}", *ch as u32),
+        Token::Key(words) => format!("[{}]", words.join(" ")),
+        Token::Call(name, _) => format!("{}()", name),
+        Token::Word(word) => word.clone(),
+        Token::Plus => "+".to_string(),
+        Token::Arrow => ">".to_string(),
+    }
+}
+
+/// `[CTRL SHIFT K_K]` as `CTRL+SHIFT+K`
+fn hotkey(value: &str) -> Option<String> {
+    let words: Vec<String> = value.trim().trim_start_matches('[').trim_end_matches(']').split_whitespace().map(|w| w.to_ascii_uppercase()).collect();
+    let (key, modifiers) = words.split_last()?;
+    let mut parts: Vec<String> = Vec::new();
+    for modifier in modifiers {
+        parts.push(
+            match modifier.as_str() {
+                "CTRL" | "LCTRL" | "RCTRL" => "CTRL",
+                "SHIFT" => "SHIFT",
+                "ALT" | "LALT" | "RALT" => "ALT",
+                _ => return None,
+            }
+            .to_string(),
+        );
+    }
+    let key = key.strip_prefix("K_")?;
+    let key = match key {
+        "SPACE" => "SPACE".to_string(),
+        _ if key.len() == 1 && key.chars().all(|ch| ch.is_ascii_alphanumeric()) => key.to_string(),
+        _ => return None,
+    };
+    parts.push(key);
+    Some(parts.join("+"))
+}
@@ -0,0 +1,103 @@
+//! Best-effort conversion of Keyman keyboard sources (.kmn) to KMS
+//!
+//! Covers the subset most layouts use:
+//! - stores become variables; `any()`, `notany()`, `index()`, `context` and
+//!   `outs()` become `$var[*]`, `$var[^]`, `$var[$n]`, back-references and
+//!   `$var`
+//! - deadkeys become states
+//! - the starting `using keys` group becomes the rules, and context-only
+//!   groups it calls with `use()` become `@post` rules
+//! - keys become the characters they type on a US layout, or virtual keys
+//!   when they have other modifiers
+//!
+//! Everything else (match/nomatch rules other than `match > use(...)`,
+//! platform and layer conditions, IMX calls, options) is left out and listed
+//! in the [`ConversionReport`]; rules using it are written as comments.
+//! States only last one key in KeyMagic, while Keyman keeps deadkeys until
+//! they are used, so a layout that types between a deadkey and its use
+//! behaves differently.
+
+mod convert;
+pub mod parser;
+
+use keymagic_core::KmsError;
+use std::path::Path;
+
+/// How much of a construct made it into the KMS source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// Left out; a rule using it was written as a comment
+    Skipped,
+    /// Converted, but KeyMagic will not behave exactly the same
+    Approximated,
+}
+
+/// Something in the source that could not be converted as written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionIssue {
+    /// Line in the .kmn file, counted from 1
+    pub line: usize,
+    pub kind: IssueKind,
+    /// The construct, as written in Keyman (`nomatch`, `platform()`, ...)
+    pub construct: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConversionIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            IssueKind::Skipped => "skipped",
+            IssueKind::Approximated => "approximated",
+        };
+        write!(f, "line {}: {} {}: {}", self.line, kind, self.construct, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConversionReport {
+    pub rules_converted: usize,
+    pub rules_skipped: usize,
+    pub issues: Vec<ConversionIssue>,
+}
+
+/// KMS source converted from a .kmn file, and what did not convert
+#[derive(Debug, Clone)]
+pub struct KmnConversion {
+    pub kms: String,
+    pub report: ConversionReport,
+}
+
+/// Converts Keyman source text to KMS source
+pub fn convert_kmn(source: &str) -> KmnConversion {
+    convert::Converter::new(source).convert()
+}
+
+/// Reads a .kmn file, which Keyman writes as UTF-8 or UTF-16 with a byte
+/// order mark
+pub fn read_kmn_file(path: &Path) -> Result<String, KmsError> {
+    decode_kmn(&std::fs::read(path)?)
+}
+
+/// Decodes a .kmn file's bytes
+pub fn decode_kmn(data: &[u8]) -> Result<String, KmsError> {
+    let utf16 = |bytes: &[u8], from: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| from([pair[0], pair[1]])).collect();
+        String::from_utf16(&units).map_err(|_| KmsError::InvalidUnicode("the .kmn file is not valid UTF-16".to_string()))
+    };
+    match data {
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        _ => {
+            let data = data.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(data);
+            String::from_utf8(data.to_vec())
+                .map_err(|_| KmsError::InvalidUnicode("the .kmn file is neither UTF-8 nor UTF-16 with a byte order mark".to_string()))
+        }
+    }
+}
+
+/// Converts a .kmn file to a .kms file and returns the report
+pub fn convert_kmn_file(input_path: &Path, output_path: &Path) -> Result<ConversionReport, KmsError> {
+    let conversion = convert_kmn(&read_kmn_file(input_path)?);
+    std::fs::write(output_path, conversion.kms)?;
+    Ok(conversion.report)
+}
@@ -0,0 +1,212 @@
+//! Statements of a Keyman source file
+//!
+//! Only the shape of each line is parsed here; what the statements mean is
+//! worked out by the converter. Keywords, store and group names are case
+//! insensitive in Keyman, so names are compared in lower case.
+
+/// One item of a store, context or output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// `'abc'` or `"abc"`; Keyman has no escapes in strings
+    Str(String),
+    /// `U+1000`, `d4096` or `x1000`
+    Char(char),
+    /// `[SHIFT K_A]`, as upper case words
+    Key(Vec<String>),
+    /// `any(cons)`: name in lower case, arguments as written
+    Call(String, String),
+    Word(String),
+    Plus,
+    Arrow,
+}
+
+/// A logical line, continuation lines joined
+#[derive(Debug, Clone)]
+pub struct Line {
+    /// First physical line, counted from 1
+    pub number: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Store { name: String, items: Vec<Token> },
+    Begin { encoding: String, group: Option<String> },
+    Group { name: String, using_keys: bool },
+    Rule { lhs: Vec<Token>, rhs: Vec<Token> },
+    /// Anything else, kept for the report
+    Other(Vec<Token>),
+}
+
+/// Headers of keyboards written before `store(&NAME)` existed, and the
+/// system stores they stand for
+const OLD_HEADERS: &[&str] = &["name", "hotkey", "version", "bitmap", "bitmaps", "copyright", "message", "language", "layout", "caps"];
+
+/// Splits a source file into logical lines: comments removed, lines ending
+/// in `\` joined with the next one, blank lines left out
+pub fn logical_lines(source: &str) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut pending: Option<Line> = None;
+    for (index, raw) in source.lines().enumerate() {
+        let text = strip_comment(raw);
+        let text = text.trim_end();
+        let (text, continued) = match text.strip_suffix('\\') {
+            Some(text) => (text, true),
+            None => (text, false),
+        };
+        let line = match pending.take() {
+            Some(mut line) => {
+                line.text.push(' ');
+                line.text.push_str(text.trim());
+                line
+            }
+            None => Line { number: index + 1, text: text.trim().to_string() },
+        };
+        if continued {
+            pending = Some(line);
+        } else if !line.text.is_empty() {
+            lines.push(line);
+        }
+    }
+    if let Some(line) = pending.filter(|line| !line.text.is_empty()) {
+        lines.push(line);
+    }
+    lines
+}
+
+/// A line without its `c` comment. The comment word stands alone, outside
+/// strings and brackets.
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut previous = ' ';
+    for (index, ch) in line.char_indices() {
+        match quote {
+            Some(q) if ch == q => quote = None,
+            Some(_) => {}
+            None if ch == '\'' || ch == '"' => quote = Some(ch),
+            None if (ch == 'c' || ch == 'C') && previous.is_whitespace() => {
+                let next = line[index + 1..].chars().next();
+                if next.is_none_or(char::is_whitespace) {
+                    return &line[..index];
+                }
+            }
+            None => {}
+        }
+        previous = ch;
+    }
+    line
+}
+
+fn is_word_char(ch: char) -> bool {
+    !ch.is_whitespace() && !matches!(ch, '\'' | '"' | '[' | ']' | '(' | ')' | '+' | '>' | ',')
+}
+
+/// Splits a logical line into tokens
+pub fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        match ch {
+            _ if ch.is_whitespace() => i += 1,
+            '+' => {
+                tokens.push(Token::Plus);
+                i += 1;
+            }
+            '>' => {
+                tokens.push(Token::Arrow);
+                i += 1;
+            }
+            '\'' | '"' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|c| *c == ch)
+                    .ok_or_else(|| format!("unterminated string starting with {}", ch))?;
+                tokens.push(Token::Str(chars[i + 1..i + 1 + end].iter().collect()));
+                i += end + 2;
+            }
+            '[' => {
+                let end = chars[i + 1..].iter().position(|c| *c == ']').ok_or("unterminated key '['")?;
+                let key: String = chars[i + 1..i + 1 + end].iter().collect();
+                tokens.push(Token::Key(key.split_whitespace().map(|word| word.to_ascii_uppercase()).collect()));
+                i += end + 2;
+            }
+            _ if is_word_char(ch) => {
+                let start = i;
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                // The `+` of `U+1000` is not a separator
+                if i - start == 1 && (ch == 'U' || ch == 'u') && chars.get(i) == Some(&'+') && chars.get(i + 1).is_some_and(char::is_ascii_hexdigit) {
+                    i += 1;
+                    while i < chars.len() && is_word_char(chars[i]) {
+                        i += 1;
+                    }
+                }
+                let word: String = chars[start..i].iter().collect();
+                let mut next = i;
+                while next < chars.len() && chars[next].is_whitespace() {
+                    next += 1;
+                }
+                if next < chars.len() && chars[next] == '(' {
+                    let end = chars[next + 1..].iter().position(|c| *c == ')').ok_or_else(|| format!("unterminated '{}('", word))?;
+                    let args: String = chars[next + 1..next + 1 + end].iter().collect();
+                    tokens.push(Token::Call(word.to_ascii_lowercase(), args.trim().to_string()));
+                    i = next + end + 2;
+                } else {
+                    tokens.push(parse_char(&word).map(Token::Char).unwrap_or(Token::Word(word)));
+                }
+            }
+            _ => return Err(format!("unexpected '{}'", ch)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// `U+1000`, `d4096` or `x1000`
+fn parse_char(word: &str) -> Option<char> {
+    let (digits, radix) = if let Some(hex) = word.strip_prefix("U+").or_else(|| word.strip_prefix("u+")) {
+        (hex, 16)
+    } else if let Some(decimal) = word.strip_prefix(['d', 'D']) {
+        (decimal, 10)
+    } else {
+        (word.strip_prefix(['x', 'X'])?, 16)
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    char::from_u32(u32::from_str_radix(digits, radix).ok()?)
+}
+
+/// What kind of statement a tokenized line is
+pub fn classify(tokens: Vec<Token>) -> Statement {
+    match tokens.first() {
+        Some(Token::Call(name, args)) if name == "store" => {
+            let name = args.clone();
+            Statement::Store { name, items: tokens[1..].to_vec() }
+        }
+        Some(Token::Call(name, args)) if name == "group" => {
+            let using_keys = tokens[1..].iter().any(|token| matches!(token, Token::Word(w) if w.eq_ignore_ascii_case("keys")));
+            Statement::Group { name: args.to_ascii_lowercase(), using_keys }
+        }
+        Some(Token::Word(word)) if word.eq_ignore_ascii_case("begin") => {
+            let encoding = match tokens.get(1) {
+                Some(Token::Word(encoding)) => encoding.to_ascii_lowercase(),
+                _ => "ansi".to_string(),
+            };
+            let group = tokens.iter().find_map(|token| match token {
+                Token::Call(name, args) if name == "use" => Some(args.to_ascii_lowercase()),
+                _ => None,
+            });
+            Statement::Begin { encoding, group }
+        }
+        Some(Token::Word(word)) if OLD_HEADERS.contains(&word.to_ascii_lowercase().as_str()) && !tokens.contains(&Token::Arrow) => {
+            Statement::Store { name: format!("&{}", word), items: tokens[1..].to_vec() }
+        }
+        _ => match tokens.iter().position(|token| *token == Token::Arrow) {
+            Some(arrow) => Statement::Rule { lhs: tokens[..arrow].to_vec(), rhs: tokens[arrow + 1..].to_vec() },
+            None => Statement::Other(tokens),
+        },
+    }
+}
@@ -19,6 +19,28 @@ pub enum Token {
     #[token("null")]
     Null,
 
+    // Rule groups
+    #[token("@group")]
+    GroupStart,
+
+    #[token("@endgroup")]
+    GroupEnd,
+
+    // Post-rules, applied to the composing text after every key
+    #[token("@post")]
+    PostStart,
+
+    #[token("@endpost")]
+    PostEnd,
+
+    // Message for the host to show, on the RHS
+    #[token("@notify")]
+    Notify,
+
+    // Test run at compile time: `@test "keys" => "output"`
+    #[token("@test")]
+    Test,
+
     // Operators
     #[token("=>")]
     Arrow,
@@ -144,4 +166,18 @@ mod tests {
         assert_eq!(lex.next(), Some(Ok(Token::Any)));
         assert_eq!(lex.next(), None);
     }
+
+    #[test]
+    fn test_group_markers() {
+        let input = r#"@group "autocorrect" "a" => "b" @endgroup"#;
+        let mut lex = Token::lexer(input);
+
+        assert_eq!(lex.next(), Some(Ok(Token::GroupStart)));
+        assert_eq!(lex.next(), Some(Ok(Token::String("autocorrect".to_string()))));
+        assert_eq!(lex.next(), Some(Ok(Token::String("a".to_string()))));
+        assert_eq!(lex.next(), Some(Ok(Token::Arrow)));
+        assert_eq!(lex.next(), Some(Ok(Token::String("b".to_string()))));
+        assert_eq!(lex.next(), Some(Ok(Token::GroupEnd)));
+        assert_eq!(lex.next(), None);
+    }
 }
\ No newline at end of file
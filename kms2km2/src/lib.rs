@@ -2,37 +2,101 @@ pub mod lexer;
 pub mod parser;
 pub mod binary;
 pub mod include_processor;
+pub mod kmn;
+pub mod embedded_tests;
 
 pub use keymagic_core::*;
 
+use binary::CompileWarning;
+use embedded_tests::run_embedded_tests;
 use std::path::Path;
 use std::fs::File;
 use std::io::BufWriter;
 
-pub fn convert_kms_to_km2(input_path: &Path, output_path: &Path) -> std::result::Result<(), KmsError> {
+/// How a KMS file is compiled
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompileOptions {
+    /// km2 version to write (major, minor), `None` for the oldest one that fits
+    pub target_version: Option<(u8, u8)>,
+    /// Leave the `@test` lines unrun
+    pub skip_tests: bool,
+    /// Leave out the state and variable names, which the file does not need
+    /// to run
+    pub strip_names: bool,
+}
+
+/// Compiles a KMS file to a KM2 file and returns the compiler warnings,
+/// failed `@test` lines included
+pub fn convert_kms_to_km2(input_path: &Path, output_path: &Path) -> std::result::Result<Vec<CompileWarning>, KmsError> {
+    convert_kms_to_km2_with_options(input_path, output_path, CompileOptions::default())
+}
+
+/// Like [`convert_kms_to_km2`], writing the given km2 version (major, minor)
+/// instead of the oldest one that fits
+pub fn convert_kms_to_km2_for_version(input_path: &Path, output_path: &Path, target_version: Option<(u8, u8)>) -> std::result::Result<Vec<CompileWarning>, KmsError> {
+    convert_kms_to_km2_with_options(input_path, output_path, CompileOptions { target_version, ..CompileOptions::default() })
+}
+
+/// Like [`convert_kms_to_km2`] with the given options
+pub fn convert_kms_to_km2_with_options(input_path: &Path, output_path: &Path, options: CompileOptions) -> std::result::Result<Vec<CompileWarning>, KmsError> {
     // Compile KMS file
-    let km2 = compile_kms_file(input_path)?;
+    let (km2, warnings) = compile_file(input_path, options)?;
     
     // Write output
+    write_km2_file(&km2, output_path)?;
+    
+    Ok(warnings)
+}
+
+/// Writes a keyboard to a KM2 file
+pub fn write_km2_file(km2: &Km2File, output_path: &Path) -> std::result::Result<(), KmsError> {
     let file = File::create(output_path)?;
     let writer = BufWriter::new(file);
-    let km2_writer = binary::Km2Writer::new(writer);
-    km2_writer.write_km2_file(&km2)?;
-    
-    Ok(())
+    binary::Km2Writer::new(writer).write_km2_file(km2)
 }
 
 pub fn compile_kms_file(input_path: &Path) -> std::result::Result<Km2File, KmsError> {
+    compile_kms_file_with_warnings(input_path).map(|(km2, _)| km2)
+}
+
+/// Compiles a KMS file and returns the compiler warnings with it, failed
+/// `@test` lines included
+pub fn compile_kms_file_with_warnings(input_path: &Path) -> std::result::Result<(Km2File, Vec<CompileWarning>), KmsError> {
+    compile_file(input_path, CompileOptions::default())
+}
+
+/// Like [`compile_kms_file_with_warnings`] with the given options
+pub fn compile_kms_file_with_options(input_path: &Path, options: CompileOptions) -> std::result::Result<(Km2File, Vec<CompileWarning>), KmsError> {
+    compile_file(input_path, options)
+}
+
+fn compile_file(input_path: &Path, options: CompileOptions) -> std::result::Result<(Km2File, Vec<CompileWarning>), KmsError> {
     // Use include processor to handle includes
     let mut processor = include_processor::IncludeProcessor::new();
-    let ast = processor.process_file(input_path)?;
+    let mut ast = processor.process_file(input_path)?;
+    let tests = std::mem::take(&mut ast.tests);
     
     // Compile to KM2
     let mut compiler = binary::Compiler::new();
     if let Some(dir) = input_path.parent() {
         compiler = compiler.with_base_dir(dir);
     }
-    compiler.compile(ast)
+    if let Some((major, minor)) = options.target_version {
+        compiler = compiler.with_target_version(major, minor);
+    }
+    let (km2, mut warnings) = compiler.with_names(!options.strip_names).compile_with_warnings(ast)?;
+    if !options.skip_tests {
+        warnings.extend(test_warnings(&km2, &tests));
+    }
+    Ok((km2, warnings))
+}
+
+/// Failed `@test` lines, as warnings about the header
+fn test_warnings(km2: &Km2File, tests: &[parser::EmbeddedTest]) -> Vec<CompileWarning> {
+    run_embedded_tests(km2, tests)
+        .into_iter()
+        .map(|failure| CompileWarning { rule: None, message: failure.to_string() })
+        .collect()
 }
 
 pub fn compile_kms(kms_content: &str) -> std::result::Result<Km2File, KmsError> {
@@ -40,14 +104,23 @@ pub fn compile_kms(kms_content: &str) -> std::result::Result<Km2File, KmsError>
 }
 
 pub fn compile_kms_with_base_dir(kms_content: &str, base_dir: Option<&Path>) -> std::result::Result<Km2File, KmsError> {
+    compile_kms_with_warnings(kms_content, base_dir).map(|(km2, _)| km2)
+}
+
+/// Compiles KMS source and returns the compiler warnings with it, failed
+/// `@test` lines included
+pub fn compile_kms_with_warnings(kms_content: &str, base_dir: Option<&Path>) -> std::result::Result<(Km2File, Vec<CompileWarning>), KmsError> {
     // Use include processor to handle includes
     let mut processor = include_processor::IncludeProcessor::new();
-    let ast = processor.process_string(kms_content, base_dir)?;
+    let mut ast = processor.process_string(kms_content, base_dir)?;
+    let tests = std::mem::take(&mut ast.tests);
     
     // Compile to KM2
     let mut compiler = binary::Compiler::new();
     if let Some(dir) = base_dir {
         compiler = compiler.with_base_dir(dir);
     }
-    compiler.compile(ast)
+    let (km2, mut warnings) = compiler.compile_with_warnings(ast)?;
+    warnings.extend(test_warnings(&km2, &tests));
+    Ok((km2, warnings))
 }
\ No newline at end of file
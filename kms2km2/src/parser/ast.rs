@@ -7,6 +7,8 @@ pub struct KmsFile {
     pub variables: Vec<VariableDecl>,
     pub rules: Vec<RuleDecl>,
     pub includes: Vec<String>,
+    /// `@test` lines, run against the compiled keyboard but not written to it
+    pub tests: Vec<EmbeddedTest>,
 }
 
 impl KmsFile {
@@ -16,10 +18,21 @@ impl KmsFile {
             variables: Vec::new(),
             rules: Vec::new(),
             includes: Vec::new(),
+            tests: Vec::new(),
         }
     }
 }
 
+/// `@test "keys" => "output"`: typing `keys` (compact key text, see
+/// `keymagic_core::key_event`) from an empty composition gives `output`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddedTest {
+    pub input: String,
+    pub expected: String,
+    /// Line of the `@test` in its file
+    pub line: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct VariableDecl {
     pub name: String,
@@ -30,6 +43,10 @@ pub struct VariableDecl {
 pub struct RuleDecl {
     pub lhs: Vec<PatternElement>,
     pub rhs: Vec<OutputElement>,
+    /// Name of the enclosing `@group` block, if any
+    pub group: Option<String>,
+    /// Declared in a `@post` block
+    pub post: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -60,4 +77,5 @@ pub enum OutputElement {
     BackRef(usize),             // $1, $2, etc.
     Null,
     State(String),              // ('state_name')
+    Notify(String),             // @notify "message"
 }
\ No newline at end of file
@@ -7,6 +7,10 @@ pub struct Parser<'a> {
     lexer: Lexer<'a>,
     current: Option<Token>,
     peek: Option<Token>,
+    /// Group of the `@group` block being parsed
+    group: Option<String>,
+    /// Whether a `@post` block is being parsed
+    post: bool,
 }
 
 impl<'a> Parser<'a> {
@@ -23,6 +27,8 @@ impl<'a> Parser<'a> {
             lexer,
             current,
             peek,
+            group: None,
+            post: false,
         }
     }
 
@@ -46,6 +52,14 @@ impl<'a> Parser<'a> {
         while let Some(token) = &self.current {
             match token {
                 Token::Include => self.parse_include(&mut ast)?,
+                Token::GroupStart => self.parse_group_start()?,
+                Token::GroupEnd => self.parse_group_end()?,
+                Token::PostStart => self.parse_post_start()?,
+                Token::PostEnd => self.parse_post_end()?,
+                Token::Test => {
+                    let test = self.parse_test()?;
+                    ast.tests.push(test);
+                }
                 Token::Variable(_) => {
                     // Check if this is a variable declaration or part of a rule
                     if self.peek == Some(Token::Equals) {
@@ -64,6 +78,19 @@ impl<'a> Parser<'a> {
                 }
             }
         }
+
+        if let Some(group) = &self.group {
+            return Err(KmsError::Parse {
+                line: self.lexer.current_line(),
+                message: format!("Missing @endgroup for group '{}'", group),
+            });
+        }
+        if self.post {
+            return Err(KmsError::Parse {
+                line: self.lexer.current_line(),
+                message: "Missing @endpost".to_string(),
+            });
+        }
         
         Ok(ast)
     }
@@ -78,7 +105,7 @@ impl<'a> Parser<'a> {
             if let Some(end) = self.lexer.input[start..].find("*/") {
                 let comment = &self.lexer.input[start..start + end + 2];
                 for (key, value) in parse_options_from_comment(comment) {
-                    options.insert(key, value);
+                    insert_option(&mut options, key, value);
                 }
             }
         }
@@ -89,7 +116,7 @@ impl<'a> Parser<'a> {
             if trimmed.starts_with("//") && trimmed.contains('@') {
                 // Pass the line with // prefix to the parser
                 for (key, value) in parse_options_from_comment(trimmed) {
-                    options.insert(key, value);
+                    insert_option(&mut options, key, value);
                 }
             }
         }
@@ -131,6 +158,113 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
+    fn parse_test(&mut self) -> Result<EmbeddedTest, KmsError> {
+        let line = self.lexer.current_line();
+        self.expect(Token::Test)?;
+
+        let Some(Token::String(input)) = &self.current else {
+            return Err(KmsError::Parse {
+                line: self.lexer.current_line(),
+                message: "Expected key string after @test".to_string(),
+            });
+        };
+        let input = input.clone();
+        self.advance()?;
+        self.expect(Token::Arrow)?;
+
+        // The expected output may be split like a rule output
+        let mut expected = String::new();
+        loop {
+            match &self.current {
+                Some(Token::String(s)) => expected.push_str(s),
+                Some(Token::Unicode(Some(code))) => match char::from_u32(*code) {
+                    Some(ch) => expected.push(ch),
+                    None => {
+                        return Err(KmsError::Parse {
+                            line: self.lexer.current_line(),
+                            message: format!("Invalid Unicode character U+{:04X} in @test output", code),
+                        });
+                    }
+                },
+                _ => {
+                    return Err(KmsError::Parse {
+                        line: self.lexer.current_line(),
+                        message: "Expected output string in @test".to_string(),
+                    });
+                }
+            }
+            self.advance()?;
+            if self.current.as_ref() == Some(&Token::Plus) {
+                self.advance()?;
+            } else {
+                break;
+            }
+        }
+
+        Ok(EmbeddedTest { input, expected, line })
+    }
+
+    fn parse_group_start(&mut self) -> Result<(), KmsError> {
+        self.expect(Token::GroupStart)?;
+
+        let name = if let Some(Token::String(name)) = &self.current {
+            name.clone()
+        } else {
+            return Err(KmsError::Parse {
+                line: self.lexer.current_line(),
+                message: "Expected group name string after '@group'".to_string(),
+            });
+        };
+
+        if name.is_empty() {
+            return Err(KmsError::Parse {
+                line: self.lexer.current_line(),
+                message: "Group name cannot be empty".to_string(),
+            });
+        }
+        if let Some(outer) = &self.group {
+            return Err(KmsError::Parse {
+                line: self.lexer.current_line(),
+                message: format!("Group '{}' cannot be nested inside group '{}'", name, outer),
+            });
+        }
+
+        self.group = Some(name);
+        self.advance()
+    }
+
+    fn parse_group_end(&mut self) -> Result<(), KmsError> {
+        if self.group.take().is_none() {
+            return Err(KmsError::Parse {
+                line: self.lexer.current_line(),
+                message: "'@endgroup' without matching '@group'".to_string(),
+            });
+        }
+        self.expect(Token::GroupEnd)
+    }
+
+    fn parse_post_start(&mut self) -> Result<(), KmsError> {
+        if self.post {
+            return Err(KmsError::Parse {
+                line: self.lexer.current_line(),
+                message: "'@post' blocks cannot be nested".to_string(),
+            });
+        }
+        self.post = true;
+        self.expect(Token::PostStart)
+    }
+
+    fn parse_post_end(&mut self) -> Result<(), KmsError> {
+        if !self.post {
+            return Err(KmsError::Parse {
+                line: self.lexer.current_line(),
+                message: "'@endpost' without matching '@post'".to_string(),
+            });
+        }
+        self.post = false;
+        self.expect(Token::PostEnd)
+    }
+
     fn parse_variable_decl(&mut self) -> Result<VariableDecl, KmsError> {
         let name = if let Some(Token::Variable(n)) = &self.current {
             n.clone()
@@ -192,11 +326,37 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_rule(&mut self) -> Result<RuleDecl, KmsError> {
+        let line = self.lexer.current_line();
         let lhs = self.parse_pattern()?;
+        if self.post {
+            Self::check_post_pattern(&lhs, line)?;
+        }
         self.expect(Token::Arrow)?;
         let rhs = self.parse_output()?;
         
-        Ok(RuleDecl { lhs, rhs })
+        Ok(RuleDecl { lhs, rhs, group: self.group.clone(), post: self.post })
+    }
+
+    /// Post-rules run without a key, so their LHS can only match text
+    fn check_post_pattern(lhs: &[PatternElement], line: usize) -> Result<(), KmsError> {
+        if let Some(PatternElement::VirtualKeyCombo(keys)) =
+            lhs.iter().find(|e| matches!(e, PatternElement::VirtualKeyCombo(_)))
+        {
+            return Err(KmsError::Parse {
+                line,
+                message: format!(
+                    "Rules in '@post' cannot match virtual keys (<{}>); they match the composing text after every key",
+                    keys.join(" & ")
+                ),
+            });
+        }
+        if lhs.iter().all(|e| matches!(e, PatternElement::State(_))) {
+            return Err(KmsError::Parse {
+                line,
+                message: "Rules in '@post' must match some composing text".to_string(),
+            });
+        }
+        Ok(())
     }
 
     fn parse_pattern(&mut self) -> Result<Vec<PatternElement>, KmsError> {
@@ -361,6 +521,17 @@ impl<'a> Parser<'a> {
                 Some(Token::LParen) => {
                     elements.push(self.parse_state_output()?);
                 }
+                Some(Token::Notify) => {
+                    self.advance()?;
+                    let Some(Token::String(message)) = &self.current else {
+                        return Err(KmsError::Parse {
+                            line: self.lexer.current_line(),
+                            message: "Expected message string after @notify".to_string(),
+                        });
+                    };
+                    elements.push(OutputElement::Notify(message.clone()));
+                    self.advance()?;
+                }
                 _ => break,
             }
             
@@ -399,4 +570,23 @@ impl<'a> Parser<'a> {
         
         Ok(OutputElement::State(state))
     }
-}
\ No newline at end of file
+}
+
+/// Adds a header option; `@LOCALE` may be given several times and its
+/// values are joined with commas, other options keep the last value.
+/// `@LOCALE` and `@SAMPLE` may be written in any case.
+fn insert_option(options: &mut HashMap<String, String>, key: String, value: String) {
+    if key.eq_ignore_ascii_case("SAMPLE") {
+        options.insert("SAMPLE".to_string(), value);
+    } else if key.eq_ignore_ascii_case("LOCALE") {
+        options
+            .entry("LOCALE".to_string())
+            .and_modify(|locales| {
+                locales.push(',');
+                locales.push_str(&value);
+            })
+            .or_insert(value);
+    } else {
+        options.insert(key, value);
+    }
+}
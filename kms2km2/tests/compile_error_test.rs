@@ -1,5 +1,7 @@
 use kms2km2::compile_kms;
 use keymagic_core::KmsError;
+use keymagic_core::types::km2::BinaryFormatElement;
+use keymagic_core::types::opcodes::MAX_NOTIFY_LEN;
 
 #[test]
 fn test_compile_fails_on_bom_without_stripping() {
@@ -41,4 +43,34 @@ fn test_compile_succeeds_on_normal_content() {
         let info_count = km2.header.info_count;
         assert_eq!(info_count, 1); // NAME info
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_notify_compiles_to_its_own_element() {
+    let km2 = compile_kms(r#"<VK_F2> => ('numbers') + @notify "Numbers mode""#).unwrap();
+    assert!(matches!(
+        km2.rules[0].rhs.as_slice(),
+        [BinaryFormatElement::Switch(0), BinaryFormatElement::Notify(message)] if message == "Numbers mode"
+    ));
+}
+
+#[test]
+fn test_notify_message_length_is_checked() {
+    let longest = format!(r#""n" => @notify "{}""#, r"\u1040".repeat(MAX_NOTIFY_LEN));
+    assert!(compile_kms(&longest).is_ok());
+
+    for message in ["a".repeat(MAX_NOTIFY_LEN + 1), String::new(), "  ".to_string()] {
+        match compile_kms(&format!(r#""n" => @notify "{}""#, message)) {
+            Err(KmsError::InvalidRule(error)) => assert!(error.contains("@notify"), "{}", error),
+            other => panic!("Expected InvalidRule for {:?}, got {:?}", message, other),
+        }
+    }
+}
+
+#[test]
+fn test_notify_needs_a_message_string() {
+    match compile_kms(r#""n" => @notify $numbers"#) {
+        Err(KmsError::Parse { message, .. }) => assert!(message.contains("@notify")),
+        other => panic!("Expected Parse error, got {:?}", other),
+    }
+}
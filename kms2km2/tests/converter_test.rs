@@ -1,7 +1,7 @@
 use std::path::Path;
 use std::fs;
 use kms2km2::{convert_kms_to_km2, compile_kms_file};
-use keymagic_core::*;
+use keymagic_core::types::{km2::*, opcodes::*};
 
 #[test]
 fn test_simple_conversion() {
@@ -0,0 +1,89 @@
+//! Tests for `@test` lines run at compile time
+
+use kms2km2::embedded_tests::{run_embedded_tests, TestFailureKind};
+use kms2km2::parser::{EmbeddedTest, Parser};
+use kms2km2::{compile_kms, compile_kms_file_with_options, convert_kms_to_km2, CompileOptions};
+use keymagic_core::key_event::KeyEventError;
+use keymagic_core::KmsError;
+use std::fs;
+use std::path::Path;
+
+const PASSING: &str = "tests/fixtures/embedded_tests_pass.kms";
+const FAILING: &str = "tests/fixtures/embedded_tests_fail.kms";
+
+#[test]
+fn test_tests_are_parsed_into_their_own_section() {
+    let source = "\"k\" => U1000\n\n@test \"ka\" => U1000 + \"ာ\"\n@test \"{BKSP}\" => \"\"\n";
+    let ast = Parser::new(source).parse().unwrap();
+    assert_eq!(ast.rules.len(), 1);
+    assert_eq!(
+        ast.tests,
+        vec![
+            EmbeddedTest { input: "ka".to_string(), expected: "ကာ".to_string(), line: 3 },
+            EmbeddedTest { input: "{BKSP}".to_string(), expected: String::new(), line: 4 },
+        ]
+    );
+
+    for (source, message) in [
+        ("@test => \"က\"", "Expected key string after @test"),
+        ("@test \"k\" \"က\"", "Expected Arrow"),
+        ("@test \"k\" => $x", "Expected output string in @test"),
+    ] {
+        match Parser::new(source).parse() {
+            Err(KmsError::Parse { line: 1, message: error }) => assert!(error.contains(message), "{}: {}", source, error),
+            other => panic!("{}: {:?}", source, other),
+        }
+    }
+}
+
+#[test]
+fn test_passing_tests_report_nothing() {
+    let output = &std::env::temp_dir().join("embedded_tests_pass.km2");
+    let warnings = convert_kms_to_km2(Path::new(PASSING), output).unwrap();
+    assert!(warnings.is_empty(), "{:?}", warnings);
+
+    // The tests are not written to the keyboard
+    let source = fs::read_to_string(PASSING).unwrap();
+    let without_tests: String = source.lines().filter(|line| !line.starts_with("@test")).map(|line| format!("{}\n", line)).collect();
+    let mut expected = Vec::new();
+    kms2km2::binary::Km2Writer::new(&mut expected).write_km2_file(&compile_kms(&without_tests).unwrap()).unwrap();
+    assert_eq!(fs::read(output).unwrap(), expected);
+    let _ = fs::remove_file(output);
+}
+
+#[test]
+fn test_failing_tests_are_reported_as_warnings() {
+    let output = &std::env::temp_dir().join("embedded_tests_fail.km2");
+    let warnings = convert_kms_to_km2(Path::new(FAILING), output).unwrap();
+    let messages: Vec<String> = warnings.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "@test on line 10 failed: \"ky\" typed \"ကြ\", expected \"ကျ\"".to_string(),
+            "@test on line 11 has invalid keys: Unknown key name: NOPE".to_string(),
+        ]
+    );
+    // The keyboard is still written
+    assert!(output.exists());
+    let _ = fs::remove_file(output);
+
+    let options = CompileOptions { skip_tests: true, ..CompileOptions::default() };
+    let (_, warnings) = compile_kms_file_with_options(Path::new(FAILING), options).unwrap();
+    assert!(warnings.is_empty(), "{:?}", warnings);
+}
+
+#[test]
+fn test_runner_reports_each_failure() {
+    let keyboard = compile_kms("\"k\" => U1000").unwrap();
+    let test = |input: &str, expected: &str| EmbeddedTest { input: input.to_string(), expected: expected.to_string(), line: 1 };
+    let tests = [test("k", "က"), test("kk", "က"), test("<Shift+", ""), test("", "")];
+
+    let failures = run_embedded_tests(&keyboard, &tests);
+    assert_eq!(failures.len(), 2);
+    assert_eq!(failures[0].test, tests[1]);
+    assert_eq!(failures[0].kind, TestFailureKind::Mismatch { actual: "ကက".to_string() });
+    assert!(matches!(failures[1].kind, TestFailureKind::InvalidKeys(KeyEventError::Syntax { offset: 0, .. })));
+
+    // Each test starts from an empty composition
+    assert!(run_embedded_tests(&keyboard, &[test("k", "က"), test("k", "က")]).is_empty());
+}
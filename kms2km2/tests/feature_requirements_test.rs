@@ -0,0 +1,38 @@
+use keymagic_core::km2::features::{CASE_INSENSITIVE_ASCII, NOTIFY, POST_RULES, RULE_GROUPS};
+use keymagic_core::km2::{FeatureRequirements, FeatureSet, Km2Loader};
+use keymagic_core::types::km2::INFO_FEAT;
+use kms2km2::binary::Km2Writer;
+use kms2km2::compile_kms;
+
+fn requirements(source: &str) -> Option<FeatureRequirements> {
+    let km2 = compile_kms(source).unwrap();
+    let mut buffer = Vec::new();
+    Km2Writer::new(&mut buffer).write_km2_file(&km2).unwrap();
+    let loaded = Km2Loader::load(&buffer).unwrap();
+    loaded.metadata().get(INFO_FEAT).and_then(|data| FeatureRequirements::decode(data))
+}
+
+#[test]
+fn test_plain_keyboard_records_no_features() {
+    assert_eq!(requirements("\"k\" => \"က\""), None);
+}
+
+#[test]
+fn test_compiler_records_the_features_used() {
+    let source = r#"
+/*
+@CASE_INSENSITIVE_ASCII = "TRUE"
+*/
+"n" => @notify "Numbers"
+
+@post
+U1038 + U103A => U103A + U1038
+@endpost
+"#;
+    let requirements = requirements(source).unwrap();
+    let expected = FeatureSet::default().with(NOTIFY).with(POST_RULES).with(CASE_INSENSITIVE_ASCII);
+    assert_eq!(requirements.features, expected);
+    assert_eq!(requirements.engine, expected.required_version());
+    assert_eq!(requirements.name(POST_RULES.bit), POST_RULES.name);
+    assert!(!requirements.features.contains(RULE_GROUPS));
+}
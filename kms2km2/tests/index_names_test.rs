@@ -0,0 +1,70 @@
+use keymagic_core::km2::Km2Loader;
+use keymagic_core::types::km2::{INFO_STNM, INFO_VRNM};
+use kms2km2::binary::{Compiler, Km2Writer};
+use kms2km2::parser::Parser;
+use kms2km2::{compile_kms, compile_kms_file_with_options, CompileOptions};
+
+const SOURCE: &str = r#"
+$cons = "ကခ"
+$vowels = "ါာ"
+$same = "ကခ"
+<VK_SHIFT & VK_KEY_Z> => ('zg')
+('zg') + "k" => "ၵ"
+"q" => ('other')
+$cons[*] + "a" => $1 + "ာ"
+"#;
+
+fn round_trip(km2: &kms2km2::Km2File) -> kms2km2::Km2File {
+    let mut buffer = Vec::new();
+    Km2Writer::new(&mut buffer).write_km2_file(km2).unwrap();
+    Km2Loader::load(&buffer).unwrap()
+}
+
+#[test]
+fn test_names_are_compiled_in() {
+    let km2 = round_trip(&compile_kms(SOURCE).unwrap());
+
+    assert_eq!(km2.states(), vec!["zg".to_string(), "other".to_string()]);
+    // Every declared variable, in order, even those sharing a string
+    assert_eq!(
+        km2.variables(),
+        vec![
+            ("cons".to_string(), "ကခ".to_string()),
+            ("vowels".to_string(), "ါာ".to_string()),
+            ("same".to_string(), "ကခ".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_names_can_be_left_out() {
+    let ast = Parser::new(SOURCE).parse().unwrap();
+    let km2 = round_trip(&Compiler::new().with_names(false).compile(ast).unwrap());
+
+    assert!(!km2.metadata().has(INFO_STNM));
+    assert!(!km2.metadata().has(INFO_VRNM));
+    assert_eq!(km2.states(), vec!["state0".to_string(), "state1".to_string()]);
+    assert_eq!(km2.variables(), vec![("var1".to_string(), "ကခ".to_string())]);
+}
+
+#[test]
+fn test_keyboards_without_states_or_variables_add_no_entries() {
+    let km2 = compile_kms("\"a\" => \"b\"").unwrap();
+    assert!(km2.info.is_empty());
+}
+
+#[test]
+fn test_strip_names_option() {
+    let dir = std::env::temp_dir().join(format!("kms2km2_names_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("names.kms");
+    std::fs::write(&path, SOURCE).unwrap();
+
+    let (named, _) = compile_kms_file_with_options(&path, CompileOptions::default()).unwrap();
+    let (stripped, _) = compile_kms_file_with_options(&path, CompileOptions { strip_names: true, ..CompileOptions::default() }).unwrap();
+    assert!(named.metadata().has(INFO_STNM));
+    assert!(!stripped.metadata().has(INFO_STNM));
+    assert_eq!(stripped.info.len(), named.info.len() - 2);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,119 @@
+use kms2km2::binary::Km2Writer;
+use kms2km2::km2::{Km2Error, Km2Json, Km2Loader};
+use kms2km2::types::opcodes::FLAG_ANYOF;
+use kms2km2::{compile_kms, Km2File, VirtualKey};
+use std::path::PathBuf;
+
+fn write(km2: &Km2File) -> Vec<u8> {
+    let mut data = Vec::new();
+    Km2Writer::new(&mut data).write_km2_file(km2).unwrap();
+    data
+}
+
+fn fixture_keyboards() -> Vec<PathBuf> {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..");
+    let mut paths = vec![root.join("kms2km2/tests/fixtures/comprehensive_test.km2")];
+    for entry in std::fs::read_dir(root.join("keyboards/bundled")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "km2") {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+#[test]
+fn test_fixture_keyboards_round_trip() {
+    for path in fixture_keyboards() {
+        let data = std::fs::read(&path).unwrap();
+        let km2 = Km2Loader::load(&data).unwrap();
+        let json = km2.to_json();
+        let restored = Km2File::from_json(&json).unwrap();
+        assert_eq!(write(&restored), data, "{}", path.display());
+    }
+}
+
+#[test]
+fn test_json_representation() {
+    let km2 = compile_kms("/*\n@NAME = \"Test\"\n*/\n$cons = \"ka\"\n$cons[*] + <VK_SHIFT & VK_KEY_A> => $1 + NULL").unwrap();
+    let json: serde_json::Value = serde_json::from_str(&km2.to_json()).unwrap();
+
+    assert_eq!(json["schema_version"], 1);
+    assert_eq!(json["format_version"], serde_json::json!({ "major": 1, "minor": 5 }));
+    assert_eq!(json["strings"], serde_json::json!(["ka"]));
+    assert_eq!(json["info"][0], serde_json::json!({ "id": "name", "data": "VGVzdA==" }));
+    assert_eq!(
+        json["rules"][0]["lhs"],
+        serde_json::json!([
+            { "type": "variable", "value": 1 },
+            { "type": "modifier", "value": FLAG_ANYOF },
+            { "type": "and" },
+            { "type": "predefined", "value": VirtualKey::Shift as u16 },
+            { "type": "predefined", "value": VirtualKey::KeyA as u16 },
+        ])
+    );
+}
+
+#[test]
+fn test_schema_version_is_validated() {
+    let km2 = compile_kms(r#""a" => "b""#).unwrap();
+    let mut json: serde_json::Value = serde_json::from_str(&km2.to_json()).unwrap();
+
+    json["schema_version"] = 2.into();
+    assert!(matches!(Km2File::from_json(&json.to_string()), Err(Km2Error::UnsupportedJsonSchema(2))));
+
+    json.as_object_mut().unwrap().remove("schema_version");
+    assert!(matches!(Km2File::from_json(&json.to_string()), Err(Km2Error::InvalidJson(_))));
+}
+
+#[test]
+fn test_unknown_fields_are_preserved() {
+    let km2 = compile_kms(r#""a" => "b""#).unwrap();
+    let mut json: serde_json::Value = serde_json::from_str(&km2.to_json()).unwrap();
+    json["editor"] = serde_json::json!({ "zoom": 2 });
+
+    let document = Km2Json::parse(&json.to_string()).unwrap();
+    assert_eq!(document.extra["editor"], serde_json::json!({ "zoom": 2 }));
+    let written: serde_json::Value = serde_json::to_value(&document).unwrap();
+    assert_eq!(written, json);
+
+    // The keyboard itself is unaffected
+    assert_eq!(write(&document.into_km2().unwrap()), write(&km2));
+}
+
+#[test]
+fn test_header_counts_follow_arrays() {
+    let km2 = compile_kms("\"a\" => \"b\"\n\"c\" => \"d\"").unwrap();
+    let mut json: serde_json::Value = serde_json::from_str(&km2.to_json()).unwrap();
+    json["rules"].as_array_mut().unwrap().pop();
+
+    let restored = Km2File::from_json(&json.to_string()).unwrap();
+    assert_eq!({ restored.header.rule_count }, 1);
+    assert_eq!(restored.rules.len(), 1);
+}
+
+#[test]
+fn test_invalid_documents() {
+    let km2 = compile_kms(r#""a" => "b""#).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&km2.to_json()).unwrap();
+
+    let mut bad_version = json.clone();
+    bad_version["format_version"]["minor"] = 9.into();
+    assert!(matches!(
+        Km2File::from_json(&bad_version.to_string()),
+        Err(Km2Error::NewerVersion { major: 1, minor: 9, .. })
+    ));
+    bad_version["format_version"]["minor"] = 2.into();
+    assert!(matches!(
+        Km2File::from_json(&bad_version.to_string()),
+        Err(Km2Error::UnsupportedVersion { major: 1, minor: 2 })
+    ));
+
+    let mut bad_element = json.clone();
+    bad_element["rules"][0]["lhs"][0] = serde_json::json!({ "type": "unknown" });
+    assert!(matches!(Km2File::from_json(&bad_element.to_string()), Err(Km2Error::InvalidJson(_))));
+
+    let mut bad_info = json;
+    bad_info["info"] = serde_json::json!([{ "id": "name", "data": "not base64!" }]);
+    assert!(matches!(Km2File::from_json(&bad_info.to_string()), Err(Km2Error::InvalidJson(_))));
+}
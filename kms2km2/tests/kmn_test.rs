@@ -0,0 +1,111 @@
+//! Converting Keyman sources and typing with the result
+
+use kms2km2::kmn::{convert_kmn, decode_kmn, IssueKind};
+use kms2km2::{compile_kms, KeyInput, KeyMagicEngine, ModifierState, VirtualKey};
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/kmn").join(name);
+    std::fs::read_to_string(path).unwrap()
+}
+
+fn engine_for(kmn: &str) -> KeyMagicEngine {
+    let conversion = convert_kmn(kmn);
+    let km2 = compile_kms(&conversion.kms).unwrap_or_else(|e| panic!("{}\n{}", e, conversion.kms));
+    KeyMagicEngine::new(km2).unwrap()
+}
+
+fn type_text(engine: &mut KeyMagicEngine, text: &str) -> String {
+    for ch in text.chars() {
+        engine.process_key(KeyInput::from_char(ch)).unwrap();
+    }
+    engine.composing_text().to_string()
+}
+
+#[test]
+fn test_basic_keyboard_converts_without_skipping() {
+    let conversion = convert_kmn(&fixture("basic.kmn"));
+    assert_eq!(conversion.report.rules_skipped, 0);
+    assert_eq!(conversion.report.rules_converted, 9);
+    assert!(conversion.kms.contains("@NAME = \"Basic Test\""));
+    assert!(conversion.kms.contains("@HOTKEY = \"CTRL+SHIFT+K\""));
+    assert!(conversion.kms.contains("$vowels[*] + $medialKeys[*] => $1 + $medials[$2]"));
+    assert!(conversion.kms.contains("<VK_ALT_GR & VK_KEY_M>"));
+
+    // Only the beep was left out
+    let issues = &conversion.report.issues;
+    assert_eq!(issues.len(), 1);
+    assert_eq!((issues[0].line, issues[0].kind, issues[0].construct.as_str()), (24, IssueKind::Approximated, "beep"));
+}
+
+#[test]
+fn test_converted_keyboard_types_like_the_source() {
+    let mut engine = engine_for(&fixture("basic.kmn"));
+    assert_eq!(type_text(&mut engine, "ka"), "\u{1000}\u{102C}");
+
+    engine.reset();
+    assert_eq!(type_text(&mut engine, "i"), "\u{1024}");
+    assert_eq!(type_text(&mut engine, "Y"), "\u{1024}\u{103C}");
+
+    // A deadkey becomes a state for the next key
+    engine.reset();
+    assert_eq!(type_text(&mut engine, "`n"), "\u{1009}");
+
+    // Context group rules run on the text after each key
+    engine.reset();
+    assert_eq!(type_text(&mut engine, "\u{1031}a"), "\u{1021}\u{1031}");
+
+    engine.reset();
+    let ctrl_q = KeyInput::new(VirtualKey::KeyQ as u16, ModifierState::new(false, true, false, false), None);
+    engine.process_key(ctrl_q).unwrap();
+    assert_eq!(engine.composing_text(), "q");
+}
+
+#[test]
+fn test_unsupported_constructs_are_reported_and_commented_out() {
+    let conversion = convert_kmn(&fixture("unsupported.kmn"));
+    assert_eq!(conversion.report.rules_converted, 1);
+    assert_eq!(conversion.report.rules_skipped, 3);
+
+    let skipped: Vec<(usize, &str)> = conversion
+        .report
+        .issues
+        .iter()
+        .filter(|issue| issue.kind == IssueKind::Skipped)
+        .map(|issue| (issue.line, issue.construct.as_str()))
+        .collect();
+    assert_eq!(
+        skipped,
+        [(2, "store(&TARGETS)"), (8, "platform()"), (9, "call()"), (10, "nomatch"), (12, "group(spare)")]
+    );
+    assert!(conversion.kms.contains("// Not converted (line 9): + 'c' > call(helper)"));
+
+    // The rest still compiles and types
+    let mut engine = engine_for(&fixture("unsupported.kmn"));
+    assert_eq!(type_text(&mut engine, "a"), "\u{1021}");
+}
+
+#[test]
+fn test_keys_and_strings_are_written_as_kms() {
+    let conversion = convert_kmn(
+        "begin Unicode > use(main)\n\
+         group(main) using keys\n\
+         + [SHIFT K_QUOTE] > '\"\\' U+200B\n\
+         + [K_F1] > 'f'\n\
+         + [K_BADKEY] > 'x'\n",
+    );
+    assert!(conversion.kms.contains("\"\\\"\" => \"\\\"\\\\\" + U200B"), "{}", conversion.kms);
+    assert!(conversion.kms.contains("<VK_F1> => \"f\""));
+    assert_eq!(conversion.report.rules_skipped, 1);
+    assert_eq!(conversion.report.issues[0].construct, "[K_BADKEY]");
+    compile_kms(&conversion.kms).unwrap();
+}
+
+#[test]
+fn test_utf16_sources_are_decoded() {
+    let text = "store(&NAME) 'Wide'\n";
+    let mut data = vec![0xFF, 0xFE];
+    data.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+    assert_eq!(decode_kmn(&data).unwrap(), text);
+    assert!(decode_kmn(&[0xC3, 0x28]).is_err());
+}
@@ -0,0 +1,120 @@
+//! Keyboards with more rules or strings than km2 1.5 can count
+
+use kms2km2::binary::{Compiler, Km2Writer};
+use kms2km2::include_processor::IncludeProcessor;
+use kms2km2::km2::{Km2Error, Km2Loader};
+use kms2km2::types::km2::KM2_LEGACY_LIMIT;
+use kms2km2::{compile_kms, KeyInput, KeyMagicEngine, Km2File, KmsError};
+
+fn write(km2: &Km2File) -> Vec<u8> {
+    let mut data = Vec::new();
+    Km2Writer::new(&mut data).write_km2_file(km2).unwrap();
+    data
+}
+
+fn compile_for(kms: &str, target: Option<(u8, u8)>) -> Result<Km2File, KmsError> {
+    let ast = IncludeProcessor::new().process_string(kms, None)?;
+    let mut compiler = Compiler::new();
+    if let Some((major, minor)) = target {
+        compiler = compiler.with_target_version(major, minor);
+    }
+    compiler.compile(ast)
+}
+
+/// One rule per number, "z<n>." => "<n>", just over the old limit
+fn many_rules_kms() -> String {
+    (0..=KM2_LEGACY_LIMIT + 1).map(|n| format!("\"z{}.\" => \"<{}>\"\n", n, n)).collect()
+}
+
+/// One variable per number; only the last one is used by a rule
+fn many_strings_kms() -> String {
+    let mut kms: String = (0..=KM2_LEGACY_LIMIT).map(|n| format!("$v{} = \"s{}\"\n", n, n)).collect();
+    kms.push_str(&format!("$v{} => \"found\"\n", KM2_LEGACY_LIMIT));
+    kms
+}
+
+fn type_text(engine: &mut KeyMagicEngine, text: &str) -> String {
+    for ch in text.chars() {
+        engine.process_key(KeyInput::from_char(ch)).unwrap();
+    }
+    engine.composing_text().to_string()
+}
+
+#[test]
+fn test_many_rules_compile_load_and_match() {
+    let km2 = compile_for(&many_rules_kms(), None).unwrap();
+    assert_eq!((km2.header.major_version, km2.header.minor_version), (1, 6));
+    assert_eq!({ km2.header.rule_count } as usize, KM2_LEGACY_LIMIT + 2);
+
+    let loaded = Km2Loader::load(&write(&km2)).unwrap();
+    assert_eq!(loaded.rules.len(), KM2_LEGACY_LIMIT + 2);
+
+    let mut engine = KeyMagicEngine::new(loaded).unwrap();
+    let last = format!("z{}.", KM2_LEGACY_LIMIT + 1);
+    assert_eq!(type_text(&mut engine, &last), format!("<{}>", KM2_LEGACY_LIMIT + 1));
+}
+
+#[test]
+fn test_many_strings_use_wide_variable_indices() {
+    let km2 = compile_for(&many_strings_kms(), None).unwrap();
+    assert_eq!(km2.header.minor_version, 6);
+    assert_eq!(km2.strings.len(), KM2_LEGACY_LIMIT + 1);
+
+    let loaded = Km2Loader::load(&write(&km2)).unwrap();
+    let mut engine = KeyMagicEngine::new(loaded).unwrap();
+    assert_eq!(type_text(&mut engine, &format!("s{}", KM2_LEGACY_LIMIT)), "found");
+}
+
+#[test]
+fn test_old_target_errors_instead_of_truncating() {
+    match compile_for(&many_rules_kms(), Some((1, 5))) {
+        Err(KmsError::TooLargeForVersion { what, count, limit, major: 1, minor: 5 }) => {
+            assert_eq!(what, "rules");
+            assert_eq!(count, KM2_LEGACY_LIMIT + 2);
+            assert_eq!(limit, KM2_LEGACY_LIMIT);
+        }
+        other => panic!("expected TooLargeForVersion, got {:?}", other.map(|_| ())),
+    }
+
+    assert!(matches!(
+        compile_for(&many_strings_kms(), Some((1, 5))),
+        Err(KmsError::TooLargeForVersion { count, .. }) if count == KM2_LEGACY_LIMIT + 1
+    ));
+
+    // A hand-built file claiming 1.5 is checked by the writer too
+    let mut km2 = compile_for(&many_rules_kms(), None).unwrap();
+    km2.header.minor_version = 5;
+    assert!(matches!(
+        Km2Writer::new(Vec::new()).write_km2_file(&km2),
+        Err(KmsError::TooLargeForVersion { .. })
+    ));
+}
+
+#[test]
+fn test_small_keyboards_stay_on_old_version() {
+    let km2 = compile_kms(r#""ka" => "က""#).unwrap();
+    assert_eq!(km2.header.minor_version, 5);
+
+    let km2 = compile_for(r#""ka" => "က""#, Some((1, 6))).unwrap();
+    let loaded = Km2Loader::load(&write(&km2)).unwrap();
+    assert_eq!(loaded.header.minor_version, 6);
+    let mut engine = KeyMagicEngine::new(loaded).unwrap();
+    assert_eq!(type_text(&mut engine, "ka"), "က");
+
+    assert!(matches!(
+        compile_for(r#""a" => "b""#, Some((1, 4))),
+        Err(KmsError::UnsupportedTarget { major: 1, minor: 4 })
+    ));
+}
+
+#[test]
+fn test_newer_files_are_rejected_before_reading_counts() {
+    let mut data = write(&compile_kms(r#""a" => "b""#).unwrap());
+    data[5] = 7;
+    match Km2Loader::load(&data) {
+        Err(e @ Km2Error::NewerVersion { major: 1, minor: 7, .. }) => {
+            assert!(e.to_string().contains("update KeyMagic"), "{}", e);
+        }
+        other => panic!("expected NewerVersion, got {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,47 @@
+use keymagic_core::km2::Km2Loader;
+use kms2km2::binary::Km2Writer;
+use kms2km2::{compile_kms, compile_kms_with_warnings};
+
+#[test]
+fn test_locales_are_compiled_into_metadata() {
+    let kms = "/*\n@NAME = \"Shan\"\n@LOCALE = \"shn-MM\"\n@LOCALE = \"my-MM\"\n*/\n\"a\" => \"ႁ\"";
+    let (km2, warnings) = compile_kms_with_warnings(kms, None).unwrap();
+    assert!(warnings.is_empty(), "{:?}", warnings);
+    assert_eq!(km2.metadata().locales(), vec!["shn-MM", "my-MM"]);
+
+    // They survive the binary format
+    let mut buffer = Vec::new();
+    Km2Writer::new(&mut buffer).write_km2_file(&km2).unwrap();
+    let loaded = Km2Loader::load(&buffer).unwrap();
+    assert_eq!(loaded.metadata().locales(), vec!["shn-MM", "my-MM"]);
+}
+
+#[test]
+fn test_comma_separated_and_duplicate_locales() {
+    let km2 = compile_kms("// @LOCALE = \"shn-MM, shn-mm\"\n// @locale = \"my\"\n\"a\" => \"b\"").unwrap();
+    assert_eq!(km2.metadata().locales(), vec!["shn-MM", "my"]);
+}
+
+#[test]
+fn test_keyboard_without_locale() {
+    let km2 = compile_kms("\"a\" => \"b\"").unwrap();
+    assert!(km2.metadata().locales().is_empty());
+}
+
+#[test]
+fn test_unusual_locale_warns() {
+    let (km2, warnings) = compile_kms_with_warnings("/*\n@LOCALE = \"SHN-mm\"\n*/\n\"a\" => \"b\"", None).unwrap();
+    assert_eq!(km2.metadata().locales(), vec!["SHN-mm"]);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].rule, None);
+    assert_eq!(warnings[0].to_string(), "@LOCALE \"SHN-mm\": usually written as \"shn-MM\"");
+}
+
+#[test]
+fn test_malformed_locale_is_an_error() {
+    for tag in ["shn_MM", "s", "shn--MM", "shn-MM-toolongsubtag", "123"] {
+        let kms = format!("/*\n@LOCALE = \"{}\"\n*/\n\"a\" => \"b\"", tag);
+        let err = compile_kms(&kms).unwrap_err();
+        assert!(err.to_string().contains("Invalid @LOCALE"), "{}: {}", tag, err);
+    }
+}
@@ -0,0 +1,26 @@
+use keymagic_core::types::opcodes::PREDEFINED_NULL;
+use keymagic_core::types::km2::BinaryFormatElement;
+use kms2km2::compile_kms_with_warnings;
+
+#[test]
+fn test_empty_string_warns_and_compiles_to_null() {
+    let (km2, warnings) = compile_kms_with_warnings("\"a\" => \"A\"\n\"abc\" => \"\"", None).unwrap();
+    assert!(matches!(km2.rules[1].rhs[..], [BinaryFormatElement::Predefined(PREDEFINED_NULL)]));
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].rule, Some(1));
+    assert_eq!(warnings[0].to_string(), "rule 2: empty string output is compiled as NULL");
+}
+
+#[test]
+fn test_null_next_to_text_warns() {
+    let (_, warnings) = compile_kms_with_warnings(r#""abc" => NULL + "x""#, None).unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].message, "NULL next to other output has no effect");
+}
+
+#[test]
+fn test_null_alone_does_not_warn() {
+    let (km2, warnings) = compile_kms_with_warnings(r#""abc" => NULL"#, None).unwrap();
+    assert!(matches!(km2.rules[0].rhs[..], [BinaryFormatElement::Predefined(PREDEFINED_NULL)]));
+    assert!(warnings.is_empty());
+}
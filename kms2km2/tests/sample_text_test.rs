@@ -0,0 +1,33 @@
+use keymagic_core::km2::Km2Loader;
+use kms2km2::binary::Km2Writer;
+use kms2km2::compile_kms;
+
+#[test]
+fn test_sample_is_compiled_into_metadata() {
+    let km2 = compile_kms("/*\n@NAME = \"Shan\"\n@SAMPLE = \"ၵႂၢမ်းတႆး\"\n*/\n\"a\" => \"ႁ\"").unwrap();
+    assert_eq!(km2.metadata().sample_text().as_deref(), Some("ၵႂၢမ်းတႆး"));
+
+    // It survives the binary format
+    let mut buffer = Vec::new();
+    Km2Writer::new(&mut buffer).write_km2_file(&km2).unwrap();
+    let loaded = Km2Loader::load(&buffer).unwrap();
+    assert_eq!(loaded.metadata().sample_text().as_deref(), Some("ၵႂၢမ်းတႆး"));
+
+    let json = loaded.to_json();
+    assert!(json.contains("\"smpl\""), "{}", json);
+}
+
+#[test]
+fn test_sample_option_in_lower_case() {
+    let km2 = compile_kms("// @sample = \"မြန်မာ\"\n\"a\" => \"b\"").unwrap();
+    assert_eq!(km2.metadata().sample_text().as_deref(), Some("မြန်မာ"));
+}
+
+#[test]
+fn test_keyboard_without_sample() {
+    let km2 = compile_kms("\"a\" => \"b\"").unwrap();
+    assert_eq!(km2.metadata().sample_text(), None);
+
+    let km2 = compile_kms("// @SAMPLE = \"  \"\n\"a\" => \"b\"").unwrap();
+    assert_eq!(km2.metadata().sample_text(), None);
+}
@@ -151,6 +151,17 @@ def update_plist_file(content, version):
     return content
 
 
+def update_pyproject_toml(content, version):
+    """Update version in the [project] section of pyproject.toml files."""
+    return re.sub(
+        r'(^\[project\][^\[]*?^version\s*=\s*)"[^"]*"',
+        f'\\g<1>"{version}"',
+        content,
+        count=1,
+        flags=re.MULTILINE | re.DOTALL
+    )
+
+
 def update_desktop_file(content, version):
     """Update version in .desktop files."""
     # Update Version field
@@ -230,6 +241,11 @@ def main():
          lambda c: update_desktop_file(c, new_version),
          'IBus desktop file'),
         
+        # Python bindings
+        (project_root / 'bindings' / 'python' / 'pyproject.toml',
+         lambda c: update_pyproject_toml(c, new_version),
+         'Python bindings pyproject.toml'),
+        
         # Note: .spec.in files use @VERSION@ placeholder and are processed during build
         # They should not be updated by this script
     ]